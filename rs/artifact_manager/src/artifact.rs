@@ -2,8 +2,12 @@
 
 use ic_consensus_message::ConsensusMessageHashable;
 use ic_types::{
-    artifact::*, consensus::certification::CertificationMessageHash, crypto::CryptoHashOf,
-    messages::SignedRequestBytes, CountBytes,
+    artifact::*,
+    chunkable::{Chunkable, MultiChunked},
+    consensus::{certification::CertificationMessageHash, HasHeight},
+    crypto::{CryptoHash, CryptoHashOf},
+    messages::SignedRequestBytes,
+    CountBytes,
 };
 use serde::{Deserialize, Serialize};
 
@@ -30,9 +34,21 @@ impl ArtifactKind for ConsensusArtifact {
             id: msg.get_id(),
             attribute,
             size,
-            integrity_hash: ic_crypto::crypto_hash(msg).get(),
+            integrity_hash: Self::integrity_hash(msg),
         }
     }
+
+    fn integrity_hash(msg: &ConsensusMessage) -> CryptoHash {
+        ic_crypto::crypto_hash(msg).get()
+    }
+
+    /// Block proposals can be larger than a single transport message, so
+    /// large consensus messages are downloaded as multiple chunks. The
+    /// advertised size is the size of the serialized message.
+    fn multi_chunk_tracker(size: usize) -> Option<Box<dyn Chunkable + Send + Sync>> {
+        MultiChunked::new(Self::TAG, size)
+            .map(|tracker| Box::new(tracker) as Box<dyn Chunkable + Send + Sync>)
+    }
 }
 
 /// The `ArtifactKind` of ingress message.
@@ -55,9 +71,13 @@ impl ArtifactKind for IngressArtifact {
             id: IngressMessageId::from(msg),
             attribute: IngressMessageAttribute::new(msg),
             size: msg.count_bytes(),
-            integrity_hash: ic_crypto::crypto_hash(msg.binary()).get(),
+            integrity_hash: Self::integrity_hash(msg.binary()),
         }
     }
+
+    fn integrity_hash(msg: &SignedRequestBytes) -> CryptoHash {
+        ic_crypto::crypto_hash(msg).get()
+    }
 }
 
 /// The `ArtifactKind` of certification messages.
@@ -101,9 +121,13 @@ impl ArtifactKind for CertificationArtifact {
             id,
             attribute,
             size: bincode::serialize(msg).unwrap().len(),
-            integrity_hash: ic_crypto::crypto_hash(msg).get(),
+            integrity_hash: Self::integrity_hash(msg),
         }
     }
+
+    fn integrity_hash(msg: &CertificationMessage) -> CryptoHash {
+        ic_crypto::crypto_hash(msg).get()
+    }
 }
 
 /// The `ArtifactKind` of DKG messages.
@@ -134,6 +158,154 @@ impl ArtifactKind for DkgArtifact {
             integrity_hash: hash.get(),
         }
     }
+
+    /// The integrity hash of a DKG message is the hash identifying it.
+    fn integrity_hash(msg: &DkgMessage) -> CryptoHash {
+        ic_crypto::crypto_hash(msg).get()
+    }
+}
+
+/// The `ArtifactKind` of equivocation proofs.
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub struct EquivocationArtifact;
+
+/// `EquivocationArtifact` implements the `ArtifactKind` trait.
+impl ArtifactKind for EquivocationArtifact {
+    const TAG: ArtifactTag = ArtifactTag::EquivocationArtifact;
+    type Id = EquivocationProofId;
+    type Message = EquivocationProof;
+    type SerializeAs = EquivocationProof;
+    type Attribute = EquivocationProofAttribute;
+    type Filter = ();
+
+    /// The function converts an `EquivocationProof` into an advert for an
+    /// `EquivocationArtifact`.
+    fn message_to_advert(msg: &EquivocationProof) -> Advert<EquivocationArtifact> {
+        let size = bincode::serialize(msg).unwrap().len();
+        let attribute = EquivocationProofAttribute {
+            height: msg.height(),
+        };
+        let hash = ic_crypto::crypto_hash(msg);
+        Advert {
+            id: hash.clone(),
+            attribute,
+            size,
+            integrity_hash: hash.get(),
+        }
+    }
+
+    /// The integrity hash of an equivocation proof is the hash identifying
+    /// it.
+    fn integrity_hash(msg: &EquivocationProof) -> CryptoHash {
+        ic_crypto::crypto_hash(msg).get()
+    }
+}
+
+/// The `ArtifactKind` of remote DKG messages.
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub struct RemoteDkgArtifact;
+
+/// `RemoteDkgArtifact` implements the `ArtifactKind` trait.
+impl ArtifactKind for RemoteDkgArtifact {
+    const TAG: ArtifactTag = ArtifactTag::RemoteDkgArtifact;
+    type Id = RemoteDkgMessageId;
+    type Message = RemoteDkgMessage;
+    type SerializeAs = RemoteDkgMessage;
+    type Attribute = RemoteDkgMessageAttribute;
+    type Filter = ();
+
+    /// The function converts a `RemoteDkgMessage` into an advert for a
+    /// `RemoteDkgArtifact`.
+    fn message_to_advert(msg: &RemoteDkgMessage) -> Advert<RemoteDkgArtifact> {
+        let size = bincode::serialize(msg).unwrap().len();
+        let attribute = RemoteDkgMessageAttribute {
+            summary_height: msg.height(),
+        };
+        let hash = ic_crypto::crypto_hash(msg);
+        Advert {
+            id: hash.clone(),
+            attribute,
+            size,
+            integrity_hash: hash.get(),
+        }
+    }
+
+    /// The integrity hash of a remote DKG message is the hash identifying it.
+    fn integrity_hash(msg: &RemoteDkgMessage) -> CryptoHash {
+        ic_crypto::crypto_hash(msg).get()
+    }
+}
+
+/// The `ArtifactKind` of canister HTTP messages.
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub struct CanisterHttpArtifact;
+
+/// `CanisterHttpArtifact` implements the `ArtifactKind` trait.
+impl ArtifactKind for CanisterHttpArtifact {
+    const TAG: ArtifactTag = ArtifactTag::CanisterHttpArtifact;
+    type Id = CanisterHttpMessageId;
+    type Message = CanisterHttpMessage;
+    type SerializeAs = CanisterHttpMessage;
+    type Attribute = CanisterHttpMessageAttribute;
+    type Filter = ();
+
+    /// The function converts a `CanisterHttpMessage` into an advert for a
+    /// `CanisterHttpArtifact`.
+    fn message_to_advert(msg: &CanisterHttpMessage) -> Advert<CanisterHttpArtifact> {
+        let size = bincode::serialize(msg).unwrap().len();
+        let attribute = CanisterHttpMessageAttribute {
+            timeout: msg.timeout(),
+        };
+        let hash = ic_crypto::crypto_hash(msg);
+        Advert {
+            id: hash.clone(),
+            attribute,
+            size,
+            integrity_hash: hash.get(),
+        }
+    }
+
+    /// The integrity hash of a canister HTTP message is the hash identifying
+    /// it.
+    fn integrity_hash(msg: &CanisterHttpMessage) -> CryptoHash {
+        ic_crypto::crypto_hash(msg).get()
+    }
+}
+
+/// The `ArtifactKind` of query statistics reports.
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub struct QueryStatsArtifact;
+
+/// `QueryStatsArtifact` implements the `ArtifactKind` trait.
+impl ArtifactKind for QueryStatsArtifact {
+    const TAG: ArtifactTag = ArtifactTag::QueryStatsArtifact;
+    type Id = QueryStatsMessageId;
+    type Message = QueryStatsMessage;
+    type SerializeAs = QueryStatsMessage;
+    type Attribute = QueryStatsMessageAttribute;
+    type Filter = ();
+
+    /// The function converts a `QueryStatsMessage` into an advert for a
+    /// `QueryStatsArtifact`.
+    fn message_to_advert(msg: &QueryStatsMessage) -> Advert<QueryStatsArtifact> {
+        let size = bincode::serialize(msg).unwrap().len();
+        let attribute = QueryStatsMessageAttribute {
+            epoch: msg.content.epoch,
+        };
+        let hash = ic_crypto::crypto_hash(msg);
+        Advert {
+            id: hash.clone(),
+            attribute,
+            size,
+            integrity_hash: hash.get(),
+        }
+    }
+
+    /// The integrity hash of a query statistics report is the hash
+    /// identifying it.
+    fn integrity_hash(msg: &QueryStatsMessage) -> CryptoHash {
+        ic_crypto::crypto_hash(msg).get()
+    }
 }
 
 /// The `ArtifactKind` of ECDSA messages.
@@ -151,7 +323,83 @@ impl ArtifactKind for EcdsaArtifact {
 
     /// The function converts a `EcdsaMessage` into an advert for a
     /// `EcdsaArtifact`.
-    fn message_to_advert(_msg: &EcdsaMessage) -> Advert<EcdsaArtifact> {
-        unimplemented!()
+    fn message_to_advert(msg: &EcdsaMessage) -> Advert<EcdsaArtifact> {
+        let size = bincode::serialize(msg).unwrap().len();
+        let attribute = EcdsaMessageAttribute {
+            height: msg.height(),
+        };
+        let hash = ic_crypto::crypto_hash(msg);
+        Advert {
+            id: hash.clone(),
+            attribute,
+            size,
+            integrity_hash: hash.get(),
+        }
+    }
+
+    fn integrity_hash(msg: &EcdsaMessage) -> CryptoHash {
+        ic_crypto::crypto_hash(msg).get()
+    }
+}
+
+/// The `ArtifactKind` of XNet stream slices pulled over gossip.
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub struct XNetStreamSliceArtifact;
+
+/// `XNetStreamSliceArtifact` implements the `ArtifactKind` trait.
+impl ArtifactKind for XNetStreamSliceArtifact {
+    const TAG: ArtifactTag = ArtifactTag::XNetStreamSliceArtifact;
+    type Id = XNetStreamSliceId;
+    type Message = XNetStreamSliceMessage;
+    type SerializeAs = XNetStreamSliceMessage;
+    type Attribute = XNetStreamSliceAttribute;
+    type Filter = XNetStreamSliceFilter;
+
+    /// The function converts an `XNetStreamSliceMessage` into an advert for
+    /// an `XNetStreamSliceArtifact`.
+    fn message_to_advert(msg: &XNetStreamSliceMessage) -> Advert<XNetStreamSliceArtifact> {
+        Advert {
+            id: msg.id.clone(),
+            attribute: XNetStreamSliceAttribute {
+                destination: msg.id.destination,
+            },
+            size: bincode::serialize(msg).unwrap().len(),
+            integrity_hash: Self::integrity_hash(msg),
+        }
+    }
+
+    fn integrity_hash(msg: &XNetStreamSliceMessage) -> CryptoHash {
+        ic_crypto::crypto_hash(msg).get()
+    }
+}
+
+/// The `ArtifactKind` of certified registry deltas distributed over gossip.
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub struct RegistryDeltaArtifact;
+
+/// `RegistryDeltaArtifact` implements the `ArtifactKind` trait.
+impl ArtifactKind for RegistryDeltaArtifact {
+    const TAG: ArtifactTag = ArtifactTag::RegistryDeltaArtifact;
+    type Id = RegistryDeltaId;
+    type Message = RegistryDeltaMessage;
+    type SerializeAs = RegistryDeltaMessage;
+    type Attribute = RegistryDeltaAttribute;
+    type Filter = ();
+
+    /// The function converts a `RegistryDeltaMessage` into an advert for a
+    /// `RegistryDeltaArtifact`.
+    fn message_to_advert(msg: &RegistryDeltaMessage) -> Advert<RegistryDeltaArtifact> {
+        Advert {
+            id: msg.id,
+            attribute: RegistryDeltaAttribute {
+                version: msg.id.version,
+            },
+            size: bincode::serialize(msg).unwrap().len(),
+            integrity_hash: Self::integrity_hash(msg),
+        }
+    }
+
+    fn integrity_hash(msg: &RegistryDeltaMessage) -> CryptoHash {
+        ic_crypto::crypto_hash(msg).get()
     }
 }
@@ -5,19 +5,25 @@ use crate::processors::ArtifactProcessorManager;
 use ic_interfaces::{
     artifact_manager::{AdvertMismatchError, ArtifactAcceptance, ArtifactClient, OnArtifactError},
     artifact_pool::{ArtifactPoolError, ReplicaVersionMismatch, UnvalidatedArtifact},
+    canister_http::{CanisterHttpGossip, CanisterHttpPool},
     certification::{CertificationPool, CertifierGossip},
     consensus::ConsensusGossip,
     consensus_pool::{ConsensusPool, ConsensusPoolCache},
     dkg::{DkgGossip, DkgPool},
     ecdsa::{EcdsaGossip, EcdsaPool},
+    equivocation::{EquivocationGossip, EquivocationPool},
     gossip_pool::{
-        CertificationGossipPool, ConsensusGossipPool, DkgGossipPool, EcdsaGossipPool,
-        IngressGossipPool,
+        CanisterHttpGossipPool, CertificationGossipPool, ConsensusGossipPool, DkgGossipPool,
+        EcdsaGossipPool, EquivocationGossipPool, QueryStatsGossipPool, RemoteDkgGossipPool,
     },
-    ingress_pool::IngressPool,
+    ingress_pool::IngressPoolLookup,
+    query_stats::{QueryStatsGossip, QueryStatsPool},
+    remote_dkg::{RemoteDkgGossip, RemoteDkgPool},
     time_source::TimeSource,
 };
 use ic_logger::{debug, ReplicaLogger};
+#[cfg(feature = "malicious_code")]
+use ic_types::malicious_strategies::DisableIngressValidation;
 use ic_types::{
     artifact,
     artifact::*,
@@ -27,7 +33,7 @@ use ic_types::{
         HasVersion,
     },
     ingress::MAX_INGRESS_TTL,
-    malicious_flags::MaliciousFlags,
+    malicious_strategies::MaliciousBehaviors,
     messages::{SignedIngress, SignedRequestBytes},
     p2p, NodeId, ReplicaVersion,
 };
@@ -78,13 +84,17 @@ pub(crate) trait ArtifactManagerBackend: Send + Sync {
     /// tag.
     fn get_remaining_quota(&self, tag: artifact::ArtifactTag, peer_id: NodeId) -> Option<usize>;
 
+    /// The method indicates whether the processor of the given artifact tag
+    /// falls behind.
+    fn has_backpressure(&self, tag: artifact::ArtifactTag) -> Option<bool>;
+
     /// The method returns a priority function for a given artifact tag.
     fn get_priority_function(&self, tag: artifact::ArtifactTag) -> Option<ArtifactPriorityFn>;
 
-    /// The method returns a chunk tracker for a given artifact ID.
+    /// The method returns a chunk tracker for a given advert.
     fn get_chunk_tracker(
         &self,
-        id: &artifact::ArtifactId,
+        advert: &p2p::GossipAdvert,
     ) -> Option<Box<dyn Chunkable + Send + Sync>>;
 }
 
@@ -133,12 +143,15 @@ where
                                 expected: expected.into(),
                             }
                         })?;
-                        // this sends to an unbounded channel, which is what we want here
-                        self.processor.on_artifact(UnvalidatedArtifact {
-                            message,
-                            peer_id,
-                            timestamp: time_source.get_relative_time(),
-                        })
+                        // The artifact is rejected if the processor queue is full. *Gossip*
+                        // stops downloading before, when the processor signals backpressure.
+                        self.processor
+                            .on_artifact(UnvalidatedArtifact {
+                                message,
+                                peer_id,
+                                timestamp: time_source.get_relative_time(),
+                            })
+                            .map_err(|_| OnArtifactError::Throttled)?
                     }
                 };
                 Ok(())
@@ -198,6 +211,15 @@ where
         }
     }
 
+    /// The method indicates whether the artifact processor falls behind.
+    fn has_backpressure(&self, tag: artifact::ArtifactTag) -> Option<bool> {
+        if tag == Artifact::TAG {
+            Some(self.processor.has_backpressure())
+        } else {
+            None
+        }
+    }
+
     /// The method returns the priority function.
     fn get_priority_function(&self, tag: artifact::ArtifactTag) -> Option<ArtifactPriorityFn> {
         if tag == Artifact::TAG {
@@ -215,15 +237,19 @@ where
         }
     }
 
-    /// The method returns the artifact chunk tracker.
+    /// The method returns the artifact chunk tracker. Artifacts larger than a
+    /// chunk are tracked as multiple chunks if their kind supports it.
     fn get_chunk_tracker(
         &self,
-        artifact_id: &artifact::ArtifactId,
+        advert: &p2p::GossipAdvert,
     ) -> Option<Box<dyn Chunkable + Send + Sync>> {
-        match artifact_id.try_into() {
-            Ok(artifact_id) => Some(self.client.as_ref().get_chunk_tracker(artifact_id)),
-            Err(_) => None,
+        let artifact_id: &Artifact::Id = (&advert.artifact_id).try_into().ok()?;
+        if advert.size > ARTIFACT_CHUNK_SIZE {
+            if let Some(tracker) = Artifact::multi_chunk_tracker(advert.size) {
+                return Some(tracker);
+            }
         }
+        Some(self.client.as_ref().get_chunk_tracker(artifact_id))
     }
 }
 
@@ -330,39 +356,38 @@ impl<Pool: ConsensusPool + ConsensusGossipPool + Send + Sync> ArtifactClient<Con
 }
 
 /// The ingress `ArtifactClient` to be managed by the `ArtifactManager`.
-pub struct IngressClient<Pool> {
+pub struct IngressClient {
     /// The time source.
     time_source: Arc<dyn TimeSource>,
-    /// The ingress pool, protected by a read-write lock and automatic reference
-    /// counting.
-    ingress_pool: Arc<RwLock<Pool>>,
+    /// The lookups in the ingress pool, which do not take the lock of the
+    /// pool, so that handling the messages of users and peers does not
+    /// contend with the ingress processor.
+    ingress_pool: Arc<dyn IngressPoolLookup>,
     /// The logger.
     log: ReplicaLogger,
 
     #[allow(dead_code)]
-    malicious_flags: MaliciousFlags,
+    malicious_behaviors: MaliciousBehaviors,
 }
 
-impl<Pool> IngressClient<Pool> {
+impl IngressClient {
     /// The constructor creates an `IngressClient` instance.
     pub fn new(
         time_source: Arc<dyn TimeSource>,
-        ingress_pool: Arc<RwLock<Pool>>,
+        ingress_pool: Arc<dyn IngressPoolLookup>,
         log: ReplicaLogger,
-        malicious_flags: MaliciousFlags,
+        malicious_behaviors: MaliciousBehaviors,
     ) -> Self {
         Self {
             time_source,
             ingress_pool,
             log,
-            malicious_flags,
+            malicious_behaviors,
         }
     }
 }
 
-impl<Pool: IngressPool + IngressGossipPool + Send + Sync> ArtifactClient<IngressArtifact>
-    for IngressClient<Pool>
-{
+impl ArtifactClient<IngressArtifact> for IngressClient {
     /// The method checks whether the given signed ingress bytes constitutes a
     /// valid singed ingress message.
     ///
@@ -386,7 +411,10 @@ impl<Pool: IngressPool + IngressGossipPool + Send + Sync> ArtifactClient<Ingress
 
         #[cfg(feature = "malicious_code")]
         {
-            if self.malicious_flags.maliciously_disable_ingress_validation {
+            if self
+                .malicious_behaviors
+                .contains::<DisableIngressValidation>()
+            {
                 return Ok(ArtifactAcceptance::AcceptedForProcessing(msg));
             }
         }
@@ -408,10 +436,7 @@ impl<Pool: IngressPool + IngressGossipPool + Send + Sync> ArtifactClient<Ingress
             );
             Err(ArtifactPoolError::MessageExpiryTooLong)
         } else {
-            self.ingress_pool
-                .read()
-                .unwrap()
-                .check_quota(&msg, peer_id)?;
+            self.ingress_pool.check_quota(&msg, peer_id)?;
             Ok(ArtifactAcceptance::AcceptedForProcessing(msg))
         }
     }
@@ -419,16 +444,13 @@ impl<Pool: IngressPool + IngressGossipPool + Send + Sync> ArtifactClient<Ingress
     /// The method checks if the ingress pool contains an ingress message with
     /// the given ID.
     fn has_artifact(&self, msg_id: &IngressMessageId) -> bool {
-        self.ingress_pool.read().unwrap().contains(msg_id)
+        self.ingress_pool.contains(msg_id)
     }
 
     /// The method returns the `SignedIngress` message with the given ingress
     /// message ID from the ingress pool (if available).
     fn get_validated_by_identifier(&self, msg_id: &IngressMessageId) -> Option<SignedIngress> {
-        self.ingress_pool
-            .read()
-            .unwrap()
-            .get_validated_by_identifier(msg_id)
+        self.ingress_pool.get_validated_by_identifier(msg_id)
     }
 
     /// The method returns the ingress message filter.
@@ -658,6 +680,15 @@ impl<Pool: EcdsaPool + EcdsaGossipPool + Send + Sync> ArtifactClient<EcdsaArtifa
             .get_validated_by_identifier(msg_id)
     }
 
+    fn get_all_validated_by_filter(&self, _filter: &()) -> Vec<Advert<EcdsaArtifact>> {
+        self.ecdsa_pool
+            .read()
+            .unwrap()
+            .get_all_validated_by_filter(())
+            .map(|msg| EcdsaArtifact::message_to_advert(&msg))
+            .collect()
+    }
+
     fn get_priority_function(&self) -> Option<PriorityFn<EcdsaMessageId, EcdsaMessageAttribute>> {
         let ecdsa_pool = &*self.ecdsa_pool.read().unwrap();
         Some(self.ecdsa_gossip.get_priority_function(ecdsa_pool))
@@ -667,3 +698,317 @@ impl<Pool: EcdsaPool + EcdsaGossipPool + Send + Sync> ArtifactClient<EcdsaArtifa
         Box::new(SingleChunked::Ecdsa)
     }
 }
+
+/// The equivocation `ArtifactClient` to be managed by the `ArtifactManager`.
+pub struct EquivocationClient<Pool> {
+    /// The equivocation pool, protected by a read-write lock and automatic
+    /// reference counting.
+    equivocation_pool: Arc<RwLock<Pool>>,
+    /// The `EquivocationGossip` client.
+    client: Arc<dyn EquivocationGossip>,
+}
+
+impl<Pool> EquivocationClient<Pool> {
+    /// The constructor creates an `EquivocationClient` instance.
+    pub fn new<T: EquivocationGossip + 'static>(
+        equivocation_pool: Arc<RwLock<Pool>>,
+        gossip: T,
+    ) -> Self {
+        Self {
+            equivocation_pool,
+            client: Arc::new(gossip),
+        }
+    }
+}
+
+impl<Pool: EquivocationPool + EquivocationGossipPool + Send + Sync>
+    ArtifactClient<EquivocationArtifact> for EquivocationClient<Pool>
+{
+    /// The method checks if the protocol version of both block proposals of
+    /// the proof is correct.
+    ///
+    /// If this is the case, the artifact is returned wrapped in an
+    /// `ArtifactAcceptance` enum.
+    fn check_artifact_acceptance(
+        &self,
+        msg: EquivocationProof,
+        _peer_id: &NodeId,
+    ) -> Result<ArtifactAcceptance<EquivocationProof>, ArtifactPoolError> {
+        let (first, second) = msg.proposals();
+        check_protocol_version(first.content.as_ref())?;
+        check_protocol_version(second.content.as_ref())?;
+        Ok(ArtifactAcceptance::AcceptedForProcessing(msg))
+    }
+
+    /// The method checks if the equivocation pool contains a proof with the
+    /// given ID.
+    fn has_artifact(&self, msg_id: &EquivocationProofId) -> bool {
+        self.equivocation_pool.read().unwrap().contains(msg_id)
+    }
+
+    /// The method returns the validated proof with the given ID if available.
+    fn get_validated_by_identifier(
+        &self,
+        msg_id: &EquivocationProofId,
+    ) -> Option<EquivocationProof> {
+        self.equivocation_pool
+            .read()
+            .unwrap()
+            .get_validated_by_identifier(msg_id)
+    }
+
+    /// The method returns adverts for all validated proofs, so that peers
+    /// that reconnect learn about the equivocations they missed.
+    fn get_all_validated_by_filter(&self, _filter: &()) -> Vec<Advert<EquivocationArtifact>> {
+        self.equivocation_pool
+            .read()
+            .unwrap()
+            .get_all_validated_by_filter(())
+            .map(|msg| EquivocationArtifact::message_to_advert(&msg))
+            .collect()
+    }
+
+    /// The method returns the priority function.
+    fn get_priority_function(
+        &self,
+    ) -> Option<PriorityFn<EquivocationProofId, EquivocationProofAttribute>> {
+        let equivocation_pool = &*self.equivocation_pool.read().unwrap();
+        Some(self.client.get_priority_function(equivocation_pool))
+    }
+
+    /// The method returns a new (single-chunked) equivocation proof tracker.
+    fn get_chunk_tracker(&self, _id: &EquivocationProofId) -> Box<dyn Chunkable + Send + Sync> {
+        Box::new(SingleChunked::EquivocationProof)
+    }
+}
+
+/// The remote DKG `ArtifactClient` to be managed by the `ArtifactManager`.
+pub struct RemoteDkgClient<Pool> {
+    /// The remote DKG pool, protected by a read-write lock and automatic
+    /// reference counting.
+    remote_dkg_pool: Arc<RwLock<Pool>>,
+    /// The `RemoteDkgGossip` client.
+    client: Arc<dyn RemoteDkgGossip>,
+}
+
+impl<Pool> RemoteDkgClient<Pool> {
+    /// The constructor creates a `RemoteDkgClient` instance.
+    pub fn new<T: RemoteDkgGossip + 'static>(
+        remote_dkg_pool: Arc<RwLock<Pool>>,
+        gossip: T,
+    ) -> Self {
+        Self {
+            remote_dkg_pool,
+            client: Arc::new(gossip),
+        }
+    }
+}
+
+impl<Pool: RemoteDkgPool + RemoteDkgGossipPool + Send + Sync> ArtifactClient<RemoteDkgArtifact>
+    for RemoteDkgClient<Pool>
+{
+    /// Remote DKG messages are validated against the DKG summary by the
+    /// processor, so the artifact is always accepted for processing.
+    fn check_artifact_acceptance(
+        &self,
+        msg: RemoteDkgMessage,
+        _peer_id: &NodeId,
+    ) -> Result<ArtifactAcceptance<RemoteDkgMessage>, ArtifactPoolError> {
+        Ok(ArtifactAcceptance::AcceptedForProcessing(msg))
+    }
+
+    /// The method checks if the remote DKG pool contains a message with the
+    /// given ID.
+    fn has_artifact(&self, msg_id: &RemoteDkgMessageId) -> bool {
+        self.remote_dkg_pool.read().unwrap().contains(msg_id)
+    }
+
+    /// The method returns the validated message with the given ID if
+    /// available.
+    fn get_validated_by_identifier(&self, msg_id: &RemoteDkgMessageId) -> Option<RemoteDkgMessage> {
+        self.remote_dkg_pool
+            .read()
+            .unwrap()
+            .get_validated_by_identifier(msg_id)
+    }
+
+    /// The method returns adverts for all validated messages.
+    fn get_all_validated_by_filter(&self, _filter: &()) -> Vec<Advert<RemoteDkgArtifact>> {
+        self.remote_dkg_pool
+            .read()
+            .unwrap()
+            .get_all_validated_by_filter(())
+            .map(|msg| RemoteDkgArtifact::message_to_advert(&msg))
+            .collect()
+    }
+
+    /// The method returns the priority function.
+    fn get_priority_function(
+        &self,
+    ) -> Option<PriorityFn<RemoteDkgMessageId, RemoteDkgMessageAttribute>> {
+        let remote_dkg_pool = &*self.remote_dkg_pool.read().unwrap();
+        Some(self.client.get_priority_function(remote_dkg_pool))
+    }
+
+    /// The method returns a new (single-chunked) remote DKG message tracker.
+    fn get_chunk_tracker(&self, _id: &RemoteDkgMessageId) -> Box<dyn Chunkable + Send + Sync> {
+        Box::new(SingleChunked::RemoteDkg)
+    }
+}
+
+/// The canister HTTP `ArtifactClient` to be managed by the `ArtifactManager`.
+pub struct CanisterHttpClient<Pool> {
+    /// The canister HTTP pool, protected by a read-write lock and automatic
+    /// reference counting.
+    canister_http_pool: Arc<RwLock<Pool>>,
+    /// The `CanisterHttpGossip` client.
+    client: Arc<dyn CanisterHttpGossip>,
+}
+
+impl<Pool> CanisterHttpClient<Pool> {
+    /// The constructor creates a `CanisterHttpClient` instance.
+    pub fn new<T: CanisterHttpGossip + 'static>(
+        canister_http_pool: Arc<RwLock<Pool>>,
+        gossip: T,
+    ) -> Self {
+        Self {
+            canister_http_pool,
+            client: Arc::new(gossip),
+        }
+    }
+}
+
+impl<Pool: CanisterHttpPool + CanisterHttpGossipPool + Send + Sync>
+    ArtifactClient<CanisterHttpArtifact> for CanisterHttpClient<Pool>
+{
+    /// Canister HTTP messages are validated by the processor, so the artifact
+    /// is always accepted for processing.
+    fn check_artifact_acceptance(
+        &self,
+        msg: CanisterHttpMessage,
+        _peer_id: &NodeId,
+    ) -> Result<ArtifactAcceptance<CanisterHttpMessage>, ArtifactPoolError> {
+        Ok(ArtifactAcceptance::AcceptedForProcessing(msg))
+    }
+
+    /// The method checks if the canister HTTP pool contains a message with
+    /// the given ID.
+    fn has_artifact(&self, msg_id: &CanisterHttpMessageId) -> bool {
+        self.canister_http_pool.read().unwrap().contains(msg_id)
+    }
+
+    /// The method returns the validated message with the given ID if
+    /// available.
+    fn get_validated_by_identifier(
+        &self,
+        msg_id: &CanisterHttpMessageId,
+    ) -> Option<CanisterHttpMessage> {
+        self.canister_http_pool
+            .read()
+            .unwrap()
+            .get_validated_by_identifier(msg_id)
+    }
+
+    /// The method returns adverts for all validated messages.
+    fn get_all_validated_by_filter(&self, _filter: &()) -> Vec<Advert<CanisterHttpArtifact>> {
+        self.canister_http_pool
+            .read()
+            .unwrap()
+            .get_all_validated_by_filter(())
+            .map(|msg| CanisterHttpArtifact::message_to_advert(&msg))
+            .collect()
+    }
+
+    /// The method returns the priority function.
+    fn get_priority_function(
+        &self,
+    ) -> Option<PriorityFn<CanisterHttpMessageId, CanisterHttpMessageAttribute>> {
+        let canister_http_pool = &*self.canister_http_pool.read().unwrap();
+        Some(self.client.get_priority_function(canister_http_pool))
+    }
+
+    /// The method returns a new (single-chunked) canister HTTP message
+    /// tracker.
+    fn get_chunk_tracker(&self, _id: &CanisterHttpMessageId) -> Box<dyn Chunkable + Send + Sync> {
+        Box::new(SingleChunked::CanisterHttp)
+    }
+}
+
+/// The query statistics `ArtifactClient` to be managed by the
+/// `ArtifactManager`.
+pub struct QueryStatsClient<Pool> {
+    /// The query statistics pool, protected by a read-write lock and
+    /// automatic reference counting.
+    query_stats_pool: Arc<RwLock<Pool>>,
+    /// The `QueryStatsGossip` client.
+    client: Arc<dyn QueryStatsGossip>,
+}
+
+impl<Pool> QueryStatsClient<Pool> {
+    /// The constructor creates a `QueryStatsClient` instance.
+    pub fn new<T: QueryStatsGossip + 'static>(
+        query_stats_pool: Arc<RwLock<Pool>>,
+        gossip: T,
+    ) -> Self {
+        Self {
+            query_stats_pool,
+            client: Arc::new(gossip),
+        }
+    }
+}
+
+impl<Pool: QueryStatsPool + QueryStatsGossipPool + Send + Sync> ArtifactClient<QueryStatsArtifact>
+    for QueryStatsClient<Pool>
+{
+    /// Query statistics reports are validated by the processor, so the
+    /// artifact is always accepted for processing.
+    fn check_artifact_acceptance(
+        &self,
+        msg: QueryStatsMessage,
+        _peer_id: &NodeId,
+    ) -> Result<ArtifactAcceptance<QueryStatsMessage>, ArtifactPoolError> {
+        Ok(ArtifactAcceptance::AcceptedForProcessing(msg))
+    }
+
+    /// The method checks if the query statistics pool contains a report with
+    /// the given ID.
+    fn has_artifact(&self, msg_id: &QueryStatsMessageId) -> bool {
+        self.query_stats_pool.read().unwrap().contains(msg_id)
+    }
+
+    /// The method returns the validated report with the given ID if
+    /// available.
+    fn get_validated_by_identifier(
+        &self,
+        msg_id: &QueryStatsMessageId,
+    ) -> Option<QueryStatsMessage> {
+        self.query_stats_pool
+            .read()
+            .unwrap()
+            .get_validated_by_identifier(msg_id)
+    }
+
+    /// The method returns adverts for all validated reports.
+    fn get_all_validated_by_filter(&self, _filter: &()) -> Vec<Advert<QueryStatsArtifact>> {
+        self.query_stats_pool
+            .read()
+            .unwrap()
+            .get_all_validated_by_filter(())
+            .map(|msg| QueryStatsArtifact::message_to_advert(&msg))
+            .collect()
+    }
+
+    /// The method returns the priority function.
+    fn get_priority_function(
+        &self,
+    ) -> Option<PriorityFn<QueryStatsMessageId, QueryStatsMessageAttribute>> {
+        let query_stats_pool = &*self.query_stats_pool.read().unwrap();
+        Some(self.client.get_priority_function(query_stats_pool))
+    }
+
+    /// The method returns a new (single-chunked) query statistics report
+    /// tracker.
+    fn get_chunk_tracker(&self, _id: &QueryStatsMessageId) -> Box<dyn Chunkable + Send + Sync> {
+        Box::new(SingleChunked::QueryStats)
+    }
+}
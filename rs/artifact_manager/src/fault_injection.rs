@@ -0,0 +1,227 @@
+//! Fault injection into the artifact processors, for testing.
+//!
+//! An artifact processor runs a client, which applies a change set to its pool,
+//! and then emits the adverts of the changes. Races between the mutation of
+//! the pool and the broadcast of the adverts are hard to reproduce, as they
+//! depend on the timing of the processor threads and the network. A
+//! `FaultInjector` installed in an `ArtifactProcessorManager` intercepts both
+//! steps and injects faults according to a `FaultSchedule`:
+//!
+//! * A run of the client, i.e. the application of a change set, can be
+//!   delayed, dropped or duplicated. A dropped run leaves the received
+//!   artifacts queued for the next run. A duplicated run runs the client a
+//!   second time right after the first one.
+//! * An advert can be delayed, dropped or duplicated. Delayed adverts are
+//!   emitted by the first run after their delay has passed.
+//!
+//! The faults are drawn from an RNG seeded with the seed of the schedule, so
+//! that the same sequence of runs and adverts gets the same faults. The rates
+//! of a schedule are validated when the injector is created. All
+//! injected faults are recorded in a `FaultLog`, so that a failing test can
+//! report the faults that led to the failure.
+
+use ic_types::artifact::{Advert, ArtifactKind};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A fault injected into a run of a client or into an advert.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    Delay(Duration),
+    Drop,
+    Duplicate,
+}
+
+/// The probabilities with which faults are injected, each between 0 and 1.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FaultRates {
+    /// The probability of a delay
+    pub delay: f64,
+
+    /// The maximum delay, the delays being drawn uniformly up to it
+    pub max_delay: Duration,
+
+    /// The probability of a drop
+    pub drop: f64,
+
+    /// The probability of a duplication
+    pub duplicate: f64,
+}
+
+impl Default for FaultRates {
+    /// No faults are injected.
+    fn default() -> Self {
+        Self {
+            delay: 0.0,
+            max_delay: Duration::from_millis(0),
+            drop: 0.0,
+            duplicate: 0.0,
+        }
+    }
+}
+
+/// The error returned for fault rates that are not probabilities, or whose
+/// sum exceeds 1.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InvalidFaultRates(pub FaultRates);
+
+impl std::fmt::Display for InvalidFaultRates {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Invalid fault rates {:?}: each rate must be between 0 and 1, and so must their sum",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for InvalidFaultRates {}
+
+impl FaultRates {
+    /// Checks that the rates are probabilities and that their sum is at most
+    /// 1.
+    fn validate(&self) -> Result<(), InvalidFaultRates> {
+        let is_probability = |rate: f64| (0.0..=1.0).contains(&rate);
+        if is_probability(self.delay)
+            && is_probability(self.drop)
+            && is_probability(self.duplicate)
+            && is_probability(self.delay + self.drop + self.duplicate)
+        {
+            Ok(())
+        } else {
+            Err(InvalidFaultRates(self.clone()))
+        }
+    }
+
+    /// Draws the fault, if any.
+    fn draw(&self, rng: &mut StdRng) -> Option<Fault> {
+        let sample: f64 = rng.gen();
+        if sample < self.drop {
+            Some(Fault::Drop)
+        } else if sample < self.drop + self.duplicate {
+            Some(Fault::Duplicate)
+        } else if sample < self.drop + self.duplicate + self.delay {
+            Some(Fault::Delay(self.max_delay.mul_f64(rng.gen::<f64>())))
+        } else {
+            None
+        }
+    }
+}
+
+/// The schedule of the faults injected into an artifact processor.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FaultSchedule {
+    /// The seed of the RNG drawing the faults
+    pub seed: u64,
+
+    /// The faults injected into the runs of the client
+    pub change_sets: FaultRates,
+
+    /// The faults injected into the adverts
+    pub adverts: FaultRates,
+}
+
+/// A fault that was injected.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InjectedFault {
+    /// The given run of the client, counting from 0, was faulty.
+    ChangeSet { run: u64, fault: Fault },
+
+    /// The advert with the given index among the adverts of the given run was
+    /// faulty.
+    Advert {
+        run: u64,
+        index: usize,
+        fault: Fault,
+    },
+}
+
+/// The injected faults, in the order in which they were injected.
+pub type FaultLog = Arc<Mutex<Vec<InjectedFault>>>;
+
+/// Injects faults into the runs and adverts of an artifact processor.
+pub struct FaultInjector<Artifact: ArtifactKind> {
+    schedule: FaultSchedule,
+    rng: StdRng,
+
+    /// The number of the current run
+    run: u64,
+
+    /// The delayed adverts, with the instants at which they are emitted
+    delayed_adverts: Vec<(Instant, Advert<Artifact>)>,
+
+    /// Clones duplicated adverts. Only the constructor requires adverts to be
+    /// `Clone`, so that the processors need not.
+    clone_advert: fn(&Advert<Artifact>) -> Advert<Artifact>,
+
+    log: FaultLog,
+}
+
+impl<Artifact: ArtifactKind> FaultInjector<Artifact> {
+    /// Creates an injector following the schedule, which records the injected
+    /// faults in the given log. Fails if the rates of the schedule are
+    /// invalid.
+    pub fn new(schedule: FaultSchedule, log: FaultLog) -> Result<Self, InvalidFaultRates>
+    where
+        Advert<Artifact>: Clone,
+    {
+        schedule.change_sets.validate()?;
+        schedule.adverts.validate()?;
+        Ok(Self {
+            rng: StdRng::seed_from_u64(schedule.seed),
+            schedule,
+            run: 0,
+            delayed_adverts: Vec::new(),
+            clone_advert: Advert::<Artifact>::clone,
+            log,
+        })
+    }
+
+    /// Starts the next run and returns the fault injected into it, if any.
+    pub fn next_run(&mut self) -> Option<Fault> {
+        self.run += 1;
+        let fault = self.schedule.change_sets.draw(&mut self.rng)?;
+        self.record(InjectedFault::ChangeSet {
+            run: self.run - 1,
+            fault,
+        });
+        Some(fault)
+    }
+
+    /// Returns the adverts to emit at the end of the current run: the delayed
+    /// adverts whose delay has passed, and the given adverts of the run that
+    /// are neither delayed nor dropped.
+    pub fn intercept_adverts(&mut self, adverts: Vec<Advert<Artifact>>) -> Vec<Advert<Artifact>> {
+        let now = Instant::now();
+        let (due, delayed): (Vec<_>, Vec<_>) = std::mem::take(&mut self.delayed_adverts)
+            .into_iter()
+            .partition(|(emit_at, _)| *emit_at <= now);
+        self.delayed_adverts = delayed;
+        let mut emitted: Vec<_> = due.into_iter().map(|(_, advert)| advert).collect();
+        for (index, advert) in adverts.into_iter().enumerate() {
+            let fault = self.schedule.adverts.draw(&mut self.rng);
+            if let Some(fault) = fault {
+                self.record(InjectedFault::Advert {
+                    run: self.run.saturating_sub(1),
+                    index,
+                    fault,
+                });
+            }
+            match fault {
+                None => emitted.push(advert),
+                Some(Fault::Drop) => {}
+                Some(Fault::Duplicate) => {
+                    emitted.push((self.clone_advert)(&advert));
+                    emitted.push(advert);
+                }
+                Some(Fault::Delay(delay)) => self.delayed_adverts.push((now + delay, advert)),
+            }
+        }
+        emitted
+    }
+
+    fn record(&self, fault: InjectedFault) {
+        self.log.lock().unwrap().push(fault);
+    }
+}
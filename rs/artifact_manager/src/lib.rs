@@ -82,13 +82,16 @@ mod unformatted {
 //!        clients::ConsensusClient, etc implement the per-client sync part
 //!
 //!     2. Async: Processes the received artifacts via on_artifact(). The new artifacts are
-//!        queued to a background worker thread. The thread runs a loop that calls into the
+//!        queued to a job on the processor scheduler. The scheduler runs the jobs of all
+//!        clients on a shared pool of worker threads, and each job calls into the
 //!        per-client ArtifactProcessor implementation with the newly received artifacts
 //!
-//!        a. processors::ArtifactProcessorManager manages the life cycle of these back ground
-//!           threads, and queues the requests to the background thread via a crossbeam channel
-//!        b. processors::ConsensusProcessor, etc implement the per-client ArtifactProcessor
-//!           logic called by the threads. These roughly perform the sequence: add the new
+//!        a. processors::ArtifactProcessorManager manages the life cycle of these jobs, and
+//!           requests the scheduler to run the job when new artifacts are queued
+//!        b. scheduler::ProcessorScheduler runs the jobs by priority, making sure that each
+//!           job is run within its deadline
+//!        c. processors::ConsensusProcessor, etc implement the per-client ArtifactProcessor
+//!           logic called by the jobs. These roughly perform the sequence: add the new
 //!           artifacts to the unvalidated pool, call the client.on_state_change(), apply the
 //!           returned changes(mutations) to the artifact pools
 //!
@@ -96,5 +99,8 @@ mod unformatted {
 
 pub mod artifact;
 pub mod clients;
+#[cfg(feature = "testing")]
+pub mod fault_injection;
 pub mod manager;
 pub mod processors;
+pub mod scheduler;
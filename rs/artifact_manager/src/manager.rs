@@ -19,6 +19,7 @@ use ic_types::{
 };
 use std::collections::HashMap;
 use std::convert::TryFrom;
+use std::fmt::{Display, Formatter, Result as FmtResult};
 use std::sync::Arc;
 
 /// The artifact manager maintains a list of artifact clients, and is generic in
@@ -131,6 +132,19 @@ impl ArtifactManager for ArtifactManagerImpl {
         adverts.collect()
     }
 
+    /// The method returns adverts of all validated artifacts by the filter
+    /// from the client identified by the given artifact tag.
+    fn get_all_validated_by_filter_for_tag(
+        &self,
+        tag: ArtifactTag,
+        filter: &artifact::ArtifactFilter,
+    ) -> Vec<p2p::GossipAdvert> {
+        self.clients
+            .get(&tag)
+            .map(|client| client.get_all_validated_by_filter(filter))
+            .unwrap_or_default()
+    }
+
     /// The method returns the remaining quota the given peer is allowed to
     /// consume for a specific client that is identified by the given
     /// artifact tag.
@@ -142,6 +156,17 @@ impl ArtifactManager for ArtifactManagerImpl {
             .and_then(|client| client.get_remaining_quota(tag, peer_id))
     }
 
+    /// The method indicates whether the processor of the client identified by
+    /// the given artifact tag falls behind.
+    ///
+    /// See `ArtifactProcessorManager::has_backpressure` for more details.
+    fn has_backpressure(&self, tag: artifact::ArtifactTag) -> bool {
+        self.clients
+            .get(&tag)
+            .and_then(|client| client.has_backpressure(tag))
+            .unwrap_or(false)
+    }
+
     /// The method returns the priority function for a specific client that is
     /// identified by the given artifact tag.
     ///
@@ -152,18 +177,47 @@ impl ArtifactManager for ArtifactManagerImpl {
             .and_then(|client| client.get_priority_function(tag))
     }
 
-    /// The method returns the chunk tracker for an advert with the given ID.
+    /// The method returns the chunk tracker for the given advert.
     ///
-    /// See `ArtifactClient::get_chunk_tracker` for more details
+    /// See `ArtifactManager::get_chunk_tracker` for more details
     fn get_chunk_tracker(
         &self,
-        artifact_id: &artifact::ArtifactId,
+        advert: &p2p::GossipAdvert,
     ) -> Option<Box<dyn Chunkable + Send + Sync>> {
-        let tag: ArtifactTag = artifact_id.into();
+        let tag: ArtifactTag = (&advert.artifact_id).into();
 
         self.clients
             .get(&tag)
-            .and_then(|client| client.get_chunk_tracker(&artifact_id))
+            .and_then(|client| client.get_chunk_tracker(advert))
+    }
+}
+
+/// The error returned when an artifact client can not be registered.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ArtifactRegistrationError {
+    /// A client for the artifact kind with the given tag is already
+    /// registered.
+    AlreadyRegistered(ArtifactTag),
+}
+
+impl Display for ArtifactRegistrationError {
+    /// The method formats the error.
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            ArtifactRegistrationError::AlreadyRegistered(tag) => {
+                write!(f, "An artifact client for {} is already registered", tag)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ArtifactRegistrationError {}
+
+impl From<ArtifactRegistrationError> for std::io::Error {
+    /// The method converts the error into an `AlreadyExists` I/O error, as
+    /// returned when setting up the artifact manager.
+    fn from(err: ArtifactRegistrationError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::AlreadyExists, err)
     }
 }
 
@@ -185,13 +239,19 @@ impl ArtifactManagerMaker {
             clients: HashMap::new(),
         }
     }
-    /// The method adds a new `ArtifactClient` (that is already wrapped in
-    /// `Arc`) to be managed.
-    pub fn add_arc_client<Artifact: ArtifactKind + 'static>(
+
+    /// The method registers the client of the artifact kind `Artifact`,
+    /// together with the processor running it. Crates outside of the P2P
+    /// stack use this method to plug in clients of new artifact kinds.
+    ///
+    /// The method returns an `ArtifactRegistrationError::AlreadyRegistered` if
+    /// a client of the same artifact kind was registered before.
+    pub fn register<Artifact: ArtifactKind + 'static>(
         &mut self,
         client: Arc<dyn ArtifactClient<Artifact>>,
         processor: ArtifactProcessorManager<Artifact>,
-    ) where
+    ) -> Result<(), ArtifactRegistrationError>
+    where
         Artifact::SerializeAs: TryFrom<artifact::Artifact, Error = artifact::Artifact>,
         Artifact::Message: ChunkableArtifact + Send,
         Advert<Artifact>:
@@ -204,18 +264,30 @@ impl ArtifactManagerMaker {
         Artifact::Attribute: 'static,
     {
         let tag = Artifact::TAG;
+        if self.clients.contains_key(&tag) {
+            return Err(ArtifactRegistrationError::AlreadyRegistered(tag));
+        }
         self.clients.insert(
             tag,
             Box::new(ArtifactManagerBackendImpl { client, processor }),
         );
+        Ok(())
+    }
+
+    /// The method returns true if a client of the artifact kind `Artifact` is
+    /// registered.
+    pub fn is_registered<Artifact: ArtifactKind>(&self) -> bool {
+        self.clients.contains_key(&Artifact::TAG)
     }
 
-    /// The method adds a new `ArtifactClient` to be managed.
+    /// The method adds a new `ArtifactClient` to be managed, like
+    /// `register` does for a client that is already wrapped in `Arc`.
     pub fn add_client<Artifact: ArtifactKind + 'static, Client: 'static>(
         &mut self,
         client: Client,
         processor: ArtifactProcessorManager<Artifact>,
-    ) where
+    ) -> Result<(), ArtifactRegistrationError>
+    where
         Client: ArtifactClient<Artifact>,
         Artifact::SerializeAs: TryFrom<artifact::Artifact, Error = artifact::Artifact>,
         Artifact::Message: ChunkableArtifact + Send,
@@ -228,14 +300,7 @@ impl ArtifactManagerMaker {
             TryFrom<&'b artifact::ArtifactAttribute, Error = &'b artifact::ArtifactAttribute>,
         Artifact::Attribute: 'static,
     {
-        let tag = Artifact::TAG;
-        self.clients.insert(
-            tag,
-            Box::new(ArtifactManagerBackendImpl {
-                client: Arc::new(client) as Arc<_>,
-                processor,
-            }),
-        );
+        self.register(Arc::new(client) as Arc<_>, processor)
     }
 
     /// The method finishes the collection of `ArtifactClient` components and
@@ -1,22 +1,39 @@
 //! The tokio thread based implementation of `ArtifactProcessor`
 
-use crate::{artifact::*, clients};
-use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
-use ic_base_thread::async_safe_block_on_await;
+#[cfg(feature = "testing")]
+use crate::fault_injection::{Fault, FaultInjector, FaultLog, FaultSchedule, InvalidFaultRates};
+use crate::{
+    artifact::*,
+    clients,
+    scheduler::{JobId, ProcessorPriority, ProcessorScheduler, ScheduledJob, SchedulingPolicy},
+};
+use ic_config::artifact_pool::ArtifactProcessorQueueLimits;
 use ic_interfaces::{
     artifact_manager::{ArtifactProcessor, ProcessingResult},
     artifact_pool::UnvalidatedArtifact,
+    canister_http::{
+        CanisterHttp, CanisterHttpChangeAction, CanisterHttpGossip, MutableCanisterHttpPool,
+    },
     certification,
     certification::{Certifier, CertifierGossip, MutableCertificationPool},
     consensus::{Consensus, ConsensusGossip},
-    consensus_pool::{ChangeAction as ConsensusAction, ConsensusPoolCache, MutableConsensusPool},
+    consensus_pool::{
+        ChangeAction as ConsensusAction, ConsensusPool, ConsensusPoolCache, MutableConsensusPool,
+    },
     dkg::{ChangeAction as DkgChangeAction, Dkg, DkgGossip, MutableDkgPool},
-    ecdsa::{Ecdsa, EcdsaGossip, MutableEcdsaPool},
+    ecdsa::{Ecdsa, EcdsaChangeAction, EcdsaGossip, MutableEcdsaPool},
+    equivocation::{
+        Equivocation, EquivocationChangeAction, EquivocationGossip, MutableEquivocationPool,
+    },
     ingress_manager::IngressHandler,
     ingress_pool::{
-        ChangeAction as IngressAction, IngressPoolObject, IngressPoolSelect, MutableIngressPool,
-        SelectResult,
+        ChangeAction as IngressAction, IngressPoolLookup, IngressPoolObject, IngressPoolSelect,
+        MutableIngressPool, SelectResult, SnapshotIngressPool,
+    },
+    query_stats::{
+        MutableQueryStatsPool, QueryStatsChangeAction, QueryStatsGossip, QueryStatsHandler,
     },
+    remote_dkg::{MutableRemoteDkgPool, RemoteDkg, RemoteDkgChangeAction, RemoteDkgGossip},
     time_source::{SysTimeSource, TimeSource},
 };
 use ic_logger::{debug, warn, ReplicaLogger};
@@ -24,15 +41,13 @@ use ic_metrics::MetricsRegistry;
 use ic_types::{
     artifact::*,
     consensus::{certification::CertificationMessage, dkg, ConsensusMessage},
-    malicious_flags::MaliciousFlags,
+    malicious_strategies::MaliciousBehaviors,
     messages::SignedIngress,
     Time,
 };
-use prometheus::{histogram_opts, labels, Histogram, IntCounter};
-use std::sync::atomic::{AtomicBool, Ordering::SeqCst};
+use prometheus::{histogram_opts, labels, opts, Histogram, IntCounter, IntGauge};
 use std::sync::{Arc, Mutex, RwLock};
-
-use tokio::task::JoinHandle;
+use std::time::Duration;
 
 /// A client may be either wrapped in `Box` or `Arc`.
 pub enum BoxOrArcClient<Artifact: ArtifactKind> {
@@ -63,6 +78,14 @@ struct ArtifactProcessorMetrics {
     processing_time: Histogram,
     /// The processing interval histogram.
     processing_interval: Histogram,
+    /// The number of artifacts waiting to be processed.
+    pending_artifacts: IntGauge,
+    /// The number of artifacts rejected because the queue was full.
+    rejected_artifacts: IntCounter,
+    /// The scheduling delay histogram.
+    scheduling_delay: Histogram,
+    /// The number of runs that started after the deadline.
+    missed_deadlines: IntCounter,
     /// The last update time.
     last_update: std::time::Instant,
 }
@@ -90,6 +113,39 @@ impl ArtifactProcessorMetrics {
                     0.0, 0.1, 0.2, 0.3, 0.4, 0.5, 0.6, 0.8, 1.0, 1.2, 1.5, 2.0, 2.2, 2.5, 5.0, 8.0,
                     10.0, 15.0, 20.0, 50.0,
                 ],
+                labels! {"client".to_string() => client.clone()}
+            ))
+            .unwrap(),
+        );
+        let pending_artifacts = metrics_registry.register(
+            IntGauge::with_opts(opts!(
+                "artifact_manager_client_pending_artifacts",
+                "Number of artifacts waiting to be processed by the artifact manager client",
+                labels! {"client".to_string() => client.clone()}
+            ))
+            .unwrap(),
+        );
+        let scheduling_delay = metrics_registry.register(
+            Histogram::with_opts(histogram_opts!(
+                "artifact_manager_client_scheduling_delay_seconds",
+                "Delay between an artifact manager client becoming due and its processing, in seconds",
+                vec![0.0, 0.001, 0.005, 0.01, 0.05, 0.1, 0.2, 0.5, 1.0, 2.0, 5.0, 10.0],
+                labels! {"client".to_string() => client.clone()}
+            ))
+            .unwrap(),
+        );
+        let missed_deadlines = metrics_registry.register(
+            IntCounter::with_opts(opts!(
+                "artifact_manager_client_missed_deadlines_total",
+                "Number of artifact manager client processing runs started after the deadline",
+                labels! {"client".to_string() => client.clone()}
+            ))
+            .unwrap(),
+        );
+        let rejected_artifacts = metrics_registry.register(
+            IntCounter::with_opts(opts!(
+                "artifact_manager_client_rejected_artifacts_total",
+                "Number of artifacts rejected because the queue of the artifact manager client was full",
                 labels! {"client".to_string() => client}
             ))
             .unwrap(),
@@ -98,6 +154,10 @@ impl ArtifactProcessorMetrics {
         Self {
             processing_time,
             processing_interval,
+            pending_artifacts,
+            rejected_artifacts,
+            scheduling_delay,
+            missed_deadlines,
             last_update: std::time::Instant::now(),
         }
     }
@@ -112,21 +172,137 @@ impl ArtifactProcessorMetrics {
     }
 }
 
-/// Pokes the thread to run on_state_change()
-struct ProcessRequest;
+/// The scheduling policy of the processor of the artifact kind with the given
+/// tag.
+///
+/// *Consensus* and certification are on the critical path of block making and
+/// run first, at least every 200ms, as do the ingress messages that go into
+/// the blocks. DKG runs with a low priority, as its work may take long but is
+/// only needed well ahead of the next DKG interval, and so do the remote DKG
+/// messages, which change once per interval; both run at least every second.
+/// Equivocation proofs are not needed for progress, but should be included in
+/// blocks while they are recent, and canister HTTP responses before their
+/// requests time out, as should XNet stream slices received over gossip; they
+/// run at least every 500ms. Query statistics are reported once per epoch and
+/// have a whole epoch to be included in a block, and state sync only advances
+/// when chunks arrive; they run at least every second. Registry deltas
+/// received over gossip are only a fallback for nodes that cannot reach the
+/// NNS, and change rarely, so they run at least every 5 seconds.
+pub fn scheduling_policy(tag: ArtifactTag) -> SchedulingPolicy {
+    let (priority, deadline_ms) = match tag {
+        ArtifactTag::ConsensusArtifact | ArtifactTag::CertificationArtifact => {
+            (ProcessorPriority::High, 200)
+        }
+        ArtifactTag::IngressArtifact => (ProcessorPriority::Normal, 200),
+        ArtifactTag::EcdsaArtifact
+        | ArtifactTag::EquivocationArtifact
+        | ArtifactTag::CanisterHttpArtifact
+        | ArtifactTag::XNetStreamSliceArtifact => (ProcessorPriority::Normal, 500),
+        ArtifactTag::DkgArtifact
+        | ArtifactTag::RemoteDkgArtifact
+        | ArtifactTag::QueryStatsArtifact
+        | ArtifactTag::FileTreeSyncArtifact
+        | ArtifactTag::StateSyncArtifact => (ProcessorPriority::Low, 1000),
+        ArtifactTag::RegistryDeltaArtifact => (ProcessorPriority::Low, 5000),
+    };
+    SchedulingPolicy {
+        priority,
+        deadline: Duration::from_millis(deadline_ms),
+    }
+}
+
+/// The job running `on_state_change()` of a client on the processor scheduler.
+struct ProcessorJob<Artifact: ArtifactKind + 'static, S> {
+    /// The list of unvalidated artifacts.
+    pending_artifacts: Arc<Mutex<Vec<UnvalidatedArtifact<Artifact::Message>>>>,
+    /// The time source.
+    time_source: Arc<SysTimeSource>,
+    /// The client.
+    client: BoxOrArcClient<Artifact>,
+    /// The function sending the adverts of the client.
+    send_advert: S,
+    /// The deadline of the client.
+    deadline: Duration,
+    /// The processor metrics.
+    metrics: ArtifactProcessorMetrics,
+    /// Injects faults into the runs and adverts, in tests.
+    #[cfg(feature = "testing")]
+    fault_injector: Option<FaultInjector<Artifact>>,
+}
 
-/// Manages the life cycle of the client specific artifact processor thread.
-/// Also serves as the front end to enqueue requests to the processor thread.
+impl<Artifact: ArtifactKind + 'static, S: Fn(Advert<Artifact>) + Send + 'static> ScheduledJob
+    for ProcessorJob<Artifact, S>
+where
+    <Artifact as ic_types::artifact::ArtifactKind>::Message: Send,
+{
+    fn run(&mut self, delay: Duration) -> ProcessingResult {
+        self.metrics.scheduling_delay.observe(delay.as_secs_f64());
+        if delay > self.deadline {
+            self.metrics.missed_deadlines.inc();
+        }
+        self.time_source.update_time().ok();
+
+        #[cfg(feature = "testing")]
+        let fault = self.fault_injector.as_mut().and_then(|f| f.next_run());
+        #[cfg(feature = "testing")]
+        match fault {
+            // The received artifacts stay queued for the next run.
+            Some(Fault::Drop) => return ProcessingResult::StateUnchanged,
+            Some(Fault::Delay(delay)) => std::thread::sleep(delay),
+            _ => {}
+        }
+
+        let artifacts = {
+            let mut artifacts = Vec::new();
+            let mut received_artifacts = self.pending_artifacts.lock().unwrap();
+            std::mem::swap(&mut artifacts, &mut received_artifacts);
+            self.metrics.pending_artifacts.set(0);
+            artifacts
+        };
+
+        let client = &self.client;
+        let time_source = self.time_source.as_ref();
+        #[allow(unused_mut)]
+        let (mut adverts, mut result) = self
+            .metrics
+            .with_metrics(|| client.process_changes(time_source, artifacts));
+        #[cfg(feature = "testing")]
+        {
+            if fault == Some(Fault::Duplicate) {
+                let (duplicate_adverts, duplicate_result) = self
+                    .metrics
+                    .with_metrics(|| client.process_changes(time_source, Vec::new()));
+                adverts.extend(duplicate_adverts);
+                if duplicate_result == ProcessingResult::StateChanged {
+                    result = ProcessingResult::StateChanged;
+                }
+            }
+            if let Some(fault_injector) = self.fault_injector.as_mut() {
+                adverts = fault_injector.intercept_adverts(adverts);
+            }
+        }
+        adverts.into_iter().for_each(&self.send_advert);
+        result
+    }
+}
+
+/// Manages the life cycle of the client specific artifact processor job on the
+/// processor scheduler. Also serves as the front end to enqueue requests to the
+/// processor.
 pub struct ArtifactProcessorManager<Artifact: ArtifactKind + 'static> {
     /// The list of unvalidated artifacts.
     pending_artifacts: Arc<Mutex<Vec<UnvalidatedArtifact<Artifact::Message>>>>,
-    /// To send the process requests
-    sender: Sender<ProcessRequest>,
-    /// Handle for the processing thread
-    handle: Option<JoinHandle<()>>,
-    /// To signal processing thread to exit.
-    /// TODO: handle.abort() does not seem to work as expected
-    shutdown: Arc<AtomicBool>,
+    /// The backpressure threshold and the capacity of the queue of pending
+    /// artifacts.
+    queue_limits: ArtifactProcessorQueueLimits,
+    /// The gauge tracking the number of pending artifacts.
+    pending_artifacts_gauge: IntGauge,
+    /// The counter of the artifacts rejected because the queue was full.
+    rejected_artifacts: IntCounter,
+    /// The scheduler running the processor job.
+    scheduler: Arc<ProcessorScheduler>,
+    /// The ID of the processor job.
+    job_id: JobId,
 }
 
 impl<Artifact: ArtifactKind + 'static> ArtifactProcessorManager<Artifact> {
@@ -135,107 +311,164 @@ impl<Artifact: ArtifactKind + 'static> ArtifactProcessorManager<Artifact> {
         metrics_registry: MetricsRegistry,
         client: BoxOrArcClient<Artifact>,
         send_advert: S,
-        rt_handle: tokio::runtime::Handle,
+        scheduler: Arc<ProcessorScheduler>,
+    ) -> Self
+    where
+        <Artifact as ic_types::artifact::ArtifactKind>::Message: Send,
+    {
+        Self::with_job(
+            time_source,
+            metrics_registry,
+            client,
+            send_advert,
+            scheduler,
+            #[cfg(feature = "testing")]
+            None,
+        )
+    }
+
+    /// Creates a processor that injects faults into the runs of the client
+    /// and into its adverts, following the schedule. The injected faults are
+    /// recorded in the given log. Fails if the rates of the schedule are
+    /// invalid.
+    #[cfg(feature = "testing")]
+    pub fn with_fault_injection<S: Fn(Advert<Artifact>) + Send + 'static>(
+        time_source: Arc<SysTimeSource>,
+        metrics_registry: MetricsRegistry,
+        client: BoxOrArcClient<Artifact>,
+        send_advert: S,
+        scheduler: Arc<ProcessorScheduler>,
+        schedule: FaultSchedule,
+        fault_log: FaultLog,
+    ) -> Result<Self, InvalidFaultRates>
+    where
+        <Artifact as ic_types::artifact::ArtifactKind>::Message: Send,
+        Advert<Artifact>: Clone,
+    {
+        let fault_injector = FaultInjector::new(schedule, fault_log)?;
+        Ok(Self::with_job(
+            time_source,
+            metrics_registry,
+            client,
+            send_advert,
+            scheduler,
+            Some(fault_injector),
+        ))
+    }
+
+    fn with_job<S: Fn(Advert<Artifact>) + Send + 'static>(
+        time_source: Arc<SysTimeSource>,
+        metrics_registry: MetricsRegistry,
+        client: BoxOrArcClient<Artifact>,
+        send_advert: S,
+        scheduler: Arc<ProcessorScheduler>,
+        #[cfg(feature = "testing")] fault_injector: Option<FaultInjector<Artifact>>,
     ) -> Self
     where
         <Artifact as ic_types::artifact::ArtifactKind>::Message: Send,
     {
         let pending_artifacts = Arc::new(Mutex::new(Vec::new()));
-        let (sender, receiver) = crossbeam_channel::unbounded();
-        let shutdown = Arc::new(AtomicBool::new(false));
-
-        // Spawn the processor thread
-        let sender_cl = sender.clone();
-        let pending_artifacts_cl = pending_artifacts.clone();
-        let shutdown_cl = shutdown.clone();
-        let handle = rt_handle.spawn_blocking(move || {
-            Self::process_messages(
-                pending_artifacts_cl,
+        let metrics = ArtifactProcessorMetrics::new(metrics_registry, Artifact::TAG.to_string());
+        let pending_artifacts_gauge = metrics.pending_artifacts.clone();
+        let rejected_artifacts = metrics.rejected_artifacts.clone();
+        let queue_limits = scheduler.queue_limits();
+        let policy = scheduling_policy(Artifact::TAG);
+        let job_id = scheduler.register(
+            policy,
+            Box::new(ProcessorJob {
+                pending_artifacts: pending_artifacts.clone(),
                 time_source,
                 client,
-                Box::new(send_advert),
-                sender_cl,
-                receiver,
-                ArtifactProcessorMetrics::new(metrics_registry, Artifact::TAG.to_string()),
-                shutdown_cl,
-            );
-        });
+                send_advert,
+                deadline: policy.deadline,
+                metrics,
+                #[cfg(feature = "testing")]
+                fault_injector,
+            }),
+        );
 
         Self {
             pending_artifacts,
-            sender,
-            handle: Some(handle),
-            shutdown,
+            queue_limits,
+            pending_artifacts_gauge,
+            rejected_artifacts,
+            scheduler,
+            job_id,
         }
     }
 
-    pub fn on_artifact(&self, artifact: UnvalidatedArtifact<Artifact::Message>) {
-        let mut pending_artifacts = self.pending_artifacts.lock().unwrap();
-        pending_artifacts.push(artifact);
-        self.sender
-            .send(ProcessRequest)
-            .unwrap_or_else(|err| panic!("Failed to send request: {:?}", err));
-    }
-
-    // The artifact processor thread loop
-    #[allow(clippy::too_many_arguments)]
-    fn process_messages<S: Fn(Advert<Artifact>) + Send + 'static>(
-        pending_artifacts: Arc<Mutex<Vec<UnvalidatedArtifact<Artifact::Message>>>>,
-        time_source: Arc<SysTimeSource>,
-        client: BoxOrArcClient<Artifact>,
-        send_advert: Box<S>,
-        sender: Sender<ProcessRequest>,
-        receiver: Receiver<ProcessRequest>,
-        mut metrics: ArtifactProcessorMetrics,
-        shutdown: Arc<AtomicBool>,
-    ) {
-        let recv_timeout = std::time::Duration::from_millis(ARTIFACT_MANAGER_TIMER_DURATION_MSEC);
-        loop {
-            let ret = receiver.recv_timeout(recv_timeout);
-            if shutdown.load(SeqCst) {
-                return;
-            }
-
-            match ret {
-                Ok(_) | Err(RecvTimeoutError::Timeout) => {
-                    time_source.update_time().ok();
-
-                    let artifacts = {
-                        let mut artifacts = Vec::new();
-                        let mut received_artifacts = pending_artifacts.lock().unwrap();
-                        std::mem::swap(&mut artifacts, &mut received_artifacts);
-                        artifacts
-                    };
-
-                    let (adverts, result) = metrics
-                        .with_metrics(|| client.process_changes(time_source.as_ref(), artifacts));
-
-                    if let ProcessingResult::StateChanged = result {
-                        // TODO: assess impact of continued processing in same
-                        // iteration if StateChanged, get rid of sending self messages
-                        sender
-                            .send(ProcessRequest)
-                            .unwrap_or_else(|err| panic!("Failed to send request: {:?}", err));
-                    }
-                    adverts.into_iter().for_each(&send_advert);
-                }
-                Err(RecvTimeoutError::Disconnected) => return,
+    /// The method enqueues the artifact for processing. If the queue is full,
+    /// the artifact is rejected and returned.
+    pub fn on_artifact(
+        &self,
+        artifact: UnvalidatedArtifact<Artifact::Message>,
+    ) -> Result<(), UnvalidatedArtifact<Artifact::Message>> {
+        {
+            let mut pending_artifacts = self.pending_artifacts.lock().unwrap();
+            if pending_artifacts.len() >= self.queue_limits.max_pending_artifacts {
+                self.rejected_artifacts.inc();
+                return Err(artifact);
             }
+            pending_artifacts.push(artifact);
+            self.pending_artifacts_gauge
+                .set(pending_artifacts.len() as i64);
         }
+        self.scheduler.request_run(self.job_id);
+        Ok(())
+    }
+
+    /// The method returns `true` if the processor falls behind, i.e., the
+    /// number of artifacts waiting to be processed reached the backpressure
+    /// threshold.
+    ///
+    /// *Gossip* uses this signal to stop requesting chunks of artifacts of this
+    /// type until the processor catches up. The downloads that are in flight
+    /// when the backpressure starts still complete, which is why the queue
+    /// only rejects artifacts at the higher `max_pending_artifacts` limit.
+    pub fn has_backpressure(&self) -> bool {
+        self.pending_artifacts.lock().unwrap().len() >= self.queue_limits.backpressure_threshold
     }
 }
 
 impl<Artifact: ArtifactKind + 'static> Drop for ArtifactProcessorManager<Artifact> {
     fn drop(&mut self) {
-        if let Some(handle) = self.handle.take() {
-            self.shutdown.store(true, SeqCst);
-            async_safe_block_on_await(handle).unwrap();
-        }
+        self.scheduler.unregister(self.job_id);
     }
 }
 
-/// Periodic duration of `PollEvent` in milliseconds.
-const ARTIFACT_MANAGER_TIMER_DURATION_MSEC: u64 = 200;
+/// Decides whether this replica produces artifacts of its own, i.e. whether the
+/// processors apply the `AddToValidated` actions of their clients. While the
+/// switch is off, the processors still validate and apply the artifacts of the
+/// peers, but drop the blocks, shares and dealings that the clients created.
+#[derive(Clone)]
+pub struct ProductionSwitch(Option<Arc<dyn Fn() -> bool + Send + Sync>>);
+
+impl ProductionSwitch {
+    /// The switch of regular replicas, which always produce artifacts.
+    pub fn always() -> Self {
+        Self(None)
+    }
+
+    /// Creates a switch that is on while the given function returns true, e.g.
+    /// while this replica is a member of the subnet.
+    pub fn new<F: Fn() -> bool + Send + Sync + 'static>(is_on: F) -> Self {
+        Self(Some(Arc::new(is_on)))
+    }
+
+    /// Returns true if the artifacts produced by this replica are applied.
+    pub fn is_on(&self) -> bool {
+        self.0.as_ref().map_or(true, |is_on| is_on())
+    }
+
+    /// Removes the actions that add artifacts produced by this replica from
+    /// the change set, unless the switch is on.
+    fn filter<A>(&self, mut change_set: Vec<A>, is_produced: fn(&A) -> bool) -> Vec<A> {
+        if !self.is_on() {
+            change_set.retain(|action| !is_produced(action));
+        }
+        change_set
+    }
+}
 
 /// *Consensus* `OnStateChange` client.
 pub struct ConsensusProcessor<PoolConsensus, PoolIngress> {
@@ -245,6 +478,8 @@ pub struct ConsensusProcessor<PoolConsensus, PoolIngress> {
     ingress_pool: Arc<RwLock<PoolIngress>>,
     /// The *Consensus* client.
     client: Box<dyn Consensus>,
+    /// Decides whether the artifacts produced by this replica are applied.
+    production: ProductionSwitch,
     /// The invalidated artifacts counter.
     invalidated_artifacts: IntCounter,
     /// The logger.
@@ -253,7 +488,7 @@ pub struct ConsensusProcessor<PoolConsensus, PoolIngress> {
 
 impl<
         PoolConsensus: MutableConsensusPool + Send + Sync + 'static,
-        PoolIngress: IngressPoolSelect + Send + Sync + 'static,
+        PoolIngress: SnapshotIngressPool + Send + Sync + 'static,
     > ConsensusProcessor<PoolConsensus, PoolIngress>
 {
     #[allow(clippy::too_many_arguments)]
@@ -268,9 +503,10 @@ impl<
         time_source: Arc<SysTimeSource>,
         consensus_pool: Arc<RwLock<PoolConsensus>>,
         ingress_pool: Arc<RwLock<PoolIngress>>,
-        rt_handle: tokio::runtime::Handle,
+        scheduler: Arc<ProcessorScheduler>,
         log: ReplicaLogger,
         metrics_registry: MetricsRegistry,
+        production: ProductionSwitch,
     ) -> (
         clients::ConsensusClient<PoolConsensus>,
         ArtifactProcessorManager<ConsensusArtifact>,
@@ -280,6 +516,7 @@ impl<
             consensus_pool: consensus_pool.clone(),
             ingress_pool,
             client: Box::new(consensus),
+            production,
             invalidated_artifacts: metrics_registry.int_counter(
                 "consensus_invalidated_artifacts",
                 "The number of invalidated consensus artifacts",
@@ -291,7 +528,7 @@ impl<
             metrics_registry,
             BoxOrArcClient::BoxClient(Box::new(client)),
             send_advert,
-            rt_handle,
+            scheduler,
         );
         (
             clients::ConsensusClient::new(consensus_pool, consensus_gossip),
@@ -302,7 +539,7 @@ impl<
 
 impl<
         PoolConsensus: MutableConsensusPool + Send + Sync,
-        PoolIngress: IngressPoolSelect + Send + Sync + 'static,
+        PoolIngress: SnapshotIngressPool + Send + Sync + 'static,
     > ArtifactProcessor<ConsensusArtifact> for ConsensusProcessor<PoolConsensus, PoolIngress>
 {
     /// The method processes changes in the *Consensus* pool and ingress pool.
@@ -328,7 +565,10 @@ impl<
             let consensus_pool = self.consensus_pool.read().unwrap();
             let ingress_pool = Arc::clone(&self.ingress_pool) as Arc<_>;
             let ingress_pool = IngressPoolSelectWrapper::new(&ingress_pool);
-            self.client.on_state_change(&*consensus_pool, &ingress_pool)
+            self.production.filter(
+                self.client.on_state_change(&*consensus_pool, &ingress_pool),
+                |action| matches!(action, ConsensusAction::AddToValidated(_)),
+            )
         };
         let changed = if !change_set.is_empty() {
             ProcessingResult::StateChanged
@@ -377,14 +617,15 @@ impl<
 }
 
 /// A wrapper for the ingress pool that delays locking until the member function
-/// of `IngressPoolSelect` is actually called.
+/// of `IngressPoolSelect` is actually called, and then only holds the lock
+/// while taking a snapshot of the pool.
 struct IngressPoolSelectWrapper {
-    pool: std::sync::Arc<std::sync::RwLock<dyn IngressPoolSelect>>,
+    pool: std::sync::Arc<std::sync::RwLock<dyn SnapshotIngressPool>>,
 }
 
 impl IngressPoolSelectWrapper {
     /// The constructor creates a `IngressPoolSelectWrapper` instance.
-    pub fn new(pool: &std::sync::Arc<std::sync::RwLock<dyn IngressPoolSelect>>) -> Self {
+    pub fn new(pool: &std::sync::Arc<std::sync::RwLock<dyn SnapshotIngressPool>>) -> Self {
         IngressPoolSelectWrapper { pool: pool.clone() }
     }
 }
@@ -396,8 +637,8 @@ impl IngressPoolSelect for IngressPoolSelectWrapper {
         range: std::ops::RangeInclusive<Time>,
         f: Box<dyn FnMut(&IngressPoolObject) -> SelectResult<SignedIngress> + 'a>,
     ) -> Vec<SignedIngress> {
-        let pool = self.pool.read().unwrap();
-        pool.select_validated(range, f)
+        let snapshot = self.pool.read().unwrap().snapshot();
+        snapshot.select_validated(range, f)
     }
 }
 
@@ -416,17 +657,18 @@ impl<Pool: MutableIngressPool + Send + Sync + 'static> IngressProcessor<Pool> {
         send_advert: S,
         time_source: Arc<SysTimeSource>,
         ingress_pool: Arc<RwLock<Pool>>,
+        ingress_pool_lookup: Arc<dyn IngressPoolLookup>,
         ingress_handler: Arc<dyn IngressHandler + Send + Sync>,
-        rt_handle: tokio::runtime::Handle,
+        scheduler: Arc<ProcessorScheduler>,
         log: ReplicaLogger,
         metrics_registry: MetricsRegistry,
-        malicious_flags: MaliciousFlags,
+        malicious_behaviors: MaliciousBehaviors,
     ) -> (
-        clients::IngressClient<Pool>,
+        clients::IngressClient,
         ArtifactProcessorManager<IngressArtifact>,
     ) {
         let client = Self {
-            ingress_pool: ingress_pool.clone(),
+            ingress_pool,
             client: ingress_handler,
         };
         let manager = ArtifactProcessorManager::new(
@@ -434,10 +676,10 @@ impl<Pool: MutableIngressPool + Send + Sync + 'static> IngressProcessor<Pool> {
             metrics_registry,
             BoxOrArcClient::BoxClient(Box::new(client)),
             send_advert,
-            rt_handle,
+            scheduler,
         );
         (
-            clients::IngressClient::new(time_source, ingress_pool, log, malicious_flags),
+            clients::IngressClient::new(time_source, ingress_pool_lookup, log, malicious_behaviors),
             manager,
         )
     }
@@ -495,6 +737,8 @@ pub struct CertificationProcessor<PoolCertification> {
     certification_pool: Arc<RwLock<PoolCertification>>,
     /// The certifier.
     client: Box<dyn Certifier>,
+    /// Decides whether the artifacts produced by this replica are applied.
+    production: ProductionSwitch,
     /// The invalidated artifacts counter.
     invalidated_artifacts: IntCounter,
     /// The logger.
@@ -516,9 +760,10 @@ impl<PoolCertification: MutableCertificationPool + Send + Sync + 'static>
         time_source: Arc<SysTimeSource>,
         consensus_pool_cache: Arc<dyn ConsensusPoolCache>,
         certification_pool: Arc<RwLock<PoolCertification>>,
-        rt_handle: tokio::runtime::Handle,
+        scheduler: Arc<ProcessorScheduler>,
         log: ReplicaLogger,
         metrics_registry: MetricsRegistry,
+        production: ProductionSwitch,
     ) -> (
         clients::CertificationClient<PoolCertification>,
         ArtifactProcessorManager<CertificationArtifact>,
@@ -528,6 +773,7 @@ impl<PoolCertification: MutableCertificationPool + Send + Sync + 'static>
             consensus_pool_cache: consensus_pool_cache.clone(),
             certification_pool: certification_pool.clone(),
             client: Box::new(certifier),
+            production,
             invalidated_artifacts: metrics_registry.int_counter(
                 "certification_invalidated_artifacts",
                 "The number of invalidated certification artifacts",
@@ -539,7 +785,7 @@ impl<PoolCertification: MutableCertificationPool + Send + Sync + 'static>
             metrics_registry,
             BoxOrArcClient::BoxClient(Box::new(client)),
             send_advert,
-            rt_handle,
+            scheduler,
         );
         (
             clients::CertificationClient::new(
@@ -568,9 +814,12 @@ impl<PoolCertification: MutableCertificationPool + Send + Sync + 'static>
             }
         }
         let mut adverts = Vec::new();
-        let change_set = self.client.on_state_change(
-            self.consensus_pool_cache.as_ref(),
-            self.certification_pool.clone(),
+        let change_set = self.production.filter(
+            self.client.on_state_change(
+                self.consensus_pool_cache.as_ref(),
+                self.certification_pool.clone(),
+            ),
+            |action| matches!(action, certification::ChangeAction::AddToValidated(_)),
         );
         let changed = if !change_set.is_empty() {
             ProcessingResult::StateChanged
@@ -611,6 +860,8 @@ pub struct DkgProcessor<PoolDkg> {
     dkg_pool: Arc<RwLock<PoolDkg>>,
     /// The DKG client.
     client: Box<dyn Dkg>,
+    /// Decides whether the artifacts produced by this replica are applied.
+    production: ProductionSwitch,
     /// The invalidated artifacts counter.
     invalidated_artifacts: IntCounter,
     /// The logger.
@@ -629,9 +880,10 @@ impl<PoolDkg: MutableDkgPool + Send + Sync + 'static> DkgProcessor<PoolDkg> {
         setup: F,
         time_source: Arc<SysTimeSource>,
         dkg_pool: Arc<RwLock<PoolDkg>>,
-        rt_handle: tokio::runtime::Handle,
+        scheduler: Arc<ProcessorScheduler>,
         log: ReplicaLogger,
         metrics_registry: MetricsRegistry,
+        production: ProductionSwitch,
     ) -> (
         clients::DkgClient<PoolDkg>,
         ArtifactProcessorManager<DkgArtifact>,
@@ -640,6 +892,7 @@ impl<PoolDkg: MutableDkgPool + Send + Sync + 'static> DkgProcessor<PoolDkg> {
         let client = Self {
             dkg_pool: dkg_pool.clone(),
             client: Box::new(dkg),
+            production,
             invalidated_artifacts: metrics_registry.int_counter(
                 "dkg_invalidated_artifacts",
                 "The number of invalidated DKG artifacts",
@@ -651,7 +904,7 @@ impl<PoolDkg: MutableDkgPool + Send + Sync + 'static> DkgProcessor<PoolDkg> {
             metrics_registry,
             BoxOrArcClient::BoxClient(Box::new(client)),
             send_advert,
-            rt_handle,
+            scheduler,
         );
         (clients::DkgClient::new(dkg_pool, dkg_gossip), manager)
     }
@@ -675,7 +928,11 @@ impl<PoolDkg: MutableDkgPool + Send + Sync + 'static> ArtifactProcessor<DkgArtif
         let mut adverts = Vec::new();
         let change_set = {
             let dkg_pool = self.dkg_pool.read().unwrap();
-            let change_set = self.client.on_state_change(&*dkg_pool);
+            let change_set = self
+                .production
+                .filter(self.client.on_state_change(&*dkg_pool), |action| {
+                    matches!(action, DkgChangeAction::AddToValidated(_))
+                });
             for change_action in change_set.iter() {
                 match change_action {
                     DkgChangeAction::AddToValidated(to_add) => {
@@ -704,13 +961,345 @@ impl<PoolDkg: MutableDkgPool + Send + Sync + 'static> ArtifactProcessor<DkgArtif
     }
 }
 
-/// ECDSA `OnStateChange` client.
-pub struct EcdsaProcessor<PoolEcdsa> {
-    ecdsa_pool: Arc<RwLock<PoolEcdsa>>,
-    client: Box<dyn Ecdsa>,
+/// A pool of gossiped messages that is processed by a `PoolProcessor`.
+pub trait ProcessedPool<Artifact: ArtifactKind> {
+    /// The actions of the change sets applied to the pool.
+    type ChangeAction: ProcessedChangeAction<Artifact>;
+
+    /// Inserts the given artifact into the unvalidated section of the pool.
+    fn insert(&mut self, artifact: UnvalidatedArtifact<Artifact::Message>);
+
+    /// Applies the given change set to the pool.
+    fn apply_changes(&mut self, change_set: Vec<Self::ChangeAction>);
+}
+
+/// An action of a change set applied by a `PoolProcessor`.
+pub trait ProcessedChangeAction<Artifact: ArtifactKind> {
+    /// Returns true if the action adds an artifact produced by this replica.
+    fn is_produced(&self) -> bool;
+
+    /// Returns the adverts of the artifacts the action validates.
+    fn adverts(&self) -> Vec<Advert<Artifact>>;
+
+    /// Returns the ID of the invalid artifact and the reason why it is
+    /// invalid, if the action handles an invalid artifact.
+    fn invalid(&self) -> Option<(&Artifact::Id, &str)>;
+}
+
+/// The `OnStateChange` client of a pool of gossiped messages, which validates
+/// the received messages and adds the ones produced by this replica.
+pub struct PoolProcessor<Artifact: ArtifactKind, Pool: ProcessedPool<Artifact>> {
+    /// The pool, protected by a read-write lock and automatic reference
+    /// counting.
+    pool: Arc<RwLock<Pool>>,
+    /// Computes the change set of the pool.
+    on_state_change: Box<dyn Fn(&Pool) -> Vec<Pool::ChangeAction> + Send>,
+    /// Decides whether the artifacts produced by this replica are applied.
+    production: ProductionSwitch,
+    /// The invalidated artifacts counter.
+    invalidated_artifacts: IntCounter,
+    /// The description of the artifacts in the logs, e.g. "equivocation
+    /// proof".
+    description: &'static str,
+    /// The logger.
+    log: ReplicaLogger,
+}
+
+/// The equivocation `OnStateChange` client.
+pub type EquivocationProcessor<PoolEquivocation> =
+    PoolProcessor<EquivocationArtifact, PoolEquivocation>;
+
+/// The remote DKG `OnStateChange` client.
+pub type RemoteDkgProcessor<PoolRemoteDkg> = PoolProcessor<RemoteDkgArtifact, PoolRemoteDkg>;
+
+/// The canister HTTP `OnStateChange` client.
+pub type CanisterHttpProcessor<PoolCanisterHttp> =
+    PoolProcessor<CanisterHttpArtifact, PoolCanisterHttp>;
+
+/// The query statistics `OnStateChange` client.
+pub type QueryStatsProcessor<PoolQueryStats> = PoolProcessor<QueryStatsArtifact, PoolQueryStats>;
+
+/// The ECDSA `OnStateChange` client.
+pub type EcdsaProcessor<PoolEcdsa> = PoolProcessor<EcdsaArtifact, PoolEcdsa>;
+
+impl<Artifact, Pool> PoolProcessor<Artifact, Pool>
+where
+    Artifact: ArtifactKind + 'static,
+    Artifact::Id: std::fmt::Debug,
+    Pool: ProcessedPool<Artifact> + Send + Sync + 'static,
+{
+    /// The method creates the processor and its manager.
+    #[allow(clippy::too_many_arguments)]
+    fn new_manager<S: Fn(Advert<Artifact>) + Send + 'static>(
+        send_advert: S,
+        on_state_change: Box<dyn Fn(&Pool) -> Vec<Pool::ChangeAction> + Send>,
+        time_source: Arc<SysTimeSource>,
+        pool: Arc<RwLock<Pool>>,
+        scheduler: Arc<ProcessorScheduler>,
+        log: ReplicaLogger,
+        metrics_registry: MetricsRegistry,
+        production: ProductionSwitch,
+        metric_prefix: &str,
+        description: &'static str,
+    ) -> ArtifactProcessorManager<Artifact> {
+        let client = Self {
+            pool,
+            on_state_change,
+            production,
+            invalidated_artifacts: metrics_registry.int_counter(
+                format!("{}_invalidated_artifacts", metric_prefix),
+                format!("The number of invalidated {}s", description),
+            ),
+            description,
+            log,
+        };
+        ArtifactProcessorManager::new(
+            time_source,
+            metrics_registry,
+            BoxOrArcClient::BoxClient(Box::new(client)),
+            send_advert,
+            scheduler,
+        )
+    }
+}
+
+impl<Artifact, Pool> ArtifactProcessor<Artifact> for PoolProcessor<Artifact, Pool>
+where
+    Artifact: ArtifactKind + 'static,
+    Artifact::Id: std::fmt::Debug,
+    Pool: ProcessedPool<Artifact> + Send + Sync + 'static,
+{
+    /// The method inserts the received artifacts into the pool, and applies
+    /// the change set of the pool.
+    fn process_changes(
+        &self,
+        _time_source: &dyn TimeSource,
+        artifacts: Vec<UnvalidatedArtifact<Artifact::Message>>,
+    ) -> (Vec<Advert<Artifact>>, ProcessingResult) {
+        {
+            let mut pool = self.pool.write().unwrap();
+            for artifact in artifacts {
+                pool.insert(artifact)
+            }
+        }
+        let mut adverts = Vec::new();
+        let change_set = {
+            let pool = self.pool.read().unwrap();
+            let change_set = self.production.filter(
+                (self.on_state_change)(&*pool),
+                <Pool::ChangeAction as ProcessedChangeAction<Artifact>>::is_produced,
+            );
+            for change_action in change_set.iter() {
+                adverts.extend(change_action.adverts());
+                if let Some((id, reason)) = change_action.invalid() {
+                    self.invalidated_artifacts.inc();
+                    warn!(
+                        self.log,
+                        "Invalid {} ({:?}): {:?}", self.description, reason, id
+                    );
+                }
+            }
+            change_set
+        };
+        let changed = if !change_set.is_empty() {
+            ProcessingResult::StateChanged
+        } else {
+            ProcessingResult::StateUnchanged
+        };
+
+        self.pool.write().unwrap().apply_changes(change_set);
+        (adverts, changed)
+    }
+}
+
+impl<PoolEquivocation: MutableEquivocationPool + Send + Sync + 'static>
+    EquivocationProcessor<PoolEquivocation>
+{
+    /// The method detects equivocations in the consensus pool and validates
+    /// the received equivocation proofs.
+    #[allow(clippy::too_many_arguments)]
+    pub fn build<
+        PoolConsensus: ConsensusPool + Send + Sync + 'static,
+        C: Equivocation + 'static,
+        G: EquivocationGossip + 'static,
+        S: Fn(Advert<EquivocationArtifact>) + Send + 'static,
+        F: FnOnce() -> (C, G),
+    >(
+        send_advert: S,
+        setup: F,
+        time_source: Arc<SysTimeSource>,
+        consensus_pool: Arc<RwLock<PoolConsensus>>,
+        equivocation_pool: Arc<RwLock<PoolEquivocation>>,
+        scheduler: Arc<ProcessorScheduler>,
+        log: ReplicaLogger,
+        metrics_registry: MetricsRegistry,
+        production: ProductionSwitch,
+    ) -> (
+        clients::EquivocationClient<PoolEquivocation>,
+        ArtifactProcessorManager<EquivocationArtifact>,
+    ) {
+        let (equivocation, equivocation_gossip) = setup();
+        let manager = Self::new_manager(
+            send_advert,
+            Box::new(move |equivocation_pool: &PoolEquivocation| {
+                let consensus_pool = consensus_pool.read().unwrap();
+                equivocation.on_state_change(&*consensus_pool, equivocation_pool)
+            }),
+            time_source,
+            Arc::clone(&equivocation_pool),
+            scheduler,
+            log,
+            metrics_registry,
+            production,
+            "equivocation",
+            "equivocation proof",
+        );
+        (
+            clients::EquivocationClient::new(equivocation_pool, equivocation_gossip),
+            manager,
+        )
+    }
+}
+
+impl<PoolRemoteDkg: MutableRemoteDkgPool + Send + Sync + 'static>
+    RemoteDkgProcessor<PoolRemoteDkg>
+{
+    /// The method publishes the remote DKG messages of the latest DKG summary
+    /// and validates the received ones.
+    #[allow(clippy::too_many_arguments)]
+    pub fn build<
+        C: RemoteDkg + 'static,
+        G: RemoteDkgGossip + 'static,
+        S: Fn(Advert<RemoteDkgArtifact>) + Send + 'static,
+        F: FnOnce() -> (C, G),
+    >(
+        send_advert: S,
+        setup: F,
+        time_source: Arc<SysTimeSource>,
+        consensus_cache: Arc<dyn ConsensusPoolCache>,
+        remote_dkg_pool: Arc<RwLock<PoolRemoteDkg>>,
+        scheduler: Arc<ProcessorScheduler>,
+        log: ReplicaLogger,
+        metrics_registry: MetricsRegistry,
+        production: ProductionSwitch,
+    ) -> (
+        clients::RemoteDkgClient<PoolRemoteDkg>,
+        ArtifactProcessorManager<RemoteDkgArtifact>,
+    ) {
+        let (remote_dkg, remote_dkg_gossip) = setup();
+        let manager = Self::new_manager(
+            send_advert,
+            Box::new(move |remote_dkg_pool: &PoolRemoteDkg| {
+                remote_dkg.on_state_change(consensus_cache.as_ref(), remote_dkg_pool)
+            }),
+            time_source,
+            Arc::clone(&remote_dkg_pool),
+            scheduler,
+            log,
+            metrics_registry,
+            production,
+            "remote_dkg",
+            "remote DKG message",
+        );
+        (
+            clients::RemoteDkgClient::new(remote_dkg_pool, remote_dkg_gossip),
+            manager,
+        )
+    }
+}
+
+impl<PoolCanisterHttp: MutableCanisterHttpPool + Send + Sync + 'static>
+    CanisterHttpProcessor<PoolCanisterHttp>
+{
+    /// The method validates the received canister HTTP messages.
+    #[allow(clippy::too_many_arguments)]
+    pub fn build<
+        C: CanisterHttp + 'static,
+        G: CanisterHttpGossip + 'static,
+        S: Fn(Advert<CanisterHttpArtifact>) + Send + 'static,
+        F: FnOnce() -> (C, G),
+    >(
+        send_advert: S,
+        setup: F,
+        time_source: Arc<SysTimeSource>,
+        canister_http_pool: Arc<RwLock<PoolCanisterHttp>>,
+        scheduler: Arc<ProcessorScheduler>,
+        log: ReplicaLogger,
+        metrics_registry: MetricsRegistry,
+        production: ProductionSwitch,
+    ) -> (
+        clients::CanisterHttpClient<PoolCanisterHttp>,
+        ArtifactProcessorManager<CanisterHttpArtifact>,
+    ) {
+        let (canister_http, canister_http_gossip) = setup();
+        let manager = Self::new_manager(
+            send_advert,
+            Box::new(move |canister_http_pool: &PoolCanisterHttp| {
+                canister_http.on_state_change(canister_http_pool)
+            }),
+            time_source,
+            Arc::clone(&canister_http_pool),
+            scheduler,
+            log,
+            metrics_registry,
+            production,
+            "canister_http",
+            "canister HTTP message",
+        );
+        (
+            clients::CanisterHttpClient::new(canister_http_pool, canister_http_gossip),
+            manager,
+        )
+    }
+}
+
+impl<PoolQueryStats: MutableQueryStatsPool + Send + Sync + 'static>
+    QueryStatsProcessor<PoolQueryStats>
+{
+    /// The method validates the received query statistics reports.
+    #[allow(clippy::too_many_arguments)]
+    pub fn build<
+        C: QueryStatsHandler + 'static,
+        G: QueryStatsGossip + 'static,
+        S: Fn(Advert<QueryStatsArtifact>) + Send + 'static,
+        F: FnOnce() -> (C, G),
+    >(
+        send_advert: S,
+        setup: F,
+        time_source: Arc<SysTimeSource>,
+        query_stats_pool: Arc<RwLock<PoolQueryStats>>,
+        scheduler: Arc<ProcessorScheduler>,
+        log: ReplicaLogger,
+        metrics_registry: MetricsRegistry,
+        production: ProductionSwitch,
+    ) -> (
+        clients::QueryStatsClient<PoolQueryStats>,
+        ArtifactProcessorManager<QueryStatsArtifact>,
+    ) {
+        let (query_stats, query_stats_gossip) = setup();
+        let manager = Self::new_manager(
+            send_advert,
+            Box::new(move |query_stats_pool: &PoolQueryStats| {
+                query_stats.on_state_change(query_stats_pool)
+            }),
+            time_source,
+            Arc::clone(&query_stats_pool),
+            scheduler,
+            log,
+            metrics_registry,
+            production,
+            "query_stats",
+            "query stats report",
+        );
+        (
+            clients::QueryStatsClient::new(query_stats_pool, query_stats_gossip),
+            manager,
+        )
+    }
 }
 
 impl<PoolEcdsa: MutableEcdsaPool + Send + Sync + 'static> EcdsaProcessor<PoolEcdsa> {
+    /// The method validates the received ECDSA messages.
     #[allow(clippy::too_many_arguments)]
     pub fn build<
         C: Ecdsa + 'static,
@@ -722,54 +1311,356 @@ impl<PoolEcdsa: MutableEcdsaPool + Send + Sync + 'static> EcdsaProcessor<PoolEcd
         setup: F,
         time_source: Arc<SysTimeSource>,
         ecdsa_pool: Arc<RwLock<PoolEcdsa>>,
+        scheduler: Arc<ProcessorScheduler>,
+        log: ReplicaLogger,
         metrics_registry: MetricsRegistry,
-        rt_handle: tokio::runtime::Handle,
+        production: ProductionSwitch,
     ) -> (
         clients::EcdsaClient<PoolEcdsa>,
         ArtifactProcessorManager<EcdsaArtifact>,
     ) {
         let (ecdsa, ecdsa_gossip) = setup();
-        let client = Self {
-            ecdsa_pool: ecdsa_pool.clone(),
-            client: Box::new(ecdsa),
-        };
-        let manager = ArtifactProcessorManager::new(
+        let manager = Self::new_manager(
+            send_advert,
+            Box::new(move |ecdsa_pool: &PoolEcdsa| ecdsa.on_state_change(ecdsa_pool)),
             time_source,
+            Arc::clone(&ecdsa_pool),
+            scheduler,
+            log,
             metrics_registry,
-            BoxOrArcClient::BoxClient(Box::new(client)),
-            send_advert,
-            rt_handle,
+            production,
+            "ecdsa",
+            "ECDSA message",
         );
         (clients::EcdsaClient::new(ecdsa_pool, ecdsa_gossip), manager)
     }
 }
 
-impl<PoolEcdsa: MutableEcdsaPool + Send + Sync + 'static> ArtifactProcessor<EcdsaArtifact>
-    for EcdsaProcessor<PoolEcdsa>
-{
-    fn process_changes(
-        &self,
-        _time_source: &dyn TimeSource,
-        artifacts: Vec<UnvalidatedArtifact<EcdsaMessage>>,
-    ) -> (Vec<Advert<EcdsaArtifact>>, ProcessingResult) {
-        {
-            let mut ecdsa_pool = self.ecdsa_pool.write().unwrap();
-            for artifact in artifacts {
-                ecdsa_pool.insert(artifact)
+impl<P: MutableEquivocationPool> ProcessedPool<EquivocationArtifact> for P {
+    type ChangeAction = EquivocationChangeAction;
+
+    fn insert(&mut self, artifact: UnvalidatedArtifact<EquivocationProof>) {
+        MutableEquivocationPool::insert(self, artifact)
+    }
+
+    fn apply_changes(&mut self, change_set: Vec<EquivocationChangeAction>) {
+        MutableEquivocationPool::apply_changes(self, change_set)
+    }
+}
+
+impl ProcessedChangeAction<EquivocationArtifact> for EquivocationChangeAction {
+    fn is_produced(&self) -> bool {
+        matches!(self, EquivocationChangeAction::AddToValidated(_))
+    }
+
+    fn adverts(&self) -> Vec<Advert<EquivocationArtifact>> {
+        match self {
+            EquivocationChangeAction::AddToValidated(proof)
+            | EquivocationChangeAction::MoveToValidated(proof) => {
+                vec![EquivocationArtifact::message_to_advert(proof)]
             }
+            _ => vec![],
         }
+    }
 
-        let change_set = {
-            let ecdsa_pool = self.ecdsa_pool.read().unwrap();
-            self.client.on_state_change(&*ecdsa_pool)
+    fn invalid(&self) -> Option<(&EquivocationProofId, &str)> {
+        match self {
+            EquivocationChangeAction::HandleInvalid(id, reason) => Some((id, reason)),
+            _ => None,
+        }
+    }
+}
+
+impl<P: MutableRemoteDkgPool> ProcessedPool<RemoteDkgArtifact> for P {
+    type ChangeAction = RemoteDkgChangeAction;
+
+    fn insert(&mut self, artifact: UnvalidatedArtifact<RemoteDkgMessage>) {
+        MutableRemoteDkgPool::insert(self, artifact)
+    }
+
+    fn apply_changes(&mut self, change_set: Vec<RemoteDkgChangeAction>) {
+        MutableRemoteDkgPool::apply_changes(self, change_set)
+    }
+}
+
+impl ProcessedChangeAction<RemoteDkgArtifact> for RemoteDkgChangeAction {
+    fn is_produced(&self) -> bool {
+        matches!(self, RemoteDkgChangeAction::AddToValidated(_))
+    }
+
+    fn adverts(&self) -> Vec<Advert<RemoteDkgArtifact>> {
+        match self {
+            RemoteDkgChangeAction::AddToValidated(msg)
+            | RemoteDkgChangeAction::MoveToValidated(msg) => {
+                vec![RemoteDkgArtifact::message_to_advert(msg)]
+            }
+            _ => vec![],
+        }
+    }
+
+    fn invalid(&self) -> Option<(&RemoteDkgMessageId, &str)> {
+        match self {
+            RemoteDkgChangeAction::HandleInvalid(id, reason) => Some((id, reason)),
+            _ => None,
+        }
+    }
+}
+
+impl<P: MutableCanisterHttpPool> ProcessedPool<CanisterHttpArtifact> for P {
+    type ChangeAction = CanisterHttpChangeAction;
+
+    fn insert(&mut self, artifact: UnvalidatedArtifact<CanisterHttpMessage>) {
+        MutableCanisterHttpPool::insert(self, artifact)
+    }
+
+    fn apply_changes(&mut self, change_set: Vec<CanisterHttpChangeAction>) {
+        MutableCanisterHttpPool::apply_changes(self, change_set)
+    }
+}
+
+impl ProcessedChangeAction<CanisterHttpArtifact> for CanisterHttpChangeAction {
+    fn is_produced(&self) -> bool {
+        matches!(self, CanisterHttpChangeAction::AddToValidated(..))
+    }
+
+    /// A share produced by this replica is advertised together with the
+    /// response it signs.
+    fn adverts(&self) -> Vec<Advert<CanisterHttpArtifact>> {
+        match self {
+            CanisterHttpChangeAction::AddToValidated(share, response) => vec![
+                CanisterHttpArtifact::message_to_advert(&CanisterHttpMessage::Share(share.clone())),
+                CanisterHttpArtifact::message_to_advert(&CanisterHttpMessage::Response(
+                    response.clone(),
+                )),
+            ],
+            CanisterHttpChangeAction::MoveToValidated(msg) => {
+                vec![CanisterHttpArtifact::message_to_advert(msg)]
+            }
+            _ => vec![],
+        }
+    }
+
+    fn invalid(&self) -> Option<(&CanisterHttpMessageId, &str)> {
+        match self {
+            CanisterHttpChangeAction::HandleInvalid(id, reason) => Some((id, reason)),
+            _ => None,
+        }
+    }
+}
+
+impl<P: MutableQueryStatsPool> ProcessedPool<QueryStatsArtifact> for P {
+    type ChangeAction = QueryStatsChangeAction;
+
+    fn insert(&mut self, artifact: UnvalidatedArtifact<QueryStatsMessage>) {
+        MutableQueryStatsPool::insert(self, artifact)
+    }
+
+    fn apply_changes(&mut self, change_set: Vec<QueryStatsChangeAction>) {
+        MutableQueryStatsPool::apply_changes(self, change_set)
+    }
+}
+
+impl ProcessedChangeAction<QueryStatsArtifact> for QueryStatsChangeAction {
+    fn is_produced(&self) -> bool {
+        matches!(self, QueryStatsChangeAction::AddToValidated(_))
+    }
+
+    fn adverts(&self) -> Vec<Advert<QueryStatsArtifact>> {
+        match self {
+            QueryStatsChangeAction::AddToValidated(msg)
+            | QueryStatsChangeAction::MoveToValidated(msg) => {
+                vec![QueryStatsArtifact::message_to_advert(msg)]
+            }
+            _ => vec![],
+        }
+    }
+
+    fn invalid(&self) -> Option<(&QueryStatsMessageId, &str)> {
+        match self {
+            QueryStatsChangeAction::HandleInvalid(id, reason) => Some((id, reason)),
+            _ => None,
+        }
+    }
+}
+
+impl<P: MutableEcdsaPool> ProcessedPool<EcdsaArtifact> for P {
+    type ChangeAction = EcdsaChangeAction;
+
+    fn insert(&mut self, artifact: UnvalidatedArtifact<EcdsaMessage>) {
+        MutableEcdsaPool::insert(self, artifact)
+    }
+
+    fn apply_changes(&mut self, change_set: Vec<EcdsaChangeAction>) {
+        MutableEcdsaPool::apply_changes(self, change_set)
+    }
+}
+
+impl ProcessedChangeAction<EcdsaArtifact> for EcdsaChangeAction {
+    fn is_produced(&self) -> bool {
+        matches!(self, EcdsaChangeAction::AddToValidated(_))
+    }
+
+    fn adverts(&self) -> Vec<Advert<EcdsaArtifact>> {
+        match self {
+            EcdsaChangeAction::AddToValidated(msg) | EcdsaChangeAction::MoveToValidated(msg) => {
+                vec![EcdsaArtifact::message_to_advert(msg)]
+            }
+            _ => vec![],
+        }
+    }
+
+    fn invalid(&self) -> Option<(&EcdsaMessageId, &str)> {
+        match self {
+            EcdsaChangeAction::HandleInvalid(id, reason) => Some((id, reason)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_artifact_pool::query_stats_pool::QueryStatsPoolImpl;
+    use ic_interfaces::query_stats::QueryStatsPool;
+    use ic_logger::replica_logger::no_op_logger;
+    use ic_test_utilities::{mock_time, types::ids::node_test_id};
+    use ic_types::consensus::{
+        query_stats::{QueryStatsContent, QueryStatsEpoch, QueryStatsMessage},
+        BasicSignature,
+    };
+    use ic_types::crypto::{BasicSig, BasicSigOf};
+    use ic_types::RegistryVersion;
+    use std::collections::BTreeMap;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[test]
+    fn processors_on_the_critical_path_have_the_shortest_deadlines() {
+        let deadline = |tag| scheduling_policy(tag).deadline;
+        let critical_path = [
+            ArtifactTag::ConsensusArtifact,
+            ArtifactTag::CertificationArtifact,
+            ArtifactTag::IngressArtifact,
+        ];
+        let longest_critical_deadline = critical_path.iter().copied().map(deadline).max();
+        let others = [
+            ArtifactTag::DkgArtifact,
+            ArtifactTag::EcdsaArtifact,
+            ArtifactTag::FileTreeSyncArtifact,
+            ArtifactTag::StateSyncArtifact,
+            ArtifactTag::EquivocationArtifact,
+            ArtifactTag::RemoteDkgArtifact,
+            ArtifactTag::CanisterHttpArtifact,
+            ArtifactTag::QueryStatsArtifact,
+            ArtifactTag::XNetStreamSliceArtifact,
+            ArtifactTag::RegistryDeltaArtifact,
+        ];
+        for tag in others.iter().copied() {
+            assert!(
+                Some(deadline(tag)) > longest_critical_deadline,
+                "{:?} has a deadline as short as the critical path",
+                tag
+            );
+        }
+        assert!(deadline(ArtifactTag::DkgArtifact) > deadline(ArtifactTag::EquivocationArtifact));
+    }
+
+    #[test]
+    fn production_switch_drops_produced_actions_while_off() {
+        let is_member = Arc::new(AtomicBool::new(false));
+        let switch = {
+            let is_member = Arc::clone(&is_member);
+            ProductionSwitch::new(move || is_member.load(Ordering::SeqCst))
         };
-        let changed = if !change_set.is_empty() {
-            ProcessingResult::StateChanged
-        } else {
-            ProcessingResult::StateUnchanged
+        // Even numbers stand in for the actions adding produced artifacts.
+        let is_produced: fn(&u32) -> bool = |action| action % 2 == 0;
+
+        assert!(!switch.is_on());
+        assert_eq!(switch.filter(vec![1, 2, 3, 4], is_produced), vec![1, 3]);
+
+        is_member.store(true, Ordering::SeqCst);
+        assert!(switch.is_on());
+        assert_eq!(
+            switch.filter(vec![1, 2, 3, 4], is_produced),
+            vec![1, 2, 3, 4]
+        );
+    }
+
+    #[test]
+    fn production_switch_always_keeps_all_actions() {
+        let switch = ProductionSwitch::always();
+        assert!(switch.is_on());
+        assert_eq!(switch.filter(vec![1, 2], |_| true), vec![1, 2]);
+    }
+
+    fn make_report(signer: u64) -> QueryStatsMessage {
+        QueryStatsMessage {
+            content: QueryStatsContent {
+                epoch: QueryStatsEpoch::from(1),
+                stats: BTreeMap::new(),
+                registry_version: RegistryVersion::from(1),
+            },
+            signature: BasicSignature {
+                signature: BasicSigOf::new(BasicSig(vec![])),
+                signer: node_test_id(signer),
+            },
+        }
+    }
+
+    #[test]
+    fn pool_processor_applies_and_advertises_the_change_set() {
+        let pool = Arc::new(RwLock::new(QueryStatsPoolImpl::new(MetricsRegistry::new())));
+        let (own, valid, invalid) = (make_report(0), make_report(1), make_report(2));
+        let processor = QueryStatsProcessor {
+            pool: Arc::clone(&pool),
+            on_state_change: {
+                let (own, valid, invalid) = (own, valid.clone(), invalid.clone());
+                Box::new(move |pool: &QueryStatsPoolImpl| {
+                    if pool.get_unvalidated().count() == 0 {
+                        return vec![];
+                    }
+                    vec![
+                        QueryStatsChangeAction::AddToValidated(own.clone()),
+                        QueryStatsChangeAction::MoveToValidated(valid.clone()),
+                        QueryStatsChangeAction::HandleInvalid(
+                            ic_crypto::crypto_hash(&invalid),
+                            "invalid".to_string(),
+                        ),
+                    ]
+                })
+            },
+            // Observers validate the artifacts of others, but add none.
+            production: ProductionSwitch::new(|| false),
+            invalidated_artifacts: IntCounter::new("invalidated", "invalidated").unwrap(),
+            description: "query stats report",
+            log: no_op_logger(),
         };
+        let artifacts = [&valid, &invalid]
+            .iter()
+            .map(|report| UnvalidatedArtifact {
+                message: (*report).clone(),
+                peer_id: report.signature.signer,
+                timestamp: mock_time(),
+            })
+            .collect();
+
+        let (adverts, result) = processor.process_changes(&SysTimeSource::new(), artifacts);
+        assert!(matches!(result, ProcessingResult::StateChanged));
+        assert_eq!(
+            adverts
+                .into_iter()
+                .map(|advert| advert.id)
+                .collect::<Vec<_>>(),
+            vec![ic_crypto::crypto_hash(&valid)]
+        );
+        assert_eq!(processor.invalidated_artifacts.get(), 1);
+        {
+            let pool = pool.read().unwrap();
+            assert_eq!(pool.get_validated().collect::<Vec<_>>(), vec![&valid]);
+            assert_eq!(pool.get_unvalidated().count(), 0);
+        }
 
-        self.ecdsa_pool.write().unwrap().apply_changes(change_set);
-        (vec![], changed)
+        let (adverts, result) = processor.process_changes(&SysTimeSource::new(), vec![]);
+        assert!(matches!(result, ProcessingResult::StateUnchanged));
+        assert!(adverts.is_empty());
     }
 }
@@ -0,0 +1,287 @@
+//! The scheduler running the artifact processors on a shared thread pool.
+//!
+//! Each artifact processor is registered as a job with a `SchedulingPolicy`.
+//! A job is due when it was requested to run, e.g., because new artifacts
+//! arrived or its last run changed the state of the client, or when its
+//! deadline has passed since its last run. The deadline bounds the time a
+//! client waits between two runs, so that clients also make progress without
+//! new artifacts.
+//!
+//! The worker threads pick the due jobs as follows:
+//!
+//! 1. Jobs that are overdue, i.e., that were not started within their deadline
+//!    after becoming due, run first, earliest deadline first. This ensures
+//!    that low priority clients are not starved by high priority clients that
+//!    are always due.
+//! 2. Otherwise, the job with the highest priority runs, the job that is due
+//!    longest first among jobs of the same priority.
+//!
+//! A job is never run by two threads at the same time.
+//!
+//! The scheduler also holds the limits of the queues of the processors it
+//! runs, see `ArtifactProcessorManager::has_backpressure`.
+
+use ic_base_thread::async_safe_block_on_await;
+use ic_config::artifact_pool::{
+    ArtifactProcessorQueueLimits, DEFAULT_ARTIFACT_PROCESSOR_QUEUE_LIMITS,
+};
+use ic_interfaces::artifact_manager::ProcessingResult;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+/// The maximal number of worker threads of the default scheduler.
+const MAX_DEFAULT_NUM_THREADS: usize = 4;
+
+/// The minimal number of worker threads of the default scheduler. With at
+/// least two threads, a long running job does not block all other jobs.
+const MIN_DEFAULT_NUM_THREADS: usize = 2;
+
+/// The priority of a job. Jobs with a higher priority run first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ProcessorPriority {
+    Low,
+    Normal,
+    High,
+}
+
+/// The scheduling policy of a job.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SchedulingPolicy {
+    /// The priority of the job.
+    pub priority: ProcessorPriority,
+    /// The maximal time between the end of a run of the job and the start of
+    /// the next run, and the maximal time a due job waits to be started.
+    pub deadline: Duration,
+}
+
+/// A job that is run by the scheduler.
+pub trait ScheduledJob: Send {
+    /// The method runs the job. The given delay is the time between the job
+    /// becoming due and the start of the run.
+    ///
+    /// The job is run again right away if the method returns
+    /// `ProcessingResult::StateChanged`.
+    fn run(&mut self, delay: Duration) -> ProcessingResult;
+}
+
+/// The identifier of a registered job.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct JobId(u64);
+
+/// The state of a registered job.
+struct JobSlot {
+    /// The job, which is taken by the worker thread while it runs.
+    job: Option<Box<dyn ScheduledJob>>,
+    /// The scheduling policy of the job.
+    policy: SchedulingPolicy,
+    /// The time at which the job is due.
+    due: Instant,
+    /// True if the job was requested to run while it was running.
+    requested: bool,
+}
+
+impl JobSlot {
+    fn is_running(&self) -> bool {
+        self.job.is_none()
+    }
+}
+
+/// The state shared by the scheduler and its worker threads.
+#[derive(Default)]
+struct SchedulerState {
+    jobs: BTreeMap<JobId, JobSlot>,
+    next_job_id: u64,
+    shutdown: bool,
+}
+
+/// What a worker thread does next.
+enum NextStep {
+    Run(JobId),
+    WaitUntil(Instant),
+    Wait,
+}
+
+impl SchedulerState {
+    /// The method returns the job to run next, or how long to wait for one.
+    fn next_step(&self, now: Instant) -> NextStep {
+        let idle_jobs = || self.jobs.iter().filter(|(_, slot)| !slot.is_running());
+        let overdue = idle_jobs()
+            .filter(|(_, slot)| slot.due + slot.policy.deadline <= now)
+            .min_by_key(|(_, slot)| slot.due + slot.policy.deadline);
+        if let Some((id, _)) = overdue {
+            return NextStep::Run(*id);
+        }
+        let due = idle_jobs()
+            .filter(|(_, slot)| slot.due <= now)
+            .min_by_key(|(_, slot)| (std::cmp::Reverse(slot.policy.priority), slot.due));
+        if let Some((id, _)) = due {
+            return NextStep::Run(*id);
+        }
+        match idle_jobs().map(|(_, slot)| slot.due).min() {
+            Some(due) => NextStep::WaitUntil(due),
+            None => NextStep::Wait,
+        }
+    }
+}
+
+/// The scheduler running the artifact processor jobs on a pool of worker
+/// threads.
+pub struct ProcessorScheduler {
+    /// The state shared with the worker threads, and the condition variable
+    /// signalling its changes.
+    state: Arc<(Mutex<SchedulerState>, Condvar)>,
+    /// Handles for the worker threads.
+    workers: Vec<JoinHandle<()>>,
+    /// The limits of the queues of the processors.
+    queue_limits: ArtifactProcessorQueueLimits,
+}
+
+impl ProcessorScheduler {
+    /// The constructor creates a scheduler with the given number of worker
+    /// threads, which are run as blocking tasks on the given runtime. The
+    /// queues of the processors have the default limits.
+    pub fn new(num_threads: usize, rt_handle: tokio::runtime::Handle) -> Arc<Self> {
+        Self::with_queue_limits(
+            num_threads,
+            DEFAULT_ARTIFACT_PROCESSOR_QUEUE_LIMITS,
+            rt_handle,
+        )
+    }
+
+    /// The constructor creates a scheduler with the given number of worker
+    /// threads, whose processors have the given queue limits.
+    pub fn with_queue_limits(
+        num_threads: usize,
+        queue_limits: ArtifactProcessorQueueLimits,
+        rt_handle: tokio::runtime::Handle,
+    ) -> Arc<Self> {
+        assert!(num_threads > 0, "The scheduler needs at least one thread");
+        let state = Arc::new((Mutex::new(SchedulerState::default()), Condvar::new()));
+        let workers = (0..num_threads)
+            .map(|_| {
+                let state = state.clone();
+                rt_handle.spawn_blocking(move || Self::work(&state))
+            })
+            .collect();
+        Arc::new(Self {
+            state,
+            workers,
+            queue_limits,
+        })
+    }
+
+    /// The constructor creates a scheduler with as many threads as there are
+    /// cores, within the bounds of the default number of threads.
+    pub fn with_default_num_threads(
+        queue_limits: ArtifactProcessorQueueLimits,
+        rt_handle: tokio::runtime::Handle,
+    ) -> Arc<Self> {
+        let num_threads = num_cpus::get().clamp(MIN_DEFAULT_NUM_THREADS, MAX_DEFAULT_NUM_THREADS);
+        Self::with_queue_limits(num_threads, queue_limits, rt_handle)
+    }
+
+    /// The limits of the queues of the processors run by this scheduler.
+    pub fn queue_limits(&self) -> ArtifactProcessorQueueLimits {
+        self.queue_limits
+    }
+
+    /// The method registers the given job. The job is first due after its
+    /// deadline, or when it is requested to run.
+    pub fn register(&self, policy: SchedulingPolicy, job: Box<dyn ScheduledJob>) -> JobId {
+        let mut state = self.lock();
+        let id = JobId(state.next_job_id);
+        state.next_job_id += 1;
+        state.jobs.insert(
+            id,
+            JobSlot {
+                job: Some(job),
+                policy,
+                due: Instant::now() + policy.deadline,
+                requested: false,
+            },
+        );
+        self.state.1.notify_all();
+        id
+    }
+
+    /// The method requests the job to run as soon as possible.
+    pub fn request_run(&self, id: JobId) {
+        let mut state = self.lock();
+        if let Some(slot) = state.jobs.get_mut(&id) {
+            if slot.is_running() {
+                slot.requested = true;
+            } else {
+                slot.due = slot.due.min(Instant::now());
+            }
+            self.state.1.notify_all();
+        }
+    }
+
+    /// The method removes the job. If the job is running, the method waits
+    /// until the run has finished.
+    pub fn unregister(&self, id: JobId) {
+        let mut state = self.lock();
+        while state.jobs.get(&id).map_or(false, JobSlot::is_running) {
+            state = self.state.1.wait(state).unwrap();
+        }
+        state.jobs.remove(&id);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, SchedulerState> {
+        self.state.0.lock().unwrap()
+    }
+
+    /// The worker thread loop.
+    fn work(state: &(Mutex<SchedulerState>, Condvar)) {
+        let (lock, condvar) = state;
+        let mut state = lock.lock().unwrap();
+        loop {
+            if state.shutdown {
+                return;
+            }
+            let now = Instant::now();
+            match state.next_step(now) {
+                NextStep::Run(id) => {
+                    let slot = state.jobs.get_mut(&id).unwrap();
+                    let delay = now.saturating_duration_since(slot.due);
+                    let mut job = slot.job.take().unwrap();
+                    drop(state);
+
+                    let result = job.run(delay);
+
+                    state = lock.lock().unwrap();
+                    if let Some(slot) = state.jobs.get_mut(&id) {
+                        let finished = Instant::now();
+                        slot.due = match result {
+                            ProcessingResult::StateChanged => finished,
+                            _ if slot.requested => finished,
+                            _ => finished + slot.policy.deadline,
+                        };
+                        slot.requested = false;
+                        slot.job = Some(job);
+                    }
+                    condvar.notify_all();
+                }
+                NextStep::WaitUntil(due) => {
+                    state = condvar
+                        .wait_timeout(state, due.saturating_duration_since(now))
+                        .unwrap()
+                        .0;
+                }
+                NextStep::Wait => state = condvar.wait(state).unwrap(),
+            }
+        }
+    }
+}
+
+impl Drop for ProcessorScheduler {
+    fn drop(&mut self) {
+        self.lock().shutdown = true;
+        self.state.1.notify_all();
+        for worker in self.workers.drain(..) {
+            async_safe_block_on_await(worker).unwrap();
+        }
+    }
+}
@@ -4,12 +4,23 @@ mod setup;
 
 use assert_matches::assert_matches;
 use ic_artifact_manager::artifact::ConsensusArtifact;
+use ic_artifact_manager::manager::{ArtifactManagerMaker, ArtifactRegistrationError};
+use ic_artifact_manager::scheduler::ProcessorScheduler;
 use ic_consensus_message::{make_genesis, ConsensusMessageHashable};
-use ic_interfaces::{artifact_manager::OnArtifactError, artifact_pool::ArtifactPoolError};
+use ic_interfaces::{
+    artifact_manager::OnArtifactError, artifact_pool::ArtifactPoolError, time_source::SysTimeSource,
+};
+use ic_logger::replica_logger::no_op_logger;
+use ic_metrics::MetricsRegistry;
 use ic_test_utilities::{consensus::fake::*, types::ids::node_test_id};
-use ic_types::{artifact::ArtifactKind, consensus::*, ReplicaVersion};
-use setup::run_test;
+use ic_types::{
+    artifact::{ArtifactKind, ArtifactTag},
+    consensus::*,
+    ReplicaVersion,
+};
+use setup::{build_consensus_client, init_artifact_pools, run_test};
 use std::convert::TryFrom;
+use std::sync::Arc;
 
 #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
 async fn test_artifact_version() {
@@ -64,3 +75,37 @@ async fn test_artifact_advert_match() {
         assert_matches!(result, Err(OnArtifactError::AdvertMismatch(_)));
     });
 }
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_register_rejects_duplicate_artifact_kind() {
+    ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
+        let time_source = Arc::new(SysTimeSource::new());
+        let (ingress_pool, consensus_pool) =
+            init_artifact_pools(pool_config, MetricsRegistry::new(), no_op_logger());
+        let scheduler = ProcessorScheduler::new(1, tokio::runtime::Handle::current());
+        let mut artifact_manager_maker = ArtifactManagerMaker::new(time_source.clone());
+        assert!(!artifact_manager_maker.is_registered::<ConsensusArtifact>());
+
+        let build = || {
+            build_consensus_client(
+                time_source.clone(),
+                consensus_pool.clone(),
+                ingress_pool.clone(),
+                scheduler.clone(),
+                no_op_logger(),
+            )
+        };
+        let (client, actor) = build();
+        assert_eq!(artifact_manager_maker.register(client, actor), Ok(()));
+        assert!(artifact_manager_maker.is_registered::<ConsensusArtifact>());
+
+        // A second client of the same kind is rejected.
+        let (client, actor) = build();
+        assert_eq!(
+            artifact_manager_maker.register(client, actor),
+            Err(ArtifactRegistrationError::AlreadyRegistered(
+                ArtifactTag::ConsensusArtifact
+            ))
+        );
+    });
+}
@@ -0,0 +1,293 @@
+//! Tests for the fault injection into artifact processors
+#![cfg(feature = "testing")]
+
+use ic_artifact_manager::artifact::ConsensusArtifact;
+use ic_artifact_manager::fault_injection::{
+    Fault, FaultInjector, FaultRates, FaultSchedule, InjectedFault, InvalidFaultRates,
+};
+use ic_artifact_manager::processors::{ArtifactProcessorManager, BoxOrArcClient};
+use ic_artifact_manager::scheduler::ProcessorScheduler;
+use ic_consensus_message::{make_genesis, ConsensusMessageHashable};
+use ic_interfaces::{
+    artifact_manager::{ArtifactProcessor, ProcessingResult},
+    artifact_pool::UnvalidatedArtifact,
+    time_source::{SysTimeSource, TimeSource},
+};
+use ic_metrics::MetricsRegistry;
+use ic_test_utilities::{consensus::fake::*, mock_time, types::ids::node_test_id};
+use ic_types::{
+    artifact::{Advert, ArtifactKind},
+    consensus::*,
+};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+fn adverts(count: u64) -> Vec<Advert<ConsensusArtifact>> {
+    let cup = make_genesis(ic_types::consensus::dkg::Summary::fake());
+    (0..count)
+        .map(|i| {
+            let block = BlockProposal::fake(cup.content.block.as_ref().clone(), node_test_id(i));
+            ConsensusArtifact::message_to_advert(&block.into_message())
+        })
+        .collect()
+}
+
+fn schedule(seed: u64, adverts: FaultRates) -> FaultSchedule {
+    FaultSchedule {
+        seed,
+        change_sets: FaultRates {
+            delay: 0.2,
+            max_delay: Duration::from_millis(1),
+            drop: 0.2,
+            duplicate: 0.2,
+        },
+        adverts,
+    }
+}
+
+/// Runs an injector for the given number of runs and returns the log.
+fn run_injector(schedule: FaultSchedule, runs: usize) -> Vec<InjectedFault> {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mut injector = FaultInjector::<ConsensusArtifact>::new(schedule, log.clone()).unwrap();
+    for _ in 0..runs {
+        injector.next_run();
+        injector.intercept_adverts(adverts(4));
+    }
+    let faults = log.lock().unwrap().clone();
+    faults
+}
+
+#[test]
+fn same_seed_injects_same_faults() {
+    let rates = FaultRates {
+        delay: 0.1,
+        max_delay: Duration::from_millis(1),
+        drop: 0.1,
+        duplicate: 0.1,
+    };
+    let faults = run_injector(schedule(7, rates.clone()), 20);
+    assert!(!faults.is_empty());
+    assert_eq!(faults, run_injector(schedule(7, rates.clone()), 20));
+    assert_ne!(faults, run_injector(schedule(8, rates), 20));
+}
+
+#[test]
+fn no_faults_by_default() {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mut injector =
+        FaultInjector::<ConsensusArtifact>::new(FaultSchedule::default(), log.clone()).unwrap();
+    assert_eq!(injector.next_run(), None);
+    let adverts = adverts(3);
+    assert_eq!(injector.intercept_adverts(adverts.clone()), adverts);
+    assert!(log.lock().unwrap().is_empty());
+}
+
+#[test]
+fn adverts_are_dropped_and_duplicated() {
+    let drop_all = FaultRates {
+        drop: 1.0,
+        ..Default::default()
+    };
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mut injector = FaultInjector::<ConsensusArtifact>::new(schedule(1, drop_all), log).unwrap();
+    assert!(injector.intercept_adverts(adverts(3)).is_empty());
+
+    let duplicate_all = FaultRates {
+        duplicate: 1.0,
+        ..Default::default()
+    };
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mut injector =
+        FaultInjector::<ConsensusArtifact>::new(schedule(1, duplicate_all), log).unwrap();
+    let adverts = adverts(3);
+    let emitted = injector.intercept_adverts(adverts.clone());
+    assert_eq!(emitted.len(), 6);
+    assert!(adverts.iter().all(|advert| emitted
+        .iter()
+        .filter(|emitted| *emitted == advert)
+        .count()
+        == 2));
+}
+
+#[test]
+fn delayed_adverts_are_emitted_by_a_later_run() {
+    let delay_all = FaultRates {
+        delay: 1.0,
+        max_delay: Duration::from_millis(10),
+        ..Default::default()
+    };
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mut injector =
+        FaultInjector::<ConsensusArtifact>::new(schedule(1, delay_all), log.clone()).unwrap();
+    let adverts = adverts(2);
+    assert!(injector.intercept_adverts(adverts.clone()).is_empty());
+    assert!(log.lock().unwrap().iter().all(|fault| matches!(
+        fault,
+        InjectedFault::Advert {
+            fault: Fault::Delay(_),
+            ..
+        }
+    )));
+
+    std::thread::sleep(Duration::from_millis(10));
+    injector.next_run();
+    let emitted = injector.intercept_adverts(Vec::new());
+    assert_eq!(emitted, adverts);
+}
+
+#[test]
+fn invalid_rates_are_rejected() {
+    let invalid_rates = vec![
+        FaultRates {
+            drop: 0.6,
+            duplicate: 0.6,
+            ..Default::default()
+        },
+        FaultRates {
+            delay: -0.1,
+            ..Default::default()
+        },
+        FaultRates {
+            duplicate: f64::NAN,
+            ..Default::default()
+        },
+    ];
+    for rates in invalid_rates {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let result = FaultInjector::<ConsensusArtifact>::new(schedule(1, rates.clone()), log);
+        assert_eq!(result.err(), Some(InvalidFaultRates(rates)));
+    }
+}
+
+/// A client recording the number of artifacts it received in each run, and
+/// emitting one advert per run.
+struct RecordingClient {
+    received: Arc<Mutex<Vec<usize>>>,
+    advert: Advert<ConsensusArtifact>,
+}
+
+impl ArtifactProcessor<ConsensusArtifact> for RecordingClient {
+    fn process_changes(
+        &self,
+        _time_source: &dyn TimeSource,
+        artifacts: Vec<UnvalidatedArtifact<ConsensusMessage>>,
+    ) -> (Vec<Advert<ConsensusArtifact>>, ProcessingResult) {
+        self.received.lock().unwrap().push(artifacts.len());
+        (vec![self.advert.clone()], ProcessingResult::StateUnchanged)
+    }
+}
+
+/// The state observed while a processor with fault injection runs.
+struct ProcessorRun {
+    received: Arc<Mutex<Vec<usize>>>,
+    sent_adverts: Arc<Mutex<Vec<Advert<ConsensusArtifact>>>>,
+    fault_log: Arc<Mutex<Vec<InjectedFault>>>,
+    _processor: ArtifactProcessorManager<ConsensusArtifact>,
+}
+
+/// Starts a processor with the given schedule and hands one artifact to it.
+fn start_processor(schedule: FaultSchedule) -> ProcessorRun {
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let sent_adverts = Arc::new(Mutex::new(Vec::new()));
+    let fault_log = Arc::new(Mutex::new(Vec::new()));
+    let cup = make_genesis(ic_types::consensus::dkg::Summary::fake());
+    let message =
+        BlockProposal::fake(cup.content.block.as_ref().clone(), node_test_id(0)).into_message();
+    let client = RecordingClient {
+        received: Arc::clone(&received),
+        advert: ConsensusArtifact::message_to_advert(&message),
+    };
+    let sent = Arc::clone(&sent_adverts);
+    let processor = ArtifactProcessorManager::with_fault_injection(
+        Arc::new(SysTimeSource::new()),
+        MetricsRegistry::new(),
+        BoxOrArcClient::BoxClient(Box::new(client)),
+        move |advert| sent.lock().unwrap().push(advert),
+        ProcessorScheduler::new(1, tokio::runtime::Handle::current()),
+        schedule,
+        Arc::clone(&fault_log),
+    )
+    .unwrap();
+    assert!(processor
+        .on_artifact(UnvalidatedArtifact {
+            message,
+            peer_id: node_test_id(1),
+            timestamp: mock_time(),
+        })
+        .is_ok());
+    ProcessorRun {
+        received,
+        sent_adverts,
+        fault_log,
+        _processor: processor,
+    }
+}
+
+fn wait_until<F: Fn() -> bool>(condition: F) {
+    let start = Instant::now();
+    while !condition() {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "Condition not met in time"
+        );
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn dropped_runs_do_not_run_the_client() {
+    let run = start_processor(FaultSchedule {
+        seed: 1,
+        change_sets: FaultRates {
+            drop: 1.0,
+            ..Default::default()
+        },
+        adverts: FaultRates::default(),
+    });
+    wait_until(|| run.fault_log.lock().unwrap().len() >= 2);
+
+    assert!(run.received.lock().unwrap().is_empty());
+    assert!(run.sent_adverts.lock().unwrap().is_empty());
+    assert!(run.fault_log.lock().unwrap().iter().all(|fault| matches!(
+        fault,
+        InjectedFault::ChangeSet {
+            fault: Fault::Drop,
+            ..
+        }
+    )));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn artifacts_of_dropped_runs_stay_queued() {
+    let run = start_processor(FaultSchedule {
+        seed: 3,
+        change_sets: FaultRates {
+            drop: 0.75,
+            ..Default::default()
+        },
+        adverts: FaultRates::default(),
+    });
+    wait_until(|| !run.received.lock().unwrap().is_empty());
+
+    // The first run that is not dropped processes the artifact.
+    assert_eq!(run.received.lock().unwrap()[0], 1);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn duplicated_runs_run_the_client_twice() {
+    let run = start_processor(FaultSchedule {
+        seed: 1,
+        change_sets: FaultRates {
+            duplicate: 1.0,
+            ..Default::default()
+        },
+        adverts: FaultRates::default(),
+    });
+    wait_until(|| run.received.lock().unwrap().len() >= 2);
+
+    // The artifact is processed by the first run of the client only.
+    assert_eq!(run.received.lock().unwrap()[..2], [1, 0]);
+    wait_until(|| run.sent_adverts.lock().unwrap().len() >= 2);
+    let sent_adverts = run.sent_adverts.lock().unwrap();
+    assert_eq!(sent_adverts[0], sent_adverts[1]);
+}
@@ -0,0 +1,130 @@
+//! Tests for the queues of the artifact processors
+
+use ic_artifact_manager::artifact::ConsensusArtifact;
+use ic_artifact_manager::processors::{ArtifactProcessorManager, BoxOrArcClient};
+use ic_artifact_manager::scheduler::ProcessorScheduler;
+use ic_config::artifact_pool::ArtifactProcessorQueueLimits;
+use ic_consensus_message::{make_genesis, ConsensusMessageHashable};
+use ic_interfaces::{
+    artifact_manager::{ArtifactProcessor, ProcessingResult},
+    artifact_pool::UnvalidatedArtifact,
+    time_source::{SysTimeSource, TimeSource},
+};
+use ic_metrics::MetricsRegistry;
+use ic_test_utilities::{consensus::fake::*, mock_time, types::ids::node_test_id};
+use ic_types::{artifact::Advert, consensus::*};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// A gate the client waits at in each run until it is opened.
+#[derive(Default)]
+struct Gate {
+    open: Mutex<bool>,
+    opened: Condvar,
+}
+
+impl Gate {
+    fn wait(&self) {
+        let open = self.open.lock().unwrap();
+        let _open = self.opened.wait_while(open, |open| !*open).unwrap();
+    }
+
+    fn open(&self) {
+        *self.open.lock().unwrap() = true;
+        self.opened.notify_all();
+    }
+}
+
+/// A client counting the artifacts it received, which blocks in each run
+/// until the gate is opened.
+struct BlockingClient {
+    gate: Arc<Gate>,
+    received: Arc<Mutex<usize>>,
+    runs: Arc<Mutex<usize>>,
+}
+
+impl ArtifactProcessor<ConsensusArtifact> for BlockingClient {
+    fn process_changes(
+        &self,
+        _time_source: &dyn TimeSource,
+        artifacts: Vec<UnvalidatedArtifact<ConsensusMessage>>,
+    ) -> (Vec<Advert<ConsensusArtifact>>, ProcessingResult) {
+        *self.runs.lock().unwrap() += 1;
+        self.gate.wait();
+        *self.received.lock().unwrap() += artifacts.len();
+        (vec![], ProcessingResult::StateUnchanged)
+    }
+}
+
+fn artifact() -> UnvalidatedArtifact<ConsensusMessage> {
+    let cup = make_genesis(ic_types::consensus::dkg::Summary::fake());
+    UnvalidatedArtifact {
+        message: BlockProposal::fake(cup.content.block.as_ref().clone(), node_test_id(0))
+            .into_message(),
+        peer_id: node_test_id(1),
+        timestamp: mock_time(),
+    }
+}
+
+fn wait_until<F: Fn() -> bool>(condition: F) {
+    let start = Instant::now();
+    while !condition() {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "Condition not met in time"
+        );
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn processor_signals_backpressure_and_rejects_artifacts_beyond_the_queue_limit() {
+    let gate = Arc::new(Gate::default());
+    let received = Arc::new(Mutex::new(0));
+    let runs = Arc::new(Mutex::new(0));
+    let client = BlockingClient {
+        gate: Arc::clone(&gate),
+        received: Arc::clone(&received),
+        runs: Arc::clone(&runs),
+    };
+    let scheduler = ProcessorScheduler::with_queue_limits(
+        1,
+        ArtifactProcessorQueueLimits {
+            backpressure_threshold: 3,
+            max_pending_artifacts: 5,
+        },
+        tokio::runtime::Handle::current(),
+    );
+    let processor = ArtifactProcessorManager::new(
+        Arc::new(SysTimeSource::new()),
+        MetricsRegistry::new(),
+        BoxOrArcClient::BoxClient(Box::new(client)),
+        |_| {},
+        scheduler,
+    );
+
+    // The first artifact is taken by a run, which blocks at the gate.
+    assert!(processor.on_artifact(artifact()).is_ok());
+    wait_until(|| *runs.lock().unwrap() == 1);
+
+    // The queue signals backpressure once the threshold is reached.
+    for _ in 0..2 {
+        assert!(processor.on_artifact(artifact()).is_ok());
+        assert!(!processor.has_backpressure());
+    }
+    assert!(processor.on_artifact(artifact()).is_ok());
+    assert!(processor.has_backpressure());
+
+    // Artifacts are queued up to the limit and rejected beyond it.
+    for _ in 0..2 {
+        assert!(processor.on_artifact(artifact()).is_ok());
+    }
+    assert!(processor.on_artifact(artifact()).is_err());
+
+    // Once the client catches up, the backpressure stops.
+    gate.open();
+    wait_until(|| *received.lock().unwrap() == 6);
+    assert!(!processor.has_backpressure());
+    assert!(processor.on_artifact(artifact()).is_ok());
+    wait_until(|| *received.lock().unwrap() == 7);
+}
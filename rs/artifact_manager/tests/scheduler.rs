@@ -0,0 +1,136 @@
+//! Tests for the processor scheduler
+
+use ic_artifact_manager::scheduler::{
+    ProcessorPriority, ProcessorScheduler, ScheduledJob, SchedulingPolicy,
+};
+use ic_interfaces::artifact_manager::ProcessingResult;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A job recording its runs in a shared log.
+struct TestJob {
+    name: &'static str,
+    runs: Arc<Mutex<Vec<&'static str>>>,
+    state_changed: bool,
+}
+
+impl ScheduledJob for TestJob {
+    fn run(&mut self, _delay: Duration) -> ProcessingResult {
+        self.runs.lock().unwrap().push(self.name);
+        std::thread::sleep(Duration::from_millis(1));
+        if self.state_changed {
+            ProcessingResult::StateChanged
+        } else {
+            ProcessingResult::StateUnchanged
+        }
+    }
+}
+
+fn policy(priority: ProcessorPriority, deadline_ms: u64) -> SchedulingPolicy {
+    SchedulingPolicy {
+        priority,
+        deadline: Duration::from_millis(deadline_ms),
+    }
+}
+
+fn wait_until<F: Fn() -> bool>(condition: F) {
+    let start = Instant::now();
+    while !condition() {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "Condition not met in time"
+        );
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_requested_job_runs() {
+    let scheduler = ProcessorScheduler::new(1, tokio::runtime::Handle::current());
+    let runs = Arc::new(Mutex::new(Vec::new()));
+    let id = scheduler.register(
+        policy(ProcessorPriority::Normal, 60_000),
+        Box::new(TestJob {
+            name: "job",
+            runs: runs.clone(),
+            state_changed: false,
+        }),
+    );
+    scheduler.request_run(id);
+    wait_until(|| runs.lock().unwrap().len() == 1);
+    scheduler.unregister(id);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_high_priority_job_runs_first() {
+    let scheduler = ProcessorScheduler::new(1, tokio::runtime::Handle::current());
+    let runs = Arc::new(Mutex::new(Vec::new()));
+    // Block the only worker, so that both jobs are due when it is free again.
+    let blocker = Arc::new(Mutex::new(()));
+    let guard = blocker.lock().unwrap();
+    struct BlockingJob(Arc<Mutex<()>>);
+    impl ScheduledJob for BlockingJob {
+        fn run(&mut self, _delay: Duration) -> ProcessingResult {
+            let _guard = self.0.lock().unwrap();
+            ProcessingResult::StateUnchanged
+        }
+    }
+    let blocking_id = scheduler.register(
+        policy(ProcessorPriority::High, 60_000),
+        Box::new(BlockingJob(blocker.clone())),
+    );
+    scheduler.request_run(blocking_id);
+    std::thread::sleep(Duration::from_millis(50));
+
+    let low_id = scheduler.register(
+        policy(ProcessorPriority::Low, 60_000),
+        Box::new(TestJob {
+            name: "low",
+            runs: runs.clone(),
+            state_changed: false,
+        }),
+    );
+    let high_id = scheduler.register(
+        policy(ProcessorPriority::High, 60_000),
+        Box::new(TestJob {
+            name: "high",
+            runs: runs.clone(),
+            state_changed: false,
+        }),
+    );
+    scheduler.request_run(low_id);
+    scheduler.request_run(high_id);
+    drop(guard);
+
+    wait_until(|| runs.lock().unwrap().len() == 2);
+    assert_eq!(*runs.lock().unwrap(), vec!["high", "low"]);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_overdue_job_is_not_starved() {
+    let scheduler = ProcessorScheduler::new(1, tokio::runtime::Handle::current());
+    let runs = Arc::new(Mutex::new(Vec::new()));
+    // The high priority job is always due again after it ran.
+    let busy_id = scheduler.register(
+        policy(ProcessorPriority::High, 60_000),
+        Box::new(TestJob {
+            name: "busy",
+            runs: runs.clone(),
+            state_changed: true,
+        }),
+    );
+    let low_id = scheduler.register(
+        policy(ProcessorPriority::Low, 20),
+        Box::new(TestJob {
+            name: "low",
+            runs: runs.clone(),
+            state_changed: false,
+        }),
+    );
+    scheduler.request_run(busy_id);
+    scheduler.request_run(low_id);
+
+    wait_until(|| runs.lock().unwrap().contains(&"low"));
+    scheduler.unregister(busy_id);
+    scheduler.unregister(low_id);
+}
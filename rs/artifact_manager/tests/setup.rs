@@ -1,4 +1,6 @@
-use ic_artifact_manager::{manager, processors};
+use ic_artifact_manager::{
+    artifact::ConsensusArtifact, manager, processors, scheduler::ProcessorScheduler,
+};
 use ic_artifact_pool::{consensus_pool::ConsensusPoolImpl, ingress_pool::IngressPoolImpl};
 use ic_config::artifact_pool::ArtifactPoolConfig;
 use ic_consensus_message::make_genesis;
@@ -17,18 +19,42 @@ fn setup_manager(
     rt_handle: tokio::runtime::Handle,
 ) -> Arc<dyn ArtifactManager> {
     let time_source = Arc::new(SysTimeSource::new());
-    let metrics_registry = MetricsRegistry::new();
     let replica_logger = no_op_logger();
 
     let mut artifact_manager_maker = manager::ArtifactManagerMaker::new(time_source.clone());
 
     let (ingress_pool, consensus_pool) = init_artifact_pools(
         artifact_pool_config,
-        metrics_registry.clone(),
+        MetricsRegistry::new(),
         replica_logger.clone(),
     );
 
     // Create consensus client
+    let (consensus_client, actor) = build_consensus_client(
+        time_source,
+        consensus_pool,
+        ingress_pool,
+        ProcessorScheduler::new(1, rt_handle),
+        replica_logger,
+    );
+    artifact_manager_maker
+        .register(consensus_client, actor)
+        .unwrap();
+    artifact_manager_maker.finish()
+}
+
+/// Build a consensus client and its processor, using MockConsensus, on the
+/// given pools.
+pub fn build_consensus_client(
+    time_source: Arc<SysTimeSource>,
+    consensus_pool: Arc<RwLock<ConsensusPoolImpl>>,
+    ingress_pool: Arc<RwLock<IngressPoolImpl>>,
+    scheduler: Arc<ProcessorScheduler>,
+    replica_logger: ReplicaLogger,
+) -> (
+    Arc<dyn ArtifactClient<ConsensusArtifact>>,
+    processors::ArtifactProcessorManager<ConsensusArtifact>,
+) {
     let (consensus_client, actor) = processors::ConsensusProcessor::build(
         |_| {},
         || {
@@ -37,18 +63,18 @@ fn setup_manager(
             let consensus_gossip = MockConsensus::new();
             (consensus, consensus_gossip)
         },
-        Arc::clone(&time_source) as Arc<_>,
-        Arc::clone(&consensus_pool),
-        Arc::clone(&ingress_pool),
-        rt_handle,
+        time_source,
+        consensus_pool,
+        ingress_pool,
+        scheduler,
         replica_logger,
-        metrics_registry,
+        MetricsRegistry::new(),
+        processors::ProductionSwitch::always(),
     );
-    artifact_manager_maker.add_client(consensus_client, actor);
-    artifact_manager_maker.finish()
+    (Arc::new(consensus_client) as Arc<_>, actor)
 }
 
-fn init_artifact_pools(
+pub fn init_artifact_pools(
     config: ArtifactPoolConfig,
     registry: MetricsRegistry,
     log: ReplicaLogger,
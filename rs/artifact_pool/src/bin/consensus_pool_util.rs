@@ -2,6 +2,7 @@ use clap::{App, Arg, SubCommand};
 use ic_artifact_pool::{
     certification_pool::CertificationPoolImpl,
     consensus_pool::{PoolSectionOps, UncachedConsensusPoolImpl},
+    dump::DumpOptions,
 };
 use ic_config::artifact_pool::ArtifactPoolConfig;
 use ic_consensus_message::ConsensusMessageHashable;
@@ -19,7 +20,7 @@ use serde_json::{Deserializer, Serializer};
 use std::convert::TryFrom;
 use std::io::BufRead;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 fn main() {
     let app = App::new("ic-consensus-pool-util")
@@ -52,6 +53,34 @@ fn main() {
                         .takes_value(true),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("dump")
+                .about("Dump the validated section of a pool to a CBOR file, for diffing pools")
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("FILE")
+                        .help("Output filename")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("pool")
+                        .short("p")
+                        .long("pool")
+                        .value_name("POOL")
+                        .help("The pool to dump")
+                        .possible_values(&["consensus", "certification"])
+                        .default_value("consensus")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("include-artifacts")
+                        .long("include-artifacts")
+                        .help("Include the full artifacts, not only their hashes and signatures"),
+                ),
+        )
         .args_from_usage("<PATH>       'PATH to the consensus pool directory'");
     let mut help = Vec::new();
     app.write_help(&mut help)
@@ -66,6 +95,8 @@ fn main() {
         import(path)
     } else if let Some(matches) = matches.subcommand_matches("export-cup-proto") {
         export_cup_proto(path, matches)
+    } else if let Some(matches) = matches.subcommand_matches("dump") {
+        dump(path, matches)
     } else {
         eprintln!(
             "{}",
@@ -114,7 +145,7 @@ fn open_consensus_pool(path: &str, read_only: bool) -> UncachedConsensusPoolImpl
     let path = PathBuf::from(path);
     let mut config = ArtifactPoolConfig::new(path);
     config.persistent_pool_read_only = read_only;
-    UncachedConsensusPoolImpl::new(config, log)
+    UncachedConsensusPoolImpl::new(config, log, MetricsRegistry::new())
 }
 
 fn open_certification_pool(path: &str, read_only: bool) -> CertificationPoolImpl {
@@ -275,3 +306,22 @@ fn export_cup_proto(path: &str, matches: &clap::ArgMatches) {
     file.write_all(&buf)
         .unwrap_or_else(|err| panic!("Cannot write to file {}: {:?}", filename, err));
 }
+
+fn dump(path: &str, matches: &clap::ArgMatches) {
+    let filename = matches
+        .value_of("output")
+        .expect("Expect an output filename");
+    let options = DumpOptions {
+        include_artifacts: matches.is_present("include-artifacts"),
+    };
+    let result = match matches.value_of("pool") {
+        Some("certification") => {
+            open_certification_pool(path, true).dump(Path::new(filename), &options)
+        }
+        _ => open_consensus_pool(path, true).dump(Path::new(filename), &options),
+    };
+    match result {
+        Ok(count) => println!("Dumped {} artifacts to {}", count, filename),
+        Err(err) => panic!("Cannot write to file {}: {:?}", filename, err),
+    }
+}
@@ -0,0 +1,276 @@
+//! The canister HTTP pool holds the responses to the HTTP outcalls of
+//! canisters and the shares of the replicas over them, until the requests
+//! time out.
+use crate::message_pool::MessagePool;
+use ic_config::artifact_pool::{UnvalidatedEvictionPolicy, UnvalidatedSectionLimits};
+use ic_interfaces::artifact_pool::UnvalidatedArtifact;
+use ic_interfaces::canister_http::{
+    CanisterHttpChangeAction, CanisterHttpChangeSet, CanisterHttpPool, MutableCanisterHttpPool,
+};
+use ic_interfaces::gossip_pool::{CanisterHttpGossipPool, GossipPool};
+use ic_metrics::MetricsRegistry;
+use ic_types::artifact::CanisterHttpMessageId;
+use ic_types::consensus::canister_http::{
+    CanisterHttpMessage, CanisterHttpResponse, CanisterHttpResponseShare,
+};
+use ic_types::crypto::CryptoHashOf;
+use ic_types::Height;
+use std::collections::BTreeMap;
+
+const POOL_CANISTER_HTTP: &str = "canister_http";
+const ARTIFACT_TYPE_CANISTER_HTTP_MESSAGE: &str = "canister_http_message";
+
+/// The limits of the unvalidated section of the canister HTTP pool. The
+/// messages of a request are only purged once it times out, and there may be
+/// many requests in flight. The messages carry no height, so the oldest ones
+/// are evicted first.
+const UNVALIDATED_LIMITS: UnvalidatedSectionLimits = UnvalidatedSectionLimits {
+    max_count: 10_000,
+    max_size_bytes: 512 * 1024 * 1024,
+    eviction_policy: UnvalidatedEvictionPolicy::OldestFirst,
+};
+
+/// The in-memory pool of canister HTTP messages.
+pub struct CanisterHttpPoolImpl {
+    pool: MessagePool<CanisterHttpMessage>,
+    /// The ids of the validated responses, by the hash of the response.
+    response_ids: BTreeMap<CryptoHashOf<CanisterHttpResponse>, CanisterHttpMessageId>,
+}
+
+impl CanisterHttpPoolImpl {
+    pub fn new(metrics_registry: MetricsRegistry) -> Self {
+        Self {
+            pool: MessagePool::new(
+                &metrics_registry,
+                POOL_CANISTER_HTTP,
+                ARTIFACT_TYPE_CANISTER_HTTP_MESSAGE,
+                UNVALIDATED_LIMITS,
+            ),
+            response_ids: BTreeMap::new(),
+        }
+    }
+
+    /// Indexes the given message by the hash of the response, if it is a
+    /// response.
+    fn index_response(&mut self, id: CanisterHttpMessageId, msg: &CanisterHttpMessage) {
+        if let CanisterHttpMessage::Response(response) = msg {
+            self.response_ids
+                .insert(ic_crypto::crypto_hash(response), id);
+        }
+    }
+}
+
+impl CanisterHttpPool for CanisterHttpPoolImpl {
+    fn get_validated_shares(&self) -> Box<dyn Iterator<Item = &CanisterHttpResponseShare> + '_> {
+        Box::new(self.pool.validated().filter_map(|msg| match msg {
+            CanisterHttpMessage::Share(share) => Some(share),
+            CanisterHttpMessage::Response(_) => None,
+        }))
+    }
+
+    fn get_validated_responses(&self) -> Box<dyn Iterator<Item = &CanisterHttpResponse> + '_> {
+        Box::new(self.pool.validated().filter_map(|msg| match msg {
+            CanisterHttpMessage::Response(response) => Some(response),
+            CanisterHttpMessage::Share(_) => None,
+        }))
+    }
+
+    fn get_unvalidated(&self) -> Box<dyn Iterator<Item = &CanisterHttpMessage> + '_> {
+        Box::new(self.pool.unvalidated())
+    }
+
+    fn get_validated_response(
+        &self,
+        content_hash: &CryptoHashOf<CanisterHttpResponse>,
+    ) -> Option<&CanisterHttpResponse> {
+        self.response_ids
+            .get(content_hash)
+            .and_then(|id| self.pool.get_validated(id))
+            .and_then(|msg| match msg {
+                CanisterHttpMessage::Response(response) => Some(response),
+                CanisterHttpMessage::Share(_) => None,
+            })
+    }
+}
+
+impl MutableCanisterHttpPool for CanisterHttpPoolImpl {
+    fn insert(&mut self, artifact: UnvalidatedArtifact<CanisterHttpMessage>) {
+        // The eviction policy does not depend on the height.
+        self.pool.insert(artifact, Height::from(0));
+    }
+
+    /// Applies the provided change set atomically.
+    ///
+    /// # Panics
+    ///
+    /// It panics if a message to be moved into the validated section cannot
+    /// be found in the unvalidated section.
+    fn apply_changes(&mut self, change_set: CanisterHttpChangeSet) {
+        for action in change_set {
+            match action {
+                CanisterHttpChangeAction::AddToValidated(share, response) => {
+                    let response = CanisterHttpMessage::Response(response);
+                    let id = self.pool.add_to_validated(response.clone());
+                    self.index_response(id, &response);
+                    self.pool
+                        .add_to_validated(CanisterHttpMessage::Share(share));
+                }
+                CanisterHttpChangeAction::MoveToValidated(msg) => {
+                    let id = self.pool.move_to_validated(msg.clone());
+                    self.index_response(id, &msg);
+                }
+                CanisterHttpChangeAction::RemoveFromUnvalidated(id)
+                | CanisterHttpChangeAction::HandleInvalid(id, _) => {
+                    self.pool.remove_unvalidated(&id);
+                }
+                // Removes all messages of requests that timed out before the
+                // given time.
+                CanisterHttpChangeAction::PurgeTimedOut(time) => {
+                    self.pool.purge(|msg| msg.timeout() < time);
+                    let pool = &self.pool;
+                    self.response_ids
+                        .retain(|_, id| pool.get_validated(id).is_some());
+                }
+            }
+        }
+    }
+}
+
+impl GossipPool<CanisterHttpMessage, CanisterHttpChangeSet> for CanisterHttpPoolImpl {
+    type MessageId = CanisterHttpMessageId;
+    type Filter = ();
+
+    fn contains(&self, id: &Self::MessageId) -> bool {
+        self.pool.contains(id)
+    }
+
+    fn get_validated_by_identifier(&self, id: &Self::MessageId) -> Option<CanisterHttpMessage> {
+        self.pool.get_validated(id).cloned()
+    }
+
+    fn get_all_validated_by_filter(
+        &self,
+        _filter: Self::Filter,
+    ) -> Box<dyn Iterator<Item = CanisterHttpMessage> + '_> {
+        Box::new(self.pool.validated().cloned())
+    }
+}
+
+impl CanisterHttpGossipPool for CanisterHttpPoolImpl {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_test_utilities::{
+        mock_time,
+        types::ids::{canister_test_id, node_test_id},
+    };
+    use ic_types::consensus::{
+        canister_http::{CanisterHttpResponseContent, CanisterHttpResponseMetadata},
+        BasicSignature,
+    };
+    use ic_types::crypto::{BasicSig, BasicSigOf};
+    use ic_types::messages::CallbackId;
+    use ic_types::{RegistryVersion, Time};
+    use std::time::Duration;
+
+    fn make_response(id: u64, timeout: Time) -> CanisterHttpResponse {
+        CanisterHttpResponse {
+            canister_id: canister_test_id(0),
+            id: CallbackId::from(id),
+            timeout,
+            content: CanisterHttpResponseContent::Success(vec![1, 2, 3]),
+        }
+    }
+
+    fn make_share(response: &CanisterHttpResponse, signer: u64) -> CanisterHttpResponseShare {
+        CanisterHttpResponseShare {
+            content: CanisterHttpResponseMetadata {
+                canister_id: response.canister_id,
+                id: response.id,
+                timeout: response.timeout,
+                content_hash: ic_crypto::crypto_hash(response),
+                registry_version: RegistryVersion::from(1),
+            },
+            signature: BasicSignature {
+                signature: BasicSigOf::new(BasicSig(vec![])),
+                signer: node_test_id(signer),
+            },
+        }
+    }
+
+    fn insert(pool: &mut CanisterHttpPoolImpl, msg: &CanisterHttpMessage) {
+        pool.insert(UnvalidatedArtifact {
+            message: msg.clone(),
+            peer_id: node_test_id(1),
+            timestamp: mock_time(),
+        });
+    }
+
+    #[test]
+    fn validated_responses_are_found_by_their_hash() {
+        let mut pool = CanisterHttpPoolImpl::new(MetricsRegistry::new());
+        let timeout = mock_time() + Duration::from_secs(10);
+        let (own, received) = (make_response(0, timeout), make_response(1, timeout));
+        let received_share = CanisterHttpMessage::Share(make_share(&received, 1));
+        let received_response = CanisterHttpMessage::Response(received.clone());
+        insert(&mut pool, &received_share);
+        insert(&mut pool, &received_response);
+        assert_eq!(
+            pool.get_validated_response(&ic_crypto::crypto_hash(&received)),
+            None
+        );
+
+        pool.apply_changes(vec![
+            CanisterHttpChangeAction::AddToValidated(make_share(&own, 0), own.clone()),
+            CanisterHttpChangeAction::MoveToValidated(received_share),
+            CanisterHttpChangeAction::MoveToValidated(received_response),
+        ]);
+        assert_eq!(pool.get_unvalidated().count(), 0);
+        assert_eq!(pool.get_validated_shares().count(), 2);
+        assert_eq!(pool.get_validated_responses().count(), 2);
+        for response in [&own, &received].iter() {
+            assert_eq!(
+                pool.get_validated_response(&ic_crypto::crypto_hash(*response)),
+                Some(*response)
+            );
+        }
+    }
+
+    #[test]
+    fn messages_of_timed_out_requests_are_removed() {
+        let mut pool = CanisterHttpPoolImpl::new(MetricsRegistry::new());
+        let timeout = mock_time() + Duration::from_secs(10);
+        let (expired, pending) = (
+            make_response(0, timeout),
+            make_response(1, timeout + Duration::from_secs(10)),
+        );
+        insert(
+            &mut pool,
+            &CanisterHttpMessage::Share(make_share(&expired, 1)),
+        );
+        insert(
+            &mut pool,
+            &CanisterHttpMessage::Share(make_share(&pending, 1)),
+        );
+        pool.apply_changes(vec![
+            CanisterHttpChangeAction::AddToValidated(make_share(&expired, 0), expired.clone()),
+            CanisterHttpChangeAction::AddToValidated(make_share(&pending, 0), pending.clone()),
+            CanisterHttpChangeAction::PurgeTimedOut(timeout + Duration::from_secs(1)),
+        ]);
+        assert_eq!(
+            pool.get_validated_response(&ic_crypto::crypto_hash(&expired)),
+            None
+        );
+        assert_eq!(
+            pool.get_validated_response(&ic_crypto::crypto_hash(&pending)),
+            Some(&pending)
+        );
+        assert_eq!(pool.get_validated_shares().count(), 1);
+        assert_eq!(pool.get_validated_responses().count(), 1);
+        assert_eq!(pool.get_unvalidated().count(), 1);
+        assert!(pool
+            .get_unvalidated()
+            .all(|msg| msg.timeout() == pending.timeout));
+    }
+}
@@ -1,10 +1,15 @@
+use crate::dump::{DumpOptions, DumpedArtifact, PoolDump};
 use crate::height_index::HeightIndex;
-use crate::metrics::{PoolMetrics, POOL_TYPE_UNVALIDATED, POOL_TYPE_VALIDATED};
+use crate::metrics::{
+    PoolArtifactMetrics, PoolMetrics, UnvalidatedLimitMetrics, POOL_TYPE_UNVALIDATED,
+    POOL_TYPE_VALIDATED,
+};
+use crate::unvalidated_limiter::{artifact_size_bytes, Admission, UnvalidatedLimiter};
 use ic_config::artifact_pool::{ArtifactPoolConfig, PersistentPoolBackend};
 use ic_crypto::crypto_hash;
 use ic_interfaces::{
     certification::{CertificationPool, ChangeAction, ChangeSet, MutableCertificationPool},
-    consensus_pool::HeightIndexedPool,
+    consensus_pool::{HeightIndexedPool, HeightRange},
     gossip_pool::{CertificationGossipPool, GossipPool},
 };
 use ic_logger::ReplicaLogger;
@@ -18,6 +23,7 @@ use ic_types::{
     Height,
 };
 use std::collections::HashSet;
+use std::path::Path;
 
 /// Certification pool contains 2 types of artifacts: partial and
 /// multi-signatures of (height, hash) pairs, where hash corresponds to an
@@ -27,14 +33,40 @@ pub struct CertificationPoolImpl {
     // performance by checking for full certifications first.
     unvalidated_shares: HeightIndex<CertificationShare>,
     unvalidated_certifications: HeightIndex<Certification>,
+    unvalidated_limiter: UnvalidatedLimiter<CertificationMessage>,
 
     pub persistent_pool: Box<dyn MutablePoolSection + Send + Sync>,
 
     unvalidated_pool_metrics: PoolMetrics,
     validated_pool_metrics: PoolMetrics,
+    unvalidated_limit_metrics: UnvalidatedLimitMetrics,
+    artifact_metrics: PoolArtifactMetrics,
 }
 
-const POOL_CERTIFICATION: &str = "certification";
+/// The name of the pool in metrics and dumps.
+pub const POOL_CERTIFICATION: &str = "certification";
+const ARTIFACT_TYPE_CERTIFICATION: &str = "certification";
+const ARTIFACT_TYPE_CERTIFICATION_SHARE: &str = "certification_share";
+
+fn artifact_type(msg: &CertificationMessage) -> &'static str {
+    match msg {
+        CertificationMessage::Certification(_) => ARTIFACT_TYPE_CERTIFICATION,
+        CertificationMessage::CertificationShare(_) => ARTIFACT_TYPE_CERTIFICATION_SHARE,
+    }
+}
+
+/// Returns the number of artifacts in the given index below the given height.
+fn count_below<T>(index: &dyn HeightIndexedPool<T>, height: Height) -> usize {
+    match index.height_range() {
+        Some(range) if range.min < height => index
+            .get_by_height_range(HeightRange::new(
+                range.min,
+                range.max.min(height.decrement()),
+            ))
+            .count(),
+        _ => 0,
+    }
+}
 
 impl CertificationPoolImpl {
     pub fn new(
@@ -42,24 +74,50 @@ impl CertificationPoolImpl {
         log: ReplicaLogger,
         metrics_registry: MetricsRegistry,
     ) -> Self {
+        let persistent_pool_read_only = config.persistent_pool_read_only;
         let persistent_pool = match config.persistent_pool_backend {
-            PersistentPoolBackend::Lmdb(lmdb_config) => Box::new(
-                crate::lmdb_pool::PersistentHeightIndexedPool::new_certification_pool(
-                    lmdb_config,
-                    config.persistent_pool_read_only,
-                    log,
-                ),
-            ) as Box<_>,
-            PersistentPoolBackend::RocksDB(config) => Box::new(
-                crate::rocksdb_pool::PersistentHeightIndexedPool::new_certification_pool(
-                    config, log,
-                ),
-            ) as Box<_>,
+            PersistentPoolBackend::Lmdb(lmdb_config) => {
+                let mut pool =
+                    crate::lmdb_pool::PersistentHeightIndexedPool::new_certification_pool(
+                        lmdb_config,
+                        persistent_pool_read_only,
+                        log,
+                    );
+                if !persistent_pool_read_only {
+                    pool.start_background_purge(&metrics_registry, POOL_CERTIFICATION);
+                }
+                Box::new(pool) as Box<_>
+            }
+            PersistentPoolBackend::RocksDB(config) => {
+                if !persistent_pool_read_only {
+                    crate::migration::migrate_certification_pool(&config, &log);
+                }
+                Box::new(
+                    crate::rocksdb_pool::PersistentHeightIndexedPool::new_certification_pool(
+                        config, log,
+                    ),
+                ) as Box<_>
+            }
         };
 
+        let artifact_metrics = PoolArtifactMetrics::new(&metrics_registry, POOL_CERTIFICATION);
+        artifact_metrics.observe_existing(
+            POOL_TYPE_VALIDATED,
+            ARTIFACT_TYPE_CERTIFICATION,
+            persistent_pool.certifications().get_all().count(),
+        );
+        artifact_metrics.observe_existing(
+            POOL_TYPE_VALIDATED,
+            ARTIFACT_TYPE_CERTIFICATION_SHARE,
+            persistent_pool.certification_shares().get_all().count(),
+        );
+
         CertificationPoolImpl {
             unvalidated_shares: HeightIndex::default(),
             unvalidated_certifications: HeightIndex::default(),
+            unvalidated_limiter: UnvalidatedLimiter::new(
+                config.certification_pool_unvalidated_limits,
+            ),
             persistent_pool,
             unvalidated_pool_metrics: PoolMetrics::new(
                 metrics_registry.clone(),
@@ -67,10 +125,105 @@ impl CertificationPoolImpl {
                 POOL_TYPE_UNVALIDATED,
             ),
             validated_pool_metrics: PoolMetrics::new(
-                metrics_registry,
+                metrics_registry.clone(),
                 POOL_CERTIFICATION,
                 POOL_TYPE_VALIDATED,
             ),
+            unvalidated_limit_metrics: UnvalidatedLimitMetrics::new(
+                &metrics_registry,
+                POOL_CERTIFICATION,
+            ),
+            artifact_metrics,
+        }
+    }
+
+    /// Dumps the validated section of the pool to the given file, and
+    /// returns the number of dumped artifacts.
+    pub fn dump(&self, path: &Path, options: &DumpOptions) -> std::io::Result<usize> {
+        self.pool_dump(options)?.write_to(path)
+    }
+
+    /// Returns the dump of the validated section of the pool.
+    pub fn pool_dump(&self, options: &DumpOptions) -> std::io::Result<PoolDump> {
+        let mut artifacts = Vec::new();
+        for certification in self.persistent_pool.certifications().get_all() {
+            artifacts.push(
+                DumpedArtifact::new(
+                    ARTIFACT_TYPE_CERTIFICATION,
+                    Some(certification.height),
+                    &crypto_hash(&certification).get_ref().0,
+                    &certification,
+                    options,
+                )?
+                .with_signature(&certification.signed.signature)?,
+            );
+        }
+        for share in self.persistent_pool.certification_shares().get_all() {
+            artifacts.push(
+                DumpedArtifact::new(
+                    ARTIFACT_TYPE_CERTIFICATION_SHARE,
+                    Some(share.height),
+                    &crypto_hash(&share).get_ref().0,
+                    &share,
+                    options,
+                )?
+                .with_signature(&share.signed.signature)?,
+            );
+        }
+        Ok(PoolDump::new(POOL_CERTIFICATION, artifacts))
+    }
+
+    /// Removes the given message from the unvalidated section.
+    fn remove_unvalidated(&mut self, msg: &CertificationMessage) {
+        let height = msg.height();
+        self.unvalidated_limiter.remove(msg);
+        let removed = match msg {
+            CertificationMessage::CertificationShare(share) => {
+                self.unvalidated_shares.remove(height, share)
+            }
+            CertificationMessage::Certification(cert) => {
+                self.unvalidated_certifications.remove(height, cert)
+            }
+        };
+        if removed {
+            self.artifact_metrics
+                .observe_remove(POOL_TYPE_UNVALIDATED, artifact_type(msg));
+        }
+    }
+
+    /// Records the removal of all artifacts below the given height.
+    fn observe_remove_all_below(&self, height: Height) {
+        let removed = [
+            (
+                POOL_TYPE_UNVALIDATED,
+                ARTIFACT_TYPE_CERTIFICATION,
+                self.unvalidated_certifications
+                    .range(..height)
+                    .map(|(_, certifications)| certifications.len())
+                    .sum(),
+            ),
+            (
+                POOL_TYPE_UNVALIDATED,
+                ARTIFACT_TYPE_CERTIFICATION_SHARE,
+                self.unvalidated_shares
+                    .range(..height)
+                    .map(|(_, shares)| shares.len())
+                    .sum(),
+            ),
+            (
+                POOL_TYPE_VALIDATED,
+                ARTIFACT_TYPE_CERTIFICATION,
+                count_below(self.persistent_pool.certifications(), height),
+            ),
+            (
+                POOL_TYPE_VALIDATED,
+                ARTIFACT_TYPE_CERTIFICATION_SHARE,
+                count_below(self.persistent_pool.certification_shares(), height),
+            ),
+        ];
+        for (pool_type, artifact_type, count) in removed.iter() {
+            self.artifact_metrics
+                .observe_removes(pool_type, artifact_type, *count);
         }
     }
 
@@ -78,7 +231,9 @@ impl CertificationPoolImpl {
         self.persistent_pool.certifications().get_all()
     }
 
-    fn insert_validated_certification(&self, certification: Certification) {
+    /// Inserts the given certification into the validated section, and
+    /// returns true if it was not there yet.
+    fn insert_validated_certification(&self, certification: Certification) -> bool {
         if let Some(existing_certification) = self
             .persistent_pool
             .certifications()
@@ -88,9 +243,11 @@ impl CertificationPoolImpl {
             if certification != existing_certification {
                 panic!("Certifications are not expected to be added more than once per height.");
             }
+            false
         } else {
             self.persistent_pool
-                .insert(CertificationMessage::Certification(certification))
+                .insert(CertificationMessage::Certification(certification));
+            true
         }
     }
 }
@@ -98,21 +255,45 @@ impl CertificationPoolImpl {
 impl MutableCertificationPool for CertificationPoolImpl {
     fn insert(&mut self, msg: CertificationMessage) {
         let height = msg.height();
-        match &msg {
+        let size_bytes = artifact_size_bytes(&msg);
+        let admission = self
+            .unvalidated_limiter
+            .admit(msg.clone(), height, size_bytes);
+        self.unvalidated_limit_metrics.observe_admission(&admission);
+        match admission {
+            Admission::Accepted { evicted } => {
+                for evicted_msg in evicted.iter() {
+                    self.remove_unvalidated(evicted_msg);
+                }
+            }
+            Admission::Rejected => return,
+        }
+        let inserted = match &msg {
             CertificationMessage::CertificationShare(share) => {
-                if self.unvalidated_shares.insert(height, share) {
+                let inserted = self.unvalidated_shares.insert(height, share);
+                if inserted {
                     self.unvalidated_pool_metrics
                         .received_artifact_bytes
                         .observe(std::mem::size_of_val(share) as f64);
                 }
+                inserted
             }
             CertificationMessage::Certification(cert) => {
-                if self.unvalidated_certifications.insert(height, cert) {
+                let inserted = self.unvalidated_certifications.insert(height, cert);
+                if inserted {
                     self.unvalidated_pool_metrics
                         .received_artifact_bytes
                         .observe(std::mem::size_of_val(cert) as f64);
                 }
+                inserted
             }
+        };
+        if inserted {
+            self.artifact_metrics.observe_insert(
+                POOL_TYPE_UNVALIDATED,
+                artifact_type(&msg),
+                size_bytes,
+            );
         }
     }
 
@@ -122,59 +303,54 @@ impl MutableCertificationPool for CertificationPoolImpl {
                 self.validated_pool_metrics
                     .received_artifact_bytes
                     .observe(std::mem::size_of_val(&msg) as f64);
+                self.artifact_metrics.observe_insert(
+                    POOL_TYPE_VALIDATED,
+                    artifact_type(&msg),
+                    artifact_size_bytes(&msg),
+                );
                 self.persistent_pool.insert(msg);
             }
 
             ChangeAction::MoveToValidated(msg) => {
-                let height = msg.height();
-                match msg {
+                self.remove_unvalidated(&msg);
+                let artifact_type = artifact_type(&msg);
+                let size_bytes = artifact_size_bytes(&msg);
+                let inserted = match msg {
                     CertificationMessage::CertificationShare(share) => {
-                        self.unvalidated_shares.remove(height, &share);
                         self.validated_pool_metrics
                             .received_artifact_bytes
                             .observe(std::mem::size_of_val(&share) as f64);
                         self.persistent_pool
                             .insert(CertificationMessage::CertificationShare(share));
+                        true
                     }
                     CertificationMessage::Certification(cert) => {
-                        self.unvalidated_certifications.remove(height, &cert);
                         self.validated_pool_metrics
                             .received_artifact_bytes
                             .observe(std::mem::size_of_val(&cert) as f64);
-                        self.insert_validated_certification(cert);
+                        self.insert_validated_certification(cert)
                     }
                 };
+                if inserted {
+                    self.artifact_metrics.observe_insert(
+                        POOL_TYPE_VALIDATED,
+                        artifact_type,
+                        size_bytes,
+                    );
+                }
             }
 
-            ChangeAction::RemoveFromUnvalidated(msg) => {
-                let height = msg.height();
-                match msg {
-                    CertificationMessage::CertificationShare(share) => {
-                        self.unvalidated_shares.remove(height, &share)
-                    }
-                    CertificationMessage::Certification(cert) => {
-                        self.unvalidated_certifications.remove(height, &cert)
-                    }
-                };
-            }
+            ChangeAction::RemoveFromUnvalidated(msg) => self.remove_unvalidated(&msg),
 
             ChangeAction::RemoveAllBelow(height) => {
+                self.observe_remove_all_below(height);
+                self.unvalidated_limiter.remove_all_below(height);
                 self.unvalidated_shares.remove_all_below(height);
                 self.unvalidated_certifications.remove_all_below(height);
                 self.persistent_pool.purge_below(height);
             }
 
-            ChangeAction::HandleInvalid(msg, _) => {
-                let height = msg.height();
-                match msg {
-                    CertificationMessage::CertificationShare(share) => {
-                        self.unvalidated_shares.remove(height, &share);
-                    }
-                    CertificationMessage::Certification(cert) => {
-                        self.unvalidated_certifications.remove(height, &cert);
-                    }
-                };
-            }
+            ChangeAction::HandleInvalid(msg, _) => self.remove_unvalidated(&msg),
         });
     }
 }
@@ -318,6 +494,7 @@ impl CertificationGossipPool for CertificationPoolImpl {}
 #[cfg(test)]
 mod tests {
     use super::*;
+    use ic_config::artifact_pool::{UnvalidatedEvictionPolicy, UnvalidatedSectionLimits};
     use ic_interfaces::certification::{CertificationPool, MutableCertificationPool};
     use ic_logger::replica_logger::no_op_logger;
     use ic_test_utilities::consensus::fake::{Fake, FakeSigner};
@@ -413,6 +590,46 @@ mod tests {
         })
     }
 
+    #[test]
+    fn test_certification_pool_unvalidated_limits() {
+        ic_test_utilities::artifact_pool_config::with_test_pool_config(|mut pool_config| {
+            pool_config.certification_pool_unvalidated_limits = UnvalidatedSectionLimits {
+                max_count: 2,
+                max_size_bytes: usize::MAX,
+                eviction_policy: UnvalidatedEvictionPolicy::OldestFirst,
+            };
+            let mut pool =
+                CertificationPoolImpl::new(pool_config, no_op_logger(), MetricsRegistry::new());
+            pool.insert(fake_share(2, 0));
+            pool.insert(fake_share(1, 1));
+            pool.insert(fake_cert(3));
+            assert_eq!(
+                pool.unvalidated_shares_at_height(Height::from(2)).count(),
+                0
+            );
+            assert_eq!(
+                pool.unvalidated_shares_at_height(Height::from(1)).count(),
+                1
+            );
+            assert_eq!(
+                pool.unvalidated_certifications_at_height(Height::from(3))
+                    .count(),
+                1
+            );
+            assert_eq!(pool.unvalidated_limit_metrics.evicted_artifacts.get(), 1);
+
+            // Removed artifacts free capacity.
+            pool.apply_changes(vec![ChangeAction::RemoveAllBelow(Height::from(3))]);
+            pool.insert(fake_share(4, 0));
+            assert_eq!(
+                pool.unvalidated_certifications_at_height(Height::from(3))
+                    .count(),
+                1
+            );
+            assert_eq!(pool.unvalidated_limit_metrics.evicted_artifacts.get(), 1);
+        })
+    }
+
     #[test]
     fn test_certification_pool_add_to_validated() {
         ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
@@ -469,6 +686,37 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_artifact_metrics() {
+        ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
+            let mut pool =
+                CertificationPoolImpl::new(pool_config, no_op_logger(), MetricsRegistry::new());
+            let share_msg = fake_share(10, 10);
+            let cert_msg = fake_cert(20);
+            pool.insert(share_msg.clone());
+            pool.insert(cert_msg.clone());
+            pool.apply_changes(vec![
+                ChangeAction::MoveToValidated(share_msg),
+                ChangeAction::MoveToValidated(cert_msg),
+            ]);
+            let artifact_types = [
+                ARTIFACT_TYPE_CERTIFICATION,
+                ARTIFACT_TYPE_CERTIFICATION_SHARE,
+            ];
+            for artifact_type in artifact_types.iter() {
+                let metrics = &pool.artifact_metrics;
+                assert_eq!(metrics.artifacts(POOL_TYPE_VALIDATED, artifact_type), 1);
+                assert_eq!(metrics.artifacts(POOL_TYPE_UNVALIDATED, artifact_type), 0);
+            }
+
+            pool.apply_changes(vec![ChangeAction::RemoveAllBelow(Height::from(21))]);
+            for artifact_type in artifact_types.iter() {
+                let metrics = &pool.artifact_metrics;
+                assert_eq!(metrics.artifacts(POOL_TYPE_VALIDATED, artifact_type), 0);
+            }
+        });
+    }
+
     #[test]
     fn test_certification_pool_remove_all() {
         ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
@@ -1,19 +1,29 @@
 use crate::backup::Backup;
 use crate::{
+    consensus_pool::backup::ArtifactLog,
     consensus_pool_cache::{
         get_highest_catch_up_package, get_highest_finalized_block, update_summary_block,
         ConsensusCacheImpl,
     },
+    dump::{DumpOptions, DumpedArtifact, PoolDump},
     inmemory_pool::InMemoryPoolSection,
-    metrics::{LABEL_POOL_TYPE, POOL_TYPE_UNVALIDATED, POOL_TYPE_VALIDATED},
+    metrics::{
+        PoolArtifactMetrics, UnvalidatedLimitMetrics, LABEL_POOL_TYPE, POOL_TYPE_UNVALIDATED,
+        POOL_TYPE_VALIDATED,
+    },
+    ttl::ArtifactTtls,
+    unvalidated_limiter::{artifact_size_bytes, Admission, UnvalidatedLimiter},
+};
+use ic_config::artifact_pool::{
+    ArtifactPoolConfig, PersistentPoolBackend, UnvalidatedSectionLimits,
 };
-use ic_config::artifact_pool::{ArtifactPoolConfig, PersistentPoolBackend};
 use ic_consensus_message::ConsensusMessageHashable;
 use ic_interfaces::{
+    artifact_pool::{HasTimestamp, IntoInner},
     consensus_pool::{
         ChangeAction, ChangeSet, ConsensusPool, ConsensusPoolCache, HeightIndexedPool, HeightRange,
-        MutableConsensusPool, PoolSection, UnvalidatedConsensusArtifact,
-        ValidatedConsensusArtifact,
+        HeightWatch, MutableConsensusPool, PoolSection, SnapshotConsensusPool,
+        UnvalidatedConsensusArtifact, ValidatedConsensusArtifact,
     },
     gossip_pool::{ConsensusGossipPool, GossipPool},
     time_source::TimeSource,
@@ -21,12 +31,18 @@ use ic_interfaces::{
 use ic_logger::ReplicaLogger;
 use ic_types::{
     artifact::ConsensusMessageId, consensus::catchup::CUPWithOriginalProtobuf, consensus::*,
-    Height, SubnetId, Time,
+    time::current_time, Height, SubnetId, Time,
 };
 use prometheus::{labels, opts, IntGauge};
+use std::collections::BTreeMap;
 use std::marker::PhantomData;
+use std::path::Path;
 use std::sync::Arc;
 use std::time::Duration;
+use tokio::sync::watch;
+
+pub mod backup;
+pub mod replay;
 
 #[derive(Debug, Clone)]
 pub enum PoolSectionOp<T> {
@@ -70,6 +86,8 @@ struct PerTypeMetrics<T> {
     phantom: PhantomData<T>,
 }
 
+/// The name of the pool in metrics and dumps.
+pub const POOL_CONSENSUS: &str = "consensus";
 const LABEL_TYPE: &str = "type";
 const LABEL_STAT: &str = "stat";
 
@@ -187,49 +205,333 @@ impl PoolMetrics {
     }
 }
 
+/// Returns the name of the type of the artifact with the given hash, as used in
+/// the metrics.
+fn artifact_type(hash: &ConsensusMessageHash) -> &'static str {
+    match hash {
+        ConsensusMessageHash::RandomBeacon(_) => "random_beacon",
+        ConsensusMessageHash::Finalization(_) => "finalization",
+        ConsensusMessageHash::Notarization(_) => "notarization",
+        ConsensusMessageHash::BlockProposal(_) => "block_proposal",
+        ConsensusMessageHash::RandomBeaconShare(_) => "random_beacon_share",
+        ConsensusMessageHash::NotarizationShare(_) => "notarization_share",
+        ConsensusMessageHash::FinalizationShare(_) => "finalization_share",
+        ConsensusMessageHash::RandomTape(_) => "random_tape",
+        ConsensusMessageHash::RandomTapeShare(_) => "random_tape_share",
+        ConsensusMessageHash::CatchUpPackage(_) => "catch_up_package",
+        ConsensusMessageHash::CatchUpPackageShare(_) => "catch_up_package_share",
+    }
+}
+
+/// Calls `f` with the ID of every artifact in the given section, or only of
+/// the artifacts below the given height, if any.
+fn for_each_artifact<T>(
+    section: &dyn PoolSection<T>,
+    below: Option<Height>,
+    f: &mut dyn FnMut(ConsensusMessageId),
+) {
+    fn visit<T: ConsensusMessageHashable>(
+        index: &dyn HeightIndexedPool<T>,
+        below: Option<Height>,
+        f: &mut dyn FnMut(ConsensusMessageId),
+    ) {
+        let range = match (index.height_range(), below) {
+            (Some(range), None) => range,
+            (Some(range), Some(height)) => match range.below(height) {
+                Some(range) => range,
+                None => return,
+            },
+            (None, _) => return,
+        };
+        for artifact in index.get_by_height_range(range) {
+            f(artifact.get_id());
+        }
+    }
+    visit(section.random_beacon(), below, f);
+    visit(section.block_proposal(), below, f);
+    visit(section.notarization(), below, f);
+    visit(section.finalization(), below, f);
+    visit(section.random_beacon_share(), below, f);
+    visit(section.notarization_share(), below, f);
+    visit(section.finalization_share(), below, f);
+    visit(section.random_tape(), below, f);
+    visit(section.random_tape_share(), below, f);
+    visit(section.catch_up_package(), below, f);
+    visit(section.catch_up_package_share(), below, f);
+}
+
+/// Returns the height of the highest catch-up package in the given section.
+fn cup_height(section: &dyn PoolSection<ValidatedConsensusArtifact>) -> Height {
+    section
+        .catch_up_package()
+        .max_height()
+        .unwrap_or_else(|| Height::from(0))
+}
+
+/// Returns an in-memory copy of the artifacts of the given validated section
+/// at or above the height of its highest catch-up package.
+fn copy_recent_artifacts(
+    section: &dyn PoolSection<ValidatedConsensusArtifact>,
+    log: ReplicaLogger,
+) -> InMemoryPoolSection<ValidatedConsensusArtifact> {
+    let cup_height = cup_height(section);
+    let mut ops = PoolSectionOps::new();
+    for_each_artifact(section, None, &mut |msg_id| {
+        if msg_id.height < cup_height {
+            return;
+        }
+        if let (Some(msg), Some(timestamp)) = (section.get(&msg_id), section.get_timestamp(&msg_id))
+        {
+            ops.insert(ValidatedConsensusArtifact { msg, timestamp });
+        }
+    });
+    let mut copy = InMemoryPoolSection::new(log);
+    copy.mutate(ops);
+    copy
+}
+
+/// Returns the dump of the given validated section.
+fn dump_section(
+    section: &dyn PoolSection<ValidatedConsensusArtifact>,
+    options: &DumpOptions,
+) -> std::io::Result<PoolDump> {
+    let mut artifacts = Vec::new();
+    let mut result = Ok(());
+    for_each_artifact(section, None, &mut |msg_id| {
+        if result.is_err() {
+            return;
+        }
+        if let Some(msg) = section.get(&msg_id) {
+            match DumpedArtifact::from_consensus_message(
+                artifact_type(&msg_id.hash),
+                msg_id.height,
+                &msg_id.hash.digest().0,
+                &msg,
+                options,
+            ) {
+                Ok(artifact) => artifacts.push(artifact),
+                Err(err) => result = Err(err),
+            }
+        }
+    });
+    result?;
+    Ok(PoolDump::new(POOL_CONSENSUS, artifacts))
+}
+
+/// The IDs and insertion times of the artifacts of a section of the pool by
+/// height, so that the artifact metrics can be updated and the expired
+/// artifacts found without reading the artifacts from the section, which
+/// deserializes them for the persistent pool.
+#[derive(Default)]
+struct SectionArtifacts {
+    by_height: BTreeMap<Height, Vec<(ConsensusMessageHash, Time)>>,
+}
+
+impl SectionArtifacts {
+    /// Returns the artifacts of the given section.
+    fn new<T>(section: &dyn PoolSection<T>) -> Self {
+        let mut artifacts = Self::default();
+        for_each_artifact(section, None, &mut |msg_id| {
+            if let Some(timestamp) = section.get_timestamp(&msg_id) {
+                artifacts.insert(&msg_id, timestamp);
+            }
+        });
+        artifacts
+    }
+
+    /// Adds the given artifact, and returns true if it was not there yet.
+    fn insert(&mut self, msg_id: &ConsensusMessageId, timestamp: Time) -> bool {
+        let artifacts = self.by_height.entry(msg_id.height).or_default();
+        if artifacts.iter().any(|(hash, _)| *hash == msg_id.hash) {
+            return false;
+        }
+        artifacts.push((msg_id.hash.clone(), timestamp));
+        true
+    }
+
+    /// Removes the given artifact, and returns true if it was there.
+    fn remove(&mut self, msg_id: &ConsensusMessageId) -> bool {
+        let artifacts = match self.by_height.get_mut(&msg_id.height) {
+            Some(artifacts) => artifacts,
+            None => return false,
+        };
+        let len = artifacts.len();
+        artifacts.retain(|(hash, _)| *hash != msg_id.hash);
+        let removed = artifacts.len() != len;
+        if artifacts.is_empty() {
+            self.by_height.remove(&msg_id.height);
+        }
+        removed
+    }
+
+    /// Removes the artifacts below the given height, and returns their hashes
+    /// and insertion times.
+    fn purge_below(&mut self, height: Height) -> Vec<(ConsensusMessageHash, Time)> {
+        let kept = self.by_height.split_off(&height);
+        std::mem::replace(&mut self.by_height, kept)
+            .into_iter()
+            .flat_map(|(_, artifacts)| artifacts)
+            .collect()
+    }
+
+    /// Returns the IDs of the expired artifacts, only considering the
+    /// artifacts below the given height, if any.
+    fn expired(
+        &self,
+        ttls: &ArtifactTtls,
+        below: Option<Height>,
+        now: Time,
+    ) -> Vec<ConsensusMessageId> {
+        let range = match below {
+            Some(height) => self.by_height.range(..height),
+            None => self.by_height.range(..),
+        };
+        range
+            .flat_map(|(height, artifacts)| {
+                artifacts
+                    .iter()
+                    .filter(|(hash, timestamp)| {
+                        ttls.is_expired(artifact_type(hash), *timestamp, now)
+                    })
+                    .map(move |(hash, _)| ConsensusMessageId {
+                        hash: hash.clone(),
+                        height: *height,
+                    })
+            })
+            .collect()
+    }
+
+    /// Records the given operations in the artifact metrics, and applies them
+    /// to the artifacts.
+    fn observe_ops<T: IntoInner<ConsensusMessage> + HasTimestamp>(
+        &mut self,
+        metrics: &PoolArtifactMetrics,
+        pool_type: &str,
+        ops: &PoolSectionOps<T>,
+    ) {
+        for op in ops.ops.iter() {
+            match op {
+                PoolSectionOp::Insert(artifact) => {
+                    let msg = artifact.as_ref();
+                    let msg_id = msg.get_id();
+                    if self.insert(&msg_id, artifact.timestamp()) {
+                        metrics.observe_insert(
+                            pool_type,
+                            artifact_type(&msg_id.hash),
+                            artifact_size_bytes(msg),
+                        );
+                    }
+                }
+                PoolSectionOp::Remove(msg_id) => {
+                    if self.remove(msg_id) {
+                        metrics.observe_remove(pool_type, artifact_type(&msg_id.hash));
+                    }
+                }
+                PoolSectionOp::PurgeBelow(height) => {
+                    let now = current_time();
+                    for (hash, timestamp) in self.purge_below(*height) {
+                        metrics.observe_purge(pool_type, artifact_type(&hash), timestamp, now);
+                    }
+                }
+            }
+        }
+    }
+}
+
 pub struct ConsensusPoolImpl {
     validated: Box<dyn InitializablePoolSection + Send + Sync>,
-    unvalidated: Box<dyn MutablePoolSection<UnvalidatedConsensusArtifact> + Send + Sync>,
+    unvalidated: InMemoryPoolSection<UnvalidatedConsensusArtifact>,
+    /// The validated artifacts at or above the height of the highest
+    /// catch-up package, kept in memory to be shared with the snapshots of
+    /// the pool.
+    recent_validated: InMemoryPoolSection<ValidatedConsensusArtifact>,
     validated_metrics: PoolMetrics,
     unvalidated_metrics: PoolMetrics,
+    unvalidated_limiter: UnvalidatedLimiter<ConsensusMessageId>,
+    unvalidated_limit_metrics: UnvalidatedLimitMetrics,
+    validated_artifacts: SectionArtifacts,
+    unvalidated_artifacts: SectionArtifacts,
+    artifact_metrics: PoolArtifactMetrics,
+    artifact_ttls: ArtifactTtls,
     cache: Arc<ConsensusCacheImpl>,
     backup: Option<Backup>,
+    artifact_log: Option<ArtifactLog>,
 }
 
 // A temporary pool implementation used for genesis initialization.
 pub struct UncachedConsensusPoolImpl {
     pub validated: Box<dyn InitializablePoolSection + Send + Sync>,
-    unvalidated: Box<dyn MutablePoolSection<UnvalidatedConsensusArtifact> + Send + Sync>,
+    unvalidated: InMemoryPoolSection<UnvalidatedConsensusArtifact>,
+    unvalidated_limits: UnvalidatedSectionLimits,
+    artifact_ttls: BTreeMap<String, Duration>,
+    // The pool is not notified of the changes applied to its sections, so
+    // the heights are refreshed whenever its finalized block is read.
+    finalized_height: HeightWatch,
+    certified_height: HeightWatch,
+    log: ReplicaLogger,
 }
 
 impl UncachedConsensusPoolImpl {
-    pub fn new(config: ArtifactPoolConfig, log: ReplicaLogger) -> UncachedConsensusPoolImpl {
+    pub fn new(
+        config: ArtifactPoolConfig,
+        log: ReplicaLogger,
+        metrics_registry: ic_metrics::MetricsRegistry,
+    ) -> UncachedConsensusPoolImpl {
+        let persistent_pool_read_only = config.persistent_pool_read_only;
         let validated = match config.persistent_pool_backend {
-            PersistentPoolBackend::Lmdb(lmdb_config) => Box::new(
-                crate::lmdb_pool::PersistentHeightIndexedPool::new_consensus_pool(
+            PersistentPoolBackend::Lmdb(lmdb_config) => {
+                let mut pool = crate::lmdb_pool::PersistentHeightIndexedPool::new_consensus_pool(
                     lmdb_config,
-                    config.persistent_pool_read_only,
+                    persistent_pool_read_only,
                     log.clone(),
-                ),
-            ) as Box<_>,
-            PersistentPoolBackend::RocksDB(config) => Box::new(
-                crate::rocksdb_pool::PersistentHeightIndexedPool::new_consensus_pool(
-                    config,
-                    log.clone(),
-                ),
-            ) as Box<_>,
+                );
+                if !persistent_pool_read_only {
+                    pool.start_background_purge(&metrics_registry, POOL_CONSENSUS);
+                }
+                Box::new(pool) as Box<_>
+            }
+            PersistentPoolBackend::RocksDB(config) => {
+                if !persistent_pool_read_only {
+                    crate::migration::migrate_consensus_pool(&config, &log);
+                }
+                Box::new(
+                    crate::rocksdb_pool::PersistentHeightIndexedPool::new_consensus_pool(
+                        config,
+                        log.clone(),
+                    ),
+                ) as Box<_>
+            }
         };
 
         UncachedConsensusPoolImpl {
             validated,
-            unvalidated: Box::new(InMemoryPoolSection::new(log)),
+            unvalidated: InMemoryPoolSection::new(log.clone()),
+            unvalidated_limits: config.consensus_pool_unvalidated_limits,
+            artifact_ttls: config.artifact_ttls,
+            finalized_height: HeightWatch::new(Height::from(0)),
+            certified_height: HeightWatch::new(Height::from(0)),
+            log,
         }
     }
+
+    /// Dumps the validated section of the pool to the given file, and
+    /// returns the number of dumped artifacts.
+    pub fn dump(&self, path: &Path, options: &DumpOptions) -> std::io::Result<usize> {
+        self.pool_dump(options)?.write_to(path)
+    }
+
+    /// Returns the dump of the validated section of the pool.
+    pub fn pool_dump(&self, options: &DumpOptions) -> std::io::Result<PoolDump> {
+        dump_section(self.validated.pool_section(), options)
+    }
 }
 
 impl ConsensusPoolCache for UncachedConsensusPoolImpl {
     fn finalized_block(&self) -> Block {
-        get_highest_finalized_block(self, &self.catch_up_package())
+        let block = get_highest_finalized_block(self, &self.catch_up_package());
+        self.finalized_height.update(block.height());
+        self.certified_height.update(block.context.certified_height);
+        block
     }
 
     fn consensus_time(&self) -> Option<Time> {
@@ -255,6 +557,18 @@ impl ConsensusPoolCache for UncachedConsensusPoolImpl {
         update_summary_block(self, &mut summary_block, &finalized_block);
         summary_block
     }
+
+    // The receivers are notified of the heights of the finalized blocks read
+    // from the pool, as it is not notified of the changes applied to it.
+    fn subscribe_finalized_height(&self) -> watch::Receiver<Height> {
+        self.finalized_block();
+        self.finalized_height.subscribe()
+    }
+
+    fn subscribe_certified_height(&self) -> watch::Receiver<Height> {
+        self.finalized_block();
+        self.certified_height.subscribe()
+    }
 }
 
 impl ConsensusPool for UncachedConsensusPoolImpl {
@@ -283,7 +597,8 @@ impl ConsensusPoolImpl {
         registry: ic_metrics::MetricsRegistry,
         log: ReplicaLogger,
     ) -> ConsensusPoolImpl {
-        let mut pool = UncachedConsensusPoolImpl::new(config.clone(), log.clone());
+        let mut pool =
+            UncachedConsensusPoolImpl::new(config.clone(), log.clone(), registry.clone());
         Self::init_genesis(catch_up_package, pool.validated.as_mut());
         let mut pool = Self::from_uncached(pool, registry.clone());
         // If the back up directory is set, instantiate the backup component
@@ -298,7 +613,15 @@ impl ConsensusPoolImpl {
                     .join(ic_types::ReplicaVersion::default().to_string()),
                 Duration::from_secs(config.retention_time_secs),
                 Duration::from_secs(config.purging_interval_secs),
-                registry,
+                registry.clone(),
+                log.clone(),
+            )
+        });
+        pool.artifact_log = config.artifact_log_config.map(|config| {
+            ArtifactLog::new(
+                config.log_path.join(subnet_id.to_string()),
+                config,
+                &registry,
                 log,
             )
         });
@@ -340,13 +663,36 @@ impl ConsensusPoolImpl {
         registry: ic_metrics::MetricsRegistry,
     ) -> ConsensusPoolImpl {
         let cache = Arc::new(ConsensusCacheImpl::new(&uncached));
+        let artifact_metrics = PoolArtifactMetrics::new(&registry, POOL_CONSENSUS);
+        let validated_artifacts = SectionArtifacts::new(uncached.validated.pool_section());
+        let unvalidated_artifacts = SectionArtifacts::new(uncached.unvalidated.pool_section());
+        for (pool_type, artifacts) in [
+            (POOL_TYPE_VALIDATED, &validated_artifacts),
+            (POOL_TYPE_UNVALIDATED, &unvalidated_artifacts),
+        ]
+        .iter()
+        {
+            for (hash, _) in artifacts.by_height.values().flatten() {
+                artifact_metrics.observe_existing(pool_type, artifact_type(hash), 1);
+            }
+        }
+        let recent_validated =
+            copy_recent_artifacts(uncached.validated.pool_section(), uncached.log);
         ConsensusPoolImpl {
             validated: uncached.validated,
             unvalidated: uncached.unvalidated,
+            recent_validated,
             validated_metrics: PoolMetrics::new(registry.clone(), POOL_TYPE_VALIDATED),
-            unvalidated_metrics: PoolMetrics::new(registry, POOL_TYPE_UNVALIDATED),
+            unvalidated_metrics: PoolMetrics::new(registry.clone(), POOL_TYPE_UNVALIDATED),
+            unvalidated_limiter: UnvalidatedLimiter::new(uncached.unvalidated_limits),
+            unvalidated_limit_metrics: UnvalidatedLimitMetrics::new(&registry, POOL_CONSENSUS),
+            validated_artifacts,
+            unvalidated_artifacts,
+            artifact_metrics,
+            artifact_ttls: ArtifactTtls::new(uncached.artifact_ttls),
             cache,
             backup: None,
+            artifact_log: None,
         }
     }
 
@@ -366,20 +712,98 @@ impl ConsensusPoolImpl {
         )
     }
 
+    /// Dumps the validated section of the pool to the given file, and
+    /// returns the number of dumped artifacts.
+    pub fn dump(&self, path: &Path, options: &DumpOptions) -> std::io::Result<usize> {
+        self.pool_dump(options)?.write_to(path)
+    }
+
+    /// Returns the dump of the validated section of the pool.
+    pub fn pool_dump(&self, options: &DumpOptions) -> std::io::Result<PoolDump> {
+        dump_section(self.validated.pool_section(), options)
+    }
+
     /// Get a copy of ConsensusPoolCache.
     pub fn get_cache(&self) -> Arc<dyn ConsensusPoolCache> {
         Arc::clone(&self.cache) as Arc<_>
     }
 
+    /// Returns a snapshot of the pool. Taking a snapshot is cheap, and the
+    /// snapshot is not affected by later changes to the pool, so it can be
+    /// read without holding the lock of the pool. The validated section of
+    /// the snapshot only holds the artifacts at or above the height of the
+    /// highest catch-up package, which is all that payload builders read.
+    pub fn snapshot(&self) -> ConsensusPoolSnapshot {
+        ConsensusPoolSnapshot {
+            validated: self.recent_validated.clone(),
+            unvalidated: self.unvalidated.clone(),
+            cache: self.cache.snapshot(),
+        }
+    }
+
     fn apply_changes_validated(&mut self, ops: PoolSectionOps<ValidatedConsensusArtifact>) {
         if !ops.ops.is_empty() {
+            self.validated_artifacts
+                .observe_ops(&self.artifact_metrics, POOL_TYPE_VALIDATED, &ops);
+            // Artifacts below the catch-up package were never copied, so only
+            // the removals of copied artifacts are applied to the copies.
+            let mut recent_ops = PoolSectionOps::new();
+            for op in ops.ops.iter() {
+                match op {
+                    PoolSectionOp::Remove(msg_id) if !self.recent_validated.contains(msg_id) => (),
+                    op => recent_ops.ops.push(op.clone()),
+                }
+            }
             self.validated.mutate(ops);
+            recent_ops.purge_below(cup_height(self.validated.pool_section()));
+            self.recent_validated.mutate(recent_ops);
             self.validated_metrics.update(self.validated.pool_section());
         }
     }
 
+    /// Removes the artifacts that are older than the retention duration of
+    /// their type, if a purge is due. Validated artifacts at or above the
+    /// height of the highest catch-up package are kept, as consensus still
+    /// needs them.
+    fn purge_expired(&mut self, now: Time) {
+        if !self.artifact_ttls.purge_due(now) {
+            return;
+        }
+        let cup_height = cup_height(self.validated.pool_section());
+        let mut validated_ops = PoolSectionOps::new();
+        for msg_id in self
+            .validated_artifacts
+            .expired(&self.artifact_ttls, Some(cup_height), now)
+        {
+            validated_ops.remove(msg_id);
+        }
+        let mut unvalidated_ops = PoolSectionOps::new();
+        for msg_id in self
+            .unvalidated_artifacts
+            .expired(&self.artifact_ttls, None, now)
+        {
+            unvalidated_ops.remove(msg_id);
+        }
+        self.apply_changes_validated(validated_ops);
+        self.apply_changes_unvalidated(unvalidated_ops);
+    }
+
     fn apply_changes_unvalidated(&mut self, ops: PoolSectionOps<UnvalidatedConsensusArtifact>) {
         if !ops.ops.is_empty() {
+            for op in ops.ops.iter() {
+                match op {
+                    PoolSectionOp::Insert(_) => (),
+                    PoolSectionOp::Remove(msg_id) => self.unvalidated_limiter.remove(msg_id),
+                    PoolSectionOp::PurgeBelow(height) => {
+                        self.unvalidated_limiter.remove_all_below(*height)
+                    }
+                }
+            }
+            self.unvalidated_artifacts.observe_ops(
+                &self.artifact_metrics,
+                POOL_TYPE_UNVALIDATED,
+                &ops,
+            );
             self.unvalidated.mutate(ops);
             self.unvalidated_metrics
                 .update(self.unvalidated.pool_section());
@@ -401,14 +825,53 @@ impl ConsensusPool for ConsensusPoolImpl {
     }
 }
 
+impl SnapshotConsensusPool for ConsensusPoolImpl {
+    fn snapshot(&self) -> Box<dyn ConsensusPool + Send + Sync> {
+        Box::new(ConsensusPoolImpl::snapshot(self))
+    }
+}
+
+/// A consistent view of the consensus pool at the time the snapshot was
+/// taken, see `ConsensusPoolImpl::snapshot`.
+pub struct ConsensusPoolSnapshot {
+    validated: InMemoryPoolSection<ValidatedConsensusArtifact>,
+    unvalidated: InMemoryPoolSection<UnvalidatedConsensusArtifact>,
+    cache: ConsensusCacheImpl,
+}
+
+impl ConsensusPool for ConsensusPoolSnapshot {
+    fn validated(&self) -> &dyn PoolSection<ValidatedConsensusArtifact> {
+        &self.validated
+    }
+
+    fn unvalidated(&self) -> &dyn PoolSection<UnvalidatedConsensusArtifact> {
+        &self.unvalidated
+    }
+
+    fn as_cache(&self) -> &dyn ConsensusPoolCache {
+        &self.cache
+    }
+}
+
 impl MutableConsensusPool for ConsensusPoolImpl {
     fn insert(&mut self, unvalidated_artifact: UnvalidatedConsensusArtifact) {
-        let mut ops = PoolSectionOps::new();
-        ops.insert(unvalidated_artifact);
-        self.apply_changes_unvalidated(ops);
+        let msg_id = unvalidated_artifact.message.get_id();
+        let height = msg_id.height;
+        let size_bytes = artifact_size_bytes(&unvalidated_artifact.message);
+        let admission = self.unvalidated_limiter.admit(msg_id, height, size_bytes);
+        self.unvalidated_limit_metrics.observe_admission(&admission);
+        if let Admission::Accepted { evicted } = admission {
+            let mut ops = PoolSectionOps::new();
+            for msg_id in evicted {
+                ops.remove(msg_id);
+            }
+            ops.insert(unvalidated_artifact);
+            self.apply_changes_unvalidated(ops);
+        }
     }
 
     fn apply_changes(&mut self, time_source: &dyn TimeSource, change_set: ChangeSet) {
+        self.purge_expired(time_source.get_relative_time());
         let updates = self.cache.prepare(&change_set);
         let mut unvalidated_ops = PoolSectionOps::new();
         let mut validated_ops = PoolSectionOps::new();
@@ -429,6 +892,11 @@ impl MutableConsensusPool for ConsensusPoolImpl {
                     let timestamp = self.unvalidated.get_timestamp(&msg_id).unwrap_or_else(|| {
                         panic!("Timestmap is not found for MoveToValidated: {:?}", to_move)
                     });
+                    self.artifact_metrics.observe_validation(
+                        artifact_type(&msg_id.hash),
+                        timestamp,
+                        time_source.get_relative_time(),
+                    );
                     unvalidated_ops.remove(msg_id);
                     validated_ops.insert(ValidatedConsensusArtifact {
                         msg: to_move,
@@ -461,8 +929,22 @@ impl MutableConsensusPool for ConsensusPoolImpl {
                 _ => None,
             })
             .collect();
+        let artifacts_for_log: Vec<_> = match self.artifact_log {
+            Some(_) => validated_ops
+                .ops
+                .iter()
+                .filter_map(|op| match op {
+                    PoolSectionOp::Insert(artifact) => Some(artifact.clone()),
+                    _ => None,
+                })
+                .collect(),
+            None => Vec::new(),
+        };
         self.apply_changes_unvalidated(unvalidated_ops);
         self.apply_changes_validated(validated_ops);
+        if let Some(artifact_log) = &self.artifact_log {
+            artifact_log.append(artifacts_for_log);
+        }
         if let Some(backup) = &self.backup {
             backup.store(time_source, artifacts_for_backup);
         }
@@ -670,6 +1152,7 @@ impl ConsensusGossipPool for ConsensusPoolImpl {}
 #[cfg(test)]
 mod tests {
     use super::*;
+    use futures::FutureExt;
     use ic_consensus_message::make_genesis;
     use ic_interfaces::artifact_pool::UnvalidatedArtifact;
     use ic_logger::replica_logger::no_op_logger;
@@ -749,6 +1232,367 @@ mod tests {
         })
     }
 
+    fn insert_random_beacon(pool: &mut ConsensusPoolImpl, height: u64) -> ConsensusMessageId {
+        let random_beacon = RandomBeacon::fake(RandomBeaconContent::new(
+            Height::from(height),
+            CryptoHashOf::from(CryptoHash(Vec::new())),
+        ));
+        let msg_id = random_beacon.get_id();
+        pool.insert(UnvalidatedArtifact {
+            message: random_beacon.into_message(),
+            peer_id: node_test_id(0),
+            timestamp: mock_time(),
+        });
+        msg_id
+    }
+
+    #[test]
+    fn test_unvalidated_limits() {
+        ic_test_utilities::artifact_pool_config::with_test_pool_config(|mut pool_config| {
+            pool_config.consensus_pool_unvalidated_limits = UnvalidatedSectionLimits {
+                max_count: 2,
+                max_size_bytes: usize::MAX,
+                eviction_policy:
+                    ic_config::artifact_pool::UnvalidatedEvictionPolicy::LowestHeightFirst,
+            };
+            let time_source = FastForwardTimeSource::new();
+            let mut pool = ConsensusPoolImpl::new_from_cup_without_bytes(
+                subnet_test_id(0),
+                make_genesis(ic_types::consensus::dkg::Summary::fake()),
+                pool_config,
+                ic_metrics::MetricsRegistry::new(),
+                no_op_logger(),
+            );
+            let msg_id_2 = insert_random_beacon(&mut pool, 2);
+            let msg_id_3 = insert_random_beacon(&mut pool, 3);
+            // The section is full, and the new artifact has the lowest height.
+            let msg_id_1 = insert_random_beacon(&mut pool, 1);
+            // The new artifact evicts the artifact with the lowest height.
+            let msg_id_4 = insert_random_beacon(&mut pool, 4);
+
+            assert!(!pool.unvalidated().contains(&msg_id_1));
+            assert!(!pool.unvalidated().contains(&msg_id_2));
+            assert!(pool.unvalidated().contains(&msg_id_3));
+            assert!(pool.unvalidated().contains(&msg_id_4));
+            assert_eq!(pool.unvalidated().size(), 2);
+            assert_eq!(pool.unvalidated_limit_metrics.rejected_inserts.get(), 1);
+            assert_eq!(pool.unvalidated_limit_metrics.evicted_artifacts.get(), 1);
+
+            // Removed artifacts free capacity.
+            pool.apply_changes(
+                time_source.as_ref(),
+                vec![ChangeAction::PurgeUnvalidatedBelow(Height::from(4))],
+            );
+            insert_random_beacon(&mut pool, 1);
+            assert_eq!(pool.unvalidated().size(), 2);
+            assert_eq!(pool.unvalidated_limit_metrics.evicted_artifacts.get(), 1);
+        })
+    }
+
+    #[test]
+    fn test_artifact_metrics() {
+        ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
+            let time_source = FastForwardTimeSource::new();
+            let mut pool = ConsensusPoolImpl::new_from_cup_without_bytes(
+                subnet_test_id(0),
+                make_genesis(ic_types::consensus::dkg::Summary::fake()),
+                pool_config,
+                ic_metrics::MetricsRegistry::new(),
+                no_op_logger(),
+            );
+            // The genesis random beacon and catch-up package are counted when
+            // the pool is created.
+            assert_eq!(
+                pool.artifact_metrics
+                    .artifacts(POOL_TYPE_VALIDATED, "catch_up_package"),
+                1
+            );
+
+            let msg_id_1 = insert_random_beacon(&mut pool, 1);
+            // Inserting a duplicate has no effect.
+            insert_random_beacon(&mut pool, 1);
+            insert_random_beacon(&mut pool, 2);
+            assert_eq!(
+                pool.artifact_metrics
+                    .artifacts(POOL_TYPE_UNVALIDATED, "random_beacon"),
+                2
+            );
+
+            let msg = pool.unvalidated().get(&msg_id_1).unwrap();
+            pool.apply_changes(
+                time_source.as_ref(),
+                vec![
+                    ChangeAction::MoveToValidated(msg),
+                    ChangeAction::PurgeUnvalidatedBelow(Height::from(3)),
+                ],
+            );
+            assert_eq!(
+                pool.artifact_metrics
+                    .artifacts(POOL_TYPE_UNVALIDATED, "random_beacon"),
+                0
+            );
+            assert_eq!(
+                pool.artifact_metrics
+                    .artifacts(POOL_TYPE_VALIDATED, "random_beacon"),
+                2
+            );
+        })
+    }
+
+    #[test]
+    fn test_section_artifacts() {
+        let msg_id = |height: u64| {
+            RandomBeacon::fake(RandomBeaconContent::new(
+                Height::from(height),
+                CryptoHashOf::from(CryptoHash(Vec::new())),
+            ))
+            .get_id()
+        };
+        let mut artifacts = SectionArtifacts::default();
+        assert!(artifacts.insert(&msg_id(1), mock_time()));
+        assert!(!artifacts.insert(&msg_id(1), mock_time()));
+        assert!(artifacts.insert(&msg_id(2), mock_time() + Duration::from_secs(60)));
+        assert!(artifacts.insert(&msg_id(3), mock_time()));
+
+        let mut ttls = BTreeMap::new();
+        ttls.insert("random_beacon".to_string(), Duration::from_secs(60));
+        let ttls = ArtifactTtls::new(ttls);
+        let now = mock_time() + Duration::from_secs(60);
+        assert_eq!(
+            artifacts.expired(&ttls, None, now),
+            vec![msg_id(1), msg_id(3)]
+        );
+        assert_eq!(
+            artifacts.expired(&ttls, Some(Height::from(3)), now),
+            vec![msg_id(1)]
+        );
+
+        assert!(artifacts.remove(&msg_id(3)));
+        assert!(!artifacts.remove(&msg_id(3)));
+        let purged = artifacts.purge_below(Height::from(2));
+        assert_eq!(purged, vec![(msg_id(1).hash, mock_time())]);
+        assert_eq!(
+            artifacts.by_height.keys().collect::<Vec<_>>(),
+            vec![&Height::from(2)]
+        );
+    }
+
+    #[test]
+    fn test_purge_expired_artifacts() {
+        ic_test_utilities::artifact_pool_config::with_test_pool_config(|mut pool_config| {
+            pool_config
+                .artifact_ttls
+                .insert("random_beacon".to_string(), Duration::from_secs(60));
+            let time_source = FastForwardTimeSource::new();
+            let mut pool = ConsensusPoolImpl::new_from_cup_without_bytes(
+                subnet_test_id(0),
+                make_genesis(ic_types::consensus::dkg::Summary::fake()),
+                pool_config,
+                ic_metrics::MetricsRegistry::new(),
+                no_op_logger(),
+            );
+            let msg_id_1 = insert_random_beacon(&mut pool, 1);
+            let msg_id_2 = insert_random_beacon(&mut pool, 2);
+            let random_beacon_1 = pool.unvalidated().get(&msg_id_1).unwrap();
+            pool.apply_changes(
+                time_source.as_ref(),
+                vec![ChangeAction::MoveToValidated(random_beacon_1)],
+            );
+
+            time_source
+                .set_time(mock_time() + Duration::from_secs(61))
+                .unwrap();
+            pool.apply_changes(time_source.as_ref(), Vec::new());
+            // The unvalidated beacon expired, while the validated beacons are
+            // kept, as they are not below the height of the catch-up package.
+            assert!(!pool.unvalidated().contains(&msg_id_2));
+            assert!(pool.validated().contains(&msg_id_1));
+            assert_eq!(pool.validated().random_beacon().get_all().count(), 2);
+        })
+    }
+
+    #[test]
+    fn test_snapshot() {
+        ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
+            let time_source = FastForwardTimeSource::new();
+            let mut pool = ConsensusPoolImpl::new_from_cup_without_bytes(
+                subnet_test_id(0),
+                make_genesis(ic_types::consensus::dkg::Summary::fake()),
+                pool_config,
+                ic_metrics::MetricsRegistry::new(),
+                no_op_logger(),
+            );
+            let msg_id_1 = insert_random_beacon(&mut pool, 1);
+            let random_beacon_1 = pool.unvalidated().get(&msg_id_1).unwrap();
+            pool.apply_changes(
+                time_source.as_ref(),
+                vec![ChangeAction::MoveToValidated(random_beacon_1)],
+            );
+            let msg_id_2 = insert_random_beacon(&mut pool, 2);
+            let snapshot = pool.snapshot();
+
+            let random_beacon_2 = pool.unvalidated().get(&msg_id_2).unwrap();
+            pool.apply_changes(
+                time_source.as_ref(),
+                vec![
+                    ChangeAction::MoveToValidated(random_beacon_2),
+                    ChangeAction::PurgeValidatedBelow(Height::from(2)),
+                ],
+            );
+            assert!(!pool.validated().contains(&msg_id_1));
+            assert!(pool.validated().contains(&msg_id_2));
+
+            // The snapshot holds the artifacts at the time it was taken,
+            // including the genesis catch-up package.
+            assert!(snapshot.validated().contains(&msg_id_1));
+            assert!(!snapshot.validated().contains(&msg_id_2));
+            assert!(snapshot.unvalidated().contains(&msg_id_2));
+            assert_eq!(
+                snapshot.validated().catch_up_package().max_height(),
+                Some(Height::from(0))
+            );
+            assert_eq!(
+                snapshot.as_cache().finalized_block().height(),
+                Height::from(0)
+            );
+
+            // Later snapshots see the changes.
+            let snapshot = pool.snapshot();
+            assert!(!snapshot.validated().contains(&msg_id_1));
+            assert!(snapshot.validated().contains(&msg_id_2));
+            assert!(!snapshot.unvalidated().contains(&msg_id_2));
+        })
+    }
+
+    #[test]
+    fn test_uncached_pool_height_subscriptions() {
+        ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
+            let mut pool =
+                UncachedConsensusPoolImpl::new(pool_config, no_op_logger(), MetricsRegistry::new());
+            let cup = make_genesis(ic_types::consensus::dkg::Summary::fake());
+            ConsensusPoolImpl::init_genesis(
+                CUPWithOriginalProtobuf::from_cup(cup),
+                pool.validated.as_mut(),
+            );
+            let mut finalized_height = pool.subscribe_finalized_height();
+            let mut certified_height = pool.subscribe_certified_height();
+            assert_eq!(*finalized_height.borrow(), Height::from(0));
+            assert_eq!(*certified_height.borrow(), Height::from(0));
+
+            // The senders are kept by the pool, so the receivers wait for the
+            // next height instead of failing.
+            assert!(finalized_height.changed().now_or_never().is_none());
+            assert!(certified_height.changed().now_or_never().is_none());
+            pool.finalized_block();
+            assert!(finalized_height.changed().now_or_never().is_none());
+        })
+    }
+
+    #[test]
+    fn test_dump() {
+        ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
+            let cup = make_genesis(ic_types::consensus::dkg::Summary::fake());
+            let pool = ConsensusPoolImpl::new_from_cup_without_bytes(
+                subnet_test_id(0),
+                cup.clone(),
+                pool_config,
+                ic_metrics::MetricsRegistry::new(),
+                no_op_logger(),
+            );
+            let dir = tempfile::Builder::new().tempdir().unwrap();
+            let path = dir.path().join("consensus.cbor");
+
+            assert_eq!(pool.dump(&path, &DumpOptions::default()).unwrap(), 2);
+            let dump = PoolDump::read_from(&path).unwrap();
+            assert_eq!(dump.pool, POOL_CONSENSUS);
+            let types: Vec<_> = dump
+                .artifacts
+                .iter()
+                .map(|artifact| artifact.artifact_type.as_str())
+                .collect();
+            assert_eq!(types, vec!["catch_up_package", "random_beacon"]);
+            let cup_id = cup.get_id();
+            assert_eq!(dump.artifacts[0].height, Some(cup_id.height));
+            assert_eq!(dump.artifacts[0].hash, cup_id.hash.digest().0);
+            assert!(dump
+                .artifacts
+                .iter()
+                .all(|artifact| artifact.signature.is_some() && artifact.artifact.is_none()));
+
+            let options = DumpOptions {
+                include_artifacts: true,
+            };
+            assert_eq!(pool.dump(&path, &options).unwrap(), 2);
+            let dump = PoolDump::read_from(&path).unwrap();
+            assert!(dump
+                .artifacts
+                .iter()
+                .all(|artifact| artifact.artifact.is_some()));
+        })
+    }
+
+    #[test]
+    // The artifact log is written by its own thread, so applying changes does
+    // not wait for the log to be written to the disk.
+    fn test_apply_changes_does_not_block_on_artifact_log() {
+        use crate::consensus_pool::backup::ArtifactLogReader;
+        use ic_config::artifact_pool::ArtifactLogConfig;
+
+        ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
+            let time_source = FastForwardTimeSource::new();
+            let log_dir = tempfile::Builder::new().tempdir().unwrap();
+            let mut pool = ConsensusPoolImpl::new_from_cup_without_bytes(
+                subnet_test_id(0),
+                make_genesis(ic_types::consensus::dkg::Summary::fake()),
+                pool_config,
+                ic_metrics::MetricsRegistry::new(),
+                no_op_logger(),
+            );
+            pool.artifact_log = Some(ArtifactLog::new(
+                log_dir.path().to_path_buf(),
+                ArtifactLogConfig {
+                    log_path: log_dir.path().to_path_buf(),
+                    max_segment_size_bytes: 1024 * 1024,
+                    retained_segments: 10,
+                },
+                &MetricsRegistry::new(),
+                no_op_logger(),
+            ));
+            let random_beacons: Vec<_> = (1..4)
+                .map(|height| {
+                    RandomBeacon::fake(RandomBeaconContent::new(
+                        Height::from(height),
+                        CryptoHashOf::from(CryptoHash(Vec::new())),
+                    ))
+                    .into_message()
+                })
+                .collect();
+
+            // The changes are applied while the log thread is stuck.
+            let paused = pool.artifact_log.as_ref().unwrap().pause();
+            for random_beacon in &random_beacons {
+                pool.apply_changes(
+                    time_source.as_ref(),
+                    vec![ChangeAction::AddToValidated(random_beacon.clone())],
+                );
+            }
+            assert_eq!(
+                pool.validated().random_beacon().max_height(),
+                Some(Height::from(3))
+            );
+            assert_eq!(ArtifactLogReader::open(log_dir.path()).unwrap().count(), 0);
+
+            // Once the log thread resumes, it appends the queued artifacts.
+            paused.recv().unwrap();
+            pool.artifact_log.as_ref().unwrap().sync();
+            let logged: Vec<_> = ArtifactLogReader::open(log_dir.path())
+                .unwrap()
+                .map(|artifact| artifact.unwrap().msg)
+                .collect();
+            assert_eq!(logged, random_beacons);
+        })
+    }
+
     #[test]
     // We create multiple artifacts for multiple heights, check that all of them are
     // written to the disk and can be restored.
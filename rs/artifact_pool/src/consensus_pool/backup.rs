@@ -0,0 +1,493 @@
+//! This module implements an append-only log of all validated consensus
+//! artifacts. In contrast to the essential artifacts backup, which stores one
+//! file per artifact, the log contains every artifact added to the validated
+//! section of the consensus pool, in the order of insertion, so that the
+//! validated pool of a subnet can be replayed from the log alone.
+//!
+//! The log is a sequence of segment files named `<sequence_number>.log`,
+//! stored in `<log_path>/<subnet_id>`. Each record of a segment consists of
+//! the length of the record as a 4-byte little-endian integer, followed by
+//! the bincode encoding of the `ValidatedConsensusArtifact`. A segment is
+//! rotated once it exceeds the configured size, and every replica start
+//! begins a new segment, so that a segment torn by a crash is never appended
+//! to. Only the configured number of most recent segments is retained.
+//!
+//! Like the essential artifacts backup, the log is written by a background
+//! thread, so that the consensus pool does not wait for the disk when
+//! applying changes.
+
+use ic_config::artifact_pool::ArtifactLogConfig;
+use ic_interfaces::consensus_pool::ValidatedConsensusArtifact;
+use ic_logger::{error, info, warn, ReplicaLogger};
+use ic_metrics::MetricsRegistry;
+use prometheus::IntCounter;
+use std::{
+    convert::TryInto,
+    fs,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::{sync_channel, Receiver, SyncSender},
+    thread::{self, JoinHandle},
+};
+
+/// The size of the length prefix of a record.
+const RECORD_LENGTH_PREFIX_SIZE: usize = 4;
+
+/// The file extension of log segments.
+const SEGMENT_EXTENSION: &str = "log";
+
+// The number of appends that can queue up before appending blocks consensus.
+// Consensus only waits for the log if the disk falls this far behind.
+const QUEUE_LENGTH: usize = 100;
+
+#[derive(Clone, Debug)]
+struct Metrics {
+    // Amount of I/O errors. Any number above 0 is critical.
+    io_errors: IntCounter,
+    // Amount of artifacts appended to the log.
+    appended_artifacts: IntCounter,
+}
+
+impl Metrics {
+    fn new(registry: &MetricsRegistry) -> Self {
+        Self {
+            io_errors: registry.int_counter(
+                "consensus_artifact_log_io_errors",
+                "The number of I/O errors happened during appending to or rotating the consensus artifact log.",
+            ),
+            appended_artifacts: registry.int_counter(
+                "consensus_artifact_log_appended_artifacts",
+                "The number of validated consensus artifacts appended to the consensus artifact log.",
+            ),
+        }
+    }
+}
+
+/// The segment currently appended to.
+struct Segment {
+    sequence_number: u64,
+    writer: BufWriter<fs::File>,
+    size_bytes: u64,
+}
+
+enum ArtifactLogRequest {
+    Append(Vec<ValidatedConsensusArtifact>),
+    Await(SyncSender<()>),
+    Shutdown,
+}
+
+/// The append-only log of validated consensus artifacts. Appends are queued
+/// and written by the log thread.
+pub(crate) struct ArtifactLog {
+    // The queue of the log thread
+    queue: SyncSender<ArtifactLogRequest>,
+    // Thread handle of the thread writing the log.
+    thread: Option<JoinHandle<()>>,
+    metrics: Metrics,
+    log: ReplicaLogger,
+}
+
+impl ArtifactLog {
+    /// Opens the log in the given directory. The first append starts a new
+    /// segment after the existing ones.
+    pub(crate) fn new(
+        path: PathBuf,
+        config: ArtifactLogConfig,
+        metrics_registry: &MetricsRegistry,
+        log: ReplicaLogger,
+    ) -> Self {
+        let metrics = Metrics::new(metrics_registry);
+        let (queue, thread) =
+            ArtifactLogThread::new(path, config, metrics.clone(), log.clone()).start();
+        Self {
+            queue,
+            thread: Some(thread),
+            metrics,
+            log,
+        }
+    }
+
+    /// Queues the given artifacts to be appended to the log.
+    pub(crate) fn append(&self, artifacts: Vec<ValidatedConsensusArtifact>) {
+        if artifacts.is_empty() {
+            return;
+        }
+        // If the queue is full, we will block here.
+        if self
+            .queue
+            .send(ArtifactLogRequest::Append(artifacts))
+            .is_err()
+        {
+            error!(
+                self.log,
+                "Artifact log thread exited unexpectedly. This is a bug."
+            );
+            self.metrics.io_errors.inc();
+        }
+    }
+
+    /// Blocks the current thread until all queued artifacts have been
+    /// appended to the log and flushed to the disk.
+    ///
+    /// Mainly useful for testing.
+    #[allow(dead_code)]
+    pub(crate) fn sync(&self) {
+        let (tx, rx) = sync_channel(0);
+        // NOTE: If we have an error here we will also have one in the next line
+        let _ = self.queue.send(ArtifactLogRequest::Await(tx));
+        if rx.recv().is_err() {
+            error!(self.log, "Error while syncing the artifact log thread");
+            self.metrics.io_errors.inc();
+        }
+    }
+
+    /// Blocks the log thread until the returned receiver is read from or
+    /// dropped.
+    #[cfg(test)]
+    pub(crate) fn pause(&self) -> Receiver<()> {
+        let (tx, rx) = sync_channel(0);
+        self.queue.send(ArtifactLogRequest::Await(tx)).unwrap();
+        rx
+    }
+}
+
+impl Drop for ArtifactLog {
+    fn drop(&mut self) {
+        // The log thread appends all queued artifacts before shutting down.
+        let _ = self.queue.send(ArtifactLogRequest::Shutdown);
+        if self.thread.take().unwrap().join().is_err() {
+            error!(
+                self.log,
+                "Artifact log thread exited prematurely during shutdown"
+            );
+        }
+    }
+}
+
+struct ArtifactLogThread {
+    // Path pointing to <log_path>/<subnet_id>, containing all segments.
+    path: PathBuf,
+    config: ArtifactLogConfig,
+    segment: Option<Segment>,
+    next_sequence_number: u64,
+    metrics: Metrics,
+    log: ReplicaLogger,
+}
+
+impl ArtifactLogThread {
+    fn new(path: PathBuf, config: ArtifactLogConfig, metrics: Metrics, log: ReplicaLogger) -> Self {
+        let next_sequence_number = match list_segments(&path) {
+            Ok(segments) => segments.last().map_or(0, |(number, _)| number + 1),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => 0,
+            Err(err) => {
+                error!(log, "Listing the artifact log segments failed: {:?}", err);
+                metrics.io_errors.inc();
+                0
+            }
+        };
+        Self {
+            path,
+            config,
+            segment: None,
+            next_sequence_number,
+            metrics,
+            log,
+        }
+    }
+
+    fn start(mut self) -> (SyncSender<ArtifactLogRequest>, JoinHandle<()>) {
+        let (tx, rx) = sync_channel(QUEUE_LENGTH);
+        let handle = thread::Builder::new()
+            .name("ArtifactLogThread".to_string())
+            .spawn(move || self.run(rx))
+            .expect("Failed to spawn ArtifactLogThread");
+        (tx, handle)
+    }
+
+    fn run(&mut self, rx: Receiver<ArtifactLogRequest>) {
+        loop {
+            match rx.recv() {
+                Ok(ArtifactLogRequest::Append(artifacts)) => self.append(&artifacts),
+                // The requester may have stopped waiting.
+                Ok(ArtifactLogRequest::Await(tx)) => {
+                    let _ = tx.send(());
+                }
+                Ok(ArtifactLogRequest::Shutdown) => {
+                    info!(self.log, "Shutting down the artifact log thread.");
+                    break;
+                }
+                Err(_) => {
+                    error!(self.log, "Orphaned artifact log thread. This is a bug");
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Appends the given artifacts to the log and flushes them to the disk.
+    fn append(&mut self, artifacts: &[ValidatedConsensusArtifact]) {
+        if let Err(err) = self.try_append(artifacts) {
+            error!(self.log, "Appending to the artifact log failed: {:?}", err);
+            self.metrics.io_errors.inc();
+            // Continue in a fresh segment, as the current one might contain a
+            // partially written record.
+            self.segment = None;
+        }
+    }
+
+    fn try_append(&mut self, artifacts: &[ValidatedConsensusArtifact]) -> io::Result<()> {
+        for artifact in artifacts {
+            let record = bincode::serialize(artifact)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            let length: u32 = record.len().try_into().map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidData, "Artifact too large for the log")
+            })?;
+            let segment = self.current_segment()?;
+            segment.writer.write_all(&length.to_le_bytes())?;
+            segment.writer.write_all(&record)?;
+            segment.size_bytes += (RECORD_LENGTH_PREFIX_SIZE + record.len()) as u64;
+            self.metrics.appended_artifacts.inc();
+        }
+        if let Some(segment) = self.segment.as_mut() {
+            segment.writer.flush()?;
+            segment.writer.get_ref().sync_data()?;
+        }
+        Ok(())
+    }
+
+    /// Returns the segment to append to, rotating the current segment if it
+    /// exceeds the maximal segment size.
+    fn current_segment(&mut self) -> io::Result<&mut Segment> {
+        let rotate = self.segment.as_ref().map_or(true, |segment| {
+            segment.size_bytes >= self.config.max_segment_size_bytes
+        });
+        if rotate {
+            if let Some(mut segment) = self.segment.take() {
+                segment.writer.flush()?;
+            }
+            fs::create_dir_all(&self.path)?;
+            let sequence_number = self.next_sequence_number;
+            let file = fs::OpenOptions::new()
+                .create_new(true)
+                .append(true)
+                .open(segment_path(&self.path, sequence_number))?;
+            self.next_sequence_number += 1;
+            self.segment = Some(Segment {
+                sequence_number,
+                writer: BufWriter::new(file),
+                size_bytes: 0,
+            });
+            self.purge_segments();
+        }
+        Ok(self.segment.as_mut().unwrap())
+    }
+
+    /// Deletes the oldest segments exceeding the number of retained segments.
+    fn purge_segments(&self) {
+        let segments = match list_segments(&self.path) {
+            Ok(segments) => segments,
+            Err(err) => {
+                error!(
+                    self.log,
+                    "Listing the artifact log segments failed: {:?}", err
+                );
+                self.metrics.io_errors.inc();
+                return;
+            }
+        };
+        let retained = self.config.retained_segments.max(1);
+        let current = self.segment.as_ref().map(|segment| segment.sequence_number);
+        let excess = segments.len().saturating_sub(retained);
+        for (sequence_number, path) in segments.into_iter().take(excess) {
+            if Some(sequence_number) == current {
+                continue;
+            }
+            if let Err(err) = fs::remove_file(&path) {
+                warn!(
+                    self.log,
+                    "Removing the artifact log segment {:?} failed: {:?}", path, err
+                );
+                self.metrics.io_errors.inc();
+            }
+        }
+    }
+}
+
+/// Reads the artifacts of a log in the order in which they were appended.
+///
+/// A truncated record at the end of a segment, which is left by a crash
+/// during an append, ends the segment, and reading continues with the next
+/// one.
+pub struct ArtifactLogReader {
+    segments: std::vec::IntoIter<(u64, PathBuf)>,
+    reader: Option<BufReader<fs::File>>,
+}
+
+impl ArtifactLogReader {
+    /// Opens the log stored in the given directory.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            segments: list_segments(path)?.into_iter(),
+            reader: None,
+        })
+    }
+
+    /// Reads the next record of the current segment. Returns None at the end
+    /// of the segment.
+    fn read_record(reader: &mut BufReader<fs::File>) -> io::Result<Option<Vec<u8>>> {
+        let mut prefix = [0; RECORD_LENGTH_PREFIX_SIZE];
+        if !read_exact_or_eof(reader, &mut prefix)? {
+            return Ok(None);
+        }
+        let mut record = vec![0; u32::from_le_bytes(prefix) as usize];
+        if !read_exact_or_eof(reader, &mut record)? {
+            return Ok(None);
+        }
+        Ok(Some(record))
+    }
+}
+
+impl Iterator for ArtifactLogReader {
+    type Item = io::Result<ValidatedConsensusArtifact>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.reader.is_none() {
+                let (_, path) = self.segments.next()?;
+                match fs::File::open(&path) {
+                    Ok(file) => self.reader = Some(BufReader::new(file)),
+                    Err(err) => return Some(Err(err)),
+                }
+            }
+            match Self::read_record(self.reader.as_mut().unwrap()) {
+                Ok(Some(record)) => {
+                    return Some(
+                        bincode::deserialize(&record)
+                            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+                    )
+                }
+                Ok(None) => self.reader = None,
+                Err(err) => {
+                    self.reader = None;
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+}
+
+/// Fills the given buffer. Returns false if the reader ends before the buffer
+/// is filled.
+fn read_exact_or_eof(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<bool> {
+    match reader.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+fn segment_path(path: &Path, sequence_number: u64) -> PathBuf {
+    path.join(format!("{:020}.{}", sequence_number, SEGMENT_EXTENSION))
+}
+
+/// Returns the segments in the given directory, ordered by sequence number.
+fn list_segments(path: &Path) -> io::Result<Vec<(u64, PathBuf)>> {
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some(SEGMENT_EXTENSION) {
+            continue;
+        }
+        if let Some(sequence_number) = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse::<u64>().ok())
+        {
+            segments.push((sequence_number, path));
+        }
+    }
+    segments.sort();
+    Ok(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_consensus_message::ConsensusMessageHashable;
+    use ic_logger::replica_logger::no_op_logger;
+    use ic_test_utilities::{consensus::fake::*, mock_time};
+    use ic_types::{
+        consensus::{RandomBeacon, RandomBeaconContent},
+        crypto::{CryptoHash, CryptoHashOf},
+        Height,
+    };
+
+    fn make_artifacts(heights: std::ops::Range<u64>) -> Vec<ValidatedConsensusArtifact> {
+        heights
+            .map(|height| ValidatedConsensusArtifact {
+                msg: RandomBeacon::fake(RandomBeaconContent::new(
+                    Height::from(height),
+                    CryptoHashOf::from(CryptoHash(Vec::new())),
+                ))
+                .into_message(),
+                timestamp: mock_time(),
+            })
+            .collect()
+    }
+
+    fn make_log(path: &Path, retained_segments: usize) -> ArtifactLog {
+        ArtifactLog::new(
+            path.to_path_buf(),
+            ArtifactLogConfig {
+                log_path: path.to_path_buf(),
+                max_segment_size_bytes: 1,
+                retained_segments,
+            },
+            &MetricsRegistry::new(),
+            no_op_logger(),
+        )
+    }
+
+    fn read_all(path: &Path) -> Vec<ValidatedConsensusArtifact> {
+        ArtifactLogReader::open(path)
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_artifact_log_replay() {
+        let dir = tempfile::Builder::new().tempdir().unwrap();
+        make_log(dir.path(), 10).append(make_artifacts(1..4));
+        // A restarted replica continues in a new segment.
+        make_log(dir.path(), 10).append(make_artifacts(4..5));
+
+        assert_eq!(list_segments(dir.path()).unwrap().len(), 4);
+        assert_eq!(read_all(dir.path()), make_artifacts(1..5));
+    }
+
+    #[test]
+    fn test_artifact_log_retention() {
+        let dir = tempfile::Builder::new().tempdir().unwrap();
+        make_log(dir.path(), 2).append(make_artifacts(1..6));
+
+        let segments: Vec<_> = list_segments(dir.path())
+            .unwrap()
+            .into_iter()
+            .map(|(sequence_number, _)| sequence_number)
+            .collect();
+        assert_eq!(segments, vec![3, 4]);
+        assert_eq!(read_all(dir.path()), make_artifacts(4..6));
+    }
+
+    #[test]
+    fn test_artifact_log_ignores_torn_record() {
+        let dir = tempfile::Builder::new().tempdir().unwrap();
+        make_log(dir.path(), 10).append(make_artifacts(1..3));
+        let (_, last_segment) = list_segments(dir.path()).unwrap().pop().unwrap();
+        let bytes = fs::read(&last_segment).unwrap();
+        fs::write(&last_segment, &bytes[..bytes.len() - 1]).unwrap();
+
+        assert_eq!(read_all(dir.path()), make_artifacts(1..2));
+    }
+}
@@ -0,0 +1,381 @@
+//! This module implements the replay of a subnet from an artifact log written
+//! by the consensus pool `backup` module.
+//!
+//! Starting from a catch-up package and the state at the height Message
+//! Routing expects the next batch for, the replay follows the finalized chain
+//! recorded in the log and re-delivers the batch of every finalized block to
+//! Message Routing, in the same way the finalizer does, until the target
+//! height is reached. Since the execution of batches is deterministic, this
+//! reconstructs the state at the target height, and the replay returns once
+//! the state at the target height is committed.
+//!
+//! The responses of consensus to subnet calls, which are computed by
+//! consensus for summary blocks, are provided by the caller, so that the
+//! replay does not depend on the consensus implementation.
+
+use crate::consensus_pool::backup::ArtifactLogReader;
+use ic_consensus_message::crypto_hashable_to_seed;
+use ic_interfaces::messaging::{MessageRouting, MessageRoutingError};
+use ic_interfaces::state_manager::StateReader;
+use ic_logger::{info, ReplicaLogger};
+use ic_types::{
+    batch::{Batch, BatchPayload},
+    consensus::{Block, BlockPayload, CatchUpPackage, ConsensusMessage, HasHeight, RandomTape},
+    crypto::CryptoHashOf,
+    messages::Response,
+    Height, Randomness,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, io,
+    path::Path,
+    time::Duration,
+};
+
+/// The time to wait before re-delivering a batch that Message Routing could
+/// not accept because its queue was full.
+const QUEUE_FULL_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The interval at which the height of the latest state is polled while
+/// waiting for the delivered batches to be executed.
+const STATE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Errors that can occur during a replay.
+#[derive(Debug)]
+pub enum ReplayError {
+    /// The artifact log could not be read.
+    Io(io::Error),
+    /// Message Routing expects a batch at or below the height of the
+    /// catch-up package, or above the target height.
+    InvalidStartHeight {
+        expected_batch_height: Height,
+        cup_height: Height,
+        target_height: Height,
+    },
+    /// The log contains no finalized block at the given height.
+    MissingFinalizedBlock(Height),
+    /// The log contains no random tape at the given height.
+    MissingRandomTape(Height),
+    /// The finalized block at the given height does not extend the finalized
+    /// block below it.
+    BrokenChain(Height),
+    /// Message Routing rejected the batch at the given height.
+    DeliveryFailed(Height, MessageRoutingError),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Io(err) => write!(f, "Failed to read the artifact log: {}", err),
+            ReplayError::InvalidStartHeight {
+                expected_batch_height,
+                cup_height,
+                target_height,
+            } => write!(
+                f,
+                "Cannot replay from expected batch height {} with CUP height {} to target height {}",
+                expected_batch_height, cup_height, target_height
+            ),
+            ReplayError::MissingFinalizedBlock(height) => {
+                write!(f, "No finalized block found at height {}", height)
+            }
+            ReplayError::MissingRandomTape(height) => {
+                write!(f, "No random tape found at height {}", height)
+            }
+            ReplayError::BrokenChain(height) => write!(
+                f,
+                "The finalized block at height {} does not extend its predecessor",
+                height
+            ),
+            ReplayError::DeliveryFailed(height, err) => {
+                write!(f, "Failed to deliver the batch at height {}: {:?}", height, err)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<io::Error> for ReplayError {
+    fn from(err: io::Error) -> Self {
+        ReplayError::Io(err)
+    }
+}
+
+/// The artifacts of the finalized chain between two heights.
+#[derive(Default)]
+struct FinalizedChain {
+    finalized_hashes: BTreeMap<Height, CryptoHashOf<Block>>,
+    blocks: HashMap<CryptoHashOf<Block>, Block>,
+    random_tapes: BTreeMap<Height, RandomTape>,
+}
+
+impl FinalizedChain {
+    /// Collects the artifacts between the given heights, inclusively, from
+    /// the log. Every catch-up package finalizes its block.
+    fn from_log(
+        log_path: &Path,
+        cup: &CatchUpPackage,
+        min: Height,
+        max: Height,
+    ) -> Result<Self, ReplayError> {
+        let mut chain = FinalizedChain::default();
+        chain.add_cup(cup);
+        for artifact in ArtifactLogReader::open(log_path)? {
+            let msg = artifact?.msg;
+            match &msg {
+                ConsensusMessage::Finalization(finalization) => {
+                    if (min..=max).contains(&finalization.height()) {
+                        chain
+                            .finalized_hashes
+                            .insert(finalization.height(), finalization.content.block.clone());
+                    }
+                }
+                ConsensusMessage::BlockProposal(proposal) => {
+                    if (min..=max).contains(&proposal.height()) {
+                        chain.blocks.insert(
+                            proposal.content.get_hash().clone(),
+                            proposal.as_ref().clone(),
+                        );
+                    }
+                }
+                ConsensusMessage::RandomTape(tape) => {
+                    if (min..=max).contains(&tape.height()) {
+                        chain.random_tapes.insert(tape.height(), tape.clone());
+                    }
+                }
+                ConsensusMessage::CatchUpPackage(cup) => {
+                    if (min..=max).contains(&cup.height()) {
+                        chain.add_cup(cup);
+                    }
+                }
+                _ => (),
+            }
+        }
+        Ok(chain)
+    }
+
+    fn add_cup(&mut self, cup: &CatchUpPackage) {
+        let hash = cup.content.block.get_hash().clone();
+        self.finalized_hashes.insert(cup.height(), hash.clone());
+        self.blocks.insert(hash, cup.content.block.as_ref().clone());
+    }
+
+    fn finalized_block(&self, height: Height) -> Option<(&CryptoHashOf<Block>, &Block)> {
+        let hash = self.finalized_hashes.get(&height)?;
+        self.blocks.get(hash).map(|block| (hash, block))
+    }
+}
+
+/// Replays the finalized chain recorded in the artifact log at the given path,
+/// starting at the height Message Routing expects the next batch for, until
+/// the batch at the target height was delivered. The given function computes
+/// the consensus responses to subnet calls for summary blocks.
+///
+/// Returns the height of the last delivered batch, once the given state reader
+/// holds the state at that height.
+pub fn replay<State>(
+    log_path: &Path,
+    cup: &CatchUpPackage,
+    target_height: Height,
+    message_routing: &dyn MessageRouting,
+    state_reader: &dyn StateReader<State = State>,
+    consensus_responses: &dyn Fn(&Block) -> Vec<Response>,
+    log: &ReplicaLogger,
+) -> Result<Height, ReplayError> {
+    let start_height = message_routing.expected_batch_height();
+    if start_height <= cup.height() || start_height > target_height {
+        return Err(ReplayError::InvalidStartHeight {
+            expected_batch_height: start_height,
+            cup_height: cup.height(),
+            target_height,
+        });
+    }
+    info!(
+        log,
+        "Replaying the artifact log from height {} to height {}", start_height, target_height
+    );
+    let chain = FinalizedChain::from_log(log_path, cup, start_height.decrement(), target_height)?;
+
+    let mut height = start_height;
+    while height <= target_height {
+        let (hash, block) = chain
+            .finalized_block(height)
+            .ok_or(ReplayError::MissingFinalizedBlock(height))?;
+        if let Some((parent_hash, _)) = chain.finalized_block(height.decrement()) {
+            if &block.parent != parent_hash {
+                return Err(ReplayError::BrokenChain(height));
+            }
+        }
+        let tape = chain
+            .random_tapes
+            .get(&height)
+            .ok_or(ReplayError::MissingRandomTape(height))?;
+        deliver_batch(
+            message_routing,
+            make_batch(block, tape, consensus_responses),
+        )?;
+        info!(log, "Replayed block {:?} at height {}", hash, height);
+        height = height.increment();
+    }
+    while state_reader.latest_state_height() < target_height {
+        std::thread::sleep(STATE_POLL_INTERVAL);
+    }
+    info!(log, "Reached the state at height {}", target_height);
+    Ok(target_height)
+}
+
+/// Builds the batch of the given finalized block, as the finalizer does.
+fn make_batch(
+    block: &Block,
+    tape: &RandomTape,
+    consensus_responses: &dyn Fn(&Block) -> Vec<Response>,
+) -> Batch {
+    let is_summary = block.payload.is_summary();
+    Batch {
+        batch_number: block.height(),
+        requires_full_state_hash: is_summary,
+        payload: if is_summary {
+            BatchPayload::default()
+        } else {
+            BlockPayload::from(block.payload.clone()).into_batch_payload()
+        },
+        randomness: Randomness::from(crypto_hashable_to_seed(tape)),
+        registry_version: block.context.registry_version,
+        time: block.context.time,
+        consensus_responses: if is_summary {
+            consensus_responses(block)
+        } else {
+            Vec::new()
+        },
+    }
+}
+
+/// Delivers the given batch, retrying while the Message Routing queue is full.
+fn deliver_batch(message_routing: &dyn MessageRouting, batch: Batch) -> Result<(), ReplayError> {
+    let height = batch.batch_number;
+    loop {
+        match message_routing.deliver_batch(batch.clone()) {
+            Ok(_) => return Ok(()),
+            Err(MessageRoutingError::QueueIsFull) => std::thread::sleep(QUEUE_FULL_RETRY_INTERVAL),
+            Err(err) => return Err(ReplayError::DeliveryFailed(height, err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus_pool::backup::ArtifactLog;
+    use ic_config::artifact_pool::ArtifactLogConfig;
+    use ic_consensus_message::{make_genesis, ConsensusMessageHashable};
+    use ic_interfaces::consensus_pool::ValidatedConsensusArtifact;
+    use ic_logger::replica_logger::no_op_logger;
+    use ic_metrics::MetricsRegistry;
+    use ic_test_utilities::{
+        consensus::fake::*, message_routing::FakeMessageRouting, mock_time,
+        state_manager::FakeStateManager, types::ids::node_test_id,
+    };
+    use ic_types::consensus::{
+        BlockProposal, Finalization, FinalizationContent, RandomTapeContent,
+    };
+    use std::sync::Arc;
+
+    /// Returns the artifacts finalizing a chain of the given length on top of
+    /// the given block.
+    fn make_chain(parent: &Block, length: u64) -> Vec<ConsensusMessage> {
+        let mut parent = parent.clone();
+        let mut artifacts = Vec::new();
+        for _ in 0..length {
+            let block = Block::from_parent(&parent);
+            let height = block.height();
+            artifacts.push(BlockProposal::fake(block.clone(), node_test_id(0)).into_message());
+            artifacts.push(
+                Finalization::fake(FinalizationContent::new(
+                    height,
+                    ic_crypto::crypto_hash(&block),
+                ))
+                .into_message(),
+            );
+            artifacts.push(RandomTape::fake(RandomTapeContent::new(height)).into_message());
+            parent = block;
+        }
+        artifacts
+    }
+
+    fn write_log(path: &Path, artifacts: Vec<ConsensusMessage>) {
+        let config = ArtifactLogConfig {
+            log_path: path.to_path_buf(),
+            max_segment_size_bytes: 1024 * 1024,
+            retained_segments: 10,
+        };
+        let artifacts: Vec<_> = artifacts
+            .into_iter()
+            .map(|msg| ValidatedConsensusArtifact {
+                msg,
+                timestamp: mock_time(),
+            })
+            .collect();
+        ArtifactLog::new(
+            path.to_path_buf(),
+            config,
+            &MetricsRegistry::new(),
+            no_op_logger(),
+        )
+        .append(artifacts);
+    }
+
+    #[test]
+    fn test_replay_delivers_finalized_batches() {
+        let dir = tempfile::Builder::new().tempdir().unwrap();
+        let cup = make_genesis(ic_types::consensus::dkg::Summary::fake());
+        write_log(dir.path(), make_chain(cup.content.block.as_ref(), 3));
+        let state_manager = Arc::new(FakeStateManager::new());
+        let message_routing =
+            FakeMessageRouting::with_state_manager(Arc::clone(&state_manager) as Arc<_>);
+
+        let height = replay(
+            dir.path(),
+            &cup,
+            Height::from(2),
+            &message_routing,
+            state_manager.as_ref(),
+            &|_| Vec::new(),
+            &no_op_logger(),
+        )
+        .unwrap();
+
+        assert_eq!(height, Height::from(2));
+        assert_eq!(state_manager.latest_state_height(), Height::from(2));
+        let batches = message_routing.batches.read().unwrap();
+        let batch_numbers: Vec<_> = batches.iter().map(|batch| batch.batch_number).collect();
+        assert_eq!(batch_numbers, vec![Height::from(1), Height::from(2)]);
+    }
+
+    #[test]
+    fn test_replay_fails_on_missing_random_tape() {
+        let dir = tempfile::Builder::new().tempdir().unwrap();
+        let cup = make_genesis(ic_types::consensus::dkg::Summary::fake());
+        let artifacts = make_chain(cup.content.block.as_ref(), 2)
+            .into_iter()
+            .filter(|msg| !matches!(msg, ConsensusMessage::RandomTape(tape) if tape.height() == Height::from(2)))
+            .collect();
+        write_log(dir.path(), artifacts);
+        let state_manager = Arc::new(FakeStateManager::new());
+        let message_routing =
+            FakeMessageRouting::with_state_manager(Arc::clone(&state_manager) as Arc<_>);
+
+        let result = replay(
+            dir.path(),
+            &cup,
+            Height::from(2),
+            &message_routing,
+            state_manager.as_ref(),
+            &|_| Vec::new(),
+            &no_op_logger(),
+        );
+
+        assert!(matches!(result, Err(ReplayError::MissingRandomTape(h)) if h == Height::from(2)));
+        assert_eq!(message_routing.batches.read().unwrap().len(), 1);
+    }
+}
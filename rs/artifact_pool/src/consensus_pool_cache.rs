@@ -1,7 +1,7 @@
 //! We define a cache for consensus objects/values that is updated whenever
 //! consensus updates the consensus pool.
 use ic_interfaces::consensus_pool::{
-    ChainIterator, ChangeAction, ConsensusPool, ConsensusPoolCache,
+    ChainIterator, ChangeAction, ConsensusPool, ConsensusPoolCache, HeightWatch,
 };
 use ic_types::{
     consensus::{
@@ -13,10 +13,13 @@ use ic_types::{
 use std::cmp::Ordering;
 use std::convert::TryFrom;
 use std::sync::RwLock;
+use tokio::sync::watch;
 
 /// Implementation of ConsensusCache and ConsensusPoolCache.
 pub(crate) struct ConsensusCacheImpl {
     cache: RwLock<CachedData>,
+    finalized_height: HeightWatch,
+    certified_height: HeightWatch,
 }
 
 /// Things that can be updated in the consensus cache.
@@ -27,6 +30,7 @@ pub(crate) enum CacheUpdateAction {
 }
 
 // Internal cached data held by the the ConsensusCache.
+#[derive(Clone)]
 struct CachedData {
     finalized_block: Block,
     summary_block: Block,
@@ -79,6 +83,14 @@ impl ConsensusPoolCache for ConsensusCacheImpl {
     fn summary_block(&self) -> Block {
         self.cache.read().unwrap().summary_block.clone()
     }
+
+    fn subscribe_finalized_height(&self) -> watch::Receiver<Height> {
+        self.finalized_height.subscribe()
+    }
+
+    fn subscribe_certified_height(&self) -> watch::Receiver<Height> {
+        self.certified_height.subscribe()
+    }
 }
 
 impl ConsensusCacheImpl {
@@ -91,6 +103,8 @@ impl ConsensusCacheImpl {
         update_summary_block(pool, &mut summary_block, &finalized_block);
 
         Self {
+            finalized_height: HeightWatch::new(finalized_block.height()),
+            certified_height: HeightWatch::new(finalized_block.context.certified_height),
             cache: RwLock::new(CachedData {
                 finalized_block,
                 summary_block,
@@ -99,6 +113,17 @@ impl ConsensusCacheImpl {
         }
     }
 
+    /// Returns a cache holding a copy of the cached data, which is not
+    /// affected by later updates of this cache.
+    pub(crate) fn snapshot(&self) -> Self {
+        let cache = self.cache.read().unwrap().clone();
+        Self {
+            finalized_height: HeightWatch::new(cache.finalized_block.height()),
+            certified_height: HeightWatch::new(cache.finalized_block.context.certified_height),
+            cache: RwLock::new(cache),
+        }
+    }
+
     pub(crate) fn prepare(&self, change_set: &[ChangeAction]) -> Vec<CacheUpdateAction> {
         if change_set.is_empty() {
             return Vec::new();
@@ -142,6 +167,9 @@ impl ConsensusCacheImpl {
             }
         });
         update_summary_block(pool, &mut cache.summary_block, &cache.finalized_block);
+        self.finalized_height.update(cache.finalized_block.height());
+        self.certified_height
+            .update(cache.finalized_block.context.certified_height);
     }
 }
 
@@ -245,6 +273,8 @@ pub(crate) fn update_summary_block(
 #[cfg(test)]
 mod test {
     use super::*;
+    use futures::FutureExt;
+    use ic_config::artifact_pool::ArtifactPoolConfig;
     use ic_consensus_message::ConsensusMessageHashable;
     use ic_test_artifact_pool::consensus_pool::TestConsensusPool;
     use ic_test_utilities::{
@@ -260,31 +290,35 @@ mod test {
     use std::sync::Arc;
     use std::time::Duration;
 
+    fn test_pool(pool_config: ArtifactPoolConfig) -> TestConsensusPool {
+        let time_source = FastForwardTimeSource::new();
+        let subnet_id = subnet_test_id(1);
+        let committee = vec![node_test_id(0)];
+        let dkg_interval_length = 3;
+        let subnet_records = vec![(
+            1,
+            SubnetRecordBuilder::from(&committee)
+                .with_dkg_interval_length(dkg_interval_length)
+                .build(),
+        )];
+        let registry = setup_registry(subnet_id, subnet_records);
+        let state_manager = FakeStateManager::new();
+        let state_manager = Arc::new(state_manager);
+        TestConsensusPool::new(
+            subnet_id,
+            pool_config,
+            time_source,
+            registry,
+            Arc::new(CryptoReturningOk::default()),
+            state_manager,
+            None,
+        )
+    }
+
     #[test]
     fn test_consensus_cache() {
         ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
-            let time_source = FastForwardTimeSource::new();
-            let subnet_id = subnet_test_id(1);
-            let committee = vec![node_test_id(0)];
-            let dkg_interval_length = 3;
-            let subnet_records = vec![(
-                1,
-                SubnetRecordBuilder::from(&committee)
-                    .with_dkg_interval_length(dkg_interval_length)
-                    .build(),
-            )];
-            let registry = setup_registry(subnet_id, subnet_records);
-            let state_manager = FakeStateManager::new();
-            let state_manager = Arc::new(state_manager);
-            let mut pool = TestConsensusPool::new(
-                subnet_id,
-                pool_config,
-                time_source,
-                registry,
-                Arc::new(CryptoReturningOk::default()),
-                state_manager,
-                None,
-            );
+            let mut pool = test_pool(pool_config);
 
             // 1. Cache is properly initialized
             let consensus_cache = ConsensusCacheImpl::new(&pool);
@@ -331,4 +365,49 @@ mod test {
             assert_eq!(consensus_cache.finalized_block().height(), Height::from(4));
         })
     }
+
+    #[test]
+    fn test_consensus_cache_height_subscriptions() {
+        ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
+            let mut pool = test_pool(pool_config);
+            let consensus_cache = ConsensusCacheImpl::new(&pool);
+            let mut finalized_height = consensus_cache.subscribe_finalized_height();
+            let mut certified_height = consensus_cache.subscribe_certified_height();
+            assert_eq!(*finalized_height.borrow(), Height::from(0));
+            assert_eq!(*certified_height.borrow(), Height::from(0));
+            assert!(finalized_height.changed().now_or_never().is_none());
+
+            pool.advance_round_normal_operation_n(2);
+            let mut block = pool.make_next_block();
+            block.content.as_mut().context.certified_height = Height::from(1);
+            pool.insert_validated(block.clone());
+            pool.notarize(&block);
+            let finalization = Finalization::fake(FinalizationContent::new(
+                block.height(),
+                block.content.get_hash().clone(),
+            ));
+            let updates = consensus_cache.prepare(&[ChangeAction::AddToValidated(
+                finalization.clone().into_message(),
+            )]);
+            pool.insert_validated(finalization);
+            consensus_cache.update(&pool, updates);
+
+            // The subscribers are notified of the new heights.
+            assert!(matches!(
+                finalized_height.changed().now_or_never(),
+                Some(Ok(()))
+            ));
+            assert_eq!(*finalized_height.borrow(), Height::from(3));
+            assert!(matches!(
+                certified_height.changed().now_or_never(),
+                Some(Ok(()))
+            ));
+            assert_eq!(*certified_height.borrow(), Height::from(1));
+
+            // They are not notified again if the heights don't change.
+            consensus_cache.update(&pool, vec![CacheUpdateAction::Finalization]);
+            assert!(finalized_height.changed().now_or_never().is_none());
+            assert!(certified_height.changed().now_or_never().is_none());
+        })
+    }
 }
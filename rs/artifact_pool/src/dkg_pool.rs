@@ -1,8 +1,17 @@
-use crate::metrics::{PoolMetrics, POOL_TYPE_UNVALIDATED, POOL_TYPE_VALIDATED};
+mod persistence;
+
+use crate::dump::{DumpOptions, DumpedArtifact, PoolDump};
+use crate::metrics::{
+    PoolArtifactMetrics, PoolMetrics, UnvalidatedLimitMetrics, POOL_TYPE_UNVALIDATED,
+    POOL_TYPE_VALIDATED,
+};
+use crate::unvalidated_limiter::{artifact_size_bytes, Admission, UnvalidatedLimiter};
+use ic_config::artifact_pool::{UnvalidatedSectionLimits, DEFAULT_DKG_POOL_UNVALIDATED_LIMITS};
 use ic_crypto::crypto_hash;
 use ic_interfaces::artifact_pool::{UnvalidatedArtifact, ValidatedArtifact};
 use ic_interfaces::dkg::{ChangeAction, ChangeSet, DkgPool, MutableDkgPool};
 use ic_interfaces::gossip_pool::{DkgGossipPool, GossipPool};
+use ic_logger::ReplicaLogger;
 use ic_metrics::MetricsRegistry;
 use ic_types::consensus;
 use ic_types::consensus::dkg;
@@ -10,8 +19,10 @@ use ic_types::crypto;
 use ic_types::crypto::CryptoHashOf;
 use ic_types::time::{current_time, Time};
 use ic_types::*;
+use persistence::DkgPoolPersistence;
 use std::collections::BTreeMap;
 use std::ops::Sub;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 /// Workaround for `consensus::dkg::Message` not implementing `CountBytes`.
@@ -29,21 +40,80 @@ pub struct DkgPoolImpl {
         crypto::CryptoHashOf<consensus::dkg::Message>,
         UnvalidatedArtifact<consensus::dkg::Message>,
     >,
+    unvalidated_limiter: UnvalidatedLimiter<crypto::CryptoHashOf<consensus::dkg::Message>>,
+    unvalidated_limit_metrics: UnvalidatedLimitMetrics,
+    artifact_metrics: PoolArtifactMetrics,
+    persistence: Option<DkgPoolPersistence>,
+    /// The retention duration of the DKG intervals ahead of the current one.
+    interval_ttl: Option<Duration>,
+    /// The time at which the most recent message of each DKG interval was
+    /// received, by the start height of the interval.
+    interval_activity: BTreeMap<Height, Time>,
     current_start_height: Height,
 }
 
-const POOL_DKG: &str = "dkg";
+/// The name of the pool in metrics and dumps.
+pub const POOL_DKG: &str = "dkg";
+const ARTIFACT_TYPE_DKG_MESSAGE: &str = "dkg_message";
 
 impl DkgPoolImpl {
     /// Instantiates a new DKG pool from the time source.
     pub fn new(metrics_registry: MetricsRegistry) -> Self {
+        Self::with_unvalidated_limits(metrics_registry, DEFAULT_DKG_POOL_UNVALIDATED_LIMITS)
+    }
+
+    /// Instantiates a new DKG pool whose unvalidated section is bounded by the
+    /// given limits.
+    pub fn with_unvalidated_limits(
+        metrics_registry: MetricsRegistry,
+        unvalidated_limits: UnvalidatedSectionLimits,
+    ) -> Self {
         Self {
             validated: PoolSection::new(metrics_registry.clone(), POOL_DKG, POOL_TYPE_VALIDATED),
-            unvalidated: PoolSection::new(metrics_registry, POOL_DKG, POOL_TYPE_UNVALIDATED),
+            unvalidated: PoolSection::new(
+                metrics_registry.clone(),
+                POOL_DKG,
+                POOL_TYPE_UNVALIDATED,
+            ),
+            unvalidated_limiter: UnvalidatedLimiter::new(unvalidated_limits),
+            unvalidated_limit_metrics: UnvalidatedLimitMetrics::new(&metrics_registry, POOL_DKG),
+            artifact_metrics: PoolArtifactMetrics::new(&metrics_registry, POOL_DKG),
+            persistence: None,
+            interval_ttl: None,
+            interval_activity: BTreeMap::new(),
             current_start_height: Height::from(1),
         }
     }
 
+    /// Instantiates a new DKG pool whose validated section is persisted in the
+    /// given directory. The dealings persisted by a previous instance are
+    /// loaded into the validated section.
+    pub fn with_persistence(
+        metrics_registry: MetricsRegistry,
+        unvalidated_limits: UnvalidatedSectionLimits,
+        path: PathBuf,
+        log: ReplicaLogger,
+    ) -> Self {
+        let mut pool = Self::with_unvalidated_limits(metrics_registry.clone(), unvalidated_limits);
+        let persistence = DkgPoolPersistence::new(path, &metrics_registry, log);
+        for artifact in persistence.load() {
+            pool.insert_validated_artifact(ic_crypto::crypto_hash(&artifact.msg), artifact);
+        }
+        pool.persistence = Some(persistence);
+        pool
+    }
+
+    /// Sets the retention durations of the messages of the pool, by artifact
+    /// type. DKG messages are retained per interval: whenever consensus moves
+    /// on to a new interval, the intervals ahead of it that did not receive a
+    /// message for longer than the duration of `dkg_message` are purged, in
+    /// addition to the past intervals. The current interval is never purged
+    /// by its age.
+    pub fn with_artifact_ttls(mut self, artifact_ttls: BTreeMap<String, Duration>) -> Self {
+        self.interval_ttl = artifact_ttls.get(ARTIFACT_TYPE_DKG_MESSAGE).copied();
+        self
+    }
+
     /// Returns a DKG message by hash if available in either the validated or
     /// unvalidated sections.
     pub fn get(
@@ -56,31 +126,162 @@ impl DkgPoolImpl {
             .or_else(|| self.unvalidated.get(hash).map(|pa| &pa.message))
     }
 
-    /// Deletes all validated and unvalidated messages, which do not correspond
-    /// to the current DKG interval.
+    /// Dumps the validated section of the pool to the given file, and
+    /// returns the number of dumped artifacts.
+    pub fn dump(&self, path: &Path, options: &DumpOptions) -> std::io::Result<usize> {
+        self.pool_dump(options)?.write_to(path)
+    }
+
+    /// Returns the dump of the validated section of the pool.
+    pub fn pool_dump(&self, options: &DumpOptions) -> std::io::Result<PoolDump> {
+        let mut artifacts = Vec::new();
+        for (hash, artifact) in self.validated.iter() {
+            let msg = &artifact.msg;
+            artifacts.push(
+                DumpedArtifact::new(
+                    ARTIFACT_TYPE_DKG_MESSAGE,
+                    Some(msg.content.dkg_id.start_block_height),
+                    &hash.get_ref().0,
+                    msg,
+                    options,
+                )?
+                .with_signature(&msg.signature)?,
+            );
+        }
+        Ok(PoolDump::new(POOL_DKG, artifacts))
+    }
+
+    /// Deletes all validated and unvalidated messages of the DKG intervals
+    /// before the current one, and of the expired intervals after it.
     fn purge(&mut self, height: Height) {
         self.current_start_height = height;
+        let now = current_time();
+        let expired_intervals = self.expired_intervals(now);
+        self.interval_activity = self.interval_activity.split_off(&height);
+        for start_height in expired_intervals.iter() {
+            self.interval_activity.remove(start_height);
+        }
+        let is_purged = |start_height: Height| {
+            start_height < height || expired_intervals.contains(&start_height)
+        };
+        if let Some(persistence) = &self.persistence {
+            persistence.purge_below(height);
+            for start_height in expired_intervals.iter() {
+                persistence.purge_interval(*start_height);
+            }
+        }
+        self.unvalidated_limiter.remove_all_below(height);
         // TODO: use drain_filter once it's stable.
         let unvalidated_keys: Vec<_> = self
             .unvalidated
             .iter()
-            .filter(|(_, artifact)| artifact.message.content.dkg_id.start_block_height < height)
+            .filter(|(_, artifact)| is_purged(artifact.message.content.dkg_id.start_block_height))
             .map(|(hash, _)| hash)
             .cloned()
             .collect();
         for hash in unvalidated_keys {
-            self.unvalidated.remove(&hash);
+            self.unvalidated_limiter.remove(&hash);
+            if let Some(artifact) = self.unvalidated.remove(&hash) {
+                self.artifact_metrics.observe_purge(
+                    POOL_TYPE_UNVALIDATED,
+                    ARTIFACT_TYPE_DKG_MESSAGE,
+                    artifact.timestamp,
+                    now,
+                );
+            }
         }
 
         let validated_keys: Vec<_> = self
             .validated
             .iter()
-            .filter(|(_, artifact)| artifact.msg.content.dkg_id.start_block_height < height)
+            .filter(|(_, artifact)| is_purged(artifact.msg.content.dkg_id.start_block_height))
             .map(|(hash, _)| hash)
             .cloned()
             .collect();
         for hash in validated_keys {
-            self.validated.remove(&hash);
+            if let Some(artifact) = self.validated.remove(&hash) {
+                self.artifact_metrics.observe_purge(
+                    POOL_TYPE_VALIDATED,
+                    ARTIFACT_TYPE_DKG_MESSAGE,
+                    artifact.timestamp,
+                    now,
+                );
+            }
+        }
+    }
+
+    /// Returns the start heights of the DKG intervals after the current one
+    /// whose most recent message was received longer than the retention
+    /// duration ago.
+    fn expired_intervals(&self, now: Time) -> Vec<Height> {
+        let ttl = match self.interval_ttl {
+            Some(ttl) => ttl,
+            None => return Vec::new(),
+        };
+        self.interval_activity
+            .range(self.current_start_height.increment()..)
+            .filter(|(_, last_activity)| **last_activity + ttl <= now)
+            .map(|(start_height, _)| *start_height)
+            .collect()
+    }
+
+    /// Records that a message of the DKG interval starting at the given height
+    /// was received at the given time.
+    fn observe_interval_activity(&mut self, start_height: Height, timestamp: Time) {
+        let last_activity = self
+            .interval_activity
+            .entry(start_height)
+            .or_insert(timestamp);
+        if *last_activity < timestamp {
+            *last_activity = timestamp;
+        }
+    }
+
+    /// Removes the message with the given hash from the unvalidated section.
+    fn remove_unvalidated(
+        &mut self,
+        hash: &CryptoHashOf<dkg::Message>,
+    ) -> Option<UnvalidatedArtifact<dkg::Message>> {
+        let removed = self.unvalidated.remove(hash);
+        if removed.is_some() {
+            self.artifact_metrics
+                .observe_remove(POOL_TYPE_UNVALIDATED, ARTIFACT_TYPE_DKG_MESSAGE);
+        }
+        removed
+    }
+
+    /// Inserts the given message into the validated section, and persists it
+    /// if the pool is persistent.
+    fn insert_validated(&mut self, hash: CryptoHashOf<dkg::Message>, message: dkg::Message) {
+        let artifact = ValidatedArtifact {
+            msg: message,
+            timestamp: current_time(),
+        };
+        if let Some(persistence) = &self.persistence {
+            if !self.validated.contains_key(&hash) {
+                persistence.store(&hash, &artifact);
+            }
+        }
+        self.insert_validated_artifact(hash, artifact);
+    }
+
+    /// Inserts the given artifact into the validated section.
+    fn insert_validated_artifact(
+        &mut self,
+        hash: CryptoHashOf<dkg::Message>,
+        artifact: ValidatedArtifact<dkg::Message>,
+    ) {
+        let size_bytes = artifact_size_bytes(&artifact.msg);
+        self.observe_interval_activity(
+            artifact.msg.content.dkg_id.start_block_height,
+            artifact.timestamp,
+        );
+        if self.validated.insert(hash, artifact).is_none() {
+            self.artifact_metrics.observe_insert(
+                POOL_TYPE_VALIDATED,
+                ARTIFACT_TYPE_DKG_MESSAGE,
+                size_bytes,
+            );
         }
     }
 
@@ -98,8 +299,30 @@ impl DkgPoolImpl {
 impl MutableDkgPool for DkgPoolImpl {
     /// Inserts an unvalidated artifact into the unvalidated section.
     fn insert(&mut self, artifact: UnvalidatedArtifact<consensus::dkg::Message>) {
-        self.unvalidated
-            .insert(ic_crypto::crypto_hash(&artifact.message), artifact);
+        let hash = ic_crypto::crypto_hash(&artifact.message);
+        let size_bytes = artifact_size_bytes(&artifact.message);
+        let admission = self.unvalidated_limiter.admit(
+            hash.clone(),
+            artifact.message.content.dkg_id.start_block_height,
+            size_bytes,
+        );
+        self.unvalidated_limit_metrics.observe_admission(&admission);
+        if let Admission::Accepted { evicted } = admission {
+            for evicted_hash in evicted.iter() {
+                self.remove_unvalidated(evicted_hash);
+            }
+            self.observe_interval_activity(
+                artifact.message.content.dkg_id.start_block_height,
+                artifact.timestamp,
+            );
+            if self.unvalidated.insert(hash, artifact).is_none() {
+                self.artifact_metrics.observe_insert(
+                    POOL_TYPE_UNVALIDATED,
+                    ARTIFACT_TYPE_DKG_MESSAGE,
+                    size_bytes,
+                );
+            }
+        }
     }
 
     /// Applies the provided change set atomically.
@@ -113,29 +336,24 @@ impl MutableDkgPool for DkgPoolImpl {
         for action in change_set {
             match action {
                 ChangeAction::HandleInvalid(hash, _) => {
-                    self.unvalidated.remove(&hash);
+                    self.unvalidated_limiter.remove(&hash);
+                    self.remove_unvalidated(&hash);
                 }
                 ChangeAction::AddToValidated(message) => {
-                    self.validated.insert(
-                        ic_crypto::crypto_hash(&message),
-                        ValidatedArtifact {
-                            msg: message,
-                            timestamp: current_time(),
-                        },
-                    );
+                    self.insert_validated(ic_crypto::crypto_hash(&message), message);
                 }
                 ChangeAction::MoveToValidated(message) => {
                     let hash = crypto_hash(&message);
-                    self.unvalidated
-                        .remove(&hash)
+                    self.unvalidated_limiter.remove(&hash);
+                    let unvalidated = self
+                        .remove_unvalidated(&hash)
                         .expect("Unvalidated artifact was not found.");
-                    self.validated.insert(
-                        hash,
-                        ValidatedArtifact {
-                            msg: message,
-                            timestamp: current_time(),
-                        },
+                    self.artifact_metrics.observe_validation(
+                        ARTIFACT_TYPE_DKG_MESSAGE,
+                        unvalidated.timestamp,
+                        current_time(),
                     );
+                    self.insert_validated(hash, message);
                 }
                 ChangeAction::Purge(height) => self.purge(height),
             }
@@ -248,7 +466,9 @@ impl<K: Ord, V> PoolSection<K, V> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use ic_config::artifact_pool::UnvalidatedEvictionPolicy;
     use ic_interfaces::dkg::DkgPool;
+    use ic_logger::replica_logger::no_op_logger;
     use ic_test_utilities::{
         consensus::fake::FakeSigner,
         mock_time,
@@ -320,6 +540,158 @@ mod test {
         assert_eq!(pool.get_unvalidated().count(), 0);
     }
 
+    #[test]
+    fn test_artifact_metrics() {
+        let mut pool = DkgPoolImpl::new(MetricsRegistry::new());
+        let artifacts = |pool: &DkgPoolImpl, pool_type| {
+            pool.artifact_metrics
+                .artifacts(pool_type, ARTIFACT_TYPE_DKG_MESSAGE)
+        };
+        pool.apply_changes(vec![ChangeAction::AddToValidated(make_message(
+            Height::from(10),
+            node_test_id(0),
+        ))]);
+        for height in [10, 30].iter() {
+            pool.insert(UnvalidatedArtifact {
+                message: make_message(Height::from(*height), node_test_id(1)),
+                peer_id: node_test_id(1),
+                timestamp: mock_time(),
+            });
+        }
+        assert_eq!(artifacts(&pool, POOL_TYPE_VALIDATED), 1);
+        assert_eq!(artifacts(&pool, POOL_TYPE_UNVALIDATED), 2);
+
+        pool.apply_changes(vec![ChangeAction::Purge(Height::from(30))]);
+        assert_eq!(artifacts(&pool, POOL_TYPE_VALIDATED), 0);
+        assert_eq!(artifacts(&pool, POOL_TYPE_UNVALIDATED), 1);
+    }
+
+    #[test]
+    fn test_dkg_pool_persistence() {
+        let dir = tempfile::Builder::new().tempdir().unwrap();
+        let open = || {
+            DkgPoolImpl::with_persistence(
+                MetricsRegistry::new(),
+                DEFAULT_DKG_POOL_UNVALIDATED_LIMITS,
+                dir.path().to_path_buf(),
+                no_op_logger(),
+            )
+        };
+        let mut pool = open();
+        pool.apply_changes(vec![
+            ChangeAction::AddToValidated(make_message(Height::from(10), node_test_id(0))),
+            ChangeAction::AddToValidated(make_message(Height::from(30), node_test_id(0))),
+        ]);
+        let message = make_message(Height::from(30), node_test_id(1));
+        pool.insert(UnvalidatedArtifact {
+            message: message.clone(),
+            peer_id: node_test_id(1),
+            timestamp: mock_time(),
+        });
+        pool.apply_changes(vec![ChangeAction::MoveToValidated(message)]);
+        // Unvalidated messages are not persisted.
+        pool.insert(UnvalidatedArtifact {
+            message: make_message(Height::from(30), node_test_id(2)),
+            peer_id: node_test_id(2),
+            timestamp: mock_time(),
+        });
+        drop(pool);
+
+        let mut pool = open();
+        assert_eq!(pool.get_validated().count(), 3);
+        assert_eq!(pool.get_unvalidated().count(), 0);
+
+        // Purged intervals are deleted from disk.
+        pool.apply_changes(vec![ChangeAction::Purge(Height::from(30))]);
+        drop(pool);
+        let pool = open();
+        let mut heights: Vec<_> = pool
+            .get_validated()
+            .map(|msg| msg.content.dkg_id.start_block_height)
+            .collect();
+        heights.sort();
+        assert_eq!(heights, vec![Height::from(30), Height::from(30)]);
+    }
+
+    #[test]
+    fn test_purge_expired_intervals() {
+        let mut pool = DkgPoolImpl::new(MetricsRegistry::new()).with_artifact_ttls(
+            vec![(
+                ARTIFACT_TYPE_DKG_MESSAGE.to_string(),
+                Duration::from_secs(3600),
+            )]
+            .into_iter()
+            .collect(),
+        );
+        // Old messages of the current interval and of an interval ahead.
+        for start_height in [30, 60].iter() {
+            pool.insert(UnvalidatedArtifact {
+                message: make_message(Height::from(*start_height), node_test_id(1)),
+                peer_id: node_test_id(1),
+                timestamp: mock_time(),
+            });
+            let message = make_message(Height::from(*start_height), node_test_id(2));
+            pool.insert_validated_artifact(
+                crypto_hash(&message),
+                ValidatedArtifact {
+                    msg: message,
+                    timestamp: mock_time(),
+                },
+            );
+        }
+        // A recent message of another interval ahead.
+        pool.apply_changes(vec![ChangeAction::AddToValidated(make_message(
+            Height::from(90),
+            node_test_id(3),
+        ))]);
+        // Nothing is purged by age between interval boundaries.
+        assert_eq!(pool.get_unvalidated().count(), 2);
+        assert_eq!(pool.get_validated().count(), 3);
+
+        pool.apply_changes(vec![ChangeAction::Purge(Height::from(30))]);
+        let mut heights: Vec<_> = pool
+            .get_validated()
+            .map(|message| message.content.dkg_id.start_block_height.get())
+            .collect();
+        heights.sort_unstable();
+        assert_eq!(heights, vec![30, 90]);
+        assert_eq!(
+            pool.get_unvalidated()
+                .map(|message| message.content.dkg_id.start_block_height.get())
+                .collect::<Vec<_>>(),
+            vec![30]
+        );
+    }
+
+    #[test]
+    fn test_dkg_pool_unvalidated_limits() {
+        let mut pool = DkgPoolImpl::with_unvalidated_limits(
+            MetricsRegistry::new(),
+            UnvalidatedSectionLimits {
+                max_count: 2,
+                max_size_bytes: usize::MAX,
+                eviction_policy: UnvalidatedEvictionPolicy::LowestHeightFirst,
+            },
+        );
+        for (height, node) in &[(20, 1), (30, 2), (10, 3), (40, 4)] {
+            pool.insert(UnvalidatedArtifact {
+                message: make_message(Height::from(*height), node_test_id(*node)),
+                peer_id: node_test_id(*node),
+                timestamp: mock_time(),
+            });
+        }
+        // The message at height 10 was rejected, and the one at height 20 was
+        // evicted.
+        let mut heights: Vec<_> = pool
+            .get_unvalidated()
+            .map(|msg| msg.content.dkg_id.start_block_height)
+            .collect();
+        heights.sort();
+        assert_eq!(heights, vec![Height::from(30), Height::from(40)]);
+        assert_eq!(pool.unvalidated_limit_metrics.rejected_inserts.get(), 1);
+        assert_eq!(pool.unvalidated_limit_metrics.evicted_artifacts.get(), 1);
+    }
+
     #[test]
     fn test_dkg_pool_filter_by_age() {
         let mut pool = DkgPoolImpl::new(MetricsRegistry::new());
@@ -0,0 +1,231 @@
+//! This module implements the on-disk persistence of the validated section of
+//! the DKG pool, so that a replica restarting in the middle of a DKG interval
+//! does not have to gossip and validate all dealings of the interval again.
+//!
+//! Every validated dealing is stored in its own file
+//! `<path>/<start_block_height>/<hash>.bin`, containing the bincode encoding
+//! of the `ValidatedArtifact`. Grouping the dealings by the start height of
+//! their DKG interval allows purging an interval by deleting its directory.
+//! Files are written to a temporary file first and then renamed, so that a
+//! crash never leaves a torn dealing behind.
+
+use ic_interfaces::artifact_pool::ValidatedArtifact;
+use ic_logger::{error, warn, ReplicaLogger};
+use ic_metrics::MetricsRegistry;
+use ic_types::{consensus::dkg, crypto::CryptoHashOf, Height};
+use prometheus::IntCounter;
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// The file extension of stored dealings.
+const DEALING_EXTENSION: &str = "bin";
+
+/// The file extension of dealings that are being written.
+const TEMPORARY_EXTENSION: &str = "tmp";
+
+#[derive(Clone, Debug)]
+struct Metrics {
+    // Amount of I/O errors. Any number above 0 is critical.
+    io_errors: IntCounter,
+    // Amount of dealings loaded at startup.
+    loaded_dealings: IntCounter,
+}
+
+impl Metrics {
+    fn new(registry: &MetricsRegistry) -> Self {
+        Self {
+            io_errors: registry.int_counter(
+                "dkg_pool_persistence_io_errors",
+                "The number of I/O errors happened while storing, loading or purging persisted DKG dealings.",
+            ),
+            loaded_dealings: registry.int_counter(
+                "dkg_pool_persistence_loaded_dealings",
+                "The number of validated DKG dealings loaded from disk at startup.",
+            ),
+        }
+    }
+}
+
+/// The persistent store of the validated section of the DKG pool.
+pub(crate) struct DkgPoolPersistence {
+    path: PathBuf,
+    metrics: Metrics,
+    log: ReplicaLogger,
+}
+
+impl DkgPoolPersistence {
+    /// Opens the store in the given directory, creating it if necessary.
+    pub(crate) fn new(
+        path: PathBuf,
+        metrics_registry: &MetricsRegistry,
+        log: ReplicaLogger,
+    ) -> Self {
+        let metrics = Metrics::new(metrics_registry);
+        if let Err(err) = fs::create_dir_all(&path) {
+            metrics.io_errors.inc();
+            error!(
+                log,
+                "Creating the DKG pool directory {:?} failed: {:?}", path, err
+            );
+        }
+        Self { path, metrics, log }
+    }
+
+    /// Returns all stored dealings. Dealings that cannot be read or decoded
+    /// are deleted, so that they are gossiped and validated again.
+    pub(crate) fn load(&self) -> Vec<ValidatedArtifact<dkg::Message>> {
+        let mut artifacts = Vec::new();
+        let intervals = match list_intervals(&self.path) {
+            Ok(intervals) => intervals,
+            Err(err) => {
+                self.metrics.io_errors.inc();
+                error!(
+                    self.log,
+                    "Listing the persisted DKG intervals failed: {:?}", err
+                );
+                return artifacts;
+            }
+        };
+        for (_, interval_path) in intervals {
+            let entries = match fs::read_dir(&interval_path) {
+                Ok(entries) => entries,
+                Err(err) => {
+                    self.metrics.io_errors.inc();
+                    error!(
+                        self.log,
+                        "Listing the persisted DKG dealings in {:?} failed: {:?}",
+                        interval_path,
+                        err
+                    );
+                    continue;
+                }
+            };
+            for entry in entries.filter_map(Result::ok) {
+                let file_path = entry.path();
+                if file_path.extension().and_then(|ext| ext.to_str()) != Some(DEALING_EXTENSION) {
+                    // Left over by an interrupted write.
+                    fs::remove_file(&file_path).ok();
+                    continue;
+                }
+                match read_dealing(&file_path) {
+                    Ok(artifact) => artifacts.push(artifact),
+                    Err(err) => {
+                        warn!(
+                            self.log,
+                            "Deleting the unreadable DKG dealing {:?}: {:?}", file_path, err
+                        );
+                        fs::remove_file(&file_path).ok();
+                    }
+                }
+            }
+        }
+        self.metrics.loaded_dealings.inc_by(artifacts.len() as u64);
+        artifacts
+    }
+
+    /// Stores the given dealing.
+    pub(crate) fn store(
+        &self,
+        hash: &CryptoHashOf<dkg::Message>,
+        artifact: &ValidatedArtifact<dkg::Message>,
+    ) {
+        if let Err(err) = self.try_store(hash, artifact) {
+            self.metrics.io_errors.inc();
+            error!(self.log, "Storing a DKG dealing failed: {:?}", err);
+        }
+    }
+
+    fn try_store(
+        &self,
+        hash: &CryptoHashOf<dkg::Message>,
+        artifact: &ValidatedArtifact<dkg::Message>,
+    ) -> io::Result<()> {
+        let interval_path =
+            interval_path(&self.path, artifact.msg.content.dkg_id.start_block_height);
+        fs::create_dir_all(&interval_path)?;
+        let file_name = hex_encode(&hash.get_ref().0);
+        let bytes = bincode::serialize(artifact)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let temporary_path = interval_path.join(format!("{}.{}", file_name, TEMPORARY_EXTENSION));
+        let mut file = fs::File::create(&temporary_path)?;
+        file.write_all(&bytes)?;
+        file.sync_all()?;
+        fs::rename(
+            &temporary_path,
+            interval_path.join(format!("{}.{}", file_name, DEALING_EXTENSION)),
+        )
+    }
+
+    /// Deletes all dealings of the DKG interval starting at the given height.
+    pub(crate) fn purge_interval(&self, start_height: Height) {
+        self.remove_interval(&interval_path(&self.path, start_height));
+    }
+
+    /// Deletes all dealings of DKG intervals starting below the given height.
+    pub(crate) fn purge_below(&self, height: Height) {
+        let intervals = match list_intervals(&self.path) {
+            Ok(intervals) => intervals,
+            Err(err) => {
+                self.metrics.io_errors.inc();
+                error!(
+                    self.log,
+                    "Listing the persisted DKG intervals failed: {:?}", err
+                );
+                return;
+            }
+        };
+        for (start_height, interval_path) in intervals {
+            if start_height < height {
+                self.remove_interval(&interval_path);
+            }
+        }
+    }
+
+    /// Deletes the directory of a DKG interval with all its dealings.
+    fn remove_interval(&self, interval_path: &Path) {
+        match fs::remove_dir_all(interval_path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => {
+                self.metrics.io_errors.inc();
+                error!(
+                    self.log,
+                    "Purging the persisted DKG dealings in {:?} failed: {:?}", interval_path, err
+                );
+            }
+            _ => (),
+        }
+    }
+}
+
+fn read_dealing(path: &Path) -> io::Result<ValidatedArtifact<dkg::Message>> {
+    let bytes = fs::read(path)?;
+    bincode::deserialize(&bytes).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+fn interval_path(path: &Path, start_height: Height) -> PathBuf {
+    path.join(start_height.to_string())
+}
+
+/// Returns the start heights and directories of all persisted DKG intervals.
+/// Entries whose name is not a height are ignored.
+fn list_intervals(path: &Path) -> io::Result<Vec<(Height, PathBuf)>> {
+    let mut intervals = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        if let Some(height) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse::<u64>().ok())
+        {
+            intervals.push((Height::from(height), entry.path()));
+        }
+    }
+    intervals.sort();
+    Ok(intervals)
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
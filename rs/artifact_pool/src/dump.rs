@@ -0,0 +1,224 @@
+//! This module implements dumps of the validated sections of the artifact
+//! pools, which support engineers can diff between replicas that have
+//! diverged.
+//!
+//! A dump is a CBOR encoded `PoolDump`, listing the type, height, hash and
+//! signature of every validated artifact of a pool, sorted by type, height and
+//! hash, so that the dumps of two replicas can be compared entry by entry.
+//! Full artifacts are only included on request, as they make the dump much
+//! larger.
+//!
+//! The pools are registered with a `PoolDumps` when the replica starts, which
+//! serves their dumps to the `/_/pool_dump` debug endpoint.
+
+use ic_interfaces::artifact_pool::PoolDumpReader;
+use ic_types::{consensus::ConsensusMessage, Height};
+use serde::{Deserialize, Serialize};
+use serde_cbor::Value;
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, BufReader, BufWriter, Write},
+    path::Path,
+    sync::{Arc, RwLock},
+};
+
+/// Options controlling the contents of a dump.
+#[derive(Clone, Debug, Default)]
+pub struct DumpOptions {
+    /// Whether to include the full artifacts, or only their type, height,
+    /// hash and signature.
+    pub include_artifacts: bool,
+}
+
+/// The dump of the validated section of a pool.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PoolDump {
+    /// The name of the pool, e.g. "consensus".
+    pub pool: String,
+    /// The artifacts, sorted by type, height and hash.
+    pub artifacts: Vec<DumpedArtifact>,
+}
+
+/// A validated artifact in a `PoolDump`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DumpedArtifact {
+    pub artifact_type: String,
+    /// The height of the artifact, if artifacts of its type have one.
+    pub height: Option<Height>,
+    #[serde(with = "serde_bytes")]
+    pub hash: Vec<u8>,
+    /// The signature of the artifact, if it is signed outside of its content.
+    pub signature: Option<Value>,
+    /// The full artifact, if requested in the `DumpOptions`.
+    pub artifact: Option<Value>,
+}
+
+impl DumpedArtifact {
+    /// Creates the dump entry of the given artifact.
+    pub(crate) fn new<T: Serialize>(
+        artifact_type: &str,
+        height: Option<Height>,
+        hash: &[u8],
+        artifact: &T,
+        options: &DumpOptions,
+    ) -> io::Result<Self> {
+        let artifact = if options.include_artifacts {
+            Some(to_value(artifact)?)
+        } else {
+            None
+        };
+        Ok(Self {
+            artifact_type: artifact_type.to_string(),
+            height,
+            hash: hash.to_vec(),
+            signature: None,
+            artifact,
+        })
+    }
+
+    /// Adds the given signature to the dump entry.
+    pub(crate) fn with_signature<S: Serialize>(mut self, signature: &S) -> io::Result<Self> {
+        self.signature = Some(to_value(signature)?);
+        Ok(self)
+    }
+
+    /// Creates the dump entry of the given consensus message.
+    pub(crate) fn from_consensus_message(
+        artifact_type: &str,
+        height: Height,
+        hash: &[u8],
+        msg: &ConsensusMessage,
+        options: &DumpOptions,
+    ) -> io::Result<Self> {
+        let entry = Self::new(artifact_type, Some(height), hash, msg, options)?;
+        match msg {
+            ConsensusMessage::RandomBeacon(x) => entry.with_signature(&x.signature),
+            ConsensusMessage::Finalization(x) => entry.with_signature(&x.signature),
+            ConsensusMessage::Notarization(x) => entry.with_signature(&x.signature),
+            ConsensusMessage::BlockProposal(x) => entry.with_signature(&x.signature),
+            ConsensusMessage::RandomBeaconShare(x) => entry.with_signature(&x.signature),
+            ConsensusMessage::NotarizationShare(x) => entry.with_signature(&x.signature),
+            ConsensusMessage::FinalizationShare(x) => entry.with_signature(&x.signature),
+            ConsensusMessage::RandomTape(x) => entry.with_signature(&x.signature),
+            ConsensusMessage::RandomTapeShare(x) => entry.with_signature(&x.signature),
+            ConsensusMessage::CatchUpPackage(x) => entry.with_signature(&x.signature),
+            ConsensusMessage::CatchUpPackageShare(x) => entry.with_signature(&x.signature),
+        }
+    }
+}
+
+impl PoolDump {
+    /// Creates the dump of the given pool with the given artifacts.
+    pub(crate) fn new(pool: &str, mut artifacts: Vec<DumpedArtifact>) -> Self {
+        artifacts.sort_by(|a, b| {
+            (&a.artifact_type, a.height, &a.hash).cmp(&(&b.artifact_type, b.height, &b.hash))
+        });
+        Self {
+            pool: pool.to_string(),
+            artifacts,
+        }
+    }
+
+    /// Writes the dump to the given file, and returns the number of dumped
+    /// artifacts.
+    pub fn write_to(&self, path: &Path) -> io::Result<usize> {
+        let mut writer = BufWriter::new(fs::File::create(path)?);
+        serde_cbor::to_writer(&mut writer, self)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        writer.flush()?;
+        Ok(self.artifacts.len())
+    }
+
+    /// Returns the CBOR encoding of the dump.
+    pub fn to_vec(&self) -> io::Result<Vec<u8>> {
+        serde_cbor::to_vec(self).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    /// Reads a dump from the given file.
+    pub fn read_from(path: &Path) -> io::Result<Self> {
+        serde_cbor::from_reader(BufReader::new(fs::File::open(path)?))
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+}
+
+type DumpFn = Box<dyn Fn(&DumpOptions) -> io::Result<PoolDump> + Send + Sync>;
+
+/// The pools whose dumps can be read while the replica runs, by name.
+#[derive(Clone, Default)]
+pub struct PoolDumps {
+    pools: Arc<RwLock<BTreeMap<String, DumpFn>>>,
+}
+
+impl PoolDumps {
+    /// Registers the function returning the dump of the pool with the given
+    /// name, replacing any function registered before.
+    pub fn register<F>(&self, pool: &str, dump: F)
+    where
+        F: Fn(&DumpOptions) -> io::Result<PoolDump> + Send + Sync + 'static,
+    {
+        self.pools
+            .write()
+            .unwrap()
+            .insert(pool.to_string(), Box::new(dump));
+    }
+
+    /// Returns the dump of the pool with the given name, or `None` if it is
+    /// not registered.
+    pub fn dump(&self, pool: &str, options: &DumpOptions) -> Option<io::Result<PoolDump>> {
+        self.pools
+            .read()
+            .unwrap()
+            .get(pool)
+            .map(|dump| dump(options))
+    }
+}
+
+impl PoolDumpReader for PoolDumps {
+    fn get_pool_dump(
+        &self,
+        pool: &str,
+        include_artifacts: bool,
+    ) -> Option<Result<Vec<u8>, String>> {
+        let options = DumpOptions { include_artifacts };
+        self.dump(pool, &options).map(|dump| {
+            dump.and_then(|dump| dump.to_vec())
+                .map_err(|err| format!("Failed to dump the {} pool: {}", pool, err))
+        })
+    }
+}
+
+fn to_value<T: Serialize>(value: &T) -> io::Result<Value> {
+    serde_cbor::value::to_value(value)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pool_dumps() {
+        let pool_dumps = PoolDumps::default();
+        assert!(pool_dumps.get_pool_dump("test", false).is_none());
+
+        pool_dumps.register("test", |options| {
+            let artifact = DumpedArtifact::new("test_artifact", None, &[1], &7u64, options)?;
+            Ok(PoolDump::new("test", vec![artifact]))
+        });
+        let read = |include_artifacts| -> PoolDump {
+            let bytes = pool_dumps
+                .get_pool_dump("test", include_artifacts)
+                .unwrap()
+                .unwrap();
+            serde_cbor::from_slice(&bytes).unwrap()
+        };
+        assert_eq!(read(false).artifacts[0].artifact, None);
+        assert_eq!(read(true).artifacts[0].artifact, Some(Value::Integer(7)));
+
+        pool_dumps.register("failing", |_| {
+            Err(io::Error::new(io::ErrorKind::Other, "broken"))
+        });
+        assert!(pool_dumps.get_pool_dump("failing", false).unwrap().is_err());
+    }
+}
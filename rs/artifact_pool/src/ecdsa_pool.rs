@@ -0,0 +1,258 @@
+//! The ECDSA pool holds the dealings, signature shares and complaints of the
+//! threshold ECDSA protocol, until the work they were requested for is older
+//! than the latest catch-up package.
+use crate::metrics::{PoolArtifactMetrics, POOL_TYPE_UNVALIDATED, POOL_TYPE_VALIDATED};
+use crate::unvalidated_limiter::{artifact_size_bytes, Admission, UnvalidatedLimiter};
+use ic_config::artifact_pool::{UnvalidatedEvictionPolicy, UnvalidatedSectionLimits};
+use ic_interfaces::artifact_pool::{UnvalidatedArtifact, ValidatedArtifact};
+use ic_interfaces::ecdsa::{EcdsaChangeAction, EcdsaChangeSet, EcdsaPool, MutableEcdsaPool};
+use ic_interfaces::gossip_pool::{EcdsaGossipPool, GossipPool};
+use ic_metrics::MetricsRegistry;
+use ic_types::artifact::EcdsaMessageId;
+use ic_types::consensus::{ecdsa::EcdsaMessage, HasHeight};
+use ic_types::time::current_time;
+use ic_types::Height;
+use std::collections::BTreeMap;
+
+const POOL_ECDSA: &str = "ecdsa";
+const ARTIFACT_TYPE_ECDSA_MESSAGE: &str = "ecdsa_message";
+
+/// The limits of the unvalidated section of the ECDSA pool, which favour the
+/// messages requested at the highest heights.
+const UNVALIDATED_LIMITS: UnvalidatedSectionLimits = UnvalidatedSectionLimits {
+    max_count: 10_000,
+    max_size_bytes: 64 * 1024 * 1024,
+    eviction_policy: UnvalidatedEvictionPolicy::LowestHeightFirst,
+};
+
+/// The in-memory pool of ECDSA messages.
+pub struct EcdsaPoolImpl {
+    validated: BTreeMap<EcdsaMessageId, ValidatedArtifact<EcdsaMessage>>,
+    unvalidated: BTreeMap<EcdsaMessageId, UnvalidatedArtifact<EcdsaMessage>>,
+    unvalidated_limiter: UnvalidatedLimiter<EcdsaMessageId>,
+    artifact_metrics: PoolArtifactMetrics,
+}
+
+impl EcdsaPoolImpl {
+    pub fn new(metrics_registry: MetricsRegistry) -> Self {
+        Self {
+            validated: BTreeMap::new(),
+            unvalidated: BTreeMap::new(),
+            unvalidated_limiter: UnvalidatedLimiter::new(UNVALIDATED_LIMITS),
+            artifact_metrics: PoolArtifactMetrics::new(&metrics_registry, POOL_ECDSA),
+        }
+    }
+
+    fn remove_unvalidated(
+        &mut self,
+        id: &EcdsaMessageId,
+    ) -> Option<UnvalidatedArtifact<EcdsaMessage>> {
+        self.unvalidated_limiter.remove(id);
+        let removed = self.unvalidated.remove(id);
+        if removed.is_some() {
+            self.artifact_metrics
+                .observe_remove(POOL_TYPE_UNVALIDATED, ARTIFACT_TYPE_ECDSA_MESSAGE);
+        }
+        removed
+    }
+
+    fn insert_validated(&mut self, id: EcdsaMessageId, msg: EcdsaMessage) {
+        let size_bytes = artifact_size_bytes(&msg);
+        let artifact = ValidatedArtifact {
+            msg,
+            timestamp: current_time(),
+        };
+        if self.validated.insert(id, artifact).is_none() {
+            self.artifact_metrics.observe_insert(
+                POOL_TYPE_VALIDATED,
+                ARTIFACT_TYPE_ECDSA_MESSAGE,
+                size_bytes,
+            );
+        }
+    }
+
+    /// Removes all messages requested below the given height from both
+    /// sections.
+    fn purge_below(&mut self, height: Height) {
+        let now = current_time();
+        self.unvalidated_limiter.remove_all_below(height);
+        let unvalidated_ids: Vec<_> = self
+            .unvalidated
+            .iter()
+            .filter(|(_, artifact)| artifact.message.height() < height)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in unvalidated_ids {
+            if let Some(artifact) = self.unvalidated.remove(&id) {
+                self.artifact_metrics.observe_purge(
+                    POOL_TYPE_UNVALIDATED,
+                    ARTIFACT_TYPE_ECDSA_MESSAGE,
+                    artifact.timestamp,
+                    now,
+                );
+            }
+        }
+        let validated_ids: Vec<_> = self
+            .validated
+            .iter()
+            .filter(|(_, artifact)| artifact.msg.height() < height)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in validated_ids {
+            if let Some(artifact) = self.validated.remove(&id) {
+                self.artifact_metrics.observe_purge(
+                    POOL_TYPE_VALIDATED,
+                    ARTIFACT_TYPE_ECDSA_MESSAGE,
+                    artifact.timestamp,
+                    now,
+                );
+            }
+        }
+    }
+}
+
+impl EcdsaPool for EcdsaPoolImpl {
+    fn get_validated(&self) -> Box<dyn Iterator<Item = &EcdsaMessage> + '_> {
+        Box::new(self.validated.values().map(|artifact| &artifact.msg))
+    }
+
+    fn get_unvalidated(&self) -> Box<dyn Iterator<Item = &EcdsaMessage> + '_> {
+        Box::new(self.unvalidated.values().map(|artifact| &artifact.message))
+    }
+}
+
+impl MutableEcdsaPool for EcdsaPoolImpl {
+    fn insert(&mut self, artifact: UnvalidatedArtifact<EcdsaMessage>) {
+        let id = ic_crypto::crypto_hash(&artifact.message);
+        if self.validated.contains_key(&id) {
+            return;
+        }
+        let size_bytes = artifact_size_bytes(&artifact.message);
+        let admission =
+            self.unvalidated_limiter
+                .admit(id.clone(), artifact.message.height(), size_bytes);
+        if let Admission::Accepted { evicted } = admission {
+            for evicted_id in evicted.iter() {
+                self.remove_unvalidated(evicted_id);
+            }
+            if self.unvalidated.insert(id, artifact).is_none() {
+                self.artifact_metrics.observe_insert(
+                    POOL_TYPE_UNVALIDATED,
+                    ARTIFACT_TYPE_ECDSA_MESSAGE,
+                    size_bytes,
+                );
+            }
+        }
+    }
+
+    /// Applies the provided change set atomically.
+    ///
+    /// # Panics
+    ///
+    /// It panics if a message to be moved into the validated section cannot
+    /// be found in the unvalidated section.
+    fn apply_changes(&mut self, change_set: EcdsaChangeSet) {
+        for action in change_set {
+            match action {
+                EcdsaChangeAction::AddToValidated(msg) => {
+                    let id = ic_crypto::crypto_hash(&msg);
+                    self.remove_unvalidated(&id);
+                    self.insert_validated(id, msg);
+                }
+                EcdsaChangeAction::MoveToValidated(msg) => {
+                    let id = ic_crypto::crypto_hash(&msg);
+                    let unvalidated = self
+                        .remove_unvalidated(&id)
+                        .expect("Unvalidated artifact was not found.");
+                    self.artifact_metrics.observe_validation(
+                        ARTIFACT_TYPE_ECDSA_MESSAGE,
+                        unvalidated.timestamp,
+                        current_time(),
+                    );
+                    self.insert_validated(id, msg);
+                }
+                EcdsaChangeAction::RemoveFromUnvalidated(id)
+                | EcdsaChangeAction::HandleInvalid(id, _) => {
+                    self.remove_unvalidated(&id);
+                }
+                EcdsaChangeAction::PurgeBelow(height) => self.purge_below(height),
+            }
+        }
+    }
+}
+
+impl GossipPool<EcdsaMessage, EcdsaChangeSet> for EcdsaPoolImpl {
+    type MessageId = EcdsaMessageId;
+    type Filter = ();
+
+    fn contains(&self, id: &Self::MessageId) -> bool {
+        self.unvalidated.contains_key(id) || self.validated.contains_key(id)
+    }
+
+    fn get_validated_by_identifier(&self, id: &Self::MessageId) -> Option<EcdsaMessage> {
+        self.validated.get(id).map(|artifact| artifact.msg.clone())
+    }
+
+    fn get_all_validated_by_filter(
+        &self,
+        _filter: Self::Filter,
+    ) -> Box<dyn Iterator<Item = EcdsaMessage> + '_> {
+        Box::new(self.validated.values().map(|artifact| artifact.msg.clone()))
+    }
+}
+
+impl EcdsaGossipPool for EcdsaPoolImpl {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_test_utilities::{mock_time, types::ids::node_test_id};
+    use ic_types::{
+        consensus::{
+            ecdsa::{EcdsaMessageContent, EcdsaSigShare, QuadrupleId},
+            BasicSignature,
+        },
+        crypto::{BasicSig, BasicSigOf},
+    };
+
+    fn make_share(height: u64) -> EcdsaMessage {
+        EcdsaMessage {
+            content: EcdsaMessageContent::EcdsaSigShare(EcdsaSigShare {
+                requested_height: Height::from(height),
+                quadruple_id: QuadrupleId(height),
+                signer: node_test_id(0),
+                share: vec![],
+            }),
+            signature: BasicSignature {
+                signature: BasicSigOf::new(BasicSig(vec![])),
+                signer: node_test_id(0),
+            },
+        }
+    }
+
+    #[test]
+    fn test_ecdsa_pool() {
+        let mut pool = EcdsaPoolImpl::new(MetricsRegistry::new());
+        let msg = make_share(5);
+        let id = ic_crypto::crypto_hash(&msg);
+        pool.insert(UnvalidatedArtifact {
+            message: msg.clone(),
+            peer_id: node_test_id(1),
+            timestamp: mock_time(),
+        });
+        assert!(pool.contains(&id));
+        assert_eq!(pool.get_unvalidated().count(), 1);
+        assert_eq!(pool.get_validated_by_identifier(&id), None);
+
+        pool.apply_changes(vec![EcdsaChangeAction::MoveToValidated(msg.clone())]);
+        assert_eq!(pool.get_unvalidated().count(), 0);
+        assert_eq!(pool.get_validated_by_identifier(&id), Some(msg));
+
+        pool.apply_changes(vec![
+            EcdsaChangeAction::AddToValidated(make_share(10)),
+            EcdsaChangeAction::PurgeBelow(Height::from(6)),
+        ]);
+        assert!(!pool.contains(&id));
+        assert_eq!(pool.get_validated().count(), 1);
+    }
+}
@@ -0,0 +1,185 @@
+//! The equivocation pool holds the proofs of block makers that signed two
+//! different block proposals at the same height, until the proofs are
+//! included in a finalized block or are too old to be included.
+use crate::message_pool::MessagePool;
+use ic_config::artifact_pool::{UnvalidatedEvictionPolicy, UnvalidatedSectionLimits};
+use ic_interfaces::artifact_pool::UnvalidatedArtifact;
+use ic_interfaces::equivocation::{
+    EquivocationChangeAction, EquivocationChangeSet, EquivocationPool, MutableEquivocationPool,
+};
+use ic_interfaces::gossip_pool::{EquivocationGossipPool, GossipPool};
+use ic_metrics::MetricsRegistry;
+use ic_types::artifact::EquivocationProofId;
+use ic_types::consensus::{equivocation::EquivocationProof, HasHeight};
+
+const POOL_EQUIVOCATION: &str = "equivocation";
+const ARTIFACT_TYPE_EQUIVOCATION_PROOF: &str = "equivocation_proof";
+
+/// The limits of the unvalidated section of the equivocation pool. Honest
+/// peers only send a handful of proofs, so the limits are small, and favour
+/// the proofs of the highest heights.
+const UNVALIDATED_LIMITS: UnvalidatedSectionLimits = UnvalidatedSectionLimits {
+    max_count: 1_000,
+    max_size_bytes: 64 * 1024 * 1024,
+    eviction_policy: UnvalidatedEvictionPolicy::LowestHeightFirst,
+};
+
+/// The in-memory pool of equivocation proofs.
+pub struct EquivocationPoolImpl {
+    pool: MessagePool<EquivocationProof>,
+}
+
+impl EquivocationPoolImpl {
+    pub fn new(metrics_registry: MetricsRegistry) -> Self {
+        Self {
+            pool: MessagePool::new(
+                &metrics_registry,
+                POOL_EQUIVOCATION,
+                ARTIFACT_TYPE_EQUIVOCATION_PROOF,
+                UNVALIDATED_LIMITS,
+            ),
+        }
+    }
+}
+
+impl EquivocationPool for EquivocationPoolImpl {
+    fn get_validated(&self) -> Box<dyn Iterator<Item = &EquivocationProof> + '_> {
+        Box::new(self.pool.validated())
+    }
+
+    fn get_unvalidated(&self) -> Box<dyn Iterator<Item = &EquivocationProof> + '_> {
+        Box::new(self.pool.unvalidated())
+    }
+}
+
+impl MutableEquivocationPool for EquivocationPoolImpl {
+    fn insert(&mut self, artifact: UnvalidatedArtifact<EquivocationProof>) {
+        let height = artifact.message.height();
+        self.pool.insert(artifact, height);
+    }
+
+    /// Applies the provided change set atomically.
+    ///
+    /// # Panics
+    ///
+    /// It panics if a proof to be moved into the validated section cannot be
+    /// found in the unvalidated section.
+    fn apply_changes(&mut self, change_set: EquivocationChangeSet) {
+        for action in change_set {
+            match action {
+                EquivocationChangeAction::AddToValidated(proof) => {
+                    self.pool.add_to_validated(proof);
+                }
+                EquivocationChangeAction::MoveToValidated(proof) => {
+                    self.pool.move_to_validated(proof);
+                }
+                EquivocationChangeAction::RemoveFromUnvalidated(id)
+                | EquivocationChangeAction::HandleInvalid(id, _) => {
+                    self.pool.remove_unvalidated(&id);
+                }
+                // Removes all proofs of proposals below the given height.
+                EquivocationChangeAction::PurgeBelow(height) => {
+                    self.pool.purge(|proof| proof.height() < height)
+                }
+            }
+        }
+    }
+}
+
+impl GossipPool<EquivocationProof, EquivocationChangeSet> for EquivocationPoolImpl {
+    type MessageId = EquivocationProofId;
+    type Filter = ();
+
+    fn contains(&self, id: &Self::MessageId) -> bool {
+        self.pool.contains(id)
+    }
+
+    fn get_validated_by_identifier(&self, id: &Self::MessageId) -> Option<EquivocationProof> {
+        self.pool.get_validated(id).cloned()
+    }
+
+    fn get_all_validated_by_filter(
+        &self,
+        _filter: Self::Filter,
+    ) -> Box<dyn Iterator<Item = EquivocationProof> + '_> {
+        Box::new(self.pool.validated().cloned())
+    }
+}
+
+impl EquivocationGossipPool for EquivocationPoolImpl {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_consensus_message::make_genesis;
+    use ic_test_utilities::{
+        consensus::fake::{Fake, FakeContentSigner, FromParent},
+        mock_time,
+        types::ids::node_test_id,
+    };
+    use ic_types::consensus::{dkg, Block, BlockProposal};
+    use ic_types::Height;
+    use std::time::Duration;
+
+    fn make_proof(height: u64) -> EquivocationProof {
+        let genesis = make_genesis(dkg::Summary::fake());
+        let proposal = |nanos| {
+            let mut block = Block::from_parent(genesis.content.block.as_ref());
+            block.height = Height::from(height);
+            block.context.time = mock_time() + Duration::from_nanos(nanos);
+            BlockProposal::fake(block, node_test_id(0))
+        };
+        EquivocationProof::new(proposal(0), proposal(1)).unwrap()
+    }
+
+    fn insert(pool: &mut EquivocationPoolImpl, proof: &EquivocationProof) {
+        pool.insert(UnvalidatedArtifact {
+            message: proof.clone(),
+            peer_id: node_test_id(1),
+            timestamp: mock_time(),
+        });
+    }
+
+    fn heights<'a>(proofs: impl Iterator<Item = &'a EquivocationProof>) -> Vec<u64> {
+        proofs.map(|proof| proof.height().get()).collect()
+    }
+
+    #[test]
+    fn invalid_proofs_are_removed_and_valid_ones_gossiped() {
+        let mut pool = EquivocationPoolImpl::new(MetricsRegistry::new());
+        let (valid, invalid) = (make_proof(5), make_proof(6));
+        insert(&mut pool, &valid);
+        insert(&mut pool, &invalid);
+        let invalid_id = ic_crypto::crypto_hash(&invalid);
+
+        pool.apply_changes(vec![
+            EquivocationChangeAction::MoveToValidated(valid.clone()),
+            EquivocationChangeAction::HandleInvalid(invalid_id.clone(), "invalid".to_string()),
+        ]);
+        assert!(!pool.contains(&invalid_id));
+        assert_eq!(pool.get_unvalidated().count(), 0);
+        assert_eq!(
+            pool.get_validated_by_identifier(&ic_crypto::crypto_hash(&valid)),
+            Some(valid.clone())
+        );
+        assert_eq!(
+            pool.get_all_validated_by_filter(()).collect::<Vec<_>>(),
+            vec![valid]
+        );
+    }
+
+    #[test]
+    fn proofs_of_proposals_below_the_purge_height_are_removed() {
+        let mut pool = EquivocationPoolImpl::new(MetricsRegistry::new());
+        for height in 4..=6 {
+            insert(&mut pool, &make_proof(height));
+        }
+        pool.apply_changes(vec![
+            EquivocationChangeAction::MoveToValidated(make_proof(5)),
+            EquivocationChangeAction::AddToValidated(make_proof(7)),
+            EquivocationChangeAction::PurgeBelow(Height::from(6)),
+        ]);
+        assert_eq!(heights(pool.get_unvalidated()), vec![6]);
+        assert_eq!(heights(pool.get_validated()), vec![7]);
+    }
+}
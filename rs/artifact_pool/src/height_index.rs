@@ -5,6 +5,7 @@ use ic_types::{
 };
 use std::collections::BTreeMap;
 
+#[derive(Clone)]
 pub struct HeightIndex<T: Eq> {
     buckets: BTreeMap<Height, Vec<T>>,
 }
@@ -89,6 +90,7 @@ impl<T: Eq + Clone> HeightIndex<T> {
     }
 }
 
+#[derive(Clone)]
 pub struct Indexes {
     pub random_beacon: HeightIndex<CryptoHashOf<RandomBeacon>>,
     pub finalization: HeightIndex<CryptoHashOf<Finalization>>,
@@ -2,81 +2,145 @@
 /// Logically it can be viewed as part of the artifact pool
 /// But we keep it separated for code readability
 use crate::{
-    metrics::{PoolMetrics, POOL_TYPE_UNVALIDATED, POOL_TYPE_VALIDATED},
+    dump::{DumpOptions, DumpedArtifact, PoolDump},
+    metrics::{PoolArtifactMetrics, PoolMetrics, POOL_TYPE_UNVALIDATED, POOL_TYPE_VALIDATED},
     peer_index::PeerIndex,
 };
-use ic_config::artifact_pool::ArtifactPoolConfig;
+use ic_config::artifact_pool::{ArtifactPoolConfig, IngressCanisterQuota};
 use ic_interfaces::{
     artifact_pool::{ArtifactPoolError, HasTimestamp, UnvalidatedArtifact},
     gossip_pool::{GossipPool, IngressGossipPool},
     ingress_pool::{
-        ChangeAction, ChangeSet, IngressPool, IngressPoolObject, IngressPoolSelect,
-        IngressPoolThrottler, MutableIngressPool, PoolSection, SelectResult,
-        UnvalidatedIngressArtifact, ValidatedIngressArtifact,
+        ChangeAction, ChangeSet, IngressPool, IngressPoolLookup, IngressPoolObject,
+        IngressPoolSelect, IngressPoolThrottler, MutableIngressPool, PoolSection, SelectResult,
+        SnapshotIngressPool, UnvalidatedIngressArtifact, ValidatedIngressArtifact,
     },
 };
 use ic_logger::{debug, trace, ReplicaLogger};
 use ic_metrics::MetricsRegistry;
 use ic_types::{
     artifact::IngressMessageId,
-    messages::{MessageId, SignedIngress, EXPECTED_MESSAGE_ID_LENGTH},
-    CountBytes, NodeId, Time,
+    messages::{correlation_id, MessageId, SignedIngress, EXPECTED_MESSAGE_ID_LENGTH},
+    time::current_time,
+    CanisterId, CountBytes, NodeId, Time,
 };
 use prometheus::IntCounter;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::path::Path;
+use std::sync::{Arc, RwLock};
 
+/// The number of shards of the bookkeeping of the ingress pool.
+const INGRESS_POOL_SHARDS: usize = 16;
+
+/// A section of the ingress pool. The artifacts are shared copy-on-write
+/// between a section and its clones, so cloning a section is cheap, and the
+/// first modification of a section after it was cloned copies the index of
+/// the artifacts, but not the artifacts themselves.
 #[derive(Clone)]
 struct IngressPoolSection<T: AsRef<IngressPoolObject>> {
-    artifacts: BTreeMap<IngressMessageId, T>,
+    artifacts: Arc<BTreeMap<IngressMessageId, Arc<T>>>,
+    /// The IDs of the artifacts by priority class and canister.
+    priority_index: Arc<PriorityIndex>,
     metrics: PoolMetrics,
 }
 
-impl<T: AsRef<IngressPoolObject>> IngressPoolSection<T> {
+/// The IDs of the messages by priority class, then by canister.
+type PriorityIndex = BTreeMap<u64, BTreeMap<CanisterId, BTreeSet<IngressMessageId>>>;
+
+/// Returns the priority class and the canister of the message, under which it
+/// is indexed.
+fn priority_key(artifact: &IngressPoolObject) -> (u64, CanisterId) {
+    let signed_ingress = &artifact.signed_ingress;
+    (signed_ingress.priority(), signed_ingress.canister_id())
+}
+
+fn remove_from_priority_index(
+    index: &mut PriorityIndex,
+    (priority, canister_id): (u64, CanisterId),
+    message_id: &IngressMessageId,
+) {
+    if let Some(canisters) = index.get_mut(&priority) {
+        if let Some(ids) = canisters.get_mut(&canister_id) {
+            ids.remove(message_id);
+            if ids.is_empty() {
+                canisters.remove(&canister_id);
+            }
+        }
+        if canisters.is_empty() {
+            index.remove(&priority);
+        }
+    }
+}
+
+/// Returns the range of the IDs of the messages expiring within the given
+/// times, inclusive.
+fn expiry_id_range(start: Time, end: Time) -> std::ops::RangeInclusive<IngressMessageId> {
+    let min_bytes = [0; EXPECTED_MESSAGE_ID_LENGTH];
+    let max_bytes = [0xff; EXPECTED_MESSAGE_ID_LENGTH];
+    std::ops::RangeInclusive::new(
+        IngressMessageId::new(start, MessageId::from(min_bytes)),
+        IngressMessageId::new(end, MessageId::from(max_bytes)),
+    )
+}
+
+/// Returns the artifact, cloning it if it is still shared with a snapshot.
+fn unwrap_or_clone<T: Clone>(artifact: Arc<T>) -> T {
+    Arc::try_unwrap(artifact).unwrap_or_else(|artifact| (*artifact).clone())
+}
+
+impl<T: AsRef<IngressPoolObject> + Clone> IngressPoolSection<T> {
     fn new(metrics: PoolMetrics) -> IngressPoolSection<T> {
         IngressPoolSection {
-            artifacts: BTreeMap::new(),
+            artifacts: Arc::new(BTreeMap::new()),
+            priority_index: Arc::new(BTreeMap::new()),
             metrics,
         }
     }
 
-    fn insert(&mut self, message_id: IngressMessageId, artifact: T) {
+    /// Inserts the given artifact, and returns the artifact it replaced, if
+    /// any.
+    fn insert(&mut self, message_id: IngressMessageId, artifact: Arc<T>) -> Option<Arc<T>> {
         let _timer = self
             .metrics
             .op_duration
             .with_label_values(&["insert"])
             .start_timer();
         self.metrics.observe_insert(artifact.as_ref().count_bytes());
-        if let Some(previous) = self.artifacts.insert(message_id, artifact) {
-            self.metrics.observe_remove(previous.as_ref().count_bytes());
-        }
-    }
-
-    fn remove(&mut self, message_id: &IngressMessageId) -> Option<T> {
-        let _timer = self
-            .metrics
-            .op_duration
-            .with_label_values(&["remove"])
-            .start_timer();
-        let removed = self.artifacts.remove(message_id);
-        if removed.is_some() {
+        let (priority, canister_id) = priority_key(artifact.as_ref());
+        Arc::make_mut(&mut self.priority_index)
+            .entry(priority)
+            .or_default()
+            .entry(canister_id)
+            .or_default()
+            .insert(message_id.clone());
+        let previous = Arc::make_mut(&mut self.artifacts).insert(message_id, artifact);
+        if let Some(previous) = &previous {
             self.metrics
-                .observe_remove(removed.as_ref().unwrap().as_ref().count_bytes());
+                .observe_remove((**previous).as_ref().count_bytes());
         }
-        removed
+        previous
     }
 
-    fn exists(&self, message_id: &IngressMessageId) -> bool {
+    fn remove(&mut self, message_id: &IngressMessageId) -> Option<Arc<T>> {
         let _timer = self
             .metrics
             .op_duration
-            .with_label_values(&["exists"])
+            .with_label_values(&["remove"])
             .start_timer();
-        self.artifacts.contains_key(message_id)
+        let removed = Arc::make_mut(&mut self.artifacts).remove(message_id)?;
+        remove_from_priority_index(
+            Arc::make_mut(&mut self.priority_index),
+            priority_key((*removed).as_ref()),
+            message_id,
+        );
+        self.metrics
+            .observe_remove((*removed).as_ref().count_bytes());
+        Some(removed)
     }
 
     // Purge below an expiry prefix (non-inclusive), and return the purged artifacts
     // as an iterator.
-    fn purge_below(&mut self, expiry: Time) -> Box<dyn Iterator<Item = T> + '_> {
+    fn purge_below(&mut self, expiry: Time) -> Box<dyn Iterator<Item = Arc<T>> + '_> {
         let _timer = self
             .metrics
             .op_duration
@@ -84,16 +148,63 @@ impl<T: AsRef<IngressPoolObject>> IngressPoolSection<T> {
             .start_timer();
         let zero_bytes = [0; EXPECTED_MESSAGE_ID_LENGTH];
         let key = IngressMessageId::new(expiry, MessageId::from(zero_bytes));
-        let mut to_remove = self.artifacts.split_off(&key);
-        std::mem::swap(&mut to_remove, &mut self.artifacts);
-        for artifact in to_remove.values() {
-            self.metrics.observe_remove(artifact.as_ref().count_bytes());
+        let artifacts = Arc::make_mut(&mut self.artifacts);
+        let mut to_remove = artifacts.split_off(&key);
+        std::mem::swap(&mut to_remove, artifacts);
+        let priority_index = Arc::make_mut(&mut self.priority_index);
+        for (message_id, artifact) in to_remove.iter() {
+            remove_from_priority_index(
+                priority_index,
+                priority_key((**artifact).as_ref()),
+                message_id,
+            );
+            self.metrics
+                .observe_remove((**artifact).as_ref().count_bytes());
         }
         Box::new(to_remove.into_iter().map(|(_, v)| v))
     }
+
+    /// Returns the artifacts in the given expiry range, the ones of higher
+    /// priority classes first. Within a class, the canisters take turns, so
+    /// that the messages to one canister cannot crowd out the messages to the
+    /// others, and the messages to a canister are ordered by expiry. The
+    /// artifacts are looked up lazily, as the caller consumes them.
+    fn get_all_by_priority(
+        &self,
+        range: std::ops::RangeInclusive<Time>,
+    ) -> impl Iterator<Item = &T> + '_ {
+        let (start, end) = range.into_inner();
+        let id_range = if end < start {
+            None
+        } else {
+            Some(expiry_id_range(start, end))
+        };
+        let mut classes = self.priority_index.values().rev();
+        let mut turns = VecDeque::new();
+        std::iter::from_fn(move || {
+            let id_range = id_range.as_ref()?;
+            loop {
+                match turns.pop_front() {
+                    Some(mut ids) => {
+                        if let Some(message_id) = ids.next() {
+                            turns.push_back(ids);
+                            return Some(self.artifacts[message_id].as_ref());
+                        }
+                    }
+                    None => {
+                        turns = classes
+                            .next()?
+                            .values()
+                            .map(|ids| ids.range(id_range.clone()))
+                            .collect();
+                    }
+                }
+            }
+        })
+    }
 }
 
-impl<T: AsRef<IngressPoolObject>> Default for IngressPoolSection<T> {
+impl<T: AsRef<IngressPoolObject> + Clone> Default for IngressPoolSection<T> {
     fn default() -> Self {
         Self::new(PoolMetrics::new(
             MetricsRegistry::new(),
@@ -105,7 +216,7 @@ impl<T: AsRef<IngressPoolObject>> Default for IngressPoolSection<T> {
 
 impl<T: AsRef<IngressPoolObject> + HasTimestamp> PoolSection<T> for IngressPoolSection<T> {
     fn get(&self, message_id: &IngressMessageId) -> Option<&T> {
-        self.artifacts.get(message_id)
+        self.artifacts.get(message_id).map(Arc::as_ref)
     }
 
     fn get_all_by_expiry_range<'a>(
@@ -116,14 +227,12 @@ impl<T: AsRef<IngressPoolObject> + HasTimestamp> PoolSection<T> for IngressPoolS
         if end < start {
             return Box::new(std::iter::empty());
         }
-        let min_bytes = [0; EXPECTED_MESSAGE_ID_LENGTH];
-        let max_bytes = [0xff; EXPECTED_MESSAGE_ID_LENGTH];
-        let range = std::ops::RangeInclusive::new(
-            IngressMessageId::new(start, MessageId::from(min_bytes)),
-            IngressMessageId::new(end, MessageId::from(max_bytes)),
-        );
         let artifacts = &self.artifacts;
-        Box::new(artifacts.range(range).map(|(_, v)| v))
+        Box::new(
+            artifacts
+                .range(expiry_id_range(start, end))
+                .map(|(_, v)| v.as_ref()),
+        )
     }
 
     fn get_timestamp(&self, message_id: &IngressMessageId) -> Option<Time> {
@@ -135,18 +244,285 @@ impl<T: AsRef<IngressPoolObject> + HasTimestamp> PoolSection<T> for IngressPoolS
     }
 }
 
+/// The number and total size of the messages to a canister.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CanisterUsage {
+    pub count: usize,
+    pub size_bytes: usize,
+}
+
+/// The bookkeeping of the ingress messages in one shard of the pool. The
+/// validated messages are shared with the validated section of the pool, so
+/// that peers can fetch them without the lock of the pool.
 #[derive(Clone)]
-pub struct IngressPoolImpl {
-    validated: IngressPoolSection<ValidatedIngressArtifact>,
-    unvalidated: IngressPoolSection<UnvalidatedIngressArtifact>,
+struct IngressPoolShard {
+    unvalidated: BTreeSet<IngressMessageId>,
+    validated: BTreeMap<IngressMessageId, Arc<ValidatedIngressArtifact>>,
     // Track unvalidated pool quota usage only
     peer_index: PeerIndex,
+    // Track the messages to each canister in both sections
+    canister_usage: BTreeMap<CanisterId, CanisterUsage>,
+}
+
+impl IngressPoolShard {
+    fn add_canister_usage(&mut self, canister_id: CanisterId, size: usize) {
+        let usage = self.canister_usage.entry(canister_id).or_default();
+        usage.count += 1;
+        usage.size_bytes += size;
+    }
+
+    fn remove_canister_usage(&mut self, canister_id: &CanisterId, size: usize) {
+        if let Some(usage) = self.canister_usage.get_mut(canister_id) {
+            usage.count -= 1;
+            usage.size_bytes -= size;
+            if usage.count == 0 {
+                self.canister_usage.remove(canister_id);
+            }
+        }
+    }
+}
+
+/// IngressPoolShards keeps track of which messages are in the ingress pool,
+/// of the validated messages, and of the unvalidated quota used by each peer.
+/// The messages are split into shards by their hash, and every shard has its
+/// own lock, so that the HTTP ingress path and the peers fetching validated
+/// messages can query the pool without contending on the lock of the whole
+/// pool with the ingress processor.
+///
+/// Cloning `IngressPoolShards` results in a handle to the same shards.
+#[derive(Clone)]
+pub struct IngressPoolShards {
+    shards: Arc<Vec<RwLock<IngressPoolShard>>>,
+    max_quota_per_peer: usize,
     ingress_pool_size_threshold: Option<usize>,
+    canister_quota: Option<IngressCanisterQuota>,
     ingress_messages_throttled: IntCounter,
+    ingress_messages_over_canister_quota: IntCounter,
+}
+
+impl IngressPoolShards {
+    fn new(
+        max_quota_per_peer: usize,
+        ingress_pool_size_threshold: Option<usize>,
+        canister_quota: Option<IngressCanisterQuota>,
+        ingress_messages_throttled: IntCounter,
+        ingress_messages_over_canister_quota: IntCounter,
+    ) -> Self {
+        let shards = (0..INGRESS_POOL_SHARDS)
+            .map(|_| {
+                RwLock::new(IngressPoolShard {
+                    unvalidated: BTreeSet::new(),
+                    validated: BTreeMap::new(),
+                    peer_index: PeerIndex::new(max_quota_per_peer),
+                    canister_usage: BTreeMap::new(),
+                })
+            })
+            .collect();
+        Self {
+            shards: Arc::new(shards),
+            max_quota_per_peer,
+            ingress_pool_size_threshold,
+            canister_quota,
+            ingress_messages_throttled,
+            ingress_messages_over_canister_quota,
+        }
+    }
+
+    /// Returns a copy of the shards that is independent of these.
+    fn deep_clone(&self) -> Self {
+        let shards = self
+            .shards
+            .iter()
+            .map(|shard| RwLock::new(shard.read().unwrap().clone()))
+            .collect();
+        Self {
+            shards: Arc::new(shards),
+            max_quota_per_peer: self.max_quota_per_peer,
+            ingress_pool_size_threshold: self.ingress_pool_size_threshold,
+            canister_quota: self.canister_quota,
+            ingress_messages_throttled: self.ingress_messages_throttled.clone(),
+            ingress_messages_over_canister_quota: self.ingress_messages_over_canister_quota.clone(),
+        }
+    }
+
+    fn shard(&self, message_id: &IngressMessageId) -> &RwLock<IngressPoolShard> {
+        let bytes = message_id.message_id.as_bytes();
+        &self.shards[bytes[0] as usize % self.shards.len()]
+    }
+
+    fn insert_unvalidated(
+        &self,
+        message_id: IngressMessageId,
+        peer_id: NodeId,
+        canister_id: CanisterId,
+        size: usize,
+    ) {
+        let mut shard = self.shard(&message_id).write().unwrap();
+        shard.peer_index.insert(peer_id, size);
+        if shard.unvalidated.insert(message_id) {
+            shard.add_canister_usage(canister_id, size);
+        }
+    }
+
+    fn remove_unvalidated(
+        &self,
+        message_id: &IngressMessageId,
+        peer_id: NodeId,
+        canister_id: &CanisterId,
+        size: usize,
+    ) {
+        let mut shard = self.shard(message_id).write().unwrap();
+        shard.peer_index.remove(peer_id, size);
+        if shard.unvalidated.remove(message_id) {
+            shard.remove_canister_usage(canister_id, size);
+        }
+    }
+
+    fn insert_validated(
+        &self,
+        message_id: IngressMessageId,
+        artifact: Arc<ValidatedIngressArtifact>,
+    ) {
+        let canister_id = artifact.msg.signed_ingress.canister_id();
+        let size = artifact.msg.count_bytes();
+        let mut shard = self.shard(&message_id).write().unwrap();
+        if shard.validated.insert(message_id, artifact).is_none() {
+            shard.add_canister_usage(canister_id, size);
+        }
+    }
+
+    fn remove_validated(&self, message_id: &IngressMessageId) {
+        let mut shard = self.shard(message_id).write().unwrap();
+        if let Some(artifact) = shard.validated.remove(message_id) {
+            shard.remove_canister_usage(
+                &artifact.msg.signed_ingress.canister_id(),
+                artifact.msg.count_bytes(),
+            );
+        }
+    }
+
+    /// Returns true if the pool contains the message with the given ID.
+    pub fn contains(&self, message_id: &IngressMessageId) -> bool {
+        let shard = self.shard(message_id).read().unwrap();
+        shard.unvalidated.contains(message_id) || shard.validated.contains_key(message_id)
+    }
+
+    /// Returns the validated message with the given ID, if any.
+    pub fn get_validated(&self, message_id: &IngressMessageId) -> Option<SignedIngress> {
+        let shard = self.shard(message_id).read().unwrap();
+        shard
+            .validated
+            .get(message_id)
+            .map(|artifact| artifact.msg.signed_ingress.clone())
+    }
+
+    /// Checks that the given message from the given peer fits in the
+    /// remaining quota of the peer and of the canister of the message.
+    pub fn check_quota(
+        &self,
+        message: &SignedIngress,
+        peer_id: &NodeId,
+    ) -> Result<(), ArtifactPoolError> {
+        if self.remaining_quota(peer_id) < message.count_bytes() {
+            return Err(ArtifactPoolError::InsufficientQuotaError);
+        }
+        if self.exceeds_canister_quota(&message.canister_id(), message.count_bytes()) {
+            return Err(ArtifactPoolError::InsufficientQuotaError);
+        }
+        Ok(())
+    }
+
+    /// Returns the remaining unvalidated quota of the given peer, in bytes.
+    pub fn remaining_quota(&self, peer_id: &NodeId) -> usize {
+        let quota_used: usize = self
+            .shards
+            .iter()
+            .map(|shard| shard.read().unwrap().peer_index.get_quota_used(peer_id))
+            .sum();
+        self.max_quota_per_peer.saturating_sub(quota_used)
+    }
+
+    /// Returns the number and total size of the messages to the given
+    /// canister in the pool.
+    pub fn canister_usage(&self, canister_id: &CanisterId) -> CanisterUsage {
+        let mut total = CanisterUsage::default();
+        for shard in self.shards.iter() {
+            if let Some(usage) = shard.read().unwrap().canister_usage.get(canister_id) {
+                total.count += usage.count;
+                total.size_bytes += usage.size_bytes;
+            }
+        }
+        total
+    }
+
+    /// Returns the number of messages in the pool.
+    pub fn size(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| {
+                let shard = shard.read().unwrap();
+                shard.unvalidated.len() + shard.validated.len()
+            })
+            .sum()
+    }
+}
+
+impl IngressPoolLookup for IngressPoolShards {
+    fn contains(&self, message_id: &IngressMessageId) -> bool {
+        IngressPoolShards::contains(self, message_id)
+    }
+
+    fn get_validated_by_identifier(&self, message_id: &IngressMessageId) -> Option<SignedIngress> {
+        self.get_validated(message_id)
+    }
+
+    fn check_quota(
+        &self,
+        message: &SignedIngress,
+        peer_id: &NodeId,
+    ) -> Result<(), ArtifactPoolError> {
+        IngressPoolShards::check_quota(self, message, peer_id)
+    }
+}
+
+impl IngressPoolThrottler for IngressPoolShards {
+    fn exceeds_threshold(&self) -> bool {
+        let mut exceeds = false;
+        if let Some(threshold) = self.ingress_pool_size_threshold {
+            if self.size() >= threshold {
+                self.ingress_messages_throttled.inc();
+                exceeds = true
+            }
+        }
+        exceeds
+    }
+
+    fn exceeds_canister_quota(&self, canister_id: &CanisterId, size_bytes: usize) -> bool {
+        let quota = match self.canister_quota {
+            Some(quota) => quota,
+            None => return false,
+        };
+        let usage = self.canister_usage(canister_id);
+        let exceeds = usage.count >= quota.max_count
+            || usage.size_bytes.saturating_add(size_bytes) > quota.max_size_bytes;
+        if exceeds {
+            self.ingress_messages_over_canister_quota.inc();
+        }
+        exceeds
+    }
+}
+
+pub struct IngressPoolImpl {
+    validated: IngressPoolSection<ValidatedIngressArtifact>,
+    unvalidated: IngressPoolSection<UnvalidatedIngressArtifact>,
+    shards: IngressPoolShards,
+    artifact_metrics: PoolArtifactMetrics,
     log: ReplicaLogger,
 }
 
-const POOL_INGRESS: &str = "ingress";
+/// The name of the pool in metrics and dumps.
+pub const POOL_INGRESS: &str = "ingress";
+const ARTIFACT_TYPE_SIGNED_INGRESS: &str = "signed_ingress";
 
 impl IngressPoolImpl {
     pub fn new(
@@ -155,10 +531,19 @@ impl IngressPoolImpl {
         log: ReplicaLogger,
     ) -> IngressPoolImpl {
         IngressPoolImpl {
-            ingress_pool_size_threshold: config.ingress_pool_size_threshold,
-            ingress_messages_throttled: metrics_registry.int_counter(
-                "ingress_messages_throttled",
-                "Number of throttled ingress messages",
+            artifact_metrics: PoolArtifactMetrics::new(&metrics_registry, POOL_INGRESS),
+            shards: IngressPoolShards::new(
+                config.ingress_pool_unvalidated_capacity_per_peer,
+                config.ingress_pool_size_threshold,
+                config.ingress_pool_canister_quota,
+                metrics_registry.int_counter(
+                    "ingress_messages_throttled",
+                    "Number of throttled ingress messages",
+                ),
+                metrics_registry.int_counter(
+                    "ingress_messages_over_canister_quota",
+                    "Number of ingress messages rejected because their canister exceeded its quota",
+                ),
             ),
             validated: IngressPoolSection::new(PoolMetrics::new(
                 metrics_registry.clone(),
@@ -170,21 +555,87 @@ impl IngressPoolImpl {
                 POOL_INGRESS,
                 POOL_TYPE_UNVALIDATED,
             )),
-            peer_index: PeerIndex::new(config.ingress_pool_unvalidated_capacity_per_peer),
             log,
         }
     }
 
+    /// Returns a snapshot of the pool. Taking a snapshot is cheap, and the
+    /// snapshot is not affected by later changes to the pool, so it can be
+    /// read without holding the lock of the pool.
+    pub fn snapshot(&self) -> IngressPoolSnapshot {
+        IngressPoolSnapshot {
+            validated: self.validated.clone(),
+            unvalidated: self.unvalidated.clone(),
+        }
+    }
+
+    /// Dumps the validated section of the pool to the given file, and
+    /// returns the number of dumped artifacts.
+    pub fn dump(&self, path: &Path, options: &DumpOptions) -> std::io::Result<usize> {
+        self.pool_dump(options)?.write_to(path)
+    }
+
+    /// Returns the dump of the validated section of the pool. Ingress
+    /// messages carry their signatures in their envelope, so only the full
+    /// artifacts contain them.
+    pub fn pool_dump(&self, options: &DumpOptions) -> std::io::Result<PoolDump> {
+        let mut artifacts = Vec::new();
+        for artifact in self.validated.artifacts.values() {
+            let msg = &artifact.msg;
+            artifacts.push(DumpedArtifact::new(
+                ARTIFACT_TYPE_SIGNED_INGRESS,
+                None,
+                msg.message_id.as_bytes(),
+                &msg.signed_ingress,
+                options,
+            )?);
+        }
+        Ok(PoolDump::new(POOL_INGRESS, artifacts))
+    }
+
+    /// Returns a handle to the sharded bookkeeping of the pool, which can be
+    /// queried without holding the lock of the pool.
+    pub fn shards(&self) -> IngressPoolShards {
+        self.shards.clone()
+    }
+
+    /// Inserts an artifact into the validated pool.
+    fn insert_validated(
+        &mut self,
+        message_id: IngressMessageId,
+        artifact: ValidatedIngressArtifact,
+    ) {
+        let artifact = Arc::new(artifact);
+        self.shards
+            .insert_validated(message_id.clone(), Arc::clone(&artifact));
+        let size = artifact.msg.count_bytes();
+        if self.validated.insert(message_id, artifact).is_none() {
+            self.artifact_metrics.observe_insert(
+                POOL_TYPE_VALIDATED,
+                ARTIFACT_TYPE_SIGNED_INGRESS,
+                size,
+            );
+        }
+    }
+
     /// Remove an artifact from unvalidated pool and remove it from peer_index
     /// Return the removed artifact and its size.
     fn remove_unvalidated(
         &mut self,
         message_id: &IngressMessageId,
-    ) -> Option<(UnvalidatedIngressArtifact, usize)> {
+    ) -> Option<(Arc<UnvalidatedIngressArtifact>, usize)> {
         match self.unvalidated.remove(message_id) {
             Some(unvalidated_artifact) => {
-                let size = unvalidated_artifact.message.signed_ingress.count_bytes();
-                self.peer_index.remove(unvalidated_artifact.peer_id, size);
+                let ingress = &unvalidated_artifact.message.signed_ingress;
+                let size = ingress.count_bytes();
+                self.shards.remove_unvalidated(
+                    message_id,
+                    unvalidated_artifact.peer_id,
+                    &ingress.canister_id(),
+                    size,
+                );
+                self.artifact_metrics
+                    .observe_remove(POOL_TYPE_UNVALIDATED, ARTIFACT_TYPE_SIGNED_INGRESS);
                 Some((unvalidated_artifact, size))
             }
             None => {
@@ -195,6 +646,18 @@ impl IngressPoolImpl {
     }
 }
 
+impl Clone for IngressPoolImpl {
+    fn clone(&self) -> Self {
+        Self {
+            validated: self.validated.clone(),
+            unvalidated: self.unvalidated.clone(),
+            shards: self.shards.deep_clone(),
+            artifact_metrics: self.artifact_metrics.clone(),
+            log: self.log.clone(),
+        }
+    }
+}
+
 impl IngressPool for IngressPoolImpl {
     /// Validated Ingress Pool Section
     fn validated(&self) -> &dyn PoolSection<ValidatedIngressArtifact> {
@@ -213,24 +676,54 @@ impl MutableIngressPool for IngressPoolImpl {
     fn insert(&mut self, artifact: UnvalidatedArtifact<SignedIngress>) {
         let ingress_pool_obj = IngressPoolObject::from(artifact.message);
         let peer_id = artifact.peer_id;
+        // Messages received by gossip are assigned their correlation ID here.
+        let span = tracing::debug_span!(
+            "ingress_pool_insert",
+            message_id = %ingress_pool_obj.message_id,
+            correlation_id = %correlation_id(&ingress_pool_obj.message_id),
+            peer_id = %peer_id,
+        );
+        let _enter = span.enter();
         let timestamp = artifact.timestamp;
         let size = ingress_pool_obj.count_bytes();
+        let message_id = IngressMessageId::from(&ingress_pool_obj);
+        let canister_id = ingress_pool_obj.signed_ingress.canister_id();
 
-        self.peer_index.insert(peer_id, size);
+        if !self.shards.contains(&message_id)
+            && self.shards.exceeds_canister_quota(&canister_id, size)
+        {
+            debug!(
+                self.log,
+                "ingress_message_over_canister_quota";
+                ingress_message.message_id => format!("{}", ingress_pool_obj.message_id)
+            );
+            return;
+        }
+        self.shards
+            .insert_unvalidated(message_id.clone(), peer_id, canister_id, size);
         debug!(
             self.log,
             "ingress_message_insert_unvalidated";
             ingress_message.message_id => format!("{}", ingress_pool_obj.message_id)
         );
 
-        self.unvalidated.insert(
-            IngressMessageId::from(&ingress_pool_obj),
-            UnvalidatedIngressArtifact {
+        let previous = self.unvalidated.insert(
+            message_id,
+            Arc::new(UnvalidatedIngressArtifact {
                 message: ingress_pool_obj,
                 peer_id,
                 timestamp,
-            },
+            }),
         );
+        // Messages received again, e.g. from another peer, replace the
+        // message in the section and are not counted twice.
+        if previous.is_none() {
+            self.artifact_metrics.observe_insert(
+                POOL_TYPE_UNVALIDATED,
+                ARTIFACT_TYPE_SIGNED_INGRESS,
+                size,
+            );
+        }
         debug!(
             self.log,
             "Ingress pool: insert {} bytes into unvalidated", size
@@ -246,7 +739,13 @@ impl MutableIngressPool for IngressPoolImpl {
                     // to the validated pool
                     match self.remove_unvalidated(&message_id) {
                         Some((unvalidated_artifact, size)) => {
-                            self.validated.insert(
+                            let unvalidated_artifact = unwrap_or_clone(unvalidated_artifact);
+                            self.artifact_metrics.observe_validation(
+                                ARTIFACT_TYPE_SIGNED_INGRESS,
+                                unvalidated_artifact.timestamp,
+                                current_time(),
+                            );
+                            self.insert_validated(
                                 message_id,
                                 ValidatedIngressArtifact {
                                     msg: unvalidated_artifact.message,
@@ -284,9 +783,12 @@ impl MutableIngressPool for IngressPoolImpl {
                     }
                 }
                 ChangeAction::RemoveFromValidated(message_id) => {
+                    self.shards.remove_validated(&message_id);
                     match self.validated.remove(&message_id) {
                         Some(artifact) => {
                             let size = artifact.msg.signed_ingress.count_bytes();
+                            self.artifact_metrics
+                                .observe_remove(POOL_TYPE_VALIDATED, ARTIFACT_TYPE_SIGNED_INGRESS);
                             debug!(
                                 self.log,
                                 "Ingress pool: remove {} bytes from validated", size
@@ -302,10 +804,31 @@ impl MutableIngressPool for IngressPoolImpl {
                     }
                 }
                 ChangeAction::PurgeBelowExpiry(expiry) => {
-                    let _unused = self.validated.purge_below(expiry);
+                    let now = current_time();
+                    for artifact in self.validated.purge_below(expiry) {
+                        self.shards
+                            .remove_validated(&IngressMessageId::from(&artifact.msg));
+                        self.artifact_metrics.observe_purge(
+                            POOL_TYPE_VALIDATED,
+                            ARTIFACT_TYPE_SIGNED_INGRESS,
+                            artifact.timestamp,
+                            now,
+                        );
+                    }
                     for artifact in self.unvalidated.purge_below(expiry) {
+                        self.artifact_metrics.observe_purge(
+                            POOL_TYPE_UNVALIDATED,
+                            ARTIFACT_TYPE_SIGNED_INGRESS,
+                            artifact.timestamp,
+                            now,
+                        );
                         let size = artifact.message.signed_ingress.count_bytes();
-                        self.peer_index.remove(artifact.peer_id, size);
+                        self.shards.remove_unvalidated(
+                            &IngressMessageId::from(&artifact.message),
+                            artifact.peer_id,
+                            &artifact.message.signed_ingress.canister_id(),
+                            size,
+                        );
                     }
                 }
             }
@@ -322,19 +845,16 @@ impl GossipPool<SignedIngress, ChangeSet> for IngressPoolImpl {
         message: &SignedIngress,
         peer_id: &NodeId,
     ) -> Result<(), ArtifactPoolError> {
-        if self.peer_index.get_remaining_quota(peer_id) < message.count_bytes() {
-            return Err(ArtifactPoolError::InsufficientQuotaError);
-        }
-        Ok(())
+        self.shards.check_quota(message, peer_id)
     }
 
     /// Check if an Ingress message exists by its hash
     fn contains(&self, id: &IngressMessageId) -> bool {
-        self.unvalidated.exists(id) || self.validated.exists(id)
+        self.shards.contains(id)
     }
 
     fn get_validated_by_identifier(&self, id: &IngressMessageId) -> Option<SignedIngress> {
-        self.validated.get(id).map(|a| a.msg.signed_ingress.clone())
+        self.shards.get_validated(id)
     }
 
     fn get_all_validated_by_filter<'a>(
@@ -358,34 +878,71 @@ impl IngressPoolSelect for IngressPoolImpl {
     fn select_validated<'a>(
         &self,
         range: std::ops::RangeInclusive<Time>,
-        mut f: Box<dyn FnMut(&IngressPoolObject) -> SelectResult<SignedIngress> + 'a>,
+        f: Box<dyn FnMut(&IngressPoolObject) -> SelectResult<SignedIngress> + 'a>,
     ) -> Vec<SignedIngress> {
-        let mut collected = Vec::new();
-        self.validated()
-            .get_all_by_expiry_range(range)
-            .try_for_each(|x| match f(&x.msg) {
-                SelectResult::Selected(msg) => {
-                    collected.push(msg);
-                    Some(())
-                }
-                SelectResult::Skip => Some(()),
-                SelectResult::Abort => None,
-            });
-        collected
+        select_validated(&self.validated, range, f)
+    }
+}
+
+impl SnapshotIngressPool for IngressPoolImpl {
+    fn snapshot(&self) -> Box<dyn IngressPoolSelect + Send + Sync> {
+        Box::new(IngressPoolImpl::snapshot(self))
+    }
+}
+
+/// A consistent view of the ingress pool at the time the snapshot was taken.
+#[derive(Clone)]
+pub struct IngressPoolSnapshot {
+    validated: IngressPoolSection<ValidatedIngressArtifact>,
+    unvalidated: IngressPoolSection<UnvalidatedIngressArtifact>,
+}
+
+impl IngressPool for IngressPoolSnapshot {
+    fn validated(&self) -> &dyn PoolSection<ValidatedIngressArtifact> {
+        &self.validated
+    }
+
+    fn unvalidated(&self) -> &dyn PoolSection<UnvalidatedIngressArtifact> {
+        &self.unvalidated
+    }
+}
+
+impl IngressPoolSelect for IngressPoolSnapshot {
+    fn select_validated<'a>(
+        &self,
+        range: std::ops::RangeInclusive<Time>,
+        f: Box<dyn FnMut(&IngressPoolObject) -> SelectResult<SignedIngress> + 'a>,
+    ) -> Vec<SignedIngress> {
+        select_validated(&self.validated, range, f)
     }
 }
 
+fn select_validated<'a>(
+    validated: &IngressPoolSection<ValidatedIngressArtifact>,
+    range: std::ops::RangeInclusive<Time>,
+    mut f: Box<dyn FnMut(&IngressPoolObject) -> SelectResult<SignedIngress> + 'a>,
+) -> Vec<SignedIngress> {
+    let mut collected = Vec::new();
+    validated
+        .get_all_by_priority(range)
+        .try_for_each(|x| match f(&x.msg) {
+            SelectResult::Selected(msg) => {
+                collected.push(msg);
+                Some(())
+            }
+            SelectResult::Skip => Some(()),
+            SelectResult::Abort => None,
+        });
+    collected
+}
+
 impl IngressPoolThrottler for IngressPoolImpl {
     fn exceeds_threshold(&self) -> bool {
-        let mut exceeds = false;
-        if let Some(threshold) = self.ingress_pool_size_threshold {
-            let total = self.validated.size() + self.unvalidated.size();
-            if total >= threshold {
-                self.ingress_messages_throttled.inc();
-                exceeds = true
-            }
-        }
-        exceeds
+        self.shards.exceeds_threshold()
+    }
+
+    fn exceeds_canister_quota(&self, canister_id: &CanisterId, size_bytes: usize) -> bool {
+        self.shards.exceeds_canister_quota(canister_id, size_bytes)
     }
 }
 
@@ -394,10 +951,14 @@ mod tests {
     use super::*;
     use ic_interfaces::time_source::TimeSource;
     use ic_test_utilities::{
-        mock_time, types::ids::node_test_id, types::messages::SignedIngressBuilder,
+        mock_time,
+        types::ids::{canister_test_id, node_test_id},
+        types::messages::SignedIngressBuilder,
         with_test_replica_logger, FastForwardTimeSource,
     };
-    use ic_types::{artifact::IngressMessageAttribute, ingress::MAX_INGRESS_TTL};
+    use ic_types::{
+        artifact::IngressMessageAttribute, ingress::MAX_INGRESS_TTL, messages::MAX_INGRESS_PRIORITY,
+    };
     use rand::Rng;
     use std::time::Duration;
 
@@ -410,16 +971,88 @@ mod tests {
 
             ingress_pool.insert(
                 message_id,
-                UnvalidatedIngressArtifact {
+                Arc::new(UnvalidatedIngressArtifact {
                     message: IngressPoolObject::from(ingress_msg),
                     peer_id: node_test_id(0),
                     timestamp: mock_time(),
-                },
+                }),
             );
             assert_eq!(ingress_pool.size(), 1);
         });
     }
 
+    #[test]
+    fn test_get_all_by_priority() {
+        let mut ingress_pool = IngressPoolSection::default();
+        // The class of the last message is capped at the highest class.
+        let messages: Vec<_> = vec![
+            (0, 0, 1),
+            (0, 0, 2),
+            (1, 0, 3),
+            (2, MAX_INGRESS_PRIORITY, 4),
+            (3, u64::MAX, 5),
+        ]
+        .into_iter()
+        .map(|(canister, priority, expiry)| {
+            SignedIngressBuilder::new()
+                .canister_id(canister_test_id(canister))
+                .priority(priority)
+                .expiry_time(mock_time() + Duration::from_secs(expiry))
+                .build()
+        })
+        .collect();
+        for message in messages.iter() {
+            ingress_pool.insert(
+                IngressMessageId::from(message),
+                Arc::new(UnvalidatedIngressArtifact {
+                    message: IngressPoolObject::from(message.clone()),
+                    peer_id: node_test_id(0),
+                    timestamp: mock_time(),
+                }),
+            );
+        }
+        let selected = |ingress_pool: &IngressPoolSection<UnvalidatedIngressArtifact>, range| {
+            ingress_pool
+                .get_all_by_priority(range)
+                .map(|artifact| artifact.message.signed_ingress.clone())
+                .collect::<Vec<_>>()
+        };
+
+        // The higher class comes first, then the canisters take turns.
+        let range = mock_time()..=mock_time() + MAX_INGRESS_TTL;
+        assert_eq!(
+            selected(&ingress_pool, range.clone()),
+            vec![
+                messages[3].clone(),
+                messages[4].clone(),
+                messages[0].clone(),
+                messages[2].clone(),
+                messages[1].clone()
+            ]
+        );
+        assert_eq!(ingress_pool.priority_index.len(), 2);
+        // Only the messages in the expiry range are returned.
+        assert_eq!(
+            selected(
+                &ingress_pool,
+                mock_time() + Duration::from_secs(2)..=mock_time() + Duration::from_secs(3)
+            ),
+            vec![messages[2].clone(), messages[1].clone()]
+        );
+
+        // Removed and purged messages are dropped from the index.
+        ingress_pool.remove(&IngressMessageId::from(&messages[3]));
+        ingress_pool.remove(&IngressMessageId::from(&messages[4]));
+        ingress_pool
+            .purge_below(mock_time() + Duration::from_secs(2))
+            .for_each(drop);
+        assert_eq!(
+            selected(&ingress_pool, range),
+            vec![messages[2].clone(), messages[1].clone()]
+        );
+        assert_eq!(ingress_pool.priority_index.len(), 1);
+    }
+
     #[test]
     fn test_exists() {
         with_test_replica_logger(|log| {
@@ -436,7 +1069,7 @@ mod tests {
                 let ingress_msg = SignedIngressBuilder::new().nonce(2).build();
                 let message_id = IngressMessageId::from(&ingress_msg);
 
-                ingress_pool.validated.insert(
+                ingress_pool.insert_validated(
                     message_id.clone(),
                     ValidatedIngressArtifact {
                         msg: IngressPoolObject::from(ingress_msg),
@@ -464,7 +1097,7 @@ mod tests {
                 let ingress_msg = SignedIngressBuilder::new().nonce(2).build();
                 let message_id = IngressMessageId::from(&ingress_msg);
 
-                ingress_pool.validated.insert(
+                ingress_pool.insert_validated(
                     message_id,
                     ValidatedIngressArtifact {
                         msg: IngressPoolObject::from(ingress_msg),
@@ -506,7 +1139,7 @@ mod tests {
                         msgs_in_range.push(ingress_msg.clone())
                     }
                     let message_id = IngressMessageId::from(&ingress_msg);
-                    ingress_pool.validated.insert(
+                    ingress_pool.insert_validated(
                         message_id.clone(),
                         ValidatedIngressArtifact {
                             msg: IngressPoolObject::from(ingress_msg),
@@ -674,7 +1307,7 @@ mod tests {
 
                 let ingress_msg = SignedIngressBuilder::new().nonce(3).build();
                 let message_id = IngressMessageId::from(&ingress_msg);
-                ingress_pool.validated.insert(
+                ingress_pool.insert_validated(
                     message_id,
                     ValidatedIngressArtifact {
                         msg: IngressPoolObject::from(ingress_msg),
@@ -702,6 +1335,234 @@ mod tests {
         })
     }
 
+    #[test]
+    fn test_canister_quota() {
+        with_test_replica_logger(|log| {
+            ic_test_utilities::artifact_pool_config::with_test_pool_config(|mut pool_config| {
+                pool_config.ingress_pool_canister_quota = Some(IngressCanisterQuota {
+                    max_count: 2,
+                    max_size_bytes: usize::MAX,
+                });
+                let time_source = FastForwardTimeSource::new();
+                let metrics_registry = MetricsRegistry::new();
+                let mut ingress_pool = IngressPoolImpl::new(pool_config, metrics_registry, log);
+                let canister_1 = canister_test_id(1);
+                let canister_2 = canister_test_id(2);
+
+                let mut message_ids = Vec::new();
+                let mut attributes = Vec::new();
+                for nonce in 0..3 {
+                    let ingress_msg = SignedIngressBuilder::new()
+                        .canister_id(canister_1)
+                        .nonce(nonce)
+                        .build();
+                    message_ids.push(IngressMessageId::from(&ingress_msg));
+                    attributes.push(IngressMessageAttribute::new(&ingress_msg));
+                    ingress_pool.insert(UnvalidatedArtifact {
+                        message: ingress_msg,
+                        peer_id: node_test_id(0),
+                        timestamp: time_source.get_relative_time(),
+                    });
+                }
+                // The third message to the first canister exceeds its quota.
+                assert_eq!(ingress_pool.unvalidated().size(), 2);
+                assert!(ingress_pool.unvalidated().get(&message_ids[2]).is_none());
+                assert!(ingress_pool.exceeds_canister_quota(&canister_1, 0));
+                assert_eq!(ingress_pool.shards().canister_usage(&canister_1).count, 2);
+
+                // Other canisters are not affected.
+                assert!(!ingress_pool.exceeds_canister_quota(&canister_2, 0));
+                let ingress_msg = SignedIngressBuilder::new()
+                    .canister_id(canister_2)
+                    .nonce(0)
+                    .build();
+                assert!(ingress_pool
+                    .check_quota(&ingress_msg, &node_test_id(0))
+                    .is_ok());
+
+                // Validating a message does not change the usage, removing it
+                // frees the quota.
+                ingress_pool.apply_changeset(vec![ChangeAction::MoveToValidated((
+                    message_ids[0].clone(),
+                    0,
+                    attributes[0].clone(),
+                    ic_types::crypto::CryptoHash(vec![]),
+                ))]);
+                assert!(ingress_pool.exceeds_canister_quota(&canister_1, 0));
+                ingress_pool.apply_changeset(vec![ChangeAction::RemoveFromValidated(
+                    message_ids[0].clone(),
+                )]);
+                assert!(!ingress_pool.exceeds_canister_quota(&canister_1, 0));
+                assert_eq!(ingress_pool.shards().canister_usage(&canister_1).count, 1);
+            })
+        })
+    }
+
+    #[test]
+    fn test_artifact_metrics() {
+        with_test_replica_logger(|log| {
+            ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
+                let metrics_registry = MetricsRegistry::new();
+                let mut ingress_pool = IngressPoolImpl::new(pool_config, metrics_registry, log);
+                let artifacts = |pool: &IngressPoolImpl, pool_type| {
+                    pool.artifact_metrics
+                        .artifacts(pool_type, ARTIFACT_TYPE_SIGNED_INGRESS)
+                };
+                let ingress_msgs: Vec<_> = (0..2)
+                    .map(|i| {
+                        SignedIngressBuilder::new()
+                            .nonce(i)
+                            .expiry_time(mock_time() + Duration::from_secs(60 * (i + 1)))
+                            .build()
+                    })
+                    .collect();
+                for ingress_msg in ingress_msgs.iter() {
+                    // The same message received from two peers is counted once.
+                    for peer_id in 0..2 {
+                        ingress_pool.insert(UnvalidatedArtifact {
+                            message: ingress_msg.clone(),
+                            peer_id: node_test_id(peer_id),
+                            timestamp: mock_time(),
+                        });
+                    }
+                }
+                assert_eq!(artifacts(&ingress_pool, POOL_TYPE_UNVALIDATED), 2);
+
+                ingress_pool.apply_changeset(vec![ChangeAction::MoveToValidated((
+                    IngressMessageId::from(&ingress_msgs[0]),
+                    0,
+                    IngressMessageAttribute::new(&ingress_msgs[0]),
+                    ic_types::crypto::CryptoHash(vec![]),
+                ))]);
+                assert_eq!(artifacts(&ingress_pool, POOL_TYPE_UNVALIDATED), 1);
+                assert_eq!(artifacts(&ingress_pool, POOL_TYPE_VALIDATED), 1);
+
+                ingress_pool.apply_changeset(vec![ChangeAction::PurgeBelowExpiry(
+                    mock_time() + Duration::from_secs(180),
+                )]);
+                assert_eq!(artifacts(&ingress_pool, POOL_TYPE_UNVALIDATED), 0);
+                assert_eq!(artifacts(&ingress_pool, POOL_TYPE_VALIDATED), 0);
+            })
+        })
+    }
+
+    #[test]
+    fn test_snapshot_is_not_affected_by_changes() {
+        with_test_replica_logger(|log| {
+            ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
+                let time_source = FastForwardTimeSource::new();
+                let metrics_registry = MetricsRegistry::new();
+                let mut ingress_pool = IngressPoolImpl::new(pool_config, metrics_registry, log);
+                let ingress_msg_0 = SignedIngressBuilder::new()
+                    .nonce(0)
+                    .expiry_time(mock_time() + Duration::from_secs(60))
+                    .build();
+                let message_id_0 = IngressMessageId::from(&ingress_msg_0);
+                ingress_pool.insert_validated(
+                    message_id_0.clone(),
+                    ValidatedIngressArtifact {
+                        msg: IngressPoolObject::from(ingress_msg_0),
+                        timestamp: mock_time(),
+                    },
+                );
+
+                let snapshot = ingress_pool.snapshot();
+                let ingress_msg_1 = SignedIngressBuilder::new().nonce(1).build();
+                ingress_pool.insert(UnvalidatedArtifact {
+                    message: ingress_msg_1,
+                    peer_id: node_test_id(0),
+                    timestamp: time_source.get_relative_time(),
+                });
+                ingress_pool.apply_changeset(vec![ChangeAction::RemoveFromValidated(
+                    message_id_0.clone(),
+                )]);
+                assert_eq!(ingress_pool.validated().size(), 0);
+                assert_eq!(ingress_pool.unvalidated().size(), 1);
+
+                assert!(snapshot.validated().get(&message_id_0).is_some());
+                assert_eq!(snapshot.unvalidated().size(), 0);
+                let selected = snapshot.select_validated(
+                    mock_time()..=mock_time() + MAX_INGRESS_TTL,
+                    Box::new(|obj| SelectResult::Selected(obj.signed_ingress.clone())),
+                );
+                assert_eq!(selected.len(), 1);
+            })
+        })
+    }
+
+    #[test]
+    fn test_shards_track_pool() {
+        with_test_replica_logger(|log| {
+            ic_test_utilities::artifact_pool_config::with_test_pool_config(|mut pool_config| {
+                pool_config.ingress_pool_size_threshold = Some(2);
+                let max_quota = pool_config.ingress_pool_unvalidated_capacity_per_peer;
+                let time_source = FastForwardTimeSource::new();
+                let metrics_registry = MetricsRegistry::new();
+                let mut ingress_pool = IngressPoolImpl::new(pool_config, metrics_registry, log);
+                let shards = ingress_pool.shards();
+
+                let mut message_ids = Vec::new();
+                for nonce in 0..2 {
+                    let ingress_msg = SignedIngressBuilder::new().nonce(nonce).build();
+                    message_ids.push(IngressMessageId::from(&ingress_msg));
+                    ingress_pool.insert(UnvalidatedArtifact {
+                        message: ingress_msg,
+                        peer_id: node_test_id(0),
+                        timestamp: time_source.get_relative_time(),
+                    });
+                }
+                assert!(message_ids.iter().all(|id| shards.contains(id)));
+                assert!(shards.exceeds_threshold());
+                assert!(shards.remaining_quota(&node_test_id(0)) < max_quota);
+                assert_eq!(shards.remaining_quota(&node_test_id(1)), max_quota);
+
+                ingress_pool.apply_changeset(vec![
+                    ChangeAction::RemoveFromUnvalidated(message_ids[0].clone()),
+                    ChangeAction::RemoveFromUnvalidated(message_ids[1].clone()),
+                ]);
+                assert!(!message_ids.iter().any(|id| shards.contains(id)));
+                assert!(!shards.exceeds_threshold());
+                assert_eq!(shards.remaining_quota(&node_test_id(0)), max_quota);
+            })
+        })
+    }
+
+    #[test]
+    fn test_shards_serve_validated_messages() {
+        with_test_replica_logger(|log| {
+            ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
+                let metrics_registry = MetricsRegistry::new();
+                let mut ingress_pool = IngressPoolImpl::new(pool_config, metrics_registry, log);
+                let shards: Box<dyn IngressPoolLookup> = Box::new(ingress_pool.shards());
+                let ingress_msg = SignedIngressBuilder::new().nonce(1).build();
+                let message_id = IngressMessageId::from(&ingress_msg);
+                ingress_pool.insert(UnvalidatedArtifact {
+                    message: ingress_msg.clone(),
+                    peer_id: node_test_id(0),
+                    timestamp: mock_time(),
+                });
+                assert!(shards.contains(&message_id));
+                assert_eq!(shards.get_validated_by_identifier(&message_id), None);
+
+                ingress_pool.apply_changeset(vec![ChangeAction::MoveToValidated((
+                    message_id.clone(),
+                    0,
+                    IngressMessageAttribute::new(&ingress_msg),
+                    ic_types::crypto::CryptoHash(vec![]),
+                ))]);
+                assert_eq!(
+                    shards.get_validated_by_identifier(&message_id),
+                    Some(ingress_msg)
+                );
+
+                ingress_pool
+                    .apply_changeset(vec![ChangeAction::RemoveFromValidated(message_id.clone())]);
+                assert!(!shards.contains(&message_id));
+                assert_eq!(shards.get_validated_by_identifier(&message_id), None);
+            })
+        })
+    }
+
     #[test]
     fn test_throttling_disabled() {
         with_test_replica_logger(|log| {
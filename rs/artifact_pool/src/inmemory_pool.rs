@@ -15,18 +15,25 @@ use ic_types::{
     Height, Time,
 };
 use std::collections::BTreeMap;
+use std::sync::Arc;
 
+/// A section of the consensus pool that is held in memory. The artifacts and
+/// their indexes are shared copy-on-write between a section and its clones,
+/// so cloning a section is cheap, and the first modification of a section
+/// after it was cloned copies the indexes and the map of the artifacts, but
+/// not the artifacts themselves.
+#[derive(Clone)]
 pub struct InMemoryPoolSection<T: IntoInner<ConsensusMessage>> {
-    indexes: Indexes,
-    artifacts: BTreeMap<CryptoHash, T>,
+    indexes: Arc<Indexes>,
+    artifacts: Arc<BTreeMap<CryptoHash, Arc<T>>>,
     log: ReplicaLogger,
 }
 
 impl<T: IntoInner<ConsensusMessage> + HasTimestamp + Clone> InMemoryPoolSection<T> {
     pub fn new(log: ReplicaLogger) -> InMemoryPoolSection<T> {
         InMemoryPoolSection {
-            artifacts: BTreeMap::new(),
-            indexes: Indexes::new(),
+            artifacts: Arc::new(BTreeMap::new()),
+            indexes: Arc::new(Indexes::new()),
             log,
         }
     }
@@ -34,97 +41,44 @@ impl<T: IntoInner<ConsensusMessage> + HasTimestamp + Clone> InMemoryPoolSection<
     fn insert(&mut self, artifact: T) {
         let msg = artifact.as_ref();
         let hash = msg.get_cm_hash().digest().clone();
-        self.indexes.insert(&msg, &hash);
-        self.artifacts.entry(hash).or_insert(artifact);
+        Arc::make_mut(&mut self.indexes).insert(&msg, &hash);
+        Arc::make_mut(&mut self.artifacts)
+            .entry(hash)
+            .or_insert_with(|| Arc::new(artifact));
     }
 
     fn remove(&mut self, msg_id: &ConsensusMessageId) -> Option<T> {
         self.remove_by_hash(msg_id.hash.digest())
     }
 
-    #[allow(clippy::cognitive_complexity)]
     fn purge_below(&mut self, height: Height) {
-        if let Some(range) = self.random_beacon().height_range() {
-            for h in range.min.get()..height.get() {
-                for hash in self.indexes.random_beacon.remove_all(Height::from(h)) {
-                    self.artifacts.remove(hash.get_ref());
+        fn purge<S, T>(
+            index: &mut HeightIndex<CryptoHashOf<S>>,
+            artifacts: &mut BTreeMap<CryptoHash, Arc<T>>,
+            height: Height,
+        ) where
+            CryptoHashOf<S>: Eq + Clone,
+        {
+            let heights: Vec<Height> = index.range(..height).map(|(h, _)| *h).collect();
+            for h in heights {
+                for hash in index.remove_all(h) {
+                    artifacts.remove(hash.get_ref());
                 }
             }
-        };
-        if let Some(range) = self.finalization().height_range() {
-            for h in range.min.get()..height.get() {
-                for hash in self.indexes.finalization.remove_all(Height::from(h)) {
-                    self.artifacts.remove(hash.get_ref());
-                }
-            }
-        };
-        if let Some(range) = self.notarization().height_range() {
-            for h in range.min.get()..height.get() {
-                for hash in self.indexes.notarization.remove_all(Height::from(h)) {
-                    self.artifacts.remove(hash.get_ref());
-                }
-            }
-        };
-        if let Some(range) = self.block_proposal().height_range() {
-            for h in range.min.get()..height.get() {
-                for hash in self.indexes.block_proposal.remove_all(Height::from(h)) {
-                    self.artifacts.remove(hash.get_ref());
-                }
-            }
-        };
-        if let Some(range) = self.random_beacon_share().height_range() {
-            for h in range.min.get()..height.get() {
-                for hash in self.indexes.random_beacon_share.remove_all(Height::from(h)) {
-                    self.artifacts.remove(hash.get_ref());
-                }
-            }
-        };
-        if let Some(range) = self.notarization_share().height_range() {
-            for h in range.min.get()..height.get() {
-                for hash in self.indexes.notarization_share.remove_all(Height::from(h)) {
-                    self.artifacts.remove(hash.get_ref());
-                }
-            }
-        };
-        if let Some(range) = self.finalization_share().height_range() {
-            for h in range.min.get()..height.get() {
-                for hash in self.indexes.finalization_share.remove_all(Height::from(h)) {
-                    self.artifacts.remove(hash.get_ref());
-                }
-            }
-        };
-        if let Some(range) = self.random_tape().height_range() {
-            for h in range.min.get()..height.get() {
-                for hash in self.indexes.random_tape.remove_all(Height::from(h)) {
-                    self.artifacts.remove(hash.get_ref());
-                }
-            }
-        };
-        if let Some(range) = self.random_tape_share().height_range() {
-            for h in range.min.get()..height.get() {
-                for hash in self.indexes.random_tape_share.remove_all(Height::from(h)) {
-                    self.artifacts.remove(hash.get_ref());
-                }
-            }
-        };
-        if let Some(range) = self.catch_up_package().height_range() {
-            for h in range.min.get()..height.get() {
-                for hash in self.indexes.catch_up_package.remove_all(Height::from(h)) {
-                    self.artifacts.remove(hash.get_ref());
-                }
-            }
-        };
-        if let Some(range) = self.catch_up_package_share().height_range() {
-            for h in range.min.get()..height.get() {
-                for hash in self
-                    .indexes
-                    .catch_up_package_share
-                    .remove_all(Height::from(h))
-                {
-                    self.artifacts.remove(hash.get_ref());
-                }
-            }
-        };
+        }
+        let indexes = Arc::make_mut(&mut self.indexes);
+        let artifacts = Arc::make_mut(&mut self.artifacts);
+        purge(&mut indexes.random_beacon, artifacts, height);
+        purge(&mut indexes.finalization, artifacts, height);
+        purge(&mut indexes.notarization, artifacts, height);
+        purge(&mut indexes.block_proposal, artifacts, height);
+        purge(&mut indexes.random_beacon_share, artifacts, height);
+        purge(&mut indexes.notarization_share, artifacts, height);
+        purge(&mut indexes.finalization_share, artifacts, height);
+        purge(&mut indexes.random_tape, artifacts, height);
+        purge(&mut indexes.random_tape_share, artifacts, height);
+        purge(&mut indexes.catch_up_package, artifacts, height);
+        purge(&mut indexes.catch_up_package_share, artifacts, height);
     }
 
     fn get_by_hashes<S: ConsensusMessageHashable>(&self, hashes: Vec<&CryptoHashOf<S>>) -> Vec<S> {
@@ -145,15 +99,14 @@ impl<T: IntoInner<ConsensusMessage> + HasTimestamp + Clone> InMemoryPoolSection<
 
     /// Get a consensus message by its hash
     pub fn get_by_hash(&self, hash: &CryptoHash) -> Option<T> {
-        self.artifacts.get(hash).cloned()
+        self.artifacts.get(hash).map(|artifact| T::clone(artifact))
     }
 
     /// Get a consensus message by its hash
     pub fn remove_by_hash(&mut self, hash: &CryptoHash) -> Option<T> {
-        self.artifacts.remove(hash).map(|artifact| {
-            self.indexes.remove(artifact.as_ref(), hash);
-            artifact
-        })
+        let artifact = Arc::make_mut(&mut self.artifacts).remove(hash)?;
+        Arc::make_mut(&mut self.indexes).remove((*artifact).as_ref(), hash);
+        Some(Arc::try_unwrap(artifact).unwrap_or_else(|artifact| T::clone(&artifact)))
     }
 
     fn select_index<S: SelectIndex>(&self) -> &HeightIndex<S> {
@@ -359,4 +312,23 @@ pub mod test {
             }
         ));
     }
+
+    #[test]
+    fn test_clones_are_not_affected_by_changes() {
+        let mut pool = InMemoryPoolSection::new(ic_logger::replica_logger::no_op_logger());
+        pool.insert(make_artifact(fake_random_beacon(Height::from(1))));
+        let clone = pool.clone();
+
+        pool.insert(make_artifact(fake_random_beacon(Height::from(2))));
+        pool.purge_below(Height::from(2));
+        assert_eq!(
+            pool.random_beacon().height_range(),
+            Some(HeightRange::new(Height::from(2), Height::from(2)))
+        );
+        assert_eq!(
+            clone.random_beacon().height_range(),
+            Some(HeightRange::new(Height::from(1), Height::from(1)))
+        );
+        assert_eq!(clone.size(), 1);
+    }
 }
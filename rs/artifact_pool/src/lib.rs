@@ -1,12 +1,22 @@
+pub mod canister_http_pool;
 pub mod certification_pool;
 pub mod consensus_pool;
 mod consensus_pool_cache;
 pub mod dkg_pool;
+pub mod dump;
+pub mod ecdsa_pool;
+pub mod equivocation_pool;
 mod height_index;
 pub mod ingress_pool;
 mod inmemory_pool;
+mod message_pool;
 mod metrics;
+mod migration;
 mod peer_index;
+pub mod query_stats_pool;
+pub mod remote_dkg_pool;
+mod ttl;
+mod unvalidated_limiter;
 
 mod backup;
 mod lmdb_iterator;
@@ -1,5 +1,6 @@
 use crate::consensus_pool::{InitializablePoolSection, PoolSectionOp, PoolSectionOps};
 use crate::lmdb_iterator::LMDBIterator;
+use crate::metrics::BackgroundPurgeMetrics;
 use ic_config::artifact_pool::LMDBConfig;
 use ic_consensus_message::ConsensusMessageHashable;
 use ic_interfaces::{
@@ -10,6 +11,7 @@ use ic_interfaces::{
     crypto::CryptoHashable,
 };
 use ic_logger::{error, ReplicaLogger};
+use ic_metrics::MetricsRegistry;
 use ic_protobuf::types::v1 as pb;
 use ic_types::{
     artifact::{CertificationMessageId, ConsensusMessageId},
@@ -31,7 +33,10 @@ use lmdb::{
 use serde::{Deserialize, Serialize};
 use std::convert::{TryFrom, TryInto};
 use std::marker::PhantomData;
-use std::{os::raw::c_uint, path::Path, sync::Arc};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use std::{os::raw::c_uint, path::Path};
 
 /// Implementation of a persistent, height indexed pool using LMDB.
 ///
@@ -76,6 +81,7 @@ pub struct PersistentHeightIndexedPool<T> {
     meta: Database,
     artifacts: Database,
     indices: Vec<(TypeKey, Database)>,
+    purge: Option<BackgroundPurge>,
     log: ReplicaLogger,
 }
 
@@ -283,6 +289,13 @@ const MAX_PERSISTENT_POOL_SIZE: usize = 0x0010_0000_0000; // 64GB
 /// Max number of DB readers.
 const MAX_READERS: c_uint = 2048;
 
+/// The maximum number of entries the background purge deletes in one
+/// transaction. It bounds the time other writers wait for the purge.
+const PURGE_BATCH_SIZE: usize = 1000;
+
+/// The pause of the background purge between two transactions.
+const PURGE_BATCH_INTERVAL: Duration = Duration::from_millis(10);
+
 ///////////////////////////// Generic Pool /////////////////////////////
 
 /// Collection of generic pool functions, indexed by Artifact type.
@@ -358,10 +371,55 @@ impl<Artifact: PoolArtifact> PersistentHeightIndexedPool<Artifact> {
             meta,
             artifacts,
             indices,
+            purge: None,
             log,
         }
     }
 
+    /// Deletes purged artifacts in a background thread from now on. A purge
+    /// then only raises the min heights of all message types, which hides
+    /// the purged artifacts from height indexed reads, and the background
+    /// thread deletes them in small batches. Artifacts left behind by a
+    /// previous instance are deleted right away.
+    pub(crate) fn start_background_purge(
+        &mut self,
+        metrics_registry: &MetricsRegistry,
+        pool: &str,
+    ) {
+        let state = Arc::new((Mutex::new(PurgeState::default()), Condvar::new()));
+        let worker = PurgeWorker {
+            db_env: self.db_env.clone(),
+            artifacts: self.artifacts,
+            indices: self.indices.iter().map(|(_, db)| *db).collect(),
+            metrics: BackgroundPurgeMetrics::new(metrics_registry, pool),
+            log: self.log.clone(),
+        };
+        let handle = {
+            let state = state.clone();
+            std::thread::Builder::new()
+                .name(format!("{}_pool_purge", pool))
+                .spawn(move || worker.run(state))
+                .expect("Failed to spawn the background purge thread")
+        };
+        let purge = BackgroundPurge {
+            state,
+            handle: Some(handle),
+        };
+        // All artifacts below the lowest min height are left over from an
+        // earlier purge.
+        if let Some(mut tx) = log_err!(self.db_env.begin_ro_txn(), self.log, "begin_ro_txn") {
+            if let Some(height_key) = Artifact::type_keys()
+                .iter()
+                .filter_map(|type_key| self.get_meta(&mut tx, type_key))
+                .map(|meta| meta.min)
+                .min()
+            {
+                purge.request(height_key);
+            }
+        }
+        self.purge = Some(purge);
+    }
+
     /// Update the meta data of the given type_key.
     fn update_meta<'a>(
         &self,
@@ -475,11 +533,17 @@ impl<Artifact: PoolArtifact> PersistentHeightIndexedPool<Artifact> {
         let index_db = self.get_index_db(&key.type_key);
         tx.del(index_db, &key.height_key, Some(&key.id_key.0))?;
 
+        // Entries below the min height may still wait for the background
+        // purge, so the new min height is searched from the old one.
+        let old_min_height = self.get_meta(tx, &key.type_key).map(|meta| meta.min);
         let min_height;
         let max_height;
         {
             let mut cursor = tx.open_ro_cursor(index_db)?;
-            let mut iter = cursor.iter_start();
+            let mut iter = match old_min_height {
+                Some(old_min_height) => cursor.iter_from(old_min_height),
+                None => cursor.iter_start(),
+            };
             min_height = iter
                 .next()
                 .transpose()?
@@ -561,6 +625,199 @@ impl<Artifact: PoolArtifact> PersistentHeightIndexedPool<Artifact> {
         }
         Ok(())
     }
+
+    /// Raise the min heights of all message types to the given HeightKey,
+    /// without deleting any artifacts.
+    fn tx_hide_below<'a>(
+        &self,
+        tx: &mut RwTransaction<'a>,
+        height_key: HeightKey,
+    ) -> lmdb::Result<()> {
+        for type_key in Artifact::type_keys() {
+            if let Some(meta) = self.get_meta(tx, type_key) {
+                if meta.min >= height_key {
+                    continue;
+                }
+                let min = if meta.max < height_key {
+                    None
+                } else {
+                    let mut cursor = tx.open_ro_cursor(self.get_index_db(type_key))?;
+                    cursor
+                        .iter_from(height_key)
+                        .next()
+                        .transpose()?
+                        .map(|(key, _)| HeightKey::from(key))
+                };
+                match min {
+                    None => tx.del(self.meta, type_key, None)?,
+                    Some(min) => self.update_meta(tx, type_key, &Meta { min, max: meta.max })?,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Purge all artifacts with heights less than the given HeightKey, either
+    /// right away or, if enabled, in the background once the transaction was
+    /// committed.
+    fn tx_purge_or_hide_below<'a>(
+        &self,
+        tx: &mut RwTransaction<'a>,
+        height_key: HeightKey,
+    ) -> lmdb::Result<()> {
+        match self.purge {
+            Some(_) => self.tx_hide_below(tx, height_key),
+            None => self.tx_purge_below(tx, height_key),
+        }
+    }
+
+    /// Hand the given HeightKey to the background purge, if enabled.
+    fn request_purge(&self, height_key: HeightKey) {
+        if let Some(purge) = &self.purge {
+            purge.request(height_key);
+        }
+    }
+}
+
+/// The state shared between a pool and its background purge thread.
+#[derive(Default)]
+struct PurgeState {
+    // All artifacts with heights less than the target are to be deleted.
+    target: Option<HeightKey>,
+    shutdown: bool,
+}
+
+/// The handle of the background purge thread of a pool. Dropping it stops the
+/// thread after its current batch.
+struct BackgroundPurge {
+    state: Arc<(Mutex<PurgeState>, Condvar)>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl BackgroundPurge {
+    /// Request the deletion of all artifacts with heights less than the given
+    /// HeightKey.
+    fn request(&self, height_key: HeightKey) {
+        let (state, condvar) = &*self.state;
+        let mut state = state.lock().unwrap();
+        state.target = Some(
+            state
+                .target
+                .map_or(height_key, |target| target.max(height_key)),
+        );
+        condvar.notify_one();
+    }
+}
+
+impl Drop for BackgroundPurge {
+    fn drop(&mut self) {
+        {
+            let (state, condvar) = &*self.state;
+            state.lock().unwrap().shutdown = true;
+            condvar.notify_one();
+        }
+        if let Some(handle) = self.handle.take() {
+            handle.join().ok();
+        }
+    }
+}
+
+/// The background purge thread, deleting index entries and artifacts below
+/// the requested height in batches of at most `PURGE_BATCH_SIZE` entries.
+struct PurgeWorker {
+    db_env: Arc<Environment>,
+    artifacts: Database,
+    indices: Vec<Database>,
+    metrics: BackgroundPurgeMetrics,
+    log: ReplicaLogger,
+}
+
+impl PurgeWorker {
+    fn run(self, state: Arc<(Mutex<PurgeState>, Condvar)>) {
+        let (state, condvar) = &*state;
+        loop {
+            let target = {
+                let mut state = state.lock().unwrap();
+                while state.target.is_none() && !state.shutdown {
+                    state = condvar.wait(state).unwrap();
+                }
+                match state.target {
+                    Some(target) if !state.shutdown => target,
+                    _ => return,
+                }
+            };
+            let timer = self.metrics.batch_duration.start_timer();
+            let result = self.delete_batch(target);
+            timer.observe_duration();
+            match result {
+                Ok(Some(backlog)) => self.metrics.backlog_heights.set(backlog),
+                Ok(None) => {
+                    self.metrics.backlog_heights.set(0);
+                    let mut state = state.lock().unwrap();
+                    // A higher target may have been requested in the meantime.
+                    if state.target == Some(target) {
+                        state.target = None;
+                    }
+                    continue;
+                }
+                Err(err) => error!(
+                    self.log,
+                    "Error in DB operation background purge: {:?}", err
+                ),
+            }
+            std::thread::sleep(PURGE_BATCH_INTERVAL);
+        }
+    }
+
+    /// Delete one batch of entries below the given HeightKey. Return None if
+    /// no entries are left below it, and the number of heights left to purge
+    /// otherwise.
+    fn delete_batch(&self, target: HeightKey) -> lmdb::Result<Option<i64>> {
+        let mut tx = self.db_env.begin_rw_txn()?;
+        let mut deleted = 0;
+        for db in self.indices.iter().chain(std::iter::once(&self.artifacts)) {
+            deleted += delete_below(&mut tx, *db, target, PURGE_BATCH_SIZE - deleted)?;
+        }
+        let lowest = {
+            let mut cursor = tx.open_ro_cursor(self.artifacts)?;
+            cursor
+                .iter_start()
+                .next()
+                .transpose()?
+                .map(|(key, _)| IdKey::from(key).height())
+        };
+        tx.commit()?;
+        self.metrics.deleted_entries.inc_by(deleted as u64);
+        if deleted < PURGE_BATCH_SIZE {
+            return Ok(None);
+        }
+        let target = Height::from(target);
+        Ok(Some(lowest.map_or(0, |lowest| {
+            target.saturating_sub(lowest).get() as i64
+        })))
+    }
+}
+
+/// Delete at most the given number of entries whose keys are prefixed by a
+/// HeightKey less than the given one, and return the number of deleted entries.
+fn delete_below(
+    tx: &mut RwTransaction<'_>,
+    db: Database,
+    height_key: HeightKey,
+    max_entries: usize,
+) -> lmdb::Result<usize> {
+    let mut deleted = 0;
+    let mut cursor = tx.open_rw_cursor(db)?;
+    while deleted < max_entries {
+        match cursor.iter().next().transpose()? {
+            Some((key, _)) if HeightKey::from(&key[0..8]) < height_key => {
+                cursor.del(WriteFlags::empty())?;
+                deleted += 1;
+            }
+            _ => break,
+        }
+    }
+    Ok(deleted)
 }
 
 impl InitializablePoolSection for PersistentHeightIndexedPool<ConsensusMessage> {
@@ -921,6 +1178,7 @@ impl PersistentHeightIndexedPool<ConsensusMessage> {
 
     fn tx_mutate(&mut self, ops: PoolSectionOps<ValidatedConsensusArtifact>) -> lmdb::Result<()> {
         let mut tx = self.db_env.begin_rw_txn()?;
+        let mut purge_height_key = None;
         for op in ops.ops {
             match op {
                 PoolSectionOp::Insert(artifact) => {
@@ -943,11 +1201,16 @@ impl PersistentHeightIndexedPool<ConsensusMessage> {
                 }
                 PoolSectionOp::PurgeBelow(height) => {
                     let height_key = HeightKey::from(height);
-                    self.tx_purge_below(&mut tx, height_key)?
+                    self.tx_purge_or_hide_below(&mut tx, height_key)?;
+                    purge_height_key = Some(height_key);
                 }
             }
         }
-        tx.commit()
+        tx.commit()?;
+        if let Some(height_key) = purge_height_key {
+            self.request_purge(height_key);
+        }
+        Ok(())
     }
 }
 
@@ -1202,9 +1465,12 @@ impl PersistentHeightIndexedPool<CertificationMessage> {
     }
 
     fn purge_below_height(&self, height: Height) -> lmdb::Result<()> {
+        let height_key = HeightKey::from(height);
         let mut tx = self.db_env.begin_rw_txn()?;
-        self.tx_purge_below(&mut tx, HeightKey::from(height))?;
-        tx.commit()
+        self.tx_purge_or_hide_below(&mut tx, height_key)?;
+        tx.commit()?;
+        self.request_purge(height_key);
+        Ok(())
     }
 }
 
@@ -1931,6 +2197,49 @@ mod tests {
         });
     }
 
+    // Test that the background purge deletes purged artifacts, which are
+    // hidden from height indexed reads right away.
+    #[test]
+    fn test_background_purge() {
+        run_persistent_pool_test("test_background_purge", |config, log| {
+            let mut pool = PersistentHeightIndexedPool::new_consensus_pool(config, false, log);
+            pool.start_background_purge(&MetricsRegistry::new(), "consensus");
+            let rb_ops = random_beacon_ops();
+            let purged_id = rb_ops
+                .ops
+                .iter()
+                .find_map(|op| match op {
+                    PoolSectionOp::Insert(artifact) if artifact.msg.height() < Height::from(10) => {
+                        Some(artifact.msg.get_id())
+                    }
+                    _ => None,
+                })
+                .unwrap();
+            pool.mutate(rb_ops);
+            let mut purge_ops = PoolSectionOps::new();
+            purge_ops.purge_below(Height::from(10));
+            pool.mutate(purge_ops);
+            assert_eq!(
+                pool.random_beacon().height_range().map(|r| r.min),
+                Some(Height::from(10))
+            );
+            assert!(pool
+                .random_beacon()
+                .get_all()
+                .all(|random_beacon| random_beacon.height() >= Height::from(10)));
+
+            let start = std::time::Instant::now();
+            while pool.contains(&purged_id) {
+                assert!(
+                    start.elapsed() < Duration::from_secs(10),
+                    "Purged artifact was not deleted in time"
+                );
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            assert_eq!(pool.random_beacon().get_all().count(), 9);
+        });
+    }
+
     // Test if timestamp survives reboot.
     #[test]
     fn test_timestamp_survives_reboot() {
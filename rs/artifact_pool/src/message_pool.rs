@@ -0,0 +1,301 @@
+//! The in-memory pool of gossiped messages that are identified by their hash,
+//! shared by the pools of equivocation proofs, remote DKG messages, canister
+//! HTTP messages and query statistics reports. The pools only differ in their
+//! limits and in which messages they purge.
+use crate::metrics::{PoolArtifactMetrics, POOL_TYPE_UNVALIDATED, POOL_TYPE_VALIDATED};
+use crate::unvalidated_limiter::{artifact_size_bytes, Admission, UnvalidatedLimiter};
+use ic_config::artifact_pool::UnvalidatedSectionLimits;
+use ic_interfaces::artifact_pool::{UnvalidatedArtifact, ValidatedArtifact};
+use ic_interfaces::crypto::CryptoHashable;
+use ic_metrics::MetricsRegistry;
+use ic_types::crypto::CryptoHashOf;
+use ic_types::time::current_time;
+use ic_types::Height;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// The validated and unvalidated sections of a pool of messages, keyed by the
+/// hash of the messages.
+pub(crate) struct MessagePool<M> {
+    validated: BTreeMap<CryptoHashOf<M>, ValidatedArtifact<M>>,
+    unvalidated: BTreeMap<CryptoHashOf<M>, UnvalidatedArtifact<M>>,
+    unvalidated_limiter: UnvalidatedLimiter<CryptoHashOf<M>>,
+    artifact_metrics: PoolArtifactMetrics,
+    /// The artifact type label of the metrics.
+    artifact_type: &'static str,
+}
+
+impl<M: CryptoHashable + Serialize + Clone> MessagePool<M> {
+    pub(crate) fn new(
+        metrics_registry: &MetricsRegistry,
+        pool: &str,
+        artifact_type: &'static str,
+        limits: UnvalidatedSectionLimits,
+    ) -> Self {
+        Self {
+            validated: BTreeMap::new(),
+            unvalidated: BTreeMap::new(),
+            unvalidated_limiter: UnvalidatedLimiter::new(limits),
+            artifact_metrics: PoolArtifactMetrics::new(metrics_registry, pool),
+            artifact_type,
+        }
+    }
+
+    /// Inserts the given artifact into the unvalidated section, unless it is
+    /// already validated or the limiter rejects it. The height is only used
+    /// by the `LowestHeightFirst` eviction policy.
+    pub(crate) fn insert(&mut self, artifact: UnvalidatedArtifact<M>, height: Height) {
+        let id = ic_crypto::crypto_hash(&artifact.message);
+        if self.validated.contains_key(&id) {
+            return;
+        }
+        let size_bytes = artifact_size_bytes(&artifact.message);
+        let admission = self
+            .unvalidated_limiter
+            .admit(id.clone(), height, size_bytes);
+        if let Admission::Accepted { evicted } = admission {
+            for evicted_id in evicted.iter() {
+                self.remove_unvalidated(evicted_id);
+            }
+            if self.unvalidated.insert(id, artifact).is_none() {
+                self.artifact_metrics.observe_insert(
+                    POOL_TYPE_UNVALIDATED,
+                    self.artifact_type,
+                    size_bytes,
+                );
+            }
+        }
+    }
+
+    /// Inserts a message produced by this replica into the validated section,
+    /// and returns its ID.
+    pub(crate) fn add_to_validated(&mut self, msg: M) -> CryptoHashOf<M> {
+        let id = ic_crypto::crypto_hash(&msg);
+        self.remove_unvalidated(&id);
+        self.insert_validated(id.clone(), msg);
+        id
+    }
+
+    /// Moves a message from the unvalidated into the validated section, and
+    /// returns its ID.
+    ///
+    /// # Panics
+    ///
+    /// It panics if the message cannot be found in the unvalidated section.
+    pub(crate) fn move_to_validated(&mut self, msg: M) -> CryptoHashOf<M> {
+        let id = ic_crypto::crypto_hash(&msg);
+        let unvalidated = self
+            .remove_unvalidated(&id)
+            .expect("Unvalidated artifact was not found.");
+        self.artifact_metrics.observe_validation(
+            self.artifact_type,
+            unvalidated.timestamp,
+            current_time(),
+        );
+        self.insert_validated(id.clone(), msg);
+        id
+    }
+
+    pub(crate) fn remove_unvalidated(
+        &mut self,
+        id: &CryptoHashOf<M>,
+    ) -> Option<UnvalidatedArtifact<M>> {
+        self.unvalidated_limiter.remove(id);
+        let removed = self.unvalidated.remove(id);
+        if removed.is_some() {
+            self.artifact_metrics
+                .observe_remove(POOL_TYPE_UNVALIDATED, self.artifact_type);
+        }
+        removed
+    }
+
+    fn insert_validated(&mut self, id: CryptoHashOf<M>, msg: M) {
+        let size_bytes = artifact_size_bytes(&msg);
+        let artifact = ValidatedArtifact {
+            msg,
+            timestamp: current_time(),
+        };
+        if self.validated.insert(id, artifact).is_none() {
+            self.artifact_metrics.observe_insert(
+                POOL_TYPE_VALIDATED,
+                self.artifact_type,
+                size_bytes,
+            );
+        }
+    }
+
+    /// Removes the messages for which the given predicate returns true from
+    /// both sections.
+    pub(crate) fn purge<F: Fn(&M) -> bool>(&mut self, is_expired: F) {
+        let now = current_time();
+        let unvalidated_ids: Vec<_> = self
+            .unvalidated
+            .iter()
+            .filter(|(_, artifact)| is_expired(&artifact.message))
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in unvalidated_ids {
+            self.unvalidated_limiter.remove(&id);
+            if let Some(artifact) = self.unvalidated.remove(&id) {
+                self.artifact_metrics.observe_purge(
+                    POOL_TYPE_UNVALIDATED,
+                    self.artifact_type,
+                    artifact.timestamp,
+                    now,
+                );
+            }
+        }
+        let validated_ids: Vec<_> = self
+            .validated
+            .iter()
+            .filter(|(_, artifact)| is_expired(&artifact.msg))
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in validated_ids {
+            if let Some(artifact) = self.validated.remove(&id) {
+                self.artifact_metrics.observe_purge(
+                    POOL_TYPE_VALIDATED,
+                    self.artifact_type,
+                    artifact.timestamp,
+                    now,
+                );
+            }
+        }
+    }
+
+    pub(crate) fn contains(&self, id: &CryptoHashOf<M>) -> bool {
+        self.unvalidated.contains_key(id) || self.validated.contains_key(id)
+    }
+
+    pub(crate) fn get_validated(&self, id: &CryptoHashOf<M>) -> Option<&M> {
+        self.validated.get(id).map(|artifact| &artifact.msg)
+    }
+
+    pub(crate) fn validated(&self) -> impl Iterator<Item = &M> + '_ {
+        self.validated.values().map(|artifact| &artifact.msg)
+    }
+
+    pub(crate) fn unvalidated(&self) -> impl Iterator<Item = &M> + '_ {
+        self.unvalidated.values().map(|artifact| &artifact.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_config::artifact_pool::UnvalidatedEvictionPolicy;
+    use ic_test_utilities::{
+        mock_time,
+        types::ids::{node_test_id, subnet_test_id},
+    };
+    use ic_types::consensus::remote_dkg::{RemoteDkgMessage, RemoteDkgResponse};
+    use ic_types::consensus::HasHeight;
+    use ic_types::crypto::threshold_sig::ni_dkg::{
+        NiDkgId, NiDkgTag, NiDkgTargetId, NiDkgTargetSubnet,
+    };
+
+    fn make_message(height: u64) -> RemoteDkgMessage {
+        RemoteDkgMessage::Response(RemoteDkgResponse {
+            summary_height: Height::from(height),
+            dkg_id: NiDkgId {
+                start_block_height: Height::from(height),
+                dealer_subnet: subnet_test_id(0),
+                dkg_tag: NiDkgTag::HighThreshold,
+                target_subnet: NiDkgTargetSubnet::Remote(NiDkgTargetId::new([1; 32])),
+            },
+            transcript: Err("Not enough dealings".to_string()),
+        })
+    }
+
+    fn unvalidated(msg: &RemoteDkgMessage) -> UnvalidatedArtifact<RemoteDkgMessage> {
+        UnvalidatedArtifact {
+            message: msg.clone(),
+            peer_id: node_test_id(1),
+            timestamp: mock_time(),
+        }
+    }
+
+    fn new_pool(max_count: usize) -> MessagePool<RemoteDkgMessage> {
+        MessagePool::new(
+            &MetricsRegistry::new(),
+            "test",
+            "test_message",
+            UnvalidatedSectionLimits {
+                max_count,
+                max_size_bytes: usize::MAX,
+                eviction_policy: UnvalidatedEvictionPolicy::LowestHeightFirst,
+            },
+        )
+    }
+
+    fn heights<'a>(messages: impl Iterator<Item = &'a RemoteDkgMessage>) -> Vec<u64> {
+        messages.map(|msg| msg.height().get()).collect()
+    }
+
+    #[test]
+    fn validated_messages_are_not_inserted_again() {
+        let mut pool = new_pool(10);
+        let msg = make_message(1);
+        let id = pool.add_to_validated(msg.clone());
+        pool.insert(unvalidated(&msg), msg.height());
+        assert_eq!(pool.unvalidated().count(), 0);
+        assert_eq!(pool.get_validated(&id), Some(&msg));
+    }
+
+    #[test]
+    fn moving_a_message_validates_it() {
+        let mut pool = new_pool(10);
+        let msg = make_message(1);
+        let id = ic_crypto::crypto_hash(&msg);
+        pool.insert(unvalidated(&msg), msg.height());
+        assert!(pool.contains(&id));
+        assert_eq!(pool.get_validated(&id), None);
+
+        pool.move_to_validated(msg.clone());
+        assert!(pool.contains(&id));
+        assert_eq!(pool.unvalidated().count(), 0);
+        assert_eq!(pool.get_validated(&id), Some(&msg));
+    }
+
+    #[test]
+    #[should_panic(expected = "Unvalidated artifact was not found.")]
+    fn moving_a_missing_message_panics() {
+        new_pool(10).move_to_validated(make_message(1));
+    }
+
+    #[test]
+    fn the_limiter_evicts_unvalidated_messages() {
+        let mut pool = new_pool(1);
+        let (low, high) = (make_message(1), make_message(2));
+        pool.insert(unvalidated(&low), low.height());
+        pool.insert(unvalidated(&high), high.height());
+        assert_eq!(heights(pool.unvalidated()), vec![2]);
+
+        // Removing a message frees its slot in the limiter.
+        pool.remove_unvalidated(&ic_crypto::crypto_hash(&high));
+        pool.insert(unvalidated(&low), low.height());
+        assert_eq!(heights(pool.unvalidated()), vec![1]);
+    }
+
+    #[test]
+    fn purging_removes_the_expired_messages_from_both_sections() {
+        let mut pool = new_pool(10);
+        let messages: Vec<_> = (1..=4).map(make_message).collect();
+        for msg in messages.iter() {
+            pool.insert(unvalidated(msg), msg.height());
+        }
+        pool.move_to_validated(messages[0].clone());
+        pool.move_to_validated(messages[2].clone());
+
+        pool.purge(|msg| msg.height() < Height::from(3));
+        assert_eq!(heights(pool.validated()), vec![3]);
+        assert_eq!(heights(pool.unvalidated()), vec![4]);
+
+        // Purged messages no longer count against the limits.
+        let mut pool = new_pool(1);
+        pool.insert(unvalidated(&messages[1]), messages[1].height());
+        pool.purge(|_| true);
+        pool.insert(unvalidated(&messages[0]), messages[0].height());
+        assert_eq!(heights(pool.unvalidated()), vec![1]);
+    }
+}
@@ -1,11 +1,18 @@
+use crate::unvalidated_limiter::Admission;
 use ic_metrics::buckets::{decimal_buckets, decimal_buckets_with_zero};
 use ic_metrics::MetricsRegistry;
-use prometheus::{histogram_opts, labels, opts, Histogram, HistogramVec, IntGauge};
+use ic_types::Time;
+use prometheus::{
+    histogram_opts, labels, opts, Histogram, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec,
+};
+use std::time::Duration;
 
 pub const LABEL_POOL: &str = "pool";
 pub const LABEL_POOL_TYPE: &str = "pool_type";
 pub const POOL_TYPE_VALIDATED: &str = "validated";
 pub const POOL_TYPE_UNVALIDATED: &str = "unvalidated";
+pub const LABEL_ARTIFACT_TYPE: &str = "artifact_type";
 
 /// Metrics for a given artifact pool's validated/unvalidated section.
 #[derive(Clone)]
@@ -74,3 +81,257 @@ impl PoolMetrics {
         self.pool_size_bytes.sub(size_bytes as i64);
     }
 }
+
+/// Metrics for the limits of a given artifact pool's unvalidated section.
+#[derive(Clone)]
+pub struct UnvalidatedLimitMetrics {
+    pub rejected_inserts: IntCounter,
+    pub evicted_artifacts: IntCounter,
+}
+
+impl UnvalidatedLimitMetrics {
+    pub fn new(metrics_registry: &MetricsRegistry, pool: &str) -> Self {
+        Self {
+            rejected_inserts: metrics_registry.register(
+                IntCounter::with_opts(opts!(
+                    "artifact_pool_unvalidated_rejected_inserts_total",
+                    "Number of artifacts not inserted into the unvalidated section of the given pool, because the section was full",
+                    labels! {LABEL_POOL => pool}
+                ))
+                .unwrap(),
+            ),
+            evicted_artifacts: metrics_registry.register(
+                IntCounter::with_opts(opts!(
+                    "artifact_pool_unvalidated_evicted_artifacts_total",
+                    "Number of artifacts evicted from the unvalidated section of the given pool to make room for new artifacts",
+                    labels! {LABEL_POOL => pool}
+                ))
+                .unwrap(),
+            ),
+        }
+    }
+
+    pub fn observe_admission<K>(&self, admission: &Admission<K>) {
+        match admission {
+            Admission::Accepted { evicted } => self.evicted_artifacts.inc_by(evicted.len() as u64),
+            Admission::Rejected => self.rejected_inserts.inc(),
+        }
+    }
+}
+
+/// Metrics for the background purge of a given persistent pool.
+#[derive(Clone)]
+pub struct BackgroundPurgeMetrics {
+    pub backlog_heights: IntGauge,
+    pub deleted_entries: IntCounter,
+    pub batch_duration: Histogram,
+}
+
+impl BackgroundPurgeMetrics {
+    pub fn new(metrics_registry: &MetricsRegistry, pool: &str) -> Self {
+        Self {
+            backlog_heights: metrics_registry.register(
+                IntGauge::with_opts(opts!(
+                    "artifact_pool_purge_backlog_heights",
+                    "Number of heights of purged artifacts the background purge of the given pool still has to delete",
+                    labels! {LABEL_POOL => pool}
+                ))
+                .unwrap(),
+            ),
+            deleted_entries: metrics_registry.register(
+                IntCounter::with_opts(opts!(
+                    "artifact_pool_purge_deleted_entries_total",
+                    "Number of database entries deleted by the background purge of the given pool",
+                    labels! {LABEL_POOL => pool}
+                ))
+                .unwrap(),
+            ),
+            batch_duration: metrics_registry.register(
+                Histogram::with_opts(histogram_opts!(
+                    "artifact_pool_purge_batch_duration_seconds",
+                    "The time it took the background purge of the given pool to delete one batch of entries",
+                    // 0.1ms - 500ms
+                    decimal_buckets(-4, -1),
+                    labels! {LABEL_POOL.to_string() => pool.to_string()}
+                ))
+                .unwrap(),
+            ),
+        }
+    }
+}
+
+/// Metrics of the artifacts in a given artifact pool, by section and artifact
+/// type. All pools record the same metrics, so that they can be compared
+/// across pools:
+/// - the number of artifacts in each section,
+/// - the byte sizes of the inserted artifacts,
+/// - the number of inserted and removed artifacts,
+/// - the time artifacts spent in the unvalidated section before they were
+///   validated,
+/// - the age of artifacts when they were purged.
+#[derive(Clone)]
+pub struct PoolArtifactMetrics {
+    artifacts: IntGaugeVec,
+    artifact_bytes: HistogramVec,
+    inserted_artifacts: IntCounterVec,
+    removed_artifacts: IntCounterVec,
+    validation_latency: HistogramVec,
+    purge_age: HistogramVec,
+}
+
+impl PoolArtifactMetrics {
+    pub fn new(metrics_registry: &MetricsRegistry, pool: &str) -> Self {
+        Self {
+            artifacts: metrics_registry.register(
+                IntGaugeVec::new(
+                    opts!(
+                        "artifact_pool_type_artifacts",
+                        "Current number of artifacts in the given pool, by section and artifact type",
+                        labels! {LABEL_POOL => pool}
+                    ),
+                    &[LABEL_POOL_TYPE, LABEL_ARTIFACT_TYPE],
+                )
+                .unwrap(),
+            ),
+            artifact_bytes: metrics_registry.register(
+                HistogramVec::new(
+                    histogram_opts!(
+                        "artifact_pool_type_artifact_bytes",
+                        "The byte size of the artifacts inserted into the given pool, by section and artifact type",
+                        // 0, 1B - 50MB
+                        decimal_buckets_with_zero(0, 7),
+                        labels! {LABEL_POOL.to_string() => pool.to_string()}
+                    ),
+                    &[LABEL_POOL_TYPE, LABEL_ARTIFACT_TYPE],
+                )
+                .unwrap(),
+            ),
+            inserted_artifacts: metrics_registry.register(
+                IntCounterVec::new(
+                    opts!(
+                        "artifact_pool_type_inserted_artifacts_total",
+                        "Number of artifacts inserted into the given pool, by section and artifact type",
+                        labels! {LABEL_POOL => pool}
+                    ),
+                    &[LABEL_POOL_TYPE, LABEL_ARTIFACT_TYPE],
+                )
+                .unwrap(),
+            ),
+            removed_artifacts: metrics_registry.register(
+                IntCounterVec::new(
+                    opts!(
+                        "artifact_pool_type_removed_artifacts_total",
+                        "Number of artifacts removed from the given pool, by section and artifact type",
+                        labels! {LABEL_POOL => pool}
+                    ),
+                    &[LABEL_POOL_TYPE, LABEL_ARTIFACT_TYPE],
+                )
+                .unwrap(),
+            ),
+            validation_latency: metrics_registry.register(
+                HistogramVec::new(
+                    histogram_opts!(
+                        "artifact_pool_validation_latency_seconds",
+                        "The time artifacts spent in the unvalidated section of the given pool before they were validated, by artifact type",
+                        // 1ms - 500s
+                        decimal_buckets(-3, 2),
+                        labels! {LABEL_POOL.to_string() => pool.to_string()}
+                    ),
+                    &[LABEL_ARTIFACT_TYPE],
+                )
+                .unwrap(),
+            ),
+            purge_age: metrics_registry.register(
+                HistogramVec::new(
+                    histogram_opts!(
+                        "artifact_pool_purge_age_seconds",
+                        "The age of the artifacts purged from the given pool, by section and artifact type",
+                        // 10ms - 5000s
+                        decimal_buckets(-2, 3),
+                        labels! {LABEL_POOL.to_string() => pool.to_string()}
+                    ),
+                    &[LABEL_POOL_TYPE, LABEL_ARTIFACT_TYPE],
+                )
+                .unwrap(),
+            ),
+        }
+    }
+
+    /// Records the insertion of an artifact of the given type and size into
+    /// the given section.
+    pub fn observe_insert(&self, pool_type: &str, artifact_type: &str, size_bytes: usize) {
+        let labels = &[pool_type, artifact_type];
+        self.artifacts.with_label_values(labels).inc();
+        self.artifact_bytes
+            .with_label_values(labels)
+            .observe(size_bytes as f64);
+        self.inserted_artifacts.with_label_values(labels).inc();
+    }
+
+    /// Records the given number of artifacts of the given type that were in
+    /// the given section before the pool was created, e.g. because the
+    /// section is persistent.
+    pub fn observe_existing(&self, pool_type: &str, artifact_type: &str, count: usize) {
+        self.artifacts
+            .with_label_values(&[pool_type, artifact_type])
+            .add(count as i64);
+    }
+
+    /// Records the removal of an artifact of the given type from the given
+    /// section.
+    pub fn observe_remove(&self, pool_type: &str, artifact_type: &str) {
+        self.observe_removes(pool_type, artifact_type, 1);
+    }
+
+    /// Records the removal of the given number of artifacts of the given type
+    /// from the given section.
+    pub fn observe_removes(&self, pool_type: &str, artifact_type: &str, count: usize) {
+        let labels = &[pool_type, artifact_type];
+        self.artifacts.with_label_values(labels).sub(count as i64);
+        self.removed_artifacts
+            .with_label_values(labels)
+            .inc_by(count as u64);
+    }
+
+    /// Records the validation of an artifact of the given type that was
+    /// inserted into the unvalidated section at the given time.
+    pub fn observe_validation(&self, artifact_type: &str, inserted_at: Time, now: Time) {
+        self.validation_latency
+            .with_label_values(&[artifact_type])
+            .observe(elapsed(inserted_at, now).as_secs_f64());
+    }
+
+    /// Records the purge of an artifact of the given type from the given
+    /// section, that was inserted at the given time.
+    pub fn observe_purge(
+        &self,
+        pool_type: &str,
+        artifact_type: &str,
+        inserted_at: Time,
+        now: Time,
+    ) {
+        self.observe_remove(pool_type, artifact_type);
+        self.purge_age
+            .with_label_values(&[pool_type, artifact_type])
+            .observe(elapsed(inserted_at, now).as_secs_f64());
+    }
+
+    /// Returns the current number of artifacts of the given type in the given
+    /// section.
+    #[cfg(test)]
+    pub fn artifacts(&self, pool_type: &str, artifact_type: &str) -> i64 {
+        self.artifacts
+            .with_label_values(&[pool_type, artifact_type])
+            .get()
+    }
+}
+
+/// Returns the time that passed between the two given times, or zero if the
+/// clock went backwards.
+fn elapsed(from: Time, to: Time) -> Duration {
+    if to > from {
+        to - from
+    } else {
+        Duration::from_secs(0)
+    }
+}
@@ -0,0 +1,295 @@
+//! This module implements the migration of the validated sections of the
+//! consensus and certification pools from LMDB to RocksDB.
+//!
+//! Both backends store a pool in the same directory, `<db_path>/consensus`
+//! and `<db_path>/certification`, respectively. When a pool is opened with
+//! the RocksDB backend and the directory contains an LMDB environment, the
+//! environment is first moved to `<db_path>/lmdb_migration`, then all
+//! artifacts are copied into the RocksDB pool, and finally the LMDB
+//! environment is deleted. As inserting an artifact twice has no effect, an
+//! interrupted migration is simply resumed on the next start.
+
+use crate::consensus_pool::{InitializablePoolSection, MutablePoolSection, PoolSectionOps};
+use ic_config::artifact_pool::{LMDBConfig, RocksDBConfig};
+use ic_consensus_message::ConsensusMessageHashable;
+use ic_interfaces::consensus_pool::{HeightIndexedPool, PoolSection, ValidatedConsensusArtifact};
+use ic_logger::{info, ReplicaLogger};
+use ic_types::consensus::{
+    catchup::CUPWithOriginalProtobuf, certification::CertificationMessage, CatchUpPackage,
+};
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+
+/// The name of the directory the LMDB environments are moved to during the
+/// migration.
+const MIGRATION_DIR: &str = "lmdb_migration";
+
+/// The data file of an LMDB environment.
+const LMDB_DATA_FILE: &str = "data.mdb";
+
+/// The name of the directory of the consensus pool.
+const CONSENSUS_POOL_DIR: &str = "consensus";
+
+/// The name of the directory of the certification pool.
+const CERTIFICATION_POOL_DIR: &str = "certification";
+
+/// Migrates the validated section of the consensus pool to RocksDB, if the
+/// pool directory contains an LMDB environment. Returns true if artifacts
+/// were migrated.
+pub(crate) fn migrate_consensus_pool(config: &RocksDBConfig, log: &ReplicaLogger) -> bool {
+    let lmdb_config = match prepare_migration(config, CONSENSUS_POOL_DIR, log) {
+        Some(lmdb_config) => lmdb_config,
+        None => return false,
+    };
+    let source = crate::lmdb_pool::PersistentHeightIndexedPool::new_consensus_pool(
+        lmdb_config,
+        false,
+        log.clone(),
+    );
+    let mut target = crate::rocksdb_pool::PersistentHeightIndexedPool::new_consensus_pool(
+        config.clone(),
+        log.clone(),
+    );
+    let section = source.pool_section();
+    let mut count = 0;
+    count += copy_consensus_artifacts(section, section.random_beacon(), &mut target);
+    count += copy_consensus_artifacts(section, section.block_proposal(), &mut target);
+    count += copy_consensus_artifacts(section, section.notarization(), &mut target);
+    count += copy_consensus_artifacts(section, section.finalization(), &mut target);
+    count += copy_consensus_artifacts(section, section.random_beacon_share(), &mut target);
+    count += copy_consensus_artifacts(section, section.notarization_share(), &mut target);
+    count += copy_consensus_artifacts(section, section.finalization_share(), &mut target);
+    count += copy_consensus_artifacts(section, section.random_tape(), &mut target);
+    count += copy_consensus_artifacts(section, section.random_tape_share(), &mut target);
+    count += copy_consensus_artifacts(section, section.catch_up_package(), &mut target);
+    count += copy_consensus_artifacts(section, section.catch_up_package_share(), &mut target);
+    // Keep the original bytes of the highest catch-up package, which are
+    // needed to serve it to other replicas.
+    if section.catch_up_package().height_range().is_some() {
+        let protobuf = section.highest_catch_up_package_proto();
+        let cup =
+            CatchUpPackage::try_from(&protobuf).expect("CUP should be retrievable from protobuf");
+        target.insert_cup_with_proto(CUPWithOriginalProtobuf { cup, protobuf });
+    }
+    drop(source);
+    finish_migration(config, CONSENSUS_POOL_DIR, count, log);
+    true
+}
+
+/// Migrates the validated section of the certification pool to RocksDB, if
+/// the pool directory contains an LMDB environment. Returns true if
+/// artifacts were migrated.
+pub(crate) fn migrate_certification_pool(config: &RocksDBConfig, log: &ReplicaLogger) -> bool {
+    use crate::certification_pool::MutablePoolSection as _;
+
+    let lmdb_config = match prepare_migration(config, CERTIFICATION_POOL_DIR, log) {
+        Some(lmdb_config) => lmdb_config,
+        None => return false,
+    };
+    let source = crate::lmdb_pool::PersistentHeightIndexedPool::new_certification_pool(
+        lmdb_config,
+        false,
+        log.clone(),
+    );
+    let target = crate::rocksdb_pool::PersistentHeightIndexedPool::new_certification_pool(
+        config.clone(),
+        log.clone(),
+    );
+    let mut count = 0;
+    for certification in source.certifications().get_all() {
+        target.insert(CertificationMessage::Certification(certification));
+        count += 1;
+    }
+    for share in source.certification_shares().get_all() {
+        target.insert(CertificationMessage::CertificationShare(share));
+        count += 1;
+    }
+    drop(source);
+    finish_migration(config, CERTIFICATION_POOL_DIR, count, log);
+    true
+}
+
+/// Copies all artifacts of the given type from the source section into the
+/// target section, and returns their number.
+fn copy_consensus_artifacts<T: ConsensusMessageHashable>(
+    source: &dyn PoolSection<ValidatedConsensusArtifact>,
+    artifacts: &dyn HeightIndexedPool<T>,
+    target: &mut dyn InitializablePoolSection,
+) -> usize {
+    let mut ops = PoolSectionOps::new();
+    for artifact in artifacts.get_all() {
+        let msg = artifact.into_message();
+        let timestamp = source
+            .get_timestamp(&msg.get_id())
+            .expect("Timestamp of a validated artifact not found");
+        ops.insert(ValidatedConsensusArtifact { msg, timestamp });
+    }
+    let count = ops.ops.len();
+    target.mutate(ops);
+    count
+}
+
+/// Moves the LMDB environment of the pool with the given directory name out
+/// of the way of the RocksDB pool, and returns the configuration to open it.
+/// Returns None if there is nothing to migrate.
+fn prepare_migration(
+    config: &RocksDBConfig,
+    pool_dir: &str,
+    log: &ReplicaLogger,
+) -> Option<LMDBConfig> {
+    let pool_path = config
+        .persistent_pool_validated_persistent_db_path
+        .join(pool_dir);
+    let migration_path = migration_path(config);
+    let staged_pool_path = migration_path.join(pool_dir);
+    if is_lmdb_environment(&pool_path) {
+        info!(
+            log,
+            "Migrating the {} pool at {:?} from LMDB to RocksDB", pool_dir, pool_path
+        );
+        std::fs::create_dir_all(&migration_path)
+            .expect("Failed to create the LMDB migration directory");
+        if staged_pool_path.exists() {
+            std::fs::remove_dir_all(&staged_pool_path)
+                .expect("Failed to remove a stale LMDB migration directory");
+        }
+        std::fs::rename(&pool_path, &staged_pool_path)
+            .expect("Failed to move the LMDB pool to the migration directory");
+    } else if is_lmdb_environment(&staged_pool_path) {
+        info!(
+            log,
+            "Resuming the migration of the {} pool from LMDB to RocksDB", pool_dir
+        );
+    } else {
+        return None;
+    }
+    Some(LMDBConfig {
+        persistent_pool_validated_persistent_db_path: migration_path,
+    })
+}
+
+/// Deletes the migrated LMDB environment of the pool with the given directory
+/// name.
+fn finish_migration(config: &RocksDBConfig, pool_dir: &str, count: usize, log: &ReplicaLogger) {
+    let migration_path = migration_path(config);
+    std::fs::remove_dir_all(migration_path.join(pool_dir))
+        .expect("Failed to remove the migrated LMDB pool");
+    // Only succeeds once all pools were migrated.
+    std::fs::remove_dir(&migration_path).ok();
+    info!(
+        log,
+        "Migrated {} artifacts of the {} pool from LMDB to RocksDB", count, pool_dir
+    );
+}
+
+fn migration_path(config: &RocksDBConfig) -> PathBuf {
+    config
+        .persistent_pool_validated_persistent_db_path
+        .join(MIGRATION_DIR)
+}
+
+fn is_lmdb_environment(path: &Path) -> bool {
+    path.join(LMDB_DATA_FILE).is_file()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::certification_pool::MutablePoolSection as _;
+    use ic_consensus_message::make_genesis;
+    use ic_logger::replica_logger::no_op_logger;
+    use ic_test_utilities::consensus::fake::*;
+    use ic_types::{
+        consensus::certification::{Certification, CertificationContent},
+        consensus::{RandomBeacon, ThresholdSignature},
+        crypto::{CryptoHash, Signed},
+        CryptoHashOfPartialState, Height,
+    };
+
+    fn rocksdb_config(path: &Path) -> RocksDBConfig {
+        RocksDBConfig {
+            persistent_pool_validated_skip_fsync_for_tests: true,
+            persistent_pool_validated_persistent_db_path: path.to_path_buf(),
+            persistent_pool_validated_purge_interval: Height::from(5000),
+        }
+    }
+
+    fn lmdb_config(path: &Path) -> LMDBConfig {
+        LMDBConfig {
+            persistent_pool_validated_persistent_db_path: path.to_path_buf(),
+        }
+    }
+
+    #[test]
+    fn test_migrate_consensus_pool() {
+        let dir = tempfile::Builder::new().tempdir().unwrap();
+        let cup = make_genesis(ic_types::consensus::dkg::Summary::fake());
+        let random_beacon = RandomBeacon::from_parent(cup.content.random_beacon.as_ref());
+        {
+            let mut lmdb_pool = crate::lmdb_pool::PersistentHeightIndexedPool::new_consensus_pool(
+                lmdb_config(dir.path()),
+                false,
+                no_op_logger(),
+            );
+            lmdb_pool.insert_cup_with_proto(CUPWithOriginalProtobuf::from_cup(cup.clone()));
+            let mut ops = PoolSectionOps::new();
+            ops.insert(ValidatedConsensusArtifact {
+                msg: random_beacon.clone().into_message(),
+                timestamp: ic_test_utilities::mock_time(),
+            });
+            lmdb_pool.mutate(ops);
+        }
+
+        let config = rocksdb_config(dir.path());
+        assert!(migrate_consensus_pool(&config, &no_op_logger()));
+        assert!(!migrate_consensus_pool(&config, &no_op_logger()));
+        assert!(!dir.path().join(MIGRATION_DIR).exists());
+
+        let rocksdb_pool = crate::rocksdb_pool::PersistentHeightIndexedPool::new_consensus_pool(
+            config,
+            no_op_logger(),
+        );
+        let section = rocksdb_pool.pool_section();
+        assert_eq!(section.catch_up_package().get_highest().unwrap(), cup);
+        assert_eq!(
+            section.random_beacon().get_all().collect::<Vec<_>>(),
+            vec![random_beacon]
+        );
+        assert_eq!(
+            section.get_timestamp(&section.random_beacon().get_highest().unwrap().get_id()),
+            Some(ic_test_utilities::mock_time())
+        );
+    }
+
+    #[test]
+    fn test_migrate_certification_pool() {
+        let dir = tempfile::Builder::new().tempdir().unwrap();
+        let certification = Certification {
+            height: Height::from(3),
+            signed: Signed {
+                content: CertificationContent::new(CryptoHashOfPartialState::from(CryptoHash(
+                    Vec::new(),
+                ))),
+                signature: ThresholdSignature::fake(),
+            },
+        };
+        crate::lmdb_pool::PersistentHeightIndexedPool::new_certification_pool(
+            lmdb_config(dir.path()),
+            false,
+            no_op_logger(),
+        )
+        .insert(CertificationMessage::Certification(certification.clone()));
+
+        let config = rocksdb_config(dir.path());
+        assert!(migrate_certification_pool(&config, &no_op_logger()));
+
+        let rocksdb_pool = crate::rocksdb_pool::PersistentHeightIndexedPool::new_certification_pool(
+            config,
+            no_op_logger(),
+        );
+        assert_eq!(
+            rocksdb_pool.certifications().get_all().collect::<Vec<_>>(),
+            vec![certification]
+        );
+    }
+}
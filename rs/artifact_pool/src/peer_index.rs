@@ -11,7 +11,6 @@ impl PeerBucket {
         PeerBucket { quota_used: 0 }
     }
 
-    #[allow(dead_code)]
     fn get_quota_used(&self) -> usize {
         self.quota_used
     }
@@ -56,6 +55,14 @@ impl PeerIndex {
         }
     }
 
+    /// Returns the quota used by the given peer.
+    pub(crate) fn get_quota_used(&self, peer_id: &NodeId) -> usize {
+        self.peer_map
+            .get(peer_id)
+            .map_or(0, |bucket| bucket.get_quota_used())
+    }
+
+    #[allow(dead_code)]
     pub(crate) fn get_remaining_quota(&self, peer_id: &NodeId) -> usize {
         match self.peer_map.get(&peer_id) {
             Some(bucket) => {
@@ -0,0 +1,189 @@
+//! The query statistics pool holds the reports of the replicas on the queries
+//! they executed, until their epoch is over.
+use crate::message_pool::MessagePool;
+use ic_config::artifact_pool::{UnvalidatedEvictionPolicy, UnvalidatedSectionLimits};
+use ic_interfaces::artifact_pool::UnvalidatedArtifact;
+use ic_interfaces::gossip_pool::{GossipPool, QueryStatsGossipPool};
+use ic_interfaces::query_stats::{
+    MutableQueryStatsPool, QueryStatsChangeAction, QueryStatsChangeSet, QueryStatsPool,
+};
+use ic_metrics::MetricsRegistry;
+use ic_types::artifact::QueryStatsMessageId;
+use ic_types::consensus::query_stats::QueryStatsMessage;
+use ic_types::Height;
+
+const POOL_QUERY_STATS: &str = "query_stats";
+const ARTIFACT_TYPE_QUERY_STATS_MESSAGE: &str = "query_stats_message";
+
+/// The limits of the unvalidated section of the query statistics pool. Every
+/// replica reports once per epoch, but a report grows with the number of
+/// canisters that were queried.
+const UNVALIDATED_LIMITS: UnvalidatedSectionLimits = UnvalidatedSectionLimits {
+    max_count: 1_000,
+    max_size_bytes: 256 * 1024 * 1024,
+    eviction_policy: UnvalidatedEvictionPolicy::OldestFirst,
+};
+
+/// The in-memory pool of query statistics reports.
+pub struct QueryStatsPoolImpl {
+    pool: MessagePool<QueryStatsMessage>,
+}
+
+impl QueryStatsPoolImpl {
+    pub fn new(metrics_registry: MetricsRegistry) -> Self {
+        Self {
+            pool: MessagePool::new(
+                &metrics_registry,
+                POOL_QUERY_STATS,
+                ARTIFACT_TYPE_QUERY_STATS_MESSAGE,
+                UNVALIDATED_LIMITS,
+            ),
+        }
+    }
+}
+
+impl QueryStatsPool for QueryStatsPoolImpl {
+    fn get_validated(&self) -> Box<dyn Iterator<Item = &QueryStatsMessage> + '_> {
+        Box::new(self.pool.validated())
+    }
+
+    fn get_unvalidated(&self) -> Box<dyn Iterator<Item = &QueryStatsMessage> + '_> {
+        Box::new(self.pool.unvalidated())
+    }
+}
+
+impl MutableQueryStatsPool for QueryStatsPoolImpl {
+    fn insert(&mut self, artifact: UnvalidatedArtifact<QueryStatsMessage>) {
+        // The eviction policy does not depend on the height.
+        self.pool.insert(artifact, Height::from(0));
+    }
+
+    /// Applies the provided change set atomically.
+    ///
+    /// # Panics
+    ///
+    /// It panics if a report to be moved into the validated section cannot be
+    /// found in the unvalidated section.
+    fn apply_changes(&mut self, change_set: QueryStatsChangeSet) {
+        for action in change_set {
+            match action {
+                QueryStatsChangeAction::AddToValidated(msg) => {
+                    self.pool.add_to_validated(msg);
+                }
+                QueryStatsChangeAction::MoveToValidated(msg) => {
+                    self.pool.move_to_validated(msg);
+                }
+                QueryStatsChangeAction::RemoveFromUnvalidated(id)
+                | QueryStatsChangeAction::HandleInvalid(id, _) => {
+                    self.pool.remove_unvalidated(&id);
+                }
+                // Removes all reports of epochs below the given one.
+                QueryStatsChangeAction::PurgeBelowEpoch(epoch) => {
+                    self.pool.purge(|msg| msg.content.epoch < epoch)
+                }
+            }
+        }
+    }
+}
+
+impl GossipPool<QueryStatsMessage, QueryStatsChangeSet> for QueryStatsPoolImpl {
+    type MessageId = QueryStatsMessageId;
+    type Filter = ();
+
+    fn contains(&self, id: &Self::MessageId) -> bool {
+        self.pool.contains(id)
+    }
+
+    fn get_validated_by_identifier(&self, id: &Self::MessageId) -> Option<QueryStatsMessage> {
+        self.pool.get_validated(id).cloned()
+    }
+
+    fn get_all_validated_by_filter(
+        &self,
+        _filter: Self::Filter,
+    ) -> Box<dyn Iterator<Item = QueryStatsMessage> + '_> {
+        Box::new(self.pool.validated().cloned())
+    }
+}
+
+impl QueryStatsGossipPool for QueryStatsPoolImpl {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_test_utilities::{mock_time, types::ids::node_test_id};
+    use ic_types::consensus::query_stats::QueryStatsEpoch;
+    use ic_types::consensus::{query_stats::QueryStatsContent, BasicSignature};
+    use ic_types::crypto::{BasicSig, BasicSigOf};
+    use ic_types::RegistryVersion;
+    use std::collections::BTreeMap;
+
+    fn make_report(epoch: u64, signer: u64) -> QueryStatsMessage {
+        QueryStatsMessage {
+            content: QueryStatsContent {
+                epoch: QueryStatsEpoch::from(epoch),
+                stats: BTreeMap::new(),
+                registry_version: RegistryVersion::from(1),
+            },
+            signature: BasicSignature {
+                signature: BasicSigOf::new(BasicSig(vec![])),
+                signer: node_test_id(signer),
+            },
+        }
+    }
+
+    fn insert(pool: &mut QueryStatsPoolImpl, report: &QueryStatsMessage) {
+        pool.insert(UnvalidatedArtifact {
+            message: report.clone(),
+            peer_id: report.signature.signer,
+            timestamp: mock_time(),
+        });
+    }
+
+    fn epochs<'a>(reports: impl Iterator<Item = &'a QueryStatsMessage>) -> Vec<u64> {
+        let mut epochs: Vec<_> = reports.map(|report| report.content.epoch.get()).collect();
+        epochs.sort_unstable();
+        epochs
+    }
+
+    #[test]
+    fn the_reports_of_all_replicas_are_kept() {
+        let mut pool = QueryStatsPoolImpl::new(MetricsRegistry::new());
+        let reports: Vec<_> = (1..=3).map(|signer| make_report(1, signer)).collect();
+        for report in reports.iter() {
+            insert(&mut pool, report);
+        }
+        pool.apply_changes(vec![
+            QueryStatsChangeAction::AddToValidated(make_report(1, 0)),
+            QueryStatsChangeAction::MoveToValidated(reports[0].clone()),
+            QueryStatsChangeAction::HandleInvalid(
+                ic_crypto::crypto_hash(&reports[1]),
+                "invalid".to_string(),
+            ),
+        ]);
+        assert_eq!(pool.get_validated().count(), 2);
+        assert_eq!(
+            pool.get_unvalidated().collect::<Vec<_>>(),
+            vec![&reports[2]]
+        );
+        assert_eq!(
+            pool.get_validated_by_identifier(&ic_crypto::crypto_hash(&reports[0])),
+            Some(reports[0].clone())
+        );
+    }
+
+    #[test]
+    fn reports_of_past_epochs_are_removed() {
+        let mut pool = QueryStatsPoolImpl::new(MetricsRegistry::new());
+        for epoch in 1..=3 {
+            insert(&mut pool, &make_report(epoch, 1));
+        }
+        pool.apply_changes(vec![
+            QueryStatsChangeAction::MoveToValidated(make_report(1, 1)),
+            QueryStatsChangeAction::AddToValidated(make_report(2, 0)),
+            QueryStatsChangeAction::PurgeBelowEpoch(QueryStatsEpoch::from(2)),
+        ]);
+        assert_eq!(epochs(pool.get_validated()), vec![2]);
+        assert_eq!(epochs(pool.get_unvalidated()), vec![2, 3]);
+    }
+}
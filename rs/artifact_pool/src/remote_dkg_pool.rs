@@ -0,0 +1,179 @@
+//! The remote DKG pool holds the requests and transcripts of the DKGs that
+//! the subnet runs for other subnets, until a newer DKG summary replaces
+//! them.
+use crate::message_pool::MessagePool;
+use ic_config::artifact_pool::{UnvalidatedEvictionPolicy, UnvalidatedSectionLimits};
+use ic_interfaces::artifact_pool::UnvalidatedArtifact;
+use ic_interfaces::gossip_pool::{GossipPool, RemoteDkgGossipPool};
+use ic_interfaces::remote_dkg::{
+    MutableRemoteDkgPool, RemoteDkgChangeAction, RemoteDkgChangeSet, RemoteDkgPool,
+};
+use ic_metrics::MetricsRegistry;
+use ic_types::artifact::RemoteDkgMessageId;
+use ic_types::consensus::{remote_dkg::RemoteDkgMessage, HasHeight};
+
+const POOL_REMOTE_DKG: &str = "remote_dkg";
+const ARTIFACT_TYPE_REMOTE_DKG_MESSAGE: &str = "remote_dkg_message";
+
+/// The limits of the unvalidated section of the remote DKG pool. A summary
+/// only contains a few remote DKGs, but their transcripts can be large, and
+/// only the messages of the latest summary are useful.
+const UNVALIDATED_LIMITS: UnvalidatedSectionLimits = UnvalidatedSectionLimits {
+    max_count: 1_000,
+    max_size_bytes: 256 * 1024 * 1024,
+    eviction_policy: UnvalidatedEvictionPolicy::LowestHeightFirst,
+};
+
+/// The in-memory pool of remote DKG messages.
+pub struct RemoteDkgPoolImpl {
+    pool: MessagePool<RemoteDkgMessage>,
+}
+
+impl RemoteDkgPoolImpl {
+    pub fn new(metrics_registry: MetricsRegistry) -> Self {
+        Self {
+            pool: MessagePool::new(
+                &metrics_registry,
+                POOL_REMOTE_DKG,
+                ARTIFACT_TYPE_REMOTE_DKG_MESSAGE,
+                UNVALIDATED_LIMITS,
+            ),
+        }
+    }
+}
+
+impl RemoteDkgPool for RemoteDkgPoolImpl {
+    fn get_validated(&self) -> Box<dyn Iterator<Item = &RemoteDkgMessage> + '_> {
+        Box::new(self.pool.validated())
+    }
+
+    fn get_unvalidated(&self) -> Box<dyn Iterator<Item = &RemoteDkgMessage> + '_> {
+        Box::new(self.pool.unvalidated())
+    }
+}
+
+impl MutableRemoteDkgPool for RemoteDkgPoolImpl {
+    fn insert(&mut self, artifact: UnvalidatedArtifact<RemoteDkgMessage>) {
+        let height = artifact.message.height();
+        self.pool.insert(artifact, height);
+    }
+
+    /// Applies the provided change set atomically.
+    ///
+    /// # Panics
+    ///
+    /// It panics if a message to be moved into the validated section cannot be
+    /// found in the unvalidated section.
+    fn apply_changes(&mut self, change_set: RemoteDkgChangeSet) {
+        for action in change_set {
+            match action {
+                RemoteDkgChangeAction::AddToValidated(msg) => {
+                    self.pool.add_to_validated(msg);
+                }
+                RemoteDkgChangeAction::MoveToValidated(msg) => {
+                    self.pool.move_to_validated(msg);
+                }
+                RemoteDkgChangeAction::RemoveFromUnvalidated(id)
+                | RemoteDkgChangeAction::HandleInvalid(id, _) => {
+                    self.pool.remove_unvalidated(&id);
+                }
+                // Removes all messages of DKG summaries below the given height.
+                RemoteDkgChangeAction::PurgeBelow(height) => {
+                    self.pool.purge(|msg| msg.height() < height)
+                }
+            }
+        }
+    }
+}
+
+impl GossipPool<RemoteDkgMessage, RemoteDkgChangeSet> for RemoteDkgPoolImpl {
+    type MessageId = RemoteDkgMessageId;
+    type Filter = ();
+
+    fn contains(&self, id: &Self::MessageId) -> bool {
+        self.pool.contains(id)
+    }
+
+    fn get_validated_by_identifier(&self, id: &Self::MessageId) -> Option<RemoteDkgMessage> {
+        self.pool.get_validated(id).cloned()
+    }
+
+    fn get_all_validated_by_filter(
+        &self,
+        _filter: Self::Filter,
+    ) -> Box<dyn Iterator<Item = RemoteDkgMessage> + '_> {
+        Box::new(self.pool.validated().cloned())
+    }
+}
+
+impl RemoteDkgGossipPool for RemoteDkgPoolImpl {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_test_utilities::{
+        mock_time,
+        types::ids::{node_test_id, subnet_test_id},
+    };
+    use ic_types::consensus::remote_dkg::RemoteDkgResponse;
+    use ic_types::crypto::threshold_sig::ni_dkg::{
+        NiDkgId, NiDkgTag, NiDkgTargetId, NiDkgTargetSubnet,
+    };
+    use ic_types::Height;
+
+    /// Returns the response of the summary at the given height to the DKG
+    /// that was started at the given height.
+    fn make_response(summary_height: u64, start_block_height: u64) -> RemoteDkgMessage {
+        RemoteDkgMessage::Response(RemoteDkgResponse {
+            summary_height: Height::from(summary_height),
+            dkg_id: NiDkgId {
+                start_block_height: Height::from(start_block_height),
+                dealer_subnet: subnet_test_id(0),
+                dkg_tag: NiDkgTag::HighThreshold,
+                target_subnet: NiDkgTargetSubnet::Remote(NiDkgTargetId::new([1; 32])),
+            },
+            transcript: Err("Not enough dealings".to_string()),
+        })
+    }
+
+    fn insert(pool: &mut RemoteDkgPoolImpl, msg: &RemoteDkgMessage, peer: u64) {
+        pool.insert(UnvalidatedArtifact {
+            message: msg.clone(),
+            peer_id: node_test_id(peer),
+            timestamp: mock_time(),
+        });
+    }
+
+    #[test]
+    fn a_message_sent_by_several_peers_is_stored_once() {
+        let mut pool = RemoteDkgPoolImpl::new(MetricsRegistry::new());
+        let msg = make_response(5, 5);
+        insert(&mut pool, &msg, 1);
+        insert(&mut pool, &msg, 2);
+        assert_eq!(pool.get_unvalidated().count(), 1);
+
+        pool.apply_changes(vec![RemoteDkgChangeAction::MoveToValidated(msg.clone())]);
+        insert(&mut pool, &msg, 3);
+        assert_eq!(pool.get_unvalidated().count(), 0);
+        assert_eq!(
+            pool.get_all_validated_by_filter(()).collect::<Vec<_>>(),
+            vec![msg]
+        );
+    }
+
+    #[test]
+    fn messages_are_purged_by_the_height_of_their_summary() {
+        let mut pool = RemoteDkgPoolImpl::new(MetricsRegistry::new());
+        // The DKG started in an earlier interval, but its transcript is in the
+        // latest summary, so it must be kept.
+        let latest = make_response(10, 0);
+        let replaced = make_response(5, 5);
+        insert(&mut pool, &replaced, 1);
+        pool.apply_changes(vec![
+            RemoteDkgChangeAction::AddToValidated(latest.clone()),
+            RemoteDkgChangeAction::PurgeBelow(Height::from(10)),
+        ]);
+        assert!(!pool.contains(&ic_crypto::crypto_hash(&replaced)));
+        assert_eq!(pool.get_validated().collect::<Vec<_>>(), vec![&latest]);
+    }
+}
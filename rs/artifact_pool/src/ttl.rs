@@ -0,0 +1,71 @@
+use ic_types::Time;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// The minimum time between two purges of expired artifacts from a pool, so
+/// that the pool is not scanned on every change.
+const TTL_PURGE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// ArtifactTtls holds the retention durations of the artifact types of a pool,
+/// and decides when to purge the artifacts that are older than the duration
+/// of their type.
+pub(crate) struct ArtifactTtls {
+    ttls: BTreeMap<String, Duration>,
+    last_purge: Option<Time>,
+}
+
+impl ArtifactTtls {
+    pub(crate) fn new(ttls: BTreeMap<String, Duration>) -> Self {
+        Self {
+            ttls,
+            last_purge: None,
+        }
+    }
+
+    /// Returns true if expired artifacts should be purged at the given time,
+    /// and if so, records the purge.
+    pub(crate) fn purge_due(&mut self, now: Time) -> bool {
+        if self.ttls.is_empty() {
+            return false;
+        }
+        match self.last_purge {
+            Some(last_purge) if now < last_purge + TTL_PURGE_INTERVAL => false,
+            _ => {
+                self.last_purge = Some(now);
+                true
+            }
+        }
+    }
+
+    /// Returns true if an artifact of the given type with the given timestamp
+    /// is expired at the given time.
+    pub(crate) fn is_expired(&self, artifact_type: &str, timestamp: Time, now: Time) -> bool {
+        self.ttls
+            .get(artifact_type)
+            .map_or(false, |ttl| timestamp + *ttl <= now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_test_utilities::mock_time;
+
+    #[test]
+    fn test_artifact_ttls() {
+        let mut ttls = ArtifactTtls::new(
+            vec![("notarization_share".to_string(), Duration::from_secs(60))]
+                .into_iter()
+                .collect(),
+        );
+        let now = mock_time() + Duration::from_secs(100);
+        assert!(ttls.is_expired("notarization_share", mock_time(), now));
+        assert!(!ttls.is_expired("notarization_share", now, now));
+        assert!(!ttls.is_expired("finalization", mock_time(), now));
+
+        assert!(ttls.purge_due(now));
+        assert!(!ttls.purge_due(now + Duration::from_secs(1)));
+        assert!(ttls.purge_due(now + TTL_PURGE_INTERVAL));
+        assert!(!ArtifactTtls::new(BTreeMap::new()).purge_due(now));
+    }
+}
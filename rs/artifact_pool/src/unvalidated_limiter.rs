@@ -0,0 +1,211 @@
+use ic_config::artifact_pool::{UnvalidatedEvictionPolicy, UnvalidatedSectionLimits};
+use ic_types::Height;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+/// The position of an artifact in the eviction order. Artifacts with a lower
+/// position are evicted first.
+type EvictionOrder = (u64, u64);
+
+/// The bookkeeping of a tracked artifact.
+struct TrackedArtifact {
+    order: EvictionOrder,
+    height: Height,
+    size_bytes: usize,
+}
+
+/// The outcome of admitting an artifact into an unvalidated section.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Admission<K> {
+    /// The artifact can be inserted after evicting the given artifacts.
+    Accepted { evicted: Vec<K> },
+    /// The artifact must not be inserted, because it does not fit into the
+    /// section or because it would be evicted first.
+    Rejected,
+}
+
+/// UnvalidatedLimiter keeps track of the number and total size of the
+/// artifacts in an unvalidated pool section, and decides which artifacts to
+/// evict to keep the section within its limits, protecting the memory of the
+/// replica against peers flooding it with bogus artifacts.
+///
+/// The limiter does not store the artifacts. The pool has to remove the
+/// evicted artifacts itself, and has to report all other removals to the
+/// limiter.
+pub(crate) struct UnvalidatedLimiter<K> {
+    limits: UnvalidatedSectionLimits,
+    artifacts: HashMap<K, TrackedArtifact>,
+    eviction_order: BTreeMap<EvictionOrder, K>,
+    size_bytes: usize,
+    next_sequence_number: u64,
+}
+
+impl<K: Clone + Eq + Hash> UnvalidatedLimiter<K> {
+    pub(crate) fn new(limits: UnvalidatedSectionLimits) -> Self {
+        Self {
+            limits,
+            artifacts: HashMap::new(),
+            eviction_order: BTreeMap::new(),
+            size_bytes: 0,
+            next_sequence_number: 0,
+        }
+    }
+
+    /// Admits the artifact with the given key, height and size into the
+    /// section, and returns the artifacts that have to be evicted to make
+    /// room for it. Admitting an artifact that is already tracked evicts
+    /// nothing.
+    pub(crate) fn admit(&mut self, key: K, height: Height, size_bytes: usize) -> Admission<K> {
+        if self.artifacts.contains_key(&key) {
+            return Admission::Accepted {
+                evicted: Vec::new(),
+            };
+        }
+        if self.limits.max_count == 0 || size_bytes > self.limits.max_size_bytes {
+            return Admission::Rejected;
+        }
+        let sequence_number = self.next_sequence_number;
+        let order = match self.limits.eviction_policy {
+            UnvalidatedEvictionPolicy::OldestFirst => (sequence_number, 0),
+            UnvalidatedEvictionPolicy::LowestHeightFirst => (height.get(), sequence_number),
+        };
+
+        // Find the artifacts to evict before evicting any, so that a rejected
+        // artifact leaves the section unchanged.
+        let mut count = self.artifacts.len() + 1;
+        let mut total_size_bytes = self.size_bytes + size_bytes;
+        let mut evicted = Vec::new();
+        for (victim_order, victim) in self.eviction_order.iter() {
+            if count <= self.limits.max_count && total_size_bytes <= self.limits.max_size_bytes {
+                break;
+            }
+            if *victim_order > order {
+                return Admission::Rejected;
+            }
+            count -= 1;
+            total_size_bytes -= self.artifacts[victim].size_bytes;
+            evicted.push(victim.clone());
+        }
+
+        for victim in evicted.iter() {
+            self.remove(victim);
+        }
+        self.next_sequence_number += 1;
+        self.size_bytes += size_bytes;
+        self.eviction_order.insert(order, key.clone());
+        self.artifacts.insert(
+            key,
+            TrackedArtifact {
+                order,
+                height,
+                size_bytes,
+            },
+        );
+        Admission::Accepted { evicted }
+    }
+
+    /// Stops tracking the artifact with the given key.
+    pub(crate) fn remove(&mut self, key: &K) {
+        if let Some(artifact) = self.artifacts.remove(key) {
+            self.eviction_order.remove(&artifact.order);
+            self.size_bytes -= artifact.size_bytes;
+        }
+    }
+
+    /// Stops tracking all artifacts below the given height.
+    pub(crate) fn remove_all_below(&mut self, height: Height) {
+        let keys: Vec<K> = self
+            .artifacts
+            .iter()
+            .filter(|(_, artifact)| artifact.height < height)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in keys.iter() {
+            self.remove(key);
+        }
+    }
+
+    /// Returns the number of tracked artifacts.
+    #[cfg(test)]
+    pub(crate) fn count(&self) -> usize {
+        self.artifacts.len()
+    }
+
+    /// Returns the total size of the tracked artifacts, in bytes.
+    #[cfg(test)]
+    pub(crate) fn size_bytes(&self) -> usize {
+        self.size_bytes
+    }
+}
+
+/// Returns the size of the given artifact, as counted against the size limit
+/// of an unvalidated section. Artifacts that cannot be serialized count as
+/// infinitely large.
+pub(crate) fn artifact_size_bytes<T: Serialize>(artifact: &T) -> usize {
+    bincode::serialized_size(artifact).map_or(usize::MAX, |size| size as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(
+        max_count: usize,
+        max_size_bytes: usize,
+        eviction_policy: UnvalidatedEvictionPolicy,
+    ) -> UnvalidatedLimiter<u32> {
+        UnvalidatedLimiter::new(UnvalidatedSectionLimits {
+            max_count,
+            max_size_bytes,
+            eviction_policy,
+        })
+    }
+
+    fn accepted(evicted: Vec<u32>) -> Admission<u32> {
+        Admission::Accepted { evicted }
+    }
+
+    #[test]
+    fn test_oldest_first_evicts_in_insertion_order() {
+        let mut limiter = limiter(2, 100, UnvalidatedEvictionPolicy::OldestFirst);
+        assert_eq!(limiter.admit(1, Height::from(5), 10), accepted(vec![]));
+        assert_eq!(limiter.admit(2, Height::from(1), 10), accepted(vec![]));
+        assert_eq!(limiter.admit(3, Height::from(3), 10), accepted(vec![1]));
+        assert_eq!(limiter.admit(3, Height::from(3), 10), accepted(vec![]));
+        assert_eq!(limiter.count(), 2);
+        assert_eq!(limiter.size_bytes(), 20);
+    }
+
+    #[test]
+    fn test_lowest_height_first_rejects_lowest_artifact() {
+        let mut limiter = limiter(2, 100, UnvalidatedEvictionPolicy::LowestHeightFirst);
+        assert_eq!(limiter.admit(1, Height::from(5), 10), accepted(vec![]));
+        assert_eq!(limiter.admit(2, Height::from(3), 10), accepted(vec![]));
+        assert_eq!(limiter.admit(3, Height::from(1), 10), Admission::Rejected);
+        assert_eq!(limiter.admit(4, Height::from(7), 10), accepted(vec![2]));
+        assert_eq!(limiter.count(), 2);
+    }
+
+    #[test]
+    fn test_size_limit() {
+        let mut limiter = limiter(10, 100, UnvalidatedEvictionPolicy::OldestFirst);
+        assert_eq!(limiter.admit(1, Height::from(1), 101), Admission::Rejected);
+        assert_eq!(limiter.admit(2, Height::from(1), 40), accepted(vec![]));
+        assert_eq!(limiter.admit(3, Height::from(1), 40), accepted(vec![]));
+        assert_eq!(limiter.admit(4, Height::from(1), 60), accepted(vec![2, 3]));
+        assert_eq!(limiter.size_bytes(), 60);
+    }
+
+    #[test]
+    fn test_removals_free_capacity() {
+        let mut limiter = limiter(2, 100, UnvalidatedEvictionPolicy::OldestFirst);
+        limiter.admit(1, Height::from(1), 10);
+        limiter.admit(2, Height::from(2), 10);
+        limiter.remove(&2);
+        limiter.remove_all_below(Height::from(2));
+        assert_eq!(limiter.count(), 0);
+        assert_eq!(limiter.size_bytes(), 0);
+        assert_eq!(limiter.admit(3, Height::from(3), 10), accepted(vec![]));
+    }
+}
@@ -0,0 +1,657 @@
+//! Backups of a node, from which a fresh node can be restored.
+//!
+//! A backup bundle is a directory holding the highest catch-up package (CUP)
+//! of the consensus pool, the checkpoint at the height of the CUP, and the
+//! validated consensus artifacts at and above that height:
+//!
+//! ```text
+//! <bundle>
+//! ├── catch_up_package.pb   protobuf of the CUP, as stored in the pool
+//! ├── artifacts.bin         bincode of the artifacts, as `Vec<ConsensusMessage>`
+//! └── checkpoint/           copy of the checkpoint at the height of the CUP
+//! ```
+//!
+//! The CUP and the checkpoint belong together: a node starts from the state
+//! of the checkpoint and continues from the block of the CUP. A snapshot is
+//! therefore written to a temporary directory, the state hash of the copied
+//! checkpoint is compared to the state hash of the CUP, and only then the
+//! temporary directory is renamed to the bundle, such that a bundle either
+//! exists and is consistent, or does not exist at all.
+//!
+//! [`snapshot`] takes the snapshot of a stopped node. [`snapshot_running`]
+//! takes it from within a running node: the pool is opened read-only and the
+//! height of the CUP is pinned in the state manager while the checkpoint is
+//! copied, so that the checkpoint is not removed in the meantime.
+//!
+//! Before a bundle is restored, it is verified against a CUP that the operator
+//! trusts, e.g. one fetched from a healthy node of the subnet. A bundle is only
+//! restored onto a fresh node, i.e. one with neither a CUP in its consensus
+//! pool nor a checkpoint. Only the CUP and the checkpoint are restored: the
+//! artifacts above the CUP are not covered by the trusted CUP, and the
+//! unvalidated section of the pool is not persisted, so the node fetches them
+//! from its peers and validates them instead. The artifacts are kept in the
+//! bundle for inspection.
+
+use ic_artifact_pool::consensus_pool::UncachedConsensusPoolImpl;
+use ic_config::artifact_pool::ArtifactPoolConfig;
+use ic_consensus_message::ConsensusMessageHashable;
+use ic_interfaces::consensus_pool::{
+    HeightIndexedPool, HeightRange, PoolSection, ValidatedConsensusArtifact,
+};
+use ic_logger::{info, ReplicaLogger};
+use ic_metrics::MetricsRegistry;
+use ic_protobuf::types::v1 as pb;
+use ic_state_layout::{CheckpointLayout, LayoutError, ReadOnly, RwPolicy, StateLayout};
+use ic_state_manager::{
+    manifest::{compute_manifest, manifest_hash, DEFAULT_CHUNK_SIZE},
+    CheckpointError, PinHeightError, StateManagerImpl,
+};
+use ic_types::{
+    consensus::{catchup::CUPWithOriginalProtobuf, CatchUpPackage, ConsensusMessage, HasHeight},
+    crypto::CryptoHash,
+    CryptoHashOfState, Height,
+};
+use ic_utils::fs::{copy_file_sparse, sync_path};
+use prost::Message;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const CATCH_UP_PACKAGE_FILE: &str = "catch_up_package.pb";
+const ARTIFACTS_FILE: &str = "artifacts.bin";
+const CHECKPOINT_DIR: &str = "checkpoint";
+
+#[derive(Debug)]
+pub enum BackupError {
+    /// Wraps a `std::io::Error`, a message and the path of the affected
+    /// file/directory.
+    IoError {
+        path: PathBuf,
+        message: String,
+        io_err: std::io::Error,
+    },
+
+    /// Wraps an error of the state layout.
+    LayoutError(LayoutError),
+
+    /// The manifest of the checkpoint could not be computed.
+    ManifestError(CheckpointError),
+
+    /// The bundle at the given path is malformed.
+    CorruptedBundle { path: PathBuf, message: String },
+
+    /// The consensus pool has no CUP.
+    NoCatchUpPackage,
+
+    /// The CUP of the consensus pool could not be decoded.
+    InvalidCatchUpPackage(String),
+
+    /// There is no checkpoint at the height of the CUP.
+    MissingCheckpoint(Height),
+
+    /// The state hash of the checkpoint differs from the one of the CUP.
+    StateHashMismatch {
+        height: Height,
+        expected: CryptoHashOfState,
+        computed: CryptoHashOfState,
+    },
+
+    /// The CUP of the bundle differs from the trusted CUP.
+    CatchUpPackageMismatch {
+        bundle_height: Height,
+        trusted_height: Height,
+    },
+
+    /// The node to restore onto is not fresh.
+    NodeNotFresh(String),
+
+    /// The height of the CUP could not be pinned.
+    PinFailed(PinHeightError),
+}
+
+impl fmt::Display for BackupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IoError {
+                path,
+                message,
+                io_err,
+            } => write!(
+                f,
+                "I/O error while accessing {}: {}: {}",
+                path.display(),
+                message,
+                io_err
+            ),
+            Self::LayoutError(err) => write!(f, "state layout error: {}", err),
+            Self::ManifestError(err) => {
+                write!(f, "failed to compute the checkpoint manifest: {}", err)
+            }
+            Self::CorruptedBundle { path, message } => {
+                write!(f, "corrupted bundle at {}: {}", path.display(), message)
+            }
+            Self::NoCatchUpPackage => write!(f, "the consensus pool has no catch-up package"),
+            Self::InvalidCatchUpPackage(message) => {
+                write!(f, "invalid catch-up package: {}", message)
+            }
+            Self::MissingCheckpoint(height) => write!(f, "no checkpoint @{} found", height),
+            Self::StateHashMismatch {
+                height,
+                expected,
+                computed,
+            } => write!(
+                f,
+                "the state hash of the checkpoint @{} is {:?}, but the catch-up package expects {:?}",
+                height, computed, expected
+            ),
+            Self::CatchUpPackageMismatch {
+                bundle_height,
+                trusted_height,
+            } => write!(
+                f,
+                "the catch-up package @{} of the bundle differs from the trusted one @{}",
+                bundle_height, trusted_height
+            ),
+            Self::NodeNotFresh(message) => write!(f, "the node is not fresh: {}", message),
+            Self::PinFailed(err) => write!(f, "failed to pin the checkpoint: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for BackupError {}
+
+impl From<LayoutError> for BackupError {
+    fn from(err: LayoutError) -> Self {
+        Self::LayoutError(err)
+    }
+}
+
+fn io_error(path: &Path, message: &str) -> impl FnOnce(std::io::Error) -> BackupError {
+    let path = path.to_path_buf();
+    let message = message.to_string();
+    move |io_err| BackupError::IoError {
+        path,
+        message,
+        io_err,
+    }
+}
+
+/// A backup bundle on disk.
+pub struct BackupBundle {
+    path: PathBuf,
+    cup: CatchUpPackage,
+}
+
+impl BackupBundle {
+    /// Opens the bundle in the given directory.
+    pub fn open(path: &Path) -> Result<Self, BackupError> {
+        let cup_path = path.join(CATCH_UP_PACKAGE_FILE);
+        let bytes = fs::read(&cup_path).map_err(io_error(&cup_path, "failed to read the CUP"))?;
+        let cup = pb::CatchUpPackage::decode(&bytes[..])
+            .map_err(|err| err.to_string())
+            .and_then(|proto| CatchUpPackage::try_from(&proto).map_err(|err| err.to_string()))
+            .map_err(|message| BackupError::CorruptedBundle {
+                path: cup_path,
+                message,
+            })?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            cup,
+        })
+    }
+
+    /// The CUP of the bundle.
+    pub fn catch_up_package(&self) -> &CatchUpPackage {
+        &self.cup
+    }
+
+    /// The height of the CUP and the checkpoint of the bundle.
+    pub fn height(&self) -> Height {
+        self.cup.height()
+    }
+
+    /// Verifies that the CUP of the bundle is the trusted CUP, and that the
+    /// checkpoint of the bundle has the state hash of the CUP.
+    pub fn verify(&self, trusted_cup: &CatchUpPackage) -> Result<(), BackupError> {
+        if &self.cup != trusted_cup {
+            return Err(BackupError::CatchUpPackageMismatch {
+                bundle_height: self.height(),
+                trusted_height: trusted_cup.height(),
+            });
+        }
+        verify_checkpoint(&self.path.join(CHECKPOINT_DIR), &self.cup)
+    }
+
+    /// Verifies the bundle against the trusted CUP and restores it onto the
+    /// fresh node with the given consensus pool and state root.
+    pub fn restore(
+        &self,
+        trusted_cup: &CatchUpPackage,
+        pool_config: ArtifactPoolConfig,
+        state_root: &Path,
+        log: &ReplicaLogger,
+    ) -> Result<(), BackupError> {
+        self.verify(trusted_cup)?;
+
+        let state_layout = StateLayout::new(log.clone(), state_root.to_path_buf());
+        if let Some(height) = state_layout.checkpoint_heights()?.last() {
+            return Err(BackupError::NodeNotFresh(format!(
+                "the state root {} has a checkpoint @{}",
+                state_root.display(),
+                height
+            )));
+        }
+        let mut pool = open_consensus_pool(pool_config, false, log);
+        if let Ok(cup) = pool.validated.catch_up_package().get_highest() {
+            return Err(BackupError::NodeNotFresh(format!(
+                "the consensus pool has a CUP @{}",
+                cup.height()
+            )));
+        }
+
+        // The checkpoint is restored first, so that an interrupted restore
+        // leaves a node without a CUP, which is still restorable after the
+        // checkpoint is removed.
+        let height = self.height();
+        let scratchpad = state_layout.tmp()?.join(format!("restore_{}", height));
+        if scratchpad.exists() {
+            fs::remove_dir_all(&scratchpad)
+                .map_err(io_error(&scratchpad, "failed to remove a stale scratchpad"))?;
+        }
+        copy_recursively(&self.path.join(CHECKPOINT_DIR), &scratchpad)?;
+        let layout = CheckpointLayout::<RwPolicy>::new(scratchpad, height)?;
+        state_layout.scratchpad_to_checkpoint(layout, height)?;
+
+        pool.validated
+            .insert_cup_with_proto(CUPWithOriginalProtobuf::from_cup(self.cup.clone()));
+
+        info!(
+            log,
+            "Restored the backup @{} from {}",
+            height,
+            self.path.display()
+        );
+        Ok(())
+    }
+
+    /// Reads the artifacts of the bundle. The artifacts are not verified
+    /// against the CUP and are not restored.
+    pub fn read_artifacts(&self) -> Result<Vec<ConsensusMessage>, BackupError> {
+        let path = self.path.join(ARTIFACTS_FILE);
+        let bytes = fs::read(&path).map_err(io_error(&path, "failed to read the artifacts"))?;
+        bincode::deserialize(&bytes).map_err(|err| BackupError::CorruptedBundle {
+            path,
+            message: err.to_string(),
+        })
+    }
+}
+
+/// Takes a snapshot of the highest CUP of the consensus pool, the checkpoint
+/// at its height and the artifacts above it, and writes it to a bundle at the
+/// given destination, which must not exist.
+///
+/// The node must be stopped, as nothing prevents the checkpoint from being
+/// removed while it is copied. Use [`snapshot_running`] for a running node.
+pub fn snapshot(
+    pool_config: ArtifactPoolConfig,
+    state_root: &Path,
+    destination: &Path,
+    log: &ReplicaLogger,
+) -> Result<BackupBundle, BackupError> {
+    let state_layout = StateLayout::new(log.clone(), state_root.to_path_buf());
+    write_bundle(pool_config, &state_layout, None, destination, log)
+}
+
+/// Like [`snapshot`], but for the running node of the given state manager.
+/// The height of the CUP is pinned while the checkpoint is copied.
+pub fn snapshot_running(
+    state_manager: &StateManagerImpl,
+    pool_config: ArtifactPoolConfig,
+    destination: &Path,
+    log: &ReplicaLogger,
+) -> Result<BackupBundle, BackupError> {
+    write_bundle(
+        pool_config,
+        state_manager.state_layout(),
+        Some(state_manager),
+        destination,
+        log,
+    )
+}
+
+/// Unpins the pinned height when dropped.
+struct PinnedHeight<'a> {
+    state_manager: &'a StateManagerImpl,
+    height: Height,
+}
+
+impl<'a> PinnedHeight<'a> {
+    fn new(state_manager: &'a StateManagerImpl, height: Height) -> Result<Self, BackupError> {
+        state_manager.pin_height(height).map_err(|err| match err {
+            PinHeightError::StateNotFound(height) => BackupError::MissingCheckpoint(height),
+            err => BackupError::PinFailed(err),
+        })?;
+        Ok(Self {
+            state_manager,
+            height,
+        })
+    }
+}
+
+impl Drop for PinnedHeight<'_> {
+    fn drop(&mut self) {
+        self.state_manager.unpin_height(self.height);
+    }
+}
+
+fn write_bundle(
+    pool_config: ArtifactPoolConfig,
+    state_layout: &StateLayout,
+    state_manager: Option<&StateManagerImpl>,
+    destination: &Path,
+    log: &ReplicaLogger,
+) -> Result<BackupBundle, BackupError> {
+    if destination.exists() {
+        return Err(BackupError::IoError {
+            path: destination.to_path_buf(),
+            message: "the destination of the bundle".to_string(),
+            io_err: std::io::Error::from(std::io::ErrorKind::AlreadyExists),
+        });
+    }
+    let pool = open_consensus_pool(pool_config, true, log);
+    if pool.validated.catch_up_package().max_height().is_none() {
+        return Err(BackupError::NoCatchUpPackage);
+    }
+    let cup_proto = pool.validated.highest_catch_up_package_proto();
+    let cup = CatchUpPackage::try_from(&cup_proto)
+        .map_err(|err| BackupError::InvalidCatchUpPackage(err.to_string()))?;
+    let height = cup.height();
+
+    // The checkpoint may be removed before the height is pinned, but not
+    // after, so its existence is checked once it is pinned (pinning fails if
+    // there is neither a state nor a checkpoint at the height).
+    let _pinned = state_manager
+        .map(|state_manager| PinnedHeight::new(state_manager, height))
+        .transpose()?;
+    let checkpoint = match state_layout.checkpoint(height) {
+        Ok(checkpoint) => checkpoint,
+        Err(LayoutError::NotFound(height)) => return Err(BackupError::MissingCheckpoint(height)),
+        Err(err) => return Err(err.into()),
+    };
+
+    let tmp = destination.with_extension("tmp");
+    if tmp.exists() {
+        fs::remove_dir_all(&tmp)
+            .map_err(io_error(&tmp, "failed to remove a stale temporary bundle"))?;
+    }
+    fs::create_dir_all(&tmp).map_err(io_error(&tmp, "failed to create the bundle"))?;
+
+    copy_recursively(checkpoint.raw_path(), &tmp.join(CHECKPOINT_DIR))?;
+    verify_checkpoint(&tmp.join(CHECKPOINT_DIR), &cup)?;
+
+    let mut cup_bytes = Vec::new();
+    cup_proto
+        .encode(&mut cup_bytes)
+        .expect("encoding into a vector cannot fail");
+    write_file(&tmp.join(CATCH_UP_PACKAGE_FILE), &cup_bytes)?;
+
+    let artifacts = validated_artifacts_from(pool.validated.pool_section(), height);
+    let artifacts_bytes =
+        bincode::serialize(&artifacts).expect("consensus messages are serializable");
+    write_file(&tmp.join(ARTIFACTS_FILE), &artifacts_bytes)?;
+
+    sync_path(&tmp).map_err(io_error(&tmp, "failed to sync the bundle"))?;
+    fs::rename(&tmp, destination).map_err(io_error(destination, "failed to rename the bundle"))?;
+    info!(
+        log,
+        "Wrote the backup @{} with {} artifacts to {}",
+        height,
+        artifacts.len(),
+        destination.display()
+    );
+    BackupBundle::open(destination)
+}
+
+fn open_consensus_pool(
+    mut pool_config: ArtifactPoolConfig,
+    read_only: bool,
+    log: &ReplicaLogger,
+) -> UncachedConsensusPoolImpl {
+    pool_config.persistent_pool_read_only = read_only;
+    UncachedConsensusPoolImpl::new(pool_config, log.clone(), MetricsRegistry::new())
+}
+
+/// Returns the validated artifacts at and above the given height, except for
+/// shares and CUPs.
+fn validated_artifacts_from(
+    pool: &dyn PoolSection<ValidatedConsensusArtifact>,
+    height: Height,
+) -> Vec<ConsensusMessage> {
+    fn extend_from<T: ConsensusMessageHashable>(
+        pool: &dyn HeightIndexedPool<T>,
+        height: Height,
+        artifacts: &mut Vec<ConsensusMessage>,
+    ) {
+        if let Some(max_height) = pool.max_height() {
+            if height <= max_height {
+                artifacts.extend(
+                    pool.get_by_height_range(HeightRange::new(height, max_height))
+                        .map(ConsensusMessageHashable::into_message),
+                );
+            }
+        }
+    }
+
+    let mut artifacts = Vec::new();
+    extend_from(pool.random_beacon(), height, &mut artifacts);
+    extend_from(pool.random_tape(), height, &mut artifacts);
+    extend_from(pool.block_proposal(), height, &mut artifacts);
+    extend_from(pool.notarization(), height, &mut artifacts);
+    extend_from(pool.finalization(), height, &mut artifacts);
+    artifacts
+}
+
+/// Verifies that the checkpoint in the given directory has the state hash of
+/// the CUP.
+fn verify_checkpoint(path: &Path, cup: &CatchUpPackage) -> Result<(), BackupError> {
+    let height = cup.height();
+    let layout = CheckpointLayout::<ReadOnly>::new(path.to_path_buf(), height)?;
+    let version = layout.system_metadata().deserialize()?.state_sync_version;
+    let manifest =
+        compute_manifest(version, path, DEFAULT_CHUNK_SIZE).map_err(BackupError::ManifestError)?;
+    let computed = CryptoHashOfState::from(CryptoHash(manifest_hash(&manifest).to_vec()));
+    let expected = cup.content.state_hash.clone();
+    if computed != expected {
+        return Err(BackupError::StateHashMismatch {
+            height,
+            expected,
+            computed,
+        });
+    }
+    Ok(())
+}
+
+fn write_file(path: &Path, bytes: &[u8]) -> Result<(), BackupError> {
+    fs::write(path, bytes).map_err(io_error(path, "failed to write"))?;
+    sync_path(path).map_err(io_error(path, "failed to sync"))
+}
+
+/// Recursively copies `src` to `dst`, syncing the copies. Symbolic links are
+/// followed, e.g. those pointing to cold heap pages, so that the copy does not
+/// depend on files outside of `src`.
+fn copy_recursively(src: &Path, dst: &Path) -> Result<(), BackupError> {
+    let metadata = fs::metadata(src).map_err(io_error(src, "failed to read the metadata"))?;
+    if metadata.is_dir() {
+        fs::create_dir_all(dst).map_err(io_error(dst, "failed to create the directory"))?;
+        let entries = src
+            .read_dir()
+            .map_err(io_error(src, "failed to read the directory"))?;
+        for entry in entries {
+            let entry = entry.map_err(io_error(src, "failed to read the directory"))?;
+            copy_recursively(&entry.path(), &dst.join(entry.file_name()))?;
+        }
+    } else {
+        copy_file_sparse(src, dst).map_err(io_error(src, "failed to copy the file"))?;
+    }
+    sync_path(dst).map_err(io_error(dst, "failed to sync"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_config::state_manager::Config;
+    use ic_consensus_message::make_genesis;
+    use ic_interfaces::state_manager::{CertificationScope, StateManager};
+    use ic_registry_subnet_type::SubnetType;
+    use ic_test_utilities::{
+        consensus::fake::{Fake, FakeVerifier},
+        types::ids::subnet_test_id,
+        with_test_replica_logger,
+    };
+    use ic_types::{consensus::dkg::Summary, malicious_flags::MaliciousFlags};
+    use std::sync::Arc;
+
+    /// Returns the state hash of the checkpoint at the given height once it
+    /// is computed.
+    fn wait_for_state_hash(state_manager: &StateManagerImpl, height: Height) -> CryptoHashOfState {
+        loop {
+            if let Ok(Some(hash)) = state_manager.get_state_hash_at(height) {
+                return hash;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn restores_a_snapshot_onto_a_fresh_node() {
+        with_test_replica_logger(|log| {
+            let tmp = tempfile::tempdir().unwrap();
+            let state_manager = StateManagerImpl::new(
+                Arc::new(FakeVerifier::new()),
+                subnet_test_id(1),
+                SubnetType::Application,
+                log.clone(),
+                &MetricsRegistry::new(),
+                &Config::new(tmp.path().join("state")),
+                MaliciousFlags::default(),
+            );
+            let height = Height::from(1);
+            let (_, state) = state_manager.take_tip();
+            state_manager.commit_and_certify(state, height, CertificationScope::Full);
+
+            let mut summary = Summary::fake();
+            summary.height = height;
+            let mut cup = make_genesis(summary);
+            cup.content.state_hash = wait_for_state_hash(&state_manager, height);
+            let pool_config = ArtifactPoolConfig::new(tmp.path().join("pool"));
+            open_consensus_pool(pool_config.clone(), false, &log)
+                .validated
+                .insert_cup_with_proto(CUPWithOriginalProtobuf::from_cup(cup.clone()));
+
+            let bundle = snapshot_running(
+                &state_manager,
+                pool_config,
+                &tmp.path().join("bundle"),
+                &log,
+            )
+            .unwrap();
+            assert_eq!(bundle.catch_up_package(), &cup);
+            assert!(state_manager.pinned_heights().is_empty());
+
+            let fresh_state_root = tmp.path().join("fresh_state");
+            let fresh_pool_config = ArtifactPoolConfig::new(tmp.path().join("fresh_pool"));
+            bundle
+                .restore(&cup, fresh_pool_config.clone(), &fresh_state_root, &log)
+                .unwrap();
+            assert_eq!(
+                StateLayout::new(log.clone(), fresh_state_root.clone())
+                    .checkpoint_heights()
+                    .unwrap(),
+                vec![height]
+            );
+            assert_eq!(
+                open_consensus_pool(fresh_pool_config.clone(), true, &log)
+                    .validated
+                    .catch_up_package()
+                    .get_highest()
+                    .unwrap(),
+                cup
+            );
+
+            match bundle.restore(&cup, fresh_pool_config, &fresh_state_root, &log) {
+                Err(BackupError::NodeNotFresh(_)) => {}
+                other => panic!("Unexpected result {:?}", other),
+            }
+        })
+    }
+
+    #[test]
+    fn bundles_are_verified_against_the_trusted_cup() {
+        with_test_replica_logger(|log| {
+            let tmp = tempfile::tempdir().unwrap();
+            let cup = make_genesis(Summary::fake());
+            let pool_config = ArtifactPoolConfig::new(tmp.path().join("pool"));
+            open_consensus_pool(pool_config.clone(), false, &log)
+                .validated
+                .insert_cup_with_proto(CUPWithOriginalProtobuf::from_cup(cup.clone()));
+            // The CUP of the bundle is written without a checkpoint, which
+            // is not reached as the CUPs already differ.
+            let path = tmp.path().join("bundle");
+            fs::create_dir_all(&path).unwrap();
+            let mut bytes = Vec::new();
+            pb::CatchUpPackage::from(&cup).encode(&mut bytes).unwrap();
+            fs::write(path.join(CATCH_UP_PACKAGE_FILE), bytes).unwrap();
+            let bundle = BackupBundle::open(&path).unwrap();
+
+            let mut summary = Summary::fake();
+            summary.height = Height::from(10);
+            let trusted_cup = make_genesis(summary);
+            match bundle.restore(
+                &trusted_cup,
+                ArtifactPoolConfig::new(tmp.path().join("fresh_pool")),
+                &tmp.path().join("fresh_state"),
+                &log,
+            ) {
+                Err(BackupError::CatchUpPackageMismatch { .. }) => {}
+                other => panic!("Unexpected result {:?}", other),
+            }
+        })
+    }
+
+    #[test]
+    fn copies_directories_recursively() {
+        let tmp = tempfile::tempdir().unwrap();
+        let src = tmp.path().join("src");
+        fs::create_dir_all(src.join("canister_states/a")).unwrap();
+        fs::write(src.join("system_metadata.pbuf"), b"metadata").unwrap();
+        fs::write(src.join("canister_states/a/vmemory_0.bin"), b"memory").unwrap();
+        std::os::unix::fs::symlink(
+            src.join("system_metadata.pbuf"),
+            src.join("canister_states/a/link"),
+        )
+        .unwrap();
+
+        let dst = tmp.path().join("dst");
+        copy_recursively(&src, &dst).unwrap();
+        assert_eq!(
+            fs::read(dst.join("canister_states/a/vmemory_0.bin")).unwrap(),
+            b"memory"
+        );
+        let link = dst.join("canister_states/a/link");
+        assert!(!link.symlink_metadata().unwrap().file_type().is_symlink());
+        assert_eq!(fs::read(link).unwrap(), b"metadata");
+    }
+
+    #[test]
+    fn opening_a_missing_bundle_fails() {
+        let tmp = tempfile::tempdir().unwrap();
+        match BackupBundle::open(&tmp.path().join("bundle")) {
+            Err(BackupError::IoError { .. }) => {}
+            other => panic!(
+                "Unexpected result {:?}",
+                other.map(|bundle| bundle.height())
+            ),
+        }
+    }
+}
@@ -12,8 +12,9 @@ use ic_protobuf::types::v1 as pb;
 use ic_types::{
     consensus::catchup::CatchUpPackageParam,
     messages::{
-        Blob, HttpReadContent, HttpRequestEnvelope, HttpStatusResponse, HttpSubmitContent,
-        MessageId, ReplicaHealthStatus,
+        from_cbor, to_canonical_cbor, Blob, HttpReadContent, HttpRequestEnvelope,
+        HttpStatusResponse, HttpSubmitContent, MessageId, ReplicaHealthAssessment,
+        ReplicaHealthStatus,
     },
     CanisterId, PrincipalId,
 };
@@ -303,7 +304,7 @@ impl Agent {
         param: Option<CatchUpPackageParam>,
     ) -> Result<Option<pb::CatchUpPackage>, String> {
         let body = param
-            .and_then(|param| serde_cbor::to_vec(&param).ok())
+            .and_then(|param| to_canonical_cbor(&param).ok())
             .unwrap_or_default();
         let bytes = self
             .http_client
@@ -484,6 +485,15 @@ impl Agent {
         Ok(response.root_key)
     }
 
+    /// Requests the replica's assessment of its own health by querying
+    /// /api/v1/status. Returns None if the replica does not report it.
+    pub async fn replica_health_assessment(
+        &self,
+    ) -> Result<Option<ReplicaHealthAssessment>, String> {
+        let response = self.get_status().await?;
+        Ok(response.replica_health_assessment)
+    }
+
     /// Checks if the target replica is healthy.
     pub async fn is_replica_healthy(&self) -> bool {
         if let Ok(response) = self.get_status().await {
@@ -567,7 +577,7 @@ pub fn sign_read(
 }
 
 fn bytes_to_cbor(bytes: Vec<u8>) -> Result<CBOR, String> {
-    let cbor = serde_cbor::from_slice(&bytes).map_err(|e| {
+    let cbor = from_cbor(&bytes).map_err(|e| {
         format!(
             "Agent::bytes_to_cbor: Failed to parse result from IC, got: {:?} - error {:?}",
             String::from_utf8(
@@ -590,7 +600,7 @@ mod tests {
     use super::*;
     use ic_test_utilities::crypto::temp_crypto_component_with_fake_registry;
     use ic_test_utilities::types::ids::node_test_id;
-    use ic_types::malicious_flags::MaliciousFlags;
+    use ic_types::malicious_strategies::MaliciousBehaviors;
     use ic_types::messages::{HttpCanisterUpdate, HttpRequest, HttpUserQuery, ReadContent};
     use ic_types::time::current_time;
     use ic_types::{PrincipalId, RegistryVersion, UserId};
@@ -632,6 +642,7 @@ mod tests {
                     .into_vec(),
                 ),
                 ingress_expiry: expiry_time.as_nanos_since_unix_epoch(),
+                priority: None,
             },
         };
         let sender = Sender::from_keypair(&keypair);
@@ -651,7 +662,7 @@ mod tests {
             &validator,
             test_start_time,
             mock_registry_version(),
-            &MaliciousFlags::default(),
+            &MaliciousBehaviors::default(),
         )
         .unwrap()
         .contains(&request.content().canister_id()));
@@ -685,6 +696,7 @@ mod tests {
                 nonce: None,
                 sender: Blob(sender_id.get().into_vec()),
                 ingress_expiry: expiry_time.as_nanos_since_unix_epoch(),
+                priority: None,
             },
         };
         let sender = Sender::from_secp256k1_keys(&sk, &pk);
@@ -704,7 +716,7 @@ mod tests {
             &validator,
             test_start_time,
             mock_registry_version(),
-            &MaliciousFlags::default(),
+            &MaliciousBehaviors::default(),
         )
         .unwrap()
         .contains(&request.content().canister_id()));
@@ -727,6 +739,7 @@ mod tests {
                 nonce: None,
                 sender: Blob(UserId::from(PrincipalId::new_anonymous()).get().into_vec()),
                 ingress_expiry: expiry_time.as_nanos_since_unix_epoch(),
+                priority: None,
             },
         };
         let (submit, id) = sign_submit(content.clone(), &Sender::Anonymous).unwrap();
@@ -745,7 +758,7 @@ mod tests {
             &validator,
             test_start_time,
             mock_registry_version(),
-            &MaliciousFlags::default(),
+            &MaliciousBehaviors::default(),
         )
         .unwrap()
         .contains(&request.content().canister_id()));
@@ -793,7 +806,7 @@ mod tests {
             &validator,
             test_start_time,
             mock_registry_version(),
-            &MaliciousFlags::default(),
+            &MaliciousBehaviors::default(),
         ));
     }
 
@@ -846,7 +859,7 @@ mod tests {
             &validator,
             test_start_time,
             mock_registry_version(),
-            &MaliciousFlags::default(),
+            &MaliciousBehaviors::default(),
         ));
     }
 }
@@ -6,7 +6,7 @@ use ic_crypto_tree_hash::{LabeledTree, Path};
 use ic_types::Time;
 use ic_types::{
     messages::{
-        Blob, Certificate, HttpCanisterUpdate, HttpReadContent, HttpReadState,
+        from_cbor, Blob, Certificate, HttpCanisterUpdate, HttpReadContent, HttpReadState,
         HttpReadStateResponse, HttpRequestEnvelope, HttpSubmitContent, HttpUserQuery, MessageId,
         SignedRequestBytes,
     },
@@ -59,7 +59,7 @@ pub fn parse_read_state_response(
     let response = serde_cbor::value::from_value::<HttpReadStateResponse>(message)
         .map_err(|source| format!("decoding to HttpReadStateResponse failed: {}", source))?;
 
-    let certificate: Certificate = serde_cbor::from_slice(&response.certificate.as_slice())
+    let certificate: Certificate = from_cbor(&response.certificate.as_slice())
         .map_err(|source| format!("decoding Certificate failed: {}", source))?;
 
     // Parse the tree.
@@ -157,6 +157,7 @@ impl Agent {
                 nonce: Some(Blob(nonce)),
                 sender: self.sender_field.clone(),
                 ingress_expiry: ingress_expiry.as_nanos_since_unix_epoch(),
+                priority: None,
             },
         };
 
@@ -228,16 +229,7 @@ fn to_blob(canister_id: &CanisterId) -> Blob {
 mod tests {
     use super::*;
     use ic_crypto_tree_hash::MixedHashTree;
-    use ic_types::messages::HttpReadStateResponse;
-    use serde::Serialize;
-
-    fn to_self_describing_cbor<T: Serialize>(e: &T) -> serde_cbor::Result<Vec<u8>> {
-        let mut serialized_bytes = Vec::new();
-        let mut serializer = serde_cbor::Serializer::new(&mut serialized_bytes);
-        serializer.self_describe()?;
-        e.serialize(&mut serializer)?;
-        Ok(serialized_bytes)
-    }
+    use ic_types::messages::{to_canonical_cbor, HttpReadStateResponse};
 
     #[test]
     fn test_parse_read_state_response_unknown() {
@@ -247,15 +239,15 @@ mod tests {
             delegation: None,
         };
 
-        let certificate_cbor: Vec<u8> = to_self_describing_cbor(&certificate).unwrap();
+        let certificate_cbor: Vec<u8> = to_canonical_cbor(&certificate).unwrap();
 
         let response = HttpReadStateResponse {
             certificate: Blob(certificate_cbor),
         };
 
-        let response_cbor: Vec<u8> = to_self_describing_cbor(&response).unwrap();
+        let response_cbor: Vec<u8> = to_canonical_cbor(&response).unwrap();
 
-        let response: CBOR = serde_cbor::from_slice(response_cbor.as_slice()).unwrap();
+        let response: CBOR = from_cbor(response_cbor.as_slice()).unwrap();
 
         let request_id: MessageId = MessageId::from([0; 32]);
         assert_eq!(
@@ -297,15 +289,15 @@ mod tests {
             delegation: None,
         };
 
-        let certificate_cbor: Vec<u8> = to_self_describing_cbor(&certificate).unwrap();
+        let certificate_cbor: Vec<u8> = to_canonical_cbor(&certificate).unwrap();
 
         let response = HttpReadStateResponse {
             certificate: Blob(certificate_cbor),
         };
 
-        let response_cbor: Vec<u8> = to_self_describing_cbor(&response).unwrap();
+        let response_cbor: Vec<u8> = to_canonical_cbor(&response).unwrap();
 
-        let response: CBOR = serde_cbor::from_slice(response_cbor.as_slice()).unwrap();
+        let response: CBOR = from_cbor(response_cbor.as_slice()).unwrap();
 
         // Request ID that exists.
         let request_id: MessageId = MessageId::from([
@@ -1,6 +1,8 @@
 use ic_types::Height;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::PathBuf;
+use std::time::Duration;
 
 /// Default capacity, in number of messages, for validated and unvalidated pools
 const MAX_INGRESS_POOL_VALIDATED_CAPACITY: usize = 1024;
@@ -9,6 +11,34 @@ const MAX_CONSENSUS_POOL_VALIDATED_CAPACITY: usize = 2048;
 const MAX_CONSENSUS_POOL_UNVALIDATED_CAPACITY_PER_PEER: usize = 2048;
 const PERSISTENT_POOL_VALIDATED_PURGE_INTERVAL: u64 = 5000;
 
+/// Default limits of the unvalidated sections of the consensus, certification
+/// and DKG pools
+pub const DEFAULT_CONSENSUS_POOL_UNVALIDATED_LIMITS: UnvalidatedSectionLimits =
+    UnvalidatedSectionLimits {
+        max_count: 50_000,
+        max_size_bytes: 1024 * 1024 * 1024,
+        eviction_policy: UnvalidatedEvictionPolicy::OldestFirst,
+    };
+pub const DEFAULT_CERTIFICATION_POOL_UNVALIDATED_LIMITS: UnvalidatedSectionLimits =
+    UnvalidatedSectionLimits {
+        max_count: 10_000,
+        max_size_bytes: 64 * 1024 * 1024,
+        eviction_policy: UnvalidatedEvictionPolicy::OldestFirst,
+    };
+pub const DEFAULT_DKG_POOL_UNVALIDATED_LIMITS: UnvalidatedSectionLimits =
+    UnvalidatedSectionLimits {
+        max_count: 10_000,
+        max_size_bytes: 512 * 1024 * 1024,
+        eviction_policy: UnvalidatedEvictionPolicy::OldestFirst,
+    };
+
+/// Default limits of the queues of the artifact processors
+pub const DEFAULT_ARTIFACT_PROCESSOR_QUEUE_LIMITS: ArtifactProcessorQueueLimits =
+    ArtifactProcessorQueueLimits {
+        backpressure_threshold: 10_000,
+        max_pending_artifacts: 20_000,
+    };
+
 /// External configuration for artifact pools meant to be used by replica's
 /// config file.
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -21,8 +51,24 @@ pub struct ArtifactPoolTomlConfig {
     /// specified, throttling would be disabled.
     pub ingress_pool_size_threshold: Option<usize>,
 
+    /// The quota of every canister in the validated + unvalidated ingress
+    /// pool. Messages to a canister exceeding its quota are rejected, so that
+    /// the clients of one canister cannot crowd out the ingress of all other
+    /// canisters. If this field is not specified, canisters have no quota.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ingress_pool_canister_quota: Option<IngressCanisterQuota>,
+
+    /// The rate limit of the ingress messages every sender may submit to this
+    /// replica, so that a single principal cannot flood the ingress of the
+    /// subnet. Messages exceeding the limit are rejected. If this field is
+    /// not specified, senders are not rate limited.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ingress_sender_rate_limit: Option<IngressSenderRateLimit>,
+
     /// Choice of persistent pool backend database. None means default choice,
     /// which at the moment is "lmdb".
+    /// When switching from "lmdb" to "rocksdb", the existing consensus and
+    /// certification pools are migrated to RocksDB at startup.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub consensus_pool_backend: Option<String>,
 
@@ -30,6 +76,55 @@ pub struct ArtifactPoolTomlConfig {
     /// If no path was provided, no backup will be saved.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub backup: Option<BackupConfig>,
+
+    /// Configuration of the append-only log of all validated consensus
+    /// artifacts. If no configuration was provided, no log will be written.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artifact_log: Option<ArtifactLogConfig>,
+
+    /// The policy choosing which artifacts to evict from a full unvalidated
+    /// section of the consensus, certification and DKG pools. None means
+    /// default choice, which at the moment is "oldest_first".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unvalidated_eviction_policy: Option<UnvalidatedEvictionPolicy>,
+
+    /// The limits of the unvalidated section of the consensus pool. Limits
+    /// that are not specified keep their default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub consensus_pool_unvalidated_limits: Option<UnvalidatedLimitsTomlConfig>,
+
+    /// The limits of the unvalidated section of the certification pool.
+    /// Limits that are not specified keep their default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub certification_pool_unvalidated_limits: Option<UnvalidatedLimitsTomlConfig>,
+
+    /// The limits of the unvalidated section of the DKG pool. Limits that are
+    /// not specified keep their default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dkg_pool_unvalidated_limits: Option<UnvalidatedLimitsTomlConfig>,
+
+    /// The path in which to store the validated section of the DKG pool, so
+    /// that the dealings of the current DKG interval survive a restart. If no
+    /// path was provided, the DKG pool is kept in memory only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dkg_pool_path: Option<PathBuf>,
+
+    /// The retention duration, in seconds, of the artifacts of the given
+    /// types in the consensus and DKG pools, e.g. `notarization_share = 3600`.
+    /// Artifacts are purged once they are older than the duration of their
+    /// type, in addition to the purging by consensus. Types use the names of
+    /// the `artifact_type` label of the artifact pool metrics. Artifacts of
+    /// types without a duration are only purged by consensus. The DKG pool
+    /// applies the duration of `dkg_message` to whole DKG intervals ahead of
+    /// the current one, see `DkgPoolImpl::with_artifact_ttls`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub artifact_ttl_secs: BTreeMap<String, u64>,
+
+    /// The limits of the queues of artifacts waiting to be processed by the
+    /// artifact processors. If no limits were provided, the default limits
+    /// are used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artifact_processor_queue_limits: Option<ArtifactProcessorQueueLimits>,
 }
 
 impl ArtifactPoolTomlConfig {
@@ -38,8 +133,18 @@ impl ArtifactPoolTomlConfig {
         Self {
             consensus_pool_path,
             ingress_pool_size_threshold: None,
+            ingress_pool_canister_quota: None,
+            ingress_sender_rate_limit: None,
             consensus_pool_backend: Some("lmdb".to_string()),
             backup,
+            artifact_log: None,
+            unvalidated_eviction_policy: None,
+            consensus_pool_unvalidated_limits: None,
+            certification_pool_unvalidated_limits: None,
+            dkg_pool_unvalidated_limits: None,
+            dkg_pool_path: None,
+            artifact_ttl_secs: BTreeMap::new(),
+            artifact_processor_queue_limits: None,
         }
     }
 }
@@ -56,6 +161,115 @@ pub struct BackupConfig {
     pub purging_interval_secs: u64,
 }
 
+/// Configuration of the append-only log of validated consensus artifacts.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArtifactLogConfig {
+    /// Path to a folder with write permissions, in which the log segments
+    /// are stored.
+    pub log_path: PathBuf,
+    /// The size, in bytes, after which a segment is closed and a new one is
+    /// started.
+    pub max_segment_size_bytes: u64,
+    /// The number of most recent segments that are retained. Older segments
+    /// are deleted.
+    pub retained_segments: usize,
+}
+
+/// The quota of a canister in the ingress pool. A message to a canister is
+/// only admitted if the messages to the canister stay within both limits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IngressCanisterQuota {
+    /// The maximum number of messages to the canister.
+    pub max_count: usize,
+    /// The maximum total size of the messages to the canister, in bytes.
+    pub max_size_bytes: usize,
+}
+
+/// The rate limit of the ingress messages submitted by a sender. A message is
+/// only admitted if the sender stays within both limits; bursts of up to one
+/// second worth of messages are allowed. The anonymous principal is not
+/// limited.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IngressSenderRateLimit {
+    /// The maximum number of messages per second.
+    pub max_messages_per_sec: u64,
+    /// The maximum total size of the messages per second, in bytes.
+    pub max_bytes_per_sec: u64,
+}
+
+/// The limits of the queue of artifacts waiting to be processed by an
+/// artifact processor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArtifactProcessorQueueLimits {
+    /// The number of pending artifacts at which the processor signals
+    /// backpressure to *Gossip*, which then stops requesting chunks of
+    /// artifacts of this type.
+    pub backpressure_threshold: usize,
+    /// The maximum number of pending artifacts. Artifacts received while the
+    /// queue is full, e.g., the downloads that were in flight when the
+    /// backpressure started, are rejected.
+    pub max_pending_artifacts: usize,
+}
+
+/// The policy choosing which artifacts to evict when an unvalidated pool
+/// section is full.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnvalidatedEvictionPolicy {
+    /// Evict the artifacts that were inserted first.
+    OldestFirst,
+    /// Evict the artifacts with the lowest height, the oldest first among
+    /// the artifacts of the same height.
+    LowestHeightFirst,
+}
+
+impl Default for UnvalidatedEvictionPolicy {
+    fn default() -> Self {
+        UnvalidatedEvictionPolicy::OldestFirst
+    }
+}
+
+/// The limits of an unvalidated pool section. An artifact is only inserted if
+/// the section stays within both limits, possibly after evicting other
+/// artifacts according to the eviction policy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnvalidatedSectionLimits {
+    /// The maximum number of artifacts in the section.
+    pub max_count: usize,
+    /// The maximum total size of the artifacts in the section, in bytes.
+    pub max_size_bytes: usize,
+    /// The policy choosing which artifacts to evict.
+    pub eviction_policy: UnvalidatedEvictionPolicy,
+}
+
+/// The limits of an unvalidated pool section in the replica's config file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnvalidatedLimitsTomlConfig {
+    /// The maximum number of artifacts in the section.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_count: Option<usize>,
+    /// The maximum total size of the artifacts in the section, in bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_size_bytes: Option<usize>,
+}
+
+impl UnvalidatedSectionLimits {
+    /// Returns the limits with the given eviction policy, overridden by the
+    /// limits specified in the config file.
+    fn configured(
+        self,
+        eviction_policy: UnvalidatedEvictionPolicy,
+        toml_config: Option<UnvalidatedLimitsTomlConfig>,
+    ) -> Self {
+        let toml_config = toml_config.unwrap_or_default();
+        Self {
+            max_count: toml_config.max_count.unwrap_or(self.max_count),
+            max_size_bytes: toml_config.max_size_bytes.unwrap_or(self.max_size_bytes),
+            eviction_policy,
+        }
+    }
+}
+
 /// The configuration for the ingress and consensus artifact pools, both the
 /// validated and unvalidated portions.
 #[derive(Clone, Debug)]
@@ -69,18 +283,41 @@ pub struct ArtifactPoolConfig {
     /// Threshold for ingress rate limiting. If this field is not
     /// specified, throttling would be disabled.
     pub ingress_pool_size_threshold: Option<usize>,
+    /// The quota of every canister in the ingress pool. If None, canisters
+    /// have no quota.
+    pub ingress_pool_canister_quota: Option<IngressCanisterQuota>,
+    /// The rate limit of the ingress messages of every sender. If None,
+    /// senders are not rate limited.
+    pub ingress_sender_rate_limit: Option<IngressSenderRateLimit>,
     /// The maximum size, in number of messages, of the unvalidated section
     /// of the artifact pool, per peer.
     pub consensus_pool_unvalidated_capacity_per_peer: usize,
     /// The maximum size, in number of messages, of the validated section
     /// of the artifact pool.
     pub consensus_pool_validated_capacity: usize,
+    /// The limits of the unvalidated section of the consensus pool.
+    pub consensus_pool_unvalidated_limits: UnvalidatedSectionLimits,
+    /// The limits of the unvalidated section of the certification pool.
+    pub certification_pool_unvalidated_limits: UnvalidatedSectionLimits,
+    /// The limits of the unvalidated section of the DKG pool.
+    pub dkg_pool_unvalidated_limits: UnvalidatedSectionLimits,
     /// Choice of persistent pool backend
     pub persistent_pool_backend: PersistentPoolBackend,
     /// Whether the persistent pool should be opened as read-only
     pub persistent_pool_read_only: bool,
     /// Contains all parameters for the consensus artifact backup.
     pub backup_config: Option<BackupConfig>,
+    /// Contains all parameters for the append-only log of validated consensus
+    /// artifacts.
+    pub artifact_log_config: Option<ArtifactLogConfig>,
+    /// The path at which the validated section of the DKG pool is stored. If
+    /// None, the DKG pool is not persisted.
+    pub dkg_pool_persistent_path: Option<PathBuf>,
+    /// The retention durations of the artifacts in the consensus and DKG
+    /// pools, by artifact type.
+    pub artifact_ttls: BTreeMap<String, Duration>,
+    /// The limits of the queues of the artifact processors.
+    pub artifact_processor_queue_limits: ArtifactProcessorQueueLimits,
 }
 
 /// Choice of persistent pool database is either LMDB or RocksDB.
@@ -134,16 +371,41 @@ impl From<ArtifactPoolTomlConfig> for ArtifactPoolConfig {
                 panic!("Unsupported persistent_pool_backend: {}, must be either \"lmdb\" or \"rocksdb\".", backend);
             }
         };
+        let eviction_policy = toml_config.unvalidated_eviction_policy.unwrap_or_default();
         ArtifactPoolConfig {
             ingress_pool_validated_capacity: MAX_INGRESS_POOL_VALIDATED_CAPACITY,
             ingress_pool_unvalidated_capacity_per_peer:
                 MAX_INGRESS_POOL_UNVALIDATED_CAPACITY_PER_PEER,
             ingress_pool_size_threshold: toml_config.ingress_pool_size_threshold,
+            ingress_pool_canister_quota: toml_config.ingress_pool_canister_quota,
+            ingress_sender_rate_limit: toml_config.ingress_sender_rate_limit,
             consensus_pool_unvalidated_capacity_per_peer: MAX_CONSENSUS_POOL_VALIDATED_CAPACITY,
             consensus_pool_validated_capacity: MAX_CONSENSUS_POOL_UNVALIDATED_CAPACITY_PER_PEER,
+            consensus_pool_unvalidated_limits: DEFAULT_CONSENSUS_POOL_UNVALIDATED_LIMITS
+                .configured(
+                    eviction_policy,
+                    toml_config.consensus_pool_unvalidated_limits,
+                ),
+            certification_pool_unvalidated_limits: DEFAULT_CERTIFICATION_POOL_UNVALIDATED_LIMITS
+                .configured(
+                    eviction_policy,
+                    toml_config.certification_pool_unvalidated_limits,
+                ),
+            dkg_pool_unvalidated_limits: DEFAULT_DKG_POOL_UNVALIDATED_LIMITS
+                .configured(eviction_policy, toml_config.dkg_pool_unvalidated_limits),
             persistent_pool_backend,
             persistent_pool_read_only: false,
             backup_config: toml_config.backup,
+            artifact_log_config: toml_config.artifact_log,
+            dkg_pool_persistent_path: toml_config.dkg_pool_path,
+            artifact_ttls: toml_config
+                .artifact_ttl_secs
+                .into_iter()
+                .map(|(artifact_type, secs)| (artifact_type, Duration::from_secs(secs)))
+                .collect(),
+            artifact_processor_queue_limits: toml_config
+                .artifact_processor_queue_limits
+                .unwrap_or(DEFAULT_ARTIFACT_PROCESSOR_QUEUE_LIMITS),
         }
     }
 }
@@ -165,3 +427,36 @@ impl ArtifactPoolConfig {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unspecified_unvalidated_limits_keep_their_default() {
+        let mut toml_config = ArtifactPoolTomlConfig::new(PathBuf::from("/tmp/pool"), None);
+        toml_config.unvalidated_eviction_policy =
+            Some(UnvalidatedEvictionPolicy::LowestHeightFirst);
+        toml_config.dkg_pool_unvalidated_limits = Some(UnvalidatedLimitsTomlConfig {
+            max_count: Some(100),
+            max_size_bytes: None,
+        });
+        let config = ArtifactPoolConfig::from(toml_config);
+
+        assert_eq!(
+            config.dkg_pool_unvalidated_limits,
+            UnvalidatedSectionLimits {
+                max_count: 100,
+                max_size_bytes: DEFAULT_DKG_POOL_UNVALIDATED_LIMITS.max_size_bytes,
+                eviction_policy: UnvalidatedEvictionPolicy::LowestHeightFirst,
+            }
+        );
+        assert_eq!(
+            config.consensus_pool_unvalidated_limits,
+            UnvalidatedSectionLimits {
+                eviction_policy: UnvalidatedEvictionPolicy::LowestHeightFirst,
+                ..DEFAULT_CONSENSUS_POOL_UNVALIDATED_LIMITS
+            }
+        );
+    }
+}
@@ -8,8 +8,10 @@ use crate::{
     config_parser::{ConfigError, ConfigSource, ConfigValidate},
     consensus::ConsensusConfig,
     crypto::CryptoConfig,
+    event_log::Config as EventLogConfig,
     execution_environment::Config as HypervisorConfig,
     firewall::Config as FirewallConfig,
+    health::Config as HealthConfig,
     http_handler,
     http_handler::Config as HttpHandlerConfig,
     logger::Config as LoggerConfig,
@@ -46,6 +48,14 @@ pub struct Config {
     pub firewall: FirewallConfig,
     pub registration: RegistrationConfig,
     pub nns_registry_replicator: NnsRegistryReplicatorConfig,
+    // The event log is disabled if it is not specified in the configuration
+    // file.
+    #[serde(default)]
+    pub event_log: EventLogConfig,
+    // If `health` is not specified in the configuration file, the default
+    // thresholds are used.
+    #[serde(default)]
+    pub health: HealthConfig,
 }
 
 /// Mirrors the Config struct except that fields are made optional. This is
@@ -68,6 +78,8 @@ pub struct ConfigOptional {
     pub firewall: Option<FirewallConfig>,
     pub registration: Option<RegistrationConfig>,
     pub nns_registry_replicator: Option<NnsRegistryReplicatorConfig>,
+    pub event_log: Option<EventLogConfig>,
+    pub health: Option<HealthConfig>,
 }
 
 impl Config {
@@ -95,6 +107,8 @@ impl Config {
             firewall: FirewallConfig::default(),
             registration: RegistrationConfig::default(),
             nns_registry_replicator: NnsRegistryReplicatorConfig::default(),
+            event_log: EventLogConfig::default(),
+            health: HealthConfig::default(),
         }
     }
 
@@ -121,7 +135,16 @@ impl Config {
     /// omitted, its value is taken from the given 'default'.
     pub fn load_with_default(source: &ConfigSource, default: Config) -> Result<Self, ConfigError> {
         let cfg = source.load::<ConfigOptional>()?;
+        Self::from_optional(source, cfg, default)
+    }
 
+    /// Builds a [Config] from the sections of 'cfg', taking the omitted ones
+    /// from the given 'default'.
+    pub(crate) fn from_optional(
+        source: &ConfigSource,
+        cfg: ConfigOptional,
+        default: Config,
+    ) -> Result<Self, ConfigError> {
         let logger = cfg.logger.unwrap_or(default.logger);
         let nodemanager_logger = cfg.nodemanager_logger.unwrap_or_else(|| logger.clone());
 
@@ -151,6 +174,8 @@ impl Config {
             nns_registry_replicator: cfg
                 .nns_registry_replicator
                 .unwrap_or(default.nns_registry_replicator),
+            event_log: cfg.event_log.unwrap_or(default.event_log),
+            health: cfg.health.unwrap_or(default.health),
         })
     }
 
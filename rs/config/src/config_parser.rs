@@ -78,8 +78,26 @@ impl ConfigSource {
     /// Loads a value from the provided config source.
     /// The source is expected to be a valid JSON5 document.
     pub fn load<T: DeserializeOwned + Default + ConfigValidate>(&self) -> Result<T, ConfigError> {
+        let cfg_str = match self.read()? {
+            Some(cfg_str) => cfg_str,
+            None => return Ok(Default::default()),
+        };
+
+        let cfg = json5::from_str::<T>(&cfg_str).map_err(|err| ConfigError::ParseError {
+            source: self.clone(),
+            message: err.to_string(),
+        })?;
+        cfg.validate().map_err(|err| ConfigError::ValidationError {
+            source: self.clone(),
+            message: err,
+        })
+    }
+
+    /// Reads the contents of the config source, or returns `None` for the
+    /// hard-coded default configuration.
+    pub(crate) fn read(&self) -> Result<Option<String>, ConfigError> {
         let cfg_str = match &self {
-            ConfigSource::Default => return Ok(Default::default()),
+            ConfigSource::Default => return Ok(None),
             ConfigSource::Literal(literal) => literal.clone(),
 
             ConfigSource::StdIn => {
@@ -103,15 +121,7 @@ impl ConfigSource {
                 })?
             }
         };
-
-        let cfg = json5::from_str::<T>(&cfg_str).map_err(|err| ConfigError::ParseError {
-            source: self.clone(),
-            message: err.to_string(),
-        })?;
-        cfg.validate().map_err(|err| ConfigError::ValidationError {
-            source: self.clone(),
-            message: err,
-        })
+        Ok(Some(cfg_str))
     }
 }
 
@@ -80,6 +80,18 @@ pub const SAMPLE_CONFIG: &str = r#"
 
         // mapping of flow ids to TCP port number, also depth of send queue
         p2p_flows: [{flow_tag: 1, server_port: 3000, queue_size: 1024}],
+
+        // How the TLS certificates of the peers are validated. Alternatives:
+        //   * EXAMPLE: tls_validation: "RegistryPinned",
+        //     use the certificates in the registry (the default)
+        //   * EXAMPLE: tls_validation: { CaIssued: { ca_cert_path: "/tmp/ca.pem", cert_path: "/tmp/node.pem" } },
+        //     accept certificates issued by a CA, for test networks
+        //   * EXAMPLE: tls_validation: { AllowList: { path: "/tmp/peers.pem" } },
+        //     accept the listed certificates, for test networks
+        //   With CaIssued and AllowList, the IP addresses of peers whose node records are not in
+        //   the registry can be given by node ID, e.g.
+        //   peer_ips: { "<node id>": "10.0.0.2" },
+        //   which are connected to at the server_ports of the p2p_flows above.
     },
     // ============================================
     // Configuration of registry client
@@ -100,6 +112,15 @@ pub const SAMPLE_CONFIG: &str = r#"
         //     read registry from the registry's local store.
         //
         // The default is not to specify it.
+        //
+        // Optionally, the fetched registry versions are cached on disk, so that
+        // the replica can start while the data provider is unreachable:
+        //   * EXAMPLE: snapshot_cache: {path: "/tmp/registry_cache", max_staleness_seconds: 604800}
+        //
+        // Optionally, with a certified registry canister data provider, the registry
+        // deltas are passed on to the other nodes of the subnet over gossip, so that
+        // nodes that cannot reach the NNS still learn about registry changes:
+        //   * EXAMPLE: delta_gossip: true
     },
     // ============================================
     // Configuration of the node state persistence.
@@ -121,7 +142,29 @@ pub const SAMPLE_CONFIG: &str = r#"
             retention_time_secs: 3600,
             // How often we purge.
             purging_interval_secs: 3600
-        }
+        },
+        // Which artifacts to evict from a full unvalidated section of the
+        // consensus, certification and DKG pools.
+        //
+        // Alternatives:
+        // - EXAMPLE: unvalidated_eviction_policy: "oldest_first",
+        // - EXAMPLE: unvalidated_eviction_policy: "lowest_height_first",
+        unvalidated_eviction_policy: "oldest_first",
+        // The limits of the unvalidated section of the DKG pool, in number of
+        // messages and bytes. The consensus and certification pools are
+        // configured in `consensus_pool_unvalidated_limits` and
+        // `certification_pool_unvalidated_limits`. Limits that are not
+        // specified keep their default.
+        dkg_pool_unvalidated_limits: {
+            max_count: 10000,
+        },
+        // The limits of the queues of artifacts waiting to be processed. At
+        // the backpressure threshold, gossip stops downloading artifacts of
+        // the type; artifacts beyond the maximum are rejected.
+        artifact_processor_queue_limits: {
+            backpressure_threshold: 10000,
+            max_pending_artifacts: 20000,
+        },
     },
     // ============================================
     // Consensus related config.
@@ -129,13 +172,32 @@ pub const SAMPLE_CONFIG: &str = r#"
     consensus: {
         // Whether or not to detect starvation. Should only be set to false in tests.
         detect_starvation: true,
+        // The number of the latest consensus round timelines kept for debugging.
+        round_timelines: 100,
+        // The number of threads that verify DKG dealings in parallel.
+        dkg_validation_threads: 4,
+        // The number of threads on which consensus and certification create and
+        // verify signatures.
+        crypto_threads: 4,
+        // Whether the replica only observes the subnet: it validates artifacts,
+        // syncs state and serves queries, but produces no blocks, shares or
+        // dealings while it is not a member at the finalized height. The node
+        // must be in the subnet record, as peers only connect to those nodes.
+        observer: false,
+        // Whether the replica only relays ingress messages and state sync chunks,
+        // e.g. on a boundary node, without holding any consensus artifacts.
+        // Cannot be combined with `observer`.
+        relay: false,
     },
     // ============================================
     // Configuration of the node state persistence.
     // ============================================
     crypto: {
         // The directory that should be used to persist node's cryptographic keys.
-        crypto_root: "/tmp/ic_crypto"
+        crypto_root: "/tmp/ic_crypto",
+        // If set, the node's committee signing and TLS keys are rotated once
+        // they are older than this many seconds.
+        // key_rotation_period_secs: 2592000,
     },
     // ========================================
     // Configuration of the message scheduling.
@@ -184,7 +246,12 @@ pub const SAMPLE_CONFIG: &str = r#"
         //   Expose prometheus metrics on the specified address.
         // - EXAMPLE: exporter: { file: "/path/to/file" },
         //   Dump prometheus metrics to the specified file on shutdown.
-        exporter: "log"
+        exporter: "log",
+
+        // The maximum number of peers with their own `peer` label value in
+        // the per-peer P2P metrics. The metrics of further peers are
+        // aggregated under the label value `other`.
+        p2p_max_peer_labels: 64
     },
     // ===================================
     // Configuration of the logging setup.
@@ -303,6 +370,7 @@ pub const SAMPLE_CONFIG: &str = r#"
         firewall_config: "",
         ipv4_prefixes: [],
         ipv6_prefixes: [],
+        dry_run: false,
     },
 
     // =================================
@@ -317,6 +385,46 @@ pub const SAMPLE_CONFIG: &str = r#"
     nns_registry_replicator: {
       poll_delay_duration_ms: 5000
     },
+    // ==================================================
+    // Configuration of the structured event log.
+    // ==================================================
+    event_log: {
+        // The path in which to store the segments of the event log, which
+        // records the consensus-critical decisions of the replica. If no path
+        // is given, no events are recorded.
+        // EXAMPLE: path: "/var/lib/ic/data/event_log",
+
+        // The size, in bytes, after which a segment is closed and a new one
+        // is started.
+        max_segment_size_bytes: 16777216,
+
+        // The number of most recent segments that are retained.
+        retained_segments: 8,
+    },
+    // ==================================================
+    // Configuration of the health self-assessment.
+    // ==================================================
+    health: {
+        // The replica is degraded if fewer than this percentage of its peers
+        // are connected, and unhealthy if none is.
+        min_connected_peers_percent: 67,
+
+        // The replica is unhealthy if no new height was finalized for this
+        // many seconds.
+        max_finalization_stall_secs: 60,
+
+        // The replica is degraded if the finalized blocks did not reference a
+        // new certified height for this many seconds.
+        max_certification_stall_secs: 60,
+
+        // The replica is degraded if this many finalized heights are not
+        // executed yet.
+        max_execution_lag: 50,
+
+        // The replica is degraded if the latest state is this many heights
+        // ahead of the latest checkpoint.
+        max_checkpoint_lag: 1500,
+    },
 }
 "#;
 
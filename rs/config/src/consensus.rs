@@ -3,22 +3,170 @@ use serde::{Deserialize, Serialize};
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ConsensusConfig {
     detect_starvation: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    adaptive_payload_size: Option<AdaptivePayloadSizeConfig>,
+    /// The number of the latest round timelines that are kept for debugging.
+    #[serde(default = "default_round_timelines")]
+    round_timelines: usize,
+    /// The number of threads that verify DKG dealings in parallel.
+    #[serde(default = "default_dkg_validation_threads")]
+    dkg_validation_threads: usize,
+    /// The number of threads of the crypto thread pool, on which the
+    /// consensus and certification components create and verify signatures.
+    #[serde(default = "default_crypto_threads")]
+    crypto_threads: usize,
+    /// Whether the replica only observes the subnet, i.e. validates the
+    /// artifacts of the subnet members and executes the finalized blocks, but
+    /// does not produce blocks, shares or dealings while it is not a member of
+    /// the subnet at the finalized height. The node must still be in the
+    /// subnet record to connect to its peers.
+    #[serde(default)]
+    observer: bool,
+    /// Whether the replica only relays ingress messages and state sync
+    /// chunks, e.g. on a boundary node, without holding consensus,
+    /// certification or DKG artifacts. Cannot be combined with `observer`.
+    #[serde(default)]
+    relay: bool,
+}
+
+fn default_round_timelines() -> usize {
+    100
+}
+
+fn default_dkg_validation_threads() -> usize {
+    4
+}
+
+fn default_crypto_threads() -> usize {
+    4
+}
+
+/// The bounds of the controller that scales the ingress and xnet payload
+/// sizes of the blocks made by this replica to the load of the subnet.
+///
+/// The payload sizes are a percentage of their maximum, which is halved when
+/// the subnet is overloaded, and grows again by a fixed step otherwise.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdaptivePayloadSizeConfig {
+    /// The smallest percentage of the maximum payload sizes that is used.
+    pub min_payload_percent: u64,
+    /// The largest percentage of the maximum payload sizes that is used.
+    pub max_payload_percent: u64,
+    /// The subnet is overloaded if the average round time exceeds this many
+    /// milliseconds.
+    pub target_round_time_ms: u64,
+    /// The subnet is overloaded if more than this many heights have been
+    /// proposed but not executed yet.
+    pub max_execution_backlog: u64,
+}
+
+impl Default for AdaptivePayloadSizeConfig {
+    fn default() -> Self {
+        Self {
+            min_payload_percent: 10,
+            max_payload_percent: 100,
+            target_round_time_ms: 3000,
+            max_execution_backlog: 10,
+        }
+    }
 }
 
 impl ConsensusConfig {
     pub fn new(detect_starvation: bool) -> Self {
-        Self { detect_starvation }
+        Self {
+            detect_starvation,
+            adaptive_payload_size: None,
+            round_timelines: default_round_timelines(),
+            dkg_validation_threads: default_dkg_validation_threads(),
+            crypto_threads: default_crypto_threads(),
+            observer: false,
+            relay: false,
+        }
+    }
+
+    /// Enables the adaptive payload sizes with the given bounds.
+    pub fn with_adaptive_payload_size(mut self, config: AdaptivePayloadSizeConfig) -> Self {
+        self.adaptive_payload_size = Some(config);
+        self
+    }
+
+    /// Keeps the timelines of the given number of latest rounds.
+    pub fn with_round_timelines(mut self, round_timelines: usize) -> Self {
+        self.round_timelines = round_timelines;
+        self
+    }
+
+    /// Verifies the DKG dealings on the given number of threads.
+    pub fn with_dkg_validation_threads(mut self, dkg_validation_threads: usize) -> Self {
+        self.dkg_validation_threads = dkg_validation_threads;
+        self
+    }
+
+    /// Creates and verifies signatures on the given number of threads.
+    pub fn with_crypto_threads(mut self, crypto_threads: usize) -> Self {
+        self.crypto_threads = crypto_threads;
+        self
+    }
+
+    /// Runs the replica as an observer of the subnet.
+    pub fn with_observer(mut self, observer: bool) -> Self {
+        self.observer = observer;
+        self
+    }
+
+    /// Runs the replica as a relay of ingress messages and state sync chunks.
+    pub fn with_relay(mut self, relay: bool) -> Self {
+        self.relay = relay;
+        self
     }
 
     pub fn detect_starvation(&self) -> bool {
         self.detect_starvation
     }
+
+    /// The bounds of the adaptive payload sizes, or None if blocks always use
+    /// the maximum payload sizes.
+    pub fn adaptive_payload_size(&self) -> Option<&AdaptivePayloadSizeConfig> {
+        self.adaptive_payload_size.as_ref()
+    }
+
+    /// The number of the latest round timelines that are kept for debugging.
+    pub fn round_timelines(&self) -> usize {
+        self.round_timelines
+    }
+
+    /// The number of threads that verify DKG dealings in parallel.
+    pub fn dkg_validation_threads(&self) -> usize {
+        self.dkg_validation_threads
+    }
+
+    /// The number of threads of the crypto thread pool.
+    pub fn crypto_threads(&self) -> usize {
+        self.crypto_threads
+    }
+
+    /// Whether the replica only observes the subnet without producing
+    /// artifacts.
+    pub fn observer(&self) -> bool {
+        self.observer
+    }
+
+    /// Whether the replica only relays ingress messages and state sync chunks.
+    pub fn relay(&self) -> bool {
+        self.relay
+    }
 }
 
 impl Default for ConsensusConfig {
     fn default() -> Self {
         Self {
             detect_starvation: true,
+            adaptive_payload_size: None,
+            round_timelines: default_round_timelines(),
+            dkg_validation_threads: default_dkg_validation_threads(),
+            crypto_threads: default_crypto_threads(),
+            observer: false,
+            relay: false,
         }
     }
 }
@@ -31,12 +31,19 @@ pub struct CryptoConfig {
         proptest(strategy = "any::<String>().prop_map(|x| PathBuf::from(x))")
     )]
     pub crypto_root: PathBuf,
+    /// If set, the replica rotates the node's committee signing and TLS keys
+    /// once the current keys are older than this many seconds.
+    #[serde(default)]
+    pub key_rotation_period_secs: Option<u64>,
 }
 
 impl CryptoConfig {
     /// Return a new CryptoConfig with the given crypto_root path.
     pub fn new(crypto_root: PathBuf) -> Self {
-        Self { crypto_root }
+        Self {
+            crypto_root,
+            key_rotation_period_secs: None,
+        }
     }
 
     /// Creates a new CryptoConfig in a temporary directory and returns the
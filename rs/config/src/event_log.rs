@@ -0,0 +1,38 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// The default size, in bytes, after which a segment of the event log is
+/// closed and a new one is started.
+pub const DEFAULT_MAX_SEGMENT_SIZE_BYTES: u64 = 16 * 1024 * 1024;
+
+/// The default number of most recent segments of the event log that are
+/// retained.
+pub const DEFAULT_RETAINED_SEGMENTS: usize = 8;
+
+/// Configuration of the structured event log, which records the
+/// consensus-critical decisions of the replica on disk for post-mortems.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// The path to a folder with write permissions, in which the segments of
+    /// the event log are stored. If no path is provided, no events are
+    /// recorded.
+    pub path: Option<PathBuf>,
+    /// The size, in bytes, after which a segment is closed and a new one is
+    /// started.
+    pub max_segment_size_bytes: u64,
+    /// The number of most recent segments that are retained. Older segments
+    /// are deleted, so the log takes at most about
+    /// `max_segment_size_bytes * retained_segments` bytes.
+    pub retained_segments: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            path: None,
+            max_segment_size_bytes: DEFAULT_MAX_SEGMENT_SIZE_BYTES,
+            retained_segments: DEFAULT_RETAINED_SEGMENTS,
+        }
+    }
+}
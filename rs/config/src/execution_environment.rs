@@ -30,6 +30,25 @@ const SUBNET_MEMORY_CAPACITY: NumBytes = NumBytes::new(300 * 1024 * 1024 * 1024)
 /// be used for storing other copies of the canister states.
 pub(crate) const SUBNET_HEAP_DELTA_CAPACITY: NumBytes = NumBytes::new(1024 * 1024 * 1024 * 1024);
 
+/// The number of threads executing queries. Queries to the same canister are
+/// batched, so that several threads mostly pay off for queries to different
+/// canisters.
+const QUERY_EXECUTION_THREADS: usize = 4;
+
+/// The maximum number of threads that execute queries to the same canister at
+/// the same time, such that a single popular canister cannot occupy all query
+/// execution threads.
+const MAX_QUERY_CONCURRENCY_PER_CANISTER: usize = 2;
+
+/// The maximum number of queries in a batch, i.e. that are executed one after
+/// the other on the same thread, such that the queries to a popular canister
+/// are spread over several batches.
+const MAX_QUERY_BATCH_SIZE: usize = 32;
+
+/// The maximum number of queries to the same canister that wait for their
+/// execution. Further queries are rejected until the queue drains.
+const MAX_QUEUED_QUERIES_PER_CANISTER: usize = 1000;
+
 #[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
 #[serde(default)]
 pub struct Config {
@@ -68,6 +87,21 @@ pub struct Config {
 
     /// Maximum number of controllers a canister can have.
     pub max_controllers: usize,
+
+    /// The number of threads executing queries.
+    pub query_execution_threads: usize,
+
+    /// The maximum number of threads that execute queries to the same canister
+    /// at the same time.
+    pub max_query_concurrency_per_canister: usize,
+
+    /// The maximum number of queries that are executed one after the other on
+    /// the same thread.
+    pub max_query_batch_size: usize,
+
+    /// The maximum number of queries to the same canister that wait for their
+    /// execution.
+    pub max_queued_queries_per_canister: usize,
 }
 
 impl Default for Config {
@@ -91,6 +125,10 @@ impl Default for Config {
             // Maximum number of controllers allowed in a request (specified in the public
             // Spec).
             max_controllers: 10,
+            query_execution_threads: QUERY_EXECUTION_THREADS,
+            max_query_concurrency_per_canister: MAX_QUERY_CONCURRENCY_PER_CANISTER,
+            max_query_batch_size: MAX_QUERY_BATCH_SIZE,
+            max_queued_queries_per_canister: MAX_QUEUED_QUERIES_PER_CANISTER,
         }
     }
 }
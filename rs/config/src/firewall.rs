@@ -21,9 +21,14 @@ pub struct Config {
         proptest(strategy = "any::<String>().prop_map(|x| PathBuf::from(x))")
     )]
     pub config_file: PathBuf,
+    /// The nftables template and prefixes used while the registry holds no
+    /// firewall configuration
     pub firewall_config: String,
     pub ipv4_prefixes: Vec<String>,
     pub ipv6_prefixes: Vec<String>,
+    /// If true, changes to the ruleset are only logged, not written
+    #[serde(default)]
+    pub dry_run: bool,
 }
 
 impl Default for Config {
@@ -33,6 +38,7 @@ impl Default for Config {
             firewall_config: "".to_string(),
             ipv4_prefixes: vec![],
             ipv6_prefixes: vec![],
+            dry_run: false,
         }
     }
 }
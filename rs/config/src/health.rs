@@ -0,0 +1,54 @@
+use serde::{Deserialize, Serialize};
+
+/// The default percentage of the peers of the subnet that must be connected
+/// for the replica not to be considered degraded.
+pub const DEFAULT_MIN_CONNECTED_PEERS_PERCENT: u64 = 67;
+
+/// The default duration, in seconds, without a new finalized height after
+/// which the replica is considered unhealthy.
+pub const DEFAULT_MAX_FINALIZATION_STALL_SECS: u64 = 60;
+
+/// The default duration, in seconds, without a new certified height after
+/// which the replica is considered degraded.
+pub const DEFAULT_MAX_CERTIFICATION_STALL_SECS: u64 = 60;
+
+/// The default number of finalized heights that execution may fall behind
+/// before the replica is considered degraded.
+pub const DEFAULT_MAX_EXECUTION_LAG: u64 = 50;
+
+/// The default number of heights that the latest state may be ahead of the
+/// latest checkpoint before the replica is considered degraded.
+pub const DEFAULT_MAX_CHECKPOINT_LAG: u64 = 1500;
+
+/// The thresholds of the health self-assessment of the replica.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// The replica is degraded if fewer than this percentage of its peers
+    /// are connected, and unhealthy if none is.
+    pub min_connected_peers_percent: u64,
+    /// The replica is unhealthy if no new height was finalized for this
+    /// many seconds.
+    pub max_finalization_stall_secs: u64,
+    /// The replica is degraded if the finalized blocks did not reference a
+    /// new certified height for this many seconds.
+    pub max_certification_stall_secs: u64,
+    /// The replica is degraded if this many finalized heights are not
+    /// executed yet.
+    pub max_execution_lag: u64,
+    /// The replica is degraded if the latest state is this many heights
+    /// ahead of the latest checkpoint.
+    pub max_checkpoint_lag: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            min_connected_peers_percent: DEFAULT_MIN_CONNECTED_PEERS_PERCENT,
+            max_finalization_stall_secs: DEFAULT_MAX_FINALIZATION_STALL_SECS,
+            max_certification_stall_secs: DEFAULT_MAX_CERTIFICATION_STALL_SECS,
+            max_execution_lag: DEFAULT_MAX_EXECUTION_LAG,
+            max_checkpoint_lag: DEFAULT_MAX_CHECKPOINT_LAG,
+        }
+    }
+}
@@ -89,6 +89,18 @@ pub struct ExternalConfig {
     // Clients X509 certificate used for establishing TLS protocol. The field
     // is base64 encoded DER certificate.
     pub clients_x509_cert: Option<String>,
+
+    /// The maximum size in bytes of the cache of query responses. The cache is
+    /// disabled if not set.
+    ///
+    /// ```json5
+    /// {
+    ///   http_handler: {
+    ///     query_cache_capacity_bytes: 104857600
+    ///   }
+    /// }
+    /// ```
+    pub query_cache_capacity_bytes: Option<usize>,
 }
 
 impl Default for ExternalConfig {
@@ -99,6 +111,7 @@ impl Default for ExternalConfig {
             port: None,
             show_root_key_in_status: true,
             clients_x509_cert: None,
+            query_cache_capacity_bytes: None,
         }
     }
 }
@@ -117,6 +130,9 @@ pub struct Config {
     pub show_root_key_in_status: bool,
     /// The digital certificate used by TLS.
     pub clients_x509_cert: Option<TlsPublicKeyCert>,
+    /// The maximum size in bytes of the cache of query responses, if the cache
+    /// is enabled
+    pub query_cache_capacity_bytes: Option<usize>,
 }
 
 impl Default for Config {
@@ -129,6 +145,7 @@ impl Default for Config {
             port_file_path: None,
             show_root_key_in_status: true,
             clients_x509_cert: None,
+            query_cache_capacity_bytes: None,
         }
     }
 }
@@ -163,6 +180,7 @@ impl TryFrom<ExternalConfig> for Config {
         }?;
 
         config.show_root_key_in_status = ec.show_root_key_in_status;
+        config.query_cache_capacity_bytes = ec.query_cache_capacity_bytes;
         if let Some(base64_clients_x509_cert) = ec.clients_x509_cert {
             let base64_decoded_clients_x509_cert = base64::decode(&base64_clients_x509_cert)
                 .map_err(|_err| "Could not decode x509 cert from base64 encoding.")?;
@@ -2,13 +2,16 @@ pub mod config;
 pub mod config_parser;
 pub mod config_sample;
 pub mod subnet_config;
+pub mod validation;
 
 pub mod artifact_pool;
 pub mod consensus;
 pub mod crypto;
 pub mod embedders;
+pub mod event_log;
 pub mod execution_environment;
 pub mod firewall;
+pub mod health;
 pub mod http_handler;
 pub mod logger;
 pub mod message_routing;
@@ -16,6 +19,7 @@ pub mod metrics;
 pub mod nns_registry_replicator;
 pub mod registration;
 pub mod registry_client;
+pub mod reload;
 pub mod state_manager;
 
 pub use config::*;
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 
 #[derive(Clone, Deserialize, Debug, PartialEq, Eq, Serialize)]
 #[serde(default)]
@@ -9,6 +10,28 @@ use serde::{Deserialize, Serialize};
 pub struct Config {
     pub xnet_ip_addr: String,
     pub xnet_port: u16,
+
+    /// How certified stream slices are pulled from other subnets. All nodes
+    /// of a subnet and of the subnets it exchanges messages with must use
+    /// the same transport.
+    pub xnet_transport: XNetTransport,
+
+    /// The interval in milliseconds at which stream slices are pulled from
+    /// the XNet peers with `XNetTransport::Gossip`. It should be well below
+    /// the block rate, so that new messages are usually pooled before the
+    /// next block is made.
+    pub xnet_gossip_pull_interval_ms: u64,
+}
+
+impl Config {
+    /// Returns the interval at which stream slices are pulled over gossip; or
+    /// `None` if they are not pulled over gossip.
+    pub fn xnet_gossip_pull_interval(&self) -> Option<Duration> {
+        match self.xnet_transport {
+            XNetTransport::Https => None,
+            XNetTransport::Gossip => Some(Duration::from_millis(self.xnet_gossip_pull_interval_ms)),
+        }
+    }
 }
 
 impl Default for Config {
@@ -16,6 +39,27 @@ impl Default for Config {
         Self {
             xnet_ip_addr: "127.0.0.1".to_string(),
             xnet_port: 2497,
+            xnet_transport: XNetTransport::default(),
+            xnet_gossip_pull_interval_ms: 300,
         }
     }
 }
+
+/// The transport used by the XNet payload builder to pull certified stream
+/// slices from other subnets.
+#[derive(Clone, Copy, Deserialize, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum XNetTransport {
+    /// Slices are queried from the XNet endpoints of the remote nodes over
+    /// HTTPS.
+    Https,
+    /// Slices are pulled over gossip from a few nodes of each remote subnet,
+    /// so that the XNet endpoints needn't be reachable from other subnets.
+    Gossip,
+}
+
+impl Default for XNetTransport {
+    fn default() -> Self {
+        XNetTransport::Https
+    }
+}
@@ -19,10 +19,33 @@ impl Default for Exporter {
     }
 }
 
-#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+/// The default maximum number of peers with their own label value in the
+/// per-peer P2P metrics.
+pub const DEFAULT_P2P_MAX_PEER_LABELS: usize = 64;
+
+fn default_p2p_max_peer_labels() -> usize {
+    DEFAULT_P2P_MAX_PEER_LABELS
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Config {
     pub exporter: Exporter,
     /// Clients X509 certificate used for establishing TLS protocol. The field
     /// is base64 encoded DER certificate.
     pub clients_x509_cert: Option<X509PublicKeyCert>,
+    /// The maximum number of peers with their own `peer` label value in the
+    /// per-peer P2P metrics. The metrics of further peers are aggregated
+    /// under the label value `other`.
+    #[serde(default = "default_p2p_max_peer_labels")]
+    pub p2p_max_peer_labels: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            exporter: Exporter::default(),
+            clients_x509_cert: None,
+            p2p_max_peer_labels: DEFAULT_P2P_MAX_PEER_LABELS,
+        }
+    }
 }
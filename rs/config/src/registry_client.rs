@@ -2,6 +2,9 @@ use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 use url::Url;
 
+/// The default maximal age of the registry snapshot cache, in seconds.
+const DEFAULT_SNAPSHOT_MAX_STALENESS_SECONDS: u64 = 7 * 24 * 60 * 60;
+
 /// Eventually, the replica will only read registry data from the local store
 /// and the node manager will both read from and write to the registry local
 /// store.
@@ -12,16 +15,57 @@ use url::Url;
 pub struct Config {
     #[serde(flatten)]
     pub data_provider: Option<DataProviderConfig>,
+
+    /// If specified, the registry client persists the registry versions it
+    /// fetched and, if the data provider is unreachable at startup, serves
+    /// the persisted versions until it becomes reachable again.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot_cache: Option<SnapshotCacheConfig>,
+
+    /// If set, and the data provider is the certified endpoint of the
+    /// registry canister, the certified registry deltas are distributed to
+    /// the other nodes of the subnet over gossip. A node that cannot reach
+    /// the registry canister then serves the deltas received from its peers.
+    #[serde(default)]
+    pub delta_gossip: bool,
 }
 
 impl std::default::Default for Config {
     fn default() -> Self {
         Self {
             data_provider: None,
+            snapshot_cache: None,
+            delta_gossip: false,
+        }
+    }
+}
+
+/// The local disk cache of the registry, used to start up while the NNS is
+/// unreachable, e.g. during a network-wide restart.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotCacheConfig {
+    /// The directory the registry versions are persisted to.
+    pub path: PathBuf,
+
+    /// The snapshot is not served if the data provider last confirmed it to
+    /// be up to date more than this many seconds ago.
+    #[serde(default = "default_snapshot_max_staleness_seconds")]
+    pub max_staleness_seconds: u64,
+}
+
+impl SnapshotCacheConfig {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            max_staleness_seconds: DEFAULT_SNAPSHOT_MAX_STALENESS_SECONDS,
         }
     }
 }
 
+fn default_snapshot_max_staleness_seconds() -> u64 {
+    DEFAULT_SNAPSHOT_MAX_STALENESS_SECONDS
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum DataProviderConfig {
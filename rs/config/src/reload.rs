@@ -0,0 +1,333 @@
+//! Reloading of the replica config while the replica is running.
+//!
+//! The `ConfigReloader` re-reads the config file when the replica receives a
+//! SIGHUP or the file is modified, and publishes the new config to its
+//! subscribers. Only the values listed in `reloadable_fields!` below are
+//! reloaded; the subscribers receive their changes as typed `ConfigChange`s.
+//! Changes of any other value are reported, but only take effect when the
+//! replica is restarted.
+use crate::{
+    artifact_pool::{IngressCanisterQuota, IngressSenderRateLimit},
+    config::Config,
+    config_parser::{ConfigError, ConfigSource},
+    validation::load_and_validate,
+};
+use ic_types::transport::TransportReconnectConfig;
+use slog::{info, warn, Logger};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+
+/// Annotates the config values that can be reloaded. Every entry defines a
+/// `ConfigChange` variant holding the new value of the field at the given
+/// path of `Config`.
+macro_rules! reloadable_fields {
+    ($($(#[$meta:meta])* $variant:ident($ty:ty) = $($field:ident).+;)*) => {
+        /// A change of a reloadable config value, holding the new value.
+        #[derive(Clone, Debug, PartialEq)]
+        pub enum ConfigChange {
+            $($(#[$meta])* $variant($ty),)*
+        }
+
+        /// Returns the changes of the reloadable values from `old` to `new`.
+        fn reloadable_changes(old: &Config, new: &Config) -> Vec<ConfigChange> {
+            let mut changes = vec![];
+            $(
+                if old.$($field).+ != new.$($field).+ {
+                    changes.push(ConfigChange::$variant(new.$($field).+.clone()));
+                }
+            )*
+            changes
+        }
+
+        /// Overwrites the reloadable values of `config` with those of `from`.
+        fn copy_reloadable(from: &Config, config: &mut Config) {
+            $(config.$($field).+ = from.$($field).+.clone();)*
+        }
+    };
+}
+
+reloadable_fields! {
+    /// `artifact_pool.ingress_pool_size_threshold`
+    IngressPoolSizeThreshold(Option<usize>) = artifact_pool.ingress_pool_size_threshold;
+    /// `artifact_pool.ingress_pool_canister_quota`
+    IngressPoolCanisterQuota(Option<IngressCanisterQuota>) =
+        artifact_pool.ingress_pool_canister_quota;
+    /// `artifact_pool.ingress_sender_rate_limit`
+    IngressSenderRateLimit(Option<IngressSenderRateLimit>) =
+        artifact_pool.ingress_sender_rate_limit;
+    /// `artifact_pool.artifact_ttl_secs`
+    ArtifactTtlSecs(BTreeMap<String, u64>) = artifact_pool.artifact_ttl_secs;
+    /// `logger.level`
+    LogLevel(slog::Level) = logger.level;
+    /// `logger.debug_overrides`
+    LogDebugOverrides(Vec<String>) = logger.debug_overrides;
+    /// `transport.reconnect`
+    TransportReconnect(TransportReconnectConfig) = transport.reconnect;
+}
+
+/// Returns the sections of the config in which `new` differs from `old` in
+/// values that are not reloadable.
+fn restart_required(old: &Config, new: &Config) -> Vec<&'static str> {
+    let mut old = old.clone();
+    copy_reloadable(new, &mut old);
+
+    // Destructured so that new sections cannot be forgotten here.
+    let Config {
+        registry_client,
+        transport,
+        state_manager,
+        hypervisor,
+        http_handler,
+        metrics,
+        artifact_pool,
+        consensus,
+        crypto,
+        logger,
+        nodemanager_logger,
+        message_routing,
+        malicious_behaviour,
+        firewall,
+        registration,
+        nns_registry_replicator,
+    } = new;
+    let mut sections = vec![];
+    macro_rules! compare {
+        ($($section:ident),*) => {
+            $(
+                if old.$section != *$section {
+                    sections.push(stringify!($section));
+                }
+            )*
+        };
+    }
+    compare!(
+        registry_client,
+        transport,
+        state_manager,
+        hypervisor,
+        http_handler,
+        metrics,
+        artifact_pool,
+        consensus,
+        crypto,
+        logger,
+        nodemanager_logger,
+        message_routing,
+        malicious_behaviour,
+        firewall,
+        registration,
+        nns_registry_replicator
+    );
+    sections
+}
+
+/// The result of a reload.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConfigDiff {
+    /// The changes of reloadable values, which were applied.
+    pub changes: Vec<ConfigChange>,
+    /// The sections with changes that take effect at the next restart.
+    pub restart_required: Vec<&'static str>,
+}
+
+/// Re-reads the config from its source and publishes the reloadable changes
+/// to the subscribers.
+pub struct ConfigReloader {
+    source: ConfigSource,
+    default: Config,
+    sender: watch::Sender<Arc<Config>>,
+    // Kept so that the current config can be read, and published even if
+    // there are no subscribers.
+    current: watch::Receiver<Arc<Config>>,
+}
+
+impl ConfigReloader {
+    /// Creates a reloader of the config that was loaded from `source` with
+    /// the given `default`, as by `Config::load_with_default()`.
+    pub fn new(source: ConfigSource, default: Config, config: Config) -> Self {
+        let (sender, current) = watch::channel(Arc::new(config));
+        Self {
+            source,
+            default,
+            sender,
+            current,
+        }
+    }
+
+    /// Returns the config with the latest reloadable values, and the values
+    /// loaded at startup otherwise.
+    pub fn config(&self) -> Arc<Config> {
+        Arc::clone(&*self.current.borrow())
+    }
+
+    pub fn subscribe(&self) -> ConfigSubscription {
+        ConfigSubscription {
+            seen: self.config(),
+            receiver: self.current.clone(),
+        }
+    }
+
+    /// Re-reads the config from the source and publishes the changes of the
+    /// reloadable values. A config that fails to load or validate is
+    /// rejected as a whole.
+    pub fn reload(&self) -> Result<ConfigDiff, ConfigError> {
+        // The paths are not reloadable, so they are not checked again.
+        let new = load_and_validate(&self.source, self.default.clone(), false)?;
+        let current = self.config();
+        let diff = ConfigDiff {
+            changes: reloadable_changes(&current, &new),
+            restart_required: restart_required(&current, &new),
+        };
+        if !diff.changes.is_empty() {
+            let mut config = (*current).clone();
+            copy_reloadable(&new, &mut config);
+            // Cannot fail, `self.current` is never dropped.
+            let _ = self.sender.send(Arc::new(config));
+        }
+        Ok(diff)
+    }
+
+    /// Reloads the config whenever the process receives a SIGHUP or, for
+    /// configs read from a file, the file's modification time changes, which
+    /// is checked every `poll_interval`. Runs until the SIGHUP handler is
+    /// closed.
+    pub async fn watch(self: Arc<Self>, poll_interval: Duration, log: Logger) {
+        let mut sighup = match signal(SignalKind::hangup()) {
+            Ok(sighup) => sighup,
+            Err(err) => {
+                warn!(
+                    log,
+                    "Failed to install SIGHUP handler, config reloading is disabled: {}", err
+                );
+                return;
+            }
+        };
+        let mut modified = self.modified();
+        loop {
+            let trigger = match tokio::time::timeout(poll_interval, sighup.recv()).await {
+                Ok(Some(())) => "SIGHUP",
+                Ok(None) => return,
+                Err(_elapsed) if self.modified() == modified => continue,
+                Err(_elapsed) => "file change",
+            };
+            modified = self.modified();
+
+            match self.reload() {
+                Ok(diff) => {
+                    info!(log, "Reloaded config on {}: {:?}", trigger, diff.changes);
+                    if !diff.restart_required.is_empty() {
+                        warn!(
+                            log,
+                            "Changes to the config sections {:?} take effect at the next restart",
+                            diff.restart_required
+                        );
+                    }
+                }
+                Err(err) => warn!(log, "Failed to reload config on {}: {}", trigger, err),
+            }
+        }
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        match &self.source {
+            ConfigSource::File(path) => std::fs::metadata(path).and_then(|m| m.modified()).ok(),
+            _ => None,
+        }
+    }
+}
+
+/// A subscription to the reloads of the config.
+pub struct ConfigSubscription {
+    receiver: watch::Receiver<Arc<Config>>,
+    seen: Arc<Config>,
+}
+
+impl ConfigSubscription {
+    /// Returns the config with the latest reloadable values.
+    pub fn config(&self) -> Arc<Config> {
+        Arc::clone(&*self.receiver.borrow())
+    }
+
+    /// Returns the changes since the last call, without blocking.
+    pub fn take_changes(&mut self) -> Vec<ConfigChange> {
+        let latest = self.config();
+        let changes = reloadable_changes(&self.seen, &latest);
+        self.seen = latest;
+        changes
+    }
+
+    /// Waits for the next reload and returns the changes since the last
+    /// call. Returns `None` once the reloader is dropped.
+    pub async fn changed(&mut self) -> Option<Vec<ConfigChange>> {
+        self.receiver.changed().await.ok()?;
+        Some(self.take_changes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn write_config(path: &std::path::Path, level: &str, state_root: &str) {
+        std::fs::write(
+            path,
+            format!(
+                "{{ logger: {{ level: \"{}\" }}, state_manager: {{ state_root: \"{}\" }} }}",
+                level, state_root
+            ),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn only_reloadable_values_are_published() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("ic.json5");
+        write_config(&path, "info", "/state");
+        let source = ConfigSource::File(path.clone());
+        let default = Config::new(PathBuf::from("/tmp"));
+        let config = Config::load_with_default(&source, default.clone()).unwrap();
+        let reloader = ConfigReloader::new(source, default, config.clone());
+        let mut subscription = reloader.subscribe();
+
+        assert_eq!(reloader.reload().unwrap(), ConfigDiff::default());
+        assert!(subscription.take_changes().is_empty());
+
+        write_config(&path, "warning", "/other_state");
+        assert_eq!(
+            reloader.reload().unwrap(),
+            ConfigDiff {
+                changes: vec![ConfigChange::LogLevel(slog::Level::Warning)],
+                restart_required: vec!["state_manager"],
+            }
+        );
+        assert_eq!(
+            subscription.take_changes(),
+            vec![ConfigChange::LogLevel(slog::Level::Warning)]
+        );
+        assert!(subscription.take_changes().is_empty());
+
+        let reloaded = subscription.config();
+        assert_eq!(reloaded.logger.level, slog::Level::Warning);
+        assert_eq!(reloaded.state_manager, config.state_manager);
+    }
+
+    #[test]
+    fn invalid_config_is_rejected() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("ic.json5");
+        write_config(&path, "info", "/state");
+        let source = ConfigSource::File(path.clone());
+        let default = Config::new(PathBuf::from("/tmp"));
+        let config = Config::load_with_default(&source, default.clone()).unwrap();
+        let reloader = ConfigReloader::new(source, default, config.clone());
+
+        std::fs::write(&path, "{ logger: ").unwrap();
+        assert!(reloader.reload().is_err());
+        assert_eq!(*reloader.config(), config);
+    }
+}
@@ -1,17 +1,223 @@
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
+/// The default number of checkpoints a heap page must stay unmodified for
+/// before it is moved to cold storage.
+const DEFAULT_COLD_AFTER_CHECKPOINTS: u8 = 3;
+
+/// The default size of the smallest heap file that has pages in cold storage.
+const DEFAULT_MIN_HEAP_SIZE_BYTES: u64 = 64 * 1024 * 1024;
+
+/// The default number of most recent checkpoints kept when removing states.
+const DEFAULT_RECENT_CHECKPOINTS: usize = 1;
+
+/// The default pause of the scrubber after verifying a chunk, in milliseconds.
+const DEFAULT_SCRUB_CHUNK_PAUSE_MILLIS: u64 = 10;
+
+/// The default pause of the scrubber between two passes over a checkpoint, in
+/// seconds.
+const DEFAULT_SCRUB_PASS_PAUSE_SECONDS: u64 = 60;
+
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Config {
     state_root: PathBuf,
+
+    /// The configuration of tiered storage. If this field is not specified,
+    /// all canister heap pages are kept under the state root.
+    ///
+    /// Removing the section moves the cold pages back to the state root at the
+    /// next checkpoints; the cold storage root must be kept until then.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tiered_storage: Option<TieredStorageConfig>,
+
+    /// The policy deciding which checkpoints are kept when older states are
+    /// removed.
+    #[serde(default)]
+    pruning_policy: StatePruningPolicy,
+
+    /// The configuration of the background task that verifies the checkpoints
+    /// on disk against their manifests.
+    #[serde(default)]
+    scrubber: ScrubberConfig,
+
+    /// Whether state sync chunks are compressed. If set, this replica asks
+    /// its peers for compressed chunks and compresses the chunks it serves to
+    /// peers that ask for them.
+    #[serde(default = "default_compress_state_sync_chunks")]
+    compress_state_sync_chunks: bool,
 }
 
 impl Config {
     pub fn new(state_root: PathBuf) -> Self {
-        Self { state_root }
+        Self {
+            state_root,
+            tiered_storage: None,
+            pruning_policy: Default::default(),
+            scrubber: Default::default(),
+            compress_state_sync_chunks: default_compress_state_sync_chunks(),
+        }
+    }
+
+    /// Returns this config with tiered storage enabled.
+    pub fn with_tiered_storage(self, tiered_storage: TieredStorageConfig) -> Self {
+        Self {
+            tiered_storage: Some(tiered_storage),
+            ..self
+        }
+    }
+
+    /// Returns this config with the given pruning policy.
+    pub fn with_pruning_policy(self, pruning_policy: StatePruningPolicy) -> Self {
+        Self {
+            pruning_policy,
+            ..self
+        }
+    }
+
+    /// Returns this config with the given scrubber configuration.
+    pub fn with_scrubber(self, scrubber: ScrubberConfig) -> Self {
+        Self { scrubber, ..self }
+    }
+
+    /// Returns this config with state sync chunk compression enabled or
+    /// disabled.
+    pub fn with_compress_state_sync_chunks(self, compress_state_sync_chunks: bool) -> Self {
+        Self {
+            compress_state_sync_chunks,
+            ..self
+        }
     }
 
     pub fn state_root(&self) -> PathBuf {
         self.state_root.clone()
     }
+
+    pub fn tiered_storage(&self) -> Option<&TieredStorageConfig> {
+        self.tiered_storage.as_ref()
+    }
+
+    pub fn pruning_policy(&self) -> &StatePruningPolicy {
+        &self.pruning_policy
+    }
+
+    pub fn scrubber(&self) -> &ScrubberConfig {
+        &self.scrubber
+    }
+
+    pub fn compress_state_sync_chunks(&self) -> bool {
+        self.compress_state_sync_chunks
+    }
+}
+
+fn default_compress_state_sync_chunks() -> bool {
+    true
+}
+
+/// The scrubber re-reads the chunks of the checkpoints on disk one by one and
+/// compares their hashes with the manifests, so that disk corruption is
+/// detected before corrupted chunks are served to state sync peers.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScrubberConfig {
+    /// Whether the scrubber runs at all. It is disabled by default, as it
+    /// keeps reading the checkpoints from disk.
+    #[serde(default = "default_scrubber_enabled")]
+    pub enabled: bool,
+
+    /// The pause after verifying a chunk, which limits the disk bandwidth
+    /// used by the scrubber.
+    #[serde(default = "default_scrub_chunk_pause_millis")]
+    pub chunk_pause_millis: u64,
+
+    /// The pause after verifying all chunks of a checkpoint.
+    #[serde(default = "default_scrub_pass_pause_seconds")]
+    pub pass_pause_seconds: u64,
+}
+
+impl Default for ScrubberConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_scrubber_enabled(),
+            chunk_pause_millis: default_scrub_chunk_pause_millis(),
+            pass_pause_seconds: default_scrub_pass_pause_seconds(),
+        }
+    }
+}
+
+fn default_scrubber_enabled() -> bool {
+    false
+}
+
+fn default_scrub_chunk_pause_millis() -> u64 {
+    DEFAULT_SCRUB_CHUNK_PAUSE_MILLIS
+}
+
+fn default_scrub_pass_pause_seconds() -> u64 {
+    DEFAULT_SCRUB_PASS_PAUSE_SECONDS
+}
+
+/// Decides which checkpoints survive the removal of states below a height.
+///
+/// Besides the checkpoints kept by this policy, the state manager always
+/// keeps the latest checkpoint at or below the requested height, all states
+/// above it, and the heights pinned via its API.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatePruningPolicy {
+    /// The number of most recent checkpoints to keep.
+    #[serde(default = "default_recent_checkpoints")]
+    pub recent_checkpoints: usize,
+
+    /// If set, the first checkpoint of every interval of this many heights is
+    /// kept as well, e.g. one per DKG interval.  These checkpoints are never
+    /// removed, so this is meant for nodes that back up states.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval_length: Option<u64>,
+}
+
+impl Default for StatePruningPolicy {
+    fn default() -> Self {
+        Self {
+            recent_checkpoints: default_recent_checkpoints(),
+            interval_length: None,
+        }
+    }
+}
+
+fn default_recent_checkpoints() -> usize {
+    DEFAULT_RECENT_CHECKPOINTS
+}
+
+/// Tiered storage keeps rarely modified canister heap pages compressed in a
+/// separate directory, usually on a cheaper and slower disk than the state
+/// root, and loads them on demand.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TieredStorageConfig {
+    /// The directory that holds the cold heap pages.
+    pub cold_storage_root: PathBuf,
+
+    /// The number of checkpoints a heap page must stay unmodified for before
+    /// it is moved to cold storage.
+    #[serde(default = "default_cold_after_checkpoints")]
+    pub cold_after_checkpoints: u8,
+
+    /// Pages of heap files smaller than this are never moved to cold storage.
+    #[serde(default = "default_min_heap_size_bytes")]
+    pub min_heap_size_bytes: u64,
+}
+
+impl TieredStorageConfig {
+    pub fn new(cold_storage_root: PathBuf) -> Self {
+        Self {
+            cold_storage_root,
+            cold_after_checkpoints: DEFAULT_COLD_AFTER_CHECKPOINTS,
+            min_heap_size_bytes: DEFAULT_MIN_HEAP_SIZE_BYTES,
+        }
+    }
+}
+
+fn default_cold_after_checkpoints() -> u8 {
+    DEFAULT_COLD_AFTER_CHECKPOINTS
+}
+
+fn default_min_heap_size_bytes() -> u64 {
+    DEFAULT_MIN_HEAP_SIZE_BYTES
 }
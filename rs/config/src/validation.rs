@@ -0,0 +1,582 @@
+//! Checks of the invariants that relate several fields of the replica config,
+//! which cannot be checked when deserializing the individual sections.
+//!
+//! All violations are collected, so that an operator can fix the config in
+//! one go instead of restarting the replica once per mistake.
+use crate::{
+    config::{Config, ConfigOptional},
+    config_parser::{ConfigError, ConfigSource},
+    metrics::Exporter,
+};
+use ic_types::transport::TlsValidationConfig;
+use ic_types::PrincipalId;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// A violated invariant of the config.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigViolation {
+    /// The path of the offending field, e.g. `transport.p2p_flows[1].flow_tag`.
+    pub field: String,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+/// All violated invariants of a config.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigValidationError {
+    pub violations: Vec<ConfigViolation>,
+}
+
+impl fmt::Display for ConfigValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} invalid config field(s):", self.violations.len())?;
+        for violation in &self.violations {
+            write!(f, "\n  {}", violation)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigValidationError {}
+
+/// Collects the violations found by the individual checks.
+#[derive(Default)]
+struct Violations(Vec<ConfigViolation>);
+
+impl Violations {
+    fn push(&mut self, field: impl Into<String>, message: impl Into<String>) {
+        self.0.push(ConfigViolation {
+            field: field.into(),
+            message: message.into(),
+        });
+    }
+
+    fn into_result(self) -> Result<(), ConfigValidationError> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigValidationError { violations: self.0 })
+        }
+    }
+}
+
+/// Checks the invariants of `config`. If `check_paths` is set, also checks
+/// that the directories the replica writes to are writable by this process,
+/// and that the files it reads at startup exist.
+pub fn validate(config: &Config, check_paths: bool) -> Result<(), ConfigValidationError> {
+    let mut violations = Violations::default();
+    validate_invariants(config, check_paths, &mut violations);
+    violations.into_result()
+}
+
+/// Loads the config from `source`, taking omitted sections from `default`,
+/// and validates it like [validate]. A section that fails to deserialize is
+/// reported alongside the violated invariants of the other sections, instead
+/// of aborting the load.
+pub fn load_and_validate(
+    source: &ConfigSource,
+    default: Config,
+    check_paths: bool,
+) -> Result<Config, ConfigError> {
+    let mut violations = Violations::default();
+    let cfg = match source.read()? {
+        Some(cfg_str) => deserialize_sections(source, &cfg_str, &mut violations)?,
+        None => ConfigOptional::default(),
+    };
+    let config = match Config::from_optional(source, cfg.clone(), default.clone()) {
+        Ok(config) => config,
+        Err(err) => {
+            violations.push("http_handler", err.to_string());
+            let cfg = ConfigOptional {
+                http_handler: None,
+                ..cfg
+            };
+            Config::from_optional(source, cfg, default)?
+        }
+    };
+    validate_invariants(&config, check_paths, &mut violations);
+
+    violations
+        .into_result()
+        .map(|()| config)
+        .map_err(|err| ConfigError::ValidationError {
+            source: source.clone(),
+            message: err.to_string(),
+        })
+}
+
+/// Deserializes each top-level section of `cfg_str` on its own, so that all
+/// malformed sections are reported. Malformed sections are left out of the
+/// result. Only a syntax error of the document as a whole is fatal.
+fn deserialize_sections(
+    source: &ConfigSource,
+    cfg_str: &str,
+    violations: &mut Violations,
+) -> Result<ConfigOptional, ConfigError> {
+    let sections =
+        json5::from_str::<serde_json::Map<String, serde_json::Value>>(cfg_str).map_err(|err| {
+            ConfigError::ParseError {
+                source: source.clone(),
+                message: err.to_string(),
+            }
+        })?;
+
+    let mut valid_sections = serde_json::Map::new();
+    for (name, value) in sections {
+        let mut section = serde_json::Map::new();
+        section.insert(name.clone(), value);
+        let section = serde_json::Value::Object(section);
+        match serde_json::from_value::<ConfigOptional>(section.clone()) {
+            Ok(_) => {
+                if let serde_json::Value::Object(section) = section {
+                    valid_sections.extend(section);
+                }
+            }
+            Err(err) => violations.push(name, err.to_string()),
+        }
+    }
+    serde_json::from_value(serde_json::Value::Object(valid_sections)).map_err(|err| {
+        ConfigError::ParseError {
+            source: source.clone(),
+            message: err.to_string(),
+        }
+    })
+}
+
+fn validate_invariants(config: &Config, check_paths: bool, violations: &mut Violations) {
+    validate_transport(config, violations);
+    validate_ports(config, violations);
+    validate_consensus(config, violations);
+    validate_artifact_pool(config, violations);
+    if check_paths {
+        validate_paths(config, violations);
+    }
+}
+
+fn validate_consensus(config: &Config, violations: &mut Violations) {
+    if config.consensus.relay() && config.consensus.observer() {
+        violations.push(
+            "consensus.relay",
+            "cannot be combined with consensus.observer",
+        );
+    }
+}
+
+fn validate_artifact_pool(config: &Config, violations: &mut Violations) {
+    if let Some(limits) = &config.artifact_pool.artifact_processor_queue_limits {
+        if limits.backpressure_threshold == 0 {
+            violations.push(
+                "artifact_pool.artifact_processor_queue_limits.backpressure_threshold",
+                "must be positive",
+            );
+        }
+        if limits.max_pending_artifacts < limits.backpressure_threshold {
+            violations.push(
+                "artifact_pool.artifact_processor_queue_limits.max_pending_artifacts",
+                "must not be below the backpressure threshold",
+            );
+        }
+    }
+}
+
+fn validate_transport(config: &Config, violations: &mut Violations) {
+    let transport = &config.transport;
+    let node_ip = IpAddr::from_str(&transport.node_ip);
+    // An empty `node_ip` is the default, for replicas that do not run
+    // the transport.
+    if node_ip.is_err() && !transport.node_ip.is_empty() {
+        violations.push(
+            "transport.node_ip",
+            format!("'{}' is not an IP address", transport.node_ip),
+        );
+    }
+    if let Some(secondary_node_ip) = &transport.secondary_node_ip {
+        match (IpAddr::from_str(secondary_node_ip), &node_ip) {
+            (Err(_), _) => violations.push(
+                "transport.secondary_node_ip",
+                format!("'{}' is not an IP address", secondary_node_ip),
+            ),
+            (Ok(secondary), Ok(primary)) if secondary.is_ipv6() == primary.is_ipv6() => violations
+                .push(
+                    "transport.secondary_node_ip",
+                    "must be of the other address family than transport.node_ip",
+                ),
+            _ => (),
+        }
+    }
+    if let Some(external_ip) = &transport.external_ip {
+        if IpAddr::from_str(external_ip).is_err() {
+            violations.push(
+                "transport.external_ip",
+                format!("'{}' is not an IP address", external_ip),
+            );
+        }
+    }
+
+    let mut flow_tags = BTreeMap::new();
+    for (i, flow) in transport.p2p_flows.iter().enumerate() {
+        if let Some(other) = flow_tags.insert(flow.flow_tag, i) {
+            violations.push(
+                format!("transport.p2p_flows[{}].flow_tag", i),
+                format!(
+                    "flow tag {} is also used by transport.p2p_flows[{}]",
+                    flow.flow_tag, other
+                ),
+            );
+        }
+        if flow.queue_size == 0 {
+            violations.push(
+                format!("transport.p2p_flows[{}].queue_size", i),
+                "must be positive",
+            );
+        }
+    }
+
+    for (node_id, ip) in transport.tls_validation.peer_ips().into_iter().flatten() {
+        if PrincipalId::from_str(node_id).is_err() {
+            violations.push(
+                "transport.tls_validation.peer_ips",
+                format!("'{}' is not a node ID", node_id),
+            );
+        }
+        if IpAddr::from_str(ip).is_err() {
+            violations.push(
+                format!("transport.tls_validation.peer_ips.{}", node_id),
+                format!("'{}' is not an IP address", ip),
+            );
+        }
+    }
+
+    let reconnect = &transport.reconnect;
+    // A zero interval would make the control plane retry without waiting.
+    if reconnect.initial_retry_interval_ms == 0 {
+        violations.push(
+            "transport.reconnect.initial_retry_interval_ms",
+            "must be positive",
+        );
+    } else if reconnect.initial_retry_interval_ms > reconnect.max_retry_interval_ms {
+        violations.push(
+            "transport.reconnect.initial_retry_interval_ms",
+            "must not exceed transport.reconnect.max_retry_interval_ms",
+        );
+    }
+    if reconnect.backoff_multiplier == 0 {
+        violations.push("transport.reconnect.backoff_multiplier", "must be positive");
+    }
+    if reconnect.jitter_percent > 100 {
+        violations.push("transport.reconnect.jitter_percent", "must be at most 100");
+    }
+}
+
+/// Checks that the ports the replica listens on are pairwise distinct. Port 0
+/// lets the OS choose a free port, so it may be used several times.
+fn validate_ports(config: &Config, violations: &mut Violations) {
+    let mut listeners = vec![(
+        "http_handler.listen_addr".to_string(),
+        config.http_handler.listen_addr.port(),
+    )];
+    if let Exporter::Http(addr) = &config.metrics.exporter {
+        listeners.push(("metrics.exporter".to_string(), addr.port()));
+    }
+    for (i, flow) in config.transport.p2p_flows.iter().enumerate() {
+        listeners.push((
+            format!("transport.p2p_flows[{}].server_port", i),
+            flow.server_port,
+        ));
+    }
+
+    let mut ports: BTreeMap<u16, &str> = BTreeMap::new();
+    for (field, port) in &listeners {
+        if *port == 0 {
+            continue;
+        }
+        if let Some(other) = ports.insert(*port, field) {
+            violations.push(
+                field.clone(),
+                format!("port {} is also used by {}", port, other),
+            );
+        }
+    }
+}
+
+fn validate_paths(config: &Config, violations: &mut Violations) {
+    let artifact_pool = &config.artifact_pool;
+    let mut dirs = vec![
+        (
+            "artifact_pool.consensus_pool_path",
+            artifact_pool.consensus_pool_path.clone(),
+        ),
+        (
+            "state_manager.state_root",
+            config.state_manager.state_root(),
+        ),
+        ("crypto.crypto_root", config.crypto.crypto_root.clone()),
+    ];
+    if let Some(dkg_pool_path) = &artifact_pool.dkg_pool_path {
+        dirs.push(("artifact_pool.dkg_pool_path", dkg_pool_path.clone()));
+    }
+    if let Some(backup) = &artifact_pool.backup {
+        dirs.push(("artifact_pool.backup.spool_path", backup.spool_path.clone()));
+    }
+    if let Some(artifact_log) = &artifact_pool.artifact_log {
+        dirs.push((
+            "artifact_pool.artifact_log.log_path",
+            artifact_log.log_path.clone(),
+        ));
+    }
+    if let Some(tiered_storage) = config.state_manager.tiered_storage() {
+        dirs.push((
+            "state_manager.tiered_storage.cold_storage_root",
+            tiered_storage.cold_storage_root.clone(),
+        ));
+    }
+
+    for (field, dir) in dirs {
+        if let Err(message) = check_writable_dir(&dir) {
+            violations.push(field, message);
+        }
+    }
+
+    let files = match &config.transport.tls_validation {
+        TlsValidationConfig::RegistryPinned => vec![],
+        TlsValidationConfig::CaIssued {
+            ca_cert_path,
+            cert_path,
+            ..
+        } => vec![
+            ("transport.tls_validation.ca_cert_path", ca_cert_path),
+            ("transport.tls_validation.cert_path", cert_path),
+        ],
+        TlsValidationConfig::AllowList { path, .. } => {
+            vec![("transport.tls_validation.path", path)]
+        }
+    };
+    for (field, file) in files {
+        if !file.is_file() {
+            violations.push(field, format!("{} is not a file", file.display()));
+        }
+    }
+}
+
+/// Checks that `dir` is a writable directory, or, if it does not exist yet,
+/// that it can be created.
+fn check_writable_dir(dir: &Path) -> Result<(), String> {
+    let mut existing = dir.to_path_buf();
+    while !existing.exists() {
+        existing = match existing.parent() {
+            Some(parent) if parent != Path::new("") => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+    }
+    if !existing.is_dir() {
+        return Err(format!("{} is not a directory", existing.display()));
+    }
+    tempfile::tempfile_in(&existing)
+        .map(|_| ())
+        .map_err(|err| format!("{} is not writable: {}", existing.display(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::artifact_pool::ArtifactProcessorQueueLimits;
+    use ic_types::transport::TransportFlowConfig;
+
+    fn flow(flow_tag: u32, server_port: u16) -> TransportFlowConfig {
+        TransportFlowConfig {
+            flow_tag,
+            server_port,
+            queue_size: 1024,
+            rate_limit: None,
+        }
+    }
+
+    fn fields(result: Result<(), ConfigValidationError>) -> Vec<String> {
+        result
+            .unwrap_err()
+            .violations
+            .into_iter()
+            .map(|violation| violation.field)
+            .collect()
+    }
+
+    #[test]
+    fn valid_config_is_accepted() {
+        Config::run_with_temp_config(|mut config| {
+            config.transport.node_ip = "10.0.0.1".to_string();
+            config.transport.p2p_flows = vec![flow(1, 4100), flow(2, 4101)];
+            assert_eq!(validate(&config, true), Ok(()));
+        })
+    }
+
+    #[test]
+    fn all_violations_are_reported() {
+        Config::run_with_temp_config(|mut config| {
+            config.transport.node_ip = "10.0.0.1".to_string();
+            config.transport.secondary_node_ip = Some("10.0.0.2".to_string());
+            let http_port = config.http_handler.listen_addr.port();
+            config.transport.p2p_flows = vec![flow(1, 4100), flow(1, 4101), flow(2, http_port)];
+            config.transport.reconnect.jitter_percent = 150;
+
+            assert_eq!(
+                fields(validate(&config, false)),
+                vec![
+                    "transport.secondary_node_ip",
+                    "transport.p2p_flows[1].flow_tag",
+                    "transport.reconnect.jitter_percent",
+                    "transport.p2p_flows[2].server_port",
+                ]
+            );
+        })
+    }
+
+    #[test]
+    fn artifact_processor_queue_limits_are_validated() {
+        Config::run_with_temp_config(|mut config| {
+            config.artifact_pool.artifact_processor_queue_limits =
+                Some(ArtifactProcessorQueueLimits {
+                    backpressure_threshold: 100,
+                    max_pending_artifacts: 100,
+                });
+            assert_eq!(validate(&config, false), Ok(()));
+
+            config.artifact_pool.artifact_processor_queue_limits =
+                Some(ArtifactProcessorQueueLimits {
+                    backpressure_threshold: 0,
+                    max_pending_artifacts: 0,
+                });
+            assert_eq!(
+                fields(validate(&config, false)),
+                vec!["artifact_pool.artifact_processor_queue_limits.backpressure_threshold"]
+            );
+
+            config.artifact_pool.artifact_processor_queue_limits =
+                Some(ArtifactProcessorQueueLimits {
+                    backpressure_threshold: 100,
+                    max_pending_artifacts: 10,
+                });
+            assert_eq!(
+                fields(validate(&config, false)),
+                vec!["artifact_pool.artifact_processor_queue_limits.max_pending_artifacts"]
+            );
+        })
+    }
+
+    #[test]
+    fn zero_retry_intervals_are_rejected() {
+        Config::run_with_temp_config(|mut config| {
+            config.transport.reconnect.initial_retry_interval_ms = 0;
+            assert_eq!(
+                fields(validate(&config, false)),
+                vec!["transport.reconnect.initial_retry_interval_ms"]
+            );
+
+            config.transport.reconnect.initial_retry_interval_ms = 100;
+            config.transport.reconnect.max_retry_interval_ms = 0;
+            assert_eq!(
+                fields(validate(&config, false)),
+                vec!["transport.reconnect.initial_retry_interval_ms"]
+            );
+        })
+    }
+
+    #[test]
+    fn invalid_peer_ips_are_reported() {
+        Config::run_with_temp_config(|mut config| {
+            config.transport.node_ip = "10.0.0.1".to_string();
+            let node_id = PrincipalId::new_node_test_id(1).to_string();
+            let other_node_id = PrincipalId::new_node_test_id(2).to_string();
+            config.transport.tls_validation = TlsValidationConfig::AllowList {
+                path: PathBuf::from("peers.pem"),
+                peer_ips: vec![
+                    (node_id, "10.0.0.2".to_string()),
+                    (other_node_id.clone(), "not an ip".to_string()),
+                    ("not a node ID".to_string(), "10.0.0.3".to_string()),
+                ]
+                .into_iter()
+                .collect(),
+            };
+
+            let mut fields = fields(validate(&config, false));
+            fields.sort();
+            assert_eq!(
+                fields,
+                vec![
+                    "transport.tls_validation.peer_ips".to_string(),
+                    format!("transport.tls_validation.peer_ips.{}", other_node_id),
+                ]
+            );
+        })
+    }
+
+    #[test]
+    fn relay_observers_are_reported() {
+        Config::run_with_temp_config(|mut config| {
+            config.consensus = config.consensus.clone().with_relay(true);
+            assert_eq!(validate(&config, false), Ok(()));
+
+            config.consensus = config.consensus.clone().with_observer(true);
+            assert_eq!(
+                fields(validate(&config, false)),
+                vec!["consensus.relay".to_string()]
+            );
+        })
+    }
+
+    #[test]
+    fn default_node_ip_is_accepted() {
+        Config::run_with_temp_config(|config| {
+            assert_eq!(config.transport.node_ip, "");
+            assert_eq!(validate(&config, false), Ok(()));
+        })
+    }
+
+    #[test]
+    fn deserialization_errors_are_reported_with_violations() {
+        let source = ConfigSource::Literal(
+            r#"{
+                transport: { node_ip: "not an ip", p2p_flows: [] },
+                state_manager: { state_root: 42 },
+            }"#
+            .to_string(),
+        );
+        let tmpdir = tempfile::tempdir().unwrap();
+        let default = Config::new(tmpdir.path().to_path_buf());
+
+        match load_and_validate(&source, default, false) {
+            Err(ConfigError::ValidationError { message, .. }) => {
+                assert!(
+                    message.starts_with("2 invalid config field(s):"),
+                    "{}",
+                    message
+                );
+                assert!(message.contains("\n  state_manager: "), "{}", message);
+                assert!(message.contains("\n  transport.node_ip: "), "{}", message);
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unwritable_paths_are_reported() {
+        Config::run_with_temp_config(|mut config| {
+            let file = tempfile::NamedTempFile::new().unwrap();
+            config.transport.node_ip = "10.0.0.1".to_string();
+            config.artifact_pool.dkg_pool_path = Some(file.path().join("dkg"));
+
+            assert_eq!(
+                fields(validate(&config, true)),
+                vec!["artifact_pool.dkg_pool_path"]
+            );
+        })
+    }
+}
@@ -14,7 +14,7 @@ use criterion::{criterion_group, criterion_main, Criterion};
 use ic_artifact_pool::consensus_pool::ConsensusPoolImpl;
 use ic_config::state_manager::Config as StateManagerConfig;
 use ic_consensus::consensus::{
-    payload_builder::{PayloadBuilder, PayloadBuilderImpl},
+    payload_builder::{default_sections, PayloadBuilder, PayloadBuilderImpl},
     pool_reader::PoolReader,
 };
 use ic_consensus_message::{make_genesis, ConsensusMessageHashable};
@@ -135,12 +135,15 @@ where
             no_op_logger(),
             Arc::new(state_manager),
             cycles_account_manager,
-            ic_types::malicious_flags::MaliciousFlags::default(),
+            ic_types::malicious_strategies::MaliciousBehaviors::default(),
         ));
 
         let payload_builder = Arc::new(PayloadBuilderImpl::new(
-            ingress_manager,
-            Arc::new(FakeXNetPayloadBuilder::new()),
+            default_sections(
+                ingress_manager,
+                Arc::new(FakeXNetPayloadBuilder::new()),
+                &metrics_registry,
+            ),
             metrics_registry,
         ));
 
@@ -1,4 +1,5 @@
 use ic_crypto::crypto_hash;
+use ic_interfaces::crypto::CryptoHashable;
 use ic_types::{
     batch::ValidationContext,
     consensus::{
@@ -17,6 +18,16 @@ use phantom_newtype::Id;
 mod hashable;
 pub use hashable::ConsensusMessageHashable;
 
+/// Convert a CryptoHashable into a 32 bytes which can be used to seed a RNG
+pub fn crypto_hashable_to_seed<T: CryptoHashable>(hashable: &T) -> [u8; 32] {
+    let hash = crypto_hash(hashable);
+    let CryptoHash(hash_bytes) = hash.get();
+    let mut seed = [0; 32]; // zero padded if digest is less than 32 bytes
+    let n = hash_bytes.len().min(32);
+    seed[0..n].copy_from_slice(&hash_bytes[0..n]);
+    seed
+}
+
 /// Return the genesis BlockProposal and RandomBeacon made for the given height.
 pub fn make_genesis(summary: dkg::Summary) -> CatchUpPackage {
     // Use the registry version and height, from which the summary package was
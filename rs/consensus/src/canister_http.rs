@@ -0,0 +1,339 @@
+//! This module defines the canister HTTP component, which validates the
+//! responses to the HTTP outcalls of canisters and the shares over them that
+//! are received from peers, and purges the ones of requests that timed out.
+//!
+//! The responses received by this replica are added to the pool together with
+//! the share of this replica over them. A share received from a peer is valid
+//! if it is signed by a node of the subnet, and a response received from a
+//! peer is valid once a valid share over it is known, since only then it may
+//! become part of a payload. See [payload_builder] for how the responses are
+//! included in blocks; message routing inducts the included responses into
+//! the input queues of the canisters.
+//!
+//! The component that makes the outcalls and adds their responses to the pool
+//! with [CanisterHttpChangeAction::AddToValidated] is not part of the replica
+//! yet, so no responses are produced until it is.
+use crate::consensus::ConsensusCrypto;
+use ic_interfaces::{
+    canister_http::{
+        CanisterHttp, CanisterHttpChangeAction, CanisterHttpChangeSet, CanisterHttpGossip,
+        CanisterHttpPayloadValidationError, CanisterHttpPermanentValidationError, CanisterHttpPool,
+        CanisterHttpTransientValidationError,
+    },
+    consensus_pool::ConsensusPoolCache,
+    crypto::ErrorReplication,
+    registry::RegistryClient,
+    validation::{ValidationError, ValidationResult},
+};
+use ic_logger::{debug, ReplicaLogger};
+use ic_registry_client::helper::subnet::SubnetRegistry;
+use ic_types::{
+    artifact::{CanisterHttpMessageAttribute, CanisterHttpMessageId, Priority, PriorityFn},
+    consensus::canister_http::{CanisterHttpMessage, CanisterHttpResponseShare},
+    NodeId, RegistryVersion, SubnetId,
+};
+use std::sync::Arc;
+
+pub mod payload_builder;
+
+/// Returns the nodes of the subnet at the given registry version.
+fn subnet_nodes(
+    registry_client: &dyn RegistryClient,
+    subnet_id: SubnetId,
+    registry_version: RegistryVersion,
+) -> Result<Vec<NodeId>, CanisterHttpPayloadValidationError> {
+    registry_client
+        .get_node_ids_on_subnet(subnet_id, registry_version)
+        .map_err(|err| {
+            ValidationError::Transient(CanisterHttpTransientValidationError::RegistryUnavailable(
+                err,
+            ))
+        })?
+        .ok_or(ValidationError::Transient(
+            CanisterHttpTransientValidationError::SubnetNotFound(registry_version),
+        ))
+}
+
+/// Checks that the share is signed by one of the given nodes of the subnet.
+fn check_share(
+    crypto: &dyn ConsensusCrypto,
+    nodes: &[NodeId],
+    share: &CanisterHttpResponseShare,
+) -> ValidationResult<CanisterHttpPayloadValidationError> {
+    if !nodes.contains(&share.signature.signer) {
+        return Err(ValidationError::Permanent(
+            CanisterHttpPermanentValidationError::SignerNotInSubnet(
+                share.content.request_id(),
+                share.signature.signer,
+            ),
+        ));
+    }
+    crypto
+        .verify(share, share.content.registry_version)
+        .map_err(|err| {
+            if err.is_replicated() {
+                ValidationError::Permanent(CanisterHttpPermanentValidationError::InvalidSignature(
+                    share.content.request_id(),
+                    err,
+                ))
+            } else {
+                ValidationError::Transient(CanisterHttpTransientValidationError::Crypto(err))
+            }
+        })
+}
+
+/// Implements the `CanisterHttp` trait.
+pub struct CanisterHttpImpl {
+    consensus_cache: Arc<dyn ConsensusPoolCache>,
+    crypto: Arc<dyn ConsensusCrypto>,
+    registry_client: Arc<dyn RegistryClient>,
+    subnet_id: SubnetId,
+    log: ReplicaLogger,
+}
+
+impl CanisterHttpImpl {
+    /// Build a new canister HTTP component.
+    pub fn new(
+        consensus_cache: Arc<dyn ConsensusPoolCache>,
+        crypto: Arc<dyn ConsensusCrypto>,
+        registry_client: Arc<dyn RegistryClient>,
+        subnet_id: SubnetId,
+        log: ReplicaLogger,
+    ) -> Self {
+        Self {
+            consensus_cache,
+            crypto,
+            registry_client,
+            subnet_id,
+            log,
+        }
+    }
+
+    /// Validates the share of a peer. Shares of registry versions above the
+    /// one of the finalized block are kept until the registry version is
+    /// finalized.
+    fn validate_share(
+        &self,
+        id: CanisterHttpMessageId,
+        share: &CanisterHttpResponseShare,
+        registry_version: RegistryVersion,
+    ) -> Option<CanisterHttpChangeAction> {
+        if share.content.registry_version > registry_version {
+            return None;
+        }
+        let result = subnet_nodes(
+            self.registry_client.as_ref(),
+            self.subnet_id,
+            share.content.registry_version,
+        )
+        .and_then(|nodes| check_share(self.crypto.as_ref(), &nodes, share));
+        match result {
+            Ok(()) => Some(CanisterHttpChangeAction::MoveToValidated(
+                CanisterHttpMessage::Share(share.clone()),
+            )),
+            Err(ValidationError::Permanent(err)) => Some(CanisterHttpChangeAction::HandleInvalid(
+                id,
+                format!("{:?}", err),
+            )),
+            Err(ValidationError::Transient(err)) => {
+                debug!(
+                    self.log,
+                    "Couldn't validate the canister http share {:?}: {:?}", id, err
+                );
+                None
+            }
+        }
+    }
+}
+
+impl CanisterHttp for CanisterHttpImpl {
+    fn on_state_change(&self, canister_http_pool: &dyn CanisterHttpPool) -> CanisterHttpChangeSet {
+        let finalized_block = self.consensus_cache.finalized_block();
+        let time = finalized_block.context.time;
+
+        let mut change_set = Vec::new();
+        for msg in canister_http_pool.get_unvalidated() {
+            let id = ic_crypto::crypto_hash(msg);
+            if msg.timeout() < time {
+                change_set.push(CanisterHttpChangeAction::RemoveFromUnvalidated(id));
+                continue;
+            }
+            match msg {
+                CanisterHttpMessage::Share(share) => {
+                    change_set.extend(self.validate_share(
+                        id,
+                        share,
+                        finalized_block.context.registry_version,
+                    ));
+                }
+                CanisterHttpMessage::Response(response) => {
+                    let content_hash = ic_crypto::crypto_hash(response);
+                    if canister_http_pool.get_validated_shares().any(|share| {
+                        share.content.request_id() == response.request_id()
+                            && share.content.content_hash == content_hash
+                    }) {
+                        change_set.push(CanisterHttpChangeAction::MoveToValidated(msg.clone()));
+                    }
+                }
+            }
+        }
+
+        let timed_out_shares = canister_http_pool
+            .get_validated_shares()
+            .any(|share| share.content.timeout < time);
+        let timed_out_responses = canister_http_pool
+            .get_validated_responses()
+            .any(|response| response.timeout < time);
+        if timed_out_shares || timed_out_responses {
+            change_set.push(CanisterHttpChangeAction::PurgeTimedOut(time));
+        }
+        change_set
+    }
+}
+
+/// Implements the `CanisterHttpGossip` trait.
+pub struct CanisterHttpGossipImpl {
+    consensus_cache: Arc<dyn ConsensusPoolCache>,
+}
+
+impl CanisterHttpGossipImpl {
+    /// Build a new canister HTTP gossip component.
+    pub fn new(consensus_cache: Arc<dyn ConsensusPoolCache>) -> Self {
+        Self { consensus_cache }
+    }
+}
+
+impl CanisterHttpGossip for CanisterHttpGossipImpl {
+    /// Messages of requests that timed out before the time of the finalized
+    /// block are dropped.
+    fn get_priority_function(
+        &self,
+        _canister_http_pool: &dyn CanisterHttpPool,
+    ) -> PriorityFn<CanisterHttpMessageId, CanisterHttpMessageAttribute> {
+        let time = self.consensus_cache.finalized_block().context.time;
+        Box::new(move |_id, attribute| {
+            if attribute.timeout < time {
+                Priority::Drop
+            } else {
+                Priority::Fetch
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::mocks::{dependencies, Dependencies};
+    use ic_artifact_pool::canister_http_pool::CanisterHttpPoolImpl;
+    use ic_interfaces::{
+        artifact_pool::UnvalidatedArtifact, canister_http::MutableCanisterHttpPool,
+    };
+    use ic_logger::replica_logger::no_op_logger;
+    use ic_metrics::MetricsRegistry;
+    use ic_test_utilities::{
+        mock_time,
+        types::ids::{canister_test_id, node_test_id, subnet_test_id},
+    };
+    use ic_types::{
+        consensus::{
+            canister_http::{
+                CanisterHttpResponse, CanisterHttpResponseContent, CanisterHttpResponseMetadata,
+            },
+            BasicSignature,
+        },
+        crypto::{BasicSig, BasicSigOf},
+        messages::CallbackId,
+        Time,
+    };
+    use std::time::Duration;
+
+    pub(crate) fn make_response(id: u64, timeout: Time) -> CanisterHttpResponse {
+        CanisterHttpResponse {
+            canister_id: canister_test_id(0),
+            id: CallbackId::from(id),
+            timeout,
+            content: CanisterHttpResponseContent::Success(vec![1, 2, 3]),
+        }
+    }
+
+    pub(crate) fn make_share(
+        response: &CanisterHttpResponse,
+        signer: u64,
+    ) -> CanisterHttpResponseShare {
+        CanisterHttpResponseShare {
+            content: CanisterHttpResponseMetadata {
+                canister_id: response.canister_id,
+                id: response.id,
+                timeout: response.timeout,
+                content_hash: ic_crypto::crypto_hash(response),
+                registry_version: RegistryVersion::from(1),
+            },
+            signature: BasicSignature {
+                signature: BasicSigOf::new(BasicSig(vec![])),
+                signer: node_test_id(signer),
+            },
+        }
+    }
+
+    #[test]
+    fn test_canister_http_validation() {
+        ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
+            let Dependencies {
+                pool,
+                crypto,
+                registry,
+                ..
+            } = dependencies(pool_config, 4);
+            let mut canister_http_pool = CanisterHttpPoolImpl::new(MetricsRegistry::new());
+            let response = make_response(0, mock_time() + Duration::from_secs(60));
+            let valid = CanisterHttpMessage::Share(make_share(&response, 1));
+            let invalid = CanisterHttpMessage::Share(make_share(&response, 9));
+            let unknown = CanisterHttpMessage::Response(make_response(
+                1,
+                mock_time() + Duration::from_secs(60),
+            ));
+            for message in vec![valid.clone(), invalid.clone(), unknown] {
+                canister_http_pool.insert(UnvalidatedArtifact {
+                    message,
+                    peer_id: node_test_id(1),
+                    timestamp: mock_time(),
+                });
+            }
+
+            let canister_http = CanisterHttpImpl::new(
+                pool.get_cache(),
+                crypto,
+                registry,
+                subnet_test_id(0),
+                no_op_logger(),
+            );
+            let change_set = canister_http.on_state_change(&canister_http_pool);
+            assert_eq!(change_set.len(), 2);
+            for action in change_set {
+                match action {
+                    CanisterHttpChangeAction::MoveToValidated(msg) => assert_eq!(msg, valid),
+                    CanisterHttpChangeAction::HandleInvalid(id, _) => {
+                        assert_eq!(id, ic_crypto::crypto_hash(&invalid))
+                    }
+                    action => panic!("Unexpected change action {:?}", action),
+                }
+            }
+
+            // A response is valid once a share over it is validated.
+            canister_http_pool
+                .apply_changes(vec![CanisterHttpChangeAction::MoveToValidated(valid)]);
+            let peer_response = CanisterHttpMessage::Response(response);
+            canister_http_pool.insert(UnvalidatedArtifact {
+                message: peer_response.clone(),
+                peer_id: node_test_id(1),
+                timestamp: mock_time(),
+            });
+            let change_set = canister_http.on_state_change(&canister_http_pool);
+            assert!(change_set.iter().any(|action| matches!(
+                action,
+                CanisterHttpChangeAction::MoveToValidated(msg) if *msg == peer_response
+            )));
+        })
+    }
+}
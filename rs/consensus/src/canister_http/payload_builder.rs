@@ -0,0 +1,558 @@
+//! The payload section of the responses to HTTP outcalls.
+//!
+//! A response is included in a block once more replicas than the subnet
+//! tolerates faults signed the same metadata over it, so that at least one
+//! honest replica saw the content. A response is only included before its
+//! request times out, and while the canister still has the callback of the
+//! request in the certified state and no past payload above the certified
+//! height includes a response to it, so that each response is delivered at
+//! most once. A response that was inducted but not executed yet still has its
+//! callback; message routing drops it, since the slot reserved for the
+//! response in the input queue of the canister is taken.
+use super::{check_share, subnet_nodes};
+use crate::consensus::{
+    payload_builder::{PayloadBuildingError, PayloadSectionBuilder, PayloadSizeLimits},
+    ConsensusCrypto,
+};
+use ic_interfaces::{
+    canister_http::{
+        CanisterHttpPayloadValidationError, CanisterHttpPermanentValidationError, CanisterHttpPool,
+        CanisterHttpTransientValidationError,
+    },
+    consensus::PayloadValidationError,
+    ingress_pool::IngressPoolSelect,
+    registry::RegistryClient,
+    state_manager::StateManager,
+    validation::{ValidationError, ValidationResult},
+};
+use ic_logger::{debug, ReplicaLogger};
+use ic_replicated_state::ReplicatedState;
+use ic_types::{
+    batch::{BatchPayload, ValidationContext},
+    consensus::{
+        canister_http::{
+            CanisterHttpRequestId, CanisterHttpResponseMetadata, CanisterHttpResponseShare,
+            CanisterHttpResponseWithConsensus,
+        },
+        get_faults_tolerated, Payload,
+    },
+    messages::MAX_CANISTER_HTTP_PAYLOAD_IN_BYTES,
+    CountBytes, Height, NodeId, SubnetId, Time,
+};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+
+/// The section of the responses to HTTP outcalls.
+pub struct CanisterHttpSectionBuilder {
+    canister_http_pool: Arc<RwLock<dyn CanisterHttpPool>>,
+    crypto: Arc<dyn ConsensusCrypto>,
+    registry_client: Arc<dyn RegistryClient>,
+    state_manager: Arc<dyn StateManager<State = ReplicatedState>>,
+    subnet_id: SubnetId,
+    log: ReplicaLogger,
+}
+
+impl CanisterHttpSectionBuilder {
+    /// Create the canister HTTP section using the responses and shares of the
+    /// given pool.
+    pub fn new(
+        canister_http_pool: Arc<RwLock<dyn CanisterHttpPool>>,
+        crypto: Arc<dyn ConsensusCrypto>,
+        registry_client: Arc<dyn RegistryClient>,
+        state_manager: Arc<dyn StateManager<State = ReplicatedState>>,
+        subnet_id: SubnetId,
+        log: ReplicaLogger,
+    ) -> Self {
+        Self {
+            canister_http_pool,
+            crypto,
+            registry_client,
+            state_manager,
+            subnet_id,
+            log,
+        }
+    }
+
+    /// Returns the number of shares of distinct replicas a response needs at
+    /// the registry version of its metadata.
+    fn threshold(
+        &self,
+        metadata: &CanisterHttpResponseMetadata,
+    ) -> Result<usize, CanisterHttpPayloadValidationError> {
+        let nodes = subnet_nodes(
+            self.registry_client.as_ref(),
+            self.subnet_id,
+            metadata.registry_version,
+        )?;
+        Ok(get_faults_tolerated(nodes.len()) + 1)
+    }
+
+    fn validate_response(
+        &self,
+        response: &CanisterHttpResponseWithConsensus,
+        state: &ReplicatedState,
+        context: &ValidationContext,
+    ) -> ValidationResult<CanisterHttpPayloadValidationError> {
+        let id = response.content.request_id();
+        let invalid = |err| Err(ValidationError::Permanent(err));
+        if response.content.timeout < context.time {
+            return invalid(CanisterHttpPermanentValidationError::Timeout(id));
+        }
+        if !is_pending(state, id) {
+            return invalid(CanisterHttpPermanentValidationError::UnknownRequest(id));
+        }
+        let metadata = match response.proof.first() {
+            Some(share) => &share.content,
+            None => {
+                return invalid(CanisterHttpPermanentValidationError::NotEnoughShares {
+                    id,
+                    expected: 1,
+                    received: 0,
+                })
+            }
+        };
+        if metadata.request_id() != id
+            || metadata.timeout != response.content.timeout
+            || metadata.content_hash != ic_crypto::crypto_hash(&response.content)
+            || metadata.registry_version > context.registry_version
+            || response
+                .proof
+                .iter()
+                .any(|share| share.content != *metadata)
+        {
+            return invalid(CanisterHttpPermanentValidationError::InvalidMetadata(id));
+        }
+
+        let signers: HashSet<NodeId> = response
+            .proof
+            .iter()
+            .map(|share| share.signature.signer)
+            .collect();
+        let nodes = subnet_nodes(
+            self.registry_client.as_ref(),
+            self.subnet_id,
+            metadata.registry_version,
+        )?;
+        let threshold = get_faults_tolerated(nodes.len()) + 1;
+        if signers.len() != response.proof.len() || signers.len() < threshold {
+            return invalid(CanisterHttpPermanentValidationError::NotEnoughShares {
+                id,
+                expected: threshold,
+                received: signers.len(),
+            });
+        }
+        for share in response.proof.iter() {
+            check_share(self.crypto.as_ref(), &nodes, share)?;
+        }
+        Ok(())
+    }
+}
+
+impl PayloadSectionBuilder for CanisterHttpSectionBuilder {
+    fn name(&self) -> &'static str {
+        "canister_http"
+    }
+
+    fn build_section(
+        &self,
+        payload: &mut BatchPayload,
+        _height: Height,
+        _ingress_pool: &dyn IngressPoolSelect,
+        past_payloads: &[(Height, Time, Payload)],
+        context: &ValidationContext,
+        limits: &PayloadSizeLimits,
+    ) -> Result<(), PayloadBuildingError> {
+        let state = match self.state_manager.get_state_at(context.certified_height) {
+            Ok(state) => state.take(),
+            Err(err) => {
+                debug!(
+                    self.log,
+                    "Couldn't get the state at height {:?} to build the canister http section: {:?}",
+                    context.certified_height,
+                    err
+                );
+                return Ok(());
+            }
+        };
+        let included = past_response_ids(past_payloads);
+        let pool = self.canister_http_pool.read().unwrap();
+
+        // Group the shares by the metadata they sign, keeping one share per
+        // signer.
+        let mut shares_by_metadata: HashMap<
+            &CanisterHttpResponseMetadata,
+            BTreeMap<NodeId, &CanisterHttpResponseShare>,
+        > = HashMap::new();
+        for share in pool.get_validated_shares() {
+            let metadata = &share.content;
+            if included.contains(&metadata.request_id())
+                || metadata.timeout < context.time
+                || metadata.registry_version > context.registry_version
+                || !is_pending(&state, metadata.request_id())
+            {
+                continue;
+            }
+            shares_by_metadata
+                .entry(metadata)
+                .or_default()
+                .insert(share.signature.signer, share);
+        }
+
+        // Replicas may sign different metadata for the same request, so keep
+        // one response per request, and include the ones of the oldest
+        // requests first.
+        let mut candidates = BTreeMap::new();
+        for (metadata, shares) in shares_by_metadata {
+            if candidates.contains_key(&metadata.request_id()) {
+                continue;
+            }
+            let threshold = match self.threshold(metadata) {
+                Ok(threshold) => threshold,
+                Err(err) => {
+                    debug!(
+                        self.log,
+                        "Couldn't get the threshold of the canister http response {:?}: {:?}",
+                        metadata.request_id(),
+                        err
+                    );
+                    continue;
+                }
+            };
+            if shares.len() < threshold {
+                continue;
+            }
+            if let Some(content) = pool.get_validated_response(&metadata.content_hash) {
+                candidates.insert(
+                    metadata.request_id(),
+                    CanisterHttpResponseWithConsensus {
+                        content: content.clone(),
+                        proof: shares
+                            .values()
+                            .take(threshold)
+                            .map(|share| (*share).clone())
+                            .collect(),
+                    },
+                );
+            }
+        }
+
+        let mut responses = Vec::new();
+        let mut size = 0;
+        for (_, response) in candidates {
+            let response_size = response.count_bytes();
+            if size + response_size > limits.max_canister_http_bytes {
+                continue;
+            }
+            size += response_size;
+            responses.push(response);
+        }
+        payload.canister_http = responses;
+        Ok(())
+    }
+
+    fn validate_section(
+        &self,
+        payload: &BatchPayload,
+        past_payloads: &[(Height, Time, Payload)],
+        context: &ValidationContext,
+    ) -> ValidationResult<PayloadValidationError> {
+        if payload.canister_http.is_empty() {
+            return Ok(());
+        }
+        let size = payload
+            .canister_http
+            .iter()
+            .map(|response| response.count_bytes())
+            .sum::<usize>();
+        let max = MAX_CANISTER_HTTP_PAYLOAD_IN_BYTES.get() as usize;
+        if size > max {
+            return Err(ValidationError::Permanent(
+                CanisterHttpPermanentValidationError::PayloadTooLarge { size, max },
+            )
+            .into());
+        }
+        let state = self
+            .state_manager
+            .get_state_at(context.certified_height)
+            .map_err(|err| {
+                ValidationError::Transient(CanisterHttpTransientValidationError::StateUnavailable(
+                    err,
+                ))
+            })?
+            .take();
+        let mut included = past_response_ids(past_payloads);
+        for response in payload.canister_http.iter() {
+            if !included.insert(response.content.request_id()) {
+                return Err(ValidationError::Permanent(
+                    CanisterHttpPermanentValidationError::DuplicateResponse(
+                        response.content.request_id(),
+                    ),
+                )
+                .into());
+            }
+            self.validate_response(response, &state, context)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns true if the canister of the request has the callback of the
+/// request in the given state, i.e. still awaits a response to it.
+fn is_pending(state: &ReplicatedState, (canister_id, callback_id): CanisterHttpRequestId) -> bool {
+    state
+        .canister_state(&canister_id)
+        .and_then(|canister| canister.system_state.call_context_manager())
+        .map_or(false, |manager| {
+            manager.callbacks().contains_key(&callback_id)
+        })
+}
+
+/// Return the requests of the canister HTTP responses of past_payloads.
+fn past_response_ids(past_payloads: &[(Height, Time, Payload)]) -> HashSet<CanisterHttpRequestId> {
+    past_payloads
+        .iter()
+        .filter(|(_, _, payload)| !payload.is_summary())
+        .flat_map(|(_, _, payload)| {
+            payload
+                .as_ref()
+                .as_batch_payload()
+                .canister_http
+                .iter()
+                .map(|response| response.content.request_id())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::canister_http::tests::{make_response, make_share};
+    use crate::consensus::mocks::{
+        dependencies_with_subnet_records_with_raw_state_manager, Dependencies,
+    };
+    use ic_artifact_pool::canister_http_pool::CanisterHttpPoolImpl;
+    use ic_interfaces::{
+        artifact_pool::UnvalidatedArtifact,
+        canister_http::{CanisterHttpChangeAction, MutableCanisterHttpPool},
+        consensus::PayloadPermanentError,
+        state_manager::Labeled,
+    };
+    use ic_logger::replica_logger::no_op_logger;
+    use ic_metrics::MetricsRegistry;
+    use ic_test_artifact_pool::ingress_pool::TestIngressPool;
+    use ic_test_utilities::{
+        mock_time,
+        registry::SubnetRecordBuilder,
+        state::{get_initial_state, CanisterStateBuilder},
+        types::ids::{canister_test_id, node_test_id, subnet_test_id},
+    };
+    use ic_types::{
+        consensus::{canister_http::CanisterHttpMessage, dkg::Dealings},
+        messages::{CallContextId, CallbackId},
+        methods::{Callback, WasmClosure},
+        Cycles, RegistryVersion,
+    };
+    use std::time::Duration;
+
+    /// Builds the section of a subnet of 4 nodes, whose certified state has a
+    /// canister with the callbacks 1 to 3.
+    fn setup(
+        pool_config: ic_config::artifact_pool::ArtifactPoolConfig,
+        canister_http_pool: CanisterHttpPoolImpl,
+    ) -> CanisterHttpSectionBuilder {
+        let committee = (0..4).map(node_test_id).collect::<Vec<_>>();
+        let Dependencies {
+            crypto,
+            registry,
+            state_manager,
+            ..
+        } = dependencies_with_subnet_records_with_raw_state_manager(
+            pool_config,
+            subnet_test_id(0),
+            vec![(1, SubnetRecordBuilder::from(&committee).build())],
+        );
+        let mut canister = CanisterStateBuilder::new()
+            .with_canister_id(canister_test_id(0))
+            .build();
+        let call_context_manager = canister.system_state.call_context_manager_mut().unwrap();
+        for _ in 0..3 {
+            call_context_manager.register_callback(Callback::new(
+                CallContextId::from(0),
+                Cycles::zero(),
+                WasmClosure::new(0, 0),
+                WasmClosure::new(0, 0),
+                None,
+            ));
+        }
+        let mut state = get_initial_state(0, 0);
+        state.put_canister_state(canister);
+        state_manager
+            .get_mut()
+            .expect_get_state_at()
+            .return_const(Ok(Labeled::new(Height::new(0), Arc::new(state))));
+        CanisterHttpSectionBuilder::new(
+            Arc::new(RwLock::new(canister_http_pool)),
+            crypto,
+            registry,
+            state_manager as Arc<_>,
+            subnet_test_id(0),
+            no_op_logger(),
+        )
+    }
+
+    fn context() -> ValidationContext {
+        ValidationContext {
+            certified_height: Height::from(0),
+            registry_version: RegistryVersion::from(1),
+            time: mock_time(),
+        }
+    }
+
+    fn build(
+        section: &CanisterHttpSectionBuilder,
+        pool_config: ic_config::artifact_pool::ArtifactPoolConfig,
+        past_payloads: &[(Height, Time, Payload)],
+        limits: &PayloadSizeLimits,
+    ) -> BatchPayload {
+        let mut payload = BatchPayload::default();
+        section
+            .build_section(
+                &mut payload,
+                Height::from(1),
+                &TestIngressPool::new(pool_config),
+                past_payloads,
+                &context(),
+                limits,
+            )
+            .unwrap();
+        payload
+    }
+
+    #[test]
+    fn test_canister_http_section() {
+        ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
+            let timeout = mock_time() + Duration::from_secs(60);
+            let response = make_response(1, timeout);
+            let other = make_response(2, timeout);
+            let mut canister_http_pool = CanisterHttpPoolImpl::new(MetricsRegistry::new());
+            // Two shares reach the threshold of a subnet of 4 nodes, which
+            // tolerates a single fault.
+            canister_http_pool.apply_changes(vec![
+                CanisterHttpChangeAction::AddToValidated(make_share(&response, 0), response),
+                CanisterHttpChangeAction::AddToValidated(make_share(&other, 0), other),
+            ]);
+            let share = make_share(&response, 1);
+            canister_http_pool.insert(UnvalidatedArtifact {
+                message: CanisterHttpMessage::Share(share.clone()),
+                peer_id: node_test_id(1),
+                timestamp: mock_time(),
+            });
+            canister_http_pool.apply_changes(vec![CanisterHttpChangeAction::MoveToValidated(
+                CanisterHttpMessage::Share(share),
+            )]);
+
+            let section = setup(pool_config.clone(), canister_http_pool);
+            let context = context();
+            let payload = build(&section, pool_config, &[], &PayloadSizeLimits::default());
+            assert_eq!(payload.canister_http.len(), 1);
+            assert_eq!(
+                payload.canister_http[0].content.request_id(),
+                (canister_test_id(0), CallbackId::from(1))
+            );
+            assert!(section.validate_section(&payload, &[], &context).is_ok());
+
+            // A response without enough shares is invalid.
+            let mut invalid = payload.clone();
+            invalid.canister_http[0].proof.pop();
+            assert!(section.validate_section(&invalid, &[], &context).is_err());
+
+            // A response is invalid after its request timed out.
+            let late_context = ValidationContext {
+                time: mock_time() + Duration::from_secs(61),
+                ..context.clone()
+            };
+            assert!(section
+                .validate_section(&payload, &[], &late_context)
+                .is_err());
+        })
+    }
+
+    #[test]
+    fn test_canister_http_section_includes_responses_once() {
+        ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
+            let timeout = mock_time() + Duration::from_secs(60);
+            let response = make_response(1, timeout);
+            // The canister has no callback 4, e.g. because a response to it
+            // was already executed.
+            let executed = make_response(4, timeout);
+            let mut canister_http_pool = CanisterHttpPoolImpl::new(MetricsRegistry::new());
+            for response in vec![response, executed.clone()] {
+                let share = CanisterHttpMessage::Share(make_share(&response, 1));
+                canister_http_pool.insert(UnvalidatedArtifact {
+                    message: share.clone(),
+                    peer_id: node_test_id(1),
+                    timestamp: mock_time(),
+                });
+                canister_http_pool.apply_changes(vec![
+                    CanisterHttpChangeAction::AddToValidated(make_share(&response, 0), response),
+                    CanisterHttpChangeAction::MoveToValidated(share),
+                ]);
+            }
+
+            let section = setup(pool_config.clone(), canister_http_pool);
+            let payload = build(
+                &section,
+                pool_config.clone(),
+                &[],
+                &PayloadSizeLimits::default(),
+            );
+            assert_eq!(payload.canister_http.len(), 1);
+            assert_eq!(payload.canister_http[0].content.id, CallbackId::from(1));
+
+            // A response of a past payload above the certified height is not
+            // included again, and including it again is invalid.
+            let past_payloads = vec![(
+                Height::from(1),
+                mock_time(),
+                Payload::new(
+                    ic_crypto::crypto_hash,
+                    (payload.clone(), Dealings::new_empty(Height::from(0))).into(),
+                ),
+            )];
+            let next = build(
+                &section,
+                pool_config.clone(),
+                &past_payloads,
+                &PayloadSizeLimits::default(),
+            );
+            assert!(next.canister_http.is_empty());
+            assert!(section
+                .validate_section(&payload, &past_payloads, &context())
+                .is_err());
+
+            // A response to a callback that is not in the certified state is
+            // invalid.
+            let mut unknown = payload.clone();
+            unknown.canister_http[0].content = executed.clone();
+            unknown.canister_http[0].proof =
+                vec![make_share(&executed, 0), make_share(&executed, 1)];
+            assert!(matches!(
+                section.validate_section(&unknown, &[], &context()),
+                Err(ValidationError::Permanent(
+                    PayloadPermanentError::CanisterHttpPayloadValidationError(
+                        CanisterHttpPermanentValidationError::UnknownRequest(_)
+                    )
+                ))
+            ));
+
+            // No responses are included beyond the size limit.
+            let limits = PayloadSizeLimits {
+                max_canister_http_bytes: payload.canister_http[0].count_bytes() - 1,
+                ..PayloadSizeLimits::default()
+            };
+            assert!(build(&section, pool_config, &[], &limits)
+                .canister_http
+                .is_empty());
+        })
+    }
+}
@@ -1,7 +1,7 @@
 //! This module defines the certification component, which is responsible for
 //! reaching consensus on parts of the replicated state produced by the upper
 //! layers by signing state hashes.
-use crate::consensus::crypto::{Aggregate, SignVerify};
+use crate::consensus::crypto::{Aggregate, BatchVerify, SignVerify};
 use ic_interfaces::crypto::{Crypto, ThresholdSigner};
 use ic_types::{
     consensus::{certification::CertificationContent, ThresholdSignature, ThresholdSignatureShare},
@@ -24,6 +24,7 @@ pub trait CertificationCrypto:
         ThresholdSignature<CertificationContent>,
     > + ThresholdSigner<CertificationContent>
     + SignVerify<CertificationContent, ThresholdSignatureShare<CertificationContent>, NiDkgId>
+    + BatchVerify<CertificationContent, ThresholdSignatureShare<CertificationContent>, NiDkgId>
     + Crypto
     + Send
     + Sync
@@ -1,6 +1,13 @@
 use super::verifier::VerifierImpl;
 use super::CertificationCrypto;
-use crate::consensus::{membership::Membership, utils};
+use crate::consensus::{
+    async_crypto::{
+        AsyncBatchVerify, AsyncCrypto, AsyncSignVerify, CryptoThreadPool, PendingSignatures,
+        MAX_SIGNING_WAIT,
+    },
+    membership::Membership,
+    utils,
+};
 use ic_interfaces::{
     certification::{
         CertificationPool, Certifier, CertifierGossip, ChangeAction, ChangeSet, Verifier,
@@ -19,8 +26,11 @@ use ic_types::{
         CertificationMessageAttribute, CertificationMessageFilter, CertificationMessageId,
         Priority, PriorityFn,
     },
-    consensus::certification::{
-        Certification, CertificationContent, CertificationMessage, CertificationShare,
+    consensus::{
+        certification::{
+            Certification, CertificationContent, CertificationMessage, CertificationShare,
+        },
+        ThresholdSignatureShare,
     },
     crypto::Signed,
     replica_config::ReplicaConfig,
@@ -28,6 +38,7 @@ use ic_types::{
 };
 use prometheus::{Histogram, IntCounter, IntGauge};
 use std::cell::RefCell;
+use std::collections::HashSet;
 use std::sync::{Arc, RwLock};
 use std::time::Instant;
 
@@ -37,10 +48,15 @@ pub struct CertifierImpl {
     replica_config: ReplicaConfig,
     membership: Arc<Membership>,
     crypto: Arc<dyn CertificationCrypto>,
+    async_crypto: AsyncCrypto<dyn CertificationCrypto>,
     state_manager: Arc<dyn StateManager<State = ReplicatedState>>,
     metrics: CertifierMetrics,
     /// The highest height that has been purged. Used to avoid redudant purging.
     highest_purged_height: RefCell<Height>,
+    pending_signatures: PendingSignatures<
+        (Height, CertificationContent),
+        ThresholdSignatureShare<CertificationContent>,
+    >,
     log: ReplicaLogger,
 }
 
@@ -53,6 +69,8 @@ pub struct CertifierGossipImpl {
 struct CertifierMetrics {
     shares_created: IntCounter,
     certifications_aggregated: IntCounter,
+    batch_verification_failures: IntCounter,
+    state_divergences: IntCounter,
     last_certified_height: IntGauge,
     execution_time: Histogram,
 }
@@ -115,6 +133,7 @@ pub fn setup(
     replica_config: ReplicaConfig,
     membership: Arc<Membership>,
     crypto: Arc<dyn CertificationCrypto>,
+    crypto_pool: CryptoThreadPool,
     state_manager: Arc<dyn StateManager<State = ReplicatedState>>,
     metrics_registry: MetricsRegistry,
     log: ReplicaLogger,
@@ -124,6 +143,7 @@ pub fn setup(
             replica_config,
             membership,
             crypto,
+            crypto_pool,
             state_manager.clone(),
             metrics_registry,
             log,
@@ -174,13 +194,14 @@ impl Certifier for CertifierImpl {
             start.elapsed()
         );
 
-        // Next we try to execute 4 steps: signing, purging, aggregating and validating
-        // sequentially and stop whenever any of these steps produces a non empty
-        // set of changes, because it might affect the next step and so has to be
-        // applied to the certification pool first.
+        // Next we try to execute 3 steps: signing and aggregating, purging and
+        // validating sequentially and stop whenever any of these steps produces a
+        // non empty set of changes, because it might affect the next step and so
+        // has to be applied to the certification pool first.
 
-        // Filter out only those heights, where the current node belongs to the
-        // committee.
+        // Sign all the heights, where the current node belongs to the committee,
+        // and aggregate the new shares together with the validated ones, so
+        // that all the uncertified heights are handled in a single invocation.
         let start = Instant::now();
         let shares = self.sign(
             consensus_cache,
@@ -195,30 +216,14 @@ impl Certifier for CertifierImpl {
                 shares.len(),
                 start.elapsed()
             );
-            return shares
-                .into_iter()
-                .map(ChangeAction::AddToValidated)
-                .collect();
         }
-
         let start = Instant::now();
-        if let Some(purge_height) = self.get_purge_height(consensus_cache) {
-            trace!(
-                &self.log,
-                "Determined a new purge height {:?} in {:?}",
-                purge_height,
-                start.elapsed()
-            );
-            return vec![ChangeAction::RemoveAllBelow(purge_height)];
-        }
-
-        let start = Instant::now();
-
-        let certifications = state_hashes_to_certify
-            .iter()
-            .flat_map(|(height, _)| self.aggregate(consensus_cache, certification_pool, *height))
-            .collect::<Vec<_>>();
-
+        let (shares, certifications) = self.aggregate(
+            consensus_cache,
+            certification_pool,
+            &state_hashes_to_certify,
+            shares,
+        );
         if !certifications.is_empty() {
             self.metrics
                 .certifications_aggregated
@@ -229,12 +234,27 @@ impl Certifier for CertifierImpl {
                 certifications.len(),
                 start.elapsed()
             );
-            return certifications
+            self.deliver(&certifications);
+        }
+        if !shares.is_empty() || !certifications.is_empty() {
+            return shares
                 .into_iter()
+                .chain(certifications)
                 .map(ChangeAction::AddToValidated)
                 .collect();
         }
 
+        let start = Instant::now();
+        if let Some(purge_height) = self.get_purge_height(consensus_cache) {
+            trace!(
+                &self.log,
+                "Determined a new purge height {:?} in {:?}",
+                purge_height,
+                start.elapsed()
+            );
+            return vec![ChangeAction::RemoveAllBelow(purge_height)];
+        }
+
         let start = Instant::now();
         let change_set = self.validate(
             consensus_cache,
@@ -266,6 +286,7 @@ impl CertifierImpl {
         replica_config: ReplicaConfig,
         membership: Arc<Membership>,
         crypto: Arc<dyn CertificationCrypto>,
+        crypto_pool: CryptoThreadPool,
         state_manager: Arc<dyn StateManager<State = ReplicatedState>>,
         metrics_registry: MetricsRegistry,
         log: ReplicaLogger,
@@ -273,6 +294,7 @@ impl CertifierImpl {
         Self {
             replica_config,
             membership,
+            async_crypto: AsyncCrypto::new(crypto.clone(), crypto_pool),
             crypto,
             state_manager,
             metrics: CertifierMetrics {
@@ -284,6 +306,14 @@ impl CertifierImpl {
                     "certification_certifications_aggregated",
                     "Amount of full certifications created.",
                 ),
+                batch_verification_failures: metrics_registry.int_counter(
+                    "certification_batch_verification_failures",
+                    "Amount of batches of certification shares that failed verification.",
+                ),
+                state_divergences: metrics_registry.int_counter(
+                    "certification_state_divergences",
+                    "Amount of valid certifications of a state hash different from the local one.",
+                ),
                 execution_time: metrics_registry.histogram(
                     "certification_execution_time",
                     "Certifier execution time in seconds.",
@@ -296,17 +326,25 @@ impl CertifierImpl {
             },
             log,
             highest_purged_height: RefCell::new(Height::from(1)),
+            pending_signatures: PendingSignatures::new(),
         }
     }
 
-    // Gets height/hash pairs and creates certification shares for them.
+    // Gets height/hash pairs and creates certification shares for them. The
+    // signatures are created on the crypto thread pool; those that do not
+    // complete in time are returned by a later invocation.
     fn sign(
         &self,
         consensus_cache: &dyn ConsensusPoolCache,
         certification_pool: &dyn CertificationPool,
         state_hashes: &[(Height, CryptoHashOfPartialState)],
     ) -> Vec<CertificationMessage> {
-        state_hashes
+        let deadline = Instant::now() + MAX_SIGNING_WAIT;
+        // The heights that are no longer to be certified do not need a share.
+        self.pending_signatures
+            .retain(|(height, _)| state_hashes.iter().any(|(h, _)| h == height));
+
+        let heights_to_sign: Vec<_> = state_hashes
             .iter()
             .cloned()
             // Filter out all heights, where the current replica does not belong to the committee
@@ -333,14 +371,35 @@ impl CertifierImpl {
                     .shares_at_height(*height)
                     .all(|share| share.signed.signature.signer != self.replica_config.node_id)
             })
-            .filter_map(|(height, hash)| {
-                let content = CertificationContent::new(hash);
-                let dkg_id =
-                    utils::active_high_threshold_transcript(consensus_cache, height)?.dkg_id;
-                match self
-                    .crypto
-                    .sign(&content, self.replica_config.node_id, dkg_id)
-                {
+            .collect();
+        // All signatures are started before waiting for any of them, so that
+        // they are created in parallel.
+        let to_sign: Vec<_> = heights_to_sign
+            .into_iter()
+            .map(|(height, hash)| (height, CertificationContent::new(hash)))
+            .collect();
+        for (height, content) in to_sign.iter() {
+            let dkg_id = match utils::active_high_threshold_transcript(consensus_cache, *height) {
+                Some(transcript) => transcript.dkg_id,
+                None => continue,
+            };
+            self.pending_signatures
+                .start(&(*height, content.clone()), || {
+                    self.async_crypto.sign_async(
+                        content.clone(),
+                        self.replica_config.node_id,
+                        dkg_id,
+                    )
+                });
+        }
+
+        to_sign
+            .into_iter()
+            .filter_map(|(height, content)| {
+                let signature = self
+                    .pending_signatures
+                    .take_before(&(height, content.clone()), deadline)?;
+                match signature {
                     Ok(signature) => Some(CertificationShare {
                         height,
                         signed: Signed { content, signature },
@@ -355,13 +414,71 @@ impl CertifierImpl {
             .collect()
     }
 
-    // Gets all shares from the certification pool at a given height and
-    // aggregates into full certification artifacts if possible.
+    // Aggregates the validated shares from the certification pool together
+    // with the given shares just created by this replica, at all the given
+    // heights. Returns the own shares at the heights that could not be
+    // certified, and the aggregated certifications, which supersede the own
+    // shares at their heights.
     fn aggregate(
         &self,
         consensus_cache: &dyn ConsensusPoolCache,
         certification_pool: &dyn CertificationPool,
-        height: Height,
+        state_hashes: &[(Height, CryptoHashOfPartialState)],
+        own_shares: Vec<CertificationMessage>,
+    ) -> (Vec<CertificationMessage>, Vec<CertificationMessage>) {
+        let certifications: Vec<_> = state_hashes
+            .iter()
+            .flat_map(|(height, _)| {
+                let own_shares_at_height =
+                    own_shares.iter().filter_map(move |message| match message {
+                        CertificationMessage::CertificationShare(share)
+                            if share.height == *height =>
+                        {
+                            Some(share.clone())
+                        }
+                        _ => None,
+                    });
+                self.aggregate_shares(
+                    consensus_cache,
+                    certification_pool
+                        .shares_at_height(*height)
+                        .chain(own_shares_at_height),
+                )
+            })
+            .collect();
+        let certified_heights: HashSet<Height> =
+            certifications.iter().map(|cert| cert.height()).collect();
+        let own_shares = own_shares
+            .into_iter()
+            .filter(|share| !certified_heights.contains(&share.height()))
+            .collect();
+        (own_shares, certifications)
+    }
+
+    // Delivers the given certifications to the state manager right away, so
+    // that it does not have to wait until they are in the certification pool.
+    fn deliver(&self, certifications: &[CertificationMessage]) {
+        for message in certifications {
+            if let CertificationMessage::Certification(certification) = message {
+                self.state_manager
+                    .deliver_state_certification(certification.clone());
+                self.metrics
+                    .last_certified_height
+                    .set(certification.height.get() as i64);
+                debug!(
+                    &self.log,
+                    "Delivered certification for height {}", certification.height
+                );
+            }
+        }
+    }
+
+    // Aggregates the given shares into full certification artifacts if
+    // possible.
+    fn aggregate_shares(
+        &self,
+        consensus_cache: &dyn ConsensusPoolCache,
+        shares: impl Iterator<Item = CertificationShare>,
     ) -> Vec<CertificationMessage> {
         // A struct defined to morph `Certification` into a format that can be
         // accepted by `utils::aggregate`.
@@ -380,7 +497,7 @@ impl CertifierImpl {
             }
         }
 
-        let shares = certification_pool.shares_at_height(height).map(|s| Signed {
+        let shares = shares.map(|s| Signed {
             content: CertificationTuple(s.height, s.signed.content),
             signature: s.signed.signature,
         });
@@ -445,12 +562,14 @@ impl CertifierImpl {
                 }
 
                 Box::new(
-                    certification_pool
-                        .unvalidated_shares_at_height(*height)
-                        .filter_map(move |share| {
-                            self.validate_share(consensus_cache, certification_pool, hash, share)
-                        })
-                        .chain(cert_change_set.into_iter()),
+                    self.validate_shares(
+                        consensus_cache,
+                        certification_pool,
+                        hash,
+                        certification_pool.unvalidated_shares_at_height(*height),
+                    )
+                    .into_iter()
+                    .chain(cert_change_set.into_iter()),
                 )
             })
             .collect()
@@ -479,9 +598,21 @@ impl CertifierImpl {
         let registry_version =
             utils::registry_version_at_height(consensus_cache, certification.height)?;
 
+        // Verify the certification signature.
+        let verification = verifier.validate(
+            self.replica_config.subnet_id,
+            certification,
+            registry_version,
+        );
+
         // check if the certification contains the same state hash as our local one. If
-        // not, we consider the certification invalid.
+        // not, we consider the certification invalid. If the certification is
+        // nevertheless signed by the subnet, the local state diverged from the
+        // state the subnet agreed on, and it cannot be certified anymore.
         if hash != &certification.signed.content.hash {
+            if verification.is_ok() {
+                self.report_state_divergence(hash, certification);
+            }
             return Some(ChangeAction::HandleInvalid(
                 msg,
                 format!(
@@ -491,12 +622,7 @@ impl CertifierImpl {
             ));
         }
 
-        // Verify the certification signature.
-        match verifier.validate(
-            self.replica_config.subnet_id,
-            certification,
-            registry_version,
-        ) {
+        match verification {
             Ok(()) => Some(ChangeAction::MoveToValidated(msg)),
             Err(ValidationError::Permanent(err)) => {
                 Some(ChangeAction::HandleInvalid(msg, format!("{:?}", err)))
@@ -511,25 +637,126 @@ impl CertifierImpl {
         }
     }
 
-    fn validate_share(
+    // Reports a valid certification of a state hash that differs from the
+    // local one to the state manager, which removes the diverged checkpoints
+    // and stops the replica, instead of letting it stall at that height.
+    fn report_state_divergence(
+        &self,
+        hash: &CryptoHashOfPartialState,
+        certification: &Certification,
+    ) {
+        self.metrics.state_divergences.inc();
+        error!(
+            self.log,
+            "The state at height {} diverged (local hash: {:?}, certified hash: {:?})",
+            certification.height,
+            hash,
+            certification.signed.content.hash
+        );
+        self.state_manager
+            .report_diverged_state(certification.height);
+    }
+
+    // Validates the unvalidated shares at one height. The signatures of the
+    // shares that pass all other checks are verified in a batch, and only
+    // verified individually if the batch verification fails.
+    fn validate_shares<'a>(
         &self,
         consensus_cache: &dyn ConsensusPoolCache,
         certification_pool: &dyn CertificationPool,
         hash: &CryptoHashOfPartialState,
+        shares: impl Iterator<Item = &'a CertificationShare>,
+    ) -> ChangeSet {
+        let mut change_set = Vec::new();
+        let mut shares_to_verify = Vec::new();
+        for share in shares {
+            match self.check_share(certification_pool, hash, share) {
+                Ok(()) => shares_to_verify.push(share),
+                Err(action) => change_set.extend(action),
+            }
+        }
+        let (height, content) = match shares_to_verify.first() {
+            Some(share) => (share.height, share.signed.content.clone()),
+            None => return change_set,
+        };
+        let dkg_id = match utils::active_high_threshold_transcript(consensus_cache, height) {
+            Some(transcript) => transcript.dkg_id,
+            None => return change_set,
+        };
+
+        // All the shares have the same content, as their state hash is checked.
+        let signatures: Vec<_> = shares_to_verify
+            .iter()
+            .map(|share| share.signed.signature.clone())
+            .collect();
+        let batch_size = signatures.len();
+        let batch_verified = batch_size > 1
+            && self
+                .async_crypto
+                .verify_batch_async(content, signatures, dkg_id)
+                .wait()
+                .is_ok();
+        if batch_size > 1 && !batch_verified {
+            self.metrics.batch_verification_failures.inc();
+            debug!(
+                self.log,
+                "Batch verification of {} shares failed at height {:?}, verifying them individually",
+                batch_size,
+                height
+            );
+        }
+
+        // The individual verifications run in parallel on the crypto thread
+        // pool.
+        let verifications: Vec<_> = shares_to_verify
+            .iter()
+            .map(|share| {
+                if batch_verified {
+                    None
+                } else {
+                    Some(self.async_crypto.verify_async(share.signed.clone(), dkg_id))
+                }
+            })
+            .collect();
+        for (share, verification) in shares_to_verify.into_iter().zip(verifications) {
+            let msg = CertificationMessage::CertificationShare(share.clone());
+            let verification = match verification {
+                Some(verification) => verification.wait(),
+                None => Ok(()),
+            };
+            match verification.map_err(VerifierError::from) {
+                Ok(()) => change_set.push(ChangeAction::MoveToValidated(msg)),
+                Err(ValidationError::Permanent(err)) => {
+                    change_set.push(ChangeAction::HandleInvalid(msg, format!("{:?}", err)))
+                }
+                Err(ValidationError::Transient(err)) => {
+                    debug!(self.log, "Couldn't verify share signature: {:?}", err);
+                }
+            }
+        }
+        change_set
+    }
+
+    // Checks everything but the signature of the given share. Returns the
+    // action to take if the share must not be validated.
+    fn check_share(
+        &self,
+        certification_pool: &dyn CertificationPool,
+        hash: &CryptoHashOfPartialState,
         share: &CertificationShare,
-    ) -> Option<ChangeAction> {
-        let msg = CertificationMessage::CertificationShare(share.clone());
+    ) -> Result<(), Option<ChangeAction>> {
+        let msg = || CertificationMessage::CertificationShare(share.clone());
         let content = &share.signed.content;
         // If the share has an invalid content or does not belong to the
         // committee
         if !hash.eq(&content.hash) {
-            return Some(ChangeAction::HandleInvalid(
-                msg,
+            return Err(Some(ChangeAction::HandleInvalid(
+                msg(),
                 format!(
                     "Unexpected state hash (expected: {:?}, received: {:?})",
                     hash, content.hash
                 ),
-            ));
+            )));
         }
         let signer = share.signed.signature.signer;
         match self.membership.node_belongs_to_threshold_committee(
@@ -543,14 +770,14 @@ impl CertifierImpl {
                     self.log,
                     "Couldn't check committee membership during share validation: {:?}", err
                 );
-                None
+                Err(None)
             }
             // If the signer does not belong to the signers committee at the
             // given height, reject this artifact.
-            Ok(false) => Some(ChangeAction::HandleInvalid(
-                msg,
+            Ok(false) => Err(Some(ChangeAction::HandleInvalid(
+                msg(),
                 "Signer does not belong to the committee".to_string(),
-            )),
+            ))),
             // The signer is valid.
             Ok(true) => {
                 // If the signer has signed a share before, invalidate the new one.
@@ -558,29 +785,9 @@ impl CertifierImpl {
                     .shares_at_height(share.height)
                     .any(|valid_share| signer == valid_share.signed.signature.signer)
                 {
-                    return Some(ChangeAction::RemoveFromUnvalidated(msg));
+                    return Err(Some(ChangeAction::RemoveFromUnvalidated(msg())));
                 }
-                // Verify the signature.
-                Some(
-                    match self
-                        .crypto
-                        .verify(
-                            &share.signed,
-                            utils::active_high_threshold_transcript(consensus_cache, share.height)?
-                                .dkg_id,
-                        )
-                        .map_err(VerifierError::from)
-                    {
-                        Ok(()) => ChangeAction::MoveToValidated(msg),
-                        Err(ValidationError::Permanent(err)) => {
-                            ChangeAction::HandleInvalid(msg, format!("{:?}", err))
-                        }
-                        Err(ValidationError::Transient(err)) => {
-                            debug!(self.log, "Couldn't verify share signature: {:?}", err);
-                            return None;
-                        }
-                    },
-                )
+                Ok(())
             }
         }
     }
@@ -763,6 +970,7 @@ mod tests {
                     replica_config,
                     membership,
                     crypto,
+                    CryptoThreadPool::new(1),
                     state_manager.clone(),
                     metrics_registry,
                     log,
@@ -881,6 +1089,7 @@ mod tests {
                     replica_config,
                     membership,
                     crypto,
+                    CryptoThreadPool::new(1),
                     state_manager.clone(),
                     metrics_registry,
                     log,
@@ -901,14 +1110,13 @@ mod tests {
                 cert_pool.apply_changes(change_set);
 
                 // emulates a call from inside on_state_change
-                let mut messages = vec![];
-                for i in 1..6 {
-                    messages.append(&mut certifier.aggregate(
-                        pool.as_cache(),
-                        &cert_pool,
-                        Height::from(i),
-                    ));
-                }
+                let (own_shares, messages) = certifier.aggregate(
+                    pool.as_cache(),
+                    &cert_pool,
+                    &state_manager.list_state_hashes_to_certify(),
+                    Vec::new(),
+                );
+                assert!(own_shares.is_empty());
 
                 assert_eq!(
                     messages.len(),
@@ -963,6 +1171,7 @@ mod tests {
                     replica_config,
                     membership,
                     crypto,
+                    CryptoThreadPool::new(1),
                     state_manager.clone(),
                     metrics_registry,
                     log,
@@ -1033,6 +1242,7 @@ mod tests {
                     replica_config,
                     membership,
                     crypto,
+                    CryptoThreadPool::new(1),
                     state_manager,
                     metrics_registry,
                     log,
@@ -1074,6 +1284,66 @@ mod tests {
         })
     }
 
+    // Tests that a single invocation signs and aggregates all the uncertified
+    // heights, and delivers the certifications to the state manager right away.
+    #[test]
+    fn test_certification_sign_and_aggregate_all_heights() {
+        ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
+            let Dependencies {
+                mut pool,
+                replica_config,
+                membership,
+                crypto,
+                state_manager,
+                ..
+            } = dependencies(pool_config.clone(), 1);
+            pool.advance_round_normal_operation_n(10);
+            add_expectations(state_manager.clone(), 3, 5);
+            state_manager
+                .get_mut()
+                .expect_deliver_state_certification()
+                .times(3)
+                .return_const(());
+            let metrics_registry = MetricsRegistry::new();
+            let cert_pool = CertificationPoolImpl::new(
+                pool_config,
+                ic_logger::replica_logger::no_op_logger(),
+                metrics_registry.clone(),
+            );
+
+            with_test_replica_logger(|log| {
+                let certifier = CertifierImpl::new(
+                    replica_config,
+                    membership,
+                    crypto,
+                    CryptoThreadPool::new(1),
+                    state_manager,
+                    metrics_registry,
+                    log,
+                );
+                let change_set =
+                    certifier.on_state_change(pool.as_cache(), Arc::new(RwLock::new(cert_pool)));
+                let mut heights: Vec<_> = change_set
+                    .iter()
+                    .map(|action| match action {
+                        ChangeAction::AddToValidated(CertificationMessage::Certification(cert)) => {
+                            cert.height
+                        }
+                        _ => panic!("Expecting only full certifications"),
+                    })
+                    .collect();
+                heights.sort();
+                assert_eq!(
+                    heights,
+                    vec![3, 4, 5]
+                        .into_iter()
+                        .map(Height::from)
+                        .collect::<Vec<_>>()
+                );
+            })
+        })
+    }
+
     // We test that the validator actually stops after the first discovered
     // certification, even if multiple are available.
     #[test]
@@ -1096,6 +1366,7 @@ mod tests {
                     replica_config,
                     membership,
                     crypto,
+                    CryptoThreadPool::new(1),
                     state_manager.clone(),
                     metrics_registry.clone(),
                     log,
@@ -1155,11 +1426,19 @@ mod tests {
                     state_manager,
                     ..
                 } = dependencies(pool_config.clone(), 1);
+                // The certification is validly signed, so the local state
+                // diverged.
+                state_manager
+                    .get_mut()
+                    .expect_report_diverged_state()
+                    .times(1)
+                    .return_const(());
 
                 let certifier = CertifierImpl::new(
                     replica_config,
                     membership,
                     crypto,
+                    CryptoThreadPool::new(1),
                     state_manager,
                     MetricsRegistry::new(),
                     log,
@@ -1217,6 +1496,7 @@ mod tests {
                     replica_config,
                     membership,
                     crypto,
+                    CryptoThreadPool::new(1),
                     state_manager.clone(),
                     metrics_registry,
                     log,
@@ -1,26 +1,34 @@
 //! This module encapsulates all components required for establishing of a
 //! distributed consensus.
 
+pub(crate) mod async_crypto;
 mod block_maker;
 mod catchup_package_maker;
 pub(crate) mod crypto;
 mod dkg_key_manager;
+pub mod equivocation;
 mod finalizer;
 mod malicious_consensus;
 pub(crate) mod membership;
 mod metrics;
 mod notary;
 pub mod payload_builder;
+mod payload_size_controller;
 pub mod pool_reader;
 mod prelude;
 mod priority;
 mod purger;
 mod random_beacon_maker;
+mod random_beacon_precomputer;
 mod random_tape_maker;
+pub(crate) mod ranking;
+pub mod remote_dkg;
+pub mod round_tracer;
 mod share_aggregator;
 pub mod utils;
 mod validator;
 
+pub use async_crypto::{AsyncCrypto, CryptoFuture, CryptoThreadPool};
 pub use crypto::ConsensusCrypto;
 pub use finalizer::generate_responses_to_subnet_calls;
 pub use membership::Membership;
@@ -35,25 +43,28 @@ use crate::consensus::{
     finalizer::Finalizer,
     metrics::{ConsensusGossipMetrics, ConsensusMetrics, ValidatorMetrics},
     notary::Notary,
-    payload_builder::PayloadBuilderImpl,
+    payload_builder::{PayloadBuilderImpl, PayloadSectionBuilder},
     pool_reader::PoolReader,
     prelude::*,
     priority::get_priority_function,
     purger::Purger,
     random_beacon_maker::RandomBeaconMaker,
     random_tape_maker::RandomTapeMaker,
+    round_tracer::{RoundTimelines, RoundTracer},
     share_aggregator::ShareAggregator,
     utils::{get_notarization_delay_settings, is_root_subnet, RoundRobin},
     validator::Validator,
 };
 use ic_config::consensus::ConsensusConfig;
+use ic_event_log::{Event, EventLog};
 use ic_interfaces::{
     consensus::{Consensus, ConsensusGossip},
     consensus_pool::ConsensusPool,
     dkg::DkgPool,
+    equivocation::EquivocationPool,
     ingress_manager::IngressSelector,
     ingress_pool::IngressPoolSelect,
-    messaging::{MessageRouting, XNetPayloadBuilder},
+    messaging::MessageRouting,
     registry::{self, LocalStoreCertifiedTimeReader, RegistryClient},
     state_manager::StateManager,
     time_source::TimeSource,
@@ -64,7 +75,7 @@ use ic_registry_client::helper::subnet::SubnetRegistry;
 use ic_replicated_state::ReplicatedState;
 use ic_types::{
     artifact::{ConsensusMessageFilter, ConsensusMessageId, PriorityFn},
-    malicious_flags::MaliciousFlags,
+    malicious_strategies::MaliciousBehaviors,
     replica_config::ReplicaConfig,
 };
 use std::cell::RefCell;
@@ -105,6 +116,7 @@ pub struct ConsensusImpl {
     validator: Validator,
     aggregator: ShareAggregator,
     purger: Purger,
+    round_tracer: RoundTracer,
     metrics: ConsensusMetrics,
     time_source: Arc<dyn TimeSource>,
     registry_client: Arc<dyn RegistryClient>,
@@ -113,8 +125,9 @@ pub struct ConsensusImpl {
     schedule: RoundRobin,
     subnet_id: SubnetId,
     #[allow(dead_code)]
-    malicious_flags: MaliciousFlags,
+    malicious_behaviors: MaliciousBehaviors,
     log: ReplicaLogger,
+    event_log: EventLog,
     config: ConsensusConfig,
     local_store_time_reader: Option<Arc<dyn LocalStoreCertifiedTimeReader>>,
 }
@@ -128,21 +141,23 @@ impl ConsensusImpl {
         registry_client: Arc<dyn RegistryClient>,
         membership: Arc<Membership>,
         crypto: Arc<dyn ConsensusCrypto>,
+        crypto_pool: CryptoThreadPool,
         ingress_selector: Arc<dyn IngressSelector>,
-        xnet_payload_builder: Arc<dyn XNetPayloadBuilder>,
+        payload_sections: Vec<Arc<dyn PayloadSectionBuilder>>,
         dkg_pool: Arc<RwLock<dyn DkgPool>>,
+        equivocation_pool: Arc<RwLock<dyn EquivocationPool>>,
         message_routing: Arc<dyn MessageRouting>,
         state_manager: Arc<dyn StateManager<State = ReplicatedState>>,
         time_source: Arc<dyn TimeSource>,
         stable_registry_version_age: Duration,
-        malicious_flags: MaliciousFlags,
+        malicious_behaviors: MaliciousBehaviors,
         metrics_registry: MetricsRegistry,
         logger: ReplicaLogger,
+        event_log: EventLog,
         local_store_time_reader: Option<Arc<dyn LocalStoreCertifiedTimeReader>>,
     ) -> Self {
         let payload_builder = Arc::new(PayloadBuilderImpl::new(
-            ingress_selector.clone(),
-            xnet_payload_builder,
+            payload_sections,
             metrics_registry.clone(),
         ));
 
@@ -170,7 +185,9 @@ impl ConsensusImpl {
                 replica_config.clone(),
                 membership.clone(),
                 crypto.clone(),
+                crypto_pool.clone(),
                 state_manager.clone(),
+                message_routing.clone(),
                 metrics_registry.clone(),
                 logger.clone(),
             ),
@@ -179,6 +196,7 @@ impl ConsensusImpl {
                 registry_client.clone(),
                 membership.clone(),
                 crypto.clone(),
+                crypto_pool.clone(),
                 message_routing.clone(),
                 ingress_selector,
                 state_manager.clone(),
@@ -189,12 +207,14 @@ impl ConsensusImpl {
                 replica_config.clone(),
                 membership.clone(),
                 crypto.clone(),
+                crypto_pool.clone(),
                 logger.clone(),
             ),
             random_tape_maker: RandomTapeMaker::new(
                 replica_config.clone(),
                 membership.clone(),
                 crypto.clone(),
+                crypto_pool,
                 message_routing.clone(),
                 logger.clone(),
             ),
@@ -214,8 +234,11 @@ impl ConsensusImpl {
                 crypto.clone(),
                 payload_builder.clone(),
                 dkg_pool.clone(),
+                equivocation_pool,
                 state_manager.clone(),
+                message_routing.clone(),
                 stable_registry_version_age,
+                consensus_config.adaptive_payload_size().cloned(),
                 metrics_registry.clone(),
                 logger.clone(),
             ),
@@ -244,11 +267,17 @@ impl ConsensusImpl {
                 logger.clone(),
                 metrics_registry.clone(),
             ),
+            round_tracer: RoundTracer::new(
+                consensus_config.round_timelines(),
+                RoundTimelines::default(),
+                logger.clone(),
+            ),
             metrics: ConsensusMetrics::new(metrics_registry),
             log: logger,
+            event_log,
             time_source,
             registry_client,
-            malicious_flags,
+            malicious_behaviors,
             subnet_id: replica_config.subnet_id,
             last_invoked: RefCell::new(last_invoked),
             schedule: RoundRobin::default(),
@@ -257,9 +286,22 @@ impl ConsensusImpl {
         }
     }
 
+    /// Keep the timelines of the latest completed consensus rounds in the
+    /// given ring buffer, so that they can be queried for debugging slow
+    /// rounds.
+    pub fn with_round_timelines(mut self, round_timelines: RoundTimelines) -> Self {
+        self.round_tracer = RoundTracer::new(
+            self.config.round_timelines(),
+            round_timelines,
+            self.log.clone(),
+        );
+        self
+    }
+
     /// Call the given sub-component's `on_state_change` function, mark the
     /// time it takes to complete, increment its invocation counter, and mark
-    /// the size of the `ChangeSet` result.
+    /// the size of the `ChangeSet` result. The consensus-critical decisions
+    /// in the `ChangeSet` are recorded in the event log.
     fn call_with_metrics<F>(
         &self,
         sub_component: ConsensusSubcomponent,
@@ -292,9 +334,50 @@ impl ConsensusImpl {
             .with_label_values(&[name])
             .observe(change_set.len() as f64);
 
+        self.record_events(sub_component, &change_set);
+
         change_set
     }
 
+    /// Record the validated blocks, aggregated shares and created catch-up
+    /// packages of the given `ChangeSet` in the event log.
+    fn record_events(&self, sub_component: ConsensusSubcomponent, change_set: &ChangeSet) {
+        for action in change_set {
+            let event = match (sub_component, action) {
+                (
+                    ConsensusSubcomponent::Validator,
+                    ChangeAction::MoveToValidated(ConsensusMessage::BlockProposal(proposal)),
+                ) => Event::BlockValidated {
+                    height: proposal.height().get(),
+                    rank: proposal.rank().0,
+                    block_hash: hex::encode(&proposal.content.get_hash().get_ref().0),
+                },
+                (
+                    ConsensusSubcomponent::Aggregator,
+                    ChangeAction::AddToValidated(ConsensusMessage::CatchUpPackage(cup)),
+                ) => Event::CatchUpPackageCreated {
+                    height: cup.height().get(),
+                    state_hash: hex::encode(&cup.content.state_hash.get_ref().0),
+                },
+                (ConsensusSubcomponent::Aggregator, ChangeAction::AddToValidated(message)) => {
+                    let artifact = match message {
+                        ConsensusMessage::Notarization(_) => "notarization",
+                        ConsensusMessage::Finalization(_) => "finalization",
+                        ConsensusMessage::RandomBeacon(_) => "random_beacon",
+                        ConsensusMessage::RandomTape(_) => "random_tape",
+                        _ => continue,
+                    };
+                    Event::ShareAggregated {
+                        artifact: artifact.to_string(),
+                        height: message.height().get(),
+                    }
+                }
+                _ => continue,
+            };
+            self.event_log.record(event);
+        }
+    }
+
     /// check whether the subnet should halt because it has not reached
     /// the registry in a long time
     pub fn check_registry_outdated(&self) -> Result<(), String> {
@@ -365,6 +448,9 @@ impl Consensus for ConsensusImpl {
         // Load new transcripts, remove outdated keys.
         self.dkg_key_manager.on_state_change(&pool_reader);
 
+        self.round_tracer
+            .on_state_change(&pool_reader, self.time_source.get_relative_time());
+
         // For non-root subnets, we must halt if our registry is outdated
         if let Ok(false) = is_root_subnet(
             self.registry_client.as_ref(),
@@ -391,7 +477,11 @@ impl Consensus for ConsensusImpl {
 
         let finalize = || {
             self.call_with_metrics(ConsensusSubcomponent::Finalizer, || {
-                add_all_to_validated(self.finalizer.on_state_change(&pool_reader))
+                let shares = self.finalizer.on_state_change(&pool_reader);
+                add_all_to_validated(
+                    self.aggregator
+                        .aggregate_own_finalization_shares(&pool_reader, shares),
+                )
             })
         };
         let make_catch_up_package = || {
@@ -406,7 +496,11 @@ impl Consensus for ConsensusImpl {
         };
         let notarize = || {
             self.call_with_metrics(ConsensusSubcomponent::Notary, || {
-                add_all_to_validated(self.notary.on_state_change(&pool_reader))
+                let shares = self.notary.on_state_change(&pool_reader);
+                add_all_to_validated(
+                    self.aggregator
+                        .aggregate_own_notarization_shares(&pool_reader, shares),
+                )
             })
         };
         let make_random_beacon = || {
@@ -447,6 +541,9 @@ impl Consensus for ConsensusImpl {
         ];
 
         let changeset = self.schedule.call_next(&calls);
+        self.random_beacon_maker
+            .on_change_set(&pool_reader, &changeset);
+        self.aggregator.on_change_set(&changeset);
 
         if let Some(settings) = get_notarization_delay_settings(
             &self.log,
@@ -478,12 +575,12 @@ impl Consensus for ConsensusImpl {
         }
 
         #[cfg(feature = "malicious_code")]
-        if self.malicious_flags.is_consensus_malicious() {
+        if !self.malicious_behaviors.is_empty() {
             crate::consensus::malicious_consensus::maliciously_alter_changeset(
                 &pool_reader,
                 ingress_pool,
                 changeset,
-                &self.malicious_flags,
+                &self.malicious_behaviors,
                 &self.block_maker,
                 &self.finalizer,
                 &self.notary,
@@ -559,23 +656,31 @@ impl ConsensusGossip for ConsensusGossipImpl {
 #[allow(clippy::too_many_arguments)]
 /// Setup consensus component, and return two objects satisfying the Consensus
 /// and ConsensusGossip interfaces respectively.
+///
+/// Block payloads are built and validated by the given `payload_sections`, in
+/// order (see [payload_builder::default_sections]). The `ingress_selector` is
+/// only used to purge the ingress messages of delivered batches.
 pub fn setup(
     replica_config: ReplicaConfig,
     consensus_config: ConsensusConfig,
     registry_client: Arc<dyn RegistryClient>,
     membership: Arc<Membership>,
     crypto: Arc<dyn ConsensusCrypto>,
+    crypto_pool: CryptoThreadPool,
     ingress_selector: Arc<dyn IngressSelector>,
-    xnet_payload_builder: Arc<dyn XNetPayloadBuilder>,
+    payload_sections: Vec<Arc<dyn PayloadSectionBuilder>>,
     dkg_pool: Arc<RwLock<dyn DkgPool>>,
+    equivocation_pool: Arc<RwLock<dyn EquivocationPool>>,
     message_routing: Arc<dyn MessageRouting>,
     state_manager: Arc<dyn StateManager<State = ReplicatedState>>,
     time_source: Arc<dyn TimeSource>,
-    malicious_flags: MaliciousFlags,
+    malicious_behaviors: MaliciousBehaviors,
     metrics_registry: MetricsRegistry,
     logger: ReplicaLogger,
+    event_log: EventLog,
     local_store_time_reader: Option<Arc<dyn LocalStoreCertifiedTimeReader>>,
     registry_poll_delay_duration_ms: u64,
+    round_timelines: RoundTimelines,
 ) -> (ConsensusImpl, ConsensusGossipImpl) {
     // Currently, the nodemanager polls the registry every
     // `registry_poll_delay_duration_ms` and writes new updates into the
@@ -594,18 +699,22 @@ pub fn setup(
             registry_client,
             membership,
             crypto,
+            crypto_pool,
             ingress_selector,
-            xnet_payload_builder,
+            payload_sections,
             dkg_pool,
+            equivocation_pool,
             message_routing.clone(),
             state_manager,
             time_source,
             stable_registry_version_age,
-            malicious_flags,
+            malicious_behaviors,
             metrics_registry.clone(),
             logger,
+            event_log,
             local_store_time_reader,
-        ),
+        )
+        .with_round_timelines(round_timelines),
         ConsensusGossipImpl::new(message_routing, metrics_registry),
     )
 }
@@ -650,6 +759,7 @@ mod tests {
             replica_config,
             state_manager,
             dkg_pool,
+            equivocation_pool,
             ..
         } = dependencies_with_subnet_params(pool_config, subnet_id, vec![(1, record)]);
         state_manager
@@ -661,22 +771,31 @@ mod tests {
             .expect_get_state_hash_at()
             .return_const(Ok(Some(CryptoHashOfState::from(CryptoHash(Vec::new())))));
 
+        let ingress_selector = Arc::new(FakeIngressSelector::new());
+        let metrics_registry = MetricsRegistry::new();
         let consensus_impl = ConsensusImpl::new(
             replica_config,
             ConsensusConfig::default(),
             registry,
             membership,
             crypto,
-            Arc::new(FakeIngressSelector::new()),
-            Arc::new(FakeXNetPayloadBuilder::new()),
+            CryptoThreadPool::new(1),
+            ingress_selector.clone(),
+            payload_builder::default_sections(
+                ingress_selector,
+                Arc::new(FakeXNetPayloadBuilder::new()),
+                &metrics_registry,
+            ),
             dkg_pool,
+            equivocation_pool,
             Arc::new(FakeMessageRouting::new()),
             state_manager,
             time_source.clone(),
             Duration::from_secs(0),
-            MaliciousFlags::default(),
-            MetricsRegistry::new(),
+            MaliciousBehaviors::default(),
+            metrics_registry,
             no_op_logger(),
+            EventLog::disabled(),
             Some(Arc::new(FakeLocalStoreCertifiedTimeReader::new(
                 time_source.clone(),
             ))),
@@ -0,0 +1,401 @@
+//! Asynchronous variants of the signing and verification interfaces of
+//! consensus, which run the crypto operations on a dedicated thread pool.
+//!
+//! Signing may take long when the secret keys are held by a remote CSP vault.
+//! The components on the critical path of a round (the notary, the finalizer,
+//! the random beacon and random tape makers, and the certifier) therefore
+//! start their signing operations on the crypto thread pool and wait for them
+//! only until a short deadline, so that a slow vault does not stall their
+//! `on_state_change()`. The signatures that were not ready in time are kept
+//! in [PendingSignatures] and picked up by a later invocation. Verification
+//! only involves public keys; the components start the verifications they
+//! need all at once, so that they run in parallel, and wait for all of them.
+//!
+//! All components share one [CryptoThreadPool], whose size is configured by
+//! the consensus config.
+use super::crypto::{BatchVerify, SignVerify};
+use ic_interfaces::validation::ValidationResult;
+use ic_types::{
+    crypto::{CryptoError, CryptoResult, Signed},
+    NodeId,
+};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+/// The time a component waits for its signing operations to complete in one
+/// invocation of `on_state_change()`.
+pub(crate) const MAX_SIGNING_WAIT: Duration = Duration::from_millis(50);
+
+struct Slot<T> {
+    result: Option<T>,
+    waker: Option<Waker>,
+}
+
+struct Shared<T> {
+    slot: Mutex<Slot<T>>,
+    completed: Condvar,
+}
+
+/// The result of a crypto operation running on the crypto thread pool. The
+/// result can be taken once, either by awaiting the future or through one of
+/// the blocking methods.
+pub struct CryptoFuture<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T: Send + 'static> CryptoFuture<T> {
+    fn spawn(pool: &rayon::ThreadPool, operation: impl FnOnce() -> T + Send + 'static) -> Self {
+        let shared = Arc::new(Shared {
+            slot: Mutex::new(Slot {
+                result: None,
+                waker: None,
+            }),
+            completed: Condvar::new(),
+        });
+        let sender = Arc::clone(&shared);
+        pool.spawn(move || {
+            let result = operation();
+            let mut slot = sender.slot.lock().unwrap();
+            slot.result = Some(result);
+            if let Some(waker) = slot.waker.take() {
+                waker.wake();
+            }
+            sender.completed.notify_all();
+        });
+        Self { shared }
+    }
+}
+
+impl<T> CryptoFuture<T> {
+    /// Returns the result if the operation completed, without blocking.
+    pub fn try_take(&mut self) -> Option<T> {
+        self.shared.slot.lock().unwrap().result.take()
+    }
+
+    /// Waits until the operation completes or the `deadline` passes. Returns
+    /// the result if the operation completed.
+    pub fn take_before(&mut self, deadline: Instant) -> Option<T> {
+        let mut slot = self.shared.slot.lock().unwrap();
+        loop {
+            if let Some(result) = slot.result.take() {
+                return Some(result);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            slot = self
+                .shared
+                .completed
+                .wait_timeout(slot, deadline - now)
+                .unwrap()
+                .0;
+        }
+    }
+
+    /// Waits until the operation completes and returns its result.
+    pub fn wait(self) -> T {
+        let mut slot = self.shared.slot.lock().unwrap();
+        loop {
+            if let Some(result) = slot.result.take() {
+                return result;
+            }
+            slot = self.shared.completed.wait(slot).unwrap();
+        }
+    }
+}
+
+impl<T> Future for CryptoFuture<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut slot = self.shared.slot.lock().unwrap();
+        match slot.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// The thread pool on which the crypto operations of the consensus and
+/// certification components run. Clones share the same threads.
+#[derive(Clone)]
+pub struct CryptoThreadPool {
+    pool: Arc<rayon::ThreadPool>,
+}
+
+impl CryptoThreadPool {
+    /// Creates a crypto thread pool with the given number of threads.
+    pub fn new(num_threads: usize) -> Self {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(num_threads)
+            .thread_name(|index| format!("crypto_{}", index))
+            .build()
+            .expect("Couldn't build the crypto thread pool");
+        Self {
+            pool: Arc::new(pool),
+        }
+    }
+}
+
+/// A crypto component whose operations run on a crypto thread pool.
+pub struct AsyncCrypto<C: ?Sized> {
+    crypto: Arc<C>,
+    pool: CryptoThreadPool,
+}
+
+impl<C: ?Sized> Clone for AsyncCrypto<C> {
+    fn clone(&self) -> Self {
+        Self {
+            crypto: Arc::clone(&self.crypto),
+            pool: self.pool.clone(),
+        }
+    }
+}
+
+impl<C: ?Sized> AsyncCrypto<C> {
+    /// Runs the operations of `crypto` on the given thread pool.
+    pub fn new(crypto: Arc<C>, pool: CryptoThreadPool) -> Self {
+        Self { crypto, pool }
+    }
+
+    /// Returns the synchronous crypto component.
+    pub fn crypto(&self) -> &Arc<C> {
+        &self.crypto
+    }
+}
+
+/// The asynchronous variant of `SignVerify`.
+pub trait AsyncSignVerify<Message, Signature, KeySelector> {
+    /// Signs a message on the crypto thread pool.
+    fn sign_async(
+        &self,
+        message: Message,
+        signer: NodeId,
+        selector: KeySelector,
+    ) -> CryptoFuture<CryptoResult<Signature>>;
+
+    /// Verifies the signature of a Signed message on the crypto thread pool.
+    fn verify_async(
+        &self,
+        message: Signed<Message, Signature>,
+        selector: KeySelector,
+    ) -> CryptoFuture<ValidationResult<CryptoError>>;
+}
+
+impl<Message, Signature, KeySelector, C> AsyncSignVerify<Message, Signature, KeySelector>
+    for AsyncCrypto<C>
+where
+    Message: Send + 'static,
+    Signature: Send + 'static,
+    KeySelector: Send + 'static,
+    C: SignVerify<Message, Signature, KeySelector> + Send + Sync + ?Sized + 'static,
+{
+    fn sign_async(
+        &self,
+        message: Message,
+        signer: NodeId,
+        selector: KeySelector,
+    ) -> CryptoFuture<CryptoResult<Signature>> {
+        let crypto = Arc::clone(&self.crypto);
+        CryptoFuture::spawn(&self.pool.pool, move || {
+            crypto.sign(&message, signer, selector)
+        })
+    }
+
+    fn verify_async(
+        &self,
+        message: Signed<Message, Signature>,
+        selector: KeySelector,
+    ) -> CryptoFuture<ValidationResult<CryptoError>> {
+        let crypto = Arc::clone(&self.crypto);
+        CryptoFuture::spawn(&self.pool.pool, move || crypto.verify(&message, selector))
+    }
+}
+
+/// The asynchronous variant of `BatchVerify`.
+pub trait AsyncBatchVerify<Message, Signature, KeySelector> {
+    /// Verifies the signature shares of several signers on the same message
+    /// at once on the crypto thread pool.
+    fn verify_batch_async(
+        &self,
+        message: Message,
+        shares: Vec<Signature>,
+        selector: KeySelector,
+    ) -> CryptoFuture<ValidationResult<CryptoError>>;
+}
+
+impl<Message, Signature, KeySelector, C> AsyncBatchVerify<Message, Signature, KeySelector>
+    for AsyncCrypto<C>
+where
+    Message: Send + 'static,
+    Signature: Send + Sync + 'static,
+    KeySelector: Send + 'static,
+    C: BatchVerify<Message, Signature, KeySelector> + Send + Sync + ?Sized + 'static,
+{
+    fn verify_batch_async(
+        &self,
+        message: Message,
+        shares: Vec<Signature>,
+        selector: KeySelector,
+    ) -> CryptoFuture<ValidationResult<CryptoError>> {
+        let crypto = Arc::clone(&self.crypto);
+        CryptoFuture::spawn(&self.pool.pool, move || {
+            let shares: Vec<_> = shares.iter().collect();
+            crypto.verify_batch(&message, &shares, selector)
+        })
+    }
+}
+
+/// The signatures that a component started on the crypto thread pool, but
+/// that did not complete before the deadline of the invocation that started
+/// them, keyed by the content they sign.
+pub(crate) struct PendingSignatures<Content, Signature> {
+    pending: RefCell<BTreeMap<Content, CryptoFuture<CryptoResult<Signature>>>>,
+}
+
+impl<Content: Clone + Ord, Signature> PendingSignatures<Content, Signature> {
+    pub(crate) fn new() -> Self {
+        Self {
+            pending: RefCell::new(BTreeMap::new()),
+        }
+    }
+
+    /// Starts signing the given content with `sign`, unless its signature is
+    /// already pending.
+    pub(crate) fn start(
+        &self,
+        content: &Content,
+        sign: impl FnOnce() -> CryptoFuture<CryptoResult<Signature>>,
+    ) {
+        self.pending
+            .borrow_mut()
+            .entry(content.clone())
+            .or_insert_with(sign);
+    }
+
+    /// Returns the signature of the given content if it was started and
+    /// completes before the `deadline`. Otherwise, it stays pending for a
+    /// later call.
+    pub(crate) fn take_before(
+        &self,
+        content: &Content,
+        deadline: Instant,
+    ) -> Option<CryptoResult<Signature>> {
+        let mut pending = self.pending.borrow_mut();
+        let signature = pending.get_mut(content)?.take_before(deadline)?;
+        pending.remove(content);
+        Some(signature)
+    }
+
+    /// Drops the pending signatures of the content that no longer needs to be
+    /// signed.
+    pub(crate) fn retain(&self, mut needed: impl FnMut(&Content) -> bool) {
+        self.pending
+            .borrow_mut()
+            .retain(|content, _| needed(content))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn results_are_taken_once_completed() {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        let (release, released) = mpsc::channel::<()>();
+        let mut future = CryptoFuture::spawn(&pool, move || {
+            released.recv().unwrap();
+            42
+        });
+
+        assert_eq!(future.try_take(), None);
+        assert_eq!(
+            future.take_before(Instant::now() + Duration::from_millis(10)),
+            None
+        );
+        release.send(()).unwrap();
+        assert_eq!(future.wait(), 42);
+    }
+
+    #[test]
+    fn futures_can_be_awaited() {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+        let futures: Vec<_> = (0..4)
+            .map(|i| CryptoFuture::spawn(&pool, move || i * 2))
+            .collect();
+        let results = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(async {
+                let mut results = vec![];
+                for future in futures {
+                    results.push(future.await);
+                }
+                results
+            });
+        assert_eq!(results, vec![0, 2, 4, 6]);
+    }
+
+    #[test]
+    fn signatures_missing_the_deadline_are_taken_later() {
+        let pool = CryptoThreadPool::new(1);
+        let pool = &pool.pool;
+        let pending_signatures = PendingSignatures::<u64, u64>::new();
+        let blocked_sign = move |released: mpsc::Receiver<()>, signature: u64| {
+            move || {
+                CryptoFuture::spawn(pool, move || {
+                    let _ = released.recv();
+                    Ok(signature)
+                })
+            }
+        };
+        let not_restarted = || -> CryptoFuture<CryptoResult<u64>> {
+            panic!("the pending signing operation must not be restarted")
+        };
+
+        // The signing operation blocks until it is released, so it misses the
+        // deadline and stays pending.
+        let (release, released) = mpsc::channel();
+        pending_signatures.start(&1, blocked_sign(released, 2));
+        let deadline = Instant::now() + Duration::from_millis(10);
+        assert!(pending_signatures.take_before(&1, deadline).is_none());
+
+        // A later call takes the signature of the pending operation once it
+        // completed.
+        pending_signatures.start(&1, not_restarted);
+        release.send(()).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(60);
+        let signature = pending_signatures.take_before(&1, deadline);
+        assert_eq!(signature.unwrap().unwrap(), 2);
+        assert!(pending_signatures.take_before(&1, deadline).is_none());
+
+        // The signatures of content that is no longer needed are dropped, so
+        // that signing starts over if it is needed again.
+        let (release, released) = mpsc::channel();
+        pending_signatures.start(&3, blocked_sign(released, 6));
+        pending_signatures.retain(|content| *content != 3);
+        drop(release);
+        let (release, released) = mpsc::channel();
+        release.send(()).unwrap();
+        pending_signatures.start(&3, blocked_sign(released, 7));
+        let signature = pending_signatures.take_before(&3, deadline);
+        assert_eq!(signature.unwrap().unwrap(), 7);
+    }
+}
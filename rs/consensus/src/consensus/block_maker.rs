@@ -1,20 +1,38 @@
 #![deny(missing_docs)]
 use crate::{
     consensus::{
-        membership::Membership, metrics::BlockMakerMetrics, payload_builder::PayloadBuilder,
-        pool_reader::PoolReader, prelude::*, utils::*, ConsensusCrypto,
+        membership::Membership,
+        metrics::BlockMakerMetrics,
+        payload_builder::{PayloadBuilder, PayloadBuildingError, PayloadSizeLimits},
+        payload_size_controller::{scale, PayloadSizeController},
+        pool_reader::PoolReader,
+        prelude::*,
+        utils::*,
+        validator::MAX_EQUIVOCATION_PROOFS_PER_BLOCK,
+        ConsensusCrypto,
     },
     dkg::create_payload,
 };
+use ic_config::consensus::AdaptivePayloadSizeConfig;
 use ic_interfaces::{
-    dkg::DkgPool, ingress_pool::IngressPoolSelect, messaging::XNetPayloadError,
-    registry::RegistryClient, state_manager::StateManager, time_source::TimeSource,
+    dkg::DkgPool, equivocation::EquivocationPool, ingress_pool::IngressPoolSelect,
+    messaging::MessageRouting, registry::RegistryClient, state_manager::StateManager,
+    time_source::TimeSource,
 };
 use ic_logger::{debug, error, info, trace, warn, ReplicaLogger};
 use ic_metrics::MetricsRegistry;
 use ic_registry_client::helper::subnet::SubnetRegistry;
 use ic_replicated_state::ReplicatedState;
-use ic_types::{consensus::dkg, replica_config::ReplicaConfig, time::current_time, ReplicaVersion};
+use ic_types::{
+    consensus::{dkg, equivocation::EquivocationProof},
+    messages::{
+        MAX_CANISTER_HTTP_PAYLOAD_IN_BYTES, MAX_QUERY_STATS_PAYLOAD_IN_BYTES,
+        MAX_XNET_PAYLOAD_IN_BYTES,
+    },
+    replica_config::ReplicaConfig,
+    time::current_time,
+    ReplicaVersion,
+};
 use std::{
     sync::{Arc, Mutex, RwLock},
     time::Duration,
@@ -27,6 +45,11 @@ use std::{
 // the block validation path can skip the expensive crypto validation.
 const VALIDATED_DEALING_AGE_THRESHOLD_MSECS: u64 = 10;
 
+// The percentage of the payload size limits used after a time-sliced round,
+// so that the paused long-running messages can be resumed without competing
+// with as many new messages.
+const SLICED_ROUND_PAYLOAD_PERCENT: u64 = 50;
+
 /// A consensus subcomponent that is responsible for creating block proposals.
 pub struct BlockMaker {
     time_source: Arc<dyn TimeSource>,
@@ -36,7 +59,9 @@ pub struct BlockMaker {
     crypto: Arc<dyn ConsensusCrypto>,
     payload_builder: Arc<dyn PayloadBuilder>,
     dkg_pool: Arc<RwLock<dyn DkgPool>>,
+    equivocation_pool: Arc<RwLock<dyn EquivocationPool>>,
     state_manager: Arc<dyn StateManager<State = ReplicatedState>>,
+    message_routing: Arc<dyn MessageRouting>,
     metrics: BlockMakerMetrics,
     log: ReplicaLogger,
     payload_context_cache: Mutex<Option<(CryptoHashOf<Block>, ValidationContext)>>,
@@ -44,6 +69,9 @@ pub struct BlockMaker {
     // block. The older is the version, the higher is the probability, that it's universally
     // available across the subnet.
     stable_registry_version_age: Duration,
+    // Scales the payload sizes of new blocks to the load of the subnet, if
+    // adaptive payload sizes are enabled.
+    payload_size_controller: Option<Mutex<PayloadSizeController>>,
 }
 
 impl BlockMaker {
@@ -57,8 +85,11 @@ impl BlockMaker {
         crypto: Arc<dyn ConsensusCrypto>,
         payload_builder: Arc<dyn PayloadBuilder>,
         dkg_pool: Arc<RwLock<dyn DkgPool>>,
+        equivocation_pool: Arc<RwLock<dyn EquivocationPool>>,
         state_manager: Arc<dyn StateManager<State = ReplicatedState>>,
+        message_routing: Arc<dyn MessageRouting>,
         stable_registry_version_age: Duration,
+        adaptive_payload_size: Option<AdaptivePayloadSizeConfig>,
         metrics_registry: MetricsRegistry,
         log: ReplicaLogger,
     ) -> Self {
@@ -70,11 +101,15 @@ impl BlockMaker {
             crypto,
             payload_builder,
             dkg_pool,
+            equivocation_pool,
             state_manager,
+            message_routing,
             log,
             metrics: BlockMakerMetrics::new(metrics_registry),
             payload_context_cache: Mutex::new(None),
             stable_registry_version_age,
+            payload_size_controller: adaptive_payload_size
+                .map(|config| Mutex::new(PayloadSizeController::new(config))),
         }
     }
 
@@ -359,20 +394,26 @@ impl BlockMaker {
         } else {
             let past_payloads =
                 pool.get_payloads_from_height(certified_height.increment(), parent.clone());
-            match self
-                .payload_builder
-                .get_payload(height, ingress_pool, &past_payloads, context)
-            {
-                Ok(payload) => {
+            let limits = self.get_payload_size_limits(height, context, parent);
+            match self.payload_builder.get_payload(
+                height,
+                ingress_pool,
+                &past_payloads,
+                context,
+                &limits,
+            ) {
+                Ok(mut payload) => {
                     self.metrics
                         .get_payload_calls
                         .with_label_values(&["success"])
                         .inc();
+                    payload.equivocation_proofs =
+                        self.select_equivocation_proofs(height, certified_height, &past_payloads);
                     Some(payload)
                 }
-                Err(XNetPayloadError::Pending) => {
-                    // In case xnet payload builder has yet to finish preparing a payload,
-                    // we will try again later.
+                Err(PayloadBuildingError::Pending(section)) => {
+                    // In case a payload section has yet to finish preparing, we
+                    // will try again later.
                     //
                     // The necessary context is remembered in a local cache so that we can
                     // use the same context when trying again.
@@ -385,7 +426,8 @@ impl BlockMaker {
 
                     info!(
                         self.log,
-                        "XNet payload builder has yet to finish. Blockmaker will try again later."
+                        "The {} payload section has yet to finish. Blockmaker will try again later.",
+                        section
                     );
                     None
                 }
@@ -393,6 +435,89 @@ impl BlockMaker {
         }
     }
 
+    /// Return the limits of the ingress and xnet payload sizes of a new block
+    /// at the given height. Without a payload size controller, these are the
+    /// maximum sizes, unless the most recently executed round was time-sliced.
+    fn get_payload_size_limits(
+        &self,
+        height: Height,
+        context: &ValidationContext,
+        parent: &Block,
+    ) -> PayloadSizeLimits {
+        let sliced = self
+            .message_routing
+            .last_round_slicing_status()
+            .map_or(false, |status| status.is_sliced());
+        let mut percent = match &self.payload_size_controller {
+            Some(controller) => controller.lock().unwrap().update(
+                height,
+                context.time,
+                parent.context.time,
+                self.state_manager.latest_state_height(),
+            ),
+            None if !sliced => return PayloadSizeLimits::default(),
+            None => 100,
+        };
+        if sliced {
+            percent = percent * SLICED_ROUND_PAYLOAD_PERCENT / 100;
+        }
+        self.metrics.payload_size_percent.set(percent as i64);
+        let max_ingress_bytes = self
+            .registry_client
+            .get_ingress_message_settings(self.replica_config.subnet_id, context.registry_version)
+            .ok()
+            .flatten()
+            .map(|settings| scale(settings.ingress_bytes_per_block_soft_cap, percent));
+        PayloadSizeLimits {
+            max_ingress_bytes,
+            max_xnet_bytes: scale(MAX_XNET_PAYLOAD_IN_BYTES.get() as usize, percent),
+            max_canister_http_bytes: scale(
+                MAX_CANISTER_HTTP_PAYLOAD_IN_BYTES.get() as usize,
+                percent,
+            ),
+            max_query_stats_bytes: scale(MAX_QUERY_STATS_PAYLOAD_IN_BYTES.get() as usize, percent),
+        }
+    }
+
+    /// Return the validated equivocation proofs to include in a new block at
+    /// the given height. Only proofs of proposals above the certified height
+    /// can be checked against the past payloads, so that proofs are included
+    /// at most once. Proofs of the lowest heights are included first.
+    fn select_equivocation_proofs(
+        &self,
+        height: Height,
+        certified_height: Height,
+        past_payloads: &[(Height, Time, Payload)],
+    ) -> Vec<EquivocationProof> {
+        let included: Vec<&EquivocationProof> = past_payloads
+            .iter()
+            .filter(|(_, _, payload)| !payload.is_summary())
+            .flat_map(|(_, _, payload)| {
+                payload
+                    .as_ref()
+                    .as_batch_payload()
+                    .equivocation_proofs
+                    .iter()
+            })
+            .collect();
+        let mut proofs: Vec<EquivocationProof> = self
+            .equivocation_pool
+            .read()
+            .unwrap()
+            .get_validated()
+            .filter(|proof| proof.height() > certified_height && proof.height() < height)
+            .filter(|proof| {
+                !included.iter().any(|included| {
+                    included.signer() == proof.signer() && included.height() == proof.height()
+                })
+            })
+            .cloned()
+            .collect();
+        proofs.sort_by_key(|proof| proof.height());
+        proofs.truncate(MAX_EQUIVOCATION_PROOFS_PER_BLOCK);
+        proofs
+    }
+
     /// Log an entry for the proposed block and each of its ingress messages
     fn log_block(&self, block: &Block) {
         let hash = get_block_hash_string(block);
@@ -628,11 +753,17 @@ mod tests {
     use crate::consensus::mocks::{
         dependencies_with_subnet_params, Dependencies, MockPayloadBuilder,
     };
+    use ic_artifact_pool::equivocation_pool::EquivocationPoolImpl;
     use ic_interfaces::consensus_pool::ConsensusPool;
+    use ic_interfaces::equivocation::EquivocationChangeAction;
+    use ic_interfaces::messaging::RoundSlicingStatus;
     use ic_logger::replica_logger::no_op_logger;
     use ic_metrics::MetricsRegistry;
     use ic_test_artifact_pool::ingress_pool::TestIngressPool;
     use ic_test_utilities::{
+        consensus::fake::{FakeContentSigner, FromParent},
+        message_routing::{FakeMessageRouting, MockMessageRouting},
+        mock_time,
         registry::{add_subnet_record, SubnetRecordBuilder},
         types::ids::{node_test_id, subnet_test_id},
     };
@@ -699,8 +830,13 @@ mod tests {
                 crypto.clone(),
                 Arc::new(payload_builder),
                 dkg_pool.clone(),
+                Arc::new(RwLock::new(EquivocationPoolImpl::new(
+                    MetricsRegistry::new(),
+                ))),
                 state_manager.clone(),
+                Arc::new(FakeMessageRouting::new()),
                 Duration::from_millis(0),
+                None,
                 MetricsRegistry::new(),
                 no_op_logger(),
             );
@@ -749,7 +885,7 @@ mod tests {
 
             payload_builder
                 .expect_get_payload()
-                .withf(move |_, _, payloads, context| {
+                .withf(move |_, _, payloads, context, _| {
                     matches_expected_payloads(payloads) && context == &expected_context
                 })
                 .return_const(Ok(BatchPayload::default()));
@@ -776,8 +912,13 @@ mod tests {
                 Arc::clone(&crypto) as Arc<_>,
                 Arc::new(payload_builder),
                 dkg_pool,
+                Arc::new(RwLock::new(EquivocationPoolImpl::new(
+                    MetricsRegistry::new(),
+                ))),
                 state_manager,
+                Arc::new(FakeMessageRouting::new()),
                 Duration::from_millis(0),
+                None,
                 MetricsRegistry::new(),
                 no_op_logger(),
             );
@@ -825,6 +966,162 @@ mod tests {
         })
     }
 
+    #[test]
+    fn test_select_equivocation_proofs() {
+        ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
+            let node_ids: Vec<_> = (0..4).map(node_test_id).collect();
+            let Dependencies {
+                pool,
+                membership,
+                registry,
+                crypto,
+                time_source,
+                replica_config,
+                state_manager,
+                ..
+            } = dependencies_with_subnet_params(
+                pool_config,
+                subnet_test_id(0),
+                vec![(1, SubnetRecordBuilder::from(&node_ids).build())],
+            );
+            let genesis = pool.get_cache().finalized_block();
+            let make_proof = |height: u64, signer: u64| {
+                let proposal = |nanos| {
+                    let mut block = Block::from_parent(&genesis);
+                    block.height = Height::from(height);
+                    block.context.time = mock_time() + Duration::from_nanos(nanos);
+                    BlockProposal::fake(block, node_test_id(signer))
+                };
+                EquivocationProof::new(proposal(0), proposal(1)).unwrap()
+            };
+
+            let equivocation_pool = Arc::new(RwLock::new(EquivocationPoolImpl::new(
+                MetricsRegistry::new(),
+            )));
+            equivocation_pool.write().unwrap().apply_changes(vec![
+                EquivocationChangeAction::AddToValidated(make_proof(2, 0)),
+                EquivocationChangeAction::AddToValidated(make_proof(4, 1)),
+                EquivocationChangeAction::AddToValidated(make_proof(5, 2)),
+                EquivocationChangeAction::AddToValidated(make_proof(9, 3)),
+            ]);
+            let block_maker = BlockMaker::new(
+                Arc::clone(&time_source) as Arc<_>,
+                replica_config,
+                Arc::clone(&registry) as Arc<dyn RegistryClient>,
+                membership,
+                crypto,
+                Arc::new(MockPayloadBuilder::new()),
+                Arc::new(RwLock::new(ic_artifact_pool::dkg_pool::DkgPoolImpl::new(
+                    MetricsRegistry::new(),
+                ))),
+                equivocation_pool,
+                state_manager,
+                Arc::new(FakeMessageRouting::new()),
+                Duration::from_millis(0),
+                None,
+                MetricsRegistry::new(),
+                no_op_logger(),
+            );
+
+            // The proof at or below the certified height and the proof at the
+            // height of the new block are not included, and the lowest of the
+            // remaining proofs is included first.
+            let proofs =
+                block_maker.select_equivocation_proofs(Height::from(9), Height::from(2), &[]);
+            assert_eq!(proofs, vec![make_proof(4, 1)]);
+
+            // A proof already included in a past payload is not included again.
+            let past_payload = Payload::new(
+                ic_crypto::crypto_hash,
+                (
+                    BatchPayload {
+                        equivocation_proofs: vec![make_proof(4, 1)],
+                        ..BatchPayload::default()
+                    },
+                    dkg::Dealings::new_empty(Height::from(0)),
+                )
+                    .into(),
+            );
+            let past_payloads = vec![(Height::from(5), mock_time(), past_payload)];
+            let proofs = block_maker.select_equivocation_proofs(
+                Height::from(9),
+                Height::from(2),
+                &past_payloads,
+            );
+            assert_eq!(proofs, vec![make_proof(5, 2)]);
+
+            // No proof is left above the certified height.
+            let proofs =
+                block_maker.select_equivocation_proofs(Height::from(9), Height::from(5), &[]);
+            assert!(proofs.is_empty());
+        })
+    }
+
+    #[test]
+    fn test_payload_size_limits_after_sliced_round() {
+        ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
+            let node_ids = vec![node_test_id(0)];
+            let Dependencies {
+                pool,
+                membership,
+                registry,
+                crypto,
+                time_source,
+                replica_config,
+                state_manager,
+                ..
+            } = dependencies_with_subnet_params(
+                pool_config,
+                subnet_test_id(0),
+                vec![(1, SubnetRecordBuilder::from(&node_ids).build())],
+            );
+            let genesis = pool.get_cache().finalized_block();
+            let payload_size_limits = |deferred_messages| {
+                let mut message_routing = MockMessageRouting::new();
+                message_routing
+                    .expect_last_round_slicing_status()
+                    .return_const(Some(RoundSlicingStatus {
+                        height: Height::from(1),
+                        deferred_messages,
+                    }));
+                let block_maker = BlockMaker::new(
+                    Arc::clone(&time_source) as Arc<_>,
+                    replica_config.clone(),
+                    Arc::clone(&registry) as Arc<dyn RegistryClient>,
+                    Arc::clone(&membership),
+                    Arc::clone(&crypto) as Arc<_>,
+                    Arc::new(MockPayloadBuilder::new()),
+                    Arc::new(RwLock::new(ic_artifact_pool::dkg_pool::DkgPoolImpl::new(
+                        MetricsRegistry::new(),
+                    ))),
+                    Arc::new(RwLock::new(EquivocationPoolImpl::new(
+                        MetricsRegistry::new(),
+                    ))),
+                    Arc::clone(&state_manager) as Arc<_>,
+                    Arc::new(message_routing),
+                    Duration::from_millis(0),
+                    None,
+                    MetricsRegistry::new(),
+                    no_op_logger(),
+                );
+                block_maker.get_payload_size_limits(Height::from(1), &genesis.context, &genesis)
+            };
+
+            // Without deferred messages, the maximum sizes apply.
+            assert_eq!(payload_size_limits(0), PayloadSizeLimits::default());
+            // After a sliced round, the limits are scaled down.
+            let limits = payload_size_limits(3);
+            assert_eq!(
+                limits.max_xnet_bytes,
+                scale(
+                    MAX_XNET_PAYLOAD_IN_BYTES.get() as usize,
+                    SLICED_ROUND_PAYLOAD_PERCENT
+                )
+            );
+            assert!(limits.max_ingress_bytes.is_some());
+        })
+    }
+
     // We expect block maker to correctly detect version change and start
     // making only empty blocks.
     #[test]
@@ -896,8 +1193,13 @@ mod tests {
                 Arc::new(RwLock::new(ic_artifact_pool::dkg_pool::DkgPoolImpl::new(
                     MetricsRegistry::new(),
                 ))),
+                Arc::new(RwLock::new(EquivocationPoolImpl::new(
+                    MetricsRegistry::new(),
+                ))),
                 state_manager.clone(),
+                Arc::new(FakeMessageRouting::new()),
                 Duration::from_millis(0),
+                None,
                 MetricsRegistry::new(),
                 no_op_logger(),
             );
@@ -939,8 +1241,13 @@ mod tests {
                 Arc::new(RwLock::new(ic_artifact_pool::dkg_pool::DkgPoolImpl::new(
                     MetricsRegistry::new(),
                 ))),
+                Arc::new(RwLock::new(EquivocationPoolImpl::new(
+                    MetricsRegistry::new(),
+                ))),
                 state_manager,
+                Arc::new(FakeMessageRouting::new()),
                 Duration::from_millis(0),
+                None,
                 MetricsRegistry::new(),
                 no_op_logger(),
             );
@@ -1021,8 +1328,13 @@ mod tests {
                 Arc::new(RwLock::new(ic_artifact_pool::dkg_pool::DkgPoolImpl::new(
                     MetricsRegistry::new(),
                 ))),
+                Arc::new(RwLock::new(EquivocationPoolImpl::new(
+                    MetricsRegistry::new(),
+                ))),
                 state_manager,
+                Arc::new(FakeMessageRouting::new()),
                 Duration::from_millis(0),
+                None,
                 MetricsRegistry::new(),
                 no_op_logger(),
             );
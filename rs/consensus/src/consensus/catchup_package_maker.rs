@@ -12,16 +12,24 @@
 //!
 //! At the moment, we will start to make a CatchUpPackage once a DKG summary
 //! block is considered finalized.
+//!
+//! Hashing the summary block and signing the share can take a while on large
+//! subnets. Both run in a [BackgroundTask], so that the consensus rounds at
+//! DKG interval boundaries are not delayed, and the share is proposed by the
+//! first round after the task completed.
 use crate::consensus::{
-    membership::Membership, pool_reader::PoolReader, prelude::*,
-    utils::active_high_threshold_transcript, ConsensusCrypto,
+    membership::Membership,
+    pool_reader::PoolReader,
+    prelude::*,
+    utils::{active_high_threshold_transcript, BackgroundTask},
+    ConsensusCrypto,
 };
 use ic_interfaces::messaging::MessageRouting;
 use ic_interfaces::state_manager::{StateManager, StateManagerError};
 use ic_logger::{debug, error, trace, ReplicaLogger};
 use ic_replicated_state::ReplicatedState;
 use ic_types::replica_config::ReplicaConfig;
-use std::sync::Arc;
+use std::{cell::RefCell, sync::Arc};
 
 /// CatchUpPackage maker is responsible for creating beacon shares
 pub struct CatchUpPackageMaker {
@@ -31,6 +39,10 @@ pub struct CatchUpPackageMaker {
     state_manager: Arc<dyn StateManager<State = ReplicatedState>>,
     message_routing: Arc<dyn MessageRouting>,
     log: ReplicaLogger,
+    // The task creating the share of this node, if one is running. It yields
+    // None if the share couldn't be created, in which case a new task is
+    // started by a later round.
+    pending_share: RefCell<Option<BackgroundTask<Option<CatchUpPackageShare>>>>,
 }
 
 impl<'a> CatchUpPackageMaker {
@@ -50,6 +62,7 @@ impl<'a> CatchUpPackageMaker {
             state_manager,
             message_routing,
             log,
+            pending_share: Default::default(),
         }
     }
 
@@ -123,6 +136,20 @@ impl<'a> CatchUpPackageMaker {
             return None;
         }
 
+        // Propose the share of a completed task, and skip while the task for
+        // this height is still running. Tasks of lower heights are obsolete.
+        if let Some(task) = self.pending_share.replace(None) {
+            if task.height() == height {
+                match task.take_result() {
+                    Some(share) => return share,
+                    None => {
+                        self.pending_share.replace(Some(task));
+                        return None;
+                    }
+                }
+            }
+        }
+
         // Skip if random beacon does not exist for the height
         let random_beacon = pool.get_random_beacon(height)?;
 
@@ -156,36 +183,40 @@ impl<'a> CatchUpPackageMaker {
                 None
             }
             Ok(Some(state_hash)) => {
-                let content = CatchUpContent::new(
-                    HashedBlock::new(ic_crypto::crypto_hash, start_block),
-                    HashedRandomBeacon::new(ic_crypto::crypto_hash, random_beacon),
-                    state_hash,
-                );
-                let share_content = CatchUpShareContent::from(&content);
-                if let Some(transcript) = active_high_threshold_transcript(pool.as_cache(), height)
-                {
-                    match self.crypto.sign(&content, my_node_id, transcript.dkg_id) {
+                let dkg_id = match active_high_threshold_transcript(pool.as_cache(), height) {
+                    Some(transcript) => transcript.dkg_id,
+                    None => {
+                        error!(self.log, "Couldn't find transcript at height {}", height);
+                        return None;
+                    }
+                };
+                let crypto = Arc::clone(&self.crypto);
+                let log = self.log.clone();
+                let task = BackgroundTask::spawn(height, move || {
+                    let content = CatchUpContent::new(
+                        HashedBlock::new(ic_crypto::crypto_hash, start_block),
+                        HashedRandomBeacon::new(ic_crypto::crypto_hash, random_beacon),
+                        state_hash,
+                    );
+                    let share_content = CatchUpShareContent::from(&content);
+                    match crypto.sign(&content, my_node_id, dkg_id) {
                         Ok(signature) => {
                             // Caution: The log string below is checked in replica_determinism_test.
                             // Changing the string might break the test.
-                            debug!(
-                                self.log,
-                                "Proposing a CatchUpPackageShare at height {}", height
-                            );
+                            debug!(log, "Proposing a CatchUpPackageShare at height {}", height);
                             Some(CatchUpPackageShare {
                                 content: share_content,
                                 signature,
                             })
                         }
                         Err(err) => {
-                            error!(self.log, "Couldn't create a signature: {:?}", err);
+                            error!(log, "Couldn't create a signature: {:?}", err);
                             None
                         }
                     }
-                } else {
-                    error!(self.log, "Couldn't find transcript at height {}", height);
-                    None
-                }
+                });
+                self.pending_share.replace(Some(task));
+                None
             }
         }
     }
@@ -203,6 +234,21 @@ mod tests {
         types::ids::{node_test_id, subnet_test_id},
     };
     use std::sync::{Arc, RwLock};
+    use std::time::{Duration, Instant};
+
+    fn wait_for_share(
+        cup_maker: &CatchUpPackageMaker,
+        pool: &PoolReader<'_>,
+    ) -> CatchUpPackageShare {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            if let Some(share) = cup_maker.on_state_change(pool) {
+                return share;
+            }
+            assert!(Instant::now() < deadline, "Expecting CatchUpPackageShare");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
 
     #[test]
     fn test_catch_up_package_maker() {
@@ -262,11 +308,11 @@ mod tests {
             // 4. Beacon does not exist, we can't make a new CUP share
             assert!(cup_maker.on_state_change(&PoolReader::new(&pool)).is_none());
 
-            // 5. Beacon now exists, we can make a new CUP share
+            // 5. Beacon now exists, we can make a new CUP share in the
+            // background, and propose it once it is done
             pool.insert_validated(pool.make_next_beacon());
-            let share = cup_maker
-                .on_state_change(&PoolReader::new(&pool))
-                .expect("Expecting CatchUpPackageShare");
+            assert!(cup_maker.on_state_change(&PoolReader::new(&pool)).is_none());
+            let share = wait_for_share(&cup_maker, &PoolReader::new(&pool));
 
             assert_eq!(&share.content.block, proposal.content.get_hash());
         })
@@ -1,7 +1,11 @@
 use crate::consensus::prelude::*;
 use ic_interfaces::{crypto::*, validation::ValidationResult};
+use ic_types::consensus::canister_http::CanisterHttpResponseMetadata;
+use ic_types::consensus::ecdsa::EcdsaMessageContent;
+use ic_types::consensus::query_stats::QueryStatsContent;
 use ic_types::crypto::threshold_sig::ni_dkg::{DkgId, NiDkgId};
 use ic_types::crypto::CryptoError;
+use std::collections::BTreeMap;
 
 /// A trait that unifies the individual signing and verification interface for
 /// both threshold and multi signatures. It is parameterized by the following:
@@ -137,6 +141,74 @@ where {
     }
 }
 
+/// A trait that unifies the batch verification interface for both threshold
+/// and multi signature shares. It is parameterized like `SignVerify`.
+pub trait BatchVerify<Message, Signature, KeySelector> {
+    /// Verify the signature shares of several signers on the same message at
+    /// once, which is faster than verifying them one by one. Return
+    /// CryptoError if it fails, in which case at least one of the shares is
+    /// invalid, and the shares have to be verified individually to find out
+    /// which ones.
+    fn verify_batch(
+        &self,
+        message: &Message,
+        shares: &[&Signature],
+        selector: KeySelector,
+    ) -> ValidationResult<CryptoError>;
+}
+
+/// Return the signatures of the given shares by signer, or an error if a
+/// signer has more than one share, as only one of them would be verified.
+fn signatures_by_signer<Signature>(
+    shares: impl ExactSizeIterator<Item = (NodeId, Signature)>,
+) -> CryptoResult<BTreeMap<NodeId, Signature>> {
+    let len = shares.len();
+    let signatures: BTreeMap<_, _> = shares.collect();
+    if signatures.len() == len {
+        Ok(signatures)
+    } else {
+        Err(CryptoError::InvalidArgument {
+            message: "A signer has more than one share in the batch".to_string(),
+        })
+    }
+}
+
+impl<Message: Signable, C: MultiSigner<Message> + MultiSigVerifier<Message>>
+    BatchVerify<Message, MultiSignatureShare<Message>, RegistryVersion> for C
+{
+    fn verify_batch(
+        &self,
+        message: &Message,
+        shares: &[&MultiSignatureShare<Message>],
+        selector: RegistryVersion,
+    ) -> ValidationResult<CryptoError> {
+        let signatures = signatures_by_signer(
+            shares
+                .iter()
+                .map(|share| (share.signer, share.signature.clone())),
+        )?;
+        self.verify_multi_sig_individual_batch(&signatures, message, selector)
+    }
+}
+
+impl<Message: Signable, C: ThresholdSigner<Message> + ThresholdSigVerifier<Message>>
+    BatchVerify<Message, ThresholdSignatureShare<Message>, NiDkgId> for C
+{
+    fn verify_batch(
+        &self,
+        message: &Message,
+        shares: &[&ThresholdSignatureShare<Message>],
+        dkg_id: NiDkgId,
+    ) -> ValidationResult<CryptoError> {
+        let signatures = signatures_by_signer(
+            shares
+                .iter()
+                .map(|share| (share.signer, share.signature.clone())),
+        )?;
+        self.verify_threshold_sig_shares_batch(&signatures, message, DkgId::NiDkgId(dkg_id))
+    }
+}
+
 /// A trait that unifies the aggregation and verification interface
 /// for both threshold and multi signatures. It is parameterized by the
 /// following:
@@ -263,6 +335,8 @@ pub trait ConsensusCrypto:
     SignVerify<HashedBlock, BasicSignature<Block>, RegistryVersion>
     + SignVerify<NotarizationContent, MultiSignatureShare<NotarizationContent>, RegistryVersion>
     + SignVerify<FinalizationContent, MultiSignatureShare<FinalizationContent>, RegistryVersion>
+    + BatchVerify<NotarizationContent, MultiSignatureShare<NotarizationContent>, RegistryVersion>
+    + BatchVerify<FinalizationContent, MultiSignatureShare<FinalizationContent>, RegistryVersion>
     + SignVerify<RandomBeaconContent, ThresholdSignatureShare<RandomBeaconContent>, NiDkgId>
     + SignVerify<RandomTapeContent, ThresholdSignatureShare<RandomTapeContent>, NiDkgId>
     + SignVerify<CatchUpContent, ThresholdSignatureShare<CatchUpContent>, NiDkgId>
@@ -292,6 +366,12 @@ pub trait ConsensusCrypto:
         NiDkgId,
         ThresholdSignature<CatchUpContent>,
     > + SignVerify<dkg::DealingContent, BasicSignature<dkg::DealingContent>, RegistryVersion>
+    + SignVerify<
+        CanisterHttpResponseMetadata,
+        BasicSignature<CanisterHttpResponseMetadata>,
+        RegistryVersion,
+    > + SignVerify<QueryStatsContent, BasicSignature<QueryStatsContent>, RegistryVersion>
+    + SignVerify<EcdsaMessageContent, BasicSignature<EcdsaMessageContent>, RegistryVersion>
     + Crypto
     + Send
     + Sync
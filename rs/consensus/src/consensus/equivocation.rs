@@ -0,0 +1,232 @@
+//! The equivocation component detects block makers that signed two different
+//! block proposals at the same height, and validates the equivocation proofs
+//! received from peers. Validated proofs are gossiped, and included in blocks
+//! by the block maker, so that misbehaving block makers can be identified from
+//! the finalized chain.
+use crate::consensus::{
+    membership::Membership, pool_reader::PoolReader, prelude::*,
+    validator::validate_equivocation_proof, ConsensusCrypto,
+};
+use ic_interfaces::{
+    consensus_pool::{ConsensusPool, ConsensusPoolCache, HeightRange},
+    equivocation::{
+        Equivocation, EquivocationChangeAction, EquivocationChangeSet, EquivocationGossip,
+        EquivocationPool,
+    },
+    validation::ValidationError,
+};
+use ic_logger::{debug, warn, ReplicaLogger};
+use ic_types::{
+    artifact::{EquivocationProofAttribute, EquivocationProofId, Priority, PriorityFn},
+    consensus::equivocation::EquivocationProof,
+};
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+
+/// Returns the lowest height of the proofs that can still be included in a
+/// block. As the certified height of the validation context of a block is not
+/// lower than that of its parent, proofs at or below the certified height of
+/// the latest finalized block can never be included.
+fn min_includable_height(finalized_block: &Block) -> Height {
+    finalized_block.context.certified_height.increment()
+}
+
+/// Returns the proofs of all block makers that signed two different validated
+/// block proposals at the same height in the given range.
+pub(crate) fn find_equivocations(
+    pool_reader: &PoolReader<'_>,
+    range: HeightRange,
+) -> Vec<EquivocationProof> {
+    let mut proposals_by_signer: BTreeMap<(Height, NodeId), Vec<BlockProposal>> = BTreeMap::new();
+    for proposal in pool_reader
+        .pool()
+        .validated()
+        .block_proposal()
+        .get_by_height_range(range)
+    {
+        proposals_by_signer
+            .entry((proposal.height(), proposal.signature.signer))
+            .or_default()
+            .push(proposal);
+    }
+    proposals_by_signer
+        .into_iter()
+        .filter_map(|(_, mut proposals)| {
+            let second = proposals.pop()?;
+            let first = proposals.pop()?;
+            EquivocationProof::new(first, second)
+        })
+        .collect()
+}
+
+/// Implements the `Equivocation` trait.
+pub struct EquivocationImpl {
+    membership: Arc<Membership>,
+    crypto: Arc<dyn ConsensusCrypto>,
+    log: ReplicaLogger,
+}
+
+impl EquivocationImpl {
+    /// Build a new equivocation component.
+    pub fn new(
+        membership: Arc<Membership>,
+        crypto: Arc<dyn ConsensusCrypto>,
+        log: ReplicaLogger,
+    ) -> Self {
+        Self {
+            membership,
+            crypto,
+            log,
+        }
+    }
+
+    /// Returns the proofs of the equivocations in the consensus pool that are
+    /// not in the validated section of the equivocation pool yet.
+    fn detect_equivocations(
+        &self,
+        pool_reader: &PoolReader<'_>,
+        equivocation_pool: &dyn EquivocationPool,
+        min_height: Height,
+    ) -> EquivocationChangeSet {
+        let max_height = match pool_reader.pool().validated().block_proposal().max_height() {
+            Some(height) => height,
+            None => return Vec::new(),
+        };
+        let min_height = min_height.max(pool_reader.get_catch_up_height().increment());
+        if min_height > max_height {
+            return Vec::new();
+        }
+        let known: HashSet<EquivocationProofId> = equivocation_pool
+            .get_validated()
+            .map(ic_crypto::crypto_hash)
+            .collect();
+        find_equivocations(pool_reader, HeightRange::new(min_height, max_height))
+            .into_iter()
+            .filter(|proof| !known.contains(&ic_crypto::crypto_hash(proof)))
+            .map(|proof| {
+                warn!(
+                    self.log,
+                    "Block maker {:?} equivocated at height {:?}",
+                    proof.signer(),
+                    proof.height()
+                );
+                EquivocationChangeAction::AddToValidated(proof)
+            })
+            .collect()
+    }
+
+    /// Validates the proofs in the unvalidated section of the equivocation
+    /// pool.
+    fn validate_proofs(
+        &self,
+        pool_reader: &PoolReader<'_>,
+        equivocation_pool: &dyn EquivocationPool,
+        min_height: Height,
+    ) -> EquivocationChangeSet {
+        let known: HashSet<EquivocationProofId> = equivocation_pool
+            .get_validated()
+            .map(ic_crypto::crypto_hash)
+            .collect();
+        let mut change_set = Vec::new();
+        for proof in equivocation_pool.get_unvalidated() {
+            let id = ic_crypto::crypto_hash(proof);
+            if known.contains(&id) || proof.height() < min_height {
+                change_set.push(EquivocationChangeAction::RemoveFromUnvalidated(id));
+                continue;
+            }
+            match validate_equivocation_proof(
+                self.membership.as_ref(),
+                self.crypto.as_ref(),
+                pool_reader,
+                proof,
+            ) {
+                Ok(()) => change_set.push(EquivocationChangeAction::MoveToValidated(proof.clone())),
+                Err(ValidationError::Permanent(reason)) => {
+                    change_set.push(EquivocationChangeAction::HandleInvalid(id, reason))
+                }
+                Err(ValidationError::Transient(reason)) => debug!(
+                    self.log,
+                    "Couldn't validate the equivocation proof {:?}: {}", id, reason
+                ),
+            }
+        }
+        change_set
+    }
+}
+
+impl Equivocation for EquivocationImpl {
+    fn on_state_change(
+        &self,
+        consensus_pool: &dyn ConsensusPool,
+        equivocation_pool: &dyn EquivocationPool,
+    ) -> EquivocationChangeSet {
+        let pool_reader = PoolReader::new(consensus_pool);
+        let min_height = min_includable_height(&pool_reader.get_finalized_tip());
+
+        let mut change_set = self.detect_equivocations(&pool_reader, equivocation_pool, min_height);
+        change_set.append(&mut self.validate_proofs(&pool_reader, equivocation_pool, min_height));
+        if equivocation_pool
+            .get_validated()
+            .any(|proof| proof.height() < min_height)
+        {
+            change_set.push(EquivocationChangeAction::PurgeBelow(min_height));
+        }
+        change_set
+    }
+}
+
+/// Implements the `EquivocationGossip` trait.
+pub struct EquivocationGossipImpl {
+    consensus_cache: Arc<dyn ConsensusPoolCache>,
+}
+
+impl EquivocationGossipImpl {
+    /// Build a new equivocation gossip component.
+    pub fn new(consensus_cache: Arc<dyn ConsensusPoolCache>) -> Self {
+        Self { consensus_cache }
+    }
+}
+
+impl EquivocationGossip for EquivocationGossipImpl {
+    /// Proofs that can no longer be included in a block are dropped.
+    fn get_priority_function(
+        &self,
+        _equivocation_pool: &dyn EquivocationPool,
+    ) -> PriorityFn<EquivocationProofId, EquivocationProofAttribute> {
+        let min_height = min_includable_height(&self.consensus_cache.finalized_block());
+        Box::new(move |_id, attribute| {
+            if attribute.height < min_height {
+                Priority::Drop
+            } else {
+                Priority::Fetch
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::mocks::{dependencies, Dependencies};
+    use ic_test_utilities::{consensus::fake::FakeContentSigner, types::ids::node_test_id};
+    use std::time::Duration;
+
+    #[test]
+    fn test_find_equivocations() {
+        ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
+            let Dependencies { mut pool, .. } = dependencies(pool_config, 1);
+            let block = pool.make_next_block().as_ref().clone();
+            let mut other = block.clone();
+            other.context.time += Duration::from_nanos(1);
+            pool.insert_validated(BlockProposal::fake(block, node_test_id(0)));
+            let range = || HeightRange::new(Height::from(1), Height::from(1));
+            assert!(find_equivocations(&PoolReader::new(&pool), range()).is_empty());
+
+            pool.insert_validated(BlockProposal::fake(other, node_test_id(0)));
+            let proofs = find_equivocations(&PoolReader::new(&pool), range());
+            assert_eq!(proofs.len(), 1);
+            assert_eq!(proofs[0].signer(), node_test_id(0));
+            assert_eq!(proofs[0].height(), Height::from(1));
+        })
+    }
+}
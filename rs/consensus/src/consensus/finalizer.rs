@@ -17,6 +17,9 @@
 //! into a complete finalization, at which point the block and its ancestors
 //! become finalized.
 use crate::consensus::{
+    async_crypto::{
+        AsyncCrypto, AsyncSignVerify, CryptoThreadPool, PendingSignatures, MAX_SIGNING_WAIT,
+    },
     membership::Membership,
     metrics::FinalizerMetrics,
     pool_reader::PoolReader,
@@ -37,25 +40,28 @@ use ic_protobuf::registry::crypto::v1::PublicKey as PublicKeyProto;
 use ic_replicated_state::ReplicatedState;
 use ic_types::{
     crypto::threshold_sig::ni_dkg::{NiDkgId, NiDkgTargetSubnet::Remote, NiDkgTranscript},
-    messages::Response,
+    messages::{correlation_id, Response},
     replica_config::ReplicaConfig,
     CountBytes, ReplicaVersion,
 };
 use std::cell::RefCell;
 use std::collections::BTreeMap;
 use std::sync::Arc;
+use std::time::Instant;
 
 pub struct Finalizer {
     replica_config: ReplicaConfig,
     registry_client: Arc<dyn RegistryClient>,
     membership: Arc<Membership>,
-    crypto: Arc<dyn ConsensusCrypto>,
+    crypto: AsyncCrypto<dyn ConsensusCrypto>,
     message_routing: Arc<dyn MessageRouting>,
     ingress_selector: Arc<dyn IngressSelector>,
     state_manager: Arc<dyn StateManager<State = ReplicatedState>>,
     log: ReplicaLogger,
     metrics: FinalizerMetrics,
     prev_finalized_height: RefCell<Height>,
+    pending_signatures:
+        PendingSignatures<FinalizationContent, MultiSignatureShare<FinalizationContent>>,
 }
 
 impl Finalizer {
@@ -65,6 +71,7 @@ impl Finalizer {
         registry_client: Arc<dyn RegistryClient>,
         membership: Arc<Membership>,
         crypto: Arc<dyn ConsensusCrypto>,
+        crypto_pool: CryptoThreadPool,
         message_routing: Arc<dyn MessageRouting>,
         ingress_selector: Arc<dyn IngressSelector>,
         state_manager: Arc<dyn StateManager<State = ReplicatedState>>,
@@ -75,13 +82,14 @@ impl Finalizer {
             replica_config,
             registry_client,
             membership,
-            crypto,
+            crypto: AsyncCrypto::new(crypto, crypto_pool),
             message_routing,
             ingress_selector,
             state_manager,
             log,
             metrics: FinalizerMetrics::new(metrics_registry),
             prev_finalized_height: RefCell::new(Height::from(0)),
+            pending_signatures: PendingSignatures::new(),
         }
     }
 
@@ -98,8 +106,11 @@ impl Finalizer {
 
         // Try to finalize rounds from finalized_height + 1 up to (and including)
         // notarized_height
+        self.pending_signatures
+            .retain(|content| content.height > finalized_height);
+        let deadline = Instant::now() + MAX_SIGNING_WAIT;
         (finalized_height.increment().get()..=notarized_height.get())
-            .filter_map(|h| self.finalize_height(pool, Height::from(h)))
+            .filter_map(|h| self.finalize_height(pool, Height::from(h), deadline))
             .collect()
     }
 
@@ -125,7 +136,7 @@ impl Finalizer {
                     debug!(
                         self.log,
                         "Finalized height";
-                        consensus => ConsensusLogEntry { height: Some(h.get()), hash: Some(get_block_hash_string(&block)) }
+                        consensus => ConsensusLogEntry { height: Some(h.get()), hash: Some(get_block_hash_string(&block)), ..Default::default() }
                     );
                     self.metrics
                         .finalization_certified_state_difference
@@ -222,13 +233,15 @@ impl Finalizer {
     /// delivery was successful, returns false otherwise.
     fn deliver_batch(&self, batch: Batch, block_hash: &str) -> bool {
         let batch_height = batch.batch_number.get();
+        let span = tracing::debug_span!("batch_delivery", batch_height);
+        let _enter = span.enter();
         debug!(self.log, "deliver batch {:?}", batch_height);
         let ingress_count = batch.payload.ingress.message_count();
         let ingress_bytes = batch.payload.ingress.count_bytes();
         let xnet_bytes = batch.payload.xnet.count_bytes();
         let ingress_ids = batch.payload.ingress.message_ids();
         match self.message_routing.deliver_batch(batch) {
-            Ok(()) => {
+            Ok(_) => {
                 self.metrics
                     .batches_delivered
                     .with_label_values(&["success"])
@@ -247,6 +260,11 @@ impl Finalizer {
                     block.hash => block_hash
                 );
                 for ingress in ingress_ids.iter() {
+                    tracing::debug!(
+                        message_id = %ingress.message_id,
+                        correlation_id = %correlation_id(&ingress.message_id),
+                        "ingress_delivered"
+                    );
                     debug!(
                         self.log,
                         "ingress_message_delivered";
@@ -335,19 +353,29 @@ impl Finalizer {
     }
 
     /// Try to create a finalization share for a notarized block at the given
-    /// height
-    fn finalize_height(&self, pool: &PoolReader<'_>, height: Height) -> Option<FinalizationShare> {
+    /// height, if the signature completes before the `deadline`. Otherwise,
+    /// the signature is kept pending and a later invocation returns the share.
+    fn finalize_height(
+        &self,
+        pool: &PoolReader<'_>,
+        height: Height,
+        deadline: Instant,
+    ) -> Option<FinalizationShare> {
         let content = FinalizationContent::new(
             height,
             ic_crypto::crypto_hash(&self.pick_block_to_finality_sign(pool, height)?),
         );
-        let signature = self
-            .crypto
-            .sign(
-                &content,
+        let registry_version = pool.registry_version(height)?;
+        self.pending_signatures.start(&content, || {
+            self.crypto.sign_async(
+                content.clone(),
                 self.replica_config.node_id,
-                pool.registry_version(height)?,
+                registry_version,
             )
+        });
+        let signature = self
+            .pending_signatures
+            .take_before(&content, deadline)?
             .ok()?;
         Some(FinalizationShare { content, signature })
     }
@@ -416,6 +444,7 @@ impl Finalizer {
         let content = FinalizationContent::new(block.height, ic_crypto::crypto_hash(&block));
         let signature = self
             .crypto
+            .crypto()
             .sign(
                 &content,
                 self.replica_config.node_id,
@@ -585,6 +614,7 @@ mod tests {
                 registry,
                 membership,
                 crypto,
+                CryptoThreadPool::new(1),
                 message_routing.clone(),
                 ingress_selector,
                 state_manager,
@@ -676,6 +706,7 @@ mod tests {
                 registry,
                 membership,
                 crypto,
+                CryptoThreadPool::new(1),
                 message_routing.clone(),
                 ingress_selector,
                 Arc::new(FakeStateManager::new()),
@@ -13,9 +13,14 @@ use ic_interfaces::ingress_pool::IngressPoolSelect;
 #[cfg(feature = "malicious_code")]
 use ic_logger::ReplicaLogger;
 #[cfg(feature = "malicious_code")]
-use ic_types::consensus::ConsensusMessage::{BlockProposal, FinalizationShare};
+use ic_types::consensus::ConsensusMessage::{
+    BlockProposal, CatchUpPackageShare, FinalizationShare, NotarizationShare, RandomBeaconShare,
+    RandomTapeShare,
+};
 #[cfg(feature = "malicious_code")]
-use ic_types::malicious_flags::MaliciousFlags;
+use ic_types::malicious_strategies::{
+    Equivocate, FinalizeAll, MaliciousBehaviors, NotarizeAll, ProposeEmptyBlocks, WithholdShares,
+};
 
 #[cfg(feature = "malicious_code")]
 #[allow(clippy::too_many_arguments)]
@@ -23,20 +28,20 @@ pub fn maliciously_alter_changeset(
     pool: &PoolReader,
     ingress_pool: &dyn IngressPoolSelect,
     honest_changeset: ChangeSet,
-    malicious_flags: &MaliciousFlags,
+    malicious_behaviors: &MaliciousBehaviors,
     block_maker: &BlockMaker,
     finalizer: &Finalizer,
     notary: &Notary,
     logger: &ReplicaLogger,
 ) -> ChangeSet {
     let mut changeset = honest_changeset;
+    let equivocate = malicious_behaviors.contains::<Equivocate>();
+    let propose_empty_blocks = malicious_behaviors.contains::<ProposeEmptyBlocks>();
 
-    if malicious_flags.maliciously_propose_equivocating_blocks
-        || malicious_flags.maliciously_propose_empty_blocks
-    {
-        // If maliciously_propose_empty_blocks is enabled, we should remove non-empty
-        // block proposals by the honest code from the changeset.
-        if malicious_flags.maliciously_propose_empty_blocks {
+    if equivocate || propose_empty_blocks {
+        // If empty blocks are proposed, we should remove non-empty block
+        // proposals by the honest code from the changeset.
+        if propose_empty_blocks {
             changeset.retain(|change_action| {
                 !matches!(
                     change_action,
@@ -49,13 +54,13 @@ pub fn maliciously_alter_changeset(
             block_maker.maliciously_propose_blocks(
                 pool,
                 ingress_pool,
-                malicious_flags.maliciously_propose_empty_blocks,
-                malicious_flags.maliciously_propose_equivocating_blocks,
+                propose_empty_blocks,
+                equivocate,
             ),
         ));
     }
 
-    if malicious_flags.maliciously_notarize_all {
+    if malicious_behaviors.contains::<NotarizeAll>() {
         // First undo validations and invalidations of block proposals by the honest
         // code. We would not want the new ChangeActions to contradict or repeat
         // an existing ChangeAction.
@@ -79,7 +84,7 @@ pub fn maliciously_alter_changeset(
         ));
     }
 
-    if malicious_flags.maliciously_finalize_all {
+    if malicious_behaviors.contains::<FinalizeAll>() {
         // Remove any finalization shares that might have been output by the honest
         // code, to avoid deduplication.
         changeset.retain(|change_action| {
@@ -95,5 +100,20 @@ pub fn maliciously_alter_changeset(
         ));
     }
 
+    if malicious_behaviors.contains::<WithholdShares>() {
+        // Shares are only gossiped once they are in the validated pool, so
+        // dropping them from the changeset withholds them from all peers.
+        changeset.retain(|change_action| {
+            !matches!(
+                change_action,
+                ChangeAction::AddToValidated(NotarizationShare(_))
+                    | ChangeAction::AddToValidated(FinalizationShare(_))
+                    | ChangeAction::AddToValidated(RandomBeaconShare(_))
+                    | ChangeAction::AddToValidated(RandomTapeShare(_))
+                    | ChangeAction::AddToValidated(CatchUpPackageShare(_))
+            )
+        });
+    }
+
     changeset
 }
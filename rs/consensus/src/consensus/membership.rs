@@ -1,17 +1,22 @@
-use crate::consensus::utils::{
-    active_high_threshold_transcript, active_low_threshold_transcript, registry_version_at_height,
+use crate::consensus::{
+    ranking::{ranking_function, shuffle_nodes},
+    utils::{
+        active_high_threshold_transcript, active_low_threshold_transcript,
+        registry_version_at_height,
+    },
 };
-use ic_crypto::prng::{Csprng, RandomnessPurpose};
+use ic_crypto::prng::RandomnessPurpose;
 use ic_interfaces::{consensus_pool::ConsensusPoolCache, registry::RegistryClient};
+use ic_protobuf::registry::subnet::v1::BlockMakerRanking;
+use ic_registry_client::helper::subnet::SubnetRegistry;
 use ic_types::{
     consensus::{
         get_committee_size, get_faults_tolerated, Committee, HasHeight, RandomBeacon, Rank,
         Threshold,
     },
     registry::RegistryClientError,
-    Height, NodeId, SubnetId,
+    Height, NodeId, RegistryVersion, SubnetId,
 };
-use rand::seq::SliceRandom;
 use std::sync::Arc;
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
@@ -43,20 +48,38 @@ impl Membership {
         }
     }
 
+    fn registry_version(&self, height: Height) -> Result<RegistryVersion, MembershipError> {
+        registry_version_at_height(self.consensus_cache.as_ref(), height)
+            .ok_or(MembershipError::UnableToRetrieveDkgSummary(height))
+    }
+
     /// Return the node IDs from the registry.
     fn get_nodes(&self, height: Height) -> Result<Vec<NodeId>, MembershipError> {
-        use ic_registry_client::helper::subnet::SubnetRegistry;
-        let registry_version = registry_version_at_height(self.consensus_cache.as_ref(), height)
-            .ok_or(MembershipError::UnableToRetrieveDkgSummary(height))?;
-
         let list = self
             .registry_client
-            .get_node_ids_on_subnet(self.subnet_id, registry_version)
+            .get_node_ids_on_subnet(self.subnet_id, self.registry_version(height)?)
             .map_err(MembershipError::RegistryClientError)?;
 
         Ok(list.unwrap_or_default())
     }
 
+    /// Return true if the given node is a member of the subnet at the given
+    /// height.
+    pub fn node_belongs_to_subnet(
+        &self,
+        node_id: NodeId,
+        height: Height,
+    ) -> Result<bool, MembershipError> {
+        Ok(self.get_nodes(height)?.contains(&node_id))
+    }
+
+    /// Return true if the subnet consists of a single node at the given height.
+    /// Such a node does not need the shares of any other node to construct the
+    /// full consensus and certification artifacts.
+    pub fn is_single_node_subnet(&self, height: Height) -> Result<bool, MembershipError> {
+        Ok(self.get_nodes(height)?.len() == 1)
+    }
+
     /// Return a shuffled list of the node IDs at a given height using the given
     /// previous beacon.
     // Here we asserts that the given random beacon is from the previous height.
@@ -73,32 +96,34 @@ impl Membership {
         purpose: &RandomnessPurpose,
     ) -> Result<Vec<NodeId>, MembershipError> {
         assert_eq!(height, previous_beacon.height().increment());
-        let mut node_ids = self.get_nodes(height)?;
-        // To achieve a deterministic shuffling, we sort the ids first, to not rely on
-        // any ordering by the registry. We assume all node_ids are unique, so
-        // `sort_unstable` is effectively the same as `sort` but slightly more
-        // efficient.
-        node_ids.sort_unstable();
-        let mut rng = Csprng::from_random_beacon_and_purpose(&previous_beacon, purpose);
-        node_ids.shuffle(&mut rng);
-        Ok(node_ids)
+        let node_ids = self.get_nodes(height)?;
+        Ok(shuffle_nodes(previous_beacon, node_ids, purpose))
     }
 
     /// Return the the block maker rank of the given node id at the given
     /// height. If the returned rank is None, it means the node id is not a
     /// block maker at this height.
+    ///
+    /// The ranks are derived with the ranking function of the subnet record
+    /// at the registry version of the height, see `ranking::RankingFunction`.
     pub fn get_block_maker_rank(
         &self,
         height: Height,
         previous_beacon: &RandomBeacon,
         node_id: NodeId,
     ) -> Result<Option<Rank>, MembershipError> {
-        let shuffled_nodes = self.get_shuffled_nodes(
-            height,
-            previous_beacon,
-            &RandomnessPurpose::BlockmakerRanking,
-        )?;
-        Membership::get_block_maker_rank_from_shuffled_nodes(&node_id, &shuffled_nodes)
+        // The same reasoning about the previous beacon applies as in
+        // `get_shuffled_nodes()`.
+        assert_eq!(height, previous_beacon.height().increment());
+        let registry_version = self.registry_version(height)?;
+        let ranking = self
+            .registry_client
+            .get_block_maker_ranking(self.subnet_id, registry_version)
+            .map_err(MembershipError::RegistryClientError)?
+            .unwrap_or(BlockMakerRanking::Unspecified);
+        let ranked_nodes =
+            ranking_function(ranking).rank_nodes(previous_beacon, self.get_nodes(height)?);
+        Membership::get_block_maker_rank_from_shuffled_nodes(&node_id, &ranked_nodes)
     }
 
     fn get_block_maker_rank_from_shuffled_nodes(
@@ -268,6 +293,108 @@ pub mod test {
         }
     }
 
+    #[test]
+    fn test_node_belongs_to_subnet() {
+        use crate::consensus::mocks::{dependencies, Dependencies};
+        ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
+            let Dependencies { membership, .. } = dependencies(pool_config, 4);
+            let height = Height::from(0);
+            for node in 0..4 {
+                assert_eq!(
+                    membership.node_belongs_to_subnet(node_test_id(node), height),
+                    Ok(true)
+                );
+            }
+            assert_eq!(
+                membership.node_belongs_to_subnet(node_test_id(4), height),
+                Ok(false)
+            );
+        })
+    }
+
+    /// Returns the ranks of the given nodes at height 1, by the membership of
+    /// a subnet with the given record, and the previous random beacon.
+    fn block_maker_ranks(
+        pool_config: ic_config::artifact_pool::ArtifactPoolConfig,
+        node_ids: &[NodeId],
+        record: ic_protobuf::registry::subnet::v1::SubnetRecord,
+    ) -> (Vec<Option<Rank>>, RandomBeacon) {
+        use crate::consensus::{
+            mocks::{dependencies_with_subnet_params, Dependencies},
+            pool_reader::PoolReader,
+        };
+        use ic_test_utilities::types::ids::subnet_test_id;
+        let Dependencies {
+            membership, pool, ..
+        } = dependencies_with_subnet_params(pool_config, subnet_test_id(0), vec![(1, record)]);
+        let beacon = PoolReader::new(&pool)
+            .get_random_beacon(Height::from(0))
+            .unwrap();
+        let ranks = node_ids
+            .iter()
+            .map(|node_id| {
+                membership
+                    .get_block_maker_rank(Height::from(1), &beacon, *node_id)
+                    .unwrap()
+            })
+            .collect();
+        (ranks, beacon)
+    }
+
+    /// Returns the expected ranks of the given nodes by the given ranking.
+    fn expected_ranks(
+        ranking: BlockMakerRanking,
+        beacon: &RandomBeacon,
+        node_ids: &[NodeId],
+    ) -> Vec<Option<Rank>> {
+        let ranked_nodes = ranking_function(ranking).rank_nodes(beacon, node_ids.to_vec());
+        node_ids
+            .iter()
+            .map(|node_id| {
+                Membership::get_block_maker_rank_from_shuffled_nodes(node_id, &ranked_nodes)
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_block_maker_ranks_follow_the_ranking_of_the_subnet_record() {
+        use ic_test_utilities::registry::SubnetRecordBuilder;
+        let node_ids: Vec<_> = (0..7).map(node_test_id).collect();
+        for ranking in &[
+            BlockMakerRanking::Unspecified,
+            BlockMakerRanking::RandomBeacon,
+            BlockMakerRanking::NodeHash,
+        ] {
+            ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
+                let mut record = SubnetRecordBuilder::from(&node_ids).build();
+                record.block_maker_ranking = *ranking as i32;
+                let (ranks, beacon) = block_maker_ranks(pool_config, &node_ids, record);
+                assert_eq!(ranks, expected_ranks(*ranking, &beacon, &node_ids));
+                // One rank is assigned per block maker.
+                let mut assigned: Vec<_> = ranks.into_iter().flatten().collect();
+                assigned.sort_unstable();
+                let block_makers = get_faults_tolerated(node_ids.len()) as u64 + 1;
+                assert_eq!(assigned, (0..block_makers).map(Rank).collect::<Vec<_>>());
+            })
+        }
+    }
+
+    #[test]
+    fn test_unknown_rankings_fall_back_to_the_random_beacon() {
+        use ic_test_utilities::registry::SubnetRecordBuilder;
+        let node_ids: Vec<_> = (0..7).map(node_test_id).collect();
+        ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
+            let mut record = SubnetRecordBuilder::from(&node_ids).build();
+            record.block_maker_ranking = i32::MAX;
+            let (ranks, beacon) = block_maker_ranks(pool_config, &node_ids, record);
+            assert_eq!(
+                ranks,
+                expected_ranks(BlockMakerRanking::RandomBeacon, &beacon, &node_ids)
+            );
+        })
+    }
+
     #[test]
     fn test_notarization_threshold_for_safety_and_liveness() {
         // This test is written assuming that the finalization treshold and
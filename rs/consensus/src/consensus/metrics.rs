@@ -5,7 +5,9 @@ use ic_metrics::{
     MetricsRegistry,
 };
 use ic_types::consensus::{Block, BlockProposal, HasHeight, HasRank};
-use prometheus::{GaugeVec, Histogram, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec};
+use prometheus::{
+    GaugeVec, Histogram, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec,
+};
 use std::sync::RwLock;
 
 // For certain metrics, we record metrics based on block's rank.
@@ -16,6 +18,7 @@ const RANKS_TO_RECORD: [&str; 6] = ["0", "1", "2", "3", "4", "5"];
 pub struct BlockMakerMetrics {
     pub get_payload_calls: IntCounterVec,
     pub block_size_bytes_estimate: IntGaugeVec,
+    pub payload_size_percent: IntGauge,
 }
 
 impl BlockMakerMetrics {
@@ -29,7 +32,11 @@ impl BlockMakerMetrics {
             block_size_bytes_estimate: metrics_registry.int_gauge_vec(
                 "consensus_block_size_bytes_estimate", 
                 "An estimate about the block size produced by the block maker.",
-                &["payload_type"])
+                &["payload_type"]),
+            payload_size_percent: metrics_registry.int_gauge(
+                "consensus_block_maker_payload_size_percent",
+                "The percentage of the maximum ingress and xnet payload sizes used by the block maker.",
+            ),
         }
     }
 
@@ -181,7 +188,7 @@ impl NotaryMetrics {
 pub struct PayloadBuilderMetrics {
     pub get_payload_duration: Histogram,
     pub validate_payload_duration: Histogram,
-    pub ingress_payload_cache_size: IntGauge,
+    pub section_duration: HistogramVec,
     pub past_payloads_length: Histogram,
 }
 
@@ -202,9 +209,13 @@ impl PayloadBuilderMetrics {
                 // 1s, 2s, 5s
                 decimal_buckets(-4, 0),
             ),
-            ingress_payload_cache_size: metrics_registry.int_gauge(
-                "ingress_payload_cache_size",
-                "The number of HashSets in payload builder's ingress payload cache.",
+            section_duration: metrics_registry.histogram_vec(
+                "consensus_payload_section_duration_seconds",
+                "The time it took to build or validate a payload section, in seconds",
+                // 0.1ms, 0.2ms, 0.5ms, 1ms, 2ms, 5ms, 10ms, 20ms, 50ms, 100ms, 200ms, 500ms,
+                // 1s, 2s, 5s
+                decimal_buckets(-4, 0),
+                &["section", "operation"],
             ),
             past_payloads_length: metrics_registry.histogram(
                 "consensus_past_payloads_length",
@@ -220,6 +231,7 @@ pub struct ValidatorMetrics {
     pub duplicate_artifact: IntCounterVec,
     pub validation_duration: HistogramVec,
     pub dkg_validator: IntCounterVec,
+    pub batch_verification_failures: IntCounter,
     // Used to sum the values within a single validator run
     dkg_time_per_validator_run: RwLock<f64>,
 }
@@ -254,6 +266,10 @@ impl ValidatorMetrics {
                 "DKG validator counter",
                 &["type"],
             ),
+            batch_verification_failures: metrics_registry.int_counter(
+                "consensus_batch_verification_failures",
+                "The number of batches of signature shares that failed verification, and were verified individually",
+            ),
             dkg_time_per_validator_run: RwLock::new(0.0),
         }
     }
@@ -1,10 +1,13 @@
 //! Contains mocks for traits internal to consensus
-use crate::consensus::{membership::Membership, payload_builder::PayloadBuilder};
-use ic_artifact_pool::dkg_pool::DkgPoolImpl;
+use crate::consensus::{
+    membership::Membership,
+    payload_builder::{PayloadBuilder, PayloadBuildingError, PayloadSizeLimits},
+};
+use ic_artifact_pool::{dkg_pool::DkgPoolImpl, equivocation_pool::EquivocationPoolImpl};
 use ic_config::artifact_pool::ArtifactPoolConfig;
 use ic_interfaces::{
     consensus::PayloadValidationError, ingress_pool::IngressPoolSelect,
-    messaging::XNetPayloadError, validation::ValidationResult,
+    validation::ValidationResult,
 };
 use ic_protobuf::registry::subnet::v1::SubnetRecord;
 use ic_registry_client::fake::FakeRegistryClient;
@@ -36,8 +39,9 @@ mock! {
             height: Height,
             ingress_pool: &'a (dyn IngressPoolSelect + 'a),
             past_payloads: &[(Height, Time, Payload)],
-            context: &ValidationContext
-        ) -> Result<BatchPayload, XNetPayloadError>;
+            context: &ValidationContext,
+            limits: &PayloadSizeLimits,
+        ) -> Result<BatchPayload, PayloadBuildingError>;
         fn validate_payload(
             &self,
             payload: &Payload,
@@ -57,6 +61,7 @@ pub struct Dependencies {
     pub replica_config: ReplicaConfig,
     pub state_manager: Arc<RefMockStateManager>,
     pub dkg_pool: Arc<RwLock<DkgPoolImpl>>,
+    pub equivocation_pool: Arc<RwLock<EquivocationPoolImpl>>,
 }
 
 /// Creates most common consensus components used for testing. All components
@@ -94,6 +99,9 @@ pub fn dependencies_with_subnet_records_with_raw_state_manager(
         registry.clone(),
         subnet_id,
     ));
+    let equivocation_pool = Arc::new(RwLock::new(EquivocationPoolImpl::new(
+        ic_metrics::MetricsRegistry::new(),
+    )));
     Dependencies {
         crypto,
         registry,
@@ -104,6 +112,7 @@ pub fn dependencies_with_subnet_records_with_raw_state_manager(
         replica_config,
         state_manager,
         dkg_pool,
+        equivocation_pool,
     }
 }
 
@@ -126,6 +135,7 @@ pub fn dependencies_with_subnet_params(
         replica_config,
         state_manager,
         dkg_pool,
+        equivocation_pool,
         ..
     } = dependencies_with_subnet_records_with_raw_state_manager(pool_config, subnet_id, records);
 
@@ -147,6 +157,7 @@ pub fn dependencies_with_subnet_params(
         replica_config,
         state_manager,
         dkg_pool,
+        equivocation_pool,
     }
 }
 
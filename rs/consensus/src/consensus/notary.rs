@@ -24,6 +24,9 @@
 //!   latest round, which would break security if it has already finality-signed
 //!   for that round.
 use crate::consensus::{
+    async_crypto::{
+        AsyncCrypto, AsyncSignVerify, CryptoThreadPool, PendingSignatures, MAX_SIGNING_WAIT,
+    },
     membership::{Membership, MembershipError},
     metrics::NotaryMetrics,
     pool_reader::PoolReader,
@@ -31,6 +34,7 @@ use crate::consensus::{
     utils::{find_lowest_ranked_proposals, get_adjusted_notary_delay},
     ConsensusCrypto,
 };
+use ic_interfaces::messaging::MessageRouting;
 use ic_interfaces::state_manager::StateManager;
 use ic_interfaces::time_source::TimeSource;
 use ic_logger::{error, trace, warn, ReplicaLogger};
@@ -38,24 +42,31 @@ use ic_metrics::MetricsRegistry;
 use ic_replicated_state::ReplicatedState;
 use ic_types::replica_config::ReplicaConfig;
 use std::sync::Arc;
+use std::time::Instant;
 
 pub struct Notary {
     time_source: Arc<dyn TimeSource>,
     replica_config: ReplicaConfig,
     membership: Arc<Membership>,
-    crypto: Arc<dyn ConsensusCrypto>,
+    crypto: AsyncCrypto<dyn ConsensusCrypto>,
     state_manager: Arc<dyn StateManager<State = ReplicatedState>>,
+    message_routing: Arc<dyn MessageRouting>,
     log: ReplicaLogger,
     metrics: NotaryMetrics,
+    pending_signatures:
+        PendingSignatures<NotarizationContent, MultiSignatureShare<NotarizationContent>>,
 }
 
 impl Notary {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         time_source: Arc<dyn TimeSource>,
         replica_config: ReplicaConfig,
         membership: Arc<Membership>,
         crypto: Arc<dyn ConsensusCrypto>,
+        crypto_pool: CryptoThreadPool,
         state_manager: Arc<dyn StateManager<State = ReplicatedState>>,
+        message_routing: Arc<dyn MessageRouting>,
         metrics_registry: MetricsRegistry,
         log: ReplicaLogger,
     ) -> Notary {
@@ -63,10 +74,12 @@ impl Notary {
             time_source,
             replica_config,
             membership,
-            crypto,
+            crypto: AsyncCrypto::new(crypto, crypto_pool),
             state_manager,
+            message_routing,
             log,
             metrics: NotaryMetrics::new(metrics_registry),
+            pending_signatures: PendingSignatures::new(),
         }
     }
 
@@ -76,6 +89,9 @@ impl Notary {
     pub fn on_state_change(&self, pool: &PoolReader<'_>) -> Vec<NotarizationShare> {
         trace!(self.log, "on_state_change");
         let notarized_height = pool.get_notarized_height();
+        self.pending_signatures
+            .retain(|content| content.height > notarized_height);
+        let deadline = Instant::now() + MAX_SIGNING_WAIT;
         let mut notarization_shares = Vec::new();
         if let Some(previous_beacon) = pool.get_random_beacon(notarized_height) {
             if !self.is_notary(pool, &previous_beacon) {
@@ -86,7 +102,7 @@ impl Notary {
                 if let Some(elapsed) = self.time_to_notarize(pool, height, proposal.rank()) {
                     if !self.is_proposal_already_notarized_by_me(pool, &proposal) {
                         let block = proposal.as_ref();
-                        if let Some(s) = self.notarize_block(pool, block) {
+                        if let Some(s) = self.notarize_block(pool, block, deadline) {
                             self.metrics.report_notarization(block, elapsed);
                             notarization_shares.push(s);
                         }
@@ -109,6 +125,7 @@ impl Notary {
             self.membership.as_ref(),
             pool,
             self.state_manager.as_ref(),
+            self.message_routing.as_ref(),
             &self.log,
             height,
             rank,
@@ -149,18 +166,25 @@ impl Notary {
         }
     }
 
-    /// Notarize and return a `NotarizationShare` for the given block
+    /// Notarize and return a `NotarizationShare` for the given block, if the
+    /// signature completes before the `deadline`. Otherwise, the signature is
+    /// kept pending and a later invocation returns the share.
     fn notarize_block<'a>(
         &self,
         pool: &PoolReader<'_>,
         block: &'a Block,
+        deadline: Instant,
     ) -> Option<NotarizationShare> {
         let registry_version = pool.registry_version(block.height)?;
         let content = NotarizationContent::new(block.height, ic_crypto::crypto_hash(block));
-        match self
-            .crypto
-            .sign(&content, self.replica_config.node_id, registry_version)
-        {
+        self.pending_signatures.start(&content, || {
+            self.crypto.sign_async(
+                content.clone(),
+                self.replica_config.node_id,
+                registry_version,
+            )
+        });
+        match self.pending_signatures.take_before(&content, deadline)? {
             Ok(signature) => Some(NotarizationShare { content, signature }),
             Err(err) => {
                 error!(self.log, "Couldn't create a signature: {:?}", err);
@@ -193,8 +217,8 @@ impl Notary {
         trace!(self.log, "maliciously_notarize");
         let mut notarization_shares = Vec::<NotarizationShare>::new();
 
-        let range = HeightRange::new(
-            pool.get_notarized_height().increment(),
+        let range = HeightRange::above(
+            pool.get_notarized_height(),
             pool.get_random_beacon_height().increment(),
         );
 
@@ -206,7 +230,9 @@ impl Notary {
         for proposal in proposals {
             if !self.is_proposal_already_notarized_by_me(pool, &proposal) {
                 let block = proposal.as_ref();
-                if let Some(share) = self.notarize_block(pool, block) {
+                if let Some(share) =
+                    self.notarize_block(pool, block, Instant::now() + MAX_SIGNING_WAIT)
+                {
                     notarization_shares.push(share);
                 }
             }
@@ -230,11 +256,12 @@ mod tests {
     //! Notary unit tests
     use super::*;
     use crate::consensus::mocks::{dependencies_with_subnet_params, Dependencies};
-    use ic_interfaces::consensus_pool::ConsensusPool;
+    use ic_interfaces::{consensus_pool::ConsensusPool, messaging::BatchPipelineOccupancy};
     use ic_logger::replica_logger::no_op_logger;
     use ic_metrics::MetricsRegistry;
     use ic_test_utilities::{
         consensus::fake::*,
+        message_routing::{FakeMessageRouting, MockMessageRouting},
         registry::SubnetRecordBuilder,
         types::ids::{node_test_id, subnet_test_id},
     };
@@ -280,13 +307,16 @@ mod tests {
             pool.insert_validated(block.clone());
 
             let metrics_registry = MetricsRegistry::new();
+            let message_routing = Arc::new(FakeMessageRouting::new());
 
             let notary = Notary::new(
                 Arc::clone(&time_source) as Arc<_>,
                 replica_config,
                 membership.clone(),
                 crypto,
+                CryptoThreadPool::new(1),
                 state_manager.clone(),
+                message_routing.clone(),
                 metrics_registry,
                 no_op_logger(),
             );
@@ -305,6 +335,7 @@ mod tests {
                             membership.as_ref(),
                             &PoolReader::new(&pool),
                             state_manager.as_ref(),
+                            message_routing.as_ref(),
                             &no_op_logger(),
                             Height::from(1),
                             Rank(0),
@@ -354,6 +385,7 @@ mod tests {
                             membership.as_ref(),
                             &PoolReader::new(&pool),
                             state_manager.as_ref(),
+                            message_routing.as_ref(),
                             &no_op_logger(),
                             Height::from(1),
                             Rank(9),
@@ -371,6 +403,7 @@ mod tests {
                             membership.as_ref(),
                             &PoolReader::new(&pool),
                             state_manager.as_ref(),
+                            message_routing.as_ref(),
                             &no_op_logger(),
                             Height::from(1),
                             twenty_block.rank(),
@@ -442,4 +475,52 @@ mod tests {
             });
         })
     }
+
+    #[test]
+    fn test_notary_delay_tolerates_half_full_batch_pipeline() {
+        ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
+            let committee = vec![node_test_id(0)];
+            let Dependencies {
+                mut pool,
+                membership,
+                state_manager,
+                ..
+            } = dependencies_with_subnet_params(
+                pool_config,
+                subnet_test_id(0),
+                vec![(1, SubnetRecordBuilder::from(&committee).build())],
+            );
+            state_manager
+                .get_mut()
+                .expect_latest_certified_height()
+                .return_const(Height::new(0));
+            pool.advance_round_normal_operation();
+
+            let notary_delay = |pending_batches| {
+                let mut message_routing = MockMessageRouting::new();
+                message_routing
+                    .expect_batch_pipeline_occupancy()
+                    .return_const(BatchPipelineOccupancy {
+                        pending_batches,
+                        capacity: 16,
+                    });
+                get_adjusted_notary_delay(
+                    membership.as_ref(),
+                    &PoolReader::new(&pool),
+                    state_manager.as_ref(),
+                    &message_routing,
+                    &no_op_logger(),
+                    Height::from(1),
+                    Rank(0),
+                )
+                .unwrap()
+            };
+
+            // Up to half of the pipeline is buffered without slowing down.
+            let delay = notary_delay(0);
+            assert_eq!(notary_delay(8), delay);
+            // Each batch beyond that adds the unit delay of the subnet record.
+            assert_eq!(notary_delay(11), delay + Duration::from_millis(3 * 500));
+        })
+    }
 }
@@ -2,11 +2,11 @@
 
 use crate::consensus::metrics::PayloadBuilderMetrics;
 use ic_interfaces::{
-    consensus::PayloadValidationError,
+    consensus::{PayloadPermanentError, PayloadValidationError},
     ingress_manager::{IngressSelector, IngressSetQuery},
-    ingress_pool::IngressPoolSelect,
+    ingress_pool::{IngressPoolObject, IngressPoolSelect, SelectResult},
     messaging::{XNetPayloadBuilder, XNetPayloadError},
-    validation::ValidationResult,
+    validation::{ValidationError, ValidationResult},
 };
 use ic_metrics::MetricsRegistry;
 use ic_types::{
@@ -14,9 +14,13 @@ use ic_types::{
     batch::{BatchPayload, ValidationContext, XNetPayload},
     consensus::{BlockPayload, Payload},
     crypto::CryptoHashOf,
-    messages::MAX_XNET_PAYLOAD_IN_BYTES,
-    Height, Time,
+    messages::{
+        SignedIngress, MAX_CANISTER_HTTP_PAYLOAD_IN_BYTES, MAX_QUERY_STATS_PAYLOAD_IN_BYTES,
+        MAX_XNET_PAYLOAD_IN_BYTES,
+    },
+    CountBytes, Height, NumBytes, Time,
 };
+use prometheus::IntGauge;
 use std::collections::{BTreeMap, HashSet};
 use std::sync::{Arc, RwLock};
 
@@ -29,16 +33,19 @@ pub trait PayloadBuilder: Send + Sync {
     /// certified height provided in `context`, in descending block height
     /// order.
     ///
+    /// The ingress and xnet parts of the payload are at most as large as
+    /// given by `limits`.
+    ///
     /// It returns a `BatchPayload` if the payload building is success, or
-    /// an error if it fails (which at the moment is only a transient error from
-    /// building XNet payload, but will be extended in the future).
+    /// an error naming the section that is not ready yet.
     fn get_payload(
         &self,
         height: Height,
         ingress_pool: &dyn IngressPoolSelect,
         past_payloads: &[(Height, Time, Payload)],
         context: &ValidationContext,
-    ) -> Result<BatchPayload, XNetPayloadError>;
+        limits: &PayloadSizeLimits,
+    ) -> Result<BatchPayload, PayloadBuildingError>;
 
     /// Checks whether the provided `payload` is valid given `past_payloads` and
     /// `context`.
@@ -54,6 +61,64 @@ pub trait PayloadBuilder: Send + Sync {
     ) -> ValidationResult<PayloadValidationError>;
 }
 
+/// The maximum sizes of the parts of a payload that is built.
+/// Payloads are always validated against the maximum sizes allowed by the
+/// subnet.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PayloadSizeLimits {
+    /// The maximum size of the ingress messages in bytes, in addition to the
+    /// limits of the subnet record, or None if only the latter apply.
+    pub max_ingress_bytes: Option<usize>,
+    /// The maximum size of the xnet payload in bytes.
+    pub max_xnet_bytes: usize,
+    /// The maximum size of the responses to canister HTTP outcalls in bytes.
+    pub max_canister_http_bytes: usize,
+    /// The maximum size of the query statistics reports in bytes.
+    pub max_query_stats_bytes: usize,
+}
+
+impl Default for PayloadSizeLimits {
+    fn default() -> Self {
+        Self {
+            max_ingress_bytes: None,
+            max_xnet_bytes: MAX_XNET_PAYLOAD_IN_BYTES.get() as usize,
+            max_canister_http_bytes: MAX_CANISTER_HTTP_PAYLOAD_IN_BYTES.get() as usize,
+            max_query_stats_bytes: MAX_QUERY_STATS_PAYLOAD_IN_BYTES.get() as usize,
+        }
+    }
+}
+
+/// An ingress pool that skips all messages that would make the selected
+/// messages larger than a byte limit.
+struct SizeLimitedIngressPool<'a> {
+    ingress_pool: &'a dyn IngressPoolSelect,
+    max_bytes: usize,
+}
+
+impl<'a> IngressPoolSelect for SizeLimitedIngressPool<'a> {
+    fn select_validated<'b>(
+        &self,
+        range: std::ops::RangeInclusive<Time>,
+        mut f: Box<dyn FnMut(&IngressPoolObject) -> SelectResult<SignedIngress> + 'b>,
+    ) -> Vec<SignedIngress> {
+        let max_bytes = self.max_bytes;
+        let mut selected_bytes = 0;
+        self.ingress_pool.select_validated(
+            range,
+            Box::new(move |object| {
+                if selected_bytes + object.count_bytes() > max_bytes {
+                    return SelectResult::Skip;
+                }
+                let result = f(object);
+                if let SelectResult::Selected(_) = result {
+                    selected_bytes += object.count_bytes();
+                }
+                result
+            }),
+        )
+    }
+}
+
 /// Cache of sets of message ids for past payloads. The index used here is a
 /// tuple (Height, HashOfBatchPayload) for two reasons:
 /// 1. We want to purge this cache by height, for those below certified height.
@@ -87,26 +152,69 @@ impl IngressSetQuery for IngressSets {
     }
 }
 
-/// Implementation of PayloadBuilder.
+/// Possible errors in building a payload.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PayloadBuildingError {
+    /// Building the section with the given name has started, but the result
+    /// is not ready yet.
+    Pending(&'static str),
+}
+
+/// Builds and validates one section of the batch payload, e.g. the ingress
+/// messages or the xnet streams.
+///
+/// The [PayloadBuilderImpl] calls its sections in the order they are given,
+/// so sections whose building may fail because they are not ready yet should
+/// come first.
+///
+/// Sections without a dedicated field in the `BatchPayload` store their
+/// content in `BatchPayload::sections` under their name, encoded by the
+/// section itself, so that a section can be added without changing the
+/// format of the payload. Payloads with sections that no builder knows are
+/// invalid.
+pub trait PayloadSectionBuilder: Send + Sync {
+    /// The name of the section, used as a metrics label and as the key of
+    /// the section in `BatchPayload::sections`.
+    fn name(&self) -> &'static str;
+
+    /// Adds the section to the `payload` of the block at `height`.
+    ///
+    /// `past_payloads` contains the `Payloads` from all blocks above the
+    /// certified height provided in `context`, in descending block height
+    /// order.
+    fn build_section(
+        &self,
+        payload: &mut BatchPayload,
+        height: Height,
+        ingress_pool: &dyn IngressPoolSelect,
+        past_payloads: &[(Height, Time, Payload)],
+        context: &ValidationContext,
+        limits: &PayloadSizeLimits,
+    ) -> Result<(), PayloadBuildingError>;
+
+    /// Checks whether the section of the provided `payload` is valid given
+    /// `past_payloads` and `context`.
+    fn validate_section(
+        &self,
+        payload: &BatchPayload,
+        past_payloads: &[(Height, Time, Payload)],
+        context: &ValidationContext,
+    ) -> ValidationResult<PayloadValidationError>;
+}
+
+/// Implementation of PayloadBuilder that builds the payload from a list of
+/// sections.
 pub struct PayloadBuilderImpl {
-    ingress_selector: Arc<dyn IngressSelector>,
-    xnet_payload_builder: Arc<dyn XNetPayloadBuilder>,
+    sections: Vec<Arc<dyn PayloadSectionBuilder>>,
     metrics: PayloadBuilderMetrics,
-    ingress_payload_cache: RwLock<IngressPayloadCache>,
 }
 
 impl PayloadBuilderImpl {
     /// Helper to create PayloadBuilder
-    pub fn new(
-        ingress_selector: Arc<dyn IngressSelector>,
-        xnet_payload_builder: Arc<dyn XNetPayloadBuilder>,
-        metrics: MetricsRegistry,
-    ) -> Self {
+    pub fn new(sections: Vec<Arc<dyn PayloadSectionBuilder>>, metrics: MetricsRegistry) -> Self {
         Self {
-            ingress_selector,
-            xnet_payload_builder,
+            sections,
             metrics: PayloadBuilderMetrics::new(metrics),
-            ingress_payload_cache: RwLock::new(BTreeMap::new()),
         }
     }
 }
@@ -118,37 +226,30 @@ impl PayloadBuilder for PayloadBuilderImpl {
         ingress_pool: &dyn IngressPoolSelect,
         past_payloads: &[(Height, Time, Payload)],
         context: &ValidationContext,
-    ) -> Result<BatchPayload, XNetPayloadError> {
+        limits: &PayloadSizeLimits,
+    ) -> Result<BatchPayload, PayloadBuildingError> {
         let _timer = self.metrics.get_payload_duration.start_timer();
-        let mut ingress_payload_cache = self.ingress_payload_cache.write().unwrap();
-        let min_block_time = match past_payloads.last() {
-            None => context.time,
-            Some((_, time, _)) => *time,
-        };
-        let (past_ingress, past_xnet) =
-            split_past_payloads(&mut ingress_payload_cache, past_payloads);
-
-        // Try making xnet payload first, since it may not be ready.
-        let xnet = self.xnet_payload_builder.get_xnet_payload(
-            height,
-            context,
-            &past_xnet,
-            MAX_XNET_PAYLOAD_IN_BYTES,
-        )?;
-
         self.metrics
             .past_payloads_length
             .observe(past_payloads.len() as f64);
-        let ingress_query = IngressSets::new(past_ingress, min_block_time);
-        let ingress =
-            self.ingress_selector
-                .get_ingress_payload(ingress_pool, &ingress_query, context);
-
-        self.metrics
-            .ingress_payload_cache_size
-            .set(ingress_payload_cache.len() as i64);
 
-        Ok(BatchPayload { ingress, xnet })
+        let mut payload = BatchPayload::default();
+        for section in self.sections.iter() {
+            let _timer = self
+                .metrics
+                .section_duration
+                .with_label_values(&[section.name(), "build"])
+                .start_timer();
+            section.build_section(
+                &mut payload,
+                height,
+                ingress_pool,
+                past_payloads,
+                context,
+                limits,
+            )?;
+        }
+        Ok(payload)
     }
 
     fn validate_payload(
@@ -162,45 +263,188 @@ impl PayloadBuilder for PayloadBuilderImpl {
             return Ok(());
         }
         let batch_payload = payload.as_ref().as_batch_payload();
+        if let Some(name) = batch_payload
+            .sections
+            .keys()
+            .find(|name| !self.sections.iter().any(|section| section.name() == *name))
+        {
+            return Err(ValidationError::Permanent(
+                PayloadPermanentError::UnknownPayloadSection(name.clone()),
+            ));
+        }
+        for section in self.sections.iter() {
+            let _timer = self
+                .metrics
+                .section_duration
+                .with_label_values(&[section.name(), "validate"])
+                .start_timer();
+            section.validate_section(batch_payload, past_payloads, context)?;
+        }
+        Ok(())
+    }
+}
+
+/// The section of the ingress messages.
+pub struct IngressSectionBuilder {
+    ingress_selector: Arc<dyn IngressSelector>,
+    ingress_payload_cache: RwLock<IngressPayloadCache>,
+    ingress_payload_cache_size: IntGauge,
+}
+
+impl IngressSectionBuilder {
+    /// Create the ingress section using the given ingress selector.
+    pub fn new(ingress_selector: Arc<dyn IngressSelector>, metrics: &MetricsRegistry) -> Self {
+        Self {
+            ingress_selector,
+            ingress_payload_cache: RwLock::new(BTreeMap::new()),
+            ingress_payload_cache_size: metrics.int_gauge(
+                "ingress_payload_cache_size",
+                "The number of HashSets in payload builder's ingress payload cache.",
+            ),
+        }
+    }
+
+    /// Return the ingress messages of `past_payloads`, which have to be
+    /// excluded from a new payload.
+    fn past_ingress(
+        &self,
+        past_payloads: &[(Height, Time, Payload)],
+        context: &ValidationContext,
+    ) -> IngressSets {
         let mut ingress_payload_cache = self.ingress_payload_cache.write().unwrap();
         let min_block_time = match past_payloads.last() {
             None => context.time,
             Some((_, time, _)) => *time,
         };
-        let (past_ingress, past_xnet) =
-            split_past_payloads(&mut ingress_payload_cache, past_payloads);
-        self.metrics
-            .ingress_payload_cache_size
+        let past_ingress = past_ingress_sets(&mut ingress_payload_cache, past_payloads);
+        self.ingress_payload_cache_size
             .set(ingress_payload_cache.len() as i64);
+        IngressSets::new(past_ingress, min_block_time)
+    }
+}
 
-        let ingress_query = IngressSets::new(past_ingress, min_block_time);
+impl PayloadSectionBuilder for IngressSectionBuilder {
+    fn name(&self) -> &'static str {
+        "ingress"
+    }
 
-        // If ingress valiation is not valid, return it early.
+    fn build_section(
+        &self,
+        payload: &mut BatchPayload,
+        _height: Height,
+        ingress_pool: &dyn IngressPoolSelect,
+        past_payloads: &[(Height, Time, Payload)],
+        context: &ValidationContext,
+        limits: &PayloadSizeLimits,
+    ) -> Result<(), PayloadBuildingError> {
+        let ingress_query = self.past_ingress(past_payloads, context);
+        payload.ingress = match limits.max_ingress_bytes {
+            Some(max_bytes) => self.ingress_selector.get_ingress_payload(
+                &SizeLimitedIngressPool {
+                    ingress_pool,
+                    max_bytes,
+                },
+                &ingress_query,
+                context,
+            ),
+            None => {
+                self.ingress_selector
+                    .get_ingress_payload(ingress_pool, &ingress_query, context)
+            }
+        };
+        Ok(())
+    }
+
+    fn validate_section(
+        &self,
+        payload: &BatchPayload,
+        past_payloads: &[(Height, Time, Payload)],
+        context: &ValidationContext,
+    ) -> ValidationResult<PayloadValidationError> {
+        let ingress_query = self.past_ingress(past_payloads, context);
         self.ingress_selector.validate_ingress_payload(
-            &batch_payload.ingress,
+            &payload.ingress,
             &ingress_query,
             context,
         )?;
+        Ok(())
+    }
+}
 
+/// The section of the xnet streams.
+pub struct XNetSectionBuilder {
+    xnet_payload_builder: Arc<dyn XNetPayloadBuilder>,
+}
+
+impl XNetSectionBuilder {
+    /// Create the xnet section using the given xnet payload builder.
+    pub fn new(xnet_payload_builder: Arc<dyn XNetPayloadBuilder>) -> Self {
+        Self {
+            xnet_payload_builder,
+        }
+    }
+}
+
+impl PayloadSectionBuilder for XNetSectionBuilder {
+    fn name(&self) -> &'static str {
+        "xnet"
+    }
+
+    fn build_section(
+        &self,
+        payload: &mut BatchPayload,
+        height: Height,
+        _ingress_pool: &dyn IngressPoolSelect,
+        past_payloads: &[(Height, Time, Payload)],
+        context: &ValidationContext,
+        limits: &PayloadSizeLimits,
+    ) -> Result<(), PayloadBuildingError> {
+        payload.xnet = self
+            .xnet_payload_builder
+            .get_xnet_payload(
+                height,
+                context,
+                &past_xnet_payloads(past_payloads),
+                NumBytes::new(limits.max_xnet_bytes as u64).min(MAX_XNET_PAYLOAD_IN_BYTES),
+            )
+            .map_err(|err| match err {
+                XNetPayloadError::Pending => PayloadBuildingError::Pending(self.name()),
+            })?;
+        Ok(())
+    }
+
+    fn validate_section(
+        &self,
+        payload: &BatchPayload,
+        past_payloads: &[(Height, Time, Payload)],
+        context: &ValidationContext,
+    ) -> ValidationResult<PayloadValidationError> {
         self.xnet_payload_builder.validate_xnet_payload(
-            &batch_payload.xnet,
+            &payload.xnet,
             context,
-            &past_xnet,
+            &past_xnet_payloads(past_payloads),
             MAX_XNET_PAYLOAD_IN_BYTES,
         )?;
-
         Ok(())
     }
 }
 
-/// Split past_payloads into past_ingress and past_xnet payloads. The
-/// past_ingress is actually a list of HashSet of MessageIds taken from the
-/// ingress_payload_cache.
-fn split_past_payloads<'a, 'b>(
-    ingress_payload_cache: &'a mut IngressPayloadCache,
-    past_payloads: &'b [(Height, Time, Payload)],
-) -> (Vec<Arc<HashSet<IngressMessageId>>>, Vec<&'b XNetPayload>) {
-    let past_xnet: Vec<_> = past_payloads
+/// Return the default payload sections, i.e. the xnet streams followed by the
+/// ingress messages. The xnet section comes first, since it may not be ready.
+pub fn default_sections(
+    ingress_selector: Arc<dyn IngressSelector>,
+    xnet_payload_builder: Arc<dyn XNetPayloadBuilder>,
+    metrics: &MetricsRegistry,
+) -> Vec<Arc<dyn PayloadSectionBuilder>> {
+    vec![
+        Arc::new(XNetSectionBuilder::new(xnet_payload_builder)),
+        Arc::new(IngressSectionBuilder::new(ingress_selector, metrics)),
+    ]
+}
+
+/// Return the xnet payloads of past_payloads.
+fn past_xnet_payloads(past_payloads: &[(Height, Time, Payload)]) -> Vec<&XNetPayload> {
+    past_payloads
         .iter()
         .filter_map(|(_, _, payload)| {
             if payload.is_summary() {
@@ -209,7 +453,15 @@ fn split_past_payloads<'a, 'b>(
                 Some(&payload.as_ref().as_batch_payload().xnet)
             }
         })
-        .collect();
+        .collect()
+}
+
+/// Return the ingress message ids of past_payloads as a list of HashSets taken
+/// from the ingress_payload_cache.
+fn past_ingress_sets(
+    ingress_payload_cache: &mut IngressPayloadCache,
+    past_payloads: &[(Height, Time, Payload)],
+) -> Vec<Arc<HashSet<IngressMessageId>>> {
     let past_ingress: Vec<_> = past_payloads
         .iter()
         .filter_map(|(height, _, payload)| {
@@ -242,7 +494,7 @@ fn split_past_payloads<'a, 'b>(
             }
         }
     }
-    (past_ingress, past_xnet)
+    past_ingress
 }
 
 #[cfg(test)]
@@ -276,8 +528,10 @@ mod test {
 
             let ingress_selector = Arc::new(ingress_selector);
             let xnet_payload_builder = Arc::new(xnet_payload_builder);
-            let payload_builder =
-                PayloadBuilderImpl::new(ingress_selector, xnet_payload_builder, metrics_registry);
+            let payload_builder = PayloadBuilderImpl::new(
+                default_sections(ingress_selector, xnet_payload_builder, &metrics_registry),
+                metrics_registry,
+            );
 
             let prev_payloads = Vec::new();
             let context = ValidationContext {
@@ -287,7 +541,13 @@ mod test {
             };
 
             let (ingress_msgs, stream_msgs) = payload_builder
-                .get_payload(Height::from(1), &ingress_pool, &prev_payloads, &context)
+                .get_payload(
+                    Height::from(1),
+                    &ingress_pool,
+                    &prev_payloads,
+                    &context,
+                    &PayloadSizeLimits::default(),
+                )
                 .unwrap()
                 .into_messages()
                 .unwrap();
@@ -347,4 +607,90 @@ mod test {
             }
         }
     }
+
+    /// A section that stores its content in `BatchPayload::sections`.
+    struct FutureSectionBuilder;
+
+    impl PayloadSectionBuilder for FutureSectionBuilder {
+        fn name(&self) -> &'static str {
+            "future"
+        }
+
+        fn build_section(
+            &self,
+            payload: &mut BatchPayload,
+            _height: Height,
+            _ingress_pool: &dyn IngressPoolSelect,
+            _past_payloads: &[(Height, Time, Payload)],
+            _context: &ValidationContext,
+            _limits: &PayloadSizeLimits,
+        ) -> Result<(), PayloadBuildingError> {
+            payload.sections.insert(self.name().to_string(), vec![1]);
+            Ok(())
+        }
+
+        fn validate_section(
+            &self,
+            _payload: &BatchPayload,
+            _past_payloads: &[(Height, Time, Payload)],
+            _context: &ValidationContext,
+        ) -> ValidationResult<PayloadValidationError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_unknown_sections_are_invalid() {
+        ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
+            let ingress_pool = TestIngressPool::new(pool_config);
+            let context = ValidationContext {
+                certified_height: Height::from(0),
+                registry_version: RegistryVersion::from(1),
+                time: mock_time(),
+            };
+            let sections: Vec<Arc<dyn PayloadSectionBuilder>> =
+                vec![Arc::new(FutureSectionBuilder)];
+            let batch_payload = PayloadBuilderImpl::new(sections, MetricsRegistry::new())
+                .get_payload(
+                    Height::from(1),
+                    &ingress_pool,
+                    &[],
+                    &context,
+                    &PayloadSizeLimits::default(),
+                )
+                .unwrap();
+            assert_eq!(batch_payload.sections.get("future"), Some(&vec![1]));
+            let payload = Payload::new(
+                ic_crypto::crypto_hash,
+                (
+                    batch_payload,
+                    consensus::dkg::Dealings::new_empty(Height::from(0)),
+                )
+                    .into(),
+            );
+
+            // A builder with the section accepts the payload, one without it
+            // rejects it.
+            let sections: Vec<Arc<dyn PayloadSectionBuilder>> =
+                vec![Arc::new(FutureSectionBuilder)];
+            assert!(PayloadBuilderImpl::new(sections, MetricsRegistry::new())
+                .validate_payload(&payload, &[], &context)
+                .is_ok());
+            let metrics_registry = MetricsRegistry::new();
+            let payload_builder = PayloadBuilderImpl::new(
+                default_sections(
+                    Arc::new(FakeIngressSelector::new()),
+                    Arc::new(FakeXNetPayloadBuilder::new()),
+                    &metrics_registry,
+                ),
+                metrics_registry,
+            );
+            assert!(matches!(
+                payload_builder.validate_payload(&payload, &[], &context),
+                Err(ValidationError::Permanent(
+                    PayloadPermanentError::UnknownPayloadSection(name)
+                )) if name == "future"
+            ));
+        })
+    }
 }
@@ -0,0 +1,152 @@
+//! The payload size controller scales the ingress and xnet payload sizes of
+//! the blocks made by this replica to the load of the subnet.
+//!
+//! The load is estimated from the round time, i.e. the time between the
+//! parent block and the new block, and from the execution backlog, i.e. the
+//! number of heights that have been proposed but not executed yet. While the
+//! subnet is overloaded, the payload sizes are halved for every block made, and
+//! otherwise they grow again by a fixed step, within the configured bounds.
+//!
+//! The controller only limits the payloads this replica builds. Payloads are
+//! still validated against the maximum sizes, so replicas using different
+//! limits agree on the validity of all blocks.
+use ic_config::consensus::AdaptivePayloadSizeConfig;
+use ic_types::{Height, Time};
+use std::time::Duration;
+
+/// The percentage points by which the payload sizes grow per block while the
+/// subnet is not overloaded.
+const ADDITIVE_INCREASE_PERCENT: u64 = 10;
+
+/// The weight of a new round time in the average round time, as a fraction
+/// 1 / ROUND_TIME_SMOOTHING.
+const ROUND_TIME_SMOOTHING: u32 = 4;
+
+/// Tracks the load of the subnet and computes the payload sizes of new blocks.
+pub(crate) struct PayloadSizeController {
+    config: AdaptivePayloadSizeConfig,
+    payload_percent: u64,
+    average_round_time: Option<Duration>,
+}
+
+impl PayloadSizeController {
+    pub(crate) fn new(config: AdaptivePayloadSizeConfig) -> Self {
+        let min_payload_percent = config.min_payload_percent.min(100);
+        let config = AdaptivePayloadSizeConfig {
+            min_payload_percent,
+            max_payload_percent: config.max_payload_percent.max(min_payload_percent).min(100),
+            ..config
+        };
+        Self {
+            payload_percent: config.max_payload_percent,
+            config,
+            average_round_time: None,
+        }
+    }
+
+    /// Records the load of the subnet when making a block at `height` at time
+    /// `now` on top of a parent block made at `parent_time`, while the latest
+    /// executed state is at `executed_height`. Returns the percentage of the
+    /// maximum payload sizes the block should use.
+    pub(crate) fn update(
+        &mut self,
+        height: Height,
+        now: Time,
+        parent_time: Time,
+        executed_height: Height,
+    ) -> u64 {
+        let round_time = if now > parent_time {
+            now - parent_time
+        } else {
+            Duration::from_secs(0)
+        };
+        let average_round_time = match self.average_round_time {
+            None => round_time,
+            Some(average) => {
+                (average * (ROUND_TIME_SMOOTHING - 1) + round_time) / ROUND_TIME_SMOOTHING
+            }
+        };
+        self.average_round_time = Some(average_round_time);
+
+        let backlog = height.saturating_sub(executed_height.increment()).get();
+        let overloaded = average_round_time
+            > Duration::from_millis(self.config.target_round_time_ms)
+            || backlog > self.config.max_execution_backlog;
+        self.payload_percent = if overloaded {
+            (self.payload_percent / 2).max(self.config.min_payload_percent)
+        } else {
+            (self.payload_percent + ADDITIVE_INCREASE_PERCENT).min(self.config.max_payload_percent)
+        };
+        self.payload_percent
+    }
+}
+
+/// Returns the given percentage of the given size.
+pub(crate) fn scale(size: usize, percent: u64) -> usize {
+    (size as u128 * percent as u128 / 100) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_test_utilities::mock_time;
+
+    fn config() -> AdaptivePayloadSizeConfig {
+        AdaptivePayloadSizeConfig {
+            min_payload_percent: 20,
+            max_payload_percent: 100,
+            target_round_time_ms: 1000,
+            max_execution_backlog: 5,
+        }
+    }
+
+    #[test]
+    fn test_payload_size_controller() {
+        let mut controller = PayloadSizeController::new(config());
+        let fast = mock_time() + Duration::from_millis(500);
+        let slow = mock_time() + Duration::from_secs(10);
+
+        // No load keeps the maximum.
+        assert_eq!(
+            controller.update(Height::from(2), fast, mock_time(), Height::from(1)),
+            100
+        );
+        // An execution backlog halves the payload sizes down to the minimum.
+        assert_eq!(
+            controller.update(Height::from(10), fast, mock_time(), Height::from(1)),
+            50
+        );
+        assert_eq!(
+            controller.update(Height::from(11), fast, mock_time(), Height::from(1)),
+            25
+        );
+        assert_eq!(
+            controller.update(Height::from(12), fast, mock_time(), Height::from(1)),
+            20
+        );
+        // Once the backlog is gone, the payload sizes grow again.
+        assert_eq!(
+            controller.update(Height::from(13), fast, mock_time(), Height::from(12)),
+            30
+        );
+        // Slow rounds are overload, too.
+        assert_eq!(
+            controller.update(Height::from(14), slow, mock_time(), Height::from(13)),
+            20
+        );
+    }
+
+    #[test]
+    fn test_payload_size_controller_bounds() {
+        let mut controller = PayloadSizeController::new(AdaptivePayloadSizeConfig {
+            min_payload_percent: 50,
+            max_payload_percent: 30,
+            ..config()
+        });
+        assert_eq!(
+            controller.update(Height::from(2), mock_time(), mock_time(), Height::from(1)),
+            50
+        );
+        assert_eq!(scale(1000, 50), 500);
+    }
+}
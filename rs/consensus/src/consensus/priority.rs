@@ -89,7 +89,7 @@ fn update_block_set<T: HasHeight + HasBlockHash>(
 ) {
     let _timer = histogram.start_timer();
     if let Some(max_height) = pool_section.max_height() {
-        let range = HeightRange::new(finalized_height.increment(), max_height);
+        let range = HeightRange::above(finalized_height, max_height);
         for obj in pool_section.get_by_height_range(range) {
             let hash = obj.block_hash();
             block_set.insert(hash.clone());
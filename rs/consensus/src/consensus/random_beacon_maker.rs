@@ -1,21 +1,29 @@
 //! Random beacon maker is responsible for creating random beacon share
 //! for the node if the node is a beacon maker and no such share exists.
 use crate::consensus::{
+    async_crypto::{
+        AsyncCrypto, AsyncSignVerify, CryptoThreadPool, PendingSignatures, MAX_SIGNING_WAIT,
+    },
     membership::{Membership, MembershipError},
     pool_reader::PoolReader,
     prelude::*,
+    random_beacon_precomputer::RandomBeaconPrecomputer,
     utils::active_low_threshold_transcript,
     ConsensusCrypto,
 };
 use ic_logger::{error, trace, ReplicaLogger};
 use ic_types::replica_config::ReplicaConfig;
 use std::sync::Arc;
+use std::time::Instant;
 
 /// Random beacon maker is responsible for creating beacon shares
 pub struct RandomBeaconMaker {
     replica_config: ReplicaConfig,
     membership: Arc<Membership>,
-    crypto: Arc<dyn ConsensusCrypto>,
+    crypto: AsyncCrypto<dyn ConsensusCrypto>,
+    precomputer: RandomBeaconPrecomputer,
+    pending_signatures:
+        PendingSignatures<RandomBeaconContent, ThresholdSignatureShare<RandomBeaconContent>>,
     log: ReplicaLogger,
 }
 
@@ -25,21 +33,38 @@ impl<'a> RandomBeaconMaker {
         replica_config: ReplicaConfig,
         membership: Arc<Membership>,
         crypto: Arc<dyn ConsensusCrypto>,
+        crypto_pool: CryptoThreadPool,
         log: ReplicaLogger,
     ) -> Self {
+        let crypto = AsyncCrypto::new(crypto, crypto_pool);
         Self {
+            precomputer: RandomBeaconPrecomputer::new(
+                replica_config.clone(),
+                membership.clone(),
+                crypto.clone(),
+                log.clone(),
+            ),
             replica_config,
             membership,
             crypto,
+            pending_signatures: PendingSignatures::new(),
             log,
         }
     }
 
+    /// Precompute the next beacon share if the given change set, which is
+    /// about to be applied to the pool, contains a new random beacon.
+    pub fn on_change_set(&self, pool: &PoolReader<'_>, change_set: &[ChangeAction]) {
+        self.precomputer.on_change_set(pool, change_set)
+    }
+
     /// If a beacon share should be proposed, propose it.
     pub fn on_state_change(&self, pool: &PoolReader<'_>) -> Option<RandomBeaconShare> {
         trace!(self.log, "on_state_change");
         let my_node_id = self.replica_config.node_id;
         let height = pool.get_notarized_height();
+        self.pending_signatures
+            .retain(|content| content.height > height);
         let beacon = pool.get_random_beacon(height)?;
         let next_height = height.increment();
         let next_beacon = pool.get_random_beacon(next_height);
@@ -72,6 +97,9 @@ impl<'a> RandomBeaconMaker {
             {
                 let content =
                     RandomBeaconContent::new(next_height, ic_crypto::crypto_hash(&beacon));
+                if let Some(share) = self.precomputer.take(&content) {
+                    return Some(share);
+                }
                 // One might wonder whether it is appropriate to use the
                 // dkg_id from the start_block at h to generate the
                 // random beacon at height h. The reason this is
@@ -82,7 +110,14 @@ impl<'a> RandomBeaconMaker {
                 if let Some(transcript) =
                     active_low_threshold_transcript(pool.as_cache(), next_height)
                 {
-                    match self.crypto.sign(&content, my_node_id, transcript.dkg_id) {
+                    // If the signature does not complete in time, it is kept
+                    // pending and a later invocation returns the share.
+                    self.pending_signatures.start(&content, || {
+                        self.crypto
+                            .sign_async(content.clone(), my_node_id, transcript.dkg_id)
+                    });
+                    let deadline = Instant::now() + MAX_SIGNING_WAIT;
+                    match self.pending_signatures.take_before(&content, deadline)? {
                         Ok(signature) => Some(RandomBeaconShare { content, signature }),
                         Err(err) => {
                             error!(self.log, "Couldn't create a signature: {:?}", err);
@@ -121,8 +156,13 @@ mod tests {
                 ..
             } = dependencies(pool_config, 1);
 
-            let beacon_maker =
-                RandomBeaconMaker::new(replica_config, membership, crypto, no_op_logger());
+            let beacon_maker = RandomBeaconMaker::new(
+                replica_config,
+                membership,
+                crypto,
+                CryptoThreadPool::new(1),
+                no_op_logger(),
+            );
 
             // 1. Make the next beacon share
             let beacon_share = beacon_maker
@@ -0,0 +1,178 @@
+//! The random beacon precomputer speculatively signs the next random beacon
+//! share on the crypto thread pool as soon as a random beacon moves to the
+//! validated pool.
+//!
+//! The share at height `h + 1` only depends on the random beacon at height
+//! `h`, but the random beacon maker can only propose it after a block at
+//! height `h` is notarized. By signing the share while the block is being
+//! proposed and notarized, the beacon maker does not have to sign on the
+//! critical path of the round.
+use crate::consensus::{
+    async_crypto::{AsyncCrypto, AsyncSignVerify, CryptoFuture},
+    membership::Membership,
+    pool_reader::PoolReader,
+    prelude::*,
+    utils::active_low_threshold_transcript,
+    ConsensusCrypto,
+};
+use ic_logger::{trace, ReplicaLogger};
+use ic_types::{crypto::CryptoResult, replica_config::ReplicaConfig};
+use std::cell::RefCell;
+use std::sync::Arc;
+
+/// A random beacon share whose signature is computed on the crypto thread
+/// pool.
+struct PrecomputedShare {
+    content: RandomBeaconContent,
+    signature: CryptoFuture<CryptoResult<ThresholdSignatureShare<RandomBeaconContent>>>,
+}
+
+/// Precomputes the random beacon shares of this node.
+pub(crate) struct RandomBeaconPrecomputer {
+    replica_config: ReplicaConfig,
+    membership: Arc<Membership>,
+    crypto: AsyncCrypto<dyn ConsensusCrypto>,
+    pending: RefCell<Option<PrecomputedShare>>,
+    log: ReplicaLogger,
+}
+
+impl RandomBeaconPrecomputer {
+    pub(crate) fn new(
+        replica_config: ReplicaConfig,
+        membership: Arc<Membership>,
+        crypto: AsyncCrypto<dyn ConsensusCrypto>,
+        log: ReplicaLogger,
+    ) -> Self {
+        Self {
+            replica_config,
+            membership,
+            crypto,
+            pending: RefCell::new(None),
+            log,
+        }
+    }
+
+    /// Start signing the share of the next random beacon if the given change
+    /// set moves a random beacon to the validated pool.
+    pub(crate) fn on_change_set(&self, pool: &PoolReader<'_>, change_set: &[ChangeAction]) {
+        let beacon = change_set
+            .iter()
+            .filter_map(|action| match action {
+                ChangeAction::AddToValidated(ConsensusMessage::RandomBeacon(beacon))
+                | ChangeAction::MoveToValidated(ConsensusMessage::RandomBeacon(beacon)) => {
+                    Some(beacon)
+                }
+                _ => None,
+            })
+            .max_by_key(|beacon| beacon.height());
+        if let Some(beacon) = beacon {
+            self.precompute(pool, beacon);
+        }
+    }
+
+    fn precompute(&self, pool: &PoolReader<'_>, beacon: &RandomBeacon) {
+        let my_node_id = self.replica_config.node_id;
+        let next_height = beacon.height().increment();
+        let mut pending = self.pending.borrow_mut();
+        if pending
+            .as_ref()
+            .map_or(false, |share| share.content.height >= next_height)
+        {
+            return;
+        }
+        match self.membership.node_belongs_to_threshold_committee(
+            my_node_id,
+            next_height,
+            RandomBeacon::committee(),
+        ) {
+            Ok(true) => (),
+            // Errors are handled by the random beacon maker, which signs the
+            // share itself if it was not precomputed.
+            _ => return,
+        }
+        let dkg_id = match active_low_threshold_transcript(pool.as_cache(), next_height) {
+            Some(transcript) => transcript.dkg_id,
+            None => return,
+        };
+
+        trace!(
+            self.log,
+            "Precomputing the random beacon share at height {:?}",
+            next_height
+        );
+        let content = RandomBeaconContent::new(next_height, ic_crypto::crypto_hash(beacon));
+        *pending = Some(PrecomputedShare {
+            signature: self.crypto.sign_async(content.clone(), my_node_id, dkg_id),
+            content,
+        });
+    }
+
+    /// Return the precomputed share with the given content if it is ready.
+    pub(crate) fn take(&self, content: &RandomBeaconContent) -> Option<RandomBeaconShare> {
+        let mut pending = self.pending.borrow_mut();
+        let signature = pending
+            .as_mut()
+            .filter(|share| share.content.height == content.height)?
+            .signature
+            .try_take()?;
+        let share = pending.take()?;
+        if &share.content != content {
+            return None;
+        }
+        signature.ok().map(|signature| RandomBeaconShare {
+            content: share.content,
+            signature,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::async_crypto::CryptoThreadPool;
+    use crate::consensus::mocks::{dependencies, Dependencies};
+    use ic_logger::replica_logger::no_op_logger;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_random_beacon_precomputer() {
+        ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
+            let Dependencies {
+                mut pool,
+                membership,
+                replica_config,
+                crypto,
+                ..
+            } = dependencies(pool_config, 1);
+            let precomputer = RandomBeaconPrecomputer::new(
+                replica_config,
+                membership,
+                AsyncCrypto::new(crypto, CryptoThreadPool::new(1)),
+                no_op_logger(),
+            );
+
+            let beacon = pool.make_next_beacon();
+            let content = RandomBeaconContent::new(
+                beacon.height().increment(),
+                ic_crypto::crypto_hash(&beacon),
+            );
+            let change_set = vec![ChangeAction::AddToValidated(beacon.clone().into_message())];
+
+            // Nothing is precomputed before the beacon moves to the validated pool.
+            assert!(precomputer.take(&content).is_none());
+            precomputer.on_change_set(&PoolReader::new(&pool), &change_set);
+
+            let deadline = Instant::now() + Duration::from_secs(10);
+            let share = loop {
+                if let Some(share) = precomputer.take(&content) {
+                    break share;
+                }
+                assert!(Instant::now() < deadline, "Expecting RandomBeaconShare");
+                std::thread::sleep(Duration::from_millis(10));
+            };
+            assert_eq!(share.content, content);
+            // The share is only returned once.
+            assert!(precomputer.take(&content).is_none());
+        })
+    }
+}
@@ -23,6 +23,9 @@
 //! random tape is delivered.
 
 use crate::consensus::{
+    async_crypto::{
+        AsyncCrypto, AsyncSignVerify, CryptoThreadPool, PendingSignatures, MAX_SIGNING_WAIT,
+    },
     membership::{Membership, MembershipError},
     pool_reader::PoolReader,
     prelude::*,
@@ -34,12 +37,15 @@ use ic_logger::{error, trace, ReplicaLogger};
 use ic_types::replica_config::ReplicaConfig;
 use std::cmp::max;
 use std::sync::Arc;
+use std::time::Instant;
 
 pub struct RandomTapeMaker {
     replica_config: ReplicaConfig,
     membership: Arc<Membership>,
-    crypto: Arc<dyn ConsensusCrypto>,
+    crypto: AsyncCrypto<dyn ConsensusCrypto>,
     message_routing: Arc<dyn MessageRouting>,
+    pending_signatures:
+        PendingSignatures<RandomTapeContent, ThresholdSignatureShare<RandomTapeContent>>,
     log: ReplicaLogger,
 }
 
@@ -49,14 +55,16 @@ impl RandomTapeMaker {
         replica_config: ReplicaConfig,
         membership: Arc<Membership>,
         crypto: Arc<dyn ConsensusCrypto>,
+        crypto_pool: CryptoThreadPool,
         message_routing: Arc<dyn MessageRouting>,
         log: ReplicaLogger,
     ) -> RandomTapeMaker {
         RandomTapeMaker {
             replica_config,
             membership,
-            crypto,
+            crypto: AsyncCrypto::new(crypto, crypto_pool),
             message_routing,
+            pending_signatures: PendingSignatures::new(),
             log,
         }
     }
@@ -113,19 +121,26 @@ impl RandomTapeMaker {
         true
     }
 
-    /// Construct a RandomTapeShare for the given height
+    /// Construct a RandomTapeShare for the given height, if the signature
+    /// completes before the `deadline`. Otherwise, the signature is kept
+    /// pending and a later invocation returns the share.
     fn create_random_tape_share(
         &self,
         height: Height,
         pool: &PoolReader<'_>,
+        deadline: Instant,
     ) -> Option<RandomTapeShare> {
         let content = RandomTapeContent::new(height);
 
         if let Some(transcript) = active_low_threshold_transcript(pool.as_cache(), height) {
-            match self
-                .crypto
-                .sign(&content, self.replica_config.node_id, transcript.dkg_id)
-            {
+            self.pending_signatures.start(&content, || {
+                self.crypto.sign_async(
+                    content.clone(),
+                    self.replica_config.node_id,
+                    transcript.dkg_id,
+                )
+            });
+            match self.pending_signatures.take_before(&content, deadline)? {
                 Ok(signature) => Some(RandomTapeShare { content, signature }),
                 Err(err) => {
                     error!(self.log, "Couldn't create a signature: {:?}", err);
@@ -156,13 +171,16 @@ impl RandomTapeMaker {
         // max(expected_batch_height, catch_up_package_height + 1) up to finalized
         // height + 1.
         let next_batch_height = max(
-            self.message_routing.expected_batch_height().get(),
-            pool.get_catch_up_height().get() + 1,
+            self.message_routing.expected_batch_height(),
+            pool.get_catch_up_height().increment(),
         );
-        let finalized_height = pool.get_finalized_height().get();
-        (next_batch_height..=finalized_height + 1)
-            .filter(|h| self.should_create_share(pool, Height::from(*h)))
-            .filter_map(|h| self.create_random_tape_share(Height::from(h), pool))
+        self.pending_signatures
+            .retain(|content| content.height >= next_batch_height);
+        let deadline = Instant::now() + MAX_SIGNING_WAIT;
+        HeightRange::new(next_batch_height, pool.get_finalized_height().increment())
+            .heights()
+            .filter(|h| self.should_create_share(pool, *h))
+            .filter_map(|h| self.create_random_tape_share(h, pool, deadline))
             .collect()
     }
 }
@@ -203,6 +221,7 @@ mod tests {
                 replica_config,
                 membership,
                 crypto,
+                CryptoThreadPool::new(1),
                 message_routing.clone(),
                 no_op_logger(),
             );
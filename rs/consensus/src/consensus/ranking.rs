@@ -0,0 +1,147 @@
+//! The functions that derive the block maker ranks of a round.
+//!
+//! The function a subnet uses is selected by the `block_maker_ranking` of its
+//! subnet record, so that alternative rankings can be evaluated on individual
+//! subnets without changing the rest of consensus. All nodes must agree on
+//! the ranks, so the function is read at the registry version of the height
+//! being ranked.
+use ic_crypto::{
+    crypto_hash,
+    prng::{Csprng, RandomnessPurpose},
+};
+use ic_crypto_sha256::{DomainSeparationContext, Sha256};
+use ic_protobuf::registry::subnet::v1::BlockMakerRanking;
+use ic_types::{consensus::RandomBeacon, NodeId};
+use rand::seq::SliceRandom;
+
+const NODE_HASH_RANKING_DOMAIN: &str = "ic-node-hash-block-maker-ranking";
+
+/// Orders the nodes of a subnet by their block maker rank.
+pub trait RankingFunction: Send + Sync {
+    /// Returns the given node IDs ordered by their rank at the height after
+    /// the one of `previous_beacon`, i.e. the node of rank 0 comes first. The
+    /// result must not depend on the order of `node_ids`.
+    fn rank_nodes(&self, previous_beacon: &RandomBeacon, node_ids: Vec<NodeId>) -> Vec<NodeId>;
+}
+
+/// Returns the ranking function selected by a subnet record.
+pub fn ranking_function(ranking: BlockMakerRanking) -> &'static dyn RankingFunction {
+    match ranking {
+        BlockMakerRanking::Unspecified | BlockMakerRanking::RandomBeacon => &RandomBeaconRanking,
+        BlockMakerRanking::NodeHash => &NodeHashRanking,
+    }
+}
+
+/// Returns the given node IDs in an order drawn from the random beacon for
+/// the given purpose.
+pub(crate) fn shuffle_nodes(
+    previous_beacon: &RandomBeacon,
+    mut node_ids: Vec<NodeId>,
+    purpose: &RandomnessPurpose,
+) -> Vec<NodeId> {
+    // To achieve a deterministic shuffling, we sort the ids first, to not rely on
+    // any ordering by the registry. We assume all node_ids are unique, so
+    // `sort_unstable` is effectively the same as `sort` but slightly more
+    // efficient.
+    node_ids.sort_unstable();
+    let mut rng = Csprng::from_random_beacon_and_purpose(previous_beacon, purpose);
+    node_ids.shuffle(&mut rng);
+    node_ids
+}
+
+/// Ranks the nodes by a permutation drawn from the random beacon. Everyone
+/// learns all ranks of a round as soon as the previous beacon is known.
+pub struct RandomBeaconRanking;
+
+impl RankingFunction for RandomBeaconRanking {
+    fn rank_nodes(&self, previous_beacon: &RandomBeacon, node_ids: Vec<NodeId>) -> Vec<NodeId> {
+        shuffle_nodes(
+            previous_beacon,
+            node_ids,
+            &RandomnessPurpose::BlockmakerRanking,
+        )
+    }
+}
+
+/// Experimental: ranks the nodes by the SHA-256 hash of the random beacon and
+/// their node ID, the lowest hash being rank 0.
+///
+/// This is not a VRF: anyone can compute the hashes, so the ranks are as
+/// public as those of `RandomBeaconRanking`. The ranking only allows to study
+/// how consensus behaves when the rank of each node is drawn independently,
+/// as it would be with a ranking by VRF outputs.
+pub struct NodeHashRanking;
+
+impl NodeHashRanking {
+    fn hash(previous_beacon: &RandomBeacon, node_id: &NodeId) -> [u8; 32] {
+        let mut hasher =
+            Sha256::new_with_context(&DomainSeparationContext::new(NODE_HASH_RANKING_DOMAIN));
+        hasher.write(&crypto_hash(previous_beacon).get_ref().0);
+        hasher.write(node_id.get().as_slice());
+        hasher.finish()
+    }
+}
+
+impl RankingFunction for NodeHashRanking {
+    fn rank_nodes(&self, previous_beacon: &RandomBeacon, mut node_ids: Vec<NodeId>) -> Vec<NodeId> {
+        // Ties are broken by the node ID, so that the order is total.
+        node_ids.sort_by_cached_key(|node_id| (Self::hash(previous_beacon, node_id), *node_id));
+        node_ids
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_test_utilities::{consensus::fake::FakeContent, types::ids::node_test_id};
+    use ic_types::{
+        consensus::RandomBeaconContent,
+        crypto::{CryptoHash, CryptoHashOf},
+        Height,
+    };
+
+    fn beacon(height: u64) -> RandomBeacon {
+        RandomBeacon::fake(RandomBeaconContent::new(
+            Height::from(height),
+            CryptoHashOf::from(CryptoHash(vec![])),
+        ))
+    }
+
+    #[test]
+    fn rankings_do_not_depend_on_the_order_of_the_nodes() {
+        let node_ids: Vec<_> = (0..10).map(node_test_id).collect();
+        let reversed: Vec<_> = node_ids.iter().rev().cloned().collect();
+        for ranking in &[BlockMakerRanking::RandomBeacon, BlockMakerRanking::NodeHash] {
+            let ranking = ranking_function(*ranking);
+            for height in 0..5 {
+                let ranked = ranking.rank_nodes(&beacon(height), node_ids.clone());
+                assert_eq!(
+                    ranking.rank_nodes(&beacon(height), reversed.clone()),
+                    ranked
+                );
+                let mut sorted = ranked.clone();
+                sorted.sort_unstable();
+                assert_eq!(sorted, node_ids);
+            }
+        }
+    }
+
+    #[test]
+    fn random_beacon_ranking_is_the_default() {
+        let node_ids: Vec<_> = (0..10).map(node_test_id).collect();
+        let beacon = beacon(3);
+        assert_eq!(
+            ranking_function(BlockMakerRanking::Unspecified).rank_nodes(&beacon, node_ids.clone()),
+            shuffle_nodes(&beacon, node_ids, &RandomnessPurpose::BlockmakerRanking)
+        );
+    }
+
+    #[test]
+    fn node_hash_ranks_change_with_the_beacon() {
+        let node_ids: Vec<_> = (0..10).map(node_test_id).collect();
+        let rankings: std::collections::BTreeSet<_> = (0..5)
+            .map(|height| NodeHashRanking.rank_nodes(&beacon(height), node_ids.clone()))
+            .collect();
+        assert!(rankings.len() > 1);
+    }
+}
@@ -0,0 +1,223 @@
+//! The remote DKG component publishes the requests and transcripts of the DKGs
+//! that the subnet runs for other subnets, e.g. to create or recover them, and
+//! validates the ones received from peers.
+//!
+//! Both are contained in the DKG summary block at the start of every interval:
+//! the requests are the configs of the remote DKGs, and the responses the
+//! transcripts computed in the previous interval. As every replica of the
+//! subnet has the same finalized summary, a message received from a peer is
+//! valid if and only if it is contained in the local summary. Messages of
+//! older summaries are purged, and messages of newer summaries are kept in the
+//! unvalidated section until the local summary catches up.
+use crate::consensus::prelude::*;
+use ic_interfaces::{
+    consensus_pool::ConsensusPoolCache,
+    remote_dkg::{
+        RemoteDkg, RemoteDkgChangeAction, RemoteDkgChangeSet, RemoteDkgGossip, RemoteDkgPool,
+    },
+};
+use ic_logger::{warn, ReplicaLogger};
+use ic_types::{
+    artifact::{Priority, PriorityFn, RemoteDkgMessageAttribute, RemoteDkgMessageId},
+    consensus::remote_dkg::{RemoteDkgMessage, RemoteDkgRequest, RemoteDkgResponse},
+    crypto::threshold_sig::ni_dkg::NiDkgTargetSubnet,
+};
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+
+/// Returns the remote DKG messages contained in the given DKG summary block.
+pub(crate) fn summary_messages(summary_block: &Block) -> Vec<RemoteDkgMessage> {
+    let summary = summary_block.payload.as_ref().as_summary();
+    let summary_height = summary_block.height;
+    let requests = summary
+        .configs
+        .values()
+        .filter(|config| config.dkg_id().target_subnet != NiDkgTargetSubnet::Local)
+        .map(|config| {
+            RemoteDkgMessage::Request(RemoteDkgRequest {
+                summary_height,
+                config: config.clone(),
+            })
+        });
+    let responses = summary
+        .transcripts_for_new_subnets()
+        .iter()
+        .map(|(dkg_id, transcript)| {
+            RemoteDkgMessage::Response(RemoteDkgResponse {
+                summary_height,
+                dkg_id: *dkg_id,
+                transcript: transcript.clone(),
+            })
+        });
+    requests.chain(responses).collect()
+}
+
+/// Implements the `RemoteDkg` trait.
+pub struct RemoteDkgImpl {
+    log: ReplicaLogger,
+}
+
+impl RemoteDkgImpl {
+    /// Build a new remote DKG component.
+    pub fn new(log: ReplicaLogger) -> Self {
+        Self { log }
+    }
+}
+
+impl RemoteDkg for RemoteDkgImpl {
+    fn on_state_change(
+        &self,
+        consensus_cache: &dyn ConsensusPoolCache,
+        remote_dkg_pool: &dyn RemoteDkgPool,
+    ) -> RemoteDkgChangeSet {
+        let summary_block = consensus_cache.summary_block();
+        let summary_height = summary_block.height;
+        let mut expected: BTreeMap<RemoteDkgMessageId, RemoteDkgMessage> =
+            summary_messages(&summary_block)
+                .into_iter()
+                .map(|msg| (ic_crypto::crypto_hash(&msg), msg))
+                .collect();
+        let known: HashSet<RemoteDkgMessageId> = remote_dkg_pool
+            .get_validated()
+            .map(ic_crypto::crypto_hash)
+            .collect();
+
+        let mut change_set = Vec::new();
+        for msg in remote_dkg_pool.get_unvalidated() {
+            let id = ic_crypto::crypto_hash(msg);
+            if msg.height() > summary_height {
+                continue;
+            }
+            if known.contains(&id) || msg.height() < summary_height {
+                change_set.push(RemoteDkgChangeAction::RemoveFromUnvalidated(id));
+            } else if let Some(msg) = expected.remove(&id) {
+                change_set.push(RemoteDkgChangeAction::MoveToValidated(msg));
+            } else {
+                warn!(
+                    self.log,
+                    "Remote DKG message {:?} is not in the DKG summary at height {:?}",
+                    msg.dkg_id(),
+                    summary_height
+                );
+                change_set.push(RemoteDkgChangeAction::HandleInvalid(
+                    id,
+                    "The message is not in the DKG summary".to_string(),
+                ));
+            }
+        }
+        change_set.extend(
+            expected
+                .into_iter()
+                .filter(|(id, _)| !known.contains(id))
+                .map(|(_, msg)| RemoteDkgChangeAction::AddToValidated(msg)),
+        );
+        if remote_dkg_pool
+            .get_validated()
+            .any(|msg| msg.height() < summary_height)
+        {
+            change_set.push(RemoteDkgChangeAction::PurgeBelow(summary_height));
+        }
+        change_set
+    }
+}
+
+/// Implements the `RemoteDkgGossip` trait.
+pub struct RemoteDkgGossipImpl {
+    consensus_cache: Arc<dyn ConsensusPoolCache>,
+}
+
+impl RemoteDkgGossipImpl {
+    /// Build a new remote DKG gossip component.
+    pub fn new(consensus_cache: Arc<dyn ConsensusPoolCache>) -> Self {
+        Self { consensus_cache }
+    }
+}
+
+impl RemoteDkgGossip for RemoteDkgGossipImpl {
+    /// Messages of older summaries are dropped, and messages of newer
+    /// summaries are stashed until the local summary catches up.
+    fn get_priority_function(
+        &self,
+        _remote_dkg_pool: &dyn RemoteDkgPool,
+    ) -> PriorityFn<RemoteDkgMessageId, RemoteDkgMessageAttribute> {
+        let summary_height = self.consensus_cache.summary_block().height;
+        Box::new(move |_id, attribute| {
+            if attribute.summary_height < summary_height {
+                Priority::Drop
+            } else if attribute.summary_height > summary_height {
+                Priority::Stash
+            } else {
+                Priority::Fetch
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::mocks::{dependencies, Dependencies};
+    use ic_artifact_pool::remote_dkg_pool::RemoteDkgPoolImpl;
+    use ic_interfaces::{artifact_pool::UnvalidatedArtifact, remote_dkg::MutableRemoteDkgPool};
+    use ic_logger::replica_logger::no_op_logger;
+    use ic_metrics::MetricsRegistry;
+    use ic_test_utilities::{
+        mock_time,
+        types::ids::{node_test_id, subnet_test_id},
+    };
+    use ic_types::crypto::threshold_sig::ni_dkg::{NiDkgId, NiDkgTag, NiDkgTargetId};
+
+    fn make_response(height: Height) -> RemoteDkgMessage {
+        RemoteDkgMessage::Response(RemoteDkgResponse {
+            summary_height: height,
+            dkg_id: NiDkgId {
+                start_block_height: height,
+                dealer_subnet: subnet_test_id(0),
+                dkg_tag: NiDkgTag::HighThreshold,
+                target_subnet: NiDkgTargetSubnet::Remote(NiDkgTargetId::new([1; 32])),
+            },
+            transcript: Err("Not enough dealings".to_string()),
+        })
+    }
+
+    #[test]
+    fn test_remote_dkg_validation() {
+        ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
+            let Dependencies { pool, .. } = dependencies(pool_config, 1);
+            let consensus_cache = pool.get_cache();
+            let summary_height = consensus_cache.summary_block().height;
+            let mut remote_dkg_pool = RemoteDkgPoolImpl::new(MetricsRegistry::new());
+            let invalid = make_response(summary_height);
+            let future = make_response(summary_height.increment());
+            for message in vec![invalid.clone(), future] {
+                remote_dkg_pool.insert(UnvalidatedArtifact {
+                    message,
+                    peer_id: node_test_id(1),
+                    timestamp: mock_time(),
+                });
+            }
+
+            // The genesis summary has no remote DKGs, so the message at the
+            // summary height is invalid, and the one above it is kept.
+            let remote_dkg = RemoteDkgImpl::new(no_op_logger());
+            let change_set = remote_dkg.on_state_change(consensus_cache.as_ref(), &remote_dkg_pool);
+            assert_eq!(change_set.len(), 1);
+            match &change_set[0] {
+                RemoteDkgChangeAction::HandleInvalid(id, _) => {
+                    assert_eq!(id, &ic_crypto::crypto_hash(&invalid))
+                }
+                action => panic!("Unexpected change action {:?}", action),
+            }
+
+            let gossip = RemoteDkgGossipImpl::new(consensus_cache);
+            let priority = gossip.get_priority_function(&remote_dkg_pool);
+            let attribute = |summary_height| RemoteDkgMessageAttribute { summary_height };
+            let id = ic_crypto::crypto_hash(&invalid);
+            assert_eq!(priority(&id, &attribute(summary_height)), Priority::Fetch);
+            assert_eq!(
+                priority(&id, &attribute(summary_height.increment())),
+                Priority::Stash
+            );
+        })
+    }
+}
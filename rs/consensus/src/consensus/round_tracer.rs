@@ -0,0 +1,317 @@
+//! The round tracer records when the phases of the consensus rounds complete,
+//! to diagnose slow rounds in production.
+//!
+//! The round at height `h` starts once the random beacon at height `h - 1`
+//! is seen, and its phases complete once the pool contains a validated block
+//! proposal, a notarization, a finalization, and the random beacon at height
+//! `h`. Each round is traced as a `consensus_round` span, which contains one
+//! `consensus_round_phase` span per phase, from the start of the round to the
+//! completion of the phase. Both carry the height of the round. The timelines
+//! of the latest completed rounds are kept in a ring buffer that can be
+//! queried through [RoundTimelines].
+use crate::consensus::{pool_reader::PoolReader, prelude::*};
+use ic_interfaces::consensus::RoundTimelineReader;
+use ic_logger::{debug, ReplicaLogger};
+use ic_protobuf::log::consensus_log_entry::v1::ConsensusLogEntry;
+use ic_types::consensus::round_timeline::{
+    duration_since, RoundPhase, RoundTimeline, ALL_ROUND_PHASES,
+};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, RwLock};
+use tracing::Span;
+
+/// The timelines of the latest completed rounds. It can be cloned and queried
+/// from other threads, e.g. by a debug endpoint.
+#[derive(Clone, Default)]
+pub struct RoundTimelines {
+    timelines: Arc<RwLock<VecDeque<RoundTimeline>>>,
+}
+
+impl RoundTimelines {
+    /// Return the timelines of the latest completed rounds, ordered by height.
+    pub fn get(&self) -> Vec<RoundTimeline> {
+        self.timelines.read().unwrap().iter().cloned().collect()
+    }
+
+    /// Return the timeline of the round at the given height, if it is among
+    /// the latest completed rounds.
+    pub fn get_at(&self, height: Height) -> Option<RoundTimeline> {
+        self.timelines
+            .read()
+            .unwrap()
+            .iter()
+            .find(|timeline| timeline.height == height)
+            .cloned()
+    }
+
+    fn push(&self, timeline: RoundTimeline, capacity: usize) {
+        let mut timelines = self.timelines.write().unwrap();
+        timelines.push_back(timeline);
+        while timelines.len() > capacity {
+            timelines.pop_front();
+        }
+    }
+}
+
+impl RoundTimelineReader for RoundTimelines {
+    fn get_round_timelines(&self) -> Vec<RoundTimeline> {
+        self.get()
+    }
+}
+
+/// A round in progress, with the spans of the round and of the phases that
+/// did not complete yet.
+struct RoundInProgress {
+    timeline: RoundTimeline,
+    // Closed once the round completes or is dropped.
+    _span: Span,
+    phase_spans: BTreeMap<RoundPhase, Span>,
+}
+
+impl RoundInProgress {
+    fn new(height: Height, start: Time) -> Self {
+        let span = tracing::debug_span!("consensus_round", height = height.get());
+        let phase_spans = ALL_ROUND_PHASES
+            .iter()
+            .map(|phase| {
+                let phase_span = tracing::debug_span!(
+                    parent: &span,
+                    "consensus_round_phase",
+                    height = height.get(),
+                    phase = phase.as_ref()
+                );
+                (*phase, phase_span)
+            })
+            .collect();
+        Self {
+            timeline: RoundTimeline {
+                height,
+                start,
+                phases: BTreeMap::new(),
+            },
+            _span: span,
+            phase_spans,
+        }
+    }
+
+    /// Record the completion of the given phase, which closes its span.
+    fn complete(&mut self, phase: RoundPhase, now: Time) {
+        self.timeline.phases.insert(phase, now);
+        self.phase_spans.remove(&phase);
+    }
+}
+
+/// Keeps track of the rounds in progress, and records the completed ones.
+pub(crate) struct RoundTracer {
+    capacity: usize,
+    // The height of the latest round that was started.
+    last_started: RefCell<Height>,
+    in_progress: RefCell<BTreeMap<Height, RoundInProgress>>,
+    completed: RoundTimelines,
+    log: ReplicaLogger,
+}
+
+impl RoundTracer {
+    /// Create a tracer that keeps the timelines of the latest `capacity`
+    /// rounds in `completed`.
+    pub(crate) fn new(capacity: usize, completed: RoundTimelines, log: ReplicaLogger) -> Self {
+        Self {
+            capacity,
+            last_started: RefCell::new(Height::from(0)),
+            in_progress: Default::default(),
+            completed,
+            log,
+        }
+    }
+
+    /// Record the phases that completed since the previous call at the given
+    /// time.
+    pub(crate) fn on_state_change(&self, pool: &PoolReader<'_>, now: Time) {
+        if self.capacity == 0 {
+            return;
+        }
+        let beacon_height = pool.get_random_beacon_height();
+        let proposal_height = pool
+            .pool()
+            .validated()
+            .block_proposal()
+            .max_height()
+            .unwrap_or_else(|| Height::from(0));
+        let notarized_height = pool.get_notarized_height();
+        let finalized_height = pool.get_finalized_height();
+        let catch_up_height = pool.get_catch_up_height();
+
+        let mut in_progress = self.in_progress.borrow_mut();
+        // Rounds that were skipped by catching up are not traced.
+        let next_height = beacon_height.increment();
+        if *self.last_started.borrow() < next_height {
+            *self.last_started.borrow_mut() = next_height;
+            in_progress.insert(next_height, RoundInProgress::new(next_height, now));
+        }
+
+        for round in in_progress.values_mut() {
+            let height = round.timeline.height;
+            for phase in ALL_ROUND_PHASES.iter() {
+                let completed_height = match phase {
+                    RoundPhase::BlockProposal => proposal_height,
+                    RoundPhase::Notarization => notarized_height,
+                    RoundPhase::Finalization => finalized_height,
+                    RoundPhase::RandomBeacon => beacon_height,
+                };
+                if completed_height < height || round.timeline.phases.contains_key(phase) {
+                    continue;
+                }
+                round.complete(*phase, now);
+                debug!(
+                    self.log,
+                    "consensus_round_phase";
+                    consensus => ConsensusLogEntry {
+                        height: Some(height.get()),
+                        phase: Some(phase.as_ref().to_string()),
+                        duration_ms: Some(duration_since(round.timeline.start, now).as_millis() as u64),
+                        ..Default::default()
+                    }
+                );
+            }
+        }
+
+        let heights: Vec<_> = in_progress.keys().cloned().collect();
+        for height in heights {
+            if in_progress[&height].timeline.is_complete() {
+                let round = in_progress.remove(&height).unwrap();
+                self.completed.push(round.timeline, self.capacity);
+            } else if height <= catch_up_height {
+                in_progress.remove(&height);
+            }
+        }
+        while in_progress.len() > self.capacity {
+            let lowest = *in_progress.keys().next().unwrap();
+            in_progress.remove(&lowest);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::mocks::{dependencies, Dependencies};
+    use ic_logger::replica_logger::no_op_logger;
+    use ic_test_utilities::mock_time;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use tracing::{
+        field::{Field, Visit},
+        span::{Attributes, Id, Record},
+        Event, Metadata, Subscriber,
+    };
+
+    #[test]
+    fn test_round_tracer() {
+        ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
+            let Dependencies { mut pool, .. } = dependencies(pool_config, 1);
+            let timelines = RoundTimelines::default();
+            let tracer = RoundTracer::new(2, timelines.clone(), no_op_logger());
+
+            // The round at height 1 starts, as the genesis beacon exists.
+            tracer.on_state_change(&PoolReader::new(&pool), mock_time());
+            assert!(timelines.get().is_empty());
+
+            for i in 1..=3 {
+                pool.advance_round_normal_operation();
+                let now = mock_time() + Duration::from_secs(i);
+                tracer.on_state_change(&PoolReader::new(&pool), now);
+            }
+
+            // Only the latest two rounds are kept.
+            let heights: Vec<_> = timelines.get().iter().map(|t| t.height).collect();
+            assert_eq!(heights, vec![Height::from(2), Height::from(3)]);
+            assert_eq!(timelines.get_round_timelines(), timelines.get());
+            let timeline = timelines.get_at(Height::from(3)).unwrap();
+            assert_eq!(
+                timeline.phase_duration(RoundPhase::Finalization),
+                Some(Duration::from_secs(1))
+            );
+            assert!(timelines.get_at(Height::from(1)).is_none());
+        })
+    }
+
+    /// A subscriber that records the names and heights of the closed spans.
+    #[derive(Clone, Default)]
+    struct ClosedSpans {
+        next_id: Arc<AtomicU64>,
+        open: Arc<Mutex<BTreeMap<u64, (&'static str, u64)>>>,
+        closed: Arc<Mutex<Vec<(&'static str, u64)>>>,
+    }
+
+    struct HeightVisitor(u64);
+
+    impl Visit for HeightVisitor {
+        fn record_u64(&mut self, field: &Field, value: u64) {
+            if field.name() == "height" {
+                self.0 = value;
+            }
+        }
+
+        fn record_debug(&mut self, _field: &Field, _value: &dyn std::fmt::Debug) {}
+    }
+
+    impl Subscriber for ClosedSpans {
+        fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let mut height = HeightVisitor(0);
+            span.record(&mut height);
+            let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+            self.open
+                .lock()
+                .unwrap()
+                .insert(id, (span.metadata().name(), height.0));
+            Id::from_u64(id)
+        }
+
+        fn record(&self, _span: &Id, _values: &Record<'_>) {}
+
+        fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+        fn event(&self, _event: &Event<'_>) {}
+
+        fn enter(&self, _span: &Id) {}
+
+        fn exit(&self, _span: &Id) {}
+
+        fn try_close(&self, span: Id) -> bool {
+            if let Some(closed) = self.open.lock().unwrap().remove(&span.into_u64()) {
+                self.closed.lock().unwrap().push(closed);
+            }
+            true
+        }
+    }
+
+    #[test]
+    fn test_round_tracer_emits_spans() {
+        ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
+            let Dependencies { mut pool, .. } = dependencies(pool_config, 1);
+            let spans = ClosedSpans::default();
+            tracing::subscriber::with_default(spans.clone(), || {
+                let tracer = RoundTracer::new(2, RoundTimelines::default(), no_op_logger());
+                tracer.on_state_change(&PoolReader::new(&pool), mock_time());
+                pool.advance_round_normal_operation();
+                tracer.on_state_change(&PoolReader::new(&pool), mock_time());
+            });
+
+            // The round at height 1 completed: its four phase spans and the
+            // round span are closed.
+            let closed = spans.closed.lock().unwrap().clone();
+            let phases = closed
+                .iter()
+                .filter(|(name, height)| *name == "consensus_round_phase" && *height == 1)
+                .count();
+            assert_eq!(phases, ALL_ROUND_PHASES.len());
+            assert!(closed.contains(&("consensus_round", 1)));
+        })
+    }
+}
@@ -2,12 +2,40 @@
 //! of shares into full objects. That is, it constructs Random Beacon objects
 //! from random beacon shares, Notarizations from notarization shares and
 //! Finalizations from finalization shares.
+//!
+//! The shares of every artifact type are kept in an [AggregationCache]. New
+//! shares are added to it from the change sets of consensus, see
+//! [ShareAggregator::on_change_set], so that the pool is only read for the
+//! shares that were validated before the aggregator was created, and every
+//! content is aggregated once, when a new share brings it to the threshold.
+//!
+//! CatchUpPackages are aggregated in a [BackgroundTask], as combining the
+//! shares of large committees would otherwise delay the round at the DKG
+//! interval boundary. There is one CatchUpPackage per DKG interval, so their
+//! shares are read from the pool.
+//!
+//! On single-node subnets, the notarization and finalization shares of the
+//! node are enough to construct the full artifacts, so they are aggregated
+//! as soon as they are created, and never added to the pool or gossiped.
 use crate::consensus::{
-    membership::Membership, pool_reader::PoolReader, prelude::*, utils, ConsensusCrypto,
+    membership::Membership,
+    pool_reader::PoolReader,
+    prelude::*,
+    utils::{self, AggregationCache, BackgroundTask},
+    ConsensusCrypto,
+};
+use ic_interfaces::{
+    consensus_pool::{HeightIndexedPool, HeightRange},
+    messaging::MessageRouting,
 };
-use ic_interfaces::messaging::MessageRouting;
 use ic_logger::ReplicaLogger;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+
+type ThresholdAggregationCache<T> =
+    Mutex<AggregationCache<T, ThresholdSignatureShare<T>, ThresholdSignature<T>>>;
+
+type MultiAggregationCache<T> =
+    Mutex<AggregationCache<T, MultiSignatureShare<T>, MultiSignature<T>>>;
 
 /// The ShareAggregator is responsible for aggregating shares of random beacons,
 /// notarizations, and finalizations into full objects
@@ -16,6 +44,22 @@ pub struct ShareAggregator {
     crypto: Arc<dyn ConsensusCrypto>,
     message_routing: Arc<dyn MessageRouting>,
     log: ReplicaLogger,
+    random_beacon_cache: ThresholdAggregationCache<RandomBeaconContent>,
+    random_tape_cache: ThresholdAggregationCache<RandomTapeContent>,
+    notarization_cache: MultiAggregationCache<NotarizationContent>,
+    finalization_cache: MultiAggregationCache<FinalizationContent>,
+    catch_up_package_cache: Arc<ThresholdAggregationCache<CatchUpContent>>,
+    catch_up_package_aggregation: Mutex<CatchUpPackageAggregation>,
+}
+
+/// The state of the background aggregation of CatchUpPackage shares.
+#[derive(Default)]
+struct CatchUpPackageAggregation {
+    // The task that is currently running, if any.
+    task: Option<BackgroundTask<Vec<CatchUpPackage>>>,
+    // The height and the number of shares of the latest task, so that the
+    // same shares are not aggregated again.
+    last_shares: Option<(Height, usize)>,
 }
 
 impl ShareAggregator {
@@ -30,6 +74,46 @@ impl ShareAggregator {
             crypto,
             message_routing,
             log,
+            random_beacon_cache: Default::default(),
+            random_tape_cache: Default::default(),
+            notarization_cache: Default::default(),
+            finalization_cache: Default::default(),
+            catch_up_package_cache: Default::default(),
+            catch_up_package_aggregation: Default::default(),
+        }
+    }
+
+    /// Add the shares of the given change set, which is about to be applied
+    /// to the pool, to the aggregation caches.
+    pub fn on_change_set(&self, change_set: &[ChangeAction]) {
+        for action in change_set {
+            let message = match action {
+                ChangeAction::AddToValidated(message) | ChangeAction::MoveToValidated(message) => {
+                    message
+                }
+                _ => continue,
+            };
+            match message {
+                ConsensusMessage::RandomBeaconShare(share) => self
+                    .random_beacon_cache
+                    .lock()
+                    .unwrap()
+                    .insert(share.clone()),
+                ConsensusMessage::RandomTapeShare(share) => {
+                    self.random_tape_cache.lock().unwrap().insert(share.clone())
+                }
+                ConsensusMessage::NotarizationShare(share) => self
+                    .notarization_cache
+                    .lock()
+                    .unwrap()
+                    .insert(share.clone()),
+                ConsensusMessage::FinalizationShare(share) => self
+                    .finalization_cache
+                    .lock()
+                    .unwrap()
+                    .insert(share.clone()),
+                _ => (),
+            }
         }
     }
 
@@ -48,16 +132,18 @@ impl ShareAggregator {
     /// Attempt to construct the next round's `RandomBeacon`
     fn aggregate_random_beacon_shares(&self, pool: &PoolReader<'_>) -> Vec<ConsensusMessage> {
         let height = pool.get_random_beacon_height().increment();
-        let shares = pool.get_random_beacon_shares(height);
+        let mut cache = self.random_beacon_cache.lock().unwrap();
+        cache.load_once(|| get_from_height(pool.pool().validated().random_beacon_share(), height));
+        cache.purge_below(height);
         let state_reader = pool.as_cache();
         let dkg_id = utils::active_low_threshold_transcript(state_reader, height)
             .map(|transcript| transcript.dkg_id);
-        to_messages(utils::aggregate(
+        to_messages(cache.aggregate(
             &self.log,
             self.membership.as_ref(),
             self.crypto.as_aggregate(),
             Box::new(|_| dkg_id),
-            shares,
+            |content| content.height() == height,
         ))
     }
 
@@ -66,12 +152,13 @@ impl ShareAggregator {
     fn aggregate_random_tape_shares(&self, pool: &PoolReader<'_>) -> Vec<ConsensusMessage> {
         let expected_height = self.message_routing.expected_batch_height();
         let finalized_height = pool.get_finalized_height();
-        // Filter out those at a height where we have a full tape already.
-        let shares = pool
-            .get_random_tape_shares(expected_height, finalized_height.increment())
-            .filter(|share| pool.get_random_tape(share.height()).is_none());
+        let mut cache = self.random_tape_cache.lock().unwrap();
+        cache.load_once(|| {
+            get_from_height(pool.pool().validated().random_tape_share(), expected_height)
+        });
+        cache.purge_below(expected_height);
         let state_reader = pool.as_cache();
-        to_messages(utils::aggregate(
+        to_messages(cache.aggregate(
             &self.log,
             self.membership.as_ref(),
             self.crypto.as_aggregate(),
@@ -79,44 +166,129 @@ impl ShareAggregator {
                 utils::active_low_threshold_transcript(state_reader, content.height())
                     .map(|transcript| transcript.dkg_id)
             }),
-            shares,
+            // Skip the heights at which we have a full tape already.
+            |content| {
+                content.height() <= finalized_height.increment()
+                    && pool.get_random_tape(content.height()).is_none()
+            },
         ))
     }
 
     /// Attempt to construct `Notarization`s at `notarized_height + 1`
     fn aggregate_notarization_shares(&self, pool: &PoolReader<'_>) -> Vec<ConsensusMessage> {
         let height = pool.get_notarized_height().increment();
-        let shares = pool.get_notarization_shares(height);
+        let mut cache = self.notarization_cache.lock().unwrap();
+        cache.load_once(|| get_from_height(pool.pool().validated().notarization_share(), height));
+        cache.purge_below(height);
         let state_reader = pool.as_cache();
         let registry_version = utils::registry_version_at_height(state_reader, height);
-        to_messages(utils::aggregate(
+        to_messages(cache.aggregate(
             &self.log,
             self.membership.as_ref(),
             self.crypto.as_aggregate(),
             Box::new(|_| registry_version),
-            shares,
+            |content| content.height() == height,
         ))
     }
 
     /// Attempt to construct `Finalization`s
     fn aggregate_finalization_shares(&self, pool: &PoolReader<'_>) -> Vec<ConsensusMessage> {
-        let shares = pool.get_finalization_shares(
-            pool.get_finalized_height().increment(),
-            pool.get_notarized_height(),
-        );
+        let height = pool.get_finalized_height().increment();
+        let notarized_height = pool.get_notarized_height();
+        let mut cache = self.finalization_cache.lock().unwrap();
+        cache.load_once(|| get_from_height(pool.pool().validated().finalization_share(), height));
+        cache.purge_below(height);
         let state_reader = pool.as_cache();
-        to_messages(utils::aggregate(
+        to_messages(cache.aggregate(
             &self.log,
             self.membership.as_ref(),
             self.crypto.as_aggregate(),
             Box::new(|content: &FinalizationContent| {
                 utils::registry_version_at_height(state_reader, content.height())
             }),
-            shares,
+            |content| content.height() <= notarized_height,
         ))
     }
 
-    /// Attempt to construct `CatchUpPackage`s.
+    /// Return the full notarizations of the given notarization shares of this
+    /// node if the subnet consists of this node only, or the shares otherwise.
+    pub fn aggregate_own_notarization_shares(
+        &self,
+        pool: &PoolReader<'_>,
+        shares: Vec<NotarizationShare>,
+    ) -> Vec<ConsensusMessage> {
+        let state_reader = pool.as_cache();
+        self.aggregate_own_shares(
+            self.crypto.as_aggregate(),
+            Box::new(|content: &NotarizationContent| {
+                utils::registry_version_at_height(state_reader, content.height())
+            }),
+            shares,
+        )
+    }
+
+    /// Return the full finalizations of the given finalization shares of this
+    /// node if the subnet consists of this node only, or the shares otherwise.
+    pub fn aggregate_own_finalization_shares(
+        &self,
+        pool: &PoolReader<'_>,
+        shares: Vec<FinalizationShare>,
+    ) -> Vec<ConsensusMessage> {
+        let state_reader = pool.as_cache();
+        self.aggregate_own_shares(
+            self.crypto.as_aggregate(),
+            Box::new(|content: &FinalizationContent| {
+                utils::registry_version_at_height(state_reader, content.height())
+            }),
+            shares,
+        )
+    }
+
+    fn aggregate_own_shares<Message, Signature, KeySelector: Copy, CommitteeSignature>(
+        &self,
+        crypto: &dyn Aggregate<Message, Signature, KeySelector, CommitteeSignature>,
+        selector: Box<dyn Fn(&Message) -> Option<KeySelector> + '_>,
+        shares: Vec<Signed<Message, Signature>>,
+    ) -> Vec<ConsensusMessage>
+    where
+        Message: Eq + Ord + Clone + std::fmt::Debug + HasHeight + HasCommittee,
+        Signed<Message, Signature>: Clone + ConsensusMessageHashable,
+        Signed<Message, CommitteeSignature>: ConsensusMessageHashable,
+    {
+        let single_node = !shares.is_empty()
+            && shares.iter().all(|share| {
+                self.membership
+                    .is_single_node_subnet(share.height())
+                    .unwrap_or(false)
+            });
+        if !single_node {
+            return to_messages(shares);
+        }
+        let aggregated = utils::aggregate(
+            &self.log,
+            self.membership.as_ref(),
+            crypto,
+            selector,
+            shares.clone().into_iter(),
+        );
+        // Shares that could not be aggregated are added to the pool, so that the
+        // regular aggregation picks them up.
+        let remaining: Vec<_> = shares
+            .into_iter()
+            .filter(|share| {
+                !aggregated
+                    .iter()
+                    .any(|artifact| artifact.content == share.content)
+            })
+            .collect();
+        let mut messages = to_messages(aggregated);
+        messages.append(&mut to_messages(remaining));
+        messages
+    }
+
+    /// Attempt to construct `CatchUpPackage`s. The shares are aggregated in
+    /// the background, and the resulting `CatchUpPackage`s are returned by the
+    /// first call after the aggregation completed.
     fn aggregate_catch_up_package_shares(&self, pool: &PoolReader<'_>) -> Vec<ConsensusMessage> {
         let start_block = pool.get_highest_summary_block();
         let height = start_block.height();
@@ -125,25 +297,73 @@ impl ShareAggregator {
         if height <= pool.get_catch_up_height() {
             return Vec::new();
         }
-        let shares = pool.get_catch_up_package_shares(height).map(|share| {
-            let block = pool
-                .get_block(&share.content.block, height)
-                .unwrap_or_else(|err| panic!("Block not found for {:?}, error: {:?}", share, err));
-            Signed {
-                content: CatchUpContent::from_share_content(share.content, block),
-                signature: share.signature,
+
+        let mut aggregation = self.catch_up_package_aggregation.lock().unwrap();
+        if let Some(task) = aggregation.task.take() {
+            if task.height() == height {
+                match task.take_result() {
+                    Some(catch_up_packages) => return to_messages(catch_up_packages),
+                    None => {
+                        aggregation.task = Some(task);
+                        return Vec::new();
+                    }
+                }
             }
-        });
+        }
+
+        let shares: Vec<_> = pool
+            .get_catch_up_package_shares(height)
+            .map(|share| {
+                let block = pool
+                    .get_block(&share.content.block, height)
+                    .unwrap_or_else(|err| {
+                        panic!("Block not found for {:?}, error: {:?}", share, err)
+                    });
+                Signed {
+                    content: CatchUpContent::from_share_content(share.content, block),
+                    signature: share.signature,
+                }
+            })
+            .collect();
+        if aggregation.last_shares == Some((height, shares.len())) {
+            return Vec::new();
+        }
+        aggregation.last_shares = Some((height, shares.len()));
+
         let state_reader = pool.as_cache();
         let dkg_id = utils::active_high_threshold_transcript(state_reader, height)
             .map(|transcript| transcript.dkg_id);
-        to_messages(utils::aggregate(
-            &self.log,
-            self.membership.as_ref(),
-            self.crypto.as_aggregate(),
-            Box::new(|_| dkg_id),
-            shares,
-        ))
+        let log = self.log.clone();
+        let membership = Arc::clone(&self.membership);
+        let crypto = Arc::clone(&self.crypto);
+        let cache = Arc::clone(&self.catch_up_package_cache);
+        aggregation.task = Some(BackgroundTask::spawn(height, move || {
+            let mut cache = cache.lock().unwrap();
+            shares.into_iter().for_each(|share| cache.insert(share));
+            cache.purge_below(height);
+            cache.aggregate(
+                &log,
+                membership.as_ref(),
+                crypto.as_aggregate(),
+                Box::new(|_| dkg_id),
+                |content| content.height() == height,
+            )
+        }));
+        Vec::new()
+    }
+}
+
+/// Returns the validated artifacts of the given section at or above the given
+/// height.
+fn get_from_height<T: 'static>(
+    section: &dyn HeightIndexedPool<T>,
+    height: Height,
+) -> Box<dyn Iterator<Item = T>> {
+    match section.max_height() {
+        Some(max_height) if max_height >= height => {
+            section.get_by_height_range(HeightRange::new(height, max_height))
+        }
+        _ => Box::new(std::iter::empty()),
     }
 }
 
@@ -154,7 +374,10 @@ fn to_messages<T: ConsensusMessageHashable>(artifacts: Vec<T>) -> Vec<ConsensusM
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::consensus::mocks::{dependencies, dependencies_with_subnet_params, Dependencies};
+    use crate::consensus::{
+        add_all_to_validated,
+        mocks::{dependencies, dependencies_with_subnet_params, Dependencies},
+    };
     use ic_interfaces::consensus_pool::ConsensusPool;
     use ic_logger::replica_logger::no_op_logger;
     use ic_test_utilities::{
@@ -164,6 +387,7 @@ mod tests {
         types::ids::{node_test_id, subnet_test_id},
     };
     use std::sync::Arc;
+    use std::time::{Duration, Instant};
 
     #[test]
     /// Adds a random beacon and notarization share to a pool
@@ -220,7 +444,10 @@ mod tests {
             assert_eq!(messages.len(), 2);
 
             let finalization_share = FinalizationShare::fake(block.as_ref(), signer);
-            pool.insert_validated(finalization_share);
+            pool.insert_validated(finalization_share.clone());
+            aggregator.on_change_set(&[ChangeAction::AddToValidated(
+                finalization_share.into_message(),
+            )]);
 
             let messages = aggregator.on_state_change(&PoolReader::new(&pool));
             let finalization_was_created = messages
@@ -232,6 +459,92 @@ mod tests {
         })
     }
 
+    #[test]
+    /// Checks that aggregated artifacts that did not make it into the pool are
+    /// returned again from the cache, until their shares are purged.
+    fn test_cached_aggregation() {
+        ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
+            let Dependencies {
+                mut pool,
+                membership,
+                crypto,
+                ..
+            } = dependencies(pool_config, 1);
+
+            let block = pool.make_next_block();
+            let signer = block.signature.signer;
+            pool.insert_validated(block.clone());
+            pool.insert_validated(NotarizationShare::fake(block.as_ref(), signer));
+
+            let aggregator = ShareAggregator::new(
+                membership,
+                Arc::new(FakeMessageRouting::new()),
+                crypto,
+                no_op_logger(),
+            );
+            let messages = aggregator.on_state_change(&PoolReader::new(&pool));
+            assert!(messages
+                .iter()
+                .any(|x| matches!(x, ConsensusMessage::Notarization(_))));
+            assert_eq!(
+                aggregator.on_state_change(&PoolReader::new(&pool)),
+                messages
+            );
+        })
+    }
+
+    #[test]
+    /// Checks that the shares added after the first call are taken from the
+    /// change sets instead of the pool, and that a content is aggregated
+    /// once it reaches the threshold.
+    fn test_incremental_aggregation() {
+        ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
+            let Dependencies {
+                mut pool,
+                membership,
+                crypto,
+                ..
+            } = dependencies(pool_config, 4);
+            let block = pool.make_next_block();
+            pool.insert_validated(block.clone());
+            let threshold = membership
+                .get_committee_threshold(block.height(), Notarization::committee())
+                .unwrap();
+
+            let aggregator = ShareAggregator::new(
+                Arc::clone(&membership),
+                Arc::new(FakeMessageRouting::new()),
+                crypto,
+                no_op_logger(),
+            );
+            assert!(aggregator
+                .on_state_change(&PoolReader::new(&pool))
+                .is_empty());
+
+            let shares: Vec<_> = (0..threshold as u64)
+                .map(|i| NotarizationShare::fake(block.as_ref(), node_test_id(i)))
+                .collect();
+            for share in shares.iter() {
+                pool.insert_validated(share.clone());
+            }
+            // The pool is not read again.
+            assert!(aggregator
+                .on_state_change(&PoolReader::new(&pool))
+                .is_empty());
+
+            let (last, first) = shares.split_last().unwrap();
+            aggregator.on_change_set(&add_all_to_validated(first.to_vec()));
+            assert!(aggregator
+                .on_state_change(&PoolReader::new(&pool))
+                .is_empty());
+
+            aggregator.on_change_set(&add_all_to_validated(vec![last.clone()]));
+            let messages = aggregator.on_state_change(&PoolReader::new(&pool));
+            assert_eq!(messages.len(), 1);
+            assert!(matches!(messages[0], ConsensusMessage::Notarization(_)));
+        })
+    }
+
     #[test]
     /// Test the aggregation of 'CatchUpPackageShare's
     fn test_catch_up_package_aggregation() {
@@ -293,8 +606,18 @@ mod tests {
             pool.insert_validated(share1);
             pool.insert_validated(share2);
 
-            // Check if CUP is made from the shares
-            let mut messages = aggregator.on_state_change(&PoolReader::new(&pool));
+            // Check if CUP is made from the shares in the background
+            assert!(aggregator
+                .on_state_change(&PoolReader::new(&pool))
+                .is_empty());
+            let deadline = Instant::now() + Duration::from_secs(10);
+            let mut messages = loop {
+                let messages = aggregator.on_state_change(&PoolReader::new(&pool));
+                if !messages.is_empty() || Instant::now() > deadline {
+                    break messages;
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            };
             assert!(messages.len() == 1);
             let cup = match messages.pop() {
                 Some(ConsensusMessage::CatchUpPackage(x)) => x,
@@ -304,4 +627,37 @@ mod tests {
             assert_eq!(CatchUpShareContent::from(&cup.content), share0.content);
         })
     }
+
+    #[test]
+    /// Checks that the own shares of a node are aggregated right away on
+    /// single-node subnets, and kept as shares on larger subnets.
+    fn test_aggregate_own_shares() {
+        for (subnet_size, aggregated) in vec![(1, true), (4, false)] {
+            ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
+                let Dependencies {
+                    mut pool,
+                    membership,
+                    crypto,
+                    ..
+                } = dependencies(pool_config, subnet_size);
+                let block = pool.make_next_block();
+                pool.insert_validated(block.clone());
+                let share = NotarizationShare::fake(block.as_ref(), node_test_id(0));
+
+                let aggregator = ShareAggregator::new(
+                    membership,
+                    Arc::new(FakeMessageRouting::new()),
+                    crypto,
+                    no_op_logger(),
+                );
+                let messages = aggregator
+                    .aggregate_own_notarization_shares(&PoolReader::new(&pool), vec![share]);
+                assert_eq!(messages.len(), 1);
+                assert_eq!(
+                    matches!(messages[0], ConsensusMessage::Notarization(_)),
+                    aggregated
+                );
+            })
+        }
+    }
 }
@@ -1,7 +1,7 @@
 //! Consensus utility functions
 use crate::consensus::{membership::Membership, pool_reader::PoolReader, prelude::*};
 use ic_interfaces::{
-    consensus_pool::ConsensusPoolCache, crypto::CryptoHashable, registry::RegistryClient,
+    consensus_pool::ConsensusPoolCache, messaging::MessageRouting, registry::RegistryClient,
     state_manager::StateManager, time_source::TimeSource,
 };
 use ic_logger::{error, warn, ReplicaLogger};
@@ -17,6 +17,7 @@ use ic_types::{
 };
 use std::collections::BTreeMap;
 use std::convert::TryFrom;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 /// Rotate on_state_change calls with a round robin schedule to ensure fairness.
@@ -46,15 +47,7 @@ impl RoundRobin {
     }
 }
 
-/// Convert a CryptoHashable into a 32 bytes which can be used to seed a RNG
-pub fn crypto_hashable_to_seed<T: CryptoHashable>(hashable: &T) -> [u8; 32] {
-    let hash = ic_crypto::crypto_hash(hashable);
-    let CryptoHash(hash_bytes) = hash.get();
-    let mut seed = [0; 32]; // zero padded if digest is less than 32 bytes
-    let n = hash_bytes.len().min(32);
-    seed[0..n].copy_from_slice(&hash_bytes[0..n]);
-    seed
-}
+pub use ic_consensus_message::crypto_hashable_to_seed;
 
 /// Calculate the required delay for block making based on the block maker's
 /// rank.
@@ -119,11 +112,13 @@ pub fn is_time_to_make_block(
 /// Calculate the required delay for notary based on the rank of block to
 /// notarize, adjusted by a multiplier depending the gap between finalized and
 /// notarized heights, and adjusted by how far the certified height lags behind
-/// the finalized height.
+/// the finalized height, not counting the batches that message routing buffers
+/// ahead of execution.
 pub fn get_adjusted_notary_delay(
     membership: &Membership,
     pool: &PoolReader<'_>,
     state_manager: &dyn StateManager<State = ReplicatedState>,
+    message_routing: &dyn MessageRouting,
     log: &ReplicaLogger,
     height: Height,
     rank: Rank,
@@ -143,10 +138,13 @@ pub fn get_adjusted_notary_delay(
     // big. This is because increasing delay leads to higher chance of notarizing
     // only 1 block, which leads to higher chance of getting a finalization for that
     // round.  This exponential backoff does not apply to block rank 0.
-    let finalized_height = pool.get_finalized_height().get();
+    let finalized_height = pool.get_finalized_height();
     let initial_delay = initial_notary_delay.as_millis() as f32;
     let ranked_delay = unit_delay.as_millis() as f32 * rank.0 as f32;
-    let finality_gap = (pool.get_notarized_height().get() - finalized_height) as i32;
+    let finality_gap = pool
+        .get_notarized_height()
+        .saturating_sub(finalized_height)
+        .get() as i32;
     let finality_adjusted_delay =
         (initial_delay + ranked_delay * 1.5_f32.powi(finality_gap)) as u64;
 
@@ -154,10 +152,25 @@ pub fn get_adjusted_notary_delay(
     // certified height: when the certified height is more than 3 rounds behind the
     // finalized height, we increase the delay. More precisely, for every additional
     // round that certified height is behind finalized height, we add `unit_delay`.
-    let certified_gap =
-        finalized_height.saturating_sub(state_manager.latest_certified_height().get() + 3);
+    let certified_gap = finalized_height
+        .saturating_sub(
+            state_manager
+                .latest_certified_height()
+                .saturating_add(Height::from(3)),
+        )
+        .get();
+
+    // The batches delivered to message routing that are not executed yet are
+    // part of that gap, but message routing buffers them so that consensus can
+    // proceed while execution catches up. Hence they only count once they fill
+    // more than half of the pipeline: for every pending batch beyond that, we
+    // add `unit_delay`.
+    let occupancy = message_routing.batch_pipeline_occupancy();
+    let pending_batches = occupancy.pending_batches as u64;
+    let execution_backlog = pending_batches.saturating_sub(occupancy.capacity as u64 / 2);
+    let adjusted_gap = certified_gap.saturating_sub(pending_batches) + execution_backlog;
 
-    let adjusted_delay = finality_adjusted_delay + unit_delay.as_millis() as u64 * certified_gap;
+    let adjusted_delay = finality_adjusted_delay + unit_delay.as_millis() as u64 * adjusted_gap;
     Some(Duration::from_millis(adjusted_delay))
 }
 
@@ -281,6 +294,140 @@ pub fn aggregate<
         .collect()
 }
 
+/// Caches the shares of the artifacts that are aggregated from them, grouped
+/// by their content, so that the artifacts are aggregated incrementally.
+///
+/// The shares are added one by one as they are validated, see
+/// [AggregationCache::insert], instead of being read from the pool on every
+/// call; only the shares that were in the pool before the cache was created
+/// are read from it, once. A content is aggregated once a share was added to
+/// it and it has shares from a threshold of the committee. The result is
+/// cached, and later shares of the same content are dropped instead of
+/// triggering another aggregation.
+pub struct AggregationCache<Message, Signature, CommitteeSignature> {
+    entries: BTreeMap<Message, AggregationCacheEntry<Signature, CommitteeSignature>>,
+    // Whether the shares that were in the pool before have been loaded.
+    loaded: bool,
+}
+
+struct AggregationCacheEntry<Signature, CommitteeSignature> {
+    // The shares that have not been aggregated yet.
+    shares: Vec<Signature>,
+    // Whether a share was added since the last aggregation attempt.
+    changed: bool,
+    // The result of the aggregation, once it succeeded.
+    signature: Option<CommitteeSignature>,
+}
+
+impl<Message, Signature, CommitteeSignature> Default
+    for AggregationCache<Message, Signature, CommitteeSignature>
+{
+    fn default() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            loaded: false,
+        }
+    }
+}
+
+impl<
+        Message: Eq + Ord + Clone + std::fmt::Debug + HasHeight + HasCommittee,
+        Signature: Eq,
+        CommitteeSignature: Clone,
+    > AggregationCache<Message, Signature, CommitteeSignature>
+{
+    /// Inserts the shares returned by the given function, if no shares have
+    /// been loaded before. The function is meant to read the shares that
+    /// were validated before the cache was created from the pool.
+    pub fn load_once<Shares: Iterator<Item = Signed<Message, Signature>>>(
+        &mut self,
+        shares: impl FnOnce() -> Shares,
+    ) {
+        if !self.loaded {
+            self.loaded = true;
+            shares().for_each(|share| self.insert(share));
+        }
+    }
+
+    /// Adds the given validated share, unless its content has already been
+    /// aggregated or the share is known.
+    pub fn insert(&mut self, share: Signed<Message, Signature>) {
+        let entry = self
+            .entries
+            .entry(share.content)
+            .or_insert_with(|| AggregationCacheEntry {
+                shares: Vec::new(),
+                changed: false,
+                signature: None,
+            });
+        if entry.signature.is_none() && !entry.shares.contains(&share.signature) {
+            entry.shares.push(share.signature);
+            entry.changed = true;
+        }
+    }
+
+    /// Drops the contents below the given height.
+    pub fn purge_below(&mut self, height: Height) {
+        self.entries.retain(|content, _| content.height() >= height);
+    }
+
+    /// Like [aggregate], but only for the contents for which `is_relevant`
+    /// returns true, and incrementally: only the contents that received
+    /// shares since the previous call are aggregated, the results of the
+    /// other contents are returned from the cache.
+    pub fn aggregate<CryptoMessage, KeySelector: Copy>(
+        &mut self,
+        log: &ReplicaLogger,
+        membership: &Membership,
+        crypto: &dyn Aggregate<CryptoMessage, Signature, KeySelector, CommitteeSignature>,
+        selector: Box<dyn Fn(&Message) -> Option<KeySelector> + '_>,
+        is_relevant: impl Fn(&Message) -> bool,
+    ) -> Vec<Signed<Message, CommitteeSignature>> {
+        let mut aggregated = Vec::new();
+        for (content, entry) in self.entries.iter_mut() {
+            if !is_relevant(content) {
+                continue;
+            }
+            if entry.signature.is_none() && entry.changed {
+                let selector = match selector(content) {
+                    Some(selector) => selector,
+                    None => {
+                        error!(
+                            log,
+                            "aggregate: cannot find selector for content {:?}", content
+                        );
+                        continue;
+                    }
+                };
+                let threshold = match membership
+                    .get_committee_threshold(content.height(), Message::committee())
+                {
+                    Ok(threshold) => threshold,
+                    Err(err) => {
+                        error!(log, "MembershipError: {:?}", err);
+                        continue;
+                    }
+                };
+                entry.changed = false;
+                if entry.shares.len() < threshold {
+                    continue;
+                }
+                if let Ok(signature) = crypto.aggregate(entry.shares.iter().collect(), selector) {
+                    entry.signature = Some(signature);
+                    entry.shares = Vec::new();
+                }
+            }
+            if let Some(signature) = &entry.signature {
+                aggregated.push(Signed {
+                    content: content.clone(),
+                    signature: signature.clone(),
+                });
+            }
+        }
+        aggregated
+    }
+}
+
 // Return a mapping from the unique content contained in `shares` to the
 // shares that contain this content
 fn group_shares<C: Eq + Ord, S, Shares: Iterator<Item = Signed<C, S>>>(
@@ -298,6 +445,38 @@ fn group_shares<C: Eq + Ord, S, Shares: Iterator<Item = Signed<C, S>>>(
     })
 }
 
+/// A computation for the given height that runs on a dedicated thread, so that
+/// it does not delay the consensus round that started it. The thread hands its
+/// result to a completion callback, from which later rounds pick it up.
+pub struct BackgroundTask<T> {
+    height: Height,
+    result: Arc<Mutex<Option<T>>>,
+}
+
+impl<T: Send + 'static> BackgroundTask<T> {
+    /// Start running the given computation for the given height.
+    pub fn spawn<F: FnOnce() -> T + Send + 'static>(height: Height, computation: F) -> Self {
+        let result = Arc::new(Mutex::new(None));
+        let on_completion = {
+            let result = Arc::clone(&result);
+            move |value| *result.lock().unwrap() = Some(value)
+        };
+        std::thread::spawn(move || on_completion(computation()));
+        Self { height, result }
+    }
+
+    /// The height the task was started for.
+    pub fn height(&self) -> Height {
+        self.height
+    }
+
+    /// Return the result of the computation if it completed, or None if it is
+    /// still running. The result is only returned once.
+    pub fn take_result(&self) -> Option<T> {
+        self.result.lock().unwrap().take()
+    }
+}
+
 /// Return the hash of a block as a string.
 pub fn get_block_hash_string(block: &Block) -> String {
     hex::encode(ic_crypto::crypto_hash(block).get().0)
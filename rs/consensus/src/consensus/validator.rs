@@ -28,6 +28,7 @@ use ic_interfaces::{
 use ic_logger::{debug, trace, warn, ReplicaLogger};
 use ic_replicated_state::ReplicatedState;
 use ic_types::{
+    consensus::equivocation::EquivocationProof,
     crypto::{threshold_sig::ni_dkg::NiDkgId, CryptoError},
     registry::RegistryClientError,
     replica_config::ReplicaConfig,
@@ -51,6 +52,7 @@ enum TransientError {
     FinalizedBlockNotFound(Height),
     FailedToGetRegistryVersion,
     ValidationContextNotReached(ValidationContext, ValidationContext),
+    EquivocationProofNotVerifiable(String),
 }
 
 /// Possible validator permanent errors.
@@ -73,6 +75,10 @@ enum PermanentError {
     MismatchedStateHashInCatchUpPackageShare,
     MismatchedRandomBeaconInCatchUpPackageShare,
     RepeatedSigner,
+    TooManyEquivocationProofs(usize),
+    EquivocationProofOutOfRange(Height),
+    DuplicateEquivocationProof(NodeId, Height),
+    InvalidEquivocationProof(String),
 }
 
 impl From<CryptoError> for TransientError {
@@ -263,6 +269,12 @@ trait NotaryIssued: Sized + HasHeight {
         signed_message: &Signed<Self, MultiSignatureShare<Self>>,
         registry_version: RegistryVersion,
     ) -> ValidationResult<CryptoError>;
+    fn verify_multi_sig_individual_batch(
+        crypto: &dyn ConsensusCrypto,
+        content: &Self,
+        shares: &[&MultiSignatureShare<Self>],
+        registry_version: RegistryVersion,
+    ) -> ValidationResult<CryptoError>;
     fn is_duplicate(&self, pool: &PoolReader) -> bool;
     fn dependencies_validated(&self, pool: &PoolReader) -> bool;
 }
@@ -288,6 +300,15 @@ impl NotaryIssued for NotarizationContent {
         crypto.verify(signed_message, registry_version)
     }
 
+    fn verify_multi_sig_individual_batch(
+        crypto: &dyn ConsensusCrypto,
+        content: &Self,
+        shares: &[&MultiSignatureShare<Self>],
+        registry_version: RegistryVersion,
+    ) -> ValidationResult<CryptoError> {
+        crypto.verify_batch(content, shares, registry_version)
+    }
+
     fn is_duplicate(&self, pool: &PoolReader) -> bool {
         pool.pool()
             .validated()
@@ -326,6 +347,15 @@ impl NotaryIssued for FinalizationContent {
         crypto.verify(signed_message, registry_version)
     }
 
+    fn verify_multi_sig_individual_batch(
+        crypto: &dyn ConsensusCrypto,
+        content: &Self,
+        shares: &[&MultiSignatureShare<Self>],
+        registry_version: RegistryVersion,
+    ) -> ValidationResult<CryptoError> {
+        crypto.verify_batch(content, shares, registry_version)
+    }
+
     fn is_duplicate(&self, pool: &PoolReader) -> bool {
         pool.pool()
             .validated()
@@ -381,15 +411,89 @@ impl<T: NotaryIssued> SignatureVerify for Signed<T, MultiSignatureShare<T>> {
         crypto: &dyn ConsensusCrypto,
         pool: &PoolReader<'_>,
     ) -> ValidationResult<ValidatorError> {
-        let height = self.height();
-        let previous_beacon = get_previous_beacon(pool, height)?;
-        verify_notary(membership, height, &previous_beacon, self.signature.signer)?;
-        let registry_version = get_registry_version(pool, height)?;
+        let registry_version = verify_notary_issued_share_signer(membership, pool, self)?;
         T::verify_multi_sig_individual(crypto, self, registry_version)?;
         Ok(())
     }
 }
 
+/// Check that the signer of the given `NotarizationShare` or
+/// `FinalizationShare` is in the notary committee, and return the registry
+/// version to verify the signature with.
+fn verify_notary_issued_share_signer<T: NotaryIssued>(
+    membership: &Membership,
+    pool: &PoolReader<'_>,
+    share: &Signed<T, MultiSignatureShare<T>>,
+) -> Result<RegistryVersion, ValidatorError> {
+    let height = share.height();
+    let previous_beacon = get_previous_beacon(pool, height)?;
+    verify_notary(membership, height, &previous_beacon, share.signature.signer)?;
+    get_registry_version(pool, height)
+}
+
+/// The maximum number of equivocation proofs a block may include.
+pub(crate) const MAX_EQUIVOCATION_PROOFS_PER_BLOCK: usize = 1;
+
+/// Checks that the given equivocation proof consists of two different block
+/// proposals of the same signer at the same height, and that both are signed
+/// by the block maker of the rank they claim. The errors are returned in their
+/// debug representation.
+pub(crate) fn validate_equivocation_proof(
+    membership: &Membership,
+    crypto: &dyn ConsensusCrypto,
+    pool: &PoolReader<'_>,
+    proof: &EquivocationProof,
+) -> ValidationResult<ValidationError<String, String>> {
+    let (first, second) = proof.proposals();
+    if first.signature.signer != second.signature.signer {
+        return Err(ValidationError::Permanent(
+            "Proposals of different signers".to_string(),
+        ));
+    }
+    if first.height() != second.height() {
+        return Err(ValidationError::Permanent(
+            "Proposals at different heights".to_string(),
+        ));
+    }
+    if first.content.get_hash() == second.content.get_hash() {
+        return Err(ValidationError::Permanent(
+            "Identical proposals".to_string(),
+        ));
+    }
+    if !proof.is_well_formed() {
+        return Err(ValidationError::Permanent(
+            "Malformed equivocation proof".to_string(),
+        ));
+    }
+    for proposal in &[first, second] {
+        if !proposal.check_integrity() {
+            return Err(ValidationError::Permanent(
+                "Proposal integrity check failed".to_string(),
+            ));
+        }
+        proposal
+            .verify_signature(membership, crypto, pool)
+            .map_err(|err| err.map(|err| format!("{:?}", err), |err| format!("{:?}", err)))?;
+    }
+    Ok(())
+}
+
+/// Returns true if the given payloads include a proof that the signer of the
+/// given proof equivocated at the same height.
+fn contains_equivocation(payloads: &[(Height, Time, Payload)], proof: &EquivocationProof) -> bool {
+    payloads.iter().any(|(_, _, payload)| {
+        !payload.is_summary()
+            && payload
+                .as_ref()
+                .as_batch_payload()
+                .equivocation_proofs
+                .iter()
+                .any(|included| {
+                    included.signer() == proof.signer() && included.height() == proof.height()
+                })
+    })
+}
+
 fn get_previous_beacon(
     pool: &PoolReader<'_>,
     height: Height,
@@ -593,7 +697,7 @@ impl Validator {
             None => return ChangeSet::new(),
         };
 
-        let range = HeightRange::new(pool_reader.get_finalized_height().increment(), max_height);
+        let range = HeightRange::above(pool_reader.get_finalized_height(), max_height);
         let finalizations = pool_reader
             .pool()
             .unvalidated()
@@ -619,16 +723,14 @@ impl Validator {
             None => return ChangeSet::new(),
         };
 
-        let range = HeightRange::new(pool_reader.get_finalized_height().increment(), max_height);
+        let range = HeightRange::above(pool_reader.get_finalized_height(), max_height);
         let finalization_shares = pool_reader
             .pool()
             .unvalidated()
             .finalization_share()
             .get_by_height_range(range);
 
-        finalization_shares
-            .filter_map(|share| self.validate_notary_issued(pool_reader, share))
-            .collect()
+        self.validate_notary_issued_shares(pool_reader, finalization_shares)
     }
 
     /// Return a `ChangeSet` of `Notarization`s. See
@@ -639,7 +741,7 @@ impl Validator {
             None => return ChangeSet::new(),
         };
 
-        let range = HeightRange::new(pool_reader.get_finalized_height().increment(), max_height);
+        let range = HeightRange::above(pool_reader.get_finalized_height(), max_height);
         let notarizations = pool_reader
             .pool()
             .unvalidated()
@@ -665,16 +767,14 @@ impl Validator {
             None => return ChangeSet::new(),
         };
 
-        let range = HeightRange::new(pool_reader.get_finalized_height().increment(), max_height);
+        let range = HeightRange::above(pool_reader.get_finalized_height(), max_height);
         let notarization_shares = pool_reader
             .pool()
             .unvalidated()
             .notarization_share()
             .get_by_height_range(range);
 
-        notarization_shares
-            .filter_map(|share| self.validate_notary_issued(pool_reader, share))
-            .collect()
+        self.validate_notary_issued_shares(pool_reader, notarization_shares)
     }
 
     /// Validate a single `Signed`, `NotaryIssued` value. This involves checking
@@ -707,6 +807,79 @@ impl Validator {
         }
     }
 
+    /// Validate the given `NotarizationShare`s or `FinalizationShare`s. The
+    /// same checks as in `validate_notary_issued` are made, but the signatures
+    /// of the shares with the same content are verified in a batch. Only if
+    /// the batch verification of some content fails, its shares are verified
+    /// individually, to find the invalid ones.
+    fn validate_notary_issued_shares<T>(
+        &self,
+        pool_reader: &PoolReader<'_>,
+        shares: Box<dyn Iterator<Item = Signed<T, MultiSignatureShare<T>>>>,
+    ) -> ChangeSet
+    where
+        Signed<T, MultiSignatureShare<T>>: ConsensusMessageHashable + Clone,
+        T: NotaryIssued + Clone + Ord,
+    {
+        let mut change_set = ChangeSet::new();
+        let mut batches: BTreeMap<(T, RegistryVersion), Vec<Signed<T, MultiSignatureShare<T>>>> =
+            BTreeMap::new();
+        for share in shares {
+            // This is checked before entering this function.
+            debug_assert!(share.height() > pool_reader.get_finalized_height());
+            if share.content.is_duplicate(pool_reader) {
+                change_set.push(ChangeAction::RemoveFromUnvalidated(share.into_message()));
+            } else if share.content.dependencies_validated(pool_reader) {
+                match verify_notary_issued_share_signer(
+                    self.membership.as_ref(),
+                    pool_reader,
+                    &share,
+                ) {
+                    Ok(registry_version) => batches
+                        .entry((share.content.clone(), registry_version))
+                        .or_default()
+                        .push(share),
+                    Err(err) => change_set.extend(
+                        self.compute_action_from_sig_verification(Err(err), share.into_message()),
+                    ),
+                }
+            }
+        }
+
+        for ((content, registry_version), shares) in batches {
+            let signatures: Vec<_> = shares.iter().map(|share| &share.signature).collect();
+            let batch_verified = signatures.len() > 1
+                && T::verify_multi_sig_individual_batch(
+                    self.crypto.as_ref(),
+                    &content,
+                    &signatures,
+                    registry_version,
+                )
+                .is_ok();
+            if signatures.len() > 1 && !batch_verified {
+                self.metrics.batch_verification_failures.inc();
+                debug!(
+                    self.log,
+                    "Batch verification of {} shares failed at height {:?}, verifying them individually",
+                    shares.len(),
+                    content.height()
+                );
+            }
+            for share in shares {
+                let verification = if batch_verified {
+                    Ok(())
+                } else {
+                    T::verify_multi_sig_individual(self.crypto.as_ref(), &share, registry_version)
+                        .map_err(ValidatorError::from)
+                };
+                change_set.extend(
+                    self.compute_action_from_sig_verification(verification, share.into_message()),
+                );
+            }
+        }
+        change_set
+    }
+
     /// Return a `ChangeSet` containing status updates concerning any currently
     /// unvalidated blocks that can now be marked valid or invalid. See
     /// `check_block_validity`.
@@ -716,7 +889,7 @@ impl Validator {
         let beacon_height = pool_reader.get_random_beacon_height();
         let finalized_height = pool_reader.get_finalized_height();
         let max_height = beacon_height.increment();
-        let range = HeightRange::new(finalized_height.increment(), max_height);
+        let range = HeightRange::above(finalized_height, max_height);
         // Collect the min of validated block proposal ranks in the range.
         let mut known_ranks: BTreeMap<Height, Option<Rank>> =
             get_min_validated_ranks(pool_reader, &range);
@@ -843,6 +1016,9 @@ impl Validator {
     /// - The signature on the `BlockProposal` is invalid.
     /// - Any messages included in the payload are present in some ancestor of
     ///   the block
+    /// - Any equivocation proof included in the payload is invalid, or proves
+    ///   an equivocation that is already included in some ancestor of the
+    ///   block
     /// - Any of the values in the `ValidationContext` on the `Block` are less
     ///   than the corresponding value on the parent `Block`'s
     ///   `ValidationContext`.
@@ -907,6 +1083,8 @@ impl Validator {
             parent.clone(),
         );
 
+        self.check_equivocation_proofs(pool_reader, proposal, &payloads)?;
+
         self.payload_builder
             .validate_payload(&proposal.payload, &payloads, &proposal.context)
             .map_err(|err| {
@@ -944,6 +1122,53 @@ impl Validator {
         ret
     }
 
+    /// Check the equivocation proofs included in the given block. A block may
+    /// include a limited number of proofs of proposals above the certified
+    /// height of its validation context and below its own height, which are
+    /// not included in any ancestor of the block since the certified height.
+    fn check_equivocation_proofs(
+        &self,
+        pool_reader: &PoolReader<'_>,
+        block: &Block,
+        payloads: &[(Height, Time, Payload)],
+    ) -> ValidationResult<ValidatorError> {
+        if block.payload.is_summary() {
+            return Ok(());
+        }
+        let proofs = &block
+            .payload
+            .as_ref()
+            .as_batch_payload()
+            .equivocation_proofs;
+        if proofs.len() > MAX_EQUIVOCATION_PROOFS_PER_BLOCK {
+            Err(PermanentError::TooManyEquivocationProofs(proofs.len()))?
+        }
+        for proof in proofs {
+            if proof.height() <= block.context.certified_height || proof.height() >= block.height {
+                Err(PermanentError::EquivocationProofOutOfRange(proof.height()))?
+            }
+            if contains_equivocation(payloads, proof) {
+                Err(PermanentError::DuplicateEquivocationProof(
+                    proof.signer(),
+                    proof.height(),
+                ))?
+            }
+            validate_equivocation_proof(
+                self.membership.as_ref(),
+                self.crypto.as_ref(),
+                pool_reader,
+                proof,
+            )
+            .map_err(|err| {
+                err.map(
+                    PermanentError::InvalidEquivocationProof,
+                    TransientError::EquivocationProofNotVerifiable,
+                )
+            })?;
+        }
+        Ok(())
+    }
+
     /// Return a `ChangeSet` of `RandomBeacon` artifacts. See
     /// `validate_beacon_artifacts` for details about exactly what is checked.
     fn validate_beacons(&self, pool_reader: &PoolReader<'_>) -> ChangeSet {
@@ -1111,7 +1336,7 @@ impl Validator {
             Some(height) => height,
             None => return ChangeSet::new(),
         };
-        let range = HeightRange::new(catch_up_height.increment(), max_height);
+        let range = HeightRange::above(catch_up_height, max_height);
 
         let catch_up_packages = pool_reader
             .pool()
@@ -1166,7 +1391,7 @@ impl Validator {
             Some(height) => height,
             None => return ChangeSet::new(),
         };
-        let range = HeightRange::new(catch_up_height.increment(), max_height);
+        let range = HeightRange::above(catch_up_height, max_height);
 
         let shares = pool_reader
             .pool()
@@ -1312,7 +1537,7 @@ pub(crate) fn maliciously_validate_all_blocks(
     let finalized_height = pool_reader.get_finalized_height();
     let beacon_height = pool_reader.get_random_beacon_height();
     let max_height = beacon_height.increment();
-    let range = HeightRange::new(finalized_height.increment(), max_height);
+    let range = HeightRange::above(finalized_height, max_height);
 
     for proposal in pool_reader
         .pool()
@@ -2098,6 +2323,182 @@ pub mod test {
         })
     }
 
+    #[test]
+    fn test_check_equivocation_proofs() {
+        ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
+            let committee: Vec<_> = (0..4).map(node_test_id).collect();
+            let (
+                payload_builder,
+                membership,
+                state_manager,
+                message_routing,
+                crypto,
+                _data_provider,
+                registry_client,
+                mut pool,
+                dkg_pool,
+                time_source,
+                replica_config,
+            ) = setup_dependencies(pool_config, &committee);
+            pool.insert_beacon_chain(&pool.make_next_beacon(), Height::from(5));
+            let block_chain = pool.insert_block_chain(Height::from(5));
+            let pool_reader = PoolReader::new(&pool);
+
+            // Two proposals of the block maker of the given rank at the height
+            // after the given parent, that differ in their time.
+            let proposal = |parent: &BlockProposal, rank: Rank, signer: Option<NodeId>, nanos| {
+                let mut block: Block = pool.make_next_block_from_parent(parent.as_ref()).into();
+                block.rank = rank;
+                block.context.time += std::time::Duration::from_nanos(nanos);
+                let signer = signer.unwrap_or_else(|| {
+                    get_block_maker_by_rank(
+                        membership.borrow(),
+                        &pool_reader,
+                        block.height(),
+                        &committee,
+                        rank,
+                    )
+                });
+                BlockProposal::fake(block, signer)
+            };
+            let proof = EquivocationProof::new(
+                proposal(&block_chain[1], Rank(0), None, 0),
+                proposal(&block_chain[1], Rank(0), None, 1),
+            )
+            .unwrap();
+            assert_eq!(proof.height(), Height::from(3));
+
+            let validator = Validator::new(
+                replica_config,
+                membership.clone(),
+                registry_client,
+                crypto,
+                payload_builder,
+                state_manager,
+                message_routing,
+                dkg_pool,
+                no_op_logger(),
+                ValidatorMetrics::new(MetricsRegistry::new()),
+                Arc::clone(&time_source) as Arc<_>,
+            );
+            let block_with_proofs = |certified_height: u64, proofs: Vec<EquivocationProof>| {
+                let mut block: Block = pool
+                    .make_next_block_from_parent(block_chain.last().unwrap().as_ref())
+                    .into();
+                block.context.certified_height = Height::from(certified_height);
+                let dealings = BlockPayload::from(block.payload.clone()).into_dealings();
+                let batch = BatchPayload {
+                    equivocation_proofs: proofs,
+                    ..BatchPayload::default()
+                };
+                block.payload = Payload::new(ic_crypto::crypto_hash, (batch, dealings).into());
+                block
+            };
+            let check = |block: &Block, payloads: &[(Height, Time, Payload)]| {
+                validator.check_equivocation_proofs(&pool_reader, block, payloads)
+            };
+
+            // A valid proof above the certified height is accepted.
+            let block = block_with_proofs(1, vec![proof.clone()]);
+            assert!(check(&block, &[]).is_ok());
+
+            // A proof at or below the certified height is rejected.
+            let block = block_with_proofs(3, vec![proof.clone()]);
+            assert!(matches!(
+                check(&block, &[]),
+                Err(ValidationError::Permanent(
+                    PermanentError::EquivocationProofOutOfRange(_)
+                ))
+            ));
+
+            // A proof that is already included in a past payload is rejected.
+            let past_payload = block_with_proofs(1, vec![proof.clone()]).payload;
+            let block = block_with_proofs(1, vec![proof.clone()]);
+            assert!(matches!(
+                check(
+                    &block,
+                    &[(
+                        Height::from(4),
+                        ic_test_utilities::mock_time(),
+                        past_payload
+                    )]
+                ),
+                Err(ValidationError::Permanent(
+                    PermanentError::DuplicateEquivocationProof(_, _)
+                ))
+            ));
+
+            // More proofs than a block may include are rejected.
+            let other_proof = EquivocationProof::new(
+                proposal(&block_chain[2], Rank(0), None, 0),
+                proposal(&block_chain[2], Rank(0), None, 1),
+            )
+            .unwrap();
+            let block = block_with_proofs(1, vec![proof.clone(), other_proof]);
+            assert!(matches!(
+                check(&block, &[]),
+                Err(ValidationError::Permanent(
+                    PermanentError::TooManyEquivocationProofs(2)
+                ))
+            ));
+
+            // A proof of proposals that are not signed by the block maker of
+            // their rank is rejected.
+            let rank_0_maker = get_block_maker_by_rank(
+                membership.borrow(),
+                &pool_reader,
+                Height::from(3),
+                &committee,
+                Rank(0),
+            );
+            let impostor = *committee.iter().find(|id| **id != rank_0_maker).unwrap();
+            let framing_proof = EquivocationProof::new(
+                proposal(&block_chain[1], Rank(0), Some(impostor), 0),
+                proposal(&block_chain[1], Rank(0), Some(impostor), 1),
+            )
+            .unwrap();
+            let block = block_with_proofs(1, vec![framing_proof]);
+            assert!(matches!(
+                check(&block, &[]),
+                Err(ValidationError::Permanent(
+                    PermanentError::InvalidEquivocationProof(_)
+                ))
+            ));
+        })
+    }
+
+    #[test]
+    fn test_equivocation_proof_decoding_checks_invariants() {
+        ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
+            let committee: Vec<_> = (0..4).map(node_test_id).collect();
+            let (_, _, _, _, _, _, _, mut pool, _, _, _) =
+                setup_dependencies(pool_config, &committee);
+            pool.advance_round_normal_operation_n(2);
+            let proposal = |signer: NodeId, nanos| {
+                let mut block: Block = pool.make_next_block().into();
+                block.context.time += std::time::Duration::from_nanos(nanos);
+                BlockProposal::fake(block, signer)
+            };
+            let decode = |first: &BlockProposal, second: &BlockProposal| {
+                serde_cbor::from_slice::<EquivocationProof>(
+                    &serde_cbor::to_vec(&(first, second)).unwrap(),
+                )
+            };
+
+            let first = proposal(node_test_id(0), 0);
+            let second = proposal(node_test_id(0), 1);
+            assert!(decode(&first, &second).is_ok());
+            // Two copies of the same proposal are not an equivocation.
+            assert!(decode(&first, &first).is_err());
+            // Neither are proposals of different signers.
+            assert!(decode(&first, &proposal(node_test_id(1), 1)).is_err());
+            // Nor proposals at different heights.
+            let mut other_height: Block = second.as_ref().clone();
+            other_height.height = other_height.height.increment();
+            assert!(decode(&first, &BlockProposal::fake(other_height, node_test_id(0))).is_err());
+        })
+    }
+
     // utility function to determine the identity of the block maker with the
     // specified rank at a given height. Panics if this rank does not exist.
     fn get_block_maker_by_rank(
@@ -2375,6 +2776,64 @@ pub mod test {
         })
     }
 
+    #[test]
+    fn test_notarization_shares_validated_in_batch() {
+        ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
+            let (
+                payload_builder,
+                membership,
+                state_manager,
+                message_routing,
+                crypto,
+                _data_provider,
+                registry_client,
+                mut pool,
+                dkg_pool,
+                time_source,
+                replica_config,
+            ) = setup_dependencies(pool_config, &(0..4).map(node_test_id).collect::<Vec<_>>());
+            let block = pool.make_next_block();
+            pool.insert_validated(block.clone());
+            for i in 0..4 {
+                pool.insert_unvalidated(NotarizationShare::fake(block.as_ref(), node_test_id(i)));
+            }
+            // A share of a node that is not on the subnet.
+            pool.insert_unvalidated(NotarizationShare::fake(block.as_ref(), node_test_id(10)));
+
+            let validator = Validator::new(
+                replica_config,
+                membership,
+                registry_client,
+                crypto,
+                payload_builder,
+                state_manager,
+                message_routing,
+                dkg_pool,
+                no_op_logger(),
+                ValidatorMetrics::new(MetricsRegistry::new()),
+                Arc::clone(&time_source) as Arc<_>,
+            );
+
+            let changeset = validator.validate_notarization_shares(&PoolReader::new(&pool));
+            assert_eq!(changeset.len(), 5);
+            let validated = changeset
+                .iter()
+                .filter(|action| {
+                    matches!(
+                        action,
+                        ChangeAction::MoveToValidated(ConsensusMessage::NotarizationShare(_))
+                    )
+                })
+                .count();
+            assert_eq!(validated, 4);
+            assert!(changeset.iter().any(|action| matches!(
+                action,
+                ChangeAction::HandleInvalid(ConsensusMessage::NotarizationShare(share), _)
+                    if share.signature.signer == node_test_id(10)
+            )));
+        })
+    }
+
     #[test]
     fn test_notarization_deduped_by_content() {
         ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
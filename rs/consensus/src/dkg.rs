@@ -3,6 +3,7 @@
 //! crate.
 
 use crate::consensus::{crypto::ConsensusCrypto, pool_reader::PoolReader};
+use ic_config::consensus::ConsensusConfig;
 use ic_crypto::crypto_hash;
 use ic_interfaces::{
     consensus_pool::ConsensusPoolCache,
@@ -162,6 +163,9 @@ pub struct DkgImpl {
     node_id: NodeId,
     crypto: Arc<dyn ConsensusCrypto>,
     consensus_cache: Arc<dyn ConsensusPoolCache>,
+    // The threads that verify the dealings, so that the validation of the
+    // dealings of large subnets does not occupy the global rayon pool.
+    validation_pool: rayon::ThreadPool,
     logger: ReplicaLogger,
     metrics: Metrics,
 }
@@ -170,17 +174,25 @@ pub struct DkgImpl {
 pub struct DkgGossipImpl {}
 
 impl DkgImpl {
-    /// Build a new DKG component
+    /// Build a new DKG component. The dealings are verified on as many threads
+    /// as configured in the given [ConsensusConfig].
     pub fn new(
         node_id: NodeId,
         crypto: Arc<dyn ConsensusCrypto>,
         consensus_cache: Arc<dyn ConsensusPoolCache>,
+        consensus_config: &ConsensusConfig,
         metrics_registry: ic_metrics::MetricsRegistry,
         logger: ReplicaLogger,
     ) -> Self {
+        let validation_pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(consensus_config.dkg_validation_threads())
+            .thread_name(|index| format!("dkg_validation_{}", index))
+            .build()
+            .expect("Couldn't build the DKG validation thread pool");
         Self {
             crypto,
             consensus_cache,
+            validation_pool,
             node_id,
             logger,
             metrics: Metrics {
@@ -1179,20 +1191,20 @@ impl Dkg for DkgImpl {
             .cloned()
             .collect();
 
-        let changeset = dealings
-            .par_iter()
-            .map(|dealings| {
-                self.validate_dealings(
-                    dkg_pool,
-                    &dkg_summary.configs,
-                    start_height,
-                    dealings.to_vec(),
-                )
-            })
-            .collect::<Vec<ChangeSet>>()
-            .into_iter()
-            .flatten()
-            .collect::<ChangeSet>();
+        let changeset = self.validation_pool.install(|| {
+            dealings
+                .par_iter()
+                .map(|dealings| {
+                    self.validate_dealings(
+                        dkg_pool,
+                        &dkg_summary.configs,
+                        start_height,
+                        dealings.to_vec(),
+                    )
+                })
+                .collect::<Vec<ChangeSet>>()
+        });
+        let changeset = changeset.into_iter().flatten().collect::<ChangeSet>();
 
         self.metrics
             .on_state_change_processed
@@ -1454,6 +1466,7 @@ mod tests {
                     replica_1,
                     crypto.clone(),
                     pool.get_cache(),
+                    &ConsensusConfig::default(),
                     MetricsRegistry::new(),
                     logger.clone(),
                 );
@@ -1517,6 +1530,7 @@ mod tests {
                     replica_2,
                     crypto,
                     pool.get_cache(),
+                    &ConsensusConfig::default(),
                     MetricsRegistry::new(),
                     logger,
                 );
@@ -1950,6 +1964,7 @@ mod tests {
                     node_test_id(3),
                     crypto.clone(),
                     pool.get_cache(),
+                    &ConsensusConfig::default(),
                     MetricsRegistry::new(),
                     logger.clone(),
                 );
@@ -1960,6 +1975,7 @@ mod tests {
                     node_test_id(1),
                     crypto,
                     pool.get_cache(),
+                    &ConsensusConfig::default(),
                     MetricsRegistry::new(),
                     logger,
                 );
@@ -2076,6 +2092,7 @@ mod tests {
                     node_test_id(1),
                     crypto,
                     pool.get_cache(),
+                    &ConsensusConfig::default(),
                     MetricsRegistry::new(),
                     logger,
                 );
@@ -2217,6 +2234,7 @@ mod tests {
                         node_id_1,
                         crypto.clone(),
                         consensus_pool_1.get_cache(),
+                        &ConsensusConfig::default(),
                         MetricsRegistry::new(),
                         logger.clone(),
                     );
@@ -2224,6 +2242,7 @@ mod tests {
                         node_id_2,
                         crypto,
                         consensus_pool_2.get_cache(),
+                        &ConsensusConfig::default(),
                         MetricsRegistry::new(),
                         logger,
                     );
@@ -2650,6 +2669,7 @@ mod tests {
                         node_test_id(1),
                         crypto_1,
                         pool_1.get_cache(),
+                        &ConsensusConfig::default(),
                         MetricsRegistry::new(),
                         logger.clone(),
                     );
@@ -2657,6 +2677,7 @@ mod tests {
                         node_test_id(2),
                         crypto_2,
                         pool_2.get_cache(),
+                        &ConsensusConfig::default(),
                         MetricsRegistry::new(),
                         logger,
                     );
@@ -3536,6 +3557,11 @@ mod tests {
         fn get_version_timestamp(&self, _: RegistryVersion) -> Option<Time> {
             None
         }
+
+        // Not needed for this test
+        fn subscribe(&self, _: &str) -> tokio::sync::watch::Receiver<RegistryVersion> {
+            tokio::sync::watch::channel(self.latest_registry_version).1
+        }
     }
 
     /// Creates a Protobuf `InitialNiDkgTranscriptRecord`. Used in the test
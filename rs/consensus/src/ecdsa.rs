@@ -0,0 +1,482 @@
+//! This module defines the threshold ECDSA component, which validates the
+//! dealings, signature shares and complaints of the threshold ECDSA protocol
+//! received from peers, contributes the dealings of this node, and purges the
+//! messages that are no longer needed.
+//!
+//! Every message is signed by the node that created it. The content of the
+//! messages cannot be verified until the threshold ECDSA crypto is available,
+//! so validation is limited to checking that the message is signed by the
+//! node it claims to be from, and that this node is in the committee of the
+//! subnet at the height the message was requested at. A node signs at most
+//! `MAX_ECDSA_MESSAGES_PER_SIGNER_AND_HEIGHT` messages per height, so the
+//! validated section is bounded by the heights between the catch-up package
+//! and the finalized block.
+//!
+//! This node deals for every quadruple a peer has dealt for, once the
+//! requesting block is finalized. Until the crypto is available, the dealing
+//! carries no key material.
+use crate::consensus::{utils::registry_version_at_height, ConsensusCrypto, Membership};
+use ic_interfaces::{
+    consensus_pool::ConsensusPoolCache,
+    crypto::ErrorReplication,
+    ecdsa::{Ecdsa, EcdsaChangeAction, EcdsaChangeSet, EcdsaGossip, EcdsaPool},
+};
+use ic_logger::{debug, warn, ReplicaLogger};
+use ic_types::{
+    artifact::{EcdsaMessageAttribute, EcdsaMessageId, Priority, PriorityFn},
+    consensus::{
+        ecdsa::{
+            EcdsaDealing, EcdsaMessage, EcdsaMessageContent, QuadrupleId,
+            MAX_ECDSA_MESSAGES_PER_SIGNER_AND_HEIGHT,
+        },
+        Committee, HasHeight,
+    },
+    Height, NodeId,
+};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::sync::Arc;
+
+/// Implements the `Ecdsa` trait.
+pub struct EcdsaImpl {
+    node_id: NodeId,
+    consensus_cache: Arc<dyn ConsensusPoolCache>,
+    crypto: Arc<dyn ConsensusCrypto>,
+    membership: Arc<Membership>,
+    log: ReplicaLogger,
+}
+
+impl EcdsaImpl {
+    /// Build a new threshold ECDSA component.
+    pub fn new(
+        node_id: NodeId,
+        consensus_cache: Arc<dyn ConsensusPoolCache>,
+        crypto: Arc<dyn ConsensusCrypto>,
+        membership: Arc<Membership>,
+        log: ReplicaLogger,
+    ) -> Self {
+        Self {
+            node_id,
+            consensus_cache,
+            crypto,
+            membership,
+            log,
+        }
+    }
+
+    /// Returns true if the given node is in the committee at the given
+    /// height, or None if the committee is not known.
+    fn is_committee_member(&self, node_id: NodeId, height: Height) -> Option<bool> {
+        self.membership
+            .node_belongs_to_threshold_committee(node_id, height, Committee::HighThreshold)
+            .map_err(|err| {
+                debug!(
+                    self.log,
+                    "Couldn't check committee membership at height {:?}: {:?}", height, err
+                )
+            })
+            .ok()
+    }
+
+    /// Signs the given content, or returns None if it could not be signed.
+    fn sign(&self, content: EcdsaMessageContent) -> Option<EcdsaMessage> {
+        let height = content.height();
+        let registry_version = registry_version_at_height(self.consensus_cache.as_ref(), height)?;
+        match self.crypto.sign(&content, self.node_id, registry_version) {
+            Ok(signature) => Some(EcdsaMessage { content, signature }),
+            Err(err) => {
+                warn!(
+                    self.log,
+                    "Couldn't sign the ECDSA message at height {:?}: {:?}", height, err
+                );
+                None
+            }
+        }
+    }
+
+    /// Creates the dealings of this node for the quadruples that peers have
+    /// dealt for, as long as this node has not reached its message limit at
+    /// the requested height.
+    fn create_dealings(
+        &self,
+        ecdsa_pool: &dyn EcdsaPool,
+        purge_height: Height,
+        finalized_height: Height,
+        counts: &mut BTreeMap<(NodeId, Height), usize>,
+    ) -> EcdsaChangeSet {
+        let mut dealt = BTreeSet::new();
+        let mut requested = BTreeSet::new();
+        for msg in ecdsa_pool.get_validated() {
+            if let EcdsaMessageContent::EcdsaDealing(dealing) = &msg.content {
+                let key = (dealing.requested_height, dealing.quadruple_id);
+                if dealing.dealer == self.node_id {
+                    dealt.insert(key);
+                } else if purge_height <= key.0 && key.0 <= finalized_height {
+                    requested.insert(key);
+                }
+            }
+        }
+
+        let mut change_set = Vec::new();
+        for (requested_height, quadruple_id) in requested.difference(&dealt).cloned() {
+            let count = counts.entry((self.node_id, requested_height)).or_insert(0);
+            if *count >= MAX_ECDSA_MESSAGES_PER_SIGNER_AND_HEIGHT
+                || self.is_committee_member(self.node_id, requested_height) != Some(true)
+            {
+                continue;
+            }
+            if let Some(msg) = self.sign(self.make_dealing(requested_height, quadruple_id)) {
+                *count += 1;
+                change_set.push(EcdsaChangeAction::AddToValidated(msg));
+            }
+        }
+        change_set
+    }
+
+    /// Returns the dealing of this node for the given quadruple.
+    fn make_dealing(
+        &self,
+        requested_height: Height,
+        quadruple_id: QuadrupleId,
+    ) -> EcdsaMessageContent {
+        EcdsaMessageContent::EcdsaDealing(EcdsaDealing {
+            requested_height,
+            quadruple_id,
+            dealer: self.node_id,
+            dealing: Vec::new(),
+        })
+    }
+
+    /// Validates the given message of a peer, or returns None if it cannot be
+    /// validated yet.
+    fn validate_message(
+        &self,
+        id: EcdsaMessageId,
+        msg: &EcdsaMessage,
+        count: usize,
+    ) -> Option<EcdsaChangeAction> {
+        let signer = msg.signature.signer;
+        let reason = if signer != msg.content.signer() {
+            Some("Message is not signed by the node it claims to be from")
+        } else if !self.is_committee_member(signer, msg.height())? {
+            Some("Signer does not belong to the committee")
+        } else if count >= MAX_ECDSA_MESSAGES_PER_SIGNER_AND_HEIGHT {
+            Some("Signer exceeded the number of messages per height")
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            return Some(EcdsaChangeAction::HandleInvalid(id, reason.to_string()));
+        }
+        let registry_version =
+            registry_version_at_height(self.consensus_cache.as_ref(), msg.height())?;
+        match self.crypto.verify(msg, registry_version) {
+            Ok(()) => Some(EcdsaChangeAction::MoveToValidated(msg.clone())),
+            Err(err) if err.is_replicated() => Some(EcdsaChangeAction::HandleInvalid(
+                id,
+                format!("Invalid signature: {:?}", err),
+            )),
+            Err(err) => {
+                debug!(
+                    self.log,
+                    "Couldn't verify the signature of the ECDSA message {:?}: {:?}", id, err
+                );
+                None
+            }
+        }
+    }
+
+    /// Validates the messages in the unvalidated section of the ECDSA pool.
+    /// Messages requested above the finalized height are kept until the
+    /// requesting block is finalized.
+    fn validate_messages(
+        &self,
+        ecdsa_pool: &dyn EcdsaPool,
+        purge_height: Height,
+        finalized_height: Height,
+        counts: &mut BTreeMap<(NodeId, Height), usize>,
+    ) -> EcdsaChangeSet {
+        let known: HashSet<EcdsaMessageId> = ecdsa_pool
+            .get_validated()
+            .map(ic_crypto::crypto_hash)
+            .collect();
+        let mut change_set = Vec::new();
+        for msg in ecdsa_pool.get_unvalidated() {
+            let id = ic_crypto::crypto_hash(msg);
+            if known.contains(&id) || msg.height() < purge_height {
+                change_set.push(EcdsaChangeAction::RemoveFromUnvalidated(id));
+                continue;
+            }
+            if msg.height() > finalized_height {
+                continue;
+            }
+            let count = counts
+                .entry((msg.signature.signer, msg.height()))
+                .or_insert(0);
+            let action = self.validate_message(id, msg, *count);
+            if let Some(EcdsaChangeAction::MoveToValidated(_)) = action {
+                *count += 1;
+            }
+            change_set.extend(action);
+        }
+        change_set
+    }
+}
+
+impl Ecdsa for EcdsaImpl {
+    fn on_state_change(&self, ecdsa_pool: &dyn EcdsaPool) -> EcdsaChangeSet {
+        let purge_height = self.consensus_cache.catch_up_package().height();
+        let finalized_height = self.consensus_cache.finalized_block().height;
+
+        // The number of validated messages of each signer by requested height.
+        let mut counts = BTreeMap::new();
+        for msg in ecdsa_pool.get_validated() {
+            *counts
+                .entry((msg.signature.signer, msg.height()))
+                .or_insert(0) += 1;
+        }
+
+        let mut change_set =
+            self.validate_messages(ecdsa_pool, purge_height, finalized_height, &mut counts);
+        change_set.extend(self.create_dealings(
+            ecdsa_pool,
+            purge_height,
+            finalized_height,
+            &mut counts,
+        ));
+        if ecdsa_pool
+            .get_validated()
+            .any(|msg| msg.height() < purge_height)
+        {
+            change_set.push(EcdsaChangeAction::PurgeBelow(purge_height));
+        }
+        change_set
+    }
+}
+
+/// Implements the `EcdsaGossip` trait.
+pub struct EcdsaGossipImpl {
+    consensus_cache: Arc<dyn ConsensusPoolCache>,
+}
+
+impl EcdsaGossipImpl {
+    /// Build a new threshold ECDSA gossip component.
+    pub fn new(consensus_cache: Arc<dyn ConsensusPoolCache>) -> Self {
+        Self { consensus_cache }
+    }
+}
+
+impl EcdsaGossip for EcdsaGossipImpl {
+    /// Messages requested below the catch-up package height are dropped, and
+    /// messages requested above the finalized height are stashed until the
+    /// requesting block is finalized.
+    fn get_priority_function(
+        &self,
+        _ecdsa_pool: &dyn EcdsaPool,
+    ) -> PriorityFn<EcdsaMessageId, EcdsaMessageAttribute> {
+        let purge_height = self.consensus_cache.catch_up_package().height();
+        let finalized_height = self.consensus_cache.finalized_block().height;
+        Box::new(move |_id, attribute| {
+            if attribute.height < purge_height {
+                Priority::Drop
+            } else if attribute.height > finalized_height {
+                Priority::Stash
+            } else {
+                Priority::Fetch
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::mocks::{dependencies, Dependencies};
+    use ic_artifact_pool::ecdsa_pool::EcdsaPoolImpl;
+    use ic_interfaces::{
+        artifact_pool::UnvalidatedArtifact, ecdsa::MutableEcdsaPool, gossip_pool::GossipPool,
+    };
+    use ic_logger::replica_logger::no_op_logger;
+    use ic_metrics::MetricsRegistry;
+    use ic_test_utilities::{mock_time, types::ids::node_test_id};
+    use ic_types::{
+        consensus::{ecdsa::EcdsaSigShare, BasicSignature},
+        crypto::{BasicSig, BasicSigOf},
+    };
+
+    fn sign(content: EcdsaMessageContent, signer: u64) -> EcdsaMessage {
+        EcdsaMessage {
+            content,
+            signature: BasicSignature {
+                signature: BasicSigOf::new(BasicSig(vec![])),
+                signer: node_test_id(signer),
+            },
+        }
+    }
+
+    fn make_share(height: Height, quadruple_id: u64, signer: u64) -> EcdsaMessage {
+        sign(
+            EcdsaMessageContent::EcdsaSigShare(EcdsaSigShare {
+                requested_height: height,
+                quadruple_id: QuadrupleId(quadruple_id),
+                signer: node_test_id(signer),
+                share: vec![],
+            }),
+            signer,
+        )
+    }
+
+    fn make_dealing(height: Height, quadruple_id: u64, dealer: u64) -> EcdsaMessage {
+        sign(
+            EcdsaMessageContent::EcdsaDealing(EcdsaDealing {
+                requested_height: height,
+                quadruple_id: QuadrupleId(quadruple_id),
+                dealer: node_test_id(dealer),
+                dealing: vec![],
+            }),
+            dealer,
+        )
+    }
+
+    fn insert(ecdsa_pool: &mut EcdsaPoolImpl, message: EcdsaMessage) {
+        ecdsa_pool.insert(UnvalidatedArtifact {
+            message,
+            peer_id: node_test_id(1),
+            timestamp: mock_time(),
+        });
+    }
+
+    #[test]
+    fn test_ecdsa_validation() {
+        ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
+            let Dependencies {
+                mut pool,
+                crypto,
+                membership,
+                ..
+            } = dependencies(pool_config, 4);
+            let finalized_height = pool.advance_round_normal_operation_n(2);
+            let mut ecdsa_pool = EcdsaPoolImpl::new(MetricsRegistry::new());
+            let valid = make_share(finalized_height, 0, 1);
+            let not_member = make_share(finalized_height, 0, 9);
+            let mut forged = make_share(finalized_height, 1, 1);
+            forged.signature.signer = node_test_id(2);
+            let future = make_share(finalized_height.increment(), 0, 1);
+            for message in vec![valid.clone(), not_member.clone(), forged.clone(), future] {
+                insert(&mut ecdsa_pool, message);
+            }
+
+            let ecdsa = EcdsaImpl::new(
+                node_test_id(0),
+                pool.get_cache(),
+                crypto,
+                membership,
+                no_op_logger(),
+            );
+            let change_set = ecdsa.on_state_change(&ecdsa_pool);
+            assert_eq!(change_set.len(), 3);
+            for action in change_set {
+                match action {
+                    EcdsaChangeAction::MoveToValidated(msg) => assert_eq!(msg, valid),
+                    EcdsaChangeAction::HandleInvalid(id, _) => assert!(
+                        id == ic_crypto::crypto_hash(&not_member)
+                            || id == ic_crypto::crypto_hash(&forged)
+                    ),
+                    action => panic!("Unexpected change action {:?}", action),
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn test_ecdsa_messages_per_signer_and_height_are_bounded() {
+        ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
+            let Dependencies {
+                mut pool,
+                crypto,
+                membership,
+                ..
+            } = dependencies(pool_config, 4);
+            let finalized_height = pool.advance_round_normal_operation_n(2);
+            let mut ecdsa_pool = EcdsaPoolImpl::new(MetricsRegistry::new());
+            // Signer 1 already reached its limit at the finalized height.
+            ecdsa_pool.apply_changes(
+                (0..MAX_ECDSA_MESSAGES_PER_SIGNER_AND_HEIGHT as u64)
+                    .map(|quadruple_id| {
+                        EcdsaChangeAction::AddToValidated(make_share(
+                            finalized_height,
+                            quadruple_id,
+                            1,
+                        ))
+                    })
+                    .collect(),
+            );
+            let excess = make_share(
+                finalized_height,
+                MAX_ECDSA_MESSAGES_PER_SIGNER_AND_HEIGHT as u64,
+                1,
+            );
+            // Another signer is not affected by the limit.
+            let other = make_share(finalized_height, 0, 2);
+            insert(&mut ecdsa_pool, excess.clone());
+            insert(&mut ecdsa_pool, other.clone());
+
+            let ecdsa = EcdsaImpl::new(
+                node_test_id(0),
+                pool.get_cache(),
+                crypto,
+                membership,
+                no_op_logger(),
+            );
+            let change_set = ecdsa.on_state_change(&ecdsa_pool);
+            assert_eq!(change_set.len(), 2);
+            for action in change_set {
+                match action {
+                    EcdsaChangeAction::MoveToValidated(msg) => assert_eq!(msg, other),
+                    EcdsaChangeAction::HandleInvalid(id, _) => {
+                        assert_eq!(id, ic_crypto::crypto_hash(&excess))
+                    }
+                    action => panic!("Unexpected change action {:?}", action),
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn test_ecdsa_deals_for_quadruples_of_peers() {
+        ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
+            let Dependencies {
+                mut pool,
+                crypto,
+                membership,
+                ..
+            } = dependencies(pool_config, 4);
+            let finalized_height = pool.advance_round_normal_operation_n(2);
+            let mut ecdsa_pool = EcdsaPoolImpl::new(MetricsRegistry::new());
+            let peer_dealing = make_dealing(finalized_height, 7, 1);
+            insert(&mut ecdsa_pool, peer_dealing.clone());
+
+            let ecdsa = EcdsaImpl::new(
+                node_test_id(0),
+                pool.get_cache(),
+                crypto,
+                membership,
+                no_op_logger(),
+            );
+            // The dealing of the peer is validated first.
+            let change_set = ecdsa.on_state_change(&ecdsa_pool);
+            assert_eq!(change_set.len(), 1);
+            ecdsa_pool.apply_changes(change_set);
+
+            // This node then deals for the same quadruple, once.
+            let change_set = ecdsa.on_state_change(&ecdsa_pool);
+            let own_dealing = make_dealing(finalized_height, 7, 0);
+            match change_set.as_slice() {
+                [EcdsaChangeAction::AddToValidated(msg)] => assert_eq!(*msg, own_dealing),
+                actions => panic!("Unexpected change actions {:?}", actions),
+            }
+            ecdsa_pool.apply_changes(change_set);
+            assert!(ecdsa_pool.contains(&ic_crypto::crypto_hash(&own_dealing)));
+            assert!(ecdsa.on_state_change(&ecdsa_pool).is_empty());
+        })
+    }
+}
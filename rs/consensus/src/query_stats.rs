@@ -0,0 +1,405 @@
+//! This module defines the query statistics component, which creates the
+//! report of this replica on the queries it executed once an epoch is over,
+//! validates the reports received from peers, and purges the ones of past
+//! epochs.
+//!
+//! The epoch of a report is over once the certified height of the finalized
+//! block is in a later epoch. Reports are only valid and included in blocks
+//! during the epoch right after their own, see [payload_builder]. Every
+//! replica reports at most once per epoch, and its statistics are aggregated
+//! in the replicated state once the reports of the next epoch arrive.
+use crate::consensus::ConsensusCrypto;
+use ic_interfaces::{
+    consensus_pool::ConsensusPoolCache,
+    crypto::ErrorReplication,
+    query_stats::{
+        QueryStatsChangeAction, QueryStatsChangeSet, QueryStatsGossip, QueryStatsHandler,
+        QueryStatsPayloadValidationError, QueryStatsPermanentValidationError, QueryStatsPool,
+        QueryStatsReader, QueryStatsTransientValidationError,
+    },
+    registry::RegistryClient,
+    validation::{ValidationError, ValidationResult},
+};
+use ic_logger::{debug, warn, ReplicaLogger};
+use ic_registry_client::helper::subnet::SubnetRegistry;
+use ic_types::{
+    artifact::{Priority, PriorityFn, QueryStatsMessageAttribute, QueryStatsMessageId},
+    consensus::query_stats::{
+        epoch_from_height, QueryStats, QueryStatsContent, QueryStatsEpoch, QueryStatsMessage,
+        MAX_QUERY_STATS_CANISTERS_PER_REPORT,
+    },
+    CanisterId, Height, NodeId, RegistryVersion, SubnetId,
+};
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+
+pub mod payload_builder;
+
+/// Returns the epoch whose reports are valid at the given certified height,
+/// i.e. the one before the epoch of the height, if any.
+pub fn reported_epoch(certified_height: Height) -> Option<QueryStatsEpoch> {
+    epoch_from_height(certified_height).checked_decrement()
+}
+
+/// Checks that the report is signed by one of the nodes of the subnet at the
+/// registry version of the report.
+fn check_report(
+    crypto: &dyn ConsensusCrypto,
+    registry_client: &dyn RegistryClient,
+    subnet_id: SubnetId,
+    report: &QueryStatsMessage,
+) -> ValidationResult<QueryStatsPayloadValidationError> {
+    let registry_version = report.content.registry_version;
+    let signer = report.signature.signer;
+    let nodes = registry_client
+        .get_node_ids_on_subnet(subnet_id, registry_version)
+        .map_err(|err| {
+            ValidationError::Transient(QueryStatsTransientValidationError::RegistryUnavailable(err))
+        })?
+        .ok_or(ValidationError::Transient(
+            QueryStatsTransientValidationError::SubnetNotFound(registry_version),
+        ))?;
+    if !nodes.contains(&signer) {
+        return Err(ValidationError::Permanent(
+            QueryStatsPermanentValidationError::SignerNotInSubnet(signer),
+        ));
+    }
+    let num_canisters = report.content.stats.len();
+    if num_canisters > MAX_QUERY_STATS_CANISTERS_PER_REPORT {
+        return Err(ValidationError::Permanent(
+            QueryStatsPermanentValidationError::TooManyCanisters(signer, num_canisters),
+        ));
+    }
+    crypto.verify(report, registry_version).map_err(|err| {
+        if err.is_replicated() {
+            ValidationError::Permanent(QueryStatsPermanentValidationError::InvalidSignature(
+                signer, err,
+            ))
+        } else {
+            ValidationError::Transient(QueryStatsTransientValidationError::Crypto(err))
+        }
+    })
+}
+
+/// Splits the given statistics into the ones of the
+/// `MAX_QUERY_STATS_CANISTERS_PER_REPORT` canisters with the most
+/// instructions, which are reported, and the others.
+fn split_report(
+    stats: BTreeMap<CanisterId, QueryStats>,
+) -> (
+    BTreeMap<CanisterId, QueryStats>,
+    BTreeMap<CanisterId, QueryStats>,
+) {
+    if stats.len() <= MAX_QUERY_STATS_CANISTERS_PER_REPORT {
+        return (stats, BTreeMap::new());
+    }
+    let mut stats: Vec<_> = stats.into_iter().collect();
+    stats.sort_by(|(_, a), (_, b)| b.num_instructions.cmp(&a.num_instructions));
+    let deferred = stats.split_off(MAX_QUERY_STATS_CANISTERS_PER_REPORT);
+    (stats.into_iter().collect(), deferred.into_iter().collect())
+}
+
+/// Implements the `QueryStatsHandler` trait.
+pub struct QueryStatsHandlerImpl {
+    node_id: NodeId,
+    consensus_cache: Arc<dyn ConsensusPoolCache>,
+    crypto: Arc<dyn ConsensusCrypto>,
+    registry_client: Arc<dyn RegistryClient>,
+    subnet_id: SubnetId,
+    /// The statistics of the queries executed by this replica. Without it,
+    /// the replica still validates the reports of its peers, but doesn't
+    /// report itself.
+    query_stats_reader: Option<Arc<dyn QueryStatsReader>>,
+    log: ReplicaLogger,
+}
+
+impl QueryStatsHandlerImpl {
+    /// Build a new query statistics component.
+    pub fn new(
+        node_id: NodeId,
+        consensus_cache: Arc<dyn ConsensusPoolCache>,
+        crypto: Arc<dyn ConsensusCrypto>,
+        registry_client: Arc<dyn RegistryClient>,
+        subnet_id: SubnetId,
+        query_stats_reader: Option<Arc<dyn QueryStatsReader>>,
+        log: ReplicaLogger,
+    ) -> Self {
+        Self {
+            node_id,
+            consensus_cache,
+            crypto,
+            registry_client,
+            subnet_id,
+            query_stats_reader,
+            log,
+        }
+    }
+
+    /// Creates the report of this replica for the given epoch from the
+    /// statistics collected since the last report. The statistics that are
+    /// not reported are returned to the reader, so that they are reported
+    /// later.
+    fn create_report(
+        &self,
+        epoch: QueryStatsEpoch,
+        registry_version: RegistryVersion,
+    ) -> Option<QueryStatsMessage> {
+        let query_stats_reader = self.query_stats_reader.as_ref()?;
+        let (stats, deferred) = split_report(query_stats_reader.take_stats());
+        if !deferred.is_empty() {
+            query_stats_reader.return_stats(deferred);
+        }
+        let content = QueryStatsContent {
+            epoch,
+            stats,
+            registry_version,
+        };
+        match self.crypto.sign(&content, self.node_id, registry_version) {
+            Ok(signature) => Some(QueryStatsMessage { content, signature }),
+            Err(err) => {
+                warn!(
+                    self.log,
+                    "Couldn't sign the query stats of epoch {:?}: {:?}", epoch, err
+                );
+                query_stats_reader.return_stats(content.stats);
+                None
+            }
+        }
+    }
+
+    /// Validates the report of a peer for the reported epoch.
+    fn validate_report(
+        &self,
+        id: QueryStatsMessageId,
+        report: &QueryStatsMessage,
+        registry_version: RegistryVersion,
+    ) -> Option<QueryStatsChangeAction> {
+        if report.content.registry_version > registry_version {
+            return None;
+        }
+        match check_report(
+            self.crypto.as_ref(),
+            self.registry_client.as_ref(),
+            self.subnet_id,
+            report,
+        ) {
+            Ok(()) => Some(QueryStatsChangeAction::MoveToValidated(report.clone())),
+            Err(ValidationError::Permanent(err)) => Some(QueryStatsChangeAction::HandleInvalid(
+                id,
+                format!("{:?}", err),
+            )),
+            Err(ValidationError::Transient(err)) => {
+                debug!(
+                    self.log,
+                    "Couldn't validate the query stats {:?}: {:?}", id, err
+                );
+                None
+            }
+        }
+    }
+}
+
+impl QueryStatsHandler for QueryStatsHandlerImpl {
+    fn on_state_change(&self, query_stats_pool: &dyn QueryStatsPool) -> QueryStatsChangeSet {
+        let finalized_block = self.consensus_cache.finalized_block();
+        let registry_version = finalized_block.context.registry_version;
+        let epoch = match reported_epoch(finalized_block.context.certified_height) {
+            Some(epoch) => epoch,
+            None => return Vec::new(),
+        };
+
+        let mut change_set = Vec::new();
+        let mut reported: HashSet<NodeId> = query_stats_pool
+            .get_validated()
+            .filter(|report| report.content.epoch == epoch)
+            .map(|report| report.signature.signer)
+            .collect();
+        if !reported.contains(&self.node_id) {
+            if let Some(report) = self.create_report(epoch, registry_version) {
+                reported.insert(self.node_id);
+                change_set.push(QueryStatsChangeAction::AddToValidated(report));
+            }
+        }
+
+        for report in query_stats_pool.get_unvalidated() {
+            let id = ic_crypto::crypto_hash(report);
+            if report.content.epoch > epoch {
+                continue;
+            }
+            if report.content.epoch < epoch || reported.contains(&report.signature.signer) {
+                change_set.push(QueryStatsChangeAction::RemoveFromUnvalidated(id));
+                continue;
+            }
+            let action = self.validate_report(id, report, registry_version);
+            if let Some(QueryStatsChangeAction::MoveToValidated(_)) = action {
+                reported.insert(report.signature.signer);
+            }
+            change_set.extend(action);
+        }
+
+        if query_stats_pool
+            .get_validated()
+            .any(|report| report.content.epoch < epoch)
+        {
+            change_set.push(QueryStatsChangeAction::PurgeBelowEpoch(epoch));
+        }
+        change_set
+    }
+}
+
+/// Implements the `QueryStatsGossip` trait.
+pub struct QueryStatsGossipImpl {
+    consensus_cache: Arc<dyn ConsensusPoolCache>,
+}
+
+impl QueryStatsGossipImpl {
+    /// Build a new query statistics gossip component.
+    pub fn new(consensus_cache: Arc<dyn ConsensusPoolCache>) -> Self {
+        Self { consensus_cache }
+    }
+}
+
+impl QueryStatsGossip for QueryStatsGossipImpl {
+    /// Reports of epochs before the reported one are dropped.
+    fn get_priority_function(
+        &self,
+        _query_stats_pool: &dyn QueryStatsPool,
+    ) -> PriorityFn<QueryStatsMessageId, QueryStatsMessageAttribute> {
+        let certified_height = self
+            .consensus_cache
+            .finalized_block()
+            .context
+            .certified_height;
+        let epoch = reported_epoch(certified_height).unwrap_or_else(|| QueryStatsEpoch::from(0));
+        Box::new(move |_id, attribute| {
+            if attribute.epoch < epoch {
+                Priority::Drop
+            } else {
+                Priority::Fetch
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::mocks::{dependencies, Dependencies};
+    use ic_artifact_pool::query_stats_pool::QueryStatsPoolImpl;
+    use ic_interfaces::{artifact_pool::UnvalidatedArtifact, query_stats::MutableQueryStatsPool};
+    use ic_logger::replica_logger::no_op_logger;
+    use ic_metrics::MetricsRegistry;
+    use ic_test_utilities::{
+        mock_time,
+        types::ids::{canister_test_id, node_test_id, subnet_test_id},
+    };
+    use ic_types::{
+        consensus::{query_stats::QUERY_STATS_EPOCH_LENGTH, BasicSignature},
+        crypto::{BasicSig, BasicSigOf},
+    };
+
+    pub(crate) fn make_report(epoch: u64, signer: u64) -> QueryStatsMessage {
+        QueryStatsMessage {
+            content: QueryStatsContent {
+                epoch: QueryStatsEpoch::from(epoch),
+                stats: BTreeMap::new(),
+                registry_version: RegistryVersion::from(1),
+            },
+            signature: BasicSignature {
+                signature: BasicSigOf::new(BasicSig(vec![])),
+                signer: node_test_id(signer),
+            },
+        }
+    }
+
+    #[test]
+    fn test_reported_epoch() {
+        assert_eq!(reported_epoch(Height::from(0)), None);
+        assert_eq!(
+            reported_epoch(Height::from(QUERY_STATS_EPOCH_LENGTH - 1)),
+            None
+        );
+        assert_eq!(
+            reported_epoch(Height::from(2 * QUERY_STATS_EPOCH_LENGTH)),
+            Some(QueryStatsEpoch::from(1))
+        );
+    }
+
+    #[test]
+    fn reports_are_split_by_instructions() {
+        let stats: BTreeMap<_, _> = (0..MAX_QUERY_STATS_CANISTERS_PER_REPORT as u64 + 2)
+            .map(|i| {
+                (
+                    canister_test_id(i),
+                    QueryStats {
+                        num_instructions: i,
+                        ..Default::default()
+                    },
+                )
+            })
+            .collect();
+        let (reported, deferred) = split_report(stats);
+        assert_eq!(reported.len(), MAX_QUERY_STATS_CANISTERS_PER_REPORT);
+        assert_eq!(
+            deferred.keys().cloned().collect::<Vec<_>>(),
+            vec![canister_test_id(0), canister_test_id(1)]
+        );
+    }
+
+    #[test]
+    fn test_query_stats_validation() {
+        ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
+            let Dependencies {
+                pool,
+                crypto,
+                registry,
+                ..
+            } = dependencies(pool_config, 4);
+            let mut query_stats_pool = QueryStatsPoolImpl::new(MetricsRegistry::new());
+            let valid = make_report(0, 1);
+            let invalid = make_report(0, 9);
+            for message in vec![valid.clone(), invalid.clone()] {
+                query_stats_pool.insert(UnvalidatedArtifact {
+                    message,
+                    peer_id: node_test_id(1),
+                    timestamp: mock_time(),
+                });
+            }
+
+            // Nothing is reported before the first epoch is over.
+            let query_stats = QueryStatsHandlerImpl::new(
+                node_test_id(0),
+                pool.get_cache(),
+                crypto,
+                registry,
+                subnet_test_id(0),
+                None,
+                no_op_logger(),
+            );
+            assert!(query_stats.on_state_change(&query_stats_pool).is_empty());
+
+            // Validate the reports of the first epoch as if it was over.
+            let registry_version = RegistryVersion::from(1);
+            assert!(matches!(
+                query_stats.validate_report(ic_crypto::crypto_hash(&valid), &valid, registry_version),
+                Some(QueryStatsChangeAction::MoveToValidated(report)) if report == valid
+            ));
+            assert!(matches!(
+                query_stats.validate_report(
+                    ic_crypto::crypto_hash(&invalid),
+                    &invalid,
+                    registry_version
+                ),
+                Some(QueryStatsChangeAction::HandleInvalid(..))
+            ));
+            // Reports of later registry versions are deferred.
+            assert!(query_stats
+                .validate_report(
+                    ic_crypto::crypto_hash(&valid),
+                    &valid,
+                    RegistryVersion::from(0)
+                )
+                .is_none());
+        })
+    }
+}
@@ -0,0 +1,266 @@
+//! The payload section of the query statistics reports.
+//!
+//! A block includes the validated reports of the epoch before the one of its
+//! certified height, at most one per replica. Past payloads only reach back to
+//! the certified height, so a report may be included again after it was
+//! certified; the replicated state only counts the first report of every
+//! replica and epoch.
+use super::{check_report, reported_epoch};
+use crate::consensus::{
+    payload_builder::{PayloadBuildingError, PayloadSectionBuilder, PayloadSizeLimits},
+    ConsensusCrypto,
+};
+use ic_interfaces::{
+    consensus::PayloadValidationError,
+    ingress_pool::IngressPoolSelect,
+    query_stats::{QueryStatsPermanentValidationError, QueryStatsPool},
+    registry::RegistryClient,
+    validation::{ValidationError, ValidationResult},
+};
+use ic_logger::{debug, ReplicaLogger};
+use ic_types::{
+    batch::{BatchPayload, ValidationContext},
+    consensus::{query_stats::QueryStatsEpoch, Payload},
+    messages::MAX_QUERY_STATS_PAYLOAD_IN_BYTES,
+    CountBytes, Height, NodeId, SubnetId, Time,
+};
+use std::collections::{BTreeMap, HashSet};
+use std::sync::{Arc, RwLock};
+
+/// The section of the query statistics reports.
+pub struct QueryStatsSectionBuilder {
+    query_stats_pool: Arc<RwLock<dyn QueryStatsPool>>,
+    crypto: Arc<dyn ConsensusCrypto>,
+    registry_client: Arc<dyn RegistryClient>,
+    subnet_id: SubnetId,
+    log: ReplicaLogger,
+}
+
+impl QueryStatsSectionBuilder {
+    /// Create the query statistics section using the reports of the given
+    /// pool.
+    pub fn new(
+        query_stats_pool: Arc<RwLock<dyn QueryStatsPool>>,
+        crypto: Arc<dyn ConsensusCrypto>,
+        registry_client: Arc<dyn RegistryClient>,
+        subnet_id: SubnetId,
+        log: ReplicaLogger,
+    ) -> Self {
+        Self {
+            query_stats_pool,
+            crypto,
+            registry_client,
+            subnet_id,
+            log,
+        }
+    }
+}
+
+impl PayloadSectionBuilder for QueryStatsSectionBuilder {
+    fn name(&self) -> &'static str {
+        "query_stats"
+    }
+
+    fn build_section(
+        &self,
+        payload: &mut BatchPayload,
+        _height: Height,
+        _ingress_pool: &dyn IngressPoolSelect,
+        past_payloads: &[(Height, Time, Payload)],
+        context: &ValidationContext,
+        limits: &PayloadSizeLimits,
+    ) -> Result<(), PayloadBuildingError> {
+        let epoch = match reported_epoch(context.certified_height) {
+            Some(epoch) => epoch,
+            None => return Ok(()),
+        };
+        let included = past_signers(past_payloads, epoch);
+        let pool = self.query_stats_pool.read().unwrap();
+        let reports: BTreeMap<NodeId, _> = pool
+            .get_validated()
+            .filter(|report| {
+                report.content.epoch == epoch
+                    && report.content.registry_version <= context.registry_version
+                    && !included.contains(&report.signature.signer)
+            })
+            .map(|report| (report.signature.signer, report.clone()))
+            .collect();
+        let mut size = 0;
+        let mut included = Vec::new();
+        for (_, report) in reports {
+            let report_size = report.count_bytes();
+            if size + report_size > limits.max_query_stats_bytes {
+                continue;
+            }
+            size += report_size;
+            included.push(report);
+        }
+        debug!(
+            self.log,
+            "Including {} query stats reports of epoch {:?}",
+            included.len(),
+            epoch
+        );
+        payload.query_stats = included;
+        Ok(())
+    }
+
+    fn validate_section(
+        &self,
+        payload: &BatchPayload,
+        past_payloads: &[(Height, Time, Payload)],
+        context: &ValidationContext,
+    ) -> ValidationResult<PayloadValidationError> {
+        if payload.query_stats.is_empty() {
+            return Ok(());
+        }
+        let invalid = |err| -> ValidationResult<PayloadValidationError> {
+            Err(ValidationError::Permanent(err).into())
+        };
+        let size = payload
+            .query_stats
+            .iter()
+            .map(|report| report.count_bytes())
+            .sum::<usize>();
+        let max = MAX_QUERY_STATS_PAYLOAD_IN_BYTES.get() as usize;
+        if size > max {
+            return invalid(QueryStatsPermanentValidationError::PayloadTooLarge { size, max });
+        }
+        let epoch = reported_epoch(context.certified_height);
+        let mut included = match epoch {
+            Some(epoch) => past_signers(past_payloads, epoch),
+            None => HashSet::new(),
+        };
+        for report in payload.query_stats.iter() {
+            let signer = report.signature.signer;
+            if Some(report.content.epoch) != epoch {
+                return invalid(QueryStatsPermanentValidationError::UnexpectedEpoch {
+                    expected: epoch.unwrap_or_else(|| QueryStatsEpoch::from(0)),
+                    received: report.content.epoch,
+                });
+            }
+            if !included.insert(signer) {
+                return invalid(QueryStatsPermanentValidationError::DuplicateReport(
+                    signer,
+                    report.content.epoch,
+                ));
+            }
+            if report.content.registry_version > context.registry_version {
+                return invalid(QueryStatsPermanentValidationError::RegistryVersionTooHigh(
+                    signer,
+                    report.content.registry_version,
+                ));
+            }
+            check_report(
+                self.crypto.as_ref(),
+                self.registry_client.as_ref(),
+                self.subnet_id,
+                report,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Return the signers of the reports of the given epoch in past_payloads.
+fn past_signers(
+    past_payloads: &[(Height, Time, Payload)],
+    epoch: QueryStatsEpoch,
+) -> HashSet<NodeId> {
+    past_payloads
+        .iter()
+        .filter(|(_, _, payload)| !payload.is_summary())
+        .flat_map(|(_, _, payload)| {
+            payload
+                .as_ref()
+                .as_batch_payload()
+                .query_stats
+                .iter()
+                .filter(|report| report.content.epoch == epoch)
+                .map(|report| report.signature.signer)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::mocks::{dependencies, Dependencies};
+    use crate::query_stats::tests::make_report;
+    use ic_artifact_pool::query_stats_pool::QueryStatsPoolImpl;
+    use ic_interfaces::query_stats::{MutableQueryStatsPool, QueryStatsChangeAction};
+    use ic_logger::replica_logger::no_op_logger;
+    use ic_metrics::MetricsRegistry;
+    use ic_test_artifact_pool::ingress_pool::TestIngressPool;
+    use ic_test_utilities::{mock_time, types::ids::subnet_test_id};
+    use ic_types::{consensus::query_stats::QUERY_STATS_EPOCH_LENGTH, RegistryVersion};
+
+    #[test]
+    fn test_query_stats_section() {
+        ic_test_utilities::artifact_pool_config::with_test_pool_config(|pool_config| {
+            let Dependencies {
+                crypto, registry, ..
+            } = dependencies(pool_config.clone(), 4);
+            let mut query_stats_pool = QueryStatsPoolImpl::new(MetricsRegistry::new());
+            query_stats_pool.apply_changes(vec![
+                QueryStatsChangeAction::AddToValidated(make_report(0, 0)),
+                QueryStatsChangeAction::AddToValidated(make_report(1, 1)),
+            ]);
+
+            let section = QueryStatsSectionBuilder::new(
+                Arc::new(RwLock::new(query_stats_pool)),
+                crypto,
+                registry,
+                subnet_test_id(0),
+                no_op_logger(),
+            );
+            let context = ValidationContext {
+                certified_height: Height::from(QUERY_STATS_EPOCH_LENGTH),
+                registry_version: RegistryVersion::from(1),
+                time: mock_time(),
+            };
+            let mut payload = BatchPayload::default();
+            section
+                .build_section(
+                    &mut payload,
+                    Height::from(QUERY_STATS_EPOCH_LENGTH + 1),
+                    &TestIngressPool::new(pool_config.clone()),
+                    &[],
+                    &context,
+                    &PayloadSizeLimits::default(),
+                )
+                .unwrap();
+            assert_eq!(payload.query_stats, vec![make_report(0, 0)]);
+            assert!(section.validate_section(&payload, &[], &context).is_ok());
+
+            // A replica reports at most once per epoch.
+            let mut invalid = payload.clone();
+            invalid.query_stats.push(make_report(0, 0));
+            assert!(section.validate_section(&invalid, &[], &context).is_err());
+
+            // No reports are included beyond the size limit.
+            let limits = PayloadSizeLimits {
+                max_query_stats_bytes: make_report(0, 0).count_bytes() - 1,
+                ..PayloadSizeLimits::default()
+            };
+            let mut limited = BatchPayload::default();
+            section
+                .build_section(
+                    &mut limited,
+                    Height::from(QUERY_STATS_EPOCH_LENGTH + 1),
+                    &TestIngressPool::new(pool_config),
+                    &[],
+                    &context,
+                    &limits,
+                )
+                .unwrap();
+            assert!(limited.query_stats.is_empty());
+
+            // Only the reports of the epoch before the certified height are
+            // valid.
+            let mut invalid = payload.clone();
+            invalid.query_stats = vec![make_report(1, 1)];
+            assert!(section.validate_section(&invalid, &[], &context).is_err());
+        })
+    }
+}
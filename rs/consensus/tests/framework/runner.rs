@@ -4,13 +4,14 @@ use super::types::*;
 use ic_config::artifact_pool::ArtifactPoolConfig;
 use ic_consensus::{
     certification::CertifierImpl,
-    consensus::{ConsensusImpl, Membership},
+    consensus::{payload_builder::default_sections, ConsensusImpl, CryptoThreadPool, Membership},
     dkg,
 };
+use ic_event_log::EventLog;
 use ic_interfaces::time_source::TimeSource;
 use ic_logger::{info, warn, ReplicaLogger};
 use ic_test_utilities::{crypto::CryptoReturningOk, FastForwardTimeSource};
-use ic_types::malicious_flags::MaliciousFlags;
+use ic_types::malicious_strategies::MaliciousBehaviors;
 use ic_types::Time;
 use rand::{thread_rng, Rng, RngCore};
 use rand_chacha::{rand_core::SeedableRng, ChaChaRng};
@@ -123,28 +124,37 @@ impl<'a> ConsensusRunner<'a> {
         context.node_id = format!("{}", node_id.clone().get());
         let replica_logger = self.logger.with_new_context(context);
 
+        let crypto_pool = CryptoThreadPool::new(1);
         let consensus = ConsensusImpl::new(
             deps.replica_config.clone(),
             Default::default(),
             Arc::clone(&deps.registry_client),
             membership.clone(),
             fake_crypto.clone(),
+            crypto_pool.clone(),
             deps.ingress_selector.clone(),
-            deps.xnet_payload_builder.clone(),
+            default_sections(
+                deps.ingress_selector.clone(),
+                deps.xnet_payload_builder.clone(),
+                &deps.metrics_registry,
+            ),
             deps.dkg_pool.clone(),
+            deps.equivocation_pool.clone(),
             deps.message_routing.clone(),
             deps.state_manager.clone(),
             Arc::clone(&self.time) as Arc<_>,
             Duration::from_secs(0),
-            MaliciousFlags::default(),
+            MaliciousBehaviors::default(),
             deps.metrics_registry.clone(),
             replica_logger.clone(),
+            EventLog::disabled(),
             None,
         );
         let dkg = dkg::DkgImpl::new(
             deps.replica_config.node_id,
             fake_crypto.clone(),
             deps.consensus_pool.read().unwrap().get_cache(),
+            &Default::default(),
             deps.metrics_registry.clone(),
             replica_logger.clone(),
         );
@@ -152,6 +162,7 @@ impl<'a> ConsensusRunner<'a> {
             deps.replica_config.clone(),
             membership,
             fake_crypto,
+            crypto_pool,
             deps.state_manager.clone(),
             deps.metrics_registry.clone(),
             replica_logger.clone(),
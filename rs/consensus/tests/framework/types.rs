@@ -1,6 +1,7 @@
 #![allow(dead_code)]
 use ic_artifact_pool::{
     certification_pool::CertificationPoolImpl, consensus_pool::ConsensusPoolImpl, dkg_pool,
+    equivocation_pool::EquivocationPoolImpl,
 };
 use ic_config::artifact_pool::ArtifactPoolConfig;
 use ic_consensus::{consensus::ConsensusImpl, dkg};
@@ -137,6 +138,7 @@ pub struct ConsensusDependencies {
     pub(crate) ingress_selector: Arc<dyn IngressSelector>,
     pub consensus_pool: Arc<RwLock<ConsensusPoolImpl>>,
     pub dkg_pool: Arc<RwLock<dkg_pool::DkgPoolImpl>>,
+    pub equivocation_pool: Arc<RwLock<EquivocationPoolImpl>>,
     pub message_routing: Arc<dyn MessageRouting>,
     pub state_manager: Arc<dyn StateManager<State = ReplicatedState>>,
     pub replica_config: ReplicaConfig,
@@ -169,6 +171,9 @@ impl ConsensusDependencies {
             registry_client: Arc::clone(&registry_client),
             consensus_pool,
             dkg_pool: Arc::new(RwLock::new(dkg_pool)),
+            equivocation_pool: Arc::new(RwLock::new(EquivocationPoolImpl::new(
+                metrics_registry.clone(),
+            ))),
             message_routing: Arc::new(FakeMessageRouting::with_state_manager(
                 state_manager.clone(),
             )),
@@ -1,9 +1,14 @@
 mod framework;
 
 use crate::framework::ConsensusDriver;
-use ic_artifact_pool::{consensus_pool, dkg_pool};
-use ic_consensus::{certification::CertifierImpl, consensus::ConsensusImpl, dkg};
+use ic_artifact_pool::{consensus_pool, dkg_pool, equivocation_pool::EquivocationPoolImpl};
+use ic_consensus::{
+    certification::CertifierImpl,
+    consensus::{payload_builder::default_sections, ConsensusImpl, CryptoThreadPool},
+    dkg,
+};
 use ic_consensus_message::make_genesis;
+use ic_event_log::EventLog;
 use ic_interfaces::{state_manager::Labeled, time_source::TimeSource};
 use ic_logger::replica_logger::no_op_logger;
 use ic_metrics::MetricsRegistry;
@@ -20,7 +25,7 @@ use ic_test_utilities::{
     xnet_payload_builder::FakeXNetPayloadBuilder,
 };
 use ic_types::{
-    crypto::CryptoHash, malicious_flags::MaliciousFlags, replica_config::ReplicaConfig,
+    crypto::CryptoHash, malicious_strategies::MaliciousBehaviors, replica_config::ReplicaConfig,
     CryptoHashOfState, Height,
 };
 use std::convert::TryInto;
@@ -106,28 +111,39 @@ fn consensus_produces_expected_batches() {
         );
         let membership = Arc::new(membership);
 
+        let crypto_pool = CryptoThreadPool::new(1);
         let consensus = ConsensusImpl::new(
             replica_config.clone(),
             Default::default(),
             Arc::clone(&registry_client) as Arc<_>,
             Arc::clone(&membership) as Arc<_>,
             Arc::clone(&fake_crypto) as Arc<_>,
+            crypto_pool.clone(),
             Arc::clone(&ingress_selector) as Arc<_>,
-            Arc::clone(&xnet_payload_builder) as Arc<_>,
+            default_sections(
+                Arc::clone(&ingress_selector) as Arc<_>,
+                Arc::clone(&xnet_payload_builder) as Arc<_>,
+                &metrics_registry,
+            ),
             Arc::clone(&dkg_pool) as Arc<_>,
+            Arc::new(RwLock::new(EquivocationPoolImpl::new(
+                metrics_registry.clone(),
+            ))),
             Arc::clone(&router) as Arc<_>,
             Arc::clone(&state_manager) as Arc<_>,
             Arc::clone(&time) as Arc<_>,
             Duration::from_secs(0),
-            MaliciousFlags::default(),
+            MaliciousBehaviors::default(),
             metrics_registry.clone(),
             no_op_logger(),
+            EventLog::disabled(),
             None,
         );
         let dkg = dkg::DkgImpl::new(
             replica_config.node_id,
             Arc::clone(&fake_crypto) as Arc<_>,
             Arc::clone(&consensus_cache),
+            &Default::default(),
             metrics_registry.clone(),
             no_op_logger(),
         );
@@ -135,6 +151,7 @@ fn consensus_produces_expected_batches() {
             replica_config,
             Arc::clone(&membership) as Arc<_>,
             Arc::clone(&fake_crypto) as Arc<_>,
+            crypto_pool,
             Arc::clone(&state_manager) as Arc<_>,
             metrics_registry.clone(),
             no_op_logger(),
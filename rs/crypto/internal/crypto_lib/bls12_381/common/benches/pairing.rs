@@ -0,0 +1,42 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use group::CurveProjective;
+use ic_crypto_internal_bls12381_common::{
+    pairing_backend, pairings_are_equal, random_bls12_381_scalar, scalar_multiply,
+};
+use pairing::bls12_381::{Bls12, G1, G2};
+use pairing::Engine;
+use rand::SeedableRng;
+use rand_chacha::ChaCha20Rng;
+
+criterion_main!(benches);
+criterion_group!(benches, bench_pairing_check);
+
+/// Compares the check `e(s * G1, G2) == e(G1, s * G2)`, as in a signature
+/// verification, with the active backend to computing both pairings.
+fn bench_pairing_check(criterion: &mut Criterion) {
+    let rng = &mut ChaCha20Rng::from_seed([42; 32]);
+    let secret = random_bls12_381_scalar(rng);
+    let signature = scalar_multiply(G1::one(), secret);
+    let public_key = scalar_multiply(G2::one(), secret);
+
+    let group = &mut criterion.benchmark_group("crypto_bls12_381_pairing_check");
+    group.bench_function(
+        format!("pairings_are_equal/{:?}", pairing_backend()),
+        |bench| {
+            bench.iter(|| {
+                assert!(pairings_are_equal(
+                    &signature,
+                    &G2::one(),
+                    &G1::one(),
+                    &public_key
+                ))
+            })
+        },
+    );
+    group.bench_function("two_pairings", |bench| {
+        bench.iter(|| {
+            assert!(Bls12::pairing(signature, G2::one()) == Bls12::pairing(G1::one(), public_key))
+        })
+    });
+    group.finish();
+}
@@ -1,4 +1,5 @@
-#![forbid(unsafe_code)]
+#![cfg_attr(not(feature = "blst"), forbid(unsafe_code))]
+#![cfg_attr(feature = "blst", deny(unsafe_code))]
 #![deny(clippy::unwrap_used)]
 
 //! Common methods for working with BLS12-381 primitives
@@ -15,4 +16,7 @@ pub use arithmetic::{scalar_multiply, sum};
 mod hash;
 pub use hash::{hash_to_fr, hash_to_g1, hash_to_miracl_g1, random_bls12_381_scalar, MiraclG1};
 
+mod pairing_check;
+pub use pairing_check::{pairing_backend, pairings_are_equal, PairingBackend};
+
 pub mod test_utils;
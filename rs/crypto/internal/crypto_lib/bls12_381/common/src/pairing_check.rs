@@ -0,0 +1,57 @@
+//! Pairing checks for BLS12-381 primitives
+//!
+//! Signature verification is dominated by the pairings, so the checks are
+//! computed with a single final exponentiation. If the crate is built with
+//! the `blst` feature and the CPU supports the instructions the assembly of
+//! the `blst` library is optimized for, the checks are computed with `blst`.
+//! Otherwise, they are computed with the `pairing` library.
+
+use ff::Field;
+use group::{CurveAffine, CurveProjective};
+use pairing::bls12_381::{Bls12, Fq12, G1, G2};
+use pairing::Engine;
+
+#[cfg(feature = "blst")]
+mod blst_backend;
+#[cfg(test)]
+mod tests;
+
+/// The implementation that computes the pairing checks.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PairingBackend {
+    /// The `pairing` library
+    Pairing,
+    /// The `blst` library
+    Blst,
+}
+
+/// Returns the implementation that computes the pairing checks on this CPU.
+pub fn pairing_backend() -> PairingBackend {
+    #[cfg(feature = "blst")]
+    {
+        if blst_backend::is_supported() {
+            return PairingBackend::Blst;
+        }
+    }
+    PairingBackend::Pairing
+}
+
+/// Checks whether `e(a1, b1) == e(a2, b2)`.
+pub fn pairings_are_equal(a1: &G1, b1: &G2, a2: &G1, b2: &G2) -> bool {
+    match pairing_backend() {
+        #[cfg(feature = "blst")]
+        PairingBackend::Blst => blst_backend::pairings_are_equal(a1, b1, a2, b2),
+        _ => pairings_are_equal_with_pairing(a1, b1, a2, b2),
+    }
+}
+
+/// Checks whether `e(a1, b1) == e(a2, b2)` by checking whether
+/// `e(a1, b1) * e(-a2, b2) == 1`, which needs only one final exponentiation.
+fn pairings_are_equal_with_pairing(a1: &G1, b1: &G2, a2: &G1, b2: &G2) -> bool {
+    let mut minus_a2 = *a2;
+    minus_a2.negate();
+    let (a1, b1) = (a1.into_affine().prepare(), b1.into_affine().prepare());
+    let (minus_a2, b2) = (minus_a2.into_affine().prepare(), b2.into_affine().prepare());
+    Bls12::final_exponentiation(&Bls12::miller_loop(&[(&a1, &b1), (&minus_a2, &b2)]))
+        == Some(Fq12::one())
+}
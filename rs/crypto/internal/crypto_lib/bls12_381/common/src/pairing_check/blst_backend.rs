@@ -0,0 +1,73 @@
+//! Pairing checks with the `blst` library
+#![allow(unsafe_code)]
+
+use crate::serde::pairing::{g1_to_bytes, g2_to_bytes};
+use blst::{
+    blst_fp12, blst_fp12_finalverify, blst_miller_loop, blst_p1_affine, blst_p1_uncompress,
+    blst_p2_affine, blst_p2_uncompress, BLST_ERROR,
+};
+use group::CurveProjective;
+use pairing::bls12_381::{G1, G2};
+
+/// Returns whether the CPU supports the instructions `blst` is built for.
+///
+/// `blst` is built with the `force-adx` feature, so that it uses the
+/// ADX and BMI2 instructions of modern x86-64 CPUs, which speed up the
+/// field arithmetic considerably. It must therefore only be called on CPUs
+/// that support them.
+pub fn is_supported() -> bool {
+    #[cfg(target_arch = "x86_64")]
+    {
+        is_x86_feature_detected!("adx") && is_x86_feature_detected!("bmi2")
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        false
+    }
+}
+
+/// Checks whether `e(a1, b1) == e(a2, b2)`.
+///
+/// The points are converted through their compressed encoding, which is the
+/// same in both libraries.
+pub fn pairings_are_equal(a1: &G1, b1: &G2, a2: &G1, b2: &G2) -> bool {
+    // `blst_miller_loop` is not defined for the points at infinity, for
+    // which the pairing is 1.
+    match (a1.is_zero() || b1.is_zero(), a2.is_zero() || b2.is_zero()) {
+        (true, true) => return true,
+        (true, false) | (false, true) => return false,
+        (false, false) => (),
+    }
+    match (
+        miller_loop(&g1_affine(a1), &g2_affine(b1)),
+        miller_loop(&g1_affine(a2), &g2_affine(b2)),
+    ) {
+        (Some(left), Some(right)) => unsafe { blst_fp12_finalverify(&left, &right) },
+        _ => false,
+    }
+}
+
+fn g1_affine(point: &G1) -> Option<blst_p1_affine> {
+    let bytes = g1_to_bytes(point);
+    let mut affine = blst_p1_affine::default();
+    match unsafe { blst_p1_uncompress(&mut affine, bytes.as_ptr()) } {
+        BLST_ERROR::BLST_SUCCESS => Some(affine),
+        _ => None,
+    }
+}
+
+fn g2_affine(point: &G2) -> Option<blst_p2_affine> {
+    let bytes = g2_to_bytes(point);
+    let mut affine = blst_p2_affine::default();
+    match unsafe { blst_p2_uncompress(&mut affine, bytes.as_ptr()) } {
+        BLST_ERROR::BLST_SUCCESS => Some(affine),
+        _ => None,
+    }
+}
+
+fn miller_loop(p: &Option<blst_p1_affine>, q: &Option<blst_p2_affine>) -> Option<blst_fp12> {
+    let (p, q) = (p.as_ref()?, q.as_ref()?);
+    let mut result = blst_fp12::default();
+    unsafe { blst_miller_loop(&mut result, q, p) };
+    Some(result)
+}
@@ -0,0 +1,74 @@
+//! Tests for pairing checks on BLS12-381 types
+
+use super::*;
+use crate::arithmetic::scalar_multiply;
+use crate::test_utils::{uint_to_fr, uint_to_g2};
+use proptest::prelude::*;
+
+fn uint_to_g1(num: u32) -> G1 {
+    scalar_multiply(G1::one(), uint_to_fr(num))
+}
+
+/// Verifies that `e(G1(a), G2(b)) == e(G1(a * b), G2(1))`, and that the
+/// pairings differ if the product is off by one.
+fn test_pairings_are_equal(a: u16, b: u16) {
+    let (a, b) = (a as u32, b as u32);
+    let product = uint_to_g1(a * b);
+    assert!(pairings_are_equal(
+        &uint_to_g1(a),
+        &uint_to_g2(b),
+        &product,
+        &G2::one()
+    ));
+    assert!(!pairings_are_equal(
+        &uint_to_g1(a),
+        &uint_to_g2(b),
+        &uint_to_g1(a * b + 1),
+        &G2::one()
+    ));
+}
+
+#[test]
+fn pairings_with_points_at_infinity_are_one() {
+    assert!(pairings_are_equal(
+        &G1::zero(),
+        &G2::one(),
+        &G1::one(),
+        &G2::zero()
+    ));
+    assert!(!pairings_are_equal(
+        &G1::zero(),
+        &G2::one(),
+        &G1::one(),
+        &G2::one()
+    ));
+}
+
+#[test]
+#[cfg(feature = "blst")]
+fn backends_agree() {
+    if pairing_backend() != PairingBackend::Blst {
+        return;
+    }
+    for (a, b, c) in &[(3, 5, 15), (3, 5, 16), (7, 0, 0), (1, 1, 2)] {
+        let args = (
+            &uint_to_g1(*a),
+            &uint_to_g2(*b),
+            &uint_to_g1(*c),
+            &G2::one(),
+        );
+        assert_eq!(
+            blst_backend::pairings_are_equal(args.0, args.1, args.2, args.3),
+            pairings_are_equal_with_pairing(args.0, args.1, args.2, args.3),
+        );
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(10))]
+
+    #[test]
+    fn proptest_pairings_are_equal(a: u16, b: u16) {
+        test_pairings_are_equal(a, b);
+    }
+}
@@ -124,6 +124,42 @@ pub fn verify_individual(
     }
 }
 
+/// Verifies the individual signatures of several signers over the same
+/// `message` with a single pairing check, using `rng` to weight the
+/// signatures.
+///
+/// If the verification fails, at least one of the signatures is invalid, and
+/// the signatures have to be verified individually to find out which ones.
+///
+/// # Errors
+/// * `CryptoError::MalformedSignature` if any of the signatures cannot be
+///   parsed as a G1 point.
+/// * `CryptoError::MalformedPublicKey` if any of the public keys cannot be
+///   parsed as a valid G2 point.
+/// * `CryptoError::SignatureVerification` if the batch verification fails.
+pub fn verify_individual_batch<R: Rng + CryptoRng>(
+    message: &[u8],
+    signatures: &[(IndividualSignatureBytes, PublicKeyBytes)],
+    rng: &mut R,
+) -> Result<(), CryptoError> {
+    let signatures: Result<Vec<(IndividualSignature, PublicKey)>, CryptoError> = signatures
+        .iter()
+        .map(|(signature, public_key)| Ok(((*signature).try_into()?, (*public_key).try_into()?)))
+        .collect();
+    if crypto::verify_individual_message_signatures_batch(message, &signatures?[..], rng) {
+        Ok(())
+    } else {
+        Err(CryptoError::SignatureVerification {
+            algorithm: AlgorithmId::MultiBls12_381,
+            public_key_bytes: Vec::new(),
+            sig_bytes: Vec::new(),
+            internal_error:
+                "Batch verification of individual contributions to multisignature failed"
+                    .to_string(),
+        })
+    }
+}
+
 /// Verifies a combined multisignature over the given `message` using the given
 /// array of `public_keys`.
 ///
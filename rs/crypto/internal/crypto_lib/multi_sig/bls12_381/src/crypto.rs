@@ -9,8 +9,7 @@ use group::CurveProjective;
 use ic_crypto_internal_bls12381_common as bls;
 use ic_crypto_internal_bls12381_common::random_bls12_381_scalar;
 use ic_crypto_sha256::{Context, DomainSeparationContext};
-use pairing::bls12_381::{Bls12, FrRepr, G1, G2};
-use pairing::Engine;
+use pairing::bls12_381::{FrRepr, G1, G2};
 use rand::{CryptoRng, Rng};
 
 /// Domain separator for Hash-to-G1 to be used for signature generation in a
@@ -92,7 +91,7 @@ pub fn combine_public_keys(public_keys: &[PublicKey]) -> CombinedPublicKey {
 }
 
 pub fn verify_point(hash: G1, signature: G1, public_key: PublicKey) -> bool {
-    Bls12::pairing(signature, G2::one()) == Bls12::pairing(hash, public_key)
+    bls::pairings_are_equal(&signature, &G2::one(), &hash, &public_key)
 }
 pub fn verify_individual_message_signature(
     message: &[u8],
@@ -102,6 +101,26 @@ pub fn verify_individual_message_signature(
     let hash = hash_message_to_g1(message);
     verify_point(hash, signature, public_key)
 }
+/// Verifies the individual signatures of several signers on the same message
+/// with a single pairing check.
+///
+/// Every signature and public key is weighted by a random scalar, so that
+/// invalid signatures cannot cancel each other out in the sums.
+pub fn verify_individual_message_signatures_batch<R: Rng + CryptoRng>(
+    message: &[u8],
+    signatures: &[(IndividualSignature, PublicKey)],
+    rng: &mut R,
+) -> bool {
+    let mut signature_sum = G1::zero();
+    let mut public_key_sum = G2::zero();
+    for (signature, public_key) in signatures {
+        let weight = FrRepr::from(random_bls12_381_scalar(rng));
+        signature_sum.add_assign(&bls::scalar_multiply(*signature, weight));
+        public_key_sum.add_assign(&bls::scalar_multiply(*public_key, weight));
+    }
+    let hash = hash_message_to_g1(message);
+    verify_point(hash, signature_sum, public_key_sum)
+}
 pub fn verify_pop(pop: Pop, public_key: PublicKey) -> bool {
     let public_key_bytes = PublicKeyBytes::from(public_key);
     let mut domain_separated_public_key: Vec<u8> = vec![];
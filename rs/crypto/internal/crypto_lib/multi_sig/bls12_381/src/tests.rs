@@ -112,6 +112,8 @@ mod advanced_functionality {
     use crate::types::{PopBytes, PublicKeyBytes};
     use ic_crypto_internal_types::curves::bls12_381::G2;
     use proptest::prelude::*;
+    use rand_chacha::ChaCha20Rng;
+    use rand_core::SeedableRng;
 
     #[test]
     fn zero_signatures_yields_signature_zero() {
@@ -206,6 +208,47 @@ mod advanced_functionality {
         multi_test_utils::multi_signature_verifies(&keys, b"abba");
     }
 
+    #[test]
+    fn batch_of_individual_signatures_verifies_only_if_all_are_valid() {
+        let mut rng = ChaCha20Rng::from_seed([7; 32]);
+        let message = b"abba";
+        let keys = [
+            multi_crypto::keypair_from_seed([1, 2, 3, 4]),
+            multi_crypto::keypair_from_seed([5, 6, 7, 8]),
+            multi_crypto::keypair_from_seed([9, 10, 11, 12]),
+        ];
+        let mut signatures: Vec<_> = keys
+            .iter()
+            .map(|(secret_key, public_key)| {
+                (
+                    multi_crypto::sign_message(message, *secret_key),
+                    *public_key,
+                )
+            })
+            .collect();
+        assert!(multi_crypto::verify_individual_message_signatures_batch(
+            message,
+            &signatures,
+            &mut rng
+        ));
+
+        // Shares that are invalid individually but sum up to the sum of valid
+        // shares must not pass the batch verification.
+        let offset = multi_crypto::hash_message_to_g1(b"offset");
+        signatures[0].0.add_assign(&offset);
+        assert!(!multi_crypto::verify_individual_message_signatures_batch(
+            message,
+            &signatures,
+            &mut rng
+        ));
+        signatures[1].0.sub_assign(&offset);
+        assert!(!multi_crypto::verify_individual_message_signatures_batch(
+            message,
+            &signatures,
+            &mut rng
+        ));
+    }
+
     // Slow tests
     proptest! {
         #![proptest_config(ProptestConfig {
@@ -50,6 +50,7 @@ use ic_types::{
     crypto::{AlgorithmId, CryptoError, CryptoResult},
     NodeIndex, NumberOfNodes, Randomness,
 };
+use rand::{CryptoRng, Rng};
 use simple_asn1::{oid, ASN1Block};
 use std::convert::{TryFrom, TryInto};
 
@@ -213,6 +214,36 @@ pub fn verify_individual_signature(
     )
 }
 
+/// Verifies that the individual signatures of several signatories on the same
+/// message are all valid, with a single pairing check that uses `rng` to weight
+/// the signatures.
+///
+/// If the verification fails, at least one of the signatures is invalid, and
+/// the signatures have to be verified individually to find out which ones.
+///
+/// # Arguments:
+/// * `message` is the bytes that have been signed.
+/// * `signatures` are the individual signatures to be verified, together with
+///   the individual public keys of their signatories.
+/// # Panics
+/// This method is not expected to panic.
+/// # Errors
+/// * If any signature or public key cannot be parsed, or if the verification
+///   fails, this will return an error.
+pub fn verify_individual_signatures_batch<R: Rng + CryptoRng>(
+    message: &[u8],
+    signatures: &[(IndividualSignatureBytes, PublicKeyBytes)],
+    rng: &mut R,
+) -> CryptoResult<()> {
+    let signatures: CryptoResult<Vec<(IndividualSignature, PublicKey)>> = signatures
+        .iter()
+        .map(|(signature, public_key)| {
+            Ok((signature.try_into()?, PublicKey::try_from(public_key)?))
+        })
+        .collect();
+    crypto::verify_individual_sigs_batch(message, &signatures?[..], rng)
+}
+
 /// Verifies that a combined signature is valid.
 ///
 /// # Arguments
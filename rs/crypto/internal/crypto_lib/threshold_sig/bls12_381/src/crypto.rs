@@ -5,7 +5,9 @@ use super::types::{
     Polynomial, PublicCoefficients, SecretKey, Signature,
 };
 use crate::api::dkg_errors::InvalidArgumentError;
-use ic_crypto_internal_bls12381_common::{hash_to_g1, scalar_multiply};
+use ic_crypto_internal_bls12381_common::{
+    hash_to_g1, pairings_are_equal, random_bls12_381_scalar, scalar_multiply,
+};
 
 use crate::types::PublicKey;
 use ff::{Field, PrimeField};
@@ -15,11 +17,8 @@ use ic_types::{
     crypto::{AlgorithmId, CryptoError, CryptoResult},
     NodeIndex, NumberOfNodes, Randomness,
 };
-use pairing::bls12_381::{G1, G2};
-use pairing::{
-    bls12_381::{Bls12, Fr, FrRepr},
-    Engine,
-};
+use pairing::bls12_381::{Fr, FrRepr, G1, G2};
+use rand::{CryptoRng, Rng};
 use rand_chacha::ChaChaRng;
 use rand_core::SeedableRng;
 use std::convert::TryFrom;
@@ -314,14 +313,43 @@ pub fn verify_combined_sig(
     })
 }
 
+/// Verifies the individual signatures of several signatories on the same
+/// message with a single pairing check.
+///
+/// Every signature and public key is weighted by a random scalar, so that
+/// invalid signatures cannot cancel each other out in the sums.
+///
+/// # Returns
+/// * OK, if all the `signatures` are valid BLS signatures on `message`
+/// * Err, otherwise
+pub fn verify_individual_sigs_batch<R: Rng + CryptoRng>(
+    message: &[u8],
+    signatures: &[(IndividualSignature, PublicKey)],
+    rng: &mut R,
+) -> CryptoResult<()> {
+    let mut signature_sum = G1::zero();
+    let mut public_key_sum = G2::zero();
+    for (signature, public_key) in signatures {
+        let weight = FrRepr::from(random_bls12_381_scalar(rng));
+        signature_sum.add_assign(&scalar_multiply(*signature, weight));
+        public_key_sum.add_assign(&scalar_multiply(public_key.0, weight));
+    }
+    verify(message, signature_sum, PublicKey(public_key_sum)).map_err(|_| {
+        CryptoError::SignatureVerification {
+            algorithm: AlgorithmId::ThresBls12_381,
+            public_key_bytes: Vec::new(),
+            sig_bytes: Vec::new(),
+            internal_error: "Batch verification of individual threshold signatures failed"
+                .to_string(),
+        }
+    })
+}
+
 /// Verifies an individual or combined signature against the provided public
 /// key.
-// TODO(DFN-1408): Optimize signature verification by combining the miller
-// loops inside the pairings, thus performing only a single final
-// exponentiation.
 fn verify(message: &[u8], signature: Signature, public_key: PublicKey) -> Result<(), ()> {
     let point = hash_message_to_g1(message);
-    if Bls12::pairing(signature, G2::one()) == Bls12::pairing(point, public_key.0) {
+    if pairings_are_equal(&signature, &G2::one(), &point, &public_key.0) {
         Ok(())
     } else {
         Err(())
@@ -297,6 +297,36 @@ fn omnipotent_dealer() {
     );
 }
 
+#[test]
+fn batch_of_individual_signatures_verifies_only_if_all_are_valid() {
+    let threshold = NumberOfNodes::from(3);
+    let num_shares = NumberOfNodes::from(4);
+    let seed = Randomness::from([1u8; 32]);
+    let message = b"foo";
+    let mut rng = ChaChaRng::from_seed([2u8; 32]);
+
+    let (public_coefficients, shares) =
+        util::keygen(seed, threshold, num_shares).expect("Could not generate keys");
+    let mut signatures: Vec<(IndividualSignature, PublicKey)> = shares
+        .iter()
+        .enumerate()
+        .map(|(index, secret_key)| {
+            (
+                crypto::sign_message(message, secret_key),
+                crypto::individual_public_key(&public_coefficients, index as NodeIndex),
+            )
+        })
+        .collect();
+    assert!(crypto::verify_individual_sigs_batch(message, &signatures, &mut rng).is_ok());
+
+    // Swapping the signatures of two signatories leaves their sum unchanged,
+    // but must not pass the batch verification.
+    let first_signature = signatures[0].0;
+    signatures[0].0 = signatures[1].0;
+    signatures[1].0 = first_signature;
+    assert!(crypto::verify_individual_sigs_batch(message, &signatures, &mut rng).is_err());
+}
+
 #[test]
 #[ignore]
 /// Verifies that `verify_keygen_args` returns an error if the vector of
@@ -1,6 +1,9 @@
 use context::*;
+use ic_sys::tls::SessionResumingConnector;
 use openssl::error::ErrorStack;
-use openssl::ssl::{SslAcceptorBuilder, SslConnectorBuilder, SslContextBuilder, SslVersion};
+use openssl::ssl::{
+    SslAcceptorBuilder, SslConnectorBuilder, SslContextBuilder, SslOptions, SslVersion,
+};
 use openssl::x509::store::{X509Store, X509StoreBuilder};
 use openssl::{
     pkey::{PKey, Private},
@@ -9,11 +12,15 @@ use openssl::{
 };
 
 pub use acceptor::{tls_acceptor, ClientAuthentication, CreateTlsAcceptorError};
-pub use connector::{tls_connector, CreateTlsConnectorError};
+pub use connector::{
+    tls_connector, tls_connector_with_session_resumption, CreateTlsConnectorError,
+    ResumableTlsConnector,
+};
 
 const MIN_PROTOCOL_VERSION: Option<SslVersion> = Some(SslVersion::TLS1_3);
 const ALLOWED_CIPHER_SUITES: &str = "TLS_AES_128_GCM_SHA256:TLS_AES_256_GCM_SHA384";
 const ALLOWED_SIGNATURE_ALGORITHMS: &str = "ed25519";
+const SESSION_ID_CONTEXT: &[u8] = b"ic-crypto-tls";
 
 #[cfg(test)]
 mod tests;
@@ -66,9 +73,28 @@ mod acceptor {
         set_private_key(private_key, server_cert, &mut builder)?;
         set_certificate(server_cert, &mut builder)?;
         check_private_key(server_cert, &mut builder)?;
+        allow_session_resumption(server_cert, &mut builder)?;
         Ok(builder.build())
     }
 
+    fn allow_session_resumption(
+        server_cert: &X509,
+        builder: &mut SslAcceptorBuilder,
+    ) -> Result<(), CreateTlsAcceptorError> {
+        // Session tickets are encrypted with keys that are specific to the
+        // acceptor, so tickets are only accepted if the same acceptor is used
+        // for subsequent handshakes. The session ID context is required for
+        // resuming sessions in which the client was authenticated.
+        builder.clear_options(SslOptions::NO_TICKET);
+        builder
+            .set_session_id_context(SESSION_ID_CONTEXT)
+            .map_err(|e| CreateTlsAcceptorError {
+                description: "Failed to set the session ID context.".to_string(),
+                cert_der: server_cert.to_der().map(Some).unwrap_or(None),
+                internal_error: Some(format!("{}", e)),
+            })
+    }
+
     fn ensure_trusted_client_certs_not_empty(
         trusted_client_certs: &[X509],
     ) -> Result<(), CreateTlsAcceptorError> {
@@ -126,34 +152,98 @@ mod connector {
         client_cert: &X509,
         trusted_server_cert: &X509,
     ) -> Result<ConnectConfiguration, CreateTlsConnectorError> {
+        let builder = connector_builder(
+            private_key,
+            client_cert,
+            std::slice::from_ref(trusted_server_cert),
+        )?;
+        build_connect_configuration(
+            builder.build().configure(),
+            &client_cert,
+            Some(trusted_server_cert),
+        )
+    }
+
+    /// Builds a TLS connector that resumes previously established sessions.
+    ///
+    /// The connector is configured in the same way as the one returned by
+    /// `tls_connector`. In addition, the most recent session ticket received
+    /// from the server is stored and offered in subsequent handshakes of the
+    /// connector, which allows the server to skip the certificate exchange.
+    ///
+    /// The server may present any of the `trusted_server_certs`, e.g. while
+    /// it replaces its certificate.
+    ///
+    /// # Errors
+    /// * `CreateTlsConnectorError` if the creation of the connector failed
+    pub fn tls_connector_with_session_resumption(
+        private_key: &PKey<Private>,
+        client_cert: &X509,
+        trusted_server_certs: &[X509],
+    ) -> Result<ResumableTlsConnector, CreateTlsConnectorError> {
+        let builder = connector_builder(private_key, client_cert, trusted_server_certs)?;
+        Ok(ResumableTlsConnector {
+            connector: SessionResumingConnector::new(builder),
+            client_cert: client_cert.clone(),
+            trusted_server_certs: trusted_server_certs.to_vec(),
+        })
+    }
+
+    /// A TLS connector that offers the most recent session ticket it received
+    /// when establishing a new connection.
+    #[derive(Clone)]
+    pub struct ResumableTlsConnector {
+        connector: SessionResumingConnector,
+        client_cert: X509,
+        trusted_server_certs: Vec<X509>,
+    }
+
+    impl ResumableTlsConnector {
+        /// Returns the configuration for a new connection.
+        ///
+        /// # Errors
+        /// * `CreateTlsConnectorError` if the configuration could not be built
+        pub fn configure(&self) -> Result<ConnectConfiguration, CreateTlsConnectorError> {
+            build_connect_configuration(
+                self.connector.configure(),
+                &self.client_cert,
+                self.trusted_server_certs.first(),
+            )
+        }
+    }
+
+    fn connector_builder(
+        private_key: &PKey<Private>,
+        client_cert: &X509,
+        trusted_server_certs: &[X509],
+    ) -> Result<SslConnectorBuilder, CreateTlsConnectorError> {
         let mut builder = SslConnector::builder(SslMethod::tls_client())
             .expect("Failed to initialize connector.");
         restrict_tls_version_and_cipher_suites_and_sig_algs(&mut builder);
         ensure_root_self_signed_sigs_verified(&mut builder);
-        set_peer_verification_cert_store(vec![trusted_server_cert.clone()], &mut builder)?;
+        set_peer_verification_cert_store(trusted_server_certs.to_vec(), &mut builder)?;
         set_most_restrictive_certificate_verification_depth(&mut builder);
         set_private_key(private_key, client_cert, &mut builder)?;
         set_certificate(client_cert, &mut builder)?;
         check_private_key(client_cert, &mut builder)?;
-        let mut connect_config =
-            build_connect_configuration(builder, &client_cert, &trusted_server_cert)?;
-        connect_config.set_verify_hostname(false);
-        Ok(connect_config)
+        Ok(builder)
     }
 
     fn build_connect_configuration(
-        builder: SslConnectorBuilder,
+        connect_config: Result<ConnectConfiguration, ErrorStack>,
         client_cert: &X509,
-        trusted_server_cert: &X509,
+        trusted_server_cert: Option<&X509>,
     ) -> Result<ConnectConfiguration, CreateTlsConnectorError> {
-        builder.build().configure().map_err(|e| {
+        let mut connect_config = connect_config.map_err(|e| {
             CreateTlsConnectorError::new(
                 "Failed to build the connector configuration.",
                 client_cert,
                 trusted_server_cert,
                 e,
             )
-        })
+        })?;
+        connect_config.set_verify_hostname(false);
+        Ok(connect_config)
     }
 
     /// A TLS connector couldn't be created.
@@ -169,13 +259,13 @@ mod connector {
         fn new(
             description: &str,
             client_cert: &X509,
-            server_cert: &X509,
+            server_cert: Option<&X509>,
             internal_error: ErrorStack,
         ) -> Self {
             Self {
                 description: description.to_string(),
                 client_cert_der: client_cert.to_der().map(Some).unwrap_or(None),
-                server_cert_der: server_cert.to_der().map(Some).unwrap_or(None),
+                server_cert_der: server_cert.and_then(|cert| cert.to_der().ok()),
                 internal_error: format!("{}", internal_error),
             }
         }
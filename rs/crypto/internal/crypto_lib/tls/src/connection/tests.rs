@@ -164,10 +164,12 @@ mod acceptor {
 
 mod connector {
     use super::*;
-    use crate::tls_connector;
+    use crate::{tls_acceptor, tls_connector, tls_connector_with_session_resumption};
     use openssl::pkey::{PKeyRef, Private};
     use openssl::ssl::{ConnectConfiguration, SslVersion};
     use openssl::x509::X509Ref;
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
 
     #[test]
     fn should_enforce_server_authentication() {
@@ -292,6 +294,171 @@ mod connector {
         let _panic = connector.ssl_context().extra_chain_certs();
     }
 
+    #[test]
+    fn should_configure_resumable_connector_like_connector() {
+        let (_, trusted_server_cert) = generate_ed25519_cert();
+        let (key_pair, client_cert) = generate_ed25519_cert();
+
+        let connector =
+            tls_connector_with_session_resumption(&key_pair, &client_cert, &[trusted_server_cert])
+                .unwrap()
+                .configure()
+                .unwrap();
+
+        assert_eq!(connector.verify_mode(), SslVerifyMode::PEER);
+        assert_eq!(
+            cert_to_der(connector.certificate()),
+            client_cert.to_der().unwrap()
+        );
+        assert_eq!(connector.version2().unwrap(), SslVersion::TLS1_3);
+        assert!(!connector.is_server());
+    }
+
+    #[test]
+    fn should_not_offer_session_before_first_handshake() {
+        let (_, trusted_server_cert) = generate_ed25519_cert();
+        let (key_pair, client_cert) = generate_ed25519_cert();
+
+        let connector =
+            tls_connector_with_session_resumption(&key_pair, &client_cert, &[trusted_server_cert])
+                .unwrap()
+                .configure()
+                .unwrap();
+
+        assert!(connector.session().is_none());
+    }
+
+    #[test]
+    fn should_resume_session_of_previous_handshake() {
+        let (server_key_pair, server_cert) = generate_ed25519_cert();
+        let (client_key_pair, client_cert) = generate_ed25519_cert();
+        let acceptor = tls_acceptor(
+            &server_key_pair,
+            &server_cert,
+            ClientAuthentication::OptionalAuthentication {
+                trusted_client_certs: vec![client_cert.clone()],
+            },
+        )
+        .unwrap();
+        let connector = tls_connector_with_session_resumption(
+            &client_key_pair,
+            &client_cert,
+            &[server_cert.clone()],
+        )
+        .unwrap();
+
+        assert!(!handshake(&acceptor, connector.configure().unwrap()));
+        assert!(handshake(&acceptor, connector.configure().unwrap()));
+        assert!(handshake(&acceptor, connector.configure().unwrap()));
+    }
+
+    #[test]
+    fn should_not_resume_session_with_other_connector() {
+        let (server_key_pair, server_cert) = generate_ed25519_cert();
+        let (client_key_pair, client_cert) = generate_ed25519_cert();
+        let acceptor = tls_acceptor(
+            &server_key_pair,
+            &server_cert,
+            ClientAuthentication::OptionalAuthentication {
+                trusted_client_certs: vec![client_cert.clone()],
+            },
+        )
+        .unwrap();
+        let new_connector = || {
+            tls_connector_with_session_resumption(
+                &client_key_pair,
+                &client_cert,
+                &[server_cert.clone()],
+            )
+            .unwrap()
+        };
+
+        assert!(!handshake(&acceptor, new_connector().configure().unwrap()));
+        assert!(!handshake(&acceptor, new_connector().configure().unwrap()));
+    }
+
+    #[test]
+    fn should_connect_to_server_presenting_any_trusted_cert() {
+        let (client_key_pair, client_cert) = generate_ed25519_cert();
+        let (old_server_key_pair, old_server_cert) = generate_ed25519_cert();
+        let (new_server_key_pair, new_server_cert) = generate_ed25519_cert();
+        let connector = tls_connector_with_session_resumption(
+            &client_key_pair,
+            &client_cert,
+            &[old_server_cert.clone(), new_server_cert.clone()],
+        )
+        .unwrap();
+
+        for (server_key_pair, server_cert) in &[
+            (old_server_key_pair, old_server_cert),
+            (new_server_key_pair, new_server_cert),
+        ] {
+            let acceptor = tls_acceptor(
+                server_key_pair,
+                server_cert,
+                ClientAuthentication::OptionalAuthentication {
+                    trusted_client_certs: vec![client_cert.clone()],
+                },
+            )
+            .unwrap();
+            assert!(!handshake(&acceptor, connector.configure().unwrap()));
+        }
+    }
+
+    #[test]
+    fn should_not_connect_to_server_presenting_untrusted_cert() {
+        let (client_key_pair, client_cert) = generate_ed25519_cert();
+        let (_, trusted_server_cert) = generate_ed25519_cert();
+        let (server_key_pair, server_cert) = generate_ed25519_cert();
+        let acceptor = tls_acceptor(
+            &server_key_pair,
+            &server_cert,
+            ClientAuthentication::OptionalAuthentication {
+                trusted_client_certs: vec![client_cert.clone()],
+            },
+        )
+        .unwrap();
+        let connector = tls_connector_with_session_resumption(
+            &client_key_pair,
+            &client_cert,
+            &[trusted_server_cert],
+        )
+        .unwrap();
+
+        let (client_socket, server_socket) = UnixStream::pair().unwrap();
+        let server = std::thread::spawn(move || acceptor.accept(server_socket).is_err());
+        assert!(connector
+            .configure()
+            .unwrap()
+            .connect("", client_socket)
+            .is_err());
+        assert!(server.join().unwrap());
+    }
+
+    /// Performs a handshake between the acceptor and the connector over a
+    /// socket pair and returns whether the session was resumed.
+    ///
+    /// The server sends a byte after the handshake, so that the client
+    /// processes the session ticket that is sent after the handshake in TLS
+    /// 1.3.
+    fn handshake(acceptor: &SslAcceptor, connect_config: ConnectConfiguration) -> bool {
+        let (client_socket, server_socket) = UnixStream::pair().unwrap();
+        let acceptor = acceptor.clone();
+        let server = std::thread::spawn(move || {
+            let mut server_stream = acceptor.accept(server_socket).unwrap();
+            server_stream.write_all(&[1]).unwrap();
+            server_stream.ssl().session_reused()
+        });
+
+        let mut client_stream = connect_config.connect("", client_socket).unwrap();
+        let mut byte = [0];
+        client_stream.read_exact(&mut byte).unwrap();
+        let client_resumed = client_stream.ssl().session_reused();
+        let server_resumed = server.join().unwrap();
+        assert_eq!(client_resumed, server_resumed);
+        client_resumed
+    }
+
     fn cert_to_der(cert: Option<&X509Ref>) -> Vec<u8> {
         cert.unwrap().to_der().unwrap()
     }
@@ -4,7 +4,7 @@
 //! In particular, the crate provides functionality to
 //! * generate TLS key material and wrap the public part in an X.509 certificate
 //! * create an OpenSSL TLS acceptor
-//! * create an OpenSSL TLS connector
+//! * create an OpenSSL TLS connector, optionally resuming previous sessions
 
 #![forbid(unsafe_code)]
 #![deny(clippy::unwrap_used)]
@@ -13,6 +13,6 @@ pub mod keygen;
 
 mod connection;
 pub use connection::{
-    tls_acceptor, tls_connector, ClientAuthentication, CreateTlsAcceptorError,
-    CreateTlsConnectorError,
+    tls_acceptor, tls_connector, tls_connector_with_session_resumption, ClientAuthentication,
+    CreateTlsAcceptorError, CreateTlsConnectorError, ResumableTlsConnector,
 };
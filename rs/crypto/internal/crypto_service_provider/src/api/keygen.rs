@@ -50,7 +50,27 @@ pub trait CspKeyGenerator {
     /// * if `not_after` is not specified according to RFC 5280 or if
     /// `not_after` is in the past
     /// * if a malformed X509 certificate is generated
-    fn gen_tls_key_pair(&mut self, node_id: NodeId, not_after: &str) -> TlsPublicKeyCert;
+    fn gen_tls_key_pair(&self, node_id: NodeId, not_after: &str) -> TlsPublicKeyCert;
+
+    /// Makes the TLS secret key of certificate `cert` usable with
+    /// `issued_cert`, a certificate for the same key pair that was issued by
+    /// another party, e.g. by a CA of a permissioned network.
+    ///
+    /// # Errors
+    /// * `CryptoError::InvalidArgument` if the certificates are not for the
+    ///   same public key
+    /// * `CryptoError::TlsSecretKeyNotFound` if the secret key of `cert` is
+    ///   not in the key store
+    fn store_tls_secret_key_for_issued_cert(
+        &self,
+        cert: &TlsPublicKeyCert,
+        issued_cert: &TlsPublicKeyCert,
+    ) -> Result<(), CryptoError>;
+
+    /// Removes the secret keys with the given `key_ids` from the key store,
+    /// e.g. those of keys that were rotated out. Key IDs that are not in the
+    /// key store are ignored.
+    fn remove_secret_keys(&self, key_ids: &[KeyId]);
 }
 
 /// A trait that allows checking the secret key store for the availability of a
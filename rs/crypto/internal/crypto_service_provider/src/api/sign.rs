@@ -88,6 +88,31 @@ pub trait CspSigner {
         algorithm_id: AlgorithmId,
     ) -> CryptoResult<CspSignature>;
 
+    /// Verify the individual multisignatures of several signers on the same
+    /// message at once. This is faster than verifying them one by one, but if
+    /// the verification fails it does not tell which of the signatures are
+    /// invalid.
+    ///
+    /// # Arguments
+    /// * `signatures` a Vec of public keys and associated signatures
+    /// * `msg` is the message data to be verified
+    /// * `algorithm_id` the signature algorithm
+    /// # Errors
+    /// * `CryptoError::AlgorithmNotSupported` if the signature algorithm used
+    ///   does not support multisignatures.
+    /// * `CryptoError::SignatureVerification` if at least one of the
+    ///   signatures was found to be invalid.
+    /// * `CryptoError::MalformedSignature` if a signature is malformed.
+    /// * `CryptoError::MalformedPublicKey` if a public key is malformed.
+    /// # Returns
+    /// `Ok(())` if all the signatures are valid or an `Err` otherwise
+    fn verify_multisig_individual_batch(
+        &self,
+        signatures: Vec<(CspPublicKey, CspSignature)>,
+        msg: &[u8],
+        algorithm_id: AlgorithmId,
+    ) -> CryptoResult<()>;
+
     /// Verify a multisignature
     ///
     /// # Arguments
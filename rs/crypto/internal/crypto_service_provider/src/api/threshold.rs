@@ -132,6 +132,17 @@ pub trait ThresholdSignatureCspClient {
         public_key: CspThresholdSigPublicKey,
     ) -> CryptoResult<()>;
 
+    /// Checks whether the individual signatures of several nodes on the same
+    /// message are all valid. This is faster than checking them one by one,
+    /// but if the check fails it does not tell which of the signatures are
+    /// invalid.
+    fn threshold_verify_individual_signatures_batch(
+        &self,
+        algorithm_id: AlgorithmId,
+        message: &[u8],
+        signatures: Vec<(CspSignature, CspThresholdSigPublicKey)>,
+    ) -> CryptoResult<()>;
+
     /// Checks whether a combined signature is valid.
     /// If sufficient valid signatures are combined, the result will pass this
     /// test and this is the ultimate goal of the threshold signature scheme.
@@ -106,8 +106,8 @@ pub trait CspTlsClientHandshake {
     ///
     /// The `self_cert` is used as client certificate for mutual SSL and
     /// the corresponding private key must be in the secret key store. The
-    /// client will only connect to a server that presents the
-    /// `trusted_server_cert` in the TLS handshake.
+    /// client will only connect to a server that presents one of the
+    /// `trusted_server_certs` in the TLS handshake.
     ///
     /// Hostname verification will _not_ be performed during the handshake.
     ///
@@ -135,6 +135,6 @@ pub trait CspTlsClientHandshake {
         &self,
         tcp_stream: TcpStream,
         self_cert: TlsPublicKeyCert,
-        trusted_server_cert: TlsPublicKeyCert,
+        trusted_server_certs: Vec<TlsPublicKeyCert>,
     ) -> Result<(TlsStream, TlsPublicKeyCert), CspTlsClientHandshakeError>;
 }
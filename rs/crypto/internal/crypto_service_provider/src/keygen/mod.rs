@@ -55,7 +55,7 @@ impl<R: Rng + CryptoRng, S: SecretKeyStore> CspKeyGenerator for Csp<R, S> {
         }
     }
 
-    fn gen_tls_key_pair(&mut self, node: NodeId, not_after: &str) -> TlsPublicKeyCert {
+    fn gen_tls_key_pair(&self, node: NodeId, not_after: &str) -> TlsPublicKeyCert {
         let serial = self.rng_write_lock().gen::<[u8; 19]>();
         let common_name = &node.get().to_string()[..];
         let not_after = Asn1Time::from_str_x509(not_after)
@@ -67,6 +67,46 @@ impl<R: Rng + CryptoRng, S: SecretKeyStore> CspKeyGenerator for Csp<R, S> {
         let _key_id = self.store_tls_secret_key(&x509_pk_cert, secret_key);
         x509_pk_cert
     }
+
+    fn store_tls_secret_key_for_issued_cert(
+        &self,
+        cert: &TlsPublicKeyCert,
+        issued_cert: &TlsPublicKeyCert,
+    ) -> Result<(), CryptoError> {
+        let same_public_key = match (
+            cert.as_x509().public_key(),
+            issued_cert.as_x509().public_key(),
+        ) {
+            (Ok(public_key), Ok(issued_public_key)) => public_key.public_eq(&issued_public_key),
+            _ => false,
+        };
+        if !same_public_key {
+            return Err(CryptoError::InvalidArgument {
+                message: "The issued TLS certificate is for another public key".to_string(),
+            });
+        }
+        let key_id = tls_cert_hash_as_key_id(&cert);
+        let issued_key_id = tls_cert_hash_as_key_id(&issued_cert);
+        let mut sks = self.sks_write_lock();
+        if sks.contains(&issued_key_id) {
+            return Ok(());
+        }
+        let secret_key = sks
+            .get(&key_id)
+            .ok_or_else(|| CryptoError::TlsSecretKeyNotFound {
+                certificate_der: cert.as_der().clone(),
+            })?;
+        sks.insert(issued_key_id, secret_key, None)
+            .expect("the key ID was checked to be unused");
+        Ok(())
+    }
+
+    fn remove_secret_keys(&self, key_ids: &[KeyId]) {
+        let mut sks = self.sks_write_lock();
+        for key_id in key_ids {
+            sks.remove(key_id);
+        }
+    }
 }
 
 impl<R: Rng + CryptoRng, S: SecretKeyStore> CspSecretKeyStoreChecker for Csp<R, S> {
@@ -165,7 +205,7 @@ mod tls_keygen {
 
     impl<R: Rng + CryptoRng, S: SecretKeyStore> Csp<R, S> {
         pub(super) fn store_tls_secret_key(
-            &mut self,
+            &self,
             cert: &TlsPublicKeyCert,
             secret_key: TlsEd25519SecretKeyDerBytes,
         ) -> KeyId {
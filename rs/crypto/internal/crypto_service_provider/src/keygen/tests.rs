@@ -45,6 +45,19 @@ fn should_retrieve_newly_generated_secret_key_from_store() {
     );
 }
 
+#[test]
+fn should_remove_secret_keys_from_store() {
+    let csp = Csp::of(csprng_seeded_with(42), volatile_key_store());
+    let (removed_key_id, _) = csp.gen_key_pair(AlgorithmId::Ed25519).unwrap();
+    let (kept_key_id, _) = csp.gen_key_pair(AlgorithmId::Ed25519).unwrap();
+    let unknown_key_id = KeyId::from([42; 32]);
+
+    csp.remove_secret_keys(&[removed_key_id, unknown_key_id]);
+
+    assert!(!csp.sks_contains(&removed_key_id));
+    assert!(csp.sks_contains(&kept_key_id));
+}
+
 #[test]
 /// If this test fails, old key IDs in the SKS will no longer work!
 fn should_correctly_convert_tls_cert_hash_as_key_id() {
@@ -153,6 +166,8 @@ mod multi {
 mod tls {
     use super::*;
     use crate::secret_key_store::test_utils::MockSecretKeyStore;
+    use ic_crypto_test_utils::tls::x509_certificates::CertWithPrivateKey;
+    use openssl::hash::MessageDigest;
     use openssl::pkey::{Id, PKey};
     use openssl::x509::X509VerifyResult;
 
@@ -190,14 +205,14 @@ mod tls {
                 [42; 32],
             ))));
 
-        let mut csp = Csp::of(rng(), sks_returning_error_on_insert);
+        let csp = Csp::of(rng(), sks_returning_error_on_insert);
 
         let _ = csp.gen_tls_key_pair(node_test_id(NODE_1), NOT_AFTER);
     }
 
     #[test]
     fn should_return_der_encoded_self_signed_certificate() {
-        let mut csp = Csp::of(rng(), volatile_key_store());
+        let csp = Csp::of(rng(), volatile_key_store());
 
         let cert = csp.gen_tls_key_pair(node_test_id(NODE_1), NOT_AFTER);
 
@@ -209,7 +224,7 @@ mod tls {
 
     #[test]
     fn should_set_cert_subject_cn_as_node_id() {
-        let mut csp = Csp::of(rng(), volatile_key_store());
+        let csp = Csp::of(rng(), volatile_key_store());
 
         let cert = csp.gen_tls_key_pair(node_test_id(NODE_1), NOT_AFTER);
 
@@ -222,7 +237,7 @@ mod tls {
 
     #[test]
     fn should_use_stable_node_id_string_representation_as_subject_cn() {
-        let mut csp = Csp::of(rng(), volatile_key_store());
+        let csp = Csp::of(rng(), volatile_key_store());
 
         let cert = csp.gen_tls_key_pair(node_test_id(NODE_1), NOT_AFTER);
 
@@ -232,7 +247,7 @@ mod tls {
 
     #[test]
     fn should_set_cert_issuer_cn_as_node_id() {
-        let mut csp = Csp::of(rng(), volatile_key_store());
+        let csp = Csp::of(rng(), volatile_key_store());
 
         let cert = csp.gen_tls_key_pair(node_test_id(NODE_1), NOT_AFTER);
 
@@ -248,7 +263,7 @@ mod tls {
 
     #[test]
     fn should_not_set_cert_subject_alt_name() {
-        let mut csp = Csp::of(rng(), volatile_key_store());
+        let csp = Csp::of(rng(), volatile_key_store());
 
         let cert = csp.gen_tls_key_pair(node_test_id(NODE_1), NOT_AFTER);
 
@@ -258,7 +273,7 @@ mod tls {
 
     #[test]
     fn should_set_random_cert_serial_number() {
-        let mut csp = Csp::of(csprng_seeded_with(FIXED_SEED), volatile_key_store());
+        let csp = Csp::of(csprng_seeded_with(FIXED_SEED), volatile_key_store());
 
         let cert = csp.gen_tls_key_pair(node_test_id(NODE_1), NOT_AFTER);
 
@@ -270,7 +285,7 @@ mod tls {
 
     #[test]
     fn should_set_different_serial_numbers_for_multiple_certs() {
-        let mut csp = Csp::of(rng(), volatile_key_store());
+        let csp = Csp::of(rng(), volatile_key_store());
 
         let cert_1 = csp.gen_tls_key_pair(node_test_id(NODE_1), NOT_AFTER);
         let cert_2 = csp.gen_tls_key_pair(node_test_id(NODE_2), NOT_AFTER);
@@ -286,7 +301,7 @@ mod tls {
 
     #[test]
     fn should_set_cert_not_after_correctly() {
-        let mut csp = Csp::of(rng(), volatile_key_store());
+        let csp = Csp::of(rng(), volatile_key_store());
         let not_after = NOT_AFTER;
 
         let cert = csp.gen_tls_key_pair(node_test_id(NODE_1), not_after);
@@ -297,7 +312,7 @@ mod tls {
     #[test]
     #[should_panic(expected = "invalid X.509 certificate expiration date (not_after)")]
     fn should_panic_on_invalid_not_after_date() {
-        let mut csp = Csp::of(rng(), volatile_key_store());
+        let csp = Csp::of(rng(), volatile_key_store());
 
         let _panic = csp.gen_tls_key_pair(node_test_id(NODE_1), "invalid_not_after_date");
     }
@@ -305,16 +320,64 @@ mod tls {
     #[test]
     #[should_panic(expected = "'not after' date must not be in the past")]
     fn should_panic_if_not_after_date_is_in_the_past() {
-        let mut csp = Csp::of(rng(), volatile_key_store());
+        let csp = Csp::of(rng(), volatile_key_store());
         let date_in_the_past = "20000102030405Z";
 
         let _panic = csp.gen_tls_key_pair(node_test_id(NODE_1), &date_in_the_past);
     }
 
+    #[test]
+    fn should_store_secret_key_for_cert_issued_for_same_key() {
+        let mut csp = Csp::of(rng(), volatile_key_store());
+        let cert = csp.gen_tls_key_pair(node_test_id(NODE_1), NOT_AFTER);
+        let issued_cert = issued_by_ca(&mut csp, &cert);
+
+        assert!(!csp.sks_contains_tls_key(&issued_cert));
+        assert!(csp
+            .store_tls_secret_key_for_issued_cert(&cert, &issued_cert)
+            .is_ok());
+        assert!(csp.sks_contains_tls_key(&issued_cert));
+        // Storing it again is a no-op.
+        assert!(csp
+            .store_tls_secret_key_for_issued_cert(&cert, &issued_cert)
+            .is_ok());
+    }
+
+    #[test]
+    fn should_not_store_secret_key_for_cert_issued_for_other_key() {
+        let csp = Csp::of(rng(), volatile_key_store());
+        let cert = csp.gen_tls_key_pair(node_test_id(NODE_1), NOT_AFTER);
+        let other_cert = csp.gen_tls_key_pair(node_test_id(NODE_2), NOT_AFTER);
+
+        let result = csp.store_tls_secret_key_for_issued_cert(&cert, &other_cert);
+
+        assert!(matches!(result, Err(CryptoError::InvalidArgument { .. })));
+    }
+
     fn rng() -> impl CryptoRng + Rng {
         csprng_seeded_with(42)
     }
 
+    /// Returns a certificate for the key pair of `cert` that is signed by a
+    /// new CA key.
+    fn issued_by_ca(
+        csp: &mut Csp<impl CryptoRng + Rng, VolatileSecretKeyStore>,
+        cert: &TlsPublicKeyCert,
+    ) -> TlsPublicKeyCert {
+        let key_pair = match secret_key_from_store(csp, cert.as_x509().clone()) {
+            CspSecretKey::TlsEd25519(sk_der_bytes) => {
+                PKey::private_key_from_der(&sk_der_bytes.bytes).unwrap()
+            }
+            _ => panic!("secret key has the wrong type"),
+        };
+        let issued_cert = CertWithPrivateKey::builder()
+            .cn(node_test_id(NODE_1).get().to_string())
+            .with_ca_signing(PKey::generate_ed25519().unwrap(), "Test CA".to_string())
+            .build(key_pair, MessageDigest::null())
+            .x509();
+        TlsPublicKeyCert::new_from_x509(issued_cert).unwrap()
+    }
+
     fn secret_key_from_store(
         csp: &mut Csp<impl CryptoRng + Rng, VolatileSecretKeyStore>,
         x509_cert: X509,
@@ -24,6 +24,7 @@ use crate::keygen::{forward_secure_key_id, public_key_hash_as_key_id};
 use crate::public_key_store::read_node_public_keys;
 use crate::secret_key_store::volatile_store::VolatileSecretKeyStore;
 use crate::secret_key_store::SecretKeyStore;
+use crate::tls_stub::context_cache::TlsContextCache;
 use crate::types::CspPublicKey;
 use ic_config::crypto::CryptoConfig;
 use ic_crypto_internal_logmon::metrics::CryptoMetrics;
@@ -118,6 +119,7 @@ pub struct Csp<R: Rng + CryptoRng, S: SecretKeyStore> {
     csprng: CspRwLock<R>,
     secret_key_store: CspRwLock<S>,
     public_key_data: PublicKeyData,
+    tls_context_cache: TlsContextCache,
     logger: ReplicaLogger,
 }
 
@@ -205,6 +207,7 @@ impl Csp<OsRng, ProtoSecretKeyStore> {
             csprng: CspRwLock::new_for_rng(OsRng::default(), Arc::clone(&metrics)),
             public_key_data,
             secret_key_store: CspRwLock::new_for_sks(secret_key_store, metrics),
+            tls_context_cache: TlsContextCache::default(),
             logger,
         }
     }
@@ -228,6 +231,7 @@ impl<R: Rng + CryptoRng> Csp<R, ProtoSecretKeyStore> {
                 ProtoSecretKeyStore::open(&config.crypto_root, None),
                 Arc::new(CryptoMetrics::none()),
             ),
+            tls_context_cache: TlsContextCache::default(),
             logger: no_op_logger(),
         }
     }
@@ -277,6 +281,7 @@ impl<R: Rng + CryptoRng, S: SecretKeyStore> Csp<R, S> {
             csprng: CspRwLock::new_for_rng(csprng, Arc::clone(&metrics)),
             public_key_data,
             secret_key_store: CspRwLock::new_for_sks(secret_key_store, metrics),
+            tls_context_cache: TlsContextCache::default(),
             logger: no_op_logger(),
         }
     }
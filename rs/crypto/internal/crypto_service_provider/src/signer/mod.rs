@@ -170,6 +170,49 @@ impl<R: Rng + CryptoRng, S: SecretKeyStore> CspSigner for Csp<R, S> {
         }
     }
 
+    fn verify_multisig_individual_batch(
+        &self,
+        signatures: Vec<(CspPublicKey, CspSignature)>,
+        msg: &[u8],
+        algorithm_id: AlgorithmId,
+    ) -> CryptoResult<()> {
+        match algorithm_id {
+            AlgorithmId::MultiBls12_381 => {
+                let signatures: CryptoResult<
+                    Vec<(
+                        multi_sig::types::IndividualSignatureBytes,
+                        multi_sig::types::PublicKeyBytes,
+                    )>,
+                > = signatures
+                    .iter()
+                    .map(|(public_key, signature)| match (public_key, signature) {
+                        (
+                            CspPublicKey::MultiBls12_381(public_key),
+                            CspSignature::MultiBls12_381(MultiBls12_381_Signature::Individual(
+                                signature,
+                            )),
+                        ) => Ok((*signature, *public_key)),
+                        _ => Err(CryptoError::SignatureVerification {
+                            algorithm: algorithm_id,
+                            public_key_bytes: public_key.as_ref().to_vec(),
+                            sig_bytes: signature.as_ref().to_vec(),
+                            internal_error: "Unsupported types".to_string(),
+                        }),
+                    })
+                    .collect();
+                multi_sig::verify_individual_batch(
+                    msg,
+                    &signatures?[..],
+                    &mut *self.rng_write_lock(),
+                )
+            }
+            _ => Err(CryptoError::AlgorithmNotSupported {
+                algorithm: algorithm_id,
+                reason: "Not a multi-signature algorithm".to_string(),
+            }),
+        }
+    }
+
     fn verify_multisig(
         &self,
         signers: Vec<CspPublicKey>,
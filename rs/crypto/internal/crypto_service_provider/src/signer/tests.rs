@@ -501,6 +501,48 @@ mod multi {
         assert!(result.unwrap_err().is_signature_verification_error());
     }
 
+    #[test]
+    fn individual_signatures_verify_in_batch() {
+        let csp1 = Csp::of(
+            ChaCha20Rng::seed_from_u64(42),
+            VolatileSecretKeyStore::new(),
+        );
+        let csp2 = Csp::of(
+            ChaCha20Rng::seed_from_u64(7_832_645),
+            VolatileSecretKeyStore::new(),
+        );
+        let verifier = Csp::of(
+            ChaCha20Rng::seed_from_u64(69),
+            secret_key_store_panicking_on_usage(),
+        );
+        let (key_id1, public_key1) = csp1.gen_key_pair(AlgorithmId::MultiBls12_381).unwrap();
+        let (key_id2, public_key2) = csp2.gen_key_pair(AlgorithmId::MultiBls12_381).unwrap();
+        let message = b"Three turtle doves";
+        let signature1 = csp1
+            .sign(AlgorithmId::MultiBls12_381, message, key_id1)
+            .expect("Signing failed");
+        let signature2 = csp2
+            .sign(AlgorithmId::MultiBls12_381, message, key_id2)
+            .expect("Signing failed");
+
+        assert!(verifier
+            .verify_multisig_individual_batch(
+                vec![
+                    (public_key1.clone(), signature1.clone()),
+                    (public_key2.clone(), signature2.clone()),
+                ],
+                message,
+                AlgorithmId::MultiBls12_381
+            )
+            .is_ok());
+        let result = verifier.verify_multisig_individual_batch(
+            vec![(public_key1, signature2), (public_key2, signature1)],
+            message,
+            AlgorithmId::MultiBls12_381,
+        );
+        assert!(result.unwrap_err().is_signature_verification_error());
+    }
+
     #[test]
     fn combined_signature_verifies() {
         // Actors:
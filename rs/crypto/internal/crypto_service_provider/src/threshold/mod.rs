@@ -174,6 +174,35 @@ impl<R: Rng + CryptoRng, S: SecretKeyStore> ThresholdSignatureCspClient for Csp<
         }
     }
 
+    fn threshold_verify_individual_signatures_batch(
+        &self,
+        algorithm_id: AlgorithmId,
+        message: &[u8],
+        signatures: Vec<(CspSignature, CspThresholdSigPublicKey)>,
+    ) -> CryptoResult<()> {
+        match algorithm_id {
+            AlgorithmId::ThresBls12_381 => {
+                let clib_signatures: CryptoResult<Vec<_>> = signatures
+                    .into_iter()
+                    .map(|(signature, public_key)| {
+                        Ok((
+                            clib::types::IndividualSignatureBytes::try_from(signature)?,
+                            PublicKeyBytes::from(public_key),
+                        ))
+                    })
+                    .collect();
+                clib::api::verify_individual_signatures_batch(
+                    message,
+                    &clib_signatures?[..],
+                    &mut *self.rng_write_lock(),
+                )
+            }
+            _ => Err(CryptoError::InvalidArgument {
+                message: format!("Unsupported algorithm: {:?}", algorithm_id),
+            }),
+        }
+    }
+
     fn threshold_verify_combined_signature(
         &self,
         algorithm_id: AlgorithmId,
@@ -33,7 +33,9 @@ impl CspCertificateChain {
     /// issuance.
     ///
     /// Returns an error if the given `chain` is empty.
-    fn new(chain: Vec<TlsPublicKeyCert>) -> Result<Self, CspCertificateChainCreationError> {
+    pub(crate) fn new(
+        chain: Vec<TlsPublicKeyCert>,
+    ) -> Result<Self, CspCertificateChainCreationError> {
         if chain.is_empty() {
             return Err(CspCertificateChainCreationError::ChainEmpty);
         }
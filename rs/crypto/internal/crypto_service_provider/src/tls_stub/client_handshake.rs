@@ -20,9 +20,9 @@ impl<R: Rng + CryptoRng + Send + Sync, S: SecretKeyStore> CspTlsClientHandshake
         &self,
         tcp_stream: TcpStream,
         self_cert: TlsPublicKeyCert,
-        trusted_server_cert: TlsPublicKeyCert,
+        trusted_server_certs: Vec<TlsPublicKeyCert>,
     ) -> Result<(TlsStream, TlsPublicKeyCert), CspTlsClientHandshakeError> {
-        let tls_connector = self.tls_connector(self_cert, trusted_server_cert)?;
+        let tls_connector = self.tls_connector(self_cert, trusted_server_certs)?;
 
         let mut tls_stream = unconnected_tls_stream(
             tls_connector,
@@ -49,18 +49,33 @@ impl<R: Rng + CryptoRng + Send + Sync, S: SecretKeyStore> Csp<R, S> {
     /// Creates a Connector for TLS. This allows to set up a TLS connection as a
     /// client. The `self_cert` is used as client certificate for mutual SSL and
     /// the corresponding private key must be in the secret key store. The
-    /// client will only connect to a server that presents the
-    /// `trusted_server_cert` in the TLS handshake.
+    /// client will only connect to a server that presents one of the
+    /// `trusted_server_certs` in the TLS handshake.
+    ///
+    /// The connector is cached per certificate configuration, so that the
+    /// session established in a previous handshake with the same server is
+    /// resumed.
     fn tls_connector(
         &self,
         self_cert: TlsPublicKeyCert,
-        trusted_server_cert: TlsPublicKeyCert,
+        trusted_server_certs: Vec<TlsPublicKeyCert>,
     ) -> Result<ConnectConfiguration, CspTlsClientHandshakeError> {
-        Ok(ic_crypto_internal_tls::tls_connector(
-            &key_from_secret_key_store(&*self.sks_read_lock(), &self_cert)?,
-            &self_cert.as_x509(),
-            &trusted_server_cert.as_x509(),
-        )?)
+        let connector =
+            self.tls_context_cache
+                .connector(&self_cert, &trusted_server_certs, || {
+                    let trusted_server_certs: Vec<_> = trusted_server_certs
+                        .iter()
+                        .map(|cert| cert.as_x509().clone())
+                        .collect();
+                    Ok::<_, CspTlsClientHandshakeError>(
+                        ic_crypto_internal_tls::tls_connector_with_session_resumption(
+                            &key_from_secret_key_store(&*self.sks_read_lock(), &self_cert)?,
+                            &self_cert.as_x509(),
+                            &trusted_server_certs,
+                        )?,
+                    )
+                })?;
+        Ok(connector.configure()?)
     }
 }
 
@@ -21,7 +21,7 @@ fn should_return_connector_from_clib_if_no_error_occurs() {
     let (_, trusted_server_cert) = generate_ed25519_tlscert();
 
     let connector = csp
-        .tls_connector(self_cert.clone(), trusted_server_cert)
+        .tls_connector(self_cert.clone(), vec![trusted_server_cert])
         .unwrap();
 
     // only check a few connector properties (details are tested in the CLib)
@@ -45,7 +45,11 @@ async fn should_return_create_connector_error_from_clib() {
     let (_, trusted_server_cert) = generate_ed25519_tlscert();
 
     let result = csp
-        .perform_tls_client_handshake(dummy_tcp_stream().await, self_cert, trusted_server_cert)
+        .perform_tls_client_handshake(
+            dummy_tcp_stream().await,
+            self_cert,
+            vec![trusted_server_cert],
+        )
         .await;
 
     assert_create_connector_error(result, "Inconsistent private key and certificate.")
@@ -59,7 +63,11 @@ async fn should_return_error_if_secret_key_not_found() {
     let (_, trusted_server_cert) = generate_ed25519_tlscert();
 
     let result = csp
-        .perform_tls_client_handshake(dummy_tcp_stream().await, self_cert, trusted_server_cert)
+        .perform_tls_client_handshake(
+            dummy_tcp_stream().await,
+            self_cert,
+            vec![trusted_server_cert],
+        )
         .await;
 
     assert!(matches!(
@@ -78,7 +86,11 @@ async fn should_return_error_on_wrong_secret_key_type() {
     let (_, trusted_server_cert) = generate_ed25519_tlscert();
 
     let result = csp
-        .perform_tls_client_handshake(dummy_tcp_stream().await, self_cert, trusted_server_cert)
+        .perform_tls_client_handshake(
+            dummy_tcp_stream().await,
+            self_cert,
+            vec![trusted_server_cert],
+        )
         .await;
 
     assert!(matches!(
@@ -96,7 +108,11 @@ async fn should_return_error_on_malformed_secret_key() {
     let (_, trusted_server_cert) = generate_ed25519_tlscert();
 
     let result = csp
-        .perform_tls_client_handshake(dummy_tcp_stream().await, self_cert, trusted_server_cert)
+        .perform_tls_client_handshake(
+            dummy_tcp_stream().await,
+            self_cert,
+            vec![trusted_server_cert],
+        )
         .await;
 
     assert!(matches!(
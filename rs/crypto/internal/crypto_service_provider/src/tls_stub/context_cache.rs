@@ -0,0 +1,103 @@
+//! Caching of TLS contexts for session resumption
+
+use ic_crypto_internal_tls::ResumableTlsConnector;
+use ic_crypto_tls_interfaces::TlsPublicKeyCert;
+use openssl::ssl::SslAcceptor;
+use parking_lot::Mutex;
+use std::collections::{BTreeSet, HashMap};
+
+#[cfg(test)]
+mod tests;
+
+/// The maximum number of contexts that are cached per handshake side. If the
+/// limit is reached, the cache is cleared, which only means that the next
+/// handshakes are full handshakes.
+const MAX_CACHED_CONTEXTS: usize = 1024;
+
+/// The key of a cached connector: the DER encodings of the client's own
+/// certificate and of the trusted server certificates.
+type ConnectorKey = (Vec<u8>, BTreeSet<Vec<u8>>);
+
+/// The key of a cached acceptor: the DER encodings of the server's own
+/// certificate and of the trusted client certificates, if client
+/// authentication is performed.
+type AcceptorKey = (Vec<u8>, Option<BTreeSet<Vec<u8>>>);
+
+/// Caches the TLS contexts used for handshakes, such that sessions can be
+/// resumed.
+///
+/// Session tickets issued by a server can only be decrypted by the acceptor
+/// that issued them, and a client only offers the ticket it received through
+/// the same connector. Contexts are cached per certificate configuration, so
+/// that a change in the trusted certificates results in a new context, which
+/// does not resume sessions established under the previous configuration.
+#[derive(Default)]
+pub struct TlsContextCache {
+    connectors: Mutex<HashMap<ConnectorKey, ResumableTlsConnector>>,
+    acceptors: Mutex<HashMap<AcceptorKey, SslAcceptor>>,
+}
+
+impl TlsContextCache {
+    /// Returns the cached connector for the given certificates, or creates
+    /// and caches a new one using `create`.
+    pub fn connector<E, F>(
+        &self,
+        self_cert: &TlsPublicKeyCert,
+        trusted_server_certs: &[TlsPublicKeyCert],
+        create: F,
+    ) -> Result<ResumableTlsConnector, E>
+    where
+        F: FnOnce() -> Result<ResumableTlsConnector, E>,
+    {
+        let key = (
+            self_cert.as_der().clone(),
+            trusted_server_certs
+                .iter()
+                .map(|cert| cert.as_der().clone())
+                .collect(),
+        );
+        get_or_insert(&self.connectors, key, create)
+    }
+
+    /// Returns the cached acceptor for the given certificates, or creates and
+    /// caches a new one using `create`.
+    pub fn acceptor<'a, E, F, I>(
+        &self,
+        self_cert: &TlsPublicKeyCert,
+        trusted_client_certs: Option<I>,
+        create: F,
+    ) -> Result<SslAcceptor, E>
+    where
+        F: FnOnce() -> Result<SslAcceptor, E>,
+        I: IntoIterator<Item = &'a TlsPublicKeyCert>,
+    {
+        let key = (
+            self_cert.as_der().clone(),
+            trusted_client_certs.map(|certs| {
+                certs
+                    .into_iter()
+                    .map(|cert| cert.as_der().clone())
+                    .collect()
+            }),
+        );
+        get_or_insert(&self.acceptors, key, create)
+    }
+}
+
+fn get_or_insert<K, V, E, F>(cache: &Mutex<HashMap<K, V>>, key: K, create: F) -> Result<V, E>
+where
+    K: Eq + std::hash::Hash,
+    V: Clone,
+    F: FnOnce() -> Result<V, E>,
+{
+    let mut cache = cache.lock();
+    if let Some(context) = cache.get(&key) {
+        return Ok(context.clone());
+    }
+    let context = create()?;
+    if cache.len() >= MAX_CACHED_CONTEXTS {
+        cache.clear();
+    }
+    cache.insert(key, context.clone());
+    Ok(context)
+}
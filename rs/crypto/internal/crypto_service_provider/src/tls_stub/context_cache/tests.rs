@@ -0,0 +1,111 @@
+#![allow(clippy::unwrap_used)]
+use crate::tls_stub::context_cache::TlsContextCache;
+use ic_crypto_internal_tls::{
+    tls_acceptor, tls_connector_with_session_resumption, ClientAuthentication,
+    CreateTlsConnectorError,
+};
+use ic_crypto_test_utils::tls::x509_certificates::generate_ed25519_tlscert;
+use ic_crypto_tls_interfaces::TlsPublicKeyCert;
+use std::cell::Cell;
+
+#[test]
+fn should_reuse_cached_connector_for_same_certificates() {
+    let cache = TlsContextCache::default();
+    let (private_key, self_cert) = generate_ed25519_tlscert();
+    let (_, server_cert) = generate_ed25519_tlscert();
+    let created = Cell::new(0);
+    let create = || {
+        created.set(created.get() + 1);
+        tls_connector_with_session_resumption(
+            &private_key,
+            self_cert.as_x509(),
+            &[server_cert.as_x509().clone()],
+        )
+    };
+
+    cache
+        .connector::<CreateTlsConnectorError, _>(&self_cert, &[server_cert.clone()], create)
+        .unwrap();
+    cache
+        .connector::<CreateTlsConnectorError, _>(&self_cert, &[server_cert.clone()], create)
+        .unwrap();
+
+    assert_eq!(created.get(), 1);
+}
+
+#[test]
+fn should_create_new_connector_for_other_server_certificate() {
+    let cache = TlsContextCache::default();
+    let (private_key, self_cert) = generate_ed25519_tlscert();
+    let (_, server_cert) = generate_ed25519_tlscert();
+    let (_, other_server_cert) = generate_ed25519_tlscert();
+    let created = Cell::new(0);
+    let create = |server_cert: &TlsPublicKeyCert| {
+        created.set(created.get() + 1);
+        tls_connector_with_session_resumption(
+            &private_key,
+            self_cert.as_x509(),
+            &[server_cert.as_x509().clone()],
+        )
+    };
+
+    cache
+        .connector(&self_cert, &[server_cert.clone()], || create(&server_cert))
+        .unwrap();
+    cache
+        .connector(&self_cert, &[other_server_cert.clone()], || {
+            create(&other_server_cert)
+        })
+        .unwrap();
+
+    assert_eq!(created.get(), 2);
+}
+
+#[test]
+fn should_create_new_acceptor_if_trusted_client_certificates_change() {
+    let cache = TlsContextCache::default();
+    let (private_key, self_cert) = generate_ed25519_tlscert();
+    let (_, client_cert) = generate_ed25519_tlscert();
+    let (_, other_client_cert) = generate_ed25519_tlscert();
+    let created = Cell::new(0);
+    let create = |trusted_client_certs: Vec<&TlsPublicKeyCert>| {
+        created.set(created.get() + 1);
+        tls_acceptor(
+            &private_key,
+            self_cert.as_x509(),
+            ClientAuthentication::OptionalAuthentication {
+                trusted_client_certs: trusted_client_certs
+                    .into_iter()
+                    .map(|cert| cert.as_x509().clone())
+                    .collect(),
+            },
+        )
+    };
+
+    let trusted = vec![&client_cert];
+    let updated = vec![&other_client_cert, &client_cert];
+    let updated_reordered = vec![&client_cert, &other_client_cert];
+    cache
+        .acceptor(&self_cert, Some(trusted.clone()), || {
+            create(trusted.clone())
+        })
+        .unwrap();
+    cache
+        .acceptor(&self_cert, Some(trusted.clone()), || {
+            create(trusted.clone())
+        })
+        .unwrap();
+    assert_eq!(created.get(), 1);
+
+    cache
+        .acceptor(&self_cert, Some(updated.clone()), || {
+            create(updated.clone())
+        })
+        .unwrap();
+    cache
+        .acceptor(&self_cert, Some(updated_reordered.clone()), || {
+            create(updated_reordered.clone())
+        })
+        .unwrap();
+    assert_eq!(created.get(), 2);
+}
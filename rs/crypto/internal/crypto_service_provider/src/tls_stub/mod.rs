@@ -17,6 +17,7 @@ use tokio_openssl::SslStream;
 
 pub mod cert_chain;
 mod client_handshake;
+pub(crate) mod context_cache;
 mod server_handshake;
 
 #[cfg(test)]
@@ -115,6 +116,17 @@ fn peer_cert_chain_from_stream(
     // Note: the result of `verified_chain` must not be used if the `verify_result`
     // is not OK because the chain may be incomplete or invalid.
     match (peer_cert_chain, verify_result_is_ok) {
+        // If a session is resumed, the client certificate is not sent and hence
+        // no chain is verified. The certificate verified in the original
+        // handshake is retained in the session, and is known to be trusted
+        // because the session ticket can only be decrypted by the acceptor
+        // configured with the same trusted certificates.
+        (None, true) if tls_stream.ssl().session_reused() => {
+            match peer_cert_from_stream(&tls_stream)? {
+                Some(peer_cert) => Ok(Some(CspCertificateChain::new(vec![peer_cert])?)),
+                None => Ok(None),
+            }
+        }
         (None, _) => Ok(None),
         (Some(verified_chain), true) => {
             let cert_chain = CspCertificateChain::try_from(verified_chain)?;
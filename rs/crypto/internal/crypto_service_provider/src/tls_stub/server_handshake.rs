@@ -66,6 +66,9 @@ impl<R: Rng + CryptoRng, S: SecretKeyStore> Csp<R, S> {
     /// If trusted_client_certs is Some then the non-empty set will
     /// list the client certificates that will be accepted. If it is None,
     /// then no client authentication will be performed.
+    ///
+    /// The acceptor is cached per certificate configuration, so that session
+    /// tickets issued in previous handshakes can be decrypted.
     fn tls_acceptor(
         &self,
         self_cert: TlsPublicKeyCert,
@@ -73,17 +76,24 @@ impl<R: Rng + CryptoRng, S: SecretKeyStore> Csp<R, S> {
     ) -> Result<SslAcceptor, CspTlsServerHandshakeError> {
         use ic_crypto_internal_tls::{tls_acceptor, ClientAuthentication};
 
-        let trusted_client_certs_x509 = match trusted_client_certs {
-            Some(c) => ClientAuthentication::OptionalAuthentication {
-                trusted_client_certs: c.iter().map(TlsPublicKeyCert::as_x509).cloned().collect(),
-            },
-            None => ClientAuthentication::NoAuthentication,
-        };
-        Ok(tls_acceptor(
-            &key_from_secret_key_store(&*self.sks_read_lock(), &self_cert)?,
-            &self_cert.as_x509(),
-            trusted_client_certs_x509,
-        )?)
+        self.tls_context_cache
+            .acceptor(&self_cert, trusted_client_certs.as_ref(), || {
+                let trusted_client_certs_x509 = match &trusted_client_certs {
+                    Some(c) => ClientAuthentication::OptionalAuthentication {
+                        trusted_client_certs: c
+                            .iter()
+                            .map(TlsPublicKeyCert::as_x509)
+                            .cloned()
+                            .collect(),
+                    },
+                    None => ClientAuthentication::NoAuthentication,
+                };
+                Ok::<_, CspTlsServerHandshakeError>(tls_acceptor(
+                    &key_from_secret_key_store(&*self.sks_read_lock(), &self_cert)?,
+                    &self_cert.as_x509(),
+                    trusted_client_certs_x509,
+                )?)
+            })
     }
 }
 
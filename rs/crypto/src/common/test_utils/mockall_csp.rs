@@ -75,6 +75,13 @@ mock! {
             algorithm_id: AlgorithmId,
         ) -> CryptoResult<CspSignature>;
 
+        fn verify_multisig_individual_batch(
+            &self,
+            signatures: Vec<(CspPublicKey, CspSignature)>,
+            msg: &[u8],
+            algorithm_id: AlgorithmId,
+        ) -> CryptoResult<()>;
+
         fn verify_multisig(
             &self,
             signers: Vec<CspPublicKey>,
@@ -93,10 +100,18 @@ mock! {
         ) -> Result<(KeyId, CspPublicKey, CspPop), CryptoError>;
 
         fn gen_tls_key_pair(
-            &mut self,
+            &self,
             node: NodeId,
             not_after: &str,
         ) -> TlsPublicKeyCert;
+
+        fn store_tls_secret_key_for_issued_cert(
+            &self,
+            cert: &TlsPublicKeyCert,
+            issued_cert: &TlsPublicKeyCert,
+        ) -> Result<(), CryptoError>;
+
+        fn remove_secret_keys(&self, key_ids: &[KeyId]);
     }
 
     pub trait ThresholdSignatureCspClient {
@@ -136,6 +151,13 @@ mock! {
             public_key: CspThresholdSigPublicKey,
         ) -> CryptoResult<()>;
 
+        fn threshold_verify_individual_signatures_batch(
+            &self,
+            algorithm_id: AlgorithmId,
+            message: &[u8],
+            signatures: Vec<(CspSignature, CspThresholdSigPublicKey)>,
+        ) -> CryptoResult<()>;
+
         fn threshold_verify_combined_signature(
             &self,
             algorithm_id: AlgorithmId,
@@ -358,7 +380,7 @@ mock! {
             &self,
             tcp_stream: TcpStream,
             self_cert: TlsPublicKeyCert,
-            trusted_server_cert: TlsPublicKeyCert,
+            trusted_server_certs: Vec<TlsPublicKeyCert>,
         ) -> Result<(TlsStream, TlsPublicKeyCert), CspTlsClientHandshakeError>;
     }
 
@@ -135,6 +135,17 @@ pub fn get_node_keys_or_generate_if_missing(crypto_root: &Path) -> (NodePublicKe
     }
 }
 
+/// Replaces the node's public keys in the public key store at `crypto_root`,
+/// e.g. once rotated keys are registered in the registry. The corresponding
+/// secret keys must already be in the secret key store.
+pub fn store_node_public_keys(crypto_root: &Path, node_pks: &NodePublicKeys) -> CryptoResult<()> {
+    public_key_store::store_node_public_keys(crypto_root, node_pks).map_err(|e| {
+        CryptoError::InvalidArgument {
+            message: format!("Failed storing public keys: {:?}", e),
+        }
+    })
+}
+
 pub(crate) fn derive_node_id(node_signing_pk: &PublicKeyProto) -> NodeId {
     let pk_bytes = ed25519::types::PublicKeyBytes::try_from(node_signing_pk)
         .expect("Corrupted node signing public key");
@@ -254,7 +265,7 @@ fn generate_committee_signing_keys(crypto_root: &Path) -> PublicKeyProto {
 ///
 /// Returns the certificate.
 fn generate_tls_keys(crypto_root: &Path, node: NodeId) -> TlsPublicKeyCert {
-    let mut csp = csp_at_root(crypto_root);
+    let csp = csp_at_root(crypto_root);
     csp.gen_tls_key_pair(node, "99991231235959Z")
 }
 
@@ -6,12 +6,15 @@ use crate::common::utils::{
 use crate::{CryptoComponent, CryptoComponentFatClient};
 use async_trait::async_trait;
 use ic_config::crypto::CryptoConfig;
+use ic_crypto_internal_csp::keygen::tls_cert_hash_as_key_id;
 use ic_crypto_internal_csp::secret_key_store::proto_store::ProtoSecretKeyStore;
+use ic_crypto_internal_csp::secret_key_store::SecretKeyStore;
+use ic_crypto_internal_csp::types::CspSecretKey;
 use ic_crypto_internal_csp::{public_key_store, CryptoServiceProvider, Csp};
 use ic_crypto_tls_interfaces::TlsPublicKeyCert;
 use ic_crypto_tls_interfaces::{
     AllowedClients, AuthenticatedPeer, Peer, TlsClientHandshakeError, TlsHandshake,
-    TlsServerHandshakeError, TlsStream,
+    TlsServerHandshakeError, TlsStream, TlsValidation,
 };
 use ic_interfaces::crypto::{
     BasicSigVerifier, BasicSigVerifierByPublicKey, CanisterSigVerifier, MultiSigVerifier, Signable,
@@ -52,9 +55,7 @@ pub type TempCryptoComponent = TempCryptoComponentGeneric<Csp<OsRng, ProtoSecret
 /// deleted once the struct goes out of scope.
 pub struct TempCryptoComponentGeneric<C: CryptoServiceProvider> {
     crypto_component: CryptoComponentFatClient<C>,
-    // the temp_dir is required even though it is never read, so the directory exists as long as
-    // TempCryptoComponent exists.
-    #[allow(dead_code)]
+    // the temp_dir is required so the directory exists as long as TempCryptoComponent exists.
     temp_dir: TempDir,
 }
 
@@ -175,6 +176,20 @@ impl TempCryptoComponent {
         (temp_crypto, node_pubkeys)
     }
 
+    /// Returns the DER encoded secret key of the TLS certificate `cert`, so
+    /// that tests can have a CA issue certificates for the node's TLS key
+    /// pair.
+    ///
+    /// # Panics
+    /// If the secret key store contains no TLS secret key for `cert`.
+    pub fn tls_secret_key_der(&self, cert: &TlsPublicKeyCert) -> Vec<u8> {
+        let sks = ProtoSecretKeyStore::open(self.temp_dir.path(), None);
+        match sks.get(&tls_cert_hash_as_key_id(cert)) {
+            Some(CspSecretKey::TlsEd25519(secret_key)) => secret_key.bytes,
+            _ => panic!("The secret key store contains no TLS secret key for the certificate"),
+        }
+    }
+
     fn new_with(
         registry_client: Arc<dyn RegistryClient>,
         node_id: NodeId,
@@ -355,6 +370,40 @@ impl<C: CryptoServiceProvider + Send + Sync> TlsHandshake for TempCryptoComponen
             .perform_tls_client_handshake(tcp_stream, server, registry_version)
             .await
     }
+
+    async fn perform_tls_server_handshake_with_validation(
+        &self,
+        tcp_stream: TcpStream,
+        allowed_clients: AllowedClients,
+        validation: &TlsValidation,
+        registry_version: RegistryVersion,
+    ) -> Result<(TlsStream, AuthenticatedPeer), TlsServerHandshakeError> {
+        self.crypto_component
+            .perform_tls_server_handshake_with_validation(
+                tcp_stream,
+                allowed_clients,
+                validation,
+                registry_version,
+            )
+            .await
+    }
+
+    async fn perform_tls_client_handshake_with_validation(
+        &self,
+        tcp_stream: TcpStream,
+        server: NodeId,
+        validation: &TlsValidation,
+        registry_version: RegistryVersion,
+    ) -> Result<TlsStream, TlsClientHandshakeError> {
+        self.crypto_component
+            .perform_tls_client_handshake_with_validation(
+                tcp_stream,
+                server,
+                validation,
+                registry_version,
+            )
+            .await
+    }
 }
 
 impl<C: CryptoServiceProvider, T: Signable> BasicSigVerifier<T> for TempCryptoComponentGeneric<C> {
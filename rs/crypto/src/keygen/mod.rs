@@ -1,5 +1,7 @@
 use crate::{key_from_registry, CryptoComponentFatClient};
-use ic_crypto_internal_csp::keygen::{forward_secure_key_id, public_key_hash_as_key_id};
+use ic_crypto_internal_csp::keygen::{
+    forward_secure_key_id, public_key_hash_as_key_id, tls_cert_hash_as_key_id,
+};
 use ic_crypto_internal_csp::types::conversions::CspPopFromPublicKeyProtoError;
 use ic_crypto_internal_csp::types::{CspPop, CspPublicKey};
 use ic_crypto_internal_csp::CryptoServiceProvider;
@@ -9,6 +11,7 @@ use ic_crypto_internal_types::encrypt::forward_secure::{
 use ic_crypto_tls_interfaces::TlsPublicKeyCert;
 use ic_interfaces::crypto::KeyManager;
 use ic_protobuf::crypto::v1::NodePublicKeys;
+use ic_protobuf::registry::crypto::v1::AlgorithmId as AlgorithmIdProto;
 use ic_protobuf::registry::crypto::v1::PublicKey as PublicKeyProto;
 use ic_registry_client::helper::crypto::CryptoRegistry;
 use ic_types::crypto::{AlgorithmId, CryptoError, CryptoResult, KeyPurpose};
@@ -28,8 +31,87 @@ impl<C: CryptoServiceProvider> KeyManager for CryptoComponentFatClient<C> {
         self.ensure_tls_key_material_is_set_up(registry_version)?;
         Ok(())
     }
+
+    fn generate_rotated_node_keys(&self) -> CryptoResult<NodePublicKeys> {
+        let committee_signing_pk = match self
+            .csp
+            .gen_key_pair_with_pop(AlgorithmId::MultiBls12_381)?
+        {
+            (
+                _key_id,
+                CspPublicKey::MultiBls12_381(pk_bytes),
+                CspPop::MultiBls12_381(pop_bytes),
+            ) => PublicKeyProto {
+                algorithm: AlgorithmIdProto::MultiBls12381 as i32,
+                key_value: pk_bytes.0.to_vec(),
+                version: 0,
+                proof_data: Some(pop_bytes.0.to_vec()),
+            },
+            _ => {
+                return Err(CryptoError::InvalidArgument {
+                    message: "Unexpected committee signing key type".to_string(),
+                })
+            }
+        };
+        let tls_certificate = self
+            .csp
+            .gen_tls_key_pair(self.node_id, TLS_CERT_NOT_AFTER)
+            .to_proto();
+        Ok(NodePublicKeys {
+            committee_signing_pk: Some(committee_signing_pk),
+            tls_certificate: Some(tls_certificate),
+            ..self.csp.node_public_keys()
+        })
+    }
+
+    fn remove_rotated_out_node_keys(&self, rotated_out_keys: &NodePublicKeys) -> CryptoResult<()> {
+        let registry_version = self.registry_client.get_latest_version();
+        let mut key_ids = Vec::new();
+        if let Some(pk_proto) = &rotated_out_keys.committee_signing_pk {
+            let registered = self.registry_client.get_crypto_key_for_node(
+                self.node_id,
+                KeyPurpose::CommitteeSigning,
+                registry_version,
+            )?;
+            if registered.as_ref().map(|pk| &pk.key_value) == Some(&pk_proto.key_value) {
+                return Err(CryptoError::InvalidArgument {
+                    message: "The committee signing key is still registered".to_string(),
+                });
+            }
+            key_ids.push(public_key_hash_as_key_id(&CspPublicKey::try_from(
+                pk_proto.clone(),
+            )?));
+        }
+        if let Some(cert) = &rotated_out_keys.tls_certificate {
+            let registered = self
+                .registry_client
+                .get_tls_certificate(self.node_id, registry_version)?;
+            if registered.as_ref().map(|cert| &cert.certificate_der) == Some(&cert.certificate_der)
+            {
+                return Err(CryptoError::InvalidArgument {
+                    message: "The TLS certificate is still registered".to_string(),
+                });
+            }
+            let cert =
+                TlsPublicKeyCert::new_from_der(cert.certificate_der.clone()).map_err(|e| {
+                    CryptoError::MalformedPublicKey {
+                        algorithm: AlgorithmId::Ed25519,
+                        key_bytes: None,
+                        internal_error: format!("{}", e),
+                    }
+                })?;
+            key_ids.push(tls_cert_hash_as_key_id(&cert));
+        }
+        self.csp.remove_secret_keys(&key_ids);
+        Ok(())
+    }
 }
 
+/// The notAfter date of the generated TLS certificates, which indicates
+/// according to RFC5280 (section 4.1.2.5) that the certificate has no
+/// well-defined expiration date.
+const TLS_CERT_NOT_AFTER: &str = "99991231235959Z";
+
 // Helpers for implementing `KeyManager`-trait.
 impl<C: CryptoServiceProvider> CryptoComponentFatClient<C> {
     fn ensure_node_signing_key_material_is_set_up(
@@ -187,6 +187,33 @@ impl<C: CryptoServiceProvider, H: Signable> MultiSigVerifier<H> for CryptoCompon
         result
     }
 
+    fn verify_multi_sig_individual_batch(
+        &self,
+        signatures: &BTreeMap<NodeId, IndividualMultiSigOf<H>>,
+        message: &H,
+        registry_version: RegistryVersion,
+    ) -> CryptoResult<()> {
+        let logger = new_logger!(&self.logger;
+            crypto.trait_name => "MultiSigner",
+            crypto.method_name => "verify_multi_sig_individual_batch",
+            crypto.registry_version => registry_version.get(),
+        );
+        debug!(logger; crypto.description => "start",);
+        let result = MultiSigVerifierInternal::verify_multi_sig_individual_batch(
+            &self.csp,
+            Arc::clone(&self.registry_client),
+            signatures,
+            message,
+            registry_version,
+        );
+        debug!(logger;
+            crypto.description => "end",
+            crypto.is_ok => result.is_ok(),
+            crypto.error => log_err(result.as_ref().err()),
+        );
+        result
+    }
+
     /// Combines a non-empty collection of individual signatures into a combined
     /// signature. Panics if called with zero signatures.
     fn combine_multi_sig_individuals(
@@ -308,6 +335,33 @@ impl<C: CryptoServiceProvider, T: Signable> ThresholdSigVerifier<T>
         result
     }
 
+    fn verify_threshold_sig_shares_batch(
+        &self,
+        signatures: &BTreeMap<NodeId, ThresholdSigShareOf<T>>,
+        message: &T,
+        dkg_id: DkgId,
+    ) -> CryptoResult<()> {
+        let logger = new_logger!(&self.logger;
+            crypto.trait_name => "ThresholdSigVerifier",
+            crypto.method_name => "verify_threshold_sig_shares_batch",
+            crypto.dkg_id => format!("{}", dkg_id),
+        );
+        debug!(logger; crypto.description => "start",);
+        let result = ThresholdSigVerifierInternal::verify_threshold_sig_shares_batch(
+            &self.lockable_threshold_sig_data_store,
+            &self.csp,
+            signatures,
+            message,
+            dkg_id,
+        );
+        debug!(logger;
+            crypto.description => "end",
+            crypto.is_ok => result.is_ok(),
+            crypto.error => log_err(result.as_ref().err()),
+        );
+        result
+    }
+
     fn combine_threshold_sig_shares(
         &self,
         shares: BTreeMap<NodeId, ThresholdSigShareOf<T>>,
@@ -24,6 +24,33 @@ impl MultiSigVerifierInternal {
         csp_signer.verify(&csp_sig, &message_bytes, algorithm_id, csp_pubkey)
     }
 
+    /// Verifies the individual signatures of several signers on the same
+    /// message at once.
+    pub fn verify_multi_sig_individual_batch<S: CspSigner, H: Signable>(
+        csp_signer: &S,
+        registry: Arc<dyn RegistryClient>,
+        signatures: &BTreeMap<NodeId, IndividualMultiSigOf<H>>,
+        message: &H,
+        registry_version: RegistryVersion,
+    ) -> CryptoResult<()> {
+        if signatures.is_empty() {
+            return Ok(());
+        }
+
+        let (pubkey_sig_pairs, algorithm) = node_sigs_to_pubkey_sig_pairs(
+            registry,
+            signatures.clone(),
+            CommitteeSigning,
+            registry_version,
+        )?;
+
+        csp_signer.verify_multisig_individual_batch(
+            pubkey_sig_pairs,
+            &message.as_signed_bytes(),
+            algorithm,
+        )
+    }
+
     /// Combines a non-empty collection of individual signatures into a combined
     /// signature. Panics if called with zero signatures.
     pub fn combine_multi_sig_individuals<S: CspSigner, H: Signable>(
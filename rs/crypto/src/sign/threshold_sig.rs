@@ -144,6 +144,43 @@ impl ThresholdSigVerifierInternal {
             )
             .map_err(panic_on_illegal_individual_sig_verification_state)
     }
+
+    /// Verifies the threshold signature shares of several signers on the same
+    /// message at once.
+    ///
+    /// # Panics
+    /// In the same cases as `verify_threshold_sig_share`.
+    pub fn verify_threshold_sig_shares_batch<C: ThresholdSignatureCspClient, H: Signable>(
+        lockable_threshold_sig_data_store: &LockableThresholdSigDataStore,
+        threshold_sig_csp_client: &C,
+        signatures: &BTreeMap<NodeId, ThresholdSigShareOf<H>>,
+        message: &H,
+        dkg_id: DkgId,
+    ) -> CryptoResult<()> {
+        let mut csp_signatures = Vec::with_capacity(signatures.len());
+        for (signer, signature) in signatures.iter() {
+            let csp_signature = CspSignature::try_from(signature)?;
+            let public_key = lazily_calculated_public_key_from_store(
+                lockable_threshold_sig_data_store,
+                threshold_sig_csp_client,
+                dkg_id,
+                *signer,
+            )?;
+            csp_signatures.push((csp_signature, public_key));
+        }
+        let algorithm_id = match csp_signatures.first() {
+            Some((_, public_key)) => AlgorithmId::from(*public_key),
+            None => return Ok(()),
+        };
+
+        threshold_sig_csp_client
+            .threshold_verify_individual_signatures_batch(
+                algorithm_id,
+                message.as_signed_bytes().as_slice(),
+                csp_signatures,
+            )
+            .map_err(panic_on_illegal_individual_sig_verification_state)
+    }
 }
 
 /// Returns the individual public key for the given `node_id` and `dkg_id` from
@@ -1,17 +1,20 @@
 use crate::tls_stub::{
-    node_id_from_cert_subject_common_name, tls_cert_from_registry, TlsCertFromRegistryError,
+    node_id_from_cert_subject_common_name, peer_tls_certs_from_registry, self_cert_for_validation,
+    self_tls_cert_from_registry, TlsCertFromRegistryError,
 };
-use ic_crypto_internal_csp::api::CspTlsClientHandshake;
+use ic_crypto_internal_csp::api::{CspSecretKeyStoreChecker, CspTlsClientHandshake};
+use ic_crypto_internal_csp::CryptoServiceProvider;
 use ic_crypto_tls_interfaces::TlsPublicKeyCert;
 use ic_crypto_tls_interfaces::{
     MalformedPeerCertificateError, PeerNotAllowedError, TlsClientHandshakeError, TlsStream,
+    TlsValidation,
 };
 use ic_interfaces::registry::RegistryClient;
 use ic_types::{NodeId, RegistryVersion};
 use std::sync::Arc;
 use tokio::net::TcpStream;
 
-pub async fn perform_tls_client_handshake<C: CspTlsClientHandshake>(
+pub async fn perform_tls_client_handshake<C: CspTlsClientHandshake + CspSecretKeyStoreChecker>(
     csp: &C,
     self_node_id: NodeId,
     registry_client: &Arc<dyn RegistryClient>,
@@ -19,22 +22,72 @@ pub async fn perform_tls_client_handshake<C: CspTlsClientHandshake>(
     server: NodeId,
     registry_version: RegistryVersion,
 ) -> Result<TlsStream, TlsClientHandshakeError> {
-    let self_tls_cert = tls_cert_from_registry(registry_client, self_node_id, registry_version)
-        .map_err(|e| map_cert_from_registry_error(e, CertFromRegistryOwner::Myself))?;
-    let trusted_server_cert = tls_cert_from_registry(registry_client, server, registry_version)
-        .map_err(|e| map_cert_from_registry_error(e, CertFromRegistryOwner::Server))?;
+    let self_tls_cert =
+        self_tls_cert_from_registry(csp, registry_client, self_node_id, registry_version)
+            .map_err(|e| map_cert_from_registry_error(e, CertFromRegistryOwner::Myself))?;
+    let trusted_server_certs =
+        peer_tls_certs_from_registry(registry_client, server, registry_version)
+            .map_err(|e| map_cert_from_registry_error(e, CertFromRegistryOwner::Server))?;
 
     let (tls_stream, peer_cert) = csp
-        .perform_tls_client_handshake(tcp_stream, self_tls_cert, trusted_server_cert.clone())
+        .perform_tls_client_handshake(tcp_stream, self_tls_cert, trusted_server_certs.clone())
         .await?;
 
-    check_cert(server, &trusted_server_cert, &peer_cert)?;
+    check_cert(server, &trusted_server_certs, &peer_cert)?;
+    Ok(tls_stream)
+}
+
+/// Performs the client handshake with certificates that are not taken from
+/// the registry. Must not be called with `TlsValidation::RegistryPinned`.
+pub async fn perform_tls_client_handshake_with_validation<C: CryptoServiceProvider>(
+    csp: &C,
+    tcp_stream: TcpStream,
+    server: NodeId,
+    validation: &TlsValidation,
+) -> Result<TlsStream, TlsClientHandshakeError> {
+    let self_tls_cert = self_cert_for_validation(csp, validation).map_err(|internal_error| {
+        TlsClientHandshakeError::MalformedSelfCertificate { internal_error }
+    })?;
+    let trusted_server_cert = match validation {
+        TlsValidation::CaIssued { ca_cert, .. } => ca_cert.clone(),
+        TlsValidation::AllowList(certs) => {
+            certs
+                .get(&server)
+                .cloned()
+                .ok_or(TlsClientHandshakeError::ServerNotAllowed(
+                    PeerNotAllowedError::HandshakeCertificateNodeIdNotAllowed,
+                ))?
+        }
+        TlsValidation::RegistryPinned => {
+            unreachable!("registry pinned handshakes are performed by perform_tls_client_handshake")
+        }
+    };
+
+    let (tls_stream, peer_cert) = csp
+        .perform_tls_client_handshake(tcp_stream, self_tls_cert, vec![trusted_server_cert.clone()])
+        .await?;
+
+    match validation {
+        TlsValidation::AllowList(_) => check_cert(
+            server,
+            std::slice::from_ref(&trusted_server_cert),
+            &peer_cert,
+        )?,
+        // The CSP checked that the CA issued the certificate.
+        _ => {
+            if node_id_from_cert_subject_common_name(&peer_cert)? != server {
+                return Err(TlsClientHandshakeError::ServerNotAllowed(
+                    PeerNotAllowedError::HandshakeCertificateNodeIdNotAllowed,
+                ));
+            }
+        }
+    }
     Ok(tls_stream)
 }
 
 fn check_cert(
     trusted_server_node_id: NodeId,
-    trusted_server_cert_from_registry: &TlsPublicKeyCert,
+    trusted_server_certs: &[TlsPublicKeyCert],
     server_cert_from_handshake: &TlsPublicKeyCert,
 ) -> Result<(), TlsClientHandshakeError> {
     let server_node_id_from_handshake_cert =
@@ -44,7 +97,7 @@ fn check_cert(
             PeerNotAllowedError::HandshakeCertificateNodeIdNotAllowed,
         ));
     }
-    if server_cert_from_handshake != trusted_server_cert_from_registry {
+    if !trusted_server_certs.contains(server_cert_from_handshake) {
         return Err(TlsClientHandshakeError::ServerNotAllowed(
             PeerNotAllowedError::CertificatesDiffer,
         ));
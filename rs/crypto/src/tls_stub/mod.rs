@@ -1,17 +1,22 @@
 use super::*;
 use async_trait::async_trait;
+use ic_crypto_internal_csp::api::{CspKeyGenerator, CspSecretKeyStoreChecker};
 use ic_crypto_tls_interfaces::TlsPublicKeyCert;
 use ic_crypto_tls_interfaces::{
     AllowedClients, AuthenticatedPeer, MalformedPeerCertificateError, Peer,
-    TlsClientHandshakeError, TlsHandshake, TlsServerHandshakeError, TlsStream,
+    TlsClientHandshakeError, TlsHandshake, TlsServerHandshakeError, TlsStream, TlsValidation,
+    TLS_CERT_REPLACEMENT_GRACE_PERIOD,
 };
 use ic_logger::{debug, new_logger};
+use ic_registry_keys::make_crypto_tls_cert_key;
 use ic_types::registry::RegistryClientError;
+use ic_types::time::current_time;
 use ic_types::{NodeId, PrincipalId, RegistryVersion};
 use openssl::nid::Nid;
 use openssl::string::OpensslString;
 use openssl::x509::{X509NameEntries, X509NameEntryRef};
 use std::str::FromStr;
+use std::time::Duration;
 use tokio::net::TcpStream;
 
 mod client_handshake;
@@ -139,6 +144,98 @@ where
         );
         result
     }
+
+    async fn perform_tls_server_handshake_with_validation(
+        &self,
+        tcp_stream: TcpStream,
+        allowed_clients: AllowedClients,
+        validation: &TlsValidation,
+        registry_version: RegistryVersion,
+    ) -> Result<(TlsStream, AuthenticatedPeer), TlsServerHandshakeError> {
+        if *validation == TlsValidation::RegistryPinned {
+            return self
+                .perform_tls_server_handshake(tcp_stream, allowed_clients, registry_version)
+                .await;
+        }
+        let logger = new_logger!(&self.logger;
+            crypto.trait_name => "TlsHandshake",
+            crypto.method_name => "perform_tls_server_handshake_with_validation",
+            crypto.allowed_tls_clients => format!("{:?}", allowed_clients),
+        );
+        debug!(logger; crypto.description => "start",);
+        let result = server_handshake::perform_tls_server_handshake_with_validation(
+            &self.csp,
+            tcp_stream,
+            allowed_clients,
+            validation,
+        )
+        .await;
+        debug!(logger;
+            crypto.description => "end",
+            crypto.is_ok => result.is_ok(),
+            crypto.error => log_err(result.as_ref().err()),
+        );
+        result
+    }
+
+    async fn perform_tls_client_handshake_with_validation(
+        &self,
+        tcp_stream: TcpStream,
+        server: NodeId,
+        validation: &TlsValidation,
+        registry_version: RegistryVersion,
+    ) -> Result<TlsStream, TlsClientHandshakeError> {
+        if *validation == TlsValidation::RegistryPinned {
+            return self
+                .perform_tls_client_handshake(tcp_stream, server, registry_version)
+                .await;
+        }
+        let logger = new_logger!(&self.logger;
+            crypto.trait_name => "TlsHandshake",
+            crypto.method_name => "perform_tls_client_handshake_with_validation",
+            crypto.tls_server => format!("{}", server),
+        );
+        debug!(logger; crypto.description => "start",);
+        let result = client_handshake::perform_tls_client_handshake_with_validation(
+            &self.csp, tcp_stream, server, validation,
+        )
+        .await;
+        debug!(logger;
+            crypto.description => "end",
+            crypto.is_ok => result.is_ok(),
+            crypto.error => log_err(result.as_ref().err()),
+        );
+        result
+    }
+}
+
+/// Returns the certificate the node authenticates with if the certificates
+/// are not taken from the registry.
+///
+/// With `TlsValidation::CaIssued`, the secret key of the node's own
+/// certificate is made available for the certificate issued by the CA.
+fn self_cert_for_validation<C: CryptoServiceProvider>(
+    csp: &C,
+    validation: &TlsValidation,
+) -> Result<TlsPublicKeyCert, String> {
+    let own_cert = csp
+        .node_public_keys()
+        .tls_certificate
+        .ok_or_else(|| "The public key store contains no TLS certificate".to_string())
+        .and_then(|cert| {
+            TlsPublicKeyCert::new_from_der(cert.certificate_der).map_err(|e| e.internal_error)
+        })?;
+    match validation {
+        TlsValidation::CaIssued { self_cert, .. } => {
+            csp.store_tls_secret_key_for_issued_cert(&own_cert, self_cert)
+                .map_err(|e| format!("{}", e))?;
+            Ok(self_cert.clone())
+        }
+        TlsValidation::AllowList(_) => Ok(own_cert),
+        TlsValidation::RegistryPinned => {
+            Err("The certificate is taken from the registry".to_string())
+        }
+    }
 }
 
 fn node_id_from_cert_subject_common_name(
@@ -211,6 +308,106 @@ fn tls_cert_from_registry(
         )
 }
 
+/// Returns the certificate the node authenticates with in registry-pinned
+/// handshakes.
+///
+/// Within `TLS_CERT_REPLACEMENT_GRACE_PERIOD` after the node's certificate
+/// was replaced, this is the replaced certificate if the node still holds its
+/// secret key, as the peers may not trust the new certificate yet. Otherwise,
+/// it is the certificate in the registry.
+fn self_tls_cert_from_registry<C: CspSecretKeyStoreChecker>(
+    csp: &C,
+    registry: &Arc<dyn RegistryClient>,
+    self_node_id: NodeId,
+    registry_version: RegistryVersion,
+) -> Result<TlsPublicKeyCert, TlsCertFromRegistryError> {
+    let certs = node_tls_certs_from_registry(
+        registry,
+        self_node_id,
+        registry_version,
+        TLS_CERT_REPLACEMENT_GRACE_PERIOD,
+    )?;
+    match certs.replaced {
+        Some(replaced) if csp.sks_contains_tls_key(&replaced) => Ok(replaced),
+        _ => Ok(certs.current),
+    }
+}
+
+/// Returns the certificates a peer may authenticate with in registry-pinned
+/// handshakes: the certificate in the registry and, within twice
+/// `TLS_CERT_REPLACEMENT_GRACE_PERIOD` after it was replaced, the replaced
+/// certificate.
+fn peer_tls_certs_from_registry(
+    registry: &Arc<dyn RegistryClient>,
+    node_id: NodeId,
+    registry_version: RegistryVersion,
+) -> Result<Vec<TlsPublicKeyCert>, TlsCertFromRegistryError> {
+    let certs = node_tls_certs_from_registry(
+        registry,
+        node_id,
+        registry_version,
+        2 * TLS_CERT_REPLACEMENT_GRACE_PERIOD,
+    )?;
+    Ok(std::iter::once(certs.current)
+        .chain(certs.replaced)
+        .collect())
+}
+
+/// The TLS certificate of a node in the registry, and the certificate it
+/// replaced if that happened recently.
+struct NodeTlsCerts {
+    current: TlsPublicKeyCert,
+    replaced: Option<TlsPublicKeyCert>,
+}
+
+/// Returns the TLS certificate of the node with ID `node_id` at
+/// `registry_version`, and the certificate it replaced if the registry
+/// version replacing it became available locally less than
+/// `replacement_period` ago.
+fn node_tls_certs_from_registry(
+    registry: &Arc<dyn RegistryClient>,
+    node_id: NodeId,
+    registry_version: RegistryVersion,
+    replacement_period: Duration,
+) -> Result<NodeTlsCerts, TlsCertFromRegistryError> {
+    let current = tls_cert_from_registry(registry, node_id, registry_version)?;
+    let replaced = replaced_tls_cert(
+        registry,
+        node_id,
+        registry_version,
+        replacement_period,
+        &current,
+    );
+    Ok(NodeTlsCerts { current, replaced })
+}
+
+fn replaced_tls_cert(
+    registry: &Arc<dyn RegistryClient>,
+    node_id: NodeId,
+    registry_version: RegistryVersion,
+    replacement_period: Duration,
+    current: &TlsPublicKeyCert,
+) -> Option<TlsPublicKeyCert> {
+    let replaced_at = registry
+        .get_versioned_value(&make_crypto_tls_cert_key(node_id), registry_version)
+        .ok()?
+        .version;
+    if replaced_at.get() <= 1 {
+        return None;
+    }
+    let available_since = registry.get_version_timestamp(replaced_at)?;
+    if available_since + replacement_period < current_time() {
+        return None;
+    }
+    let replaced = tls_cert_from_registry(
+        registry,
+        node_id,
+        RegistryVersion::from(replaced_at.get() - 1),
+    )
+    .ok()?;
+    Some(replaced).filter(|replaced| replaced != current)
+}
+
 #[derive(Debug)]
 enum TlsCertFromRegistryError {
     RegistryError(RegistryClientError),
@@ -1,11 +1,13 @@
 use crate::tls_stub::{
-    node_id_from_cert_subject_common_name, tls_cert_from_registry, TlsCertFromRegistryError,
+    node_id_from_cert_subject_common_name, peer_tls_certs_from_registry, self_cert_for_validation,
+    self_tls_cert_from_registry, TlsCertFromRegistryError,
 };
-use ic_crypto_internal_csp::api::CspTlsServerHandshake;
+use ic_crypto_internal_csp::api::{CspSecretKeyStoreChecker, CspTlsServerHandshake};
 use ic_crypto_internal_csp::tls_stub::cert_chain::CspCertificateChain;
+use ic_crypto_internal_csp::CryptoServiceProvider;
 use ic_crypto_tls_interfaces::{
     AllowedClients, AuthenticatedPeer, MalformedPeerCertificateError, Peer, PeerNotAllowedError,
-    SomeOrAllNodes, TlsPublicKeyCert, TlsServerHandshakeError, TlsStream,
+    SomeOrAllNodes, TlsPublicKeyCert, TlsServerHandshakeError, TlsStream, TlsValidation,
 };
 use ic_interfaces::registry::RegistryClient;
 use ic_registry_client::helper::node::NodeRegistry;
@@ -15,7 +17,7 @@ use std::sync::Arc;
 use tokio::net::TcpStream;
 
 // TODO (CRP-772): Simplify handshake code by moving cert equality check to CSP
-pub async fn perform_tls_server_handshake<C: CspTlsServerHandshake>(
+pub async fn perform_tls_server_handshake<C: CspTlsServerHandshake + CspSecretKeyStoreChecker>(
     csp: &C,
     self_node_id: NodeId,
     registry_client: &Arc<dyn RegistryClient>,
@@ -39,7 +41,7 @@ pub async fn perform_tls_server_handshake<C: CspTlsServerHandshake>(
 }
 
 pub async fn perform_tls_server_handshake_temp_with_optional_client_auth<
-    C: CspTlsServerHandshake,
+    C: CspTlsServerHandshake + CspSecretKeyStoreChecker,
 >(
     csp: &C,
     self_node_id: NodeId,
@@ -48,8 +50,9 @@ pub async fn perform_tls_server_handshake_temp_with_optional_client_auth<
     allowed_authenticating_clients: AllowedClients,
     registry_version: RegistryVersion,
 ) -> Result<(TlsStream, Peer), TlsServerHandshakeError> {
-    let self_tls_cert = tls_cert_from_registry(registry_client, self_node_id, registry_version)
-        .map_err(|e| map_cert_from_registry_error(e, CertFromRegistryOwner::Myself))?;
+    let self_tls_cert =
+        self_tls_cert_from_registry(csp, registry_client, self_node_id, registry_version)
+            .map_err(|e| map_cert_from_registry_error(e, CertFromRegistryOwner::Myself))?;
     let trusted_node_certs = tls_certs_from_registry(
         registry_client,
         &allowed_authenticating_clients.nodes(),
@@ -76,15 +79,18 @@ pub async fn perform_tls_server_handshake_temp_with_optional_client_auth<
     }
 }
 
-pub async fn perform_tls_server_handshake_without_client_auth<C: CspTlsServerHandshake>(
+pub async fn perform_tls_server_handshake_without_client_auth<
+    C: CspTlsServerHandshake + CspSecretKeyStoreChecker,
+>(
     csp: &C,
     self_node_id: NodeId,
     registry_client: &Arc<dyn RegistryClient>,
     tcp_stream: TcpStream,
     registry_version: RegistryVersion,
 ) -> Result<TlsStream, TlsServerHandshakeError> {
-    let self_tls_cert = tls_cert_from_registry(registry_client, self_node_id, registry_version)
-        .map_err(|e| map_cert_from_registry_error(e, CertFromRegistryOwner::Myself))?;
+    let self_tls_cert =
+        self_tls_cert_from_registry(csp, registry_client, self_node_id, registry_version)
+            .map_err(|e| map_cert_from_registry_error(e, CertFromRegistryOwner::Myself))?;
 
     let tls_stream = csp
         .perform_tls_server_handshake_without_client_auth(tcp_stream, self_tls_cert)
@@ -93,11 +99,63 @@ pub async fn perform_tls_server_handshake_without_client_auth<C: CspTlsServerHan
     Ok(tls_stream)
 }
 
+/// Performs the server handshake with certificates that are not taken from
+/// the registry. Must not be called with `TlsValidation::RegistryPinned`.
+pub async fn perform_tls_server_handshake_with_validation<C: CryptoServiceProvider>(
+    csp: &C,
+    tcp_stream: TcpStream,
+    allowed_clients: AllowedClients,
+    validation: &TlsValidation,
+) -> Result<(TlsStream, AuthenticatedPeer), TlsServerHandshakeError> {
+    let self_tls_cert = self_cert_for_validation(csp, validation).map_err(|internal_error| {
+        TlsServerHandshakeError::MalformedSelfCertificate { internal_error }
+    })?;
+    let is_allowed = |node_id: &NodeId| match allowed_clients.nodes() {
+        SomeOrAllNodes::Some(nodes) => nodes.contains(node_id),
+        SomeOrAllNodes::All => true,
+    };
+    let trusted_client_certs: HashSet<TlsPublicKeyCert> = match validation {
+        TlsValidation::CaIssued { ca_cert, .. } => std::iter::once(ca_cert.clone()).collect(),
+        TlsValidation::AllowList(certs) => certs
+            .iter()
+            .filter(|(node_id, _)| is_allowed(node_id))
+            .map(|(_, cert)| cert.clone())
+            .collect(),
+        TlsValidation::RegistryPinned => {
+            unreachable!("registry pinned handshakes are performed by perform_tls_server_handshake")
+        }
+    };
+
+    let (tls_stream, peer_cert_chain) = csp
+        .perform_tls_server_handshake(tcp_stream, self_tls_cert, trusted_client_certs)
+        .await?;
+
+    let client_cert = peer_cert_chain
+        .ok_or(TlsServerHandshakeError::UnauthenticatedClient)?
+        .leaf()
+        .clone();
+    let client_node_id = node_id_from_cert_subject_common_name(&client_cert)?;
+    if !is_allowed(&client_node_id) {
+        return Err(TlsServerHandshakeError::ClientNotAllowed(
+            PeerNotAllowedError::HandshakeCertificateNodeIdNotAllowed,
+        ));
+    }
+    // With `CaIssued`, the CSP checked that the CA issued the certificate.
+    if let TlsValidation::AllowList(certs) = validation {
+        if certs.get(&client_node_id) != Some(&client_cert) {
+            return Err(TlsServerHandshakeError::ClientNotAllowed(
+                PeerNotAllowedError::CertificatesDiffer,
+            ));
+        }
+    }
+    Ok((tls_stream, AuthenticatedPeer::Node(client_node_id)))
+}
+
 fn tls_certs_from_registry(
     registry_client: &Arc<dyn RegistryClient>,
     nodes: &SomeOrAllNodes,
     registry_version: RegistryVersion,
-) -> Result<BTreeMap<NodeId, TlsPublicKeyCert>, TlsCertFromRegistryError> {
+) -> Result<BTreeMap<NodeId, Vec<TlsPublicKeyCert>>, TlsCertFromRegistryError> {
     match nodes {
         SomeOrAllNodes::Some(nodes) => {
             tls_certs_from_registry_for_nodes(nodes, registry_client, registry_version)
@@ -116,22 +174,22 @@ fn tls_certs_from_registry_for_nodes(
     allowed_clients: &BTreeSet<NodeId>,
     registry_client: &Arc<dyn RegistryClient>,
     registry_version: RegistryVersion,
-) -> Result<BTreeMap<NodeId, TlsPublicKeyCert>, TlsCertFromRegistryError> {
+) -> Result<BTreeMap<NodeId, Vec<TlsPublicKeyCert>>, TlsCertFromRegistryError> {
     let mut node_id_to_cert = BTreeMap::new();
     for client in allowed_clients {
         node_id_to_cert.insert(
             *client,
-            tls_cert_from_registry(registry_client, *client, registry_version)?,
+            peer_tls_certs_from_registry(registry_client, *client, registry_version)?,
         );
     }
     Ok(node_id_to_cert)
 }
 
 fn combine_certs(
-    node_certs: &BTreeMap<NodeId, TlsPublicKeyCert>,
+    node_certs: &BTreeMap<NodeId, Vec<TlsPublicKeyCert>>,
     certs: &HashSet<TlsPublicKeyCert>,
 ) -> HashSet<TlsPublicKeyCert> {
-    let mut node_certs_and_certs: HashSet<_> = node_certs.values().cloned().collect();
+    let mut node_certs_and_certs: HashSet<_> = node_certs.values().flatten().cloned().collect();
     node_certs_and_certs.extend(certs.iter().cloned());
     node_certs_and_certs
 }
@@ -143,10 +201,10 @@ fn combine_certs(
 ///    the certificate C_handshake that the peer presented during the
 ///    handshake (and for which the peer therefore knows the private key).
 ///    If N_claimed is contained in `trusted_node_certs`, determine the
-///    certificate C_registry by querying the registry for the TLS certificate
-///    of node with ID N_claimed, and if C_registry is equal to C_handshake,
-///    then the peer successfully authenticated as node N_claimed. Otherwise,
-///    step 2 is taken.
+///    certificates C_registry of node with ID N_claimed, i.e. its TLS
+///    certificate in the registry and the certificate it recently replaced, if
+///    any, and if C_handshake is one of C_registry, then the peer successfully
+///    authenticated as node N_claimed. Otherwise, step 2 is taken.
 /// 2. Compare the root of the certificate chain that the peer presented during
 ///    the handshake (and for which the peer therefore knows the private key of
 ///    the chain's leaf certificate) to all the certificates in
@@ -159,7 +217,7 @@ fn combine_certs(
 fn authenticated_peer(
     client_cert_chain_from_handshake: &CspCertificateChain,
     allowed_client_certs: &HashSet<TlsPublicKeyCert>,
-    trusted_node_certs: &BTreeMap<NodeId, TlsPublicKeyCert>,
+    trusted_node_certs: &BTreeMap<NodeId, Vec<TlsPublicKeyCert>>,
 ) -> Result<AuthenticatedPeer, TlsServerHandshakeError> {
     let authenticated_node = check_cert_and_get_authenticated_client_node_id(
         trusted_node_certs,
@@ -180,14 +238,14 @@ fn authenticated_peer(
 }
 
 fn check_cert_and_get_authenticated_client_node_id(
-    trusted_node_certs: &BTreeMap<NodeId, TlsPublicKeyCert>,
+    trusted_node_certs: &BTreeMap<NodeId, Vec<TlsPublicKeyCert>>,
     client_cert_from_handshake: &TlsPublicKeyCert,
 ) -> Result<NodeId, TlsServerHandshakeError> {
     let client_node_id_from_handshake_cert =
         node_id_from_cert_subject_common_name(&client_cert_from_handshake)?;
-    let trusted_client_cert_from_registry =
-        cert_for_node_id(client_node_id_from_handshake_cert, trusted_node_certs)?;
-    if client_cert_from_handshake != trusted_client_cert_from_registry {
+    let trusted_client_certs_from_registry =
+        certs_for_node_id(client_node_id_from_handshake_cert, trusted_node_certs)?;
+    if !trusted_client_certs_from_registry.contains(client_cert_from_handshake) {
         return Err(TlsServerHandshakeError::ClientNotAllowed(
             PeerNotAllowedError::CertificatesDiffer,
         ));
@@ -196,10 +254,10 @@ fn check_cert_and_get_authenticated_client_node_id(
     Ok(client_node_id_from_handshake_cert)
 }
 
-fn cert_for_node_id(
+fn certs_for_node_id(
     claimed_node_id_from_handshake_cert: NodeId,
-    trusted_node_certs: &BTreeMap<NodeId, TlsPublicKeyCert>,
-) -> Result<&TlsPublicKeyCert, TlsServerHandshakeError> {
+    trusted_node_certs: &BTreeMap<NodeId, Vec<TlsPublicKeyCert>>,
+) -> Result<&Vec<TlsPublicKeyCert>, TlsServerHandshakeError> {
     trusted_node_certs
         .get(&claimed_node_id_from_handshake_cert)
         .ok_or(TlsServerHandshakeError::ClientNotAllowed(
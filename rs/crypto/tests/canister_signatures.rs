@@ -9,8 +9,6 @@ use ic_crypto_internal_types::sign::threshold_sig::public_key::bls12_381;
 use ic_crypto_test_utils::canister_signatures::canister_sig_pub_key_to_bytes;
 use ic_interfaces::crypto::CanisterSigVerifier;
 use ic_protobuf::registry::crypto::v1::PublicKey as PublicKeyProto;
-use ic_protobuf::types::v1::PrincipalId as PrincipalIdIdProto;
-use ic_protobuf::types::v1::SubnetId as SubnetIdProto;
 use ic_registry_client::fake::FakeRegistryClient;
 use ic_registry_common::proto_registry_data_provider::ProtoRegistryDataProvider;
 use ic_registry_keys::{make_crypto_threshold_signing_pubkey_key, ROOT_SUBNET_ID_KEY};
@@ -19,7 +17,7 @@ use ic_types::crypto::threshold_sig::ThresholdSigPublicKey;
 use ic_types::crypto::{AlgorithmId, CanisterSig, CanisterSigOf, CryptoError, UserPublicKey};
 use ic_types::messages::Delegation;
 use ic_types::time::{current_time, Time};
-use ic_types::{CanisterId, RegistryVersion, SubnetId};
+use ic_types::{subnet_id_into_protobuf, CanisterId, RegistryVersion, SubnetId};
 use simple_asn1::oid;
 use std::str::FromStr;
 use std::sync::Arc;
@@ -213,13 +211,12 @@ fn temp_crypto_with_registry_with_root_pubkey(
 ) -> TempCryptoComponent {
     let registry_data = Arc::new(ProtoRegistryDataProvider::new());
     let registry = FakeRegistryClient::new(Arc::clone(&registry_data) as Arc<_>);
-    let root_subnet_id = SubnetIdProto {
-        principal_id: Some(PrincipalIdIdProto {
-            raw: ROOT_SUBNET_ID.get_ref().to_vec(),
-        }),
-    };
     registry_data
-        .add(&ROOT_SUBNET_ID_KEY, registry_version, Some(root_subnet_id))
+        .add(
+            &ROOT_SUBNET_ID_KEY,
+            registry_version,
+            Some(subnet_id_into_protobuf(ROOT_SUBNET_ID)),
+        )
         .expect("failed to add root subnet ID to registry");
 
     let root_subnet_pubkey = PublicKeyProto::from(threshold_sig_pubkey);
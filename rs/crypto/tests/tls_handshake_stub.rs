@@ -1475,6 +1475,209 @@ mod client {
     }
 }
 
+mod replaced_certs {
+    use super::*;
+    use crate::tls_utils::REG_V2;
+
+    #[tokio::test]
+    async fn should_accept_replaced_client_cert_within_grace_period() {
+        let (server_builder, client_builder) =
+            matching_server_and_client_builders(SERVER_ID_1, CLIENT_ID_1);
+        let registry = TlsRegistry::new();
+        let server = server_builder
+            .with_registry_version(REG_V2)
+            .build(registry.get());
+        let client = client_builder
+            .with_registry_version(REG_V2)
+            .build(registry.get());
+        registry
+            .add_cert(SERVER_ID_1, server.cert())
+            .add_cert(CLIENT_ID_1, client.cert())
+            .replace_cert(CLIENT_ID_1, generate_cert_using_temp_crypto(CLIENT_ID_1))
+            .update();
+
+        let (client_result, authenticated_client) =
+            tokio::join!(client.run(server.port()), server.run());
+
+        assert!(client_result.is_ok());
+        assert_peer_node_eq(authenticated_client.unwrap(), CLIENT_ID_1);
+    }
+
+    #[tokio::test]
+    async fn should_accept_replaced_server_cert_within_grace_period() {
+        let (server_builder, client_builder) =
+            matching_server_and_client_builders(SERVER_ID_1, CLIENT_ID_1);
+        let registry = TlsRegistry::new();
+        let server = server_builder
+            .with_registry_version(REG_V2)
+            .build(registry.get());
+        let client = client_builder
+            .with_registry_version(REG_V2)
+            .build(registry.get());
+        registry
+            .add_cert(SERVER_ID_1, server.cert())
+            .add_cert(CLIENT_ID_1, client.cert())
+            .replace_cert(SERVER_ID_1, generate_cert_using_temp_crypto(SERVER_ID_1))
+            .update();
+
+        let (client_result, authenticated_client) =
+            tokio::join!(client.run(server.port()), server.run());
+
+        assert!(client_result.is_ok());
+        assert_peer_node_eq(authenticated_client.unwrap(), CLIENT_ID_1);
+    }
+
+    #[tokio::test]
+    async fn should_authenticate_with_new_cert_if_secret_key_of_replaced_cert_is_gone() {
+        let (server_builder, client_builder) =
+            matching_server_and_client_builders(SERVER_ID_1, CLIENT_ID_1);
+        let registry = TlsRegistry::new();
+        let server = server_builder
+            .with_registry_version(REG_V2)
+            .build(registry.get());
+        let client = client_builder
+            .with_registry_version(REG_V2)
+            .build(registry.get());
+        registry
+            .add_cert(SERVER_ID_1, server.cert())
+            .add_cert(CLIENT_ID_1, generate_cert_using_temp_crypto(CLIENT_ID_1))
+            .replace_cert(CLIENT_ID_1, client.cert())
+            .update();
+
+        let (client_result, authenticated_client) =
+            tokio::join!(client.run(server.port()), server.run());
+
+        assert!(client_result.is_ok());
+        assert_peer_node_eq(authenticated_client.unwrap(), CLIENT_ID_1);
+    }
+}
+
+mod validation {
+    use super::*;
+    use ic_crypto_test_utils::tls::x509_certificates::CertWithPrivateKey;
+    use ic_crypto_tls_interfaces::TlsValidation;
+
+    #[tokio::test]
+    async fn should_perform_tls_handshake_with_allow_list() {
+        let (server, client, _registry) = matching_server_and_client(SERVER_ID_1, CLIENT_ID_1);
+        let validation = TlsValidation::AllowList(
+            vec![
+                (SERVER_ID_1, server.tls_cert()),
+                (CLIENT_ID_1, client.tls_cert()),
+            ]
+            .into_iter()
+            .collect(),
+        );
+
+        let (client_result, authenticated_client) = tokio::join!(
+            client.run_with_validation(server.port(), &validation),
+            server.run_with_validation(&validation)
+        );
+
+        assert!(client_result.is_ok());
+        assert_peer_node_eq(authenticated_client.unwrap(), CLIENT_ID_1);
+    }
+
+    #[tokio::test]
+    async fn should_reject_client_not_in_allow_list() {
+        let (server, client, _registry) = matching_server_and_client(SERVER_ID_1, CLIENT_ID_1);
+        let validation = TlsValidation::AllowList(
+            vec![
+                (SERVER_ID_1, server.tls_cert()),
+                (
+                    CLIENT_ID_1,
+                    generate_tls_cert_using_temp_crypto(CLIENT_ID_1),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        );
+
+        let (_client_result, server_result) = tokio::join!(
+            client.run_with_validation(server.port(), &validation),
+            server.run_with_validation(&validation)
+        );
+
+        assert!(matches!(
+            server_result,
+            Err(TlsServerHandshakeError::HandshakeError { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn should_perform_tls_handshake_with_ca_issued_certs() {
+        let (server, client, _registry) = matching_server_and_client(SERVER_ID_1, CLIENT_ID_1);
+        let ca = test_ca();
+        let server_validation = ca_issued(&ca, server.cert_issued_by(&ca));
+        let client_validation = ca_issued(&ca, client.cert_issued_by(&ca));
+
+        let (client_result, authenticated_client) = tokio::join!(
+            client.run_with_validation(server.port(), &client_validation),
+            server.run_with_validation(&server_validation)
+        );
+
+        assert!(client_result.is_ok());
+        assert_peer_node_eq(authenticated_client.unwrap(), CLIENT_ID_1);
+    }
+
+    #[tokio::test]
+    async fn should_reject_client_cert_issued_by_other_ca() {
+        let (server, client, _registry) = matching_server_and_client(SERVER_ID_1, CLIENT_ID_1);
+        let ca = test_ca();
+        let other_ca = test_ca();
+        let server_validation = ca_issued(&ca, server.cert_issued_by(&ca));
+        let client_validation = ca_issued(&ca, client.cert_issued_by(&other_ca));
+
+        let (_client_result, server_result) = tokio::join!(
+            client.run_with_validation(server.port(), &client_validation),
+            server.run_with_validation(&server_validation)
+        );
+
+        assert!(matches!(
+            server_result,
+            Err(TlsServerHandshakeError::HandshakeError { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn should_return_error_if_self_cert_is_issued_for_other_key() {
+        let (server, client, _registry) = matching_server_and_client(SERVER_ID_1, CLIENT_ID_1);
+        let ca = test_ca();
+        let server_validation = ca_issued(&ca, client.cert_issued_by(&ca));
+
+        let port = server.port();
+
+        let (_tcp_stream, server_result) = tokio::join!(
+            tokio::net::TcpStream::connect(("127.0.0.1", port)),
+            server.run_with_validation(&server_validation)
+        );
+
+        assert!(matches!(
+            server_result,
+            Err(TlsServerHandshakeError::MalformedSelfCertificate { .. })
+        ));
+    }
+
+    fn test_ca() -> CertWithPrivateKey {
+        CertWithPrivateKey::builder()
+            .cn("Test CA".to_string())
+            .set_ca_key_usage_extension()
+            .build_ed25519()
+    }
+
+    fn ca_issued(ca: &CertWithPrivateKey, self_cert: TlsPublicKeyCert) -> TlsValidation {
+        TlsValidation::CaIssued {
+            ca_cert: TlsPublicKeyCert::new_from_x509(ca.x509()).unwrap(),
+            self_cert,
+        }
+    }
+
+    fn generate_tls_cert_using_temp_crypto(node_id: NodeId) -> TlsPublicKeyCert {
+        TlsPublicKeyCert::new_from_der(generate_cert_using_temp_crypto(node_id).certificate_der)
+            .unwrap()
+    }
+}
+
 mod communication {
     use super::*;
 
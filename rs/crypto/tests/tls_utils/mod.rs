@@ -1,7 +1,12 @@
 use ic_crypto::utils::TempCryptoComponent;
+use ic_crypto_test_utils::tls::x509_certificates::CertWithPrivateKey;
 use ic_crypto_tls_interfaces::TlsPublicKeyCert;
 use ic_registry_client::fake::FakeRegistryClient;
 use ic_types::{NodeId, RegistryVersion};
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::PKey;
+use openssl::x509::X509Ref;
 use std::sync::Arc;
 
 pub mod registry;
@@ -9,6 +14,7 @@ pub mod test_client;
 pub mod test_server;
 
 pub const REG_V1: RegistryVersion = RegistryVersion::new(1);
+pub const REG_V2: RegistryVersion = RegistryVersion::new(2);
 
 pub fn temp_crypto_component_with_tls_keys(
     registry: Arc<FakeRegistryClient>,
@@ -16,3 +22,31 @@ pub fn temp_crypto_component_with_tls_keys(
 ) -> (TempCryptoComponent, TlsPublicKeyCert) {
     TempCryptoComponent::new_with_tls_key_generation(registry as Arc<_>, node_id)
 }
+
+/// Returns a certificate for the TLS key pair and with the subject of `cert`,
+/// the certificate of `crypto`, that is issued by the `ca`.
+pub fn issued_by_ca(
+    crypto: &TempCryptoComponent,
+    cert: &TlsPublicKeyCert,
+    ca: &CertWithPrivateKey,
+) -> TlsPublicKeyCert {
+    let key_pair = PKey::private_key_from_der(&crypto.tls_secret_key_der(cert))
+        .expect("failed to parse the TLS secret key");
+    let issued_cert = CertWithPrivateKey::builder()
+        .cn(common_name(cert.as_x509()))
+        .with_ca_signing(ca.key_pair(), common_name(&ca.x509()))
+        .build(key_pair, MessageDigest::null())
+        .x509();
+    TlsPublicKeyCert::new_from_x509(issued_cert).expect("failed to convert the issued cert")
+}
+
+fn common_name(x509: &X509Ref) -> String {
+    x509.subject_name()
+        .entries_by_nid(Nid::COMMONNAME)
+        .next()
+        .expect("the certificate has no subject common name")
+        .data()
+        .as_utf8()
+        .expect("the subject common name is not UTF-8")
+        .to_string()
+}
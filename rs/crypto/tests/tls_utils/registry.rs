@@ -1,4 +1,4 @@
-use crate::tls_utils::REG_V1;
+use crate::tls_utils::{REG_V1, REG_V2};
 use ic_protobuf::registry::crypto::v1::X509PublicKeyCert;
 use ic_registry_client::fake::FakeRegistryClient;
 use ic_registry_client::helper::node::NodeRecord;
@@ -30,6 +30,16 @@ impl TlsRegistry {
         self
     }
 
+    /// Replaces the certificate of the node with the given `cert` at
+    /// `REG_V2`.
+    #[allow(unused)]
+    pub fn replace_cert(self, node_id: NodeId, cert: X509PublicKeyCert) -> TlsRegistry {
+        self.data_provider
+            .add(&make_crypto_tls_cert_key(node_id), REG_V2, Some(cert))
+            .expect("failed to replace TLS cert in registry");
+        self
+    }
+
     pub fn add_node_record(self, node_id: NodeId) -> TlsRegistry {
         self.data_provider
             .add(
@@ -1,11 +1,14 @@
 #![allow(clippy::unwrap_used)]
-use crate::tls_utils::{temp_crypto_component_with_tls_keys, REG_V1};
+use crate::tls_utils::{issued_by_ca, temp_crypto_component_with_tls_keys, REG_V1};
 use ic_crypto::utils::TempCryptoComponent;
+use ic_crypto_test_utils::tls::x509_certificates::CertWithPrivateKey;
 use ic_crypto_tls_interfaces::TlsPublicKeyCert;
-use ic_crypto_tls_interfaces::{TlsClientHandshakeError, TlsHandshake, TlsReadHalf, TlsWriteHalf};
+use ic_crypto_tls_interfaces::{
+    TlsClientHandshakeError, TlsHandshake, TlsReadHalf, TlsValidation, TlsWriteHalf,
+};
 use ic_protobuf::registry::crypto::v1::X509PublicKeyCert;
 use ic_registry_client::fake::FakeRegistryClient;
-use ic_types::NodeId;
+use ic_types::{NodeId, RegistryVersion};
 use std::sync::Arc;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
@@ -16,6 +19,7 @@ pub struct ClientBuilder {
     msg_expected_from_server: Option<String>,
     msg_for_server: Option<String>,
     expected_error_substring_when_reading_stream: Option<String>,
+    registry_version: RegistryVersion,
 }
 
 impl ClientBuilder {
@@ -34,6 +38,12 @@ impl ClientBuilder {
         self
     }
 
+    #[allow(unused)]
+    pub fn with_registry_version(mut self, registry_version: RegistryVersion) -> ClientBuilder {
+        self.registry_version = registry_version;
+        self
+    }
+
     pub fn build(self, registry: Arc<FakeRegistryClient>) -> Client {
         let (crypto, cert) = temp_crypto_component_with_tls_keys(registry, self.node_id);
         Client {
@@ -43,6 +53,7 @@ impl ClientBuilder {
             msg_for_server: self.msg_for_server,
             expected_error_substring_when_reading_stream: self
                 .expected_error_substring_when_reading_stream,
+            registry_version: self.registry_version,
             cert,
         }
     }
@@ -56,6 +67,7 @@ pub struct Client {
     msg_expected_from_server: Option<String>,
     msg_for_server: Option<String>,
     expected_error_substring_when_reading_stream: Option<String>,
+    registry_version: RegistryVersion,
     cert: TlsPublicKeyCert,
 }
 
@@ -67,6 +79,7 @@ impl Client {
             msg_expected_from_server: None,
             msg_for_server: None,
             expected_error_substring_when_reading_stream: None,
+            registry_version: REG_V1,
         }
     }
 
@@ -77,7 +90,7 @@ impl Client {
 
         let tls_stream = self
             .crypto
-            .perform_tls_client_handshake(tcp_stream, self.server_node_id, REG_V1)
+            .perform_tls_client_handshake(tcp_stream, self.server_node_id, self.registry_version)
             .await?;
         let (mut tls_read_half, tls_write_half) = tls_stream.split();
 
@@ -89,6 +102,32 @@ impl Client {
         Ok(())
     }
 
+    pub async fn run_with_validation(
+        self,
+        server_port: u16,
+        validation: &TlsValidation,
+    ) -> Result<(), TlsClientHandshakeError> {
+        let tcp_stream = TcpStream::connect(("127.0.0.1", server_port))
+            .await
+            .expect("failed to connect");
+
+        let tls_stream = self
+            .crypto
+            .perform_tls_client_handshake_with_validation(
+                tcp_stream,
+                self.server_node_id,
+                validation,
+                self.registry_version,
+            )
+            .await?;
+        let (mut tls_read_half, tls_write_half) = tls_stream.split();
+
+        self.expect_msg_from_server_if_configured(&mut tls_read_half)
+            .await;
+        self.send_msg_to_server_if_configured(tls_write_half).await;
+        Ok(())
+    }
+
     async fn send_msg_to_server_if_configured(&self, mut tls_write_half: TlsWriteHalf) {
         if let Some(msg_for_server) = &self.msg_for_server {
             let num_bytes_written = tls_write_half
@@ -132,4 +171,12 @@ impl Client {
     pub fn cert(&self) -> X509PublicKeyCert {
         self.cert.to_proto()
     }
+
+    pub fn tls_cert(&self) -> TlsPublicKeyCert {
+        self.cert.clone()
+    }
+
+    pub fn cert_issued_by(&self, ca: &CertWithPrivateKey) -> TlsPublicKeyCert {
+        issued_by_ca(&self.crypto, &self.cert, ca)
+    }
 }
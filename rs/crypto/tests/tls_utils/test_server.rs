@@ -1,14 +1,15 @@
 #![allow(clippy::unwrap_used)]
-use crate::tls_utils::{temp_crypto_component_with_tls_keys, REG_V1};
+use crate::tls_utils::{issued_by_ca, temp_crypto_component_with_tls_keys, REG_V1};
 use ic_crypto::utils::TempCryptoComponent;
+use ic_crypto_test_utils::tls::x509_certificates::CertWithPrivateKey;
 use ic_crypto_tls_interfaces::TlsPublicKeyCert;
 use ic_crypto_tls_interfaces::{
     AllowedClients, AuthenticatedPeer, Peer, SomeOrAllNodes, TlsHandshake, TlsReadHalf,
-    TlsServerHandshakeError, TlsWriteHalf,
+    TlsServerHandshakeError, TlsValidation, TlsWriteHalf,
 };
 use ic_protobuf::registry::crypto::v1::X509PublicKeyCert;
 use ic_registry_client::fake::FakeRegistryClient;
-use ic_types::NodeId;
+use ic_types::{NodeId, RegistryVersion};
 use proptest::std_facade::BTreeSet;
 use std::collections::HashSet;
 use std::sync::Arc;
@@ -21,6 +22,7 @@ pub struct ServerBuilder {
     msg_expected_from_client: Option<String>,
     allowed_nodes: Option<SomeOrAllNodes>,
     allowed_certs: HashSet<TlsPublicKeyCert>,
+    registry_version: RegistryVersion,
 }
 
 impl ServerBuilder {
@@ -75,6 +77,12 @@ impl ServerBuilder {
         self
     }
 
+    #[allow(unused)]
+    pub fn with_registry_version(mut self, registry_version: RegistryVersion) -> ServerBuilder {
+        self.registry_version = registry_version;
+        self
+    }
+
     pub fn build(self, registry: Arc<FakeRegistryClient>) -> Server {
         let listener = std::net::TcpListener::bind(("0.0.0.0", 0)).expect("failed to bind");
         let (crypto, cert) = temp_crypto_component_with_tls_keys(registry, self.node_id);
@@ -90,6 +98,7 @@ impl ServerBuilder {
             allowed_clients,
             msg_for_client: self.msg_for_client,
             msg_expected_from_client: self.msg_expected_from_client,
+            registry_version: self.registry_version,
             cert,
         }
     }
@@ -103,6 +112,7 @@ pub struct Server {
     allowed_clients: AllowedClients,
     msg_for_client: Option<String>,
     msg_expected_from_client: Option<String>,
+    registry_version: RegistryVersion,
     cert: TlsPublicKeyCert,
 }
 
@@ -114,6 +124,7 @@ impl Server {
             msg_expected_from_client: None,
             allowed_nodes: None,
             allowed_certs: HashSet::new(),
+            registry_version: REG_V1,
         }
     }
 
@@ -122,7 +133,34 @@ impl Server {
 
         let (tls_stream, authenticated_node) = self
             .crypto
-            .perform_tls_server_handshake(tcp_stream, self.allowed_clients.clone(), REG_V1)
+            .perform_tls_server_handshake(
+                tcp_stream,
+                self.allowed_clients.clone(),
+                self.registry_version,
+            )
+            .await?;
+        let (tls_read_half, tls_write_half) = tls_stream.split();
+
+        self.send_msg_to_client_if_configured(tls_write_half).await;
+        self.expect_msg_from_client_if_configured(tls_read_half)
+            .await;
+        Ok(authenticated_node)
+    }
+
+    pub async fn run_with_validation(
+        self,
+        validation: &TlsValidation,
+    ) -> Result<AuthenticatedPeer, TlsServerHandshakeError> {
+        let tcp_stream = self.accept_connection_on_listener().await;
+
+        let (tls_stream, authenticated_node) = self
+            .crypto
+            .perform_tls_server_handshake_with_validation(
+                tcp_stream,
+                self.allowed_clients.clone(),
+                validation,
+                self.registry_version,
+            )
             .await?;
         let (tls_read_half, tls_write_half) = tls_stream.split();
 
@@ -140,7 +178,7 @@ impl Server {
             .perform_tls_server_handshake_temp_with_optional_client_auth(
                 tcp_stream,
                 self.allowed_clients.clone(),
-                REG_V1,
+                self.registry_version,
             )
             .await?;
         let (tls_read_half, tls_write_half) = tls_stream.split();
@@ -156,7 +194,7 @@ impl Server {
 
         let tls_stream = self
             .crypto
-            .perform_tls_server_handshake_without_client_auth(tcp_stream, REG_V1)
+            .perform_tls_server_handshake_without_client_auth(tcp_stream, self.registry_version)
             .await?;
         let (tls_read_half, tls_write_half) = tls_stream.split();
 
@@ -210,6 +248,14 @@ impl Server {
         self.cert.to_proto()
     }
 
+    pub fn tls_cert(&self) -> TlsPublicKeyCert {
+        self.cert.clone()
+    }
+
+    pub fn cert_issued_by(&self, ca: &CertWithPrivateKey) -> TlsPublicKeyCert {
+        issued_by_ca(&self.crypto, &self.cert, ca)
+    }
+
     pub fn allowed_clients(&self) -> &BTreeSet<NodeId> {
         match self.allowed_clients.nodes() {
             SomeOrAllNodes::Some(nodes) => nodes,
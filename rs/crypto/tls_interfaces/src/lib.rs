@@ -6,16 +6,19 @@ use async_trait::async_trait;
 use core::fmt;
 use ic_protobuf::registry::crypto::v1::X509PublicKeyCert;
 use ic_types::registry::RegistryClientError;
-use ic_types::{NodeId, RegistryVersion};
+use ic_types::{NodeId, PrincipalId, RegistryVersion};
 use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
 use openssl::x509::X509;
 use serde::{Deserialize, Deserializer, Serialize};
-use std::collections::{BTreeSet, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::fmt::{Display, Formatter};
 use std::hash::{Hash, Hasher};
 use std::io;
 use std::pin::Pin;
+use std::str::FromStr;
 use std::task::{Context, Poll};
+use std::time::Duration;
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf, ReadHalf, WriteHalf};
 use tokio::net::TcpStream;
 use tokio_openssl::SslStream;
@@ -344,6 +347,18 @@ impl AsyncWrite for TlsWriteHalf {
         Pin::new(&mut self.write_half).poll_write(cx, buf)
     }
 
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<Result<usize, io::Error>> {
+        Pin::new(&mut self.write_half).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.write_half.is_write_vectored()
+    }
+
     fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
         Pin::new(&mut self.write_half).poll_flush(cx)
     }
@@ -356,6 +371,18 @@ impl AsyncWrite for TlsWriteHalf {
     }
 }
 
+/// How long a node keeps presenting its previous TLS certificate after a new
+/// one replaced it in the registry, e.g. when its keys are rotated.
+///
+/// The nodes learn about a new registry version at different times, so a
+/// node switching to its new certificate right away would fail the
+/// handshakes with the peers that do not know it yet. In registry-pinned
+/// handshakes, a peer's replaced certificate is therefore trusted next to
+/// its registered one for twice this period after the replacement became
+/// available locally, which covers the difference between the times at
+/// which the nodes learn about it.
+pub const TLS_CERT_REPLACEMENT_GRACE_PERIOD: Duration = Duration::from_secs(5 * 60);
+
 #[async_trait]
 /// Implementors provide methods for transforming TCP streams into TLS stream.
 ///
@@ -363,6 +390,10 @@ impl AsyncWrite for TlsWriteHalf {
 /// allow for extracting the secret keys of the underlying TLS session. This
 /// is done because directly returning the underlying structs may allow for
 /// extraction of the secret session keys.
+///
+/// While a node's TLS certificate is being replaced, the node and its peers
+/// use the previous certificate as described for
+/// `TLS_CERT_REPLACEMENT_GRACE_PERIOD`.
 pub trait TlsHandshake {
     /// Transforms a TCP stream into a TLS stream by first performing a TLS
     /// server handshake and then verifying that the authenticated peer is an
@@ -374,6 +405,9 @@ pub trait TlsHandshake {
     /// * Allowed cipher suites: TLS_AES_128_GCM_SHA256, TLS_AES_256_GCM_SHA384
     /// * Client authentication: mandatory, with ed25519 certificate
     /// * Maximum number of intermediate CA certificates: 1
+    /// * Session resumption: session tickets issued with the same set of
+    ///   allowed client certificates are accepted, in which case C_handshake
+    ///   is the certificate presented in the resumed session
     ///
     /// To determine whether the peer (that successfully performed the
     /// handshake) is an allowed client, the following steps are taken:
@@ -562,6 +596,8 @@ pub trait TlsHandshake {
     /// * Supported signature algorithms: ed25519
     /// * Allowed cipher suites: TLS_AES_128_GCM_SHA256, TLS_AES_256_GCM_SHA384
     /// * Server authentication: mandatory, with ed25519 certificate
+    /// * Session resumption: the session ticket received in the previous
+    ///   handshake with the same server certificate is offered
     ///
     /// To determine whether the peer (that successfully performed the
     /// handshake) is the `server`, the following steps are taken:
@@ -606,6 +642,143 @@ pub trait TlsHandshake {
         server: NodeId,
         registry_version: RegistryVersion,
     ) -> Result<TlsStream, TlsClientHandshakeError>;
+
+    /// Like `perform_tls_server_handshake`, but the certificates are
+    /// determined according to `validation`. With
+    /// `TlsValidation::RegistryPinned`, this is `perform_tls_server_handshake`.
+    /// Otherwise, the registry is not accessed, and a client authenticates
+    /// as the node N_claimed in the subject name of its certificate if
+    /// N_claimed is contained in the nodes in `allowed_clients` and
+    /// * with `TlsValidation::CaIssued`, the certificate is issued by the CA,
+    ///   or
+    /// * with `TlsValidation::AllowList`, the certificate is the one in the
+    ///   list for N_claimed.
+    ///
+    /// The certificates in `allowed_clients` are only considered with
+    /// `TlsValidation::RegistryPinned`.
+    ///
+    /// # Errors
+    /// As for `perform_tls_server_handshake`. In addition,
+    /// TlsServerHandshakeError::MalformedSelfCertificate is returned if
+    /// * with `TlsValidation::CaIssued`, the node's certificate issued by the
+    ///   CA is not for the node's TLS key pair, or
+    /// * with `TlsValidation::AllowList`, the node's public key store does not
+    ///   contain a well-formed certificate.
+    async fn perform_tls_server_handshake_with_validation(
+        &self,
+        tcp_stream: TcpStream,
+        allowed_clients: AllowedClients,
+        validation: &TlsValidation,
+        registry_version: RegistryVersion,
+    ) -> Result<(TlsStream, AuthenticatedPeer), TlsServerHandshakeError>;
+
+    /// Like `perform_tls_client_handshake`, but the certificates are
+    /// determined according to `validation`. With
+    /// `TlsValidation::RegistryPinned`, this is `perform_tls_client_handshake`.
+    /// Otherwise, the registry is not accessed, and the peer's certificate
+    /// must be for the node ID `server` and
+    /// * with `TlsValidation::CaIssued`, be issued by the CA, or
+    /// * with `TlsValidation::AllowList`, be the one in the list for
+    ///   `server`.
+    ///
+    /// # Errors
+    /// As for `perform_tls_client_handshake`, and
+    /// TlsClientHandshakeError::MalformedSelfCertificate in the cases listed
+    /// for `perform_tls_server_handshake_with_validation`.
+    async fn perform_tls_client_handshake_with_validation(
+        &self,
+        tcp_stream: TcpStream,
+        server: NodeId,
+        validation: &TlsValidation,
+        registry_version: RegistryVersion,
+    ) -> Result<TlsStream, TlsClientHandshakeError>;
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// How the TLS certificates used in a handshake are determined.
+pub enum TlsValidation {
+    /// The node and its peers authenticate with their certificates in the
+    /// registry.
+    RegistryPinned,
+    /// The peers authenticate with a certificate for their node ID that is
+    /// issued by the CA with certificate `ca_cert`. The node authenticates
+    /// with `self_cert`, which the CA issued for the node's TLS key pair.
+    CaIssued {
+        ca_cert: TlsPublicKeyCert,
+        self_cert: TlsPublicKeyCert,
+    },
+    /// The peers authenticate with their certificate in the list. The node
+    /// authenticates with its own certificate in its public key store.
+    AllowList(BTreeMap<NodeId, TlsPublicKeyCert>),
+}
+
+impl TlsValidation {
+    /// Creates a `CaIssued` validation from the PEM encoded certificates of
+    /// the CA and of the node.
+    pub fn ca_issued_from_pem(
+        ca_cert_pem: &[u8],
+        self_cert_pem: &[u8],
+    ) -> Result<Self, TlsPublicKeyCertCreationError> {
+        Ok(TlsValidation::CaIssued {
+            ca_cert: single_cert_from_pem(ca_cert_pem)?,
+            self_cert: single_cert_from_pem(self_cert_pem)?,
+        })
+    }
+
+    /// Creates an `AllowList` validation from PEM encoded certificates. Each
+    /// certificate is allowed for the node ID in its subject common name.
+    pub fn allow_list_from_pem(certs_pem: &[u8]) -> Result<Self, TlsPublicKeyCertCreationError> {
+        let mut certs = BTreeMap::new();
+        for cert in certs_from_pem(certs_pem)? {
+            let node_id = node_id_from_subject_common_name(&cert)?;
+            if certs.insert(node_id, cert).is_some() {
+                return Err(TlsPublicKeyCertCreationError {
+                    internal_error: format!("Several certificates for node {}", node_id),
+                });
+            }
+        }
+        Ok(TlsValidation::AllowList(certs))
+    }
+}
+
+fn certs_from_pem(pem: &[u8]) -> Result<Vec<TlsPublicKeyCert>, TlsPublicKeyCertCreationError> {
+    X509::stack_from_pem(pem)
+        .map_err(|e| TlsPublicKeyCertCreationError {
+            internal_error: format!("Error parsing PEM: {}", e),
+        })?
+        .into_iter()
+        .map(TlsPublicKeyCert::new_from_x509)
+        .collect()
+}
+
+fn single_cert_from_pem(pem: &[u8]) -> Result<TlsPublicKeyCert, TlsPublicKeyCertCreationError> {
+    let mut certs = certs_from_pem(pem)?;
+    if certs.len() != 1 {
+        return Err(TlsPublicKeyCertCreationError {
+            internal_error: format!("Expected one certificate, found {}", certs.len()),
+        });
+    }
+    Ok(certs.remove(0))
+}
+
+fn node_id_from_subject_common_name(
+    cert: &TlsPublicKeyCert,
+) -> Result<NodeId, TlsPublicKeyCertCreationError> {
+    let error = |internal_error: String| TlsPublicKeyCertCreationError { internal_error };
+    let mut entries = cert
+        .as_x509()
+        .subject_name()
+        .entries_by_nid(Nid::COMMONNAME);
+    let common_name = match (entries.next(), entries.next()) {
+        (Some(entry), None) => entry
+            .data()
+            .as_utf8()
+            .map_err(|e| error(format!("ASN1 to UTF-8 conversion error: {}", e)))?,
+        _ => return Err(error("Expected one subject common name".to_string())),
+    };
+    PrincipalId::from_str(common_name.as_ref())
+        .map(NodeId::from)
+        .map_err(|e| error(format!("Principal ID parse error: {}", e)))
 }
 
 #[derive(Clone, Debug)]
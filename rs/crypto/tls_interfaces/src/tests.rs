@@ -267,3 +267,63 @@ mod allowed_clients {
         NodeId::from(PrincipalId::new_node_test_id(id))
     }
 }
+
+mod tls_validation {
+    use crate::{TlsPublicKeyCert, TlsValidation};
+    use ic_crypto_test_utils::tls::x509_certificates::CertWithPrivateKey;
+    use ic_types::{NodeId, PrincipalId};
+    use maplit::btreemap;
+
+    #[test]
+    fn should_create_allow_list_keyed_by_subject_cn() {
+        let (cert_1, pem_1) = cert_with_cn(&node_id(1).get().to_string());
+        let (cert_2, pem_2) = cert_with_cn(&node_id(2).get().to_string());
+
+        let validation = TlsValidation::allow_list_from_pem(&[pem_1, pem_2].concat()).unwrap();
+
+        assert_eq!(
+            validation,
+            TlsValidation::AllowList(btreemap! {node_id(1) => cert_1, node_id(2) => cert_2})
+        );
+    }
+
+    #[test]
+    fn should_fail_on_allow_list_with_cn_that_is_no_node_id() {
+        let (_, pem) = cert_with_cn("not a node ID");
+
+        assert!(TlsValidation::allow_list_from_pem(&pem).is_err());
+    }
+
+    #[test]
+    fn should_fail_on_allow_list_with_duplicate_node_id() {
+        let (_, pem_1) = cert_with_cn(&node_id(1).get().to_string());
+        let (_, pem_2) = cert_with_cn(&node_id(1).get().to_string());
+
+        assert!(TlsValidation::allow_list_from_pem(&[pem_1, pem_2].concat()).is_err());
+    }
+
+    #[test]
+    fn should_fail_on_ca_issued_with_several_ca_certs() {
+        let (_, ca_pem_1) = cert_with_cn("CA 1");
+        let (_, ca_pem_2) = cert_with_cn("CA 2");
+        let (_, self_pem) = cert_with_cn(&node_id(1).get().to_string());
+
+        assert!(TlsValidation::ca_issued_from_pem(&ca_pem_1, &self_pem).is_ok());
+        assert!(
+            TlsValidation::ca_issued_from_pem(&[ca_pem_1, ca_pem_2].concat(), &self_pem).is_err()
+        );
+    }
+
+    fn cert_with_cn(cn: &str) -> (TlsPublicKeyCert, Vec<u8>) {
+        let x509 = CertWithPrivateKey::builder()
+            .cn(cn.to_string())
+            .build_ed25519()
+            .x509();
+        let pem = x509.to_pem().unwrap();
+        (TlsPublicKeyCert::new_from_x509(x509).unwrap(), pem)
+    }
+
+    fn node_id(id: u64) -> NodeId {
+        NodeId::from(PrincipalId::new_node_test_id(id))
+    }
+}
@@ -14,7 +14,10 @@
 //! 3. reimburse the canister with `cycles_reserved` - `cycles_spent`
 
 use ic_config::subnet_config::CyclesAccountManagerConfig;
+use ic_interfaces::registry::RegistryClient;
 use ic_logger::{info, ReplicaLogger};
+use ic_protobuf::registry::fee_schedule::v1::FeeScheduleRecord;
+use ic_registry_client::helper::fee_schedule::FeeScheduleRegistry;
 use ic_registry_subnet_type::SubnetType;
 use ic_replicated_state::{CanisterState, SystemState};
 use ic_types::{
@@ -26,9 +29,15 @@ use ic_types::{
         MAX_INTER_CANISTER_PAYLOAD_IN_BYTES,
     },
     nominal_cycles::NominalCycles,
-    CanisterId, ComputeAllocation, Cycles, MemoryAllocation, NumBytes, NumInstructions, SubnetId,
+    registry::RegistryClientError,
+    CanisterId, ComputeAllocation, Cycles, MemoryAllocation, NumBytes, NumInstructions,
+    RegistryVersion, SubnetId,
+};
+use std::{
+    str::FromStr,
+    sync::{Arc, RwLock},
+    time::Duration,
 };
-use std::{str::FromStr, time::Duration};
 
 /// Errors returned by the [`CyclesAccountManager`].
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -70,9 +79,46 @@ impl std::fmt::Display for CanisterOutOfCyclesError {
     }
 }
 
+/// A version of the fees that the [`CyclesAccountManager`] charges.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FeeSchedule {
+    /// The version of the schedule in the registry. The fees compiled into
+    /// the replica have version 0.
+    pub version: u64,
+    /// The fees of the schedule.
+    pub config: CyclesAccountManagerConfig,
+}
+
+impl From<&FeeScheduleRecord> for FeeSchedule {
+    fn from(record: &FeeScheduleRecord) -> Self {
+        Self {
+            version: record.version,
+            config: CyclesAccountManagerConfig {
+                canister_creation_fee: Cycles::from(record.canister_creation_fee),
+                update_message_execution_fee: Cycles::from(record.update_message_execution_fee),
+                ten_update_instructions_execution_fee: Cycles::from(
+                    record.ten_update_instructions_execution_fee,
+                ),
+                xnet_call_fee: Cycles::from(record.xnet_call_fee),
+                xnet_byte_transmission_fee: Cycles::from(record.xnet_byte_transmission_fee),
+                ingress_message_reception_fee: Cycles::from(record.ingress_message_reception_fee),
+                ingress_byte_reception_fee: Cycles::from(record.ingress_byte_reception_fee),
+                gib_storage_per_second_fee: Cycles::from(record.gib_storage_per_second_fee),
+                compute_percent_allocated_per_second_fee: Cycles::from(
+                    record.compute_percent_allocated_per_second_fee,
+                ),
+            },
+        }
+    }
+}
+
 /// Handles any operation related to cycles accounting, such as charging (due to
 /// using system resources) or refunding unused cycles.
-#[derive(Clone, Copy, Debug, PartialEq)]
+///
+/// The fees are taken from the fee schedule in the registry. Message routing
+/// activates the schedule of the registry version of each batch before the
+/// batch is executed, so that all replicas charge the same fees for it.
+#[derive(Clone, Debug)]
 pub struct CyclesAccountManager {
     /// The maximum allowed instructions to be spent on a single message
     /// execution.
@@ -84,9 +130,13 @@ pub struct CyclesAccountManager {
     own_subnet_type: SubnetType,
     /// The subnet id of this [`CyclesAccountManager`].
     subnet_id: SubnetId,
-    /// The configuration of this [`CyclesAccountManager`] controlling the fees
-    /// that are charged for various operations.
-    config: CyclesAccountManagerConfig,
+    /// The fees compiled into the replica, which are charged as long as the
+    /// registry contains no fee schedule.
+    default_config: CyclesAccountManagerConfig,
+    /// The active fee schedule, controlling the fees that are charged for
+    /// various operations. It is shared by the clones of this
+    /// [`CyclesAccountManager`].
+    fee_schedule: Arc<RwLock<FeeSchedule>>,
 }
 
 impl CyclesAccountManager {
@@ -104,7 +154,55 @@ impl CyclesAccountManager {
             max_cycles_per_canister,
             own_subnet_type,
             subnet_id,
-            config,
+            default_config: config,
+            fee_schedule: Arc::new(RwLock::new(FeeSchedule { version: 0, config })),
+        }
+    }
+
+    /// Returns the fees of the active fee schedule.
+    fn config(&self) -> CyclesAccountManagerConfig {
+        self.fee_schedule.read().unwrap().config
+    }
+
+    /// Returns the active fee schedule.
+    pub fn fee_schedule(&self) -> FeeSchedule {
+        *self.fee_schedule.read().unwrap()
+    }
+
+    /// Returns the fee schedule that applies at the given registry version,
+    /// i.e. the one in the registry, or the fees compiled into the replica if
+    /// the registry contains none. System subnets are free, so they always
+    /// charge the compiled-in fees.
+    pub fn fee_schedule_at(
+        &self,
+        registry: &dyn RegistryClient,
+        version: RegistryVersion,
+    ) -> Result<FeeSchedule, RegistryClientError> {
+        let default = FeeSchedule {
+            version: 0,
+            config: self.default_config,
+        };
+        if self.own_subnet_type == SubnetType::System {
+            return Ok(default);
+        }
+        Ok(registry
+            .get_fee_schedule(version)?
+            .map(|record| FeeSchedule::from(&record))
+            .unwrap_or(default))
+    }
+
+    /// Makes `fee_schedule` the active fee schedule of this
+    /// [`CyclesAccountManager`] and all its clones.
+    pub fn set_fee_schedule(&self, fee_schedule: FeeSchedule) {
+        *self.fee_schedule.write().unwrap() = fee_schedule;
+    }
+
+    /// Returns a copy of this [`CyclesAccountManager`] that charges the fees
+    /// of `fee_schedule`, independently of the active schedule of this one.
+    pub fn with_fee_schedule(&self, fee_schedule: FeeSchedule) -> Self {
+        Self {
+            fee_schedule: Arc::new(RwLock::new(fee_schedule)),
+            ..self.clone()
         }
     }
 
@@ -121,27 +219,27 @@ impl CyclesAccountManager {
 
     /// Returns the fee to create a canister in [`Cycles`].
     pub fn canister_creation_fee(&self) -> Cycles {
-        self.config.canister_creation_fee
+        self.config().canister_creation_fee
     }
 
     /// Returns the fee for receiving an ingress message in [`Cycles`].
     pub fn ingress_message_received_fee(&self) -> Cycles {
-        self.config.ingress_message_reception_fee
+        self.config().ingress_message_reception_fee
     }
 
     /// Returns the fee per byte of ingress message received in [`Cycles`].
     pub fn ingress_byte_received_fee(&self) -> Cycles {
-        self.config.ingress_byte_reception_fee
+        self.config().ingress_byte_reception_fee
     }
 
     /// Returns the fee for performing a xnet call in [`Cycles`].
     pub fn xnet_call_performed_fee(&self) -> Cycles {
-        self.config.xnet_call_fee
+        self.config().xnet_call_fee
     }
 
     /// Returns the fee per byte of transmitted xnet call in [`Cycles`].
     pub fn xnet_call_bytes_transmitted_fee(&self, payload_size: NumBytes) -> Cycles {
-        self.config.xnet_byte_transmission_fee * Cycles::from(payload_size.get())
+        self.config().xnet_byte_transmission_fee * Cycles::from(payload_size.get())
     }
 
     #[doc(hidden)]
@@ -160,7 +258,7 @@ impl CyclesAccountManager {
             };
             Cycles::from(
                 (memory.get() as u128
-                    * self.config.gib_storage_per_second_fee.get()
+                    * self.config().gib_storage_per_second_fee.get()
                     * system_state.freeze_threshold.get() as u128)
                     / one_gib,
             )
@@ -169,7 +267,7 @@ impl CyclesAccountManager {
         let compute_fee = {
             Cycles::from(
                 compute_allocation.as_percent() as u128
-                    * self.config.compute_percent_allocated_per_second_fee.get()
+                    * self.config().compute_percent_allocated_per_second_fee.get()
                     * system_state.freeze_threshold.get() as u128,
             )
         };
@@ -268,7 +366,7 @@ impl CyclesAccountManager {
         system_state: &mut SystemState,
         num_instructions: NumInstructions,
     ) {
-        let cycles_to_refund = self.config.ten_update_instructions_execution_fee
+        let cycles_to_refund = self.config().ten_update_instructions_execution_fee
             * Cycles::from(num_instructions.get() / 10);
         self.refund_cycles(system_state, cycles_to_refund);
     }
@@ -298,7 +396,7 @@ impl CyclesAccountManager {
         compute_allocation: ComputeAllocation,
         duration: Duration,
     ) -> Cycles {
-        self.config.compute_percent_allocated_per_second_fee
+        self.config().compute_percent_allocated_per_second_fee
             * Cycles::from(duration.as_secs())
             * Cycles::from(compute_allocation.as_percent())
     }
@@ -359,8 +457,9 @@ impl CyclesAccountManager {
                 let bytes_to_charge = ingress.arg().len()
                     + ingress.method_name().len()
                     + ingress.nonce().map(|n| n.len()).unwrap_or(0);
-                let cost = self.config.ingress_message_reception_fee
-                    + self.config.ingress_byte_reception_fee * bytes_to_charge;
+                let config = self.config();
+                let cost = config.ingress_message_reception_fee
+                    + config.ingress_byte_reception_fee * bytes_to_charge;
                 Ok(IngressInductionCost::Fee {
                     payer: paying_canister,
                     cost,
@@ -405,7 +504,7 @@ impl CyclesAccountManager {
         let one_gib = 1024 * 1024 * 1024;
         Cycles::from(
             (bytes.get() as u128
-                * self.config.gib_storage_per_second_fee.get()
+                * self.config().gib_storage_per_second_fee.get()
                 * duration.as_secs() as u128)
                 / one_gib,
         )
@@ -444,10 +543,10 @@ impl CyclesAccountManager {
         // response) + the fee to send the request + the fee for the largest
         // possible response + the fee for executing the largest allowed
         // response when it eventually arrives.
-        let fee = self.config.xnet_call_fee
-            + self.config.xnet_byte_transmission_fee
-                * Cycles::from(request.payload_size_bytes().get())
-            + self.config.xnet_byte_transmission_fee
+        let config = self.config();
+        let fee = config.xnet_call_fee
+            + config.xnet_byte_transmission_fee * Cycles::from(request.payload_size_bytes().get())
+            + config.xnet_byte_transmission_fee
                 * Cycles::from(MAX_INTER_CANISTER_PAYLOAD_IN_BYTES.get())
             + self.execution_cost(self.max_num_instructions);
         self.consume_with_threshold(
@@ -469,7 +568,7 @@ impl CyclesAccountManager {
         // figure out how many extra bytes we charged for.
         let extra_bytes = MAX_INTER_CANISTER_PAYLOAD_IN_BYTES - response.response_payload.size_of();
         let cycles_to_refund =
-            self.config.xnet_byte_transmission_fee * Cycles::from(extra_bytes.get());
+            self.config().xnet_byte_transmission_fee * Cycles::from(extra_bytes.get());
         self.refund_cycles(system_state, cycles_to_refund);
     }
 
@@ -606,8 +705,9 @@ impl CyclesAccountManager {
     /// tests.
     #[doc(hidden)]
     pub fn execution_cost(&self, num_instructions: NumInstructions) -> Cycles {
-        self.config.update_message_execution_fee
-            + self.config.ten_update_instructions_execution_fee
+        let config = self.config();
+        config.update_message_execution_fee
+            + config.ten_update_instructions_execution_fee
                 * Cycles::from(num_instructions.get() / 10)
     }
 
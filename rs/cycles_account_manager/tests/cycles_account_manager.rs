@@ -3,6 +3,11 @@ use ic_config::subnet_config::SubnetConfigs;
 use ic_cycles_account_manager::{
     CanisterOutOfCyclesError, IngressInductionCost, IngressInductionCostError,
 };
+use ic_interfaces::registry::RegistryClient;
+use ic_protobuf::registry::fee_schedule::v1::FeeScheduleRecord;
+use ic_registry_client::client::RegistryClientImpl;
+use ic_registry_common::proto_registry_data_provider::ProtoRegistryDataProvider;
+use ic_registry_keys::make_fee_schedule_record_key;
 use ic_registry_subnet_type::SubnetType;
 use ic_replicated_state::SystemState;
 use ic_test_utilities::{
@@ -19,8 +24,9 @@ use ic_types::{
     messages::SignedIngressContent,
     nominal_cycles::NominalCycles,
     CanisterId, ComputeAllocation, Cycles, MemoryAllocation, NumBytes, NumInstructions,
+    RegistryVersion,
 };
-use std::{convert::TryFrom, time::Duration};
+use std::{convert::TryFrom, sync::Arc, time::Duration};
 
 const CYCLES_LIMIT_PER_CANISTER: Cycles = Cycles::new(100_000_000_000_000);
 const INITIAL_CYCLES: Cycles = Cycles::new(5_000_000_000_000);
@@ -754,3 +760,77 @@ fn verify_refund() {
         initial_consumed_cycles - NominalCycles::from(cycles)
     );
 }
+
+fn registry_with_fee_schedule(
+    version: RegistryVersion,
+    record: FeeScheduleRecord,
+) -> Arc<dyn RegistryClient> {
+    let data_provider = Arc::new(ProtoRegistryDataProvider::new());
+    data_provider
+        .add(&make_fee_schedule_record_key(), version, Some(record))
+        .unwrap();
+    let registry = Arc::new(RegistryClientImpl::new(data_provider, None));
+    registry.fetch_and_start_polling().unwrap();
+    registry
+}
+
+#[test]
+fn fee_schedule_is_loaded_from_the_registry() {
+    let registry = registry_with_fee_schedule(
+        RegistryVersion::from(2),
+        FeeScheduleRecord {
+            version: 7,
+            ingress_message_reception_fee: 10,
+            ingress_byte_reception_fee: 1,
+            ..Default::default()
+        },
+    );
+    let cycles_account_manager = CyclesAccountManagerBuilder::new().build();
+    let default_fee = cycles_account_manager.ingress_message_received_fee();
+
+    // There is no schedule at the first registry version.
+    let fee_schedule = cycles_account_manager
+        .fee_schedule_at(registry.as_ref(), RegistryVersion::from(1))
+        .unwrap();
+    assert_eq!(fee_schedule, cycles_account_manager.fee_schedule());
+
+    let fee_schedule = cycles_account_manager
+        .fee_schedule_at(registry.as_ref(), RegistryVersion::from(2))
+        .unwrap();
+    assert_eq!(fee_schedule.version, 7);
+    let with_schedule = cycles_account_manager.with_fee_schedule(fee_schedule);
+    assert_eq!(
+        with_schedule.ingress_message_received_fee(),
+        Cycles::from(10)
+    );
+    assert_eq!(
+        cycles_account_manager.ingress_message_received_fee(),
+        default_fee
+    );
+
+    // The active schedule is shared by the clones.
+    let clone = cycles_account_manager.clone();
+    cycles_account_manager.set_fee_schedule(fee_schedule);
+    assert_eq!(clone.ingress_message_received_fee(), Cycles::from(10));
+}
+
+#[test]
+fn system_subnets_ignore_the_fee_schedule() {
+    let registry = registry_with_fee_schedule(
+        RegistryVersion::from(1),
+        FeeScheduleRecord {
+            version: 1,
+            canister_creation_fee: 1_000,
+            ..Default::default()
+        },
+    );
+    let cycles_account_manager = CyclesAccountManagerBuilder::new()
+        .with_subnet_type(SubnetType::System)
+        .build();
+    assert_eq!(
+        cycles_account_manager
+            .fee_schedule_at(registry.as_ref(), RegistryVersion::from(1))
+            .unwrap(),
+        cycles_account_manager.fee_schedule()
+    );
+}
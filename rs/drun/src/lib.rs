@@ -172,7 +172,7 @@ pub fn run_drun(uo: DrunOptions) -> Result<(), String> {
         subnet_config.cycles_account_manager_config,
     ));
 
-    let (_, ingress_history_writer, http_query_handler, scheduler) = setup_execution(
+    let (_, ingress_history_writer, http_query_handler, scheduler, _) = setup_execution(
         log.clone().into(),
         &metrics_registry,
         replica_config.subnet_id,
@@ -272,12 +272,12 @@ fn build_batch(message_routing: &dyn MessageRouting, msgs: Vec<SignedIngress>) -
     Batch {
         batch_number: message_routing.expected_batch_height(),
         requires_full_state_hash: !msgs.is_empty(),
-        payload: BatchPayload {
-            ingress: IngressPayload::from(msgs),
-            xnet: XNetPayload {
+        payload: BatchPayload::new(
+            IngressPayload::from(msgs),
+            XNetPayload {
                 stream_slices: Default::default(),
             },
-        },
+        ),
         randomness: Randomness::from([0; 32]),
         registry_version: RegistryVersion::from(1),
         time: mock_time(),
@@ -18,8 +18,8 @@ use ic_interfaces::execution_environment::{
 use ic_logger::{error, fatal, info, ReplicaLogger};
 use ic_registry_provisional_whitelist::ProvisionalWhitelist;
 use ic_replicated_state::{
-    CallOrigin, CanisterState, CanisterStatus, ExecutionState, ReplicatedState, SchedulerState,
-    SystemState,
+    page_map::cold_storage::cold_pages_link, CallOrigin, CanisterState, CanisterStatus,
+    ExecutionState, ReplicatedState, SchedulerState, SystemState,
 };
 use ic_state_layout::{CanisterLayout, CheckpointLayout, RwPolicy};
 use ic_types::{
@@ -1502,6 +1502,19 @@ pub(crate) fn truncate_canister_heap(
             )
         }
     }
+    // The cold pages of the heap are gone with its contents.
+    let cold_pages_link = cold_pages_link(&heap_file);
+    if let Err(err) = std::fs::remove_file(&cold_pages_link) {
+        if err.kind() != std::io::ErrorKind::NotFound {
+            fatal!(
+                log,
+                "failed to remove cold heap pages of canister {} linked at {}: {}",
+                canister_id,
+                cold_pages_link.display(),
+                err
+            )
+        }
+    }
 }
 
 pub(crate) fn truncate_canister_stable_memory(
@@ -35,8 +35,8 @@ use ic_types::{
     crypto::threshold_sig::ni_dkg::NiDkgTargetId,
     ingress::{IngressStatus, WasmResult},
     messages::{
-        is_subnet_message, CallbackId, Ingress, MessageId, Payload, RejectContext, Request,
-        Response, SignedIngressContent, StopCanisterContext,
+        correlation_id, is_subnet_message, CallbackId, Ingress, MessageId, Payload, RejectContext,
+        Request, Response, SignedIngressContent, StopCanisterContext,
     },
     user_error::{ErrorCode, RejectCode, UserError},
     CanisterId, CanisterStatusType, Cycles, InstallCodeContext, NumBytes, NumInstructions,
@@ -482,6 +482,12 @@ impl ExecutionEnvironment for ExecutionEnvironmentImpl {
             }
 
             CanisterInputMessage::Ingress(ingress) => {
+                let span = tracing::debug_span!(
+                    "ingress_execution",
+                    message_id = %ingress.message_id,
+                    correlation_id = %correlation_id(&ingress.message_id),
+                );
+                let _enter = span.enter();
                 let memory_usage = canister.memory_usage();
                 let compute_allocation = canister.scheduler_state.compute_allocation;
                 if let Err(err) = self.cycles_account_manager.withdraw_execution_cycles(
@@ -7,23 +7,163 @@ use ic_metrics::{buckets::decimal_buckets, MetricsRegistry, Timer};
 use ic_replicated_state::ReplicatedState;
 use ic_types::{
     ingress::IngressStatus,
-    messages::MessageId,
+    messages::{correlation_id, MessageId},
     user_error::{ErrorCode, RejectCode},
     Height, Time,
 };
 use prometheus::{Histogram, HistogramVec};
 use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use tokio::sync::{mpsc, oneshot, watch};
+
+/// The pending subscriptions to terminal statuses and to status transitions.
+#[derive(Default)]
+struct Subscriptions {
+    /// The subscribers waiting for each message.
+    senders: HashMap<MessageId, Vec<oneshot::Sender<IngressStatus>>>,
+    /// The subscribers to the status transitions of each message, with the
+    /// last status sent to each of them.
+    transitions: HashMap<MessageId, Vec<(mpsc::UnboundedSender<IngressStatus>, IngressStatus)>>,
+    /// True if the thread checking the subscriptions is running.
+    watcher_running: bool,
+}
+
+impl Subscriptions {
+    /// Notifies the subscribers of the statuses of their messages in the
+    /// given certified state.
+    fn notify(&mut self, state: &ReplicatedState) {
+        self.senders.retain(|message_id, senders| {
+            let status = state.get_ingress_status(message_id);
+            if is_terminal(&status) {
+                for sender in senders.drain(..) {
+                    let _ = sender.send(status.clone());
+                }
+            }
+            !senders.is_empty()
+        });
+        self.transitions.retain(|message_id, subscribers| {
+            let status = state.get_ingress_status(message_id);
+            for (sender, last_status) in subscribers.iter_mut() {
+                if status != *last_status {
+                    let _ = sender.send(status.clone());
+                    *last_status = status.clone();
+                }
+            }
+            // Dropping the senders after the terminal status closes the
+            // subscriptions.
+            !is_terminal(&status)
+        });
+    }
+
+    /// Removes the subscriptions whose receivers were dropped.
+    fn remove_closed(&mut self) {
+        self.senders.retain(|_, senders| {
+            senders.retain(|sender| !sender.is_closed());
+            !senders.is_empty()
+        });
+        self.transitions.retain(|_, subscribers| {
+            subscribers.retain(|(sender, _)| !sender.is_closed());
+            !subscribers.is_empty()
+        });
+    }
+
+    fn is_empty(&self) -> bool {
+        self.senders.is_empty() && self.transitions.is_empty()
+    }
+}
+
+/// Returns true if the status is final, i.e. it will not change anymore
+/// until the status is pruned from the ingress history.
+fn is_terminal(status: &IngressStatus) -> bool {
+    matches!(
+        status,
+        IngressStatus::Completed { .. } | IngressStatus::Failed { .. }
+    )
+}
 
 /// Struct that implements the ingress history reader trait. Consumers of this
 /// trait can use this to inspect the ingress history.
 pub struct IngressHistoryReaderImpl {
     state_reader: Arc<dyn StateReader<State = ReplicatedState>>,
+    subscriptions: Arc<Mutex<Subscriptions>>,
 }
 
 impl IngressHistoryReaderImpl {
     pub fn new(state_reader: Arc<dyn StateReader<State = ReplicatedState>>) -> Self {
-        Self { state_reader }
+        Self {
+            state_reader,
+            subscriptions: Default::default(),
+        }
+    }
+
+    /// Returns the status of the given message in the latest certified state.
+    fn get_certified_status(&self, message_id: &MessageId) -> IngressStatus {
+        let height = self.state_reader.latest_certified_height();
+        self.state_reader
+            .get_state_at(height)
+            .map(|state| state.get_ref().get_ingress_status(message_id))
+            .unwrap_or(IngressStatus::Unknown)
+    }
+
+    /// Spawns the thread checking the subscriptions, unless it is running.
+    fn ensure_watcher_running(&self, subscriptions: &mut Subscriptions) {
+        if !subscriptions.watcher_running {
+            subscriptions.watcher_running = true;
+            let state_reader = Arc::clone(&self.state_reader);
+            let certified_height = self.state_reader.subscribe_certified_height();
+            let weak_subscriptions = Arc::downgrade(&self.subscriptions);
+            std::thread::Builder::new()
+                .name("ingress_history_subscriptions".to_string())
+                .spawn(move || {
+                    Self::watch_subscriptions(state_reader, certified_height, weak_subscriptions)
+                })
+                .expect("Couldn't spawn the ingress history subscription thread");
+        }
+    }
+
+    /// Checks the subscriptions whenever the certification of a newer state
+    /// is delivered, looking up the subscribed messages once per certified
+    /// state, until there are no subscriptions left or the reader is dropped.
+    /// If no more certifications will be delivered, the remaining
+    /// subscriptions are closed.
+    fn watch_subscriptions(
+        state_reader: Arc<dyn StateReader<State = ReplicatedState>>,
+        mut certified_height: watch::Receiver<Height>,
+        weak_subscriptions: Weak<Mutex<Subscriptions>>,
+    ) {
+        // A certification may have been delivered between the subscription
+        // and the spawning of this thread, so the first check doesn't wait.
+        let mut checked_height = None;
+        loop {
+            let subscriptions = match weak_subscriptions.upgrade() {
+                Some(subscriptions) => subscriptions,
+                None => return,
+            };
+            let mut subscriptions = subscriptions.lock().unwrap();
+            let height = state_reader.latest_certified_height();
+            if checked_height < Some(height) {
+                if let Ok(state) = state_reader.get_state_at(height) {
+                    subscriptions.notify(state.get_ref());
+                    checked_height = Some(height);
+                }
+            }
+            subscriptions.remove_closed();
+            if subscriptions.is_empty() {
+                subscriptions.watcher_running = false;
+                return;
+            }
+            drop(subscriptions);
+
+            if futures::executor::block_on(certified_height.changed()).is_err() {
+                if let Some(subscriptions) = weak_subscriptions.upgrade() {
+                    let mut subscriptions = subscriptions.lock().unwrap();
+                    subscriptions.senders.clear();
+                    subscriptions.transitions.clear();
+                    subscriptions.watcher_running = false;
+                }
+                return;
+            }
+        }
     }
 }
 
@@ -55,6 +195,48 @@ impl IngressHistoryReader for IngressHistoryReaderImpl {
                 .unwrap_or(IngressStatus::Unknown)
         }))
     }
+
+    fn subscribe_to_terminal_status(
+        &self,
+        message_id: MessageId,
+    ) -> oneshot::Receiver<IngressStatus> {
+        let (sender, receiver) = oneshot::channel();
+        let status = self.get_certified_status(&message_id);
+        if is_terminal(&status) {
+            let _ = sender.send(status);
+            return receiver;
+        }
+
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        subscriptions
+            .senders
+            .entry(message_id)
+            .or_default()
+            .push(sender);
+        self.ensure_watcher_running(&mut subscriptions);
+        receiver
+    }
+
+    fn subscribe_to_status_transitions(
+        &self,
+        message_id: MessageId,
+    ) -> mpsc::UnboundedReceiver<IngressStatus> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let status = self.get_certified_status(&message_id);
+        let _ = sender.send(status.clone());
+        if is_terminal(&status) {
+            return receiver;
+        }
+
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        subscriptions
+            .transitions
+            .entry(message_id)
+            .or_default()
+            .push((sender, status));
+        self.ensure_watcher_running(&mut subscriptions);
+        receiver
+    }
 }
 
 /// Records the Internet Computer time and system time of an event.
@@ -156,6 +338,12 @@ impl IngressHistoryWriter for IngressHistoryWriterImpl {
                 );
             }
             Completed { .. } => {
+                tracing::debug!(
+                    message_id = %message_id,
+                    correlation_id = %correlation_id(&message_id),
+                    status = "completed",
+                    "ingress_executed"
+                );
                 if let Some((ic_duration, wall_duration)) =
                     self.calculate_durations(&message_id, time)
                 {
@@ -168,6 +356,13 @@ impl IngressHistoryWriter for IngressHistoryWriterImpl {
             Failed {
                 error: user_error, ..
             } => {
+                tracing::debug!(
+                    message_id = %message_id,
+                    correlation_id = %correlation_id(&message_id),
+                    status = "failed",
+                    error_code = ?user_error.code(),
+                    "ingress_executed"
+                );
                 if let Some((ic_duration, wall_duration)) =
                     self.calculate_durations(&message_id, time)
                 {
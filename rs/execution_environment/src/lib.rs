@@ -16,8 +16,9 @@ pub use history::{IngressHistoryReaderImpl, IngressHistoryWriterImpl};
 pub use hypervisor::{execute, Hypervisor, HypervisorMetrics};
 use ic_config::{execution_environment::Config, subnet_config::SchedulerConfig};
 use ic_cycles_account_manager::CyclesAccountManager;
-use ic_interfaces::execution_environment::{
-    IngressHistoryWriter, IngressMessageFilter, QueryHandler, Scheduler,
+use ic_interfaces::{
+    execution_environment::{IngressHistoryWriter, IngressMessageFilter, QueryHandler, Scheduler},
+    query_stats::QueryStatsReader,
 };
 use ic_logger::ReplicaLogger;
 use ic_metrics::MetricsRegistry;
@@ -26,7 +27,7 @@ use ic_registry_subnet_type::SubnetType;
 use ic_replicated_state::ReplicatedState;
 use ic_types::{messages::CallContextId, SubnetId};
 use ingress_message_filter::IngressMessageFilterImpl;
-use query_handler::HttpQueryHandlerImpl;
+use query_handler::{HttpQueryHandlerImpl, QueryStatsCollectorImpl};
 use scheduler::SchedulerImpl;
 use std::sync::Arc;
 
@@ -52,7 +53,8 @@ pub enum QueryExecutionType {
 }
 
 /// Helper function to constructs the public facing components that the
-/// `ExecutionEnvironment` crate exports.
+/// `ExecutionEnvironment` crate exports. The `QueryStatsReader` hands out the
+/// statistics of the queries executed by the query handler.
 #[allow(clippy::type_complexity, clippy::too_many_arguments)]
 pub fn setup_execution(
     logger: ReplicaLogger,
@@ -67,6 +69,7 @@ pub fn setup_execution(
     Arc<dyn IngressHistoryWriter<State = ReplicatedState>>,
     Arc<dyn QueryHandler<State = ReplicatedState>>,
     Box<dyn Scheduler<State = ReplicatedState>>,
+    Arc<dyn QueryStatsReader>,
 ) {
     let hypervisor = Arc::new(Hypervisor::new(
         config.clone(),
@@ -93,13 +96,19 @@ pub fn setup_execution(
         config.clone(),
         Arc::clone(&cycles_account_manager),
     ));
+    let query_stats_collector = Arc::new(QueryStatsCollectorImpl::default());
     let http_query_handler = Arc::new(HttpQueryHandlerImpl::new(
         logger.clone(),
         hypervisor,
         own_subnet_id,
         own_subnet_type,
         config.subnet_memory_capacity,
+        config.query_execution_threads,
+        config.max_query_concurrency_per_canister,
+        config.max_query_batch_size,
+        config.max_queued_queries_per_canister,
         &metrics_registry,
+        Arc::clone(&query_stats_collector) as Arc<_>,
     ));
 
     let ingress_message_filter = Box::new(IngressMessageFilterImpl::new(Arc::clone(&exec_env)));
@@ -119,5 +128,6 @@ pub fn setup_execution(
         ingress_history_writer,
         http_query_handler,
         scheduler,
+        query_stats_collector,
     )
 }
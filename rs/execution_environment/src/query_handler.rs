@@ -3,6 +3,8 @@
 
 mod query_allocations;
 mod query_context;
+mod query_scheduler;
+mod query_stats;
 #[cfg(test)]
 mod tests;
 
@@ -10,7 +12,10 @@ use crate::{
     hypervisor::Hypervisor,
     metrics::{MeasurementScope, QueryHandlerMetrics},
 };
-use ic_interfaces::execution_environment::{QueryHandler, SubnetAvailableMemory};
+use ic_interfaces::{
+    execution_environment::{QueryHandler, SubnetAvailableMemory},
+    query_stats::QueryStatsCollector,
+};
 use ic_logger::ReplicaLogger;
 use ic_metrics::MetricsRegistry;
 use ic_registry_subnet_type::SubnetType;
@@ -19,10 +24,10 @@ use ic_types::{
     ingress::WasmResult, messages::UserQuery, user_error::UserError, NumBytes, SubnetId,
 };
 use query_allocations::QueryAllocationsUsed;
+use query_scheduler::QueryScheduler;
+pub(crate) use query_stats::QueryStatsCollectorImpl;
 use std::sync::{Arc, RwLock};
 
-const QUERY_EXECUTION_THREADS: usize = 1;
-
 pub(crate) struct InternalHttpQueryHandlerImpl {
     log: ReplicaLogger,
     hypervisor: Arc<Hypervisor>,
@@ -31,12 +36,13 @@ pub(crate) struct InternalHttpQueryHandlerImpl {
     query_allocations_used: Arc<RwLock<QueryAllocationsUsed>>,
     subnet_memory_capacity: NumBytes,
     pub(crate) metrics: QueryHandlerMetrics,
+    query_stats_collector: Arc<dyn QueryStatsCollector>,
 }
 
 /// Struct that is responsible for handling queries sent by user.
 pub struct HttpQueryHandlerImpl {
     internal: Arc<InternalHttpQueryHandlerImpl>,
-    threadpool: rayon::ThreadPool,
+    scheduler: QueryScheduler<ReplicatedState>,
 }
 
 impl InternalHttpQueryHandlerImpl {
@@ -47,6 +53,7 @@ impl InternalHttpQueryHandlerImpl {
         own_subnet_type: SubnetType,
         subnet_memory_capacity: NumBytes,
         metrics_registry: &MetricsRegistry,
+        query_stats_collector: Arc<dyn QueryStatsCollector>,
     ) -> Self {
         Self {
             log,
@@ -56,6 +63,7 @@ impl InternalHttpQueryHandlerImpl {
             query_allocations_used: Arc::new(RwLock::new(QueryAllocationsUsed::new())),
             subnet_memory_capacity,
             metrics: QueryHandlerMetrics::new(metrics_registry),
+            query_stats_collector,
         }
     }
 
@@ -89,37 +97,50 @@ impl InternalHttpQueryHandlerImpl {
             data_certificate,
             self.query_allocations_used.clone(),
             subnet_available_memory,
+            self.query_stats_collector.as_ref(),
         );
         context.run(query, &self.metrics, &measurement_scope)
     }
 }
 
 impl HttpQueryHandlerImpl {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         log: ReplicaLogger,
         hypervisor: Arc<Hypervisor>,
         own_subnet_id: SubnetId,
         own_subnet_type: SubnetType,
         subnet_memory_capacity: NumBytes,
+        query_execution_threads: usize,
+        max_query_concurrency_per_canister: usize,
+        max_query_batch_size: usize,
+        max_queued_queries_per_canister: usize,
         metrics_registry: &MetricsRegistry,
+        query_stats_collector: Arc<dyn QueryStatsCollector>,
     ) -> Self {
-        let pool = rayon::ThreadPoolBuilder::new()
-            .num_threads(QUERY_EXECUTION_THREADS)
-            .thread_name(|idx| format!("query_execution thread index {}", idx))
-            .stack_size(8_192_000)
-            .build()
-            .unwrap();
+        let internal = Arc::new(InternalHttpQueryHandlerImpl::new(
+            log,
+            hypervisor,
+            own_subnet_id,
+            own_subnet_type,
+            subnet_memory_capacity,
+            metrics_registry,
+            query_stats_collector,
+        ));
+        let executor = Arc::clone(&internal);
+        let scheduler = QueryScheduler::new(
+            query_execution_threads,
+            max_query_concurrency_per_canister,
+            max_query_batch_size,
+            max_queued_queries_per_canister,
+            Box::new(move |query, state, data_certificate| {
+                executor.query(query, state, data_certificate)
+            }),
+        );
 
         Self {
-            internal: Arc::new(InternalHttpQueryHandlerImpl::new(
-                log,
-                hypervisor,
-                own_subnet_id,
-                own_subnet_type,
-                subnet_memory_capacity,
-                metrics_registry,
-            )),
-            threadpool: pool,
+            internal,
+            scheduler,
         }
     }
 }
@@ -143,10 +164,7 @@ impl QueryHandler for HttpQueryHandlerImpl {
         data_certificate: Vec<u8>,
         callback: Box<dyn FnOnce(Result<WasmResult, UserError>) + Send + 'static>,
     ) {
-        let internal = Arc::clone(&self.internal);
-        self.threadpool.spawn(move || {
-            let v = internal.query(query, state, data_certificate);
-            callback(v);
-        });
+        self.scheduler
+            .schedule(query, state, data_certificate, callback);
     }
 }
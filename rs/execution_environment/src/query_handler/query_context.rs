@@ -41,21 +41,23 @@ use crate::{
     metrics::{MeasurementScope, QueryHandlerMetrics},
     QueryExecutionType,
 };
-use ic_interfaces::execution_environment::{
-    HypervisorError, HypervisorResult, SubnetAvailableMemory,
+use ic_interfaces::{
+    execution_environment::{HypervisorError, HypervisorResult, SubnetAvailableMemory},
+    query_stats::QueryStatsCollector,
 };
 use ic_logger::{debug, fatal, ReplicaLogger};
 use ic_registry_routing_table::RoutingTable;
 use ic_registry_subnet_type::SubnetType;
 use ic_replicated_state::{CallContextAction, CallOrigin, CanisterState, ReplicatedState};
 use ic_types::{
+    consensus::query_stats::QueryStats,
     ingress::WasmResult,
     messages::{
         CallContextId, CallbackId, Payload, RejectContext, Request, RequestOrResponse, Response,
         UserQuery,
     },
     user_error::{ErrorCode, RejectCode, UserError},
-    CanisterId, Cycles, NumMessages, PrincipalId, QueryAllocation, SubnetId,
+    CanisterId, Cycles, NumInstructions, NumMessages, PrincipalId, QueryAllocation, SubnetId,
 };
 use std::{
     collections::BTreeMap,
@@ -107,6 +109,7 @@ pub(super) struct QueryContext<'a> {
     outstanding_response: Option<Response>,
     query_allocations_used: Arc<RwLock<QueryAllocationsUsed>>,
     subnet_available_memory: SubnetAvailableMemory,
+    query_stats_collector: &'a dyn QueryStatsCollector,
 }
 
 impl<'a> QueryContext<'a> {
@@ -120,6 +123,7 @@ impl<'a> QueryContext<'a> {
         data_certificate: Vec<u8>,
         query_allocations_used: Arc<RwLock<QueryAllocationsUsed>>,
         subnet_available_memory: SubnetAvailableMemory,
+        query_stats_collector: &'a dyn QueryStatsCollector,
     ) -> Self {
         let routing_table = Arc::new(state.metadata.network_topology.routing_table.clone());
         Self {
@@ -135,6 +139,7 @@ impl<'a> QueryContext<'a> {
             query_allocations_used,
             routing_table,
             subnet_available_memory,
+            query_stats_collector,
         }
     }
 
@@ -384,6 +389,12 @@ impl<'a> QueryContext<'a> {
                 &canister,
                 QueryAllocation::from(instructions_executed),
             );
+        self.register_query_stats(
+            canister.canister_id(),
+            instructions_executed,
+            method_payload.len(),
+            &result,
+        );
         (canister, result)
     }
 
@@ -421,6 +432,7 @@ impl<'a> QueryContext<'a> {
             .call_origin(callback.call_context_id)
             .unwrap();
         let call_context_id = callback.call_context_id;
+        let payload_size = response.response_payload.size_of().get() as usize;
 
         // We do not support inter canister queries between subnets so
         // we can use nominal values for these fields to satisfy the
@@ -458,9 +470,40 @@ impl<'a> QueryContext<'a> {
                 &canister,
                 QueryAllocation::from(instructions_executed),
             );
+        self.register_query_stats(
+            canister.canister_id(),
+            instructions_executed,
+            payload_size,
+            &execution_result,
+        );
         (canister, call_context_id, call_origin, execution_result)
     }
 
+    // Records the statistics of a query or callback executed on the given
+    // canister, to be reported to consensus.
+    fn register_query_stats(
+        &self,
+        canister_id: CanisterId,
+        instructions_executed: NumInstructions,
+        ingress_payload_size: usize,
+        result: &HypervisorResult<Option<WasmResult>>,
+    ) {
+        let egress_payload_size = match result {
+            Ok(Some(WasmResult::Reply(data))) => data.len(),
+            Ok(Some(WasmResult::Reject(message))) => message.len(),
+            Ok(None) | Err(_) => 0,
+        };
+        self.query_stats_collector.register_query(
+            canister_id,
+            QueryStats {
+                num_calls: 1,
+                num_instructions: instructions_executed.get(),
+                ingress_payload_size: ingress_payload_size as u64,
+                egress_payload_size: egress_payload_size as u64,
+            },
+        );
+    }
+
     // Loads a fresh version of the canister from the state and ensures that it
     // has a call context manager i.e. it is not stopped.
     fn get_canister_from_state(
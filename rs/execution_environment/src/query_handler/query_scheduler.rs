@@ -0,0 +1,407 @@
+//! Schedules the execution of queries on a pool of worker threads.
+//!
+//! Queries to the same canister that arrive while the canister is busy are
+//! grouped into a batch, which is executed sequentially on one worker against
+//! a single state snapshot. A query only joins a batch that is executed
+//! against the same snapshot, i.e. the snapshot the query was sent with, so
+//! that the results do not change. Only the batch holds a reference to the
+//! snapshot, so that a burst of queries does not keep a reference per query.
+//! A batch holds at most `max_batch_size` queries, so that the queries to a
+//! popular canister are spread over several batches, which may be executed
+//! on different workers.
+//!
+//! At most `max_concurrency_per_canister` batches of a canister are executed at
+//! the same time, such that a single busy canister cannot occupy all workers.
+//! The batches of a canister are executed in the order in which they were
+//! created. At most `max_queued_queries_per_canister` queries of a canister
+//! wait for their execution; further queries are rejected right away, so that
+//! the queue of a canister that receives more queries than it can execute
+//! does not grow without bound.
+
+use ic_types::{
+    ingress::WasmResult,
+    messages::UserQuery,
+    user_error::{ErrorCode, UserError},
+    CanisterId,
+};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+/// Called with the result of a query.
+pub(crate) type QueryCallback = Box<dyn FnOnce(Result<WasmResult, UserError>) + Send + 'static>;
+
+/// Executes a query against a state snapshot and a data certificate.
+pub(crate) type QueryExecutor<State> =
+    dyn Fn(UserQuery, Arc<State>, Vec<u8>) -> Result<WasmResult, UserError> + Send + Sync;
+
+/// Queries executed against the same snapshot.
+struct Batch<State> {
+    state: Arc<State>,
+    data_certificate: Vec<u8>,
+    queries: Vec<(UserQuery, QueryCallback)>,
+}
+
+/// The batches of a canister that are waiting to be executed.
+struct CanisterQueue<State> {
+    running: usize,
+    batches: VecDeque<Batch<State>>,
+    /// The number of queries in `batches`.
+    queued: usize,
+}
+
+impl<State> Default for CanisterQueue<State> {
+    fn default() -> Self {
+        Self {
+            running: 0,
+            batches: VecDeque::new(),
+            queued: 0,
+        }
+    }
+}
+
+struct Inner<State> {
+    threadpool: rayon::ThreadPool,
+    max_concurrency_per_canister: usize,
+    max_batch_size: usize,
+    max_queued_queries_per_canister: usize,
+    execute: Box<QueryExecutor<State>>,
+    queues: Mutex<BTreeMap<CanisterId, CanisterQueue<State>>>,
+}
+
+/// Groups queries into batches and executes them on a pool of worker threads.
+pub(crate) struct QueryScheduler<State> {
+    inner: Arc<Inner<State>>,
+}
+
+impl<State: Send + Sync + 'static> QueryScheduler<State> {
+    /// Creates a scheduler executing queries with `execute` on `num_threads`
+    /// worker threads, running at most `max_concurrency_per_canister` batches
+    /// of a canister at the same time, each of at most `max_batch_size`
+    /// queries, and queueing at most `max_queued_queries_per_canister`
+    /// queries of a canister.
+    pub(crate) fn new(
+        num_threads: usize,
+        max_concurrency_per_canister: usize,
+        max_batch_size: usize,
+        max_queued_queries_per_canister: usize,
+        execute: Box<QueryExecutor<State>>,
+    ) -> Self {
+        let threadpool = rayon::ThreadPoolBuilder::new()
+            .num_threads(num_threads)
+            .thread_name(|idx| format!("query_execution thread index {}", idx))
+            .stack_size(8_192_000)
+            .build()
+            .unwrap();
+        Self {
+            inner: Arc::new(Inner {
+                threadpool,
+                max_concurrency_per_canister: max_concurrency_per_canister.max(1),
+                max_batch_size: max_batch_size.max(1),
+                max_queued_queries_per_canister: max_queued_queries_per_canister.max(1),
+                execute,
+                queues: Mutex::new(BTreeMap::new()),
+            }),
+        }
+    }
+
+    /// Schedules the execution of the query against the given snapshot. The
+    /// callback is called with the result on a worker thread, or right away
+    /// with an error if too many queries to the canister are queued.
+    pub(crate) fn schedule(
+        &self,
+        query: UserQuery,
+        state: Arc<State>,
+        data_certificate: Vec<u8>,
+        callback: QueryCallback,
+    ) {
+        let canister_id = query.receiver;
+        {
+            let mut queues = self.inner.queues.lock().unwrap();
+            let queue = queues.entry(canister_id).or_default();
+            if queue.queued >= self.inner.max_queued_queries_per_canister {
+                drop(queues);
+                callback(Err(UserError::new(
+                    ErrorCode::SubnetOversubscribed,
+                    format!(
+                        "Canister {} has too many queued queries, please try again later.",
+                        canister_id
+                    ),
+                )));
+                return;
+            }
+            queue.queued += 1;
+            match queue.batches.back_mut() {
+                Some(batch)
+                    if batch.queries.len() < self.inner.max_batch_size
+                        && Arc::ptr_eq(&batch.state, &state)
+                        && batch.data_certificate == data_certificate =>
+                {
+                    batch.queries.push((query, callback))
+                }
+                _ => queue.batches.push_back(Batch {
+                    state,
+                    data_certificate,
+                    queries: vec![(query, callback)],
+                }),
+            }
+        }
+        Inner::dispatch(&self.inner, canister_id);
+    }
+}
+
+impl<State: Send + Sync + 'static> Inner<State> {
+    /// Starts the execution of batches of the canister, as long as the
+    /// canister has queued batches and its concurrency limit is not reached.
+    fn dispatch(inner: &Arc<Self>, canister_id: CanisterId) {
+        let mut queues = inner.queues.lock().unwrap();
+        let queue = match queues.get_mut(&canister_id) {
+            Some(queue) => queue,
+            None => return,
+        };
+        while queue.running < inner.max_concurrency_per_canister {
+            let batch = match queue.batches.pop_front() {
+                Some(batch) => batch,
+                None => break,
+            };
+            queue.running += 1;
+            queue.queued -= batch.queries.len();
+            let inner_clone = Arc::clone(inner);
+            inner
+                .threadpool
+                .spawn(move || inner_clone.execute_batch(canister_id, batch));
+        }
+        if queue.running == 0 && queue.batches.is_empty() {
+            queues.remove(&canister_id);
+        }
+    }
+
+    fn execute_batch(self: Arc<Self>, canister_id: CanisterId, batch: Batch<State>) {
+        let Batch {
+            state,
+            data_certificate,
+            queries,
+        } = batch;
+        for (query, callback) in queries {
+            callback((self.execute)(
+                query,
+                Arc::clone(&state),
+                data_certificate.clone(),
+            ));
+        }
+        // Release the snapshot before the next batch of the canister starts.
+        drop(state);
+        if let Some(queue) = self.queues.lock().unwrap().get_mut(&canister_id) {
+            queue.running -= 1;
+        }
+        Self::dispatch(&self, canister_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_test_utilities::types::ids::{canister_test_id, user_test_id};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    fn query(canister_id: CanisterId, method_name: &str) -> UserQuery {
+        UserQuery {
+            source: user_test_id(1),
+            receiver: canister_id,
+            method_name: method_name.to_string(),
+            method_payload: vec![],
+            ingress_expiry: 0,
+            nonce: None,
+        }
+    }
+
+    fn send_result_to(sender: &mpsc::Sender<Result<WasmResult, UserError>>) -> QueryCallback {
+        let sender = sender.clone();
+        Box::new(move |result| sender.send(result).unwrap())
+    }
+
+    #[test]
+    fn queued_queries_share_one_snapshot() {
+        let (unblock_sender, unblock_receiver) = mpsc::channel::<()>();
+        let unblock_receiver = Mutex::new(unblock_receiver);
+        let scheduler = QueryScheduler::<String>::new(
+            2,
+            1,
+            10,
+            10,
+            Box::new(move |query, state, _| {
+                if query.method_name == "block" {
+                    unblock_receiver.lock().unwrap().recv().unwrap();
+                }
+                Ok(WasmResult::Reply(state.as_bytes().to_vec()))
+            }),
+        );
+        let (sender, receiver) = mpsc::channel();
+        let canister_id = canister_test_id(1);
+        scheduler.schedule(
+            query(canister_id, "block"),
+            Arc::new("old".to_string()),
+            vec![],
+            send_result_to(&sender),
+        );
+
+        let state = Arc::new("new".to_string());
+        for _ in 0..3 {
+            scheduler.schedule(
+                query(canister_id, "read"),
+                Arc::clone(&state),
+                vec![],
+                send_result_to(&sender),
+            );
+        }
+        // The queued queries hold a single reference to the snapshot.
+        assert_eq!(Arc::strong_count(&state), 2);
+
+        unblock_sender.send(()).unwrap();
+        let results: Vec<_> = (0..4).map(|_| receiver.recv().unwrap()).collect();
+        assert_eq!(results[0], Ok(WasmResult::Reply(b"old".to_vec())));
+        for result in &results[1..] {
+            assert_eq!(*result, Ok(WasmResult::Reply(b"new".to_vec())));
+        }
+    }
+
+    #[test]
+    fn concurrency_per_canister_is_limited() {
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+        let (running_clone, max_running_clone) = (Arc::clone(&running), Arc::clone(&max_running));
+        let scheduler = QueryScheduler::<()>::new(
+            4,
+            2,
+            10,
+            10,
+            Box::new(move |_, _, _| {
+                let now_running = running_clone.fetch_add(1, Ordering::SeqCst) + 1;
+                max_running_clone.fetch_max(now_running, Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(10));
+                running_clone.fetch_sub(1, Ordering::SeqCst);
+                Ok(WasmResult::Reply(vec![]))
+            }),
+        );
+        let (sender, receiver) = mpsc::channel();
+        for _ in 0..8 {
+            // Every query is sent with its own snapshot, so that no two queries
+            // are batched.
+            scheduler.schedule(
+                query(canister_test_id(1), "read"),
+                Arc::new(()),
+                vec![],
+                send_result_to(&sender),
+            );
+        }
+        for _ in 0..8 {
+            assert_eq!(receiver.recv().unwrap(), Ok(WasmResult::Reply(vec![])));
+        }
+        assert!(max_running.load(Ordering::SeqCst) <= 2);
+    }
+
+    /// Returns a scheduler with one worker, whose queries block until a
+    /// message is sent to the returned sender, and reply with the snapshot.
+    fn blocking_scheduler(
+        max_batch_size: usize,
+        max_queued_queries_per_canister: usize,
+    ) -> (QueryScheduler<String>, mpsc::Sender<()>) {
+        let (unblock_sender, unblock_receiver) = mpsc::channel::<()>();
+        let unblock_receiver = Mutex::new(unblock_receiver);
+        let scheduler = QueryScheduler::<String>::new(
+            1,
+            1,
+            max_batch_size,
+            max_queued_queries_per_canister,
+            Box::new(move |query, state, _| {
+                if query.method_name == "block" {
+                    unblock_receiver.lock().unwrap().recv().unwrap();
+                }
+                Ok(WasmResult::Reply(state.as_bytes().to_vec()))
+            }),
+        );
+        (scheduler, unblock_sender)
+    }
+
+    #[test]
+    fn batches_are_capped() {
+        let (scheduler, unblock_sender) = blocking_scheduler(2, 10);
+        let (sender, receiver) = mpsc::channel();
+        let canister_id = canister_test_id(1);
+        let state = Arc::new("state".to_string());
+        scheduler.schedule(
+            query(canister_id, "block"),
+            Arc::clone(&state),
+            vec![],
+            send_result_to(&sender),
+        );
+        for _ in 0..5 {
+            scheduler.schedule(
+                query(canister_id, "read"),
+                Arc::clone(&state),
+                vec![],
+                send_result_to(&sender),
+            );
+        }
+
+        // The blocking query is executed right away, the others are queued.
+        let batch_sizes: Vec<_> = scheduler.inner.queues.lock().unwrap()[&canister_id]
+            .batches
+            .iter()
+            .map(|batch| batch.queries.len())
+            .collect();
+        assert_eq!(batch_sizes, vec![2, 2, 1]);
+
+        unblock_sender.send(()).unwrap();
+        for _ in 0..6 {
+            assert_eq!(
+                receiver.recv().unwrap(),
+                Ok(WasmResult::Reply(b"state".to_vec()))
+            );
+        }
+    }
+
+    #[test]
+    fn queries_beyond_the_queue_limit_are_rejected() {
+        let (scheduler, unblock_sender) = blocking_scheduler(10, 2);
+        let (sender, receiver) = mpsc::channel();
+        let canister_id = canister_test_id(1);
+        let state = Arc::new("state".to_string());
+        scheduler.schedule(
+            query(canister_id, "block"),
+            Arc::clone(&state),
+            vec![],
+            send_result_to(&sender),
+        );
+        for _ in 0..3 {
+            scheduler.schedule(
+                query(canister_id, "read"),
+                Arc::clone(&state),
+                vec![],
+                send_result_to(&sender),
+            );
+        }
+        // The third query is rejected right away.
+        assert_eq!(
+            receiver.recv().unwrap().unwrap_err().code(),
+            ErrorCode::SubnetOversubscribed
+        );
+        // Queries to other canisters are not affected.
+        let other_canister_id = canister_test_id(2);
+        scheduler.schedule(
+            query(other_canister_id, "read"),
+            Arc::clone(&state),
+            vec![],
+            send_result_to(&sender),
+        );
+
+        unblock_sender.send(()).unwrap();
+        for _ in 0..4 {
+            assert_eq!(
+                receiver.recv().unwrap(),
+                Ok(WasmResult::Reply(b"state".to_vec()))
+            );
+        }
+    }
+}
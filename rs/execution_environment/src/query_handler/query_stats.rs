@@ -0,0 +1,66 @@
+//! Collects the statistics of the queries executed by this replica until
+//! they are reported.
+
+use ic_interfaces::query_stats::{QueryStatsCollector, QueryStatsReader};
+use ic_types::{consensus::query_stats::QueryStats, CanisterId};
+use std::{collections::BTreeMap, sync::Mutex};
+
+/// Implements both the `QueryStatsCollector` used by the query handler and
+/// the `QueryStatsReader` used by the query statistics component of
+/// consensus.
+#[derive(Default)]
+pub(crate) struct QueryStatsCollectorImpl {
+    stats: Mutex<BTreeMap<CanisterId, QueryStats>>,
+}
+
+impl QueryStatsCollector for QueryStatsCollectorImpl {
+    fn register_query(&self, canister_id: CanisterId, stats: QueryStats) {
+        self.stats
+            .lock()
+            .unwrap()
+            .entry(canister_id)
+            .or_default()
+            .saturating_accumulate(&stats);
+    }
+}
+
+impl QueryStatsReader for QueryStatsCollectorImpl {
+    fn take_stats(&self) -> BTreeMap<CanisterId, QueryStats> {
+        std::mem::take(&mut *self.stats.lock().unwrap())
+    }
+
+    fn return_stats(&self, stats: BTreeMap<CanisterId, QueryStats>) {
+        for (canister_id, stats) in stats {
+            self.register_query(canister_id, stats);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_test_utilities::types::ids::canister_test_id;
+
+    #[test]
+    fn stats_are_accumulated_until_taken() {
+        let collector = QueryStatsCollectorImpl::default();
+        let stats = QueryStats {
+            num_calls: 1,
+            num_instructions: 100,
+            ingress_payload_size: 10,
+            egress_payload_size: 20,
+        };
+        collector.register_query(canister_test_id(1), stats.clone());
+        collector.register_query(canister_test_id(1), stats);
+
+        let taken = collector.take_stats();
+        assert_eq!(taken.len(), 1);
+        assert_eq!(taken[&canister_test_id(1)].num_calls, 2);
+        assert_eq!(taken[&canister_test_id(1)].num_instructions, 200);
+        assert!(collector.take_stats().is_empty());
+
+        collector.register_query(canister_test_id(1), QueryStats::default());
+        collector.return_stats(taken);
+        assert_eq!(collector.take_stats()[&canister_test_id(1)].num_calls, 2);
+    }
+}
@@ -2,7 +2,7 @@ use crate::{
     canister_manager::{CanisterManager, CanisterMgrConfig},
     canister_settings::CanisterSettings,
     hypervisor::Hypervisor,
-    HttpQueryHandlerImpl, IngressHistoryWriterImpl,
+    HttpQueryHandlerImpl, IngressHistoryWriterImpl, QueryStatsCollectorImpl,
 };
 use ic_base_types::NumSeconds;
 use ic_config::execution_environment::Config;
@@ -92,7 +92,12 @@ where
             subnet_id,
             subnet_type,
             MEMORY_CAPACITY,
+            1,
+            1,
+            1,
+            1,
             &metrics_registry,
+            Arc::new(QueryStatsCollectorImpl::default()),
         );
         f(query_handler, canister_manager, state);
     });
@@ -1,5 +1,6 @@
 use ic_execution_environment::{IngressHistoryReaderImpl, IngressHistoryWriterImpl};
 use ic_interfaces::{
+    consensus_pool::HeightWatch,
     execution_environment::{IngressHistoryReader, IngressHistoryWriter},
     state_manager::Labeled,
 };
@@ -44,6 +45,114 @@ fn get_status_for_non_existing_message_id() {
     assert_eq!(history, IngressStatus::Unknown);
 }
 
+#[test]
+fn subscribers_are_notified_of_terminal_status() {
+    let message_id = message_test_id(1);
+    let state_with_status = |status: IngressStatus| {
+        let mut state = ReplicatedState::new_rooted_at(
+            subnet_test_id(1),
+            SubnetType::Application,
+            "NOT_USED".into(),
+        );
+        state.set_ingress_status(message_id.clone(), status);
+        std::sync::Arc::new(state)
+    };
+    let height = std::sync::Arc::new(std::sync::atomic::AtomicU64::new(1));
+    let certified_state =
+        std::sync::Arc::new(std::sync::Mutex::new(state_with_status(processing())));
+
+    let certified_height = std::sync::Arc::new(HeightWatch::new(Height::new(1)));
+
+    let mut state_manager = MockStateManager::new();
+    let current_height = std::sync::Arc::clone(&height);
+    state_manager
+        .expect_latest_certified_height()
+        .returning(move || Height::new(current_height.load(std::sync::atomic::Ordering::SeqCst)));
+    let watch = std::sync::Arc::clone(&certified_height);
+    state_manager
+        .expect_subscribe_certified_height()
+        .returning(move || watch.subscribe());
+    let current_state = std::sync::Arc::clone(&certified_state);
+    state_manager
+        .expect_get_state_at()
+        .returning(move |height| {
+            Ok(Labeled::new(
+                height,
+                std::sync::Arc::clone(&current_state.lock().unwrap()),
+            ))
+        });
+    let ingress_history_reader = IngressHistoryReaderImpl::new(std::sync::Arc::new(state_manager));
+
+    let mut receiver = ingress_history_reader.subscribe_to_terminal_status(message_id.clone());
+    assert!(receiver.try_recv().is_err());
+
+    // The subscriber is notified once the certified state has the terminal
+    // status.
+    *certified_state.lock().unwrap() = state_with_status(completed());
+    height.store(2, std::sync::atomic::Ordering::SeqCst);
+    certified_height.update(Height::new(2));
+    assert_eq!(futures::executor::block_on(receiver), Ok(completed()));
+
+    // Subscriptions to messages that are already terminal resolve right away.
+    let mut receiver = ingress_history_reader.subscribe_to_terminal_status(message_id);
+    assert_eq!(receiver.try_recv().unwrap(), completed());
+}
+
+#[test]
+fn subscribers_receive_status_transitions() {
+    let message_id = message_test_id(1);
+    let state_with_status = |status: IngressStatus| {
+        let mut state = ReplicatedState::new_rooted_at(
+            subnet_test_id(1),
+            SubnetType::Application,
+            "NOT_USED".into(),
+        );
+        state.set_ingress_status(message_id.clone(), status);
+        std::sync::Arc::new(state)
+    };
+    let height = std::sync::Arc::new(std::sync::atomic::AtomicU64::new(1));
+    let certified_state = std::sync::Arc::new(std::sync::Mutex::new(state_with_status(received())));
+
+    let certified_height = std::sync::Arc::new(HeightWatch::new(Height::new(1)));
+
+    let mut state_manager = MockStateManager::new();
+    let current_height = std::sync::Arc::clone(&height);
+    state_manager
+        .expect_latest_certified_height()
+        .returning(move || Height::new(current_height.load(std::sync::atomic::Ordering::SeqCst)));
+    let watch = std::sync::Arc::clone(&certified_height);
+    state_manager
+        .expect_subscribe_certified_height()
+        .returning(move || watch.subscribe());
+    let current_state = std::sync::Arc::clone(&certified_state);
+    state_manager
+        .expect_get_state_at()
+        .returning(move |height| {
+            Ok(Labeled::new(
+                height,
+                std::sync::Arc::clone(&current_state.lock().unwrap()),
+            ))
+        });
+    let ingress_history_reader = IngressHistoryReaderImpl::new(std::sync::Arc::new(state_manager));
+
+    // The subscriber receives the current status right away.
+    let mut receiver = ingress_history_reader.subscribe_to_status_transitions(message_id);
+    let mut next_status = || futures::executor::block_on(receiver.recv());
+    assert_eq!(next_status(), Some(received()));
+
+    *certified_state.lock().unwrap() = state_with_status(processing());
+    height.store(2, std::sync::atomic::Ordering::SeqCst);
+    certified_height.update(Height::new(2));
+    assert_eq!(next_status(), Some(processing()));
+
+    // The subscription is closed after the terminal status.
+    *certified_state.lock().unwrap() = state_with_status(completed());
+    height.store(3, std::sync::atomic::Ordering::SeqCst);
+    certified_height.update(Height::new(3));
+    assert_eq!(next_status(), Some(completed()));
+    assert_eq!(next_status(), None);
+}
+
 fn received() -> IngressStatus {
     Received {
         receiver: canister_test_id(0).get(),
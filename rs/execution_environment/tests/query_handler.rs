@@ -34,7 +34,7 @@ fn query_non_existent() {
         let state = initial_state(tmpdir.path(), subnet_id);
         let metrics_registry = MetricsRegistry::new();
         let cycles_account_manager = Arc::new(CyclesAccountManagerBuilder::new().build());
-        let (_, _, query_handler, _) = setup_execution(
+        let (_, _, query_handler, _, _) = setup_execution(
             log,
             &metrics_registry,
             subnet_id,
@@ -3,7 +3,7 @@
 use crate::common;
 use hyper::{Body, Response, StatusCode};
 use ic_interfaces::consensus_pool::ConsensusPoolCache;
-use ic_types::consensus::catchup::CatchUpPackageParam;
+use ic_types::{consensus::catchup::CatchUpPackageParam, messages::from_cbor};
 
 /// Handles a call to /_/catch_up_package
 pub(crate) fn handle(
@@ -14,7 +14,7 @@ pub(crate) fn handle(
     if body.is_empty() {
         common::protobuf_response(&cup.protobuf)
     } else {
-        match serde_cbor::from_slice::<CatchUpPackageParam>(&body) {
+        match from_cbor::<CatchUpPackageParam>(&body) {
             Ok(param) => {
                 if CatchUpPackageParam::from(&cup.cup) > param {
                     common::protobuf_response(&cup.protobuf)
@@ -9,8 +9,8 @@ use ic_interfaces::state_manager::StateReader;
 use ic_logger::{info, ReplicaLogger};
 use ic_replicated_state::ReplicatedState;
 use ic_types::{
-    messages::{Blob, Certificate, CertificateDelegation, MessageId},
-    CanisterId,
+    messages::{to_canonical_cbor, Blob, Certificate, CertificateDelegation, MessageId},
+    CanisterId, Height, SubnetId,
 };
 use ic_validator::RequestValidationError;
 use prost::Message;
@@ -46,12 +46,9 @@ fn get_cors_headers() -> HeaderMap {
     headers
 }
 
-/// Convert an object into CBOR binary.
+/// Convert an object into canonical CBOR binary.
 pub(crate) fn into_cbor<R: Serialize>(r: &R) -> Vec<u8> {
-    let mut ser = serde_cbor::Serializer::new(Vec::new());
-    ser.self_describe().expect("Could not write magic tag.");
-    r.serialize(&mut ser).expect("Serialization failed.");
-    ser.into_inner()
+    to_canonical_cbor(r).expect("Serialization failed.")
 }
 
 /// Write the "self describing" CBOR tag and serialize the response
@@ -127,11 +124,46 @@ pub(crate) fn get_latest_certified_state(
         .map(|r| r.0)
 }
 
+/// Asks the state reader to precompute the witnesses of the paths that most
+/// `read_state` requests ask for: the time alone, which requests without
+/// paths read, and, on the NNS subnet, the public key and canister ranges of
+/// every subnet in `state`, which the subnets read to fetch their delegation.
+/// `read_state` adds the time to the requested paths, so it is part of every
+/// prefetched set of paths.
+pub(crate) fn prefetch_read_state_paths(
+    state_reader: &dyn StateReader<State = ReplicatedState>,
+    state: &ReplicatedState,
+    subnet_id: SubnetId,
+    nns_subnet_id: SubnetId,
+) {
+    let time = Path::from(Label::from("time"));
+    let mut prefetched_paths = vec![vec![time.clone()]];
+    if subnet_id == nns_subnet_id {
+        for subnet_id in state.metadata.network_topology.subnets.keys() {
+            let subnet_path = |label: &str| {
+                Path::new(vec![
+                    Label::from("subnet"),
+                    Label::from(subnet_id.get_ref()),
+                    Label::from(label),
+                ])
+            };
+            prefetched_paths.push(vec![
+                subnet_path("public_key"),
+                subnet_path("canister_ranges"),
+                time.clone(),
+            ]);
+        }
+    }
+    for mut paths in prefetched_paths {
+        state_reader.prefetch_certified_paths(sparse_labeled_tree_from_paths(&mut paths));
+    }
+}
+
 pub(crate) fn get_latest_certified_state_and_data_certificate(
     state_reader: &dyn StateReader<State = ReplicatedState>,
     delegation_from_nns: &Option<CertificateDelegation>,
     canister_id: CanisterId,
-) -> Option<(Arc<ReplicatedState>, Vec<u8>)> {
+) -> Option<(Arc<ReplicatedState>, Vec<u8>, Height)> {
     // The path to fetch the data certificate for the canister.
     let path = SubTree(flatmap! {
         label("canister") => SubTree(
@@ -156,6 +188,7 @@ pub(crate) fn get_latest_certified_state_and_data_certificate(
                     signature: Blob(cert.signed.signature.signature.get().0),
                     delegation: delegation_from_nns.clone(),
                 }),
+                cert.height,
             )
         })
 }
@@ -0,0 +1,65 @@
+//! Module that serves the timelines of the latest completed consensus rounds
+//! at /_/consensus_rounds, to diagnose slow rounds of a running replica.
+//!
+//! The timelines are returned as a JSON array, ordered by height. Each
+//! timeline holds the height of the round, its start and the completion times
+//! of its phases, in nanoseconds since the Unix epoch.
+
+use crate::common;
+use hyper::{header, Body, Response, StatusCode};
+use ic_interfaces::consensus::RoundTimelineReader;
+
+/// Handles a call to /_/consensus_rounds
+pub(crate) fn handle(round_timeline_reader: &dyn RoundTimelineReader) -> Response<Body> {
+    match serde_json::to_vec(&round_timeline_reader.get_round_timelines()) {
+        Ok(body) => {
+            let mut response = Response::new(Body::from(body));
+            response.headers_mut().insert(
+                header::CONTENT_TYPE,
+                header::HeaderValue::from_static("application/json"),
+            );
+            response
+        }
+        Err(err) => common::make_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Encoding the round timelines failed: {}", err),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ic_types::{
+        consensus::round_timeline::{RoundPhase, RoundTimeline},
+        time::Time,
+        Height,
+    };
+
+    struct FixedTimelines(Vec<RoundTimeline>);
+
+    impl RoundTimelineReader for FixedTimelines {
+        fn get_round_timelines(&self) -> Vec<RoundTimeline> {
+            self.0.clone()
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_round_timelines() {
+        let timeline = RoundTimeline {
+            height: Height::from(3),
+            start: Time::from_nanos_since_unix_epoch(10),
+            phases: vec![(
+                RoundPhase::Notarization,
+                Time::from_nanos_since_unix_epoch(25),
+            )]
+            .into_iter()
+            .collect(),
+        };
+        let response = handle(&FixedTimelines(vec![timeline.clone()]));
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let timelines: Vec<RoundTimeline> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(timelines, vec![timeline]);
+    }
+}
@@ -0,0 +1,133 @@
+//! Module that serves the structured event log of the replica at
+//! /_/event_log, so that the consensus-critical decisions of a running
+//! replica can be queried, e.g. `/_/event_log?kind=block_validated&limit=10`.
+//!
+//! The query parameters are the fields of `EventQuery`: `kind`, `since` (in
+//! nanoseconds since the Unix epoch) and `limit`. The matching records are
+//! returned as a JSON array. A query reads the journal from disk, so only one
+//! query is served at a time.
+
+use crate::common;
+use hyper::{header, Body, Response, StatusCode};
+use ic_event_log::{EventLog, EventQuery};
+use ic_types::time::Time;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+/// Handles a call to /_/event_log
+pub(crate) async fn handle(
+    event_log: &EventLog,
+    running_queries: Arc<Semaphore>,
+    query: Option<&str>,
+) -> Response<Body> {
+    let event_query = match parse_query(query) {
+        Ok(event_query) => event_query,
+        Err(err) => return common::make_response(StatusCode::BAD_REQUEST, &err),
+    };
+    let _permit = match running_queries.try_acquire_owned() {
+        Ok(permit) => permit,
+        Err(_) => {
+            return common::make_response(
+                StatusCode::TOO_MANY_REQUESTS,
+                "Another event log query is running. Please try again later.",
+            )
+        }
+    };
+
+    let event_log = event_log.clone();
+    let records = match tokio::task::spawn_blocking(move || event_log.query(&event_query)).await {
+        Ok(Ok(records)) => records,
+        Ok(Err(err)) => {
+            return common::make_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Reading the event log failed: {}", err),
+            )
+        }
+        Err(err) => {
+            return common::make_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("The event log query panicked: {}", err),
+            )
+        }
+    };
+
+    match serde_json::to_vec(&records) {
+        Ok(body) => {
+            let mut response = Response::new(Body::from(body));
+            response.headers_mut().insert(
+                header::CONTENT_TYPE,
+                header::HeaderValue::from_static("application/json"),
+            );
+            response
+        }
+        Err(err) => common::make_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Encoding the event log records failed: {}", err),
+        ),
+    }
+}
+
+/// Parses the query string of the request into an event query.
+fn parse_query(query: Option<&str>) -> Result<EventQuery, String> {
+    let mut event_query = EventQuery::default();
+    for param in query
+        .unwrap_or("")
+        .split('&')
+        .filter(|param| !param.is_empty())
+    {
+        let (key, value) = match param.find('=') {
+            Some(i) => (&param[..i], &param[i + 1..]),
+            None => (param, ""),
+        };
+        match key {
+            "kind" => event_query.kind = Some(value.to_string()),
+            "since" => {
+                let nanos = value
+                    .parse()
+                    .map_err(|err| format!("Invalid since {}: {}", value, err))?;
+                event_query.since = Some(Time::from_nanos_since_unix_epoch(nanos));
+            }
+            "limit" => {
+                let limit = value
+                    .parse()
+                    .map_err(|err| format!("Invalid limit {}: {}", value, err))?;
+                event_query.limit = Some(limit);
+            }
+            _ => return Err(format!("Unknown query parameter {}", key)),
+        }
+    }
+    Ok(event_query)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_event_queries() {
+        assert_eq!(parse_query(None), Ok(EventQuery::default()));
+        assert_eq!(
+            parse_query(Some("kind=block_validated&since=7&limit=10")),
+            Ok(EventQuery {
+                kind: Some("block_validated".to_string()),
+                since: Some(Time::from_nanos_since_unix_epoch(7)),
+                limit: Some(10),
+            })
+        );
+        assert!(parse_query(Some("limit=ten")).is_err());
+        assert!(parse_query(Some("height=1")).is_err());
+    }
+
+    #[tokio::test]
+    async fn serves_one_query_at_a_time() {
+        let running_queries = Arc::new(Semaphore::new(1));
+        let response = handle(&EventLog::disabled(), Arc::clone(&running_queries), None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"[]");
+
+        let _running = Arc::clone(&running_queries).try_acquire_owned().unwrap();
+        let response = handle(&EventLog::disabled(), Arc::clone(&running_queries), None).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+}
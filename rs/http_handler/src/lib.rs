@@ -7,9 +7,14 @@
 /// Specification](https://sdk.dfinity.org/docs/interface-spec/index.html)
 mod catch_up_package;
 mod common;
+mod consensus_rounds;
 mod dashboard;
+mod event_log;
 mod metrics;
+mod pool_dump;
+mod query_cache;
 mod read;
+mod request_status;
 mod status;
 mod submit;
 mod types;
@@ -17,16 +22,18 @@ mod types;
 use crate::types::*;
 use futures_util::stream::StreamExt;
 use hyper::{server::conn::Http, service::service_fn};
-use hyper::{Body, Request, Response, StatusCode};
+use hyper::{Body, Request, Response, StatusCode, Uri};
 use ic_base_thread::ObservableCountingSemaphore;
 use ic_config::http_handler::Config;
 use ic_crypto_tls_interfaces::{AllowedClients, SomeOrAllNodes, TlsHandshake};
 use ic_crypto_tree_hash::Path;
-use ic_interfaces::execution_environment::IngressMessageFilter;
+use ic_event_log::EventLog;
+use ic_interfaces::execution_environment::{IngressHistoryReader, IngressMessageFilter};
 use ic_interfaces::{
+    artifact_pool::PoolDumpReader, consensus::RoundTimelineReader,
     consensus_pool::ConsensusPoolCache, crypto::IngressSigVerifier,
-    execution_environment::QueryHandler, p2p::IngressEventHandler, registry::RegistryClient,
-    state_manager::StateReader,
+    execution_environment::QueryHandler, health::ReplicaHealthAssessor, p2p::IngressEventHandler,
+    registry::RegistryClient, state_manager::StateReader,
 };
 use ic_logger::{debug, error, fatal, info, warn, ReplicaLogger};
 use ic_metrics::MetricsRegistry;
@@ -34,15 +41,17 @@ use ic_registry_subnet_type::SubnetType;
 use ic_replicated_state::{NodeTopology, ReplicatedState};
 use ic_types::{
     malicious_flags::MaliciousFlags,
+    malicious_strategies::{MaliciousBehaviors, MaliciousComponent},
     messages::CertificateDelegation,
     messages::{
-        Blob, HttpReadContent, HttpReadState, HttpReadStateResponse, HttpRequestEnvelope,
-        ReplicaHealthStatus,
+        from_cbor, to_canonical_cbor, Blob, HttpReadContent, HttpReadState, HttpReadStateResponse,
+        HttpRequestEnvelope, ReplicaHealthStatus,
     },
     time::current_time_and_expiry_time,
     SubnetId,
 };
 use metrics::HttpHandlerMetrics;
+use query_cache::QueryCache;
 use rand::Rng;
 use std::collections::HashSet;
 use std::convert::Infallible;
@@ -54,7 +63,10 @@ use std::sync::RwLock;
 use std::time::Duration;
 use tempfile::NamedTempFile;
 
-use tokio::{net::TcpListener, net::TcpStream, time::timeout, time::timeout_at, time::Instant};
+use tokio::{
+    net::TcpListener, net::TcpStream, sync::Semaphore, time::timeout, time::timeout_at,
+    time::Instant,
+};
 
 // Constants defining the limits of the HttpHandler.
 
@@ -99,12 +111,26 @@ struct HttpHandler {
     state_reader: Arc<dyn StateReader<State = ReplicatedState>>,
     validator: Arc<dyn IngressSigVerifier + Send + Sync>,
     consensus_pool_cache: Arc<dyn ConsensusPoolCache>,
+    replica_health_assessor: Arc<dyn ReplicaHealthAssessor>,
     ingress_message_filter: Arc<dyn IngressMessageFilter<State = ReplicatedState>>,
+    ingress_history_reader: Arc<dyn IngressHistoryReader>,
+    query_cache: Option<QueryCache>,
     subnet_type: SubnetType,
+    // The open server-sent event streams of request statuses.
+    request_status_streams: Arc<Semaphore>,
+    event_log: EventLog,
+    // The running queries of the event log.
+    event_log_queries: Arc<Semaphore>,
+    round_timeline_reader: Arc<dyn RoundTimelineReader>,
+    pool_dump_reader: Arc<dyn PoolDumpReader>,
+    // The running dumps of the artifact pools.
+    pool_dumps: Arc<Semaphore>,
 
     delegation_from_nns: Arc<RwLock<Option<CertificateDelegation>>>,
     health_status: Arc<RwLock<ReplicaHealthStatus>>,
-    malicious_flags: MaliciousFlags,
+    // The requests are validated like the ingress manager validates ingress
+    // messages, so the handler is subject to the same malicious behaviors.
+    ingress_behaviors: MaliciousBehaviors,
 }
 
 // Crates a detached tokio blocking task that initializes the server (reading
@@ -131,6 +157,14 @@ fn start_server_initialization(
             std::thread::sleep(Duration::from_secs(1));
         }
         info!(log, "Certified state is now available.");
+        if let Some(state) = common::get_latest_certified_state(state_reader.as_ref()) {
+            common::prefetch_read_state_paths(
+                state_reader.as_ref(),
+                &state,
+                subnet_id,
+                nns_subnet_id,
+            );
+        }
         // Fetch the delegation from the NNS for this subnet to be
         // able to issue certificates.
         *health_status.write().unwrap() = ReplicaHealthStatus::WaitingForRootDelegation;
@@ -209,11 +243,19 @@ pub async fn start_server(
     nns_subnet_id: SubnetId,
     log: ReplicaLogger,
     consensus_pool_cache: Arc<dyn ConsensusPoolCache>,
+    replica_health_assessor: Arc<dyn ReplicaHealthAssessor>,
     ingress_message_filter: Arc<dyn IngressMessageFilter<State = ReplicatedState>>,
+    ingress_history_reader: Arc<dyn IngressHistoryReader>,
     subnet_type: SubnetType,
+    event_log: EventLog,
+    round_timeline_reader: Arc<dyn RoundTimelineReader>,
+    pool_dump_reader: Arc<dyn PoolDumpReader>,
     malicious_flags: MaliciousFlags,
 ) -> Result<(), Error> {
     let metrics = Arc::new(HttpHandlerMetrics::new(&metrics_registry));
+    let query_cache = config
+        .query_cache_capacity_bytes
+        .map(|capacity_bytes| QueryCache::new(&metrics_registry, capacity_bytes));
 
     let http_handler = Arc::new(HttpHandler::new(
         config,
@@ -228,7 +270,13 @@ pub async fn start_server(
         state_reader,
         ingress_verifier,
         consensus_pool_cache,
+        replica_health_assessor,
         ingress_message_filter,
+        ingress_history_reader,
+        query_cache,
+        event_log,
+        round_timeline_reader,
+        pool_dump_reader,
         malicious_flags,
     ));
 
@@ -362,7 +410,13 @@ impl HttpHandler {
         state_reader: Arc<dyn StateReader<State = ReplicatedState>>,
         validator: Arc<dyn IngressSigVerifier + Send + Sync>,
         consensus_pool_cache: Arc<dyn ConsensusPoolCache>,
+        replica_health_assessor: Arc<dyn ReplicaHealthAssessor>,
         ingress_message_filter: Arc<dyn IngressMessageFilter<State = ReplicatedState>>,
+        ingress_history_reader: Arc<dyn IngressHistoryReader>,
+        query_cache: Option<QueryCache>,
+        event_log: EventLog,
+        round_timeline_reader: Arc<dyn RoundTimelineReader>,
+        pool_dump_reader: Arc<dyn PoolDumpReader>,
         malicious_flags: MaliciousFlags,
     ) -> Self {
         Self {
@@ -378,10 +432,22 @@ impl HttpHandler {
             state_reader,
             validator,
             consensus_pool_cache,
+            replica_health_assessor,
             ingress_message_filter,
+            ingress_history_reader,
+            query_cache,
+            request_status_streams: Arc::new(Semaphore::new(
+                request_status::MAX_CONCURRENT_STREAMS,
+            )),
+            event_log,
+            event_log_queries: Arc::new(Semaphore::new(1)),
+            round_timeline_reader,
+            pool_dump_reader,
+            pool_dumps: Arc::new(Semaphore::new(1)),
             delegation_from_nns: Arc::new(RwLock::new(None)),
             health_status: Arc::new(RwLock::new(ReplicaHealthStatus::Starting)),
-            malicious_flags,
+            ingress_behaviors: MaliciousBehaviors::from(&malicious_flags)
+                .for_component(MaliciousComponent::IngressManager),
         }
     }
 }
@@ -488,6 +554,9 @@ fn needs_all_preconditions(request_type: &RequestType) -> bool {
             | RequestType::Options
             | RequestType::RedirectToDashboard
             | RequestType::Dashboard
+            | RequestType::EventLog
+            | RequestType::ConsensusRounds
+            | RequestType::PoolDump
             | RequestType::Status
     )
 }
@@ -534,6 +603,7 @@ async fn route(
                             route_to_handlers(
                                 Arc::clone(&metrics),
                                 http_handler,
+                                &parts.uri,
                                 parsed_body,
                                 request_type,
                             )
@@ -564,6 +634,7 @@ async fn route(
 async fn route_to_handlers(
     metrics: Arc<HttpHandlerMetrics>,
     http_handler: Arc<HttpHandler>,
+    uri: &Uri,
     parsed_body: Vec<u8>,
     request_type: RequestType,
 ) -> (Response<Body>, ApiReqType) {
@@ -574,11 +645,13 @@ async fn route_to_handlers(
                 &http_handler.delegation_from_nns,
                 http_handler.query_handler.as_ref(),
                 http_handler.state_reader.as_ref(),
+                http_handler.ingress_history_reader.as_ref(),
+                http_handler.query_cache.as_ref(),
                 http_handler.validator.as_ref(),
                 http_handler.registry_client.get_latest_version(),
                 parsed_body,
                 metrics.as_ref(),
-                &http_handler.malicious_flags,
+                &http_handler.ingress_behaviors,
             )
             .await
         }
@@ -591,12 +664,20 @@ async fn route_to_handlers(
                 Arc::clone(&http_handler.state_reader),
                 Arc::clone(&http_handler.validator),
                 Arc::clone(&http_handler.ingress_sender),
-                http_handler.malicious_flags.clone(),
+                http_handler.ingress_behaviors.clone(),
                 Arc::clone(&http_handler.ingress_message_filter),
                 parsed_body,
             )
             .await
         }
+        RequestType::RequestStatus => (
+            request_status::handle(
+                http_handler.ingress_history_reader.as_ref(),
+                Arc::clone(&http_handler.request_status_streams),
+                uri.path(),
+            ),
+            ApiReqType::Unknown,
+        ),
         RequestType::Status => (
             status::handle(
                 &http_handler.log,
@@ -604,6 +685,7 @@ async fn route_to_handlers(
                 http_handler.nns_subnet_id,
                 http_handler.state_reader.as_ref(),
                 http_handler.health_status.read().unwrap().clone(),
+                http_handler.replica_health_assessor.assess_health(),
             ),
             ApiReqType::Unknown,
         ),
@@ -615,6 +697,28 @@ async fn route_to_handlers(
             ),
             ApiReqType::Unknown,
         ),
+        RequestType::EventLog => (
+            event_log::handle(
+                &http_handler.event_log,
+                Arc::clone(&http_handler.event_log_queries),
+                uri.query(),
+            )
+            .await,
+            ApiReqType::Unknown,
+        ),
+        RequestType::ConsensusRounds => (
+            consensus_rounds::handle(http_handler.round_timeline_reader.as_ref()),
+            ApiReqType::Unknown,
+        ),
+        RequestType::PoolDump => (
+            pool_dump::handle(
+                Arc::clone(&http_handler.pool_dump_reader),
+                Arc::clone(&http_handler.pool_dumps),
+                uri.query(),
+            )
+            .await,
+            ApiReqType::Unknown,
+        ),
         RequestType::CatchUpPackage => (
             catch_up_package::handle(http_handler.consensus_pool_cache.as_ref(), parsed_body),
             ApiReqType::Unknown,
@@ -694,7 +798,7 @@ fn load_root_delegation(
             sender_delegation: None,
         };
 
-        let body = serde_cbor::ser::to_vec(&envelope).unwrap();
+        let body = to_canonical_cbor(&envelope).unwrap();
         let http_client = reqwest::blocking::Client::new();
         let ip_addr = node.ip_address.parse().unwrap();
         // any effective canister id can be used when invoking read_state here
@@ -723,7 +827,7 @@ fn load_root_delegation(
             Ok(raw_response) => {
                 debug!(log, "Response from nns subnet: {:?}", raw_response);
 
-                let response: HttpReadStateResponse = serde_cbor::from_slice(&raw_response)
+                let response: HttpReadStateResponse = from_cbor(&raw_response)
                     .expect("Incomprehensible response when fetching delegation from nns subnet");
 
                 let delegation = CertificateDelegation {
@@ -786,7 +890,11 @@ async fn parse_body(
     // Read "content-length" bytes
     // Parse the body only when needed.
     match request_type {
-        RequestType::Options | RequestType::RedirectToDashboard => Ok(Vec::new()),
+        RequestType::Options
+        | RequestType::RedirectToDashboard
+        | RequestType::EventLog
+        | RequestType::ConsensusRounds
+        | RequestType::PoolDump => Ok(Vec::new()),
         _ => {
             let mut parsed_body = Vec::<u8>::new();
             // Timeout when we are waiting for the next chunk because this wait depends on
@@ -874,7 +982,15 @@ fn validate_parts(parts: &http::request::Parts) -> Result<RequestType, Response<
             "/api/v2/status" => Ok(RequestType::Status),
             "/" | "/_/" => Ok(RequestType::RedirectToDashboard),
             HTTP_DASHBOARD_URL_PATH => Ok(RequestType::Dashboard),
-            _ => Err(common::make_response(StatusCode::NOT_FOUND, "")),
+            "/_/event_log" => Ok(RequestType::EventLog),
+            "/_/consensus_rounds" => Ok(RequestType::ConsensusRounds),
+            "/_/pool_dump" => Ok(RequestType::PoolDump),
+            path => match *path.split('/').collect::<Vec<&str>>().as_slice() {
+                ["", "api", "v2", "canister", _, "request_status", _] => {
+                    Ok(RequestType::RequestStatus)
+                }
+                _ => Err(common::make_response(StatusCode::NOT_FOUND, "")),
+            },
         },
         Method::OPTIONS => Ok(RequestType::Options),
         _ => Err(common::make_response(
@@ -0,0 +1,169 @@
+//! Module that serves the dumps of the validated sections of the artifact
+//! pools at /_/pool_dump, so that the pools of replicas that have diverged
+//! can be diffed, e.g. `/_/pool_dump?pool=consensus&include_artifacts=true`.
+//!
+//! The `pool` query parameter names the pool: `consensus`, `certification`,
+//! `dkg` or `ingress`. The full artifacts are only included if
+//! `include_artifacts` is `true`. The dump is returned CBOR encoded, see
+//! `ic_artifact_pool::dump::PoolDump`. Dumping reads the whole pool, so only
+//! one dump is served at a time.
+
+use crate::common;
+use hyper::{header, Body, Response, StatusCode};
+use ic_interfaces::artifact_pool::PoolDumpReader;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+/// The parameters of a dump request.
+#[derive(Debug, PartialEq)]
+struct DumpQuery {
+    pool: String,
+    include_artifacts: bool,
+}
+
+/// Handles a call to /_/pool_dump
+pub(crate) async fn handle(
+    pool_dump_reader: Arc<dyn PoolDumpReader>,
+    running_dumps: Arc<Semaphore>,
+    query: Option<&str>,
+) -> Response<Body> {
+    let dump_query = match parse_query(query) {
+        Ok(dump_query) => dump_query,
+        Err(err) => return common::make_response(StatusCode::BAD_REQUEST, &err),
+    };
+    let _permit = match running_dumps.try_acquire_owned() {
+        Ok(permit) => permit,
+        Err(_) => {
+            return common::make_response(
+                StatusCode::TOO_MANY_REQUESTS,
+                "Another pool dump is running. Please try again later.",
+            )
+        }
+    };
+
+    let pool = dump_query.pool.clone();
+    let dump = tokio::task::spawn_blocking(move || {
+        pool_dump_reader.get_pool_dump(&dump_query.pool, dump_query.include_artifacts)
+    })
+    .await;
+    match dump {
+        Ok(Some(Ok(body))) => {
+            let mut response = Response::new(Body::from(body));
+            response.headers_mut().insert(
+                header::CONTENT_TYPE,
+                header::HeaderValue::from_static("application/cbor"),
+            );
+            response
+        }
+        Ok(Some(Err(err))) => common::make_response(StatusCode::INTERNAL_SERVER_ERROR, &err),
+        Ok(None) => common::make_response(StatusCode::NOT_FOUND, &format!("Unknown pool {}", pool)),
+        Err(err) => common::make_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("The pool dump panicked: {}", err),
+        ),
+    }
+}
+
+/// Parses the query string of the request into a dump query.
+fn parse_query(query: Option<&str>) -> Result<DumpQuery, String> {
+    let mut pool = None;
+    let mut include_artifacts = false;
+    for param in query
+        .unwrap_or("")
+        .split('&')
+        .filter(|param| !param.is_empty())
+    {
+        let (key, value) = match param.find('=') {
+            Some(i) => (&param[..i], &param[i + 1..]),
+            None => (param, ""),
+        };
+        match key {
+            "pool" => pool = Some(value.to_string()),
+            "include_artifacts" => {
+                include_artifacts = value
+                    .parse()
+                    .map_err(|err| format!("Invalid include_artifacts {}: {}", value, err))?;
+            }
+            _ => return Err(format!("Unknown query parameter {}", key)),
+        }
+    }
+    match pool {
+        Some(pool) => Ok(DumpQuery {
+            pool,
+            include_artifacts,
+        }),
+        None => Err("Missing query parameter pool".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Dumps the "consensus" pool as the given bytes, with a trailing 1 if
+    /// the artifacts are included.
+    struct FixedDump(Vec<u8>);
+
+    impl PoolDumpReader for FixedDump {
+        fn get_pool_dump(
+            &self,
+            pool: &str,
+            include_artifacts: bool,
+        ) -> Option<Result<Vec<u8>, String>> {
+            if pool != "consensus" {
+                return None;
+            }
+            let mut dump = self.0.clone();
+            if include_artifacts {
+                dump.push(1);
+            }
+            Some(Ok(dump))
+        }
+    }
+
+    #[test]
+    fn parses_dump_queries() {
+        assert!(parse_query(None).is_err());
+        assert_eq!(
+            parse_query(Some("pool=dkg")),
+            Ok(DumpQuery {
+                pool: "dkg".to_string(),
+                include_artifacts: false,
+            })
+        );
+        assert_eq!(
+            parse_query(Some("pool=consensus&include_artifacts=true")),
+            Ok(DumpQuery {
+                pool: "consensus".to_string(),
+                include_artifacts: true,
+            })
+        );
+        assert!(parse_query(Some("pool=dkg&include_artifacts=yes")).is_err());
+        assert!(parse_query(Some("pool=dkg&height=1")).is_err());
+    }
+
+    #[tokio::test]
+    async fn serves_pool_dumps() {
+        let reader: Arc<dyn PoolDumpReader> = Arc::new(FixedDump(vec![7]));
+        let running_dumps = Arc::new(Semaphore::new(1));
+        let dump = |query: &'static str| {
+            handle(Arc::clone(&reader), Arc::clone(&running_dumps), Some(query))
+        };
+
+        let response = dump("pool=consensus&include_artifacts=true").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            header::HeaderValue::from_static("application/cbor")
+        );
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(&body[..], &[7, 1]);
+
+        let response = dump("pool=ingress").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let _running = Arc::clone(&running_dumps).try_acquire_owned().unwrap();
+        let response = dump("pool=consensus").await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+}
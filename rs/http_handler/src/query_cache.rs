@@ -0,0 +1,229 @@
+//! A cache of the responses to queries.
+//!
+//! A query is executed against the latest certified state, hence all queries
+//! with the same content that are answered at the same certified height have
+//! the same response. Read-heavy dapps send the same queries over and over
+//! again, so the responses are cached, keyed by the canister, the method, the
+//! hash of the argument and the sender. The sender is part of the key because
+//! canisters may respond differently depending on the caller.
+//!
+//! All entries of the cache belong to a single certified height. The cache is
+//! cleared as soon as a query is answered at a newer height, so that no
+//! response is served from a state other than the latest certified one.
+//!
+//! Only the results of executions are cached, i.e. replies and rejects of the
+//! canister, but not errors of the execution environment, which may be
+//! transient. Once the cache is full, responses are not cached until the next
+//! height.
+
+use ic_crypto_sha256::Sha256;
+use ic_metrics::MetricsRegistry;
+use ic_types::{ingress::WasmResult, messages::UserQuery, CanisterId, Height, UserId};
+use prometheus::{IntCounter, IntGauge};
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::sync::Mutex;
+
+/// The key of a cached query response.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct QueryCacheKey {
+    receiver: CanisterId,
+    method_name: String,
+    method_payload_hash: [u8; 32],
+    source: UserId,
+}
+
+impl QueryCacheKey {
+    pub(crate) fn new(query: &UserQuery) -> Self {
+        Self {
+            receiver: query.receiver,
+            method_name: query.method_name.clone(),
+            method_payload_hash: Sha256::hash(&query.method_payload),
+            source: query.source,
+        }
+    }
+
+    /// The number of bytes the key occupies in the cache.
+    fn size_bytes(&self) -> usize {
+        size_of::<Self>() + self.method_name.len()
+    }
+}
+
+/// Returns the number of bytes the result occupies in the cache.
+fn result_size_bytes(result: &WasmResult) -> usize {
+    size_of::<WasmResult>()
+        + match result {
+            WasmResult::Reply(bytes) => bytes.len(),
+            WasmResult::Reject(message) => message.len(),
+        }
+}
+
+struct QueryCacheMetrics {
+    hits: IntCounter,
+    misses: IntCounter,
+    invalidations: IntCounter,
+    size_bytes: IntGauge,
+}
+
+impl QueryCacheMetrics {
+    fn new(metrics_registry: &MetricsRegistry) -> Self {
+        Self {
+            hits: metrics_registry.int_counter(
+                "replica_http_query_cache_hits_total",
+                "The number of queries answered from the query cache.",
+            ),
+            misses: metrics_registry.int_counter(
+                "replica_http_query_cache_misses_total",
+                "The number of queries that were not found in the query cache.",
+            ),
+            invalidations: metrics_registry.int_counter(
+                "replica_http_query_cache_invalidations_total",
+                "The number of times the query cache was cleared, because the certified height changed.",
+            ),
+            size_bytes: metrics_registry.int_gauge(
+                "replica_http_query_cache_size_bytes",
+                "The size of the entries of the query cache in bytes.",
+            ),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Entries {
+    /// The certified height at which the cached queries were answered
+    height: Height,
+    results: HashMap<QueryCacheKey, WasmResult>,
+    size_bytes: usize,
+}
+
+/// The cache of the responses to queries at the latest certified height.
+pub(crate) struct QueryCache {
+    capacity_bytes: usize,
+    entries: Mutex<Entries>,
+    metrics: QueryCacheMetrics,
+}
+
+impl QueryCache {
+    /// Creates a cache whose entries occupy at most the given number of bytes.
+    pub(crate) fn new(metrics_registry: &MetricsRegistry, capacity_bytes: usize) -> Self {
+        Self {
+            capacity_bytes,
+            entries: Mutex::new(Entries::default()),
+            metrics: QueryCacheMetrics::new(metrics_registry),
+        }
+    }
+
+    /// Returns the cached result of the query answered at the given certified
+    /// height, if any.
+    pub(crate) fn get(&self, key: &QueryCacheKey, height: Height) -> Option<WasmResult> {
+        let mut entries = self.entries.lock().unwrap();
+        self.invalidate_before(&mut entries, height);
+        let result = if entries.height == height {
+            entries.results.get(key).cloned()
+        } else {
+            None
+        };
+        match result {
+            Some(_) => self.metrics.hits.inc(),
+            None => self.metrics.misses.inc(),
+        }
+        result
+    }
+
+    /// Caches the result of the query answered at the given certified height.
+    /// Results of older heights and results that do not fit into the cache are
+    /// not cached.
+    pub(crate) fn insert(&self, key: QueryCacheKey, height: Height, result: WasmResult) {
+        let mut entries = self.entries.lock().unwrap();
+        self.invalidate_before(&mut entries, height);
+        if entries.height != height || entries.results.contains_key(&key) {
+            return;
+        }
+        let size_bytes = key.size_bytes() + result_size_bytes(&result);
+        if entries.size_bytes + size_bytes > self.capacity_bytes {
+            return;
+        }
+        entries.results.insert(key, result);
+        entries.size_bytes += size_bytes;
+        self.metrics.size_bytes.set(entries.size_bytes as i64);
+    }
+
+    /// Clears the cache if its entries belong to a height below the given one.
+    fn invalidate_before(&self, entries: &mut Entries, height: Height) {
+        if entries.height < height {
+            if !entries.results.is_empty() {
+                self.metrics.invalidations.inc();
+            }
+            *entries = Entries {
+                height,
+                ..Default::default()
+            };
+            self.metrics.size_bytes.set(0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_test_utilities::types::ids::{canister_test_id, user_test_id};
+
+    fn query(method_name: &str, method_payload: Vec<u8>) -> UserQuery {
+        UserQuery {
+            receiver: canister_test_id(1),
+            source: user_test_id(2),
+            method_name: method_name.to_string(),
+            method_payload,
+            ingress_expiry: 0,
+            nonce: None,
+        }
+    }
+
+    fn reply(bytes: &[u8]) -> WasmResult {
+        WasmResult::Reply(bytes.to_vec())
+    }
+
+    #[test]
+    fn cached_results_are_returned_at_the_same_height() {
+        let cache = QueryCache::new(&MetricsRegistry::new(), 1 << 20);
+        let key = QueryCacheKey::new(&query("get", vec![1]));
+        assert_eq!(cache.get(&key, Height::from(3)), None);
+        cache.insert(key.clone(), Height::from(3), reply(b"value"));
+        assert_eq!(cache.get(&key, Height::from(3)), Some(reply(b"value")));
+        assert_eq!(
+            cache.get(&QueryCacheKey::new(&query("get", vec![2])), Height::from(3)),
+            None
+        );
+        assert_eq!(cache.metrics.hits.get(), 1);
+        assert_eq!(cache.metrics.misses.get(), 2);
+    }
+
+    #[test]
+    fn cache_is_invalidated_at_newer_heights() {
+        let cache = QueryCache::new(&MetricsRegistry::new(), 1 << 20);
+        let key = QueryCacheKey::new(&query("get", vec![]));
+        cache.insert(key.clone(), Height::from(3), reply(b"old"));
+        assert_eq!(cache.get(&key, Height::from(4)), None);
+        assert_eq!(cache.metrics.invalidations.get(), 1);
+        assert_eq!(cache.metrics.size_bytes.get(), 0);
+
+        // Results of older heights neither replace nor invalidate the entries.
+        cache.insert(key.clone(), Height::from(4), reply(b"new"));
+        cache.insert(key.clone(), Height::from(3), reply(b"old"));
+        assert_eq!(cache.get(&key, Height::from(3)), None);
+        assert_eq!(cache.get(&key, Height::from(4)), Some(reply(b"new")));
+    }
+
+    #[test]
+    fn results_exceeding_the_capacity_are_not_cached() {
+        let small = QueryCacheKey::new(&query("get", vec![]));
+        let capacity = small.size_bytes() + result_size_bytes(&reply(b"small"));
+        let cache = QueryCache::new(&MetricsRegistry::new(), capacity);
+        cache.insert(small.clone(), Height::from(1), reply(b"small"));
+        let other = QueryCacheKey::new(&query("other", vec![]));
+        cache.insert(other.clone(), Height::from(1), reply(b"other"));
+        assert_eq!(cache.get(&small, Height::from(1)), Some(reply(b"small")));
+        assert_eq!(cache.get(&other, Height::from(1)), None);
+        assert_eq!(cache.metrics.size_bytes.get() as usize, capacity);
+    }
+}
@@ -3,18 +3,19 @@
 use crate::{
     common,
     metrics::HttpHandlerMetrics,
+    query_cache::{QueryCache, QueryCacheKey},
     types::{ApiReqType, RequestType},
 };
 use hyper::{Body, Response, StatusCode};
 use ic_crypto_tree_hash::{sparse_labeled_tree_from_paths, Label, Path};
 use ic_interfaces::crypto::IngressSigVerifier;
-use ic_interfaces::execution_environment::QueryHandler;
+use ic_interfaces::execution_environment::{IngressHistoryReader, QueryHandler};
 use ic_interfaces::state_manager::StateReader;
 use ic_logger::{info, trace, ReplicaLogger};
 use ic_replicated_state::ReplicatedState;
 use ic_types::{
-    ingress::WasmResult,
-    malicious_flags::MaliciousFlags,
+    ingress::{IngressStatus, WasmResult},
+    malicious_strategies::MaliciousBehaviors,
     messages::{
         Blob, Certificate, CertificateDelegation, HttpQueryResponse, HttpQueryResponseReply,
         HttpReadContent, HttpReadStateResponse, HttpRequest, HttpRequestEnvelope, MessageId,
@@ -27,9 +28,14 @@ use ic_types::{
 use ic_validator::{get_authorized_canisters, CanisterIdSet};
 use std::convert::TryFrom;
 use std::sync::RwLock;
+use std::time::Duration;
 
 const MAX_READ_STATE_REQUEST_IDS: u8 = 100;
 
+/// The maximum time a read_state request waits for the next certification,
+/// in case certification stalls.
+const REQUEST_STATUS_WAIT_TIMEOUT: Duration = Duration::from_secs(5);
+
 enum VerifyPathsError {
     InvalidPath,
     InvalidRequestId,
@@ -63,11 +69,13 @@ pub(crate) async fn handle(
     delegation_from_nns: &RwLock<Option<CertificateDelegation>>,
     query_handler: &dyn QueryHandler<State = ReplicatedState>,
     state_reader: &dyn StateReader<State = ReplicatedState>,
+    ingress_history_reader: &dyn IngressHistoryReader,
+    query_cache: Option<&QueryCache>,
     validator: &(dyn IngressSigVerifier + Send + Sync),
     registry_version: RegistryVersion,
     body: Vec<u8>,
     metrics: &HttpHandlerMetrics,
-    malicious_flags: &MaliciousFlags,
+    malicious_behaviors: &MaliciousBehaviors,
 ) -> (Response<Body>, ApiReqType) {
     trace!(log, "in handle read");
     use ApiReqType::*;
@@ -105,7 +113,7 @@ pub(crate) async fn handle(
         validator,
         current_time(),
         registry_version,
-        malicious_flags,
+        malicious_behaviors,
     ) {
         Ok(targets) => targets,
         Err(err) => {
@@ -125,6 +133,7 @@ pub(crate) async fn handle(
                 delegation_from_nns,
                 query_handler,
                 state_reader,
+                query_cache,
                 query.clone(),
                 targets,
                 metrics,
@@ -136,10 +145,12 @@ pub(crate) async fn handle(
             handle_read_state(
                 delegation_from_nns,
                 state_reader,
+                ingress_history_reader,
                 read_state.clone(),
                 targets,
                 metrics,
-            ),
+            )
+            .await,
             ReadState,
         ),
     }
@@ -147,11 +158,13 @@ pub(crate) async fn handle(
 
 // TODO(INF-328): The errors codes below are mostly 500. They should be more
 // descriptive.
+#[allow(clippy::too_many_arguments)]
 async fn handle_query(
     log: &ReplicaLogger,
     delegation_from_nns: Option<CertificateDelegation>,
     query_handler: &dyn QueryHandler<State = ReplicatedState>,
     state_reader: &dyn StateReader<State = ReplicatedState>,
+    query_cache: Option<&QueryCache>,
     query: UserQuery,
     targets: CanisterIdSet,
     metrics: &HttpHandlerMetrics,
@@ -170,7 +183,13 @@ async fn handle_query(
         &delegation_from_nns,
         query.receiver,
     ) {
-        Some((state, cert)) => {
+        Some((state, cert, height)) => {
+            let cache = query_cache.map(|cache| (cache, QueryCacheKey::new(&query)));
+            if let Some((cache, key)) = &cache {
+                if let Some(result) = cache.get(key, height) {
+                    return query_response(Ok(result), log);
+                }
+            }
             let (tx, mut rx) = tokio::sync::mpsc::channel(1);
             let log = log.clone();
             let callback = move |res| {
@@ -187,7 +206,12 @@ async fn handle_query(
                         "Broken channel. Query execution didn't return a value.",
                     )
                 }
-                Some(res) => res,
+                Some(res) => {
+                    if let (Some((cache, key)), Ok(result)) = (cache, &res) {
+                        cache.insert(key, height, result.clone());
+                    }
+                    res
+                }
             }
         }
         None => Err(UserError::new(
@@ -196,6 +220,10 @@ async fn handle_query(
         )),
     };
 
+    query_response(res, log)
+}
+
+fn query_response(res: Result<WasmResult, UserError>, log: &ReplicaLogger) -> Response<Body> {
     match res {
         Ok(res) => {
             let response = match res {
@@ -222,23 +250,27 @@ async fn handle_query(
     }
 }
 
-fn handle_read_state(
+async fn handle_read_state(
     delegation_from_nns: Option<CertificateDelegation>,
     state_reader: &dyn StateReader<State = ReplicatedState>,
+    ingress_history_reader: &dyn IngressHistoryReader,
     read_state: ReadState,
     targets: CanisterIdSet,
     metrics: &HttpHandlerMetrics,
 ) -> Response<Body> {
     // Verify that the sender has authorization to the paths requested.
-    if let Err(err) = verify_paths(
+    let known_request_ids = match verify_paths(
         state_reader,
         &read_state.source,
         &read_state.paths,
         &targets,
     ) {
-        metrics.observe_forbidden_request(&RequestType::Read, "InvalidPaths");
-        return common::make_response(StatusCode::FORBIDDEN, &err.description());
-    }
+        Ok(known_request_ids) => known_request_ids,
+        Err(err) => {
+            metrics.observe_forbidden_request(&RequestType::Read, "InvalidPaths");
+            return common::make_response(StatusCode::FORBIDDEN, &err.description());
+        }
+    };
     metrics.observe_unreliable_request_acceptance_duration(
         RequestType::Read,
         ApiReqType::ReadState,
@@ -252,6 +284,26 @@ fn handle_read_state(
 
     let labeled_tree = sparse_labeled_tree_from_paths(&mut paths);
 
+    // Clients poll the status of their requests until it is terminal. Rather
+    // than answering every poll with the same non-terminal status, answer
+    // right away if the requested messages are completed or failed in the
+    // certified state and otherwise once the next certification is
+    // delivered, so that clients need fewer polls.
+    if !known_request_ids.is_empty() {
+        let mut certified_height = state_reader.subscribe_certified_height();
+        let receivers = known_request_ids
+            .into_iter()
+            .map(|message_id| ingress_history_reader.subscribe_to_terminal_status(message_id));
+        let _ = tokio::time::timeout(
+            REQUEST_STATUS_WAIT_TIMEOUT,
+            futures::future::select(
+                futures::future::join_all(receivers),
+                Box::pin(certified_height.changed()),
+            ),
+        )
+        .await;
+    }
+
     match state_reader.read_certified_state(&labeled_tree) {
         Some((_state, tree, certification)) => {
             let signature = certification.signed.signature.signature.get().0;
@@ -272,14 +324,16 @@ fn handle_read_state(
 }
 
 // Verifies that the `user` is authorized to retrieve the `paths` requested.
+// Returns the requested message IDs that are known in the latest state.
 fn verify_paths(
     state_reader: &dyn StateReader<State = ReplicatedState>,
     user: &UserId,
     paths: &[Path],
     targets: &CanisterIdSet,
-) -> Result<(), VerifyPathsError> {
+) -> Result<Vec<MessageId>, VerifyPathsError> {
     let state = state_reader.get_latest_state().take();
     let mut num_request_ids = 0;
+    let mut known_request_ids = Vec::new();
 
     // Convert the paths to slices to make it easier to match below.
     let paths: Vec<Vec<&[u8]>> = paths
@@ -313,6 +367,10 @@ fn verify_paths(
                             }
                         }
                     }
+
+                    if ingress_status != IngressStatus::Unknown {
+                        known_request_ids.push(message_id);
+                    }
                 } else {
                     return Err(VerifyPathsError::InvalidRequestId);
                 }
@@ -324,7 +382,7 @@ fn verify_paths(
         }
     }
 
-    Ok(())
+    Ok(known_request_ids)
 }
 
 #[cfg(test)]
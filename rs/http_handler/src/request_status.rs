@@ -0,0 +1,349 @@
+//! Module that deals with requests to
+//! /api/v2/canister/<canister_id>/request_status/<request_id>
+//!
+//! The status of an ingress message is streamed as server-sent events, so that
+//! agents can wait for the outcome of a call instead of busy-polling
+//! `read_state`. Each event carries the name of the status of the message in
+//! the latest certified state, as defined in the interface spec, and a new
+//! event is sent on every status transition. The stream ends after the
+//! terminal status, such that clients that do not consume event streams can
+//! use the endpoint as a long poll.
+//!
+//! The events are neither certified nor do they carry the reply. Agents fetch
+//! the certified reply with a single `read_state` request once the stream
+//! ended.
+//!
+//! The endpoint is not authenticated, so it is restricted to keep it cheap:
+//! only messages to the given canister that are known in the latest state can
+//! be subscribed to, at most `MAX_CONCURRENT_STREAMS` streams are open at a
+//! time, and every stream ends after `MAX_STREAM_DURATION`, even if the
+//! message never reaches a terminal status (e.g. because it expired). Agents
+//! that get a 404 for a message they just submitted retry a bit later, or fall
+//! back to polling `read_state`.
+
+use crate::common;
+use hyper::{header, Body, Response, StatusCode};
+use ic_interfaces::execution_environment::IngressHistoryReader;
+use ic_types::{ingress::IngressStatus, messages::MessageId, CanisterId};
+use std::convert::{Infallible, TryFrom};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::time::Instant;
+
+/// The maximum number of status streams that are open at the same time.
+pub(crate) const MAX_CONCURRENT_STREAMS: usize = 1000;
+
+/// The maximum duration of a status stream. Messages expire after at most
+/// five minutes, so a longer wait would only keep the stream of an expired
+/// message open.
+const MAX_STREAM_DURATION: Duration = Duration::from_secs(6 * 60);
+
+/// Handles a call to /api/v2/canister/<canister_id>/request_status/<request_id>
+pub(crate) fn handle(
+    ingress_history_reader: &dyn IngressHistoryReader,
+    open_streams: Arc<Semaphore>,
+    path: &str,
+) -> Response<Body> {
+    handle_with_max_duration(
+        ingress_history_reader,
+        open_streams,
+        path,
+        MAX_STREAM_DURATION,
+    )
+}
+
+fn handle_with_max_duration(
+    ingress_history_reader: &dyn IngressHistoryReader,
+    open_streams: Arc<Semaphore>,
+    path: &str,
+    max_duration: Duration,
+) -> Response<Body> {
+    let (canister_id, message_id) = match parse_path(path) {
+        Ok(ids) => ids,
+        Err(err) => return common::make_response(StatusCode::BAD_REQUEST, &err),
+    };
+
+    // Unknown messages and messages to other canisters are treated alike, so
+    // that the response does not reveal whether the message exists.
+    let latest_status = ingress_history_reader.get_latest_status()(&message_id);
+    if latest_status.receiver() != Some(canister_id) {
+        return common::make_response(
+            StatusCode::NOT_FOUND,
+            &format!(
+                "Request {} to canister {} is not known yet.",
+                message_id, canister_id
+            ),
+        );
+    }
+
+    let permit = match open_streams.try_acquire_owned() {
+        Ok(permit) => permit,
+        Err(_) => {
+            return common::make_response(
+                StatusCode::TOO_MANY_REQUESTS,
+                "Too many open request status streams. Please try again later.",
+            )
+        }
+    };
+
+    let deadline = Instant::now() + max_duration;
+    let receiver = ingress_history_reader.subscribe_to_status_transitions(message_id);
+    // The permit is released when the stream ends or the client disconnects.
+    let events = futures::stream::unfold(Some((receiver, permit)), move |stream| async move {
+        let (mut receiver, permit) = stream?;
+        let status = tokio::time::timeout_at(deadline, receiver.recv())
+            .await
+            .ok()??;
+        // The statuses of messages to other canisters are not revealed.
+        if status
+            .receiver()
+            .map_or(false, |receiver| receiver != canister_id)
+        {
+            return Some((
+                Ok::<_, Infallible>(status_event(&IngressStatus::Unknown)),
+                None,
+            ));
+        }
+        Some((Ok(status_event(&status)), Some((receiver, permit))))
+    });
+
+    let mut response = common::make_response(StatusCode::OK, "");
+    *response.body_mut() = Body::wrap_stream(events);
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static("text/event-stream"),
+    );
+    response.headers_mut().insert(
+        header::CACHE_CONTROL,
+        header::HeaderValue::from_static("no-cache"),
+    );
+    response
+}
+
+/// Parses the canister ID and the hex-encoded request ID from the path.
+fn parse_path(path: &str) -> Result<(CanisterId, MessageId), String> {
+    match *path.split('/').collect::<Vec<&str>>().as_slice() {
+        ["", "api", "v2", "canister", canister_id, "request_status", request_id] => {
+            let canister_id = CanisterId::from_str(canister_id)
+                .map_err(|err| format!("Invalid canister ID {}: {}", canister_id, err))?;
+            let message_id = hex::decode(request_id)
+                .map_err(|err| err.to_string())
+                .and_then(|bytes| MessageId::try_from(&bytes[..]).map_err(|err| err.to_string()))
+                .map_err(|err| format!("Invalid request ID {}: {}", request_id, err))?;
+            Ok((canister_id, message_id))
+        }
+        _ => Err(format!("Invalid request status path {}", path)),
+    }
+}
+
+/// Encodes the server-sent event of the given status.
+fn status_event(status: &IngressStatus) -> String {
+    format!("event: status\ndata: {}\n\n", status.as_str())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ic_interfaces::execution_environment::IngressHistoryError;
+    use ic_test_utilities::types::ids::{canister_test_id, message_test_id, user_test_id};
+    use ic_types::{time::UNIX_EPOCH, Height};
+    use std::sync::Mutex;
+    use tokio::sync::{mpsc, oneshot};
+
+    /// An ingress history whose latest status is fixed, and whose status
+    /// transitions are sent by the test.
+    struct FakeIngressHistory {
+        latest_status: IngressStatus,
+        transitions: Mutex<Option<mpsc::UnboundedReceiver<IngressStatus>>>,
+    }
+
+    impl FakeIngressHistory {
+        fn new(latest_status: IngressStatus) -> (Self, mpsc::UnboundedSender<IngressStatus>) {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            let history = Self {
+                latest_status,
+                transitions: Mutex::new(Some(receiver)),
+            };
+            (history, sender)
+        }
+    }
+
+    impl IngressHistoryReader for FakeIngressHistory {
+        fn get_latest_status(&self) -> Box<dyn Fn(&MessageId) -> IngressStatus> {
+            let status = self.latest_status.clone();
+            Box::new(move |_| status.clone())
+        }
+
+        fn get_status_at_height(
+            &self,
+            _height: Height,
+        ) -> Result<Box<dyn Fn(&MessageId) -> IngressStatus>, IngressHistoryError> {
+            unimplemented!()
+        }
+
+        fn subscribe_to_terminal_status(
+            &self,
+            _message_id: MessageId,
+        ) -> oneshot::Receiver<IngressStatus> {
+            unimplemented!()
+        }
+
+        fn subscribe_to_status_transitions(
+            &self,
+            _message_id: MessageId,
+        ) -> mpsc::UnboundedReceiver<IngressStatus> {
+            self.transitions.lock().unwrap().take().unwrap()
+        }
+    }
+
+    fn received(canister_id: CanisterId) -> IngressStatus {
+        IngressStatus::Received {
+            receiver: canister_id.get(),
+            user_id: user_test_id(1),
+            time: UNIX_EPOCH,
+        }
+    }
+
+    fn processing(canister_id: CanisterId) -> IngressStatus {
+        IngressStatus::Processing {
+            receiver: canister_id.get(),
+            user_id: user_test_id(1),
+            time: UNIX_EPOCH,
+        }
+    }
+
+    fn path(canister_id: CanisterId, message_id: &MessageId) -> String {
+        format!(
+            "/api/v2/canister/{}/request_status/{}",
+            canister_id,
+            hex::encode(message_id.as_bytes())
+        )
+    }
+
+    async fn body(response: Response<Body>) -> String {
+        let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn streams_status_transitions() {
+        let canister_id = canister_test_id(1);
+        let (history, sender) = FakeIngressHistory::new(received(canister_id));
+        let open_streams = Arc::new(Semaphore::new(1));
+
+        let response = handle(
+            &history,
+            Arc::clone(&open_streams),
+            &path(canister_id, &message_test_id(7)),
+        );
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(open_streams.available_permits(), 0);
+
+        sender.send(received(canister_id)).unwrap();
+        sender.send(processing(canister_id)).unwrap();
+        drop(sender);
+        assert_eq!(
+            body(response).await,
+            "event: status\ndata: received\n\nevent: status\ndata: processing\n\n"
+        );
+        assert_eq!(open_streams.available_permits(), 1);
+    }
+
+    #[tokio::test]
+    async fn does_not_stream_unknown_messages_or_messages_to_other_canisters() {
+        let canister_id = canister_test_id(1);
+        let message_id = message_test_id(7);
+        let open_streams = Arc::new(Semaphore::new(1));
+
+        let (history, _sender) = FakeIngressHistory::new(IngressStatus::Unknown);
+        let response = handle(
+            &history,
+            Arc::clone(&open_streams),
+            &path(canister_id, &message_id),
+        );
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let (history, _sender) = FakeIngressHistory::new(received(canister_test_id(2)));
+        let response = handle(
+            &history,
+            Arc::clone(&open_streams),
+            &path(canister_id, &message_id),
+        );
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(open_streams.available_permits(), 1);
+    }
+
+    #[tokio::test]
+    async fn limits_the_number_of_open_streams() {
+        let canister_id = canister_test_id(1);
+        let message_id = message_test_id(7);
+        let open_streams = Arc::new(Semaphore::new(1));
+
+        let (history, _sender) = FakeIngressHistory::new(received(canister_id));
+        let open = handle(
+            &history,
+            Arc::clone(&open_streams),
+            &path(canister_id, &message_id),
+        );
+        assert_eq!(open.status(), StatusCode::OK);
+
+        let (history, _sender) = FakeIngressHistory::new(received(canister_id));
+        let response = handle(
+            &history,
+            Arc::clone(&open_streams),
+            &path(canister_id, &message_id),
+        );
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        drop(open);
+        let (history, _sender) = FakeIngressHistory::new(received(canister_id));
+        let response = handle(
+            &history,
+            Arc::clone(&open_streams),
+            &path(canister_id, &message_id),
+        );
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn ends_streams_after_the_max_duration() {
+        let canister_id = canister_test_id(1);
+        let (history, sender) = FakeIngressHistory::new(received(canister_id));
+        let open_streams = Arc::new(Semaphore::new(1));
+
+        let response = handle_with_max_duration(
+            &history,
+            Arc::clone(&open_streams),
+            &path(canister_id, &message_test_id(7)),
+            Duration::from_millis(10),
+        );
+        sender.send(received(canister_id)).unwrap();
+        // The sender stays open, as for a message that never completes.
+        assert_eq!(body(response).await, "event: status\ndata: received\n\n");
+        assert_eq!(open_streams.available_permits(), 1);
+        drop(sender);
+    }
+
+    #[test]
+    fn parses_request_status_path() {
+        let canister_id = canister_test_id(1);
+        let message_id = message_test_id(7);
+        let path = format!(
+            "/api/v2/canister/{}/request_status/{}",
+            canister_id,
+            hex::encode(message_id.as_bytes())
+        );
+        assert_eq!(parse_path(&path), Ok((canister_id, message_id)));
+        assert!(parse_path("/api/v2/canister/aaaaa-aa/request_status/00").is_err());
+    }
+
+    #[test]
+    fn encodes_status_events() {
+        assert_eq!(
+            status_event(&IngressStatus::Unknown),
+            "event: status\ndata: unknown\n\n"
+        );
+    }
+}
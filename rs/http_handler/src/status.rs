@@ -6,7 +6,7 @@ use ic_interfaces::state_manager::StateReader;
 use ic_logger::{trace, warn, ReplicaLogger};
 use ic_replicated_state::ReplicatedState;
 use ic_types::{
-    messages::{Blob, HttpStatusResponse, ReplicaHealthStatus},
+    messages::{Blob, HttpStatusResponse, ReplicaHealthAssessment, ReplicaHealthStatus},
     replica_version::REPLICA_BINARY_HASH,
     ReplicaVersion, SubnetId,
 };
@@ -18,6 +18,7 @@ pub(crate) fn handle(
     nns_subnet_id: SubnetId,
     state_reader: &dyn StateReader<State = ReplicatedState>,
     replica_health_status: ReplicaHealthStatus,
+    replica_health_assessment: ReplicaHealthAssessment,
 ) -> Response<Body> {
     trace!(log, "in handle status");
 
@@ -58,6 +59,7 @@ pub(crate) fn handle(
         impl_version: Some(ReplicaVersion::default().to_string()),
         impl_hash: REPLICA_BINARY_HASH.get().map(|s| s.to_string()),
         replica_health_status: Some(replica_health_status),
+        replica_health_assessment: Some(replica_health_assessment),
     };
     common::cbor_response(&response)
 }
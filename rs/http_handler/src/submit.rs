@@ -2,6 +2,7 @@
 
 use crate::{common, metrics::HttpHandlerMetrics, types::*};
 use hyper::{Body, Response, StatusCode};
+use ic_interfaces::artifact_manager::OnArtifactError;
 use ic_interfaces::crypto::IngressSigVerifier;
 use ic_interfaces::execution_environment::IngressMessageFilter;
 use ic_interfaces::execution_environment::{HypervisorError, MessageAcceptanceError};
@@ -15,7 +16,7 @@ use ic_registry_client::helper::{
 use ic_registry_provisional_whitelist::ProvisionalWhitelist;
 use ic_replicated_state::ReplicatedState;
 use ic_types::{
-    malicious_flags::MaliciousFlags,
+    malicious_strategies::MaliciousBehaviors,
     messages::{HttpHandlerError, SignedIngress, SignedRequestBytes},
     time::current_time,
     CountBytes, SubnetId,
@@ -81,7 +82,7 @@ pub(crate) async fn handle(
     state_reader: Arc<dyn StateReader<State = ReplicatedState>>,
     validator: Arc<dyn IngressSigVerifier + Send + Sync>,
     ingress_sender: Arc<dyn IngressEventHandler>,
-    malicious_flags: MaliciousFlags,
+    malicious_behaviors: MaliciousBehaviors,
     ingress_message_filter: Arc<dyn IngressMessageFilter<State = ReplicatedState>>,
     body: Vec<u8>,
 ) -> (Response<Body>, ApiReqType) {
@@ -112,7 +113,9 @@ pub(crate) async fn handle(
     let message_id = msg.id();
     let registry_version = registry_client.get_latest_version();
 
-    match registry_client.get_ingress_message_settings(subnet_id, registry_version) {
+    let max_ingress_ttl = match registry_client
+        .get_ingress_message_settings(subnet_id, registry_version)
+    {
         Ok(Some(settings)) => {
             if msg.count_bytes() > settings.max_ingress_bytes_per_message {
                 return (
@@ -124,6 +127,7 @@ pub(crate) async fn handle(
                     Call,
                 );
             }
+            settings.expiry_window.max_ttl
         }
         Ok(None) => {
             let err_msg = format!(
@@ -153,8 +157,9 @@ pub(crate) async fn handle(
         msg.as_ref(),
         validator.as_ref(),
         current_time(),
+        max_ingress_ttl,
         registry_version,
-        &malicious_flags,
+        &malicious_behaviors,
     ) {
         let response = common::make_response_on_validation_error(message_id, err, &log);
         metrics.observe_forbidden_request(&RequestType::Submit, "SubmitReqAuthFailed");
@@ -200,6 +205,13 @@ pub(crate) async fn handle(
             error!(log, "route_to_handlers failed with: {}", err);
             (common::empty_response(), ApiReqType::Unknown)
         }
+        Ok(Err(OnArtifactError::RateLimited)) => (
+            common::make_response(
+                StatusCode::TOO_MANY_REQUESTS,
+                "The sender exceeded its ingress rate limit, please retry later.",
+            ),
+            Call,
+        ),
         Ok(Err(_e)) => (
             common::make_response(StatusCode::SERVICE_UNAVAILABLE, "Service Unavailable!"),
             Call,
@@ -238,6 +250,7 @@ mod test {
                 nonce: None,
                 sender: Blob(vec![0x04]),
                 ingress_expiry: expiry_time.as_nanos_since_unix_epoch(),
+                priority: None,
             },
         };
         let request1 = HttpRequestEnvelope::<HttpSubmitContent> {
@@ -255,6 +268,7 @@ mod test {
                 nonce: None,
                 sender: Blob(vec![0x04]),
                 ingress_expiry: expiry_time.as_nanos_since_unix_epoch(),
+                priority: None,
             },
         };
         let request2 = HttpRequestEnvelope::<HttpSubmitContent> {
@@ -59,6 +59,14 @@ pub(crate) enum RequestType {
     Dashboard,
     /// A request for the latest Catch-Up Package (CUP)
     CatchUpPackage,
+    /// A request for the stream of status transitions of an ingress message
+    RequestStatus,
+    /// A query of the event log
+    EventLog,
+    /// A query of the timelines of the latest consensus rounds
+    ConsensusRounds,
+    /// A request for the dump of an artifact pool
+    PoolDump,
 }
 
 impl RequestType {
@@ -72,6 +80,10 @@ impl RequestType {
             RedirectToDashboard => "redirect_to_dashboard",
             Dashboard => "dashboard",
             CatchUpPackage => "catch-up-package",
+            RequestStatus => "request_status",
+            EventLog => "event_log",
+            ConsensusRounds => "consensus_rounds",
+            PoolDump => "pool_dump",
         }
     }
 }
@@ -11,11 +11,11 @@ use ic_interfaces::{
 use ic_logger::{debug, warn};
 use ic_types::{
     artifact::{IngressMessageAttribute, IngressMessageId},
-    ingress::{IngressStatus, MAX_INGRESS_TTL},
+    ingress::IngressStatus,
     time::current_time,
     CountBytes,
 };
-use ic_validator::validate_request;
+use std::collections::HashSet;
 
 impl IngressHandler for IngressManager {
     #[allow(clippy::cognitive_complexity)]
@@ -47,15 +47,19 @@ impl IngressHandler for IngressManager {
         }
 
         let current_time = current_time();
-        let expiry_range = current_time..=(current_time + MAX_INGRESS_TTL);
+        let max_ingress_ttl = ingress_message_settings.expiry_window.max_ttl;
+        let expiry_range = current_time..=(current_time + max_ingress_ttl);
 
         // looks at the unvalidated ingress messages and
         // 1. either discards them
-        // 2. or moves them to validated.
+        // 2. or submits them for signature verification, and once verified,
+        //    moves them to validated or discards them.
         let unvalidated_artifacts = pool
             .unvalidated()
             .get_all_by_expiry_range(expiry_range.clone());
-        change_set.extend(unvalidated_artifacts.map(|artifact| {
+        let mut pending = HashSet::new();
+        let mut to_verify = Vec::new();
+        for artifact in unvalidated_artifacts {
             let ingress_object = &artifact.message;
             let ingress_message = &ingress_object.signed_ingress;
             let max_ingress_bytes_per_message =
@@ -70,7 +74,10 @@ impl IngressHandler for IngressManager {
                     ingress_message.reason => "message_too_large",
                     ingress_message.size => size as u64,
                 );
-                return RemoveFromUnvalidated(IngressMessageId::from(ingress_object));
+                change_set.push(RemoveFromUnvalidated(IngressMessageId::from(
+                    ingress_object,
+                )));
+                continue;
             }
 
             // Check status of the ingress message against IngressHistoryReader,
@@ -83,40 +90,54 @@ impl IngressHandler for IngressManager {
                     ingress_message.message_id => format!("{}", ingress_object.message_id),
                     ingress_message.reason => format!("unexpected_status_{}", status.as_str()),
                 );
-                return RemoveFromUnvalidated(IngressMessageId::from(ingress_object));
+                change_set.push(RemoveFromUnvalidated(IngressMessageId::from(
+                    ingress_object,
+                )));
+                continue;
             }
 
             // Check signatures, remove from unvalidated if they can't be
-            // verified, add to validated otherwise.
-            if let Err(err) = validate_request(
-                ingress_message.as_ref(),
-                self.ingress_signature_crypto.as_ref(),
-                current_time,
-                registry_version,
-                &self.malicious_flags,
-            ) {
-                debug!(
-                    self.log,
-                    "ingress_message_remove_unvalidated";
-                    ingress_message.message_id => format!("{}", ingress_object.message_id),
-                    ingress_message.reason => format!("auth_failure: {}", err),
-                );
-                return RemoveFromUnvalidated(IngressMessageId::from(ingress_object));
+            // verified, add to validated otherwise. Messages that were not
+            // verified yet are submitted to the signature verifier, and their
+            // results are picked up in a later invocation.
+            let message_id = IngressMessageId::from(ingress_object);
+            match self.signature_verifier.take_result(&message_id) {
+                Some(Err(err)) => {
+                    debug!(
+                        self.log,
+                        "ingress_message_remove_unvalidated";
+                        ingress_message.message_id => format!("{}", ingress_object.message_id),
+                        ingress_message.reason => format!("auth_failure: {}", err),
+                    );
+                    change_set.push(RemoveFromUnvalidated(message_id));
+                }
+                Some(Ok(())) => {
+                    debug!(
+                        self.log,
+                        "ingress_message_insert_validated";
+                        ingress_message.message_id => format!("{}", ingress_object.message_id),
+                    );
+                    let integrity_hash = ic_crypto::crypto_hash(ingress_message.binary()).get();
+                    change_set.push(MoveToValidated((
+                        message_id,
+                        size,
+                        IngressMessageAttribute::new(ingress_message),
+                        integrity_hash,
+                    )));
+                }
+                None => {
+                    if !self.signature_verifier.is_in_flight(&message_id) {
+                        to_verify.push((message_id.clone(), ingress_message.clone()));
+                    }
+                    pending.insert(message_id);
+                }
             }
-
-            debug!(
-                self.log,
-                "ingress_message_insert_validated";
-                ingress_message.message_id => format!("{}", ingress_object.message_id),
-            );
-            let integrity_hash = ic_crypto::crypto_hash(ingress_message.binary()).get();
-            MoveToValidated((
-                IngressMessageId::from(ingress_object),
-                size,
-                IngressMessageAttribute::new(ingress_message),
-                integrity_hash,
-            ))
-        }));
+        }
+        // Results of messages that left the unvalidated section in the
+        // meantime are not needed anymore.
+        self.signature_verifier.retain_results(&pending);
+        self.signature_verifier
+            .submit(to_verify, current_time, max_ingress_ttl, registry_version);
 
         // Check validated messages and remove if they are not required anymore (i.e.
         // IngressHistoryReader returns status other than Unknown).
@@ -166,10 +187,22 @@ mod tests {
         types::messages::SignedIngressBuilder,
         FastForwardTimeSource,
     };
-    use ic_types::time::UNIX_EPOCH;
+    use ic_types::{ingress::MAX_INGRESS_TTL, time::UNIX_EPOCH};
     use std::sync::Arc;
     use std::time::Duration;
 
+    /// Runs on_state_change, waits for the submitted signatures to be
+    /// verified, and runs on_state_change again to pick up the results.
+    fn on_state_change_with_verification(
+        ingress_manager: &IngressManager,
+        ingress_pool: &dyn IngressPool,
+    ) -> ChangeSet {
+        let mut change_set = ingress_manager.on_state_change(ingress_pool);
+        ingress_manager.signature_verifier.wait_for_results();
+        change_set.extend(ingress_manager.on_state_change(ingress_pool));
+        change_set
+    }
+
     #[tokio::test]
     async fn test_ingress_on_state_change_valid() {
         let time = current_time();
@@ -202,6 +235,13 @@ mod tests {
                     peer_id: node_test_id(0),
                     timestamp: time,
                 });
+                // The message is only validated once its signature was
+                // verified.
+                let change_set = ingress_manager.on_state_change(&ingress_pool);
+                assert!(!change_set
+                    .iter()
+                    .any(|action| matches!(action, ChangeAction::MoveToValidated(_))));
+                ingress_manager.signature_verifier.wait_for_results();
                 let change_set = ingress_manager.on_state_change(&ingress_pool);
                 let size = ingress_message.count_bytes();
                 let expected_change_action =
@@ -303,9 +343,11 @@ mod tests {
     #[tokio::test]
     async fn test_ingress_on_state_change_remove_validated() {
         let mut ingress_hist_reader = Box::new(MockIngressHistory::new());
+        // The message is validated in two invocations, see
+        // `on_state_change_with_verification`.
         ingress_hist_reader
             .expect_get_latest_status()
-            .times(1)
+            .times(2)
             .returning(|| Box::new(|_| IngressStatus::Unknown));
         ingress_hist_reader
             .expect_get_latest_status()
@@ -342,7 +384,7 @@ mod tests {
                     peer_id: node_test_id(0),
                     timestamp: time,
                 });
-                let change_set = ingress_manager.on_state_change(&ingress_pool);
+                let change_set = on_state_change_with_verification(&ingress_manager, &ingress_pool);
                 ingress_pool.apply_changeset(change_set);
                 let change_set = ingress_manager.on_state_change(&ingress_pool);
                 let expected_change_action = ChangeAction::RemoveFromValidated(message_id);
@@ -406,7 +448,7 @@ mod tests {
 
                 let good_id = IngressMessageId::from(&good_msg);
                 let bad_id = IngressMessageId::from(&bad_msg);
-                let change_set = ingress_manager.on_state_change(&ingress_pool);
+                let change_set = on_state_change_with_verification(&ingress_manager, &ingress_pool);
                 let expected_change_action0 = PurgeBelowExpiry(batch_time);
                 let expected_change_action1 = ChangeAction::MoveToValidated((
                     good_id,
@@ -3,7 +3,7 @@
 //! messages of Consensus payloads and to keep track of finalized Ingress
 //! Messages to ensure that no message is added to a block more than once.
 use crate::IngressManager;
-use ic_cycles_account_manager::IngressInductionCost;
+use ic_cycles_account_manager::{CyclesAccountManager, IngressInductionCost};
 use ic_interfaces::{
     execution_environment::IngressHistoryReader,
     ingress_manager::{
@@ -19,7 +19,7 @@ use ic_types::{
     artifact::IngressMessageId,
     batch::{IngressPayload, ValidationContext},
     ingress::{IngressStatus, MAX_INGRESS_TTL},
-    messages::{MessageId, SignedIngress},
+    messages::{correlation_id, MessageId, SignedIngress},
     CanisterId, CountBytes, Cycles, Height, Time,
 };
 use ic_validator::{validate_request, RequestValidationError};
@@ -63,16 +63,22 @@ impl<'a> IngressSelector for IngressManager {
         }
         .take();
 
-        let min_expiry = context.time;
-        let max_expiry = context.time + MAX_INGRESS_TTL;
-        let expiry_range = min_expiry..=max_expiry;
-
         let settings = self
             .get_ingress_message_settings(context.registry_version)
             .expect("Couldn't fetch ingress message parameters from the registry.");
+        let cycles_account_manager = self
+            .get_cycles_account_manager(context.registry_version)
+            .expect("Couldn't fetch the fee schedule from the registry.");
 
-        // Select valid ingress messages and stop once the total size
-        // becomes greater than ingress_bytes_per_block_soft_cap.
+        // The block time lags behind the clock that the messages in the pool
+        // were validated against, so the clock skew tolerance applies.
+        let min_expiry = context.time;
+        let max_expiry = context.time + settings.expiry_window.max_ttl_with_tolerance();
+        let expiry_range = min_expiry..=max_expiry;
+
+        // Select valid ingress messages, the ones of higher priority classes
+        // first and the canisters of a class in turns, and stop once the
+        // total size becomes greater than ingress_bytes_per_block_soft_cap.
         let mut accumulated_size = 0;
         let mut cycles_needed: BTreeMap<CanisterId, Cycles> = BTreeMap::new();
         let mut num_messages = 0;
@@ -80,12 +86,20 @@ impl<'a> IngressSelector for IngressManager {
         let messages_in_payload = ingress_pool.select_validated(
             expiry_range,
             Box::new(move |ingress_obj| {
+                let span = tracing::debug_span!(
+                    "ingress_selection",
+                    message_id = %ingress_obj.message_id,
+                    correlation_id = %correlation_id(&ingress_obj.message_id),
+                    certified_height = %certified_height,
+                );
+                let _enter = span.enter();
                 let result = self.validate_ingress(
                     IngressMessageId::from(ingress_obj),
                     &ingress_obj.signed_ingress,
                     &state,
                     &context,
                     &settings,
+                    &cycles_account_manager,
                     &past_ingress_set,
                     num_messages,
                     accumulated_size,
@@ -93,6 +107,7 @@ impl<'a> IngressSelector for IngressManager {
                 );
                 match result {
                     Ok(()) => {
+                        tracing::debug!("ingress_included_in_payload");
                         num_messages += 1;
                         accumulated_size += ingress_obj.signed_ingress.count_bytes();
                         SelectResult::Selected(ingress_obj.signed_ingress.clone())
@@ -138,6 +153,9 @@ impl<'a> IngressSelector for IngressManager {
         let settings = self
             .get_ingress_message_settings(context.registry_version)
             .expect("Couldn't get ingress_bytes_per_block_soft_cap from the registry.");
+        let cycles_account_manager = self
+            .get_cycles_account_manager(context.registry_version)
+            .expect("Couldn't fetch the fee schedule from the registry.");
 
         let past_ingress = match IngressSetChain::new(context.time, past_ingress, || {
             IngressHistorySet::new(self.ingress_hist_reader.as_ref(), certified_height)
@@ -191,6 +209,7 @@ impl<'a> IngressSelector for IngressManager {
                 &state,
                 &context,
                 &settings,
+                &cycles_account_manager,
                 &past_ingress,
                 0, // message count is checked above.
                 accumulated_size,
@@ -217,6 +236,7 @@ impl IngressManager {
         state: &ReplicatedState,
         context: &ValidationContext,
         settings: &IngressMessageSettings,
+        cycles_account_manager: &CyclesAccountManager,
         past_ingress_set: &IngressSetChain<IngressHistorySet>,
         num_messages: usize,
         accumulated_size: usize,
@@ -262,14 +282,13 @@ impl IngressManager {
 
         // Skip the message if there aren't enough cycles to induct the message.
         let msg = signed_ingress.content();
-        match self.cycles_account_manager.ingress_induction_cost(msg) {
+        match cycles_account_manager.ingress_induction_cost(msg) {
             Ok(IngressInductionCost::Fee { payer, cost }) => match state.canister_state(&payer) {
                 Some(canister) => {
                     let canister_cycles_needed = cycles_needed
                         .entry(payer)
                         .or_insert_with(|| Cycles::from(0));
-                    let cycles_available = self
-                        .cycles_account_manager
+                    let cycles_available = cycles_account_manager
                         .cycles_balance_above_storage_reserve(
                             &canister.system_state,
                             canister.memory_usage(),
@@ -308,8 +327,9 @@ impl IngressManager {
             signed_ingress.as_ref(),
             self.ingress_signature_crypto.as_ref(),
             context.time,
+            settings.expiry_window.max_ttl_with_tolerance(),
             context.registry_version,
-            &self.malicious_flags,
+            &self.malicious_behaviors,
         ) {
             let message_id = MessageId::from(&ingress_id);
             return Err(ValidationError::Permanent(match err {
@@ -410,7 +430,9 @@ impl<'a, T: IngressSetQuery> IngressSetQuery for IngressSetChain<'a, T> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::tests::{setup, setup_registry, setup_with_params};
+    use crate::tests::{
+        setup, setup_registry, setup_registry_with_subnet_record, setup_with_params,
+    };
     use assert_matches::assert_matches;
     use ic_crypto::crypto_hash;
     use ic_interfaces::artifact_pool::UnvalidatedArtifact;
@@ -422,6 +444,7 @@ mod tests {
         cycles_account_manager::CyclesAccountManagerBuilder,
         history::MockIngressHistory,
         mock_time,
+        registry::test_subnet_record,
         state::{CanisterStateBuilder, ReplicatedStateBuilder},
         types::ids::{canister_test_id, node_test_id, subnet_test_id, user_test_id},
         types::messages::SignedIngressBuilder,
@@ -680,6 +703,60 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_validate_payload_with_configured_expiry_window() {
+        let subnet_id = subnet_test_id(0);
+        let mut subnet_record = test_subnet_record();
+        subnet_record.max_ingress_ttl_millis = 60_000;
+        subnet_record.ingress_clock_skew_tolerance_millis = 10_000;
+        setup_with_params(
+            None,
+            Some((
+                setup_registry_with_subnet_record(subnet_id, subnet_record),
+                subnet_id,
+            )),
+            None,
+            Some(
+                ReplicatedStateBuilder::default()
+                    .with_canister(
+                        CanisterStateBuilder::default()
+                            .with_canister_id(canister_test_id(0))
+                            .build(),
+                    )
+                    .build(),
+            ),
+            |ingress_manager, _| {
+                let time = mock_time();
+                let validation_context = ValidationContext {
+                    time,
+                    registry_version: RegistryVersion::from(1),
+                    certified_height: Height::from(0),
+                };
+                let validate = |expiry_delay: Duration| {
+                    let ingress = SignedIngressBuilder::new()
+                        .canister_id(canister_test_id(0))
+                        .expiry_time(time + expiry_delay)
+                        .build();
+                    ingress_manager.validate_ingress_payload(
+                        &IngressPayload::from(vec![ingress]),
+                        &HashSet::new(),
+                        &validation_context,
+                    )
+                };
+
+                // Messages may expire up to the configured TTL plus the clock
+                // skew tolerance after the block time.
+                assert_matches!(validate(Duration::from_secs(70)), Ok(()));
+                assert_matches!(
+                    validate(Duration::from_secs(71)),
+                    Err(ValidationError::Permanent(
+                        IngressPermanentError::IngressExpired(_, _)
+                    ))
+                );
+            },
+        )
+    }
+
     #[tokio::test]
     // Validation should fail if the ingress message exists in the past payload
     async fn test_validate_ingress_payload_exists() {
@@ -986,6 +1063,79 @@ mod tests {
         )
     }
 
+    #[tokio::test]
+    // Select the message of the higher priority class if only one fits
+    async fn test_get_payload_prefers_higher_priority() {
+        let subnet_id = subnet_test_id(0);
+        let registry = setup_registry(subnet_id, MAX_SIZE / 2, MAX_SIZE);
+        setup_with_params(
+            None,
+            Some((registry, subnet_id)),
+            None,
+            Some(
+                ReplicatedStateBuilder::default()
+                    .with_canister(
+                        CanisterStateBuilder::default()
+                            .with_canister_id(canister_test_id(0))
+                            .build(),
+                    )
+                    .build(),
+            ),
+            |ingress_manager, mut ingress_pool| {
+                let time_source = FastForwardTimeSource::new();
+
+                // The message of the lower class expires first.
+                let ingress_msg1 = SignedIngressBuilder::new()
+                    .canister_id(canister_test_id(0))
+                    .nonce(1)
+                    .expiry_time(mock_time() + MAX_INGRESS_TTL / 2)
+                    .method_payload(vec![0; MAX_SIZE / 2 + 2])
+                    .build();
+                let ingress_msg2 = SignedIngressBuilder::new()
+                    .canister_id(canister_test_id(0))
+                    .nonce(2)
+                    .priority(1)
+                    .expiry_time(mock_time() + MAX_INGRESS_TTL)
+                    .method_payload(vec![0; MAX_SIZE / 2 + 2])
+                    .build();
+
+                for ingress_msg in vec![ingress_msg1, ingress_msg2.clone()] {
+                    let message_id = IngressMessageId::from(&ingress_msg);
+                    let attribute = IngressMessageAttribute::new(&ingress_msg);
+                    let integrity_hash = crypto_hash(ingress_msg.binary()).get();
+                    let size = ingress_msg.count_bytes();
+                    ingress_pool.insert(UnvalidatedArtifact {
+                        message: ingress_msg,
+                        peer_id: node_test_id(0),
+                        timestamp: time_source.get_relative_time(),
+                    });
+                    ingress_pool.apply_changeset(vec![ChangeAction::MoveToValidated((
+                        message_id,
+                        size,
+                        attribute,
+                        integrity_hash,
+                    ))]);
+                }
+
+                let validation_context = ValidationContext {
+                    time: mock_time(),
+                    registry_version: RegistryVersion::from(1),
+                    certified_height: Height::from(0),
+                };
+
+                let ingress_payload = ingress_manager.get_ingress_payload(
+                    &ingress_pool,
+                    &HashSet::new(),
+                    &validation_context,
+                );
+                assert_eq!(
+                    ingress_payload.message_ids(),
+                    vec![IngressMessageId::from(&ingress_msg2)]
+                );
+            },
+        )
+    }
+
     #[tokio::test]
     // Validation should fail if the history status of ingress message is "Received"
     async fn test_validate_ingress_payload_invalid_history() {
@@ -4,6 +4,7 @@
 
 mod ingress_handler;
 mod ingress_selector;
+mod signature_verifier;
 
 use ic_cycles_account_manager::CyclesAccountManager;
 use ic_interfaces::{
@@ -17,11 +18,12 @@ use ic_registry_client::helper::subnet::{IngressMessageSettings, SubnetRegistry}
 use ic_replicated_state::ReplicatedState;
 use ic_types::{
     artifact::IngressMessageId,
-    malicious_flags::MaliciousFlags,
+    malicious_strategies::MaliciousBehaviors,
     time::{Time, UNIX_EPOCH},
     RegistryVersion, SubnetId,
 };
 use prometheus::Histogram;
+use signature_verifier::SignatureVerifier;
 use std::sync::{Arc, RwLock};
 
 /// Keeps the metrics to be exported by the IngressManager
@@ -29,6 +31,7 @@ struct IngressManagerMetrics {
     ingress_handler_time: Histogram,
     ingress_selector_get_payload_time: Histogram,
     ingress_selector_validate_payload_time: Histogram,
+    ingress_handler_verification_batch_time: Histogram,
 }
 
 impl IngressManagerMetrics {
@@ -49,6 +52,11 @@ impl IngressManagerMetrics {
                 "Ingress Selector vaidate_payload execution time in seconds",
                 decimal_buckets(-3, 1),
             ),
+            ingress_handler_verification_batch_time: metrics_registry.histogram(
+                "ingress_handler_verification_batch_time",
+                "Time to verify the signatures of a batch of ingress messages in seconds",
+                decimal_buckets(-3, 1),
+            ),
         }
     }
 }
@@ -70,7 +78,9 @@ pub struct IngressManager {
     pub(crate) last_purge_time: std::sync::RwLock<Time>,
     state_manager: Arc<dyn StateManager<State = ReplicatedState>>,
     cycles_account_manager: Arc<CyclesAccountManager>,
-    malicious_flags: MaliciousFlags,
+    malicious_behaviors: MaliciousBehaviors,
+    /// Verifies the signatures of unvalidated messages on its own threads.
+    signature_verifier: SignatureVerifier,
 }
 
 impl IngressManager {
@@ -86,21 +96,28 @@ impl IngressManager {
         log: ReplicaLogger,
         state_manager: Arc<dyn StateManager<State = ReplicatedState>>,
         cycles_account_manager: Arc<CyclesAccountManager>,
-        malicious_flags: MaliciousFlags,
+        malicious_behaviors: MaliciousBehaviors,
     ) -> Self {
+        let metrics = IngressManagerMetrics::new(metrics_registry);
+        let signature_verifier = SignatureVerifier::new(
+            Arc::clone(&ingress_signature_crypto),
+            malicious_behaviors.clone(),
+            metrics.ingress_handler_verification_batch_time.clone(),
+        );
         Self {
             consensus_pool_cache,
             ingress_hist_reader,
             registry_client,
             ingress_signature_crypto,
-            metrics: IngressManagerMetrics::new(metrics_registry),
+            metrics,
             subnet_id,
             log,
             last_purge_time: std::sync::RwLock::new(UNIX_EPOCH),
             messages_to_purge: RwLock::new(Vec::new()),
             state_manager,
             cycles_account_manager,
-            malicious_flags,
+            malicious_behaviors,
+            signature_verifier,
         }
     }
 
@@ -132,6 +149,30 @@ impl IngressManager {
             Ok(settings) => settings,
         }
     }
+
+    /// Returns a cycles account manager that charges the fees of the fee
+    /// schedule at the given registry version, so that all replicas agree on
+    /// the induction costs of a payload.
+    fn get_cycles_account_manager(
+        &self,
+        registry_version: RegistryVersion,
+    ) -> Option<CyclesAccountManager> {
+        match self
+            .cycles_account_manager
+            .fee_schedule_at(self.registry_client.as_ref(), registry_version)
+        {
+            Ok(fee_schedule) => Some(self.cycles_account_manager.with_fee_schedule(fee_schedule)),
+            Err(err) => {
+                error!(
+                    self.log,
+                    "Could not retrieve the fee schedule at registry version={:?}: {:?}",
+                    registry_version,
+                    err
+                );
+                None
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -140,6 +181,7 @@ mod tests {
     use ic_artifact_pool::ingress_pool::IngressPoolImpl;
     use ic_interfaces::registry::RegistryClient;
     use ic_metrics::MetricsRegistry;
+    use ic_protobuf::registry::subnet::v1::SubnetRecord;
     use ic_registry_client::client::RegistryClientImpl;
     use ic_registry_common::proto_registry_data_provider::ProtoRegistryDataProvider;
     use ic_registry_keys::make_subnet_record_key;
@@ -163,11 +205,17 @@ mod tests {
         ingress_bytes_per_block_soft_cap: usize,
         max_ingress_bytes_per_message: usize,
     ) -> Arc<dyn RegistryClient> {
-        let registry_data_provider = Arc::new(ProtoRegistryDataProvider::new());
         let mut subnet_record = test_subnet_record();
         subnet_record.ingress_bytes_per_block_soft_cap = ingress_bytes_per_block_soft_cap as u64;
         subnet_record.max_ingress_bytes_per_message = max_ingress_bytes_per_message as u64;
+        setup_registry_with_subnet_record(subnet_id, subnet_record)
+    }
 
+    pub(crate) fn setup_registry_with_subnet_record(
+        subnet_id: SubnetId,
+        subnet_record: SubnetRecord,
+    ) -> Arc<dyn RegistryClient> {
+        let registry_data_provider = Arc::new(ProtoRegistryDataProvider::new());
         registry_data_provider
             .add(
                 &make_subnet_record_key(subnet_id),
@@ -237,7 +285,7 @@ mod tests {
                         log.clone(),
                         Arc::new(state_manager),
                         cycles_account_manager,
-                        MaliciousFlags::default(),
+                        MaliciousBehaviors::default(),
                     ),
                     IngressPoolImpl::new(pool_config, metrics_registry, log),
                 )
@@ -0,0 +1,152 @@
+//! The verification of the signatures of unvalidated ingress messages, off
+//! the thread of the ingress processor.
+//!
+//! The ingress handler submits the messages that passed the cheap checks in
+//! batches, and picks up the verification results in later invocations. This
+//! way, a burst of ingress messages does not delay the gossip of the messages
+//! that were already validated.
+
+use ic_interfaces::crypto::IngressSigVerifier;
+use ic_types::{
+    artifact::IngressMessageId, malicious_strategies::MaliciousBehaviors, messages::SignedIngress,
+    time::Time, RegistryVersion,
+};
+use ic_validator::validate_request;
+use prometheus::Histogram;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+/// The number of threads verifying ingress signatures.
+const VERIFICATION_THREADS: usize = 4;
+
+/// The number of messages verified by one job on the verification threads.
+pub(crate) const VERIFICATION_BATCH_SIZE: usize = 32;
+
+/// The outcome of the verification of an ingress message; the error describes
+/// why the message is invalid.
+pub(crate) type VerificationResult = Result<(), String>;
+
+#[derive(Default)]
+struct VerificationState {
+    /// The messages submitted for verification that are not verified yet.
+    in_flight: HashSet<IngressMessageId>,
+    /// The results of the verified messages that were not picked up yet.
+    completed: HashMap<IngressMessageId, VerificationResult>,
+}
+
+/// Verifies the signatures of ingress messages on a dedicated thread pool.
+pub(crate) struct SignatureVerifier {
+    ingress_signature_crypto: Arc<dyn IngressSigVerifier + Send + Sync>,
+    malicious_behaviors: MaliciousBehaviors,
+    thread_pool: rayon::ThreadPool,
+    state: Arc<(Mutex<VerificationState>, Condvar)>,
+    batch_time: Histogram,
+}
+
+impl SignatureVerifier {
+    pub(crate) fn new(
+        ingress_signature_crypto: Arc<dyn IngressSigVerifier + Send + Sync>,
+        malicious_behaviors: MaliciousBehaviors,
+        batch_time: Histogram,
+    ) -> Self {
+        let thread_pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(VERIFICATION_THREADS)
+            .thread_name(|index| format!("ingress_verification_{}", index))
+            .build()
+            .expect("Couldn't build the ingress verification thread pool");
+        Self {
+            ingress_signature_crypto,
+            malicious_behaviors,
+            thread_pool,
+            state: Default::default(),
+            batch_time,
+        }
+    }
+
+    /// Returns the result of the verification of the given message, if it
+    /// was completed, and forgets about it. Returns `None` if the message
+    /// still needs to be submitted or is being verified.
+    pub(crate) fn take_result(&self, id: &IngressMessageId) -> Option<VerificationResult> {
+        self.state.0.lock().unwrap().completed.remove(id)
+    }
+
+    /// Returns true if the given message was submitted and its verification
+    /// is not completed yet.
+    pub(crate) fn is_in_flight(&self, id: &IngressMessageId) -> bool {
+        self.state.0.lock().unwrap().in_flight.contains(id)
+    }
+
+    /// Drops the results of all messages that are not in the given set, e.g.
+    /// because they were purged from the pool before their results were
+    /// picked up.
+    pub(crate) fn retain_results(&self, ids: &HashSet<IngressMessageId>) {
+        self.state
+            .0
+            .lock()
+            .unwrap()
+            .completed
+            .retain(|id, _| ids.contains(id));
+    }
+
+    /// Submits the given messages for verification at the given time, maximum
+    /// ingress TTL and registry version, in batches of
+    /// `VERIFICATION_BATCH_SIZE` messages.
+    pub(crate) fn submit(
+        &self,
+        messages: Vec<(IngressMessageId, SignedIngress)>,
+        current_time: Time,
+        max_ingress_ttl: Duration,
+        registry_version: RegistryVersion,
+    ) {
+        {
+            let mut state = self.state.0.lock().unwrap();
+            for (id, _) in messages.iter() {
+                state.in_flight.insert(id.clone());
+            }
+        }
+        let mut messages = messages.into_iter().peekable();
+        while messages.peek().is_some() {
+            let batch: Vec<_> = messages.by_ref().take(VERIFICATION_BATCH_SIZE).collect();
+            let crypto = Arc::clone(&self.ingress_signature_crypto);
+            let malicious_behaviors = self.malicious_behaviors.clone();
+            let state = Arc::clone(&self.state);
+            let batch_time = self.batch_time.clone();
+            self.thread_pool.spawn(move || {
+                let _timer = batch_time.start_timer();
+                let results: Vec<_> = batch
+                    .into_iter()
+                    .map(|(id, message)| {
+                        let result = validate_request(
+                            message.as_ref(),
+                            crypto.as_ref(),
+                            current_time,
+                            max_ingress_ttl,
+                            registry_version,
+                            &malicious_behaviors,
+                        )
+                        .map_err(|err| err.to_string());
+                        (id, result)
+                    })
+                    .collect();
+                let (lock, condvar) = &*state;
+                let mut state = lock.lock().unwrap();
+                for (id, result) in results {
+                    state.in_flight.remove(&id);
+                    state.completed.insert(id, result);
+                }
+                condvar.notify_all();
+            });
+        }
+    }
+
+    /// Blocks until all submitted messages are verified.
+    #[cfg(test)]
+    pub(crate) fn wait_for_results(&self) {
+        let (lock, condvar) = &*self.state;
+        let mut state = lock.lock().unwrap();
+        while !state.in_flight.is_empty() {
+            state = condvar.wait(state).unwrap();
+        }
+    }
+}
@@ -32,6 +32,8 @@ pub enum OnArtifactError<T> {
     ArtifactPoolError(ArtifactPoolError),
     MessageConversionfailed(p2p::GossipAdvert),
     Throttled,
+    /// The sender of an ingress message exceeded its rate limit.
+    RateLimited,
 }
 
 #[derive(Debug)]
@@ -215,12 +217,28 @@ pub trait ArtifactManager: Send + Sync {
         filter: &artifact::ArtifactFilter,
     ) -> Vec<p2p::GossipAdvert>;
 
+    /// Get adverts of all validated artifacts by the filter from the client
+    /// that is identified by the given artifact tag only.
+    ///
+    /// See `ArtifactClient::get_all_validated_by_filter` for more details.
+    fn get_all_validated_by_filter_for_tag(
+        &self,
+        tag: artifact::ArtifactTag,
+        filter: &artifact::ArtifactFilter,
+    ) -> Vec<p2p::GossipAdvert>;
+
     /// Gets the remaining quota the given peer is allowed to consume for a
     /// specific client that is identified by the given artifact tag.
     ///
     /// See `ArtifactClient::get_remaining_quota` for more details.
     fn get_remaining_quota(&self, tag: artifact::ArtifactTag, peer_id: NodeId) -> Option<usize>;
 
+    /// Checks if the processor of a specific client that is identified by the
+    /// given artifact tag falls behind processing received artifacts. Gossip
+    /// stops requesting chunks of artifacts with this tag while this is the
+    /// case.
+    fn has_backpressure(&self, tag: artifact::ArtifactTag) -> bool;
+
     /// Return the priority function for a specific client that is identified by
     /// the given artifact tag.
     ///
@@ -229,10 +247,13 @@ pub trait ArtifactManager: Send + Sync {
 
     /// Get Chunk tracker for an advert.
     ///
-    /// See `ArtifactClient::get_chunk_tracker` for more details
+    /// Artifacts larger than `chunkable::ARTIFACT_CHUNK_SIZE` are tracked as
+    /// multiple chunks if their kind supports it, see
+    /// `ArtifactKind::multi_chunk_tracker`. Otherwise, see
+    /// `ArtifactClient::get_chunk_tracker` for more details
     fn get_chunk_tracker(
         &self,
-        artifact_id: &artifact::ArtifactId,
+        advert: &p2p::GossipAdvert,
     ) -> Option<Box<dyn chunkable::Chunkable + Send + Sync>>;
 }
 // end::artifact_manager[]
@@ -97,3 +97,12 @@ impl<T> HasTimestamp for UnvalidatedArtifact<T> {
         self.timestamp
     }
 }
+
+/// Reads the dumps of the validated sections of the artifact pools, see
+/// `ic_artifact_pool::dump`.
+pub trait PoolDumpReader: Send + Sync {
+    /// Returns the CBOR encoded dump of the pool with the given name, e.g.
+    /// "consensus", or `None` if there is no such pool.
+    fn get_pool_dump(&self, pool: &str, include_artifacts: bool)
+        -> Option<Result<Vec<u8>, String>>;
+}
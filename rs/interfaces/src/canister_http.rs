@@ -0,0 +1,112 @@
+//! The public interfaces of the HTTP outcalls of canisters, whose responses
+//! are gossiped between replicas and included in blocks once enough replicas
+//! agree on them.
+use crate::{
+    artifact_pool::UnvalidatedArtifact, state_manager::StateManagerError,
+    validation::ValidationError,
+};
+use ic_types::{
+    artifact::{CanisterHttpMessageAttribute, CanisterHttpMessageId, PriorityFn},
+    consensus::canister_http::{
+        CanisterHttpMessage, CanisterHttpRequestId, CanisterHttpResponse, CanisterHttpResponseShare,
+    },
+    crypto::{CryptoError, CryptoHashOf},
+    registry::RegistryClientError,
+    NodeId, RegistryVersion, Time,
+};
+
+/// Various actions that can be performed on the canister HTTP pool.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
+pub enum CanisterHttpChangeAction {
+    /// Adds a response received by this replica to the validated section,
+    /// together with the share of this replica over it.
+    AddToValidated(CanisterHttpResponseShare, CanisterHttpResponse),
+    /// Moves a message from the unvalidated to the validated section.
+    MoveToValidated(CanisterHttpMessage),
+    /// Removes a message from the unvalidated section, e.g. because it is
+    /// already known.
+    RemoveFromUnvalidated(CanisterHttpMessageId),
+    /// Removes an invalid message from the unvalidated section.
+    HandleInvalid(CanisterHttpMessageId, String),
+    /// Removes all messages of requests that timed out before the given time.
+    PurgeTimedOut(Time),
+}
+
+pub type CanisterHttpChangeSet = Vec<CanisterHttpChangeAction>;
+
+/// Artifact pool for the canister HTTP messages (query interface)
+pub trait CanisterHttpPool: Send + Sync {
+    fn get_validated_shares(&self) -> Box<dyn Iterator<Item = &CanisterHttpResponseShare> + '_>;
+    fn get_validated_responses(&self) -> Box<dyn Iterator<Item = &CanisterHttpResponse> + '_>;
+    fn get_unvalidated(&self) -> Box<dyn Iterator<Item = &CanisterHttpMessage> + '_>;
+    /// Returns the validated response with the given hash, if any.
+    fn get_validated_response(
+        &self,
+        content_hash: &CryptoHashOf<CanisterHttpResponse>,
+    ) -> Option<&CanisterHttpResponse>;
+}
+
+/// Artifact pool for the canister HTTP messages (update interface)
+pub trait MutableCanisterHttpPool: CanisterHttpPool {
+    fn insert(&mut self, msg: UnvalidatedArtifact<CanisterHttpMessage>);
+    fn apply_changes(&mut self, change_set: CanisterHttpChangeSet);
+}
+
+/// Validates the canister HTTP messages received from peers, and purges the
+/// ones of requests that timed out.
+pub trait CanisterHttp: Send {
+    fn on_state_change(&self, canister_http_pool: &dyn CanisterHttpPool) -> CanisterHttpChangeSet;
+}
+
+pub trait CanisterHttpGossip: Send + Sync {
+    fn get_priority_function(
+        &self,
+        canister_http_pool: &dyn CanisterHttpPool,
+    ) -> PriorityFn<CanisterHttpMessageId, CanisterHttpMessageAttribute>;
+}
+
+/// Reasons why the canister HTTP responses of a payload are invalid.
+#[derive(Debug)]
+pub enum CanisterHttpPermanentValidationError {
+    /// The response was already included in a past payload, or twice in the
+    /// same payload.
+    DuplicateResponse(CanisterHttpRequestId),
+    /// The canister has no callback of the request in the certified state,
+    /// e.g. because a response to it was already delivered.
+    UnknownRequest(CanisterHttpRequestId),
+    /// The request of the response timed out before the time of the block.
+    Timeout(CanisterHttpRequestId),
+    /// A share was not over the metadata of the response.
+    InvalidMetadata(CanisterHttpRequestId),
+    /// The response did not have enough shares of distinct replicas.
+    NotEnoughShares {
+        id: CanisterHttpRequestId,
+        expected: usize,
+        received: usize,
+    },
+    /// A share was signed by a node that is not in the subnet.
+    SignerNotInSubnet(CanisterHttpRequestId, NodeId),
+    /// A share had an invalid signature.
+    InvalidSignature(CanisterHttpRequestId, CryptoError),
+    /// The responses of the payload exceed the maximum size.
+    PayloadTooLarge { size: usize, max: usize },
+}
+
+/// Reasons why the canister HTTP responses of a payload cannot be validated
+/// yet.
+#[derive(Debug)]
+pub enum CanisterHttpTransientValidationError {
+    /// The registry could not be read.
+    RegistryUnavailable(RegistryClientError),
+    /// The subnet record is missing at the registry version of a share.
+    SubnetNotFound(RegistryVersion),
+    /// A signature could not be verified.
+    Crypto(CryptoError),
+    /// The certified state of the validation context is not available.
+    StateUnavailable(StateManagerError),
+}
+
+/// Canister HTTP payload validation error
+pub type CanisterHttpPayloadValidationError =
+    ValidationError<CanisterHttpPermanentValidationError, CanisterHttpTransientValidationError>;
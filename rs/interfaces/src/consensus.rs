@@ -1,15 +1,24 @@
 //! The consensus public interface.
 use crate::{
+    canister_http::{
+        CanisterHttpPayloadValidationError, CanisterHttpPermanentValidationError,
+        CanisterHttpTransientValidationError,
+    },
     consensus_pool::{ChangeSet, ConsensusPool},
     ingress_manager::{
         IngressPayloadValidationError, IngressPermanentError, IngressTransientError,
     },
     ingress_pool::IngressPoolSelect,
     messaging::{InvalidXNetPayload, XNetPayloadValidationError, XNetTransientValidationError},
+    query_stats::{
+        QueryStatsPayloadValidationError, QueryStatsPermanentValidationError,
+        QueryStatsTransientValidationError,
+    },
     validation::ValidationError,
 };
-use ic_types::artifact::{
-    ConsensusMessageAttribute, ConsensusMessageFilter, ConsensusMessageId, PriorityFn,
+use ic_types::{
+    artifact::{ConsensusMessageAttribute, ConsensusMessageFilter, ConsensusMessageId, PriorityFn},
+    consensus::round_timeline::RoundTimeline,
 };
 
 /// Consensus artifact processing interface.
@@ -47,16 +56,29 @@ pub trait ConsensusGossip: Send + Sync {
     fn get_filter(&self) -> ConsensusMessageFilter;
 }
 
+/// Gives access to the timelines of the latest completed consensus rounds, to
+/// diagnose slow rounds.
+pub trait RoundTimelineReader: Send + Sync {
+    /// Return the timelines of the latest completed rounds, ordered by height.
+    fn get_round_timelines(&self) -> Vec<RoundTimeline>;
+}
+
 #[derive(Debug)]
 pub enum PayloadPermanentError {
     XNetPayloadValidationError(InvalidXNetPayload),
     IngressPayloadValidationError(IngressPermanentError),
+    CanisterHttpPayloadValidationError(CanisterHttpPermanentValidationError),
+    QueryStatsPayloadValidationError(QueryStatsPermanentValidationError),
+    /// The payload has a section that no payload builder knows, by name.
+    UnknownPayloadSection(String),
 }
 
 #[derive(Debug)]
 pub enum PayloadTransientError {
     XNetPayloadValidationError(XNetTransientValidationError),
     IngressPayloadValidationError(IngressTransientError),
+    CanisterHttpPayloadValidationError(CanisterHttpTransientValidationError),
+    QueryStatsPayloadValidationError(QueryStatsTransientValidationError),
 }
 
 /// Payload validation error
@@ -79,3 +101,21 @@ impl From<XNetPayloadValidationError> for PayloadValidationError {
         )
     }
 }
+
+impl From<CanisterHttpPayloadValidationError> for PayloadValidationError {
+    fn from(err: CanisterHttpPayloadValidationError) -> Self {
+        err.map(
+            PayloadPermanentError::CanisterHttpPayloadValidationError,
+            PayloadTransientError::CanisterHttpPayloadValidationError,
+        )
+    }
+}
+
+impl From<QueryStatsPayloadValidationError> for PayloadValidationError {
+    fn from(err: QueryStatsPayloadValidationError) -> Self {
+        err.map(
+            PayloadPermanentError::QueryStatsPayloadValidationError,
+            PayloadTransientError::QueryStatsPayloadValidationError,
+        )
+    }
+}
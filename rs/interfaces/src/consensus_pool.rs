@@ -6,6 +6,7 @@ use crate::{
 };
 use ic_base_types::RegistryVersion;
 use ic_protobuf::types::v1 as pb;
+pub use ic_types::HeightRange;
 use ic_types::{
     artifact::ConsensusMessageId,
     consensus::{
@@ -18,6 +19,7 @@ use ic_types::{
     Height,
 };
 use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
 
 // tag::change_set[]
 pub type ChangeSet = Vec<ChangeAction>;
@@ -101,17 +103,6 @@ pub type ValidatedConsensusArtifact = ValidatedArtifact<ConsensusMessage>;
 /// Unvalidated consensus artifact.
 pub type UnvalidatedConsensusArtifact = UnvalidatedArtifact<ConsensusMessage>;
 
-pub struct HeightRange {
-    pub min: Height,
-    pub max: Height,
-}
-
-impl HeightRange {
-    pub fn new(min: Height, max: Height) -> HeightRange {
-        HeightRange { min, max }
-    }
-}
-
 #[derive(Debug)]
 pub enum OnlyError {
     NoneAvailable,
@@ -208,6 +199,16 @@ pub trait ConsensusPool {
     fn as_cache(&self) -> &dyn ConsensusPoolCache;
 }
 
+/// A consensus pool that can take snapshots.
+pub trait SnapshotConsensusPool {
+    /// Returns a cheap, consistent view of the pool that is not affected by
+    /// later changes to the pool, so that the caller can read it without
+    /// holding the lock of the pool. The validated section of the view only
+    /// holds the artifacts at or above the height of the highest catch-up
+    /// package.
+    fn snapshot(&self) -> Box<dyn ConsensusPool + Send + Sync>;
+}
+
 /// Mutation operations on top of ConsensusPool.
 pub trait MutableConsensusPool: ConsensusPool {
     /// Insert an unvalidated artifact.
@@ -295,6 +296,46 @@ pub trait ConsensusPoolCache: Send + Sync {
             .as_summary()
             .get_subnet_membership_version()
     }
+
+    /// Return a receiver of the height of the latest/highest finalized block,
+    /// which is notified whenever the finalized height increases.
+    fn subscribe_finalized_height(&self) -> watch::Receiver<Height>;
+
+    /// Return a receiver of the certified height referenced by the
+    /// latest/highest finalized block, which is notified whenever this height
+    /// increases.
+    fn subscribe_certified_height(&self) -> watch::Receiver<Height>;
+}
+
+/// The latest value of a height that other components can subscribe to, e.g.
+/// to await the progress of consensus instead of polling the
+/// [ConsensusPoolCache].
+pub struct HeightWatch {
+    sender: watch::Sender<Height>,
+    // Keeps the channel open when there are no other receivers.
+    receiver: watch::Receiver<Height>,
+}
+
+impl HeightWatch {
+    /// Create a new watch with the given initial height.
+    pub fn new(height: Height) -> Self {
+        let (sender, receiver) = watch::channel(height);
+        Self { sender, receiver }
+    }
+
+    /// Return a receiver of the height.
+    pub fn subscribe(&self) -> watch::Receiver<Height> {
+        self.receiver.clone()
+    }
+
+    /// Set the height, and notify the receivers if it changed.
+    pub fn update(&self, height: Height) {
+        if *self.receiver.borrow() != height {
+            // Sending can only fail if there are no receivers, while this
+            // watch holds one.
+            let _ = self.sender.send(height);
+        }
+    }
 }
 
 /// An iterator for block ancestors.
@@ -31,8 +31,11 @@ pub use sign::ThresholdSigVerifierByPublicKey;
 pub use sign::ThresholdSigner;
 pub use sign::{Signable, SignableMock};
 
+use ic_types::consensus::canister_http::CanisterHttpResponseMetadata;
 use ic_types::consensus::certification::CertificationContent;
 use ic_types::consensus::dkg as consensus_dkg;
+use ic_types::consensus::ecdsa::EcdsaMessageContent;
+use ic_types::consensus::query_stats::QueryStatsContent;
 use ic_types::consensus::{
     Block, CatchUpContent, CatchUpContentProtobufBytes, FinalizationContent, NotarizationContent,
     RandomBeaconContent, RandomTapeContent,
@@ -77,6 +80,15 @@ pub trait Crypto:
     // RandomTape
     + ThresholdSigner<RandomTapeContent>
     + ThresholdSigVerifier<RandomTapeContent>
+    // CanisterHttpResponseMetadata
+    + BasicSigner<CanisterHttpResponseMetadata>
+    + BasicSigVerifier<CanisterHttpResponseMetadata>
+    // QueryStatsContent
+    + BasicSigner<QueryStatsContent>
+    + BasicSigVerifier<QueryStatsContent>
+    // EcdsaMessageContent
+    + BasicSigner<EcdsaMessageContent>
+    + BasicSigVerifier<EcdsaMessageContent>
     // Traits for signing/verifying a MerkleRoot
     // (both Multi- and ThresholdSig) will be added at a later stage.
     //
@@ -121,5 +133,11 @@ impl<T> Crypto for T where
         + ThresholdSigVerifier<RandomBeaconContent>
         + ThresholdSigner<RandomTapeContent>
         + ThresholdSigVerifier<RandomTapeContent>
+        + BasicSigner<CanisterHttpResponseMetadata>
+        + BasicSigVerifier<CanisterHttpResponseMetadata>
+        + BasicSigner<QueryStatsContent>
+        + BasicSigVerifier<QueryStatsContent>
+        + BasicSigner<EcdsaMessageContent>
+        + BasicSigVerifier<EcdsaMessageContent>
 {
 }
@@ -1,9 +1,13 @@
-use ic_types::artifact::StateSyncMessage;
+use ic_types::artifact::{RegistryDeltaMessage, StateSyncMessage, XNetStreamSliceMessage};
 use ic_types::consensus::certification::CertificationMessage;
 use ic_types::consensus::dkg as consensus_dkg;
 use ic_types::consensus::{
+    canister_http::{CanisterHttpMessage, CanisterHttpResponse},
     certification::{Certification, CertificationContent, CertificationShare},
     ecdsa::EcdsaMessage,
+    equivocation::EquivocationProof,
+    query_stats::QueryStatsMessage,
+    remote_dkg::RemoteDkgMessage,
     BasicSignature, Block, BlockPayload, CatchUpContent, CatchUpContentProtobufBytes,
     CatchUpShareContent, ConsensusMessage, FinalizationContent, HashedBlock, MultiSignature,
     MultiSignatureShare, NotarizationContent, RandomBeaconContent, RandomTapeContent,
@@ -62,7 +66,24 @@ const DOMAIN_CATCH_UP_PACKAGE_SHARE: &str = "catch_up_package_share_domain";
 const DOMAIN_STATE_SYNC_MESSAGE: &str = "state_sync_message_domain";
 const DOMAIN_CONSENSUS_MESSAGE: &str = "consensus_message_domain";
 const DOMAIN_CERTIFICATION_MESSAGE: &str = "certification_message_domain";
+pub(crate) const DOMAIN_ECDSA_MESSAGE_CONTENT: &str = "ecdsa_message_content_domain";
 const DOMAIN_ECDSA_MESSAGE: &str = "ecdsa_message_domain";
+const DOMAIN_EQUIVOCATION_PROOF: &str = "equivocation_proof_domain";
+const DOMAIN_REMOTE_DKG_MESSAGE: &str = "remote_dkg_message_domain";
+
+const DOMAIN_CANISTER_HTTP_RESPONSE: &str = "canister_http_response_domain";
+pub(crate) const DOMAIN_CANISTER_HTTP_RESPONSE_METADATA: &str =
+    "canister_http_response_metadata_domain";
+const DOMAIN_CANISTER_HTTP_MESSAGE: &str = "canister_http_message_domain";
+
+pub(crate) const DOMAIN_QUERY_STATS_CONTENT: &str = "query_stats_content_domain";
+const DOMAIN_QUERY_STATS_MESSAGE: &str = "query_stats_message_domain";
+
+pub(crate) const DOMAIN_RELEASE_PACKAGE_CONTENT: &str = "release_package_content_domain";
+
+const DOMAIN_XNET_STREAM_SLICE_MESSAGE: &str = "xnet_stream_slice_message_domain";
+
+const DOMAIN_REGISTRY_DELTA_MESSAGE: &str = "registry_delta_message_domain";
 
 /// A cryptographically hashable type.
 pub trait CryptoHashable: CryptoHashDomain + Hash {}
@@ -141,6 +162,13 @@ mod private {
     impl CryptoHashDomainSeal for ConsensusMessage {}
     impl CryptoHashDomainSeal for CertificationMessage {}
     impl CryptoHashDomainSeal for EcdsaMessage {}
+    impl CryptoHashDomainSeal for EquivocationProof {}
+    impl CryptoHashDomainSeal for RemoteDkgMessage {}
+    impl CryptoHashDomainSeal for CanisterHttpResponse {}
+    impl CryptoHashDomainSeal for CanisterHttpMessage {}
+    impl CryptoHashDomainSeal for QueryStatsMessage {}
+    impl CryptoHashDomainSeal for XNetStreamSliceMessage {}
+    impl CryptoHashDomainSeal for RegistryDeltaMessage {}
 
     impl CryptoHashDomainSeal for CryptoHashableTestDummy {}
 }
@@ -339,6 +367,48 @@ impl CryptoHashDomain for EcdsaMessage {
     }
 }
 
+impl CryptoHashDomain for EquivocationProof {
+    fn domain(&self) -> String {
+        DOMAIN_EQUIVOCATION_PROOF.to_string()
+    }
+}
+
+impl CryptoHashDomain for RemoteDkgMessage {
+    fn domain(&self) -> String {
+        DOMAIN_REMOTE_DKG_MESSAGE.to_string()
+    }
+}
+
+impl CryptoHashDomain for CanisterHttpResponse {
+    fn domain(&self) -> String {
+        DOMAIN_CANISTER_HTTP_RESPONSE.to_string()
+    }
+}
+
+impl CryptoHashDomain for CanisterHttpMessage {
+    fn domain(&self) -> String {
+        DOMAIN_CANISTER_HTTP_MESSAGE.to_string()
+    }
+}
+
+impl CryptoHashDomain for QueryStatsMessage {
+    fn domain(&self) -> String {
+        DOMAIN_QUERY_STATS_MESSAGE.to_string()
+    }
+}
+
+impl CryptoHashDomain for XNetStreamSliceMessage {
+    fn domain(&self) -> String {
+        DOMAIN_XNET_STREAM_SLICE_MESSAGE.to_string()
+    }
+}
+
+impl CryptoHashDomain for RegistryDeltaMessage {
+    fn domain(&self) -> String {
+        DOMAIN_REGISTRY_DELTA_MESSAGE.to_string()
+    }
+}
+
 impl CryptoHashDomain for CryptoHashableTestDummy {
     fn domain(&self) -> String {
         "test_struct_domain".to_string()
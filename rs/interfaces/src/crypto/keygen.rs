@@ -14,4 +14,21 @@ pub trait KeyManager {
     /// created. Node public keys stay the same throughout the lifetime of
     /// the component.
     fn node_public_keys(&self) -> NodePublicKeys;
+
+    /// Generates a new committee signing key pair and a new TLS key pair for
+    /// rotating this node's keys. The secret keys are added to the secret key
+    /// store next to the current ones, which remain usable for the registry
+    /// versions at which they are registered.
+    ///
+    /// Returns the node public keys with the new committee signing key and TLS
+    /// certificate, and the current keys otherwise.
+    fn generate_rotated_node_keys(&self) -> CryptoResult<NodePublicKeys>;
+
+    /// Removes the secret keys of the committee signing key and the TLS
+    /// certificate in `rotated_out_keys` from the secret key store, once the
+    /// keys that replaced them are used at all registry versions still in use.
+    ///
+    /// Returns `CryptoError::InvalidArgument` if one of the keys is still
+    /// registered for this node at the latest registry version.
+    fn remove_rotated_out_node_keys(&self, rotated_out_keys: &NodePublicKeys) -> CryptoResult<()>;
 }
@@ -23,9 +23,10 @@
 //! Please refer to the trait documentation for details.
 
 use crate::crypto::hash::{
-    DOMAIN_BLOCK, DOMAIN_CATCH_UP_CONTENT, DOMAIN_CERTIFICATION_CONTENT, DOMAIN_DEALING_CONTENT,
-    DOMAIN_FINALIZATION_CONTENT, DOMAIN_NOTARIZATION_CONTENT, DOMAIN_RANDOM_BEACON_CONTENT,
-    DOMAIN_RANDOM_TAPE_CONTENT,
+    DOMAIN_BLOCK, DOMAIN_CANISTER_HTTP_RESPONSE_METADATA, DOMAIN_CATCH_UP_CONTENT,
+    DOMAIN_CERTIFICATION_CONTENT, DOMAIN_DEALING_CONTENT, DOMAIN_ECDSA_MESSAGE_CONTENT,
+    DOMAIN_FINALIZATION_CONTENT, DOMAIN_NOTARIZATION_CONTENT, DOMAIN_QUERY_STATS_CONTENT,
+    DOMAIN_RANDOM_BEACON_CONTENT, DOMAIN_RANDOM_TAPE_CONTENT, DOMAIN_RELEASE_PACKAGE_CONTENT,
 };
 use ic_types::crypto::{
     BasicSigOf, CanisterSigOf, CombinedMultiSigOf, CryptoResult, IndividualMultiSigOf,
@@ -34,10 +35,12 @@ use ic_types::crypto::{
 use ic_types::messages::{Delegation, MessageId, WebAuthnEnvelope};
 use ic_types::{
     consensus::{
-        certification::CertificationContent, dkg::DealingContent, Block, CatchUpContent,
-        CatchUpContentProtobufBytes, FinalizationContent, NotarizationContent, RandomBeaconContent,
-        RandomTapeContent,
+        canister_http::CanisterHttpResponseMetadata, certification::CertificationContent,
+        dkg::DealingContent, ecdsa::EcdsaMessageContent, query_stats::QueryStatsContent, Block,
+        CatchUpContent, CatchUpContentProtobufBytes, FinalizationContent, NotarizationContent,
+        RandomBeaconContent, RandomTapeContent,
     },
+    replica_version::ReleasePackageContent,
     NodeId, RegistryVersion,
 };
 use std::collections::{BTreeMap, BTreeSet};
@@ -96,6 +99,9 @@ mod private {
     impl SignatureDomainSeal for CatchUpContentProtobufBytes {}
     impl SignatureDomainSeal for RandomBeaconContent {}
     impl SignatureDomainSeal for RandomTapeContent {}
+    impl SignatureDomainSeal for CanisterHttpResponseMetadata {}
+    impl SignatureDomainSeal for QueryStatsContent {}
+    impl SignatureDomainSeal for EcdsaMessageContent {}
     impl SignatureDomainSeal for SignableMock {}
 }
 
@@ -175,6 +181,30 @@ impl SignatureDomain for RandomTapeContent {
     }
 }
 
+impl SignatureDomain for CanisterHttpResponseMetadata {
+    fn domain(&self) -> Vec<u8> {
+        domain_with_prepended_length(DOMAIN_CANISTER_HTTP_RESPONSE_METADATA)
+    }
+}
+
+impl SignatureDomain for QueryStatsContent {
+    fn domain(&self) -> Vec<u8> {
+        domain_with_prepended_length(DOMAIN_QUERY_STATS_CONTENT)
+    }
+}
+
+impl SignatureDomain for EcdsaMessageContent {
+    fn domain(&self) -> Vec<u8> {
+        domain_with_prepended_length(DOMAIN_ECDSA_MESSAGE_CONTENT)
+    }
+}
+
+impl SignatureDomain for ReleasePackageContent {
+    fn domain(&self) -> Vec<u8> {
+        domain_with_prepended_length(DOMAIN_RELEASE_PACKAGE_CONTENT)
+    }
+}
+
 // Returns a vector of bytes that contains the given domain
 // prepended with a single byte that holds the length of the domain.
 // This is the recommended format for non-empty domain separators,
@@ -396,6 +426,28 @@ pub trait MultiSigVerifier<T: Signable> {
         registry_version: RegistryVersion,
     ) -> CryptoResult<()>;
 
+    /// Verifies the individual multi-signatures of several signers on the
+    /// same `message` at once, which is faster than verifying them one by one.
+    /// If the verification fails, at least one of the signatures is invalid,
+    /// and the signatures have to be verified individually to find out which
+    /// ones.
+    ///
+    /// The default implementation verifies the signatures one by one.
+    ///
+    /// # Errors
+    /// * The same errors as `verify_multi_sig_individual`, for any of the
+    ///   signatures.
+    fn verify_multi_sig_individual_batch(
+        &self,
+        signatures: &BTreeMap<NodeId, IndividualMultiSigOf<T>>,
+        message: &T,
+        registry_version: RegistryVersion,
+    ) -> CryptoResult<()> {
+        signatures.iter().try_for_each(|(signer, signature)| {
+            self.verify_multi_sig_individual(signature, message, *signer, registry_version)
+        })
+    }
+
     /// Combines individual multi-signature shares.
     ///
     /// The registry version is not needed for the cryptographic scheme we use
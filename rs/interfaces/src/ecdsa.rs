@@ -3,15 +3,31 @@
 use crate::artifact_pool::UnvalidatedArtifact;
 use ic_types::artifact::{EcdsaMessageAttribute, EcdsaMessageId, PriorityFn};
 use ic_types::consensus::ecdsa::EcdsaMessage;
+use ic_types::Height;
 
+/// Various actions that can be performed on the ECDSA pool.
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug)]
-pub enum EcdsaChangeAction {}
+pub enum EcdsaChangeAction {
+    /// Adds a message created by this node to the validated section.
+    AddToValidated(EcdsaMessage),
+    /// Moves a message from the unvalidated to the validated section.
+    MoveToValidated(EcdsaMessage),
+    /// Removes a message from the unvalidated section, e.g. because it is
+    /// already known.
+    RemoveFromUnvalidated(EcdsaMessageId),
+    /// Removes an invalid message from the unvalidated section.
+    HandleInvalid(EcdsaMessageId, String),
+    /// Removes all messages requested below the given height.
+    PurgeBelow(Height),
+}
 
 pub type EcdsaChangeSet = Vec<EcdsaChangeAction>;
 
 /// Artifact pool for the ECDSA messages (query interface)
 pub trait EcdsaPool: Send + Sync {
     fn get_validated(&self) -> Box<dyn Iterator<Item = &EcdsaMessage> + '_>;
+    fn get_unvalidated(&self) -> Box<dyn Iterator<Item = &EcdsaMessage> + '_>;
 }
 
 /// Artifact pool for the ECDSA messages (update interface)
@@ -0,0 +1,56 @@
+//! The public interfaces of the detection and gossip of equivocating block
+//! makers.
+use crate::{artifact_pool::UnvalidatedArtifact, consensus_pool::ConsensusPool};
+use ic_types::{
+    artifact::{EquivocationProofAttribute, EquivocationProofId, PriorityFn},
+    consensus::equivocation::EquivocationProof,
+    Height,
+};
+
+/// Various actions that can be performed on the equivocation pool.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
+pub enum EquivocationChangeAction {
+    /// Adds a proof that was detected locally to the validated section.
+    AddToValidated(EquivocationProof),
+    /// Moves a proof from the unvalidated to the validated section.
+    MoveToValidated(EquivocationProof),
+    /// Removes a proof from the unvalidated section, e.g. because it is
+    /// already known.
+    RemoveFromUnvalidated(EquivocationProofId),
+    /// Removes an invalid proof from the unvalidated section.
+    HandleInvalid(EquivocationProofId, String),
+    /// Removes all proofs of proposals below the given height.
+    PurgeBelow(Height),
+}
+
+pub type EquivocationChangeSet = Vec<EquivocationChangeAction>;
+
+/// Artifact pool for the equivocation proofs (query interface)
+pub trait EquivocationPool: Send + Sync {
+    fn get_validated(&self) -> Box<dyn Iterator<Item = &EquivocationProof> + '_>;
+    fn get_unvalidated(&self) -> Box<dyn Iterator<Item = &EquivocationProof> + '_>;
+}
+
+/// Artifact pool for the equivocation proofs (update interface)
+pub trait MutableEquivocationPool: EquivocationPool {
+    fn insert(&mut self, msg: UnvalidatedArtifact<EquivocationProof>);
+    fn apply_changes(&mut self, change_set: EquivocationChangeSet);
+}
+
+/// Detects equivocating block makers in the consensus pool, and validates the
+/// equivocation proofs received from peers.
+pub trait Equivocation: Send {
+    fn on_state_change(
+        &self,
+        consensus_pool: &dyn ConsensusPool,
+        equivocation_pool: &dyn EquivocationPool,
+    ) -> EquivocationChangeSet;
+}
+
+pub trait EquivocationGossip: Send + Sync {
+    fn get_priority_function(
+        &self,
+        equivocation_pool: &dyn EquivocationPool,
+    ) -> PriorityFn<EquivocationProofId, EquivocationProofAttribute>;
+}
@@ -14,6 +14,7 @@ use ic_types::{
 };
 use serde::{Deserialize, Serialize};
 use std::sync::{Arc, RwLock};
+use tokio::sync::{mpsc, oneshot};
 
 /// Instance execution statistics. The stats are cumulative and
 /// contain measurements from the point in time when the instance was
@@ -174,6 +175,28 @@ pub trait IngressHistoryReader: Send + Sync {
         &self,
         height: Height,
     ) -> Result<Box<dyn Fn(&MessageId) -> IngressStatus>, IngressHistoryError>;
+
+    /// Returns a receiver of the status of the given `message_id` once it is
+    /// terminal (i.e. completed or failed) in the latest certified state, so
+    /// that callers can await the outcome of a message instead of polling the
+    /// state.
+    ///
+    /// The caller drops the receiver once it is no longer interested.
+    fn subscribe_to_terminal_status(
+        &self,
+        message_id: MessageId,
+    ) -> oneshot::Receiver<IngressStatus>;
+
+    /// Returns a receiver of the statuses of the given `message_id` in the
+    /// latest certified state: first the current status, and then every new
+    /// status it transitions to. The receiver is closed after the terminal
+    /// status has been sent.
+    ///
+    /// The caller drops the receiver once it is no longer interested.
+    fn subscribe_to_status_transitions(
+        &self,
+        message_id: MessageId,
+    ) -> mpsc::UnboundedReceiver<IngressStatus>;
 }
 
 /// Interface for updating the history of ingress messages.
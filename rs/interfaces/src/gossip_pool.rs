@@ -1,14 +1,23 @@
 //! The gossip pool public interface.
 use crate::{
-    artifact_pool::ArtifactPoolError, certification::ChangeSet as CertificationChangeSet,
+    artifact_pool::ArtifactPoolError, canister_http::CanisterHttpChangeSet,
+    certification::ChangeSet as CertificationChangeSet,
     consensus_pool::ChangeSet as ConsensusChangeSet, dkg::ChangeSet as DkgChangeSet,
-    ecdsa::EcdsaChangeSet, ingress_pool::ChangeSet as IngressChangeSet,
+    ecdsa::EcdsaChangeSet, equivocation::EquivocationChangeSet,
+    ingress_pool::ChangeSet as IngressChangeSet, query_stats::QueryStatsChangeSet,
+    remote_dkg::RemoteDkgChangeSet,
 };
 use ic_types::{
     artifact::{
-        CertificationMessageId, ConsensusMessageId, DkgMessageId, EcdsaMessageId, IngressMessageId,
+        CanisterHttpMessageId, CertificationMessageId, ConsensusMessageId, DkgMessageId,
+        EcdsaMessageId, EquivocationProofId, IngressMessageId, QueryStatsMessageId,
+        RemoteDkgMessageId,
+    },
+    consensus::{
+        canister_http::CanisterHttpMessage, certification::CertificationMessage, dkg,
+        ecdsa::EcdsaMessage, equivocation::EquivocationProof, query_stats::QueryStatsMessage,
+        remote_dkg::RemoteDkgMessage, ConsensusMessage,
     },
-    consensus::{certification::CertificationMessage, dkg, ecdsa::EcdsaMessage, ConsensusMessage},
     messages::SignedIngress,
     Height, NodeId, Time,
 };
@@ -90,3 +99,32 @@ pub trait EcdsaGossipPool:
     GossipPool<EcdsaMessage, EcdsaChangeSet, MessageId = EcdsaMessageId, Filter = ()>
 {
 }
+
+/// GossipPool trait for EquivocationPool
+pub trait EquivocationGossipPool:
+    GossipPool<EquivocationProof, EquivocationChangeSet, MessageId = EquivocationProofId, Filter = ()>
+{
+}
+
+/// GossipPool trait for RemoteDkgPool
+pub trait RemoteDkgGossipPool:
+    GossipPool<RemoteDkgMessage, RemoteDkgChangeSet, MessageId = RemoteDkgMessageId, Filter = ()>
+{
+}
+
+/// GossipPool trait for CanisterHttpPool
+pub trait CanisterHttpGossipPool:
+    GossipPool<
+    CanisterHttpMessage,
+    CanisterHttpChangeSet,
+    MessageId = CanisterHttpMessageId,
+    Filter = (),
+>
+{
+}
+
+/// GossipPool trait for QueryStatsPool
+pub trait QueryStatsGossipPool:
+    GossipPool<QueryStatsMessage, QueryStatsChangeSet, MessageId = QueryStatsMessageId, Filter = ()>
+{
+}
@@ -0,0 +1,9 @@
+//! The health self-assessment public interface.
+use ic_types::messages::ReplicaHealthAssessment;
+
+/// Assesses the health of the replica from the progress of its components,
+/// e.g. to report it on the status endpoint.
+pub trait ReplicaHealthAssessor: Send + Sync {
+    /// Return the current health of the replica.
+    fn assess_health(&self) -> ReplicaHealthAssessment;
+}
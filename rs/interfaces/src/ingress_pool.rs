@@ -1,10 +1,10 @@
 //! The ingress pool public interface.
-use crate::artifact_pool::{UnvalidatedArtifact, ValidatedArtifact};
+use crate::artifact_pool::{ArtifactPoolError, UnvalidatedArtifact, ValidatedArtifact};
 use ic_types::{
     artifact::{IngressMessageAttribute, IngressMessageId},
     crypto::CryptoHash,
     messages::{MessageId, SignedIngress},
-    CountBytes, Time,
+    CanisterId, CountBytes, NodeId, Time,
 };
 // tag::interface[]
 
@@ -132,7 +132,9 @@ pub enum SelectResult<T> {
 
 /// A query interface that selects qualifying artifacts from the validated pool.
 pub trait IngressPoolSelect {
-    /// Select qualifying objects from the validated pool.
+    /// Select qualifying objects from the validated pool. Objects of higher
+    /// priority classes are offered first; within a class, the canisters take
+    /// turns and the objects of a canister are offered by expiry.
     fn select_validated<'a>(
         &self,
         range: std::ops::RangeInclusive<Time>,
@@ -140,9 +142,39 @@ pub trait IngressPoolSelect {
     ) -> Vec<SignedIngress>;
 }
 
+/// An ingress pool that can take snapshots of its validated section.
+pub trait SnapshotIngressPool {
+    /// Returns a cheap, consistent view of the validated section that is not
+    /// affected by later changes to the pool, so that the caller can select
+    /// artifacts without holding the lock of the pool.
+    fn snapshot(&self) -> Box<dyn IngressPoolSelect + Send + Sync>;
+}
+
+/// Lookups in the ingress pool that do not take the lock of the whole pool,
+/// for the paths handling the ingress messages of users and peers.
+pub trait IngressPoolLookup: Send + Sync {
+    /// Checks if the pool contains the message with the given ID.
+    fn contains(&self, id: &IngressMessageId) -> bool;
+
+    /// Returns the validated message with the given ID, if any.
+    fn get_validated_by_identifier(&self, id: &IngressMessageId) -> Option<SignedIngress>;
+
+    /// Checks that the given message from the given peer fits in the quota of
+    /// the peer and of the canister of the message.
+    fn check_quota(
+        &self,
+        message: &SignedIngress,
+        peer_id: &NodeId,
+    ) -> Result<(), ArtifactPoolError>;
+}
+
 /// Interface to throttle user ingress messages
 pub trait IngressPoolThrottler {
     /// Checks if the total number of entries is within the configured threshold
     fn exceeds_threshold(&self) -> bool;
+
+    /// Checks if admitting a message of the given size to the given canister
+    /// would exceed the configured quota of the canister
+    fn exceeds_canister_quota(&self, canister_id: &CanisterId, size_bytes: usize) -> bool;
 }
 // end::interface[]
@@ -4,6 +4,7 @@
 //! helps reduce unnecessary dependencies between them.
 pub mod artifact_manager;
 pub mod artifact_pool;
+pub mod canister_http;
 pub mod certification;
 pub mod certified_stream_store;
 pub mod consensus;
@@ -11,14 +12,18 @@ pub mod consensus_pool;
 pub mod crypto;
 pub mod dkg;
 pub mod ecdsa;
+pub mod equivocation;
 pub mod execution_environment;
 pub mod gossip_pool;
+pub mod health;
 pub mod ingress_manager;
 pub mod ingress_pool;
 pub mod messages;
 pub mod messaging;
 pub mod p2p;
+pub mod query_stats;
 pub mod registry;
+pub mod remote_dkg;
 pub mod replica_config;
 pub mod state_manager;
 pub mod time_source;
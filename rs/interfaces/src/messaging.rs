@@ -18,6 +18,45 @@ pub enum MessageRoutingError {
     },
 }
 
+/// The batches delivered to `MessageRouting` that were not fully executed
+/// yet, relative to the number of batches it accepts ahead of execution.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BatchPipelineOccupancy {
+    /// The number of delivered batches that are queued or being executed.
+    pub pending_batches: usize,
+    /// The number of batches that can be queued ahead of the one being
+    /// executed before `deliver_batch()` returns `QueueIsFull`.
+    pub capacity: usize,
+}
+
+/// Whether the execution round of a batch was sliced, i.e. whether the round
+/// ran out of instructions before it executed all the messages of the
+/// running canisters, deferring the rest to later rounds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RoundSlicingStatus {
+    /// The height of the batch the round executed.
+    pub height: Height,
+    /// The number of messages left in the queues of running canisters at the
+    /// end of the round.
+    pub deferred_messages: usize,
+}
+
+impl RoundSlicingStatus {
+    /// Returns true if any message was deferred to later rounds.
+    pub fn is_sliced(&self) -> bool {
+        self.deferred_messages > 0
+    }
+}
+
+/// The result of a successful `deliver_batch()`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BatchDelivered {
+    /// The slicing status of the most recently executed round, if any round
+    /// was executed yet. The delivered batch itself is executed
+    /// asynchronously, so this is usually the status of an earlier batch.
+    pub last_executed_round: Option<RoundSlicingStatus>,
+}
+
 /// XNet payload validation error details.
 #[derive(Debug)]
 pub enum InvalidXNetPayload {
@@ -43,10 +82,18 @@ pub trait MessageRouting: Send + Sync {
     /// This function is asynchronous: it returns immediately after enqueuing
     /// the batch for processing and doesn't wait for execution of the batch to
     /// complete.
-    fn deliver_batch(&self, b: Batch) -> Result<(), MessageRoutingError>;
+    fn deliver_batch(&self, b: Batch) -> Result<BatchDelivered, MessageRoutingError>;
 
     /// Returns the height of the next expected batch.
     fn expected_batch_height(&self) -> Height;
+
+    /// Returns the occupancy of the pipeline of delivered batches, so that
+    /// consensus can slow down when execution falls behind.
+    fn batch_pipeline_occupancy(&self) -> BatchPipelineOccupancy;
+
+    /// Returns the slicing status of the most recently executed round, so
+    /// that block makers can leave room for resumed long-running messages.
+    fn last_round_slicing_status(&self) -> Option<RoundSlicingStatus>;
 }
 
 /// Interface for selecting `Streams` for inclusion into a `Payload`.
@@ -8,6 +8,13 @@ use crate::artifact_manager::OnArtifactError;
 /// given ingress message in a *Gossip* artifact and sends it to the P2P
 /// `GossipArtifact` channel. It is mainly to be used by the HTTP handler to
 /// submit ingress messages.
+///
+/// The way of an ingress message through the replica can be followed with
+/// `tracing`: the submission, the insertion into the ingress pool, the
+/// selection for a payload, the delivery in a batch and the execution each
+/// emit a span or event with a `message_id` field, which holds the ID of the
+/// message, and a `correlation_id` field, which holds the correlation ID the
+/// replica assigned to the message, see `ic_types::messages::correlation_id`.
 pub trait IngressEventHandler: Send + Sync {
     /// The method is called when an ingress message is received.
     fn on_ingress_message(&self, message: SignedIngress) -> Result<(), OnArtifactError<Artifact>>;
@@ -24,3 +31,19 @@ pub trait P2PRunner: Send {
     /// The method starts the execution of the `P2PRunner`.
     fn run(&mut self);
 }
+
+/// The connections of this node to the other nodes of its subnet.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PeerConnectivity {
+    /// The number of other nodes in the subnet.
+    pub peers: usize,
+    /// The number of other nodes in the subnet that are currently connected.
+    pub connected_peers: usize,
+}
+
+/// Reports the connections of this node to its peers, e.g. for the health
+/// self-assessment of the replica.
+pub trait PeerConnectivityReader: Send + Sync {
+    /// The method returns the current connectivity to the peers.
+    fn peer_connectivity(&self) -> PeerConnectivity;
+}
@@ -0,0 +1,112 @@
+//! The public interfaces of the query statistics, which replicas collect on
+//! the queries they execute, report once per epoch, and aggregate in the
+//! replicated state.
+use crate::{artifact_pool::UnvalidatedArtifact, validation::ValidationError};
+use ic_types::{
+    artifact::{PriorityFn, QueryStatsMessageAttribute, QueryStatsMessageId},
+    consensus::query_stats::{QueryStats, QueryStatsEpoch, QueryStatsMessage},
+    crypto::CryptoError,
+    registry::RegistryClientError,
+    CanisterId, NodeId, RegistryVersion,
+};
+use std::collections::BTreeMap;
+
+/// Records the statistics of the queries executed by this replica.
+pub trait QueryStatsCollector: Send + Sync {
+    /// Adds the statistics of a query executed on the given canister.
+    fn register_query(&self, canister_id: CanisterId, stats: QueryStats);
+}
+
+/// Hands out the statistics of the queries executed by this replica.
+pub trait QueryStatsReader: Send + Sync {
+    /// Returns the statistics collected since the last call, by canister.
+    fn take_stats(&self) -> BTreeMap<CanisterId, QueryStats>;
+
+    /// Adds back taken statistics that were not reported, so that they are
+    /// returned by the next call to `take_stats`.
+    fn return_stats(&self, stats: BTreeMap<CanisterId, QueryStats>);
+}
+
+/// Various actions that can be performed on the query statistics pool.
+#[derive(Debug)]
+pub enum QueryStatsChangeAction {
+    /// Adds the report of this replica to the validated section.
+    AddToValidated(QueryStatsMessage),
+    /// Moves a report from the unvalidated to the validated section.
+    MoveToValidated(QueryStatsMessage),
+    /// Removes a report from the unvalidated section, e.g. because its epoch
+    /// is over or its signer already reported.
+    RemoveFromUnvalidated(QueryStatsMessageId),
+    /// Removes an invalid report from the unvalidated section.
+    HandleInvalid(QueryStatsMessageId, String),
+    /// Removes all reports of epochs below the given one.
+    PurgeBelowEpoch(QueryStatsEpoch),
+}
+
+pub type QueryStatsChangeSet = Vec<QueryStatsChangeAction>;
+
+/// Artifact pool for the query statistics reports (query interface)
+pub trait QueryStatsPool: Send + Sync {
+    fn get_validated(&self) -> Box<dyn Iterator<Item = &QueryStatsMessage> + '_>;
+    fn get_unvalidated(&self) -> Box<dyn Iterator<Item = &QueryStatsMessage> + '_>;
+}
+
+/// Artifact pool for the query statistics reports (update interface)
+pub trait MutableQueryStatsPool: QueryStatsPool {
+    fn insert(&mut self, msg: UnvalidatedArtifact<QueryStatsMessage>);
+    fn apply_changes(&mut self, change_set: QueryStatsChangeSet);
+}
+
+/// Creates the report of this replica on every new epoch, validates the
+/// reports received from peers, and purges the ones of past epochs.
+pub trait QueryStatsHandler: Send {
+    fn on_state_change(&self, query_stats_pool: &dyn QueryStatsPool) -> QueryStatsChangeSet;
+}
+
+pub trait QueryStatsGossip: Send + Sync {
+    fn get_priority_function(
+        &self,
+        query_stats_pool: &dyn QueryStatsPool,
+    ) -> PriorityFn<QueryStatsMessageId, QueryStatsMessageAttribute>;
+}
+
+/// Reasons why the query statistics reports of a payload are invalid.
+#[derive(Debug)]
+pub enum QueryStatsPermanentValidationError {
+    /// The signer already reported for the epoch, in a past payload or in the
+    /// same payload.
+    DuplicateReport(NodeId, QueryStatsEpoch),
+    /// The report is not for the epoch before the one of the certified
+    /// height.
+    UnexpectedEpoch {
+        expected: QueryStatsEpoch,
+        received: QueryStatsEpoch,
+    },
+    /// The report is verified at a registry version above the one of the
+    /// payload.
+    RegistryVersionTooHigh(NodeId, RegistryVersion),
+    /// The report was signed by a node that is not in the subnet.
+    SignerNotInSubnet(NodeId),
+    /// The report had an invalid signature.
+    InvalidSignature(NodeId, CryptoError),
+    /// The report has statistics of more canisters than allowed.
+    TooManyCanisters(NodeId, usize),
+    /// The reports of the payload are larger than allowed.
+    PayloadTooLarge { size: usize, max: usize },
+}
+
+/// Reasons why the query statistics reports of a payload cannot be
+/// validated yet.
+#[derive(Debug)]
+pub enum QueryStatsTransientValidationError {
+    /// The registry could not be read.
+    RegistryUnavailable(RegistryClientError),
+    /// The subnet record is missing at the registry version of a report.
+    SubnetNotFound(RegistryVersion),
+    /// A signature could not be verified.
+    Crypto(CryptoError),
+}
+
+/// Query statistics payload validation error
+pub type QueryStatsPayloadValidationError =
+    ValidationError<QueryStatsPermanentValidationError, QueryStatsTransientValidationError>;
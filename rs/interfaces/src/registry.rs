@@ -5,6 +5,7 @@ use ic_types::{
 pub use prost::Message as RegistryValue;
 use serde::{Deserialize, Serialize};
 use std::{cmp::Eq, fmt::Debug, hash::Hash, time::Duration};
+use tokio::sync::watch;
 
 /// The registry at version `0` is the empty registry.
 pub const ZERO_REGISTRY_VERSION: RegistryVersion = RegistryVersion::new(0);
@@ -97,6 +98,16 @@ pub trait RegistryClient: Send + Sync {
     /// Returns the time at which the given version became available locally or
     /// None if the version is not available locally,
     fn get_version_timestamp(&self, registry_version: RegistryVersion) -> Option<Time>;
+
+    /// Returns a receiver of the latest version at which a key starting with
+    /// `key_prefix` was added, updated or removed, where the versions at which
+    /// no such key changed are skipped. If no such key changed yet, the
+    /// receiver holds `ZERO_REGISTRY_VERSION`.
+    ///
+    /// This allows components to react to the registry changes relevant to
+    /// them as soon as they become available locally, rather than polling
+    /// `get_latest_version()`. All keys match the empty prefix.
+    fn subscribe(&self, key_prefix: &str) -> watch::Receiver<RegistryVersion>;
 }
 
 /// A versioned (Key, Value) pair returned from the registry.
@@ -162,6 +173,16 @@ pub trait RegistryDataProvider: Send + Sync {
         &self,
         version: RegistryVersion,
     ) -> Result<Vec<RegistryTransportRecord>, RegistryDataProviderError>;
+
+    /// Like `get_updates_since()`, but also returns the time at which the NNS
+    /// certified that the returned records are the latest ones, if the data
+    /// provider verifies the certified responses of the registry canister.
+    fn get_certified_updates_since(
+        &self,
+        version: RegistryVersion,
+    ) -> Result<(Vec<RegistryTransportRecord>, Option<Time>), RegistryDataProviderError> {
+        Ok((self.get_updates_since(version)?, None))
+    }
 }
 
 /// Whenever the local store is successfully updated, the time contained in the
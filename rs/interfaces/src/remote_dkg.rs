@@ -0,0 +1,57 @@
+//! The public interfaces of the gossip of the DKGs that a subnet runs for
+//! other subnets.
+use crate::{artifact_pool::UnvalidatedArtifact, consensus_pool::ConsensusPoolCache};
+use ic_types::{
+    artifact::{PriorityFn, RemoteDkgMessageAttribute, RemoteDkgMessageId},
+    consensus::remote_dkg::RemoteDkgMessage,
+    Height,
+};
+
+/// Various actions that can be performed on the remote DKG pool.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
+pub enum RemoteDkgChangeAction {
+    /// Adds a message that was taken from the local DKG summary to the
+    /// validated section.
+    AddToValidated(RemoteDkgMessage),
+    /// Moves a message from the unvalidated to the validated section.
+    MoveToValidated(RemoteDkgMessage),
+    /// Removes a message from the unvalidated section, e.g. because it is
+    /// already known.
+    RemoveFromUnvalidated(RemoteDkgMessageId),
+    /// Removes an invalid message from the unvalidated section.
+    HandleInvalid(RemoteDkgMessageId, String),
+    /// Removes all messages of DKG summaries below the given height.
+    PurgeBelow(Height),
+}
+
+pub type RemoteDkgChangeSet = Vec<RemoteDkgChangeAction>;
+
+/// Artifact pool for the remote DKG messages (query interface)
+pub trait RemoteDkgPool: Send + Sync {
+    fn get_validated(&self) -> Box<dyn Iterator<Item = &RemoteDkgMessage> + '_>;
+    fn get_unvalidated(&self) -> Box<dyn Iterator<Item = &RemoteDkgMessage> + '_>;
+}
+
+/// Artifact pool for the remote DKG messages (update interface)
+pub trait MutableRemoteDkgPool: RemoteDkgPool {
+    fn insert(&mut self, msg: UnvalidatedArtifact<RemoteDkgMessage>);
+    fn apply_changes(&mut self, change_set: RemoteDkgChangeSet);
+}
+
+/// Publishes the remote DKG requests and transcripts of the latest DKG
+/// summary, and validates the ones received from peers against it.
+pub trait RemoteDkg: Send {
+    fn on_state_change(
+        &self,
+        consensus_cache: &dyn ConsensusPoolCache,
+        remote_dkg_pool: &dyn RemoteDkgPool,
+    ) -> RemoteDkgChangeSet;
+}
+
+pub trait RemoteDkgGossip: Send + Sync {
+    fn get_priority_function(
+        &self,
+        remote_dkg_pool: &dyn RemoteDkgPool,
+    ) -> PriorityFn<RemoteDkgMessageId, RemoteDkgMessageAttribute>;
+}
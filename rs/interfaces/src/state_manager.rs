@@ -5,6 +5,7 @@ use ic_types::{
 };
 use phantom_newtype::BitMask;
 use std::sync::Arc;
+use tokio::sync::watch;
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum StateManagerError {
@@ -298,6 +299,12 @@ pub trait StateReader: Send + Sync {
     /// Returns the height of the latest certified state available.
     fn latest_certified_height(&self) -> Height;
 
+    /// Returns a receiver of the height of the latest certified state, which
+    /// is notified whenever the certification of a newer state is delivered,
+    /// so that readers of certified state can await new certifications
+    /// instead of polling.
+    fn subscribe_certified_height(&self) -> watch::Receiver<Height>;
+
     /// Reads part of the certified state tree specified by the shape of
     /// `paths`.  Path can reference either a leaf or a subtree.  E.g.,
     ///  if the tree looks like this:
@@ -328,4 +335,10 @@ pub trait StateReader: Send + Sync {
         &self,
         paths: &LabeledTree<()>,
     ) -> Option<(Arc<Self::State>, MixedHashTree, Certification)>;
+
+    /// Asks the state reader to precompute the witness of `paths` for every
+    /// certified state, such that `read_certified_state` of exactly these
+    /// paths does not need to build it. Meant for paths that are read
+    /// frequently. State readers that do not cache witnesses ignore this.
+    fn prefetch_certified_paths(&self, _paths: LabeledTree<()>) {}
 }
@@ -4,7 +4,7 @@ use async_trait::async_trait;
 use ic_protobuf::registry::node::v1::NodeRecord;
 use ic_types::transport::{
     FlowId, FlowTag, TransportClientType, TransportErrorCode, TransportPayload,
-    TransportStateChange,
+    TransportStateChange, WireCodecId,
 };
 use ic_types::{NodeId, RegistryVersion};
 use std::{fmt::Debug, sync::Arc};
@@ -48,7 +48,8 @@ pub trait Transport: Send + Sync {
     ) -> Result<(), TransportErrorCode>;
 
     /// Send the message to the specified peer. The message will be enqueued
-    /// into the appropriate TxQ based on the TransportQueueConfig.
+    /// into the appropriate TxQ based on the TransportQueueConfig. The payload
+    /// is not copied on its way to the socket.
     fn send(
         &self,
         client_type: TransportClientType,
@@ -57,6 +58,28 @@ pub trait Transport: Send + Sync {
         message: TransportPayload,
     ) -> Result<(), TransportErrorCode>;
 
+    /// Return the number of messages in the send queue for the given peer and
+    /// flow tag.
+    ///
+    /// The client is notified via `TransportStateChange::SendQueueHighWatermark`
+    /// and `TransportStateChange::SendQueueLowWatermark` when the queue crosses
+    /// its high and low watermarks.
+    fn queue_len(
+        &self,
+        client_type: TransportClientType,
+        peer_id: &NodeId,
+        flow_tag: FlowTag,
+    ) -> Result<usize, TransportErrorCode>;
+
+    /// Return the wire codec negotiated with the peer during the handshake
+    /// of the current connection. Messages to the peer are to be encoded, and
+    /// messages from the peer decoded, with this codec.
+    fn wire_codec(
+        &self,
+        client_type: TransportClientType,
+        peer_id: &NodeId,
+    ) -> Result<WireCodecId, TransportErrorCode>;
+
     /// Clear any unsent messages in all the send queues for the peer.
     fn clear_send_queues(&self, client_type: TransportClientType, peer_id: &NodeId);
 
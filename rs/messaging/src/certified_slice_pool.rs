@@ -171,7 +171,7 @@ mod messages {
     use super::*;
 
     /// Wrapper around slice messages plus transient metadata.
-    #[derive(Debug, PartialEq)]
+    #[derive(Clone, Debug, PartialEq)]
     pub(super) struct Messages {
         /// Slice messages.
         ///
@@ -335,7 +335,7 @@ mod messages {
 }
 
 /// Unpacked `CertifiedStreamSlice::payload`, plus transient metadata.
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 struct Payload {
     /// The intended destination subnet of this stream slice.
     subnet_id: Label,
@@ -720,7 +720,7 @@ impl From<Payload> for Vec<u8> {
 /// An unpacked `CertifiedStreamSlice`: a slice of the stream of messages
 /// produced by a subnet together with a cryptographic proof that the majority
 /// of that subnet agrees on it.
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct UnpackedStreamSlice {
     /// Stream slice contents.
     payload: Payload,
@@ -938,6 +938,10 @@ pub enum CertifiedSliceError {
     /// Attempted to take already garbage-collected messages, slice was dropped
     /// from pool.
     TakeBeforeSliceBegin,
+
+    /// The certification of the slice (or of the pooled slice with the
+    /// provided slice appended) did not verify. Provided slice was discarded.
+    InvalidCertification,
 }
 
 /// `CertifiedSliceError::InvalidPayload` and
@@ -973,6 +977,7 @@ impl CertifiedSliceError {
             Self::DecodeFailed(_) => "DecodeFailed",
             Self::InvalidAppend(_) => "InvalidAppend",
             Self::TakeBeforeSliceBegin => "TakeBeforeSliceBegin",
+            Self::InvalidCertification => "InvalidCertification",
         }
     }
 }
@@ -1226,6 +1231,21 @@ impl CertifiedSlicePool {
         )
     }
 
+    /// Returns the backlog of the stream from the given subnet, i.e. the number
+    /// of messages between the cached stream position and the end of the
+    /// stream, according to the certified header of the pooled slice; or
+    /// `None` if no slice from the given subnet is pooled.
+    pub fn stream_backlog(&self, subnet_id: SubnetId) -> Option<u64> {
+        let slice = self.slices.get(&subnet_id)?;
+        let stream_end = slice.payload.header.end();
+        let message_index = self
+            .stream_positions
+            .get(&subnet_id)
+            .map(|position| position.message_index)
+            .unwrap_or_else(|| slice.payload.header.begin());
+        Some(stream_end.get().saturating_sub(message_index.get()))
+    }
+
     /// Returns the total estimated size of the slices in the pool.
     pub fn byte_size(&self) -> usize {
         self.slices.values().map(|slice| slice.count_bytes()).sum()
@@ -1286,6 +1306,63 @@ impl CertifiedSlicePool {
         res
     }
 
+    /// Places the provided slice into the pool, like `put()`, but only if
+    /// `is_valid` accepts its certification. Unlike `put()`, a pooled slice is
+    /// never replaced by one that does not verify.
+    ///
+    /// Returns `Err(InvalidCertification)` if `is_valid` rejects `slice`;
+    /// `Err(InvalidPayload)` or `Err(WitnessPruningFailed)` if `slice` is
+    /// malformed.
+    pub fn put_verified(
+        &mut self,
+        subnet_id: SubnetId,
+        slice: CertifiedStreamSlice,
+        is_valid: impl FnOnce(&CertifiedStreamSlice) -> bool,
+    ) -> CertifiedSliceResult<()> {
+        if !is_valid(&slice) {
+            return Err(CertifiedSliceError::InvalidCertification);
+        }
+        self.put(subnet_id, slice)
+    }
+
+    /// Appends a partial slice to the corresponding pool entry, like
+    /// `append()`, but only if `is_valid` accepts the certification of the
+    /// resulting slice. The pooled slice is left untouched if the resulting
+    /// slice does not verify or `partial` cannot be appended.
+    ///
+    /// Returns `Err(InvalidCertification)` if `is_valid` rejects the resulting
+    /// slice. Otherwise, returns the same errors as `append()`.
+    pub fn append_verified(
+        &mut self,
+        subnet_id: SubnetId,
+        partial: CertifiedStreamSlice,
+        is_valid: impl FnOnce(&CertifiedStreamSlice) -> bool,
+    ) -> CertifiedSliceResult<()> {
+        let partial: UnpackedStreamSlice = partial.try_into()?;
+
+        let merged = match self.slices.get(&subnet_id) {
+            // We have a pooled slice, try appending to a copy of it.
+            Some(pooled) => {
+                let mut merged = pooled.clone();
+                merged.append(partial)?;
+                merged
+            }
+
+            // No existing slice, the partial slice must actually be complete.
+            None => {
+                if !partial.is_complete()? {
+                    return Err(CertifiedSliceError::InvalidAppend(IndexMismatch));
+                }
+                partial
+            }
+        };
+
+        if !is_valid(&merged.clone().pack()) {
+            return Err(CertifiedSliceError::InvalidCertification);
+        }
+        self.put_impl(subnet_id, merged)
+    }
+
     /// Garbage collects the provided slice and pools the rest, if any.
     ///
     /// Returns `Err(InvalidPayload)` or `Err(WitnessPruningFailed)` if
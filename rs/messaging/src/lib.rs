@@ -16,4 +16,5 @@ pub use message_routing::MessageRoutingImpl;
 pub use xnet_endpoint::{XNetEndpoint, XNetEndpointConfig};
 pub use xnet_payload_builder::{
     testing as xnet_payload_builder_testing, ExpectedIndices, XNetPayloadBuilderImpl,
+    XNetSliceGossipClient,
 };
@@ -7,7 +7,10 @@ use ic_interfaces::state_manager::{CertificationScope, StateManagerError};
 use ic_interfaces::{
     certified_stream_store::CertifiedStreamStore,
     execution_environment::{IngressHistoryWriter, Scheduler},
-    messaging::{MessageRouting, MessageRoutingError},
+    messaging::{
+        BatchDelivered, BatchPipelineOccupancy, MessageRouting, MessageRoutingError,
+        RoundSlicingStatus,
+    },
     registry::RegistryClient,
     state_manager::StateManager,
 };
@@ -32,7 +35,7 @@ use ic_types::{
     messages::MessageId,
     registry::RegistryClientError,
     xnet::{StreamHeader, StreamIndex},
-    CanisterId, Height, NodeId, NumBytes, RegistryVersion, SubnetId,
+    CanisterId, CanisterStatusType, Height, NodeId, NumBytes, RegistryVersion, SubnetId,
 };
 use ic_utils::thread::JoinOnDrop;
 #[cfg(test)]
@@ -41,6 +44,7 @@ use prometheus::{Histogram, HistogramVec, IntCounterVec, IntGauge};
 use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use std::convert::TryFrom;
 use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc::{sync_channel, TrySendError};
 use std::sync::{Arc, Mutex, RwLock};
 use std::thread::sleep;
@@ -51,6 +55,7 @@ const BATCH_QUEUE_BUFFER_SIZE: usize = 16;
 
 const METRIC_DELIVER_BATCH_COUNT: &str = "mr_deliver_batch_count";
 const METRIC_EXPECTED_BATCH_HEIGHT: &str = "mr_expected_batch_height";
+const METRIC_PENDING_BATCHES: &str = "mr_pending_batches";
 pub(crate) const METRIC_TIME_IN_BACKLOG: &str = "mr_time_in_backlog";
 pub(crate) const METRIC_TIME_IN_STREAM: &str = "mr_time_in_stream";
 
@@ -195,6 +200,8 @@ pub(crate) struct MessageRoutingMetrics {
     deliver_batch_count: IntCounterVec,
     /// Expected batch height.
     expected_batch_height: IntGauge,
+    /// Number of delivered batches that are queued or being executed.
+    pending_batches: IntGauge,
     /// Batch processing durations.
     process_batch_duration: Histogram,
     /// Batch processing phase durations, by phase.
@@ -228,6 +235,10 @@ impl MessageRoutingMetrics {
                 METRIC_EXPECTED_BATCH_HEIGHT,
                 "Height of the batch that MR expects to be delivered next.",
             ),
+            pending_batches: metrics_registry.int_gauge(
+                METRIC_PENDING_BATCHES,
+                "Number of delivered batches that are queued or being executed.",
+            ),
             process_batch_phase_duration: metrics_registry.histogram_vec(
                 METRIC_PROCESS_BATCH_PHASE_DURATION,
                 "Batch processing phase durations, by phase.",
@@ -247,6 +258,11 @@ impl MessageRoutingMetrics {
 pub struct MessageRoutingImpl {
     last_seen_batch: RwLock<Height>,
     batch_sender: std::sync::mpsc::SyncSender<Batch>,
+    // Number of batches delivered but not yet executed, including the one
+    // being executed.
+    pending_batches: Arc<AtomicUsize>,
+    // Slicing status of the most recently executed round.
+    last_round_slicing_status: Arc<RwLock<Option<RoundSlicingStatus>>>,
     state_manager: Arc<dyn StateManager<State = ReplicatedState>>,
     metrics: Arc<MessageRoutingMetrics>,
     log: ReplicaLogger,
@@ -260,7 +276,9 @@ pub struct MessageRoutingImpl {
 /// retrieving the matching state, applying the batch and committing the result.
 #[cfg_attr(test, automock)]
 trait BatchProcessor: Send {
-    fn process_batch(&self, batch: Batch);
+    /// Executes `batch` and returns the slicing status of the round, or `None`
+    /// if the batch was ignored.
+    fn process_batch(&self, batch: Batch) -> Option<RoundSlicingStatus>;
 }
 
 /// Implementation of [`BatchProcessor`].
@@ -268,6 +286,7 @@ struct BatchProcessorImpl {
     state_manager: Arc<dyn StateManager<State = ReplicatedState>>,
     state_machine: Box<dyn StateMachine>,
     registry: Arc<dyn RegistryClient>,
+    cycles_account_manager: Arc<CyclesAccountManager>,
     metrics: Arc<MessageRoutingMetrics>,
     log: ReplicaLogger,
 }
@@ -277,6 +296,7 @@ impl BatchProcessorImpl {
         state_manager: Arc<dyn StateManager<State = ReplicatedState>>,
         state_machine: Box<dyn StateMachine>,
         registry: Arc<dyn RegistryClient>,
+        cycles_account_manager: Arc<CyclesAccountManager>,
         metrics: Arc<MessageRoutingMetrics>,
         log: ReplicaLogger,
     ) -> Self {
@@ -284,6 +304,7 @@ impl BatchProcessorImpl {
             state_manager,
             state_machine,
             registry,
+            cycles_account_manager,
             metrics,
             log,
         }
@@ -342,6 +363,42 @@ impl BatchProcessorImpl {
         provisional_whitelist.unwrap_or_else(|| ProvisionalWhitelist::Set(BTreeSet::new()))
     }
 
+    // Activates the fee schedule of the registry version of the batch, so that
+    // all replicas charge the same fees when executing it.
+    //
+    // # Warning
+    // If the registry is unavailable, this method keeps trying again forever until
+    // the registry becomes available.
+    fn activate_fee_schedule(&self, registry_version: RegistryVersion) {
+        let fee_schedule = loop {
+            match self
+                .cycles_account_manager
+                .fee_schedule_at(self.registry.as_ref(), registry_version)
+            {
+                Ok(fee_schedule) => break fee_schedule,
+                Err(err) => {
+                    warn!(
+                        self.log,
+                        "Unable to read the fee schedule: {}. Trying again...",
+                        err.to_string(),
+                    );
+                }
+            }
+            sleep(std::time::Duration::from_millis(100));
+        };
+        let active = self.cycles_account_manager.fee_schedule();
+        if fee_schedule != active {
+            info!(
+                self.log,
+                "Switching from fee schedule version {} to version {} at registry version {}",
+                active.version,
+                fee_schedule.version,
+                registry_version
+            );
+            self.cycles_account_manager.set_fee_schedule(fee_schedule);
+        }
+    }
+
     // Populates a `NetworkTopology` from the registry at a specific version.
     //
     // # Warning
@@ -451,11 +508,16 @@ impl BatchProcessorImpl {
 
         let routing_table_record = self.registry.get_routing_table(registry_version)?;
         let routing_table = routing_table_record.unwrap_or_default();
+        let canister_migrations = self
+            .registry
+            .get_canister_migrations(registry_version)?
+            .unwrap_or_default();
         let nns_subnet_id = self.get_nns_subnet_id(subnet_ids, registry_version);
 
         Ok(NetworkTopology {
             subnets,
             routing_table,
+            canister_migrations,
             nns_subnet_id,
         })
     }
@@ -524,8 +586,22 @@ fn get_subnet_public_key(
         .expect("Initial DKG transcripts not found."))
 }
 
+/// Returns the number of messages left in the queues of the running canisters
+/// after a round, i.e. the messages the round had no instructions left for.
+/// The messages of stopping and stopped canisters are not executed anyway.
+fn deferred_message_count(state: &ReplicatedState) -> usize {
+    state
+        .canisters_iter()
+        .filter(|canister| canister.status() == CanisterStatusType::Running)
+        .map(|canister| {
+            let queues = &canister.system_state.queues;
+            queues.ingress_queue_message_count() + queues.input_queues_message_count()
+        })
+        .sum()
+}
+
 impl BatchProcessor for BatchProcessorImpl {
-    fn process_batch(&self, batch: Batch) {
+    fn process_batch(&self, batch: Batch) -> Option<RoundSlicingStatus> {
         let timer = Timer::start();
 
         // Fetch the mutable tip from StateManager
@@ -541,7 +617,7 @@ impl BatchProcessor for BatchProcessorImpl {
                     batch.batch_number,
                     self.state_manager.latest_state_height()
                 );
-                return;
+                return None;
             }
             Err(StateManagerError::StateNotCommittedYet(_)) => fatal!(
                 self.log,
@@ -565,12 +641,17 @@ impl BatchProcessor for BatchProcessorImpl {
         // referenced in batch changes.
         let network_topology = self.populate_network_topology(batch.registry_version);
         let provisional_whitelist = self.get_provisional_whitelist(batch.registry_version);
+        self.activate_fee_schedule(batch.registry_version);
 
         let batch_requires_full_state_hash = batch.requires_full_state_hash;
         let mut state_after_round =
             self.state_machine
                 .execute_round(state, network_topology, batch, provisional_whitelist);
         self.observe_canisters_memory_usage(&state_after_round);
+        let slicing_status = RoundSlicingStatus {
+            height: commit_height,
+            deferred_messages: deferred_message_count(&state_after_round),
+        };
 
         // See documentation around the definition of `heap_delta_estimate` for
         // an explanation.
@@ -588,6 +669,8 @@ impl BatchProcessor for BatchProcessorImpl {
         self.observe_phase_duration(PHASE_COMMIT, &phase_timer);
 
         self.metrics.process_batch_duration.observe(timer.elapsed());
+
+        Some(slicing_status)
     }
 }
 
@@ -635,7 +718,7 @@ fn update_state(
 }
 
 impl BatchProcessor for FakeBatchProcessorImpl {
-    fn process_batch(&self, batch: Batch) {
+    fn process_batch(&self, batch: Batch) -> Option<RoundSlicingStatus> {
         // Fetch the mutable tip from StateManager
         let mut state = match self
             .state_manager
@@ -649,7 +732,7 @@ impl BatchProcessor for FakeBatchProcessorImpl {
                     batch.batch_number,
                     self.state_manager.latest_state_height()
                 );
-                return;
+                return None;
             }
             Err(StateManagerError::StateNotCommittedYet(_)) => fatal!(
                 self.log,
@@ -713,6 +796,12 @@ impl BatchProcessor for FakeBatchProcessorImpl {
             commit_height,
             certification_scope,
         );
+
+        // Messages are not executed, so none is deferred.
+        Some(RoundSlicingStatus {
+            height: commit_height,
+            deferred_messages: 0,
+        })
     }
 }
 
@@ -724,21 +813,34 @@ impl MessageRoutingImpl {
         log: ReplicaLogger,
     ) -> Self {
         let (batch_sender, batch_receiver) = sync_channel(BATCH_QUEUE_BUFFER_SIZE);
-
-        let _batch_processor_handle = JoinOnDrop::new(
-            std::thread::Builder::new()
-                .name("MR Batch Processor".to_string())
-                .spawn(move || {
-                    while let Ok(batch) = batch_receiver.recv() {
-                        batch_processor.process_batch(batch);
-                    }
-                })
-                .expect("Can spawn a batch processing thread in MR"),
-        );
+        let pending_batches = Arc::new(AtomicUsize::new(0));
+        let last_round_slicing_status = Arc::new(RwLock::new(None));
+
+        let _batch_processor_handle = {
+            let pending_batches = Arc::clone(&pending_batches);
+            let last_round_slicing_status = Arc::clone(&last_round_slicing_status);
+            let metrics = Arc::clone(&metrics);
+            JoinOnDrop::new(
+                std::thread::Builder::new()
+                    .name("MR Batch Processor".to_string())
+                    .spawn(move || {
+                        while let Ok(batch) = batch_receiver.recv() {
+                            if let Some(status) = batch_processor.process_batch(batch) {
+                                *last_round_slicing_status.write().unwrap() = Some(status);
+                            }
+                            let pending = pending_batches.fetch_sub(1, Ordering::SeqCst) - 1;
+                            metrics.pending_batches.set(pending as i64);
+                        }
+                    })
+                    .expect("Can spawn a batch processing thread in MR"),
+            )
+        };
 
         Self {
             last_seen_batch: RwLock::new(Height::from(0)),
             batch_sender,
+            pending_batches,
+            last_round_slicing_status,
             state_manager,
             metrics,
             log,
@@ -771,7 +873,7 @@ impl MessageRoutingImpl {
         ));
         let vsr = Box::new(scheduling::valid_set_rule::ValidSetRuleImpl::new(
             ingress_history_writer,
-            cycles_account_manager,
+            Arc::clone(&cycles_account_manager),
             metrics_registry,
             subnet_id,
             log.clone(),
@@ -801,6 +903,7 @@ impl MessageRoutingImpl {
             state_manager.clone(),
             state_machine,
             registry,
+            cycles_account_manager,
             Arc::clone(&metrics),
             log.clone(),
         ));
@@ -851,7 +954,7 @@ impl MessageRoutingImpl {
 }
 
 impl MessageRouting for MessageRoutingImpl {
-    fn deliver_batch(&self, batch: Batch) -> Result<(), MessageRoutingError> {
+    fn deliver_batch(&self, batch: Batch) -> Result<BatchDelivered, MessageRoutingError> {
         let batch_number = batch.batch_number;
         let expected_number = self.expected_batch_height();
         self.metrics
@@ -869,17 +972,24 @@ impl MessageRouting for MessageRoutingImpl {
             });
         }
 
+        // Count the batch as pending before it is sent, so that the batch
+        // processor never sees it finished before it was counted.
+        let pending = self.pending_batches.fetch_add(1, Ordering::SeqCst) + 1;
         match self.batch_sender.try_send(batch) {
             Ok(_) => {
+                self.metrics.pending_batches.set(pending as i64);
                 self.inc_deliver_batch(STATUS_SUCCESS);
                 debug!(self.log, "Inserted batch {}", batch_number);
                 *self.last_seen_batch.write().unwrap() = batch_number;
-                Ok(())
+                Ok(BatchDelivered {
+                    last_executed_round: self.last_round_slicing_status(),
+                })
             }
             // If the queue is already full, we pretend that we never received
             // the batch. It's important not to block Consensus, it will try to
             // resend the overflowing batches later.
             Err(TrySendError::Full(_)) => {
+                self.pending_batches.fetch_sub(1, Ordering::SeqCst);
                 self.inc_deliver_batch(STATUS_QUEUE_FULL);
                 info!(
                     self.log,
@@ -904,6 +1014,17 @@ impl MessageRouting for MessageRoutingImpl {
             .increment()
             .max(self.state_manager.latest_state_height().increment())
     }
+
+    fn batch_pipeline_occupancy(&self) -> BatchPipelineOccupancy {
+        BatchPipelineOccupancy {
+            pending_batches: self.pending_batches.load(Ordering::SeqCst),
+            capacity: BATCH_QUEUE_BUFFER_SIZE,
+        }
+    }
+
+    fn last_round_slicing_status(&self) -> Option<RoundSlicingStatus> {
+        *self.last_round_slicing_status.read().unwrap()
+    }
 }
 
 #[cfg(test)]
@@ -912,8 +1033,13 @@ mod tests {
     use ic_test_utilities::{
         metrics::{fetch_int_counter_vec, metric_vec},
         notification::{Notification, WaitResult},
+        state::{CanisterStateBuilder, ReplicatedStateBuilder},
         state_manager::MockStateManager,
-        types::batch::BatchBuilder,
+        types::{
+            batch::BatchBuilder,
+            ids::canister_test_id,
+            messages::{IngressBuilder, RequestBuilder},
+        },
         with_test_replica_logger,
     };
     use std::sync::Arc;
@@ -954,6 +1080,7 @@ mod tests {
                         notification.wait_with_timeout(timeout),
                         WaitResult::Notified(())
                     );
+                    None
                 }
             });
 
@@ -1002,4 +1129,36 @@ mod tests {
             notification.notify(());
         });
     }
+
+    #[test]
+    fn messages_left_to_running_canisters_are_deferred() {
+        let running = canister_test_id(1);
+        let stopped = canister_test_id(2);
+        let state = ReplicatedStateBuilder::new()
+            .with_canister(
+                CanisterStateBuilder::new()
+                    .with_canister_id(running)
+                    .with_ingress(IngressBuilder::new().receiver(running).build())
+                    .with_input(RequestBuilder::new().receiver(running).build().into())
+                    .build(),
+            )
+            .with_canister(
+                CanisterStateBuilder::new()
+                    .with_canister_id(stopped)
+                    .with_status(CanisterStatusType::Stopped)
+                    .with_ingress(IngressBuilder::new().receiver(stopped).build())
+                    .build(),
+            )
+            .build();
+        assert_eq!(deferred_message_count(&state), 2);
+
+        let state = ReplicatedStateBuilder::new()
+            .with_canister(
+                CanisterStateBuilder::new()
+                    .with_canister_id(running)
+                    .build(),
+            )
+            .build();
+        assert_eq!(deferred_message_count(&state), 0);
+    }
 }
@@ -1,8 +1,11 @@
 use crate::{routing::stream_handler::StreamHandler, scheduling::valid_set_rule::ValidSetRule};
 use ic_interfaces::certified_stream_store::CertifiedStreamStore;
-use ic_logger::{trace, ReplicaLogger};
-use ic_replicated_state::ReplicatedState;
-use ic_types::{batch::BatchPayload, messages::SignedIngressContent};
+use ic_logger::{debug, trace, ReplicaLogger};
+use ic_replicated_state::{canister_state::QUEUE_INDEX_NONE, ReplicatedState};
+use ic_types::{
+    batch::BatchPayload,
+    messages::{RequestOrResponse, SignedIngressContent},
+};
 use std::sync::Arc;
 
 #[cfg(test)]
@@ -13,7 +16,7 @@ use std::collections::BTreeMap;
 pub(crate) trait Demux: Send {
     /// Process the provided payload. Splices off XNetMessages as appropriate
     /// and (attempts) to induct the messages contained in the payload as
-    /// appropriate.
+    /// appropriate, including the responses to canister HTTP outcalls.
     fn process_payload(&self, state: ReplicatedState, payload: BatchPayload) -> ReplicatedState;
 }
 
@@ -41,9 +44,16 @@ impl<'a> DemuxImpl<'a> {
 }
 
 impl<'a> Demux for DemuxImpl<'a> {
-    fn process_payload(&self, state: ReplicatedState, payload: BatchPayload) -> ReplicatedState {
+    fn process_payload(
+        &self,
+        state: ReplicatedState,
+        mut payload: BatchPayload,
+    ) -> ReplicatedState {
         trace!(self.log, "Processing Payload");
 
+        let query_stats = std::mem::take(&mut payload.query_stats);
+        let canister_http_responses = std::mem::take(&mut payload.canister_http);
+
         let (signed_ingress_msgs, certified_stream_slices) =
             payload.into_messages().unwrap_or_else(|err| {
                 unreachable!(
@@ -73,6 +83,29 @@ impl<'a> Demux for DemuxImpl<'a> {
         self.valid_set_rule
             .induct_messages(&mut state, ingress_msgs);
 
+        // A response is dropped if the canister has no slot reserved for it,
+        // e.g. because a response to the same request was already inducted.
+        for response in canister_http_responses {
+            let response = RequestOrResponse::Response(response.content.to_response());
+            if let Err((err, msg)) = state.push_input(QUEUE_INDEX_NONE, response) {
+                debug!(
+                    self.log,
+                    "Dropping canister http response {:?}: {:?}", msg, err
+                );
+            }
+        }
+
+        // Only the statistics of the canisters hosted by this subnet count.
+        let canister_states = &state.canister_states;
+        for report in query_stats {
+            state.metadata.query_stats.add_report(
+                report.signature.signer,
+                report.content.epoch,
+                report.content.stats,
+                |canister_id| canister_states.contains_key(canister_id),
+            );
+        }
+
         state
     }
 }
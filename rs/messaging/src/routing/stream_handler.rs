@@ -3,7 +3,7 @@ use ic_logger::{debug, trace, warn, ReplicaLogger};
 use ic_metrics::{buckets::decimal_buckets, MetricsRegistry};
 use ic_replicated_state::{
     canister_state::QUEUE_INDEX_NONE,
-    metadata_state::Stream,
+    metadata_state::{Stream, Streams},
     replicated_state::{
         LABEL_VALUE_CANISTER_NOT_FOUND, LABEL_VALUE_CANISTER_OUT_OF_CYCLES,
         LABEL_VALUE_CANISTER_STOPPED, LABEL_VALUE_CANISTER_STOPPING,
@@ -16,7 +16,7 @@ use ic_types::{
     messages::{Payload, RejectContext, RequestOrResponse, Response},
     user_error::RejectCode,
     xnet::{StreamIndex, StreamSlice},
-    SubnetId,
+    CanisterId, SubnetId,
 };
 use prometheus::{Histogram, IntCounter, IntCounterVec, IntGaugeVec};
 use std::cell::RefCell;
@@ -47,6 +47,7 @@ const LABEL_STATUS: &str = "status";
 const LABEL_VALUE_SUCCESS: &str = "success";
 const LABEL_VALUE_SENDER_SUBNET_MISMATCH: &str = "SenderSubnetMismatch";
 const LABEL_VALUE_SENDER_SUBNET_UNKNOWN: &str = "SenderSubnetUnknown";
+const LABEL_VALUE_REROUTED: &str = "Rerouted";
 const LABEL_TYPE: &str = "type";
 const LABEL_VALUE_TYPE_REQUEST: &str = "request";
 const LABEL_VALUE_TYPE_RESPONSE: &str = "response";
@@ -316,12 +317,14 @@ impl StreamHandlerImpl {
         let mut streams = state.take_streams();
 
         for (remote_subnet_id, mut stream_slice) in stream_slices {
-            // Output stream, for resulting signals and (in the initial iteration) reject
-            // `Responses`.
-            let stream = streams.entry(remote_subnet_id).or_default();
-
             while let Some((stream_index, msg)) = stream_slice.pop_message() {
-                self.induct_message(msg, remote_subnet_id, stream_index, &mut state, stream);
+                self.induct_message(
+                    msg,
+                    remote_subnet_id,
+                    stream_index,
+                    &mut state,
+                    &mut streams,
+                );
             }
         }
 
@@ -331,21 +334,29 @@ impl StreamHandlerImpl {
 
     /// Attempts to induct the given message at `stream_index` in the incoming
     /// stream from `remote_subnet_id` into `state`, producing a signal onto the
-    /// provided reverse `stream`. The induction attempt will result in one of
+    /// reverse stream in `streams`. The induction attempt will result in one of
     /// the following outcomes (in addition to the signal):
     ///
     ///  * enqueuing the message into the corresponding input queue;
-    ///  * a reject response enqueued into the reverse stream: if enqueuing of a
-    ///    request failed (queue full, canister not found);
+    ///  * rerouting the message into the stream to the subnet that the
+    ///    receiver has been migrated to;
+    ///  * a reject response enqueued into the stream to the sender's host
+    ///    subnet: if enqueuing of a request failed (queue full, canister not
+    ///    found);
     ///  * no other action: if the sender canister and source subnet do not
     ///    match; or enqueuing of a response failed.
+    ///
+    /// Besides messages from canisters hosted by (or migrated away from)
+    /// `remote_subnet_id`, messages addressed to a canister that was migrated
+    /// from `remote_subnet_id` to this subnet are accepted too: these were
+    /// rerouted by `remote_subnet_id` after the canister's migration.
     fn induct_message(
         &self,
         msg: RequestOrResponse,
         remote_subnet_id: SubnetId,
         stream_index: StreamIndex,
         state: &mut ReplicatedState,
-        stream: &mut Stream,
+        streams: &mut Streams,
     ) {
         let payload_size = match &msg {
             RequestOrResponse::Request(req) => req.payload_size_bytes().get(),
@@ -363,8 +374,13 @@ impl StreamHandlerImpl {
             .route(msg.sender().get())
         {
             Some(host_subnet) => {
-                if host_subnet == remote_subnet_id {
-                    // Sender is hosted by `remote_subnet_id`, proceed with induction.
+                if host_subnet == remote_subnet_id
+                    || is_migrated_from(state, msg.sender(), remote_subnet_id)
+                    || self.is_rerouted_from(state, msg.receiver(), remote_subnet_id)
+                {
+                    // Sender is (or was, until its migration) hosted by `remote_subnet_id`,
+                    // or `remote_subnet_id` rerouted the message to the new host of the
+                    // receiver; proceed with induction.
                     match state.push_input(QUEUE_INDEX_NONE, msg) {
                         // Message successfully inducted, all done.
                         Ok(()) => {
@@ -372,13 +388,38 @@ impl StreamHandlerImpl {
                             self.observe_inducted_payload_size(payload_size);
                         }
 
-                        // Message not inducted.
                         Err((err, msg)) => {
-                            debug!(self.log, "Induction failed with error '{}', generating reject Response for {:?}", &err, &msg);
-                            self.observe_inducted_message_status(msg_type, err.to_label_value());
-
-                            let code = reject_code_for_state_error(&err);
-                            self.try_enqueue_reject_response(msg, code, err.to_string(), stream);
+                            match (&err, self.migrated_host(state, msg.receiver())) {
+                                // Receiver was migrated away, forward the message to its new host.
+                                (StateError::CanisterNotFound(_), Some(new_host)) => {
+                                    debug!(
+                                        self.log,
+                                        "Rerouting message to subnet {}: {:?}", new_host, &msg
+                                    );
+                                    self.observe_inducted_message_status(
+                                        msg_type,
+                                        LABEL_VALUE_REROUTED,
+                                    );
+                                    streams.entry(new_host).or_default().push(msg);
+                                }
+
+                                // Message not inducted.
+                                _ => {
+                                    debug!(self.log, "Induction failed with error '{}', generating reject Response for {:?}", &err, &msg);
+                                    self.observe_inducted_message_status(
+                                        msg_type,
+                                        err.to_label_value(),
+                                    );
+
+                                    let code = reject_code_for_state_error(&err);
+                                    self.try_enqueue_reject_response(
+                                        msg,
+                                        code,
+                                        err.to_string(),
+                                        streams.entry(host_subnet).or_default(),
+                                    );
+                                }
+                            }
                         }
                     }
                 } else {
@@ -414,6 +455,7 @@ impl StreamHandlerImpl {
         }
 
         // Signals use the `StreamIndex` of the incoming message.
+        let stream = streams.entry(remote_subnet_id).or_default();
         assert_eq!(
             stream.signals_end(),
             stream_index,
@@ -448,6 +490,34 @@ impl StreamHandlerImpl {
         }
     }
 
+    /// Returns the subnet that `receiver` has been migrated to from this subnet,
+    /// if any.
+    fn migrated_host(&self, state: &ReplicatedState, receiver: CanisterId) -> Option<SubnetId> {
+        let network_topology = &state.metadata.network_topology;
+        let trace = network_topology.canister_migrations.lookup(receiver)?;
+        if !trace.contains(&self.subnet_id) {
+            return None;
+        }
+        network_topology
+            .routing_table
+            .route(receiver.get())
+            .filter(|host| *host != self.subnet_id)
+    }
+
+    /// Tests whether `receiver` was migrated from `remote_subnet_id` to this
+    /// subnet, i.e. whether `remote_subnet_id` reroutes messages addressed to
+    /// `receiver` to this subnet.
+    fn is_rerouted_from(
+        &self,
+        state: &ReplicatedState,
+        receiver: CanisterId,
+        remote_subnet_id: SubnetId,
+    ) -> bool {
+        let network_topology = &state.metadata.network_topology;
+        is_migrated_from(state, receiver, remote_subnet_id)
+            && network_topology.routing_table.route(receiver.get()) == Some(self.subnet_id)
+    }
+
     /// Records the result of inducting an XNet message.
     fn observe_inducted_message_status(&self, msg_type: &str, status: &str) {
         self.metrics
@@ -471,6 +541,18 @@ impl StreamHandlerImpl {
     }
 }
 
+/// Returns true if `sender` is being migrated and was hosted by `subnet_id`
+/// before, i.e. messages it sent before the migration may still arrive from
+/// `subnet_id`.
+fn is_migrated_from(state: &ReplicatedState, sender: CanisterId, subnet_id: SubnetId) -> bool {
+    state
+        .metadata
+        .network_topology
+        .canister_migrations
+        .lookup(sender)
+        .map_or(false, |trace| trace.contains(&subnet_id))
+}
+
 /// Generates a reject `Response` for a `Request` message with the provided
 /// `RejectContext`.
 fn generate_reject_response(msg: RequestOrResponse, context: RejectContext) -> RequestOrResponse {
@@ -7,7 +7,7 @@ use super::*;
 use crate::message_routing::{LABEL_SUBNET, METRIC_TIME_IN_BACKLOG, METRIC_TIME_IN_STREAM};
 use ic_base_types::NumSeconds;
 use ic_metrics::MetricsRegistry;
-use ic_registry_routing_table::{CanisterIdRange, RoutingTable};
+use ic_registry_routing_table::{CanisterIdRange, CanisterMigrations, RoutingTable};
 use ic_registry_subnet_type::SubnetType;
 use ic_replicated_state::{
     canister_state::QUEUE_INDEX_NONE, replicated_state::LABEL_VALUE_CANISTER_NOT_FOUND,
@@ -19,7 +19,7 @@ use ic_test_utilities::{
         fetch_int_gauge_vec, metric_vec, nonzero_values, HistogramStats, MetricVec,
     },
     state::new_canister_state,
-    types::ids::{user_test_id, SUBNET_12, SUBNET_23},
+    types::ids::{user_test_id, SUBNET_12, SUBNET_23, SUBNET_27},
     types::messages::{RequestBuilder, ResponseBuilder},
     types::xnet::{StreamHeaderBuilder, StreamSliceBuilder},
     with_test_replica_logger,
@@ -34,6 +34,7 @@ use maplit::btreemap;
 
 const LOCAL_SUBNET: SubnetId = SUBNET_12;
 const REMOTE_SUBNET: SubnetId = SUBNET_23;
+const OTHER_SUBNET: SubnetId = SUBNET_27;
 const CANISTER_FREEZE_BALANCE_RESERVE: Cycles = Cycles::new(5_000_000_000_000);
 
 lazy_static! {
@@ -699,6 +700,215 @@ fn induct_stream_slices_partial_success() {
     });
 }
 
+/// Tests that messages sent by canisters migrated away from the remote subnet
+/// are inducted and that messages addressed to canisters migrated away from
+/// the local subnet are rerouted to their new host.
+#[test]
+fn induct_stream_slices_with_migrated_canisters() {
+    with_test_replica_logger(|log| {
+        let (stream_handler, mut initial_state, metrics_registry) = new_fixture(&log);
+        let migrated_canister = CanisterId::from(0x234);
+
+        // `REMOTE_CANISTER` is migrated from `REMOTE_SUBNET` and
+        // `migrated_canister` from `LOCAL_SUBNET`, both to `OTHER_SUBNET`.
+        let network_topology = &mut initial_state.metadata.network_topology;
+        network_topology.routing_table = RoutingTable::new(btreemap! {
+            CanisterIdRange{ start: CanisterId::from(0x0), end: CanisterId::from(0xff) } => LOCAL_SUBNET,
+            CanisterIdRange{ start: CanisterId::from(0x100), end: CanisterId::from(0x2ff) } => OTHER_SUBNET,
+        });
+        network_topology.canister_migrations = CanisterMigrations::new(btreemap! {
+            CanisterIdRange{ start: CanisterId::from(0x100), end: CanisterId::from(0x1ff) } => vec![REMOTE_SUBNET, OTHER_SUBNET],
+            CanisterIdRange{ start: CanisterId::from(0x200), end: CanisterId::from(0x2ff) } => vec![LOCAL_SUBNET, OTHER_SUBNET],
+        });
+
+        initial_state.put_canister_state(new_canister_state(
+            *LOCAL_CANISTER,
+            user_test_id(24).get(),
+            *INITIAL_CYCLES,
+            NumSeconds::from(100_000),
+        ));
+        let mut expected_state = initial_state.clone();
+
+        let mut stream_slice = generate_stream_slice(StreamSliceConfig {
+            header_begin: 0,
+            header_end: None,
+            messages_begin: 0,
+            message_count: 0,
+            signals_end: 0,
+        });
+        let request_to_local: RequestOrResponse =
+            test_request(*REMOTE_CANISTER, *LOCAL_CANISTER).into();
+        stream_slice.push_message(request_to_local.clone());
+        let request_to_migrated: RequestOrResponse =
+            test_request(*REMOTE_CANISTER, migrated_canister).into();
+        stream_slice.push_message(request_to_migrated.clone());
+
+        // The first request is inducted, the second one is rerouted.
+        expected_state
+            .canister_state_mut(&LOCAL_CANISTER)
+            .unwrap()
+            .push_input(QUEUE_INDEX_NONE, request_to_local)
+            .unwrap();
+        let mut reverse_stream = Stream::default();
+        reverse_stream.increment_signals_end();
+        reverse_stream.increment_signals_end();
+        let mut rerouted_stream = Stream::default();
+        rerouted_stream.push(request_to_migrated);
+        expected_state.put_streams(btreemap![
+            REMOTE_SUBNET => reverse_stream,
+            OTHER_SUBNET => rerouted_stream,
+        ]);
+
+        let inducted_state = stream_handler
+            .induct_stream_slices(initial_state, btreemap![REMOTE_SUBNET => stream_slice]);
+
+        assert_eq!(expected_state, inducted_state);
+        assert_inducted_xnet_messages_eq(
+            metric_vec(&[
+                (
+                    &[
+                        (LABEL_TYPE, LABEL_VALUE_TYPE_REQUEST),
+                        (LABEL_STATUS, LABEL_VALUE_SUCCESS),
+                    ],
+                    1,
+                ),
+                (
+                    &[
+                        (LABEL_TYPE, LABEL_VALUE_TYPE_REQUEST),
+                        (LABEL_STATUS, LABEL_VALUE_REROUTED),
+                    ],
+                    1,
+                ),
+            ]),
+            &metrics_registry,
+        );
+    });
+}
+
+/// Tests that after `LOCAL_SUBNET` is split, the messages in flight to the
+/// canisters migrated to `OTHER_SUBNET` are rerouted by `LOCAL_SUBNET` and
+/// inducted by `OTHER_SUBNET`; and that a reject response for a rerouted
+/// request that cannot be inducted is routed to the sender's host subnet.
+#[test]
+fn induct_stream_slices_rerouted_after_split() {
+    with_test_replica_logger(|log| {
+        let (local_stream_handler, mut state, _) = new_fixture(&log);
+        let migrated_canister = CanisterId::from(0x80);
+        let missing_canister = CanisterId::from(0x90);
+        let migrated_range = CanisterIdRange {
+            start: CanisterId::from(0x80),
+            end: CanisterId::from(0xff),
+        };
+
+        for canister_id in &[*LOCAL_CANISTER, migrated_canister] {
+            state.put_canister_state(new_canister_state(
+                *canister_id,
+                user_test_id(24).get(),
+                *INITIAL_CYCLES,
+                NumSeconds::from(100_000),
+            ));
+        }
+        let network_topology = &mut state.metadata.network_topology;
+        network_topology.routing_table = RoutingTable::new(btreemap! {
+            CanisterIdRange{ start: CanisterId::from(0x0), end: CanisterId::from(0x7f) } => LOCAL_SUBNET,
+            migrated_range => OTHER_SUBNET,
+            CanisterIdRange{ start: CanisterId::from(0x100), end: CanisterId::from(0x1ff) } => REMOTE_SUBNET,
+        });
+        network_topology.canister_migrations = CanisterMigrations::new(btreemap! {
+            migrated_range => vec![LOCAL_SUBNET, OTHER_SUBNET],
+        });
+
+        let (local_state, other_state) = state.split(&migrated_range, OTHER_SUBNET);
+        assert!(local_state.canister_state(&migrated_canister).is_none());
+        assert!(other_state.canister_state(&migrated_canister).is_some());
+
+        // `REMOTE_SUBNET` is not yet aware of the split and sends both requests
+        // to `LOCAL_SUBNET`, which reroutes them to `OTHER_SUBNET`.
+        let request_to_migrated: RequestOrResponse =
+            test_request(*REMOTE_CANISTER, migrated_canister).into();
+        let request_to_missing: RequestOrResponse =
+            test_request(*REMOTE_CANISTER, missing_canister).into();
+        let mut stream_slice = generate_stream_slice(StreamSliceConfig {
+            header_begin: 0,
+            header_end: None,
+            messages_begin: 0,
+            message_count: 0,
+            signals_end: 0,
+        });
+        stream_slice.push_message(request_to_migrated.clone());
+        stream_slice.push_message(request_to_missing.clone());
+
+        let local_state = local_stream_handler
+            .induct_stream_slices(local_state, btreemap![REMOTE_SUBNET => stream_slice]);
+        let rerouted_stream = local_state.get_stream(OTHER_SUBNET).unwrap().clone();
+        assert_eq!(
+            vec![&request_to_migrated, &request_to_missing],
+            rerouted_stream
+                .messages()
+                .iter()
+                .map(|(_, msg)| msg)
+                .collect::<Vec<_>>()
+        );
+
+        // `OTHER_SUBNET` inducts the rerouted request to the migrated canister
+        // and rejects the one to the missing canister towards `REMOTE_SUBNET`.
+        let other_metrics_registry = MetricsRegistry::new();
+        let other_stream_handler = StreamHandlerImpl::new(
+            OTHER_SUBNET,
+            &other_metrics_registry,
+            Arc::new(Mutex::new(LatencyMetrics::new_time_in_stream(
+                &other_metrics_registry,
+            ))),
+            log.clone(),
+        );
+        let mut expected_state = other_state.clone();
+        expected_state
+            .canister_state_mut(&migrated_canister)
+            .unwrap()
+            .push_input(QUEUE_INDEX_NONE, request_to_migrated)
+            .unwrap();
+        let mut reverse_stream = Stream::default();
+        reverse_stream.increment_signals_end();
+        reverse_stream.increment_signals_end();
+        let mut reject_stream = Stream::default();
+        let err = StateError::CanisterNotFound(missing_canister);
+        reject_stream.push(generate_reject_response(
+            request_to_missing,
+            RejectContext::new(reject_code_for_state_error(&err), err.to_string()),
+        ));
+        expected_state.put_streams(btreemap![
+            LOCAL_SUBNET => reverse_stream,
+            REMOTE_SUBNET => reject_stream,
+        ]);
+
+        let other_state = other_stream_handler.induct_stream_slices(
+            other_state,
+            btreemap![LOCAL_SUBNET => rerouted_stream.into()],
+        );
+
+        assert_eq!(expected_state, other_state);
+        assert_inducted_xnet_messages_eq(
+            metric_vec(&[
+                (
+                    &[
+                        (LABEL_TYPE, LABEL_VALUE_TYPE_REQUEST),
+                        (LABEL_STATUS, LABEL_VALUE_SUCCESS),
+                    ],
+                    1,
+                ),
+                (
+                    &[
+                        (LABEL_TYPE, LABEL_VALUE_TYPE_REQUEST),
+                        (LABEL_STATUS, LABEL_VALUE_CANISTER_NOT_FOUND),
+                    ],
+                    1,
+                ),
+            ]),
+            &other_metrics_registry,
+        );
+    });
+}
+
 /// Tests that given a loopback stream and a certified stream slice,
 /// messages are inducted (with signals added appropriately), and
 /// messages present in the initial state are removed as appropriate.
@@ -102,6 +102,7 @@ fn test_fixture(provided_batch: &Batch) -> StateMachineTestFixture {
     let network_topology = NetworkTopology {
         subnets,
         routing_table: Default::default(),
+        canister_migrations: Default::default(),
         nns_subnet_id: SubnetId::from(PrincipalId::new_subnet_test_id(0)),
     };
 
@@ -1,3 +1,4 @@
+mod gossip;
 mod proximity;
 
 #[cfg(test)]
@@ -15,6 +16,7 @@ use crate::{
     xnet_uri::XNetAuthority,
 };
 use async_trait::async_trait;
+pub use gossip::XNetSliceGossipClient;
 use hyper::{client::Client, Body, Request, StatusCode, Uri};
 use ic_crypto_tls_interfaces::TlsHandshake;
 use ic_interfaces::{
@@ -191,7 +193,9 @@ pub struct XNetPayloadBuilderImpl {
     slice_pool: Arc<Mutex<CertifiedSlicePool>>,
 
     /// Handle to the pool refill task, used to asynchronously trigger refill.
-    refill_task_handle: RefillTaskHandle,
+    /// `None` if slices are pulled over gossip, which refills the pool on its
+    /// own schedule.
+    refill_task_handle: Option<RefillTaskHandle>,
 
     metrics: Arc<XNetPayloadBuilderMetrics>,
 
@@ -300,12 +304,59 @@ impl XNetPayloadBuilderImpl {
             certified_stream_store,
             registry,
             slice_pool,
-            refill_task_handle,
+            refill_task_handle: Some(refill_task_handle),
             metrics,
             log,
         }
     }
 
+    /// Creates a new `XNetPayloadBuilderImpl` for a node on `subnet_id` that
+    /// pulls `CertifiedStreamSlices` over gossip instead of querying the
+    /// `XNetEndpoints` of remote subnets.
+    ///
+    /// Returns the payload builder together with the gossip client that must
+    /// be registered with the artifact manager; the client fills the payload
+    /// builder's slice pool with the slices it receives and serves the slices
+    /// of this subnet's streams to the nodes of other subnets.
+    pub fn new_with_gossip(
+        state_manager: Arc<dyn StateManager<State = ReplicatedState>>,
+        certified_stream_store: Arc<dyn CertifiedStreamStore>,
+        registry: Arc<dyn RegistryClient>,
+        subnet_id: SubnetId,
+        metrics_registry: &MetricsRegistry,
+        log: ReplicaLogger,
+    ) -> (XNetPayloadBuilderImpl, Arc<XNetSliceGossipClient>) {
+        let slice_pool = Arc::new(Mutex::new(CertifiedSlicePool::new(metrics_registry)));
+        let metrics = Arc::new(XNetPayloadBuilderMetrics::new(metrics_registry));
+        let gossip_client = Arc::new(XNetSliceGossipClient::new(
+            subnet_id,
+            Arc::clone(&certified_stream_store),
+            Arc::clone(&registry),
+            Arc::clone(&slice_pool),
+            Arc::clone(&metrics),
+            log.clone(),
+        ));
+
+        let payload_builder = Self {
+            state_manager,
+            certified_stream_store,
+            registry,
+            slice_pool,
+            refill_task_handle: None,
+            metrics,
+            log,
+        };
+        (payload_builder, gossip_client)
+    }
+
+    /// Triggers a slice pool refill, if the pool is refilled by a
+    /// `PoolRefillTask`.
+    fn trigger_refill(&self) {
+        if let Some(refill_task_handle) = &self.refill_task_handle {
+            refill_task_handle.trigger_refill();
+        }
+    }
+
     /// Calculates the next expected message and signal indices for a given
     /// stream, based on `state` and the subsequent `payloads`.
     ///
@@ -632,6 +683,21 @@ impl XNetPayloadBuilderImpl {
             // Trim off messages in the state or past payloads.
             slice_pool.garbage_collect(stream_positions);
 
+            // Split the payload space among the pooled slices according to the
+            // backlogs of their streams.
+            let slice_demands: BTreeMap<_, _> = rotated_stream_positions
+                .iter()
+                .filter_map(|(subnet_id, _)| {
+                    let backlog = slice_pool.stream_backlog(*subnet_id)?;
+                    let (_, _, _, byte_size) = slice_pool.slice_stats(*subnet_id);
+                    Some((*subnet_id, SliceDemand { byte_size, backlog }))
+                })
+                .collect();
+            let slice_byte_limits = allocate_slice_byte_limits(bytes_left, &slice_demands);
+            // Space allotted to the slices considered so far, whatever is left of it
+            // is passed on to the next slice.
+            let mut allotted_bytes = 0;
+
             // Keep adding slices until we run out of payload space.
             for (subnet_id, begin) in rotated_stream_positions {
                 if !stream_slices.is_empty() && bytes_left < SLICE_BYTE_SIZE_MIN {
@@ -640,11 +706,15 @@ impl XNetPayloadBuilderImpl {
                 }
 
                 let msg_limit = self.get_msg_limit(subnet_id, &state);
+                allotted_bytes = allotted_bytes
+                    .saturating_add(slice_byte_limits.get(&subnet_id).copied().unwrap_or(0));
+                let used_bytes = byte_limit.get() as usize - bytes_left;
+                let slice_byte_limit = allotted_bytes.saturating_sub(used_bytes).min(bytes_left);
                 let (slice, slice_bytes) = match slice_pool.take_slice(
                     subnet_id,
                     Some(&begin),
                     msg_limit,
-                    Some(bytes_left),
+                    Some(slice_byte_limit),
                 ) {
                     Ok(Some(slice)) => slice,
                     Ok(None) => continue,
@@ -692,6 +762,61 @@ impl XNetPayloadBuilderImpl {
     }
 }
 
+/// The payload space wanted by the slice pooled for a remote subnet.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct SliceDemand {
+    /// The estimated byte size of the pooled slice.
+    byte_size: usize,
+
+    /// The number of messages in the stream not yet included, according to
+    /// the certified stream header.
+    backlog: u64,
+}
+
+/// Splits `byte_limit` among the pooled slices in proportion to the backlogs
+/// of their streams, so that a congested subnet with a large pooled slice
+/// cannot crowd out the streams of all other subnets.
+///
+/// Slices smaller than their proportional share are allotted their full size
+/// and the remainder is split again among the other slices, until all slices
+/// fit or the remaining space is exhausted. Streams without backlog (e.g.
+/// header-only slices carrying signals) are weighted as if they had a backlog
+/// of one message.
+fn allocate_slice_byte_limits(
+    byte_limit: usize,
+    demands: &BTreeMap<SubnetId, SliceDemand>,
+) -> BTreeMap<SubnetId, usize> {
+    let weight = |demand: &SliceDemand| demand.backlog.max(1) as u128;
+
+    let mut byte_limits = BTreeMap::new();
+    let mut bytes_left = byte_limit;
+    let mut pending: Vec<_> = demands.iter().collect();
+    while !pending.is_empty() {
+        let total_weight: u128 = pending.iter().map(|(_, demand)| weight(demand)).sum();
+
+        // Slices that fit into their proportional share of the remaining space.
+        let (fitting, rest): (Vec<_>, Vec<_>) = pending.into_iter().partition(|(_, demand)| {
+            demand.byte_size as u128 * total_weight <= bytes_left as u128 * weight(demand)
+        });
+
+        if fitting.is_empty() {
+            // No slice fits, split the remaining space proportionally.
+            for (subnet_id, demand) in rest {
+                let share = bytes_left as u128 * weight(demand) / total_weight;
+                byte_limits.insert(*subnet_id, share as usize);
+            }
+            break;
+        }
+
+        for (subnet_id, demand) in fitting {
+            bytes_left = bytes_left.saturating_sub(demand.byte_size);
+            byte_limits.insert(*subnet_id, demand.byte_size);
+        }
+        pending = rest;
+    }
+    byte_limits
+}
+
 /// Resolves a stream index and byte limit to an `EndpointLocator`, consisting
 /// of URL, node ID and proximity.
 pub struct XNetEndpointResolver {
@@ -824,7 +949,7 @@ impl XNetPayloadBuilder for XNetPayloadBuilderImpl {
 
         // We don't care if the send succeeded or not. If it didn't, the refill task is
         // just behind.
-        self.refill_task_handle.trigger_refill();
+        self.trigger_refill();
 
         Ok(payload)
     }
@@ -897,7 +1022,7 @@ impl XNetPayloadBuilder for XNetPayloadBuilderImpl {
             }
         }
         // And trigger a pool refill.
-        self.refill_task_handle.trigger_refill();
+        self.trigger_refill();
 
         self.metrics
             .observe_validate_duration(VALIDATION_STATUS_VALID, timer);
@@ -1110,62 +1235,26 @@ impl PoolRefillTask {
 
     /// Queries all subnets for new slices and puts / appends them to the pool.
     async fn refill_pool(&self, pool_byte_size_soft_cap: usize, slice_byte_size_max: usize) {
-        let pool_slice_stats = {
-            let pool = self.pool.lock().unwrap();
-
-            if pool.byte_size() > pool_byte_size_soft_cap {
-                // Abort if pool is already full.
-                return;
-            }
-
-            pool.peers()
-                // Skip our own subnet, the loopback stream is routed separately.
-                .filter(|&&subnet_id| subnet_id != self.endpoint_resolver.subnet_id)
-                .map(|&subnet_id| (subnet_id, pool.slice_stats(subnet_id)))
-                .collect::<BTreeMap<_, _>>()
-        };
-
-        for (subnet_id, slice_stats) in pool_slice_stats {
-            let (stream_position, messages_begin, msg_count, byte_size) = match slice_stats {
-                // Have a cached stream position.
-                (Some(stream_position), messages_begin, msg_count, byte_size) => {
-                    (stream_position, messages_begin, msg_count, byte_size)
-                }
-
-                // No cached stream position, no pooling / refill necessary.
-                (None, _, _, _) => continue,
-            };
-
-            let (witness_begin, msg_begin, slice_byte_limit) = match messages_begin {
-                // Existing pooled stream, pull partial slice and append.
-                Some(messages_begin) if messages_begin == stream_position.message_index => (
-                    stream_position.message_index,
-                    stream_position.message_index + (msg_count as u64).into(),
-                    slice_byte_size_max.saturating_sub(byte_size),
-                ),
-
-                // No pooled stream, or pooled stream does not begin at cached stream position, pull
-                // complete slice from cached stream position.
-                _ => (
-                    stream_position.message_index,
-                    stream_position.message_index,
-                    slice_byte_size_max,
-                ),
-            };
-
-            if slice_byte_limit < SLICE_BYTE_SIZE_MIN {
-                // No more space left in the pool for this slice, bail out.
-                continue;
-            }
+        let slice_pulls = plan_slice_pulls(
+            &self.pool.lock().unwrap(),
+            self.endpoint_resolver.subnet_id,
+            pool_byte_size_soft_cap,
+            slice_byte_size_max,
+        );
 
+        for SlicePull {
+            subnet_id,
+            witness_begin,
+            msg_begin,
+            byte_limit: slice_byte_limit,
+        } in slice_pulls
+        {
             // `XNetEndpoint` URL of a node on `subnet_id`.
             let endpoint_locator = match self.endpoint_resolver.xnet_endpoint_url(
                 subnet_id,
                 witness_begin,
                 msg_begin,
-                // XNetEndpoint only counts message bytes, allow some overhead (measuread: 350
-                // bytes for certification plus base witness, 2% for large payloads).
-                (slice_byte_limit.saturating_sub(350)) * 98 / 100,
+                message_byte_limit(slice_byte_limit),
             ) {
                 Ok(endpoint_locator) => endpoint_locator,
                 Err(e) => {
@@ -1234,6 +1323,99 @@ impl PoolRefillTask {
     }
 }
 
+/// A slice to be pulled from a remote subnet in order to refill the pool.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct SlicePull {
+    /// The subnet to pull the slice from.
+    pub subnet_id: SubnetId,
+
+    /// The index of the first message covered by the witness.
+    pub witness_begin: StreamIndex,
+
+    /// The index of the first message to include in the slice.
+    pub msg_begin: StreamIndex,
+
+    /// The byte size limit of the slice, including certification and witness.
+    pub byte_limit: usize,
+}
+
+/// Decides which slices to pull from which subnets in order to refill `pool`,
+/// skipping `own_subnet_id` (the loopback stream is routed separately).
+///
+/// Returns no slices if the pool is already larger than
+/// `pool_byte_size_soft_cap`. Where a pooled slice begins at the cached stream
+/// position, only the suffix following it is pulled (to be appended); else a
+/// complete slice is pulled from the cached stream position.
+pub(crate) fn plan_slice_pulls(
+    pool: &CertifiedSlicePool,
+    own_subnet_id: SubnetId,
+    pool_byte_size_soft_cap: usize,
+    slice_byte_size_max: usize,
+) -> Vec<SlicePull> {
+    if pool.byte_size() > pool_byte_size_soft_cap {
+        // Abort if pool is already full.
+        return vec![];
+    }
+
+    let pool_slice_stats = pool
+        .peers()
+        // Skip our own subnet, the loopback stream is routed separately.
+        .filter(|&&subnet_id| subnet_id != own_subnet_id)
+        .map(|&subnet_id| (subnet_id, pool.slice_stats(subnet_id)))
+        .collect::<BTreeMap<_, _>>();
+
+    let mut slice_pulls = Vec::new();
+    for (subnet_id, slice_stats) in pool_slice_stats {
+        let (stream_position, messages_begin, msg_count, byte_size) = match slice_stats {
+            // Have a cached stream position.
+            (Some(stream_position), messages_begin, msg_count, byte_size) => {
+                (stream_position, messages_begin, msg_count, byte_size)
+            }
+
+            // No cached stream position, no pooling / refill necessary.
+            (None, _, _, _) => continue,
+        };
+
+        let (witness_begin, msg_begin, byte_limit) = match messages_begin {
+            // Existing pooled stream, pull partial slice and append.
+            Some(messages_begin) if messages_begin == stream_position.message_index => (
+                stream_position.message_index,
+                stream_position.message_index + (msg_count as u64).into(),
+                slice_byte_size_max.saturating_sub(byte_size),
+            ),
+
+            // No pooled stream, or pooled stream does not begin at cached stream position, pull
+            // complete slice from cached stream position.
+            _ => (
+                stream_position.message_index,
+                stream_position.message_index,
+                slice_byte_size_max,
+            ),
+        };
+
+        if byte_limit < SLICE_BYTE_SIZE_MIN {
+            // No more space left in the pool for this slice, bail out.
+            continue;
+        }
+
+        slice_pulls.push(SlicePull {
+            subnet_id,
+            witness_begin,
+            msg_begin,
+            byte_limit,
+        });
+    }
+    slice_pulls
+}
+
+/// Converts a slice byte limit into a limit on the message bytes of the slice,
+/// as enforced by the source subnet.
+pub(crate) fn message_byte_limit(slice_byte_limit: usize) -> usize {
+    // Only message bytes are counted, allow some overhead (measured: 350 bytes for
+    // certification plus base witness, 2% for large payloads).
+    (slice_byte_limit.saturating_sub(350)) * 98 / 100
+}
+
 /// A handle for a `PoolRefillTask`to be used for triggering pool refills and
 /// terminating the task (by dropping the handle).
 pub struct RefillTaskHandle(Mutex<mpsc::Sender<()>>);
@@ -0,0 +1,348 @@
+//! Pulling of `CertifiedStreamSlices` over gossip, as an alternative to
+//! querying the `XNetEndpoints` of remote subnets over HTTPS.
+//!
+//! A node does not advertise slices on its own. Instead, the nodes of the
+//! destination subnet send the stream positions they have reached (rendered by
+//! `get_filter()`) to their XNet peers on the source subnets in retransmission
+//! requests. The source nodes encode a slice from their latest certified state
+//! for each such request and advertise it back to the requesting node only.
+//! The destination nodes verify the certification of each downloaded slice
+//! before pooling it.
+
+use super::{
+    message_byte_limit, plan_slice_pulls, SlicePull, XNetPayloadBuilderMetrics,
+    POOL_BYTE_SIZE_SOFT_CAP, POOL_SLICE_BYTE_SIZE_MAX, STATUS_SUCCESS,
+};
+use crate::certified_slice_pool::CertifiedSlicePool;
+use ic_artifact_manager::artifact::XNetStreamSliceArtifact;
+use ic_interfaces::{
+    artifact_manager::{ArtifactAcceptance, ArtifactClient, ArtifactProcessor, ProcessingResult},
+    artifact_pool::{ArtifactPoolError, UnvalidatedArtifact},
+    certified_stream_store::CertifiedStreamStore,
+    registry::RegistryClient,
+    time_source::TimeSource,
+};
+use ic_logger::{debug, warn, ReplicaLogger};
+use ic_types::{
+    artifact::{
+        Advert, ArtifactKind, Priority, PriorityFn, XNetStreamSliceAttribute,
+        XNetStreamSliceFilter, XNetStreamSliceId, XNetStreamSliceMessage, XNetStreamSliceRequest,
+    },
+    chunkable::{Chunkable, SingleChunked},
+    xnet::CertifiedStreamSlice,
+    NodeId, SubnetId,
+};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+/// The number of identifiers of downloaded slices that are remembered in
+/// order not to download them again.
+const SLICE_CACHE_SIZE: usize = 100;
+
+/// Upper bound on the total size of the slices encoded for remote subnets that
+/// are kept around to be downloaded. The oldest slices are dropped first.
+pub(super) const SERVED_SLICES_BYTE_SIZE_MAX: usize = 4 * POOL_SLICE_BYTE_SIZE_MAX;
+
+/// The artifact client and processor of `XNetStreamSliceArtifacts`.
+///
+/// On the destination subnet, it puts the downloaded slices into the slice
+/// pool of the `XNetPayloadBuilderImpl` it was created by. On the source
+/// subnet, it encodes the slices requested by remote nodes.
+pub struct XNetSliceGossipClient {
+    /// The subnet this node is on.
+    subnet_id: SubnetId,
+
+    /// Used for encoding the slices requested by remote subnets and for
+    /// verifying the slices downloaded from them.
+    certified_stream_store: Arc<dyn CertifiedStreamStore>,
+
+    /// Used for looking up the keys that downloaded slices are verified with.
+    registry: Arc<dyn RegistryClient>,
+
+    /// The payload builder's slice pool.
+    slice_pool: Arc<Mutex<CertifiedSlicePool>>,
+
+    /// The slices most recently encoded for remote subnets, oldest first. Their
+    /// total size is bounded by `SERVED_SLICES_BYTE_SIZE_MAX`.
+    served_slices: Mutex<VecDeque<XNetStreamSliceMessage>>,
+
+    /// The identifiers of the slices most recently downloaded, oldest first.
+    received_slices: Mutex<VecDeque<XNetStreamSliceId>>,
+
+    metrics: Arc<XNetPayloadBuilderMetrics>,
+
+    log: ReplicaLogger,
+}
+
+impl XNetSliceGossipClient {
+    pub(super) fn new(
+        subnet_id: SubnetId,
+        certified_stream_store: Arc<dyn CertifiedStreamStore>,
+        registry: Arc<dyn RegistryClient>,
+        slice_pool: Arc<Mutex<CertifiedSlicePool>>,
+        metrics: Arc<XNetPayloadBuilderMetrics>,
+        log: ReplicaLogger,
+    ) -> Self {
+        Self {
+            subnet_id,
+            certified_stream_store,
+            registry,
+            slice_pool,
+            served_slices: Mutex::new(VecDeque::new()),
+            received_slices: Mutex::new(VecDeque::new()),
+            metrics,
+            log,
+        }
+    }
+
+    /// Encodes the slice of the stream to `destination` requested by
+    /// `request` from the latest certified state. The requested byte limit is
+    /// capped at what a destination node would pool from one stream.
+    fn encode_slice(
+        &self,
+        destination: SubnetId,
+        request: &XNetStreamSliceRequest,
+    ) -> Option<XNetStreamSliceMessage> {
+        let slice = match self.certified_stream_store.encode_certified_stream_slice(
+            destination,
+            Some(request.witness_begin),
+            Some(request.msg_begin),
+            None,
+            Some(
+                request
+                    .byte_limit
+                    .min(message_byte_limit(POOL_SLICE_BYTE_SIZE_MAX)),
+            ),
+        ) {
+            Ok(slice) => slice,
+            Err(e) => {
+                debug!(
+                    self.log,
+                    "Failed to encode stream slice for subnet {}: {}", destination, e
+                );
+                return None;
+            }
+        };
+
+        Some(XNetStreamSliceMessage {
+            id: XNetStreamSliceId {
+                source: self.subnet_id,
+                destination,
+                height: slice.certification.height,
+                witness_begin: request.witness_begin,
+                msg_begin: request.msg_begin,
+            },
+            slice,
+        })
+    }
+
+    /// Tests whether `slice` from `source` carries a valid certification, as
+    /// of the latest registry version.
+    fn is_valid(&self, source: SubnetId, slice: &CertifiedStreamSlice) -> bool {
+        match self.certified_stream_store.decode_certified_stream_slice(
+            source,
+            self.registry.get_latest_version(),
+            slice,
+        ) {
+            Ok(_) => true,
+            Err(e) => {
+                warn!(
+                    self.log,
+                    "Failed to verify stream slice from subnet {}: {}", source, e
+                );
+                false
+            }
+        }
+    }
+}
+
+/// Pushes `item` to the back of `queue`, unless already present, dropping the
+/// oldest items beyond `SLICE_CACHE_SIZE`.
+fn push_bounded<T: PartialEq>(queue: &mut VecDeque<T>, item: T) {
+    if queue.contains(&item) {
+        return;
+    }
+    queue.push_back(item);
+    while queue.len() > SLICE_CACHE_SIZE {
+        queue.pop_front();
+    }
+}
+
+/// Estimated size of a served slice, in bytes.
+fn slice_byte_size(msg: &XNetStreamSliceMessage) -> usize {
+    msg.slice.payload.len() + msg.slice.merkle_proof.len()
+}
+
+/// Pushes `msg` to the back of `served_slices`, unless already present,
+/// dropping the oldest slices until their total size is within
+/// `SERVED_SLICES_BYTE_SIZE_MAX`.
+pub(super) fn push_served(
+    served_slices: &mut VecDeque<XNetStreamSliceMessage>,
+    msg: XNetStreamSliceMessage,
+) {
+    if served_slices.iter().any(|served| served.id == msg.id) {
+        return;
+    }
+    served_slices.push_back(msg);
+    let mut byte_size: usize = served_slices.iter().map(slice_byte_size).sum();
+    while byte_size > SERVED_SLICES_BYTE_SIZE_MAX && served_slices.len() > 1 {
+        if let Some(dropped) = served_slices.pop_front() {
+            byte_size -= slice_byte_size(&dropped);
+        }
+    }
+}
+
+impl ArtifactClient<XNetStreamSliceArtifact> for XNetSliceGossipClient {
+    /// Accepts all slices, their certifications are verified before they are
+    /// pooled.
+    fn check_artifact_acceptance(
+        &self,
+        msg: XNetStreamSliceMessage,
+        _peer_id: &NodeId,
+    ) -> Result<ArtifactAcceptance<XNetStreamSliceMessage>, ArtifactPoolError> {
+        Ok(ArtifactAcceptance::AcceptedForProcessing(msg))
+    }
+
+    fn has_artifact(&self, msg_id: &XNetStreamSliceId) -> bool {
+        self.received_slices.lock().unwrap().contains(msg_id)
+            || self
+                .served_slices
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|msg| &msg.id == msg_id)
+    }
+
+    fn get_validated_by_identifier(
+        &self,
+        msg_id: &XNetStreamSliceId,
+    ) -> Option<XNetStreamSliceMessage> {
+        self.served_slices
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|msg| &msg.id == msg_id)
+            .cloned()
+    }
+
+    /// Returns the stream positions reached in the slice pool, for each remote
+    /// subnet that a slice should be pulled from.
+    fn get_filter(&self) -> XNetStreamSliceFilter {
+        let slice_pulls = plan_slice_pulls(
+            &self.slice_pool.lock().unwrap(),
+            self.subnet_id,
+            POOL_BYTE_SIZE_SOFT_CAP,
+            POOL_SLICE_BYTE_SIZE_MAX,
+        );
+
+        XNetStreamSliceFilter {
+            destination: Some(self.subnet_id),
+            requests: slice_pulls
+                .into_iter()
+                .map(|pull: SlicePull| {
+                    (
+                        pull.subnet_id,
+                        XNetStreamSliceRequest {
+                            witness_begin: pull.witness_begin,
+                            msg_begin: pull.msg_begin,
+                            byte_limit: message_byte_limit(pull.byte_limit),
+                        },
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    /// Encodes the slice of this subnet's stream requested by the filter and
+    /// returns its advert.
+    fn get_all_validated_by_filter(
+        &self,
+        filter: &XNetStreamSliceFilter,
+    ) -> Vec<Advert<XNetStreamSliceArtifact>> {
+        let destination = match filter.destination {
+            Some(destination) if destination != self.subnet_id => destination,
+            _ => return vec![],
+        };
+        let request = match filter.requests.get(&self.subnet_id) {
+            Some(request) => request,
+            None => return vec![],
+        };
+
+        match self.encode_slice(destination, request) {
+            Some(msg) => {
+                let advert = XNetStreamSliceArtifact::message_to_advert(&msg);
+                push_served(&mut self.served_slices.lock().unwrap(), msg);
+                vec![advert]
+            }
+            None => vec![],
+        }
+    }
+
+    /// Only slices addressed to this subnet are downloaded.
+    fn get_priority_function(
+        &self,
+    ) -> Option<PriorityFn<XNetStreamSliceId, XNetStreamSliceAttribute>> {
+        let subnet_id = self.subnet_id;
+        Some(Box::new(
+            move |id: &XNetStreamSliceId, _: &XNetStreamSliceAttribute| {
+                if id.destination == subnet_id {
+                    Priority::FetchNow
+                } else {
+                    Priority::Drop
+                }
+            },
+        ))
+    }
+
+    fn get_chunk_tracker(&self, _id: &XNetStreamSliceId) -> Box<dyn Chunkable + Send + Sync> {
+        Box::new(SingleChunked::XNetStreamSlice)
+    }
+}
+
+impl ArtifactProcessor<XNetStreamSliceArtifact> for XNetSliceGossipClient {
+    /// Puts / appends the downloaded slices to the slice pool, provided that
+    /// the resulting slice carries a valid certification. Never produces
+    /// adverts, slices are only advertised in response to retransmission
+    /// requests.
+    fn process_changes(
+        &self,
+        _time_source: &dyn TimeSource,
+        artifacts: Vec<UnvalidatedArtifact<XNetStreamSliceMessage>>,
+    ) -> (Vec<Advert<XNetStreamSliceArtifact>>, ProcessingResult) {
+        for artifact in artifacts {
+            let XNetStreamSliceMessage { id, slice } = artifact.message;
+            if id.destination != self.subnet_id || id.source == self.subnet_id {
+                warn!(
+                    self.log,
+                    "Dropping stream slice {:?} from peer {}", id, artifact.peer_id
+                );
+                continue;
+            }
+
+            let is_valid = |slice: &CertifiedStreamSlice| self.is_valid(id.source, slice);
+            let res = if id.witness_begin != id.msg_begin {
+                // Pulled a stream suffix, append to pooled slice.
+                self.slice_pool
+                    .lock()
+                    .unwrap()
+                    .append_verified(id.source, slice, is_valid)
+            } else {
+                // Pulled a complete stream, replace pooled slice (if any).
+                self.slice_pool
+                    .lock()
+                    .unwrap()
+                    .put_verified(id.source, slice, is_valid)
+            };
+            let status = match res {
+                Ok(()) => STATUS_SUCCESS,
+                Err(e) => e.to_label_value(),
+            };
+            self.metrics.observe_pull_attempt(status);
+            push_bounded(&mut self.received_slices.lock().unwrap(), id);
+        }
+
+        (vec![], ProcessingResult::StateUnchanged)
+    }
+}
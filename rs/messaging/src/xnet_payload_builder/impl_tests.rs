@@ -3,16 +3,27 @@
 use super::test_fixtures::*;
 use super::*;
 use assert_matches::assert_matches;
-use ic_interfaces::{certified_stream_store::DecodeStreamError, state_manager::StateReader};
+use ic_interfaces::{
+    artifact_manager::{ArtifactClient, ArtifactProcessor},
+    artifact_pool::UnvalidatedArtifact,
+    certified_stream_store::DecodeStreamError,
+    state_manager::StateReader,
+};
 use ic_registry_subnet_type::SubnetType;
 use ic_test_utilities::{
     certified_stream_store::MockCertifiedStreamStore,
     crypto::fake_tls_handshake::FakeTlsHandshake,
+    mock_time,
     state_manager::FakeStateManager,
-    types::ids::{subnet_test_id, SUBNET_1, SUBNET_2, SUBNET_3, SUBNET_4, SUBNET_5},
-    with_test_replica_logger,
+    types::ids::{subnet_test_id, NODE_2, SUBNET_1, SUBNET_2, SUBNET_3, SUBNET_4, SUBNET_5},
+    with_test_replica_logger, FastForwardTimeSource,
+};
+use ic_types::artifact::{
+    Priority, XNetStreamSliceAttribute, XNetStreamSliceFilter, XNetStreamSliceId,
+    XNetStreamSliceMessage, XNetStreamSliceRequest,
 };
 use maplit::btreemap;
+use std::collections::VecDeque;
 
 #[tokio::test]
 async fn expected_indices_for_stream() {
@@ -448,6 +459,242 @@ async fn validate_slice_above_msg_limit() {
     });
 }
 
+#[test]
+fn allocate_slice_byte_limits_by_backlog() {
+    let demand = |byte_size, backlog| SliceDemand { byte_size, backlog };
+
+    // Everything fits, all slices are allotted their full size.
+    assert_eq!(
+        btreemap! { SUBNET_1 => 100, SUBNET_2 => 200 },
+        allocate_slice_byte_limits(
+            1000,
+            &btreemap! { SUBNET_1 => demand(100, 1), SUBNET_2 => demand(200, 100) }
+        )
+    );
+
+    // The large slice of the congested `SUBNET_3` only gets what is left after
+    // the other slices, which fit into their shares.
+    assert_eq!(
+        btreemap! { SUBNET_1 => 100, SUBNET_2 => 200, SUBNET_3 => 700 },
+        allocate_slice_byte_limits(
+            1000,
+            &btreemap! {
+                SUBNET_1 => demand(100, 10),
+                SUBNET_2 => demand(200, 10),
+                SUBNET_3 => demand(5000, 10),
+            }
+        )
+    );
+
+    // No slice fits, the space is split in proportion to the backlogs.
+    assert_eq!(
+        btreemap! { SUBNET_1 => 250, SUBNET_2 => 750 },
+        allocate_slice_byte_limits(
+            1000,
+            &btreemap! { SUBNET_1 => demand(2000, 10), SUBNET_2 => demand(2000, 30) }
+        )
+    );
+
+    // Streams without backlog are weighted as having a backlog of one message.
+    assert_eq!(
+        btreemap! { SUBNET_1 => 500, SUBNET_2 => 500 },
+        allocate_slice_byte_limits(
+            1000,
+            &btreemap! { SUBNET_1 => demand(2000, 0), SUBNET_2 => demand(2000, 1) }
+        )
+    );
+}
+
+#[test]
+fn gossip_client_only_serves_remote_subnets() {
+    with_test_replica_logger(|log| {
+        let state_manager = Arc::new(FakeStateManager::new());
+        let (_, gossip_client) = XNetPayloadBuilderImpl::new_with_gossip(
+            Arc::clone(&state_manager) as Arc<_>,
+            state_manager,
+            get_empty_registry_for_test(),
+            LOCAL_SUBNET,
+            &MetricsRegistry::new(),
+            log,
+        );
+
+        // Nothing to pull yet, but the filter identifies the requesting subnet.
+        let filter = gossip_client.get_filter();
+        assert_eq!(Some(LOCAL_SUBNET), filter.destination);
+        assert!(filter.requests.is_empty());
+
+        // Requests from our own subnet or not addressed to it are ignored.
+        let request = XNetStreamSliceRequest {
+            witness_begin: 0.into(),
+            msg_begin: 0.into(),
+            byte_limit: POOL_SLICE_BYTE_SIZE_MAX,
+        };
+        let own_filter = XNetStreamSliceFilter {
+            destination: Some(LOCAL_SUBNET),
+            requests: btreemap! { LOCAL_SUBNET => request.clone() },
+        };
+        assert!(gossip_client
+            .get_all_validated_by_filter(&own_filter)
+            .is_empty());
+        let other_filter = XNetStreamSliceFilter {
+            destination: Some(SUBNET_2),
+            requests: btreemap! { SUBNET_3 => request },
+        };
+        assert!(gossip_client
+            .get_all_validated_by_filter(&other_filter)
+            .is_empty());
+
+        // Only slices addressed to our own subnet are downloaded.
+        let priority_fn = gossip_client.get_priority_function().unwrap();
+        let slice_id = |destination| XNetStreamSliceId {
+            source: REMOTE_SUBNET,
+            destination,
+            height: Height::new(1),
+            witness_begin: 0.into(),
+            msg_begin: 0.into(),
+        };
+        let attribute = |destination| XNetStreamSliceAttribute { destination };
+        assert_eq!(
+            Priority::FetchNow,
+            priority_fn(&slice_id(LOCAL_SUBNET), &attribute(LOCAL_SUBNET))
+        );
+        assert_eq!(
+            Priority::Drop,
+            priority_fn(&slice_id(SUBNET_2), &attribute(SUBNET_2))
+        );
+    });
+}
+
+#[test]
+fn gossip_client_caps_served_slices_and_verifies_received_slices() {
+    with_test_replica_logger(|log| {
+        // A `CertifiedStreamStore` that encodes slices no larger than what we would
+        // pool from one stream; and fails to verify any slice.
+        let mut certified_stream_store = MockCertifiedStreamStore::new();
+        certified_stream_store
+            .expect_encode_certified_stream_slice()
+            .withf(|_, _, _, _, byte_limit| {
+                *byte_limit == Some(message_byte_limit(POOL_SLICE_BYTE_SIZE_MAX))
+            })
+            .returning(|_, _, _, _, _| {
+                Ok(make_certified_stream_slice(
+                    REMOTE_SUBNET,
+                    StreamConfig {
+                        message_begin: 0,
+                        message_end: 2,
+                        signal_end: 0,
+                    },
+                ))
+            });
+        certified_stream_store
+            .expect_decode_certified_stream_slice()
+            .returning(|subnet_id, _, _| Err(DecodeStreamError::InvalidSignature(subnet_id)));
+
+        let (payload_builder, gossip_client) = XNetPayloadBuilderImpl::new_with_gossip(
+            Arc::new(FakeStateManager::new()),
+            Arc::new(certified_stream_store),
+            get_empty_registry_for_test(),
+            LOCAL_SUBNET,
+            &MetricsRegistry::new(),
+            log,
+        );
+
+        // A remote node requesting an arbitrarily large slice gets a capped one.
+        let request = XNetStreamSliceRequest {
+            witness_begin: 0.into(),
+            msg_begin: 0.into(),
+            byte_limit: usize::MAX,
+        };
+        let filter = XNetStreamSliceFilter {
+            destination: Some(REMOTE_SUBNET),
+            requests: btreemap! { LOCAL_SUBNET => request },
+        };
+        let adverts = gossip_client.get_all_validated_by_filter(&filter);
+        assert_eq!(1, adverts.len());
+        assert!(gossip_client.has_artifact(&adverts[0].id));
+
+        // A slice received from a remote subnet that does not verify is not pooled.
+        let slice = make_certified_stream_slice(
+            LOCAL_SUBNET,
+            StreamConfig {
+                message_begin: 0,
+                message_end: 2,
+                signal_end: 0,
+            },
+        );
+        let id = XNetStreamSliceId {
+            source: REMOTE_SUBNET,
+            destination: LOCAL_SUBNET,
+            height: slice.certification.height,
+            witness_begin: 0.into(),
+            msg_begin: 0.into(),
+        };
+        gossip_client.process_changes(
+            &*FastForwardTimeSource::new(),
+            vec![UnvalidatedArtifact {
+                message: XNetStreamSliceMessage {
+                    id: id.clone(),
+                    slice,
+                },
+                peer_id: NODE_2,
+                timestamp: mock_time(),
+            }],
+        );
+        assert_eq!(
+            (None, None, 0, 0),
+            payload_builder
+                .slice_pool
+                .lock()
+                .unwrap()
+                .slice_stats(REMOTE_SUBNET)
+        );
+        // But it is not downloaded again.
+        assert!(gossip_client.has_artifact(&id));
+    });
+}
+
+#[test]
+fn served_slices_are_bounded_by_size() {
+    let slice = |height: u64| {
+        let mut slice = make_certified_stream_slice(
+            REMOTE_SUBNET,
+            StreamConfig {
+                message_begin: 0,
+                message_end: 1,
+                signal_end: 0,
+            },
+        );
+        slice.payload = vec![0; POOL_SLICE_BYTE_SIZE_MAX];
+        XNetStreamSliceMessage {
+            id: XNetStreamSliceId {
+                source: LOCAL_SUBNET,
+                destination: REMOTE_SUBNET,
+                height: Height::new(height),
+                witness_begin: 0.into(),
+                msg_begin: 0.into(),
+            },
+            slice,
+        }
+    };
+    let capacity = (gossip::SERVED_SLICES_BYTE_SIZE_MAX / POOL_SLICE_BYTE_SIZE_MAX) as u64;
+
+    let mut served_slices = VecDeque::new();
+    for height in 0..=capacity {
+        gossip::push_served(&mut served_slices, slice(height));
+    }
+
+    // The oldest slice was dropped to make room for the newest one.
+    let heights: Vec<_> = served_slices
+        .iter()
+        .map(|msg| msg.id.height.get())
+        .collect();
+    assert_eq!((1..=capacity).collect::<Vec<_>>(), heights);
+
+    // Slices already served are not duplicated.
+    gossip::push_served(&mut served_slices, slice(capacity));
+    assert_eq!(capacity as usize, served_slices.len());
+}
+
 /// Constructs an `XNetPayloadBuilder` around `state_manager`, `log` and an
 /// empty registry.
 fn get_xnet_payload_builder_for_test(
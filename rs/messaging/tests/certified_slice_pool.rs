@@ -793,6 +793,69 @@ proptest! {
         });
     }
 
+    #[test]
+    fn pool_verified_never_replaces_valid_slice(
+        (stream, from, msg_count) in arb_stream_slice(2, 10),
+    ) {
+        with_test_replica_logger(|log| {
+            let fixture = StateManagerFixture::new(log).with_stream(DST_SUBNET, stream);
+
+            let mut pool = CertifiedSlicePool::new(&fixture.metrics);
+
+            // Slice midpoint.
+            let prefix_len = msg_count / 2;
+            let suffix_len = msg_count - prefix_len;
+            let mid = from + (prefix_len as u64).into();
+            let assert_prefix_pooled = |pool: &CertifiedSlicePool| {
+                assert_matches!(
+                    pool.slice_stats(SRC_SUBNET),
+                    (None, Some(messages_begin), count, _)
+                        if messages_begin == from && count == prefix_len
+                );
+            };
+
+            // A slice that does not verify is not pooled.
+            let prefix_slice = fixture.get_slice(DST_SUBNET, from, prefix_len);
+            assert_matches!(
+                pool.put_verified(SRC_SUBNET, prefix_slice.clone(), |_| false),
+                Err(CertifiedSliceError::InvalidCertification)
+            );
+            assert_eq!((None, None, 0, 0), pool.slice_stats(SRC_SUBNET));
+
+            // One that does is.
+            pool.put_verified(SRC_SUBNET, prefix_slice, |_| true).unwrap();
+            assert_prefix_pooled(&pool);
+
+            // A complete slice that does not verify does not replace it.
+            let full_slice = fixture.get_slice(DST_SUBNET, from, msg_count);
+            assert_matches!(
+                pool.put_verified(SRC_SUBNET, full_slice.clone(), |_| false),
+                Err(CertifiedSliceError::InvalidCertification)
+            );
+            assert_prefix_pooled(&pool);
+
+            // Nor does appending a suffix, if the resulting slice does not verify.
+            let suffix_slice = fixture.get_partial_slice(DST_SUBNET, from, mid, suffix_len);
+            assert_matches!(
+                pool.append_verified(SRC_SUBNET, suffix_slice.clone(), |_| false),
+                Err(CertifiedSliceError::InvalidCertification)
+            );
+            assert_prefix_pooled(&pool);
+
+            // The slice that is verified is the one resulting from the append.
+            pool.append_verified(SRC_SUBNET, suffix_slice, |merged| {
+                assert_slices_eq(full_slice.clone(), merged.clone());
+                true
+            })
+            .unwrap();
+            assert_matches!(
+                pool.slice_stats(SRC_SUBNET),
+                (None, Some(messages_begin), count, _)
+                    if messages_begin == from && count == msg_count
+            );
+        });
+    }
+
     #[test]
     fn pool_append_non_empty_to_non_empty(
         (mut stream, from, msg_count) in arb_stream_slice(2, 10),
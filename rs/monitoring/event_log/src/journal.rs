@@ -0,0 +1,291 @@
+//! The on-disk journal of the event log.
+//!
+//! The journal is a directory of segment files, each containing one JSON
+//! encoded `EventRecord` per line, so that the journal can also be inspected
+//! with standard tools. A segment is rotated once it exceeds the maximal
+//! segment size, and only the most recent segments are retained, which bounds
+//! the size of the journal on disk. Every start of the replica begins a new
+//! segment.
+use crate::{Event, EventRecord};
+use ic_config::event_log::Config as EventLogConfig;
+use ic_logger::{error, warn, ReplicaLogger};
+use ic_metrics::MetricsRegistry;
+use ic_types::time::Time;
+use prometheus::IntCounter;
+use std::fs;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+const SEGMENT_EXTENSION: &str = "jsonl";
+
+#[derive(Clone, Debug)]
+struct Metrics {
+    // Amount of I/O errors. Any number above 0 is critical.
+    io_errors: IntCounter,
+    // Amount of events recorded.
+    recorded_events: IntCounter,
+}
+
+impl Metrics {
+    fn new(registry: &MetricsRegistry) -> Self {
+        Self {
+            io_errors: registry.int_counter(
+                "event_log_io_errors",
+                "The number of I/O errors happened during appending to or rotating the event log.",
+            ),
+            recorded_events: registry.int_counter(
+                "event_log_recorded_events",
+                "The number of events recorded in the event log.",
+            ),
+        }
+    }
+}
+
+/// The segment currently appended to.
+struct Segment {
+    segment_number: u64,
+    writer: BufWriter<fs::File>,
+    size_bytes: u64,
+}
+
+/// The append-only, bounded journal of events.
+pub(crate) struct Journal {
+    path: PathBuf,
+    config: EventLogConfig,
+    segment: Option<Segment>,
+    next_segment_number: u64,
+    next_sequence_number: u64,
+    metrics: Metrics,
+    log: ReplicaLogger,
+}
+
+impl Journal {
+    /// Opens the journal in the given directory. The first append starts a
+    /// new segment after the existing ones, and the sequence numbers continue
+    /// after the last retained record.
+    pub(crate) fn new(
+        path: PathBuf,
+        config: EventLogConfig,
+        metrics_registry: &MetricsRegistry,
+        log: ReplicaLogger,
+    ) -> Self {
+        let metrics = Metrics::new(metrics_registry);
+        let segments = match list_segments(&path) {
+            Ok(segments) => segments,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => {
+                error!(log, "Listing the event log segments failed: {:?}", err);
+                metrics.io_errors.inc();
+                Vec::new()
+            }
+        };
+        let next_segment_number = segments.last().map_or(0, |(number, _)| number + 1);
+        let next_sequence_number = match last_sequence_number(&segments) {
+            Ok(last) => last.map_or(0, |number| number + 1),
+            Err(err) => {
+                error!(log, "Reading the last event log record failed: {:?}", err);
+                metrics.io_errors.inc();
+                0
+            }
+        };
+        Self {
+            path,
+            config,
+            segment: None,
+            next_segment_number,
+            next_sequence_number,
+            metrics,
+            log,
+        }
+    }
+
+    /// Appends the given event to the journal and flushes it to the disk.
+    pub(crate) fn append(&mut self, time: Time, event: Event) {
+        let record = EventRecord {
+            sequence_number: self.next_sequence_number,
+            time,
+            event,
+        };
+        match self.try_append(&record) {
+            Ok(()) => {
+                self.next_sequence_number += 1;
+                self.metrics.recorded_events.inc();
+            }
+            Err(err) => {
+                error!(self.log, "Appending to the event log failed: {:?}", err);
+                self.metrics.io_errors.inc();
+                // Continue in a fresh segment, as the current one might contain
+                // a partially written record.
+                self.segment = None;
+            }
+        }
+    }
+
+    fn try_append(&mut self, record: &EventRecord) -> io::Result<()> {
+        let mut line = serde_json::to_vec(record)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        line.push(b'\n');
+        let segment = self.current_segment()?;
+        segment.writer.write_all(&line)?;
+        segment.writer.flush()?;
+        segment.size_bytes += line.len() as u64;
+        Ok(())
+    }
+
+    /// Returns the segment to append to, rotating the current segment if it
+    /// exceeds the maximal segment size.
+    fn current_segment(&mut self) -> io::Result<&mut Segment> {
+        let rotate = self.segment.as_ref().map_or(true, |segment| {
+            segment.size_bytes >= self.config.max_segment_size_bytes
+        });
+        if rotate {
+            self.segment = None;
+            fs::create_dir_all(&self.path)?;
+            let segment_number = self.next_segment_number;
+            let file = fs::OpenOptions::new()
+                .create_new(true)
+                .append(true)
+                .open(segment_path(&self.path, segment_number))?;
+            self.next_segment_number += 1;
+            self.segment = Some(Segment {
+                segment_number,
+                writer: BufWriter::new(file),
+                size_bytes: 0,
+            });
+            self.purge_segments();
+        }
+        Ok(self.segment.as_mut().unwrap())
+    }
+
+    /// Deletes the oldest segments exceeding the number of retained segments.
+    fn purge_segments(&self) {
+        let segments = match list_segments(&self.path) {
+            Ok(segments) => segments,
+            Err(err) => {
+                error!(self.log, "Listing the event log segments failed: {:?}", err);
+                self.metrics.io_errors.inc();
+                return;
+            }
+        };
+        let retained = self.config.retained_segments.max(1);
+        let current = self.segment.as_ref().map(|segment| segment.segment_number);
+        let excess = segments.len().saturating_sub(retained);
+        for (segment_number, path) in segments.into_iter().take(excess) {
+            if Some(segment_number) == current {
+                continue;
+            }
+            if let Err(err) = fs::remove_file(&path) {
+                warn!(
+                    self.log,
+                    "Removing the event log segment {:?} failed: {:?}", path, err
+                );
+                self.metrics.io_errors.inc();
+            }
+        }
+    }
+}
+
+/// Reads the records of an event log in the order in which they were
+/// recorded, e.g. to analyze the event log of a node after an incident.
+///
+/// A truncated line at the end of a segment, which is left by a crash during
+/// an append or is still being written, ends the segment, and reading
+/// continues with the next one. Segments that are deleted while the event log
+/// is read are skipped.
+pub struct EventLogReader {
+    segments: std::vec::IntoIter<(u64, PathBuf)>,
+    reader: Option<BufReader<fs::File>>,
+}
+
+impl EventLogReader {
+    /// Opens the event log stored in the given directory.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Ok(Self::from_segments(list_segments(path)?))
+    }
+
+    fn from_segments(segments: Vec<(u64, PathBuf)>) -> Self {
+        Self {
+            segments: segments.into_iter(),
+            reader: None,
+        }
+    }
+
+    /// Reads the next complete line of the current segment. Returns None at
+    /// the end of the segment.
+    fn read_line(reader: &mut BufReader<fs::File>) -> io::Result<Option<String>> {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || !line.ends_with('\n') {
+            return Ok(None);
+        }
+        Ok(Some(line))
+    }
+}
+
+impl Iterator for EventLogReader {
+    type Item = io::Result<EventRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.reader.is_none() {
+                let (_, path) = self.segments.next()?;
+                match fs::File::open(&path) {
+                    Ok(file) => self.reader = Some(BufReader::new(file)),
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                    Err(err) => return Some(Err(err)),
+                }
+            }
+            match Self::read_line(self.reader.as_mut().unwrap()) {
+                Ok(Some(line)) => {
+                    return Some(
+                        serde_json::from_str(&line)
+                            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+                    )
+                }
+                Ok(None) => self.reader = None,
+                Err(err) => {
+                    self.reader = None;
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+}
+
+/// Returns the sequence number of the last record in the given segments.
+/// Only the last non-empty segment is read.
+fn last_sequence_number(segments: &[(u64, PathBuf)]) -> io::Result<Option<u64>> {
+    for segment in segments.iter().rev() {
+        let mut last = None;
+        for record in EventLogReader::from_segments(vec![segment.clone()]) {
+            last = Some(record?.sequence_number);
+        }
+        if last.is_some() {
+            return Ok(last);
+        }
+    }
+    Ok(None)
+}
+
+fn segment_path(path: &Path, segment_number: u64) -> PathBuf {
+    path.join(format!("{:020}.{}", segment_number, SEGMENT_EXTENSION))
+}
+
+/// Returns the segments in the given directory, ordered by segment number.
+fn list_segments(path: &Path) -> io::Result<Vec<(u64, PathBuf)>> {
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some(SEGMENT_EXTENSION) {
+            continue;
+        }
+        if let Some(segment_number) = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse::<u64>().ok())
+        {
+            segments.push((segment_number, path));
+        }
+    }
+    segments.sort();
+    Ok(segments)
+}
@@ -0,0 +1,378 @@
+//! The structured event log of the replica.
+//!
+//! The textual output of the `ReplicaLogger` is meant for humans and is hard
+//! to correlate across nodes after an incident. The event log complements it
+//! with machine-readable records of the consensus-critical decisions of the
+//! replica, e.g. which blocks it validated and when it created a catch-up
+//! package, so that the decisions of all nodes of a subnet can be lined up
+//! in a post-mortem.
+//!
+//! The events are appended to a bounded journal on disk by a background
+//! thread, so that recording an event never waits for the disk. They can be
+//! queried while the replica runs, see [EventLog::query], or read offline
+//! with an [EventLogReader].
+mod journal;
+
+pub use journal::EventLogReader;
+
+use crossbeam_channel::{bounded, Sender, TrySendError};
+use ic_config::event_log::Config as EventLogConfig;
+use ic_logger::ReplicaLogger;
+use ic_metrics::MetricsRegistry;
+use ic_types::time::{current_time, Time};
+use ic_utils::thread::JoinOnDrop;
+use journal::Journal;
+use prometheus::IntCounter;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// The maximum number of events waiting to be written. Events recorded while
+/// the queue is full are dropped.
+const MAX_QUEUED_EVENTS: usize = 10_000;
+
+/// The maximum number of records returned by [EventLog::query].
+pub const MAX_QUERY_RECORDS: usize = 10_000;
+
+/// A consensus-critical decision of the replica.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Event {
+    /// A block proposal was validated.
+    BlockValidated {
+        height: u64,
+        rank: u64,
+        /// The hex-encoded hash of the block.
+        block_hash: String,
+    },
+    /// Shares were aggregated into a full artifact, e.g. a notarization.
+    ShareAggregated {
+        /// The type of the aggregated artifact, e.g. `notarization`.
+        artifact: String,
+        height: u64,
+    },
+    /// A catch-up package was created from the shares of the subnet.
+    CatchUpPackageCreated {
+        height: u64,
+        /// The hex-encoded hash of the state the catch-up package certifies.
+        state_hash: String,
+    },
+    /// A peer was quarantined, i.e. its artifacts are ignored for a while.
+    PeerQuarantined {
+        /// The node ID of the peer.
+        peer: String,
+        reason: String,
+    },
+}
+
+impl Event {
+    /// Returns the kind of the event, as stored in the `kind` field of its
+    /// record.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Event::BlockValidated { .. } => "block_validated",
+            Event::ShareAggregated { .. } => "share_aggregated",
+            Event::CatchUpPackageCreated { .. } => "catch_up_package_created",
+            Event::PeerQuarantined { .. } => "peer_quarantined",
+        }
+    }
+}
+
+/// An event as stored in the event log.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventRecord {
+    /// The number of the record, which increases by one with every recorded
+    /// event, also across restarts.
+    pub sequence_number: u64,
+    /// The time at which the event was recorded.
+    pub time: Time,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+/// The criteria that the records returned by [EventLog::query] satisfy.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EventQuery {
+    /// If set, only events of this kind, see [Event::kind], are returned.
+    pub kind: Option<String>,
+    /// If set, only events recorded at or after this time are returned.
+    pub since: Option<Time>,
+    /// If set, only the given number of most recent matching records are
+    /// returned. At most [MAX_QUERY_RECORDS] records are returned in any case.
+    pub limit: Option<usize>,
+}
+
+impl EventQuery {
+    fn matches(&self, record: &EventRecord) -> bool {
+        self.kind
+            .as_ref()
+            .map_or(true, |kind| kind == record.event.kind())
+            && self.since.map_or(true, |since| record.time >= since)
+    }
+}
+
+/// A handle to the event log, which is cheap to clone and shared by all
+/// components recording events. A disabled event log drops all events.
+#[derive(Clone)]
+pub struct EventLog {
+    writer: Option<Arc<Writer>>,
+}
+
+/// A request to the thread writing the journal.
+enum WriterCommand {
+    Append(Time, Event),
+    /// Notifies the sender once all previous events are written.
+    Flush(Sender<()>),
+}
+
+/// The handle of the thread writing the journal.
+struct Writer {
+    path: PathBuf,
+    sender: Sender<WriterCommand>,
+    dropped_events: IntCounter,
+    // Declared after the sender: dropping the sender stops the thread, which
+    // is then joined.
+    _handle: JoinOnDrop<()>,
+}
+
+impl EventLog {
+    /// Opens the event log in the configured directory, or returns a
+    /// disabled event log if no directory is configured.
+    pub fn new(
+        config: &EventLogConfig,
+        metrics_registry: &MetricsRegistry,
+        log: ReplicaLogger,
+    ) -> Self {
+        let path = match &config.path {
+            Some(path) => path.clone(),
+            None => return Self::disabled(),
+        };
+        let dropped_events = metrics_registry.int_counter(
+            "event_log_dropped_events",
+            "The number of events dropped because too many events were waiting to be written.",
+        );
+        let mut journal = Journal::new(path.clone(), config.clone(), metrics_registry, log);
+        let (sender, receiver) = bounded(MAX_QUEUED_EVENTS);
+        let handle = std::thread::Builder::new()
+            .name("EventLogWriter".to_string())
+            .spawn(move || {
+                while let Ok(command) = receiver.recv() {
+                    match command {
+                        WriterCommand::Append(time, event) => journal.append(time, event),
+                        WriterCommand::Flush(done) => {
+                            let _ = done.send(());
+                        }
+                    }
+                }
+            })
+            .expect("failed to spawn event log writer");
+        Self {
+            writer: Some(Arc::new(Writer {
+                path,
+                sender,
+                dropped_events,
+                _handle: JoinOnDrop::new(handle),
+            })),
+        }
+    }
+
+    /// Returns an event log that drops all events, e.g. for tests.
+    pub fn disabled() -> Self {
+        Self { writer: None }
+    }
+
+    /// Records the given event. The event is written in the background;
+    /// failures are logged, but otherwise ignored, and if too many events are
+    /// waiting to be written, the event is dropped, so that the event log
+    /// never stalls the replica.
+    pub fn record(&self, event: Event) {
+        if let Some(writer) = &self.writer {
+            match writer
+                .sender
+                .try_send(WriterCommand::Append(current_time(), event))
+            {
+                Ok(()) | Err(TrySendError::Disconnected(_)) => {}
+                Err(TrySendError::Full(_)) => writer.dropped_events.inc(),
+            }
+        }
+    }
+
+    /// Blocks until all events recorded so far are written.
+    pub fn flush(&self) {
+        if let Some(writer) = &self.writer {
+            let (done_sender, done_receiver) = bounded(1);
+            if writer
+                .sender
+                .send(WriterCommand::Flush(done_sender))
+                .is_ok()
+            {
+                let _ = done_receiver.recv();
+            }
+        }
+    }
+
+    /// Returns the most recent retained records matching the given query, at
+    /// most [MAX_QUERY_RECORDS], in the order in which they were recorded.
+    /// Events that are still waiting to be written are not returned. A
+    /// disabled event log has no records.
+    ///
+    /// The journal is read from disk on the calling thread, without blocking
+    /// the writer.
+    pub fn query(&self, query: &EventQuery) -> io::Result<Vec<EventRecord>> {
+        let writer = match &self.writer {
+            Some(writer) => writer,
+            None => return Ok(Vec::new()),
+        };
+        let limit = query
+            .limit
+            .unwrap_or(MAX_QUERY_RECORDS)
+            .min(MAX_QUERY_RECORDS);
+        if limit == 0 {
+            return Ok(Vec::new());
+        }
+        let reader = match EventLogReader::open(&writer.path) {
+            Ok(reader) => reader,
+            // Nothing was recorded yet.
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+        let mut records = VecDeque::new();
+        for record in reader {
+            let record = record?;
+            if query.matches(&record) {
+                if records.len() == limit {
+                    records.pop_front();
+                }
+                records.push_back(record);
+            }
+        }
+        Ok(records.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_logger::replica_logger::no_op_logger;
+
+    fn open(path: &std::path::Path, retained_segments: usize) -> EventLog {
+        EventLog::new(
+            &EventLogConfig {
+                path: Some(path.to_path_buf()),
+                max_segment_size_bytes: 256,
+                retained_segments,
+            },
+            &MetricsRegistry::new(),
+            no_op_logger(),
+        )
+    }
+
+    fn aggregated(height: u64) -> Event {
+        Event::ShareAggregated {
+            artifact: "notarization".to_string(),
+            height,
+        }
+    }
+
+    #[test]
+    fn events_are_queried_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let event_log = open(dir.path(), 100);
+        for height in 0..10 {
+            event_log.record(aggregated(height));
+        }
+        event_log.record(Event::PeerQuarantined {
+            peer: "node".to_string(),
+            reason: "invalid artifacts".to_string(),
+        });
+        event_log.flush();
+
+        let records = event_log.query(&EventQuery::default()).unwrap();
+        assert_eq!(records.len(), 11);
+        assert!(records
+            .iter()
+            .enumerate()
+            .all(|(i, record)| record.sequence_number == i as u64));
+
+        let query = EventQuery {
+            kind: Some("share_aggregated".to_string()),
+            limit: Some(2),
+            ..EventQuery::default()
+        };
+        let events: Vec<_> = event_log
+            .query(&query)
+            .unwrap()
+            .into_iter()
+            .map(|record| record.event)
+            .collect();
+        assert_eq!(events, vec![aggregated(8), aggregated(9)]);
+    }
+
+    #[test]
+    fn only_the_most_recent_segments_are_retained() {
+        let dir = tempfile::tempdir().unwrap();
+        let event_log = open(dir.path(), 2);
+        for height in 0..100 {
+            event_log.record(aggregated(height));
+        }
+        event_log.flush();
+        let records = event_log.query(&EventQuery::default()).unwrap();
+        assert!(records.len() < 100);
+        assert_eq!(records.last().unwrap().event, aggregated(99));
+    }
+
+    #[test]
+    fn sequence_numbers_continue_after_a_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        open(dir.path(), 100).record(aggregated(1));
+        let event_log = open(dir.path(), 100);
+        event_log.record(aggregated(2));
+        event_log.flush();
+
+        let records = event_log.query(&EventQuery::default()).unwrap();
+        assert_eq!(
+            records
+                .iter()
+                .map(|record| record.sequence_number)
+                .collect::<Vec<_>>(),
+            vec![0, 1]
+        );
+    }
+
+    #[test]
+    fn queries_return_at_most_the_max_number_of_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let event_log = EventLog::new(
+            &EventLogConfig {
+                path: Some(dir.path().to_path_buf()),
+                ..EventLogConfig::default()
+            },
+            &MetricsRegistry::new(),
+            no_op_logger(),
+        );
+        for height in 0..(MAX_QUERY_RECORDS as u64 + 10) {
+            event_log.record(aggregated(height));
+            if height % 1000 == 0 {
+                // Keeps the queue of the writer from overflowing.
+                event_log.flush();
+            }
+        }
+        event_log.flush();
+
+        let records = event_log.query(&EventQuery::default()).unwrap();
+        assert_eq!(records.len(), MAX_QUERY_RECORDS);
+        assert_eq!(
+            records.last().unwrap().event,
+            aggregated(MAX_QUERY_RECORDS as u64 + 9)
+        );
+    }
+
+    #[test]
+    fn disabled_event_log_has_no_records() {
+        let event_log = EventLog::disabled();
+        event_log.record(aggregated(1));
+        assert!(event_log.query(&EventQuery::default()).unwrap().is_empty());
+    }
+}
@@ -1,7 +1,7 @@
 use ic_context_logger::{ContextLogger, LogMetadata, Logger};
 use ic_protobuf::log::log_entry::v1::LogEntry;
 use std::collections::HashMap;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::{Duration, Instant};
 
 /// A logger that logs `LogEntry`s using a `LogEntryLogger`
@@ -25,12 +25,19 @@ impl From<LogEntryLogger> for ReplicaLogger {
     }
 }
 
+/// The levels at which a `LogEntryLogger` logs.
+struct LogLevels {
+    // Only logs at `level` or above
+    level: slog::Level,
+    debug_overrides: Vec<String>,
+}
+
 /// Logs `LogEntry`s using `slog`
 pub struct LogEntryLogger {
     pub root: slog::Logger,
-    // Only logs at `level` or above
-    pub level: slog::Level,
-    pub debug_overrides: Vec<String>,
+    // Shared by all clones, so that changing the levels of one logger, e.g.
+    // when the config is reloaded, changes them for all of them.
+    levels: Arc<RwLock<LogLevels>>,
     pub sampling_rates: HashMap<String, u32>,
     pub enabled_tags: Vec<String>,
     pub last_log: Mutex<HashMap<String, Instant>>,
@@ -46,8 +53,10 @@ impl LogEntryLogger {
     ) -> Self {
         Self {
             root,
-            level,
-            debug_overrides,
+            levels: Arc::new(RwLock::new(LogLevels {
+                level,
+                debug_overrides,
+            })),
             sampling_rates,
             enabled_tags,
             last_log: Mutex::new(HashMap::new()),
@@ -55,6 +64,20 @@ impl LogEntryLogger {
     }
 }
 
+impl LogEntryLogger {
+    /// Only logs at `level` or above from now on, in this logger and all its
+    /// clones.
+    pub fn set_level(&self, level: slog::Level) {
+        self.levels.write().unwrap().level = level;
+    }
+
+    /// Logs at debug level in the given modules from now on, in this logger
+    /// and all its clones.
+    pub fn set_debug_overrides(&self, debug_overrides: Vec<String>) {
+        self.levels.write().unwrap().debug_overrides = debug_overrides;
+    }
+}
+
 impl From<slog::Logger> for LogEntryLogger {
     fn from(root: slog::Logger) -> Self {
         let level = if cfg!(debug_assertions) {
@@ -71,8 +94,7 @@ impl Clone for LogEntryLogger {
     fn clone(&self) -> Self {
         Self {
             root: self.root.new(slog::o!()),
-            level: self.level,
-            debug_overrides: self.debug_overrides.clone(),
+            levels: Arc::clone(&self.levels),
             sampling_rates: self.sampling_rates.clone(),
             enabled_tags: self.enabled_tags.clone(),
             // `last_log` is not cloned because different instances of this
@@ -115,13 +137,14 @@ impl Logger<LogEntry> for LogEntryLogger {
     }
 
     fn is_enabled_at(&self, level: slog::Level, module_path: &'static str) -> bool {
-        if !self.debug_overrides.is_empty()
+        let levels = self.levels.read().unwrap();
+        if !levels.debug_overrides.is_empty()
             && level == slog::Level::Debug
-            && self.debug_overrides.contains(&module_path.to_string())
+            && levels.debug_overrides.iter().any(|m| m == module_path)
         {
             true
         } else {
-            level.is_at_least(self.level)
+            level.is_at_least(levels.level)
         }
     }
 
@@ -176,6 +199,27 @@ pub fn get_module(module_path: &'static str) -> String {
 mod tests {
     use super::*;
 
+    #[test]
+    fn level_changes_apply_to_all_clones() {
+        let logger = LogEntryLogger::new(
+            slog::Logger::root(slog::Discard, slog::o!()),
+            slog::Level::Info,
+            vec![],
+            HashMap::new(),
+            vec![],
+        );
+        let clone = logger.clone();
+        assert!(!clone.is_enabled_at(slog::Level::Debug, "ic_test::module"));
+
+        logger.set_level(slog::Level::Warning);
+        assert!(!clone.is_enabled_at(slog::Level::Info, "ic_test::module"));
+        assert!(clone.is_enabled_at(slog::Level::Warning, "ic_test::module"));
+
+        logger.set_debug_overrides(vec!["ic_test::module".to_string()]);
+        assert!(clone.is_enabled_at(slog::Level::Debug, "ic_test::module"));
+        assert!(!clone.is_enabled_at(slog::Level::Debug, "ic_test::other"));
+    }
+
     #[test]
     fn test_should_sample() {
         let mut logger = LogEntryLogger::new(
@@ -0,0 +1,107 @@
+//! A minimal HTTP endpoint serving the metrics of a `MetricsRegistry` in the
+//! Prometheus text format.
+//!
+//! The replica exports its metrics through the metrics runtime, which also
+//! handles TLS. This endpoint is meant for test setups and tools, which want
+//! to scrape a registry without pulling in the whole runtime. Every request
+//! is answered with all metrics, regardless of its method and path.
+use prometheus::{Encoder, TextEncoder};
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// The interval at which the exporter checks whether it was stopped.
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// The time after which a connection that sends no request is dropped.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A running HTTP exporter. The exporter is stopped when this is dropped.
+pub struct HttpExporter {
+    local_addr: SocketAddr,
+    stopped: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl HttpExporter {
+    pub(crate) fn start(registry: prometheus::Registry, addr: SocketAddr) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        let local_addr = listener.local_addr()?;
+        let stopped = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stopped = Arc::clone(&stopped);
+            std::thread::Builder::new()
+                .name("metrics_http_exporter".to_string())
+                .spawn(move || {
+                    while !stopped.load(Ordering::Relaxed) {
+                        match listener.accept() {
+                            Ok((stream, _)) => {
+                                // A failed response only affects the client that
+                                // requested it.
+                                let _ = serve(&registry, stream);
+                            }
+                            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                                std::thread::sleep(ACCEPT_POLL_INTERVAL)
+                            }
+                            Err(_) => std::thread::sleep(ACCEPT_POLL_INTERVAL),
+                        }
+                    }
+                })?
+        };
+        Ok(Self {
+            local_addr,
+            stopped,
+            thread: Some(thread),
+        })
+    }
+
+    /// Returns the address the exporter listens on, e.g. to learn the port
+    /// chosen by the OS when started on port 0.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for HttpExporter {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn serve(registry: &prometheus::Registry, mut stream: TcpStream) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    // Only the end of the request headers is of interest.
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+
+    let encoder = TextEncoder::new();
+    let mut body = Vec::new();
+    encoder
+        .encode(&registry.gather(), &mut body)
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        encoder.format_type(),
+        body.len()
+    )?;
+    stream.write_all(&body)?;
+    stream.flush()
+}
@@ -1,9 +1,12 @@
 pub mod buckets;
+pub mod http_exporter;
 #[cfg(target_os = "linux")]
 pub mod process_collector;
 pub mod registry;
+pub mod snapshot;
 
 pub use registry::MetricsRegistry;
+pub use snapshot::MetricsSnapshot;
 
 use std::time::Instant;
 
@@ -1,7 +1,11 @@
+use crate::{http_exporter::HttpExporter, snapshot::MetricsSnapshot};
 use prometheus::{
     core::Collector, Gauge, GaugeVec, Histogram, HistogramOpts, HistogramVec, IntCounter,
     IntCounterVec, IntGauge, IntGaugeVec, Opts,
 };
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
 
 /// A wrapper around `prometheus::Registry` with helpers for creating metrics
 ///
@@ -12,6 +16,9 @@ use prometheus::{
 #[derive(Debug, Clone, Default)]
 pub struct MetricsRegistry {
     registry: prometheus::Registry,
+    /// The snapshots taken with `take_snapshot()`, by name. They are shared
+    /// by all clones of the registry.
+    snapshots: Arc<Mutex<BTreeMap<String, MetricsSnapshot>>>,
 }
 
 impl MetricsRegistry {
@@ -27,13 +34,17 @@ impl MetricsRegistry {
             // collector once.
             .ok();
 
-        Self { registry }
+        Self {
+            registry,
+            snapshots: Default::default(),
+        }
     }
 
     /// Create a new, empty registry.
     pub fn new() -> Self {
         Self {
             registry: prometheus::Registry::new(),
+            snapshots: Default::default(),
         }
     }
 
@@ -106,4 +117,88 @@ impl MetricsRegistry {
         self.registry.register(Box::new(C::clone(&c))).unwrap();
         c
     }
+
+    /// Returns the current values of all counters.
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot::from_metric_families(self.registry.gather())
+    }
+
+    /// Takes a snapshot of all counters and keeps it under the given name,
+    /// replacing an earlier snapshot of the same name.
+    pub fn take_snapshot(&self, name: &str) {
+        let snapshot = self.snapshot();
+        self.snapshots
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), snapshot);
+    }
+
+    /// Returns how much the counters changed since the snapshot of the given
+    /// name was taken, or `None` if there is no such snapshot.
+    pub fn diff_since(&self, name: &str) -> Option<MetricsSnapshot> {
+        let snapshots = self.snapshots.lock().unwrap();
+        let earlier = snapshots.get(name)?;
+        Some(self.snapshot().diff(earlier))
+    }
+
+    /// Serves the metrics of this registry in the Prometheus text format on
+    /// the given address, until the returned exporter is dropped.
+    pub fn serve_http(&self, addr: SocketAddr) -> std::io::Result<HttpExporter> {
+        HttpExporter::start(self.registry.clone(), addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+
+    #[test]
+    fn diffs_contain_the_changed_counters() {
+        let registry = MetricsRegistry::new();
+        let counter = registry.int_counter("requests", "Number of requests");
+        let counter_vec = registry.int_counter_vec("errors", "Number of errors", &["kind"]);
+        let histogram = registry.histogram("latency", "Request latency", vec![1.0, 10.0]);
+        counter.inc();
+        counter_vec.with_label_values(&["timeout"]).inc();
+
+        assert_eq!(registry.diff_since("start"), None);
+        registry.take_snapshot("start");
+        assert!(registry.diff_since("start").unwrap().is_empty());
+
+        counter.inc_by(2);
+        counter_vec.with_label_values(&["reset"]).inc();
+        histogram.observe(5.0);
+
+        let diff = registry.diff_since("start").unwrap();
+        assert_eq!(diff.counter("requests"), 2.0);
+        assert_eq!(diff.counter("errors"), 1.0);
+        assert_eq!(
+            diff.counter_with_labels("errors", &[("kind", "reset")]),
+            1.0
+        );
+        assert_eq!(
+            diff.counter_with_labels("errors", &[("kind", "timeout")]),
+            0.0
+        );
+        assert_eq!(diff.counter("latency_count"), 1.0);
+        assert_eq!(registry.snapshot().counter("requests"), 3.0);
+    }
+
+    #[test]
+    fn http_exporter_serves_the_metrics() {
+        let registry = MetricsRegistry::new();
+        registry.int_counter("requests", "Number of requests").inc();
+        let exporter = registry.serve_http("127.0.0.1:0".parse().unwrap()).unwrap();
+
+        let mut stream = std::net::TcpStream::connect(exporter.local_addr()).unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("requests 1"));
+    }
 }
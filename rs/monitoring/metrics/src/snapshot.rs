@@ -0,0 +1,108 @@
+//! Snapshots of the counters of a `MetricsRegistry`.
+//!
+//! Tests take a snapshot before exercising a component and compare it with a
+//! later one, so that they can assert on the change of a counter rather than
+//! on its absolute value, which depends on everything else that ran before.
+use prometheus::proto::{Metric, MetricFamily, MetricType};
+use std::collections::BTreeMap;
+
+/// The label names and values of one time series.
+pub type Labels = BTreeMap<String, String>;
+
+/// The values of all counters at some point in time, by metric name and
+/// labels. The sample count of a histogram is included as the counter
+/// `<name>_count`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MetricsSnapshot {
+    counters: BTreeMap<String, BTreeMap<Labels, f64>>,
+}
+
+impl MetricsSnapshot {
+    pub(crate) fn from_metric_families(metric_families: Vec<MetricFamily>) -> Self {
+        let mut counters: BTreeMap<String, BTreeMap<Labels, f64>> = BTreeMap::new();
+        for metric_family in metric_families {
+            let (name, value): (String, fn(&Metric) -> f64) = match metric_family.get_field_type() {
+                MetricType::COUNTER => (metric_family.get_name().to_string(), counter_value),
+                MetricType::HISTOGRAM => (
+                    format!("{}_count", metric_family.get_name()),
+                    histogram_count,
+                ),
+                _ => continue,
+            };
+            let series = counters.entry(name).or_default();
+            for metric in metric_family.get_metric() {
+                series.insert(to_labels(metric), value(metric));
+            }
+        }
+        Self { counters }
+    }
+
+    /// Returns the sum of the counter with the given name over all labels.
+    pub fn counter(&self, name: &str) -> f64 {
+        self.counters
+            .get(name)
+            .map(|series| series.values().sum())
+            .unwrap_or_default()
+    }
+
+    /// Returns the value of the counter with the given name and labels.
+    pub fn counter_with_labels(&self, name: &str, labels: &[(&str, &str)]) -> f64 {
+        let labels: Labels = labels
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        self.counters
+            .get(name)
+            .and_then(|series| series.get(&labels))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Returns how much the counters changed since the `earlier` snapshot.
+    /// Counters that did not change are left out.
+    pub fn diff(&self, earlier: &MetricsSnapshot) -> MetricsSnapshot {
+        let mut counters: BTreeMap<String, BTreeMap<Labels, f64>> = BTreeMap::new();
+        for (name, series) in &self.counters {
+            for (labels, value) in series {
+                let delta = value - earlier.counter_at(name, labels);
+                if delta != 0.0 {
+                    counters
+                        .entry(name.clone())
+                        .or_default()
+                        .insert(labels.clone(), delta);
+                }
+            }
+        }
+        Self { counters }
+    }
+
+    /// Returns true if the snapshot contains no counters, e.g. if no counter
+    /// changed in a diff.
+    pub fn is_empty(&self) -> bool {
+        self.counters.is_empty()
+    }
+
+    fn counter_at(&self, name: &str, labels: &Labels) -> f64 {
+        self.counters
+            .get(name)
+            .and_then(|series| series.get(labels))
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+fn counter_value(metric: &Metric) -> f64 {
+    metric.get_counter().get_value()
+}
+
+fn histogram_count(metric: &Metric) -> f64 {
+    metric.get_histogram().get_sample_count() as f64
+}
+
+fn to_labels(metric: &Metric) -> Labels {
+    metric
+        .get_label()
+        .iter()
+        .map(|pair| (pair.get_name().to_string(), pair.get_value().to_string()))
+        .collect()
+}
@@ -410,6 +410,7 @@ impl NnsFunction {
             NnsFunction::StopOrStartNnsCanister => (ROOT_CANISTER_ID, "stop_or_start_nns_canister"),
             NnsFunction::RemoveNodes => (REGISTRY_CANISTER_ID, "remove_nodes"),
             NnsFunction::UninstallCode => (CanisterId::ic_00(), "uninstall_code"),
+            NnsFunction::SetFeeSchedule => (REGISTRY_CANISTER_ID, "set_fee_schedule"),
         };
         Ok((canister_id, method))
     }
@@ -977,7 +978,8 @@ impl Proposal {
                                 Topic::NetworkCanisterManagement
                             }
                             NnsFunction::IcpXdrConversionRate => Topic::ExchangeRate,
-                            NnsFunction::ClearProvisionalWhitelist => Topic::NetworkEconomics,
+                            NnsFunction::ClearProvisionalWhitelist
+                            | NnsFunction::SetFeeSchedule => Topic::NetworkEconomics,
                             NnsFunction::SetAuthorizedSubnetworks => Topic::Governance,
                             NnsFunction::SetFirewallConfig => Topic::SubnetManagement,
                             NnsFunction::UninstallCode => Topic::Governance,
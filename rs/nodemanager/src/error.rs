@@ -61,6 +61,14 @@ pub enum NodeManagerError {
 
     /// An error occurred with a release package
     ReleasePackageError(ReleaseError),
+
+    /// The public key of the NNS subnet is not in the Registry at the given
+    /// version
+    NnsPublicKeyMissingError(RegistryVersion),
+
+    /// The NNS signature of the release package of the given replica version
+    /// is invalid
+    ReleasePackageSignatureError(ReplicaVersion, String),
 }
 
 impl NodeManagerError {
@@ -169,6 +177,16 @@ impl fmt::Display for NodeManagerError {
                 subnet_id, registry_version,
             ),
             NodeManagerError::UpgradeError(msg) => write!(f, "Failed to upgrade: {}", msg),
+            NodeManagerError::NnsPublicKeyMissingError(registry_version) => write!(
+                f,
+                "The NNS public key was not found in the Registry at registry version {:?}",
+                registry_version
+            ),
+            NodeManagerError::ReleasePackageSignatureError(replica_version, msg) => write!(
+                f,
+                "Invalid NNS signature of the release package of replica version {}: {}",
+                replica_version, msg
+            ),
         }
     }
 }
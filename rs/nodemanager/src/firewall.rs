@@ -1,3 +1,4 @@
+use crate::firewall_rules::{FirewallRulesSource, Ruleset};
 use crate::registry_helper::RegistryHelper;
 use crate::{
     error::{NodeManagerError, NodeManagerResult},
@@ -5,34 +6,36 @@ use crate::{
 };
 use ic_config::firewall::{Config as FirewallConfig, FIREWALL_FILE_DEFAULT_PATH};
 use ic_logger::{debug, info, warn, ReplicaLogger};
-use ic_protobuf::registry::firewall::v1::FirewallConfig as FirewallConfigPB;
 use ic_types::RegistryVersion;
 use ic_utils::fs::write_string_using_tmp_file;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::Command;
 use std::sync::Arc;
 use std::time::Duration;
 
 const FIREWALL_CHECK_INTERVAL: Duration = Duration::from_secs(2);
 
-#[derive(Clone, Debug, PartialEq, Eq)]
-enum DataSource {
-    Config,
-    Registry,
-}
-
 /// Continuously checks the Registry to determine if there has been a change in
-/// the firewall config, and if so, updates the node's firewall rules file
-/// accordingly.
+/// the records the firewall rules are generated from, and if so, updates the
+/// node's firewall rules file accordingly.
 pub(crate) struct Firewall {
     registry: Arc<RegistryHelper>,
     metrics: Arc<NodeManagerMetrics>,
-    configuration: FirewallConfig,
-    source: DataSource,
+    rules_source: Arc<dyn FirewallRulesSource>,
+    config_file: PathBuf,
     logger: ReplicaLogger,
-    compiled_config: String,
 
-    // If true, write the file content even if no change was detected in registry, i.e. first time
-    must_write: bool,
+    // The ruleset last written to the file, `None` before the first write.
+    ruleset: Option<Ruleset>,
+
+    // The nftables configuration last written to the file.
+    nftables: Option<String>,
+
+    // The registry version the ruleset was last generated at.
+    checked_version: Option<RegistryVersion>,
+
+    // If true, only log the changes to the ruleset instead of writing them
+    dry_run: bool,
 
     // If false, do not start or terminate the background task
     enabled: Arc<std::sync::atomic::AtomicBool>,
@@ -42,11 +45,10 @@ impl Firewall {
     pub(crate) fn new(
         registry: Arc<RegistryHelper>,
         metrics: Arc<NodeManagerMetrics>,
+        rules_source: Arc<dyn FirewallRulesSource>,
         firewall_config: FirewallConfig,
         logger: ReplicaLogger,
     ) -> Self {
-        let config = firewall_config.clone();
-
         // Disable if the config is the default one (e.g if we're in a test)
         let enabled = firewall_config
             .config_file
@@ -63,11 +65,13 @@ impl Firewall {
         Self {
             registry,
             metrics,
-            configuration: config,
-            source: DataSource::Config,
+            rules_source,
+            config_file: firewall_config.config_file,
             logger,
-            compiled_config: "".to_string(),
-            must_write: true,
+            ruleset: None,
+            nftables: None,
+            checked_version: None,
+            dry_run: firewall_config.dry_run,
             enabled,
         }
     }
@@ -78,93 +82,80 @@ impl Firewall {
         result
     }
 
-    fn update_config_from_pb(&mut self, pb: FirewallConfigPB) {
-        self.configuration.firewall_config = pb.firewall_config;
-        self.configuration.ipv4_prefixes = pb.ipv4_prefixes;
-        self.configuration.ipv6_prefixes = pb.ipv6_prefixes;
+    /// Whether a record the firewall rules depend on changed since the rules
+    /// were last generated.
+    fn must_check(&self) -> bool {
+        match self.checked_version {
+            Some(checked_version) => self.rules_source.last_change() > checked_version,
+            None => true,
+        }
     }
 
-    /// Checks for new firewall config, and if found, update local firewall
-    /// rules
-    pub(crate) fn check_for_firewall_config(
+    /// Generates the firewall rules at the given registry version, and if
+    /// they changed, updates the local firewall rules. The rules are only
+    /// written once nftables accepts them, so that the last valid rules stay
+    /// in place otherwise.
+    pub(crate) fn check_for_firewall_rules(
         &mut self,
         registry_version: RegistryVersion,
     ) -> NodeManagerResult<()> {
-        match self.registry.get_firewall_config(registry_version) {
-            Ok(registry_fw_config) => {
-                // Data found in registry!
-                self.update_config_from_pb(registry_fw_config);
-                self.source = DataSource::Registry;
-            }
-            Err(e) => {
-                // No data was found in registry
-                match self.source {
-                    DataSource::Registry => warn!(
-                        every_n_seconds => 300,
-                        self.logger,
-                        "Firewall configuration was not found in registry. Using previously fetched data. (Error from registry: {:?})",
-                        e
-                    ),
-                    DataSource::Config => warn!(
-                        every_n_seconds => 300,
-                        self.logger,
-                        "Firewall configuration was not found in registry. Using config file instead. (Error from registry: {:?})",
-                        e
-                    ),
-                };
-            }
+        let ruleset = self.rules_source.get_ruleset(registry_version)?;
+        let nftables = ruleset.to_nftables();
+        if self.nftables.as_ref() == Some(&nftables) {
+            return Ok(());
         }
+        let diff = self.ruleset.clone().unwrap_or_default().diff(&ruleset);
 
-        let content = self.generate_firewall_file_content_full();
-
-        let changed = content.ne(&self.compiled_config);
-        if changed {
-            // Firewall config is different - update it
+        if self.dry_run {
             info!(
                 self.logger,
-                "New firewall configuration found (source: {:?}). Updating local firewall.",
-                self.source
+                "New firewall rules found at registry version {} (dry run, not updating the local firewall):\n{}",
+                registry_version,
+                diff
             );
+        } else {
+            let f = &self.config_file;
+            let candidate = f.with_extension("candidate");
+            write_string_using_tmp_file(&candidate, nftables.as_str())
+                .map_err(|e| NodeManagerError::file_write_error(&candidate, e))?;
+            check_nftables(&candidate)?;
+            info!(
+                self.logger,
+                "New firewall rules found at registry version {}. Updating local firewall:\n{}",
+                registry_version,
+                diff
+            );
+            std::fs::rename(&candidate, f).map_err(|e| NodeManagerError::file_write_error(f, e))?;
         }
-
-        if changed || self.must_write {
-            if content.is_empty() {
-                warn!(
-                    self.logger,
-                    "No firewall configuration found. Node manager will not write any config to a file."
-                );
-            } else {
-                let f = &self.configuration.config_file;
-                write_string_using_tmp_file(f, content.as_str())
-                    .map_err(|e| NodeManagerError::file_write_error(f, e))?;
-                self.compiled_config = content;
-            }
-            self.must_write = false;
-        }
+        self.metrics
+            .firewall_rules_added
+            .inc_by(diff.added.len() as u64);
+        self.metrics
+            .firewall_rules_removed
+            .inc_by(diff.removed.len() as u64);
+        self.metrics.firewall_rules.set(ruleset.len() as i64);
+        self.ruleset = Some(ruleset);
+        self.nftables = Some(nftables);
 
         Ok(())
     }
+}
 
-    /// Generates a string with the content for the firewall rules file
-    fn generate_firewall_file_content_full(&self) -> String {
-        self.configuration
-            .firewall_config
-            .replace(
-                "<< ipv4_prefixes >>",
-                &Self::sanitize_prefixes(&self.configuration.ipv4_prefixes).join(",\n"),
-            )
-            .replace(
-                "<< ipv6_prefixes >>",
-                &Self::sanitize_prefixes(&self.configuration.ipv6_prefixes).join(",\n"),
-            )
-    }
-
-    fn sanitize_prefixes(prefixes: &[String]) -> Vec<String> {
-        prefixes
-            .iter()
-            .map(|prefix| prefix.replace(",", "").replace("\n", ""))
-            .collect()
+/// Checks the nftables configuration in the given file without applying it.
+fn check_nftables(path: &Path) -> NodeManagerResult<()> {
+    let mut c = Command::new("nft");
+    c.arg("--check").arg("--file").arg(path);
+    let output = c
+        .output()
+        .map_err(|e| NodeManagerError::file_command_error(e, &c))?;
+    if !output.status.success() {
+        return Err(NodeManagerError::InvalidConfigurationError(format!(
+            "nftables rejected the firewall rules in {}: {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr)
+        )));
     }
+    Ok(())
 }
 
 async fn background_task(mut firewall: Firewall) {
@@ -173,22 +164,30 @@ async fn background_task(mut firewall: Firewall) {
             return;
         }
 
-        let registry_version = firewall.registry.get_latest_version();
-        debug!(
-            firewall.logger,
-            "Checking for firewall config registry version: {}", registry_version
-        );
-
-        match firewall.check_for_firewall_config(registry_version) {
-            Ok(()) => firewall
-                .metrics
-                .datacenter_registry_version
-                .set(registry_version.get() as i64),
-            Err(e) => info!(
+        if firewall.must_check() {
+            let registry_version = firewall.registry.get_latest_version();
+            debug!(
                 firewall.logger,
-                "Failed to check for firewall config at version {}: {}", registry_version, e
-            ),
-        };
+                "Checking for firewall rules at registry version: {}", registry_version
+            );
+
+            match firewall.check_for_firewall_rules(registry_version) {
+                Ok(()) => {
+                    firewall.checked_version = Some(registry_version);
+                    firewall
+                        .metrics
+                        .datacenter_registry_version
+                        .set(registry_version.get() as i64)
+                }
+                Err(e) => warn!(
+                    every_n_seconds => 300,
+                    firewall.logger,
+                    "Failed to update the firewall rules at version {}, keeping the last valid rules: {}",
+                    registry_version,
+                    e
+                ),
+            };
+        }
 
         tokio::time::sleep(FIREWALL_CHECK_INTERVAL).await;
     }
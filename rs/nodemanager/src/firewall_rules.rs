@@ -0,0 +1,670 @@
+//! Generation of the node's firewall rules from the registry.
+//!
+//! The rules are derived from the firewall record and from the node records:
+//! the prefixes of the firewall record may reach all ports of the node, the
+//! other nodes in the registry may reach its XNet and P2P ports, and anyone
+//! may reach its public API. The node manager renders the resulting ruleset
+//! into the nftables template of the firewall record, or into a table of its
+//! own if there is no template.
+use crate::error::{NodeManagerError, NodeManagerResult};
+use ic_config::firewall::Config as FirewallConfig;
+use ic_interfaces::registry::{RegistryClient, ZERO_REGISTRY_VERSION};
+use ic_protobuf::registry::node::v1::{ConnectionEndpoint, NodeRecord};
+use ic_registry_client::helper::{firewall::FirewallRegistry, node::NodeRegistry};
+use ic_registry_keys::{make_firewall_config_record_key, NODE_RECORD_KEY_PREFIX};
+use ic_types::{NodeId, RegistryVersion};
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// The placeholders of the template of the firewall record, which are replaced
+/// with the prefixes that may reach all ports of the node and with the rules
+/// generated from the node records.
+const IPV4_PREFIXES_PLACEHOLDER: &str = "<< ipv4_prefixes >>";
+const IPV6_PREFIXES_PLACEHOLDER: &str = "<< ipv6_prefixes >>";
+const GENERATED_RULES_PLACEHOLDER: &str = "<< generated_rules >>";
+
+/// The rules accepting traffic that a ruleset without a template starts with:
+/// loopback traffic, established connections and the ICMP messages needed for
+/// path MTU discovery and IPv6 neighbor discovery. Only the table of the node
+/// manager is replaced, the tables of other services are kept.
+const NFTABLES_PREAMBLE: &str = "\
+# Generated by the node manager from the registry. Do not edit.
+table inet ic_node_manager
+delete table inet ic_node_manager
+
+table inet ic_node_manager {
+  chain input {
+    type filter hook input priority 0; policy drop;
+    iif lo accept
+    ct state invalid drop
+    ct state { established, related } accept
+    icmp type { destination-unreachable, echo-request, time-exceeded } accept
+    icmpv6 type { destination-unreachable, packet-too-big, time-exceeded, echo-request, nd-router-advert, nd-neighbor-solicit, nd-neighbor-advert } accept
+";
+
+const NFTABLES_EPILOGUE: &str = "  }
+
+  chain forward {
+    type filter hook forward priority 0; policy drop;
+  }
+
+  chain output {
+    type filter hook output priority 0; policy accept;
+  }
+}
+";
+
+/// An IPv4 or IPv6 prefix, with all bits beyond the prefix length cleared.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) struct IpPrefix {
+    addr: IpAddr,
+    len: u8,
+}
+
+impl IpPrefix {
+    /// The prefix holding only `addr`.
+    fn host(addr: IpAddr) -> Self {
+        Self {
+            addr,
+            len: max_prefix_len(&addr),
+        }
+    }
+
+    /// The prefix holding all addresses of the family of `addr`.
+    fn any(addr: IpAddr) -> Self {
+        Self {
+            addr: mask(addr, 0),
+            len: 0,
+        }
+    }
+}
+
+impl FromStr for IpPrefix {
+    type Err = String;
+
+    /// Parses an address with an optional prefix length, e.g. `10.0.0.0/8`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().splitn(2, '/');
+        let addr: IpAddr = parts
+            .next()
+            .unwrap_or_default()
+            .parse()
+            .map_err(|_| format!("invalid address in prefix {:?}", s))?;
+        let len = match parts.next() {
+            None => max_prefix_len(&addr),
+            Some(len) => len
+                .parse::<u8>()
+                .ok()
+                .filter(|len| *len <= max_prefix_len(&addr))
+                .ok_or_else(|| format!("invalid length of prefix {:?}", s))?,
+        };
+        Ok(Self {
+            addr: mask(addr, len),
+            len,
+        })
+    }
+}
+
+impl fmt::Display for IpPrefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.len)
+    }
+}
+
+fn max_prefix_len(addr: &IpAddr) -> u8 {
+    match addr {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+/// Clears the bits of `addr` beyond the first `len` bits.
+fn mask(addr: IpAddr, len: u8) -> IpAddr {
+    match addr {
+        IpAddr::V4(addr) => {
+            let mask = u32::MAX.checked_shl(32 - len as u32).unwrap_or(0);
+            IpAddr::V4(Ipv4Addr::from(u32::from(addr) & mask))
+        }
+        IpAddr::V6(addr) => {
+            let mask = u128::MAX.checked_shl(128 - len as u32).unwrap_or(0);
+            IpAddr::V6(Ipv6Addr::from(u128::from(addr) & mask))
+        }
+    }
+}
+
+/// The destination ports of a rule.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) enum Ports {
+    All,
+    Tcp(BTreeSet<u16>),
+}
+
+/// Accepts the traffic from `source` to `ports`.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) struct FirewallRule {
+    pub(crate) source: IpPrefix,
+    pub(crate) ports: Ports,
+}
+
+impl fmt::Display for FirewallRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.ports {
+            Ports::All => write!(f, "{} to all ports", self.source),
+            Ports::Tcp(ports) => write!(f, "{} to TCP ports {:?}", self.source, ports),
+        }
+    }
+}
+
+/// The rules accepting traffic to this node, all other traffic is dropped.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Ruleset {
+    /// The nftables template the rules are rendered into, empty if there is
+    /// none.
+    template: String,
+    rules: BTreeSet<FirewallRule>,
+}
+
+impl Ruleset {
+    pub(crate) fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    /// Returns the rules that `newer` adds to and removes from this ruleset.
+    pub(crate) fn diff(&self, newer: &Ruleset) -> RulesetDiff {
+        RulesetDiff {
+            added: newer.rules.difference(&self.rules).cloned().collect(),
+            removed: self.rules.difference(&newer.rules).cloned().collect(),
+        }
+    }
+
+    /// Renders the ruleset as an nftables configuration, using the template
+    /// if there is one.
+    pub(crate) fn to_nftables(&self) -> String {
+        if self.template.is_empty() {
+            return format!(
+                "{}{}{}",
+                NFTABLES_PREAMBLE,
+                self.accept_rules(),
+                NFTABLES_EPILOGUE
+            );
+        }
+        let prefixes = |is_ipv6: bool| {
+            self.rules
+                .iter()
+                .filter(|rule| rule.ports == Ports::All && rule.source.addr.is_ipv6() == is_ipv6)
+                .map(|rule| rule.source.to_string())
+                .collect::<Vec<_>>()
+                .join(",\n")
+        };
+        self.template
+            .replace(IPV4_PREFIXES_PLACEHOLDER, &prefixes(false))
+            .replace(IPV6_PREFIXES_PLACEHOLDER, &prefixes(true))
+            .replace(GENERATED_RULES_PLACEHOLDER, &self.accept_rules())
+    }
+
+    /// Renders the rules as nftables accept statements. The rules with the
+    /// same ports and address family are merged into one.
+    fn accept_rules(&self) -> String {
+        let mut merged: BTreeMap<(&Ports, bool), Vec<String>> = BTreeMap::new();
+        for rule in &self.rules {
+            merged
+                .entry((&rule.ports, rule.source.addr.is_ipv6()))
+                .or_default()
+                .push(rule.source.to_string());
+        }
+
+        let mut nftables = String::new();
+        for ((ports, is_ipv6), sources) in merged {
+            let family = if is_ipv6 { "ip6" } else { "ip" };
+            let ports = match ports {
+                Ports::All => "".to_string(),
+                Ports::Tcp(ports) => format!(
+                    "tcp dport {{ {} }} ",
+                    ports
+                        .iter()
+                        .map(|port| port.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            };
+            nftables.push_str(&format!(
+                "    {} saddr {{ {} }} {}accept\n",
+                family,
+                sources.join(", "),
+                ports
+            ));
+        }
+        nftables
+    }
+}
+
+/// The difference between two rulesets.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct RulesetDiff {
+    pub(crate) added: Vec<FirewallRule>,
+    pub(crate) removed: Vec<FirewallRule>,
+}
+
+impl RulesetDiff {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+impl fmt::Display for RulesetDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for rule in &self.added {
+            writeln!(f, "+ {}", rule)?;
+        }
+        for rule in &self.removed {
+            writeln!(f, "- {}", rule)?;
+        }
+        Ok(())
+    }
+}
+
+/// The source of the firewall rules of this node.
+pub(crate) trait FirewallRulesSource: Send + Sync {
+    /// Returns the latest registry version at which a record the rules depend
+    /// on changed.
+    fn last_change(&self) -> RegistryVersion;
+
+    /// Returns the validated rules at `version`.
+    fn get_ruleset(&self, version: RegistryVersion) -> NodeManagerResult<Ruleset>;
+}
+
+/// Generates the firewall rules of a node from the registry.
+pub(crate) struct RegistryFirewallRules {
+    registry: Arc<dyn RegistryClient>,
+    node_id: NodeId,
+    /// The template and prefixes used while the registry holds no firewall
+    /// record.
+    fallback_template: String,
+    fallback_prefixes: Vec<String>,
+    /// The changes of the firewall record and the node records.
+    changes: Vec<watch::Receiver<RegistryVersion>>,
+}
+
+impl RegistryFirewallRules {
+    pub(crate) fn new(
+        registry: Arc<dyn RegistryClient>,
+        node_id: NodeId,
+        config: &FirewallConfig,
+    ) -> Self {
+        let changes = vec![
+            registry.subscribe(&make_firewall_config_record_key()),
+            registry.subscribe(NODE_RECORD_KEY_PREFIX),
+        ];
+        Self {
+            registry,
+            node_id,
+            fallback_template: config.firewall_config.clone(),
+            fallback_prefixes: config
+                .ipv4_prefixes
+                .iter()
+                .chain(config.ipv6_prefixes.iter())
+                .cloned()
+                .collect(),
+            changes,
+        }
+    }
+}
+
+impl FirewallRulesSource for RegistryFirewallRules {
+    fn last_change(&self) -> RegistryVersion {
+        self.changes
+            .iter()
+            .map(|changes| *changes.borrow())
+            .max()
+            .unwrap_or(ZERO_REGISTRY_VERSION)
+    }
+
+    /// Fails if a prefix or a port of this node is invalid, or if this node is
+    /// not in the registry yet, so that the last valid rules are kept. Other
+    /// nodes whose records cannot be read or whose addresses cannot be parsed
+    /// are skipped instead, so that a single broken node record does not keep
+    /// all nodes from updating their rules.
+    fn get_ruleset(&self, version: RegistryVersion) -> NodeManagerResult<Ruleset> {
+        let (template, prefixes) = match self
+            .registry
+            .get_firewall_config(version)
+            .map_err(NodeManagerError::RegistryError)?
+        {
+            Some(config) => (
+                config.firewall_config,
+                config
+                    .ipv4_prefixes
+                    .into_iter()
+                    .chain(config.ipv6_prefixes.into_iter())
+                    .collect(),
+            ),
+            None => (
+                self.fallback_template.clone(),
+                self.fallback_prefixes.clone(),
+            ),
+        };
+
+        let mut rules = BTreeSet::new();
+        for prefix in prefixes {
+            let source = prefix.parse().map_err(|err| {
+                NodeManagerError::InvalidConfigurationError(format!(
+                    "Invalid firewall prefix: {}",
+                    err
+                ))
+            })?;
+            rules.insert(FirewallRule {
+                source,
+                ports: Ports::All,
+            });
+        }
+
+        let own_record = self
+            .registry
+            .get_transport_info(self.node_id, version)
+            .map_err(NodeManagerError::RegistryError)?
+            .ok_or_else(|| {
+                NodeManagerError::InvalidConfigurationError(format!(
+                    "Node {} is not in the registry at version {}",
+                    self.node_id, version
+                ))
+            })?;
+
+        let public_ports = tcp_ports(own_record.http.iter().chain(&own_record.public_api))?;
+        if !public_ports.is_empty() {
+            for any in &[
+                IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+            ] {
+                rules.insert(FirewallRule {
+                    source: IpPrefix::any(*any),
+                    ports: Ports::Tcp(public_ports.clone()),
+                });
+            }
+        }
+
+        let peer_ports = tcp_ports(
+            own_record.xnet.iter().chain(&own_record.xnet_api).chain(
+                own_record
+                    .p2p_flow_endpoints
+                    .iter()
+                    .filter_map(|flow| flow.endpoint.as_ref()),
+            ),
+        )?;
+        if !peer_ports.is_empty() {
+            let node_ids = self
+                .registry
+                .get_node_ids(version)
+                .map_err(NodeManagerError::RegistryError)?;
+            for node_id in node_ids {
+                let record = match self.registry.get_transport_info(node_id, version) {
+                    Ok(Some(record)) => record,
+                    Ok(None) | Err(_) => continue,
+                };
+                for addr in node_addresses(&record) {
+                    rules.insert(FirewallRule {
+                        source: IpPrefix::host(addr),
+                        ports: Ports::Tcp(peer_ports.clone()),
+                    });
+                }
+            }
+        }
+
+        Ok(Ruleset { template, rules })
+    }
+}
+
+/// Returns the ports of `endpoints`.
+fn tcp_ports<'a>(
+    endpoints: impl Iterator<Item = &'a ConnectionEndpoint>,
+) -> NodeManagerResult<BTreeSet<u16>> {
+    endpoints
+        .map(|endpoint| {
+            u16::try_from(endpoint.port)
+                .ok()
+                .filter(|port| *port != 0)
+                .ok_or_else(|| {
+                    NodeManagerError::InvalidConfigurationError(format!(
+                        "Invalid port in node record: {}",
+                        endpoint.port
+                    ))
+                })
+        })
+        .collect()
+}
+
+/// Returns the valid addresses of all endpoints of the node.
+fn node_addresses(record: &NodeRecord) -> BTreeSet<IpAddr> {
+    record
+        .xnet
+        .iter()
+        .chain(&record.http)
+        .chain(
+            record
+                .p2p_flow_endpoints
+                .iter()
+                .filter_map(|flow| flow.endpoint.as_ref()),
+        )
+        .chain(&record.public_api)
+        .chain(&record.xnet_api)
+        .filter_map(|endpoint| endpoint.ip_addr.parse().ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_protobuf::registry::{
+        firewall::v1::FirewallConfig as FirewallConfigPB, node::v1::FlowEndpoint,
+    };
+    use ic_registry_client::fake::FakeRegistryClient;
+    use ic_registry_common::proto_registry_data_provider::ProtoRegistryDataProvider;
+    use ic_registry_keys::make_node_record_key;
+    use ic_test_utilities::types::ids::node_test_id;
+
+    fn endpoint(ip_addr: &str, port: u32) -> ConnectionEndpoint {
+        ConnectionEndpoint {
+            ip_addr: ip_addr.to_string(),
+            port,
+            ..Default::default()
+        }
+    }
+
+    fn node_record(ip_addr: &str) -> NodeRecord {
+        NodeRecord {
+            http: Some(endpoint(ip_addr, 8080)),
+            xnet: Some(endpoint(ip_addr, 2497)),
+            p2p_flow_endpoints: vec![FlowEndpoint {
+                flow_tag: 0,
+                endpoint: Some(endpoint(ip_addr, 4100)),
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn prefix(s: &str) -> IpPrefix {
+        s.parse().unwrap()
+    }
+
+    fn ports(ports: &[u16]) -> Ports {
+        Ports::Tcp(ports.iter().cloned().collect())
+    }
+
+    #[test]
+    fn prefixes_are_parsed_and_masked() {
+        assert_eq!(prefix("10.1.2.3/8").to_string(), "10.0.0.0/8");
+        assert_eq!(prefix(" 10.1.2.3 ").to_string(), "10.1.2.3/32");
+        assert_eq!(prefix("2001:db8::1/32").to_string(), "2001:db8::/32");
+        assert_eq!(prefix("::1/0").to_string(), "::/0");
+        assert!("10.0.0.0/33".parse::<IpPrefix>().is_err());
+        assert!("10.0.0/8".parse::<IpPrefix>().is_err());
+        assert!("10.0.0.0/8, 11.0.0.0/8".parse::<IpPrefix>().is_err());
+    }
+
+    #[test]
+    fn ruleset_is_generated_from_the_registry() {
+        let data_provider = Arc::new(ProtoRegistryDataProvider::new());
+        let version = RegistryVersion::from(1);
+        let (own, peer) = (node_test_id(1), node_test_id(2));
+        data_provider
+            .add(
+                &make_node_record_key(own),
+                version,
+                Some(node_record("10.0.0.1")),
+            )
+            .unwrap();
+        data_provider
+            .add(
+                &make_node_record_key(peer),
+                version,
+                Some(node_record("2001:db8::2")),
+            )
+            .unwrap();
+        data_provider
+            .add(
+                &make_firewall_config_record_key(),
+                version,
+                Some(FirewallConfigPB {
+                    firewall_config: "".to_string(),
+                    ipv4_prefixes: vec!["192.168.0.0/16".to_string()],
+                    ipv6_prefixes: vec![],
+                }),
+            )
+            .unwrap();
+        let registry = Arc::new(FakeRegistryClient::new(data_provider));
+        registry.update_to_latest_version();
+
+        let rules = RegistryFirewallRules::new(registry, own, &FirewallConfig::default());
+        assert_eq!(rules.last_change(), version);
+
+        let ruleset = rules.get_ruleset(version).unwrap();
+        let expected: BTreeSet<_> = vec![
+            FirewallRule {
+                source: prefix("192.168.0.0/16"),
+                ports: Ports::All,
+            },
+            FirewallRule {
+                source: prefix("0.0.0.0/0"),
+                ports: ports(&[8080]),
+            },
+            FirewallRule {
+                source: prefix("::/0"),
+                ports: ports(&[8080]),
+            },
+            FirewallRule {
+                source: prefix("10.0.0.1"),
+                ports: ports(&[2497, 4100]),
+            },
+            FirewallRule {
+                source: prefix("2001:db8::2"),
+                ports: ports(&[2497, 4100]),
+            },
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(ruleset.rules, expected);
+
+        let nftables = ruleset.to_nftables();
+        assert!(nftables.contains("    ip saddr { 192.168.0.0/16 } accept\n"));
+        assert!(nftables.contains("    ip saddr { 0.0.0.0/0 } tcp dport { 8080 } accept\n"));
+        assert!(nftables
+            .contains("    ip6 saddr { 2001:db8::2/128 } tcp dport { 2497, 4100 } accept\n"));
+        assert!(!nftables.contains("flush ruleset"));
+    }
+
+    #[test]
+    fn ruleset_is_rendered_into_the_template_of_the_registry() {
+        let data_provider = Arc::new(ProtoRegistryDataProvider::new());
+        let version = RegistryVersion::from(1);
+        let own = node_test_id(1);
+        data_provider
+            .add(
+                &make_node_record_key(own),
+                version,
+                Some(node_record("10.0.0.1")),
+            )
+            .unwrap();
+        data_provider
+            .add(
+                &make_firewall_config_record_key(),
+                version,
+                Some(FirewallConfigPB {
+                    firewall_config: "set allowed { elements = { << ipv4_prefixes >> } }\n\
+                                      set allowed6 { elements = { << ipv6_prefixes >> } }\n\
+                                      << generated_rules >>"
+                        .to_string(),
+                    ipv4_prefixes: vec!["192.168.0.0/16".to_string(), "10.0.0.0/8".to_string()],
+                    ipv6_prefixes: vec!["2001:db8::/32".to_string()],
+                }),
+            )
+            .unwrap();
+        let registry = Arc::new(FakeRegistryClient::new(data_provider));
+        registry.update_to_latest_version();
+
+        let rules = RegistryFirewallRules::new(registry, own, &FirewallConfig::default());
+        let nftables = rules.get_ruleset(version).unwrap().to_nftables();
+        assert!(nftables.starts_with(
+            "set allowed { elements = { 10.0.0.0/8,\n192.168.0.0/16 } }\n\
+             set allowed6 { elements = { 2001:db8::/32 } }\n"
+        ));
+        assert!(nftables.contains("    ip saddr { 10.0.0.1/32 } tcp dport { 2497, 4100 } accept\n"));
+        assert!(!nftables.contains("<<"));
+    }
+
+    #[test]
+    fn no_ruleset_is_generated_before_the_node_is_registered() {
+        let data_provider = Arc::new(ProtoRegistryDataProvider::new());
+        let version = RegistryVersion::from(1);
+        data_provider
+            .add(
+                &make_node_record_key(node_test_id(2)),
+                version,
+                Some(node_record("10.0.0.2")),
+            )
+            .unwrap();
+        let registry = Arc::new(FakeRegistryClient::new(data_provider));
+        registry.update_to_latest_version();
+
+        let rules =
+            RegistryFirewallRules::new(registry, node_test_id(1), &FirewallConfig::default());
+        assert!(rules.get_ruleset(version).is_err());
+    }
+
+    #[test]
+    fn diff_lists_added_and_removed_rules() {
+        let rule = |source: &str| FirewallRule {
+            source: prefix(source),
+            ports: Ports::All,
+        };
+        let old = Ruleset {
+            rules: vec![rule("10.0.0.1"), rule("10.0.0.2")]
+                .into_iter()
+                .collect(),
+            ..Ruleset::default()
+        };
+        let new = Ruleset {
+            rules: vec![rule("10.0.0.2"), rule("10.0.0.3")]
+                .into_iter()
+                .collect(),
+            ..Ruleset::default()
+        };
+
+        let diff = old.diff(&new);
+        assert_eq!(
+            diff,
+            RulesetDiff {
+                added: vec![rule("10.0.0.3")],
+                removed: vec![rule("10.0.0.1")],
+            }
+        );
+        assert_eq!(
+            diff.to_string(),
+            "+ 10.0.0.3/32 to all ports\n- 10.0.0.1/32 to all ports\n"
+        );
+        assert!(new.diff(&new).is_empty());
+    }
+}
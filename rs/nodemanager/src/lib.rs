@@ -37,6 +37,7 @@ mod catch_up_package_provider;
 mod crypto_helper;
 mod error;
 mod firewall;
+mod firewall_rules;
 mod metrics;
 mod nns_registry_replicator;
 pub mod node_manager;
@@ -44,5 +45,7 @@ mod registration;
 mod registry_helper;
 mod release_package;
 mod release_package_provider;
+mod replica_health;
 mod replica_process;
+pub mod upgrade_image;
 mod utils;
@@ -8,6 +8,13 @@ pub struct NodeManagerMetrics {
     pub resident_mem_used: IntGauge,
     /// Registry version last used to succesfully fetch datacenter information
     pub datacenter_registry_version: IntGauge,
+    /// Number of rules in the firewall ruleset last generated
+    pub firewall_rules: IntGauge,
+    pub firewall_rules_added: IntCounter,
+    pub firewall_rules_removed: IntCounter,
+    /// The health the replica reports for itself: 0 if healthy, 1 if
+    /// degraded, 2 if unhealthy and -1 if unknown
+    pub replica_health_assessment: IntGauge,
 }
 
 impl NodeManagerMetrics {
@@ -25,6 +32,22 @@ impl NodeManagerMetrics {
                 "datacenter_registry_version",
                 "Registry version last used to successfully fetch datacenter information",
             ),
+            firewall_rules: metrics_registry.int_gauge(
+                "firewall_rules",
+                "Number of rules in the firewall ruleset last generated",
+            ),
+            firewall_rules_added: metrics_registry.int_counter(
+                "firewall_rules_added_total",
+                "Number of rules added to the firewall ruleset",
+            ),
+            firewall_rules_removed: metrics_registry.int_counter(
+                "firewall_rules_removed_total",
+                "Number of rules removed from the firewall ruleset",
+            ),
+            replica_health_assessment: metrics_registry.int_gauge(
+                "replica_health_assessment",
+                "The health the replica reports for itself: 0 if healthy, 1 if degraded, 2 if unhealthy and -1 if unknown",
+            ),
         }
     }
 }
@@ -32,13 +32,10 @@ use ic_base_thread::async_safe_block_on_await;
 use ic_interfaces::registry::{RegistryClient, ZERO_REGISTRY_VERSION};
 use ic_logger::{debug, warn, ReplicaLogger};
 use ic_protobuf::registry::subnet::v1::SubnetType;
-use ic_protobuf::{
-    registry::{
-        node::v1::ConnectionEndpoint,
-        routing_table::v1::RoutingTable as PbRoutingTable,
-        subnet::v1::{SubnetListRecord, SubnetRecord},
-    },
-    types::v1::{PrincipalId as PrincipalIdProto, SubnetId as SubnetIdProto},
+use ic_protobuf::registry::{
+    node::v1::ConnectionEndpoint,
+    routing_table::v1::RoutingTable as PbRoutingTable,
+    subnet::v1::{SubnetListRecord, SubnetRecord},
 };
 use ic_registry_client::helper::{
     crypto::CryptoRegistry,
@@ -53,7 +50,7 @@ use ic_registry_keys::{
 };
 use ic_registry_routing_table::{CanisterIdRange, RoutingTable};
 use ic_types::crypto::threshold_sig::ThresholdSigPublicKey;
-use ic_types::{NodeId, RegistryVersion, SubnetId};
+use ic_types::{subnet_id_into_protobuf, NodeId, RegistryVersion, SubnetId};
 use std::collections::BTreeMap;
 use std::fmt::Debug;
 use std::io::{Error, ErrorKind};
@@ -417,13 +414,8 @@ impl InternalState {
         });
 
         // set nns subnet id (actually, root subnet id)
-        let new_nns_subnet_id_proto = SubnetIdProto {
-            principal_id: Some(PrincipalIdProto {
-                raw: new_nns_subnet_id.get().into_vec(),
-            }),
-        };
         let mut new_nns_subnet_id_bytes = vec![];
-        new_nns_subnet_id_proto
+        subnet_id_into_protobuf(new_nns_subnet_id)
             .encode(&mut new_nns_subnet_id_bytes)
             .expect("encoding can't fail");
         last.push(KeyMutation {
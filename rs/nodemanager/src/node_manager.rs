@@ -2,17 +2,19 @@ use crate::args::NodeManagerArgs;
 use crate::catch_up_package_provider::CatchUpPackageProvider;
 use crate::crypto_helper::setup_crypto;
 use crate::firewall::Firewall;
+use crate::firewall_rules::RegistryFirewallRules;
 use crate::metrics::NodeManagerMetrics;
 use crate::nns_registry_replicator::NnsRegistryReplicator;
 use crate::registration::NodeRegistration;
 use crate::registry_helper::RegistryHelper;
 use crate::release_package::ReleasePackage;
 use crate::release_package_provider::ReleasePackageProvider;
+use crate::replica_health::ReplicaHealthCheck;
 use crate::replica_process::ReplicaProcess;
 use crate::utils;
 use ic_config::registry_client::DataProviderConfig;
 use ic_config::{
-    metrics::{Config as MetricsConfig, Exporter},
+    metrics::{Config as MetricsConfig, Exporter, DEFAULT_P2P_MAX_PEER_LABELS},
     Config,
 };
 use ic_crypto::utils::get_node_keys_or_generate_if_missing;
@@ -168,13 +170,20 @@ impl NodeManager {
             logger.clone(),
         )
         .await;
+        let firewall_rules = Arc::new(RegistryFirewallRules::new(
+            Arc::clone(&registry.registry_client),
+            node_id,
+            &config.firewall,
+        ));
         let firewall = Firewall::new(
             Arc::clone(&registry),
             Arc::clone(&metrics),
+            firewall_rules,
             config.firewall.clone(),
             logger.clone(),
         )
         .start();
+        ReplicaHealthCheck::new(&config.http_handler, Arc::clone(&metrics), logger.clone()).start();
         Ok(Self {
             logger,
             _async_log_guard,
@@ -225,6 +234,7 @@ impl NodeManager {
         let metrics_config = MetricsConfig {
             exporter: Exporter::Http(metrics_addr),
             clients_x509_cert: None,
+            p2p_max_peer_labels: DEFAULT_P2P_MAX_PEER_LABELS,
         };
 
         let metrics_runtime = MetricsRuntimeImpl::new(
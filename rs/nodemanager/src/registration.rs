@@ -358,12 +358,25 @@ pub(crate) fn transport_config_to_endpoints(
         ));
     }
 
+    // Nodes behind NAT register the address observed from the outside.
+    let node_ip = transport_config
+        .external_ip
+        .as_ref()
+        .unwrap_or(&transport_config.node_ip);
     for tf in transport_config.p2p_flows.iter() {
         flow_endpoints.push(format!(
             "{},{}",
             tf.flow_tag,
-            get_endpoint(log, transport_config.node_ip.clone(), tf.server_port)?
+            get_endpoint(log, node_ip.clone(), tf.server_port)?
         ));
+        // Dual-stack nodes register one endpoint per address family.
+        if let Some(secondary_node_ip) = &transport_config.secondary_node_ip {
+            flow_endpoints.push(format!(
+                "{},{}",
+                tf.flow_tag,
+                get_endpoint(log, secondary_node_ip.clone(), tf.server_port)?
+            ));
+        }
     }
     Ok(flow_endpoints)
 }
@@ -437,16 +450,24 @@ mod tests {
     fn transport_config_endpoints_succeeds() {
         let transport_config = TransportConfig {
             node_ip: "::1".to_string(),
+            secondary_node_ip: None,
+            external_ip: None,
+            nat_traversal: false,
+            reconnect: Default::default(),
+            wire_codecs: Vec::new(),
+            tls_validation: Default::default(),
             p2p_flows: vec![
                 TransportFlowConfig {
                     flow_tag: 1337,
                     server_port: 23,
                     queue_size: 1,
+                    rate_limit: None,
                 },
                 TransportFlowConfig {
                     flow_tag: 1338,
                     server_port: 24,
                     queue_size: 1,
+                    rate_limit: None,
                 },
             ],
         };
@@ -459,6 +480,58 @@ mod tests {
         });
     }
 
+    #[test]
+    fn transport_config_external_ip_endpoints_succeeds() {
+        let transport_config = TransportConfig {
+            node_ip: "10.0.0.1".to_string(),
+            secondary_node_ip: None,
+            external_ip: Some("::1".to_string()),
+            nat_traversal: true,
+            reconnect: Default::default(),
+            wire_codecs: Vec::new(),
+            tls_validation: Default::default(),
+            p2p_flows: vec![TransportFlowConfig {
+                flow_tag: 1337,
+                server_port: 23,
+                queue_size: 1,
+                rate_limit: None,
+            }],
+        };
+
+        with_test_replica_logger(|log| {
+            assert_eq!(
+                transport_config_to_endpoints(&log, &transport_config).unwrap(),
+                vec!["1337,[::1]:23".to_string()]
+            )
+        });
+    }
+
+    #[test]
+    fn transport_config_dual_stack_endpoints_succeeds() {
+        let transport_config = TransportConfig {
+            node_ip: "::1".to_string(),
+            secondary_node_ip: Some("127.0.0.1".to_string()),
+            external_ip: None,
+            nat_traversal: false,
+            reconnect: Default::default(),
+            wire_codecs: Vec::new(),
+            tls_validation: Default::default(),
+            p2p_flows: vec![TransportFlowConfig {
+                flow_tag: 1337,
+                server_port: 23,
+                queue_size: 1,
+                rate_limit: None,
+            }],
+        };
+
+        with_test_replica_logger(|log| {
+            assert_eq!(
+                transport_config_to_endpoints(&log, &transport_config).unwrap(),
+                vec!["1337,[::1]:23".to_string(), "1337,127.0.0.1:23".to_string()]
+            )
+        });
+    }
+
     #[test]
     fn capturing_echo_succeeds() {
         // echo `test` | sha256sum
@@ -4,14 +4,14 @@ use ic_consensus::dkg::make_registry_cup;
 use ic_interfaces::registry::RegistryClient;
 use ic_logger::{warn, ReplicaLogger};
 use ic_metrics::MetricsRegistry;
-use ic_protobuf::registry::firewall::v1::FirewallConfig;
 use ic_protobuf::registry::node::v1::NodeRecord;
 use ic_protobuf::registry::replica_version::v1::ReplicaVersionRecord;
 use ic_protobuf::registry::subnet::v1::SubnetRecord;
 use ic_registry_client::client::{create_data_provider, RegistryClientImpl};
-use ic_registry_client::helper::firewall::FirewallRegistry;
+use ic_registry_client::helper::crypto::CryptoRegistry;
 use ic_registry_client::helper::subnet::{SubnetRegistry, SubnetTransportRegistry};
 use ic_types::consensus::CatchUpPackage;
+use ic_types::crypto::threshold_sig::ThresholdSigPublicKey;
 use ic_types::{NodeId, RegistryVersion, ReplicaVersion, SubnetId};
 use std::convert::TryFrom;
 use std::net::SocketAddr;
@@ -50,6 +50,7 @@ impl RegistryHelper {
                 .as_ref()
                 .expect("No data provider was provided in the registry client configuration"),
             /* nns_public_key= */ None,
+            /* delta_pool= */ None,
         );
         let registry_client = Arc::new(RegistryClientImpl::new(
             data_provider,
@@ -166,6 +167,23 @@ impl RegistryHelper {
             ))
     }
 
+    /// Return the threshold signing public key of the NNS subnet at the given
+    /// registry version
+    pub(crate) fn get_nns_public_key(
+        &self,
+        version: RegistryVersion,
+    ) -> NodeManagerResult<ThresholdSigPublicKey> {
+        let nns_subnet_id = self
+            .registry_client
+            .get_root_subnet_id(version)
+            .map_err(NodeManagerError::RegistryError)?
+            .ok_or(NodeManagerError::NnsPublicKeyMissingError(version))?;
+        self.registry_client
+            .get_threshold_signing_public_key_for_subnet(nns_subnet_id, version)
+            .map_err(NodeManagerError::RegistryError)?
+            .ok_or(NodeManagerError::NnsPublicKeyMissingError(version))
+    }
+
     /// Return the genesis cup at the given registry version for this node
     pub(crate) fn get_registry_cup(
         &self,
@@ -183,18 +201,6 @@ impl RegistryHelper {
         }
     }
 
-    pub(crate) fn get_firewall_config(
-        &self,
-        version: RegistryVersion,
-    ) -> NodeManagerResult<FirewallConfig> {
-        match self.registry_client.get_firewall_config(version) {
-            Ok(Some(firewall_config)) => Ok(firewall_config),
-            _ => Err(NodeManagerError::InvalidConfigurationError(
-                "Invalid firewall configuration".to_string(),
-            )),
-        }
-    }
-
     pub(crate) fn get_registry_client(&self) -> Arc<dyn RegistryClient> {
         Arc::clone(&self.registry_client)
     }
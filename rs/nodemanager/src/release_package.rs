@@ -5,7 +5,6 @@ use crate::registry_helper::RegistryHelper;
 use crate::release_package_provider::ReleasePackageProvider;
 use crate::replica_process::ReplicaProcess;
 use crate::utils;
-use ic_http_utils::file_downloader::check_file_hash;
 use ic_http_utils::file_downloader::FileDownloader;
 use ic_logger::{debug, info, warn, ReplicaLogger};
 use ic_protobuf::registry::subnet::v1::SubnetRecord;
@@ -353,22 +352,12 @@ impl ReleasePackage {
 
         if ReleasePackageProvider::release_package_is_available(&replica_version_record) {
             info!(self.logger, "Upgrade is guest-OS upgrade");
-            // Download base OS upgrade
+            // Download, verify and stage the base OS upgrade
             let download_path = self
                 .release_package_provider
-                .make_version_dir(&new_replica_version)?
-                .join("base-os.tar.gz");
-            info!(self.logger, "Upgrading from {:?}", download_path);
-            let _ = self
-                .release_package_provider
-                .download_release_package(new_replica_version.clone())
+                .stage_upgrade_image(new_replica_version)
                 .await?;
-            // Better safe than sorry.
-            check_file_hash(
-                &download_path,
-                &replica_version_record.release_package_sha256_hex,
-            )
-            .expect("Upgrade file with correct checksum needed here");
+            info!(self.logger, "Upgrading from {:?}", download_path);
 
             let mut script = self.ic_binary_dir.clone();
             script.push("install-upgrade.sh");
@@ -1,5 +1,6 @@
 use crate::error::{NodeManagerError, NodeManagerResult};
 use crate::registry_helper::RegistryHelper;
+use crate::upgrade_image::UpgradeImageStager;
 use crate::utils;
 use ic_http_utils::file_downloader::{FileDownloadError, FileDownloader};
 use ic_logger::{info, warn, ReplicaLogger};
@@ -27,6 +28,7 @@ const MIN_RELEASE_PACKAGE_AGE: Duration = Duration::from_secs(60);
 pub(crate) struct ReleasePackageProvider {
     registry: Arc<RegistryHelper>,
     file_downloader: Arc<FileDownloader>,
+    upgrade_image_stager: Arc<UpgradeImageStager>,
     release_content_dir: PathBuf,
     force_replica_binary: Option<String>,
     logger: ReplicaLogger,
@@ -40,10 +42,16 @@ impl ReleasePackageProvider {
         logger: ReplicaLogger,
     ) -> Self {
         let file_downloader = Arc::new(FileDownloader::new(Some(logger.clone())));
+        let upgrade_image_stager = Arc::new(UpgradeImageStager::new(
+            Arc::clone(&registry),
+            release_content_dir.clone(),
+            logger.clone(),
+        ));
 
         Self {
             registry,
             file_downloader,
+            upgrade_image_stager,
             release_content_dir,
             force_replica_binary,
             logger,
@@ -76,24 +84,13 @@ impl ReleasePackageProvider {
         )?;
 
         if ReleasePackageProvider::release_package_is_available(&replica_version_record) {
-            let tar_gz_path = version_dir.join("base-os.tar.gz");
             info!(
                 self.logger,
-                "Downloading release package for replica version {} from {} to {:?}",
+                "Downloading release package for replica version {} from {}",
                 replica_version.as_ref(),
                 &replica_version_record.release_package_url,
-                &tar_gz_path,
             );
-
-            let tar_gz_path = version_dir.join("base-os.tar.gz");
-            self.file_downloader
-                .download_file(
-                    &replica_version_record.release_package_url,
-                    &tar_gz_path,
-                    Some(replica_version_record.release_package_sha256_hex),
-                )
-                .await
-                .map_err(NodeManagerError::from)?;
+            self.stage_upgrade_image(&replica_version).await?;
         } else {
             // This is legacy code and not used in production.
             info!(
@@ -133,6 +130,15 @@ impl ReleasePackageProvider {
         content.map_err(NodeManagerError::ReleasePackageError)
     }
 
+    /// Download, verify and stage the release image of the given replica
+    /// version, see `UpgradeImageStager`, and return its path
+    pub(crate) async fn stage_upgrade_image(
+        &self,
+        replica_version: &ReplicaVersion,
+    ) -> NodeManagerResult<PathBuf> {
+        self.upgrade_image_stager.stage(replica_version).await
+    }
+
     /// Download the given binary from the given URL and check its hash
     ///
     /// The file will only be generate if the downloaded file matches
@@ -0,0 +1,172 @@
+use crate::metrics::NodeManagerMetrics;
+use ic_canister_client::{Agent, Sender};
+use ic_config::http_handler::Config as HttpConfig;
+use ic_logger::{info, warn, ReplicaLogger};
+use ic_types::messages::ReplicaHealthAssessment;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use url::Url;
+
+const REPLICA_HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Continuously queries the local replica for its assessment of its own
+/// health, and exports it as a metric. Changes of the assessment are logged.
+pub(crate) struct ReplicaHealthCheck {
+    agent: Agent,
+    metrics: Arc<NodeManagerMetrics>,
+    logger: ReplicaLogger,
+
+    // The assessment last reported by the replica, `None` if the replica did
+    // not report one yet or could not be reached.
+    assessment: Option<ReplicaHealthAssessment>,
+}
+
+impl ReplicaHealthCheck {
+    pub(crate) fn new(
+        http_config: &HttpConfig,
+        metrics: Arc<NodeManagerMetrics>,
+        logger: ReplicaLogger,
+    ) -> Self {
+        // The replica listens on all interfaces if its listen address is
+        // unspecified.
+        let listen_addr = http_config.listen_addr;
+        let ip = if listen_addr.ip().is_unspecified() {
+            IpAddr::V4(Ipv4Addr::LOCALHOST)
+        } else {
+            listen_addr.ip()
+        };
+        let url = Url::parse(&format!(
+            "http://{}",
+            SocketAddr::new(ip, listen_addr.port())
+        ))
+        .expect("the replica's HTTP address is not a valid URL");
+        Self {
+            agent: Agent::new(url, Sender::Anonymous),
+            metrics,
+            logger,
+            assessment: None,
+        }
+    }
+
+    pub(crate) fn start(self) {
+        tokio::spawn(background_task(self));
+    }
+
+    async fn check_replica_health(&mut self) {
+        let assessment = match self.agent.replica_health_assessment().await {
+            Ok(assessment) => assessment,
+            Err(e) => {
+                if self.assessment.is_some() {
+                    warn!(self.logger, "Failed to query the replica's health: {}", e);
+                }
+                None
+            }
+        };
+        if assessment != self.assessment {
+            info!(
+                self.logger,
+                "The replica's health changed from {:?} to {:?}", self.assessment, assessment
+            );
+        }
+        self.metrics
+            .replica_health_assessment
+            .set(match assessment {
+                Some(ReplicaHealthAssessment::Healthy) => 0,
+                Some(ReplicaHealthAssessment::Degraded) => 1,
+                Some(ReplicaHealthAssessment::Unhealthy) => 2,
+                None => -1,
+            });
+        self.assessment = assessment;
+    }
+}
+
+async fn background_task(mut check: ReplicaHealthCheck) {
+    loop {
+        check.check_replica_health().await;
+        tokio::time::sleep(REPLICA_HEALTH_CHECK_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_logger::replica_logger::no_op_logger;
+    use ic_metrics::MetricsRegistry;
+    use ic_types::messages::HttpStatusResponse;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    // Serves the given assessment on every connection, like the status
+    // endpoint of the replica.
+    async fn serve_assessment(listener: TcpListener, assessment: Option<ReplicaHealthAssessment>) {
+        let body = serde_cbor::to_vec(&HttpStatusResponse {
+            ic_api_version: "0.18.0".to_string(),
+            root_key: None,
+            impl_version: None,
+            impl_hash: None,
+            replica_health_status: None,
+            replica_health_assessment: assessment,
+        })
+        .unwrap();
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).await;
+            let header = format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: application/cbor\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+                body.len()
+            );
+            let _ = stream.write_all(header.as_bytes()).await;
+            let _ = stream.write_all(&body).await;
+        }
+    }
+
+    async fn check_against(
+        assessment: Option<ReplicaHealthAssessment>,
+    ) -> (ReplicaHealthCheck, Arc<NodeManagerMetrics>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let http_config = HttpConfig {
+            listen_addr: listener.local_addr().unwrap(),
+            ..Default::default()
+        };
+        tokio::spawn(serve_assessment(listener, assessment));
+        let metrics = Arc::new(NodeManagerMetrics::new(&MetricsRegistry::new()));
+        let check = ReplicaHealthCheck::new(&http_config, Arc::clone(&metrics), no_op_logger());
+        (check, metrics)
+    }
+
+    #[tokio::test]
+    async fn exports_the_assessment_of_the_replica() {
+        for (assessment, value) in &[
+            (Some(ReplicaHealthAssessment::Healthy), 0),
+            (Some(ReplicaHealthAssessment::Degraded), 1),
+            (Some(ReplicaHealthAssessment::Unhealthy), 2),
+            (None, -1),
+        ] {
+            let (mut check, metrics) = check_against(*assessment).await;
+            check.check_replica_health().await;
+            assert_eq!(check.assessment, *assessment);
+            assert_eq!(metrics.replica_health_assessment.get(), *value);
+        }
+    }
+
+    #[tokio::test]
+    async fn unreachable_replica_has_unknown_health() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let http_config = HttpConfig {
+            listen_addr: listener.local_addr().unwrap(),
+            ..Default::default()
+        };
+        drop(listener);
+        let metrics = Arc::new(NodeManagerMetrics::new(&MetricsRegistry::new()));
+        let mut check = ReplicaHealthCheck::new(&http_config, Arc::clone(&metrics), no_op_logger());
+        check.assessment = Some(ReplicaHealthAssessment::Healthy);
+        metrics.replica_health_assessment.set(0);
+
+        check.check_replica_health().await;
+
+        assert_eq!(check.assessment, None);
+        assert_eq!(metrics.replica_health_assessment.get(), -1);
+    }
+}
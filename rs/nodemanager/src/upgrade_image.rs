@@ -0,0 +1,364 @@
+//! Staging of the release images the node upgrades to.
+//!
+//! Before a guest-OS upgrade is installed, the node manager stages the
+//! release package of the new replica version:
+//!
+//! 1. The URL and the SHA-256 hash of the package are taken from the
+//! `ReplicaVersionRecord` in the Registry.
+//!
+//! 2. The package is downloaded into the directory of the version, unless a
+//! package with the right hash is already there.
+//!
+//! 3. The hash of the package is verified, and so is the NNS signature on
+//! the version and the hash, which is published next to the package, at its
+//! URL with the suffix `.sig`. A package without a signature is not staged.
+//!
+//! The progress is tracked as an `UpgradeStatus`, which is persisted in the
+//! release content directory. A package that is already staged is not
+//! verified again as long as its hash matches, as the release packages are
+//! staged again on every run of the upgrade loop.
+//!
+//! All release packages that are downloaded, including the ones downloaded
+//! ahead of an upgrade, are staged by the `UpgradeImageStager`.
+
+use crate::error::{NodeManagerError, NodeManagerResult};
+use crate::registry_helper::RegistryHelper;
+use crate::release_package_provider::ReleasePackageProvider;
+use ic_crypto_utils_threshold_sig::verify_combined;
+use ic_http_utils::file_downloader::{
+    check_file_hash, FileDownloadError, FileDownloader, HttpError,
+};
+use ic_logger::{info, warn, ReplicaLogger};
+use ic_protobuf::registry::replica_version::v1::ReplicaVersionRecord;
+use ic_types::crypto::threshold_sig::ThresholdSigPublicKey;
+use ic_types::crypto::{CombinedThresholdSig, CombinedThresholdSigOf};
+use ic_types::replica_version::{ReleasePackageContent, UpgradeStatus};
+use ic_types::ReplicaVersion;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// The name of the release image in the directory of its version
+const IMAGE_FILE_NAME: &str = "base-os.tar.gz";
+
+/// The suffix of the URL and the file name of the NNS signature of an image
+const SIGNATURE_SUFFIX: &str = ".sig";
+
+/// The name of the file in the release content directory holding the
+/// upgrade status
+const UPGRADE_STATUS_FILE_NAME: &str = "upgrade_status.json";
+
+/// Downloads, verifies and stages the release images of replica versions
+pub(crate) struct UpgradeImageStager {
+    registry: Arc<RegistryHelper>,
+    file_downloader: FileDownloader,
+    release_content_dir: PathBuf,
+    logger: ReplicaLogger,
+}
+
+impl UpgradeImageStager {
+    pub(crate) fn new(
+        registry: Arc<RegistryHelper>,
+        release_content_dir: PathBuf,
+        logger: ReplicaLogger,
+    ) -> Self {
+        Self {
+            registry,
+            file_downloader: FileDownloader::new(Some(logger.clone())),
+            release_content_dir,
+            logger,
+        }
+    }
+
+    /// Stage the release image of the given replica version and return its
+    /// path
+    ///
+    /// The image is only staged if both its hash and its NNS signature are
+    /// valid. Otherwise, the upgrade status is set to failed and the image is
+    /// removed, so that it is downloaded again on the next attempt.
+    pub(crate) async fn stage(
+        &self,
+        replica_version: &ReplicaVersion,
+    ) -> NodeManagerResult<PathBuf> {
+        if let Some(image) = self.already_staged(replica_version) {
+            return Ok(image);
+        }
+        match self.download_and_verify(replica_version).await {
+            Ok(image) => {
+                self.set_status(UpgradeStatus::Staged {
+                    replica_version: replica_version.clone(),
+                    image: image.clone(),
+                });
+                Ok(image)
+            }
+            Err(e) => {
+                self.set_status(UpgradeStatus::Failed {
+                    replica_version: replica_version.clone(),
+                    reason: e.to_string(),
+                });
+                Err(e)
+            }
+        }
+    }
+
+    /// Return the path of the image of the given version if it is staged and
+    /// its hash still matches the one in the Registry
+    fn already_staged(&self, replica_version: &ReplicaVersion) -> Option<PathBuf> {
+        let image = match read_upgrade_status(&self.release_content_dir)? {
+            UpgradeStatus::Staged {
+                replica_version: staged_version,
+                image,
+            } if &staged_version == replica_version => image,
+            _ => return None,
+        };
+        let record = self
+            .registry
+            .get_replica_version_record(replica_version.clone(), self.registry.get_latest_version())
+            .ok()?;
+        check_file_hash(&image, &record.release_package_sha256_hex).ok()?;
+        Some(image)
+    }
+
+    async fn download_and_verify(
+        &self,
+        replica_version: &ReplicaVersion,
+    ) -> NodeManagerResult<PathBuf> {
+        let registry_version = self.registry.get_latest_version();
+        let record = self
+            .registry
+            .get_replica_version_record(replica_version.clone(), registry_version)?;
+        if !ReleasePackageProvider::release_package_is_available(&record) {
+            return Err(NodeManagerError::UpgradeError(format!(
+                "No release package defined for replica version {}",
+                replica_version
+            )));
+        }
+
+        let version_dir = self.release_content_dir.join(replica_version.as_ref());
+        fs::create_dir_all(&version_dir)
+            .map_err(|e| NodeManagerError::dir_create_error(&version_dir, e))?;
+        let image = version_dir.join(IMAGE_FILE_NAME);
+
+        self.set_status(UpgradeStatus::Downloading(replica_version.clone()));
+        self.file_downloader
+            .download_file(
+                &record.release_package_url,
+                &image,
+                Some(record.release_package_sha256_hex.clone()),
+            )
+            .await?;
+
+        self.set_status(UpgradeStatus::Verifying(replica_version.clone()));
+        let verified = match self.download_signature(&record, &version_dir).await {
+            Ok(signature) => self
+                .registry
+                .get_nns_public_key(self.registry.get_latest_version())
+                .and_then(|nns_public_key| {
+                    verify_image(
+                        &image,
+                        replica_version,
+                        &record.release_package_sha256_hex,
+                        signature,
+                        &nns_public_key,
+                    )
+                }),
+            Err(e) => Err(e),
+        };
+        if verified.is_err() {
+            if let Err(e) = fs::remove_file(&image) {
+                warn!(
+                    self.logger,
+                    "Failed to delete unverified image {:?}: {:?}", image, e
+                );
+            }
+        }
+        verified.map(|()| image)
+    }
+
+    /// Download the NNS signature published next to the release package,
+    /// `None` if there is none
+    async fn download_signature(
+        &self,
+        record: &ReplicaVersionRecord,
+        version_dir: &Path,
+    ) -> NodeManagerResult<Option<Vec<u8>>> {
+        let signature_path = version_dir.join(format!("{}{}", IMAGE_FILE_NAME, SIGNATURE_SUFFIX));
+        let signature_url = format!("{}{}", record.release_package_url, SIGNATURE_SUFFIX);
+        match self
+            .file_downloader
+            .download_file(&signature_url, &signature_path, None)
+            .await
+        {
+            Ok(()) => fs::read(&signature_path)
+                .map(Some)
+                .map_err(|e| NodeManagerError::file_open_error(&signature_path, e)),
+            Err(FileDownloadError::HttpError(HttpError::NonSuccessResponse(_, _, status)))
+                if status == http::StatusCode::NOT_FOUND =>
+            {
+                Ok(None)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn set_status(&self, status: UpgradeStatus) {
+        info!(self.logger, "Upgrade status: {:?}", status);
+        if let Err(e) = write_upgrade_status(&self.release_content_dir, &status) {
+            warn!(self.logger, "Failed to persist the upgrade status: {}", e);
+        }
+    }
+}
+
+/// Verify that the given image has the given hash, and that the given NNS
+/// signature, `None` if none is published, is valid for the version and the
+/// hash of the image
+fn verify_image(
+    image: &Path,
+    replica_version: &ReplicaVersion,
+    sha256_hex: &str,
+    signature: Option<Vec<u8>>,
+    nns_public_key: &ThresholdSigPublicKey,
+) -> NodeManagerResult<()> {
+    check_file_hash(image, sha256_hex)?;
+    let signature = signature.ok_or_else(|| {
+        NodeManagerError::ReleasePackageSignatureError(
+            replica_version.clone(),
+            "no signature is published".to_string(),
+        )
+    })?;
+    let content = ReleasePackageContent {
+        replica_version: replica_version.clone(),
+        sha256_hex: sha256_hex.to_string(),
+    };
+    verify_combined(
+        &content,
+        &CombinedThresholdSigOf::new(CombinedThresholdSig(signature)),
+        nns_public_key,
+    )
+    .map_err(|e| {
+        NodeManagerError::ReleasePackageSignatureError(replica_version.clone(), e.to_string())
+    })
+}
+
+/// Read the upgrade status persisted in the given release content directory,
+/// `None` if no upgrade was staged yet
+fn read_upgrade_status(release_content_dir: &Path) -> Option<UpgradeStatus> {
+    let contents = fs::read(release_content_dir.join(UPGRADE_STATUS_FILE_NAME)).ok()?;
+    serde_json::from_slice(&contents).ok()
+}
+
+/// Persist the given upgrade status in the given release content directory
+fn write_upgrade_status(
+    release_content_dir: &Path,
+    status: &UpgradeStatus,
+) -> NodeManagerResult<()> {
+    let path = release_content_dir.join(UPGRADE_STATUS_FILE_NAME);
+    let contents = serde_json::to_vec(status).expect("Failed to serialize the upgrade status");
+    fs::write(&path, contents).map_err(|e| NodeManagerError::file_write_error(&path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_crypto_internal_types::sign::threshold_sig::public_key::bls12_381::PublicKeyBytes;
+    use ic_crypto_internal_types::sign::threshold_sig::public_key::CspThresholdSigPublicKey;
+    use std::convert::TryFrom;
+
+    /// A valid BLS12-381 public key, that did not sign any release package
+    fn nns_public_key() -> ThresholdSigPublicKey {
+        let bytes = base64::decode(
+            "rhikA2Yo5M6YyK4JjdN0LB3/1koSp2GjQh/ZX6rKv5aFIC7npCY2PicCsFd7wQVzE6ZqudtpER8u/tE//hZcDPz+dtxEBA2wz3IOU7lNQLHfsTcDNehb/dFYCbgYXN+d",
+        )
+        .unwrap();
+        let mut buffer = [0u8; PublicKeyBytes::SIZE];
+        buffer.copy_from_slice(&bytes);
+        ThresholdSigPublicKey::from(CspThresholdSigPublicKey::ThresBls12_381(PublicKeyBytes(
+            buffer,
+        )))
+    }
+
+    /// Write an image to the given directory and return its path and hash
+    fn write_image(dir: &Path) -> (PathBuf, String) {
+        let image = dir.join(IMAGE_FILE_NAME);
+        fs::write(&image, b"image").unwrap();
+        let hash = ic_http_utils::file_downloader::compute_sha256_hex(&image).unwrap();
+        (image, hash)
+    }
+
+    #[test]
+    fn image_with_wrong_hash_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let (image, _) = write_image(dir.path());
+        let replica_version = ReplicaVersion::try_from("1.2.3").unwrap();
+        match verify_image(
+            &image,
+            &replica_version,
+            &"0".repeat(64),
+            Some(vec![0; 48]),
+            &nns_public_key(),
+        ) {
+            Err(NodeManagerError::FileDownloadError(_)) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn image_without_signature_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let (image, hash) = write_image(dir.path());
+        let replica_version = ReplicaVersion::try_from("1.2.3").unwrap();
+        match verify_image(&image, &replica_version, &hash, None, &nns_public_key()) {
+            Err(NodeManagerError::ReleasePackageSignatureError(version, _)) => {
+                assert_eq!(version, replica_version)
+            }
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn image_with_invalid_signature_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let (image, hash) = write_image(dir.path());
+        let replica_version = ReplicaVersion::try_from("1.2.3").unwrap();
+        // A valid signature of the key, but on a different message.
+        let signature =
+            base64::decode("i2lvnjJXA9PdH8Ed+S6jIYyX6YR1ZOymEaEbifqQZ6XUT/PsuD93UIDwL3CTZxSU")
+                .unwrap();
+        for signature in vec![signature, vec![0; 3]] {
+            match verify_image(
+                &image,
+                &replica_version,
+                &hash,
+                Some(signature),
+                &nns_public_key(),
+            ) {
+                Err(NodeManagerError::ReleasePackageSignatureError(_, _)) => {}
+                other => panic!("Unexpected result {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn upgrade_status_is_persisted() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(read_upgrade_status(dir.path()), None);
+
+        let replica_version = ReplicaVersion::try_from("1.2.3").unwrap();
+        let status = UpgradeStatus::Staged {
+            replica_version: replica_version.clone(),
+            image: dir
+                .path()
+                .join(replica_version.as_ref())
+                .join(IMAGE_FILE_NAME),
+        };
+        write_upgrade_status(dir.path(), &status).unwrap();
+        assert_eq!(read_upgrade_status(dir.path()), Some(status));
+
+        let status = UpgradeStatus::Failed {
+            replica_version,
+            reason: "invalid signature".to_string(),
+        };
+        write_upgrade_status(dir.path(), &status).unwrap();
+        assert_eq!(read_upgrade_status(dir.path()), Some(status));
+    }
+}
@@ -159,7 +159,7 @@ impl ArtifactDownloadList for ArtifactDownloadListImpl {
                 Some(_) => { /* enough quota remaining */ }
             }
 
-            if let Some(chunk_tracker) = artifact_manager.get_chunk_tracker(&advert.artifact_id) {
+            if let Some(chunk_tracker) = artifact_manager.get_chunk_tracker(advert) {
                 let requested_instant = Instant::now();
                 // Calculate the worst-case time estimate for the artifact download, which
                 // assumes that all chunks for the artifact will time out for
@@ -305,6 +305,7 @@ mod tests {
         let artifact_manager = TestArtifactManager {
             quota: std::usize::MAX,
             num_chunks: 0,
+            ..Default::default()
         };
         let logger = p2p_test_setup_logger();
         let log: ReplicaLogger = logger.root.clone().into();
@@ -342,6 +343,7 @@ mod tests {
         let artifact_manager = TestArtifactManager {
             quota: std::usize::MAX,
             num_chunks: 0,
+            ..Default::default()
         };
         let logger = p2p_test_setup_logger();
         let log: ReplicaLogger = logger.root.clone().into();
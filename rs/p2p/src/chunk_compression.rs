@@ -0,0 +1,147 @@
+//! The compression of artifact chunks on the wire.
+//!
+//! A replica announces in its chunk requests that it accepts compressed
+//! chunks. Chunks served to such a replica are compressed with zstd if they
+//! are large enough and their compressed form is smaller, which speeds up the
+//! transfer of state sync chunks, e.g., of page maps, on slow links. Replicas
+//! that don't announce the capability receive uncompressed chunks, so
+//! replicas with and without compression support interoperate.
+//!
+//! Compression is enabled by the `compress_state_sync_chunks` option of the
+//! state manager configuration. A replica on which it is disabled neither
+//! asks for compressed chunks nor compresses the chunks it serves.
+//!
+//! A malicious peer may send a small chunk that decompresses to a huge one.
+//! Hence, the decompression is aborted once the decompressed data exceeds
+//! `MAX_DECOMPRESSED_CHUNK_SIZE`. Larger chunks are never served compressed.
+
+use crate::metrics::ChunkCompressionMetrics;
+use ic_types::chunkable::{ArtifactChunk, ArtifactChunkData};
+use std::io::{Error, ErrorKind, Read, Result};
+
+/// Chunks smaller than this size in bytes are served uncompressed.
+const MIN_COMPRESSED_CHUNK_SIZE: usize = 4 * 1024;
+
+/// The maximum size in bytes of a chunk after decompression.
+const MAX_DECOMPRESSED_CHUNK_SIZE: usize = 16 * 1024 * 1024;
+
+/// The zstd compression level, trading compression ratio for speed.
+const COMPRESSION_LEVEL: i32 = 3;
+
+/// The function compresses the data of the given chunk if that reduces its
+/// size. It returns the resulting chunk and true if it is compressed.
+pub(crate) fn compress_chunk(
+    chunk: ArtifactChunk,
+    metrics: &ChunkCompressionMetrics,
+) -> (ArtifactChunk, bool) {
+    let data = match &chunk.artifact_chunk_data {
+        ArtifactChunkData::SemiStructuredChunkData(data)
+            if data.len() >= MIN_COMPRESSED_CHUNK_SIZE
+                && data.len() <= MAX_DECOMPRESSED_CHUNK_SIZE =>
+        {
+            data
+        }
+        _ => return (chunk, false),
+    };
+    match zstd::stream::encode_all(&data[..], COMPRESSION_LEVEL) {
+        Ok(compressed) if compressed.len() < data.len() => {
+            metrics
+                .compression_ratio
+                .observe(data.len() as f64 / compressed.len() as f64);
+            let chunk = ArtifactChunk {
+                artifact_chunk_data: ArtifactChunkData::SemiStructuredChunkData(compressed),
+                ..chunk
+            };
+            (chunk, true)
+        }
+        _ => (chunk, false),
+    }
+}
+
+/// The function decompresses the data of the given compressed chunk.
+pub(crate) fn decompress_chunk(
+    chunk: ArtifactChunk,
+    metrics: &ChunkCompressionMetrics,
+) -> Result<ArtifactChunk> {
+    let result = decompress_chunk_data(&chunk.artifact_chunk_data, MAX_DECOMPRESSED_CHUNK_SIZE);
+    if result.is_err() {
+        metrics.decompression_failed.inc();
+    }
+    Ok(ArtifactChunk {
+        artifact_chunk_data: ArtifactChunkData::SemiStructuredChunkData(result?),
+        ..chunk
+    })
+}
+
+/// The function decompresses the given chunk data, failing if the
+/// decompressed data exceeds the given size.
+fn decompress_chunk_data(data: &ArtifactChunkData, max_size: usize) -> Result<Vec<u8>> {
+    let data = match data {
+        ArtifactChunkData::SemiStructuredChunkData(data) => data,
+        ArtifactChunkData::UnitChunkData(_) | ArtifactChunkData::EncodedChunkData { .. } => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "only semi-structured chunks are compressed",
+            ))
+        }
+    };
+    let mut decompressed = Vec::new();
+    // Reading one byte more than allowed detects oversized chunks without
+    // decompressing them entirely.
+    zstd::stream::read::Decoder::new(&data[..])?
+        .take(max_size as u64 + 1)
+        .read_to_end(&mut decompressed)?;
+    if decompressed.len() > max_size {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("decompressed chunk exceeds {} bytes", max_size),
+        ));
+    }
+    Ok(decompressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_metrics::MetricsRegistry;
+    use ic_types::chunkable::ChunkId;
+
+    fn chunk(data: Vec<u8>) -> ArtifactChunk {
+        ArtifactChunk {
+            chunk_id: ChunkId::from(1),
+            witness: Vec::new(),
+            artifact_chunk_data: ArtifactChunkData::SemiStructuredChunkData(data),
+        }
+    }
+
+    #[test]
+    fn compressed_chunks_decompress_to_the_original() {
+        let metrics = ChunkCompressionMetrics::new(&MetricsRegistry::new());
+        let original = chunk(vec![7; 64 * 1024]);
+        let (compressed, is_compressed) = compress_chunk(original.clone(), &metrics);
+        assert!(is_compressed);
+        assert_ne!(compressed, original);
+        assert_eq!(decompress_chunk(compressed, &metrics).unwrap(), original);
+    }
+
+    #[test]
+    fn small_chunks_are_not_compressed() {
+        let metrics = ChunkCompressionMetrics::new(&MetricsRegistry::new());
+        let original = chunk(vec![7; 1024]);
+        let (served, is_compressed) = compress_chunk(original.clone(), &metrics);
+        assert!(!is_compressed);
+        assert_eq!(served, original);
+    }
+
+    #[test]
+    fn oversized_chunks_are_rejected() {
+        let bomb = zstd::stream::encode_all(&vec![0; 1024 * 1024][..], COMPRESSION_LEVEL).unwrap();
+        let data = ArtifactChunkData::SemiStructuredChunkData(bomb);
+        assert!(decompress_chunk_data(&data, 1024 * 1024).is_ok());
+        assert!(decompress_chunk_data(&data, 1024 * 1024 - 1).is_err());
+
+        let metrics = ChunkCompressionMetrics::new(&MetricsRegistry::new());
+        assert!(decompress_chunk(chunk(vec![1, 2, 3]), &metrics).is_err());
+        assert_eq!(metrics.decompression_failed.get(), 1);
+    }
+}
@@ -0,0 +1,252 @@
+//! The codec module implements the encodings of gossip messages on the wire.
+//!
+//! <h1>Overview</h1>
+//!
+//! Gossip messages are encoded by a `WireCodec` before they are handed to
+//! *Transport*. The codec used for a peer is negotiated by *Transport* during
+//! the connection handshake, based on the codecs configured on both nodes.
+//! Protocol buffers are supported by all nodes and used as the fallback
+//! whenever no other codec was negotiated.
+//!
+//! The following codecs are available:
+//!
+//! * *Protobuf*: The protocol buffer encoding of the `GossipMessage` proxy.
+//! * *CBOR*: The CBOR encoding of the `GossipMessage`.
+//! * *Bincode*: The bincode encoding of the `GossipMessage`, prefixed with its
+//!   length as a 4-byte little-endian integer.
+//!
+//! Payloads are self-describing, so that they are decoded correctly whichever
+//! codec the connection they arrive on negotiated. This matters because
+//! messages are encoded when they are enqueued, and may be sent on a later
+//! connection than the one whose codec was used. Protobuf payloads are sent
+//! as is, so that nodes that predate the codecs can decode them. The payloads
+//! of other codecs are prefixed with a zero byte followed by the ID of the
+//! codec. A protobuf encoding never starts with a zero byte, as zero is not a
+//! valid field key.
+
+use crate::gossip_protocol::GossipMessage;
+use ic_protobuf::p2p::v1 as pb;
+use ic_protobuf::proxy::ProtoProxy;
+use ic_types::transport::{TransportPayload, WireCodecId};
+use std::convert::{TryFrom, TryInto};
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+/// The size of the length prefix of the bincode codec.
+const BINCODE_LENGTH_PREFIX_SIZE: usize = 4;
+
+/// The first byte of payloads that are not encoded with protobuf, followed by
+/// the ID of their codec.
+const CODEC_MARKER: u8 = 0;
+
+/// A wire codec error.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum WireCodecError {
+    /// The message could not be encoded.
+    EncodingFailed(String),
+    /// The message could not be decoded.
+    DecodingFailed(String),
+}
+
+impl Display for WireCodecError {
+    /// The method formats the error.
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            WireCodecError::EncodingFailed(e) => write!(f, "Encoding failed: {}", e),
+            WireCodecError::DecodingFailed(e) => write!(f, "Decoding failed: {}", e),
+        }
+    }
+}
+
+/// A `WireCodec` encodes gossip messages to bytes and decodes them back.
+pub(crate) trait WireCodec: Send + Sync {
+    /// The method encodes the given gossip message.
+    fn encode(&self, message: GossipMessage) -> Result<Vec<u8>, WireCodecError>;
+
+    /// The method decodes a gossip message from the given bytes.
+    fn decode(&self, bytes: &[u8]) -> Result<GossipMessage, WireCodecError>;
+}
+
+/// The protocol buffer codec.
+pub(crate) struct ProtobufCodec;
+
+impl WireCodec for ProtobufCodec {
+    fn encode(&self, message: GossipMessage) -> Result<Vec<u8>, WireCodecError> {
+        pb::GossipMessage::proxy_encode(message)
+            .map_err(|e| WireCodecError::EncodingFailed(e.to_string()))
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<GossipMessage, WireCodecError> {
+        <pb::GossipMessage as ProtoProxy<GossipMessage>>::proxy_decode(bytes)
+            .map_err(|e| WireCodecError::DecodingFailed(e.to_string()))
+    }
+}
+
+/// The CBOR codec.
+pub(crate) struct CborCodec;
+
+impl WireCodec for CborCodec {
+    fn encode(&self, message: GossipMessage) -> Result<Vec<u8>, WireCodecError> {
+        serde_cbor::to_vec(&message).map_err(|e| WireCodecError::EncodingFailed(e.to_string()))
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<GossipMessage, WireCodecError> {
+        serde_cbor::from_slice(bytes).map_err(|e| WireCodecError::DecodingFailed(e.to_string()))
+    }
+}
+
+/// The length-prefixed bincode codec.
+pub(crate) struct BincodeCodec;
+
+impl WireCodec for BincodeCodec {
+    fn encode(&self, message: GossipMessage) -> Result<Vec<u8>, WireCodecError> {
+        let body = bincode::serialize(&message)
+            .map_err(|e| WireCodecError::EncodingFailed(e.to_string()))?;
+        let length: u32 = body
+            .len()
+            .try_into()
+            .map_err(|_| WireCodecError::EncodingFailed("Message too large".to_string()))?;
+        let mut bytes = Vec::with_capacity(BINCODE_LENGTH_PREFIX_SIZE + body.len());
+        bytes.extend_from_slice(&length.to_le_bytes());
+        bytes.extend_from_slice(&body);
+        Ok(bytes)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<GossipMessage, WireCodecError> {
+        if bytes.len() < BINCODE_LENGTH_PREFIX_SIZE {
+            return Err(WireCodecError::DecodingFailed(
+                "Missing length prefix".to_string(),
+            ));
+        }
+        let (prefix, body) = bytes.split_at(BINCODE_LENGTH_PREFIX_SIZE);
+        let length = u32::from_le_bytes(prefix.try_into().unwrap()) as usize;
+        if length != body.len() {
+            return Err(WireCodecError::DecodingFailed(format!(
+                "Length prefix {} does not match message length {}",
+                length,
+                body.len()
+            )));
+        }
+        bincode::deserialize(body).map_err(|e| WireCodecError::DecodingFailed(e.to_string()))
+    }
+}
+
+/// The function returns the codec with the given ID.
+pub(crate) fn wire_codec(id: WireCodecId) -> &'static dyn WireCodec {
+    match id {
+        WireCodecId::Protobuf => &ProtobufCodec,
+        WireCodecId::Cbor => &CborCodec,
+        WireCodecId::Bincode => &BincodeCodec,
+    }
+}
+
+/// The function encodes the gossip message with the given codec into a
+/// self-describing payload.
+pub(crate) fn encode_gossip_message(
+    id: WireCodecId,
+    message: GossipMessage,
+) -> Result<TransportPayload, WireCodecError> {
+    let body = wire_codec(id).encode(message)?;
+    if id == WireCodecId::Protobuf {
+        return Ok(TransportPayload::from(body));
+    }
+    let mut bytes = Vec::with_capacity(2 + body.len());
+    bytes.push(CODEC_MARKER);
+    bytes.push(id as u8);
+    bytes.extend_from_slice(&body);
+    Ok(TransportPayload::from(bytes))
+}
+
+/// The function returns the codec that the payload indicates, and the encoded
+/// message.
+pub(crate) fn payload_codec(bytes: &[u8]) -> Result<(WireCodecId, &[u8]), WireCodecError> {
+    match bytes {
+        [CODEC_MARKER, id, body @ ..] => {
+            let id = WireCodecId::try_from(*id).map_err(|id| {
+                WireCodecError::DecodingFailed(format!("Unknown wire codec {}", id))
+            })?;
+            Ok((id, body))
+        }
+        _ => Ok((WireCodecId::Protobuf, bytes)),
+    }
+}
+
+/// The function decodes a gossip message from a payload encoded by
+/// `encode_gossip_message`, with the codec the payload indicates.
+pub(crate) fn decode_gossip_message(bytes: &[u8]) -> Result<GossipMessage, WireCodecError> {
+    let (id, body) = payload_codec(bytes)?;
+    wire_codec(id).decode(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::download_prioritization::test::make_gossip_advert;
+    use crate::gossip_protocol::GossipRetransmissionRequest;
+    use ic_types::artifact::ArtifactFilter;
+
+    fn test_messages() -> Vec<GossipMessage> {
+        vec![
+            GossipMessage::Advert(make_gossip_advert(1)),
+            GossipMessage::RetransmissionRequest(GossipRetransmissionRequest {
+                filter: ArtifactFilter::default(),
+            }),
+        ]
+    }
+
+    /// The function tests that all codecs decode what they encode.
+    #[test]
+    fn wire_codec_round_trip() {
+        for id in &[
+            WireCodecId::Protobuf,
+            WireCodecId::Cbor,
+            WireCodecId::Bincode,
+        ] {
+            let codec = wire_codec(*id);
+            for message in test_messages() {
+                let bytes = codec.encode(message.clone()).unwrap();
+                assert_eq!(codec.decode(&bytes).unwrap(), message, "codec {:?}", id);
+            }
+        }
+    }
+
+    /// The function tests that payloads are decoded with the codec they were
+    /// encoded with, and that protobuf payloads are unchanged, so that nodes
+    /// without codecs can decode them.
+    #[test]
+    fn gossip_message_payloads_are_self_describing() {
+        for id in &[
+            WireCodecId::Protobuf,
+            WireCodecId::Cbor,
+            WireCodecId::Bincode,
+        ] {
+            for message in test_messages() {
+                let payload = encode_gossip_message(*id, message.clone()).unwrap();
+                assert_eq!(
+                    decode_gossip_message(&payload.0).unwrap(),
+                    message,
+                    "codec {:?}",
+                    id
+                );
+                if *id == WireCodecId::Protobuf {
+                    assert_eq!(payload.0, ProtobufCodec.encode(message).unwrap());
+                }
+            }
+        }
+        assert!(decode_gossip_message(&[CODEC_MARKER, 42, 1, 2, 3]).is_err());
+    }
+
+    /// The function tests that the bincode codec rejects messages whose
+    /// length does not match the length prefix.
+    #[test]
+    fn bincode_codec_rejects_truncated_message() {
+        let mut bytes = BincodeCodec
+            .encode(GossipMessage::Advert(make_gossip_advert(1)))
+            .unwrap();
+        bytes.pop();
+        assert!(matches!(
+            BincodeCodec.decode(&bytes),
+            Err(WireCodecError::DecodingFailed(_))
+        ));
+        assert!(BincodeCodec.decode(&bytes[..2]).is_err());
+    }
+}
@@ -3,6 +3,12 @@
 //!
 //! A replica connects to all peers that are listed in the current subnet record
 //! or the subnet record preceding the current record.
+//!
+//! Subnet membership changes take effect without restarting P2P. The nodes
+//! of the latest registry version are connected as soon as the version is
+//! available, while removed nodes stay connected until consensus activates a
+//! registry version without them, i.e., until the finalized block refers to
+//! such a version. Only then are their connections and flows torn down.
 //! For each connected peer, the peer manager manages the list
 //! of adverts and ongoing chunk downloads.
 //!
@@ -64,45 +70,77 @@
 //! In theory, the above locking rules prevent "circular waits" and thus
 //! guarantee deadlock avoidance.
 
+use ic_artifact_manager::artifact::{
+    CanisterHttpArtifact, CertificationArtifact, ConsensusArtifact, DkgArtifact, EcdsaArtifact,
+    EquivocationArtifact, IngressArtifact, QueryStatsArtifact, RegistryDeltaArtifact,
+    RemoteDkgArtifact, XNetStreamSliceArtifact,
+};
+use ic_consensus::consensus::utils::registry_version_at_height;
+use ic_crypto_sha256::Sha256;
+use ic_event_log::{Event, EventLog};
 use ic_interfaces::registry::RegistryClient;
-use ic_interfaces::{artifact_manager::ArtifactManager, transport::Transport};
+use ic_interfaces::{
+    artifact_manager::ArtifactManager, consensus_pool::ConsensusPoolCache, p2p::PeerConnectivity,
+    transport::Transport,
+};
 use ic_metrics::MetricsRegistry;
-use ic_protobuf::p2p::v1 as pb;
-use ic_protobuf::proxy::ProtoProxy;
+use ic_state_manager::state_sync::StateSyncArtifact;
 use ic_types::{
-    artifact::{Artifact, ArtifactId},
-    chunkable::{ArtifactErrorCode, ChunkId},
+    artifact::{Artifact, ArtifactId, ArtifactKind, ArtifactTag},
+    artifact_encoding::ARTIFACT_ENCODING_VERSION,
+    chunkable::{ArtifactErrorCode, ChunkId, CHUNKID_UNIT_CHUNK},
+    consensus::HasHeight,
     crypto::CryptoHash,
     p2p::GossipAdvert,
+    time::current_time,
     transport::{FlowTag, TransportClientType, TransportPayload},
-    NodeId, SubnetId,
+    Height, NodeId, SubnetId,
 };
 
 use crate::{
     artifact_download_list::{ArtifactDownloadList, ArtifactDownloadListImpl},
+    codec::encode_gossip_message,
     download_prioritization::{
         AdvertTracker, AdvertTrackerFinalAction, DownloadAttemptTracker, DownloadPrioritizer,
         DownloadPrioritizerImpl,
     },
+    download_state::{DownloadState, PersistedDownload},
     event_handler::P2PEventHandlerControl,
     gossip_protocol::{
         GossipChunk, GossipChunkRequest, GossipMessage, GossipRetransmissionRequest,
     },
-    metrics::{DownloadManagementMetrics, DownloadPrioritizerMetrics},
+    metrics::{DownloadManagementMetrics, DownloadPrioritizerMetrics, RetransmissionMetrics},
+    peer_metrics::PeerMetrics,
+    recently_seen_ingress::RecentlySeenIngress,
+    retransmission_manager::{
+        RetransmissionManager, RetransmissionManagerImpl, RETRANSMISSION_BUDGET_PER_PEER,
+        RETRANSMISSION_COALESCING_INTERVAL_MS,
+    },
     utils::FlowMapper,
     P2PError, P2PErrorCode, P2PResult,
 };
 
 extern crate lru;
+use futures::future::select_all;
 use ic_protobuf::registry::subnet::v1::GossipConfig;
-use ic_registry_client::helper::subnet::{SubnetRegistry, SubnetTransportRegistry};
+use ic_registry_client::helper::node::NodeRegistry;
+use ic_registry_client::helper::subnet::{
+    SubnetListRegistry, SubnetRegistry, SubnetTransportRegistry,
+};
+use ic_registry_keys::{
+    make_subnet_list_record_key, NODE_RECORD_KEY_PREFIX, SUBNET_RECORD_KEY_PREFIX,
+};
 use lru::LruCache;
+use tokio::sync::watch::{self, error::RecvError};
 
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     error::Error,
+    future::Future,
+    path::PathBuf,
+    pin::Pin,
     sync::{Arc, Mutex, RwLock},
-    time::{Instant, SystemTime},
+    time::{Duration, Instant, SystemTime},
 };
 
 use ic_interfaces::artifact_manager::OnArtifactError::ArtifactPoolError;
@@ -158,6 +196,16 @@ pub(crate) trait DownloadManager {
     /// node ID.
     fn send_retransmission_request(&self, peer_id: NodeId);
 
+    /// The method schedules a retransmission request for the peer with the
+    /// given node ID.
+    ///
+    /// Scheduled requests are coalesced per peer and are subject to a
+    /// per-peer budget. They are sent by `send_due_retransmission_requests()`.
+    fn schedule_retransmission_request(&self, peer_id: NodeId);
+
+    /// The method sends all scheduled retransmission requests that are due.
+    fn send_due_retransmission_requests(&self);
+
     /// The method is invoked periodically by the gossip component to perform
     /// p2p book keeping tasks.
     ///
@@ -166,8 +214,53 @@ pub(crate) trait DownloadManager {
     /// a) Call 'download_next' for all peers when the priority function
     /// changes.</br>
     /// b) Check for chunk download timeouts.</br>
-    /// c) Poll the registry for subnet membership changes.
+    /// c) Periodically refresh the peers from the registry, in case a
+    /// change notification was missed.
     fn on_timer(&self, event_handler: &Arc<dyn P2PEventHandlerControl>);
+
+    /// The method is invoked when the registry records the peers are derived
+    /// from change or consensus finalizes a new height, see
+    /// `RegistryChanges`.
+    ///
+    /// It refreshes the peers if a new registry version is available or
+    /// consensus activated a new registry version.
+    fn on_registry_change(&self, event_handler: &Arc<dyn P2PEventHandlerControl>);
+}
+
+/// A pending change of a watched value.
+type Change<'a> = Pin<Box<dyn Future<Output = Result<(), RecvError>> + Send + 'a>>;
+
+/// The changes that may require the peers to be refreshed: the versions at
+/// which the registry keys the peers are derived from changed and, if there
+/// is a consensus pool cache, the finalized height, at which consensus may
+/// activate a new registry version.
+pub(crate) struct RegistryChanges {
+    registry_versions: Vec<watch::Receiver<RegistryVersion>>,
+    finalized_height: Option<watch::Receiver<Height>>,
+}
+
+impl RegistryChanges {
+    /// The method waits until one of the watched values changes. It returns
+    /// false once none of them can change anymore.
+    pub(crate) async fn changed(&mut self) -> bool {
+        let mut changes: Vec<Change<'_>> = self
+            .registry_versions
+            .iter_mut()
+            .map(|receiver| Box::pin(receiver.changed()) as Change<'_>)
+            .collect();
+        if let Some(receiver) = self.finalized_height.as_mut() {
+            changes.push(Box::pin(receiver.changed()));
+        }
+        // A closed channel completes right away; keep waiting for the others.
+        while !changes.is_empty() {
+            let (result, _, remaining) = select_all(changes).await;
+            if result.is_ok() {
+                return true;
+            }
+            changes = remaining;
+        }
+        false
+    }
 }
 
 /// The peer manager manages the list of current peers.
@@ -188,7 +281,12 @@ pub(crate) trait PeerManager {
     ) -> P2PResult<()>;
 
     /// The method removes the given peer from the list of current peers.
-    fn remove_peer(&self, peer: NodeId, registry_version: RegistryVersion);
+    fn remove_peer(
+        &self,
+        peer: NodeId,
+        registry_version: RegistryVersion,
+        event_handler: &Arc<dyn P2PEventHandlerControl>,
+    );
 }
 
 /// A node tracks the chunks it requested from each peer.
@@ -224,6 +322,8 @@ pub struct PeerContext {
     requested: HashMap<GossipRequestTrackerKey, GossipRequestTracker>,
     /// The time when the peer was disconnected.
     disconnect_time: Option<SystemTime>,
+    /// Whether the transport reported the connection to the peer as up.
+    connected: bool,
     /// The time of the last processed retransmission request from this peer.
     last_retransmission_request_processed_time: Instant,
 }
@@ -237,6 +337,7 @@ impl From<NodeId> for PeerContext {
             peer_id,
             requested: HashMap::new(),
             disconnect_time: None,
+            connected: false,
             last_retransmission_request_processed_time: Instant::now(),
         }
     }
@@ -289,8 +390,13 @@ pub(crate) struct DownloadManagerImpl {
     artifacts_under_construction: RwLock<ArtifactDownloadListImpl>,
     /// The logger.
     log: ReplicaLogger,
+    /// The event log, in which the peers whose artifacts are ignored are
+    /// recorded.
+    event_log: EventLog,
     /// The download management metrics.
     metrics: DownloadManagementMetrics,
+    /// The per-peer metrics.
+    peer_metrics: PeerMetrics,
     /// The *Gossip* configuration.
     gossip_config: GossipConfig,
     /// The cache that is used to check if an artifact has been downloaded
@@ -300,15 +406,43 @@ pub(crate) struct DownloadManagerImpl {
     pfn_invocation_instant: Mutex<Instant>,
     /// The last registry refresh time.
     registry_refresh_instant: Mutex<Instant>,
+    /// The registry version used in the last registry refresh.
+    refreshed_registry_version: Mutex<RegistryVersion>,
+    /// The consensus pool cache, used to look up the registry version
+    /// activated by consensus, if any.
+    consensus_pool_cache: Option<Arc<dyn ConsensusPoolCache>>,
+    /// The activated registry version used in the last registry refresh.
+    activated_registry_version: Mutex<RegistryVersion>,
     /// The last retransmission request time.
     retransmission_request_instant: Mutex<Instant>,
+    /// The retransmission manager coalescing and rate-limiting
+    /// retransmission requests.
+    retransmission_manager: Arc<dyn RetransmissionManager>,
+    /// The ingress messages seen recently, shared with the ingress event
+    /// handler.
+    recently_seen_ingress: Arc<dyn RecentlySeenIngress>,
+    /// The node records of the peers that are used if the registry contains
+    /// none for them.
+    static_node_records: BTreeMap<NodeId, NodeRecord>,
+    /// The path at which in-progress downloads are persisted, if any.
+    download_state_path: Option<PathBuf>,
+    /// The interval at which XNet stream slices are pulled from the XNet
+    /// peers, if XNet streams are pulled over gossip.
+    xnet_pull_interval: Option<Duration>,
+    /// Whether chunks are requested compressed.
+    compress_state_sync_chunks: bool,
+    /// The peers on other subnets that only XNet stream slices are exchanged
+    /// with.
+    xnet_peers: RwLock<BTreeSet<NodeId>>,
+    /// The last time XNet stream slices were pulled from the XNet peers.
+    xnet_pull_instant: Mutex<Instant>,
 }
 
 /// `DownloadManagerImpl` implements the `DownloadManager` trait.
 impl DownloadManager for DownloadManagerImpl {
-    /// The method sends adverts to all peers.
+    /// The method sends adverts to all peers on the own subnet.
     fn send_advert_to_peers(&self, gossip_advert: GossipAdvert) {
-        let current_peers = self.peer_manager.get_current_peer_ids();
+        let current_peers = self.get_subnet_peer_ids();
         self.send_advert_to_peer_list(gossip_advert, current_peers);
     }
 
@@ -329,6 +463,25 @@ impl DownloadManager for DownloadManagerImpl {
             return;
         }
 
+        // Ingress messages that were seen recently, e.g. submitted locally or
+        // received from another peer, are not downloaded again.
+        if let ArtifactId::IngressMessage(id) = &gossip_advert.artifact_id {
+            if self
+                .recently_seen_ingress
+                .is_duplicate(id, &gossip_advert.integrity_hash)
+            {
+                return;
+            }
+        }
+
+        // XNet peers may only advertise XNet stream slices.
+        if self.xnet_peers.read().unwrap().contains(&peer_id)
+            && ArtifactTag::from(&gossip_advert.artifact_id) != ArtifactTag::XNetStreamSliceArtifact
+        {
+            warn!(every_n_seconds => 30, self.log, "Dropping advert from XNet peer {:?}", peer_id);
+            return;
+        }
+
         let mut current_peers = self.current_peers.lock().unwrap();
         if let Some(_peer_context) = current_peers.get_mut(&peer_id) {
             let _ = self.prioritizer.add_advert(gossip_advert, peer_id);
@@ -336,6 +489,7 @@ impl DownloadManager for DownloadManagerImpl {
             warn!(every_n_seconds => 30, self.log, "Dropping advert from unknown node {:?}", peer_id);
         }
         self.metrics.adverts_received.inc();
+        self.peer_metrics.advert_received(&peer_id);
     }
 
     /// The method starts downloading a chunk of the highest-priority
@@ -423,6 +577,12 @@ impl DownloadManager for DownloadManagerImpl {
                     ArtifactId::EcdsaMessage(_) => "ecdsa",
                     ArtifactId::FileTreeSync(_) => "file_tree_sync",
                     ArtifactId::StateSync(_) => "state_sync",
+                    ArtifactId::EquivocationProof(_) => "equivocation",
+                    ArtifactId::RemoteDkgMessage(_) => "remote_dkg",
+                    ArtifactId::CanisterHttpMessage(_) => "canister_http",
+                    ArtifactId::QueryStatsMessage(_) => "query_stats",
+                    ArtifactId::XNetStreamSlice(_) => "xnet_stream_slice",
+                    ArtifactId::RegistryDelta(_) => "registry_delta",
                 };
                 self.metrics
                     .chunk_delivery_time
@@ -463,16 +623,34 @@ impl DownloadManager for DownloadManagerImpl {
                 peer_id
             );
             if let P2PErrorCode::NotFound = error.p2p_error_code {
+                let mut artifacts_under_construction =
+                    self.artifacts_under_construction.write().unwrap();
+                // Replicas running an older version only serve the unit chunk
+                // of artifacts that are downloaded as multiple chunks, so the
+                // artifact is downloaded as its unit chunk instead. If the
+                // peer doesn't have the artifact either, the unit chunk is not
+                // found and the advert is dropped below.
+                if gossip_chunk.chunk_id != ChunkId::from(CHUNKID_UNIT_CHUNK)
+                    && !matches!(
+                        gossip_chunk.artifact_id,
+                        ArtifactId::StateSync(_) | ArtifactId::FileTreeSync(_)
+                    )
+                {
+                    if let Some(tracker) =
+                        artifacts_under_construction.get_tracker(&gossip_chunk.artifact_id)
+                    {
+                        if tracker.chunkable.fall_back_to_unit_chunk() {
+                            return;
+                        }
+                    }
+                }
                 // If the artifact is not found on the sender's side, drop the
                 // advert from the context for this peer to prevent it from
                 // being requested again from this peer.
                 self.delete_advert_from_peer(
                     peer_id,
                     &gossip_chunk.artifact_id,
-                    self.artifacts_under_construction
-                        .write()
-                        .unwrap()
-                        .deref_mut(),
+                    artifacts_under_construction.deref_mut(),
                 )
             }
             return;
@@ -553,20 +731,8 @@ impl DownloadManager for DownloadManagerImpl {
             }
         };
         // Check if the artifact's integrity hash matches the advertised hash
-        // This construction to compute the integrity hash over all variants of an enum
-        // may be updated in the future.
-        let expected_ih = match &completed_artifact {
-            Artifact::ConsensusMessage(msg) => ic_crypto::crypto_hash(msg).get(),
-            Artifact::IngressMessage(msg) => ic_crypto::crypto_hash(msg).get(),
-            Artifact::CertificationMessage(msg) => ic_crypto::crypto_hash(msg).get(),
-            Artifact::DkgMessage(msg) => ic_crypto::crypto_hash(msg).get(),
-            Artifact::EcdsaMessage(msg) => ic_crypto::crypto_hash(msg).get(),
-            // FileTreeSync is not of ArtifactKind kind, and it's used only for testing.
-            // Thus, we make up the integrity_hash.
-            Artifact::FileTreeSync(_msg) => CryptoHash(vec![]),
-            Artifact::StateSync(msg) => ic_crypto::crypto_hash(msg).get(),
-        };
-
+        // before handing it to the artifact manager.
+        let expected_ih = integrity_hash(&completed_artifact);
         if expected_ih != advert.integrity_hash {
             warn!(
                 self.log,
@@ -577,6 +743,13 @@ impl DownloadManager for DownloadManagerImpl {
                 advert.integrity_hash;
             );
             self.metrics.integrity_hash_check_failed.inc();
+            self.event_log.record(Event::PeerQuarantined {
+                peer: peer_id.to_string(),
+                reason: format!(
+                    "the integrity hash of {:?} does not match its advert",
+                    gossip_chunk.artifact_id
+                ),
+            });
 
             // The advert is deleted from this particular peer. Gossip may fetch the
             // artifact again from another peer.
@@ -608,6 +781,13 @@ impl DownloadManager for DownloadManagerImpl {
         std::mem::drop(artifacts_under_construction);
         std::mem::drop(current_peers);
 
+        // Ingress messages handed to the artifact manager are recorded as
+        // seen recently.
+        let ingress = match &gossip_chunk.artifact_id {
+            ArtifactId::IngressMessage(id) => Some((id.clone(), advert.integrity_hash.clone())),
+            _ => None,
+        };
+
         // Client callbacks.
         trace!(
             self.log,
@@ -620,7 +800,12 @@ impl DownloadManager for DownloadManagerImpl {
             .artifact_manager
             .on_artifact(completed_artifact, advert, &peer_id)
         {
-            Ok(_) => (),
+            Ok(_) => {
+                if let Some((id, integrity_hash)) = ingress {
+                    self.recently_seen_ingress
+                        .insert(id, integrity_hash, current_time());
+                }
+            }
             // If this Replica is running an unexpected version, it will log
             // an unhelpfully large volume of `ArtifactReplicaVersionError`s.
             // Here we set the log rate at a more appropriate level.
@@ -638,19 +823,51 @@ impl DownloadManager for DownloadManagerImpl {
 
     /// The method reacts to a disconnect event event for the peer with the
     /// given node ID.
+    ///
+    /// The chunks requested from the peer are released, so that they are
+    /// requested from other peers right away instead of after the chunk
+    /// timeout.
     fn peer_connection_down(&self, peer_id: NodeId) {
         self.metrics.connection_down_events.inc();
         let now = SystemTime::now();
         let mut current_peers = self.current_peers.lock().unwrap();
-        if let Some(peer_context) = current_peers.get_mut(&peer_id) {
-            peer_context.disconnect_time = Some(now);
-            trace!(
-                self.log,
-                "Gossip On Disconnect event with peer: {:?} at time {:?}",
-                peer_id,
-                now
-            );
+        let released_chunks = match current_peers.get_mut(&peer_id) {
+            Some(peer_context) => {
+                peer_context.disconnect_time = Some(now);
+                peer_context.connected = false;
+                trace!(
+                    self.log,
+                    "Gossip On Disconnect event with peer: {:?} at time {:?}",
+                    peer_id,
+                    now
+                );
+                peer_context
+                    .requested
+                    .drain()
+                    .map(|(key, _)| (key.artifact_id, key.chunk_id))
+                    .collect::<Vec<_>>()
+            }
+            None => return,
         };
+        let other_peers = current_peers
+            .keys()
+            .filter(|node_id| **node_id != peer_id)
+            .copied()
+            .collect::<Vec<_>>();
+        std::mem::drop(current_peers);
+
+        if released_chunks.is_empty() {
+            return;
+        }
+        self.metrics
+            .chunks_released_on_disconnect
+            .inc_by(released_chunks.len() as u64);
+        for (artifact_id, chunk_id) in released_chunks {
+            self.process_timed_out_chunk(&peer_id, artifact_id, chunk_id);
+        }
+        for other_peer in other_peers {
+            let _ = self.download_next(other_peer);
+        }
     }
 
     /// The method reacts to a connect event event for the peer with the given
@@ -664,7 +881,10 @@ impl DownloadManager for DownloadManagerImpl {
             .lock()
             .unwrap()
             .get_mut(&peer_id)
-            .and_then(|res| res.disconnect_time);
+            .and_then(|peer_context| {
+                peer_context.connected = true;
+                peer_context.disconnect_time
+            });
         match last_disconnect {
             Some(last_disconnect) => {
                 match last_disconnect.elapsed() {
@@ -676,8 +896,8 @@ impl DownloadManager for DownloadManagerImpl {
                             elapsed
                         );
 
-                        // Clear the send queues and send re-transmission request to the peer on
-                        // connect.
+                        // Clear the send queues and schedule a re-transmission request to the
+                        // peer on connect.
                         self.transport
                             .clear_send_queues(self.transport_client_type, &peer_id);
                     }
@@ -694,7 +914,8 @@ impl DownloadManager for DownloadManagerImpl {
                 );
             }
         }
-        self.send_retransmission_request(peer_id);
+        self.schedule_retransmission_request(peer_id);
+        self.send_due_retransmission_requests();
     }
 
     /// The method reacts to a retransmission request.
@@ -706,6 +927,11 @@ impl DownloadManager for DownloadManagerImpl {
         const BUSY_ERR: P2PResult<()> = Err(P2PError {
             p2p_error_code: P2PErrorCode::Busy,
         });
+        let is_xnet_peer = self.xnet_peers.read().unwrap().contains(&peer_id);
+        let min_interval = match self.xnet_pull_interval {
+            Some(xnet_pull_interval) if is_xnet_peer => xnet_pull_interval,
+            _ => Duration::from_millis(self.gossip_config.retransmission_request_ms as u64),
+        };
         // Throttle processing of incoming re-transmission request
         self.current_peers
             .lock()
@@ -718,11 +944,11 @@ impl DownloadManager for DownloadManagerImpl {
                 }
             })
             .map_or_else(Err, |peer_context| {
-                let elapsed_ms = peer_context
+                if peer_context
                     .last_retransmission_request_processed_time
                     .elapsed()
-                    .as_millis();
-                if elapsed_ms < self.gossip_config.retransmission_request_ms as u128 {
+                    < min_interval
+                {
                     BUSY_ERR
                 } else {
                     peer_context.last_retransmission_request_processed_time = Instant::now();
@@ -736,10 +962,17 @@ impl DownloadManager for DownloadManagerImpl {
         self.transport
             .clear_send_queues(self.transport_client_type, &peer_id);
 
-        let adverts = self
-            .artifact_manager
-            .get_all_validated_by_filter(&gossip_re_request.filter)
-            .into_iter();
+        // XNet peers only get the XNet stream slices they asked for.
+        let adverts = if is_xnet_peer {
+            self.artifact_manager.get_all_validated_by_filter_for_tag(
+                ArtifactTag::XNetStreamSliceArtifact,
+                &gossip_re_request.filter,
+            )
+        } else {
+            self.artifact_manager
+                .get_all_validated_by_filter(&gossip_re_request.filter)
+        }
+        .into_iter();
 
         adverts.for_each(|advert| self.send_advert_to_peer_list(advert, vec![peer_id]));
         Ok(())
@@ -768,11 +1001,25 @@ impl DownloadManager for DownloadManagerImpl {
             .observe(start_time.elapsed().as_millis() as f64)
     }
 
+    /// The method schedules a retransmission request for the peer with the
+    /// given node ID.
+    fn schedule_retransmission_request(&self, peer_id: NodeId) {
+        self.retransmission_manager.schedule(peer_id);
+    }
+
+    /// The method sends all scheduled retransmission requests that are due.
+    fn send_due_retransmission_requests(&self) {
+        for peer_id in self.retransmission_manager.take_due(Instant::now()) {
+            self.send_retransmission_request(peer_id);
+        }
+    }
+
     /// The method is invoked periodically by the *Gossip* component to perform
     /// P2P book keeping tasks.
     fn on_timer(&self, event_handler: &Arc<dyn P2PEventHandlerControl>) {
         let (update_priority_fns, retransmission_request, refresh_registry) =
             self.get_timer_tasks();
+        let xnet_pull = self.is_xnet_pull_due();
         if update_priority_fns {
             let dropped_adverts = self
                 .prioritizer
@@ -782,12 +1029,24 @@ impl DownloadManager for DownloadManagerImpl {
             dropped_adverts
                 .iter()
                 .for_each(|id| artifacts_under_construction.remove_tracker(id));
+            std::mem::drop(artifacts_under_construction);
+            self.persist_download_state();
         }
 
         if retransmission_request {
-            // Send a retransmission request to all peers.
-            let current_peers = self.peer_manager.get_current_peer_ids();
-            for peer in current_peers {
+            // Schedule a retransmission request to all peers on the own subnet.
+            for peer in self.get_subnet_peer_ids() {
+                self.schedule_retransmission_request(peer);
+            }
+        }
+        self.send_due_retransmission_requests();
+
+        // XNet stream slices are only ever pulled, so the XNet peers are sent
+        // retransmission requests much more often, bypassing the
+        // retransmission manager.
+        if xnet_pull {
+            let xnet_peers = self.xnet_peers.read().unwrap().clone();
+            for peer in xnet_peers {
                 self.send_retransmission_request(peer);
             }
         }
@@ -798,11 +1057,18 @@ impl DownloadManager for DownloadManagerImpl {
 
         // Collect the peers with timed-out requests.
         let mut timed_out_peers = Vec::new();
-        for (node_id, peer_context) in self.current_peers.lock().unwrap().iter_mut() {
+        let mut current_peers = self.current_peers.lock().unwrap();
+        for (node_id, peer_context) in current_peers.iter_mut() {
             if self.process_timed_out_requests(node_id, peer_context) {
                 timed_out_peers.push(*node_id);
             }
         }
+        self.peer_metrics.set_chunks_in_flight(
+            current_peers
+                .iter()
+                .map(|(node_id, peer_context)| (*node_id, peer_context.requested.len())),
+        );
+        std::mem::drop(current_peers);
 
         // Process timed-out artifacts.
         self.process_timed_out_artifacts();
@@ -819,6 +1085,20 @@ impl DownloadManager for DownloadManagerImpl {
             let _ = self.download_next(peer_id);
         }
     }
+
+    /// The method refreshes the peers if the registry or the activated
+    /// registry version changed since the last refresh.
+    fn on_registry_change(&self, event_handler: &Arc<dyn P2PEventHandlerControl>) {
+        let latest_version = self.registry_client.get_latest_version();
+        let new_registry_version =
+            latest_version > *self.refreshed_registry_version.lock().unwrap();
+        let new_activated_version = self.activation_registry_version(latest_version)
+            != *self.activated_registry_version.lock().unwrap();
+        if new_registry_version || new_activated_version {
+            *self.registry_refresh_instant.lock().unwrap() = Instant::now();
+            self.refresh_registry(event_handler);
+        }
+    }
 }
 
 impl DownloadManagerImpl {
@@ -832,7 +1112,15 @@ impl DownloadManagerImpl {
         transport: Arc<dyn Transport>,
         event_handler: Arc<dyn P2PEventHandlerControl>,
         flow_mapper: Arc<FlowMapper>,
+        static_node_records: BTreeMap<NodeId, NodeRecord>,
+        download_state_path: Option<PathBuf>,
+        recently_seen_ingress: Arc<dyn RecentlySeenIngress>,
+        xnet_pull_interval: Option<Duration>,
+        compress_state_sync_chunks: bool,
+        consensus_pool_cache: Option<Arc<dyn ConsensusPoolCache>>,
+        max_peer_metric_labels: usize,
         log: ReplicaLogger,
+        event_log: EventLog,
         metrics_registry: &MetricsRegistry,
     ) -> Self {
         let transport_client_type = TransportClientType::P2P;
@@ -852,6 +1140,15 @@ impl DownloadManagerImpl {
             DownloadPrioritizerMetrics::new(&metrics_registry),
         ));
 
+        // Retransmission requests are coalesced per peer and each peer may
+        // receive a limited number of requests per retransmission period.
+        let retransmission_manager = Arc::new(RetransmissionManagerImpl::new(
+            Duration::from_millis(RETRANSMISSION_COALESCING_INTERVAL_MS),
+            RETRANSMISSION_BUDGET_PER_PEER,
+            Duration::from_millis(gossip_config.retransmission_request_ms as u64),
+            RetransmissionMetrics::new(&metrics_registry),
+        ));
+
         let download_manager = DownloadManagerImpl {
             node_id,
             subnet_id: RwLock::new(Some(subnet_id)),
@@ -865,17 +1162,165 @@ impl DownloadManagerImpl {
             transport_client_type,
             artifacts_under_construction: RwLock::new(ArtifactDownloadListImpl::new(log.clone())),
             log,
+            event_log,
             metrics: DownloadManagementMetrics::new(&metrics_registry),
+            peer_metrics: PeerMetrics::new(&metrics_registry, max_peer_metric_labels),
             gossip_config,
             receive_check_caches: RwLock::new(HashMap::new()),
             pfn_invocation_instant: Mutex::new(Instant::now()),
             registry_refresh_instant: Mutex::new(Instant::now()),
+            refreshed_registry_version: Mutex::new(RegistryVersion::from(0)),
+            consensus_pool_cache,
+            activated_registry_version: Mutex::new(RegistryVersion::from(0)),
             retransmission_request_instant: Mutex::new(Instant::now()),
+            retransmission_manager,
+            recently_seen_ingress,
+            static_node_records,
+            download_state_path,
+            xnet_pull_interval,
+            compress_state_sync_chunks,
+            xnet_peers: RwLock::new(BTreeSet::new()),
+            xnet_pull_instant: Mutex::new(Instant::now()),
         };
         download_manager.refresh_registry(&event_handler);
+        download_manager.restore_download_state();
         download_manager
     }
 
+    /// The method subscribes to the changes that may require the peers to be
+    /// refreshed, which are to be passed to `on_registry_change()`.
+    ///
+    /// Changes made before the subscription are not notified, so the caller
+    /// is to invoke `on_registry_change()` once after subscribing.
+    pub(crate) fn registry_changes(&self) -> RegistryChanges {
+        RegistryChanges {
+            registry_versions: [
+                NODE_RECORD_KEY_PREFIX.to_string(),
+                SUBNET_RECORD_KEY_PREFIX.to_string(),
+                make_subnet_list_record_key(),
+            ]
+            .iter()
+            .map(|key_prefix| self.registry_client.subscribe(key_prefix))
+            .collect(),
+            finalized_height: self
+                .consensus_pool_cache
+                .as_ref()
+                .map(|cache| cache.subscribe_finalized_height()),
+        }
+    }
+
+    /// The method returns how many of the peers of the own subnet are
+    /// currently connected. Peers on other subnets are not counted.
+    pub(crate) fn peer_connectivity(&self) -> PeerConnectivity {
+        let xnet_peers = self.xnet_peers.read().unwrap().clone();
+        let current_peers = self.current_peers.lock().unwrap();
+        let subnet_peers = current_peers
+            .values()
+            .filter(|peer_context| !xnet_peers.contains(&peer_context.peer_id));
+        let (peers, connected_peers) =
+            subnet_peers.fold((0, 0), |(peers, connected_peers), peer_context| {
+                (peers + 1, connected_peers + peer_context.connected as usize)
+            });
+        PeerConnectivity {
+            peers,
+            connected_peers,
+        }
+    }
+
+    /// The method stores a snapshot of the in-progress state sync downloads.
+    ///
+    /// Only state sync downloads are persisted as other artifacts are small
+    /// and re-advertised quickly after a restart.
+    fn persist_download_state(&self) {
+        let path = match &self.download_state_path {
+            Some(path) => path,
+            None => return,
+        };
+
+        // Copy out the requested chunks and drop the peer context lock.
+        let mut requested_chunks: HashMap<ArtifactId, Vec<(NodeId, ChunkId)>> = HashMap::new();
+        for (peer_id, peer_context) in self.current_peers.lock().unwrap().iter() {
+            for key in peer_context.requested.keys() {
+                requested_chunks
+                    .entry(key.artifact_id.clone())
+                    .or_default()
+                    .push((*peer_id, key.chunk_id));
+            }
+        }
+
+        // Copy out the downloads and drop the under construction list lock.
+        let downloads: Vec<(ArtifactId, NodeId)> = self
+            .artifacts_under_construction
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(artifact_id, _)| matches!(artifact_id, ArtifactId::StateSync(_)))
+            .map(|(artifact_id, tracker)| (artifact_id.clone(), tracker.peer_id))
+            .collect();
+
+        let downloads = downloads
+            .into_iter()
+            .filter_map(|(artifact_id, charged_peer)| {
+                let advert_tracker = self
+                    .prioritizer
+                    .get_advert_tracker_by_id(&artifact_id)
+                    .ok()?;
+                let advert_tracker = advert_tracker.read().unwrap();
+                Some(PersistedDownload {
+                    advert: advert_tracker.advert.clone(),
+                    charged_peer,
+                    advertisers: advert_tracker.peers.clone(),
+                    requested_chunks: requested_chunks.remove(&artifact_id).unwrap_or_default(),
+                })
+            })
+            .collect();
+
+        if let Err(e) = (DownloadState { downloads }).store(path) {
+            warn!(
+                every_n_seconds => 30,
+                self.log,
+                "Failed to persist the download state to {:?}: {:?}", path, e
+            );
+        }
+    }
+
+    /// The method restores the persisted downloads by re-inserting the adverts
+    /// of all artifacts that are not yet available locally.
+    fn restore_download_state(&self) {
+        let path = match &self.download_state_path {
+            Some(path) => path,
+            None => return,
+        };
+        let download_state = match DownloadState::load(path) {
+            Ok(Some(download_state)) => download_state,
+            Ok(None) => return,
+            Err(e) => {
+                warn!(
+                    self.log,
+                    "Failed to load the download state from {:?}: {:?}", path, e
+                );
+                return;
+            }
+        };
+
+        for download in download_state.downloads {
+            if self
+                .artifact_manager
+                .has_artifact(&download.advert.artifact_id)
+            {
+                continue;
+            }
+            info!(
+                self.log,
+                "Resuming download of {:?}", download.advert.artifact_id
+            );
+            for peer_id in download.peers_in_resume_order() {
+                self.on_advert(download.advert.clone(), peer_id);
+            }
+            self.metrics.downloads_restored.inc();
+        }
+    }
+
     /// This helper method returns a list of tasks to be performed by this timer
     /// invocation.
     fn get_timer_tasks(&self) -> (bool, bool, bool) {
@@ -905,7 +1350,9 @@ impl DownloadManagerImpl {
             }
         }
 
-        // Check if the registry has to be refreshed.
+        // Check if the registry has to be refreshed. Registry changes are
+        // handled as they happen by `on_registry_change()`, this is only a
+        // periodic fallback.
         {
             let mut registry_refresh_instant = self.registry_refresh_instant.lock().unwrap();
             if registry_refresh_instant.elapsed().as_millis()
@@ -922,35 +1369,111 @@ impl DownloadManagerImpl {
         }
     }
 
+    /// Returns the registry version that consensus activated at the latest
+    /// finalized height, capped at the given latest registry version. Without
+    /// a consensus pool cache, the latest version is active.
+    fn activation_registry_version(&self, latest_version: RegistryVersion) -> RegistryVersion {
+        self.consensus_pool_cache
+            .as_ref()
+            .and_then(|cache| {
+                registry_version_at_height(cache.as_ref(), cache.finalized_block().height())
+            })
+            .map_or(latest_version, |version| version.min(latest_version))
+    }
+
+    /// Returns the nodes of the given subnet at the given registry version,
+    /// with their node records.
+    ///
+    /// The node record of a node is taken from the registry or, if the
+    /// registry contains none, from the static node records. Nodes without
+    /// either are still members of the subnet, but cannot be connected to.
+    fn get_subnet_node_records(
+        &self,
+        subnet_id: Option<SubnetId>,
+        registry_version: RegistryVersion,
+    ) -> Vec<(NodeId, Option<NodeRecord>)> {
+        let node_ids = match subnet_id {
+            Some(subnet) => self
+                .registry_client
+                .get_node_ids_on_subnet(subnet, registry_version)
+                .unwrap_or(None)
+                .unwrap_or_else(Vec::new),
+            None => Vec::new(),
+        };
+        node_ids
+            .into_iter()
+            .map(|node_id| {
+                let node_record = self
+                    .registry_client
+                    .get_transport_info(node_id, registry_version)
+                    .unwrap_or(None)
+                    .or_else(|| self.static_node_records.get(&node_id).cloned());
+                if node_record.is_none() && node_id != self.node_id {
+                    warn!(
+                        self.log,
+                        "No node record for node {:?} at registry version {}",
+                        node_id,
+                        registry_version
+                    );
+                }
+                (node_id, node_record)
+            })
+            .collect()
+    }
+
     // Update the peer manager state based on the latest registry value.
+    //
+    // Nodes added at the latest registry version are connected right away.
+    // Nodes removed at the latest version are only disconnected once the
+    // activated registry version no longer contains them either.
     pub fn refresh_registry(&self, event_handler: &Arc<dyn P2PEventHandlerControl>) {
         let registry_version = self.registry_client.get_latest_version();
+        let activated_version = self.activation_registry_version(registry_version);
+        *self.refreshed_registry_version.lock().unwrap() = registry_version;
+        *self.activated_registry_version.lock().unwrap() = activated_version;
         self.metrics
             .registry_version_used
             .set(registry_version.get() as i64);
         let subnet_id = *self.subnet_id.read().unwrap();
-        let node_records: Vec<(NodeId, NodeRecord)> = match subnet_id {
-            Some(subnet) => self
-                .registry_client
-                .get_subnet_transport_infos(subnet, registry_version)
-                .unwrap_or(None)
-                .unwrap_or_else(Vec::new),
-            None => Vec::new(),
-        };
+        let mut node_records = self.get_subnet_node_records(subnet_id, registry_version);
+        let latest_nodes: BTreeSet<NodeId> = node_records.iter().map(|node_id| node_id.0).collect();
+        if activated_version < registry_version {
+            node_records.extend(
+                self.get_subnet_node_records(subnet_id, activated_version)
+                    .into_iter()
+                    .filter(|(node_id, _)| !latest_nodes.contains(node_id)),
+            );
+        }
         let registry_nodes: BTreeSet<NodeId> =
             node_records.iter().map(|node_id| node_id.0).collect();
-        node_records.into_iter().for_each(|(node_id, node_record)| {
-            if self
-                .peer_manager
-                .add_peer(node_id, &node_record, registry_version, event_handler)
-                .is_ok()
-            {
-                self.receive_check_caches.write().unwrap().insert(
-                    node_id,
-                    ReceiveCheckCache::new(self.gossip_config.receive_check_cache_size as usize),
-                );
+        let node_records = node_records
+            .into_iter()
+            .filter_map(|(node_id, node_record)| node_record.map(|record| (node_id, record)));
+        let xnet_records = match (subnet_id, self.xnet_pull_interval) {
+            (Some(subnet), Some(_)) => {
+                self.get_xnet_peer_records(subnet, &latest_nodes, registry_version)
             }
-        });
+            _ => Vec::new(),
+        };
+        let xnet_nodes: BTreeSet<NodeId> = xnet_records.iter().map(|node_id| node_id.0).collect();
+        *self.xnet_peers.write().unwrap() = xnet_nodes.clone();
+        node_records
+            .into_iter()
+            .chain(xnet_records.into_iter())
+            .for_each(|(node_id, node_record)| {
+                if self
+                    .peer_manager
+                    .add_peer(node_id, &node_record, registry_version, event_handler)
+                    .is_ok()
+                {
+                    self.receive_check_caches.write().unwrap().insert(
+                        node_id,
+                        ReceiveCheckCache::new(
+                            self.gossip_config.receive_check_cache_size as usize,
+                        ),
+                    );
+                }
+            });
         // If this node is removed from current subnet, update subnet_id to reflect new
         // state.
         if !registry_nodes.contains(&self.node_id) {
@@ -959,14 +1482,85 @@ impl DownloadManagerImpl {
         }
         for peer in self.peer_manager.get_current_peer_ids().into_iter() {
             // If a peer is not in registry, remove peer. If this node is not in registry,
-            // remove all peers.
-            if !registry_nodes.contains(&peer) || !registry_nodes.contains(&self.node_id) {
-                self.remove_node(peer, registry_version);
+            // remove all peers: the peers don't accept connections from nodes outside
+            // the subnet record, which is why observer replicas must be in it, see
+            // `P2PMode::Observer`.
+            if !(registry_nodes.contains(&peer) || xnet_nodes.contains(&peer))
+                || !registry_nodes.contains(&self.node_id)
+            {
+                self.remove_node(peer, registry_version, event_handler);
                 self.metrics.nodes_removed.inc();
             }
         }
     }
 
+    /// Returns the node records of the XNet peers of this node, i.e., of the
+    /// nodes on other subnets that XNet stream slices are pulled from and
+    /// served to.
+    ///
+    /// The nodes of this and of each other subnet are paired by
+    /// `select_xnet_peers()`, so that the choice is the same on both sides
+    /// and every node has at least one honest XNet peer on every other subnet.
+    fn get_xnet_peer_records(
+        &self,
+        subnet_id: SubnetId,
+        subnet_nodes: &BTreeSet<NodeId>,
+        registry_version: RegistryVersion,
+    ) -> Vec<(NodeId, NodeRecord)> {
+        if !subnet_nodes.contains(&self.node_id) {
+            return Vec::new();
+        }
+        let subnet_ids = self
+            .registry_client
+            .get_subnet_ids(registry_version)
+            .unwrap_or(None)
+            .unwrap_or_else(Vec::new);
+
+        let mut xnet_records = Vec::new();
+        for remote_subnet in subnet_ids.into_iter().filter(|id| *id != subnet_id) {
+            let remote_records = self
+                .registry_client
+                .get_subnet_transport_infos(remote_subnet, registry_version)
+                .unwrap_or(None)
+                .unwrap_or_else(Vec::new);
+            let remote_nodes = remote_records.iter().map(|(node_id, _)| *node_id).collect();
+            let xnet_peers = select_xnet_peers(self.node_id, subnet_nodes, &remote_nodes);
+            xnet_records.extend(
+                remote_records
+                    .into_iter()
+                    .filter(|(node_id, _)| xnet_peers.contains(node_id)),
+            );
+        }
+        xnet_records
+    }
+
+    /// Returns the current peers on the own subnet, i.e., without the XNet
+    /// peers.
+    fn get_subnet_peer_ids(&self) -> Vec<NodeId> {
+        let xnet_peers = self.xnet_peers.read().unwrap();
+        self.peer_manager
+            .get_current_peer_ids()
+            .into_iter()
+            .filter(|peer| !xnet_peers.contains(peer))
+            .collect()
+    }
+
+    /// Returns true if XNet stream slices are pulled over gossip and the XNet
+    /// pull interval has elapsed since the last pull.
+    fn is_xnet_pull_due(&self) -> bool {
+        let xnet_pull_interval = match self.xnet_pull_interval {
+            Some(xnet_pull_interval) => xnet_pull_interval,
+            None => return false,
+        };
+        let mut xnet_pull_instant = self.xnet_pull_instant.lock().unwrap();
+        if xnet_pull_instant.elapsed() >= xnet_pull_interval {
+            *xnet_pull_instant = Instant::now();
+            true
+        } else {
+            false
+        }
+    }
+
     fn update_subnet_id(&self, version: RegistryVersion) {
         if let Some((subnet_id, _)) = self
             .registry_client
@@ -990,9 +1584,17 @@ impl DownloadManagerImpl {
     }
 
     /// This method removes the given node from peer manager and clears adverts.
-    fn remove_node(&self, node: NodeId, registry_version: RegistryVersion) {
-        self.peer_manager.remove_peer(node, registry_version);
+    fn remove_node(
+        &self,
+        node: NodeId,
+        registry_version: RegistryVersion,
+        event_handler: &Arc<dyn P2PEventHandlerControl>,
+    ) {
+        self.peer_manager
+            .remove_peer(node, registry_version, event_handler);
+        self.peer_metrics.remove_peer(&node);
         self.receive_check_caches.write().unwrap().remove(&node);
+        self.retransmission_manager.remove_peer(&node);
         self.prioritizer
             .clear_peer_adverts(node, AdvertTrackerFinalAction::Abort)
             .unwrap_or_else(|e| {
@@ -1015,7 +1617,21 @@ impl DownloadManagerImpl {
             .op_duration
             .with_label_values(&["transport_send"])
             .start_timer();
-        let message = TransportPayload(pb::GossipMessage::proxy_encode(message).unwrap());
+        let codec = self
+            .transport
+            .wire_codec(self.transport_client_type, &peer_id)
+            .unwrap_or_default();
+        let message = encode_gossip_message(codec, message).map_err(|e| {
+            warn!(
+                every_n_seconds => 30,
+                self.log,
+                "Failed to encode gossip message to peer {:?} with {:?}: {}",
+                peer_id,
+                codec,
+                e
+            );
+            TransportErrorCode::SerializationFailed
+        })?;
         self.transport
             .send(self.transport_client_type, &peer_id, flow_tag, message)
             .map_err(|e| {
@@ -1035,7 +1651,10 @@ impl DownloadManagerImpl {
         let flow_tag = self.flow_mapper.map(&message);
         for peer_id in peer_ids {
             self.transport_send(message.clone(), peer_id, flow_tag)
-                .map(|_| self.metrics.adverts_sent.inc())
+                .map(|_| {
+                    self.metrics.adverts_sent.inc();
+                    self.peer_metrics.advert_sent(&peer_id);
+                })
                 .unwrap_or_else(|_e| {
                     // Ignore advert send failures
                     self.metrics.adverts_send_failed.inc();
@@ -1159,6 +1778,8 @@ impl DownloadManagerImpl {
         Some(GossipChunkRequest {
             artifact_id: advert_tracker.advert.artifact_id.clone(),
             chunk_id,
+            accepts_compressed_chunks: self.compress_state_sync_chunks,
+            artifact_encoding_version: ARTIFACT_ENCODING_VERSION,
         })
     }
 
@@ -1188,6 +1809,10 @@ impl DownloadManagerImpl {
         let peer_advert_queues = self.prioritizer.get_peer_priority_queues(peer_id);
         let peer_advert_map = peer_advert_queues.peer_advert_map_ref.read().unwrap();
 
+        // The backpressure signal of each artifact processor is queried at most
+        // once per call.
+        let mut backpressure: HashMap<ArtifactTag, bool> = HashMap::new();
+
         let mut visited = 0;
         for (_, advert_tracker) in peer_advert_map.iter() {
             visited += 1;
@@ -1198,6 +1823,18 @@ impl DownloadManagerImpl {
             let mut advert_tracker = advert_tracker.write().unwrap();
             let advert_tracker = advert_tracker.deref_mut();
 
+            // Do not request chunks of artifacts whose processor falls behind, so
+            // that the unvalidated section of the pool does not grow without bound.
+            let tag = ArtifactTag::from(&advert_tracker.advert.artifact_id);
+            let artifact_manager = self.artifact_manager.as_ref();
+            if *backpressure
+                .entry(tag)
+                .or_insert_with(|| artifact_manager.has_backpressure(tag))
+            {
+                self.metrics.download_next_backpressured.inc();
+                continue;
+            }
+
             // Try to begin a download for the artifact and collect its chunk requests.
             if let Some(artifact_tracker) = artifacts_under_construction.schedule_download(
                 peer_id,
@@ -1325,6 +1962,10 @@ impl DownloadManagerImpl {
             !timed_out
         });
 
+        if !timed_out_chunks.is_empty() {
+            self.peer_metrics
+                .chunks_timed_out(node_id, timed_out_chunks.len());
+        }
         for (node_id, chunk_id, artifact_id) in timed_out_chunks.into_iter() {
             self.process_timed_out_chunk(&node_id, artifact_id, chunk_id)
         }
@@ -1361,6 +2002,28 @@ impl DownloadManagerImpl {
     }
 }
 
+/// The function recomputes the integrity hash of a downloaded artifact, using
+/// the hash function of its artifact kind.
+fn integrity_hash(artifact: &Artifact) -> CryptoHash {
+    match artifact {
+        Artifact::ConsensusMessage(msg) => ConsensusArtifact::integrity_hash(msg),
+        Artifact::IngressMessage(msg) => IngressArtifact::integrity_hash(msg),
+        Artifact::CertificationMessage(msg) => CertificationArtifact::integrity_hash(msg),
+        Artifact::DkgMessage(msg) => DkgArtifact::integrity_hash(msg),
+        Artifact::EcdsaMessage(msg) => EcdsaArtifact::integrity_hash(msg),
+        // FileTreeSync is not of ArtifactKind kind, and it's used only for testing.
+        // Thus, we make up the integrity_hash.
+        Artifact::FileTreeSync(_msg) => CryptoHash(vec![]),
+        Artifact::StateSync(msg) => StateSyncArtifact::integrity_hash(msg),
+        Artifact::EquivocationProof(msg) => EquivocationArtifact::integrity_hash(msg),
+        Artifact::RemoteDkgMessage(msg) => RemoteDkgArtifact::integrity_hash(msg),
+        Artifact::CanisterHttpMessage(msg) => CanisterHttpArtifact::integrity_hash(msg),
+        Artifact::QueryStatsMessage(msg) => QueryStatsArtifact::integrity_hash(msg),
+        Artifact::XNetStreamSlice(msg) => XNetStreamSliceArtifact::integrity_hash(msg),
+        Artifact::RegistryDelta(msg) => RegistryDeltaArtifact::integrity_hash(msg),
+    }
+}
+
 /// `PeerManagerImpl` implements the `PeerManager` trait.
 impl PeerManager for PeerManagerImpl {
     /// The method returns the current list of peers.
@@ -1451,8 +2114,14 @@ impl PeerManager for PeerManagerImpl {
             })
     }
 
-    /// The method removes the given peer from the list of current peers.
-    fn remove_peer(&self, node_id: NodeId, registry_version: RegistryVersion) {
+    /// The method removes the given peer from the list of current peers, and
+    /// tears down its flows in the event handler.
+    fn remove_peer(
+        &self,
+        node_id: NodeId,
+        registry_version: RegistryVersion,
+        event_handler: &Arc<dyn P2PEventHandlerControl>,
+    ) {
         let mut current_peers = self.current_peers.lock().unwrap();
         if let Err(e) =
             self.transport
@@ -1462,27 +2131,94 @@ impl PeerManager for PeerManagerImpl {
         }
         // Remove the peer irrespective of the result of the stop_connections() call.
         current_peers.remove(&node_id);
+        event_handler.remove_node(node_id);
     }
 }
 
+/// Returns the nodes among `remote_nodes` that `node_id`, one of
+/// `local_nodes`, pulls XNet stream slices from and serves them to.
+///
+/// Every pair of nodes is scored by hashing both node IDs. Each node picks the
+/// `f + 1` highest scoring nodes of the other subnet, where `f` is the number
+/// of faulty nodes that subnet tolerates; and two nodes are paired if either
+/// picks the other. Pairing is thus symmetric, independent of the subnet sizes
+/// and every node is paired with at least one honest node of the other subnet.
+pub(crate) fn select_xnet_peers(
+    node_id: NodeId,
+    local_nodes: &BTreeSet<NodeId>,
+    remote_nodes: &BTreeSet<NodeId>,
+) -> BTreeSet<NodeId> {
+    let mut xnet_peers = pick_xnet_peers(node_id, remote_nodes);
+    xnet_peers.extend(
+        remote_nodes
+            .iter()
+            .filter(|remote_node| pick_xnet_peers(**remote_node, local_nodes).contains(&node_id)),
+    );
+    xnet_peers
+}
+
+/// Returns the `f + 1` nodes among `candidates` with the highest pairing
+/// scores with `node_id`, where `f = (candidates.len() - 1) / 3`.
+fn pick_xnet_peers(node_id: NodeId, candidates: &BTreeSet<NodeId>) -> BTreeSet<NodeId> {
+    let mut scored: Vec<_> = candidates
+        .iter()
+        .map(|candidate| (pairing_score(node_id, *candidate), *candidate))
+        .collect();
+    scored.sort_unstable_by(|a, b| b.cmp(a));
+    let count = candidates.len().saturating_sub(1) / 3 + 1;
+    scored
+        .into_iter()
+        .take(count)
+        .map(|(_, candidate)| candidate)
+        .collect()
+}
+
+/// Symmetric pairing score of two nodes: the SHA256 hash of their IDs, in
+/// ascending order.
+fn pairing_score(a: NodeId, b: NodeId) -> [u8; 32] {
+    let (low, high) = if a <= b { (a, b) } else { (b, a) };
+    let mut hasher = Sha256::new();
+    hasher.write(low.get().as_slice());
+    hasher.write(high.get().as_slice());
+    hasher.finish()
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::*;
     use crate::download_prioritization::DownloadPrioritizerError;
+    use crate::download_state::DOWNLOAD_STATE_FILE_NAME;
     use crate::event_handler::{tests::new_test_event_handler, MAX_ADVERT_BUFFER};
+    use crate::metrics::RecentlySeenIngressMetrics;
+    use crate::recently_seen_ingress::{RecentlySeenIngressImpl, RECENTLY_SEEN_INGRESS_CAPACITY};
+    use futures::FutureExt;
+    use ic_config::metrics::DEFAULT_P2P_MAX_PEER_LABELS;
     use ic_interfaces::artifact_manager::OnArtifactError;
     use ic_logger::LoggerImpl;
     use ic_metrics::MetricsRegistry;
     use ic_registry_client::client::RegistryClientImpl;
     use ic_registry_client::fake::FakeRegistryClient;
     use ic_test_utilities::port_allocation::allocate_ports;
-    use ic_test_utilities::registry::{add_subnet_record, SubnetRecordBuilder};
+    use ic_test_utilities::registry::{
+        add_subnet_record, insert_initial_dkg_transcript, SubnetRecordBuilder,
+    };
     use ic_test_utilities::{
+        consensus::{
+            make_catch_up_package_with_empty_transcript_with_version, FakeConsensusPoolCache,
+        },
         p2p::*,
         thread_transport::*,
-        types::ids::{node_id_to_u64, node_test_id, subnet_test_id},
+        types::{
+            ids::{node_id_to_u64, node_test_id, subnet_test_id},
+            messages::SignedIngressBuilder,
+        },
+    };
+    use ic_types::artifact::{
+        IngressMessageAttribute, IngressMessageId, RegistryDeltaId, RegistryDeltaMessage,
+        StateSyncMessage,
     };
-    use ic_types::artifact::StateSyncMessage;
+    use ic_types::chunkable::ARTIFACT_CHUNK_SIZE;
+    use ic_types::consensus::catchup::CUPWithOriginalProtobuf;
     use ic_types::crypto::CryptoHash;
     use ic_types::NodeId;
     use ic_types::{
@@ -1509,6 +2245,8 @@ pub mod tests {
         pub quota: usize,
         /// The number of chunks.
         pub num_chunks: u32,
+        /// The tags of the artifacts whose processor falls behind.
+        pub backpressured_tags: Mutex<Vec<artifact::ArtifactTag>>,
     }
 
     /// The test artifact.
@@ -1579,10 +2317,9 @@ pub mod tests {
             Ok(())
         }
 
-        /// The method to test if an artifact is available is not implemented as
-        /// it is not used.
+        /// The method always returns "false".
         fn has_artifact(&self, _message_id: &artifact::ArtifactId) -> bool {
-            unimplemented!()
+            false
         }
 
         /// The method to return a validated artifact is not implemented as
@@ -1608,6 +2345,16 @@ pub mod tests {
             unimplemented!()
         }
 
+        /// The method to get the list of validated adverts of a client is not
+        /// implemented as it is not used.
+        fn get_all_validated_by_filter_for_tag(
+            &self,
+            _tag: artifact::ArtifactTag,
+            _filter: &artifact::ArtifactFilter,
+        ) -> Vec<GossipAdvert> {
+            unimplemented!()
+        }
+
         /// The method returns the internal quota.
         fn get_remaining_quota(
             &self,
@@ -1617,6 +2364,11 @@ pub mod tests {
             Some(self.quota)
         }
 
+        /// The method returns "true" for the backpressured tags.
+        fn has_backpressure(&self, tag: artifact::ArtifactTag) -> bool {
+            self.backpressured_tags.lock().unwrap().contains(&tag)
+        }
+
         /// The method returns the priority function that always uses
         /// Priority::FetchAll.
         fn get_priority_function(&self, _: artifact::ArtifactTag) -> Option<ArtifactPriorityFn> {
@@ -1626,8 +2378,13 @@ pub mod tests {
         /// The method returns a new TestArtifact instance.
         fn get_chunk_tracker(
             &self,
-            _id: &artifact::ArtifactId,
+            advert: &GossipAdvert,
         ) -> Option<Box<dyn Chunkable + Send + Sync>> {
+            // Registry deltas are downloaded as multiple chunks like in
+            // production.
+            if let ArtifactId::RegistryDelta(_) = advert.artifact_id {
+                return RegistryDeltaArtifact::multi_chunk_tracker(advert.size);
+            }
             let chunks = vec![];
             Some(Box::new(TestArtifact {
                 num_chunks: self.num_chunks,
@@ -1651,11 +2408,44 @@ pub mod tests {
         num_replicas: u32,
         logger: &LoggerImpl,
         registry_client: Arc<dyn RegistryClient>,
+    ) -> DownloadManagerImpl {
+        new_test_download_manager_with_consensus_pool_cache(
+            num_replicas,
+            logger,
+            registry_client,
+            None,
+        )
+    }
+
+    fn new_test_download_manager_with_consensus_pool_cache(
+        num_replicas: u32,
+        logger: &LoggerImpl,
+        registry_client: Arc<dyn RegistryClient>,
+        consensus_pool_cache: Option<Arc<dyn ConsensusPoolCache>>,
+    ) -> DownloadManagerImpl {
+        new_test_download_manager_with_static_node_records(
+            num_replicas,
+            logger,
+            registry_client,
+            consensus_pool_cache,
+            BTreeMap::new(),
+            None,
+        )
+    }
+
+    fn new_test_download_manager_with_static_node_records(
+        num_replicas: u32,
+        logger: &LoggerImpl,
+        registry_client: Arc<dyn RegistryClient>,
+        consensus_pool_cache: Option<Arc<dyn ConsensusPoolCache>>,
+        static_node_records: BTreeMap<NodeId, NodeRecord>,
+        download_state_path: Option<PathBuf>,
     ) -> DownloadManagerImpl {
         let log: ReplicaLogger = logger.root.clone().into();
         let artifact_manager = TestArtifactManager {
             quota: 2 * 1024 * 1024 * 1024,
             num_chunks: 1,
+            ..Default::default()
         };
 
         // Set up transport.
@@ -1688,7 +2478,20 @@ pub mod tests {
             tp,
             event_handler,
             flow_mapper,
+            static_node_records,
+            download_state_path,
+            // The test artifact manager has no artifacts in its pools.
+            Arc::new(RecentlySeenIngressImpl::new(
+                RECENTLY_SEEN_INGRESS_CAPACITY,
+                Box::new(|_| false),
+                RecentlySeenIngressMetrics::new(&metrics_registry),
+            )),
+            None,
+            true,
+            consensus_pool_cache,
+            DEFAULT_P2P_MAX_PEER_LABELS,
             log,
+            EventLog::disabled(),
             &metrics_registry,
         )
     }
@@ -1841,6 +2644,197 @@ pub mod tests {
         }
     }
 
+    /// This function tests that a node removed at the latest registry version
+    /// stays connected until consensus activates that version.
+    #[tokio::test]
+    async fn download_manager_removes_replica_at_activation() {
+        let logger = p2p_test_setup_logger();
+        let num_replicas = 3;
+        let subnet_id = subnet_test_id(P2P_SUBNET_ID_DEFAULT);
+
+        let allocated_ports = allocate_ports("127.0.0.1", num_replicas as u16)
+            .expect("Port allocation for test failed");
+        let node_port_allocation: Vec<u16> = allocated_ports.iter().map(|np| np.port).collect();
+        let node_port_allocation = Arc::new(node_port_allocation);
+        let data_provider = test_group_set_registry(subnet_id, node_port_allocation);
+        let all_node_ids: Vec<_> = (0..num_replicas as u64).map(node_test_id).collect();
+        insert_initial_dkg_transcript(
+            1,
+            subnet_id,
+            &SubnetRecordBuilder::from(&all_node_ids).build(),
+            &data_provider,
+        );
+        let registry_client = Arc::new(FakeRegistryClient::new(data_provider.clone()));
+        registry_client.update_to_latest_version();
+
+        // Consensus activated registry version 1.
+        let cup_at_version = |version| {
+            CUPWithOriginalProtobuf::from_cup(
+                make_catch_up_package_with_empty_transcript_with_version(
+                    Arc::clone(&registry_client) as Arc<_>,
+                    subnet_id,
+                    version,
+                ),
+            )
+        };
+        let consensus_pool_cache = Arc::new(FakeConsensusPoolCache::new(cup_at_version(1)));
+        let download_manager = new_test_download_manager_with_consensus_pool_cache(
+            num_replicas,
+            &logger,
+            Arc::clone(&registry_client) as Arc<_>,
+            Some(Arc::clone(&consensus_pool_cache) as Arc<_>),
+        );
+        let event_handler = Arc::new(new_test_event_handler(MAX_ADVERT_BUFFER, node_test_id(0)))
+            as Arc<dyn P2PEventHandlerControl>;
+        download_manager.refresh_registry(&event_handler);
+        let removed_peer = node_test_id(num_replicas as u64 - 1);
+        assert!(download_manager
+            .peer_manager
+            .get_current_peer_ids()
+            .contains(&removed_peer));
+
+        // Registry version 2 removes the last node.
+        let node_ids: Vec<_> = (0..(num_replicas as u64 - 1)).map(node_test_id).collect();
+        add_subnet_record(
+            &data_provider,
+            2,
+            subnet_id,
+            SubnetRecordBuilder::from(&node_ids).build(),
+        );
+        registry_client.update_to_latest_version();
+
+        // The removed node stays connected while version 1 is active.
+        download_manager.on_registry_change(&event_handler);
+        assert!(download_manager
+            .peer_manager
+            .get_current_peer_ids()
+            .contains(&removed_peer));
+
+        // Once consensus activates version 2, the node is disconnected.
+        consensus_pool_cache.update_cup(cup_at_version(2));
+        download_manager.on_registry_change(&event_handler);
+        assert!(!download_manager
+            .peer_manager
+            .get_current_peer_ids()
+            .contains(&removed_peer));
+        assert_eq!(
+            download_manager.peer_manager.get_current_peer_ids().len(),
+            node_ids.len() - 1
+        );
+    }
+
+    /// This function tests that the nodes of the subnet without node records
+    /// in the registry are connected to with their static node records, and
+    /// that the other peers stay connected if a node has no record at all.
+    #[tokio::test]
+    async fn download_manager_uses_static_node_records() {
+        let logger = p2p_test_setup_logger();
+        let num_replicas = 3;
+        let subnet_id = subnet_test_id(P2P_SUBNET_ID_DEFAULT);
+
+        let allocated_ports = allocate_ports("127.0.0.1", num_replicas as u16)
+            .expect("Port allocation for test failed");
+        let node_port_allocation: Vec<u16> = allocated_ports.iter().map(|np| np.port).collect();
+        let data_provider = test_group_set_registry(subnet_id, Arc::new(node_port_allocation));
+        let registry_client = Arc::new(FakeRegistryClient::new(data_provider.clone()));
+        registry_client.update_to_latest_version();
+
+        // Node 3 has a static node record, node 4 has none.
+        let static_peer = node_test_id(num_replicas as u64);
+        let unreachable_peer = node_test_id(num_replicas as u64 + 1);
+        let download_manager = new_test_download_manager_with_static_node_records(
+            num_replicas,
+            &logger,
+            Arc::clone(&registry_client) as Arc<_>,
+            None,
+            vec![(static_peer, NodeRecord::default())]
+                .into_iter()
+                .collect(),
+            None,
+        );
+        let event_handler = Arc::new(new_test_event_handler(MAX_ADVERT_BUFFER, node_test_id(0)))
+            as Arc<dyn P2PEventHandlerControl>;
+        download_manager.refresh_registry(&event_handler);
+
+        // Registry version 2 adds both nodes to the subnet, without node
+        // records.
+        let node_ids: Vec<_> = (0..(num_replicas as u64 + 2)).map(node_test_id).collect();
+        add_subnet_record(
+            &data_provider,
+            2,
+            subnet_id,
+            SubnetRecordBuilder::from(&node_ids).build(),
+        );
+        registry_client.update_to_latest_version();
+        download_manager.on_registry_change(&event_handler);
+
+        let peers: BTreeSet<_> = download_manager
+            .peer_manager
+            .get_current_peer_ids()
+            .into_iter()
+            .collect();
+        let expected_peers: BTreeSet<_> = (1..num_replicas as u64)
+            .map(node_test_id)
+            .chain(std::iter::once(static_peer))
+            .collect();
+        assert_eq!(peers, expected_peers);
+        assert!(!peers.contains(&unreachable_peer));
+        assert_eq!(*download_manager.subnet_id.read().unwrap(), Some(subnet_id));
+    }
+
+    /// This function tests that the registry changes are notified as soon as
+    /// a new registry version is available and that the peers are refreshed
+    /// on the notification rather than by the timer.
+    #[tokio::test]
+    async fn download_manager_refreshes_registry_on_new_version() {
+        let logger = p2p_test_setup_logger();
+        let num_replicas = 3;
+
+        let allocated_ports = allocate_ports("127.0.0.1", num_replicas as u16)
+            .expect("Port allocation for test failed");
+        let node_port_allocation: Vec<u16> = allocated_ports.iter().map(|np| np.port).collect();
+        let node_port_allocation = Arc::new(node_port_allocation);
+        let data_provider = test_group_set_registry(
+            subnet_test_id(P2P_SUBNET_ID_DEFAULT),
+            node_port_allocation.clone(),
+        );
+        let registry_client = Arc::new(FakeRegistryClient::new(data_provider.clone()));
+        registry_client.update_to_latest_version();
+        let download_manager = new_test_download_manager_with_registry(
+            num_replicas,
+            &logger,
+            Arc::clone(&registry_client) as Arc<_>,
+        );
+        let event_handler = Arc::new(new_test_event_handler(MAX_ADVERT_BUFFER, node_test_id(0)))
+            as Arc<dyn P2PEventHandlerControl>;
+        let mut registry_changes = download_manager.registry_changes();
+        assert_eq!(registry_changes.changed().now_or_never(), None);
+        let removed_peer = node_test_id(num_replicas as u64 - 1);
+        assert!(download_manager
+            .peer_manager
+            .get_current_peer_ids()
+            .contains(&removed_peer));
+
+        let node_nums: Vec<u64> = (0..((node_port_allocation.len() - 1) as u64)).collect();
+        add_subnet_record(
+            &data_provider,
+            2,
+            subnet_test_id(P2P_SUBNET_ID_DEFAULT),
+            SubnetRecordBuilder::from(&node_nums.into_iter().map(node_test_id).collect::<Vec<_>>())
+                .build(),
+        );
+        registry_client.update_to_latest_version();
+        assert_eq!(registry_changes.changed().now_or_never(), Some(true));
+        let (_, _, refresh_registry) = download_manager.get_timer_tasks();
+        assert!(!refresh_registry);
+
+        download_manager.on_registry_change(&event_handler);
+        assert!(!download_manager
+            .peer_manager
+            .get_current_peer_ids()
+            .contains(&removed_peer));
+    }
+
     #[tokio::test]
     async fn download_manager_add_adverts() {
         let logger = p2p_test_setup_logger();
@@ -1848,6 +2842,49 @@ pub mod tests {
         test_add_adverts(&download_manager, 0..1000, node_test_id(1));
     }
 
+    /// This function tests that adverts of ingress messages that were seen
+    /// recently and are still in the ingress pool are not downloaded again.
+    #[tokio::test]
+    async fn download_manager_ignores_adverts_of_recently_seen_ingress() {
+        let logger = p2p_test_setup_logger();
+        let mut download_manager = new_test_download_manager(2, &logger);
+        let recently_seen_ingress = Arc::new(RecentlySeenIngressImpl::new(
+            RECENTLY_SEEN_INGRESS_CAPACITY,
+            Box::new(|_| true),
+            RecentlySeenIngressMetrics::new(&MetricsRegistry::new()),
+        ));
+        download_manager.recently_seen_ingress = recently_seen_ingress.clone();
+
+        let ingress = SignedIngressBuilder::new()
+            .expiry_time(current_time() + std::time::Duration::from_secs(60))
+            .build();
+        let id = IngressMessageId::from(&ingress);
+        let ingress_advert = |integrity_hash| GossipAdvert {
+            artifact_id: ArtifactId::IngressMessage(id.clone()),
+            attribute: ArtifactAttribute::IngressMessage(IngressMessageAttribute::new(&ingress)),
+            size: 0,
+            integrity_hash,
+        };
+        recently_seen_ingress.insert(id.clone(), CryptoHash(vec![1]), current_time());
+
+        // The advert of the recently-seen message is ignored.
+        download_manager.on_advert(ingress_advert(CryptoHash(vec![1])), node_test_id(1));
+        assert_eq!(
+            download_manager
+                .prioritizer
+                .get_advert_from_peer(&ArtifactId::IngressMessage(id.clone()), &node_test_id(1)),
+            Err(DownloadPrioritizerError::NotFound)
+        );
+
+        // A copy with different bytes is downloaded.
+        download_manager.on_advert(ingress_advert(CryptoHash(vec![2])), node_test_id(1));
+        assert!(download_manager
+            .prioritizer
+            .get_advert_from_peer(&ArtifactId::IngressMessage(id.clone()), &node_test_id(1))
+            .unwrap()
+            .is_some());
+    }
+
     /// This function asserts that the chunks to be downloaded is correctly
     /// upper bounded, where the upper bound is specified in the gossip
     /// configuration.
@@ -1877,6 +2914,27 @@ pub mod tests {
         }
     }
 
+    /// This function tests that compressed chunks are only requested if
+    /// chunk compression is enabled.
+    #[tokio::test]
+    async fn download_manager_requests_compressed_chunks_if_enabled() {
+        let logger = p2p_test_setup_logger();
+        let peer_id = node_test_id(1);
+        for compress_state_sync_chunks in [true, false].iter() {
+            let mut download_manager = new_test_download_manager(2, &logger);
+            download_manager.compress_state_sync_chunks = *compress_state_sync_chunks;
+            test_add_adverts(&download_manager, 0..1, peer_id);
+            let chunk_requests = download_manager
+                .download_next_compute_work(peer_id)
+                .unwrap();
+            assert_eq!(chunk_requests.len(), 1);
+            assert_eq!(
+                chunk_requests[0].accepts_compressed_chunks,
+                *compress_state_sync_chunks
+            );
+        }
+    }
+
     /// This function tests the correct functioning when a single chunk times
     /// out.
     ///
@@ -1962,6 +3020,41 @@ pub mod tests {
         }
     }
 
+    /// The function tests that the chunks requested from a peer are requested
+    /// from the other peers as soon as the peer disconnects.
+    #[tokio::test]
+    async fn download_manager_peer_down_releases_chunks() {
+        let num_replicas = 3;
+        let logger = p2p_test_setup_logger();
+        let download_manager = new_test_download_manager(num_replicas, &logger);
+        let request_queue_size =
+            download_manager.gossip_config.max_artifact_streams_per_peer as usize;
+        for peer_id in 1..num_replicas {
+            test_add_adverts(
+                &download_manager,
+                0..request_queue_size as u32,
+                node_test_id(peer_id as u64),
+            );
+        }
+
+        let requests = download_manager
+            .download_next_compute_work(node_test_id(1))
+            .unwrap();
+        assert_eq!(requests.len(), request_queue_size);
+
+        download_manager.peer_connection_down(node_test_id(1));
+        let current_peers = download_manager.current_peers.lock().unwrap();
+        assert!(current_peers
+            .get(&node_test_id(1))
+            .unwrap()
+            .requested
+            .is_empty());
+        assert_eq!(
+            current_peers.get(&node_test_id(2)).unwrap().requested.len(),
+            request_queue_size
+        );
+    }
+
     /// This functions tests the correct functioning when chunks and artifacts
     /// time out.
     #[tokio::test]
@@ -2057,6 +3150,7 @@ pub mod tests {
         download_manager.artifact_manager = Arc::new(TestArtifactManager {
             quota: 2 * 1024 * 1024 * 1024,
             num_chunks: request_queue_size * num_peers,
+            ..Default::default()
         });
 
         // Each peer should download the node_id'th range of chunks, i.e.,
@@ -2093,6 +3187,135 @@ pub mod tests {
         }
     }
 
+    /// The function tests that no chunks of artifacts whose processor falls
+    /// behind are requested, and that their download resumes once the
+    /// processor caught up.
+    #[tokio::test]
+    async fn download_next_skips_backpressured_artifacts() {
+        let logger = p2p_test_setup_logger();
+        let mut download_manager = new_test_download_manager(2, &logger);
+        let artifact_manager = Arc::new(TestArtifactManager {
+            quota: 2 * 1024 * 1024 * 1024,
+            num_chunks: 1,
+            backpressured_tags: Mutex::new(vec![artifact::ArtifactTag::FileTreeSyncArtifact]),
+        });
+        download_manager.artifact_manager = artifact_manager.clone();
+        let node_id = node_test_id(1);
+
+        // The file tree sync adverts are skipped while their processor falls
+        // behind, the state sync advert is not.
+        test_add_adverts(&download_manager, 0..2, node_id);
+        let state_sync_advert =
+            StateSyncArtifact::message_to_advert(&receive_check_test_create_message());
+        download_manager.on_advert(state_sync_advert.clone(), node_id);
+        let requested = |download_manager: &DownloadManagerImpl| {
+            let mut artifact_ids = download_manager
+                .download_next_compute_work(node_id)
+                .unwrap()
+                .into_iter()
+                .map(|request| request.artifact_id)
+                .collect::<Vec<_>>();
+            artifact_ids.sort_by_key(|artifact_id| format!("{:?}", artifact_id));
+            artifact_ids
+        };
+        assert_eq!(
+            requested(&download_manager),
+            vec![state_sync_advert.artifact_id]
+        );
+        assert_eq!(
+            download_manager.metrics.download_next_backpressured.get(),
+            2
+        );
+
+        // Once the processor caught up, the skipped adverts are downloaded.
+        artifact_manager.backpressured_tags.lock().unwrap().clear();
+        assert_eq!(
+            requested(&download_manager),
+            vec![
+                ArtifactId::FileTreeSync(0.to_string()),
+                ArtifactId::FileTreeSync(1.to_string()),
+            ]
+        );
+        assert_eq!(
+            download_manager.metrics.download_next_backpressured.get(),
+            2
+        );
+    }
+
+    /// The function tests that an artifact downloaded as multiple chunks is
+    /// downloaded as its unit chunk if the peer doesn't serve the other
+    /// chunks, as replicas running an older version do.
+    #[tokio::test]
+    async fn download_manager_falls_back_to_the_unit_chunk() {
+        let logger = p2p_test_setup_logger();
+        let download_manager = new_test_download_manager(2, &logger);
+        let peer_id = node_test_id(1);
+        let message = RegistryDeltaMessage {
+            id: RegistryDeltaId {
+                since_version: RegistryVersion::from(1),
+                version: RegistryVersion::from(2),
+            },
+            certified_response: vec![1; 2 * ARTIFACT_CHUNK_SIZE],
+        };
+        let advert: GossipAdvert = RegistryDeltaArtifact::message_to_advert(&message).into();
+        let artifact_id = advert.artifact_id.clone();
+        download_manager.on_advert(advert, peer_id);
+
+        let requests = download_manager
+            .download_next_compute_work(peer_id)
+            .unwrap();
+        assert!(requests
+            .iter()
+            .all(|request| request.chunk_id != ChunkId::from(CHUNKID_UNIT_CHUNK)));
+
+        // The peer doesn't serve the first chunk.
+        download_manager.on_chunk(
+            GossipChunk {
+                artifact_id: artifact_id.clone(),
+                chunk_id: ChunkId::from(1),
+                artifact_chunk: Err(P2PError {
+                    p2p_error_code: P2PErrorCode::NotFound,
+                }),
+                compressed: false,
+                artifact_encoding_version: ARTIFACT_ENCODING_VERSION,
+            },
+            peer_id,
+        );
+        assert!(download_manager
+            .prioritizer
+            .get_advert_from_peer(&artifact_id, &peer_id)
+            .unwrap()
+            .is_some());
+        {
+            let mut artifacts_under_construction = download_manager
+                .artifacts_under_construction
+                .write()
+                .unwrap();
+            let tracker = artifacts_under_construction
+                .get_tracker(&artifact_id)
+                .unwrap();
+            assert_eq!(
+                tracker.chunkable.chunks_to_download().collect::<Vec<_>>(),
+                vec![ChunkId::from(CHUNKID_UNIT_CHUNK)]
+            );
+        }
+
+        let unit_chunk = Box::new(message)
+            .get_chunk(ChunkId::from(CHUNKID_UNIT_CHUNK))
+            .unwrap();
+        download_manager.on_chunk(
+            GossipChunk {
+                artifact_id: artifact_id.clone(),
+                chunk_id: ChunkId::from(CHUNKID_UNIT_CHUNK),
+                artifact_chunk: Ok(unit_chunk),
+                compressed: false,
+                artifact_encoding_version: ARTIFACT_ENCODING_VERSION,
+            },
+            peer_id,
+        );
+        assert_eq!(download_manager.metrics.artifacts_received.get(), 1);
+    }
+
     /// The function returns an arbitrary Node ID in a BoxedStrategy.
     fn arbitrary_node_id() -> BoxedStrategy<NodeId> {
         any::<u64>().prop_map(node_test_id).boxed()
@@ -2106,6 +3329,78 @@ pub mod tests {
             .boxed()
     }
 
+    /// This function tests that the in-progress state sync downloads are
+    /// re-queued when the download manager is rebuilt from the persisted
+    /// download state, with the peer serving the download first.
+    #[tokio::test]
+    async fn download_manager_restores_persisted_downloads() {
+        let logger = p2p_test_setup_logger();
+        let num_replicas = 4;
+        let allocated_ports = allocate_ports("127.0.0.1", num_replicas as u16)
+            .expect("Port allocation for test failed");
+        let node_port_allocation: Vec<u16> = allocated_ports.iter().map(|np| np.port).collect();
+        let data_provider = test_group_set_registry(
+            subnet_test_id(P2P_SUBNET_ID_DEFAULT),
+            Arc::new(node_port_allocation),
+        );
+        let registry_client = Arc::new(FakeRegistryClient::new(data_provider));
+        registry_client.update_to_latest_version();
+        let tmp = tempfile::tempdir().unwrap();
+        let download_state_path = tmp.path().join(DOWNLOAD_STATE_FILE_NAME);
+        let new_download_manager = || {
+            new_test_download_manager_with_static_node_records(
+                num_replicas,
+                &logger,
+                Arc::clone(&registry_client) as Arc<_>,
+                None,
+                BTreeMap::new(),
+                Some(download_state_path.clone()),
+            )
+        };
+
+        let msg = receive_check_test_create_message();
+        let advert = StateSyncArtifact::message_to_advert(&msg);
+        let artifact_id = advert.artifact_id.clone();
+        let serving_peer = node_test_id(2);
+
+        // Nodes 3, 2 and 1 advertise the artifact, which is then downloaded
+        // from node 2.
+        let download_manager = new_download_manager();
+        for peer_id in &[node_test_id(3), serving_peer, node_test_id(1)] {
+            download_manager.on_advert(advert.clone(), *peer_id);
+        }
+        let requests = download_manager
+            .download_next_compute_work(serving_peer)
+            .unwrap();
+        assert_eq!(requests.len(), 1);
+        download_manager.persist_download_state();
+        drop(download_manager);
+
+        // The adverts are restored with the serving peer first.
+        let download_manager = new_download_manager();
+        let advert_tracker = download_manager
+            .prioritizer
+            .get_advert_tracker_by_id(&artifact_id)
+            .unwrap();
+        assert_eq!(
+            advert_tracker.read().unwrap().peers,
+            vec![serving_peer, node_test_id(3), node_test_id(1)]
+        );
+        assert_eq!(download_manager.metrics.downloads_restored.get(), 1);
+
+        // The download resumes from the serving peer.
+        let requests = download_manager
+            .download_next_compute_work(serving_peer)
+            .unwrap();
+        assert_eq!(
+            requests
+                .iter()
+                .map(|request| request.artifact_id.clone())
+                .collect::<Vec<_>>(),
+            vec![artifact_id]
+        );
+    }
+
     /// The function returns a simple state sync message.
     fn receive_check_test_create_message() -> StateSyncMessage {
         StateSyncMessage {
@@ -2121,6 +3416,18 @@ pub mod tests {
         }
     }
 
+    /// The function tests that the recomputed integrity hash of a downloaded
+    /// artifact matches the integrity hash advertised for it.
+    #[test]
+    fn test_integrity_hash_matches_advert() {
+        let msg = receive_check_test_create_message();
+        let advert = StateSyncArtifact::message_to_advert(&msg);
+        assert_eq!(
+            integrity_hash(&Artifact::StateSync(msg)),
+            advert.integrity_hash
+        );
+    }
+
     /// The function returns a new chunk with the given chunk ID and artifact
     /// ID.
     fn receive_check_test_create_chunk(chunk_id: ChunkId, artifact_id: ArtifactId) -> GossipChunk {
@@ -2135,6 +3442,8 @@ pub mod tests {
             artifact_id,
             chunk_id,
             artifact_chunk: Ok(artifact_chunk),
+            compressed: false,
+            artifact_encoding_version: ARTIFACT_ENCODING_VERSION,
         }
     }
 
@@ -2319,4 +3628,32 @@ pub mod tests {
             std::mem::drop(current_peers);
         }
     }
+
+    #[test]
+    fn xnet_peers_are_paired_symmetrically() {
+        let nodes = |first: u64, count: u64| {
+            (first..first + count)
+                .map(node_test_id)
+                .collect::<BTreeSet<_>>()
+        };
+        for (local_nodes, remote_nodes) in vec![
+            (nodes(0, 1), nodes(100, 1)),
+            (nodes(0, 4), nodes(100, 13)),
+            (nodes(0, 13), nodes(100, 4)),
+            (nodes(0, 28), nodes(100, 40)),
+        ] {
+            let f_remote = (remote_nodes.len() - 1) / 3;
+            for local_node in &local_nodes {
+                let xnet_peers = select_xnet_peers(*local_node, &local_nodes, &remote_nodes);
+                // Every node has at least one honest XNet peer.
+                assert!(xnet_peers.len() > f_remote);
+                assert!(xnet_peers.is_subset(&remote_nodes));
+                // And every XNet peer pairs with it in turn.
+                for remote_node in &xnet_peers {
+                    assert!(select_xnet_peers(*remote_node, &remote_nodes, &local_nodes)
+                        .contains(local_node));
+                }
+            }
+        }
+    }
 }
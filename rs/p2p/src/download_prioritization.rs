@@ -365,6 +365,12 @@ struct ClientAdvertMap {
     ecdsa: ClientAdvertMapInt,
     file_tree_sync: ClientAdvertMapInt,
     state: ClientAdvertMapInt,
+    equivocation: ClientAdvertMapInt,
+    remote_dkg: ClientAdvertMapInt,
+    canister_http: ClientAdvertMapInt,
+    query_stats: ClientAdvertMapInt,
+    xnet_stream_slice: ClientAdvertMapInt,
+    registry_delta: ClientAdvertMapInt,
 }
 
 /// A single client advert tracking data structure
@@ -395,6 +401,12 @@ impl Index<&ArtifactId> for ClientAdvertMap {
             ArtifactId::EcdsaMessage(_) => &self.ecdsa,
             ArtifactId::FileTreeSync(_) => &self.file_tree_sync,
             ArtifactId::StateSync(_) => &self.state,
+            ArtifactId::EquivocationProof(_) => &self.equivocation,
+            ArtifactId::RemoteDkgMessage(_) => &self.remote_dkg,
+            ArtifactId::CanisterHttpMessage(_) => &self.canister_http,
+            ArtifactId::QueryStatsMessage(_) => &self.query_stats,
+            ArtifactId::XNetStreamSlice(_) => &self.xnet_stream_slice,
+            ArtifactId::RegistryDelta(_) => &self.registry_delta,
         }
     }
 }
@@ -409,6 +421,12 @@ impl IndexMut<&ArtifactId> for ClientAdvertMap {
             ArtifactId::EcdsaMessage(_) => &mut self.ecdsa,
             ArtifactId::FileTreeSync(_) => &mut self.file_tree_sync,
             ArtifactId::StateSync(_) => &mut self.state,
+            ArtifactId::EquivocationProof(_) => &mut self.equivocation,
+            ArtifactId::RemoteDkgMessage(_) => &mut self.remote_dkg,
+            ArtifactId::CanisterHttpMessage(_) => &mut self.canister_http,
+            ArtifactId::QueryStatsMessage(_) => &mut self.query_stats,
+            ArtifactId::XNetStreamSlice(_) => &mut self.xnet_stream_slice,
+            ArtifactId::RegistryDelta(_) => &mut self.registry_delta,
         }
     }
 }
@@ -424,6 +442,12 @@ impl Index<ArtifactTag> for ClientAdvertMap {
             ArtifactTag::EcdsaArtifact => &self.ecdsa,
             ArtifactTag::FileTreeSyncArtifact => &self.file_tree_sync,
             ArtifactTag::StateSyncArtifact => &self.state,
+            ArtifactTag::EquivocationArtifact => &self.equivocation,
+            ArtifactTag::RemoteDkgArtifact => &self.remote_dkg,
+            ArtifactTag::CanisterHttpArtifact => &self.canister_http,
+            ArtifactTag::QueryStatsArtifact => &self.query_stats,
+            ArtifactTag::XNetStreamSliceArtifact => &self.xnet_stream_slice,
+            ArtifactTag::RegistryDeltaArtifact => &self.registry_delta,
         }
     }
 }
@@ -438,6 +462,12 @@ impl IndexMut<ArtifactTag> for ClientAdvertMap {
             ArtifactTag::EcdsaArtifact => &mut self.ecdsa,
             ArtifactTag::FileTreeSyncArtifact => &mut self.file_tree_sync,
             ArtifactTag::StateSyncArtifact => &mut self.state,
+            ArtifactTag::EquivocationArtifact => &mut self.equivocation,
+            ArtifactTag::RemoteDkgArtifact => &mut self.remote_dkg,
+            ArtifactTag::CanisterHttpArtifact => &mut self.canister_http,
+            ArtifactTag::QueryStatsArtifact => &mut self.query_stats,
+            ArtifactTag::XNetStreamSliceArtifact => &mut self.xnet_stream_slice,
+            ArtifactTag::RegistryDeltaArtifact => &mut self.registry_delta,
         }
     }
 }
@@ -0,0 +1,163 @@
+//! Persistence of in-progress artifact downloads across replica restarts.
+//!
+//! <h1>Overview</h1>
+//!
+//! Downloads of large artifacts, in particular state sync artifacts, may take
+//! a long time to complete. If the replica restarts while such a download is
+//! in progress, the download manager loses track of the artifact and has to
+//! wait for the peers to re-advertise it before the download can resume.
+//!
+//! To avoid this, the download manager periodically stores a snapshot of the
+//! in-progress state sync downloads in the artifact pool directory. Each entry
+//! consists of the advert, the peer whose quota is charged for the download,
+//! the peers that advertised the artifact, and the chunks that were in flight
+//! together with the peers they were requested from.
+//!
+//! On startup, the adverts are re-inserted for all peers that advertised the
+//! artifact, starting with the peers that were serving the download before the
+//! restart. The chunks that were already received are tracked by the chunk
+//! tracker of the artifact client (e.g., in the state sync scratchpad), so the
+//! download only fetches the missing chunks.
+
+use ic_types::{chunkable::ChunkId, p2p::GossipAdvert, NodeId};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::{Error, ErrorKind, Result},
+    path::Path,
+};
+
+/// The name of the file storing the download state in the artifact pool
+/// directory.
+pub(crate) const DOWNLOAD_STATE_FILE_NAME: &str = "gossip_download_state.bin";
+
+/// A snapshot of the in-progress downloads.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct DownloadState {
+    /// The in-progress downloads.
+    pub downloads: Vec<PersistedDownload>,
+}
+
+/// A single in-progress download.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct PersistedDownload {
+    /// The advert of the artifact being downloaded.
+    pub advert: GossipAdvert,
+    /// The ID of the node whose quota is charged for this download.
+    pub charged_peer: NodeId,
+    /// The peers that advertised the artifact.
+    pub advertisers: Vec<NodeId>,
+    /// The chunks that were requested, with the peers they were requested
+    /// from.
+    pub requested_chunks: Vec<(NodeId, ChunkId)>,
+}
+
+impl PersistedDownload {
+    /// The method returns the peers that advertised the artifact in the order
+    /// in which the advert should be restored.
+    ///
+    /// The charged peer comes first, followed by the peers that had chunk
+    /// requests in flight and finally all remaining advertisers.
+    pub(crate) fn peers_in_resume_order(&self) -> Vec<NodeId> {
+        let mut peers = vec![self.charged_peer];
+        let candidates = self
+            .requested_chunks
+            .iter()
+            .map(|(peer_id, _)| *peer_id)
+            .chain(self.advertisers.iter().copied());
+        for peer_id in candidates {
+            if !peers.contains(&peer_id) {
+                peers.push(peer_id);
+            }
+        }
+        peers
+    }
+}
+
+impl DownloadState {
+    /// The function loads the download state from the given path.
+    ///
+    /// Returns `Ok(None)` if no download state was stored.
+    pub(crate) fn load(path: &Path) -> Result<Option<Self>> {
+        match fs::read(path) {
+            Ok(bytes) => bincode::deserialize(&bytes)
+                .map(Some)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The method stores the download state at the given path.
+    ///
+    /// The state is written to a temporary file first, which is then renamed,
+    /// such that a crash while storing leaves the previous state intact. If
+    /// there are no downloads in progress, the stored state is removed.
+    pub(crate) fn store(&self, path: &Path) -> Result<()> {
+        if self.downloads.is_empty() {
+            return match fs::remove_file(path) {
+                Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            };
+        }
+        let bytes = bincode::serialize(self).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, bytes)?;
+        fs::rename(&tmp_path, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_test_utilities::types::ids::node_test_id;
+    use ic_types::{
+        artifact::{ArtifactAttribute, ArtifactId},
+        crypto::CryptoHash,
+    };
+
+    fn test_download(charged_peer: u64) -> PersistedDownload {
+        PersistedDownload {
+            advert: GossipAdvert {
+                artifact_id: ArtifactId::FileTreeSync(charged_peer.to_string()),
+                attribute: ArtifactAttribute::FileTreeSync(charged_peer.to_string()),
+                size: 0,
+                integrity_hash: CryptoHash(vec![]),
+            },
+            charged_peer: node_test_id(charged_peer),
+            advertisers: vec![node_test_id(3), node_test_id(2), node_test_id(1)],
+            requested_chunks: vec![
+                (node_test_id(2), ChunkId::from(0)),
+                (node_test_id(2), ChunkId::from(1)),
+            ],
+        }
+    }
+
+    /// The function tests that a stored download state is loaded unchanged.
+    #[test]
+    fn download_state_store_and_load() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join(DOWNLOAD_STATE_FILE_NAME);
+        assert_eq!(DownloadState::load(&path).unwrap(), None);
+
+        let state = DownloadState {
+            downloads: vec![test_download(1), test_download(2)],
+        };
+        state.store(&path).unwrap();
+        assert_eq!(DownloadState::load(&path).unwrap(), Some(state));
+
+        // Storing an empty state removes the stored state.
+        DownloadState::default().store(&path).unwrap();
+        assert_eq!(DownloadState::load(&path).unwrap(), None);
+    }
+
+    /// The function tests that the peers serving a download are restored
+    /// first.
+    #[test]
+    fn download_state_resume_order() {
+        assert_eq!(
+            test_download(1).peers_in_resume_order(),
+            vec![node_test_id(1), node_test_id(2), node_test_id(3)]
+        );
+    }
+}
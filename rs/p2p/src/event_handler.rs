@@ -68,13 +68,17 @@
 //!      PeerFlowQueueMap: A single flow being addressed by 1 thread.
 //! ```
 use crate::{
+    codec::decode_gossip_message,
     gossip_protocol::{
         Gossip, GossipChunk, GossipChunkRequest, GossipMessage, GossipRetransmissionRequest,
     },
+    ingress_rate_limiter::IngressRateLimiter,
     metrics::EventHandlerMetrics,
+    recently_seen_ingress::RecentlySeenIngress,
     P2PErrorCode, P2PResult,
 };
 use async_trait::async_trait;
+use ic_artifact_manager::artifact::IngressArtifact;
 use ic_base_thread::async_safe_block_on_await;
 use ic_interfaces::{
     artifact_manager::OnArtifactError,
@@ -84,14 +88,13 @@ use ic_interfaces::{
 };
 use ic_logger::{info, replica_logger::ReplicaLogger, trace};
 use ic_metrics::MetricsRegistry;
-use ic_protobuf::p2p::v1 as pb;
-use ic_protobuf::proxy::ProtoProxy;
 use ic_protobuf::registry::subnet::v1::GossipConfig;
 use ic_types::{
-    artifact::Artifact,
-    messages::SignedIngress,
+    artifact::{Artifact, ArtifactKind, IngressMessageId},
+    messages::{correlation_id, SignedIngress},
+    time::current_time,
     transport::{FlowId, TransportNotification, TransportPayload},
-    NodeId,
+    CanisterId, CountBytes, NodeId,
 };
 use ic_types::{p2p::GossipAdvert, transport};
 use std::{
@@ -120,7 +123,7 @@ use tokio::{
 };
 
 /// The trait for P2P event handler control, exposing methods to start and stop
-/// control, as well as add nodes.
+/// control, as well as add and remove nodes.
 #[async_trait]
 pub(crate) trait P2PEventHandlerControl: Send + Sync {
     /// The method starts the event processing loop, dispatching events to the
@@ -136,6 +139,13 @@ pub(crate) trait P2PEventHandlerControl: Send + Sync {
     /// before any network processing starts.
     fn add_node(&self, node_id: NodeId);
 
+    /// The method removes a peer node from the event handler.
+    ///
+    /// The queues of the peer are dropped, and messages that are still queued
+    /// are discarded. Subsequent messages from the peer are dropped until it
+    /// is added again.
+    fn remove_node(&self, node_id: NodeId);
+
     /// The method stops the event handler.
     ///
     /// This is a no-op call if the event handler has not been started.
@@ -163,6 +173,8 @@ enum FlowType {
 enum ManagementCommands<T> {
     /// Add peer variant.
     AddPeer(NodeId, Receiver<T>),
+    /// Remove peer variant.
+    RemovePeer(NodeId),
     /// Stop variant.
     Stop,
 }
@@ -179,7 +191,7 @@ type ManagementCommandSender<T> = CrossBeamSender<ManagementCommands<T>>;
 type ManagementCommandReceiver<T> = CrossBeamReceiver<ManagementCommands<T>>;
 
 /// A *Gossip* type with automatic reference counting.
-type GossipArc = Arc<
+pub(crate) type GossipArc = Arc<
     dyn Gossip<
             GossipAdvert = GossipAdvert,
             GossipChunkRequest = GossipChunkRequest,
@@ -333,6 +345,9 @@ impl<T: Send + 'static> PeerFlowQueueMap<T> {
                     ManagementCommands::AddPeer(node_id, receiver) => {
                         receive_map.push((node_id, receiver));
                     }
+                    ManagementCommands::RemovePeer(node_id) => {
+                        receive_map.retain(|(peer_id, _)| *peer_id != node_id);
+                    }
                     ManagementCommands::Stop => return P2PErrorCode::ChannelShutDown.into(),
                 },
                 Err(crossbeam_channel::TryRecvError::Empty) => return Ok(()),
@@ -355,6 +370,16 @@ impl<T: Send + 'static> PeerFlowQueueMap<T> {
                 .expect("Failed to send ManagementCommands::AddPeer command");
         }
     }
+
+    /// The method removes the node with the given node ID.
+    fn remove_node(&self, node_id: NodeId) {
+        let mut send_map = self.send_map.write().unwrap();
+        if send_map.remove(&node_id).is_some() {
+            self.management_command_sender
+                .send(ManagementCommands::RemovePeer(node_id))
+                .expect("Failed to send ManagementCommands::RemovePeer command");
+        }
+    }
 }
 
 /// The peer flow struct, which contains a flow for each flow type.
@@ -454,6 +479,17 @@ impl PeerFlows {
         }
     }
 
+    /// The method removes the node with the given node ID from the flows of
+    /// each flow type.
+    fn remove_node(&self, node_id: NodeId) {
+        self.advert.remove_node(node_id);
+        self.request.remove_node(node_id);
+        self.chunk.remove_node(node_id);
+        self.retransmission.remove_node(node_id);
+        self.transport.remove_node(node_id);
+        self.send_advert.remove_node(node_id);
+    }
+
     /// The method stops the flows for each flow type.
     fn stop(&self) {
         for flow_type in FlowType::iter() {
@@ -486,6 +522,10 @@ pub(crate) struct P2PEventHandlerImpl {
     channel_config: ChannelConfig,
     /// The peer flows.
     peer_flows: PeerFlows,
+    /// The recorder of the received messages, used to generate fuzzing
+    /// corpora.
+    #[cfg(fuzzing)]
+    corpus_recorder: Option<crate::fuzzing::CorpusRecorder>,
 }
 
 /// This constant specifies the expected maximum number of peers.
@@ -547,6 +587,8 @@ impl P2PEventHandlerImpl {
             metrics: EventHandlerMetrics::new(metrics_registry),
             channel_config: ChannelConfig::from(gossip_config),
             peer_flows: PeerFlows::new(rt_handle),
+            #[cfg(fuzzing)]
+            corpus_recorder: crate::fuzzing::CorpusRecorder::from_env(),
         };
         handler
             .peer_flows
@@ -568,6 +610,11 @@ impl P2PEventHandlerControl for P2PEventHandlerImpl {
         self.peer_flows.add_node(node_id, &self.channel_config);
     }
 
+    /// The method removes a node from the event handler.
+    fn remove_node(&self, node_id: NodeId) {
+        self.peer_flows.remove_node(node_id);
+    }
+
     /// The method stops the P2P event handler.
     fn stop(&self) {
         self.peer_flows.stop();
@@ -580,10 +627,12 @@ impl AsyncTransportEventHandler for P2PEventHandlerImpl {
     /// The method sends the given message on the flow associated with the given
     /// flow ID.
     async fn send_message(&self, flow: FlowId, message: TransportPayload) -> Result<(), SendError> {
-        let gossip_message = <pb::GossipMessage as ProtoProxy<GossipMessage>>::proxy_decode(
-            &message.0,
-        )
-        .map_err(|e| {
+        #[cfg(fuzzing)]
+        if let Some(recorder) = &self.corpus_recorder {
+            recorder.record(&message.0);
+        }
+        // The payload indicates the codec it was encoded with.
+        let gossip_message = decode_gossip_message(&message.0).map_err(|e| {
             trace!(self.log, "Deserialization failed {}", e);
             SendError::DeserializationFailed
         })?;
@@ -750,15 +799,27 @@ pub(crate) struct IngressEventHandlerImpl {
     c_gossip: GossipArc,
     /// The node ID.
     node_id: NodeId,
+    /// The ingress messages seen recently, shared with the download manager.
+    recently_seen_ingress: Arc<dyn RecentlySeenIngress>,
+    /// The per-sender rate limiter, if senders are rate limited.
+    rate_limiter: Option<IngressRateLimiter>,
 }
 
 impl IngressEventHandlerImpl {
     /// The function creates an `IngressEventHandlerImpl` instance.
-    pub fn new(ingress_throttle: IngressThrottler, c_gossip: GossipArc, node_id: NodeId) -> Self {
+    pub fn new(
+        ingress_throttle: IngressThrottler,
+        c_gossip: GossipArc,
+        node_id: NodeId,
+        recently_seen_ingress: Arc<dyn RecentlySeenIngress>,
+        rate_limiter: Option<IngressRateLimiter>,
+    ) -> Self {
         Self {
             ingress_throttler: ingress_throttle,
             c_gossip,
             node_id,
+            recently_seen_ingress,
+            rate_limiter,
         }
     }
 }
@@ -766,14 +827,57 @@ impl IngressEventHandlerImpl {
 /// `IngressEventHandlerImpl` implements the `IngressEventHandler` trait.
 impl IngressEventHandler for IngressEventHandlerImpl {
     /// The method is called when an ingress message is received.
+    ///
+    /// Messages that were seen recently, e.g. because the user submitted them
+    /// to another boundary node as well, and that are still in the ingress
+    /// pool are accepted without inserting them into the ingress pool again.
     fn on_ingress_message(
         &self,
         signed_ingress: SignedIngress,
     ) -> Result<(), OnArtifactError<Artifact>> {
-        if self.ingress_throttler.read().unwrap().exceeds_threshold() {
-            return Err(OnArtifactError::Throttled);
+        let message_id = IngressMessageId::from(&signed_ingress);
+        let span = tracing::debug_span!(
+            "ingress_submitted",
+            message_id = %message_id.message_id,
+            correlation_id = %correlation_id(&message_id.message_id),
+        );
+        let _enter = span.enter();
+        let integrity_hash = IngressArtifact::integrity_hash(signed_ingress.binary());
+        if self
+            .recently_seen_ingress
+            .is_duplicate(&message_id, &integrity_hash)
+        {
+            return Ok(());
+        }
+        {
+            let throttler = self.ingress_throttler.read().unwrap();
+            if throttler.exceeds_threshold()
+                || throttler.exceeds_canister_quota(
+                    &signed_ingress.canister_id(),
+                    signed_ingress.count_bytes(),
+                )
+            {
+                return Err(OnArtifactError::Throttled);
+            }
+        }
+        let sender = signed_ingress.sender();
+        let size_bytes = signed_ingress.count_bytes();
+        if let Some(rate_limiter) = &self.rate_limiter {
+            if !rate_limiter.try_admit(sender, size_bytes, std::time::Instant::now()) {
+                return Err(OnArtifactError::RateLimited);
+            }
         }
-        self.c_gossip.on_user_ingress(signed_ingress, self.node_id)
+        // Messages that are not accepted do not count towards the sender's
+        // rate limit.
+        if let Err(err) = self.c_gossip.on_user_ingress(signed_ingress, self.node_id) {
+            if let Some(rate_limiter) = &self.rate_limiter {
+                rate_limiter.refund(&sender, size_bytes);
+            }
+            return Err(err);
+        }
+        self.recently_seen_ingress
+            .insert(message_id, integrity_hash, current_time());
+        Ok(())
     }
 }
 
@@ -808,12 +912,19 @@ impl AdvertSubscriber for P2PEventHandlerImpl {
 #[allow(dead_code)]
 pub mod tests {
     use super::*;
+    use crate::codec::encode_gossip_message;
     use crate::download_prioritization::test::make_gossip_advert;
+    use crate::metrics::RecentlySeenIngressMetrics;
+    use crate::recently_seen_ingress::{RecentlySeenIngressImpl, RECENTLY_SEEN_INGRESS_CAPACITY};
     use ic_interfaces::ingress_pool::IngressPoolThrottler;
     use ic_metrics::MetricsRegistry;
-    use ic_test_utilities::{p2p::p2p_test_setup_logger, types::ids::node_test_id};
-    use ic_types::transport::FlowTag;
+    use ic_test_utilities::{
+        p2p::p2p_test_setup_logger,
+        types::{ids::node_test_id, messages::SignedIngressBuilder},
+    };
     use ic_types::transport::TransportStateChange;
+    use ic_types::transport::{FlowTag, WireCodecId};
+    use std::collections::BTreeSet;
     use tokio::time::Duration;
 
     struct TestThrottle();
@@ -821,6 +932,10 @@ pub mod tests {
         fn exceeds_threshold(&self) -> bool {
             false
         }
+
+        fn exceeds_canister_quota(&self, _canister_id: &CanisterId, _size_bytes: usize) -> bool {
+            false
+        }
     }
 
     type ItemCountCollector = Mutex<BTreeMap<NodeId, usize>>;
@@ -927,10 +1042,13 @@ pub mod tests {
         /// The method is called when a transport state change is received.
         fn on_transport_state_change(&self, transport_state_change: TransportStateChange) {
             let peer_id = match transport_state_change {
-                TransportStateChange::PeerFlowUp(x) => x,
-                TransportStateChange::PeerFlowDown(x) => x,
-            }
-            .peer_id;
+                TransportStateChange::PeerFlowUp(x) => x.peer_id,
+                TransportStateChange::PeerFlowDown(x) => x.peer_id,
+                TransportStateChange::SendQueueHighWatermark(x) => x.peer_id,
+                TransportStateChange::SendQueueLowWatermark(x) => x.peer_id,
+                TransportStateChange::PeerConnectionUp(peer_id) => peer_id,
+                TransportStateChange::PeerConnectionDown(peer_id) => peer_id,
+            };
             TestGossip::increment_or_set(&self.num_changes, peer_id);
         }
 
@@ -943,6 +1061,11 @@ pub mod tests {
         fn on_timer(&self, _event_handler: &Arc<dyn P2PEventHandlerControl>) {
             unimplemented!()
         }
+
+        /// The method is called when the registry records of the peers change.
+        fn on_registry_change(&self, _event_handler: &Arc<dyn P2PEventHandlerControl>) {
+            unimplemented!()
+        }
     }
 
     /// The function creates a new test event handler.
@@ -969,7 +1092,7 @@ pub mod tests {
     async fn send_advert(count: usize, handler: &P2PEventHandlerImpl, peer_id: NodeId) {
         for i in 0..count {
             let message = GossipMessage::Advert(make_gossip_advert(i as u64));
-            let message = TransportPayload(pb::GossipMessage::proxy_encode(message).unwrap());
+            let message = encode_gossip_message(WireCodecId::Protobuf, message).unwrap();
             let _ = handler
                 .send_message(
                     FlowId {
@@ -1032,7 +1155,7 @@ pub mod tests {
         handler.stop();
     }
 
-    /// Test the addition of nodes to the event handler.
+    /// Test the addition and removal of nodes to the event handler.
     #[tokio::test(flavor = "multi_thread")]
     async fn event_handler_add_remove_nodes() {
         let node_id = node_test_id(0);
@@ -1043,6 +1166,23 @@ pub mod tests {
         }
         handler.start(Arc::new(TestGossip::new(Duration::from_secs(0), node_id)));
         send_advert(100, &handler, node_id).await;
+
+        // Messages from removed nodes are rejected, until they are added again.
+        let removed_node = node_test_id(1);
+        handler.remove_node(removed_node);
+        let message = GossipMessage::Advert(make_gossip_advert(0));
+        let message = encode_gossip_message(WireCodecId::Protobuf, message).unwrap();
+        let flow = FlowId {
+            client_type: transport::TransportClientType::P2P,
+            peer_id: removed_node,
+            flow_tag: FlowTag::from(0),
+        };
+        assert!(matches!(
+            handler.send_message(flow, message.clone()).await,
+            Err(SendError::EndpointNotFound)
+        ));
+        handler.add_node(removed_node);
+        assert!(handler.send_message(flow, message).await.is_ok());
         handler.stop();
     }
 
@@ -1076,4 +1216,48 @@ pub mod tests {
             sleep(Duration::from_millis(1000)).await;
         }
     }
+
+    /// This function tests that a resubmitted ingress message is dropped while
+    /// it is in the ingress pool, and handed to gossip again once the pool
+    /// dropped it.
+    #[test]
+    fn ingress_event_handler_accepts_messages_dropped_from_the_pool() {
+        let node_id = node_test_id(0);
+        let gossip = Arc::new(TestGossip::new(Duration::from_secs(0), node_id));
+        let pool = Arc::new(Mutex::new(BTreeSet::new()));
+        let recently_seen_ingress = {
+            let pool = Arc::clone(&pool);
+            RecentlySeenIngressImpl::new(
+                RECENTLY_SEEN_INGRESS_CAPACITY,
+                Box::new(move |message_id| pool.lock().unwrap().contains(message_id)),
+                RecentlySeenIngressMetrics::new(&MetricsRegistry::new()),
+            )
+        };
+        let handler = IngressEventHandlerImpl::new(
+            Arc::new(RwLock::new(TestThrottle())),
+            gossip.clone(),
+            node_id,
+            Arc::new(recently_seen_ingress),
+            None,
+        );
+        let ingress = SignedIngressBuilder::new()
+            .expiry_time(current_time() + Duration::from_secs(60))
+            .build();
+        let message_id = IngressMessageId::from(&ingress);
+
+        handler.on_ingress_message(ingress.clone()).unwrap();
+        pool.lock().unwrap().insert(message_id.clone());
+        handler.on_ingress_message(ingress.clone()).unwrap();
+        assert_eq!(
+            TestGossip::get_node_flow_count(&gossip.num_ingress, node_id),
+            1
+        );
+
+        pool.lock().unwrap().remove(&message_id);
+        handler.on_ingress_message(ingress).unwrap();
+        assert_eq!(
+            TestGossip::get_node_flow_count(&gossip.num_ingress, node_id),
+            2
+        );
+    }
 }
@@ -0,0 +1,484 @@
+//! The fuzzing module exposes entry points for fuzzing the handling of gossip
+//! messages with cargo-fuzz.
+//!
+//! <h1>Overview</h1>
+//!
+//! Gossip messages are received from untrusted peers. They are decoded by the
+//! wire codec negotiated with the peer, converted from their protobuf
+//! representation, and handed to the gossip state machine, which checks them
+//! against the state of the node, e.g. drops adverts of unknown peers, looks
+//! up the artifacts of chunk requests, throttles retransmission requests, and
+//! decompresses chunks. Each entry point takes raw bytes and runs them through
+//! this path:
+//!
+//! * `fuzz_gossip_message`: The bytes are decoded as a gossip message payload,
+//!   with the wire codec the payload indicates.
+//! * `fuzz_advert`, `fuzz_chunk_request`, `fuzz_retransmission_request`: The
+//!   bytes are decoded as the protobuf of the respective message.
+//!
+//! Decoded messages are encoded again and must decode to the same message.
+//! They are then handled by a fresh gossip component, as if received from a
+//! peer on its subnet. The gossip component runs with an empty artifact
+//! manager and a transport that discards the messages sent by the node, so
+//! each input is handled independently of the previous ones.
+//!
+//! The module is compiled with `--cfg fuzzing`, which cargo-fuzz sets, and
+//! in the unit tests of this crate, which check that the entry points handle
+//! recorded and malformed messages. The fuzz targets are in the `fuzz`
+//! directory of this crate.
+//!
+//! <h1>Corpus</h1>
+//!
+//! A replica compiled with `--cfg fuzzing` records the gossip messages it
+//! receives if the environment variable `IC_P2P_FUZZ_CORPUS_DIR` is set. Each
+//! message is written to the corpus of `fuzz_gossip_message` as received, and
+//! its body to the corpus of the respective protobuf entry point. Running a
+//! testnet with such replicas generates a corpus of real traffic, which the
+//! fuzzer mutates into new inputs.
+
+use crate::{
+    codec::{decode_gossip_message, payload_codec, wire_codec},
+    event_handler::{GossipArc, P2PEventHandlerControl},
+    gossip_protocol::{
+        Gossip, GossipChunkRequest, GossipImpl, GossipMessage, GossipRetransmissionRequest,
+    },
+    metrics::RecentlySeenIngressMetrics,
+    recently_seen_ingress::{RecentlySeenIngressImpl, RECENTLY_SEEN_INGRESS_CAPACITY},
+};
+use ic_artifact_manager::manager::ArtifactManagerMaker;
+use ic_config::metrics::DEFAULT_P2P_MAX_PEER_LABELS;
+use ic_event_log::EventLog;
+use ic_interfaces::{
+    time_source::SysTimeSource,
+    transport::{AsyncTransportEventHandler, Transport},
+};
+use ic_logger::replica_logger::no_op_logger;
+use ic_metrics::MetricsRegistry;
+use ic_protobuf::p2p::v1 as pb;
+use ic_protobuf::proxy::ProtoProxy;
+use ic_protobuf::registry::node::v1::NodeRecord;
+use ic_registry_client::fake::FakeRegistryClient;
+use ic_test_utilities::{
+    p2p::{test_group_set_registry, P2P_SUBNET_ID_DEFAULT},
+    types::ids::{node_test_id, subnet_test_id},
+};
+use ic_types::{
+    malicious_strategies::MaliciousBehaviors,
+    p2p::GossipAdvert,
+    transport::{FlowTag, TransportClientType, TransportErrorCode, TransportPayload, WireCodecId},
+    NodeId, RegistryVersion,
+};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// The environment variable naming the directory of the recorded corpus.
+const CORPUS_DIR_ENV_VAR: &str = "IC_P2P_FUZZ_CORPUS_DIR";
+
+/// The number of nodes on the subnet of the fuzzed gossip component.
+const NUM_NODES: u64 = 2;
+
+/// The node running the fuzzed gossip component.
+const NODE: u64 = 0;
+
+/// The peer sending the fuzzed messages.
+const PEER: u64 = 1;
+
+/// The entry point for gossip messages. The given data is a payload as
+/// received from a peer, which indicates its wire codec.
+pub fn fuzz_gossip_message(data: &[u8]) {
+    let (codec, bytes) = match payload_codec(data) {
+        Ok((codec, bytes)) => (codec, bytes),
+        Err(_) => return,
+    };
+    let codec = wire_codec(codec);
+    let message = match codec.decode(bytes) {
+        Ok(message) => message,
+        Err(_) => return,
+    };
+    // Chunks are not checked for round trips, as the protobuf decoding
+    // converts their artifacts to the encoding version of this replica.
+    if !matches!(message, GossipMessage::Chunk(_)) {
+        let encoded = codec
+            .encode(message.clone())
+            .expect("A decoded message must be encodable");
+        assert_eq!(
+            codec
+                .decode(&encoded)
+                .expect("An encoded message must be decodable"),
+            message
+        );
+    }
+    FuzzedGossip::new().handle(message);
+}
+
+/// The entry point for protobuf-encoded adverts.
+pub fn fuzz_advert(data: &[u8]) {
+    if let Some(advert) = decode_proxy::<pb::GossipAdvert, GossipAdvert>(data) {
+        FuzzedGossip::new().handle(GossipMessage::Advert(advert));
+    }
+}
+
+/// The entry point for protobuf-encoded chunk requests.
+pub fn fuzz_chunk_request(data: &[u8]) {
+    if let Some(request) = decode_proxy::<pb::GossipChunkRequest, GossipChunkRequest>(data) {
+        FuzzedGossip::new().handle(GossipMessage::ChunkRequest(request));
+    }
+}
+
+/// The entry point for protobuf-encoded retransmission requests.
+pub fn fuzz_retransmission_request(data: &[u8]) {
+    if let Some(request) =
+        decode_proxy::<pb::GossipRetransmissionRequest, GossipRetransmissionRequest>(data)
+    {
+        FuzzedGossip::new().handle(GossipMessage::RetransmissionRequest(request));
+    }
+}
+
+/// The function decodes the given data via the protobuf proxy `M`, checks
+/// that the decoded message survives a round trip, and returns it.
+fn decode_proxy<M, T>(data: &[u8]) -> Option<T>
+where
+    M: ProtoProxy<T>,
+    T: Clone + Debug + PartialEq,
+{
+    let message = M::proxy_decode(data).ok()?;
+    let encoded = M::proxy_encode(message.clone()).expect("A decoded message must be encodable");
+    assert_eq!(
+        M::proxy_decode(&encoded).expect("An encoded message must be decodable"),
+        message
+    );
+    Some(message)
+}
+
+/// A gossip component on a subnet of `NUM_NODES` nodes, which handles the
+/// fuzzed messages as if they were received from `PEER`.
+struct FuzzedGossip {
+    gossip: GossipImpl,
+    transport: Arc<FuzzedTransport>,
+}
+
+impl FuzzedGossip {
+    /// The constructor creates a gossip component that knows all nodes of
+    /// its subnet as peers.
+    fn new() -> Self {
+        let subnet_id = subnet_test_id(P2P_SUBNET_ID_DEFAULT);
+        // The ports of the nodes are not used by the fuzzed transport.
+        let data_provider =
+            test_group_set_registry(subnet_id, Arc::new(vec![0; NUM_NODES as usize]));
+        let registry_client = Arc::new(FakeRegistryClient::new(data_provider));
+        registry_client.update_to_latest_version();
+        let metrics_registry = MetricsRegistry::new();
+        let transport = Arc::new(FuzzedTransport::default());
+        let gossip = GossipImpl::new(
+            node_test_id(NODE),
+            subnet_id,
+            registry_client,
+            ArtifactManagerMaker::new(Arc::new(SysTimeSource::new())).finish(),
+            Arc::clone(&transport) as Arc<_>,
+            Arc::new(FuzzedEventHandler),
+            vec![FlowTag::from(0)],
+            BTreeMap::new(),
+            None,
+            // The artifact manager has no ingress pool.
+            Arc::new(RecentlySeenIngressImpl::new(
+                RECENTLY_SEEN_INGRESS_CAPACITY,
+                Box::new(|_| false),
+                RecentlySeenIngressMetrics::new(&metrics_registry),
+            )),
+            None,
+            true,
+            None,
+            DEFAULT_P2P_MAX_PEER_LABELS,
+            no_op_logger(),
+            EventLog::disabled(),
+            &metrics_registry,
+            MaliciousBehaviors::default(),
+        );
+        Self { gossip, transport }
+    }
+
+    /// The method hands the given message to the gossip component, as the
+    /// event handler does for the messages received from a peer.
+    fn handle(&self, message: GossipMessage) {
+        let peer_id = node_test_id(PEER);
+        match message {
+            GossipMessage::Advert(advert) => self.gossip.on_advert(advert, peer_id),
+            GossipMessage::ChunkRequest(request) => self.gossip.on_chunk_request(request, peer_id),
+            GossipMessage::Chunk(chunk) => self.gossip.on_chunk(chunk, peer_id),
+            GossipMessage::RetransmissionRequest(request) => {
+                self.gossip.on_retransmission_request(request, peer_id)
+            }
+        }
+    }
+}
+
+/// A transport that discards the messages sent by the fuzzed gossip
+/// component and only counts them.
+#[derive(Default)]
+struct FuzzedTransport {
+    sent_messages: AtomicUsize,
+}
+
+impl Transport for FuzzedTransport {
+    fn register_client(
+        &self,
+        _client_type: TransportClientType,
+        _event_handler: Arc<dyn AsyncTransportEventHandler>,
+    ) -> Result<(), TransportErrorCode> {
+        Ok(())
+    }
+
+    fn start_connections(
+        &self,
+        _client_type: TransportClientType,
+        _peer: &NodeId,
+        _node_record: &NodeRecord,
+        _registry_version: RegistryVersion,
+    ) -> Result<(), TransportErrorCode> {
+        Ok(())
+    }
+
+    fn stop_connections(
+        &self,
+        _client_type: TransportClientType,
+        _peer_id: &NodeId,
+        _registry_version: RegistryVersion,
+    ) -> Result<(), TransportErrorCode> {
+        Ok(())
+    }
+
+    fn send(
+        &self,
+        _client_type: TransportClientType,
+        _peer_id: &NodeId,
+        _flow_tag: FlowTag,
+        _message: TransportPayload,
+    ) -> Result<(), TransportErrorCode> {
+        self.sent_messages.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn queue_len(
+        &self,
+        _client_type: TransportClientType,
+        _peer_id: &NodeId,
+        _flow_tag: FlowTag,
+    ) -> Result<usize, TransportErrorCode> {
+        Ok(0)
+    }
+
+    fn wire_codec(
+        &self,
+        _client_type: TransportClientType,
+        _peer_id: &NodeId,
+    ) -> Result<WireCodecId, TransportErrorCode> {
+        Ok(WireCodecId::Protobuf)
+    }
+
+    fn clear_send_queues(&self, _client_type: TransportClientType, _peer_id: &NodeId) {}
+
+    fn clear_send_queue(
+        &self,
+        _client_type: TransportClientType,
+        _peer_id: &NodeId,
+        _flow_tag: FlowTag,
+    ) {
+    }
+}
+
+/// An event handler that ignores the peers added by the fuzzed gossip
+/// component, as the fuzzed messages are handed to gossip directly.
+struct FuzzedEventHandler;
+
+impl P2PEventHandlerControl for FuzzedEventHandler {
+    fn start(&self, _gossip_arc: GossipArc) {}
+
+    fn add_node(&self, _node_id: NodeId) {}
+
+    fn remove_node(&self, _node_id: NodeId) {}
+
+    fn stop(&self) {}
+}
+
+/// The corpus recorder writes the received gossip messages to the corpora of
+/// the entry points.
+pub(crate) struct CorpusRecorder {
+    /// The directory containing the corpus of each entry point.
+    dir: PathBuf,
+}
+
+impl CorpusRecorder {
+    /// The function returns a recorder writing to the directory given by the
+    /// environment variable, if it is set.
+    pub(crate) fn from_env() -> Option<Self> {
+        std::env::var_os(CORPUS_DIR_ENV_VAR).map(|dir| Self { dir: dir.into() })
+    }
+
+    /// The method records the given payload of a gossip message.
+    pub(crate) fn record(&self, bytes: &[u8]) {
+        self.write("gossip_message", bytes);
+
+        let message = match decode_gossip_message(bytes) {
+            Ok(message) => message,
+            Err(_) => return,
+        };
+        match message {
+            GossipMessage::Advert(advert) => {
+                self.write_proxy::<pb::GossipAdvert, _>("advert", advert)
+            }
+            GossipMessage::ChunkRequest(request) => {
+                self.write_proxy::<pb::GossipChunkRequest, _>("chunk_request", request)
+            }
+            GossipMessage::RetransmissionRequest(request) => self
+                .write_proxy::<pb::GossipRetransmissionRequest, _>(
+                    "retransmission_request",
+                    request,
+                ),
+            GossipMessage::Chunk(_) => {}
+        }
+    }
+
+    /// The method writes the protobuf encoding of the given message to the
+    /// corpus of the given entry point.
+    fn write_proxy<M: ProtoProxy<T>, T>(&self, target: &str, message: T) {
+        if let Ok(bytes) = M::proxy_encode(message) {
+            self.write(target, &bytes);
+        }
+    }
+
+    /// The method writes the given entry to the corpus of the given entry
+    /// point. Entries are named after their hash, so that recording the same
+    /// message twice yields a single entry.
+    fn write(&self, target: &str, entry: &[u8]) {
+        let mut hasher = DefaultHasher::new();
+        entry.hash(&mut hasher);
+        let dir = self.dir.join(target);
+        // Recording is best effort: failures must not affect the replica.
+        if std::fs::create_dir_all(&dir).is_ok() {
+            let _ = std::fs::write(dir.join(format!("{:016x}", hasher.finish())), entry);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::encode_gossip_message;
+    use crate::download_prioritization::test::make_gossip_advert;
+    use crate::gossip_protocol::GossipChunk;
+    use ic_types::{
+        artifact::{ArtifactFilter, ArtifactId},
+        artifact_encoding::ARTIFACT_ENCODING_VERSION,
+        chunkable::{ArtifactChunk, ArtifactChunkData, ChunkId},
+    };
+
+    const WIRE_CODECS: [WireCodecId; 3] = [
+        WireCodecId::Protobuf,
+        WireCodecId::Cbor,
+        WireCodecId::Bincode,
+    ];
+
+    fn chunk_request() -> GossipChunkRequest {
+        GossipChunkRequest {
+            artifact_id: ArtifactId::FileTreeSync("file".to_string()),
+            chunk_id: ChunkId::from(0),
+            accepts_compressed_chunks: true,
+            artifact_encoding_version: ARTIFACT_ENCODING_VERSION,
+        }
+    }
+
+    fn test_messages() -> Vec<GossipMessage> {
+        vec![
+            GossipMessage::Advert(make_gossip_advert(1)),
+            GossipMessage::ChunkRequest(chunk_request()),
+            GossipMessage::RetransmissionRequest(GossipRetransmissionRequest {
+                filter: ArtifactFilter::default(),
+            }),
+            // A chunk whose compressed data is garbage.
+            GossipMessage::Chunk(GossipChunk {
+                artifact_id: ArtifactId::FileTreeSync("file".to_string()),
+                chunk_id: ChunkId::from(0),
+                artifact_chunk: Ok(ArtifactChunk {
+                    chunk_id: ChunkId::from(0),
+                    witness: vec![],
+                    artifact_chunk_data: ArtifactChunkData::SemiStructuredChunkData(vec![7; 64]),
+                }),
+                compressed: true,
+                artifact_encoding_version: ARTIFACT_ENCODING_VERSION,
+            }),
+        ]
+    }
+
+    fn encoded_test_messages() -> Vec<Vec<u8>> {
+        WIRE_CODECS
+            .iter()
+            .flat_map(|codec| {
+                test_messages()
+                    .into_iter()
+                    .map(move |message| encode_gossip_message(*codec, message).unwrap().0)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn recorded_messages_are_handled() {
+        let dir = tempfile::tempdir().unwrap();
+        let recorder = CorpusRecorder {
+            dir: dir.path().to_path_buf(),
+        };
+        for payload in encoded_test_messages() {
+            recorder.record(&payload);
+        }
+
+        let entry_points: [(&str, fn(&[u8])); 4] = [
+            ("gossip_message", fuzz_gossip_message),
+            ("advert", fuzz_advert),
+            ("chunk_request", fuzz_chunk_request),
+            ("retransmission_request", fuzz_retransmission_request),
+        ];
+        for (target, entry_point) in entry_points.iter() {
+            let entries: Vec<_> = std::fs::read_dir(dir.path().join(target))
+                .unwrap()
+                .map(|entry| std::fs::read(entry.unwrap().path()).unwrap())
+                .collect();
+            assert!(!entries.is_empty(), "No corpus for {}", target);
+            for entry in entries {
+                entry_point(&entry);
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_messages_are_handled() {
+        for payload in encoded_test_messages() {
+            for len in 0..payload.len() {
+                fuzz_gossip_message(&payload[..len]);
+            }
+            for (i, byte) in payload.iter().enumerate() {
+                let mut flipped = payload.clone();
+                flipped[i] = !byte;
+                fuzz_gossip_message(&flipped);
+            }
+        }
+    }
+
+    #[test]
+    fn messages_reach_the_gossip_component() {
+        // The peer is known, so its request for an artifact the node does not
+        // have is answered with a chunk that reports the artifact missing.
+        let fuzzed_gossip = FuzzedGossip::new();
+        fuzzed_gossip.handle(GossipMessage::ChunkRequest(chunk_request()));
+        assert_eq!(
+            fuzzed_gossip
+                .transport
+                .sent_messages
+                .load(Ordering::Relaxed),
+            1
+        );
+    }
+}
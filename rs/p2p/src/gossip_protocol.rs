@@ -52,15 +52,20 @@
 //! the current height.
 
 use crate::{
-    download_management::{DownloadManager, DownloadManagerImpl},
+    chunk_compression::{compress_chunk, decompress_chunk},
+    download_management::{DownloadManager, DownloadManagerImpl, RegistryChanges},
     event_handler::P2PEventHandlerControl,
-    metrics::GossipMetrics,
+    metrics::{ChunkCompressionMetrics, GossipMetrics},
+    recently_seen_ingress::RecentlySeenIngress,
     use_gossip_malicious_behavior_on_chunk_request,
     utils::FlowMapper,
     P2PError, P2PErrorCode, P2PResult,
 };
 use ic_artifact_manager::artifact::IngressArtifact;
+use ic_event_log::EventLog;
 use ic_interfaces::artifact_manager::{ArtifactManager, OnArtifactError};
+use ic_interfaces::consensus_pool::ConsensusPoolCache;
+use ic_interfaces::p2p::{PeerConnectivity, PeerConnectivityReader};
 use ic_interfaces::registry::RegistryClient;
 use ic_interfaces::transport::Transport;
 use ic_logger::{info, replica_logger::ReplicaLogger, warn};
@@ -69,19 +74,35 @@ use ic_protobuf::p2p::v1 as pb;
 use ic_protobuf::p2p::v1::gossip_chunk::Response;
 use ic_protobuf::p2p::v1::gossip_message::Body;
 use ic_protobuf::proxy::{try_from_option_field, ProxyDecodeError, ProxyDecodeError::*};
+use ic_protobuf::registry::node::v1::NodeRecord;
 use ic_types::{
     artifact::{Artifact, ArtifactFilter, ArtifactId, ArtifactKind},
-    chunkable::{ArtifactChunk, ArtifactChunkData, ChunkId},
-    malicious_flags::MaliciousFlags,
+    artifact_encoding::{ArtifactEncodingVersion, ArtifactEnvelope, ARTIFACT_ENCODING_VERSION},
+    chunkable::{
+        multi_chunk, ArtifactChunk, ArtifactChunkData, ChunkId, ChunkableArtifact,
+        CHUNKID_UNIT_CHUNK,
+    },
+    malicious_strategies::{
+        ArtifactNotFound, CorruptChunks, DropChunkRequests, MaliciousBehaviors, SendManyChunks,
+    },
     messages::SignedIngress,
     p2p::GossipAdvert,
     transport::{FlowTag, TransportError, TransportNotification, TransportStateChange},
     NodeId, SubnetId,
 };
 
+#[cfg(feature = "malicious_code")]
+use crate::malicious_gossip;
+#[cfg(feature = "malicious_code")]
+use ic_types::malicious_strategies::DelayAdverts;
+
 use bincode::{deserialize, serialize};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
 use std::convert::{TryFrom, TryInto};
-use std::sync::Arc;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 /// The main *Gossip* trait, specifying the P2P gossip functionality.
 pub(crate) trait Gossip {
@@ -155,23 +176,34 @@ pub(crate) trait Gossip {
     /// - It checks each peer for request timeouts and advert download
     /// eligibility.
     ///
+    /// - It sends the adverts held back by malicious behaviors.
+    ///
     /// In short, the method is a catch-all for a periodic and
     /// holistic refresh of IC state.
     fn on_timer(&self, event_handler: &Arc<dyn P2PEventHandlerControl>);
+
+    /// The method is called when the registry records of the peers change or
+    /// consensus finalizes a new height, so that peers are added and removed
+    /// without waiting for the next periodic refresh.
+    fn on_registry_change(&self, event_handler: &Arc<dyn P2PEventHandlerControl>);
 }
 
 /// A request for an artifact sent to the peer.
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub(crate) struct GossipChunkRequest {
     /// The artifact ID.
     pub artifact_id: ArtifactId,
     /// The chunk ID.
     pub chunk_id: ChunkId,
+    /// True if the requester accepts compressed chunks.
+    pub accepts_compressed_chunks: bool,
+    /// The latest artifact encoding version the requester understands.
+    pub artifact_encoding_version: ArtifactEncodingVersion,
 }
 
 /// A re-transmission request. A filter is used to restrict the set of
 /// adverts that are to be returned as a response to this request.
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub(crate) struct GossipRetransmissionRequest {
     /// The artifact filter used to restrict the set of returned adverts.
     pub filter: ArtifactFilter,
@@ -179,7 +211,7 @@ pub(crate) struct GossipRetransmissionRequest {
 
 /// A *Gossip* chunk, identified by its artifact ID and chunk ID.
 /// It contains the actual chunk data in an artifact chunk.
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub(crate) struct GossipChunk {
     /// The artifact ID.
     pub artifact_id: ArtifactId,
@@ -187,12 +219,18 @@ pub(crate) struct GossipChunk {
     pub chunk_id: ChunkId,
     /// The artifact chunk, encapsulated in a `P2PResult`.
     pub artifact_chunk: P2PResult<ArtifactChunk>,
+    /// True if the data of the artifact chunk is compressed.
+    pub compressed: bool,
+    /// The artifact encoding version of the artifact of the chunk on the wire,
+    /// i.e., the older of the one the requester understands and the one of
+    /// this replica.
+    pub artifact_encoding_version: ArtifactEncodingVersion,
 }
 
 /// This is the message exchanged on the wire with other peers.  This
 /// enum is private to the gossip layer because lower layers like
 /// *Transport* do not need to interpret the content.
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[allow(clippy::large_enum_variant)]
 pub(crate) enum GossipMessage {
     /// The advert variant.
@@ -219,15 +257,84 @@ impl From<&GossipMessage> for FlowTag {
 /// The canonical implementation of the `GossipMessage` trait.
 pub(crate) struct GossipImpl {
     /// The download manager used to initiate and track downloads.
-    download_manager: DownloadManagerImpl,
+    download_manager: Arc<DownloadManagerImpl>,
     /// The artifact manager used to handle received artifacts.
     artifact_manager: Arc<dyn ArtifactManager>,
     /// The replica logger.
     log: ReplicaLogger,
     /// The *Gossip* metrics.
     metrics: GossipMetrics,
-    /// Flags for malicious behavior used in testing.
-    malicious_flags: MaliciousFlags,
+    /// The chunk compression metrics.
+    compression_metrics: ChunkCompressionMetrics,
+    /// Whether chunks are compressed for the peers that accept compressed
+    /// chunks.
+    compress_state_sync_chunks: bool,
+    /// The malicious behaviors of gossip, used in testing.
+    malicious_behaviors: MaliciousBehaviors,
+    /// Sends the adverts held back by the `DelayAdverts` behavior.
+    #[cfg(feature = "malicious_code")]
+    advert_delayer: Option<malicious_gossip::AdvertDelayer>,
+    /// The recently served artifacts that are downloaded as multiple chunks,
+    /// in their encoded form.
+    encoded_artifacts: EncodedArtifactCache,
+}
+
+/// The maximum total size in bytes of the encoded artifacts kept to serve
+/// their chunks.
+const MAX_ENCODED_ARTIFACT_CACHE_BYTES: usize = 128 * 1024 * 1024;
+
+/// A cache of the encoded form of the artifacts whose chunks are served, so
+/// that an artifact downloaded as multiple chunks is encoded once rather than
+/// for every chunk. The cache holds at most
+/// `MAX_ENCODED_ARTIFACT_CACHE_BYTES` bytes and evicts the least recently
+/// added artifacts first.
+#[derive(Default)]
+struct EncodedArtifactCache {
+    entries: Mutex<VecDeque<(ArtifactId, Arc<ArtifactEnvelope>)>>,
+}
+
+impl EncodedArtifactCache {
+    /// Returns the encoded artifact with the given ID in the given version,
+    /// encoding the artifact returned by `get_artifact` if it is not cached.
+    fn get_or_encode<'a>(
+        &self,
+        id: &ArtifactId,
+        version: ArtifactEncodingVersion,
+        get_artifact: impl FnOnce() -> Option<Box<dyn ChunkableArtifact + 'a>>,
+    ) -> Option<Arc<ArtifactEnvelope>> {
+        if let Some(envelope) = self
+            .entries
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(cached_id, envelope)| cached_id == id && envelope.version == version)
+            .map(|(_, envelope)| envelope.clone())
+        {
+            return Some(envelope);
+        }
+
+        // The artifact is encoded outside of the lock, so that serving other
+        // chunks is not held up by it.
+        let envelope = Arc::new(get_artifact()?.encode(version)?);
+        if envelope.encoded.len() > MAX_ENCODED_ARTIFACT_CACHE_BYTES {
+            return Some(envelope);
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if !entries
+            .iter()
+            .any(|(cached_id, cached)| cached_id == id && cached.version == version)
+        {
+            entries.push_back((id.clone(), envelope.clone()));
+        }
+        let mut total_bytes: usize = entries.iter().map(|(_, e)| e.encoded.len()).sum();
+        while total_bytes > MAX_ENCODED_ARTIFACT_CACHE_BYTES {
+            match entries.pop_front() {
+                Some((_, evicted)) => total_bytes -= evicted.encoded.len(),
+                None => break,
+            }
+        }
+        Some(envelope)
+    }
 }
 
 impl GossipImpl {
@@ -245,9 +352,17 @@ impl GossipImpl {
         transport: Arc<dyn Transport>,
         event_handler: Arc<dyn P2PEventHandlerControl>,
         flow_tags: Vec<FlowTag>,
+        static_node_records: BTreeMap<NodeId, NodeRecord>,
+        download_state_path: Option<PathBuf>,
+        recently_seen_ingress: Arc<dyn RecentlySeenIngress>,
+        xnet_pull_interval: Option<Duration>,
+        compress_state_sync_chunks: bool,
+        consensus_pool_cache: Option<Arc<dyn ConsensusPoolCache>>,
+        max_peer_metric_labels: usize,
         log: ReplicaLogger,
+        event_log: EventLog,
         metrics_registry: &MetricsRegistry,
-        malicious_flags: MaliciousFlags,
+        malicious_behaviors: MaliciousBehaviors,
     ) -> Self {
         let download_manager = DownloadManagerImpl::new(
             node_id,
@@ -257,49 +372,76 @@ impl GossipImpl {
             transport.clone(),
             event_handler,
             Arc::new(FlowMapper::new(flow_tags)),
+            static_node_records,
+            download_state_path,
+            recently_seen_ingress,
+            xnet_pull_interval,
+            compress_state_sync_chunks,
+            consensus_pool_cache,
+            max_peer_metric_labels,
             log.clone(),
+            event_log,
             metrics_registry,
         );
+        let download_manager = Arc::new(download_manager);
         GossipImpl {
-            malicious_flags,
+            #[cfg(feature = "malicious_code")]
+            advert_delayer: malicious_behaviors.get::<DelayAdverts>().map(
+                |DelayAdverts { delay }| {
+                    malicious_gossip::AdvertDelayer::new(*delay, Arc::clone(&download_manager))
+                },
+            ),
+            malicious_behaviors,
+            encoded_artifacts: EncodedArtifactCache::default(),
             download_manager,
             artifact_manager,
             log,
             metrics: GossipMetrics::new(metrics_registry),
+            compression_metrics: ChunkCompressionMetrics::new(metrics_registry),
+            compress_state_sync_chunks,
         }
     }
 
     /// The method returns the artifact chunk matching the given chunk request
     /// (if available).
+    ///
+    /// The chunks of artifacts downloaded as multiple chunks are cut from the
+    /// encoded artifact kept in the encoded artifact cache.
     fn serve_chunk(&self, gossip_request: &GossipChunkRequest) -> P2PResult<ArtifactChunk> {
-        self.artifact_manager
-            .get_validated_by_identifier(&gossip_request.artifact_id)
-            .ok_or_else(|| {
-                self.metrics.chunk_req_not_found.inc();
-                P2PError {
-                    p2p_error_code: P2PErrorCode::NotFound,
-                }
-            })?
-            .get_chunk(gossip_request.chunk_id)
-            .ok_or_else(|| {
-                self.metrics.chunk_req_not_found.inc();
-                P2PError {
-                    p2p_error_code: P2PErrorCode::NotFound,
-                }
-            })
+        let id = &gossip_request.artifact_id;
+        let version = served_encoding_version(gossip_request.artifact_encoding_version);
+        let is_multi_chunk = gossip_request.chunk_id != ChunkId::from(CHUNKID_UNIT_CHUNK)
+            && !matches!(id, ArtifactId::StateSync(_) | ArtifactId::FileTreeSync(_));
+        let chunk = if is_multi_chunk {
+            self.encoded_artifacts
+                .get_or_encode(id, version, || {
+                    self.artifact_manager.get_validated_by_identifier(id)
+                })
+                .and_then(|envelope| multi_chunk(&envelope, gossip_request.chunk_id))
+        } else {
+            self.artifact_manager
+                .get_validated_by_identifier(id)
+                .and_then(|artifact| artifact.get_versioned_chunk(gossip_request.chunk_id, version))
+        };
+        chunk.ok_or_else(|| {
+            self.metrics.chunk_req_not_found.inc();
+            P2PError {
+                p2p_error_code: P2PErrorCode::NotFound,
+            }
+        })
     }
 
     /// The method reacts in a malicious way when receiving a chunk
     /// request from a certain peer.
     ///
-    /// The malicious flags define the actual behavior, which may
+    /// The malicious behaviors define the actual behavior, which may
     /// either drop the request, respond that the artifact could not
     /// be found, sending too many artifacts back, or sending invalid
     /// artifacts.
     fn malicious_behavior_on_chunk_request(&self, gossip_chunk: GossipChunk, node_id: NodeId) {
-        if self.malicious_flags.maliciously_gossip_drop_requests {
+        if self.malicious_behaviors.contains::<DropChunkRequests>() {
             warn!(self.log, "Malicious behavior: dropping requests");
-        } else if self.malicious_flags.maliciously_gossip_artifact_not_found {
+        } else if self.malicious_behaviors.contains::<ArtifactNotFound>() {
             warn!(self.log, "Malicious behavior: artifact not found");
             let chunk_not_found = GossipChunk {
                 artifact_id: gossip_chunk.artifact_id,
@@ -307,19 +449,18 @@ impl GossipImpl {
                 artifact_chunk: Err(P2PError {
                     p2p_error_code: P2PErrorCode::NotFound,
                 }),
+                compressed: false,
+                artifact_encoding_version: gossip_chunk.artifact_encoding_version,
             };
             self.download_manager
                 .send_chunk_to_peer(chunk_not_found, node_id);
-        } else if self.malicious_flags.maliciously_gossip_send_many_artifacts {
+        } else if self.malicious_behaviors.contains::<SendManyChunks>() {
             warn!(self.log, "Malicious behavior: sending too many artifacts");
             for _n in 1..10000 {
                 self.download_manager
                     .send_chunk_to_peer(gossip_chunk.clone(), node_id);
             }
-        } else if self
-            .malicious_flags
-            .maliciously_gossip_send_invalid_artifacts
-        {
+        } else if self.malicious_behaviors.contains::<CorruptChunks>() {
             warn!(self.log, "Malicious behavior: sending invalid artifacts");
             let artifact_id = gossip_chunk.artifact_id;
             let chunk_id = gossip_chunk.chunk_id;
@@ -333,6 +474,8 @@ impl GossipImpl {
                 artifact_id,
                 chunk_id,
                 artifact_chunk,
+                compressed: false,
+                artifact_encoding_version: gossip_chunk.artifact_encoding_version,
             };
             self.download_manager
                 .send_chunk_to_peer(invalid_chunk, node_id);
@@ -340,6 +483,20 @@ impl GossipImpl {
             warn!(self.log, "Malicious behavior: This should never happen!");
         }
     }
+
+    /// The method subscribes to the changes that are to be passed to
+    /// `on_registry_change()`.
+    pub(crate) fn registry_changes(&self) -> RegistryChanges {
+        self.download_manager.registry_changes()
+    }
+}
+
+impl PeerConnectivityReader for GossipImpl {
+    /// The method returns the connectivity to the peers tracked by the
+    /// download manager.
+    fn peer_connectivity(&self) -> PeerConnectivity {
+        self.download_manager.peer_connectivity()
+    }
 }
 
 /// Canonical Implementation for the *Gossip* trait.
@@ -376,6 +533,15 @@ impl Gossip for GossipImpl {
     fn on_chunk_request(&self, gossip_request: GossipChunkRequest, node_id: NodeId) {
         let start = std::time::Instant::now();
         let artifact_chunk = self.serve_chunk(&gossip_request);
+        let (artifact_chunk, compressed) = match artifact_chunk {
+            Ok(chunk)
+                if self.compress_state_sync_chunks && gossip_request.accepts_compressed_chunks =>
+            {
+                let (chunk, compressed) = compress_chunk(chunk, &self.compression_metrics);
+                (Ok(chunk), compressed)
+            }
+            artifact_chunk => (artifact_chunk, false),
+        };
         self.metrics
             .op_duration
             .with_label_values(&["serve_chunk"])
@@ -384,6 +550,10 @@ impl Gossip for GossipImpl {
             artifact_id: gossip_request.artifact_id.clone(),
             chunk_id: gossip_request.chunk_id,
             artifact_chunk,
+            compressed,
+            artifact_encoding_version: served_encoding_version(
+                gossip_request.artifact_encoding_version,
+            ),
         };
         use_gossip_malicious_behavior_on_chunk_request!(
             self,
@@ -397,7 +567,23 @@ impl Gossip for GossipImpl {
 
     /// The method adds the given chunk to the corresponding artifact
     /// under construction.
-    fn on_chunk(&self, gossip_chunk: GossipChunk, peer_id: NodeId) {
+    fn on_chunk(&self, mut gossip_chunk: GossipChunk, peer_id: NodeId) {
+        if gossip_chunk.compressed {
+            let compression_metrics = &self.compression_metrics;
+            let log = &self.log;
+            gossip_chunk.artifact_chunk = gossip_chunk.artifact_chunk.and_then(|chunk| {
+                decompress_chunk(chunk, compression_metrics).map_err(|err| {
+                    warn!(
+                        log,
+                        "Failed to decompress chunk from peer {:?}: {}", peer_id, err
+                    );
+                    P2PError {
+                        p2p_error_code: P2PErrorCode::Failed,
+                    }
+                })
+            });
+            gossip_chunk.compressed = false;
+        }
         self.download_manager.on_chunk(gossip_chunk, peer_id);
         let _ = self.download_manager.download_next(peer_id);
     }
@@ -423,6 +609,14 @@ impl Gossip for GossipImpl {
 
     /// The method broadcasts the given advert to other peers.
     fn broadcast_advert(&self, advert: GossipAdvert) {
+        #[cfg(feature = "malicious_code")]
+        {
+            if let Some(advert_delayer) = &self.advert_delayer {
+                warn!(self.log, "Malicious behavior: delaying advert");
+                advert_delayer.delay(advert);
+                return;
+            }
+        }
         self.download_manager.send_advert_to_peers(advert);
     }
 
@@ -448,13 +642,19 @@ impl Gossip for GossipImpl {
             self.log,
             "Transport state change: {:?}", transport_state_change
         );
+        // All flows with a peer share a connection, so the download manager
+        // only needs to react to the connection transitions.
         match transport_state_change {
-            TransportStateChange::PeerFlowDown(info) => {
-                self.download_manager.peer_connection_down(info.peer_id)
+            TransportStateChange::PeerConnectionDown(peer_id) => {
+                self.download_manager.peer_connection_down(peer_id)
             }
-            TransportStateChange::PeerFlowUp(info) => {
-                self.download_manager.peer_connection_up(info.peer_id)
+            TransportStateChange::PeerConnectionUp(peer_id) => {
+                self.download_manager.peer_connection_up(peer_id)
             }
+            TransportStateChange::PeerFlowDown(_)
+            | TransportStateChange::PeerFlowUp(_)
+            | TransportStateChange::SendQueueHighWatermark(_)
+            | TransportStateChange::SendQueueLowWatermark(_) => (),
         }
     }
 
@@ -490,6 +690,11 @@ impl Gossip for GossipImpl {
     fn on_timer(&self, event_handler: &Arc<dyn P2PEventHandlerControl>) {
         self.download_manager.on_timer(event_handler);
     }
+
+    /// The method is called when the registry records of the peers change.
+    fn on_registry_change(&self, event_handler: &Arc<dyn P2PEventHandlerControl>) {
+        self.download_manager.on_registry_change(event_handler);
+    }
 }
 
 /// A *Gossip* message can be converted into a
@@ -532,6 +737,14 @@ impl TryFrom<pb::GossipMessage> for GossipMessage {
     }
 }
 
+/// Returns the encoding version in which artifacts are served to a requester
+/// that understands the given version: the requester's version if this replica
+/// can down-convert to it, its own version otherwise, which the requester
+/// up-converts.
+fn served_encoding_version(requested: ArtifactEncodingVersion) -> ArtifactEncodingVersion {
+    requested.min(ARTIFACT_ENCODING_VERSION)
+}
+
 /// A chunk request can be converted into a `pb::GossipChunkRequest`.
 impl From<GossipChunkRequest> for pb::GossipChunkRequest {
     /// The function converts the given chunk request into the Protobuf
@@ -541,6 +754,8 @@ impl From<GossipChunkRequest> for pb::GossipChunkRequest {
             artifact_id: serialize(&gossip_chunk_request.artifact_id)
                 .expect("Local value serailization should succeed"),
             chunk_id: gossip_chunk_request.chunk_id.get(),
+            accepts_compressed_chunks: gossip_chunk_request.accepts_compressed_chunks,
+            artifact_encoding_version: gossip_chunk_request.artifact_encoding_version,
         }
     }
 }
@@ -554,6 +769,8 @@ impl TryFrom<pb::GossipChunkRequest> for GossipChunkRequest {
         Ok(Self {
             artifact_id: deserialize(&gossip_chunk_request.artifact_id)?,
             chunk_id: ChunkId::from(gossip_chunk_request.chunk_id),
+            accepts_compressed_chunks: gossip_chunk_request.accepts_compressed_chunks,
+            artifact_encoding_version: gossip_chunk_request.artifact_encoding_version,
         })
     }
 }
@@ -562,8 +779,15 @@ impl TryFrom<pb::GossipChunkRequest> for GossipChunkRequest {
 impl From<GossipChunk> for pb::GossipChunk {
     /// The function converts the given chunk into the Protobuf equivalent.
     fn from(gossip_chunk: GossipChunk) -> Self {
-        let response = match gossip_chunk.artifact_chunk {
-            Ok(artifact_chunk) => Some(Response::Chunk(artifact_chunk.into())),
+        let version = served_encoding_version(gossip_chunk.artifact_encoding_version);
+        let response = match gossip_chunk
+            .artifact_chunk
+            .map(|artifact_chunk| artifact_chunk.into_protobuf(version))
+        {
+            Ok(Ok(artifact_chunk)) => Some(Response::Chunk(artifact_chunk)),
+            // An artifact that cannot be encoded in the version of the
+            // requester cannot be served to it.
+            Ok(Err(_)) => Some(Response::Error(pb::P2pError::NotFound as i32)),
             // Add additional cases as required.
             Err(_) => Some(Response::Error(pb::P2pError::NotFound as i32)),
         };
@@ -572,6 +796,7 @@ impl From<GossipChunk> for pb::GossipChunk {
                 .expect("Local value serailization should succeed"),
             chunk_id: gossip_chunk.chunk_id.get(),
             response,
+            compressed: gossip_chunk.compressed,
         }
     }
 }
@@ -592,6 +817,10 @@ impl TryFrom<pb::GossipChunk> for GossipChunk {
                     p2p_error_code: P2PErrorCode::NotFound,
                 }),
             },
+            compressed: gossip_chunk.compressed,
+            // The artifact of a unit chunk is converted to the encoding
+            // version of this replica when decoding it.
+            artifact_encoding_version: ARTIFACT_ENCODING_VERSION,
         })
     }
 }
@@ -635,3 +864,52 @@ impl TryFrom<pb::GossipRetransmissionRequest> for GossipRetransmissionRequest {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_types::{
+        artifact::{RegistryDeltaId, RegistryDeltaMessage},
+        RegistryVersion,
+    };
+
+    /// Chunks requested in an encoding version newer than the one of this
+    /// replica are sent in its own version, which the requester up-converts.
+    #[test]
+    fn chunks_requested_in_a_newer_version_are_sent_in_the_own_version() {
+        let artifact = Artifact::RegistryDelta(RegistryDeltaMessage {
+            id: RegistryDeltaId {
+                since_version: RegistryVersion::from(1),
+                version: RegistryVersion::from(2),
+            },
+            certified_response: vec![1, 2, 3],
+        });
+        let artifact_id = ArtifactId::RegistryDelta(RegistryDeltaId {
+            since_version: RegistryVersion::from(1),
+            version: RegistryVersion::from(2),
+        });
+        let chunk_id = ChunkId::from(0);
+        let gossip_chunk = GossipChunk {
+            artifact_id,
+            chunk_id,
+            artifact_chunk: Ok(ArtifactChunk {
+                chunk_id,
+                witness: Vec::new(),
+                artifact_chunk_data: ArtifactChunkData::UnitChunkData(artifact),
+            }),
+            compressed: false,
+            artifact_encoding_version: ARTIFACT_ENCODING_VERSION + 1,
+        };
+
+        let pb_chunk = pb::GossipChunk::from(gossip_chunk.clone());
+        match &pb_chunk.response {
+            Some(Response::Chunk(pb::ArtifactChunk {
+                data: Some(pb::artifact_chunk::Data::Envelope(envelope)),
+                ..
+            })) => assert_eq!(envelope.version, ARTIFACT_ENCODING_VERSION),
+            response => panic!("expected an envelope, got {:?}", response),
+        }
+        let decoded = GossipChunk::try_from(pb_chunk).unwrap();
+        assert_eq!(decoded.artifact_chunk, gossip_chunk.artifact_chunk);
+    }
+}
@@ -0,0 +1,196 @@
+//! The ingress rate limiter enforces the per-sender rate limits of the
+//! ingress messages submitted to this replica.
+//!
+//! Every sender principal has a token bucket for the number and one for the
+//! total size of its messages. The buckets are refilled at the configured
+//! rates and hold at most one second worth of tokens, so a sender may burst
+//! up to its per-second limits. A message is admitted if both buckets hold
+//! enough tokens; messages larger than the per-second byte limit are admitted
+//! from a full bucket, so that they are not rejected forever. The tokens of
+//! admitted messages that are not accepted afterwards, e.g. because the
+//! ingress pool is full, are refunded.
+//!
+//! Messages of the anonymous principal are not limited: they may come from
+//! any number of users, which would share a single budget. They are subject
+//! to the per-canister quotas of the ingress throttler like all messages.
+//!
+//! Full buckets are equivalent to not having a bucket, so they are dropped
+//! once a second to bound the memory of the limiter.
+
+use crate::metrics::IngressRateLimiterMetrics;
+use ic_config::artifact_pool::IngressSenderRateLimit;
+use ic_types::UserId;
+use ic_utils::token_bucket::TokenBucket;
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// The interval at which full buckets are dropped.
+const PURGE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The token buckets of a sender.
+struct SenderBuckets {
+    /// The tokens for the number of messages.
+    messages: TokenBucket,
+    /// The tokens for the total size of the messages, in bytes.
+    bytes: TokenBucket,
+}
+
+/// The state of the rate limiter.
+struct RateLimiterState {
+    /// The token buckets of the senders that submitted messages recently.
+    buckets: HashMap<UserId, SenderBuckets>,
+    /// The last time the buckets of idle senders were dropped.
+    last_purge: Instant,
+}
+
+/// Enforces the per-sender rate limits of ingress messages.
+pub(crate) struct IngressRateLimiter {
+    /// The configured rate limit.
+    limit: IngressSenderRateLimit,
+    /// The token buckets.
+    state: Mutex<RateLimiterState>,
+    /// The rate limiter metrics.
+    metrics: IngressRateLimiterMetrics,
+}
+
+impl IngressRateLimiter {
+    /// The constructor creates a rate limiter enforcing the given limit.
+    pub(crate) fn new(limit: IngressSenderRateLimit, metrics: IngressRateLimiterMetrics) -> Self {
+        Self {
+            limit,
+            state: Mutex::new(RateLimiterState {
+                buckets: HashMap::new(),
+                last_purge: Instant::now(),
+            }),
+            metrics,
+        }
+    }
+
+    /// The method returns true and charges the sender if a message of the
+    /// given size from the sender is within the rate limit at the given
+    /// time, and false otherwise. Messages of the anonymous principal are
+    /// always admitted.
+    pub(crate) fn try_admit(&self, sender: UserId, size_bytes: usize, now: Instant) -> bool {
+        if sender.get_ref().is_anonymous() {
+            return true;
+        }
+        let max_messages = self.limit.max_messages_per_sec as f64;
+        let max_bytes = self.limit.max_bytes_per_sec as f64;
+        let mut state = self.state.lock().unwrap();
+        if now.saturating_duration_since(state.last_purge) >= PURGE_INTERVAL {
+            state.buckets.retain(|_, buckets| {
+                !(buckets.messages.is_full(now) && buckets.bytes.is_full(now))
+            });
+            state.last_purge = now;
+        }
+
+        let buckets = state
+            .buckets
+            .entry(sender)
+            .or_insert_with(|| SenderBuckets {
+                messages: TokenBucket::new(max_messages, max_messages, now),
+                bytes: TokenBucket::new(max_bytes, max_bytes, now),
+            });
+        let size_bytes = size_bytes as f64;
+        if buckets.messages.available(now) < 1.0
+            || buckets.bytes.available(now) < size_bytes.min(max_bytes)
+        {
+            self.metrics.messages_rate_limited.inc();
+            return false;
+        }
+        buckets.messages.take(1.0);
+        buckets.bytes.take(size_bytes);
+        true
+    }
+
+    /// The method refunds the charge of an admitted message of the given size
+    /// from the sender that was not accepted afterwards.
+    pub(crate) fn refund(&self, sender: &UserId, size_bytes: usize) {
+        if let Some(buckets) = self.state.lock().unwrap().buckets.get_mut(sender) {
+            buckets.messages.give_back(1.0);
+            buckets.bytes.give_back(size_bytes as f64);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_metrics::MetricsRegistry;
+    use ic_test_utilities::types::ids::user_test_id;
+    use ic_types::PrincipalId;
+
+    fn new_rate_limiter(max_messages_per_sec: u64, max_bytes_per_sec: u64) -> IngressRateLimiter {
+        IngressRateLimiter::new(
+            IngressSenderRateLimit {
+                max_messages_per_sec,
+                max_bytes_per_sec,
+            },
+            IngressRateLimiterMetrics::new(&MetricsRegistry::new()),
+        )
+    }
+
+    #[test]
+    fn messages_per_sec_are_limited_per_sender() {
+        let rate_limiter = new_rate_limiter(2, 1_000_000);
+        let now = Instant::now();
+        assert!(rate_limiter.try_admit(user_test_id(1), 10, now));
+        assert!(rate_limiter.try_admit(user_test_id(1), 10, now));
+        assert!(!rate_limiter.try_admit(user_test_id(1), 10, now));
+        // Other senders have their own limits.
+        assert!(rate_limiter.try_admit(user_test_id(2), 10, now));
+
+        // The bucket is refilled over time.
+        let later = now + Duration::from_millis(500);
+        assert!(rate_limiter.try_admit(user_test_id(1), 10, later));
+        assert!(!rate_limiter.try_admit(user_test_id(1), 10, later));
+        assert_eq!(rate_limiter.metrics.messages_rate_limited.get(), 2);
+    }
+
+    #[test]
+    fn bytes_per_sec_are_limited() {
+        let rate_limiter = new_rate_limiter(100, 1_000);
+        let now = Instant::now();
+        assert!(rate_limiter.try_admit(user_test_id(1), 600, now));
+        assert!(!rate_limiter.try_admit(user_test_id(1), 600, now));
+
+        // A message larger than the limit is admitted from a full bucket.
+        let later = now + Duration::from_secs(1);
+        assert!(rate_limiter.try_admit(user_test_id(1), 5_000, later));
+        assert!(!rate_limiter.try_admit(user_test_id(1), 1, later));
+    }
+
+    #[test]
+    fn idle_senders_are_dropped() {
+        let rate_limiter = new_rate_limiter(1, 1_000);
+        let now = Instant::now();
+        assert!(rate_limiter.try_admit(user_test_id(1), 1, now));
+        let later = now + Duration::from_secs(2);
+        assert!(rate_limiter.try_admit(user_test_id(2), 1, later));
+        assert_eq!(rate_limiter.state.lock().unwrap().buckets.len(), 1);
+    }
+
+    #[test]
+    fn refunded_messages_do_not_count() {
+        let rate_limiter = new_rate_limiter(1, 1_000);
+        let now = Instant::now();
+        assert!(rate_limiter.try_admit(user_test_id(1), 600, now));
+        rate_limiter.refund(&user_test_id(1), 600);
+        assert!(rate_limiter.try_admit(user_test_id(1), 1_000, now));
+        assert!(!rate_limiter.try_admit(user_test_id(1), 1, now));
+    }
+
+    #[test]
+    fn anonymous_senders_are_not_limited() {
+        let rate_limiter = new_rate_limiter(1, 1_000);
+        let anonymous = UserId::from(PrincipalId::new_anonymous());
+        let now = Instant::now();
+        for _ in 0..10 {
+            assert!(rate_limiter.try_admit(anonymous, 1_000, now));
+        }
+        assert!(rate_limiter.state.lock().unwrap().buckets.is_empty());
+    }
+}
@@ -0,0 +1,205 @@
+//! The adaptive ingress throttler decides whether user ingress messages are
+//! admitted to the ingress pool.
+//!
+//! Besides rejecting messages while the ingress pool is full, it sheds a
+//! growing fraction of the messages as the subnet falls behind, so that the
+//! load decreases before the subnet becomes unresponsive. The subnet falls
+//! behind if
+//!
+//! * the certified height lags behind the finalized height, i.e., execution
+//!   or certification can't keep up with consensus, or
+//! * batches queue up in message routing before they are executed.
+//!
+//! For both signals, all messages are admitted up to a soft limit, and the
+//! admission probability then decreases linearly to zero at the hard limit.
+//! The lower of both probabilities applies. Messages are shed
+//! deterministically: an admission credit grows by the admission probability
+//! with every message, and a message is admitted if a full credit is
+//! available.
+
+use crate::metrics::IngressAdmissionMetrics;
+use ic_interfaces::{
+    consensus_pool::ConsensusPoolCache,
+    ingress_pool::IngressPoolThrottler,
+    messaging::MessageRouting,
+    state_manager::{StateManager, StateReader},
+};
+use ic_replicated_state::ReplicatedState;
+use ic_types::CanisterId;
+use std::sync::{Arc, Mutex};
+
+/// The certified height lag up to which all messages are admitted.
+const CERTIFIED_HEIGHT_LAG_SOFT_LIMIT: u64 = 10;
+
+/// The certified height lag from which no messages are admitted.
+const CERTIFIED_HEIGHT_LAG_HARD_LIMIT: u64 = 50;
+
+/// The number of batches queued in message routing up to which all messages
+/// are admitted.
+const BATCH_QUEUE_DEPTH_SOFT_LIMIT: u64 = 4;
+
+/// The number of batches queued in message routing from which no messages are
+/// admitted. Message routing rejects batches once 16 are queued.
+const BATCH_QUEUE_DEPTH_HARD_LIMIT: u64 = 14;
+
+/// Returns the probability to admit a message if the given load signal has
+/// the given value.
+fn signal_probability(value: u64, soft_limit: u64, hard_limit: u64) -> f64 {
+    if value <= soft_limit {
+        1.0
+    } else if value >= hard_limit {
+        0.0
+    } else {
+        (hard_limit - value) as f64 / (hard_limit - soft_limit) as f64
+    }
+}
+
+/// Returns the probability to admit a message for the given certified height
+/// lag and batch queue depth.
+fn admission_probability(certified_height_lag: u64, batch_queue_depth: u64) -> f64 {
+    signal_probability(
+        certified_height_lag,
+        CERTIFIED_HEIGHT_LAG_SOFT_LIMIT,
+        CERTIFIED_HEIGHT_LAG_HARD_LIMIT,
+    )
+    .min(signal_probability(
+        batch_queue_depth,
+        BATCH_QUEUE_DEPTH_SOFT_LIMIT,
+        BATCH_QUEUE_DEPTH_HARD_LIMIT,
+    ))
+}
+
+/// The credit to admit messages.
+#[derive(Default)]
+struct AdmissionCredit(f64);
+
+impl AdmissionCredit {
+    /// Returns true and consumes a credit if a message can be admitted with
+    /// the given probability.
+    fn admit(&mut self, probability: f64) -> bool {
+        // Without the bound, a long period of low load would accumulate the
+        // credit to admit a burst of messages once the load increases.
+        self.0 = (self.0 + probability).min(1.0);
+        if self.0 >= 1.0 {
+            self.0 -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// An `IngressPoolThrottler` that sheds load depending on the pool and the
+/// progress of the subnet.
+pub(crate) struct AdaptiveIngressThrottler {
+    /// The throttler of the ingress pool.
+    pool_throttler: Box<dyn IngressPoolThrottler + Send + Sync>,
+    /// The consensus cache, for the finalized height.
+    consensus_cache: Arc<dyn ConsensusPoolCache>,
+    /// The state manager, for the certified and the executed height.
+    state_manager: Arc<dyn StateManager<State = ReplicatedState>>,
+    /// Message routing, for the height of the next batch.
+    message_router: Arc<dyn MessageRouting>,
+    /// The admission credit.
+    credit: Mutex<AdmissionCredit>,
+    /// The throttler metrics.
+    metrics: IngressAdmissionMetrics,
+}
+
+impl AdaptiveIngressThrottler {
+    /// The constructor creates a throttler that consults the given pool
+    /// throttler first.
+    pub(crate) fn new(
+        pool_throttler: Box<dyn IngressPoolThrottler + Send + Sync>,
+        consensus_cache: Arc<dyn ConsensusPoolCache>,
+        state_manager: Arc<dyn StateManager<State = ReplicatedState>>,
+        message_router: Arc<dyn MessageRouting>,
+        metrics: IngressAdmissionMetrics,
+    ) -> Self {
+        Self {
+            pool_throttler,
+            consensus_cache,
+            state_manager,
+            message_router,
+            credit: Default::default(),
+            metrics,
+        }
+    }
+
+    /// Returns the current probability to admit a message.
+    fn current_admission_probability(&self) -> f64 {
+        let finalized_height = self.consensus_cache.finalized_block().height;
+        let certified_height = self.state_manager.latest_certified_height();
+        let executed_height = self.state_manager.latest_state_height();
+        // The batches up to the one before the expected one were delivered.
+        let delivered_height = self.message_router.expected_batch_height().get();
+        let batch_queue_depth = delivered_height.saturating_sub(executed_height.get() + 1);
+        admission_probability(
+            finalized_height
+                .get()
+                .saturating_sub(certified_height.get()),
+            batch_queue_depth,
+        )
+    }
+}
+
+impl IngressPoolThrottler for AdaptiveIngressThrottler {
+    fn exceeds_threshold(&self) -> bool {
+        if self.pool_throttler.exceeds_threshold() {
+            return true;
+        }
+        let probability = self.current_admission_probability();
+        self.metrics.admission_probability.set(probability);
+        if self.credit.lock().unwrap().admit(probability) {
+            false
+        } else {
+            self.metrics.messages_shed.inc();
+            true
+        }
+    }
+
+    fn exceeds_canister_quota(&self, canister_id: &CanisterId, size_bytes: usize) -> bool {
+        self.pool_throttler
+            .exceeds_canister_quota(canister_id, size_bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn admission_probability_decreases_between_the_limits() {
+        assert_eq!(admission_probability(0, 0), 1.0);
+        assert_eq!(
+            admission_probability(
+                CERTIFIED_HEIGHT_LAG_SOFT_LIMIT,
+                BATCH_QUEUE_DEPTH_SOFT_LIMIT
+            ),
+            1.0
+        );
+        assert_eq!(admission_probability(30, 0), 0.5);
+        assert_eq!(admission_probability(0, 9), 0.5);
+        // The lower probability applies.
+        assert_eq!(admission_probability(40, 9), 0.25);
+        assert_eq!(
+            admission_probability(CERTIFIED_HEIGHT_LAG_HARD_LIMIT, 0),
+            0.0
+        );
+        assert_eq!(
+            admission_probability(0, BATCH_QUEUE_DEPTH_HARD_LIMIT + 1),
+            0.0
+        );
+    }
+
+    #[test]
+    fn admitted_fraction_follows_the_probability() {
+        let admitted = |probability: f64| {
+            let mut credit = AdmissionCredit::default();
+            (0..100).filter(|_| credit.admit(probability)).count()
+        };
+        assert_eq!(admitted(1.0), 100);
+        assert_eq!(admitted(0.25), 25);
+        assert_eq!(admitted(0.0), 0);
+    }
+}
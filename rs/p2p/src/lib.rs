@@ -101,13 +101,25 @@ use std::{
 };
 
 mod artifact_download_list;
+mod chunk_compression;
+mod codec;
 mod download_management;
 mod download_prioritization;
+mod download_state;
 mod event_handler;
+#[cfg(any(fuzzing, test))]
+pub mod fuzzing;
 mod gossip_protocol;
+mod ingress_rate_limiter;
+mod ingress_throttler;
 mod malicious_gossip;
 mod metrics;
 pub mod p2p;
+mod peer_metrics;
+mod recently_seen_ingress;
+mod retransmission_manager;
+#[cfg(feature = "testing")]
+pub mod simulation;
 
 /// Custom P2P result type returning a P2P error in case of error.
 pub(crate) type P2PResult<T> = std::result::Result<T, P2PError>;
@@ -2,16 +2,14 @@
 /// A macro to choose between running malicious code or the normal replica code.
 macro_rules! use_gossip_malicious_behavior_on_chunk_request {
     ($trait_self:ident, $malicious_code: expr, $replica_code_code:block) => {
-        if $trait_self.malicious_flags.maliciously_gossip_drop_requests
+        if $trait_self
+            .malicious_behaviors
+            .contains::<DropChunkRequests>()
             || $trait_self
-                .malicious_flags
-                .maliciously_gossip_artifact_not_found
-            || $trait_self
-                .malicious_flags
-                .maliciously_gossip_send_invalid_artifacts
-            || $trait_self
-                .malicious_flags
-                .maliciously_gossip_send_many_artifacts
+                .malicious_behaviors
+                .contains::<ArtifactNotFound>()
+            || $trait_self.malicious_behaviors.contains::<CorruptChunks>()
+            || $trait_self.malicious_behaviors.contains::<SendManyChunks>()
         {
             $malicious_code
         } else {
@@ -19,3 +17,51 @@ macro_rules! use_gossip_malicious_behavior_on_chunk_request {
         }
     };
 }
+
+#[cfg(feature = "malicious_code")]
+use crate::download_management::{DownloadManager, DownloadManagerImpl};
+#[cfg(feature = "malicious_code")]
+use crossbeam_channel::{unbounded, Sender};
+#[cfg(feature = "malicious_code")]
+use ic_types::p2p::GossipAdvert;
+#[cfg(feature = "malicious_code")]
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// Sends the adverts held back by the `DelayAdverts` behavior on its own
+/// thread, each as soon as its delay has passed.
+#[cfg(feature = "malicious_code")]
+pub(crate) struct AdvertDelayer {
+    delay: Duration,
+    sender: Sender<(Instant, GossipAdvert)>,
+}
+
+#[cfg(feature = "malicious_code")]
+impl AdvertDelayer {
+    pub(crate) fn new(delay: Duration, download_manager: Arc<DownloadManagerImpl>) -> Self {
+        let (sender, receiver) = unbounded::<(Instant, GossipAdvert)>();
+        // All adverts are delayed by the same duration, so they are due in the
+        // order in which they are received. The thread exits once the delayer
+        // is dropped.
+        std::thread::Builder::new()
+            .name("P2P_AdvertDelayer".to_string())
+            .spawn(move || {
+                for (send_at, advert) in receiver {
+                    let now = Instant::now();
+                    if send_at > now {
+                        std::thread::sleep(send_at - now);
+                    }
+                    download_manager.send_advert_to_peers(advert);
+                }
+            })
+            .expect("Failed to spawn the advert delayer thread");
+        Self { delay, sender }
+    }
+
+    /// Sends the advert to the peers once the delay has passed.
+    pub(crate) fn delay(&self, advert: GossipAdvert) {
+        let _ = self.sender.send((Instant::now() + self.delay, advert));
+    }
+}
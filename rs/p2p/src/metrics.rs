@@ -1,5 +1,5 @@
 use ic_metrics::{buckets::decimal_buckets, MetricsRegistry};
-use prometheus::{Histogram, HistogramVec, IntCounter, IntGauge};
+use prometheus::{Gauge, Histogram, HistogramVec, IntCounter, IntGauge};
 
 /// The *Gossip* metrics.
 #[derive(Debug, Clone)]
@@ -36,6 +36,33 @@ impl GossipMetrics {
     }
 }
 
+/// The chunk compression metrics.
+pub struct ChunkCompressionMetrics {
+    /// The ratio of the uncompressed to the compressed size of the chunks
+    /// served compressed.
+    pub compression_ratio: Histogram,
+    /// The number of received compressed chunks that could not be
+    /// decompressed.
+    pub decompression_failed: IntCounter,
+}
+
+impl ChunkCompressionMetrics {
+    /// The constructor returns a `ChunkCompressionMetrics` instance.
+    pub fn new(metrics_registry: &MetricsRegistry) -> Self {
+        Self {
+            compression_ratio: metrics_registry.histogram(
+                "p2p_chunk_compression_ratio",
+                "The ratio of the uncompressed to the compressed size of served chunks",
+                vec![1.0, 1.25, 1.5, 2.0, 3.0, 4.0, 6.0, 8.0, 16.0, 32.0],
+            ),
+            decompression_failed: metrics_registry.int_counter(
+                "p2p_chunk_decompression_failed",
+                "Number of received compressed chunks that could not be decompressed",
+            ),
+        }
+    }
+}
+
 /// The download management metrics.
 #[derive(Debug)]
 pub struct DownloadManagementMetrics {
@@ -50,6 +77,8 @@ pub struct DownloadManagementMetrics {
     pub received_artifact_size: IntGauge,
     /// The number of failed integrity hash checks.
     pub integrity_hash_check_failed: IntCounter,
+    /// The number of downloads restored after a restart.
+    pub downloads_restored: IntCounter,
 
     // Chunking fields.
     /// The number of requested chunks.
@@ -64,6 +93,8 @@ pub struct DownloadManagementMetrics {
     pub chunks_received: IntCounter,
     /// The number of timed-out chunks.
     pub chunks_timed_out: IntCounter,
+    /// The number of chunk requests released because the peer disconnected.
+    pub chunks_released_on_disconnect: IntCounter,
     /// The chunk delivery times.
     pub chunk_delivery_time: HistogramVec,
     /// The number of failures to download chunks.
@@ -122,6 +153,9 @@ pub struct DownloadManagementMetrics {
     pub download_next_selected: IntGauge,
     /// The number of calls to the `download_next()` function.
     pub download_next_calls: IntCounter,
+    /// The number of entries skipped by the `download_next()` function
+    /// because the artifact processor signals backpressure.
+    pub download_next_backpressured: IntCounter,
     /// The number of sent retransmission requests.
     pub download_next_retrans_requests_sent: IntCounter,
 }
@@ -149,6 +183,10 @@ impl DownloadManagementMetrics {
                 "integrity_hash_check_failed",
                 "Number of times the integrity check failed for artifacts",
             ),
+            downloads_restored: metrics_registry.int_counter(
+                "gossip_downloads_restored",
+                "Number of in-progress downloads restored after a restart",
+            ),
 
             // Chunking fields.
             chunks_requested: metrics_registry.int_counter(
@@ -177,6 +215,10 @@ impl DownloadManagementMetrics {
                 .int_counter("chunkd_send_failed", "Number of chunk send failures"),
             chunks_timed_out: metrics_registry
                 .int_counter("gossip_chunks_timedout", "Timed-out chunks"),
+            chunks_released_on_disconnect: metrics_registry.int_counter(
+                "gossip_chunks_released_on_disconnect",
+                "Chunk requests released because the peer disconnected",
+            ),
             connection_up_events: metrics_registry.int_counter(
                 "gossip_connection_up_event",
                 "Number of connection up events received",
@@ -274,6 +316,10 @@ impl DownloadManagementMetrics {
             ),
             download_next_calls: metrics_registry
                 .int_counter("download_next_calls", "Num calls to download_next()"),
+            download_next_backpressured: metrics_registry.int_counter(
+                "download_next_backpressured",
+                "Entries skipped by download_next() due to artifact processor backpressure",
+            ),
             download_next_retrans_requests_sent: metrics_registry.int_counter(
                 "download_next_retrans_requests_sent",
                 "Number of retransmission requests sent",
@@ -282,6 +328,109 @@ impl DownloadManagementMetrics {
     }
 }
 
+/// The retransmission manager metrics.
+#[derive(Debug, Clone)]
+pub struct RetransmissionMetrics {
+    /// The number of retransmission requests coalesced with a pending request.
+    pub requests_coalesced: IntCounter,
+    /// The number of retransmission requests held back due to an exhausted
+    /// per-peer budget.
+    pub requests_suppressed: IntCounter,
+    /// The number of peers with a pending retransmission request.
+    pub requests_pending: IntGauge,
+}
+
+impl RetransmissionMetrics {
+    /// The constructor returns a `RetransmissionMetrics` instance.
+    pub fn new(metrics_registry: &MetricsRegistry) -> Self {
+        Self {
+            requests_coalesced: metrics_registry.int_counter(
+                "retransmission_requests_coalesced",
+                "Number of retransmission requests coalesced with a pending request",
+            ),
+            requests_suppressed: metrics_registry.int_counter(
+                "retransmission_requests_suppressed",
+                "Number of retransmission requests held back due to an exhausted per-peer budget",
+            ),
+            requests_pending: metrics_registry.int_gauge(
+                "retransmission_requests_pending",
+                "Number of peers with a pending retransmission request",
+            ),
+        }
+    }
+}
+
+/// The recently-seen ingress cache metrics.
+#[derive(Debug, Clone)]
+pub struct RecentlySeenIngressMetrics {
+    /// The number of ingress messages dropped as duplicates.
+    pub duplicates_dropped: IntCounter,
+    /// The number of messages in the cache.
+    pub entries: IntGauge,
+}
+
+impl RecentlySeenIngressMetrics {
+    /// The constructor returns a `RecentlySeenIngressMetrics` instance.
+    pub fn new(metrics_registry: &MetricsRegistry) -> Self {
+        Self {
+            duplicates_dropped: metrics_registry.int_counter(
+                "recently_seen_ingress_duplicates_dropped",
+                "Number of ingress messages dropped because they were seen recently",
+            ),
+            entries: metrics_registry.int_gauge(
+                "recently_seen_ingress_entries",
+                "Number of ingress messages in the recently-seen cache",
+            ),
+        }
+    }
+}
+
+/// The ingress rate limiter metrics.
+#[derive(Debug, Clone)]
+pub struct IngressRateLimiterMetrics {
+    /// The number of ingress messages rejected due to the rate limit of their
+    /// sender.
+    pub messages_rate_limited: IntCounter,
+}
+
+impl IngressRateLimiterMetrics {
+    /// The constructor returns an `IngressRateLimiterMetrics` instance.
+    pub fn new(metrics_registry: &MetricsRegistry) -> Self {
+        Self {
+            messages_rate_limited: metrics_registry.int_counter(
+                "ingress_messages_rate_limited",
+                "Number of ingress messages rejected due to the rate limit of their sender",
+            ),
+        }
+    }
+}
+
+/// The adaptive ingress throttler metrics.
+#[derive(Debug, Clone)]
+pub struct IngressAdmissionMetrics {
+    /// The probability to admit an ingress message, given the progress of the
+    /// subnet.
+    pub admission_probability: Gauge,
+    /// The number of ingress messages rejected to shed load.
+    pub messages_shed: IntCounter,
+}
+
+impl IngressAdmissionMetrics {
+    /// The constructor returns an `IngressAdmissionMetrics` instance.
+    pub fn new(metrics_registry: &MetricsRegistry) -> Self {
+        Self {
+            admission_probability: metrics_registry.gauge(
+                "ingress_admission_probability",
+                "Probability to admit an ingress message, given the progress of the subnet",
+            ),
+            messages_shed: metrics_registry.int_counter(
+                "ingress_messages_shed",
+                "Number of ingress messages rejected to shed load",
+            ),
+        }
+    }
+}
+
 /// The download prioritizer metrics.
 pub struct DownloadPrioritizerMetrics {
     /// The number of adverts deleted from this peer.
@@ -3,28 +3,58 @@
 //! Specifically, it constructs all the artifact pools and the Consensus/P2P
 //! time source.
 
+use crate::download_state::DOWNLOAD_STATE_FILE_NAME;
 use crate::gossip_protocol::{Gossip, GossipImpl};
 use crate::{
     event_handler::IngressEventHandlerImpl,
     event_handler::{
         AdvertSubscriber, IngressThrottler, P2PEventHandlerControl, P2PEventHandlerImpl,
     },
+    ingress_rate_limiter::IngressRateLimiter,
+    ingress_throttler::AdaptiveIngressThrottler,
+    metrics::{IngressAdmissionMetrics, IngressRateLimiterMetrics, RecentlySeenIngressMetrics},
+    recently_seen_ingress::{RecentlySeenIngressImpl, RECENTLY_SEEN_INGRESS_CAPACITY},
 };
-use ic_artifact_manager::{manager, processors};
+use ic_artifact_manager::{
+    manager,
+    processors::{self, ProductionSwitch},
+    scheduler::ProcessorScheduler,
+};
+pub use ic_artifact_pool::dump::PoolDumps;
 use ic_artifact_pool::{
-    certification_pool::CertificationPoolImpl, consensus_pool::ConsensusPoolImpl,
-    dkg_pool::DkgPoolImpl, ensure_persistent_pool_replica_version_compatibility,
-    ingress_pool::IngressPoolImpl,
+    canister_http_pool::CanisterHttpPoolImpl,
+    certification_pool::{CertificationPoolImpl, POOL_CERTIFICATION},
+    consensus_pool::{ConsensusPoolImpl, POOL_CONSENSUS},
+    dkg_pool::{DkgPoolImpl, POOL_DKG},
+    ecdsa_pool::EcdsaPoolImpl,
+    ensure_persistent_pool_replica_version_compatibility,
+    equivocation_pool::EquivocationPoolImpl,
+    ingress_pool::{IngressPoolImpl, POOL_INGRESS},
+    query_stats_pool::QueryStatsPoolImpl,
+    remote_dkg_pool::RemoteDkgPoolImpl,
 };
 use ic_base_thread::async_safe_block_on_await;
 use ic_config::{artifact_pool::ArtifactPoolConfig, consensus::ConsensusConfig};
 use ic_consensus::{
+    canister_http::{
+        payload_builder::CanisterHttpSectionBuilder, CanisterHttpGossipImpl, CanisterHttpImpl,
+    },
     certification,
-    consensus::{ConsensusCrypto, Membership},
-    dkg,
+    consensus::{
+        equivocation::{EquivocationGossipImpl, EquivocationImpl},
+        payload_builder,
+        remote_dkg::{RemoteDkgGossipImpl, RemoteDkgImpl},
+        round_tracer::RoundTimelines,
+        ConsensusCrypto, CryptoThreadPool, Membership,
+    },
+    dkg, ecdsa,
+    query_stats::{
+        payload_builder::QueryStatsSectionBuilder, QueryStatsGossipImpl, QueryStatsHandlerImpl,
+    },
 };
 use ic_crypto_tls_interfaces::TlsHandshake;
 use ic_cycles_account_manager::CyclesAccountManager;
+use ic_event_log::EventLog;
 use ic_ingress_manager::IngressManager;
 use ic_interfaces::{
     artifact_manager::{ArtifactClient, ArtifactManager, ArtifactProcessor},
@@ -32,7 +62,8 @@ use ic_interfaces::{
     crypto::{Crypto, IngressSigVerifier},
     execution_environment::IngressHistoryReader,
     messaging::{MessageRouting, XNetPayloadBuilder},
-    p2p::{IngressEventHandler, P2PRunner},
+    p2p::{IngressEventHandler, P2PRunner, PeerConnectivityReader},
+    query_stats::QueryStatsReader,
     registry::RegistryClient,
     state_manager::StateManager,
     time_source::SysTimeSource,
@@ -40,21 +71,28 @@ use ic_interfaces::{
 };
 use ic_logger::{debug, replica_logger::ReplicaLogger};
 use ic_metrics::MetricsRegistry;
-use ic_protobuf::registry::subnet::v1::GossipConfig;
+use ic_protobuf::registry::{
+    node::v1::{connection_endpoint::Protocol, ConnectionEndpoint, FlowEndpoint, NodeRecord},
+    subnet::v1::GossipConfig,
+};
 use ic_registry_client::helper::subnet::SubnetRegistry;
 use ic_replicated_state::ReplicatedState;
 use ic_state_manager::StateManagerImpl;
 use ic_transport::transport::create_transport;
 use ic_types::{
-    artifact::{Advert, ArtifactKind, ArtifactTag, FileTreeSyncAttribute},
-    consensus::catchup::CUPWithOriginalProtobuf,
+    artifact::{
+        Advert, ArtifactId, ArtifactKind, ArtifactTag, FileTreeSyncAttribute, IngressMessageId,
+    },
+    consensus::{catchup::CUPWithOriginalProtobuf, HasHeight},
     crypto::CryptoHash,
     filetree_sync::{FileTreeSyncArtifact, FileTreeSyncId},
     p2p,
     replica_config::ReplicaConfig,
     transport::{FlowTag, TransportClientType, TransportConfig},
-    NodeId, SubnetId,
+    NodeId, PrincipalId, SubnetId,
 };
+use std::collections::BTreeMap;
+use std::str::FromStr;
 use std::sync::{
     atomic::{AtomicBool, Ordering::SeqCst},
     Arc, RwLock,
@@ -62,9 +100,9 @@ use std::sync::{
 use std::time::Duration;
 use tokio::task::JoinHandle;
 
-// import of malicious flags definition for p2p
+// import of malicious behavior definitions for p2p
 use ic_interfaces::registry::LocalStoreCertifiedTimeReader;
-use ic_types::malicious_flags::MaliciousFlags;
+use ic_types::malicious_strategies::{MaliciousBehaviors, MaliciousComponent};
 
 /// Periodic timer duration in milliseconds between polling calls to the P2P
 /// component.
@@ -82,6 +120,9 @@ struct P2P {
     gossip: Arc<GossipImpl>,
     /// The task handles.
     task_handles: Vec<JoinHandle<()>>,
+    /// The handle of the task refreshing the peers on registry changes, which
+    /// is aborted on drop.
+    registry_watcher: Option<JoinHandle<()>>,
     /// Flag indicating if P2P has been terminated.
     killed: Arc<AtomicBool>,
     /// The P2P event handler control with automatic reference counting.
@@ -102,6 +143,82 @@ pub enum P2PStateSyncClient {
     ),
 }
 
+/// The mode P2P runs in, which determines the set of artifact clients that is
+/// set up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum P2PMode {
+    /// All artifact clients are set up. This is the mode of regular replicas.
+    Full,
+    /// Only the ingress and state sync clients are set up.
+    ///
+    /// This mode is used by boundary and relay nodes, which participate in
+    /// gossip and forward ingress messages but neither take part in
+    /// *Consensus* nor hold *Consensus*, certification, or DKG artifacts. The
+    /// consensus pool is only used to serve the consensus pool cache, which
+    /// is initialized from the catch-up package.
+    Relay,
+    /// All artifact clients are set up, but the replica does not produce
+    /// artifacts while it is not a member of the subnet.
+    ///
+    /// This mode is used by observer replicas, which validate the artifacts
+    /// of the subnet members, sync and execute the state, and serve queries,
+    /// but neither make blocks nor create shares or dealings until they are
+    /// members at the finalized height. An observer must be in the subnet
+    /// record of the latest registry version, e.g. a node that was added to
+    /// the subnet but does not take part in consensus yet: the peers only
+    /// accept transport connections from the nodes in the subnet record, and
+    /// the download manager disconnects from all peers of a node that is not
+    /// in it. An observer needs the same keys as the other nodes in the
+    /// subnet record.
+    Observer,
+}
+
+impl Default for P2PMode {
+    fn default() -> Self {
+        P2PMode::Full
+    }
+}
+
+impl From<&ConsensusConfig> for P2PMode {
+    /// The method selects the mode configured by the `relay` and `observer`
+    /// flags of the consensus config, which are mutually exclusive.
+    fn from(config: &ConsensusConfig) -> Self {
+        if config.relay() {
+            P2PMode::Relay
+        } else if config.observer() {
+            P2PMode::Observer
+        } else {
+            P2PMode::Full
+        }
+    }
+}
+
+/// The context passed to the registrations of external artifact clients. It
+/// provides the components the processor of a client is built from.
+pub struct ArtifactRegistrationContext {
+    /// The time source shared by all artifact clients.
+    pub time_source: Arc<SysTimeSource>,
+    /// The metrics registry.
+    pub metrics_registry: MetricsRegistry,
+    /// The scheduler the processors are run on.
+    pub processor_scheduler: Arc<ProcessorScheduler>,
+    /// The function broadcasting an advert to the peers.
+    pub advert_broadcaster: Arc<dyn Fn(p2p::GossipAdvert) + Send + Sync>,
+}
+
+/// A registration of the client of an artifact kind that is not part of the
+/// P2P stack itself, e.g., of a downstream crate. The registration is called
+/// with the artifact manager maker after all built-in clients were added, and
+/// is expected to create the client and its processor and to add them with
+/// `ArtifactManagerMaker::register`.
+pub type ArtifactClientRegistration = Box<
+    dyn FnOnce(
+            &mut manager::ArtifactManagerMaker,
+            &ArtifactRegistrationContext,
+        ) -> Result<(), manager::ArtifactRegistrationError>
+        + Send,
+>;
+
 /// Fetch the Gossip configuration from the registry.
 pub(crate) fn fetch_gossip_config(
     registry_client: Arc<dyn RegistryClient>,
@@ -116,9 +233,49 @@ pub(crate) fn fetch_gossip_config(
     }
 }
 
+/// Returns the node records of the peers that are connected to at the IP
+/// addresses in the transport configuration if the registry contains no node
+/// records for them, see `TlsValidationConfig`. The peers listen on the same
+/// ports as this node.
+fn static_node_records(
+    transport_config: &TransportConfig,
+) -> Result<BTreeMap<NodeId, NodeRecord>, String> {
+    let peer_ips = match transport_config.tls_validation.peer_ips() {
+        Some(peer_ips) => peer_ips,
+        None => return Ok(BTreeMap::new()),
+    };
+    peer_ips
+        .iter()
+        .map(|(node_id, ip_addr)| {
+            let node_id = PrincipalId::from_str(node_id)
+                .map(NodeId::from)
+                .map_err(|err| format!("Invalid node ID {} in peer_ips: {}", node_id, err))?;
+            let p2p_flow_endpoints = transport_config
+                .p2p_flows
+                .iter()
+                .map(|flow_config| FlowEndpoint {
+                    flow_tag: flow_config.flow_tag,
+                    endpoint: Some(ConnectionEndpoint {
+                        ip_addr: ip_addr.clone(),
+                        port: flow_config.server_port as u32,
+                        protocol: Protocol::P2p1Tls13 as i32,
+                    }),
+                })
+                .collect();
+            let node_record = NodeRecord {
+                p2p_flow_endpoints,
+                ..Default::default()
+            };
+            Ok((node_id, node_record))
+        })
+        .collect()
+}
+
 /// The function constructs a P2P instance. Currently, it constructs all the
 /// artifact pools and the Consensus/P2P time source. Artifact
-/// clients are constructed and run in their separate actors.
+/// clients are constructed and run in their separate actors. Besides the
+/// handles to the stack, it returns a reader of the connectivity to the
+/// peers.
 #[allow(
     clippy::too_many_arguments,
     clippy::type_complexity,
@@ -127,11 +284,22 @@ pub(crate) fn fetch_gossip_config(
 pub fn create_networking_stack(
     metrics_registry: MetricsRegistry,
     log: ReplicaLogger,
+    // The structured event log, in which consensus and gossip record their
+    // decisions.
+    event_log: EventLog,
+    // The ring buffer in which consensus keeps the timelines of the latest
+    // rounds.
+    round_timelines: RoundTimelines,
+    // The registry with which the artifact pools are registered, to serve
+    // their dumps.
+    pool_dumps: PoolDumps,
     rt_handle: tokio::runtime::Handle,
     transport_config: TransportConfig,
     artifact_pool_config: ArtifactPoolConfig,
     consensus_config: ConsensusConfig,
-    malicious_flags: MaliciousFlags,
+    // The malicious behaviors injected into the components of the stack, for
+    // testing. Each component receives the behaviors that target it.
+    malicious_behaviors: MaliciousBehaviors,
     node_id: NodeId,
     subnet_id: SubnetId,
     // For testing purposes the caller can pass a transport object instead. Otherwise, the callee
@@ -140,6 +308,7 @@ pub fn create_networking_stack(
     tls_handshake: Arc<dyn TlsHandshake + Send + Sync>,
     state_manager: Arc<dyn StateManager<State = ReplicatedState>>,
     state_sync_client: P2PStateSyncClient,
+    p2p_mode: P2PMode,
     xnet_payload_builder: Arc<dyn XNetPayloadBuilder>,
     message_router: Arc<dyn MessageRouting>,
     crypto: Arc<dyn Crypto + Send + Sync>,
@@ -152,16 +321,33 @@ pub fn create_networking_stack(
     cycles_account_manager: Arc<CyclesAccountManager>,
     local_store_time_reader: Option<Arc<dyn LocalStoreCertifiedTimeReader>>,
     registry_poll_delay_duration_ms: u64,
+    // The maximum number of peers with their own label value in the per-peer
+    // gossip metrics.
+    max_peer_metric_labels: usize,
+    // If set, gossip also connects to nodes on other subnets to exchange XNet
+    // stream slices with, pulling them at this interval. The client of the
+    // XNet stream slices must be passed in `artifact_registrations`.
+    xnet_pull_interval: Option<Duration>,
+    // Whether state sync chunks are requested and served compressed.
+    compress_state_sync_chunks: bool,
+    // Clients of artifact kinds that are not part of the stack itself.
+    artifact_registrations: Vec<ArtifactClientRegistration>,
+    // The statistics of the queries executed by this replica, reported once
+    // per epoch. Without it, the replica doesn't report its own statistics.
+    query_stats_reader: Option<Arc<dyn QueryStatsReader>>,
 ) -> Result<
     (
         Arc<dyn IngressEventHandler>,
         Box<dyn P2PRunner>,
         Arc<dyn ConsensusPoolCache>,
+        Arc<dyn PeerConnectivityReader>,
     ),
     String,
 > {
-    let transport = transport.unwrap_or_else(|| {
-        create_transport(
+    let static_node_records = static_node_records(&transport_config)?;
+    let transport = match transport {
+        Some(transport) => transport,
+        None => create_transport(
             node_id,
             transport_config.clone(),
             registry_client.get_latest_version(),
@@ -169,7 +355,15 @@ pub fn create_networking_stack(
             tls_handshake,
             tokio::runtime::Handle::current(),
             log.clone(),
-        )
+        )?,
+    };
+    // In-progress downloads are persisted next to the persistent pool, so that
+    // they can be resumed after a restart.
+    let download_state_path = artifact_pool_config
+        .persistent_pool_db_path()
+        .join(DOWNLOAD_STATE_FILE_NAME);
+    let rate_limiter = artifact_pool_config.ingress_sender_rate_limit.map(|limit| {
+        IngressRateLimiter::new(limit, IngressRateLimiterMetrics::new(&metrics_registry))
     });
     let p2p_flow_tags = transport_config
         .p2p_flows
@@ -200,22 +394,41 @@ pub fn create_networking_stack(
         artifact_pool_config,
         consensus_config,
         log.clone(),
+        event_log.clone(),
+        round_timelines,
+        pool_dumps,
         metrics_registry.clone(),
         Arc::clone(&registry_client),
         state_manager,
         state_sync_client,
+        p2p_mode,
         xnet_payload_builder,
         message_router,
         ingress_history_reader,
         catch_up_package,
-        malicious_flags.clone(),
+        &malicious_behaviors,
         cycles_account_manager,
         local_store_time_reader,
         registry_poll_delay_duration_ms,
         Arc::clone(&event_handler) as Arc<_>,
+        artifact_registrations,
+        query_stats_reader,
     )
     .unwrap();
 
+    // Ingress messages are deduplicated across local submissions and gossip
+    // while they are in the ingress pool.
+    let in_ingress_pool = {
+        let artifact_manager = artifact_manager.clone();
+        Box::new(move |message_id: &IngressMessageId| {
+            artifact_manager.has_artifact(&ArtifactId::IngressMessage(message_id.clone()))
+        })
+    };
+    let recently_seen_ingress = Arc::new(RecentlySeenIngressImpl::new(
+        RECENTLY_SEEN_INGRESS_CAPACITY,
+        in_ingress_pool,
+        RecentlySeenIngressMetrics::new(&metrics_registry),
+    ));
     let gossip = Arc::new(GossipImpl::new(
         node_id,
         subnet_id,
@@ -224,9 +437,17 @@ pub fn create_networking_stack(
         transport.clone(),
         event_handler.clone(),
         p2p_flow_tags,
+        static_node_records,
+        Some(download_state_path),
+        recently_seen_ingress.clone(),
+        xnet_pull_interval,
+        compress_state_sync_chunks,
+        Some(consensus_pool_cache.clone()),
+        max_peer_metric_labels,
         log.clone(),
+        event_log,
         &metrics_registry,
-        malicious_flags,
+        malicious_behaviors.for_component(MaliciousComponent::Gossip),
     ));
     event_handler.start(gossip.clone());
 
@@ -235,20 +456,29 @@ pub fn create_networking_stack(
         rt_handle,
         gossip: gossip.clone(),
         task_handles: Vec::new(),
+        registry_watcher: None,
         killed: Arc::new(AtomicBool::new(false)),
         event_handler,
     };
 
     let ingress_handler = Arc::from(IngressEventHandlerImpl::new(
         ingress_throttle,
-        gossip,
+        gossip.clone(),
         node_id,
+        recently_seen_ingress,
+        rate_limiter,
     ));
-    Ok((ingress_handler, Box::new(p2p), consensus_pool_cache))
+    Ok((
+        ingress_handler,
+        Box::new(p2p),
+        consensus_pool_cache,
+        gossip as Arc<_>,
+    ))
 }
 
 impl P2PRunner for P2P {
-    /// The method starts the P2P timer task in the background.
+    /// The method starts the P2P timer task and the task refreshing the peers
+    /// on registry changes in the background.
     fn run(&mut self) {
         let gossip = self.gossip.clone();
         let event_handler = self.event_handler.clone();
@@ -264,6 +494,24 @@ impl P2PRunner for P2P {
             }
         });
         self.task_handles.push(handle);
+
+        let gossip = self.gossip.clone();
+        let event_handler = self.event_handler.clone();
+        let mut registry_changes = gossip.registry_changes();
+        self.registry_watcher = Some(self.rt_handle.spawn(async move {
+            // Changes made before the subscription are not notified, so the
+            // peers are refreshed once before waiting for the first change.
+            loop {
+                let gossip = gossip.clone();
+                let event_handler = event_handler.clone();
+                tokio::task::spawn_blocking(move || gossip.on_registry_change(&event_handler))
+                    .await
+                    .ok();
+                if !registry_changes.changed().await {
+                    break;
+                }
+            }
+        }));
     }
 }
 
@@ -271,6 +519,10 @@ impl Drop for P2P {
     /// The method signals the tasks to exit and waits for them to complete.
     fn drop(&mut self) {
         self.killed.store(true, SeqCst);
+        if let Some(handle) = self.registry_watcher.take() {
+            handle.abort();
+            async_safe_block_on_await(handle).ok();
+        }
         while let Some(handle) = self.task_handles.pop() {
             async_safe_block_on_await(handle).ok();
         }
@@ -280,7 +532,10 @@ impl Drop for P2P {
 
 /// The function sets up and returns the Artifact Manager and Consensus Pool.
 ///
-/// The Artifact Manager runs all artifact clients as separate actors.
+/// The Artifact Manager runs all artifact clients as separate actors. In
+/// `P2PMode::Relay`, only the ingress and state sync clients are set up. In
+/// `P2PMode::Observer`, the processors drop the artifacts produced by this
+/// replica while it is not a member of the subnet.
 #[allow(clippy::too_many_arguments, clippy::type_complexity)]
 fn setup_artifact_manager(
     rt_handle: tokio::runtime::Handle,
@@ -295,19 +550,25 @@ fn setup_artifact_manager(
     artifact_pool_config: ArtifactPoolConfig,
     consensus_config: ConsensusConfig,
     replica_logger: ReplicaLogger,
+    event_log: EventLog,
+    round_timelines: RoundTimelines,
+    pool_dumps: PoolDumps,
     metrics_registry: MetricsRegistry,
     registry_client: Arc<dyn RegistryClient>,
     state_manager: Arc<dyn StateManager<State = ReplicatedState>>,
     state_sync_client: P2PStateSyncClient,
+    p2p_mode: P2PMode,
     xnet_payload_builder: Arc<dyn XNetPayloadBuilder>,
     message_router: Arc<dyn MessageRouting>,
     ingress_history_reader: Box<dyn IngressHistoryReader>,
     catch_up_package: CUPWithOriginalProtobuf,
-    malicious_flags: MaliciousFlags,
+    malicious_behaviors: &MaliciousBehaviors,
     cycles_account_manager: Arc<CyclesAccountManager>,
     local_store_time_reader: Option<Arc<dyn LocalStoreCertifiedTimeReader>>,
     registry_poll_delay_duration_ms: u64,
     event_handler: Arc<dyn AdvertSubscriber + Send + Sync>,
+    artifact_registrations: Vec<ArtifactClientRegistration>,
+    query_stats_reader: Option<Arc<dyn QueryStatsReader>>,
 ) -> std::io::Result<(
     Arc<dyn ArtifactManager>,
     Arc<dyn ConsensusPoolCache>,
@@ -316,21 +577,55 @@ fn setup_artifact_manager(
     // Initialize the time source.
     let time_source = Arc::new(SysTimeSource::new());
 
+    // Initialize the scheduler running the artifact processors.
+    let processor_scheduler = ProcessorScheduler::with_default_num_threads(
+        artifact_pool_config.artifact_processor_queue_limits,
+        rt_handle,
+    );
+
     let mut artifact_manager_maker = manager::ArtifactManagerMaker::new(time_source.clone());
+    let registration_context = {
+        let event_handler = event_handler.clone();
+        ArtifactRegistrationContext {
+            time_source: Arc::clone(&time_source),
+            metrics_registry: metrics_registry.clone(),
+            processor_scheduler: Arc::clone(&processor_scheduler),
+            advert_broadcaster: Arc::new(move |advert| event_handler.broadcast_advert(advert)),
+        }
+    };
 
     ensure_persistent_pool_replica_version_compatibility(
         artifact_pool_config.persistent_pool_db_path(),
     );
 
-    let (ingress_pool, consensus_pool, cert_pool, dkg_pool) = init_artifact_pools(
+    let (ingress_pool, consensus_pool, ecdsa_pool) = init_artifact_pools(
         subnet_id,
-        artifact_pool_config,
+        artifact_pool_config.clone(),
         metrics_registry.clone(),
         replica_logger.clone(),
         catch_up_package,
     );
+    {
+        let ingress_pool = Arc::clone(&ingress_pool);
+        pool_dumps.register(POOL_INGRESS, move |options| {
+            ingress_pool.read().unwrap().pool_dump(options)
+        });
+        let consensus_pool = Arc::clone(&consensus_pool);
+        pool_dumps.register(POOL_CONSENSUS, move |options| {
+            consensus_pool.read().unwrap().pool_dump(options)
+        });
+    }
 
     let consensus_cache = consensus_pool.read().unwrap().get_cache();
+    // The HTTP ingress path only queries the sharded bookkeeping of the
+    // ingress pool, so that it does not contend with the ingress processor.
+    let ingress_throttler: IngressThrottler = Arc::new(RwLock::new(AdaptiveIngressThrottler::new(
+        Box::new(ingress_pool.read().unwrap().shards()),
+        Arc::clone(&consensus_cache),
+        Arc::clone(&state_manager),
+        Arc::clone(&message_router),
+        IngressAdmissionMetrics::new(&metrics_registry),
+    )));
 
     if let P2PStateSyncClient::TestChunkingPool(client, client_on_state_change) = state_sync_client
     {
@@ -340,13 +635,17 @@ fn setup_artifact_manager(
             metrics_registry,
             processors::BoxOrArcClient::ArcClient(client_on_state_change),
             move |advert| c_event_handler.broadcast_advert(advert.into()),
-            rt_handle,
+            Arc::clone(&processor_scheduler),
         );
-        artifact_manager_maker.add_arc_client(client, addr);
+        artifact_manager_maker.register(client, addr)?;
         return Ok((
-            artifact_manager_maker.finish(),
+            finish_artifact_manager(
+                artifact_manager_maker,
+                artifact_registrations,
+                &registration_context,
+            )?,
             consensus_cache,
-            ingress_pool as Arc<_>,
+            ingress_throttler,
         ));
     }
     if let P2PStateSyncClient::Client(state_sync_client) = state_sync_client {
@@ -356,9 +655,9 @@ fn setup_artifact_manager(
             metrics_registry.clone(),
             processors::BoxOrArcClient::ArcClient(Arc::clone(&state_sync_client) as Arc<_>),
             move |advert| event_handler.broadcast_advert(advert.into()),
-            rt_handle.clone(),
+            Arc::clone(&processor_scheduler),
         );
-        artifact_manager_maker.add_arc_client(state_sync_client, addr);
+        artifact_manager_maker.register(state_sync_client, addr)?;
     }
 
     let consensus_replica_config = ReplicaConfig { node_id, subnet_id };
@@ -369,6 +668,24 @@ fn setup_artifact_manager(
     );
     let membership = Arc::new(membership);
 
+    // Observer replicas only validate the artifacts of the subnet members. The
+    // switch is checked on every run of the processors, so that a replica
+    // that is added to the subnet starts producing artifacts at the height
+    // at which it becomes a member.
+    let production = match p2p_mode {
+        P2PMode::Observer => {
+            let membership = Arc::clone(&membership);
+            let consensus_cache = Arc::clone(&consensus_cache);
+            ProductionSwitch::new(move || {
+                membership
+                    .node_belongs_to_subnet(node_id, consensus_cache.finalized_block().height())
+                    .unwrap_or(false)
+            })
+        }
+        P2PMode::Full | P2PMode::Relay => ProductionSwitch::always(),
+    };
+
+    let ingress_behaviors = malicious_behaviors.for_component(MaliciousComponent::IngressManager);
     let ingress_manager = IngressManager::new(
         consensus_cache.clone(),
         ingress_history_reader,
@@ -379,59 +696,150 @@ fn setup_artifact_manager(
         replica_logger.clone(),
         Arc::clone(&state_manager) as Arc<_>,
         cycles_account_manager,
-        malicious_flags.clone(),
+        ingress_behaviors.clone(),
     );
     let ingress_manager = Arc::new(ingress_manager);
 
+    {
+        // Create the ingress client.
+        let event_handler = event_handler.clone();
+        let (ingress_client, actor) = processors::IngressProcessor::build(
+            move |advert| event_handler.broadcast_advert(advert.into()),
+            Arc::clone(&time_source) as Arc<_>,
+            Arc::clone(&ingress_pool),
+            Arc::new(ingress_pool.read().unwrap().shards()),
+            Arc::clone(&ingress_manager) as Arc<_>,
+            Arc::clone(&processor_scheduler),
+            replica_logger.clone(),
+            metrics_registry.clone(),
+            ingress_behaviors,
+        );
+        artifact_manager_maker.add_client(ingress_client, actor)?;
+    }
+
+    if p2p_mode == P2PMode::Relay {
+        return Ok((
+            finish_artifact_manager(
+                artifact_manager_maker,
+                artifact_registrations,
+                &registration_context,
+            )?,
+            consensus_cache,
+            ingress_throttler,
+        ));
+    }
+
+    let dkg_pool_unvalidated_limits = artifact_pool_config.dkg_pool_unvalidated_limits;
+    let dkg_pool_persistent_path = artifact_pool_config.dkg_pool_persistent_path.clone();
+    let artifact_ttls = artifact_pool_config.artifact_ttls.clone();
+    let cert_pool = Arc::new(RwLock::new(CertificationPoolImpl::new(
+        artifact_pool_config,
+        replica_logger.clone(),
+        metrics_registry.clone(),
+    )));
+    let dkg_pool = Arc::new(RwLock::new(
+        match dkg_pool_persistent_path {
+            Some(path) => DkgPoolImpl::with_persistence(
+                metrics_registry.clone(),
+                dkg_pool_unvalidated_limits,
+                path,
+                replica_logger.clone(),
+            ),
+            None => DkgPoolImpl::with_unvalidated_limits(
+                metrics_registry.clone(),
+                dkg_pool_unvalidated_limits,
+            ),
+        }
+        .with_artifact_ttls(artifact_ttls),
+    ));
+    {
+        let cert_pool = Arc::clone(&cert_pool);
+        pool_dumps.register(POOL_CERTIFICATION, move |options| {
+            cert_pool.read().unwrap().pool_dump(options)
+        });
+        let dkg_pool = Arc::clone(&dkg_pool);
+        pool_dumps.register(POOL_DKG, move |options| {
+            dkg_pool.read().unwrap().pool_dump(options)
+        });
+    }
+    let equivocation_pool = Arc::new(RwLock::new(EquivocationPoolImpl::new(
+        metrics_registry.clone(),
+    )));
+    let remote_dkg_pool = Arc::new(RwLock::new(RemoteDkgPoolImpl::new(
+        metrics_registry.clone(),
+    )));
+    let canister_http_pool = Arc::new(RwLock::new(CanisterHttpPoolImpl::new(
+        metrics_registry.clone(),
+    )));
+    let query_stats_pool = Arc::new(RwLock::new(QueryStatsPoolImpl::new(
+        metrics_registry.clone(),
+    )));
+
+    // The consensus config is moved into the consensus client, but it also
+    // configures the DKG client.
+    let dkg_consensus_config = consensus_config.clone();
+    // Consensus and certification create and verify signatures on one shared
+    // thread pool.
+    let crypto_pool = CryptoThreadPool::new(consensus_config.crypto_threads());
+    let consensus_behaviors = malicious_behaviors.for_component(MaliciousComponent::Consensus);
     {
         // Create the consensus client.
         let event_handler = event_handler.clone();
         let (consensus_client, actor) = processors::ConsensusProcessor::build(
             move |advert| event_handler.broadcast_advert(advert.into()),
             || {
+                let mut sections = payload_builder::default_sections(
+                    Arc::clone(&ingress_manager) as Arc<_>,
+                    Arc::clone(&xnet_payload_builder) as Arc<_>,
+                    &metrics_registry,
+                );
+                sections.push(Arc::new(CanisterHttpSectionBuilder::new(
+                    Arc::clone(&canister_http_pool) as Arc<_>,
+                    Arc::clone(&consensus_crypto),
+                    Arc::clone(&registry_client),
+                    Arc::clone(&state_manager) as Arc<_>,
+                    subnet_id,
+                    replica_logger.clone(),
+                )));
+                sections.push(Arc::new(QueryStatsSectionBuilder::new(
+                    Arc::clone(&query_stats_pool) as Arc<_>,
+                    Arc::clone(&consensus_crypto),
+                    Arc::clone(&registry_client),
+                    subnet_id,
+                    replica_logger.clone(),
+                )));
                 ic_consensus::consensus::setup(
                     consensus_replica_config.clone(),
                     consensus_config,
                     Arc::clone(&registry_client),
                     Arc::clone(&membership) as Arc<_>,
                     Arc::clone(&consensus_crypto),
+                    crypto_pool.clone(),
                     Arc::clone(&ingress_manager) as Arc<_>,
-                    Arc::clone(&xnet_payload_builder) as Arc<_>,
+                    sections,
                     Arc::clone(&dkg_pool) as Arc<_>,
+                    Arc::clone(&equivocation_pool) as Arc<_>,
                     Arc::clone(&message_router) as Arc<_>,
                     Arc::clone(&state_manager) as Arc<_>,
                     Arc::clone(&time_source) as Arc<_>,
-                    malicious_flags.clone(),
+                    consensus_behaviors.clone(),
                     metrics_registry.clone(),
                     replica_logger.clone(),
+                    event_log,
                     local_store_time_reader,
                     registry_poll_delay_duration_ms,
+                    round_timelines,
                 )
             },
             Arc::clone(&time_source) as Arc<_>,
             Arc::clone(&consensus_pool),
             Arc::clone(&ingress_pool),
-            rt_handle.clone(),
-            replica_logger.clone(),
-            metrics_registry.clone(),
-        );
-        artifact_manager_maker.add_client(consensus_client, actor);
-    }
-
-    {
-        // Create the ingress client.
-        let event_handler = event_handler.clone();
-        let (ingress_client, actor) = processors::IngressProcessor::build(
-            move |advert| event_handler.broadcast_advert(advert.into()),
-            Arc::clone(&time_source) as Arc<_>,
-            Arc::clone(&ingress_pool),
-            ingress_manager,
-            rt_handle.clone(),
+            Arc::clone(&processor_scheduler),
             replica_logger.clone(),
             metrics_registry.clone(),
-            malicious_flags,
+            production.clone(),
         );
-        artifact_manager_maker.add_client(ingress_client, actor);
+        artifact_manager_maker.add_client(consensus_client, actor)?;
     }
 
     {
@@ -444,6 +852,7 @@ fn setup_artifact_manager(
                     consensus_replica_config.clone(),
                     Arc::clone(&membership) as Arc<_>,
                     Arc::clone(&certifier_crypto),
+                    crypto_pool,
                     Arc::clone(&state_manager) as Arc<_>,
                     metrics_registry.clone(),
                     replica_logger.clone(),
@@ -452,15 +861,16 @@ fn setup_artifact_manager(
             Arc::clone(&time_source) as Arc<_>,
             Arc::clone(&consensus_cache) as Arc<_>,
             Arc::clone(&cert_pool),
-            rt_handle.clone(),
+            Arc::clone(&processor_scheduler),
             replica_logger.clone(),
             metrics_registry.clone(),
+            production.clone(),
         );
-        artifact_manager_maker.add_client(certification_client, actor);
+        artifact_manager_maker.add_client(certification_client, actor)?;
     }
 
     {
-        let event_handler = event_handler;
+        let event_handler = event_handler.clone();
         let (dkg_client, actor) = processors::DkgProcessor::build(
             move |advert| event_handler.broadcast_advert(advert.into()),
             || {
@@ -469,6 +879,7 @@ fn setup_artifact_manager(
                         consensus_replica_config.node_id,
                         Arc::clone(&consensus_crypto),
                         Arc::clone(&consensus_cache),
+                        &dkg_consensus_config,
                         metrics_registry.clone(),
                         replica_logger.clone(),
                     ),
@@ -477,21 +888,171 @@ fn setup_artifact_manager(
             },
             Arc::clone(&time_source) as Arc<_>,
             Arc::clone(&dkg_pool),
-            rt_handle,
+            Arc::clone(&processor_scheduler),
             replica_logger.clone(),
             metrics_registry.clone(),
+            production.clone(),
         );
-        artifact_manager_maker.add_client(dkg_client, actor);
+        artifact_manager_maker.add_client(dkg_client, actor)?;
+    }
+
+    {
+        // Create the ECDSA client.
+        let event_handler = event_handler.clone();
+        let (ecdsa_client, actor) = processors::EcdsaProcessor::build(
+            move |advert| event_handler.broadcast_advert(advert.into()),
+            || {
+                (
+                    ecdsa::EcdsaImpl::new(
+                        node_id,
+                        Arc::clone(&consensus_cache) as Arc<_>,
+                        Arc::clone(&consensus_crypto),
+                        Arc::clone(&membership),
+                        replica_logger.clone(),
+                    ),
+                    ecdsa::EcdsaGossipImpl::new(Arc::clone(&consensus_cache) as Arc<_>),
+                )
+            },
+            Arc::clone(&time_source) as Arc<_>,
+            Arc::clone(&ecdsa_pool),
+            Arc::clone(&processor_scheduler),
+            replica_logger.clone(),
+            metrics_registry.clone(),
+            production.clone(),
+        );
+        artifact_manager_maker.add_client(ecdsa_client, actor)?;
+    }
+
+    {
+        // Create the equivocation client.
+        let event_handler = event_handler.clone();
+        let (equivocation_client, actor) = processors::EquivocationProcessor::build(
+            move |advert| event_handler.broadcast_advert(advert.into()),
+            || {
+                (
+                    EquivocationImpl::new(
+                        Arc::clone(&membership),
+                        Arc::clone(&consensus_crypto),
+                        replica_logger.clone(),
+                    ),
+                    EquivocationGossipImpl::new(Arc::clone(&consensus_cache) as Arc<_>),
+                )
+            },
+            Arc::clone(&time_source) as Arc<_>,
+            Arc::clone(&consensus_pool),
+            Arc::clone(&equivocation_pool),
+            Arc::clone(&processor_scheduler),
+            replica_logger.clone(),
+            metrics_registry.clone(),
+            production.clone(),
+        );
+        artifact_manager_maker.add_client(equivocation_client, actor)?;
+    }
+
+    {
+        // Create the remote DKG client.
+        let event_handler = event_handler.clone();
+        let (remote_dkg_client, actor) = processors::RemoteDkgProcessor::build(
+            move |advert| event_handler.broadcast_advert(advert.into()),
+            || {
+                (
+                    RemoteDkgImpl::new(replica_logger.clone()),
+                    RemoteDkgGossipImpl::new(Arc::clone(&consensus_cache) as Arc<_>),
+                )
+            },
+            Arc::clone(&time_source) as Arc<_>,
+            Arc::clone(&consensus_cache) as Arc<_>,
+            Arc::clone(&remote_dkg_pool),
+            Arc::clone(&processor_scheduler),
+            replica_logger.clone(),
+            metrics_registry.clone(),
+            production.clone(),
+        );
+        artifact_manager_maker.add_client(remote_dkg_client, actor)?;
+    }
+
+    {
+        // Create the canister HTTP client.
+        let event_handler = event_handler.clone();
+        let (canister_http_client, actor) = processors::CanisterHttpProcessor::build(
+            move |advert| event_handler.broadcast_advert(advert.into()),
+            || {
+                (
+                    CanisterHttpImpl::new(
+                        Arc::clone(&consensus_cache) as Arc<_>,
+                        Arc::clone(&consensus_crypto),
+                        Arc::clone(&registry_client),
+                        subnet_id,
+                        replica_logger.clone(),
+                    ),
+                    CanisterHttpGossipImpl::new(Arc::clone(&consensus_cache) as Arc<_>),
+                )
+            },
+            Arc::clone(&time_source) as Arc<_>,
+            Arc::clone(&canister_http_pool),
+            Arc::clone(&processor_scheduler),
+            replica_logger.clone(),
+            metrics_registry.clone(),
+            production.clone(),
+        );
+        artifact_manager_maker.add_client(canister_http_client, actor)?;
+    }
+
+    {
+        // Create the query stats client.
+        let event_handler = event_handler;
+        let (query_stats_client, actor) = processors::QueryStatsProcessor::build(
+            move |advert| event_handler.broadcast_advert(advert.into()),
+            || {
+                (
+                    QueryStatsHandlerImpl::new(
+                        node_id,
+                        Arc::clone(&consensus_cache) as Arc<_>,
+                        Arc::clone(&consensus_crypto),
+                        Arc::clone(&registry_client),
+                        subnet_id,
+                        query_stats_reader,
+                        replica_logger.clone(),
+                    ),
+                    QueryStatsGossipImpl::new(Arc::clone(&consensus_cache) as Arc<_>),
+                )
+            },
+            Arc::clone(&time_source) as Arc<_>,
+            Arc::clone(&query_stats_pool),
+            Arc::clone(&processor_scheduler),
+            replica_logger.clone(),
+            metrics_registry.clone(),
+            production,
+        );
+        artifact_manager_maker.add_client(query_stats_client, actor)?;
     }
 
     Ok((
-        artifact_manager_maker.finish(),
+        finish_artifact_manager(
+            artifact_manager_maker,
+            artifact_registrations,
+            &registration_context,
+        )?,
         consensus_cache,
-        ingress_pool as Arc<_>,
+        ingress_throttler,
     ))
 }
 
-/// The function initializes the artifact pools.
+/// The function registers the clients of the external artifact kinds and
+/// creates the artifact manager.
+fn finish_artifact_manager(
+    mut artifact_manager_maker: manager::ArtifactManagerMaker,
+    artifact_registrations: Vec<ArtifactClientRegistration>,
+    registration_context: &ArtifactRegistrationContext,
+) -> std::io::Result<Arc<dyn ArtifactManager>> {
+    for registration in artifact_registrations {
+        registration(&mut artifact_manager_maker, registration_context)?;
+    }
+    Ok(artifact_manager_maker.finish())
+}
+
+/// The function initializes the ingress and consensus pools, which are
+/// required in all P2P modes, and the ECDSA pool.
 #[allow(clippy::type_complexity)]
 pub(crate) fn init_artifact_pools(
     subnet_id: SubnetId,
@@ -502,8 +1063,7 @@ pub(crate) fn init_artifact_pools(
 ) -> (
     Arc<RwLock<IngressPoolImpl>>,
     Arc<RwLock<ConsensusPoolImpl>>,
-    Arc<RwLock<CertificationPoolImpl>>,
-    Arc<RwLock<DkgPoolImpl>>,
+    Arc<RwLock<EcdsaPoolImpl>>,
 ) {
     (
         Arc::new(RwLock::new(IngressPoolImpl::new(
@@ -514,16 +1074,11 @@ pub(crate) fn init_artifact_pools(
         Arc::new(RwLock::new(ConsensusPoolImpl::new(
             subnet_id,
             catch_up_package,
-            config.clone(),
-            registry.clone(),
-            log.clone(),
-        ))),
-        Arc::new(RwLock::new(CertificationPoolImpl::new(
             config,
-            log,
             registry.clone(),
+            log,
         ))),
-        Arc::new(RwLock::new(DkgPoolImpl::new(registry))),
+        Arc::new(RwLock::new(EcdsaPoolImpl::new(registry))),
     )
 }
 
@@ -557,7 +1112,170 @@ impl ArtifactKind for TestArtifact {
             attribute: msg.id.to_string(),
             size: 0,
             id: msg.id.clone(),
-            integrity_hash: CryptoHash(msg.id.clone().into_bytes()),
+            integrity_hash: Self::integrity_hash(msg),
         }
     }
+
+    fn integrity_hash(msg: &TestArtifactMessage) -> CryptoHash {
+        CryptoHash(msg.id.clone().into_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_handler::{tests::new_test_event_handler, MAX_ADVERT_BUFFER};
+    use ic_artifact_manager::artifact::IngressArtifact;
+    use ic_execution_environment::IngressHistoryReaderImpl;
+    use ic_logger::replica_logger::no_op_logger;
+    use ic_registry_client::client::RegistryClientImpl;
+    use ic_test_utilities::{
+        consensus::make_catch_up_package_with_empty_transcript,
+        crypto::CryptoReturningOk,
+        cycles_account_manager::CyclesAccountManagerBuilder,
+        message_routing::FakeMessageRouting,
+        p2p::{test_group_set_registry, P2P_SUBNET_ID_DEFAULT},
+        state_manager::FakeStateManager,
+        types::ids::{node_test_id, subnet_test_id},
+        xnet_payload_builder::FakeXNetPayloadBuilder,
+    };
+    use ic_types::malicious_strategies::MaliciousBehaviors;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    /// The tags of the artifact clients set up by `setup_artifact_manager`,
+    /// apart from the state sync client.
+    const ARTIFACT_TAGS: [ArtifactTag; 8] = [
+        ArtifactTag::ConsensusArtifact,
+        ArtifactTag::IngressArtifact,
+        ArtifactTag::CertificationArtifact,
+        ArtifactTag::DkgArtifact,
+        ArtifactTag::EquivocationArtifact,
+        ArtifactTag::RemoteDkgArtifact,
+        ArtifactTag::CanisterHttpArtifact,
+        ArtifactTag::QueryStatsArtifact,
+    ];
+
+    /// Sets up the artifact manager of a test node in the given mode, with the
+    /// given registrations of external artifact clients.
+    fn setup_test_artifact_manager(
+        p2p_mode: P2PMode,
+        artifact_registrations: Vec<ArtifactClientRegistration>,
+    ) -> std::io::Result<Arc<dyn ArtifactManager>> {
+        ic_test_utilities::artifact_pool_config::with_test_pool_config(|artifact_pool_config| {
+            let node_id = node_test_id(0);
+            let subnet_id = subnet_test_id(P2P_SUBNET_ID_DEFAULT);
+            let data_provider = test_group_set_registry(subnet_id, Arc::new(vec![0]));
+            let registry_client = Arc::new(RegistryClientImpl::new(data_provider, None));
+            registry_client.fetch_and_start_polling().unwrap();
+            let crypto = Arc::new(CryptoReturningOk::default());
+            let state_manager = Arc::new(FakeStateManager::new());
+            setup_artifact_manager(
+                tokio::runtime::Handle::current(),
+                node_id,
+                Arc::clone(&crypto) as Arc<_>,
+                Arc::clone(&crypto) as Arc<_>,
+                Arc::clone(&crypto) as Arc<_>,
+                Arc::clone(&crypto) as Arc<_>,
+                subnet_id,
+                artifact_pool_config,
+                ConsensusConfig::default(),
+                no_op_logger(),
+                EventLog::disabled(),
+                RoundTimelines::default(),
+                PoolDumps::default(),
+                MetricsRegistry::new(),
+                Arc::clone(&registry_client) as Arc<_>,
+                Arc::clone(&state_manager) as Arc<_>,
+                P2PStateSyncClient::TestClient(),
+                p2p_mode,
+                Arc::new(FakeXNetPayloadBuilder::new()),
+                Arc::new(FakeMessageRouting::with_state_manager(
+                    Arc::clone(&state_manager) as Arc<_>,
+                )),
+                Box::new(IngressHistoryReaderImpl::new(
+                    Arc::clone(&state_manager) as Arc<_>
+                )),
+                CUPWithOriginalProtobuf::from_cup(make_catch_up_package_with_empty_transcript(
+                    registry_client,
+                    subnet_id,
+                )),
+                &MaliciousBehaviors::default(),
+                Arc::new(CyclesAccountManagerBuilder::new().build()),
+                None,
+                0,
+                Arc::new(new_test_event_handler(MAX_ADVERT_BUFFER, node_id)),
+                artifact_registrations,
+                None,
+            )
+            .map(|(artifact_manager, _, _)| artifact_manager)
+        })
+    }
+
+    /// Returns the tags of the artifact clients that are set up in the given
+    /// mode.
+    fn artifact_client_tags(p2p_mode: P2PMode) -> Vec<ArtifactTag> {
+        let artifact_manager = setup_test_artifact_manager(p2p_mode, Vec::new()).unwrap();
+        ARTIFACT_TAGS
+            .iter()
+            .copied()
+            .filter(|tag| {
+                artifact_manager
+                    .get_remaining_quota(*tag, node_test_id(1))
+                    .is_some()
+            })
+            .collect()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn relay_mode_only_sets_up_the_ingress_client() {
+        assert_eq!(
+            artifact_client_tags(P2PMode::Relay),
+            vec![ArtifactTag::IngressArtifact]
+        );
+        assert_eq!(artifact_client_tags(P2PMode::Full), ARTIFACT_TAGS.to_vec());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn registrations_are_called_after_the_built_in_clients_are_added() {
+        let called = Arc::new(AtomicBool::new(false));
+        let registration: ArtifactClientRegistration = {
+            let called = Arc::clone(&called);
+            Box::new(
+                move |maker: &mut manager::ArtifactManagerMaker,
+                      _: &ArtifactRegistrationContext| {
+                    assert!(maker.is_registered::<IngressArtifact>());
+                    called.store(true, Ordering::SeqCst);
+                    Ok(())
+                },
+            )
+        };
+        setup_test_artifact_manager(P2PMode::Relay, vec![registration]).unwrap();
+        assert!(called.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn failed_registrations_fail_the_setup() {
+        let registration: ArtifactClientRegistration = Box::new(
+            |_: &mut manager::ArtifactManagerMaker, _: &ArtifactRegistrationContext| {
+                Err(manager::ArtifactRegistrationError::AlreadyRegistered(
+                    ArtifactTag::IngressArtifact,
+                ))
+            },
+        );
+        let err = setup_test_artifact_manager(P2PMode::Relay, vec![registration])
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+    }
+
+    #[test]
+    fn p2p_mode_is_selected_by_the_consensus_config() {
+        let config = ConsensusConfig::default();
+        assert_eq!(P2PMode::from(&config), P2PMode::Full);
+        assert_eq!(
+            P2PMode::from(&config.clone().with_observer(true)),
+            P2PMode::Observer
+        );
+        assert_eq!(P2PMode::from(&config.with_relay(true)), P2PMode::Relay);
+    }
 }
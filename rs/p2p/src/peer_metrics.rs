@@ -0,0 +1,191 @@
+//! The per-peer *Gossip* metrics.
+//!
+//! <h1>Overview</h1>
+//!
+//! The global gossip counters tell that, e.g., chunks time out, but not which
+//! peer they time out on. The per-peer metrics export the same quantities
+//! with a `peer` label, so that a misbehaving or overloaded peer can be
+//! identified.
+//!
+//! Every label value is a separate time series, so the number of peers with
+//! their own label value is capped. The first peers seen get their own label
+//! values, all further peers are aggregated under the label value `other`. A
+//! peer that is removed frees its label value for the next new peer.
+
+use ic_metrics::MetricsRegistry;
+use ic_types::NodeId;
+use prometheus::{IntCounterVec, IntGaugeVec};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Mutex,
+};
+
+/// The label value of the peers aggregated because the cap was reached.
+const OTHER_PEERS: &str = "other";
+
+/// The per-peer *Gossip* metrics.
+pub(crate) struct PeerMetrics {
+    /// The number of adverts sent to the peer.
+    adverts_sent: IntCounterVec,
+    /// The number of adverts received from the peer.
+    adverts_received: IntCounterVec,
+    /// The number of chunks requested from the peer that are not delivered
+    /// yet.
+    chunks_in_flight: IntGaugeVec,
+    /// The number of chunk requests to the peer that timed out.
+    chunks_timed_out: IntCounterVec,
+    /// The maximum number of peers with their own label value.
+    max_peer_labels: usize,
+    /// The peers with their own label value.
+    labelled_peers: Mutex<BTreeSet<NodeId>>,
+}
+
+impl PeerMetrics {
+    /// The constructor returns a `PeerMetrics` instance that gives at most
+    /// `max_peer_labels` peers their own label value.
+    pub(crate) fn new(metrics_registry: &MetricsRegistry, max_peer_labels: usize) -> Self {
+        Self {
+            adverts_sent: metrics_registry.int_counter_vec(
+                "p2p_peer_adverts_sent",
+                "Number of adverts sent to the peer",
+                &["peer"],
+            ),
+            adverts_received: metrics_registry.int_counter_vec(
+                "p2p_peer_adverts_received",
+                "Number of adverts received from the peer",
+                &["peer"],
+            ),
+            chunks_in_flight: metrics_registry.int_gauge_vec(
+                "p2p_peer_chunks_in_flight",
+                "Number of chunks requested from the peer that are not delivered yet",
+                &["peer"],
+            ),
+            chunks_timed_out: metrics_registry.int_counter_vec(
+                "p2p_peer_chunks_timed_out",
+                "Number of chunk requests to the peer that timed out",
+                &["peer"],
+            ),
+            max_peer_labels,
+            labelled_peers: Mutex::new(BTreeSet::new()),
+        }
+    }
+
+    /// Returns the label value of the given peer, assigning it its own label
+    /// value if the cap is not reached yet.
+    fn peer_label(&self, peer_id: &NodeId) -> String {
+        let mut labelled_peers = self.labelled_peers.lock().unwrap();
+        if labelled_peers.contains(peer_id) || labelled_peers.len() < self.max_peer_labels {
+            labelled_peers.insert(*peer_id);
+            peer_id.to_string()
+        } else {
+            OTHER_PEERS.to_string()
+        }
+    }
+
+    /// Records that an advert was sent to the given peer.
+    pub(crate) fn advert_sent(&self, peer_id: &NodeId) {
+        self.adverts_sent
+            .with_label_values(&[&self.peer_label(peer_id)])
+            .inc();
+    }
+
+    /// Records that an advert was received from the given peer.
+    pub(crate) fn advert_received(&self, peer_id: &NodeId) {
+        self.adverts_received
+            .with_label_values(&[&self.peer_label(peer_id)])
+            .inc();
+    }
+
+    /// Records that the given number of chunk requests to the given peer
+    /// timed out.
+    pub(crate) fn chunks_timed_out(&self, peer_id: &NodeId, count: usize) {
+        self.chunks_timed_out
+            .with_label_values(&[&self.peer_label(peer_id)])
+            .inc_by(count as u64);
+    }
+
+    /// Sets the number of chunks in flight of all peers. The numbers of the
+    /// aggregated peers are summed up.
+    pub(crate) fn set_chunks_in_flight(&self, in_flight: impl Iterator<Item = (NodeId, usize)>) {
+        let mut by_label: BTreeMap<String, usize> = BTreeMap::new();
+        for (peer_id, chunks) in in_flight {
+            *by_label.entry(self.peer_label(&peer_id)).or_default() += chunks;
+        }
+        self.chunks_in_flight.reset();
+        for (label, chunks) in by_label {
+            self.chunks_in_flight
+                .with_label_values(&[&label])
+                .set(chunks as i64);
+        }
+    }
+
+    /// Removes the label values of the given peer, making room for another
+    /// peer to get its own label value. The counts of the aggregated peers
+    /// are kept.
+    pub(crate) fn remove_peer(&self, peer_id: &NodeId) {
+        if !self.labelled_peers.lock().unwrap().remove(peer_id) {
+            return;
+        }
+        let label = peer_id.to_string();
+        for metric in &[
+            &self.adverts_sent,
+            &self.adverts_received,
+            &self.chunks_timed_out,
+        ] {
+            let _ = metric.remove_label_values(&[&label]);
+        }
+        let _ = self.chunks_in_flight.remove_label_values(&[&label]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_test_utilities::types::ids::node_test_id;
+
+    #[test]
+    fn peers_beyond_the_cap_are_aggregated() {
+        let metrics = PeerMetrics::new(&MetricsRegistry::new(), 2);
+        for i in 0..4 {
+            metrics.advert_received(&node_test_id(i));
+        }
+        metrics.advert_received(&node_test_id(3));
+
+        let received = |label: &str| metrics.adverts_received.with_label_values(&[label]).get();
+        assert_eq!(received(&node_test_id(0).to_string()), 1);
+        assert_eq!(received(&node_test_id(1).to_string()), 1);
+        assert_eq!(received(OTHER_PEERS), 3);
+
+        metrics.set_chunks_in_flight((0..4).map(|i| (node_test_id(i), 2)));
+        assert_eq!(
+            metrics
+                .chunks_in_flight
+                .with_label_values(&[OTHER_PEERS])
+                .get(),
+            4
+        );
+    }
+
+    #[test]
+    fn removed_peers_free_their_label() {
+        let metrics = PeerMetrics::new(&MetricsRegistry::new(), 1);
+        metrics.chunks_timed_out(&node_test_id(0), 1);
+        metrics.remove_peer(&node_test_id(0));
+        metrics.chunks_timed_out(&node_test_id(1), 2);
+
+        assert_eq!(
+            metrics
+                .chunks_timed_out
+                .with_label_values(&[&node_test_id(1).to_string()])
+                .get(),
+            2
+        );
+        assert_eq!(
+            metrics
+                .chunks_timed_out
+                .with_label_values(&[OTHER_PEERS])
+                .get(),
+            0
+        );
+    }
+}
@@ -0,0 +1,211 @@
+//! The recently-seen ingress cache deduplicates ingress messages across the
+//! HTTP and the gossip paths.
+//!
+//! <h1>Overview</h1>
+//!
+//! Users may submit the same ingress message to several boundary nodes, so
+//! the same message reaches a replica both from a local submission and via
+//! gossip from its peers. Each copy inserted into the ingress pool is
+//! validated again and, once validated, advertised again.
+//!
+//! The cache remembers the messages that were handed to the artifact manager
+//! recently. Both `IngressEventHandlerImpl` (local submissions) and the
+//! download manager (adverts received via gossip) drop a message that is
+//! already in the cache, as long as the message is still in the ingress pool.
+//! The pool may drop a message after it was handed over, e.g. if the
+//! canister's quota is exceeded or the message fails validation, so the entry
+//! of a message that is no longer in the pool is removed and the message is
+//! accepted again.
+//!
+//! A message is identified by its ID together with the integrity hash of its
+//! bytes. The message ID does not cover the signature, so a copy with a
+//! different (e.g., invalid) signature is not considered a duplicate and
+//! cannot shadow the original message.
+//!
+//! Entries are dropped once their message expired, and the oldest-expiring
+//! entries are evicted when the cache is full.
+
+use crate::metrics::RecentlySeenIngressMetrics;
+use ic_types::{artifact::IngressMessageId, crypto::CryptoHash, time::Time};
+use std::{collections::BTreeMap, sync::Mutex};
+
+/// The maximum number of messages kept in the recently-seen ingress cache.
+pub(crate) const RECENTLY_SEEN_INGRESS_CAPACITY: usize = 100_000;
+
+/// The trait defines the behavior of the recently-seen ingress cache.
+pub(crate) trait RecentlySeenIngress: Send + Sync {
+    /// The method returns true if the message with the given ID and integrity
+    /// hash was seen recently and is still in the ingress pool, i.e., it is a
+    /// duplicate that does not need to be inserted into the ingress pool
+    /// again.
+    fn is_duplicate(&self, message_id: &IngressMessageId, integrity_hash: &CryptoHash) -> bool;
+
+    /// The method records that the message with the given ID and integrity
+    /// hash was handed to the artifact manager. Entries of messages that
+    /// expired before the given time are dropped.
+    fn insert(&self, message_id: IngressMessageId, integrity_hash: CryptoHash, now: Time);
+}
+
+/// The function returns true if the ingress pool contains the message with the
+/// given ID.
+pub(crate) type IngressPoolContains = Box<dyn Fn(&IngressMessageId) -> bool + Send + Sync>;
+
+/// An implementation of the `RecentlySeenIngress` trait.
+pub(crate) struct RecentlySeenIngressImpl {
+    /// The maximum number of entries.
+    capacity: usize,
+    /// Checks whether a message is still in the ingress pool.
+    in_pool: IngressPoolContains,
+    /// The integrity hashes of the recently-seen messages. Message IDs are
+    /// ordered by expiry time first, so expired entries are at the front.
+    entries: Mutex<BTreeMap<IngressMessageId, CryptoHash>>,
+    /// The cache metrics.
+    metrics: RecentlySeenIngressMetrics,
+}
+
+impl RecentlySeenIngressImpl {
+    /// The constructor creates a new cache that holds at most `capacity`
+    /// entries and looks up messages in the ingress pool with `in_pool`.
+    pub(crate) fn new(
+        capacity: usize,
+        in_pool: IngressPoolContains,
+        metrics: RecentlySeenIngressMetrics,
+    ) -> Self {
+        Self {
+            capacity,
+            in_pool,
+            entries: Mutex::new(BTreeMap::new()),
+            metrics,
+        }
+    }
+}
+
+/// `RecentlySeenIngressImpl` implements the `RecentlySeenIngress` trait.
+impl RecentlySeenIngress for RecentlySeenIngressImpl {
+    /// The method returns true if the message was seen recently and is still
+    /// in the ingress pool. The entry of a message that was dropped from the
+    /// pool is removed.
+    fn is_duplicate(&self, message_id: &IngressMessageId, integrity_hash: &CryptoHash) -> bool {
+        if self.entries.lock().unwrap().get(message_id) != Some(integrity_hash) {
+            return false;
+        }
+        // The pool is looked up without holding the lock on the entries.
+        if !(self.in_pool)(message_id) {
+            let mut entries = self.entries.lock().unwrap();
+            if entries.get(message_id) == Some(integrity_hash) {
+                entries.remove(message_id);
+                self.metrics.entries.set(entries.len() as i64);
+            }
+            return false;
+        }
+        self.metrics.duplicates_dropped.inc();
+        true
+    }
+
+    /// The method records the given message.
+    fn insert(&self, message_id: IngressMessageId, integrity_hash: CryptoHash, now: Time) {
+        let mut entries = self.entries.lock().unwrap();
+        while let Some(first) = entries.keys().next().cloned() {
+            if first.expiry() >= now && entries.len() < self.capacity {
+                break;
+            }
+            entries.remove(&first);
+        }
+        entries.insert(message_id, integrity_hash);
+        self.metrics.entries.set(entries.len() as i64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_metrics::MetricsRegistry;
+    use ic_test_utilities::mock_time;
+    use ic_types::messages::MessageId;
+    use std::{collections::BTreeSet, sync::Arc, time::Duration};
+
+    fn message_id(expiry: Time, id: u8) -> IngressMessageId {
+        IngressMessageId::new(expiry, MessageId::from([id; 32]))
+    }
+
+    fn new_cache(capacity: usize) -> RecentlySeenIngressImpl {
+        RecentlySeenIngressImpl::new(
+            capacity,
+            Box::new(|_| true),
+            RecentlySeenIngressMetrics::new(&MetricsRegistry::new()),
+        )
+    }
+
+    #[test]
+    fn duplicates_are_detected() {
+        let cache = new_cache(10);
+        let now = mock_time();
+        let id = message_id(now + Duration::from_secs(60), 1);
+        assert!(!cache.is_duplicate(&id, &CryptoHash(vec![1])));
+
+        cache.insert(id.clone(), CryptoHash(vec![1]), now);
+        assert!(cache.is_duplicate(&id, &CryptoHash(vec![1])));
+        // A copy with different bytes, e.g. another signature, is no
+        // duplicate.
+        assert!(!cache.is_duplicate(&id, &CryptoHash(vec![2])));
+        assert_eq!(cache.metrics.duplicates_dropped.get(), 1);
+    }
+
+    #[test]
+    fn expired_and_excess_entries_are_dropped() {
+        let cache = new_cache(2);
+        let now = mock_time();
+        let expired = message_id(now, 1);
+        let first = message_id(now + Duration::from_secs(10), 2);
+        let second = message_id(now + Duration::from_secs(20), 3);
+        let third = message_id(now + Duration::from_secs(30), 4);
+        cache.insert(expired.clone(), CryptoHash(vec![]), now);
+        cache.insert(first.clone(), CryptoHash(vec![]), now);
+
+        // The expired entry is dropped before the cache is full.
+        cache.insert(
+            second.clone(),
+            CryptoHash(vec![]),
+            now + Duration::from_secs(1),
+        );
+        assert!(!cache.is_duplicate(&expired, &CryptoHash(vec![])));
+        assert!(cache.is_duplicate(&first, &CryptoHash(vec![])));
+
+        // The entry expiring first is evicted from a full cache.
+        cache.insert(
+            third.clone(),
+            CryptoHash(vec![]),
+            now + Duration::from_secs(1),
+        );
+        assert!(!cache.is_duplicate(&first, &CryptoHash(vec![])));
+        assert!(cache.is_duplicate(&second, &CryptoHash(vec![])));
+        assert!(cache.is_duplicate(&third, &CryptoHash(vec![])));
+        assert_eq!(cache.metrics.entries.get(), 2);
+    }
+
+    #[test]
+    fn entries_of_messages_dropped_from_the_pool_are_removed() {
+        let pool = Arc::new(Mutex::new(BTreeSet::new()));
+        let cache = {
+            let pool = Arc::clone(&pool);
+            RecentlySeenIngressImpl::new(
+                10,
+                Box::new(move |message_id| pool.lock().unwrap().contains(message_id)),
+                RecentlySeenIngressMetrics::new(&MetricsRegistry::new()),
+            )
+        };
+        let now = mock_time();
+        let id = message_id(now + Duration::from_secs(60), 1);
+        cache.insert(id.clone(), CryptoHash(vec![1]), now);
+        pool.lock().unwrap().insert(id.clone());
+        assert!(cache.is_duplicate(&id, &CryptoHash(vec![1])));
+
+        // Once the pool dropped the message, it is accepted again.
+        pool.lock().unwrap().remove(&id);
+        assert!(!cache.is_duplicate(&id, &CryptoHash(vec![1])));
+        assert_eq!(cache.metrics.entries.get(), 0);
+        pool.lock().unwrap().insert(id.clone());
+        assert!(!cache.is_duplicate(&id, &CryptoHash(vec![1])));
+        assert_eq!(cache.metrics.duplicates_dropped.get(), 1);
+    }
+}
@@ -0,0 +1,265 @@
+//! The retransmission manager decides when retransmission requests are sent to
+//! peers.
+//!
+//! <h1>Overview</h1>
+//!
+//! Retransmission requests are triggered by two events: the periodic
+//! retransmission timer and a peer (re-)connecting. Sending a request for
+//! each of these events results in bursts of identical requests, each of
+//! which makes the peer clear its send queues and re-send all adverts that
+//! pass the filter.
+//!
+//! Instead of sending a request right away, the download manager schedules a
+//! request for a peer with the retransmission manager. The following rules
+//! apply:
+//!
+//! a. Coalescing
+//!
+//!    At most one request is pending per peer. Scheduling a request for a
+//!    peer that already has a pending request is a no-op. The filter is
+//!    computed when the request is eventually sent, so a single request
+//!    covers all filter updates that happened in the meantime. Pending
+//!    requests are only released if at least the coalescing interval has
+//!    elapsed since the last request was sent to the same peer.
+//!
+//! b. Budget
+//!
+//!    Each peer has a token bucket holding a budget of requests, which is
+//!    refilled over the budget period. Pending requests of peers that
+//!    exhausted their budget are held back until the bucket holds a token
+//!    again.
+
+use crate::metrics::RetransmissionMetrics;
+use ic_types::NodeId;
+use ic_utils::token_bucket::TokenBucket;
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Minimum interval in milliseconds between two retransmission requests sent
+/// to the same peer.
+pub(crate) const RETRANSMISSION_COALESCING_INTERVAL_MS: u64 = 1_000;
+
+/// Maximum number of retransmission requests sent to a peer per budget period.
+pub(crate) const RETRANSMISSION_BUDGET_PER_PEER: u32 = 5;
+
+/// The trait defines the behavior of the retransmission manager.
+pub(crate) trait RetransmissionManager: Send + Sync {
+    /// The method schedules a retransmission request for the given peer.
+    ///
+    /// If a request is already pending for the peer, the new request is
+    /// coalesced with the pending one.
+    fn schedule(&self, peer_id: NodeId);
+
+    /// The method returns the peers whose pending requests are due at the
+    /// given instant, i.e., the coalescing interval has elapsed and the peer's
+    /// budget is not exhausted.
+    ///
+    /// The returned requests are charged to the peers' budgets and are no
+    /// longer pending.
+    fn take_due(&self, now: Instant) -> Vec<NodeId>;
+
+    /// The method drops all state associated with the given peer.
+    fn remove_peer(&self, peer_id: &NodeId);
+}
+
+/// The retransmission state of a single peer.
+#[derive(Default)]
+struct PeerRetransmissionState {
+    /// Flag indicating if a request is pending for this peer.
+    pending: bool,
+    /// Flag indicating if the pending request was held back due to an
+    /// exhausted budget.
+    suppressed: bool,
+    /// The instant when the last request was sent to this peer.
+    last_sent_instant: Option<Instant>,
+    /// The request budget of this peer, once a request is due.
+    budget: Option<TokenBucket>,
+}
+
+/// An implementation of the `RetransmissionManager` trait.
+pub(crate) struct RetransmissionManagerImpl {
+    /// The minimum interval between two requests sent to the same peer.
+    coalescing_interval: Duration,
+    /// The maximum number of requests per peer and budget period.
+    budget_per_peer: u32,
+    /// The time it takes to refill an exhausted budget.
+    budget_period: Duration,
+    /// The retransmission state of all peers.
+    peers: Mutex<HashMap<NodeId, PeerRetransmissionState>>,
+    /// The retransmission metrics.
+    metrics: RetransmissionMetrics,
+}
+
+impl RetransmissionManagerImpl {
+    /// The constructor creates a new retransmission manager instance.
+    pub(crate) fn new(
+        coalescing_interval: Duration,
+        budget_per_peer: u32,
+        budget_period: Duration,
+        metrics: RetransmissionMetrics,
+    ) -> Self {
+        Self {
+            coalescing_interval,
+            budget_per_peer,
+            budget_period,
+            peers: Mutex::new(HashMap::new()),
+            metrics,
+        }
+    }
+}
+
+/// `RetransmissionManagerImpl` implements the `RetransmissionManager` trait.
+impl RetransmissionManager for RetransmissionManagerImpl {
+    /// The method schedules a retransmission request for the given peer.
+    fn schedule(&self, peer_id: NodeId) {
+        let mut peers = self.peers.lock().unwrap();
+        let state = peers.entry(peer_id).or_default();
+        if state.pending {
+            self.metrics.requests_coalesced.inc();
+        } else {
+            state.pending = true;
+        }
+    }
+
+    /// The method returns the peers whose pending requests are due.
+    fn take_due(&self, now: Instant) -> Vec<NodeId> {
+        let mut due = Vec::new();
+        let mut peers = self.peers.lock().unwrap();
+        for (peer_id, state) in peers.iter_mut() {
+            if !state.pending {
+                continue;
+            }
+
+            // Hold back the request if the last one was sent too recently.
+            if let Some(last_sent_instant) = state.last_sent_instant {
+                if now.saturating_duration_since(last_sent_instant) < self.coalescing_interval {
+                    continue;
+                }
+            }
+
+            // Hold back the request if the peer's budget is exhausted.
+            let budget_per_peer = self.budget_per_peer as f64;
+            let budget_period = self.budget_period;
+            let budget = state.budget.get_or_insert_with(|| {
+                TokenBucket::new(
+                    budget_per_peer / budget_period.as_secs_f64(),
+                    budget_per_peer,
+                    now,
+                )
+            });
+            if budget.available(now) < 1.0 {
+                if !state.suppressed {
+                    state.suppressed = true;
+                    self.metrics.requests_suppressed.inc();
+                }
+                continue;
+            }
+
+            budget.take(1.0);
+            state.pending = false;
+            state.suppressed = false;
+            state.last_sent_instant = Some(now);
+            due.push(*peer_id);
+        }
+        self.metrics
+            .requests_pending
+            .set(peers.values().filter(|state| state.pending).count() as i64);
+        due
+    }
+
+    /// The method drops all state associated with the given peer.
+    fn remove_peer(&self, peer_id: &NodeId) {
+        self.peers.lock().unwrap().remove(peer_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_metrics::MetricsRegistry;
+    use ic_test_utilities::types::ids::node_test_id;
+
+    /// The function returns a retransmission manager with a coalescing interval
+    /// of 1 second, and a budget of 2 requests per 10 seconds.
+    fn new_test_retransmission_manager() -> RetransmissionManagerImpl {
+        RetransmissionManagerImpl::new(
+            Duration::from_secs(1),
+            2,
+            Duration::from_secs(10),
+            RetransmissionMetrics::new(&MetricsRegistry::new()),
+        )
+    }
+
+    /// The function tests that multiple requests scheduled for the same peer
+    /// result in a single request.
+    #[test]
+    fn retransmission_requests_are_coalesced() {
+        let manager = new_test_retransmission_manager();
+        let now = Instant::now();
+        manager.schedule(node_test_id(1));
+        manager.schedule(node_test_id(1));
+        manager.schedule(node_test_id(2));
+
+        let mut due = manager.take_due(now);
+        due.sort();
+        assert_eq!(due, vec![node_test_id(1), node_test_id(2)]);
+        assert_eq!(manager.metrics.requests_coalesced.get(), 1);
+        assert!(manager.take_due(now).is_empty());
+    }
+
+    /// The function tests that a pending request is held back until the
+    /// coalescing interval has elapsed.
+    #[test]
+    fn retransmission_requests_respect_coalescing_interval() {
+        let manager = new_test_retransmission_manager();
+        let now = Instant::now();
+        manager.schedule(node_test_id(1));
+        assert_eq!(manager.take_due(now), vec![node_test_id(1)]);
+
+        manager.schedule(node_test_id(1));
+        assert!(manager
+            .take_due(now + Duration::from_millis(500))
+            .is_empty());
+        assert_eq!(
+            manager.take_due(now + Duration::from_secs(1)),
+            vec![node_test_id(1)]
+        );
+    }
+
+    /// The function tests that requests exceeding the per-peer budget are
+    /// suppressed until the budget is refilled.
+    #[test]
+    fn retransmission_requests_respect_budget() {
+        let manager = new_test_retransmission_manager();
+        let start = Instant::now();
+        for i in 0..2 {
+            manager.schedule(node_test_id(1));
+            assert_eq!(
+                manager.take_due(start + Duration::from_secs(i)),
+                vec![node_test_id(1)]
+            );
+        }
+
+        manager.schedule(node_test_id(1));
+        assert!(manager.take_due(start + Duration::from_secs(2)).is_empty());
+        assert!(manager.take_due(start + Duration::from_secs(3)).is_empty());
+        assert_eq!(manager.metrics.requests_suppressed.get(), 1);
+
+        assert_eq!(
+            manager.take_due(start + Duration::from_secs(10)),
+            vec![node_test_id(1)]
+        );
+    }
+
+    /// The function tests that removing a peer drops its pending request.
+    #[test]
+    fn retransmission_requests_dropped_for_removed_peer() {
+        let manager = new_test_retransmission_manager();
+        manager.schedule(node_test_id(1));
+        manager.remove_peer(&node_test_id(1));
+        assert!(manager.take_due(Instant::now()).is_empty());
+    }
+}
@@ -0,0 +1,640 @@
+//! The simulation harness runs a network of nodes in a single process on a
+//! virtual clock, so that the interaction of gossip and consensus can be
+//! regression-tested deterministically.
+//!
+//! <h1>Overview</h1>
+//!
+//! The nodes of a simulation are connected through the loopback transport.
+//! With a [SimulationClock::Virtual] clock, all nodes run on a
+//! single-threaded runtime whose clock is paused: time only advances when all
+//! tasks are idle, and then jumps to the next timer. A simulation of minutes
+//! of network activity thus completes as fast as the nodes can process their
+//! messages, and the latency of the transport is virtual.
+//!
+//! A simulation is driven by a [Scenario], which schedules events at virtual
+//! times:
+//!
+//! a. Network conditions
+//!
+//!    The latency of messages follows a [LatencyDistribution]. The latency
+//!    and the drop rate of messages can change during the simulation.
+//!
+//! b. Partitions
+//!
+//!    The network can be partitioned into groups of nodes, and healed again.
+//!
+//! c. Churn
+//!
+//!    Nodes can be stopped and started again. A stopped node is dropped and
+//!    disconnected from its peers. When started again, the node is created
+//!    anew with a fresh transport.
+//!
+//! The random jitter and drops of messages are drawn from an RNG seeded with
+//! the seed of the simulation. Two runs with the same seed, the same scenario
+//! and deterministic nodes are thus identical. Note that nodes are only
+//! deterministic if they run all their work as tasks on the runtime of the
+//! simulation and use the clock of the runtime, i.e. `tokio::time`. Nodes
+//! using threads or the system clock still run in a simulation, but their
+//! interleaving with the network is not reproducible.
+//!
+//! The nodes are created by a closure that receives the ID of the node, the
+//! IDs of its peers, its transport and the handle of the runtime. To run
+//! replica stacks, the closure creates the networking stack of the node with
+//! the given transport and returns the running P2P runner. The artifact
+//! processors and the gossip timer of a replica stack run on threads with the
+//! system clock, so replica stacks are simulated with a
+//! [SimulationClock::Real] clock, and their progress is awaited with
+//! [Simulation::run_until_condition]. See the `n_node_simulation` test of
+//! this crate.
+
+use ic_interfaces::transport::Transport;
+use ic_transport::loopback::{LoopbackConfig, LoopbackHub};
+use ic_types::{NodeId, PrincipalId};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::{Handle, Runtime};
+use tokio::time::Instant;
+
+/// The distribution of the latency of messages.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LatencyDistribution {
+    /// Every message is delayed by the same latency.
+    Constant(Duration),
+
+    /// The latency of every message is drawn uniformly from the range.
+    Uniform { min: Duration, max: Duration },
+}
+
+impl LatencyDistribution {
+    /// Sets the latency and the jitter of the loopback config.
+    fn apply(&self, config: &mut LoopbackConfig) {
+        match self {
+            LatencyDistribution::Constant(latency) => {
+                config.latency = *latency;
+                config.jitter = Duration::from_millis(0);
+            }
+            LatencyDistribution::Uniform { min, max } => {
+                assert!(min <= max, "Invalid latency range {:?}..{:?}", min, max);
+                config.latency = *min;
+                config.jitter = *max - *min;
+            }
+        }
+    }
+}
+
+/// The clock that the nodes of a simulation run on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SimulationClock {
+    /// The nodes run on a single-threaded runtime whose clock is paused and
+    /// jumps to the next timer whenever all tasks are idle.
+    Virtual,
+
+    /// The nodes run on a multi-threaded runtime in real time.
+    Real,
+}
+
+/// The configuration of a simulation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SimulationConfig {
+    /// The number of nodes, which have the node test IDs `0..num_nodes`
+    pub num_nodes: u64,
+
+    /// The seed of the RNG of the network
+    pub seed: u64,
+
+    /// The initial distribution of the latency of messages
+    pub latency: LatencyDistribution,
+
+    /// The initial probability with which a message is dropped
+    pub drop_rate: f64,
+
+    /// The clock that the nodes run on
+    pub clock: SimulationClock,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        Self {
+            num_nodes: 4,
+            seed: 0,
+            latency: LatencyDistribution::Constant(Duration::from_millis(10)),
+            drop_rate: 0.0,
+            clock: SimulationClock::Virtual,
+        }
+    }
+}
+
+/// An event of a scenario.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SimulationEvent {
+    /// Changes the distribution of the latency of messages.
+    SetLatency(LatencyDistribution),
+
+    /// Changes the probability with which messages are dropped.
+    SetDropRate(f64),
+
+    /// Partitions the network into the given groups of nodes, see
+    /// `LoopbackHub::partition`.
+    Partition(Vec<Vec<NodeId>>),
+
+    /// Heals the partition of the network.
+    Heal,
+
+    /// Stops the node, if it is running.
+    StopNode(NodeId),
+
+    /// Starts the node, if it is stopped.
+    StartNode(NodeId),
+}
+
+/// The events of a simulation, with the virtual times at which they happen.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Scenario {
+    events: Vec<(Duration, SimulationEvent)>,
+}
+
+impl Scenario {
+    /// Creates an empty scenario.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an event at the given time since the start of the simulation.
+    /// Events at the same time happen in the order in which they were added.
+    pub fn at(mut self, time: Duration, event: SimulationEvent) -> Self {
+        self.events.push((time, event));
+        self
+    }
+}
+
+/// A network of nodes running on a virtual or a real clock.
+pub struct Simulation<N, F> {
+    /// The runtime of the nodes
+    runtime: Runtime,
+
+    /// The start of the simulation on the virtual clock
+    start: Instant,
+
+    /// The hub connecting the nodes
+    hub: Arc<LoopbackHub>,
+
+    /// The current network conditions
+    loopback_config: LoopbackConfig,
+
+    /// The IDs of all nodes
+    node_ids: Vec<NodeId>,
+
+    /// The running nodes
+    nodes: BTreeMap<NodeId, N>,
+
+    /// Creates a node from its ID, the IDs of its peers, its transport and the
+    /// handle of the runtime
+    make_node: F,
+}
+
+impl<N, F> Simulation<N, F>
+where
+    F: FnMut(NodeId, &[NodeId], Arc<dyn Transport>, Handle) -> N,
+{
+    /// Creates a simulation with the given configuration and starts all
+    /// nodes.
+    pub fn new(config: SimulationConfig, make_node: F) -> Self {
+        let runtime = match config.clock {
+            SimulationClock::Virtual => tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build(),
+            SimulationClock::Real => tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build(),
+        }
+        .expect("Failed to create the runtime of the simulation");
+        let start = runtime.block_on(async {
+            if config.clock == SimulationClock::Virtual {
+                tokio::time::pause();
+            }
+            Instant::now()
+        });
+        let mut loopback_config = LoopbackConfig {
+            drop_rate: config.drop_rate,
+            ..Default::default()
+        };
+        config.latency.apply(&mut loopback_config);
+        let hub = LoopbackHub::with_seed(loopback_config.clone(), config.seed);
+        let node_ids = (0..config.num_nodes)
+            .map(|n| NodeId::from(PrincipalId::new_node_test_id(n)))
+            .collect();
+        let mut simulation = Self {
+            runtime,
+            start,
+            hub,
+            loopback_config,
+            node_ids,
+            nodes: BTreeMap::new(),
+            make_node,
+        };
+        for node_id in simulation.node_ids.clone() {
+            simulation.start_node(node_id);
+        }
+        simulation
+    }
+
+    /// Returns the IDs of all nodes, running or not.
+    pub fn node_ids(&self) -> &[NodeId] {
+        &self.node_ids
+    }
+
+    /// Returns the node, if it is running.
+    pub fn node(&self, node_id: &NodeId) -> Option<&N> {
+        self.nodes.get(node_id)
+    }
+
+    /// Returns the hub connecting the nodes.
+    pub fn hub(&self) -> &Arc<LoopbackHub> {
+        &self.hub
+    }
+
+    /// Returns the handle of the runtime of the nodes.
+    pub fn handle(&self) -> Handle {
+        self.runtime.handle().clone()
+    }
+
+    /// Returns the virtual time since the start of the simulation.
+    pub fn now(&self) -> Duration {
+        let start = self.start;
+        self.runtime.block_on(async move { Instant::now() - start })
+    }
+
+    /// Runs the nodes until the given virtual time since the start of the
+    /// simulation. Does nothing if the time has already passed.
+    pub fn run_until(&mut self, time: Duration) {
+        let deadline = self.start + time;
+        self.runtime.block_on(tokio::time::sleep_until(deadline));
+    }
+
+    /// Runs the nodes until the condition holds, which is checked every
+    /// `interval`, but at most until the given time since the start of the
+    /// simulation. Returns whether the condition holds.
+    pub fn run_until_condition(
+        &mut self,
+        until: Duration,
+        interval: Duration,
+        mut condition: impl FnMut(&Self) -> bool,
+    ) -> bool {
+        loop {
+            if condition(self) {
+                return true;
+            }
+            let now = self.now();
+            if now >= until {
+                return false;
+            }
+            self.run_until((now + interval).min(until));
+        }
+    }
+
+    /// Runs the scenario, and then the nodes until the given virtual time
+    /// since the start of the simulation. Events scheduled after that time
+    /// are ignored.
+    pub fn run(&mut self, scenario: Scenario, until: Duration) {
+        let mut events = scenario.events;
+        // The sort is stable, so events at the same time keep their order.
+        events.sort_by_key(|(time, _)| *time);
+        for (time, event) in events.into_iter().take_while(|(time, _)| *time <= until) {
+            self.run_until(time);
+            self.apply(event);
+        }
+        self.run_until(until);
+    }
+
+    /// Applies the event at the current virtual time.
+    pub fn apply(&mut self, event: SimulationEvent) {
+        match event {
+            SimulationEvent::SetLatency(latency) => {
+                latency.apply(&mut self.loopback_config);
+                self.hub.set_config(self.loopback_config.clone());
+            }
+            SimulationEvent::SetDropRate(drop_rate) => {
+                self.loopback_config.drop_rate = drop_rate;
+                self.hub.set_config(self.loopback_config.clone());
+            }
+            SimulationEvent::Partition(groups) => self.hub.partition(&groups),
+            SimulationEvent::Heal => self.hub.heal(),
+            SimulationEvent::StopNode(node_id) => self.stop_node(node_id),
+            SimulationEvent::StartNode(node_id) => self.start_node(node_id),
+        }
+    }
+
+    /// Creates the node with a fresh transport, if it is not running.
+    fn start_node(&mut self, node_id: NodeId) {
+        if self.nodes.contains_key(&node_id) {
+            return;
+        }
+        let peer_ids: Vec<_> = self
+            .node_ids
+            .iter()
+            .copied()
+            .filter(|peer_id| *peer_id != node_id)
+            .collect();
+        let handle = self.handle();
+        let _guard = self.runtime.enter();
+        let transport = self.hub.create_transport(node_id, handle.clone());
+        let node = (self.make_node)(node_id, &peer_ids, transport as Arc<_>, handle);
+        self.nodes.insert(node_id, node);
+    }
+
+    /// Disconnects the node from its peers and drops it, if it is running.
+    fn stop_node(&mut self, node_id: NodeId) {
+        if let Some(node) = self.nodes.remove(&node_id) {
+            self.hub.detach(&node_id);
+            let _guard = self.runtime.enter();
+            drop(node);
+        }
+    }
+}
+
+impl<N, F> Drop for Simulation<N, F> {
+    fn drop(&mut self) {
+        // The nodes are dropped before the runtime, as they may use it.
+        let _guard = self.runtime.enter();
+        self.nodes.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use ic_interfaces::transport::{AsyncTransportEventHandler, SendError};
+    use ic_protobuf::registry::node::v1::NodeRecord;
+    use ic_types::transport::{
+        FlowId, FlowTag, TransportClientType, TransportErrorCode, TransportPayload,
+        TransportStateChange,
+    };
+    use ic_types::RegistryVersion;
+    use std::sync::Mutex;
+
+    /// A message received by a node: the virtual time, the sender and the
+    /// payload.
+    type Delivery = (Duration, NodeId, Vec<u8>);
+
+    /// Records the messages received by a node.
+    struct RecordingEventHandler {
+        start: Instant,
+        deliveries: Arc<Mutex<Vec<Delivery>>>,
+    }
+
+    #[async_trait]
+    impl AsyncTransportEventHandler for RecordingEventHandler {
+        async fn send_message(
+            &self,
+            flow: FlowId,
+            message: TransportPayload,
+        ) -> Result<(), SendError> {
+            self.deliveries.lock().unwrap().push((
+                Instant::now() - self.start,
+                flow.peer_id,
+                message.0.to_vec(),
+            ));
+            Ok(())
+        }
+
+        async fn state_changed(&self, _state_change: TransportStateChange) {}
+
+        async fn error(&self, _flow: FlowId, _error: TransportErrorCode) {}
+    }
+
+    /// A node that sends a numbered message to all peers every 100ms.
+    struct BroadcastNode {
+        deliveries: Arc<Mutex<Vec<Delivery>>>,
+        task: tokio::task::JoinHandle<()>,
+    }
+
+    impl Drop for BroadcastNode {
+        fn drop(&mut self) {
+            self.task.abort();
+        }
+    }
+
+    fn broadcast_node(
+        start: Instant,
+        peer_ids: &[NodeId],
+        transport: Arc<dyn Transport>,
+        handle: Handle,
+    ) -> BroadcastNode {
+        let deliveries = Arc::new(Mutex::new(Vec::new()));
+        transport
+            .register_client(
+                TransportClientType::P2P,
+                Arc::new(RecordingEventHandler {
+                    start,
+                    deliveries: deliveries.clone(),
+                }),
+            )
+            .unwrap();
+        for peer_id in peer_ids {
+            transport
+                .start_connections(
+                    TransportClientType::P2P,
+                    peer_id,
+                    &NodeRecord::default(),
+                    RegistryVersion::from(1),
+                )
+                .unwrap();
+        }
+        let peer_ids = peer_ids.to_vec();
+        let task = handle.spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_millis(100));
+            for counter in 0..=u8::MAX {
+                interval.tick().await;
+                for peer_id in &peer_ids {
+                    let _ = transport.send(
+                        TransportClientType::P2P,
+                        peer_id,
+                        FlowTag::from(0),
+                        TransportPayload::from(vec![counter]),
+                    );
+                }
+            }
+        });
+        BroadcastNode { deliveries, task }
+    }
+
+    fn node_id(n: u64) -> NodeId {
+        NodeId::from(PrincipalId::new_node_test_id(n))
+    }
+
+    /// Runs the scenario and returns the messages received by each node that
+    /// is running at the end.
+    fn run_scenario(
+        config: SimulationConfig,
+        scenario: Scenario,
+        until: Duration,
+    ) -> BTreeMap<NodeId, Vec<Delivery>> {
+        let start = Arc::new(Mutex::new(None));
+        let node_start = start.clone();
+        let make_node = move |_, peer_ids: &[NodeId], transport, handle| {
+            // All nodes record the time since the first node was created,
+            // which is the start of the simulation.
+            let start = *node_start.lock().unwrap().get_or_insert_with(Instant::now);
+            broadcast_node(start, peer_ids, transport, handle)
+        };
+        let mut simulation = Simulation::new(config, make_node);
+        simulation.run(scenario, until);
+        assert_eq!(simulation.now(), until);
+        simulation
+            .node_ids()
+            .iter()
+            .filter_map(|node_id| {
+                let node = simulation.node(node_id)?;
+                let deliveries = node.deliveries.lock().unwrap().clone();
+                Some((*node_id, deliveries))
+            })
+            .collect()
+    }
+
+    fn churn_scenario() -> Scenario {
+        Scenario::new()
+            .at(
+                Duration::from_millis(250),
+                SimulationEvent::SetLatency(LatencyDistribution::Uniform {
+                    min: Duration::from_millis(5),
+                    max: Duration::from_millis(80),
+                }),
+            )
+            .at(
+                Duration::from_millis(300),
+                SimulationEvent::SetDropRate(0.2),
+            )
+            .at(
+                Duration::from_millis(500),
+                SimulationEvent::Partition(vec![vec![node_id(0), node_id(1)]]),
+            )
+            .at(
+                Duration::from_millis(700),
+                SimulationEvent::StopNode(node_id(3)),
+            )
+            .at(Duration::from_millis(900), SimulationEvent::Heal)
+            .at(
+                Duration::from_millis(1100),
+                SimulationEvent::StartNode(node_id(3)),
+            )
+    }
+
+    #[test]
+    fn simulation_is_deterministic() {
+        let config = SimulationConfig::default();
+        let until = Duration::from_millis(1500);
+        let first = run_scenario(config.clone(), churn_scenario(), until);
+        let second = run_scenario(config, churn_scenario(), until);
+        assert_eq!(first.len(), 4);
+        assert!(first.values().all(|deliveries| !deliveries.is_empty()));
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn simulation_runs_until_the_condition_holds() {
+        let mut simulation = Simulation::new(
+            SimulationConfig::default(),
+            |_, peer_ids: &[NodeId], transport, handle| {
+                broadcast_node(Instant::now(), peer_ids, transport, handle)
+            },
+        );
+        // Node 0 receives three messages every 100ms, after 10ms latency.
+        assert!(simulation.run_until_condition(
+            Duration::from_secs(1),
+            Duration::from_millis(50),
+            |simulation| {
+                let node = simulation.node(&node_id(0)).unwrap();
+                node.deliveries.lock().unwrap().len() >= 6
+            }
+        ));
+        assert_eq!(simulation.now(), Duration::from_millis(150));
+
+        assert!(!simulation.run_until_condition(
+            Duration::from_millis(200),
+            Duration::from_millis(50),
+            |_| false
+        ));
+        assert_eq!(simulation.now(), Duration::from_millis(200));
+    }
+
+    #[test]
+    fn simulation_applies_constant_latency() {
+        let config = SimulationConfig {
+            num_nodes: 2,
+            latency: LatencyDistribution::Constant(Duration::from_millis(30)),
+            ..Default::default()
+        };
+        let deliveries = run_scenario(config, Scenario::new(), Duration::from_millis(250));
+        // The messages sent at 0ms, 100ms and 200ms arrive 30ms later.
+        let expected: Vec<_> = (0..3u8)
+            .map(|counter| {
+                (
+                    Duration::from_millis(100 * counter as u64 + 30),
+                    node_id(1),
+                    vec![counter],
+                )
+            })
+            .collect();
+        assert_eq!(deliveries[&node_id(0)], expected);
+    }
+
+    #[test]
+    fn simulation_partitions_the_network() {
+        let scenario = Scenario::new().at(
+            Duration::from_millis(50),
+            SimulationEvent::Partition(vec![vec![node_id(0), node_id(1)]]),
+        );
+        let deliveries = run_scenario(
+            SimulationConfig::default(),
+            scenario,
+            Duration::from_millis(500),
+        );
+        // After the partition, node 0 only hears from node 1.
+        let senders: Vec<_> = deliveries[&node_id(0)]
+            .iter()
+            .filter(|(time, _, _)| *time > Duration::from_millis(50))
+            .map(|(_, sender, _)| *sender)
+            .collect();
+        assert!(!senders.is_empty());
+        assert!(senders.iter().all(|sender| *sender == node_id(1)));
+    }
+
+    #[test]
+    fn stopped_nodes_are_restarted_with_a_fresh_state() {
+        let scenario = Scenario::new()
+            .at(
+                Duration::from_millis(150),
+                SimulationEvent::StopNode(node_id(1)),
+            )
+            .at(
+                Duration::from_millis(350),
+                SimulationEvent::StartNode(node_id(1)),
+            );
+        let deliveries = run_scenario(
+            SimulationConfig::default(),
+            scenario,
+            Duration::from_millis(500),
+        );
+        // The restarted node counts from zero again, and nothing is received
+        // from it while it is stopped.
+        let from_node_1: Vec<_> = deliveries[&node_id(0)]
+            .iter()
+            .filter(|(_, sender, _)| *sender == node_id(1))
+            .map(|(time, _, payload)| (*time, payload[0]))
+            .collect();
+        assert!(from_node_1
+            .iter()
+            .all(|(time, _)| *time < Duration::from_millis(160)
+                || *time > Duration::from_millis(350)));
+        assert_eq!(
+            from_node_1
+                .iter()
+                .filter(|(time, _)| *time > Duration::from_millis(350))
+                .map(|(_, counter)| *counter)
+                .next(),
+            Some(0)
+        );
+    }
+}
@@ -1,3 +1,5 @@
 mod file_tree_artifact_mgr;
 mod p2p_runner;
-pub use p2p_runner::{replica_run_till_height, spawn_replicas_as_threads};
+mod simulated_replica;
+pub use p2p_runner::{replica_run_till_height, spawn_replicas_as_threads, P2P_TEST_START_SNAPSHOT};
+pub use simulated_replica::{simulated_replica, simulation_registry, SimulatedReplica};
@@ -1,12 +1,15 @@
 use crate::framework::file_tree_artifact_mgr::ArtifactChunkingTestImpl;
 use ic_config::execution_environment::Config as HypervisorConfig;
+use ic_config::metrics::DEFAULT_P2P_MAX_PEER_LABELS;
 use ic_config::subnet_config::SubnetConfigs;
+use ic_consensus::consensus::round_tracer::RoundTimelines;
 use ic_cycles_account_manager::CyclesAccountManager;
+use ic_event_log::EventLog;
 use ic_execution_environment::IngressHistoryReaderImpl;
 use ic_interfaces::{registry::RegistryClient, transport::Transport};
 use ic_logger::{debug, info, ReplicaLogger};
 use ic_metrics::MetricsRegistry;
-use ic_p2p::p2p::create_networking_stack;
+use ic_p2p::p2p::{create_networking_stack, P2PMode, PoolDumps};
 use ic_registry_client::client::RegistryClientImpl;
 use ic_registry_subnet_type::SubnetType;
 use ic_test_utilities::{
@@ -29,6 +32,9 @@ use tempfile::Builder;
 
 pub const P2P_TEST_END_BARRIER: &str = "TEST_END";
 pub const P2P_TEST_START_BARRIER: &str = "TEST_START";
+/// The name of the metrics snapshot taken right before a test starts, so that
+/// tests can assert on the change of the metrics during the test.
+pub const P2P_TEST_START_SNAPSHOT: &str = "test_start";
 
 /// Setup and execute a test for replica with Mock dependencies.
 /// Currently these components' mocked versions are used:
@@ -80,9 +86,12 @@ fn execute_test(
             subnet_config.cycles_account_manager_config,
         ));
 
-        let (_, p2p_runner, _) = create_networking_stack(
+        let (_, p2p_runner, _, _) = create_networking_stack(
             metrics_registry.clone(),
             log.clone(),
+            EventLog::disabled(),
+            RoundTimelines::default(),
+            PoolDumps::default(),
             tokio::runtime::Handle::current(),
             transport_config,
             artifact_pool_config,
@@ -94,6 +103,7 @@ fn execute_test(
             Arc::new(FakeTlsHandshake::new()),
             Arc::clone(&state_manager) as Arc<_>,
             no_state_sync_client,
+            P2PMode::Full,
             xnet_payload_builder as Arc<_>,
             message_router as Arc<_>,
             Arc::clone(&fake_crypto) as Arc<_>,
@@ -108,6 +118,11 @@ fn execute_test(
             cycles_account_manager,
             None,
             0,
+            DEFAULT_P2P_MAX_PEER_LABELS,
+            Default::default(),
+            true,
+            Vec::new(),
+            None,
         )
         .expect("Failed to initialize P2P");
 
@@ -123,6 +138,9 @@ fn execute_test(
 
         // Call the test
         test_synchronizer.wait_on_barrier(P2P_TEST_START_BARRIER.to_string());
+        p2p_test_context
+            .metrics_registry
+            .take_snapshot(P2P_TEST_START_SNAPSHOT);
         test(&mut p2p_test_context);
         std::mem::drop(p2p_test_context.p2p);
         test_synchronizer.wait_on_barrier(P2P_TEST_END_BARRIER.to_string());
@@ -232,9 +250,12 @@ fn execute_test_chunking_pool(
             subnet_config.cycles_account_manager_config,
         ));
 
-        let (_a, p2p_runner, _) = create_networking_stack(
+        let (_a, p2p_runner, _, _) = create_networking_stack(
             metrics_registry.clone(),
             log.clone(),
+            EventLog::disabled(),
+            RoundTimelines::default(),
+            PoolDumps::default(),
             tokio::runtime::Handle::current(),
             transport_config,
             artifact_pool_config,
@@ -246,6 +267,7 @@ fn execute_test_chunking_pool(
             Arc::new(FakeTlsHandshake::new()),
             Arc::clone(&state_manager) as Arc<_>,
             state_sync_client,
+            P2PMode::Full,
             xnet_payload_builder,
             message_router,
             Arc::clone(&fake_crypto) as Arc<_>,
@@ -260,6 +282,11 @@ fn execute_test_chunking_pool(
             cycles_account_manager,
             None,
             0,
+            DEFAULT_P2P_MAX_PEER_LABELS,
+            Default::default(),
+            true,
+            Vec::new(),
+            None,
         )
         .expect("Failed to initialize P2P");
 
@@ -275,6 +302,9 @@ fn execute_test_chunking_pool(
         println!("\n \n \n Starting p2p (SMS) test \n \n \n ");
         // Call the test
         test_synchronizer.wait_on_barrier(P2P_TEST_START_BARRIER.to_string());
+        p2p_test_context
+            .metrics_registry
+            .take_snapshot(P2P_TEST_START_SNAPSHOT);
         test(&mut p2p_test_context);
         std::mem::drop(p2p_test_context.p2p);
         test_synchronizer.wait_on_barrier(P2P_TEST_END_BARRIER.to_string());
@@ -0,0 +1,134 @@
+use ic_config::artifact_pool::ArtifactPoolConfig;
+use ic_config::execution_environment::Config as HypervisorConfig;
+use ic_config::metrics::DEFAULT_P2P_MAX_PEER_LABELS;
+use ic_config::subnet_config::SubnetConfigs;
+use ic_consensus::consensus::round_tracer::RoundTimelines;
+use ic_cycles_account_manager::CyclesAccountManager;
+use ic_event_log::EventLog;
+use ic_execution_environment::IngressHistoryReaderImpl;
+use ic_interfaces::{p2p::P2PRunner, registry::RegistryClient, transport::Transport};
+use ic_logger::ReplicaLogger;
+use ic_metrics::MetricsRegistry;
+use ic_p2p::p2p::{create_networking_stack, P2PMode, P2PStateSyncClient, PoolDumps};
+use ic_registry_client::client::RegistryClientImpl;
+use ic_registry_subnet_type::SubnetType;
+use ic_test_utilities::{
+    consensus::make_catch_up_package_with_empty_transcript,
+    crypto::fake_tls_handshake::FakeTlsHandshake, crypto::CryptoReturningOk,
+    message_routing::FakeMessageRouting, p2p::*, state_manager::FakeStateManager,
+    types::ids::subnet_test_id, xnet_payload_builder::FakeXNetPayloadBuilder,
+};
+use ic_types::{
+    consensus::catchup::CUPWithOriginalProtobuf, malicious_strategies::MaliciousBehaviors,
+    replica_config::ReplicaConfig, NodeId,
+};
+use std::sync::Arc;
+use tempfile::{Builder, TempDir};
+
+/// A replica stack with mock dependencies running in a simulation, see
+/// `ic_p2p::simulation`.
+pub struct SimulatedReplica {
+    pub metrics_registry: MetricsRegistry,
+    // Declared before the pool directory, so that the stack is stopped before
+    // its pool is removed.
+    _p2p: Box<dyn P2PRunner>,
+    _pool_dir: TempDir,
+}
+
+/// Returns a registry with a subnet of the given number of nodes with the
+/// node test IDs `0..num_nodes`. The ports of the nodes are not used by the
+/// loopback transport of a simulation.
+pub fn simulation_registry(num_nodes: u64) -> Arc<dyn RegistryClient> {
+    let node_port_allocation = (0..num_nodes).map(|n| 30_000 + n as u16).collect();
+    let data_provider = test_group_set_registry(
+        subnet_test_id(P2P_SUBNET_ID_DEFAULT),
+        Arc::new(node_port_allocation),
+    );
+    let registry_client = Arc::new(RegistryClientImpl::new(data_provider, None));
+    registry_client.fetch_and_start_polling().unwrap();
+    registry_client
+}
+
+/// Creates and runs the replica stack of the given node with the given
+/// malicious behaviors on the given transport and runtime. The same
+/// components are mocked as in `spawn_replicas_as_threads`.
+pub fn simulated_replica(
+    node_id: NodeId,
+    malicious_behaviors: MaliciousBehaviors,
+    registry: Arc<dyn RegistryClient>,
+    transport: Arc<dyn Transport>,
+    rt_handle: tokio::runtime::Handle,
+    log: ReplicaLogger,
+) -> SimulatedReplica {
+    let pool_dir = Builder::new()
+        .prefix("persistent-pool")
+        .tempdir()
+        .expect("Cannot create a pool directory");
+    let metrics_registry = MetricsRegistry::new();
+    let state_manager = Arc::new(FakeStateManager::new());
+    let subnet_id = subnet_test_id(P2P_SUBNET_ID_DEFAULT);
+    let replica_config = ReplicaConfig { node_id, subnet_id };
+    let transport_config = get_replica_transport_config(&replica_config, Arc::clone(&registry));
+    let message_router = Arc::new(FakeMessageRouting::with_state_manager(
+        Arc::clone(&state_manager) as Arc<_>,
+    ));
+    let fake_crypto = Arc::new(CryptoReturningOk::default());
+    let ingress_hist_reader = Box::new(IngressHistoryReaderImpl::new(
+        Arc::clone(&state_manager) as Arc<_>
+    ));
+    let subnet_config = SubnetConfigs::default().own_subnet_config(SubnetType::System);
+    let cycles_account_manager = Arc::new(CyclesAccountManager::new(
+        subnet_config.scheduler_config.max_instructions_per_message,
+        HypervisorConfig::default().max_cycles_per_canister,
+        SubnetType::System,
+        subnet_id,
+        subnet_config.cycles_account_manager_config,
+    ));
+
+    let (_, mut p2p_runner, _, _) = create_networking_stack(
+        metrics_registry.clone(),
+        log,
+        EventLog::disabled(),
+        RoundTimelines::default(),
+        PoolDumps::default(),
+        rt_handle,
+        transport_config,
+        ArtifactPoolConfig::new(pool_dir.path().to_path_buf()),
+        Default::default(),
+        malicious_behaviors,
+        node_id,
+        subnet_id,
+        Some(transport),
+        Arc::new(FakeTlsHandshake::new()),
+        Arc::clone(&state_manager) as Arc<_>,
+        P2PStateSyncClient::TestClient(),
+        P2PMode::Full,
+        Arc::new(FakeXNetPayloadBuilder::new()) as Arc<_>,
+        message_router as Arc<_>,
+        Arc::clone(&fake_crypto) as Arc<_>,
+        Arc::clone(&fake_crypto) as Arc<_>,
+        Arc::clone(&fake_crypto) as Arc<_>,
+        Arc::clone(&fake_crypto) as Arc<_>,
+        Arc::clone(&registry),
+        ingress_hist_reader,
+        CUPWithOriginalProtobuf::from_cup(make_catch_up_package_with_empty_transcript(
+            registry, subnet_id,
+        )),
+        cycles_account_manager,
+        None,
+        0,
+        DEFAULT_P2P_MAX_PEER_LABELS,
+        Default::default(),
+        true,
+        Vec::new(),
+        None,
+    )
+    .expect("Failed to initialize P2P");
+    p2p_runner.run();
+
+    SimulatedReplica {
+        metrics_registry,
+        _p2p: p2p_runner,
+        _pool_dir: pool_dir,
+    }
+}
@@ -7,7 +7,6 @@
 //!
 //! Note that most of the logic is driven by a test artifact manager.
 
-use ic_test_utilities::metrics::fetch_int_counter;
 use std::time::Duration;
 
 pub mod framework;
@@ -38,11 +37,11 @@ async fn n_node_chunking() {
                 panic!("Test exceeded {} iterations", MAX_ALLOWED_ITER);
             }
 
-            let artifacts_recv_count = fetch_int_counter(
-                &p2p_test_context.metrics_registry,
-                "gossip_artifacts_received",
-            )
-            .expect("Test cannot read gauge");
+            let artifacts_recv_count = p2p_test_context
+                .metrics_registry
+                .diff_since(framework::P2P_TEST_START_SNAPSHOT)
+                .expect("Test cannot read the metrics snapshot")
+                .counter("gossip_artifacts_received") as u64;
             println!(
                 "Node {:?}: Number of received artifacts: {}",
                 p2p_test_context.node_id, artifacts_recv_count
@@ -0,0 +1,184 @@
+//! Runs replica stacks in a simulated network, see `ic_p2p::simulation`.
+pub mod framework;
+
+use framework::{simulated_replica, simulation_registry, SimulatedReplica};
+use ic_interfaces::transport::Transport;
+use ic_logger::ReplicaLogger;
+use ic_p2p::simulation::{Simulation, SimulationClock, SimulationConfig, SimulationEvent};
+use ic_test_utilities::{
+    metrics::{fetch_int_counter, fetch_int_gauge},
+    p2p::p2p_test_setup_logger,
+    types::ids::node_test_id,
+};
+use ic_types::malicious_strategies::{
+    DelayAdverts, DropChunkRequests, Equivocate, MaliciousBehaviors, WithholdShares,
+};
+use ic_types::NodeId;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+/// The number of nodes of the subnet, which tolerates one faulty node.
+const NUM_NODES: u64 = 4;
+
+/// The time within which the nodes are expected to make progress.
+const PROGRESS_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The interval at which the progress of the nodes is checked.
+const CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Returns the finalized height of each running node.
+fn finalized_heights<F>(simulation: &Simulation<SimulatedReplica, F>) -> Vec<(NodeId, u64)>
+where
+    F: FnMut(NodeId, &[NodeId], Arc<dyn Transport>, Handle) -> SimulatedReplica,
+{
+    simulation
+        .node_ids()
+        .iter()
+        .filter_map(|node_id| {
+            let replica = simulation.node(node_id)?;
+            let height = fetch_int_gauge(
+                &replica.metrics_registry,
+                "consensus_pool_validated_finalization_max_height",
+            )
+            .unwrap_or(0);
+            Some((*node_id, height))
+        })
+        .collect()
+}
+
+/// Runs the simulation until all running nodes finalized the given height,
+/// and fails the test if they don't within the timeout.
+fn await_finalized_height<F>(simulation: &mut Simulation<SimulatedReplica, F>, height: u64)
+where
+    F: FnMut(NodeId, &[NodeId], Arc<dyn Transport>, Handle) -> SimulatedReplica,
+{
+    let until = simulation.now() + PROGRESS_TIMEOUT;
+    let finalized = simulation.run_until_condition(until, CHECK_INTERVAL, |simulation| {
+        finalized_heights(simulation)
+            .iter()
+            .all(|(_, finalized_height)| *finalized_height >= height)
+    });
+    assert!(
+        finalized,
+        "Nodes did not finalize height {}: {:?}",
+        height,
+        finalized_heights(simulation)
+    );
+}
+
+/// Returns the maximum finalized height of the running nodes.
+fn max_finalized_height<F>(simulation: &Simulation<SimulatedReplica, F>) -> u64
+where
+    F: FnMut(NodeId, &[NodeId], Arc<dyn Transport>, Handle) -> SimulatedReplica,
+{
+    finalized_heights(simulation)
+        .into_iter()
+        .map(|(_, height)| height)
+        .max()
+        .unwrap_or(0)
+}
+
+/// The subnet stops finalizing blocks while it is partitioned into two
+/// halves, and continues once the partition heals, also with one node
+/// stopped. A restarted node catches up with the others.
+#[test]
+fn n_node_simulation_with_partition_and_churn() {
+    // Keep the logger around until the end of the test, as it contains a
+    // guard that stops async logging on drop.
+    let logger = p2p_test_setup_logger();
+    let log: ReplicaLogger = logger.root.into();
+    let registry = simulation_registry(NUM_NODES);
+    let config = SimulationConfig {
+        num_nodes: NUM_NODES,
+        clock: SimulationClock::Real,
+        ..Default::default()
+    };
+    let mut simulation = Simulation::new(config, move |node_id, _, transport, handle| {
+        simulated_replica(
+            node_id,
+            MaliciousBehaviors::new(),
+            Arc::clone(&registry),
+            transport,
+            handle,
+            log.clone(),
+        )
+    });
+    await_finalized_height(&mut simulation, 2);
+
+    // No half of the subnet can notarize blocks on its own. Blocks that were
+    // already notarized when the network was partitioned may still be
+    // finalized, so the heights are compared once the partition settled.
+    simulation.apply(SimulationEvent::Partition(vec![
+        vec![node_test_id(0), node_test_id(1)],
+        vec![node_test_id(2), node_test_id(3)],
+    ]));
+    let now = simulation.now();
+    simulation.run_until(now + Duration::from_secs(3));
+    let partitioned_height = max_finalized_height(&simulation);
+    let now = simulation.now();
+    simulation.run_until(now + Duration::from_secs(3));
+    assert_eq!(max_finalized_height(&simulation), partitioned_height);
+
+    simulation.apply(SimulationEvent::Heal);
+    await_finalized_height(&mut simulation, partitioned_height + 2);
+
+    // The remaining three nodes still make progress.
+    simulation.apply(SimulationEvent::StopNode(node_test_id(3)));
+    let stopped_height = max_finalized_height(&simulation);
+    await_finalized_height(&mut simulation, stopped_height + 2);
+
+    // The restarted node starts from scratch and catches up by gossip.
+    simulation.apply(SimulationEvent::StartNode(node_test_id(3)));
+    let restarted_height = max_finalized_height(&simulation);
+    await_finalized_height(&mut simulation, restarted_height + 1);
+}
+
+/// A subnet with one malicious node still finalizes blocks. The malicious
+/// node drops the chunk requests of its peers, and, in builds with the
+/// `malicious_code` feature, also equivocates, withholds its shares and
+/// delays its adverts.
+#[test]
+fn n_node_simulation_with_a_malicious_node() {
+    let logger = p2p_test_setup_logger();
+    let log: ReplicaLogger = logger.root.into();
+    let registry = simulation_registry(NUM_NODES);
+    let malicious_node = node_test_id(0);
+    let config = SimulationConfig {
+        num_nodes: NUM_NODES,
+        clock: SimulationClock::Real,
+        ..Default::default()
+    };
+    let mut simulation = Simulation::new(config, move |node_id, _, transport, handle| {
+        let malicious_behaviors = if node_id == malicious_node {
+            MaliciousBehaviors::new()
+                .with(DropChunkRequests)
+                .with(Equivocate)
+                .with(WithholdShares)
+                .with(DelayAdverts {
+                    delay: Duration::from_millis(500),
+                })
+        } else {
+            MaliciousBehaviors::new()
+        };
+        simulated_replica(
+            node_id,
+            malicious_behaviors,
+            Arc::clone(&registry),
+            transport,
+            handle,
+            log.clone(),
+        )
+    });
+    await_finalized_height(&mut simulation, 5);
+
+    let chunks_sent = |node_id: NodeId| {
+        fetch_int_counter(
+            &simulation.node(&node_id).unwrap().metrics_registry,
+            "gossip_chunks_sent",
+        )
+        .unwrap_or(0)
+    };
+    assert_eq!(chunks_sent(malicious_node), 0);
+    assert!(chunks_sent(node_test_id(1)) > 0);
+}
@@ -77,6 +77,28 @@ use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Sub, SubAssign};
 /// assert_eq!(Apples::from(55), (1..=10_u64).map(Apples::from).sum());
 /// ```
 ///
+/// Amounts represented by unsigned integers also have checked and saturating
+/// arithmetics, for the cases where overflowing is not a bug:
+///
+/// ```
+/// use phantom_newtype::AmountOf;
+///
+/// enum MetricApple {}
+/// type Apples = AmountOf<MetricApple, u64>;
+///
+/// let x = Apples::from(5);
+/// let y = Apples::from(3);
+///
+/// assert_eq!(x.checked_sub(y), Some(Apples::from(2)));
+/// assert_eq!(y.checked_sub(x), None);
+/// assert_eq!(y.saturating_sub(x), Apples::from(0));
+/// assert_eq!(Apples::from(u64::MAX).checked_add(y), None);
+/// assert_eq!(Apples::from(u64::MAX).saturating_add(y), Apples::from(u64::MAX));
+/// assert_eq!(x.checked_decrement(), Some(Apples::from(4)));
+/// assert_eq!(Apples::from(0).checked_decrement(), None);
+/// assert_eq!(Apples::from(u64::MAX).checked_increment(), None);
+/// ```
+///
 /// Multiplication of amounts is not supported: multiplying meters by
 /// meters gives square meters. However, you can scale an amount by a
 /// scalar; divide amounts; or divide amounts by scalars:
@@ -348,6 +370,48 @@ where
     }
 }
 
+macro_rules! impl_checked_arithmetics {
+    ($($repr:ty),*) => {
+        $(
+            impl<Unit> AmountOf<Unit, $repr> {
+                /// Returns the sum of the amounts, or `None` on overflow.
+                pub fn checked_add(self, rhs: Self) -> Option<Self> {
+                    self.0.checked_add(rhs.0).map(Self::from)
+                }
+
+                /// Returns the difference of the amounts, or `None` if `rhs`
+                /// is larger than `self`.
+                pub fn checked_sub(self, rhs: Self) -> Option<Self> {
+                    self.0.checked_sub(rhs.0).map(Self::from)
+                }
+
+                /// Returns the sum of the amounts, saturating at the maximum.
+                pub fn saturating_add(self, rhs: Self) -> Self {
+                    Self::from(self.0.saturating_add(rhs.0))
+                }
+
+                /// Returns the difference of the amounts, saturating at zero.
+                pub fn saturating_sub(self, rhs: Self) -> Self {
+                    Self::from(self.0.saturating_sub(rhs.0))
+                }
+
+                /// Returns the amount incremented by 1, or `None` on overflow.
+                pub fn checked_increment(self) -> Option<Self> {
+                    self.0.checked_add(1).map(Self::from)
+                }
+
+                /// Returns the amount decremented by 1, or `None` if the amount
+                /// is zero.
+                pub fn checked_decrement(self) -> Option<Self> {
+                    self.0.checked_sub(1).map(Self::from)
+                }
+            }
+        )*
+    };
+}
+
+impl_checked_arithmetics!(u8, u16, u32, u64, u128, usize);
+
 impl<Unit, Repr> SubAssign for AmountOf<Unit, Repr>
 where
     Repr: SubAssign,
@@ -10,15 +10,16 @@ fn base_config() -> Config {
     let mut config = Config::new();
     // Use BTreeMap for all proto map fields.
     config.btree_map(&["."]);
-    config.file_descriptor_set_path(
-        // OUT_DIR is set by cargo
-        // https://doc.rust-lang.org/cargo/reference/environment-variables.html
-        PathBuf::from(env::var("OUT_DIR").expect("OUT_DIR environment variable not set"))
-            .join("protoc_file_descriptor_set.bin"),
-    );
+    config.file_descriptor_set_path(out_dir().join("protoc_file_descriptor_set.bin"));
     config
 }
 
+fn out_dir() -> PathBuf {
+    // OUT_DIR is set by cargo
+    // https://doc.rust-lang.org/cargo/reference/environment-variables.html
+    PathBuf::from(env::var("OUT_DIR").expect("OUT_DIR environment variable not set"))
+}
+
 /// Derives fields for protobuf log messages and optional fields
 macro_rules! add_log_proto_derives {
     ($prost_build:expr, $message_type:ident, $package:expr, $log_entry_field:ident $(,$message_field:ident)*) => {{
@@ -49,6 +50,7 @@ fn main() {
     build_messaging_proto();
     build_state_proto();
     build_p2p_proto();
+    build_released_p2p_proto();
 }
 
 /// Generates Rust structs from logging Protobuf messages.
@@ -191,6 +193,7 @@ fn build_registry_proto() {
     let registry_files = [
         "def/registry/conversion_rate/v1/conversion_rate.proto",
         "def/registry/crypto/v1/crypto.proto",
+        "def/registry/fee_schedule/v1/fee_schedule.proto",
         "def/registry/node_operator/v1/node_operator.proto",
         "def/registry/nns/v1/nns.proto",
         "def/registry/node/v1/node.proto",
@@ -268,11 +271,30 @@ fn build_crypto_proto() {
 fn build_p2p_proto() {
     let mut config = base_config();
     config.out_dir("gen/p2p");
+    config.file_descriptor_set_path(out_dir().join("p2p_file_descriptor_set.bin"));
     config.type_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]");
     let files = ["def/p2p/v1/p2p.proto"];
     compile_protos(config, &files);
 }
 
+/// Compiles the last released P2P protos, which are vendored in `released/`,
+/// to the file descriptor set that the current ones are checked against. Only
+/// `released/` is on the include path, so the protos they import have to be
+/// vendored as well, and changes of imported messages are checked too.
+fn build_released_p2p_proto() {
+    // The generated code is not used.
+    let generated_dir = out_dir().join("released");
+    std::fs::create_dir_all(&generated_dir).expect("Failed to create the output directory");
+    let mut config = base_config();
+    config.out_dir(generated_dir);
+    config.file_descriptor_set_path(out_dir().join("released_p2p_file_descriptor_set.bin"));
+    let proto_file = "released/p2p/v1/p2p.proto";
+    println!("cargo:rerun-if-changed={}", proto_file);
+    config
+        .compile_protos(&[proto_file], &["released/"])
+        .unwrap();
+}
+
 /// Compiles the given `proto_files` and emits `cargo:rerun-if-changed` outputs
 /// for each of them.
 fn compile_protos(mut config: Config, proto_files: &[&str]) {
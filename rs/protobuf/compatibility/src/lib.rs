@@ -0,0 +1,514 @@
+//! Checks that protobuf definitions stay compatible on the wire.
+//!
+//! Replicas of different versions exchange protobuf messages while a subnet
+//! is upgraded, so a change of a definition that breaks the wire format only
+//! surfaces on mixed-version subnets. To catch such changes earlier, the
+//! crates compiling protos also compile the last released version of them,
+//! which they vendor, and a test compares the file descriptor sets of both
+//! with [check_compatibility].
+//!
+//! The following changes are reported as incompatible:
+//! * a message or an enum is removed,
+//! * a field or an enum value is removed without reserving its number,
+//! * the type of a field changes to one with a different encoding, or the
+//!   field becomes repeated or stops being repeated,
+//! * a new field or enum value uses a number that was reserved.
+//!
+//! Adding messages, fields and enum values, and renaming any of them is
+//! compatible on the wire.
+use prost::Message;
+use prost_types::{
+    field_descriptor_proto::{Label, Type},
+    DescriptorProto, EnumDescriptorProto, FieldDescriptorProto, FileDescriptorSet,
+};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// A change of a protobuf definition that breaks the wire format. Messages
+/// and enums are identified by their fully qualified name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Incompatibility {
+    MessageRemoved {
+        message: String,
+    },
+    EnumRemoved {
+        enum_name: String,
+    },
+    FieldRemoved {
+        message: String,
+        field: String,
+        number: i32,
+    },
+    FieldTypeChanged {
+        message: String,
+        field: String,
+        number: i32,
+        released: String,
+        current: String,
+    },
+    FieldCardinalityChanged {
+        message: String,
+        field: String,
+        number: i32,
+    },
+    EnumValueRemoved {
+        enum_name: String,
+        value: String,
+        number: i32,
+    },
+    ReservedNumberReused {
+        parent: String,
+        name: String,
+        number: i32,
+    },
+}
+
+impl fmt::Display for Incompatibility {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Incompatibility::MessageRemoved { message } => {
+                write!(f, "message {} was removed", message)
+            }
+            Incompatibility::EnumRemoved { enum_name } => {
+                write!(f, "enum {} was removed", enum_name)
+            }
+            Incompatibility::FieldRemoved {
+                message,
+                field,
+                number,
+            } => write!(
+                f,
+                "field {}.{} = {} was removed without reserving its number",
+                message, field, number
+            ),
+            Incompatibility::FieldTypeChanged {
+                message,
+                field,
+                number,
+                released,
+                current,
+            } => write!(
+                f,
+                "field {}.{} = {} changed its type from {} to {}",
+                message, field, number, released, current
+            ),
+            Incompatibility::FieldCardinalityChanged {
+                message,
+                field,
+                number,
+            } => write!(
+                f,
+                "field {}.{} = {} became repeated or stopped being repeated",
+                message, field, number
+            ),
+            Incompatibility::EnumValueRemoved {
+                enum_name,
+                value,
+                number,
+            } => write!(
+                f,
+                "enum value {}.{} = {} was removed without reserving its number",
+                enum_name, value, number
+            ),
+            Incompatibility::ReservedNumberReused {
+                parent,
+                name,
+                number,
+            } => write!(f, "{}.{} uses the reserved number {}", parent, name, number),
+        }
+    }
+}
+
+/// Returns the changes from the `released` to the `current` definitions
+/// that break the wire format.
+pub fn check_compatibility(
+    released: &FileDescriptorSet,
+    current: &FileDescriptorSet,
+) -> Vec<Incompatibility> {
+    let released = Definitions::new(released);
+    let current = Definitions::new(current);
+    let mut incompatibilities = Vec::new();
+    for (name, released_message) in &released.messages {
+        match current.messages.get(name) {
+            Some(current_message) => check_message(
+                name,
+                released_message,
+                current_message,
+                &mut incompatibilities,
+            ),
+            None => incompatibilities.push(Incompatibility::MessageRemoved {
+                message: name.clone(),
+            }),
+        }
+    }
+    for (name, released_enum) in &released.enums {
+        match current.enums.get(name) {
+            Some(current_enum) => {
+                check_enum(name, released_enum, current_enum, &mut incompatibilities)
+            }
+            None => incompatibilities.push(Incompatibility::EnumRemoved {
+                enum_name: name.clone(),
+            }),
+        }
+    }
+    incompatibilities
+}
+
+/// Decodes the given encoded file descriptor sets and panics with a list of
+/// all incompatibilities if the `current` definitions are not compatible with
+/// the `released` ones. Meant to be called from a test.
+pub fn assert_compatible(released: &[u8], current: &[u8]) {
+    let released =
+        FileDescriptorSet::decode(released).expect("Failed to decode the released descriptors");
+    let current =
+        FileDescriptorSet::decode(current).expect("Failed to decode the current descriptors");
+    let incompatibilities = check_compatibility(&released, &current);
+    if !incompatibilities.is_empty() {
+        let list: Vec<_> = incompatibilities
+            .iter()
+            .map(|incompatibility| format!("  * {}", incompatibility))
+            .collect();
+        panic!(
+            "The protos are not compatible with the released ones on the wire:\n{}\n\
+             If the break is intended, e.g. because no released replica uses the \
+             changed messages, update the vendored released protos.",
+            list.join("\n")
+        );
+    }
+}
+
+/// The messages and enums of a file descriptor set, by fully qualified name.
+struct Definitions<'a> {
+    messages: BTreeMap<String, &'a DescriptorProto>,
+    enums: BTreeMap<String, &'a EnumDescriptorProto>,
+}
+
+impl<'a> Definitions<'a> {
+    fn new(set: &'a FileDescriptorSet) -> Self {
+        let mut definitions = Self {
+            messages: BTreeMap::new(),
+            enums: BTreeMap::new(),
+        };
+        for file in &set.file {
+            let scope = if file.package().is_empty() {
+                String::new()
+            } else {
+                format!(".{}", file.package())
+            };
+            for message in &file.message_type {
+                definitions.add_message(&scope, message);
+            }
+            for enum_type in &file.enum_type {
+                definitions.add_enum(&scope, enum_type);
+            }
+        }
+        definitions
+    }
+
+    fn add_message(&mut self, scope: &str, message: &'a DescriptorProto) {
+        let name = format!("{}.{}", scope, message.name());
+        for nested in &message.nested_type {
+            self.add_message(&name, nested);
+        }
+        for enum_type in &message.enum_type {
+            self.add_enum(&name, enum_type);
+        }
+        self.messages.insert(name, message);
+    }
+
+    fn add_enum(&mut self, scope: &str, enum_type: &'a EnumDescriptorProto) {
+        self.enums
+            .insert(format!("{}.{}", scope, enum_type.name()), enum_type);
+    }
+}
+
+fn check_message(
+    name: &str,
+    released: &DescriptorProto,
+    current: &DescriptorProto,
+    incompatibilities: &mut Vec<Incompatibility>,
+) {
+    let current_fields: BTreeMap<i32, &FieldDescriptorProto> = current
+        .field
+        .iter()
+        .map(|field| (field.number(), field))
+        .collect();
+    for released_field in &released.field {
+        let number = released_field.number();
+        let current_field = match current_fields.get(&number) {
+            Some(current_field) => current_field,
+            None => {
+                if !is_reserved_field(current, number) {
+                    incompatibilities.push(Incompatibility::FieldRemoved {
+                        message: name.to_string(),
+                        field: released_field.name().to_string(),
+                        number,
+                    });
+                }
+                continue;
+            }
+        };
+        if !have_compatible_types(released_field, current_field) {
+            incompatibilities.push(Incompatibility::FieldTypeChanged {
+                message: name.to_string(),
+                field: current_field.name().to_string(),
+                number,
+                released: type_description(released_field),
+                current: type_description(current_field),
+            });
+        }
+        if (released_field.label() == Label::Repeated) != (current_field.label() == Label::Repeated)
+        {
+            incompatibilities.push(Incompatibility::FieldCardinalityChanged {
+                message: name.to_string(),
+                field: current_field.name().to_string(),
+                number,
+            });
+        }
+    }
+    for current_field in &current.field {
+        if is_reserved_field(released, current_field.number()) {
+            incompatibilities.push(Incompatibility::ReservedNumberReused {
+                parent: name.to_string(),
+                name: current_field.name().to_string(),
+                number: current_field.number(),
+            });
+        }
+    }
+}
+
+fn check_enum(
+    name: &str,
+    released: &EnumDescriptorProto,
+    current: &EnumDescriptorProto,
+    incompatibilities: &mut Vec<Incompatibility>,
+) {
+    for released_value in &released.value {
+        let number = released_value.number();
+        if !current.value.iter().any(|value| value.number() == number)
+            && !is_reserved_enum_value(current, number)
+        {
+            incompatibilities.push(Incompatibility::EnumValueRemoved {
+                enum_name: name.to_string(),
+                value: released_value.name().to_string(),
+                number,
+            });
+        }
+    }
+    for current_value in &current.value {
+        if is_reserved_enum_value(released, current_value.number()) {
+            incompatibilities.push(Incompatibility::ReservedNumberReused {
+                parent: name.to_string(),
+                name: current_value.name().to_string(),
+                number: current_value.number(),
+            });
+        }
+    }
+}
+
+/// Whether the given field number is reserved in the message. The end of a
+/// reserved range of a message is exclusive.
+fn is_reserved_field(message: &DescriptorProto, number: i32) -> bool {
+    message
+        .reserved_range
+        .iter()
+        .any(|range| range.start() <= number && number < range.end())
+}
+
+/// Whether the given value is reserved in the enum. The end of a reserved
+/// range of an enum is inclusive.
+fn is_reserved_enum_value(enum_type: &EnumDescriptorProto, number: i32) -> bool {
+    enum_type
+        .reserved_range
+        .iter()
+        .any(|range| range.start() <= number && number <= range.end())
+}
+
+/// Whether a value of the released type of a field is decoded correctly as a
+/// value of its current type. Integer types that are encoded the same way
+/// are compatible, as are strings and bytes.
+fn have_compatible_types(released: &FieldDescriptorProto, current: &FieldDescriptorProto) -> bool {
+    fn class(field_type: Type) -> Option<u8> {
+        match field_type {
+            Type::Int32 | Type::Int64 | Type::Uint32 | Type::Uint64 | Type::Bool => Some(0),
+            Type::Sint32 | Type::Sint64 => Some(1),
+            Type::Fixed32 | Type::Sfixed32 => Some(2),
+            Type::Fixed64 | Type::Sfixed64 => Some(3),
+            Type::String | Type::Bytes => Some(4),
+            _ => None,
+        }
+    }
+    let (released_type, current_type) = (released.r#type(), current.r#type());
+    match (class(released_type), class(current_type)) {
+        (Some(released_class), Some(current_class)) => released_class == current_class,
+        // Messages, groups and enums must keep their type.
+        _ => released_type == current_type && released.type_name() == current.type_name(),
+    }
+}
+
+fn type_description(field: &FieldDescriptorProto) -> String {
+    match field.r#type() {
+        Type::Message | Type::Enum | Type::Group => field.type_name().to_string(),
+        field_type => format!("{:?}", field_type).to_lowercase(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prost_types::{
+        descriptor_proto::ReservedRange, EnumValueDescriptorProto, FileDescriptorProto,
+    };
+
+    fn field(name: &str, number: i32, field_type: Type) -> FieldDescriptorProto {
+        FieldDescriptorProto {
+            name: Some(name.to_string()),
+            number: Some(number),
+            label: Some(Label::Optional as i32),
+            r#type: Some(field_type as i32),
+            ..Default::default()
+        }
+    }
+
+    fn message(fields: Vec<FieldDescriptorProto>) -> DescriptorProto {
+        DescriptorProto {
+            name: Some("Advert".to_string()),
+            field: fields,
+            ..Default::default()
+        }
+    }
+
+    fn set(messages: Vec<DescriptorProto>, enums: Vec<EnumDescriptorProto>) -> FileDescriptorSet {
+        FileDescriptorSet {
+            file: vec![FileDescriptorProto {
+                package: Some("p2p.v1".to_string()),
+                message_type: messages,
+                enum_type: enums,
+                ..Default::default()
+            }],
+        }
+    }
+
+    #[test]
+    fn adding_and_renaming_fields_is_compatible() {
+        let released = set(vec![message(vec![field("id", 1, Type::Bytes)])], vec![]);
+        let current = set(
+            vec![message(vec![
+                field("artifact_id", 1, Type::String),
+                field("size", 2, Type::Uint64),
+            ])],
+            vec![],
+        );
+        assert_eq!(check_compatibility(&released, &current), vec![]);
+    }
+
+    #[test]
+    fn removing_a_field_requires_reserving_its_number() {
+        let released = set(
+            vec![message(vec![
+                field("id", 1, Type::Bytes),
+                field("size", 2, Type::Uint64),
+            ])],
+            vec![],
+        );
+        let current = set(vec![message(vec![field("id", 1, Type::Bytes)])], vec![]);
+        assert_eq!(
+            check_compatibility(&released, &current),
+            vec![Incompatibility::FieldRemoved {
+                message: ".p2p.v1.Advert".to_string(),
+                field: "size".to_string(),
+                number: 2,
+            }]
+        );
+
+        let mut reserving = message(vec![field("id", 1, Type::Bytes)]);
+        reserving.reserved_range.push(ReservedRange {
+            start: Some(2),
+            end: Some(3),
+        });
+        let current = set(vec![reserving], vec![]);
+        assert_eq!(check_compatibility(&released, &current), vec![]);
+
+        // Reusing the reserved number later is not compatible.
+        let reusing = set(
+            vec![message(vec![
+                field("id", 1, Type::Bytes),
+                field("priority", 2, Type::Int32),
+            ])],
+            vec![],
+        );
+        assert_eq!(
+            check_compatibility(&current, &reusing),
+            vec![Incompatibility::ReservedNumberReused {
+                parent: ".p2p.v1.Advert".to_string(),
+                name: "priority".to_string(),
+                number: 2,
+            }]
+        );
+    }
+
+    #[test]
+    fn changing_the_encoding_of_a_field_is_incompatible() {
+        let released = set(vec![message(vec![field("size", 1, Type::Uint64)])], vec![]);
+        let compatible = set(vec![message(vec![field("size", 1, Type::Int32)])], vec![]);
+        assert_eq!(check_compatibility(&released, &compatible), vec![]);
+
+        let incompatible = set(vec![message(vec![field("size", 1, Type::Fixed64)])], vec![]);
+        assert_eq!(
+            check_compatibility(&released, &incompatible),
+            vec![Incompatibility::FieldTypeChanged {
+                message: ".p2p.v1.Advert".to_string(),
+                field: "size".to_string(),
+                number: 1,
+                released: "uint64".to_string(),
+                current: "fixed64".to_string(),
+            }]
+        );
+
+        let mut repeated = field("size", 1, Type::Uint64);
+        repeated.label = Some(Label::Repeated as i32);
+        let repeated = set(vec![message(vec![repeated])], vec![]);
+        assert_eq!(
+            check_compatibility(&released, &repeated),
+            vec![Incompatibility::FieldCardinalityChanged {
+                message: ".p2p.v1.Advert".to_string(),
+                field: "size".to_string(),
+                number: 1,
+            }]
+        );
+    }
+
+    #[test]
+    fn removing_messages_and_enum_values_is_incompatible() {
+        let priority = |values: &[(&str, i32)]| EnumDescriptorProto {
+            name: Some("Priority".to_string()),
+            value: values
+                .iter()
+                .map(|(name, number)| EnumValueDescriptorProto {
+                    name: Some(name.to_string()),
+                    number: Some(*number),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        };
+        let released = set(
+            vec![message(vec![])],
+            vec![priority(&[("PRIORITY_LOW", 0), ("PRIORITY_HIGH", 1)])],
+        );
+        let current = set(vec![], vec![priority(&[("PRIORITY_LOW", 0)])]);
+        assert_eq!(
+            check_compatibility(&released, &current),
+            vec![
+                Incompatibility::MessageRemoved {
+                    message: ".p2p.v1.Advert".to_string(),
+                },
+                Incompatibility::EnumValueRemoved {
+                    enum_name: ".p2p.v1.Priority".to_string(),
+                    value: "PRIORITY_HIGH".to_string(),
+                    number: 1,
+                },
+            ]
+        );
+    }
+}
@@ -1,3 +1,17 @@
 #[path = "../gen/p2p/p2p.v1.rs"]
 #[rustfmt::skip]
 pub mod v1;
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn p2p_protos_are_compatible_with_the_released_ones() {
+        ic_protobuf_compatibility::assert_compatible(
+            include_bytes!(concat!(
+                env!("OUT_DIR"),
+                "/released_p2p_file_descriptor_set.bin"
+            )),
+            include_bytes!(concat!(env!("OUT_DIR"), "/p2p_file_descriptor_set.bin")),
+        );
+    }
+}
@@ -70,8 +70,11 @@ where
     M::Error: Into<ProxyDecodeError>,
 {
     fn proxy_encode(t: T) -> Result<Vec<u8>, EncodeError> {
-        let mut buf = vec![];
-        t.into().encode(&mut buf).map(|()| buf)
+        // Allocate the buffer upfront, so that large messages (e.g., state
+        // sync chunks) are not copied while the buffer grows.
+        let message: M = t.into();
+        let mut buf = Vec::with_capacity(message.encoded_len());
+        message.encode(&mut buf).map(|()| buf)
     }
 
     fn proxy_decode(bytes: &[u8]) -> Result<T, ProxyDecodeError> {
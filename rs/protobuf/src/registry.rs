@@ -1,5 +1,6 @@
 pub mod conversion_rate;
 pub mod crypto;
+pub mod fee_schedule;
 pub mod firewall;
 pub mod nns;
 pub mod node;
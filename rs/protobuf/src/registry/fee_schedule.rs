@@ -0,0 +1,3 @@
+#[path = "../../gen/registry/registry.fee_schedule.v1.rs"]
+#[rustfmt::skip]
+pub mod v1;
@@ -34,6 +34,7 @@ use registry_canister::{
         do_remove_node_directly::RemoveNodeDirectlyPayload, do_remove_nodes::RemoveNodesPayload,
         do_remove_nodes_from_subnet::RemoveNodesFromSubnetPayload,
         do_update_icp_xdr_conversion_rate::UpdateIcpXdrConversionRatePayload,
+        do_update_node_keys::UpdateNodeKeysPayload,
         do_update_node_operator_config::UpdateNodeOperatorConfigPayload,
         do_update_subnet::UpdateSubnetPayload,
         do_update_subnet_replica::UpdateSubnetReplicaVersionPayload,
@@ -45,6 +46,7 @@ use registry_canister::{
 
 #[cfg(target_arch = "wasm32")]
 use dfn_core::println;
+use registry_canister::mutations::do_set_fee_schedule::SetFeeSchedulePayload;
 use registry_canister::mutations::do_set_firewall_config::SetFirewallConfigPayload;
 
 fn main() {}
@@ -477,6 +479,23 @@ fn remove_node_directly() {
     });
 }
 
+#[export_name = "canister_update update_node_keys"]
+fn update_node_keys() {
+    // This method can be called by any node, for its own keys
+    let caller = dfn_core::api::caller();
+    println!(
+        "{}call: {} from: {}",
+        LOG_PREFIX,
+        "update_node_keys".to_string(),
+        caller
+    );
+    over_may_reject(candid_one, |payload: UpdateNodeKeysPayload| {
+        let result = registry_mut().do_update_node_keys(caller, payload);
+        recertify_registry();
+        result
+    });
+}
+
 #[export_name = "canister_update update_node_operator_config"]
 fn update_node_operator_config() {
     check_caller_is_governance_and_log("update_node_operator_config");
@@ -513,6 +532,15 @@ fn set_firewall_config() {
     });
 }
 
+#[export_name = "canister_update set_fee_schedule"]
+fn set_fee_schedule() {
+    check_caller_is_governance_and_log("set_fee_schedule");
+    over(candid_one, |payload: SetFeeSchedulePayload| {
+        registry_mut().do_set_fee_schedule(payload);
+        recertify_registry();
+    });
+}
+
 fn recertify_registry() {
     let witness_generator = witness_generator_mut();
     *witness_generator = registry_canister::certification::rebuild_tree(&*registry());
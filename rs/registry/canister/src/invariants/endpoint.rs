@@ -81,7 +81,9 @@ pub(crate) fn check_endpoint_invariants(
         //      address-port-pair of different flow endpoints of the same node can be
         //      the same, but the flow identifier must be different. However, 2 nodes
         //      can have he same flow endpoints.
-        let mut flow_ids = BTreeSet::<u32>::new();
+        //    * As an exception, a dual-stack node may have two endpoints with the same
+        //      flow identifier, one with an IPv4 and one with an IPv6 address.
+        let mut flow_ids = BTreeSet::<(u32, bool)>::new();
         for endpoint in node_record.p2p_flow_endpoints {
             let connection_endpoint = match endpoint.endpoint {
                 None => {
@@ -95,9 +97,9 @@ pub(crate) fn check_endpoint_invariants(
                 }
                 Some(ep) => ep,
             };
-            validate_endpoint(&connection_endpoint, strict)?;
+            let (ip, _) = validate_endpoint(&connection_endpoint, strict)?;
 
-            if flow_ids.contains(&endpoint.flow_tag) {
+            if !flow_ids.insert((endpoint.flow_tag, ip.is_ipv6())) {
                 return Err(InvariantCheckError {
                     msg: format!(
                         "Duplicate flow_tag for p2p flow endpoints: {}",
@@ -106,7 +108,6 @@ pub(crate) fn check_endpoint_invariants(
                     source: None,
                 });
             }
-            flow_ids.insert(endpoint.flow_tag);
         }
 
         if strict {
@@ -521,6 +522,52 @@ mod tests {
 
         snapshot.remove(&key);
 
+        // Add a dual-stack node with one endpoint per address family for a flow
+        let node_id = NodeId::from(PrincipalId::new_node_test_id(2));
+        let key = make_node_record_key(node_id).into_bytes();
+        snapshot.insert(
+            key.clone(),
+            encode_or_panic::<NodeRecord>(&NodeRecord {
+                node_operator_id: vec![0],
+                xnet: None,
+                http: None,
+                p2p_flow_endpoints: vec![
+                    FlowEndpoint {
+                        flow_tag: 1,
+                        endpoint: Some(ConnectionEndpoint {
+                            ip_addr: "200.1.1.3".to_string(),
+                            port: 8080,
+                            protocol: Protocol::P2p1Tls13 as i32,
+                        }),
+                    },
+                    FlowEndpoint {
+                        flow_tag: 1,
+                        endpoint: Some(ConnectionEndpoint {
+                            ip_addr: "2a00:fb01:400:100::3".to_string(),
+                            port: 8080,
+                            protocol: Protocol::P2p1Tls13 as i32,
+                        }),
+                    },
+                ],
+                prometheus_metrics_http: None,
+                public_api: vec![ConnectionEndpoint {
+                    ip_addr: "200.1.1.2".to_string(),
+                    port: 9000,
+                    protocol: Protocol::Http1 as i32,
+                }],
+                private_api: vec![],
+                prometheus_metrics: vec![],
+                xnet_api: vec![ConnectionEndpoint {
+                    ip_addr: "200.1.1.2".to_string(),
+                    port: 9001,
+                    protocol: Protocol::Http1 as i32,
+                }],
+            }),
+        );
+        assert!(check_endpoint_invariants(&snapshot, true).is_ok());
+
+        snapshot.remove(&key);
+
         // Add a node with conflicting flow IDs
         let node_id = NodeId::from(PrincipalId::new_node_test_id(2));
         let key = make_node_record_key(node_id).into_bytes();
@@ -175,6 +175,9 @@ impl Registry {
             max_instructions_per_message: payload.max_instructions_per_message,
             max_instructions_per_round: payload.max_instructions_per_round,
             max_instructions_per_install_code: payload.max_instructions_per_install_code,
+            max_ingress_ttl_millis: payload.max_ingress_ttl_millis,
+            ingress_clock_skew_tolerance_millis: payload.ingress_clock_skew_tolerance_millis,
+            block_maker_ranking: payload.block_maker_ranking,
         };
 
         // 4. Update registry with the new subnet data
@@ -261,6 +264,10 @@ pub struct CreateSubnetPayload {
     pub max_instructions_per_message: u64,
     pub max_instructions_per_round: u64,
     pub max_instructions_per_install_code: u64,
+    pub max_ingress_ttl_millis: u64,
+    pub ingress_clock_skew_tolerance_millis: u64,
+    // A `BlockMakerRanking` of the subnet record.
+    pub block_maker_ranking: i32,
 }
 
 impl From<CreateSubnetPayload> for SubnetRecord {
@@ -302,6 +309,9 @@ impl From<CreateSubnetPayload> for SubnetRecord {
             max_instructions_per_message: val.max_instructions_per_message,
             max_instructions_per_round: val.max_instructions_per_round,
             max_instructions_per_install_code: val.max_instructions_per_install_code,
+            max_ingress_ttl_millis: val.max_ingress_ttl_millis,
+            ingress_clock_skew_tolerance_millis: val.ingress_clock_skew_tolerance_millis,
+            block_maker_ranking: val.block_maker_ranking,
         }
     }
 }
@@ -0,0 +1,79 @@
+use crate::{
+    common::LOG_PREFIX,
+    mutations::common::{decode_registry_value, encode_or_panic},
+    registry::Registry,
+};
+
+use candid::{CandidType, Deserialize};
+#[cfg(target_arch = "wasm32")]
+use dfn_core::println;
+
+use ic_protobuf::registry::fee_schedule::v1::FeeScheduleRecord;
+use ic_registry_keys::make_fee_schedule_record_key;
+use ic_registry_transport::upsert;
+
+impl Registry {
+    /// Sets the fee schedule of application subnets.
+    ///
+    /// This method is called by the proposals canister.
+    pub fn do_set_fee_schedule(&mut self, payload: SetFeeSchedulePayload) {
+        println!("{}do_set_fee_schedule: {:?}", LOG_PREFIX, payload);
+
+        // The version identifies the fees in the logs and metrics of the
+        // replicas, so it must increase with every change.
+        let key = make_fee_schedule_record_key();
+        let current_version = self
+            .get(key.as_bytes(), self.latest_version())
+            .map(|value| decode_registry_value::<FeeScheduleRecord>(value.value.clone()).version)
+            .unwrap_or(0);
+        if payload.version <= current_version {
+            panic!(
+                "{}do_set_fee_schedule: The version {} must be greater than the current version {}",
+                LOG_PREFIX, payload.version, current_version
+            );
+        }
+
+        let mutations = vec![upsert(
+            key.into_bytes(),
+            encode_or_panic::<FeeScheduleRecord>(&payload.into()),
+        )];
+
+        // Check invariants before applying mutations
+        self.maybe_apply_mutation_internal(mutations);
+    }
+}
+
+/// The payload of a proposal to set the fee schedule of application subnets.
+///
+/// See /rs/protobuf/def/registry/fee_schedule/v1/fee_schedule.proto for the
+/// explanation of the fields, which are copied to the FeeScheduleRecord.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SetFeeSchedulePayload {
+    pub version: u64,
+    pub canister_creation_fee: u64,
+    pub update_message_execution_fee: u64,
+    pub ten_update_instructions_execution_fee: u64,
+    pub xnet_call_fee: u64,
+    pub xnet_byte_transmission_fee: u64,
+    pub ingress_message_reception_fee: u64,
+    pub ingress_byte_reception_fee: u64,
+    pub gib_storage_per_second_fee: u64,
+    pub compute_percent_allocated_per_second_fee: u64,
+}
+
+impl From<SetFeeSchedulePayload> for FeeScheduleRecord {
+    fn from(val: SetFeeSchedulePayload) -> Self {
+        FeeScheduleRecord {
+            version: val.version,
+            canister_creation_fee: val.canister_creation_fee,
+            update_message_execution_fee: val.update_message_execution_fee,
+            ten_update_instructions_execution_fee: val.ten_update_instructions_execution_fee,
+            xnet_call_fee: val.xnet_call_fee,
+            xnet_byte_transmission_fee: val.xnet_byte_transmission_fee,
+            ingress_message_reception_fee: val.ingress_message_reception_fee,
+            ingress_byte_reception_fee: val.ingress_byte_reception_fee,
+            gib_storage_per_second_fee: val.gib_storage_per_second_fee,
+            compute_percent_allocated_per_second_fee: val.compute_percent_allocated_per_second_fee,
+        }
+    }
+}
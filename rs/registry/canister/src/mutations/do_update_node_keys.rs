@@ -0,0 +1,182 @@
+use crate::{
+    common::LOG_PREFIX,
+    mutations::common::{decode_registry_value, encode_or_panic},
+    registry::Registry,
+};
+
+use candid::{CandidType, Deserialize};
+#[cfg(target_arch = "wasm32")]
+use dfn_core::println;
+
+use ic_base_types::{NodeId, PrincipalId};
+use ic_crypto_node_key_validation::ValidNodePublicKeys;
+use ic_protobuf::{
+    crypto::v1::NodePublicKeys,
+    registry::crypto::v1::{PublicKey, X509PublicKeyCert},
+};
+use ic_registry_keys::{make_crypto_node_key, make_crypto_tls_cert_key, make_node_record_key};
+use ic_registry_transport::update;
+use ic_types::crypto::KeyPurpose;
+
+use prost::Message;
+
+impl Registry {
+    /// Replaces the committee signing key and the TLS certificate of a node.
+    ///
+    /// This method is called directly by the node whose keys are rotated, so
+    /// the caller is the node's ID.
+    pub fn do_update_node_keys(
+        &mut self,
+        caller: PrincipalId,
+        payload: UpdateNodeKeysPayload,
+    ) -> Result<(), String> {
+        println!(
+            "{}do_update_node_keys: caller: {}, {:?}",
+            LOG_PREFIX, caller, payload
+        );
+        let node_id = NodeId::from(caller);
+
+        // 1. Check that the caller is a node in the registry
+        let node_key = make_node_record_key(node_id);
+        if self
+            .get(node_key.as_bytes(), self.latest_version())
+            .is_none()
+        {
+            return Err(format!(
+                "{}do_update_node_keys: Node Id {:} not found in the registry, aborting key update.",
+                LOG_PREFIX, node_id
+            ));
+        }
+
+        // 2. Validate the new keys together with the keys that stay the same
+        let current_key = |key_purpose| {
+            let key = make_crypto_node_key(node_id, key_purpose);
+            self.get(key.as_bytes(), self.latest_version())
+                .map(|value| decode_registry_value::<PublicKey>(value.value.clone()))
+                .ok_or_else(|| format!("{:?} key of node {} not found", key_purpose, node_id))
+        };
+        let valid_pks = valid_keys_from_payload(
+            node_id,
+            current_key(KeyPurpose::NodeSigning)?,
+            current_key(KeyPurpose::DkgDealingEncryption)?,
+            &payload,
+        )?;
+
+        // 3. Replace the keys
+        let mutations = vec![
+            update(
+                make_crypto_node_key(node_id, KeyPurpose::CommitteeSigning)
+                    .as_bytes()
+                    .to_vec(),
+                encode_or_panic(valid_pks.committee_signing_key()),
+            ),
+            update(
+                make_crypto_tls_cert_key(node_id).as_bytes().to_vec(),
+                encode_or_panic(valid_pks.tls_certificate()),
+            ),
+        ];
+
+        // Check invariants before applying mutations
+        self.maybe_apply_mutation_internal(mutations);
+
+        Ok(())
+    }
+}
+
+/// The payload of an update request of a node to replace its committee
+/// signing key and TLS certificate with newly generated ones.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct UpdateNodeKeysPayload {
+    // Raw bytes of the protobuf, but this should be a PublicKey
+    pub committee_signing_pk: Vec<u8>,
+    // Raw bytes of the protobuf, but this should be a X509PublicKeyCert
+    pub transport_tls_cert: Vec<u8>,
+}
+
+/// Validates the new keys in the payload together with the node's current
+/// keys that are not rotated.
+fn valid_keys_from_payload(
+    node_id: NodeId,
+    node_signing_pk: PublicKey,
+    dkg_dealing_encryption_pk: PublicKey,
+    payload: &UpdateNodeKeysPayload,
+) -> Result<ValidNodePublicKeys, String> {
+    let committee_signing_pk =
+        PublicKey::decode(&payload.committee_signing_pk[..]).map_err(|e| {
+            format!(
+                "committee_signing_pk is not in the expected format: {:?}",
+                e
+            )
+        })?;
+    let tls_certificate = X509PublicKeyCert::decode(&payload.transport_tls_cert[..])
+        .map_err(|e| format!("transport_tls_cert is not in the expected format: {:?}", e))?;
+
+    let node_pks = NodePublicKeys {
+        version: 1, // irrelevant
+        node_signing_pk: Some(node_signing_pk),
+        committee_signing_pk: Some(committee_signing_pk),
+        tls_certificate: Some(tls_certificate),
+        dkg_dealing_encryption_pk: Some(dkg_dealing_encryption_pk),
+    };
+    ValidNodePublicKeys::try_from(&node_pks, node_id)
+        .map_err(|e| format!("Could not validate public keys, due to {:?}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_crypto::utils::get_node_keys_or_generate_if_missing;
+    use ic_test_utilities::crypto::temp_dir::temp_dir;
+
+    fn protobuf_to_vec<M: Message>(entry: &M) -> Vec<u8> {
+        let mut buf: Vec<u8> = Vec::new();
+        entry.encode(&mut buf).expect("This must not fail");
+        buf
+    }
+
+    fn validate(
+        node_pks: &NodePublicKeys,
+        node_id: NodeId,
+        payload: &UpdateNodeKeysPayload,
+    ) -> Result<ValidNodePublicKeys, String> {
+        valid_keys_from_payload(
+            node_id,
+            node_pks.node_signing_pk.clone().unwrap(),
+            node_pks.dkg_dealing_encryption_pk.clone().unwrap(),
+            payload,
+        )
+    }
+
+    #[test]
+    fn keys_bound_to_another_node_are_rejected() {
+        let node_dir = temp_dir();
+        let (node_pks, node_id) = get_node_keys_or_generate_if_missing(node_dir.path());
+        let other_node_dir = temp_dir();
+        let (other_pks, _) = get_node_keys_or_generate_if_missing(other_node_dir.path());
+
+        let own_keys = UpdateNodeKeysPayload {
+            committee_signing_pk: protobuf_to_vec(node_pks.committee_signing_pk.as_ref().unwrap()),
+            transport_tls_cert: protobuf_to_vec(node_pks.tls_certificate.as_ref().unwrap()),
+        };
+        assert!(validate(&node_pks, node_id, &own_keys).is_ok());
+
+        // The TLS certificate of the other node is issued to the other node's ID.
+        let foreign_cert = UpdateNodeKeysPayload {
+            transport_tls_cert: protobuf_to_vec(other_pks.tls_certificate.as_ref().unwrap()),
+            ..own_keys
+        };
+        assert!(validate(&node_pks, node_id, &foreign_cert).is_err());
+    }
+
+    #[test]
+    fn malformed_committee_signing_key_is_rejected() {
+        let node_dir = temp_dir();
+        let (node_pks, node_id) = get_node_keys_or_generate_if_missing(node_dir.path());
+
+        let payload = UpdateNodeKeysPayload {
+            committee_signing_pk: vec![1, 2, 3],
+            transport_tls_cert: protobuf_to_vec(node_pks.tls_certificate.as_ref().unwrap()),
+        };
+        assert!(validate(&node_pks, node_id, &payload).is_err());
+    }
+}
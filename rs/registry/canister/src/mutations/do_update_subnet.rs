@@ -78,6 +78,10 @@ pub struct UpdateSubnetPayload {
     pub max_instructions_per_message: Option<u64>,
     pub max_instructions_per_round: Option<u64>,
     pub max_instructions_per_install_code: Option<u64>,
+    pub max_ingress_ttl_millis: Option<u64>,
+    pub ingress_clock_skew_tolerance_millis: Option<u64>,
+    // A `BlockMakerRanking` of the subnet record.
+    pub block_maker_ranking: Option<i32>,
 }
 
 #[macro_use]
@@ -147,6 +151,9 @@ fn merge_subnet_record(
         max_instructions_per_message,
         max_instructions_per_round,
         max_instructions_per_install_code,
+        max_ingress_ttl_millis,
+        ingress_clock_skew_tolerance_millis,
+        block_maker_ranking,
     } = payload;
 
     maybe_set!(subnet_record, ingress_bytes_per_block_soft_cap);
@@ -186,6 +193,9 @@ fn merge_subnet_record(
     maybe_set!(subnet_record, max_instructions_per_message);
     maybe_set!(subnet_record, max_instructions_per_round);
     maybe_set!(subnet_record, max_instructions_per_install_code);
+    maybe_set!(subnet_record, max_ingress_ttl_millis);
+    maybe_set!(subnet_record, ingress_clock_skew_tolerance_millis);
+    maybe_set!(subnet_record, block_maker_ranking);
     subnet_record
 }
 
@@ -226,6 +236,9 @@ mod tests {
             max_instructions_per_message: 5_000_000_000,
             max_instructions_per_round: 7_000_000_000,
             max_instructions_per_install_code: 200_000_000_000,
+            max_ingress_ttl_millis: 0,
+            ingress_clock_skew_tolerance_millis: 0,
+            block_maker_ranking: 0,
         };
 
         let payload = UpdateSubnetPayload {
@@ -257,6 +270,9 @@ mod tests {
             max_instructions_per_message: Some(6_000_000_000),
             max_instructions_per_round: Some(8_000_000_000),
             max_instructions_per_install_code: Some(300_000_000_000),
+            max_ingress_ttl_millis: Some(240_000),
+            ingress_clock_skew_tolerance_millis: Some(20_000),
+            block_maker_ranking: Some(2),
         };
 
         assert_eq!(
@@ -288,6 +304,9 @@ mod tests {
                 max_instructions_per_message: 6_000_000_000,
                 max_instructions_per_round: 8_000_000_000,
                 max_instructions_per_install_code: 300_000_000_000,
+                max_ingress_ttl_millis: 240_000,
+                ingress_clock_skew_tolerance_millis: 20_000,
+                block_maker_ranking: 2,
             }
         );
     }
@@ -321,6 +340,9 @@ mod tests {
             max_instructions_per_message: 5_000_000_000,
             max_instructions_per_round: 7_000_000_000,
             max_instructions_per_install_code: 200_000_000_000,
+            max_ingress_ttl_millis: 0,
+            ingress_clock_skew_tolerance_millis: 0,
+            block_maker_ranking: 0,
         };
 
         let payload = UpdateSubnetPayload {
@@ -352,6 +374,9 @@ mod tests {
             max_instructions_per_message: None,
             max_instructions_per_round: Some(8_000_000_000),
             max_instructions_per_install_code: None,
+            max_ingress_ttl_millis: None,
+            ingress_clock_skew_tolerance_millis: None,
+            block_maker_ranking: None,
         };
 
         assert_eq!(
@@ -383,6 +408,9 @@ mod tests {
                 max_instructions_per_message: 5_000_000_000,
                 max_instructions_per_round: 8_000_000_000,
                 max_instructions_per_install_code: 200_000_000_000,
+                max_ingress_ttl_millis: 0,
+                ingress_clock_skew_tolerance_millis: 0,
+                block_maker_ranking: 0,
             }
         );
     }
@@ -411,6 +439,9 @@ mod tests {
             max_instructions_per_message: 5_000_000_000,
             max_instructions_per_round: 7_000_000_000,
             max_instructions_per_install_code: 200_000_000_000,
+            max_ingress_ttl_millis: 0,
+            ingress_clock_skew_tolerance_millis: 0,
+            block_maker_ranking: 0,
         };
 
         let payload = UpdateSubnetPayload {
@@ -442,6 +473,9 @@ mod tests {
             max_instructions_per_message: None,
             max_instructions_per_round: None,
             max_instructions_per_install_code: None,
+            max_ingress_ttl_millis: None,
+            ingress_clock_skew_tolerance_millis: None,
+            block_maker_ranking: None,
         };
 
         merge_subnet_record(subnet_record, payload);
@@ -467,6 +501,9 @@ mod tests {
             max_instructions_per_message: 5_000_000_000,
             max_instructions_per_round: 7_000_000_000,
             max_instructions_per_install_code: 200_000_000_000,
+            max_ingress_ttl_millis: 0,
+            ingress_clock_skew_tolerance_millis: 0,
+            block_maker_ranking: 0,
         };
 
         let payload = UpdateSubnetPayload {
@@ -498,6 +535,9 @@ mod tests {
             max_instructions_per_message: None,
             max_instructions_per_round: None,
             max_instructions_per_install_code: None,
+            max_ingress_ttl_millis: None,
+            ingress_clock_skew_tolerance_millis: None,
+            block_maker_ranking: None,
         };
 
         assert_eq!(
@@ -529,6 +569,9 @@ mod tests {
                 max_instructions_per_message: 5_000_000_000,
                 max_instructions_per_round: 7_000_000_000,
                 max_instructions_per_install_code: 200_000_000_000,
+                max_ingress_ttl_millis: 0,
+                ingress_clock_skew_tolerance_millis: 0,
+                block_maker_ranking: 0,
             }
         );
     }
@@ -11,8 +11,10 @@ pub mod do_recover_subnet;
 pub mod do_remove_node_directly;
 pub mod do_remove_nodes;
 pub mod do_remove_nodes_from_subnet;
+pub mod do_set_fee_schedule;
 pub mod do_set_firewall_config;
 pub mod do_update_icp_xdr_conversion_rate;
+pub mod do_update_node_keys;
 pub mod do_update_node_operator_config;
 pub mod do_update_subnet;
 pub mod do_update_subnet_replica;
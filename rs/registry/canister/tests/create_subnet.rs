@@ -125,6 +125,9 @@ fn test_the_anonymous_user_cannot_create_a_subnet() {
             max_instructions_per_message: 5_000_000_000,
             max_instructions_per_round: 7_000_000_000,
             max_instructions_per_install_code: 200_000_000_000,
+            max_ingress_ttl_millis: 0,
+            ingress_clock_skew_tolerance_millis: 0,
+            block_maker_ranking: 0,
         };
 
         // The anonymous end-user tries to create a subnet, bypassing the proposals
@@ -209,6 +212,9 @@ fn test_a_canister_other_than_the_proposals_canister_cannot_create_a_subnet() {
             max_instructions_per_message: 5_000_000_000,
             max_instructions_per_round: 7_000_000_000,
             max_instructions_per_install_code: 200_000_000_000,
+            max_ingress_ttl_millis: 0,
+            ingress_clock_skew_tolerance_millis: 0,
+            block_maker_ranking: 0,
         };
 
         // The attacker canister tries to create a subnet, pretending to be the
@@ -303,6 +309,9 @@ fn test_accepted_proposal_mutates_the_registry_no_subnet_apriori() {
             max_instructions_per_message: 5_000_000_000,
             max_instructions_per_round: 7_000_000_000,
             max_instructions_per_install_code: 200_000_000_000,
+            max_ingress_ttl_millis: 0,
+            ingress_clock_skew_tolerance_millis: 0,
+            block_maker_ranking: 0,
         };
 
         assert!(
@@ -453,6 +462,9 @@ fn test_accepted_proposal_mutates_the_registry_some_subnets_present() {
             max_instructions_per_message: 5_000_000_000,
             max_instructions_per_round: 7_000_000_000,
             max_instructions_per_install_code: 200_000_000_000,
+            max_ingress_ttl_millis: 0,
+            ingress_clock_skew_tolerance_millis: 0,
+            block_maker_ranking: 0,
         };
 
         assert!(
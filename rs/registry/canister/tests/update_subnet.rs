@@ -63,6 +63,9 @@ fn test_the_anonymous_user_cannot_update_a_subnets_configuration() {
             max_instructions_per_message: Some(6_000_000_000),
             max_instructions_per_round: Some(8_000_000_000),
             max_instructions_per_install_code: Some(300_000_000_000),
+            max_ingress_ttl_millis: None,
+            ingress_clock_skew_tolerance_millis: None,
+            block_maker_ranking: None,
         };
 
         // The anonymous end-user tries to update a subnet's configuration, bypassing
@@ -133,6 +136,9 @@ fn test_a_canister_other_than_the_proposals_canister_cannot_update_a_subnets_con
             max_instructions_per_message: 5_000_000_000,
             max_instructions_per_round: 7_000_000_000,
             max_instructions_per_install_code: 200_000_000_000,
+            max_ingress_ttl_millis: 0,
+            ingress_clock_skew_tolerance_millis: 0,
+            block_maker_ranking: 0,
         };
 
         // An attacker got a canister that is trying to pass for the proposals
@@ -184,6 +190,9 @@ fn test_a_canister_other_than_the_proposals_canister_cannot_update_a_subnets_con
             max_instructions_per_message: Some(6_000_000_000),
             max_instructions_per_round: Some(8_000_000_000),
             max_instructions_per_install_code: Some(300_000_000_000),
+            max_ingress_ttl_millis: None,
+            ingress_clock_skew_tolerance_millis: None,
+            block_maker_ranking: None,
         };
 
         // The attacker canister tries to update the subnet's configuration, pretending
@@ -255,6 +264,9 @@ fn test_the_proposals_canister_can_update_a_subnets_configuration() {
                             max_instructions_per_message: 5_000_000_000,
                             max_instructions_per_round: 7_000_000_000,
                             max_instructions_per_install_code: 200_000_000_000,
+                            max_ingress_ttl_millis: 0,
+                            ingress_clock_skew_tolerance_millis: 0,
+                            block_maker_ranking: 0,
                         }),
                     )],
                     preconditions: vec![],
@@ -297,6 +309,9 @@ fn test_the_proposals_canister_can_update_a_subnets_configuration() {
             max_instructions_per_message: Some(6_000_000_000),
             max_instructions_per_round: Some(8_000_000_000),
             max_instructions_per_install_code: Some(300_000_000_000),
+            max_ingress_ttl_millis: None,
+            ingress_clock_skew_tolerance_millis: None,
+            block_maker_ranking: None,
         };
 
         // Attempt to update the subnet's configuration. Since the update happens from
@@ -344,6 +359,9 @@ fn test_the_proposals_canister_can_update_a_subnets_configuration() {
                 max_instructions_per_message: 6_000_000_000,
                 max_instructions_per_round: 8_000_000_000,
                 max_instructions_per_install_code: 300_000_000_000,
+                max_ingress_ttl_millis: 0,
+                ingress_clock_skew_tolerance_millis: 0,
+                block_maker_ranking: 0,
             }
         );
 
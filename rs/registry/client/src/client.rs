@@ -9,6 +9,7 @@ pub use ic_interfaces::registry::{
 use ic_metrics::MetricsRegistry;
 use ic_registry_common::local_store::LocalStoreImpl;
 use ic_registry_common::{
+    certified_delta_pool::CertifiedDeltaPool,
     data_provider::{CertifiedNnsDataProvider, NnsDataProvider},
     proto_registry_data_provider::ProtoRegistryDataProvider,
     registry::RegistryCanister,
@@ -22,14 +23,20 @@ pub use ic_types::{
 use std::collections::BTreeMap;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, RwLock, RwLockReadGuard};
+use tokio::sync::watch;
 use url::Url;
 
 use crate::metrics::Metrics;
+use crate::snapshot_cache::{RegistrySnapshotCache, SnapshotWriter};
+use crate::subscriptions::Subscriptions;
 
 #[derive(Clone)]
 pub struct RegistryClientImpl {
     cache: Arc<RwLock<CacheState>>,
     data_provider: Arc<dyn RegistryDataProvider>,
+    snapshot_cache: Option<Arc<RegistrySnapshotCache>>,
+    snapshot_writer: Option<Arc<SnapshotWriter>>,
+    subscriptions: Arc<Subscriptions>,
     metrics: Arc<Metrics>,
     started: Arc<AtomicBool>,
     cancelled: Arc<AtomicBool>,
@@ -51,14 +58,44 @@ impl RegistryClientImpl {
         Self {
             cache: Arc::new(RwLock::new(CacheState::new())),
             data_provider,
+            snapshot_cache: None,
+            snapshot_writer: None,
+            subscriptions: Default::default(),
             metrics,
             started: Arc::new(AtomicBool::new(false)),
             cancelled: Arc::new(AtomicBool::new(false)),
         }
     }
 
+    /// Creates a new instance of the RegistryClient that persists the fetched
+    /// registry versions to `snapshot_cache` and serves them at startup if
+    /// the data provider is unreachable. Only the versions the data provider
+    /// returns with a certified time are persisted.
+    pub fn new_with_snapshot_cache(
+        data_provider: Arc<dyn RegistryDataProvider>,
+        snapshot_cache: RegistrySnapshotCache,
+        metrics_registry: Option<&MetricsRegistry>,
+    ) -> Self {
+        let mut client = Self::new(data_provider, metrics_registry);
+        let snapshot_cache = Arc::new(snapshot_cache);
+        let metrics = Arc::clone(&client.metrics);
+        client.snapshot_writer = Some(Arc::new(SnapshotWriter::new(
+            Arc::clone(&snapshot_cache),
+            move |_err| {
+                metrics
+                    .snapshot_cache_errors
+                    .with_label_values(&["persist"])
+                    .inc()
+            },
+        )));
+        client.snapshot_cache = Some(snapshot_cache);
+        client
+    }
+
     /// Calls `poll_once()` synchronously, if it succeeds a background task is
-    /// spawned that continuously polls for updates.
+    /// spawned that continuously polls for updates. If it fails, the client
+    /// falls back to the snapshot cache (if any) and the background task keeps
+    /// polling until the data provider becomes reachable.
     /// The background task is stopped when the object is dropped.
     pub fn fetch_and_start_polling(&self) -> Result<(), RegistryClientError> {
         // TODO(IDX-1862)
@@ -71,7 +108,11 @@ impl RegistryClientImpl {
                 error: "'fetch_and_start_polling' already called".to_string(),
             });
         }
-        self.poll_once()?;
+        if let Err(err) = self.poll_once() {
+            if !self.load_snapshot() {
+                return Err(err);
+            }
+        }
         let cancelled = Arc::clone(&self.cancelled);
         let self_ = self.clone();
         tokio::spawn(async move {
@@ -92,12 +133,15 @@ impl RegistryClientImpl {
     pub fn poll_once(&self) -> Result<(), RegistryClientError> {
         let (records, version) = {
             let latest_version = self.cache.read().unwrap().latest_version;
-            let records = match self
+            let (records, certified_time) = match self
                 .data_provider
-                .get_updates_since(latest_version)
+                .get_certified_updates_since(latest_version)
             {
-                Ok(records) if !records.is_empty() => records,
-                Ok(_) /*if version == cache_state.latest_version*/ => return Ok(()),
+                Ok((records, certified_time)) if !records.is_empty() => (records, certified_time),
+                Ok((_, certified_time)) /*if version == cache_state.latest_version*/ => {
+                    self.persist_snapshot(latest_version, &[], certified_time);
+                    return Ok(());
+                }
                 Err(e) => return Err(RegistryClientError::from(e)),
             };
             let new_version = records
@@ -105,6 +149,7 @@ impl RegistryClientImpl {
                 .max_by_key(|r| r.version)
                 .map(|r| r.version)
                 .unwrap_or(latest_version);
+            self.persist_snapshot(latest_version, &records, certified_time);
 
             (records, new_version)
         };
@@ -115,6 +160,9 @@ impl RegistryClientImpl {
         // Check version again under write lock, to prevent race conditions.
         if version > cache_state.latest_version {
             self.metrics.registry_version.set(version.get() as i64);
+            // The subscribers cannot read the new version before the write
+            // lock is released.
+            self.subscriptions.notify(&records);
             cache_state.update(records, version);
         }
         Ok(())
@@ -147,6 +195,55 @@ impl RegistryClientImpl {
         Err(RegistryClientError::PollingLatestVersionFailed { retries })
     }
 
+    /// Serves the registry versions from the snapshot cache, if there is a
+    /// snapshot that is recent enough. Returns false otherwise.
+    fn load_snapshot(&self) -> bool {
+        let snapshot_cache = match &self.snapshot_cache {
+            Some(snapshot_cache) => snapshot_cache,
+            None => return false,
+        };
+        let records = match snapshot_cache.load(current_time()) {
+            Ok(records) => records,
+            Err(_) => {
+                self.metrics
+                    .snapshot_cache_errors
+                    .with_label_values(&["load"])
+                    .inc();
+                return false;
+            }
+        };
+
+        let mut cache_state = self.cache.write().unwrap();
+        let records: Vec<_> = records
+            .into_iter()
+            .filter(|r| r.version > cache_state.latest_version)
+            .collect();
+        if let Some(version) = records.iter().map(|r| r.version).max() {
+            self.metrics.registry_version.set(version.get() as i64);
+            // The subscribers cannot read the new version before the write
+            // lock is released.
+            self.subscriptions.notify(&records);
+            cache_state.update(records, version);
+        }
+        true
+    }
+
+    /// Queues the `records` fetched for the versions above `since` to be
+    /// persisted to the snapshot cache, if any and if the records are
+    /// certified.
+    fn persist_snapshot(
+        &self,
+        since: RegistryVersion,
+        records: &[RegistryTransportRecord],
+        certified_time: Option<Time>,
+    ) {
+        if let (Some(snapshot_writer), Some(certified_time)) =
+            (&self.snapshot_writer, certified_time)
+        {
+            snapshot_writer.persist(since, records.to_vec(), certified_time);
+        }
+    }
+
     fn check_version(
         &self,
         version: RegistryVersion,
@@ -169,16 +266,27 @@ impl Drop for RegistryClientImpl {
 /// `DataProviderConfig::Bootstrap` and
 /// `DataProviderConfig::RegistryCanisterUrl`, a corresponding
 /// `ThresholdSigPublicKey` can be provided to verify certified updates provided
-/// by the registry canister.
+/// by the registry canister. The certified updates are then shared with the
+/// other nodes of the subnet through the `optional_delta_pool`, if provided.
 pub fn create_data_provider(
     data_provider_config: &DataProviderConfig,
     optional_nns_public_key: Option<ThresholdSigPublicKey>,
+    optional_delta_pool: Option<Arc<CertifiedDeltaPool>>,
 ) -> Arc<dyn RegistryDataProvider> {
     let nns_data_provider = |urls: Vec<Url>| -> Arc<dyn RegistryDataProvider> {
         let registry_canister = RegistryCanister::new(urls);
-        match optional_nns_public_key {
-            Some(nns_pk) => Arc::new(CertifiedNnsDataProvider::new(registry_canister, nns_pk)),
-            None => Arc::new(NnsDataProvider::new(registry_canister)),
+        match (optional_nns_public_key, optional_delta_pool) {
+            (Some(nns_pk), Some(delta_pool)) => {
+                Arc::new(CertifiedNnsDataProvider::new_with_delta_pool(
+                    registry_canister,
+                    nns_pk,
+                    delta_pool,
+                ))
+            }
+            (Some(nns_pk), None) => {
+                Arc::new(CertifiedNnsDataProvider::new(registry_canister, nns_pk))
+            }
+            (None, _) => Arc::new(NnsDataProvider::new(registry_canister)),
         }
     };
 
@@ -355,6 +463,12 @@ impl RegistryClient for RegistryClientImpl {
             .get(&registry_version)
             .cloned()
     }
+
+    fn subscribe(&self, key_prefix: &str) -> watch::Receiver<RegistryVersion> {
+        let cache_state = self.cache.read().unwrap();
+        self.subscriptions
+            .subscribe(key_prefix, &cache_state.records)
+    }
 }
 
 /// An empty registry data provider that emulates a static, empty registry.
@@ -374,6 +488,7 @@ impl RegistryDataProvider for EmptyRegistryDataProvider {
 mod tests {
     use super::*;
     use assert_matches::assert_matches;
+    use ic_config::registry_client::SnapshotCacheConfig;
     use ic_interfaces::registry::ZERO_REGISTRY_VERSION;
     use ic_registry_common::{
         pb::test_protos::v1::TestProto, proto_registry_data_provider::ProtoRegistryDataProvider,
@@ -537,6 +652,36 @@ mod tests {
         assert!(get("B2", 7).is_err());
     }
 
+    #[test]
+    fn subscribers_are_notified_of_changes_to_their_keys() {
+        let data_provider = Arc::new(ProtoRegistryDataProvider::new());
+        let registry = RegistryClientImpl::new(data_provider.clone(), None);
+
+        data_provider.add("A", v(1), Some(value(1))).unwrap();
+        registry.poll_once().unwrap();
+
+        let a = registry.subscribe("A");
+        let b = registry.subscribe("B");
+        let all = registry.subscribe("");
+        assert_eq!(*a.borrow(), v(1));
+        assert_eq!(*b.borrow(), ZERO_REGISTRY_VERSION);
+        assert_eq!(*all.borrow(), v(1));
+
+        data_provider.add("B1", v(2), Some(value(2))).unwrap();
+        data_provider.add::<TestProto>("B2", v(3), None).unwrap();
+        registry.poll_once().unwrap();
+        assert_eq!(*a.borrow(), v(1));
+        assert_eq!(*b.borrow(), v(3));
+        assert_eq!(*all.borrow(), v(3));
+
+        // Dropped subscriptions are removed on the next change.
+        std::mem::drop(b);
+        data_provider.add("B1", v(4), Some(value(4))).unwrap();
+        registry.poll_once().unwrap();
+        assert_eq!(*all.borrow(), v(4));
+        assert_eq!(registry.subscriptions.subscription_count(), 2);
+    }
+
     #[tokio::test]
     async fn start_polling_actually_polls_data_provider() {
         let data_provider = Arc::new(FakeDataProvider {
@@ -553,6 +698,60 @@ mod tests {
         assert!(data_provider.poll_counter.load(Ordering::Relaxed) > 0);
     }
 
+    #[tokio::test]
+    async fn falls_back_to_snapshot_cache_if_data_provider_is_unreachable() {
+        let dir = tempfile::tempdir().unwrap();
+        let snapshot_cache =
+            || RegistrySnapshotCache::new(&SnapshotCacheConfig::new(dir.path().to_path_buf()));
+
+        let data_provider = Arc::new(ProtoRegistryDataProvider::new());
+        data_provider.add("A", v(1), Some(value(1))).unwrap();
+        data_provider.add("A", v(2), Some(value(2))).unwrap();
+        let registry = RegistryClientImpl::new_with_snapshot_cache(
+            Arc::new(CertifyingDataProvider(data_provider)),
+            snapshot_cache(),
+            None,
+        );
+        registry.poll_once().unwrap();
+        // Dropping the client waits until the versions are persisted.
+        drop(registry);
+
+        let registry = RegistryClientImpl::new_with_snapshot_cache(
+            Arc::new(UnreachableDataProvider),
+            snapshot_cache(),
+            None,
+        );
+        registry.fetch_and_start_polling().unwrap();
+        assert_eq!(registry.get_latest_version(), v(2));
+        assert_eq!(registry.get_test_proto("A", v(1)).unwrap(), Some(value(1)));
+        assert_eq!(registry.get_test_proto("A", v(2)).unwrap(), Some(value(2)));
+
+        // Without a snapshot cache, the client cannot start.
+        let registry = RegistryClientImpl::new(Arc::new(UnreachableDataProvider), None);
+        assert!(registry.fetch_and_start_polling().is_err());
+    }
+
+    #[tokio::test]
+    async fn uncertified_versions_are_not_persisted() {
+        let dir = tempfile::tempdir().unwrap();
+        let snapshot_cache =
+            || RegistrySnapshotCache::new(&SnapshotCacheConfig::new(dir.path().to_path_buf()));
+
+        let data_provider = Arc::new(ProtoRegistryDataProvider::new());
+        data_provider.add("A", v(1), Some(value(1))).unwrap();
+        let registry =
+            RegistryClientImpl::new_with_snapshot_cache(data_provider, snapshot_cache(), None);
+        registry.poll_once().unwrap();
+        drop(registry);
+
+        let registry = RegistryClientImpl::new_with_snapshot_cache(
+            Arc::new(UnreachableDataProvider),
+            snapshot_cache(),
+            None,
+        );
+        assert!(registry.fetch_and_start_polling().is_err());
+    }
+
     #[test]
     fn polling_for_latest_version_fails_for_insufficient_retries() {
         let data_provider = Arc::new(ProtoRegistryDataProvider::new());
@@ -687,6 +886,38 @@ mod tests {
         }
     }
 
+    /// Returns the records of the wrapped data provider as if the NNS
+    /// certified them just now.
+    struct CertifyingDataProvider(Arc<dyn RegistryDataProvider>);
+
+    impl RegistryDataProvider for CertifyingDataProvider {
+        fn get_updates_since(
+            &self,
+            version: RegistryVersion,
+        ) -> Result<Vec<RegistryTransportRecord>, RegistryDataProviderError> {
+            self.0.get_updates_since(version)
+        }
+
+        fn get_certified_updates_since(
+            &self,
+            version: RegistryVersion,
+        ) -> Result<(Vec<RegistryTransportRecord>, Option<Time>), RegistryDataProviderError>
+        {
+            Ok((self.get_updates_since(version)?, Some(current_time())))
+        }
+    }
+
+    struct UnreachableDataProvider;
+
+    impl RegistryDataProvider for UnreachableDataProvider {
+        fn get_updates_since(
+            &self,
+            _version: RegistryVersion,
+        ) -> Result<Vec<RegistryTransportRecord>, RegistryDataProviderError> {
+            Err(RegistryDataProviderError::Timeout)
+        }
+    }
+
     struct LimitingDataProvider {
         changelog_size: RegistryVersion,
         data_provider: Arc<dyn RegistryDataProvider>,
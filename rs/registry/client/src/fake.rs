@@ -2,6 +2,7 @@
 //! tests and utility functions where a real registry that polls in the
 //! background is not required.
 
+use crate::subscriptions::Subscriptions;
 use ic_interfaces::registry::{
     empty_zero_registry_record, RegistryClient, RegistryClientVersionedResult,
     RegistryDataProvider, RegistryTransportRecord, ZERO_REGISTRY_VERSION,
@@ -9,6 +10,7 @@ use ic_interfaces::registry::{
 use ic_types::{registry::RegistryClientError, time::current_time, RegistryVersion, Time};
 use std::collections::BTreeMap;
 use std::sync::{Arc, RwLock, RwLockReadGuard};
+use tokio::sync::watch;
 
 type CacheState = (
     RegistryVersion,
@@ -19,6 +21,7 @@ type CacheState = (
 pub struct FakeRegistryClient {
     data_provider: Arc<dyn RegistryDataProvider>,
     cache: Arc<RwLock<CacheState>>,
+    subscriptions: Subscriptions,
 }
 
 impl FakeRegistryClient {
@@ -27,6 +30,7 @@ impl FakeRegistryClient {
         Self {
             data_provider,
             cache: Arc::new(RwLock::new(Default::default())),
+            subscriptions: Default::default(),
         }
     }
 
@@ -47,6 +51,7 @@ impl FakeRegistryClient {
 
         // perform update
         assert!(!new_records.is_empty());
+        self.subscriptions.notify(&new_records);
         let mut timestamps = cache.1.clone();
         let mut new_version = ZERO_REGISTRY_VERSION;
         for record in new_records {
@@ -168,4 +173,9 @@ impl RegistryClient for FakeRegistryClient {
     fn get_version_timestamp(&self, registry_version: RegistryVersion) -> Option<Time> {
         self.cache.read().unwrap().1.get(&registry_version).cloned()
     }
+
+    fn subscribe(&self, key_prefix: &str) -> watch::Receiver<RegistryVersion> {
+        let cache_state = self.cache.read().unwrap();
+        self.subscriptions.subscribe(key_prefix, &cache_state.2)
+    }
 }
@@ -0,0 +1,21 @@
+use ic_interfaces::registry::{RegistryClient, RegistryClientResult};
+use ic_protobuf::registry::fee_schedule::v1::FeeScheduleRecord;
+use ic_registry_common::values::deserialize_registry_value;
+use ic_registry_keys::make_fee_schedule_record_key;
+use ic_types::RegistryVersion;
+
+/// A trait that allows access to the fee schedule of application subnets.
+pub trait FeeScheduleRegistry {
+    fn get_fee_schedule(&self, version: RegistryVersion)
+        -> RegistryClientResult<FeeScheduleRecord>;
+}
+
+impl<T: RegistryClient + ?Sized> FeeScheduleRegistry for T {
+    fn get_fee_schedule(
+        &self,
+        version: RegistryVersion,
+    ) -> RegistryClientResult<FeeScheduleRecord> {
+        let bytes = self.get_value(&make_fee_schedule_record_key(), version);
+        deserialize_registry_value::<FeeScheduleRecord>(bytes)
+    }
+}
@@ -1,8 +1,8 @@
 use ic_interfaces::registry::{RegistryClient, RegistryClientResult};
 use ic_protobuf::registry::routing_table::v1 as pb;
 use ic_registry_common::values::deserialize_registry_value;
-use ic_registry_keys::make_routing_table_record_key;
-use ic_registry_routing_table::RoutingTable;
+use ic_registry_keys::{make_canister_migrations_record_key, make_routing_table_record_key};
+use ic_registry_routing_table::{CanisterMigrations, RoutingTable};
 use ic_types::RegistryVersion;
 use std::convert::TryFrom;
 
@@ -11,6 +11,12 @@ use std::convert::TryFrom;
 /// that we can simply return the entire struct here.
 pub trait RoutingTableRegistry {
     fn get_routing_table(&self, version: RegistryVersion) -> RegistryClientResult<RoutingTable>;
+
+    /// Returns the canister ID ranges that are being migrated between subnets.
+    fn get_canister_migrations(
+        &self,
+        version: RegistryVersion,
+    ) -> RegistryClientResult<CanisterMigrations>;
 }
 
 impl<T: RegistryClient + ?Sized> RoutingTableRegistry for T {
@@ -21,4 +27,15 @@ impl<T: RegistryClient + ?Sized> RoutingTableRegistry for T {
                 .map(|pb_routing_table| RoutingTable::try_from(pb_routing_table).unwrap())
         })
     }
+
+    fn get_canister_migrations(
+        &self,
+        version: RegistryVersion,
+    ) -> RegistryClientResult<CanisterMigrations> {
+        let bytes = self.get_value(&make_canister_migrations_record_key(), version);
+        deserialize_registry_value::<pb::CanisterMigrations>(bytes).map(|option_pb_migrations| {
+            option_pb_migrations
+                .map(|pb_migrations| CanisterMigrations::try_from(pb_migrations).unwrap())
+        })
+    }
 }
@@ -4,7 +4,10 @@ use ic_interfaces::registry::{
 use ic_protobuf::registry::{
     node::v1::NodeRecord,
     replica_version::v1::ReplicaVersionRecord,
-    subnet::v1::{CatchUpPackageContents, GossipConfig, SubnetListRecord, SubnetRecord},
+    subnet::v1::{
+        BlockMakerRanking, CatchUpPackageContents, GossipConfig, SubnetListRecord, SubnetRecord,
+        SubnetType,
+    },
 };
 use ic_protobuf::types::v1::SubnetId as SubnetIdProto;
 use ic_registry_common::values::deserialize_registry_value;
@@ -12,7 +15,14 @@ use ic_registry_keys::{
     make_catch_up_package_contents_key, make_node_record_key, make_replica_version_key,
     make_subnet_list_record_key, make_subnet_record_key, ROOT_SUBNET_ID_KEY,
 };
-use ic_types::{Height, NodeId, PrincipalId, RegistryVersion, ReplicaVersion, SubnetId};
+use ic_types::{
+    ingress::{
+        IngressExpiryWindow, APPLICATION_SUBNET_INGRESS_EXPIRY_WINDOW,
+        SYSTEM_SUBNET_INGRESS_EXPIRY_WINDOW,
+    },
+    subnet_id_try_from_protobuf, Height, NodeId, PrincipalId, RegistryVersion, ReplicaVersion,
+    SubnetId,
+};
 use std::convert::TryFrom;
 use std::time::Duration;
 
@@ -33,6 +43,25 @@ pub struct IngressMessageSettings {
     /// Maximum number of messages per block. This is a hard cap, which means
     /// blocks will never have more than this number of messages.
     pub max_ingress_messages_per_block: usize,
+    /// The range of expiry times accepted for ingress messages.
+    pub expiry_window: IngressExpiryWindow,
+}
+
+/// Returns the ingress expiry window of the given subnet record, or the
+/// default of its subnet type if the record doesn't specify one.
+fn ingress_expiry_window(subnet: &SubnetRecord) -> IngressExpiryWindow {
+    if subnet.max_ingress_ttl_millis == 0 {
+        if subnet.subnet_type == SubnetType::System as i32 {
+            SYSTEM_SUBNET_INGRESS_EXPIRY_WINDOW
+        } else {
+            APPLICATION_SUBNET_INGRESS_EXPIRY_WINDOW
+        }
+    } else {
+        IngressExpiryWindow::new(
+            Duration::from_millis(subnet.max_ingress_ttl_millis),
+            Duration::from_millis(subnet.ingress_clock_skew_tolerance_millis),
+        )
+    }
 }
 
 /// A helper trait that wraps a RegistryClient and provides utility methods for
@@ -82,6 +111,14 @@ pub trait SubnetRegistry {
         version: RegistryVersion,
     ) -> RegistryClientResult<NotarizationDelaySettings>;
 
+    /// Returns the function that derives the block maker ranks. Unknown
+    /// values are returned as `BlockMakerRanking::Unspecified`.
+    fn get_block_maker_ranking(
+        &self,
+        subnet_id: SubnetId,
+        version: RegistryVersion,
+    ) -> RegistryClientResult<BlockMakerRanking>;
+
     /// Returns the upper bound for the number of dealings we allow in a block.
     fn get_dkg_dealings_per_block(
         &self,
@@ -168,10 +205,11 @@ impl<T: RegistryClient + ?Sized> SubnetRegistry for T {
     /// Return the root subnet id if it is available and can be parsed
     fn get_root_subnet_id(&self, version: RegistryVersion) -> RegistryClientResult<SubnetId> {
         let bytes = self.get_value(ROOT_SUBNET_ID_KEY, version);
-        Ok(deserialize_registry_value::<SubnetIdProto>(bytes)?
-            .and_then(|subnet_id_proto| subnet_id_proto.principal_id)
-            .map(|pr_id| PrincipalId::try_from(pr_id.raw).expect("Could not parse principal id!"))
-            .map(SubnetId::from))
+        Ok(
+            deserialize_registry_value::<SubnetIdProto>(bytes)?.map(|subnet_id_proto| {
+                subnet_id_try_from_protobuf(subnet_id_proto).expect("Could not parse subnet id!")
+            }),
+        )
     }
 
     fn get_node_ids_on_subnet(
@@ -209,6 +247,7 @@ impl<T: RegistryClient + ?Sized> SubnetRegistry for T {
                         as usize,
                     max_ingress_bytes_per_message: subnet.max_ingress_bytes_per_message as usize,
                     max_ingress_messages_per_block: subnet.max_ingress_messages_per_block as usize,
+                    expiry_window: ingress_expiry_window(&subnet),
                 }
             }),
         )
@@ -240,6 +279,20 @@ impl<T: RegistryClient + ?Sized> SubnetRegistry for T {
         )
     }
 
+    fn get_block_maker_ranking(
+        &self,
+        subnet_id: SubnetId,
+        version: RegistryVersion,
+    ) -> RegistryClientResult<BlockMakerRanking> {
+        let bytes = self.get_value(&make_subnet_record_key(subnet_id), version);
+        Ok(
+            deserialize_registry_value::<SubnetRecord>(bytes)?.map(|subnet| {
+                BlockMakerRanking::from_i32(subnet.block_maker_ranking)
+                    .unwrap_or(BlockMakerRanking::Unspecified)
+            }),
+        )
+    }
+
     fn get_dkg_dealings_per_block(
         &self,
         subnet_id: SubnetId,
@@ -551,4 +604,31 @@ mod tests {
             .unwrap();
         assert_eq!(result, Some(replica_version_record))
     }
+
+    #[test]
+    fn ingress_expiry_window_defaults_to_the_one_of_the_subnet_type() {
+        let mut subnet_record = SubnetRecord {
+            subnet_type: SubnetType::Application as i32,
+            ..Default::default()
+        };
+        assert_eq!(
+            ingress_expiry_window(&subnet_record),
+            APPLICATION_SUBNET_INGRESS_EXPIRY_WINDOW
+        );
+        subnet_record.subnet_type = SubnetType::System as i32;
+        assert_eq!(
+            ingress_expiry_window(&subnet_record),
+            SYSTEM_SUBNET_INGRESS_EXPIRY_WINDOW
+        );
+
+        // The configured window is capped at the maximum ingress TTL.
+        subnet_record.max_ingress_ttl_millis = 600_000;
+        subnet_record.ingress_clock_skew_tolerance_millis = 20_000;
+        let expiry_window = ingress_expiry_window(&subnet_record);
+        assert_eq!(expiry_window.clock_skew_tolerance, Duration::from_secs(20));
+        assert_eq!(
+            expiry_window.max_ttl_with_tolerance(),
+            ic_types::ingress::MAX_INGRESS_TTL
+        );
+    }
 }
@@ -2,3 +2,5 @@ pub mod client;
 pub mod fake;
 pub mod helper;
 mod metrics;
+pub mod snapshot_cache;
+mod subscriptions;
@@ -2,12 +2,14 @@
 
 use ic_metrics::buckets::decimal_buckets;
 use ic_metrics::MetricsRegistry;
-use prometheus::{HistogramVec, IntGauge};
+use prometheus::{HistogramVec, IntCounterVec, IntGauge};
 
 pub(crate) struct Metrics {
     /// Most recent registry version fetched by the client
     pub(crate) registry_version: IntGauge,
     pub(crate) api_call_duration: HistogramVec,
+    /// Failures to load or persist the registry snapshot cache
+    pub(crate) snapshot_cache_errors: IntCounterVec,
 }
 
 impl Metrics {
@@ -25,6 +27,12 @@ impl Metrics {
                 "ic_registry_client_registry_version",
                 "Most recent registry version fetched by the client",
             ),
+
+            snapshot_cache_errors: r.int_counter_vec(
+                "ic_registry_client_snapshot_cache_errors_total",
+                "Failures to load or persist the registry snapshot cache, by operation.",
+                &["op"],
+            ),
         }
     }
 }
@@ -0,0 +1,368 @@
+//! A local disk cache of the registry versions fetched by the
+//! `RegistryClientImpl`. If the data provider (usually the registry canister
+//! on the NNS) is unreachable at startup, e.g. during a network-wide restart,
+//! the client serves the cached versions until the data provider becomes
+//! reachable again.
+//!
+//! The cache is laid out as a registry local store. Its certified time is the
+//! time at which the NNS last certified the cached versions to be up to date,
+//! so only the versions of data providers that verify the certified responses
+//! of the registry canister are persisted. A snapshot that was not certified
+//! within the configured staleness bound of the local clock, or whose
+//! certified time lies in the future, is not served.
+//!
+//! The `SnapshotWriter` persists the versions on a background thread, so that
+//! polling the data provider doesn't wait for the disk.
+use ic_config::registry_client::SnapshotCacheConfig;
+use ic_interfaces::registry::{
+    LocalStoreCertifiedTimeReader, RegistryDataProvider, RegistryTransportRecord,
+    ZERO_REGISTRY_VERSION,
+};
+use ic_registry_common::local_store::{
+    ChangelogEntry, KeyMutation, LocalStoreImpl, LocalStoreWriter,
+};
+use ic_types::{RegistryVersion, Time};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// Reasons for the snapshot cache not being loaded or updated.
+#[derive(Debug)]
+pub enum SnapshotCacheError {
+    /// The cache holds no registry versions.
+    Empty,
+    /// The data provider last confirmed the snapshot at `certified_time`,
+    /// which is longer than the staleness bound ago.
+    Stale { certified_time: Time },
+    /// The certified time of the snapshot lies in the future.
+    CertifiedInTheFuture { certified_time: Time },
+    /// The cache does not hold the version the records to persist follow up
+    /// on, e.g. because persisting an earlier version failed.
+    Inconsistent {
+        cached_version: Option<RegistryVersion>,
+        since: RegistryVersion,
+    },
+    /// Reading or writing the cache failed.
+    Io(String),
+}
+
+impl fmt::Display for SnapshotCacheError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotCacheError::Empty => write!(f, "Registry snapshot cache is empty"),
+            SnapshotCacheError::Stale { certified_time } => write!(
+                f,
+                "Registry snapshot was last confirmed at {}, too long ago",
+                certified_time
+            ),
+            SnapshotCacheError::CertifiedInTheFuture { certified_time } => write!(
+                f,
+                "Registry snapshot was confirmed at {}, in the future",
+                certified_time
+            ),
+            SnapshotCacheError::Inconsistent {
+                cached_version,
+                since,
+            } => write!(
+                f,
+                "Registry snapshot cache holds version {:?}, cannot persist versions above {}",
+                cached_version, since
+            ),
+            SnapshotCacheError::Io(err) => write!(f, "Registry snapshot cache I/O error: {}", err),
+        }
+    }
+}
+
+/// The registry versions persisted by a `RegistryClientImpl`.
+pub struct RegistrySnapshotCache {
+    local_store: LocalStoreImpl,
+    max_staleness: Duration,
+    /// The latest version the local store is known to hold all versions up
+    /// to. `None` until the cache was loaded or cleared, or after persisting
+    /// a version failed.
+    cached_version: Mutex<Option<RegistryVersion>>,
+}
+
+impl RegistrySnapshotCache {
+    pub fn new(config: &SnapshotCacheConfig) -> Self {
+        Self {
+            local_store: LocalStoreImpl::new(&config.path),
+            max_staleness: Duration::from_secs(config.max_staleness_seconds),
+            cached_version: Mutex::new(None),
+        }
+    }
+
+    /// Returns all cached records, if the snapshot was confirmed to be up to
+    /// date within the staleness bound before `now`.
+    pub(crate) fn load(
+        &self,
+        now: Time,
+    ) -> Result<Vec<RegistryTransportRecord>, SnapshotCacheError> {
+        let certified_time = self.local_store.read_certified_time();
+        if certified_time == Time::from_nanos_since_unix_epoch(0) {
+            return Err(SnapshotCacheError::Empty);
+        }
+        if certified_time > now {
+            return Err(SnapshotCacheError::CertifiedInTheFuture { certified_time });
+        }
+        if now - certified_time > self.max_staleness {
+            return Err(SnapshotCacheError::Stale { certified_time });
+        }
+
+        let records = self
+            .local_store
+            .get_updates_since(ZERO_REGISTRY_VERSION)
+            .map_err(|err| SnapshotCacheError::Io(err.to_string()))?;
+        let version = match records.iter().map(|r| r.version).max() {
+            Some(version) => version,
+            None => return Err(SnapshotCacheError::Empty),
+        };
+        *self.cached_version.lock().unwrap() = Some(version);
+        Ok(records)
+    }
+
+    /// Persists the `records` the data provider returned for the versions
+    /// above `since` and records `certified_time` as the time the snapshot was
+    /// certified to be up to date.
+    ///
+    /// The registry versions must be consecutive, as the local store cannot
+    /// represent gaps between versions.
+    pub(crate) fn persist(
+        &self,
+        since: RegistryVersion,
+        records: &[RegistryTransportRecord],
+        certified_time: Time,
+    ) -> Result<(), SnapshotCacheError> {
+        let io_error = |err: std::io::Error| SnapshotCacheError::Io(err.to_string());
+        let mut cached_version = self.cached_version.lock().unwrap();
+
+        if since == ZERO_REGISTRY_VERSION {
+            // Starting from scratch, drop whatever an earlier run cached.
+            // Clearing a missing directory leaves nothing to clear.
+            match self.local_store.clear() {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                    *cached_version = None;
+                    return Err(io_error(err));
+                }
+                _ => *cached_version = Some(ZERO_REGISTRY_VERSION),
+            }
+        }
+        if *cached_version != Some(since) {
+            return Err(SnapshotCacheError::Inconsistent {
+                cached_version: *cached_version,
+                since,
+            });
+        }
+
+        let mut changelog: BTreeMap<RegistryVersion, ChangelogEntry> = BTreeMap::new();
+        for record in records.iter().filter(|r| r.version > since) {
+            changelog
+                .entry(record.version)
+                .or_default()
+                .push(KeyMutation {
+                    key: record.key.clone(),
+                    value: record.value.clone(),
+                });
+        }
+        for (version, entry) in changelog {
+            if let Err(err) = self.local_store.store(version, entry) {
+                *cached_version = None;
+                return Err(io_error(err));
+            }
+            *cached_version = Some(version);
+        }
+        if *cached_version == Some(ZERO_REGISTRY_VERSION) {
+            // The empty registry is not worth serving.
+            return Ok(());
+        }
+
+        self.local_store
+            .update_certified_time(certified_time.as_nanos_since_unix_epoch())
+            .map_err(io_error)
+    }
+}
+
+/// The records fetched for the versions above `since`, certified to be up to
+/// date at `certified_time`.
+struct PersistRequest {
+    since: RegistryVersion,
+    records: Vec<RegistryTransportRecord>,
+    certified_time: Time,
+}
+
+/// Persists registry versions to a `RegistrySnapshotCache` on a background
+/// thread, in the order they are passed to `persist()`. Dropping the writer
+/// waits until the pending versions are persisted.
+pub(crate) struct SnapshotWriter {
+    sender: Option<Mutex<Sender<PersistRequest>>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl SnapshotWriter {
+    /// Starts the background thread, which calls `on_error` for every
+    /// version that could not be persisted.
+    pub(crate) fn new<F>(snapshot_cache: Arc<RegistrySnapshotCache>, on_error: F) -> Self
+    where
+        F: Fn(SnapshotCacheError) + Send + 'static,
+    {
+        let (sender, receiver) = channel::<PersistRequest>();
+        let handle = std::thread::Builder::new()
+            .name("RegistrySnapshotWriter".to_string())
+            .spawn(move || {
+                while let Ok(request) = receiver.recv() {
+                    if let Err(err) = snapshot_cache.persist(
+                        request.since,
+                        &request.records,
+                        request.certified_time,
+                    ) {
+                        on_error(err);
+                    }
+                }
+            })
+            .expect("failed to spawn the registry snapshot writer");
+        Self {
+            sender: Some(Mutex::new(sender)),
+            handle: Some(handle),
+        }
+    }
+
+    /// Queues the `records` fetched for the versions above `since` to be
+    /// persisted, with `certified_time` as the time they were certified to be
+    /// up to date.
+    pub(crate) fn persist(
+        &self,
+        since: RegistryVersion,
+        records: Vec<RegistryTransportRecord>,
+        certified_time: Time,
+    ) {
+        if let Some(sender) = &self.sender {
+            // The receiver only stops once the sender is dropped.
+            let _ = sender.lock().unwrap().send(PersistRequest {
+                since,
+                records,
+                certified_time,
+            });
+        }
+    }
+}
+
+impl Drop for SnapshotWriter {
+    fn drop(&mut self) {
+        // Dropping the sender stops the thread once the queue is drained.
+        self.sender.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+
+    const MAX_STALENESS: Duration = Duration::from_secs(3600);
+
+    fn snapshot_cache(dir: &tempfile::TempDir) -> RegistrySnapshotCache {
+        RegistrySnapshotCache::new(&SnapshotCacheConfig {
+            path: dir.path().to_path_buf(),
+            max_staleness_seconds: MAX_STALENESS.as_secs(),
+        })
+    }
+
+    fn record(key: &str, version: u64) -> RegistryTransportRecord {
+        RegistryTransportRecord {
+            key: key.to_string(),
+            version: RegistryVersion::from(version),
+            value: Some(vec![version as u8]),
+        }
+    }
+
+    fn nanos(secs: u64) -> Time {
+        Time::from_nanos_since_unix_epoch(secs * 1_000_000_000)
+    }
+
+    #[test]
+    fn persisted_records_are_loaded() {
+        let dir = tempfile::tempdir().unwrap();
+        let records = vec![record("A", 1), record("B", 1), record("A", 2)];
+
+        snapshot_cache(&dir)
+            .persist(ZERO_REGISTRY_VERSION, &records, nanos(1000))
+            .unwrap();
+
+        let loaded = snapshot_cache(&dir).load(nanos(1020)).unwrap();
+        assert_eq!(records, loaded);
+    }
+
+    #[test]
+    fn persisting_follows_up_on_the_cached_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = snapshot_cache(&dir);
+
+        cache
+            .persist(ZERO_REGISTRY_VERSION, &[record("A", 1)], nanos(1000))
+            .unwrap();
+        cache
+            .persist(RegistryVersion::from(1), &[record("B", 2)], nanos(1010))
+            .unwrap();
+        assert_matches!(
+            cache.persist(RegistryVersion::from(1), &[record("C", 2)], nanos(1020)),
+            Err(SnapshotCacheError::Inconsistent { .. })
+        );
+
+        let loaded = snapshot_cache(&dir).load(nanos(1030)).unwrap();
+        assert_eq!(vec![record("A", 1), record("B", 2)], loaded);
+    }
+
+    #[test]
+    fn stale_snapshot_is_not_loaded() {
+        let dir = tempfile::tempdir().unwrap();
+        snapshot_cache(&dir)
+            .persist(ZERO_REGISTRY_VERSION, &[record("A", 1)], nanos(1000))
+            .unwrap();
+
+        assert_matches!(
+            snapshot_cache(&dir).load(nanos(1000) + MAX_STALENESS + Duration::from_secs(1)),
+            Err(SnapshotCacheError::Stale { .. })
+        );
+        assert_matches!(
+            snapshot_cache(&dir).load(nanos(999)),
+            Err(SnapshotCacheError::CertifiedInTheFuture { .. })
+        );
+        assert!(snapshot_cache(&dir)
+            .load(nanos(1000) + MAX_STALENESS)
+            .is_ok());
+    }
+
+    #[test]
+    fn writer_persists_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let errors = Arc::new(Mutex::new(vec![]));
+        let writer = SnapshotWriter::new(Arc::new(snapshot_cache(&dir)), {
+            let errors = Arc::clone(&errors);
+            move |err| errors.lock().unwrap().push(err)
+        });
+
+        writer.persist(ZERO_REGISTRY_VERSION, vec![record("A", 1)], nanos(1000));
+        writer.persist(RegistryVersion::from(1), vec![record("B", 2)], nanos(1010));
+        // Dropping the writer waits for the queued versions.
+        drop(writer);
+
+        assert!(errors.lock().unwrap().is_empty());
+        let loaded = snapshot_cache(&dir).load(nanos(1030)).unwrap();
+        assert_eq!(vec![record("A", 1), record("B", 2)], loaded);
+    }
+
+    #[test]
+    fn empty_snapshot_is_not_loaded() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_matches!(
+            snapshot_cache(&dir).load(nanos(1000)),
+            Err(SnapshotCacheError::Empty)
+        );
+    }
+}
@@ -0,0 +1,63 @@
+//! The subscriptions to registry changes, shared by the `RegistryClient`
+//! implementations of this crate.
+use ic_interfaces::registry::{RegistryTransportRecord, ZERO_REGISTRY_VERSION};
+use ic_types::RegistryVersion;
+use std::sync::Mutex;
+use tokio::sync::watch;
+
+#[derive(Default)]
+pub(crate) struct Subscriptions {
+    /// The key prefix and the sending half of the channel, per subscription.
+    subscriptions: Mutex<Vec<(String, watch::Sender<RegistryVersion>)>>,
+}
+
+impl Subscriptions {
+    /// Subscribes to the changes of the keys starting with `key_prefix`.
+    /// `records` are the records the registry client currently holds.
+    ///
+    /// Must be called while holding the registry client's cache lock, so that
+    /// no update of the cache is missed between computing the initial version
+    /// and registering the subscription.
+    pub(crate) fn subscribe(
+        &self,
+        key_prefix: &str,
+        records: &[RegistryTransportRecord],
+    ) -> watch::Receiver<RegistryVersion> {
+        let version = latest_change(key_prefix, records).unwrap_or(ZERO_REGISTRY_VERSION);
+        let (sender, receiver) = watch::channel(version);
+        self.subscriptions
+            .lock()
+            .unwrap()
+            .push((key_prefix.to_string(), sender));
+        receiver
+    }
+
+    /// Notifies the subscribers of the keys changed by `new_records`. The
+    /// subscriptions whose receivers were all dropped are removed.
+    pub(crate) fn notify(&self, new_records: &[RegistryTransportRecord]) {
+        self.subscriptions
+            .lock()
+            .unwrap()
+            .retain(
+                |(key_prefix, sender)| match latest_change(key_prefix, new_records) {
+                    Some(version) => sender.send(version).is_ok(),
+                    None => true,
+                },
+            );
+    }
+
+    #[cfg(test)]
+    pub(crate) fn subscription_count(&self) -> usize {
+        self.subscriptions.lock().unwrap().len()
+    }
+}
+
+/// Returns the latest version in `records` at which a key starting with
+/// `key_prefix` changed.
+fn latest_change(key_prefix: &str, records: &[RegistryTransportRecord]) -> Option<RegistryVersion> {
+    records
+        .iter()
+        .filter(|r| r.key.starts_with(key_prefix))
+        .map(|r| r.version)
+        .max()
+}
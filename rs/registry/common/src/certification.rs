@@ -12,7 +12,7 @@ use std::convert::TryFrom;
 use tree_deserializer::{types::Leb128EncodedU64, LabeledTreeDeserializer};
 
 #[cfg(test)]
-mod tests;
+pub(crate) mod tests;
 
 /// Describes an error occurred during parsing and validation of the result of a
 /// "get_certified_changes_since" method call.
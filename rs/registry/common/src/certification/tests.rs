@@ -22,7 +22,7 @@ use std::string::ToString;
 const REPLICA_TIME: u64 = 1234567;
 
 #[derive(Clone)]
-enum GarbleResponse {
+pub(crate) enum GarbleResponse {
     LeaveAsIs,
     OverrideCertifiedData(Digest),
     OverrideSignature(CombinedThresholdSig),
@@ -35,7 +35,7 @@ impl GarbleResponse {
     }
 }
 
-type EncodedResponse = Vec<u8>;
+pub(crate) type EncodedResponse = Vec<u8>;
 
 fn make_certificate(
     cid: &CanisterId,
@@ -100,7 +100,7 @@ fn make_certificate(
     (pk, bytes)
 }
 
-fn make_certified_delta(
+pub(crate) fn make_certified_delta(
     deltas: Vec<RegistryAtomicMutateRequest>,
     selection: impl std::ops::RangeBounds<u64>,
     garble_response: GarbleResponse,
@@ -179,7 +179,11 @@ fn make_certified_delta(
     (cid, pk, encoded_response)
 }
 
-fn set_key(version: u64, k: impl ToString, v: impl AsRef<[u8]>) -> RegistryTransportRecord {
+pub(crate) fn set_key(
+    version: u64,
+    k: impl ToString,
+    v: impl AsRef<[u8]>,
+) -> RegistryTransportRecord {
     RegistryTransportRecord {
         version: RegistryVersion::from(version),
         key: k.to_string(),
@@ -195,7 +199,7 @@ fn rem_key(version: u64, k: impl ToString) -> RegistryTransportRecord {
     }
 }
 
-fn make_change(mutations: Vec<RegistryMutation>) -> RegistryAtomicMutateRequest {
+pub(crate) fn make_change(mutations: Vec<RegistryMutation>) -> RegistryAtomicMutateRequest {
     RegistryAtomicMutateRequest {
         mutations,
         preconditions: vec![],
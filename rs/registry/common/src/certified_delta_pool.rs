@@ -0,0 +1,321 @@
+//! A pool of certified registry deltas, i.e. of responses of the
+//! `get_certified_changes_since` method of the registry canister, that the
+//! nodes of a subnet pass on to each other over gossip.
+//!
+//! The responses are certified by the NNS subnet, so the deltas received from
+//! a peer are verified just like those fetched from the registry canister. A
+//! node that cannot reach the NNS thus still learns about the changes to the
+//! registry, e.g. to the subnet membership or the firewall rules, as long as
+//! one of its peers can.
+use crate::certification::decode_certified_deltas;
+use ic_interfaces::registry::RegistryTransportRecord;
+use ic_registry_transport::Error;
+use ic_types::{
+    artifact::RegistryDeltaId, crypto::threshold_sig::ThresholdSigPublicKey, CanisterId,
+    RegistryVersion, Time,
+};
+use std::collections::BTreeMap;
+use std::sync::{Mutex, RwLock};
+
+/// The maximal number of certified responses kept in the pool. The oldest
+/// responses are dropped first.
+const MAX_POOLED_DELTAS: usize = 64;
+
+/// A verified certified response and the records it holds.
+struct CertifiedDelta {
+    certified_response: Vec<u8>,
+    records: Vec<RegistryTransportRecord>,
+}
+
+pub struct CertifiedDeltaPool {
+    canister_id: CanisterId,
+    nns_public_key: ThresholdSigPublicKey,
+    deltas: RwLock<BTreeMap<RegistryDeltaId, CertifiedDelta>>,
+    /// The identifiers of the deltas added since the last call to
+    /// `take_unadvertised()`.
+    unadvertised: Mutex<Vec<RegistryDeltaId>>,
+}
+
+impl CertifiedDeltaPool {
+    pub fn new(nns_public_key: ThresholdSigPublicKey) -> Self {
+        Self::new_for_canister(ic_nns_constants::REGISTRY_CANISTER_ID, nns_public_key)
+    }
+
+    fn new_for_canister(canister_id: CanisterId, nns_public_key: ThresholdSigPublicKey) -> Self {
+        Self {
+            canister_id,
+            nns_public_key,
+            deltas: RwLock::new(BTreeMap::new()),
+            unadvertised: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Verifies the `certified_response` to a query for the changes since
+    /// `since_version` and adds it to the pool. Returns the records it holds.
+    ///
+    /// Responses without any changes are not pooled, and neither are those
+    /// covered by a pooled response.
+    pub fn insert(
+        &self,
+        since_version: RegistryVersion,
+        certified_response: Vec<u8>,
+    ) -> Result<Vec<RegistryTransportRecord>, Error> {
+        self.insert_certified(since_version, certified_response)
+            .map(|(records, _time)| records)
+    }
+
+    /// Like `insert()`, but also returns the time at which the response was
+    /// certified.
+    pub fn insert_certified(
+        &self,
+        since_version: RegistryVersion,
+        certified_response: Vec<u8>,
+    ) -> Result<(Vec<RegistryTransportRecord>, Time), Error> {
+        self.insert_checked(since_version, None, certified_response)
+    }
+
+    /// Like `insert()`, for a `certified_response` downloaded under the
+    /// identifier `id`. The response is rejected unless it holds the changes
+    /// up to `id.version`, so that a response cannot be passed on under the
+    /// identifier of another.
+    pub fn insert_with_id(
+        &self,
+        id: &RegistryDeltaId,
+        certified_response: Vec<u8>,
+    ) -> Result<Vec<RegistryTransportRecord>, Error> {
+        self.insert_checked(id.since_version, Some(id.version), certified_response)
+            .map(|(records, _time)| records)
+    }
+
+    fn insert_checked(
+        &self,
+        since_version: RegistryVersion,
+        expected_version: Option<RegistryVersion>,
+        certified_response: Vec<u8>,
+    ) -> Result<(Vec<RegistryTransportRecord>, Time), Error> {
+        let (records, _current_version, time) = decode_certified_deltas(
+            since_version.get(),
+            &self.canister_id,
+            &self.nns_public_key,
+            &certified_response[..],
+        )
+        .map_err(|err| Error::UnknownError(format!("{:?}", err)))?;
+
+        // The response may not hold all versions up to the current one, the
+        // deltas it holds are consecutive though.
+        let latest_version = records.iter().map(|r| r.version).max();
+        if let Some(expected_version) = expected_version {
+            if latest_version != Some(expected_version) {
+                return Err(Error::UnknownError(format!(
+                    "The response holds the changes up to version {:?}, not up to version {}",
+                    latest_version, expected_version
+                )));
+            }
+        }
+        let version = match latest_version {
+            Some(version) => version,
+            None => return Ok((records, time)),
+        };
+        let id = RegistryDeltaId {
+            since_version,
+            version,
+        };
+
+        let mut deltas = self.deltas.write().unwrap();
+        if deltas.keys().any(|pooled| covers(pooled, &id)) {
+            return Ok((records, time));
+        }
+        let covered: Vec<_> = deltas
+            .keys()
+            .filter(|pooled| covers(&id, pooled))
+            .cloned()
+            .collect();
+        for pooled in covered {
+            deltas.remove(&pooled);
+        }
+        deltas.insert(
+            id,
+            CertifiedDelta {
+                certified_response,
+                records: records.clone(),
+            },
+        );
+        while deltas.len() > MAX_POOLED_DELTAS {
+            let oldest = *deltas
+                .keys()
+                .min_by_key(|pooled| pooled.version)
+                .expect("the pool is not empty");
+            deltas.remove(&oldest);
+        }
+        self.unadvertised.lock().unwrap().push(id);
+
+        Ok((records, time))
+    }
+
+    pub fn contains(&self, id: &RegistryDeltaId) -> bool {
+        self.deltas.read().unwrap().contains_key(id)
+    }
+
+    /// Returns the certified response with the given identifier.
+    pub fn get(&self, id: &RegistryDeltaId) -> Option<Vec<u8>> {
+        self.deltas
+            .read()
+            .unwrap()
+            .get(id)
+            .map(|delta| delta.certified_response.clone())
+    }
+
+    /// Returns the identifiers of all pooled responses.
+    pub fn ids(&self) -> Vec<RegistryDeltaId> {
+        self.deltas.read().unwrap().keys().cloned().collect()
+    }
+
+    /// Returns the identifiers of the responses pooled since the last call,
+    /// which were not yet advertised to the peers.
+    pub fn take_unadvertised(&self) -> Vec<RegistryDeltaId> {
+        let mut unadvertised = self.unadvertised.lock().unwrap();
+        let deltas = self.deltas.read().unwrap();
+        unadvertised
+            .drain(..)
+            .filter(|id| deltas.contains_key(id))
+            .collect()
+    }
+
+    /// Returns the records of all versions above `version` that the pooled
+    /// responses hold without gaps, or `None` if no pooled response holds the
+    /// version following `version`.
+    pub fn get_updates_since(
+        &self,
+        version: RegistryVersion,
+    ) -> Option<Vec<RegistryTransportRecord>> {
+        let deltas = self.deltas.read().unwrap();
+        let mut latest = version;
+        let mut records = vec![];
+        // Chain the responses, each time picking the one reaching furthest.
+        while let Some((id, delta)) = deltas
+            .iter()
+            .filter(|(id, _)| id.since_version <= latest && id.version > latest)
+            .max_by_key(|(id, _)| id.version)
+        {
+            records.extend(
+                delta
+                    .records
+                    .iter()
+                    .filter(|record| record.version > latest)
+                    .cloned(),
+            );
+            latest = id.version;
+        }
+
+        if latest == version {
+            None
+        } else {
+            Some(records)
+        }
+    }
+}
+
+/// Whether the deltas identified by `a` include all those identified by `b`.
+fn covers(a: &RegistryDeltaId, b: &RegistryDeltaId) -> bool {
+    a.since_version <= b.since_version && a.version >= b.version
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::certification::tests::{make_certified_delta, make_change, set_key, GarbleResponse};
+    use ic_registry_transport::upsert;
+
+    fn deltas() -> Vec<ic_registry_transport::pb::v1::RegistryAtomicMutateRequest> {
+        vec![
+            make_change(vec![upsert("A", "1")]),
+            make_change(vec![upsert("B", "2")]),
+            make_change(vec![upsert("A", "3")]),
+        ]
+    }
+
+    fn id(since_version: u64, version: u64) -> RegistryDeltaId {
+        RegistryDeltaId {
+            since_version: RegistryVersion::from(since_version),
+            version: RegistryVersion::from(version),
+        }
+    }
+
+    #[test]
+    fn pooled_deltas_are_chained() {
+        let (cid, pk, first) = make_certified_delta(deltas(), 1..=2, GarbleResponse::LeaveAsIs);
+        let (_, _, second) = make_certified_delta(deltas(), 3..=3, GarbleResponse::LeaveAsIs);
+        let pool = CertifiedDeltaPool::new_for_canister(cid, pk);
+
+        assert_eq!(
+            pool.insert(RegistryVersion::from(2), second).unwrap(),
+            vec![set_key(3, "A", "3")]
+        );
+        assert_eq!(pool.get_updates_since(RegistryVersion::from(0)), None);
+        pool.insert(RegistryVersion::from(0), first).unwrap();
+
+        assert_eq!(
+            pool.get_updates_since(RegistryVersion::from(0)),
+            Some(vec![
+                set_key(1, "A", "1"),
+                set_key(2, "B", "2"),
+                set_key(3, "A", "3")
+            ])
+        );
+        assert_eq!(
+            pool.get_updates_since(RegistryVersion::from(1)),
+            Some(vec![set_key(2, "B", "2"), set_key(3, "A", "3")])
+        );
+        assert_eq!(pool.get_updates_since(RegistryVersion::from(3)), None);
+        assert_eq!(pool.take_unadvertised(), vec![id(2, 3), id(0, 2)]);
+        assert_eq!(pool.take_unadvertised(), vec![]);
+    }
+
+    #[test]
+    fn covered_deltas_are_dropped() {
+        let (cid, pk, partial) = make_certified_delta(deltas(), 2..=2, GarbleResponse::LeaveAsIs);
+        let (_, _, full) = make_certified_delta(deltas(), 1..=3, GarbleResponse::LeaveAsIs);
+        let pool = CertifiedDeltaPool::new_for_canister(cid, pk);
+
+        pool.insert(RegistryVersion::from(1), partial.clone())
+            .unwrap();
+        pool.insert(RegistryVersion::from(0), full).unwrap();
+        assert_eq!(pool.ids(), vec![id(0, 3)]);
+
+        // Already covered by the pooled response.
+        pool.insert(RegistryVersion::from(1), partial).unwrap();
+        assert_eq!(pool.ids(), vec![id(0, 3)]);
+        assert!(!pool.contains(&id(1, 2)));
+    }
+
+    #[test]
+    fn deltas_must_match_their_id() {
+        let (cid, pk, delta) = make_certified_delta(deltas(), 1..=2, GarbleResponse::LeaveAsIs);
+        let pool = CertifiedDeltaPool::new_for_canister(cid, pk);
+
+        assert!(pool.insert_with_id(&id(0, 3), delta.clone()).is_err());
+        assert!(pool.ids().is_empty());
+        assert!(pool.take_unadvertised().is_empty());
+
+        assert_eq!(
+            pool.insert_with_id(&id(0, 2), delta).unwrap(),
+            vec![set_key(1, "A", "1"), set_key(2, "B", "2")]
+        );
+        assert_eq!(pool.ids(), vec![id(0, 2)]);
+    }
+
+    #[test]
+    fn deltas_without_valid_certification_are_rejected() {
+        let (cid, pk, _) = make_certified_delta(deltas(), 1..=3, GarbleResponse::LeaveAsIs);
+        let (_, _, garbled) = make_certified_delta(
+            deltas(),
+            1..=3,
+            GarbleResponse::OverrideCertifiedData(ic_crypto_tree_hash::Digest([0; 32])),
+        );
+        let pool = CertifiedDeltaPool::new_for_canister(cid, pk);
+
+        assert!(pool.insert(RegistryVersion::from(0), garbled).is_err());
+        assert!(pool.ids().is_empty());
+        assert!(pool.take_unadvertised().is_empty());
+    }
+}
@@ -1,10 +1,11 @@
 use ic_interfaces::registry::{RegistryDataProvider, RegistryTransportRecord};
 
+use crate::certified_delta_pool::CertifiedDeltaPool;
 use crate::registry::RegistryCanister;
 use ic_base_thread::async_safe_block_on_await;
 use ic_types::{
     crypto::threshold_sig::ThresholdSigPublicKey, registry::RegistryDataProviderError,
-    RegistryVersion,
+    RegistryVersion, Time,
 };
 use std::sync::Arc;
 
@@ -15,6 +16,10 @@ pub struct NnsDataProvider {
 pub struct CertifiedNnsDataProvider {
     registry_canister: Arc<RegistryCanister>,
     nns_public_key: Arc<ThresholdSigPublicKey>,
+    /// If set, the certified responses of the registry canister are added to
+    /// the pool, and the pooled responses received from peers are served
+    /// while the registry canister is unreachable.
+    delta_pool: Option<Arc<CertifiedDeltaPool>>,
 }
 
 impl NnsDataProvider {
@@ -70,6 +75,20 @@ impl CertifiedNnsDataProvider {
         Self {
             registry_canister: Arc::new(registry_canister),
             nns_public_key: Arc::new(nns_public_key),
+            delta_pool: None,
+        }
+    }
+
+    /// Creates a data provider that shares the certified registry deltas with
+    /// the other nodes of the subnet through `delta_pool`.
+    pub fn new_with_delta_pool(
+        registry_canister: RegistryCanister,
+        nns_public_key: ThresholdSigPublicKey,
+        delta_pool: Arc<CertifiedDeltaPool>,
+    ) -> Self {
+        Self {
+            delta_pool: Some(delta_pool),
+            ..Self::new(registry_canister, nns_public_key)
         }
     }
 }
@@ -79,7 +98,40 @@ impl RegistryDataProvider for CertifiedNnsDataProvider {
         &self,
         version: RegistryVersion,
     ) -> Result<Vec<RegistryTransportRecord>, RegistryDataProviderError> {
-        let (records, _version, _time) = async_safe_block_on_await({
+        self.get_certified_updates_since(version)
+            .map(|(records, _time)| records)
+    }
+
+    /// The deltas received from peers while the registry canister is
+    /// unreachable are returned without a certified time, as they may be
+    /// outdated.
+    fn get_certified_updates_since(
+        &self,
+        version: RegistryVersion,
+    ) -> Result<(Vec<RegistryTransportRecord>, Option<Time>), RegistryDataProviderError> {
+        if let Some(delta_pool) = &self.delta_pool {
+            let response = async_safe_block_on_await({
+                let registry_canister = Arc::clone(&self.registry_canister);
+                async move {
+                    registry_canister
+                        .get_certified_response_since(version.get())
+                        .await
+                }
+            });
+            return match response {
+                Ok(response) => delta_pool
+                    .insert_certified(version, response)
+                    .map(|(records, time)| (records, Some(time)))
+                    .map_err(|source| RegistryDataProviderError::Transfer { source }),
+                // Fall back to the deltas received from peers.
+                Err(source) => delta_pool
+                    .get_updates_since(version)
+                    .map(|records| (records, None))
+                    .ok_or(RegistryDataProviderError::Transfer { source }),
+            };
+        }
+
+        let (records, _version, time) = async_safe_block_on_await({
             let registry_canister = Arc::clone(&self.registry_canister);
             let nns_public_key = Arc::clone(&self.nns_public_key);
             async move {
@@ -89,6 +141,6 @@ impl RegistryDataProvider for CertifiedNnsDataProvider {
                     .map_err(|source| RegistryDataProviderError::Transfer { source })
             }
         })?;
-        Ok(records)
+        Ok((records, Some(time)))
     }
 }
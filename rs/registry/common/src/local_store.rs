@@ -6,7 +6,7 @@ use ic_interfaces::registry::{
     LocalStoreCertifiedTimeReader, RegistryDataProvider, RegistryTransportRecord,
 };
 use ic_types::registry::RegistryDataProviderError;
-use ic_types::RegistryVersion;
+use ic_types::{RegistryVersion, Time};
 use ic_utils::fs::write_protobuf_using_tmp_file;
 use prost::Message;
 use std::{
@@ -214,6 +214,19 @@ impl RegistryDataProvider for LocalStoreImpl {
             .collect();
         Ok(res)
     }
+
+    /// The certified time is read before the changelog, so that it doesn't
+    /// cover versions that were stored after the changelog was read.
+    fn get_certified_updates_since(
+        &self,
+        version: RegistryVersion,
+    ) -> Result<(Vec<RegistryTransportRecord>, Option<Time>), RegistryDataProviderError> {
+        let certified_time = self.read_certified_time();
+        let records = self.get_updates_since(version)?;
+        let certified_time =
+            Some(certified_time).filter(|time| *time != Time::from_nanos_since_unix_epoch(0));
+        Ok((records, certified_time))
+    }
 }
 
 impl TryFrom<PbChangelogEntry> for ChangelogEntry {
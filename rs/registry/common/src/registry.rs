@@ -111,9 +111,23 @@ impl RegistryCanister {
         version: u64,
         nns_public_key: &ThresholdSigPublicKey,
     ) -> Result<(Vec<RegistryTransportRecord>, RegistryVersion, Time), Error> {
+        let response = self.get_certified_response_since(version).await?;
+
+        crate::certification::decode_certified_deltas(
+            version,
+            &self.canister_id,
+            nns_public_key,
+            &response[..],
+        )
+        .map_err(|err| Error::UnknownError(format!("{:?}", err)))
+    }
+
+    /// Queries the certified endpoint of the registry for all the changes that
+    /// occurred since `version` and returns the response without verifying or
+    /// decoding it, e.g. to pass it on to other nodes.
+    pub async fn get_certified_response_since(&self, version: u64) -> Result<Vec<u8>, Error> {
         let payload = serialize_get_changes_since_request(version).unwrap();
-        let response = self
-            .choose_random_agent()
+        self.choose_random_agent()
             .execute_query(&self.canister_id, "get_certified_changes_since", payload)
             .await
             .map_err(|err| {
@@ -127,15 +141,7 @@ impl RegistryCanister {
                     "No response was received when queried get_certified_changes_since on {}",
                     self.canister_id,
                 ))
-            })?;
-
-        crate::certification::decode_certified_deltas(
-            version,
-            &self.canister_id,
-            nns_public_key,
-            &response[..],
-        )
-        .map_err(|err| Error::UnknownError(format!("{:?}", err)))
+            })
     }
 
     pub async fn get_latest_version(&self) -> Result<u64, Error> {
@@ -52,10 +52,20 @@ pub fn make_routing_table_record_key() -> String {
     "routing_table".to_string()
 }
 
+pub fn make_canister_migrations_record_key() -> String {
+    "canister_migrations".to_string()
+}
+
 pub fn make_firewall_config_record_key() -> String {
     "firewall_config".to_string()
 }
 
+/// Returns the only key whose payload is the fee schedule of application
+/// subnets.
+pub fn make_fee_schedule_record_key() -> String {
+    "fee_schedule".to_string()
+}
+
 pub fn make_provisional_whitelist_record_key() -> String {
     "provisional_whitelist".to_string()
 }
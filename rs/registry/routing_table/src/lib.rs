@@ -131,6 +131,8 @@ pub enum WellFormedError {
     RoutingTableNonEmptyRange(String),
     RoutingTableAppGroupSplit(String),
     RoutingTableNotDisjoint(String),
+    CanisterMigrationsNotDisjoint(String),
+    CanisterMigrationsInvalidTrace(String),
 }
 
 /// A list of closed `CanisterId` ranges that are present in the `RoutingTable`
@@ -333,48 +335,7 @@ impl RoutingTable {
         // If the `principal_id` was not a subnet, it must be a `CanisterId` (otherwise
         // we can't route to it).
         match CanisterId::try_from(principal_id) {
-            Ok(canister_id) => {
-                // In simple terms, we need to do a binary search of all the interval
-                // ranges tracked in self to see if `canister_id` in included in any of
-                // them.  BTreeMap offers this functionality in the form of the
-                // `range()` function.  In particular, assume self is [a1, b1] ... [an,
-                // bn].  Pretend to insert [canister_id, u64::MAX] into this sequence.
-                // We look for the interval [i1, i2] that is before (or equal to) the
-                // position where [caniter_id, u64::MAX] would be inserted.
-                let before = self
-                    .0
-                    .range(
-                        ..=(CanisterIdRange {
-                            start: canister_id,
-                            end: CanisterId::from(u64::MAX),
-                        }),
-                    )
-                    .next_back();
-                if let Some((interval, subnet_id)) = before {
-                    // We found an interval [star, end], it must be the case that
-                    // [start, end]<=[canister_id, u64::MAX] lexicographically, whence
-                    // start <= canister_id.
-                    assert!(interval.start <= canister_id);
-                    // If canister_id is in the interval then we found our answer.
-                    if canister_id <= interval.end {
-                        Some(*subnet_id)
-                    } else {
-                        // In this case, either [start, end] is the last interval in the
-                        // map and c comes after end, or there is an interval [a,b] in
-                        // the map such that lexicographically [start, end] <= [c,
-                        // u64::MAX] < [a, b]. This means that canister_id < a so
-                        // canister_id is not assigned to any subnetwork. Because if
-                        // canister_id == a, then u64::MAX < b which is impossible.
-                        None
-                    }
-                } else {
-                    // All intervals [a,b] of the map are lexicographically > than
-                    // [canister_id, u64::MAX]. But if [a, b] > [canister_id, u64::MAX]
-                    // then a > canister_id, which means that canister_id is unassigned
-                    // (or a == b and b > u64::MAX which is impossible).
-                    None
-                }
-            }
+            Ok(canister_id) => lookup_in_ranges(&self.0, canister_id).cloned(),
             // Cannot route to any subnet as we couldn't convert to a `CanisterId`.
             Err(_) => None,
         }
@@ -394,6 +355,50 @@ impl RoutingTable {
     }
 }
 
+/// Returns the value of the range in `map` that contains `canister_id`, if
+/// any. The ranges in `map` must be disjoint.
+fn lookup_in_ranges<V>(map: &BTreeMap<CanisterIdRange, V>, canister_id: CanisterId) -> Option<&V> {
+    // In simple terms, we need to do a binary search of all the interval
+    // ranges tracked in `map` to see if `canister_id` in included in any of
+    // them.  BTreeMap offers this functionality in the form of the
+    // `range()` function.  In particular, assume `map` is [a1, b1] ... [an,
+    // bn].  Pretend to insert [canister_id, u64::MAX] into this sequence.
+    // We look for the interval [i1, i2] that is before (or equal to) the
+    // position where [caniter_id, u64::MAX] would be inserted.
+    let before = map
+        .range(
+            ..=(CanisterIdRange {
+                start: canister_id,
+                end: CanisterId::from(u64::MAX),
+            }),
+        )
+        .next_back();
+    if let Some((interval, value)) = before {
+        // We found an interval [star, end], it must be the case that
+        // [start, end]<=[canister_id, u64::MAX] lexicographically, whence
+        // start <= canister_id.
+        assert!(interval.start <= canister_id);
+        // If canister_id is in the interval then we found our answer.
+        if canister_id <= interval.end {
+            Some(value)
+        } else {
+            // In this case, either [start, end] is the last interval in the
+            // map and c comes after end, or there is an interval [a,b] in
+            // the map such that lexicographically [start, end] <= [c,
+            // u64::MAX] < [a, b]. This means that canister_id < a so
+            // canister_id is not assigned to any subnetwork. Because if
+            // canister_id == a, then u64::MAX < b which is impossible.
+            None
+        }
+    } else {
+        // All intervals [a,b] of the map are lexicographically > than
+        // [canister_id, u64::MAX]. But if [a, b] > [canister_id, u64::MAX]
+        // then a > canister_id, which means that canister_id is unassigned
+        // (or a == b and b > u64::MAX which is impossible).
+        None
+    }
+}
+
 impl IntoIterator for RoutingTable {
     type Item = (CanisterIdRange, SubnetId);
     type IntoIter = std::collections::btree_map::IntoIter<CanisterIdRange, SubnetId>;
@@ -403,6 +408,78 @@ impl IntoIterator for RoutingTable {
     }
 }
 
+/// Stores the canister ID ranges that are being migrated between subnets,
+/// e.g. because a subnet is split, mapped to the subnets that hosted them, from
+/// the source to the destination subnet (the "trace").  The ranges tracked are
+/// inclusive of start and end i.e. can be denoted as [a, b].
+///
+/// While a range is being migrated, messages sent by its canisters may still
+/// arrive from any subnet of its trace and messages addressed to its canisters
+/// may still arrive at any subnet of its trace.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CanisterMigrations(BTreeMap<CanisterIdRange, Vec<SubnetId>>);
+
+impl CanisterMigrations {
+    pub fn new(map: BTreeMap<CanisterIdRange, Vec<SubnetId>>) -> Self {
+        let ret = Self(map);
+        assert_eq!(ret.well_formed(), Ok(()));
+        ret
+    }
+
+    /// Records that the given range is migrated from `source` to
+    /// `destination`.
+    pub fn insert_range(
+        &mut self,
+        range: CanisterIdRange,
+        source: SubnetId,
+        destination: SubnetId,
+    ) -> Result<(), WellFormedError> {
+        self.0.insert(range, vec![source, destination]);
+        self.well_formed()
+    }
+
+    pub fn iter(&self) -> impl std::iter::Iterator<Item = (&CanisterIdRange, &Vec<SubnetId>)> {
+        self.0.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns true if the canister migrations are well-formed.
+    pub fn well_formed(&self) -> Result<(), WellFormedError> {
+        use WellFormedError::*;
+
+        let mut previous_end: Option<CanisterId> = None;
+        for (range, trace) in self.0.iter() {
+            // Check that ranges are non-empty and disjoint.
+            if range.start > range.end || previous_end >= Some(range.start) {
+                return Err(CanisterMigrationsNotDisjoint(format!(
+                    "Previous end {:?}, current range {:?}",
+                    previous_end, range
+                )));
+            }
+            previous_end = Some(range.end);
+
+            // Check that each range moves between at least two subnets.
+            if trace.len() < 2 || trace.windows(2).any(|w| w[0] == w[1]) {
+                return Err(CanisterMigrationsInvalidTrace(format!(
+                    "Invalid trace {:?} of range {:?}",
+                    trace, range
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the trace of the migration of `canister_id` or `None` if it is
+    /// not being migrated.
+    pub fn lookup(&self, canister_id: CanisterId) -> Option<&[SubnetId]> {
+        lookup_in_ranges(&self.0, canister_id).map(|trace| &trace[..])
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -631,4 +708,37 @@ mod test {
         assert_eq!(rt.route(subnet_id5.get()), None);
         assert_eq!(rt.route(subnet_id12.get()), None);
     }
+
+    #[test]
+    fn canister_migrations_lookup() {
+        let range = |start: u64, end: u64| CanisterIdRange {
+            start: CanisterId::from(start),
+            end: CanisterId::from(end),
+        };
+        let mut migrations = CanisterMigrations::default();
+        migrations
+            .insert_range(range(0x100, 0x1ff), subnet_test_id(1), subnet_test_id(2))
+            .unwrap();
+        migrations
+            .insert_range(range(0x1000, 0x1fff), subnet_test_id(3), subnet_test_id(4))
+            .unwrap();
+
+        let trace = |a, b| Some(&[subnet_test_id(a), subnet_test_id(b)][..]);
+        assert_eq!(migrations.lookup(CanisterId::from(0xff)), None);
+        assert_eq!(migrations.lookup(CanisterId::from(0x100)), trace(1, 2));
+        assert_eq!(migrations.lookup(CanisterId::from(0x1ff)), trace(1, 2));
+        assert_eq!(migrations.lookup(CanisterId::from(0x200)), None);
+        assert_eq!(migrations.lookup(CanisterId::from(0x1500)), trace(3, 4));
+
+        assert_matches!(
+            migrations.insert_range(range(0x180, 0x2ff), subnet_test_id(1), subnet_test_id(2)),
+            Err(WellFormedError::CanisterMigrationsNotDisjoint(_))
+        );
+
+        let mut migrations = CanisterMigrations::default();
+        assert_matches!(
+            migrations.insert_range(range(0x100, 0x1ff), subnet_test_id(1), subnet_test_id(1)),
+            Err(WellFormedError::CanisterMigrationsInvalidTrace(_))
+        );
+    }
 }
@@ -1,4 +1,4 @@
-use super::{CanisterIdRange, CanisterIdRanges, RoutingTable};
+use super::{CanisterIdRange, CanisterIdRanges, CanisterMigrations, RoutingTable};
 use ic_base_types::{subnet_id_into_protobuf, subnet_id_try_from_protobuf, CanisterId};
 use ic_protobuf::{
     proxy::{try_from_option_field, ProxyDecodeError},
@@ -86,3 +86,41 @@ impl TryFrom<pb::RoutingTable> for RoutingTable {
         Ok(Self(map))
     }
 }
+
+impl From<CanisterMigrations> for pb::CanisterMigrations {
+    fn from(src: CanisterMigrations) -> Self {
+        let entries = src
+            .0
+            .into_iter()
+            .map(|(range, trace)| pb::canister_migrations::Entry {
+                range: Some(pb::CanisterIdRange::from(range)),
+                subnet_ids: trace.into_iter().map(subnet_id_into_protobuf).collect(),
+            })
+            .collect();
+        Self { entries }
+    }
+}
+
+impl TryFrom<pb::CanisterMigrations> for CanisterMigrations {
+    type Error = ProxyDecodeError;
+
+    fn try_from(src: pb::CanisterMigrations) -> Result<Self, Self::Error> {
+        let mut map = BTreeMap::new();
+        for entry in src.entries {
+            let range = try_from_option_field(entry.range, "CanisterMigrations::Entry::range")?;
+            let trace = entry
+                .subnet_ids
+                .into_iter()
+                .map(subnet_id_try_from_protobuf)
+                .collect::<Result<Vec<_>, _>>()?;
+            if let Some(prev_trace) = map.insert(range, trace) {
+                return Err(ProxyDecodeError::DuplicateEntry {
+                    key: format!("{:?}", range),
+                    v1: format!("{:?}", prev_trace),
+                    v2: format!("{:?}", map[&range]),
+                });
+            }
+        }
+        Ok(Self(map))
+    }
+}
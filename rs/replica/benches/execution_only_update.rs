@@ -111,12 +111,12 @@ fn build_batch(message_routing: &dyn MessageRouting, msgs: Vec<SignedIngress>) -
     Batch {
         batch_number: message_routing.expected_batch_height(),
         requires_full_state_hash: !msgs.is_empty(),
-        payload: BatchPayload {
-            ingress: IngressPayload::from(msgs),
-            xnet: XNetPayload {
+        payload: BatchPayload::new(
+            IngressPayload::from(msgs),
+            XNetPayload {
                 stream_slices: Default::default(),
             },
-        },
+        ),
         randomness: Randomness::from([0; 32]),
         registry_version: RegistryVersion::from(1),
         time: mock_time(),
@@ -181,7 +181,7 @@ fn criterion_calls(criterion: &mut Criterion) {
             .cycles_account_manager_config,
     ));
 
-    let (_, ingress_history_writer, _, scheduler) = setup_execution(
+    let (_, ingress_history_writer, _, scheduler, _) = setup_execution(
         bench_replica.log.clone(),
         &bench_replica.metrics_registry,
         bench_replica.replica_config.subnet_id,
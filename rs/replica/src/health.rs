@@ -0,0 +1,571 @@
+//! The health self-assessment of the replica.
+//!
+//! The replica checks the progress of its components against the thresholds
+//! of the `health` section of its configuration:
+//! * P2P: the share of the peers of the subnet that are connected.
+//! * Consensus: the time since a new height was last finalized.
+//! * Certification: the time since the finalized blocks last referenced a new
+//!   certified height.
+//! * Execution: the number of finalized heights that are not executed yet.
+//! * State manager: the number of heights since the latest checkpoint.
+//!
+//! The most severe outcome of the checks is the health of the replica, which
+//! is reported on the status endpoint, where the node manager picks it up.
+//!
+//! The finalized and certified heights are watched by a background thread,
+//! which subscribes to them in the consensus pool cache and records when
+//! they change, so the stalls are measured independently of how often the
+//! health is assessed. Listing the checkpoints requires reading the state
+//! directory, so the latest checkpoint height is refreshed by another
+//! background thread every `CHECKPOINT_REFRESH_INTERVAL` rather than on every
+//! assessment.
+use crossbeam_channel::{bounded, RecvTimeoutError, Sender};
+use ic_config::health::Config as HealthConfig;
+use ic_interfaces::{
+    consensus_pool::ConsensusPoolCache,
+    health::ReplicaHealthAssessor,
+    messaging::MessageRouting,
+    p2p::{PeerConnectivity, PeerConnectivityReader},
+    state_manager::StateReader,
+};
+use ic_logger::{info, warn, ReplicaLogger};
+use ic_replicated_state::ReplicatedState;
+use ic_types::{messages::ReplicaHealthAssessment, Height};
+use ic_utils::thread::JoinOnDrop;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::{oneshot, watch};
+
+/// How often the latest checkpoint height is refreshed.
+const CHECKPOINT_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Returns the height of the latest checkpoint on disk, or a description of
+/// why the checkpoints cannot be listed.
+pub type LatestCheckpointHeight = Box<dyn Fn() -> Result<Height, String> + Send>;
+
+/// A finding of a check, with the assessment it implies.
+type HealthIssue = (ReplicaHealthAssessment, String);
+
+/// The state kept between two assessments.
+struct MonitorState {
+    /// The outcome of the previous assessment, to log changes only.
+    assessment: ReplicaHealthAssessment,
+}
+
+/// The latest height seen, and when it was first seen.
+#[derive(Clone, Copy)]
+struct HeightProgress {
+    height: Height,
+    since: Instant,
+}
+
+impl HeightProgress {
+    fn new(height: Height) -> Self {
+        Self {
+            height,
+            since: Instant::now(),
+        }
+    }
+
+    fn advance(&mut self, height: Height) {
+        if height > self.height {
+            *self = Self::new(height);
+        }
+    }
+}
+
+/// Assesses the health of the replica on demand.
+pub struct ReplicaHealthMonitor {
+    config: HealthConfig,
+    peer_connectivity: Arc<dyn PeerConnectivityReader>,
+    message_routing: Arc<dyn MessageRouting>,
+    state_reader: Arc<dyn StateReader<State = ReplicatedState>>,
+    state: Mutex<MonitorState>,
+    log: ReplicaLogger,
+    /// The latest finalized and certified heights, as last recorded by the
+    /// height watcher thread.
+    finalized: Arc<Mutex<HeightProgress>>,
+    certified: Arc<Mutex<HeightProgress>>,
+    /// The latest checkpoint height, as last listed by the refresher thread.
+    latest_checkpoint_height: Arc<RwLock<Result<Height, String>>>,
+    // Dropping the senders stops the threads, so they must be dropped before
+    // the handles, which join the threads.
+    _stop_height_watcher: oneshot::Sender<()>,
+    _stop_checkpoint_refresher: Sender<()>,
+    _height_watcher_handle: JoinOnDrop<()>,
+    _checkpoint_refresher_handle: JoinOnDrop<()>,
+}
+
+impl ReplicaHealthMonitor {
+    pub fn new(
+        config: HealthConfig,
+        peer_connectivity: Arc<dyn PeerConnectivityReader>,
+        consensus_pool_cache: Arc<dyn ConsensusPoolCache>,
+        message_routing: Arc<dyn MessageRouting>,
+        state_reader: Arc<dyn StateReader<State = ReplicatedState>>,
+        checkpoint_height: LatestCheckpointHeight,
+        log: ReplicaLogger,
+    ) -> Self {
+        let finalized_height = consensus_pool_cache.subscribe_finalized_height();
+        let certified_height = consensus_pool_cache.subscribe_certified_height();
+        let finalized = Arc::new(Mutex::new(HeightProgress::new(*finalized_height.borrow())));
+        let certified = Arc::new(Mutex::new(HeightProgress::new(*certified_height.borrow())));
+        let (stop_watcher_sender, stop_watcher_receiver) = oneshot::channel();
+        let watcher_handle = {
+            let finalized = Arc::clone(&finalized);
+            let certified = Arc::clone(&certified);
+            std::thread::Builder::new()
+                .name("HeightWatcher".to_string())
+                .spawn(move || {
+                    watch_heights(
+                        finalized_height,
+                        certified_height,
+                        finalized,
+                        certified,
+                        stop_watcher_receiver,
+                    )
+                })
+                .expect("failed to spawn the height watcher thread")
+        };
+
+        let latest_checkpoint_height = Arc::new(RwLock::new(checkpoint_height()));
+        let (stop_sender, stop_receiver) = bounded::<()>(0);
+        let refreshed_height = Arc::clone(&latest_checkpoint_height);
+        let handle = std::thread::Builder::new()
+            .name("CheckpointHeightRefresher".to_string())
+            .spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) =
+                    stop_receiver.recv_timeout(CHECKPOINT_REFRESH_INTERVAL)
+                {
+                    *refreshed_height.write().unwrap() = checkpoint_height();
+                }
+            })
+            .expect("failed to spawn the checkpoint height refresher thread");
+        Self {
+            config,
+            peer_connectivity,
+            message_routing,
+            state_reader,
+            state: Mutex::new(MonitorState {
+                assessment: ReplicaHealthAssessment::Healthy,
+            }),
+            log,
+            finalized,
+            certified,
+            latest_checkpoint_height,
+            _stop_height_watcher: stop_watcher_sender,
+            _stop_checkpoint_refresher: stop_sender,
+            _height_watcher_handle: JoinOnDrop::new(watcher_handle),
+            _checkpoint_refresher_handle: JoinOnDrop::new(handle),
+        }
+    }
+
+    fn check_peer_connectivity(&self, issues: &mut Vec<HealthIssue>) {
+        let PeerConnectivity {
+            peers,
+            connected_peers,
+        } = self.peer_connectivity.peer_connectivity();
+        if peers == 0 {
+            return;
+        }
+        if connected_peers == 0 {
+            issues.push((
+                ReplicaHealthAssessment::Unhealthy,
+                format!("none of the {} peers is connected", peers),
+            ));
+        } else if (connected_peers as u64) * 100
+            < self.config.min_connected_peers_percent * peers as u64
+        {
+            issues.push((
+                ReplicaHealthAssessment::Degraded,
+                format!(
+                    "only {} of the {} peers are connected",
+                    connected_peers, peers
+                ),
+            ));
+        }
+    }
+
+    fn check_finalization(&self, finalized: HeightProgress, issues: &mut Vec<HealthIssue>) {
+        let stalled_for = finalized.since.elapsed();
+        if stalled_for > Duration::from_secs(self.config.max_finalization_stall_secs) {
+            issues.push((
+                ReplicaHealthAssessment::Unhealthy,
+                format!(
+                    "no height was finalized since height {} for {:?}",
+                    finalized.height, stalled_for
+                ),
+            ));
+        }
+    }
+
+    fn check_certification(&self, issues: &mut Vec<HealthIssue>) {
+        let certified = *self.certified.lock().unwrap();
+        let stalled_for = certified.since.elapsed();
+        if stalled_for > Duration::from_secs(self.config.max_certification_stall_secs) {
+            issues.push((
+                ReplicaHealthAssessment::Degraded,
+                format!(
+                    "no height was certified since height {} for {:?}",
+                    certified.height, stalled_for
+                ),
+            ));
+        }
+    }
+
+    fn check_execution(&self, finalized_height: Height, issues: &mut Vec<HealthIssue>) {
+        let executed_height = self
+            .message_routing
+            .expected_batch_height()
+            .get()
+            .saturating_sub(1);
+        let lag = finalized_height.get().saturating_sub(executed_height);
+        if lag > self.config.max_execution_lag {
+            issues.push((
+                ReplicaHealthAssessment::Degraded,
+                format!(
+                    "execution is {} heights behind the finalized height {}",
+                    lag, finalized_height
+                ),
+            ));
+        }
+    }
+
+    fn check_checkpoints(&self, issues: &mut Vec<HealthIssue>) {
+        let latest_checkpoint_height = match &*self.latest_checkpoint_height.read().unwrap() {
+            Ok(height) => *height,
+            Err(err) => {
+                issues.push((
+                    ReplicaHealthAssessment::Degraded,
+                    format!("the checkpoints cannot be listed: {}", err),
+                ));
+                return;
+            }
+        };
+        let latest_state_height = self.state_reader.latest_state_height();
+        let lag = latest_state_height
+            .get()
+            .saturating_sub(latest_checkpoint_height.get());
+        if lag > self.config.max_checkpoint_lag {
+            issues.push((
+                ReplicaHealthAssessment::Degraded,
+                format!(
+                    "the latest state {} is {} heights ahead of the latest checkpoint",
+                    latest_state_height, lag
+                ),
+            ));
+        }
+    }
+}
+
+impl ReplicaHealthAssessor for ReplicaHealthMonitor {
+    fn assess_health(&self) -> ReplicaHealthAssessment {
+        let mut issues = Vec::new();
+        let finalized = *self.finalized.lock().unwrap();
+        let mut state = self.state.lock().unwrap();
+        self.check_peer_connectivity(&mut issues);
+        self.check_finalization(finalized, &mut issues);
+        self.check_certification(&mut issues);
+        self.check_execution(finalized.height, &mut issues);
+        self.check_checkpoints(&mut issues);
+
+        let assessment = issues
+            .iter()
+            .map(|(assessment, _)| *assessment)
+            .max()
+            .unwrap_or(ReplicaHealthAssessment::Healthy);
+        if assessment != state.assessment {
+            if issues.is_empty() {
+                info!(self.log, "The replica is healthy again");
+            } else {
+                let descriptions: Vec<_> = issues
+                    .iter()
+                    .map(|(_, description)| description.as_str())
+                    .collect();
+                warn!(
+                    self.log,
+                    "The replica is {:?}: {}",
+                    assessment,
+                    descriptions.join("; ")
+                );
+            }
+            state.assessment = assessment;
+        }
+        assessment
+    }
+}
+
+/// Records the changes of the finalized and certified heights until `stop`
+/// is signalled or the consensus pool cache is dropped.
+fn watch_heights(
+    mut finalized_height: watch::Receiver<Height>,
+    mut certified_height: watch::Receiver<Height>,
+    finalized: Arc<Mutex<HeightProgress>>,
+    certified: Arc<Mutex<HeightProgress>>,
+    mut stop: oneshot::Receiver<()>,
+) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .expect("failed to build the runtime of the height watcher");
+    runtime.block_on(async move {
+        loop {
+            tokio::select! {
+                changed = finalized_height.changed() => match changed {
+                    Ok(()) => finalized.lock().unwrap().advance(*finalized_height.borrow()),
+                    Err(_) => break,
+                },
+                changed = certified_height.changed() => match changed {
+                    Ok(()) => certified.lock().unwrap().advance(*certified_height.borrow()),
+                    Err(_) => break,
+                },
+                _ = &mut stop => break,
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_logger::replica_logger::no_op_logger;
+    use ic_test_utilities::{
+        consensus::MockConsensusCache, message_routing::MockMessageRouting,
+        state_manager::MockStateManager,
+    };
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct FakePeerConnectivity(PeerConnectivity);
+
+    impl PeerConnectivityReader for FakePeerConnectivity {
+        fn peer_connectivity(&self) -> PeerConnectivity {
+            self.0
+        }
+    }
+
+    /// The progress of the components that the monitor observes.
+    struct Progress {
+        connected_peers: usize,
+        finalized_height: u64,
+        executed_height: u64,
+        latest_state_height: u64,
+    }
+
+    impl Default for Progress {
+        fn default() -> Self {
+            Self {
+                connected_peers: 3,
+                finalized_height: 100,
+                executed_height: 100,
+                latest_state_height: 100,
+            }
+        }
+    }
+
+    /// The senders of the heights that the monitor subscribes to.
+    struct Heights {
+        finalized: watch::Sender<Height>,
+        certified: watch::Sender<Height>,
+    }
+
+    fn monitor(
+        config: HealthConfig,
+        progress: Progress,
+        checkpoint_height: LatestCheckpointHeight,
+    ) -> ReplicaHealthMonitor {
+        monitor_with_heights(config, progress, checkpoint_height).0
+    }
+
+    fn monitor_with_heights(
+        config: HealthConfig,
+        progress: Progress,
+        checkpoint_height: LatestCheckpointHeight,
+    ) -> (ReplicaHealthMonitor, Heights) {
+        let mut consensus_pool_cache = MockConsensusCache::new();
+        let (finalized, finalized_height) = watch::channel(Height::from(progress.finalized_height));
+        let (certified, certified_height) = watch::channel(Height::from(progress.finalized_height));
+        consensus_pool_cache
+            .expect_subscribe_finalized_height()
+            .returning(move || finalized_height.clone());
+        consensus_pool_cache
+            .expect_subscribe_certified_height()
+            .returning(move || certified_height.clone());
+        let mut message_routing = MockMessageRouting::new();
+        let executed_height = progress.executed_height;
+        message_routing
+            .expect_expected_batch_height()
+            .returning(move || Height::from(executed_height + 1));
+        let mut state_manager = MockStateManager::new();
+        let latest_state_height = progress.latest_state_height;
+        state_manager
+            .expect_latest_state_height()
+            .returning(move || Height::from(latest_state_height));
+        let monitor = ReplicaHealthMonitor::new(
+            config,
+            Arc::new(FakePeerConnectivity(PeerConnectivity {
+                peers: 3,
+                connected_peers: progress.connected_peers,
+            })),
+            Arc::new(consensus_pool_cache),
+            Arc::new(message_routing),
+            Arc::new(state_manager),
+            checkpoint_height,
+            no_op_logger(),
+        );
+        (
+            monitor,
+            Heights {
+                finalized,
+                certified,
+            },
+        )
+    }
+
+    /// Waits until the height watcher recorded the given height.
+    fn await_height(progress: &Mutex<HeightProgress>, height: u64) {
+        while progress.lock().unwrap().height < Height::from(height) {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn checkpoint_at(height: u64) -> LatestCheckpointHeight {
+        Box::new(move || Ok(Height::from(height)))
+    }
+
+    #[test]
+    fn replica_making_progress_is_healthy() {
+        let monitor = monitor(
+            HealthConfig::default(),
+            Progress::default(),
+            checkpoint_at(0),
+        );
+        assert_eq!(monitor.assess_health(), ReplicaHealthAssessment::Healthy);
+    }
+
+    #[test]
+    fn replica_with_few_connected_peers_is_degraded() {
+        let progress = Progress {
+            connected_peers: 1,
+            ..Default::default()
+        };
+        let monitor = monitor(HealthConfig::default(), progress, checkpoint_at(0));
+        assert_eq!(monitor.assess_health(), ReplicaHealthAssessment::Degraded);
+    }
+
+    #[test]
+    fn replica_without_connected_peers_is_unhealthy() {
+        let progress = Progress {
+            connected_peers: 0,
+            ..Default::default()
+        };
+        let monitor = monitor(HealthConfig::default(), progress, checkpoint_at(0));
+        assert_eq!(monitor.assess_health(), ReplicaHealthAssessment::Unhealthy);
+    }
+
+    #[test]
+    fn replica_with_execution_lag_is_degraded() {
+        let config = HealthConfig::default();
+        let progress = Progress {
+            executed_height: 100 - config.max_execution_lag - 1,
+            ..Default::default()
+        };
+        let monitor = monitor(config, progress, checkpoint_at(0));
+        assert_eq!(monitor.assess_health(), ReplicaHealthAssessment::Degraded);
+    }
+
+    #[test]
+    fn replica_with_stalled_finalization_is_unhealthy() {
+        let config = HealthConfig {
+            max_finalization_stall_secs: 0,
+            ..Default::default()
+        };
+        let monitor = monitor(config, Progress::default(), checkpoint_at(0));
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(monitor.assess_health(), ReplicaHealthAssessment::Unhealthy);
+    }
+
+    #[test]
+    fn replica_with_stalled_certification_is_degraded() {
+        let config = HealthConfig {
+            max_certification_stall_secs: 0,
+            ..Default::default()
+        };
+        let monitor = monitor(config, Progress::default(), checkpoint_at(0));
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(monitor.assess_health(), ReplicaHealthAssessment::Degraded);
+    }
+
+    #[test]
+    fn progress_between_assessments_is_observed() {
+        let config = HealthConfig {
+            max_finalization_stall_secs: 1,
+            max_certification_stall_secs: 1,
+            ..Default::default()
+        };
+        let (monitor, heights) =
+            monitor_with_heights(config, Progress::default(), checkpoint_at(0));
+        assert_eq!(monitor.assess_health(), ReplicaHealthAssessment::Healthy);
+
+        std::thread::sleep(Duration::from_millis(1100));
+        heights.finalized.send(Height::from(101)).unwrap();
+        heights.certified.send(Height::from(101)).unwrap();
+        await_height(&monitor.finalized, 101);
+        await_height(&monitor.certified, 101);
+
+        // The heights advanced since the previous assessment, so neither
+        // finalization nor certification stalled.
+        assert_eq!(monitor.assess_health(), ReplicaHealthAssessment::Healthy);
+    }
+
+    #[test]
+    fn most_severe_issue_determines_the_assessment() {
+        let config = HealthConfig::default();
+        let progress = Progress {
+            connected_peers: 0,
+            executed_height: 100 - config.max_execution_lag - 1,
+            ..Default::default()
+        };
+        let monitor = monitor(config, progress, checkpoint_at(0));
+        assert_eq!(monitor.assess_health(), ReplicaHealthAssessment::Unhealthy);
+    }
+
+    #[test]
+    fn replica_with_checkpoint_lag_is_degraded() {
+        let config = HealthConfig::default();
+        let progress = Progress {
+            finalized_height: config.max_checkpoint_lag + 1,
+            executed_height: config.max_checkpoint_lag + 1,
+            latest_state_height: config.max_checkpoint_lag + 1,
+            ..Default::default()
+        };
+        let monitor = monitor(config, progress, checkpoint_at(0));
+        assert_eq!(monitor.assess_health(), ReplicaHealthAssessment::Degraded);
+    }
+
+    #[test]
+    fn replica_without_listable_checkpoints_is_degraded() {
+        let monitor = monitor(
+            HealthConfig::default(),
+            Progress::default(),
+            Box::new(|| Err("permission denied".to_string())),
+        );
+        assert_eq!(monitor.assess_health(), ReplicaHealthAssessment::Degraded);
+    }
+
+    #[test]
+    fn checkpoints_are_not_listed_on_every_assessment() {
+        let listings = Arc::new(AtomicUsize::new(0));
+        let counted_listings = Arc::clone(&listings);
+        let monitor = monitor(
+            HealthConfig::default(),
+            Progress::default(),
+            Box::new(move || {
+                counted_listings.fetch_add(1, Ordering::SeqCst);
+                Ok(Height::from(0))
+            }),
+        );
+        for _ in 0..10 {
+            assert_eq!(monitor.assess_health(), ReplicaHealthAssessment::Healthy);
+        }
+        assert_eq!(listings.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,585 @@
+//! Rotation of the node's committee signing and TLS keys.
+//!
+//! Once the node's keys are older than the configured rotation period, new
+//! keys are generated in the secret key store and submitted to the registry
+//! canister in an `update_node_keys` request signed with the node signing
+//! key. The crypto component selects keys by registry version, so the node
+//! switches to the new keys exactly at the registry version that contains
+//! them, except for the TLS certificate, which is switched with a grace
+//! period, see `TLS_CERT_REPLACEMENT_GRACE_PERIOD`.
+//!
+//! The new public keys are kept in a file in the crypto root until they are
+//! in the local registry, so that a restart in between does not generate
+//! another set of keys. Then they replace the old ones in the public key
+//! store, and the old public keys are kept in another file until their
+//! secret keys are removed. This happens once the oldest registry version in
+//! use by the subnet registers the new keys, e.g. after the next DKG
+//! interval, and the TLS grace period is over.
+use candid::Encode;
+use ic_canister_client::{ed25519_public_key_to_der, Agent, Sender};
+use ic_crypto::utils::store_node_public_keys;
+use ic_crypto_tls_interfaces::TLS_CERT_REPLACEMENT_GRACE_PERIOD;
+use ic_interfaces::crypto::{BasicSigner, KeyManager, DOMAIN_IC_REQUEST};
+use ic_interfaces::registry::RegistryClient;
+use ic_logger::{info, warn, ReplicaLogger};
+use ic_nns_constants::REGISTRY_CANISTER_ID;
+use ic_protobuf::crypto::v1::NodePublicKeys;
+use ic_registry_client::helper::{
+    crypto::CryptoRegistry, node::NodeRegistry, subnet::SubnetRegistry,
+};
+use ic_registry_keys::make_crypto_tls_cert_key;
+use ic_types::time::current_time;
+use ic_types::{crypto::KeyPurpose, messages::MessageId, NodeId, RegistryVersion};
+use prost::Message;
+use rand::seq::SliceRandom;
+use registry_canister::mutations::do_update_node_keys::UpdateNodeKeysPayload;
+use std::convert::TryFrom;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use url::Url;
+
+const KEY_ROTATION_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The file of the public key store, whose modification time is the time the
+/// current keys were generated or activated.
+const PUBLIC_KEYS_FILENAME: &str = "public_keys.pb";
+
+/// The file holding the generated keys that are not in the registry yet.
+const ROTATED_KEYS_FILENAME: &str = "rotated_public_keys.pb";
+
+/// The file holding the replaced keys whose secret keys are not removed yet.
+const RETIRED_KEYS_FILENAME: &str = "retired_public_keys.pb";
+
+pub struct KeyRotation {
+    node_id: NodeId,
+    crypto_root: PathBuf,
+    period: Duration,
+    key_manager: Arc<dyn KeyManager + Send + Sync>,
+    signer: Arc<dyn BasicSigner<MessageId> + Send + Sync>,
+    registry: Arc<dyn RegistryClient>,
+    oldest_registry_version_in_use: Box<dyn Fn() -> RegistryVersion + Send>,
+    log: ReplicaLogger,
+
+    // How long after the registry version registering the new keys became
+    // available locally the secret keys of the replaced ones are removed at
+    // the earliest. The peers trust the replaced TLS certificate for twice
+    // the grace period after learning about the new one.
+    retirement_delay: Duration,
+    // Whether the registry canister accepted the rotated keys since the
+    // replica started.
+    submitted: bool,
+}
+
+impl KeyRotation {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        node_id: NodeId,
+        crypto_root: PathBuf,
+        period: Duration,
+        key_manager: Arc<dyn KeyManager + Send + Sync>,
+        signer: Arc<dyn BasicSigner<MessageId> + Send + Sync>,
+        registry: Arc<dyn RegistryClient>,
+        oldest_registry_version_in_use: Box<dyn Fn() -> RegistryVersion + Send>,
+        log: ReplicaLogger,
+    ) -> Self {
+        Self {
+            node_id,
+            crypto_root,
+            period,
+            key_manager,
+            signer,
+            registry,
+            oldest_registry_version_in_use,
+            log,
+            retirement_delay: 2 * TLS_CERT_REPLACEMENT_GRACE_PERIOD,
+            submitted: false,
+        }
+    }
+
+    /// Starts checking periodically whether the keys are due for rotation,
+    /// rotated keys are registered, or replaced keys can be removed.
+    pub fn start(mut self) {
+        tokio::spawn(async move {
+            loop {
+                if let Err(err) = self.check().await {
+                    warn!(self.log, "Key rotation failed: {}", err);
+                }
+                tokio::time::sleep(KEY_ROTATION_CHECK_INTERVAL).await;
+            }
+        });
+    }
+
+    async fn check(&mut self) -> Result<(), String> {
+        if let Some(rotated_keys) = self.update()? {
+            self.submit(&rotated_keys, self.registry.get_latest_version())
+                .await?;
+            self.submitted = true;
+            info!(self.log, "Submitted the rotated keys to the registry");
+        }
+        Ok(())
+    }
+
+    /// Advances the rotation as far as possible without the registry
+    /// canister: removes the secret keys of replaced keys once they are no
+    /// longer used, generates new keys once the current ones are due for
+    /// rotation, and activates rotated keys once they are registered.
+    ///
+    /// Returns the rotated keys if they still need to be submitted to the
+    /// registry canister.
+    fn update(&mut self) -> Result<Option<NodePublicKeys>, String> {
+        // Another rotation starts only after the keys replaced by the previous
+        // one are gone, so that at most two sets of keys are in use.
+        if let Some(retired_keys) = read_public_keys(&self.retired_keys_file())? {
+            self.remove_retired_keys(&retired_keys)?;
+            return Ok(None);
+        }
+
+        let rotated_keys = match read_public_keys(&self.rotated_keys_file())? {
+            Some(rotated_keys) => rotated_keys,
+            None if self.rotation_due()? => {
+                let rotated_keys = self
+                    .key_manager
+                    .generate_rotated_node_keys()
+                    .map_err(|err| format!("Failed to generate keys: {}", err))?;
+                ic_utils::fs::write_protobuf_using_tmp_file(
+                    self.rotated_keys_file(),
+                    &rotated_keys,
+                )
+                .map_err(|err| format!("Failed to store the rotated keys: {}", err))?;
+                info!(self.log, "Generated new committee signing and TLS keys");
+                rotated_keys
+            }
+            None => return Ok(None),
+        };
+
+        let version = self.registry.get_latest_version();
+        if self.is_registered(&rotated_keys, version)? {
+            self.activate(&rotated_keys)?;
+            info!(
+                self.log,
+                "The rotated keys are registered at registry version {}", version
+            );
+            Ok(None)
+        } else if !self.submitted {
+            Ok(Some(rotated_keys))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Replaces the current public keys with the rotated ones, keeping the
+    /// current ones until their secret keys are removed.
+    fn activate(&mut self, rotated_keys: &NodePublicKeys) -> Result<(), String> {
+        let current_keys = read_public_keys(&self.crypto_root.join(PUBLIC_KEYS_FILENAME))?
+            .ok_or("The public key store is missing")?;
+        ic_utils::fs::write_protobuf_using_tmp_file(self.retired_keys_file(), &current_keys)
+            .map_err(|err| format!("Failed to store the replaced keys: {}", err))?;
+        store_node_public_keys(&self.crypto_root, rotated_keys)
+            .map_err(|err| format!("Failed to activate the rotated keys: {}", err))?;
+        std::fs::remove_file(self.rotated_keys_file())
+            .map_err(|err| format!("Failed to remove the rotated keys file: {}", err))?;
+        self.submitted = false;
+        Ok(())
+    }
+
+    /// Removes the secret keys of the `retired_keys` if the subnet no longer
+    /// uses registry versions before the one registering the keys that
+    /// replaced them, and the peers no longer trust the replaced TLS
+    /// certificate.
+    fn remove_retired_keys(&self, retired_keys: &NodePublicKeys) -> Result<(), String> {
+        let latest_version = self.registry.get_latest_version();
+        // The TLS certificate and the committee signing key are replaced by
+        // the same mutation.
+        let replaced_at = self
+            .registry
+            .get_versioned_value(&make_crypto_tls_cert_key(self.node_id), latest_version)
+            .map_err(|err| format!("Failed to read the TLS certificate: {}", err))?
+            .version;
+        if (self.oldest_registry_version_in_use)() < replaced_at {
+            return Ok(());
+        }
+        match self.registry.get_version_timestamp(replaced_at) {
+            Some(available_since) if available_since + self.retirement_delay <= current_time() => {}
+            _ => return Ok(()),
+        }
+        self.key_manager
+            .remove_rotated_out_node_keys(retired_keys)
+            .map_err(|err| format!("Failed to remove the replaced keys: {}", err))?;
+        std::fs::remove_file(self.retired_keys_file())
+            .map_err(|err| format!("Failed to remove the replaced keys file: {}", err))?;
+        info!(
+            self.log,
+            "Removed the secret keys replaced at registry version {}", replaced_at
+        );
+        Ok(())
+    }
+
+    fn rotated_keys_file(&self) -> PathBuf {
+        self.crypto_root.join(ROTATED_KEYS_FILENAME)
+    }
+
+    fn retired_keys_file(&self) -> PathBuf {
+        self.crypto_root.join(RETIRED_KEYS_FILENAME)
+    }
+
+    fn rotation_due(&self) -> Result<bool, String> {
+        let file = self.crypto_root.join(PUBLIC_KEYS_FILENAME);
+        let modified = std::fs::metadata(&file)
+            .and_then(|metadata| metadata.modified())
+            .map_err(|err| format!("Failed to read the age of {}: {}", file.display(), err))?;
+        // A modification time in the future counts as new keys.
+        let age = SystemTime::now()
+            .duration_since(modified)
+            .unwrap_or_default();
+        Ok(age >= self.period)
+    }
+
+    fn is_registered(
+        &self,
+        rotated_keys: &NodePublicKeys,
+        version: RegistryVersion,
+    ) -> Result<bool, String> {
+        let committee_signing_pk = self
+            .registry
+            .get_crypto_key_for_node(self.node_id, KeyPurpose::CommitteeSigning, version)
+            .map_err(|err| format!("Failed to read the committee signing key: {}", err))?;
+        let tls_certificate = self
+            .registry
+            .get_tls_certificate(self.node_id, version)
+            .map_err(|err| format!("Failed to read the TLS certificate: {}", err))?;
+        Ok(committee_signing_pk.map(|pk| pk.key_value)
+            == rotated_keys
+                .committee_signing_pk
+                .as_ref()
+                .map(|pk| pk.key_value.clone())
+            && tls_certificate.map(|cert| cert.certificate_der)
+                == rotated_keys
+                    .tls_certificate
+                    .as_ref()
+                    .map(|cert| cert.certificate_der.clone()))
+    }
+
+    /// Sends an `update_node_keys` request with the rotated keys to a random
+    /// NNS node. The request is signed with the node signing key, so that
+    /// its sender is this node.
+    async fn submit(
+        &self,
+        rotated_keys: &NodePublicKeys,
+        version: RegistryVersion,
+    ) -> Result<(), String> {
+        let (node_signing_pk, committee_signing_pk, tls_certificate) = match rotated_keys {
+            NodePublicKeys {
+                node_signing_pk: Some(node_signing_pk),
+                committee_signing_pk: Some(committee_signing_pk),
+                tls_certificate: Some(tls_certificate),
+                ..
+            } => (node_signing_pk, committee_signing_pk, tls_certificate),
+            _ => return Err("The rotated keys are incomplete".to_string()),
+        };
+        let payload = UpdateNodeKeysPayload {
+            committee_signing_pk: protobuf_to_vec(committee_signing_pk),
+            transport_tls_cert: protobuf_to_vec(tls_certificate),
+        };
+
+        let signer = Arc::clone(&self.signer);
+        let registry = Arc::clone(&self.registry);
+        let node_id = self.node_id;
+        let sign = move |msg: &[u8]| -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+            if !msg.starts_with(&DOMAIN_IC_REQUEST[..]) {
+                return Err("Only request IDs are signed".into());
+            }
+            let message_id = MessageId::try_from(&msg[DOMAIN_IC_REQUEST.len()..])
+                .map_err(|err| format!("{:?}", err))?;
+            let signature = signer
+                .sign_basic(&message_id, node_id, registry.get_latest_version())
+                .map_err(|err| format!("{}", err))?;
+            Ok(signature.get().0)
+        };
+        let sender = Sender::ExternalHsm {
+            pub_key: ed25519_public_key_to_der(node_signing_pk.key_value.clone()),
+            sign: Arc::new(sign),
+        };
+
+        let agent = Agent::new(self.nns_url(version)?, sender);
+        agent
+            .execute_update(
+                &REGISTRY_CANISTER_ID,
+                "update_node_keys",
+                Encode!(&payload)
+                    .map_err(|err| format!("Failed to encode the payload: {}", err))?,
+                generate_nonce(),
+            )
+            .await
+            .map(|_| ())
+    }
+
+    fn nns_url(&self, version: RegistryVersion) -> Result<Url, String> {
+        let nns_subnet_id = self
+            .registry
+            .get_root_subnet_id(version)
+            .ok()
+            .flatten()
+            .ok_or("Failed to read the NNS subnet ID")?;
+        let node_ids = self
+            .registry
+            .get_node_ids_on_subnet(nns_subnet_id, version)
+            .ok()
+            .flatten()
+            .ok_or("Failed to read the NNS nodes")?;
+        let node_id = node_ids
+            .choose(&mut rand::thread_rng())
+            .ok_or("The NNS subnet has no nodes")?;
+        let http = self
+            .registry
+            .get_transport_info(*node_id, version)
+            .ok()
+            .flatten()
+            .and_then(|record| record.http)
+            .ok_or_else(|| format!("Failed to read the HTTP endpoint of node {}", node_id))?;
+        let ip_addr: IpAddr = http
+            .ip_addr
+            .parse()
+            .map_err(|err| format!("Invalid IP address {}: {}", http.ip_addr, err))?;
+        let host = match ip_addr {
+            IpAddr::V4(_) => http.ip_addr,
+            IpAddr::V6(_) => format!("[{}]", http.ip_addr),
+        };
+        Url::parse(&format!("http://{}:{}/", host, http.port)).map_err(|err| err.to_string())
+    }
+}
+
+fn read_public_keys(file: &Path) -> Result<Option<NodePublicKeys>, String> {
+    if !file.exists() {
+        return Ok(None);
+    }
+    let bytes =
+        std::fs::read(file).map_err(|err| format!("Failed to read {}: {}", file.display(), err))?;
+    NodePublicKeys::decode(&bytes[..])
+        .map(Some)
+        .map_err(|err| format!("Failed to decode {}: {}", file.display(), err))
+}
+
+fn protobuf_to_vec<M: Message>(entry: &M) -> Vec<u8> {
+    let mut buf: Vec<u8> = Vec::new();
+    entry.encode(&mut buf).expect("This must not fail");
+    buf
+}
+
+fn generate_nonce() -> Vec<u8> {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos()
+        .to_le_bytes()
+        .to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_logger::replica_logger::no_op_logger;
+    use ic_protobuf::registry::crypto::v1::{PublicKey as PublicKeyProto, X509PublicKeyCert};
+    use ic_registry_client::fake::FakeRegistryClient;
+    use ic_registry_common::proto_registry_data_provider::ProtoRegistryDataProvider;
+    use ic_registry_keys::make_crypto_node_key;
+    use ic_test_utilities::crypto::CryptoReturningOk;
+    use ic_test_utilities::types::ids::node_test_id;
+    use ic_types::crypto::CryptoResult;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Mutex;
+    use tempfile::TempDir;
+
+    /// A key manager that generates `node_keys(1)`, `node_keys(2)`, ... and
+    /// records the keys it is asked to remove.
+    #[derive(Default)]
+    struct FakeKeyManager {
+        generated: Mutex<u8>,
+        removed: Mutex<Vec<NodePublicKeys>>,
+    }
+
+    impl KeyManager for FakeKeyManager {
+        fn check_keys_with_registry(&self, _registry_version: RegistryVersion) -> CryptoResult<()> {
+            Ok(())
+        }
+
+        fn node_public_keys(&self) -> NodePublicKeys {
+            node_keys(0)
+        }
+
+        fn generate_rotated_node_keys(&self) -> CryptoResult<NodePublicKeys> {
+            let mut generated = self.generated.lock().unwrap();
+            *generated += 1;
+            Ok(node_keys(*generated))
+        }
+
+        fn remove_rotated_out_node_keys(
+            &self,
+            rotated_out_keys: &NodePublicKeys,
+        ) -> CryptoResult<()> {
+            self.removed.lock().unwrap().push(rotated_out_keys.clone());
+            Ok(())
+        }
+    }
+
+    fn node_keys(n: u8) -> NodePublicKeys {
+        NodePublicKeys {
+            committee_signing_pk: Some(PublicKeyProto {
+                key_value: vec![n; 96],
+                ..Default::default()
+            }),
+            tls_certificate: Some(X509PublicKeyCert {
+                certificate_der: vec![n; 32],
+            }),
+            ..Default::default()
+        }
+    }
+
+    struct Setup {
+        rotation: KeyRotation,
+        key_manager: Arc<FakeKeyManager>,
+        data_provider: Arc<ProtoRegistryDataProvider>,
+        registry: Arc<FakeRegistryClient>,
+        oldest_registry_version_in_use: Arc<AtomicU64>,
+        crypto_root: TempDir,
+    }
+
+    impl Setup {
+        /// Sets up the rotation of the keys `node_keys(0)`, which are in the
+        /// public key store and registered at version 1.
+        fn new(period: Duration) -> Self {
+            let crypto_root = tempfile::tempdir().unwrap();
+            store_node_public_keys(crypto_root.path(), &node_keys(0)).unwrap();
+            let data_provider = Arc::new(ProtoRegistryDataProvider::new());
+            let registry = Arc::new(FakeRegistryClient::new(Arc::clone(&data_provider) as Arc<_>));
+            let key_manager = Arc::new(FakeKeyManager::default());
+            let oldest_registry_version_in_use = Arc::new(AtomicU64::new(1));
+            let rotation = KeyRotation::new(
+                node_test_id(1),
+                crypto_root.path().to_path_buf(),
+                period,
+                Arc::clone(&key_manager) as Arc<_>,
+                Arc::new(CryptoReturningOk::default()),
+                Arc::clone(&registry) as Arc<_>,
+                {
+                    let version = Arc::clone(&oldest_registry_version_in_use);
+                    Box::new(move || RegistryVersion::from(version.load(Ordering::SeqCst)))
+                },
+                no_op_logger(),
+            );
+            let setup = Self {
+                rotation,
+                key_manager,
+                data_provider,
+                registry,
+                oldest_registry_version_in_use,
+                crypto_root,
+            };
+            setup.register(1, &node_keys(0));
+            setup
+        }
+
+        fn register(&self, version: u64, keys: &NodePublicKeys) {
+            let version = RegistryVersion::from(version);
+            let node_id = node_test_id(1);
+            self.data_provider
+                .add(
+                    &make_crypto_node_key(node_id, KeyPurpose::CommitteeSigning),
+                    version,
+                    keys.committee_signing_pk.clone(),
+                )
+                .unwrap();
+            self.data_provider
+                .add(
+                    &make_crypto_tls_cert_key(node_id),
+                    version,
+                    keys.tls_certificate.clone(),
+                )
+                .unwrap();
+            self.registry.update_to_latest_version();
+        }
+
+        fn stored_keys(&self, filename: &str) -> Option<NodePublicKeys> {
+            read_public_keys(&self.crypto_root.path().join(filename)).unwrap()
+        }
+
+        fn generated(&self) -> u8 {
+            *self.key_manager.generated.lock().unwrap()
+        }
+
+        fn removed(&self) -> Vec<NodePublicKeys> {
+            self.key_manager.removed.lock().unwrap().clone()
+        }
+    }
+
+    #[test]
+    fn keys_are_not_rotated_before_the_period() {
+        let mut setup = Setup::new(Duration::from_secs(24 * 60 * 60));
+
+        assert_eq!(setup.rotation.update(), Ok(None));
+        assert_eq!(setup.generated(), 0);
+        assert_eq!(setup.stored_keys(ROTATED_KEYS_FILENAME), None);
+    }
+
+    #[test]
+    fn rotated_keys_are_submitted_until_they_are_registered() {
+        let mut setup = Setup::new(Duration::from_secs(0));
+
+        assert_eq!(setup.rotation.update(), Ok(Some(node_keys(1))));
+        assert_eq!(setup.stored_keys(ROTATED_KEYS_FILENAME), Some(node_keys(1)));
+
+        // The keys are not submitted again, nor generated again.
+        setup.rotation.submitted = true;
+        assert_eq!(setup.rotation.update(), Ok(None));
+        assert_eq!(setup.generated(), 1);
+
+        // After a restart, the stored keys are submitted again.
+        setup.rotation.submitted = false;
+        assert_eq!(setup.rotation.update(), Ok(Some(node_keys(1))));
+        assert_eq!(setup.generated(), 1);
+    }
+
+    #[test]
+    fn registered_keys_replace_the_current_keys() {
+        let mut setup = Setup::new(Duration::from_secs(0));
+        assert_eq!(setup.rotation.update(), Ok(Some(node_keys(1))));
+        setup.rotation.submitted = true;
+
+        setup.register(2, &node_keys(1));
+        assert_eq!(setup.rotation.update(), Ok(None));
+
+        assert_eq!(setup.stored_keys(PUBLIC_KEYS_FILENAME), Some(node_keys(1)));
+        assert_eq!(setup.stored_keys(RETIRED_KEYS_FILENAME), Some(node_keys(0)));
+        assert_eq!(setup.stored_keys(ROTATED_KEYS_FILENAME), None);
+        assert!(!setup.rotation.submitted);
+    }
+
+    #[test]
+    fn replaced_keys_are_removed_once_they_are_no_longer_used() {
+        let mut setup = Setup::new(Duration::from_secs(0));
+        assert_eq!(setup.rotation.update(), Ok(Some(node_keys(1))));
+        setup.register(2, &node_keys(1));
+        assert_eq!(setup.rotation.update(), Ok(None));
+
+        // The subnet still uses the registry version of the replaced keys.
+        assert_eq!(setup.rotation.update(), Ok(None));
+        assert!(setup.removed().is_empty());
+
+        // The peers may still trust the replaced TLS certificate.
+        setup
+            .oldest_registry_version_in_use
+            .store(2, Ordering::SeqCst);
+        assert_eq!(setup.rotation.update(), Ok(None));
+        assert!(setup.removed().is_empty());
+        assert_eq!(setup.generated(), 1);
+
+        setup.rotation.retirement_delay = Duration::from_secs(0);
+        assert_eq!(setup.rotation.update(), Ok(None));
+        assert_eq!(setup.removed(), vec![node_keys(0)]);
+        assert_eq!(setup.stored_keys(RETIRED_KEYS_FILENAME), None);
+
+        // The next rotation starts once the replaced keys are removed.
+        assert_eq!(setup.rotation.update(), Ok(Some(node_keys(2))));
+    }
+}
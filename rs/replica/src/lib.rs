@@ -1,3 +1,7 @@
 pub mod args;
+pub mod health;
+pub mod key_rotation;
+mod registry_gossip;
 pub mod setup;
 pub mod setup_p2p;
+pub mod tracing_logger;
@@ -2,16 +2,26 @@
 
 use ic_base_server::shutdown_signal;
 use ic_config::registry_client::DataProviderConfig;
-use ic_config::{subnet_config::SubnetConfigs, Config};
+use ic_config::{
+    reload::{ConfigChange, ConfigReloader},
+    subnet_config::SubnetConfigs,
+    Config, ConfigSource,
+};
+use ic_consensus::consensus::round_tracer::RoundTimelines;
 use ic_crypto_sha256::Sha256;
 use ic_crypto_tls_interfaces::TlsHandshake;
+use ic_event_log::EventLog;
+use ic_execution_environment::IngressHistoryReaderImpl;
 use ic_interfaces::crypto::IngressSigVerifier;
 use ic_interfaces::registry::{LocalStoreCertifiedTimeReader, RegistryClient};
-use ic_logger::info;
+use ic_logger::{error, info, warn};
 use ic_metrics::MetricsRegistry;
 use ic_metrics_exporter::MetricsRuntimeImpl;
+use ic_p2p::p2p::PoolDumps;
 use ic_registry_client::helper::subnet::SubnetRegistry;
-use ic_replica::{args::ReplicaArgs, setup};
+use ic_replica::{
+    args::ReplicaArgs, key_rotation::KeyRotation, setup, tracing_logger::TracingLogger,
+};
 use ic_types::{replica_version::REPLICA_BINARY_HASH, PrincipalId, ReplicaVersion, SubnetId};
 use ic_utils::ic_features::*;
 use nix::unistd::{setpgid, Pid};
@@ -36,6 +46,9 @@ use jemallocator::Jemalloc;
 #[cfg(target_os = "linux")]
 static ALLOC: Jemalloc = Jemalloc;
 
+/// The interval at which the config file is checked for changes.
+const CONFIG_RELOAD_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
 use ic_registry_common::local_store::LocalStoreImpl;
 #[cfg(feature = "profiler")]
 use pprof::{protos::Message, ProfilerGuard};
@@ -136,10 +149,55 @@ async fn run() -> io::Result<()> {
     }
 
     let config_source = setup::get_config_source(&replica_args);
-    let config = Config::load_with_tmpdir(config_source, tmpdir.path().to_path_buf());
+    let config = match ic_config::validation::load_and_validate(
+        &config_source,
+        Config::new(tmpdir.path().to_path_buf()),
+        /* check_paths= */ true,
+    ) {
+        Ok(config) => config,
+        Err(err) => {
+            // The config cannot be trusted, so the error is logged with the
+            // default logger settings.
+            let (logger, async_log_guard) =
+                setup::get_replica_logger(&Config::new(tmpdir.path().to_path_buf()));
+            error!(logger, "Invalid config from {}: {}", config_source, err);
+            // Flush the log before exiting.
+            drop(async_log_guard);
+            std::process::exit(1);
+        }
+    };
 
     let (logger, _async_log_guard) = setup::get_replica_logger(&config);
 
+    // Only configs read from a file can be reloaded.
+    if let ConfigSource::File(_) = config_source {
+        let config_reloader = Arc::new(ConfigReloader::new(
+            config_source,
+            Config::new(tmpdir.path().to_path_buf()),
+            config.clone(),
+        ));
+        // The log levels of all loggers are shared with this clone.
+        let log_levels = logger.inner_logger.clone();
+        let mut subscription = config_reloader.subscribe();
+        task::spawn(async move {
+            while let Some(changes) = subscription.changed().await {
+                for change in changes {
+                    match change {
+                        ConfigChange::LogLevel(level) => log_levels.set_level(level),
+                        ConfigChange::LogDebugOverrides(debug_overrides) => {
+                            log_levels.set_debug_overrides(debug_overrides)
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        });
+        task::spawn(config_reloader.watch(
+            CONFIG_RELOAD_POLL_INTERVAL,
+            logger.inner_logger.root.clone(),
+        ));
+    }
+
     let optional_nns_key_path = match &replica_args {
         Ok(ReplicaArgs {
             nns_public_key_file: Some(path_buf),
@@ -153,7 +211,7 @@ async fn run() -> io::Result<()> {
     #[cfg(target_os = "linux")]
     metrics_registry.register(jemalloc_metrics::JemallocMetrics::new());
 
-    let (registry, crypto) = setup::setup_crypto_registry(
+    let (registry, crypto, registry_delta_pool) = setup::setup_crypto_registry(
         config.clone(),
         Some(&metrics_registry),
         optional_nns_key_path,
@@ -247,6 +305,11 @@ async fn run() -> io::Result<()> {
     context.subnet_id = format!("{}", subnet_id.get());
     let logger = logger.with_new_context(context);
 
+    // Forward the tracing spans of the components to the replica's log.
+    if let Err(err) = tracing::subscriber::set_global_default(TracingLogger::new(logger.clone())) {
+        warn!(logger, "Failed to install the tracing subscriber: {}", err);
+    }
+
     info!(logger, "Replica Started");
     info!(logger, "Running in subnetwork {:?}", subnet_id);
     if let Ok((path, hash)) = get_replica_binary_hash() {
@@ -284,6 +347,9 @@ async fn run() -> io::Result<()> {
             None
         };
 
+    let event_log = EventLog::new(&config.event_log, &metrics_registry, logger.clone());
+    let round_timelines = RoundTimelines::default();
+    let pool_dumps = PoolDumps::default();
     let (
         crypto,
         state_manager,
@@ -291,6 +357,7 @@ async fn run() -> io::Result<()> {
         mut p2p_runner,
         p2p_event_handler,
         consensus_pool_cache,
+        replica_health_assessor,
         ingress_message_filter,
         _xnet_endpoint,
     ) = ic_replica::setup_p2p::construct_ic_stack(
@@ -305,11 +372,35 @@ async fn run() -> io::Result<()> {
         metrics_registry.clone(),
         cup_with_proto,
         registry_certified_time_reader,
+        registry_delta_pool,
+        event_log.clone(),
+        round_timelines.clone(),
+        pool_dumps.clone(),
     )?;
 
     p2p_runner.run();
 
+    if let Some(period_secs) = config.crypto.key_rotation_period_secs {
+        KeyRotation::new(
+            node_id,
+            config.crypto.crypto_root.clone(),
+            Duration::from_secs(period_secs),
+            Arc::clone(&crypto) as Arc<_>,
+            Arc::clone(&crypto) as Arc<_>,
+            registry.clone(),
+            {
+                let consensus_pool_cache = Arc::clone(&consensus_pool_cache);
+                Box::new(move || consensus_pool_cache.get_subnet_membership_version())
+            },
+            logger.clone(),
+        )
+        .start();
+    }
+
     let malicious_behaviour = &config.malicious_behaviour;
+    let ingress_history_reader = Arc::new(IngressHistoryReaderImpl::new(
+        Arc::clone(&state_manager) as Arc<_>,
+    ));
 
     task::spawn(ic_http_handler::start_server(
         metrics_registry.clone(),
@@ -324,8 +415,13 @@ async fn run() -> io::Result<()> {
         root_subnet_id,
         logger.clone(),
         consensus_pool_cache,
+        replica_health_assessor,
         Arc::from(ingress_message_filter),
+        ingress_history_reader,
         subnet_type,
+        event_log,
+        Arc::new(round_timelines),
+        Arc::new(pool_dumps),
         malicious_behaviour.malicious_flags.clone(),
     ));
 
@@ -0,0 +1,144 @@
+//! Distribution of certified registry deltas among the nodes of a subnet over
+//! gossip, for nodes that cannot reach the NNS.
+//!
+//! Each node adds the certified responses of the registry canister to its
+//! `CertifiedDeltaPool` and advertises them to its peers. A node downloads
+//! the deltas above its latest registry version and, after verifying their
+//! certification, advertises them in turn. While a node cannot reach the
+//! registry canister, its registry data provider serves the pooled deltas.
+
+use ic_artifact_manager::artifact::RegistryDeltaArtifact;
+use ic_interfaces::{
+    artifact_manager::{ArtifactAcceptance, ArtifactClient, ArtifactProcessor, ProcessingResult},
+    artifact_pool::{ArtifactPoolError, UnvalidatedArtifact},
+    registry::RegistryClient,
+    time_source::TimeSource,
+};
+use ic_logger::{warn, ReplicaLogger};
+use ic_registry_common::certified_delta_pool::CertifiedDeltaPool;
+use ic_types::{
+    artifact::{
+        Advert, ArtifactKind, Priority, PriorityFn, RegistryDeltaAttribute, RegistryDeltaId,
+        RegistryDeltaMessage,
+    },
+    chunkable::{Chunkable, SingleChunked},
+    NodeId,
+};
+use std::sync::Arc;
+
+/// The artifact client and processor of `RegistryDeltaArtifacts`.
+pub(crate) struct RegistryDeltaGossipClient {
+    /// The pool the registry data provider adds the deltas fetched from the
+    /// registry canister to.
+    delta_pool: Arc<CertifiedDeltaPool>,
+
+    /// Used for downloading only the deltas above the latest version.
+    registry: Arc<dyn RegistryClient>,
+
+    log: ReplicaLogger,
+}
+
+impl RegistryDeltaGossipClient {
+    pub(crate) fn new(
+        delta_pool: Arc<CertifiedDeltaPool>,
+        registry: Arc<dyn RegistryClient>,
+        log: ReplicaLogger,
+    ) -> Self {
+        Self {
+            delta_pool,
+            registry,
+            log,
+        }
+    }
+
+    fn advert(&self, id: &RegistryDeltaId) -> Option<Advert<RegistryDeltaArtifact>> {
+        self.get_validated_by_identifier(id)
+            .map(|msg| RegistryDeltaArtifact::message_to_advert(&msg))
+    }
+}
+
+impl ArtifactClient<RegistryDeltaArtifact> for RegistryDeltaGossipClient {
+    /// Accepts all deltas, their certifications are verified when they are
+    /// added to the pool.
+    fn check_artifact_acceptance(
+        &self,
+        msg: RegistryDeltaMessage,
+        _peer_id: &NodeId,
+    ) -> Result<ArtifactAcceptance<RegistryDeltaMessage>, ArtifactPoolError> {
+        Ok(ArtifactAcceptance::AcceptedForProcessing(msg))
+    }
+
+    fn has_artifact(&self, msg_id: &RegistryDeltaId) -> bool {
+        self.delta_pool.contains(msg_id)
+    }
+
+    fn get_validated_by_identifier(
+        &self,
+        msg_id: &RegistryDeltaId,
+    ) -> Option<RegistryDeltaMessage> {
+        self.delta_pool
+            .get(msg_id)
+            .map(|certified_response| RegistryDeltaMessage {
+                id: *msg_id,
+                certified_response,
+            })
+    }
+
+    /// Returns the adverts of all pooled deltas.
+    fn get_all_validated_by_filter(&self, _filter: &()) -> Vec<Advert<RegistryDeltaArtifact>> {
+        self.delta_pool
+            .ids()
+            .iter()
+            .filter_map(|id| self.advert(id))
+            .collect()
+    }
+
+    /// Only deltas above the latest registry version are downloaded.
+    fn get_priority_function(&self) -> Option<PriorityFn<RegistryDeltaId, RegistryDeltaAttribute>> {
+        let registry = Arc::clone(&self.registry);
+        Some(Box::new(
+            move |_: &RegistryDeltaId, attribute: &RegistryDeltaAttribute| {
+                if attribute.version > registry.get_latest_version() {
+                    Priority::FetchNow
+                } else {
+                    Priority::Drop
+                }
+            },
+        ))
+    }
+
+    fn get_chunk_tracker(&self, _id: &RegistryDeltaId) -> Box<dyn Chunkable + Send + Sync> {
+        Box::new(SingleChunked::RegistryDelta)
+    }
+}
+
+impl ArtifactProcessor<RegistryDeltaArtifact> for RegistryDeltaGossipClient {
+    /// Adds the downloaded deltas to the pool and advertises all deltas added
+    /// since the last call, including those fetched from the registry canister.
+    fn process_changes(
+        &self,
+        _time_source: &dyn TimeSource,
+        artifacts: Vec<UnvalidatedArtifact<RegistryDeltaMessage>>,
+    ) -> (Vec<Advert<RegistryDeltaArtifact>>, ProcessingResult) {
+        for artifact in artifacts {
+            let RegistryDeltaMessage {
+                id,
+                certified_response,
+            } = artifact.message;
+            if let Err(err) = self.delta_pool.insert_with_id(&id, certified_response) {
+                warn!(
+                    self.log,
+                    "Dropping registry delta {:?} from peer {}: {}", id, artifact.peer_id, err
+                );
+            }
+        }
+
+        let adverts = self
+            .delta_pool
+            .take_unadvertised()
+            .iter()
+            .filter_map(|id| self.advert(id))
+            .collect();
+        (adverts, ProcessingResult::StateUnchanged)
+    }
+}
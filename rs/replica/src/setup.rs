@@ -8,6 +8,8 @@ use ic_metrics::MetricsRegistry;
 use ic_protobuf::types::v1 as pb;
 use ic_registry_client::client::{create_data_provider, RegistryClientImpl};
 use ic_registry_client::helper::subnet::{SubnetListRegistry, SubnetRegistry};
+use ic_registry_client::snapshot_cache::RegistrySnapshotCache;
+use ic_registry_common::certified_delta_pool::CertifiedDeltaPool;
 use ic_registry_common::proto_registry_data_provider::ProtoRegistryDataProvider;
 use ic_registry_subnet_type::SubnetType;
 use ic_types::consensus::catchup::{CUPWithOriginalProtobuf, CatchUpPackage};
@@ -203,15 +205,22 @@ pub fn create_consensus_pool_dir(config: &Config) {
     });
 }
 
+/// Sets up the registry client and the crypto component. If registry delta
+/// gossip is enabled and the registry deltas are certified, also returns the
+/// pool of certified deltas to be shared with the other nodes of the subnet.
 pub fn setup_crypto_registry(
     config: Config,
     metrics_registry: Option<&MetricsRegistry>,
     optional_nns_public_key_file: Option<&Path>,
     logger: ReplicaLogger,
     prepare_registry_data_provider: impl FnOnce(&CryptoComponent, ProtoRegistryDataProvider),
-) -> (std::sync::Arc<RegistryClientImpl>, CryptoComponent) {
+) -> (
+    std::sync::Arc<RegistryClientImpl>,
+    CryptoComponent,
+    Option<Arc<CertifiedDeltaPool>>,
+) {
     // TODO(OR4-61)
-    let (crypto, registry) = if config.registry_client.data_provider.is_none() {
+    let (crypto, registry, delta_pool) = if config.registry_client.data_provider.is_none() {
         let data_provider = ProtoRegistryDataProvider::new();
         let registry = Arc::new(RegistryClientImpl::new(
             Arc::new(data_provider.clone()),
@@ -225,7 +234,7 @@ pub fn setup_crypto_registry(
         );
         // callback to manipulate the mutable data provider
         prepare_registry_data_provider(&crypto, data_provider);
-        (crypto, registry)
+        (crypto, registry, None)
     } else {
         if config.registry_client.data_provider.is_none() {
             panic!("No data provider was provided in the registry client configuration.")
@@ -234,12 +243,27 @@ pub fn setup_crypto_registry(
         let optional_nns_public_key = optional_nns_public_key_file
             .map(|path| parse_threshold_sig_key(path).expect("failed to parse NNS PK file"));
 
+        let delta_pool = match optional_nns_public_key {
+            Some(nns_public_key) if config.registry_client.delta_gossip => {
+                Some(Arc::new(CertifiedDeltaPool::new(nns_public_key)))
+            }
+            _ => None,
+        };
+
         let data_provider = create_data_provider(
             &config.registry_client.data_provider.as_ref().unwrap(),
             optional_nns_public_key,
+            delta_pool.clone(),
         );
 
-        let registry = Arc::new(RegistryClientImpl::new(data_provider, metrics_registry));
+        let registry = Arc::new(match &config.registry_client.snapshot_cache {
+            Some(snapshot_cache) => RegistryClientImpl::new_with_snapshot_cache(
+                data_provider,
+                RegistrySnapshotCache::new(snapshot_cache),
+                metrics_registry,
+            ),
+            None => RegistryClientImpl::new(data_provider, metrics_registry),
+        });
         // TODO(RPL-49): pass in registry_client
         let crypto = setup_crypto_provider(
             &config.crypto,
@@ -247,14 +271,14 @@ pub fn setup_crypto_registry(
             logger,
             metrics_registry,
         );
-        (crypto, registry)
+        (crypto, registry, delta_pool)
     };
 
     if let Err(e) = registry.fetch_and_start_polling() {
         panic!("fetch_and_start_polling failed: {}", e);
     }
 
-    (registry, crypto)
+    (registry, crypto, delta_pool)
 }
 
 fn get_config_source_or_abort(args: &[String]) -> ConfigSource {
@@ -1,23 +1,40 @@
-use ic_config::{artifact_pool::ArtifactPoolConfig, subnet_config::SubnetConfig, Config};
-use ic_consensus::certification::VerifierImpl;
+use crate::health::ReplicaHealthMonitor;
+use crate::registry_gossip::RegistryDeltaGossipClient;
+use ic_artifact_manager::{
+    manager::ArtifactManagerMaker,
+    processors::{ArtifactProcessorManager, BoxOrArcClient},
+};
+use ic_config::{
+    artifact_pool::ArtifactPoolConfig, message_routing::XNetTransport, subnet_config::SubnetConfig,
+    Config,
+};
+use ic_consensus::{certification::VerifierImpl, consensus::round_tracer::RoundTimelines};
 use ic_crypto::CryptoComponent;
 use ic_cycles_account_manager::CyclesAccountManager;
+use ic_event_log::EventLog;
 use ic_execution_environment::{setup_execution, IngressHistoryReaderImpl};
 use ic_interfaces::execution_environment::IngressMessageFilter;
 use ic_interfaces::registry::LocalStoreCertifiedTimeReader;
 use ic_interfaces::{
     certified_stream_store::CertifiedStreamStore, consensus_pool::ConsensusPoolCache,
-    execution_environment::QueryHandler, p2p::IngressEventHandler, p2p::P2PRunner,
-    registry::RegistryClient,
+    execution_environment::QueryHandler, health::ReplicaHealthAssessor, p2p::IngressEventHandler,
+    p2p::P2PRunner, registry::RegistryClient,
 };
 use ic_logger::ReplicaLogger;
 use ic_messaging::{MessageRoutingImpl, XNetPayloadBuilderImpl};
 use ic_messaging::{XNetEndpoint, XNetEndpointConfig};
-use ic_p2p::p2p::{create_networking_stack, P2PStateSyncClient};
+use ic_p2p::p2p::{
+    create_networking_stack, ArtifactClientRegistration, ArtifactRegistrationContext, P2PMode,
+    P2PStateSyncClient, PoolDumps,
+};
+use ic_registry_common::certified_delta_pool::CertifiedDeltaPool;
 use ic_registry_subnet_type::SubnetType;
 use ic_replicated_state::ReplicatedState;
 use ic_state_manager::StateManagerImpl;
-use ic_types::{consensus::catchup::CUPWithOriginalProtobuf, NodeId, SubnetId};
+use ic_types::{
+    consensus::catchup::CUPWithOriginalProtobuf, malicious_strategies::MaliciousBehaviors, Height,
+    NodeId, SubnetId,
+};
 use std::sync::Arc;
 
 #[allow(clippy::too_many_arguments, clippy::type_complexity)]
@@ -33,6 +50,10 @@ pub fn construct_ic_stack(
     metrics_registry: ic_metrics::MetricsRegistry,
     catch_up_package: Option<CUPWithOriginalProtobuf>,
     local_store_time_reader: Option<Arc<dyn LocalStoreCertifiedTimeReader>>,
+    registry_delta_pool: Option<Arc<CertifiedDeltaPool>>,
+    event_log: EventLog,
+    round_timelines: RoundTimelines,
+    pool_dumps: PoolDumps,
 ) -> std::io::Result<(
     // TODO(SCL-213): When Rust traits support it, simplify and pass a single
     // trait.
@@ -42,6 +63,7 @@ pub fn construct_ic_stack(
     Box<dyn P2PRunner>,
     Arc<dyn IngressEventHandler>,
     Arc<dyn ConsensusPoolCache>,
+    Arc<dyn ReplicaHealthAssessor>,
     Box<dyn IngressMessageFilter<State = ReplicatedState>>,
     XNetEndpoint,
 )> {
@@ -52,16 +74,21 @@ pub fn construct_ic_stack(
         subnet_id,
         subnet_config.cycles_account_manager_config,
     ));
-    let (ingress_message_filter, ingress_history_writer, http_query_handler, scheduler) =
-        setup_execution(
-            replica_logger.clone(),
-            &metrics_registry,
-            subnet_id,
-            subnet_type,
-            subnet_config.scheduler_config,
-            config.hypervisor.clone(),
-            Arc::clone(&cycles_account_manager),
-        );
+    let (
+        ingress_message_filter,
+        ingress_history_writer,
+        http_query_handler,
+        scheduler,
+        query_stats_reader,
+    ) = setup_execution(
+        replica_logger.clone(),
+        &metrics_registry,
+        subnet_id,
+        subnet_type,
+        subnet_config.scheduler_config,
+        config.hypervisor.clone(),
+        Arc::clone(&cycles_account_manager),
+    );
 
     let verifier = VerifierImpl::new(crypto.clone());
     let state_manager = StateManagerImpl::new(
@@ -118,57 +145,140 @@ pub fn construct_ic_stack(
         replica_logger.clone(),
     );
 
-    // Use default runtime to spawn xnet client threads.
-    let xnet_payload_builder = XNetPayloadBuilderImpl::new(
-        Arc::clone(&state_manager) as Arc<_>,
-        Arc::clone(&certified_stream_store) as Arc<_>,
-        Arc::clone(&crypto) as Arc<_>,
-        Arc::clone(&registry) as Arc<_>,
-        tokio::runtime::Handle::current(),
-        node_id,
-        subnet_id,
-        &metrics_registry,
-        replica_logger.clone(),
-    );
+    let xnet_transport = config.message_routing.xnet_transport;
+    let mut artifact_registrations: Vec<ArtifactClientRegistration> = Vec::new();
+    let xnet_payload_builder = match xnet_transport {
+        // Use default runtime to spawn xnet client threads.
+        XNetTransport::Https => XNetPayloadBuilderImpl::new(
+            Arc::clone(&state_manager) as Arc<_>,
+            Arc::clone(&certified_stream_store) as Arc<_>,
+            Arc::clone(&crypto) as Arc<_>,
+            Arc::clone(&registry) as Arc<_>,
+            tokio::runtime::Handle::current(),
+            node_id,
+            subnet_id,
+            &metrics_registry,
+            replica_logger.clone(),
+        ),
+        XNetTransport::Gossip => {
+            let (xnet_payload_builder, xnet_slice_client) = XNetPayloadBuilderImpl::new_with_gossip(
+                Arc::clone(&state_manager) as Arc<_>,
+                Arc::clone(&certified_stream_store) as Arc<_>,
+                Arc::clone(&registry) as Arc<_>,
+                subnet_id,
+                &metrics_registry,
+                replica_logger.clone(),
+            );
+            artifact_registrations.push(Box::new(
+                move |maker: &mut ArtifactManagerMaker, context: &ArtifactRegistrationContext| {
+                    // Slices are only advertised in response to retransmission
+                    // requests, the processor never produces adverts.
+                    let processor = ArtifactProcessorManager::new(
+                        Arc::clone(&context.time_source),
+                        context.metrics_registry.clone(),
+                        BoxOrArcClient::ArcClient(Arc::clone(&xnet_slice_client) as Arc<_>),
+                        |_| {},
+                        Arc::clone(&context.processor_scheduler),
+                    );
+                    maker.register(xnet_slice_client as Arc<_>, processor)
+                },
+            ));
+            xnet_payload_builder
+        }
+    };
     let xnet_payload_builder = Arc::new(xnet_payload_builder);
 
+    if let Some(registry_delta_pool) = registry_delta_pool {
+        let registry_delta_client = Arc::new(RegistryDeltaGossipClient::new(
+            registry_delta_pool,
+            Arc::clone(&registry) as Arc<_>,
+            replica_logger.clone(),
+        ));
+        artifact_registrations.push(Box::new(
+            move |maker: &mut ArtifactManagerMaker, context: &ArtifactRegistrationContext| {
+                let advert_broadcaster = Arc::clone(&context.advert_broadcaster);
+                let processor = ArtifactProcessorManager::new(
+                    Arc::clone(&context.time_source),
+                    context.metrics_registry.clone(),
+                    BoxOrArcClient::ArcClient(Arc::clone(&registry_delta_client) as Arc<_>),
+                    move |advert| advert_broadcaster(advert.into()),
+                    Arc::clone(&context.processor_scheduler),
+                );
+                maker.register(registry_delta_client as Arc<_>, processor)
+            },
+        ));
+    }
+
     let catch_up_package = catch_up_package.unwrap_or_else(|| {
         CUPWithOriginalProtobuf::from_cup(ic_consensus_message::make_genesis(
             ic_consensus::dkg::make_genesis_summary(&*registry, subnet_id, None),
         ))
     });
 
-    let (p2p_event_handler, p2p_runner, consensus_pool_cache) = create_networking_stack(
-        metrics_registry,
-        replica_logger,
-        tokio::runtime::Handle::current(),
-        config.transport,
-        ArtifactPoolConfig::from(config.artifact_pool),
-        config.consensus,
-        config.malicious_behaviour.malicious_flags,
-        node_id,
-        subnet_id,
-        None,
-        Arc::clone(&crypto) as Arc<_>,
-        Arc::clone(&state_manager) as Arc<_>,
-        P2PStateSyncClient::Client(Arc::clone(&state_manager) as Arc<_>),
-        xnet_payload_builder as Arc<_>,
+    // Observer replicas take part in gossip and execute the subnet's blocks, but
+    // do not produce artifacts of their own. Relay replicas only gossip ingress
+    // messages and state sync chunks.
+    let p2p_mode = P2PMode::from(&config.consensus);
+    let (p2p_event_handler, p2p_runner, consensus_pool_cache, peer_connectivity) =
+        create_networking_stack(
+            metrics_registry,
+            replica_logger.clone(),
+            event_log,
+            round_timelines,
+            pool_dumps,
+            tokio::runtime::Handle::current(),
+            config.transport,
+            ArtifactPoolConfig::from(config.artifact_pool),
+            config.consensus,
+            MaliciousBehaviors::from(&config.malicious_behaviour.malicious_flags),
+            node_id,
+            subnet_id,
+            None,
+            Arc::clone(&crypto) as Arc<_>,
+            Arc::clone(&state_manager) as Arc<_>,
+            P2PStateSyncClient::Client(Arc::clone(&state_manager) as Arc<_>),
+            p2p_mode,
+            xnet_payload_builder as Arc<_>,
+            Arc::clone(&message_router) as Arc<_>,
+            // TODO(SCL-213)
+            Arc::clone(&crypto) as Arc<_>,
+            Arc::clone(&crypto) as Arc<_>,
+            Arc::clone(&crypto) as Arc<_>,
+            Arc::clone(&crypto) as Arc<_>,
+            registry,
+            Box::new(IngressHistoryReaderImpl::new(
+                Arc::clone(&state_manager) as Arc<_>
+            )),
+            catch_up_package,
+            cycles_account_manager,
+            local_store_time_reader,
+            config.nns_registry_replicator.poll_delay_duration_ms,
+            config.metrics.p2p_max_peer_labels,
+            config.message_routing.xnet_gossip_pull_interval(),
+            config.state_manager.compress_state_sync_chunks(),
+            artifact_registrations,
+            Some(query_stats_reader),
+        )
+        .expect("Failed to construct p2p");
+
+    let replica_health_monitor = ReplicaHealthMonitor::new(
+        config.health,
+        peer_connectivity,
+        Arc::clone(&consensus_pool_cache),
         message_router as Arc<_>,
-        // TODO(SCL-213)
-        Arc::clone(&crypto) as Arc<_>,
-        Arc::clone(&crypto) as Arc<_>,
-        Arc::clone(&crypto) as Arc<_>,
-        Arc::clone(&crypto) as Arc<_>,
-        registry,
-        Box::new(IngressHistoryReaderImpl::new(
-            Arc::clone(&state_manager) as Arc<_>
-        )),
-        catch_up_package,
-        cycles_account_manager,
-        local_store_time_reader,
-        config.nns_registry_replicator.poll_delay_duration_ms,
-    )
-    .expect("Failed to construct p2p");
+        Arc::clone(&state_manager) as Arc<_>,
+        {
+            let state_manager = Arc::clone(&state_manager);
+            Box::new(move || {
+                state_manager
+                    .state_layout()
+                    .checkpoint_heights()
+                    .map(|heights| heights.last().copied().unwrap_or_else(|| Height::from(0)))
+                    .map_err(|err| format!("{:?}", err))
+            })
+        },
+        replica_logger,
+    );
 
     Ok((
         crypto,
@@ -177,6 +287,7 @@ pub fn construct_ic_stack(
         p2p_runner,
         p2p_event_handler,
         consensus_pool_cache,
+        Arc::new(replica_health_monitor) as Arc<_>,
         ingress_message_filter,
         xnet_endpoint,
     ))
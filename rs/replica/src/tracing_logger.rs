@@ -0,0 +1,382 @@
+//! Forwards the `tracing` spans and events of the replica to its logger.
+//!
+//! The components emit `tracing` spans and events at the hops of an ingress
+//! message, see `IngressEventHandler`, each with the ID of the message in a
+//! `message_id` field and its correlation ID in a `correlation_id` field. The
+//! replica installs a `TracingLogger` as the global subscriber, so that they
+//! end up in the regular log of the replica, with the ID of the message in
+//! the `ingress_message.message_id` field of the log entry. The log of one
+//! ingress message can thus be selected with the same filters as the other
+//! ingress message logs, or by its correlation ID.
+//!
+//! Events are logged when they occur, together with the fields of the spans
+//! they occur in. Spans are logged when they are closed, with the time since
+//! they were created in a `duration` field.
+//!
+//! Only the spans and events of the crates of the replica, whose targets
+//! start with `ic_`, are forwarded; those of dependencies like hyper and h2
+//! are dropped. Whether a span or event is logged is decided when it is
+//! logged, as the log level of the replica can change while it runs.
+use ic_logger::{LogMetadata, ReplicaLogger};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread::{self, ThreadId};
+use std::time::Instant;
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+    subscriber::Interest,
+    Event, Level, Metadata, Subscriber,
+};
+
+/// The name of the field holding the ID of an ingress message.
+const MESSAGE_ID_FIELD: &str = "message_id";
+
+/// The name of the field holding the message of an event.
+const MESSAGE_FIELD: &str = "message";
+
+/// The prefix of the targets of the crates of the replica.
+const REPLICA_TARGET_PREFIX: &str = "ic_";
+
+/// A `tracing` subscriber that logs spans and events with a `ReplicaLogger`.
+pub struct TracingLogger {
+    log: ReplicaLogger,
+    next_span_id: AtomicU64,
+    /// The spans that are not closed yet, by ID.
+    spans: Mutex<HashMap<u64, SpanData>>,
+    /// The IDs of the spans entered on each thread, the innermost last.
+    entered: Mutex<HashMap<ThreadId, Vec<u64>>>,
+}
+
+/// A span that is not closed yet.
+struct SpanData {
+    metadata: &'static Metadata<'static>,
+    fields: FieldsVisitor,
+    created: Instant,
+    /// The number of handles to the span.
+    references: usize,
+}
+
+impl TracingLogger {
+    pub fn new(log: ReplicaLogger) -> Self {
+        Self {
+            log,
+            // Span IDs must not be 0.
+            next_span_id: AtomicU64::new(1),
+            spans: Mutex::new(HashMap::new()),
+            entered: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Logs the given message with the given fields, if the level of the
+    /// metadata is enabled.
+    fn log(&self, metadata: &'static Metadata<'static>, message: String, fields: FieldsVisitor) {
+        let module_path = metadata.module_path().unwrap_or_else(|| metadata.target());
+        let level = to_slog_level(metadata.level());
+        if !self.log.is_enabled_at(level, module_path) {
+            return;
+        }
+        let mut context = self.log.get_context();
+        if let Some(message_id) = fields.message_id {
+            let mut ingress_message = context.ingress_message.unwrap_or_default();
+            ingress_message.message_id = Some(message_id);
+            context.ingress_message = Some(ingress_message);
+        }
+        let message = if fields.fields.is_empty() {
+            message
+        } else {
+            format!("{} {}", message, fields.fields.join(" "))
+        };
+        self.log.log(
+            message,
+            context,
+            LogMetadata {
+                level,
+                module_path,
+                line: metadata.line().unwrap_or_default(),
+                column: 0,
+            },
+        );
+    }
+
+    /// Returns the names of the spans entered on the current thread, joined
+    /// by colons, and their fields, the outermost first.
+    fn entered_spans(&self) -> (String, FieldsVisitor) {
+        let mut names = Vec::new();
+        let mut fields = FieldsVisitor::default();
+        let entered = self.entered.lock().unwrap();
+        let spans = self.spans.lock().unwrap();
+        for id in entered.get(&thread::current().id()).into_iter().flatten() {
+            if let Some(span) = spans.get(id) {
+                names.push(span.metadata.name());
+                fields.extend(&span.fields);
+            }
+        }
+        (names.join(":"), fields)
+    }
+}
+
+impl Subscriber for TracingLogger {
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        // The target of a callsite never changes, so the interest in the
+        // callsites of other crates can be cached. The level is checked when
+        // logging.
+        if is_forwarded(metadata) {
+            Interest::always()
+        } else {
+            Interest::never()
+        }
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        is_forwarded(metadata)
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let mut fields = FieldsVisitor::default();
+        span.record(&mut fields);
+        let id = self.next_span_id.fetch_add(1, Ordering::Relaxed);
+        self.spans.lock().unwrap().insert(
+            id,
+            SpanData {
+                metadata: span.metadata(),
+                fields,
+                created: Instant::now(),
+                references: 1,
+            },
+        );
+        Id::from_u64(id)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        if let Some(data) = self.spans.lock().unwrap().get_mut(&span.into_u64()) {
+            values.record(&mut data.fields);
+        }
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let (span_names, mut fields) = self.entered_spans();
+        let mut event_fields = FieldsVisitor::default();
+        event.record(&mut event_fields);
+        let message = event_fields
+            .message
+            .take()
+            .unwrap_or_else(|| event.metadata().name().to_string());
+        fields.extend(&event_fields);
+        let message = if span_names.is_empty() {
+            message
+        } else {
+            format!("{}: {}", span_names, message)
+        };
+        self.log(event.metadata(), message, fields);
+    }
+
+    fn enter(&self, span: &Id) {
+        self.entered
+            .lock()
+            .unwrap()
+            .entry(thread::current().id())
+            .or_default()
+            .push(span.into_u64());
+    }
+
+    fn exit(&self, span: &Id) {
+        let mut entered = self.entered.lock().unwrap();
+        let thread_id = thread::current().id();
+        if let Some(spans) = entered.get_mut(&thread_id) {
+            if let Some(position) = spans.iter().rposition(|id| *id == span.into_u64()) {
+                spans.remove(position);
+            }
+            if spans.is_empty() {
+                entered.remove(&thread_id);
+            }
+        }
+    }
+
+    fn clone_span(&self, span: &Id) -> Id {
+        if let Some(data) = self.spans.lock().unwrap().get_mut(&span.into_u64()) {
+            data.references += 1;
+        }
+        span.clone()
+    }
+
+    fn try_close(&self, span: Id) -> bool {
+        let id = span.into_u64();
+        let closed = {
+            let mut spans = self.spans.lock().unwrap();
+            match spans.get_mut(&id) {
+                Some(data) if data.references > 1 => {
+                    data.references -= 1;
+                    return false;
+                }
+                Some(_) => spans.remove(&id),
+                None => None,
+            }
+        };
+        match closed {
+            Some(closed) => {
+                let message = format!(
+                    "{} closed duration={}µs",
+                    closed.metadata.name(),
+                    closed.created.elapsed().as_micros()
+                );
+                self.log(closed.metadata, message, closed.fields);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Returns true if the spans and events of the given callsite are forwarded,
+/// i.e. if it is in one of the crates of the replica.
+fn is_forwarded(metadata: &Metadata<'_>) -> bool {
+    metadata.target().starts_with(REPLICA_TARGET_PREFIX)
+}
+
+/// Collects the fields of a span or event as `name=value` pairs.
+#[derive(Default)]
+struct FieldsVisitor {
+    fields: Vec<String>,
+    message_id: Option<String>,
+    message: Option<String>,
+}
+
+impl FieldsVisitor {
+    /// Adds the fields of the given visitor, whose message ID takes
+    /// precedence.
+    fn extend(&mut self, other: &FieldsVisitor) {
+        self.fields.extend(other.fields.iter().cloned());
+        if other.message_id.is_some() {
+            self.message_id = other.message_id.clone();
+        }
+    }
+}
+
+impl Visit for FieldsVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record_debug(field, &format_args!("{}", value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        let value = format!("{:?}", value);
+        match field.name() {
+            MESSAGE_FIELD => self.message = Some(value),
+            name => {
+                if name == MESSAGE_ID_FIELD {
+                    self.message_id = Some(value.clone());
+                }
+                self.fields.push(format!("{}={}", name, value));
+            }
+        }
+    }
+}
+
+fn to_slog_level(level: &Level) -> slog::Level {
+    if *level == Level::TRACE {
+        slog::Level::Trace
+    } else if *level == Level::DEBUG {
+        slog::Level::Debug
+    } else if *level == Level::INFO {
+        slog::Level::Info
+    } else if *level == Level::WARN {
+        slog::Level::Warning
+    } else {
+        slog::Level::Error
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    /// A drain collecting the messages of the log records.
+    #[derive(Clone, Default)]
+    struct Messages(Arc<Mutex<Vec<String>>>);
+
+    impl slog::Drain for Messages {
+        type Ok = ();
+        type Err = slog::Never;
+
+        fn log(&self, record: &slog::Record, _: &slog::OwnedKVList) -> Result<(), slog::Never> {
+            self.0.lock().unwrap().push(record.msg().to_string());
+            Ok(())
+        }
+    }
+
+    impl Messages {
+        fn take(&self) -> Vec<String> {
+            std::mem::take(&mut *self.0.lock().unwrap())
+        }
+    }
+
+    fn tracing_logger() -> (ReplicaLogger, Messages) {
+        let messages = Messages::default();
+        let log: ReplicaLogger = slog::Logger::root(messages.clone(), slog::o!()).into();
+        log.inner_logger.set_level(slog::Level::Trace);
+        (log, messages)
+    }
+
+    #[test]
+    fn events_are_logged_with_the_fields_of_their_spans() {
+        let (log, messages) = tracing_logger();
+        tracing::subscriber::with_default(TracingLogger::new(log), || {
+            let span = tracing::debug_span!("hop", message_id = "0x01", correlation_id = "0a");
+            let _enter = span.enter();
+            tracing::debug!(status = "done", "handled");
+        });
+
+        let messages = messages.take();
+        assert_eq!(messages.len(), 2, "{:?}", messages);
+        assert!(messages[0].ends_with("hop: handled message_id=0x01 correlation_id=0a status=done"));
+        assert!(messages[1].contains("hop closed duration="));
+        assert!(messages[1].ends_with("message_id=0x01 correlation_id=0a"));
+    }
+
+    #[test]
+    fn spans_are_logged_once_all_handles_are_closed() {
+        let (log, messages) = tracing_logger();
+        tracing::subscriber::with_default(TracingLogger::new(log), || {
+            let span = tracing::debug_span!("hop");
+            let clone = span.clone();
+            drop(span);
+            assert!(messages.take().is_empty());
+            drop(clone);
+            assert_eq!(messages.take().len(), 1);
+        });
+    }
+
+    #[test]
+    fn events_outside_of_the_replica_are_dropped() {
+        let (log, messages) = tracing_logger();
+        tracing::subscriber::with_default(TracingLogger::new(log), || {
+            tracing::info!(target: "hyper::proto::h1", "parsed request");
+            tracing::info!(target: "h2::codec", "received frame");
+        });
+        assert!(messages.take().is_empty());
+    }
+
+    #[test]
+    fn level_changes_take_effect() {
+        let (log, messages) = tracing_logger();
+        let log_levels = log.inner_logger.clone();
+        tracing::subscriber::with_default(TracingLogger::new(log), || {
+            let debug = || tracing::debug!("debug event");
+            debug();
+            assert_eq!(messages.take().len(), 1);
+
+            log_levels.set_level(slog::Level::Info);
+            debug();
+            assert!(messages.take().is_empty());
+
+            log_levels.set_level(slog::Level::Debug);
+            debug();
+            assert_eq!(messages.take().len(), 1);
+        });
+    }
+}
@@ -14,6 +14,8 @@ pub use canister_state::{
     CanisterQueues, CanisterState, EmbedderCache, ExecutionState, ExportedFunctions, Global,
     NumWasmPages, SchedulerState,
 };
-pub use metadata_state::{NetworkTopology, NodeTopology, Stream, SubnetTopology, SystemMetadata};
+pub use metadata_state::{
+    NetworkTopology, NodeTopology, QueryStatsState, Stream, SubnetTopology, SystemMetadata,
+};
 pub use page_map::{PageDelta, PageIndex, PageMap};
 pub use replicated_state::{ReplicatedState, StateError};
@@ -1,6 +1,7 @@
-use ic_registry_routing_table::RoutingTable;
+use ic_registry_routing_table::{CanisterMigrations, RoutingTable};
 use ic_registry_subnet_type::SubnetType;
 use ic_types::{
+    consensus::query_stats::{QueryStats, QueryStatsEpoch},
     crypto::{
         threshold_sig::ni_dkg::{id::ni_dkg_target_id, NiDkgTargetId},
         CryptoHash,
@@ -11,7 +12,8 @@ use ic_types::{
     subnet_id_try_from_protobuf,
     time::{Time, UNIX_EPOCH},
     xnet::{StreamHeader, StreamIndex, StreamIndexedQueue, StreamSlice},
-    CountBytes, CryptoHashOfPartialState, NodeId, NumBytes, PrincipalId, RegistryVersion, SubnetId,
+    CanisterId, CountBytes, CryptoHashOfPartialState, NodeId, NumBytes, PrincipalId,
+    RegistryVersion, SubnetId,
 };
 use std::{
     collections::{BTreeMap, BTreeSet},
@@ -25,6 +27,7 @@ use ic_protobuf::{
     state::{
         ingress::v1 as pb_ingress, queues::v1 as pb_queues, system_metadata::v1 as pb_metadata,
     },
+    types::v1 as pb_types,
 };
 use std::{
     convert::{From, TryFrom, TryInto},
@@ -100,12 +103,17 @@ pub struct SystemMetadata {
     /// always be <= this field + (the maximum delta capacity of the subnet /
     /// 2).
     pub heap_delta_estimate: NumBytes,
+
+    /// The query statistics reported by the replicas of the subnet.
+    pub query_stats: QueryStatsState,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct NetworkTopology {
     pub subnets: BTreeMap<SubnetId, SubnetTopology>,
     pub routing_table: RoutingTable,
+    /// The canister ID ranges that are being migrated between subnets.
+    pub canister_migrations: CanisterMigrations,
     pub nns_subnet_id: SubnetId,
 }
 
@@ -114,6 +122,7 @@ impl Default for NetworkTopology {
         Self {
             subnets: Default::default(),
             routing_table: Default::default(),
+            canister_migrations: Default::default(),
             nns_subnet_id: SubnetId::new(PrincipalId::new_anonymous()),
         }
     }
@@ -132,6 +141,7 @@ impl From<&NetworkTopology> for pb_metadata::NetworkTopology {
                 .collect(),
             routing_table: Some(item.routing_table.clone().into()),
             nns_subnet_id: Some(subnet_id_into_protobuf(item.nns_subnet_id)),
+            canister_migrations: Some(item.canister_migrations.clone().into()),
         }
     }
 }
@@ -163,6 +173,12 @@ impl TryFrom<pb_metadata::NetworkTopology> for NetworkTopology {
                 item.routing_table,
                 "NetworkTopology::routing_table",
             )?,
+            // Absent in states written before canister migrations existed.
+            canister_migrations: item
+                .canister_migrations
+                .map(CanisterMigrations::try_from)
+                .transpose()?
+                .unwrap_or_default(),
             nns_subnet_id,
         })
     }
@@ -244,6 +260,166 @@ impl TryFrom<pb_metadata::NodeTopology> for NodeTopology {
     }
 }
 
+/// The query statistics reported by the replicas of the subnet, see
+/// `ic_types::consensus::query_stats`.
+///
+/// The reports of an epoch are kept until the first report of a later epoch
+/// is delivered. They are then aggregated into the totals of the canisters:
+/// for every statistic, the median of the values reported by the replicas,
+/// where a replica that didn't report a canister counts as zero, multiplied
+/// by the number of reports. This way, a minority of replicas cannot bias
+/// the totals by reporting arbitrary values.
+///
+/// Only the statistics of the canisters hosted by the subnet are kept, so
+/// that the reports and the totals are bounded by the number of canisters.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct QueryStatsState {
+    /// The epoch of the reports that are not aggregated yet.
+    epoch: Option<QueryStatsEpoch>,
+    /// The reports of the current epoch, by replica.
+    reports: BTreeMap<NodeId, BTreeMap<CanisterId, QueryStats>>,
+    /// The aggregated statistics of all past epochs, by canister.
+    totals: BTreeMap<CanisterId, QueryStats>,
+}
+
+impl QueryStatsState {
+    /// Adds the report of the given replica for the given epoch. Reports of
+    /// epochs before the current one and further reports of a replica for the
+    /// same epoch are ignored, and so are the statistics of the canisters
+    /// for which `is_hosted` doesn't hold.
+    pub fn add_report(
+        &mut self,
+        signer: NodeId,
+        epoch: QueryStatsEpoch,
+        mut stats: BTreeMap<CanisterId, QueryStats>,
+        is_hosted: impl Fn(&CanisterId) -> bool,
+    ) {
+        match self.epoch {
+            Some(current) if epoch < current => return,
+            Some(current) if epoch > current => {
+                self.aggregate(&is_hosted);
+                self.epoch = Some(epoch);
+            }
+            Some(_) => (),
+            None => self.epoch = Some(epoch),
+        }
+        stats.retain(|canister_id, _| is_hosted(canister_id));
+        self.reports.entry(signer).or_insert(stats);
+    }
+
+    /// Returns the aggregated statistics of the given canister over all
+    /// epochs before the current one.
+    pub fn total_query_stats(&self, canister_id: &CanisterId) -> QueryStats {
+        self.totals.get(canister_id).cloned().unwrap_or_default()
+    }
+
+    /// Adds the reports of the current epoch to the totals, and drops the
+    /// totals of the canisters that are no longer hosted.
+    fn aggregate(&mut self, is_hosted: &impl Fn(&CanisterId) -> bool) {
+        let reports = std::mem::take(&mut self.reports);
+        let num_reports = reports.len() as u64;
+        self.totals.retain(|canister_id, _| is_hosted(canister_id));
+        let canister_ids: BTreeSet<CanisterId> = reports
+            .values()
+            .flat_map(|stats| stats.keys().cloned())
+            .filter(|canister_id| is_hosted(canister_id))
+            .collect();
+        let aggregate = |field: fn(&QueryStats) -> u64, canister_id: &CanisterId| -> u64 {
+            let mut values: Vec<u64> = reports
+                .values()
+                .map(|stats| stats.get(canister_id).map(field).unwrap_or(0))
+                .collect();
+            values.sort_unstable();
+            values[values.len() / 2].saturating_mul(num_reports)
+        };
+        for canister_id in canister_ids {
+            let stats = QueryStats {
+                num_calls: aggregate(|stats| stats.num_calls, &canister_id),
+                num_instructions: aggregate(|stats| stats.num_instructions, &canister_id),
+                ingress_payload_size: aggregate(|stats| stats.ingress_payload_size, &canister_id),
+                egress_payload_size: aggregate(|stats| stats.egress_payload_size, &canister_id),
+            };
+            self.totals
+                .entry(canister_id)
+                .or_default()
+                .saturating_accumulate(&stats);
+        }
+    }
+}
+
+fn canister_query_stats_into_protobuf(
+    stats: &BTreeMap<CanisterId, QueryStats>,
+) -> Vec<pb_metadata::CanisterQueryStats> {
+    stats
+        .iter()
+        .map(|(canister_id, stats)| pb_metadata::CanisterQueryStats {
+            canister_id: Some(pb_types::CanisterId::from(*canister_id)),
+            num_calls: stats.num_calls,
+            num_instructions: stats.num_instructions,
+            ingress_payload_size: stats.ingress_payload_size,
+            egress_payload_size: stats.egress_payload_size,
+        })
+        .collect()
+}
+
+fn canister_query_stats_try_from_protobuf(
+    stats: Vec<pb_metadata::CanisterQueryStats>,
+) -> Result<BTreeMap<CanisterId, QueryStats>, ProxyDecodeError> {
+    let mut result = BTreeMap::new();
+    for entry in stats {
+        let canister_id: CanisterId =
+            try_from_option_field(entry.canister_id, "CanisterQueryStats::canister_id")?;
+        result.insert(
+            canister_id,
+            QueryStats {
+                num_calls: entry.num_calls,
+                num_instructions: entry.num_instructions,
+                ingress_payload_size: entry.ingress_payload_size,
+                egress_payload_size: entry.egress_payload_size,
+            },
+        );
+    }
+    Ok(result)
+}
+
+impl From<&QueryStatsState> for pb_metadata::QueryStatsState {
+    fn from(item: &QueryStatsState) -> Self {
+        Self {
+            epoch: item.epoch.map(|epoch| epoch.get()),
+            reports: item
+                .reports
+                .iter()
+                .map(|(node_id, stats)| pb_metadata::QueryStatsReport {
+                    node_id: Some(node_id_into_protobuf(*node_id)),
+                    stats: canister_query_stats_into_protobuf(stats),
+                })
+                .collect(),
+            totals: canister_query_stats_into_protobuf(&item.totals),
+        }
+    }
+}
+
+impl TryFrom<pb_metadata::QueryStatsState> for QueryStatsState {
+    type Error = ProxyDecodeError;
+    fn try_from(item: pb_metadata::QueryStatsState) -> Result<Self, Self::Error> {
+        let mut reports = BTreeMap::new();
+        for report in item.reports {
+            reports.insert(
+                node_id_try_from_protobuf(try_from_option_field(
+                    report.node_id,
+                    "QueryStatsState::reports::K",
+                )?)?,
+                canister_query_stats_try_from_protobuf(report.stats)?,
+            );
+        }
+        Ok(Self {
+            epoch: item.epoch.map(QueryStatsEpoch::from),
+            reports,
+            totals: canister_query_stats_try_from_protobuf(item.totals)?,
+        })
+    }
+}
+
 impl From<&SystemMetadata> for pb_metadata::SystemMetadata {
     fn from(item: &SystemMetadata) -> Self {
         // We do not store the subnet type when we serialize SystemMetadata. We rely on
@@ -270,6 +446,7 @@ impl From<&SystemMetadata> for pb_metadata::SystemMetadata {
             state_sync_version: item.state_sync_version,
             certification_version: item.certification_version,
             heap_delta_estimate: item.heap_delta_estimate.get(),
+            query_stats: Some((&item.query_stats).into()),
         }
     }
 }
@@ -316,6 +493,10 @@ impl TryFrom<pb_metadata::SystemMetadata> for SystemMetadata {
             },
 
             heap_delta_estimate: NumBytes::from(item.heap_delta_estimate),
+            query_stats: match item.query_stats {
+                Some(query_stats) => QueryStatsState::try_from(query_stats)?,
+                None => Default::default(),
+            },
         })
     }
 }
@@ -338,6 +519,7 @@ impl SystemMetadata {
             state_sync_version: 0,
             certification_version: 0,
             heap_delta_estimate: NumBytes::from(0),
+            query_stats: Default::default(),
         }
     }
 
@@ -758,6 +940,36 @@ impl IngressHistoryState {
         self.statuses.is_empty()
     }
 
+    /// Retains only the entries whose status satisfies the given predicate.
+    pub fn retain<F: Fn(&IngressStatus) -> bool>(&mut self, f: F) {
+        let removed: BTreeSet<MessageId> = self
+            .statuses
+            .iter()
+            .filter(|(_, status)| !f(status))
+            .map(|(message_id, _)| message_id.clone())
+            .collect();
+        if removed.is_empty() {
+            return;
+        }
+
+        let statuses = Arc::make_mut(&mut self.statuses);
+        for message_id in removed.iter() {
+            statuses.remove(message_id);
+        }
+        let pruning_times = Arc::make_mut(&mut self.pruning_times);
+        for message_ids in pruning_times.values_mut() {
+            *message_ids = message_ids.difference(&removed).cloned().collect();
+        }
+        let empty_times: Vec<Time> = pruning_times
+            .iter()
+            .filter(|(_, message_ids)| message_ids.is_empty())
+            .map(|(time, _)| *time)
+            .collect();
+        for time in empty_times {
+            pruning_times.remove(&time);
+        }
+    }
+
     /// Removes ingress history entries that are associated with a pruning_time
     /// that's older than the given time.
     pub fn prune(&mut self, time: Time) {
@@ -779,7 +991,7 @@ mod tests {
     use super::*;
     use ic_test_utilities::{
         mock_time,
-        types::ids::{canister_test_id, message_test_id, user_test_id},
+        types::ids::{canister_test_id, message_test_id, node_test_id, user_test_id},
     };
     use ic_types::ingress::{WasmResult, MAX_INGRESS_TTL};
 
@@ -858,4 +1070,90 @@ mod tests {
 
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn query_stats_are_aggregated_by_median() {
+        let report = |num_calls: u64| {
+            let mut stats = BTreeMap::new();
+            stats.insert(
+                canister_test_id(1),
+                QueryStats {
+                    num_calls,
+                    ..Default::default()
+                },
+            );
+            stats
+        };
+        let mut query_stats = QueryStatsState::default();
+        let epoch = QueryStatsEpoch::from(1);
+        query_stats.add_report(node_test_id(1), epoch, report(10), |_| true);
+        query_stats.add_report(node_test_id(2), epoch, report(12), |_| true);
+        query_stats.add_report(node_test_id(3), epoch, report(1_000), |_| true);
+        // Only the first report of a replica counts.
+        query_stats.add_report(node_test_id(3), epoch, report(0), |_| true);
+        // Reports are aggregated once the next epoch is reported.
+        assert_eq!(
+            query_stats
+                .total_query_stats(&canister_test_id(1))
+                .num_calls,
+            0
+        );
+
+        query_stats.add_report(
+            node_test_id(1),
+            QueryStatsEpoch::from(2),
+            BTreeMap::new(),
+            |_| true,
+        );
+        assert_eq!(
+            query_stats
+                .total_query_stats(&canister_test_id(1))
+                .num_calls,
+            36
+        );
+        // Reports of past epochs are ignored.
+        query_stats.add_report(node_test_id(4), epoch, report(10), |_| true);
+
+        let decoded =
+            QueryStatsState::try_from(pb_metadata::QueryStatsState::from(&query_stats)).unwrap();
+        assert_eq!(decoded, query_stats);
+    }
+
+    #[test]
+    fn query_stats_of_canisters_not_hosted_are_dropped() {
+        let mut stats = BTreeMap::new();
+        for i in 1..=2 {
+            stats.insert(
+                canister_test_id(i),
+                QueryStats {
+                    num_calls: 1,
+                    ..Default::default()
+                },
+            );
+        }
+        let mut query_stats = QueryStatsState::default();
+        query_stats.add_report(node_test_id(1), QueryStatsEpoch::from(1), stats, |_| true);
+
+        // Canister 1 is removed before the epoch is aggregated.
+        let hosted = |canister_id: &CanisterId| *canister_id == canister_test_id(2);
+        query_stats.add_report(
+            node_test_id(1),
+            QueryStatsEpoch::from(2),
+            BTreeMap::new(),
+            hosted,
+        );
+        assert_eq!(query_stats.totals.len(), 1);
+        assert_eq!(
+            query_stats
+                .total_query_stats(&canister_test_id(2))
+                .num_calls,
+            1
+        );
+
+        // Canister 3 is not hosted by the subnet.
+        let mut stats = BTreeMap::new();
+        stats.insert(canister_test_id(3), QueryStats::default());
+        query_stats.add_report(node_test_id(2), QueryStatsEpoch::from(2), stats, hosted);
+        assert!(query_stats.reports[&node_test_id(2)].is_empty());
+    }
 }
@@ -1,4 +1,5 @@
 mod checkpoint;
+pub mod cold_storage;
 pub mod int_map;
 
 use checkpoint::Checkpoint;
@@ -241,6 +242,8 @@ pub enum PersistenceError {
     },
     /// (Slice) size is not equal to page size.
     BadPageSize { expected: usize, actual: usize },
+    /// The cold page index of a heap file is malformed.
+    InvalidColdPageIndex { path: String, message: String },
 }
 
 impl PersistenceError {
@@ -278,6 +281,9 @@ impl std::fmt::Display for PersistenceError {
                 "Bad slice size: expected {}, actual {}",
                 expected, actual
             ),
+            PersistenceError::InvalidColdPageIndex { path, message } => {
+                write!(f, "Invalid cold page index {}: {}", path, message)
+            }
         }
     }
 }
@@ -345,6 +351,26 @@ impl PageMap {
         !self.page_delta.is_empty()
     }
 
+    /// Returns the indices of the pages stored only in memory, i.e., the
+    /// pages modified since the page map was opened.
+    pub fn delta_page_indices(&self) -> Vec<PageIndex> {
+        self.page_delta.iter().map(|(index, _)| index).collect()
+    }
+
+    /// Returns the device and inode number of the heap file the page map was
+    /// opened from, or None if it isn't backed by a non-empty file. Together
+    /// with the `delta_page_indices`, this tells which pages of a copy of
+    /// that file changed.
+    pub fn checkpoint_file_id(&self) -> Option<(u64, u64)> {
+        self.checkpoint.file_id()
+    }
+
+    /// Returns the indices of the cold pages, see `cold_storage`, that were
+    /// read since the page map was opened.
+    pub fn accessed_cold_page_indices(&self) -> Vec<PageIndex> {
+        self.checkpoint.accessed_cold_page_indices()
+    }
+
     /// Persists the heap delta contained in this page map to the specified
     /// destination.
     pub fn persist_delta(&self, dst: &Path) -> Result<(), PersistenceError> {
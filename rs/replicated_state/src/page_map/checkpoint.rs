@@ -1,7 +1,8 @@
-use crate::page_map::{PageIndex, PersistenceError};
+use crate::page_map::{cold_storage::ColdPages, PageIndex, PersistenceError};
 use ic_sys::{mmap::ScopedMmap, PAGE_SIZE};
 use lazy_static::lazy_static;
 use std::fs::OpenOptions;
+use std::os::unix::fs::MetadataExt;
 use std::path::Path;
 use std::sync::Arc;
 
@@ -13,14 +14,18 @@ lazy_static! {
 /// module.
 ///
 /// Conceptually it's an immutable byte array backed by a file and
-/// aligned to a page boundary.
+/// aligned to a page boundary.  The cold pages of the file, if any, are
+/// served from cold storage.
 #[derive(Clone)]
 pub(crate) struct Checkpoint {
     mapping: Option<Arc<Mapping>>,
+    cold_pages: Option<Arc<ColdPages>>,
 }
 
 struct Mapping {
     mmap: ScopedMmap,
+    /// The device and inode number of the mapped file.
+    file_id: (u64, u64),
 }
 
 impl Mapping {
@@ -61,7 +66,10 @@ impl Mapping {
                     internal_error: err.to_string(),
                 }
             })?;
-            Ok(Some(Mapping { mmap }))
+            Ok(Some(Mapping {
+                mmap,
+                file_id: (metadata.dev(), metadata.ino()),
+            }))
         }
     }
 
@@ -84,24 +92,56 @@ impl Checkpoint {
     /// Returns an empty checkpoint, not backed by any file. It serves
     /// zeroed pages.
     pub fn empty() -> Checkpoint {
-        Checkpoint { mapping: None }
+        Checkpoint {
+            mapping: None,
+            cold_pages: None,
+        }
     }
 
-    /// Opens an existing heap file located at the specified path.
+    /// Opens an existing heap file located at the specified path together
+    /// with its cold pages.
     pub fn open(path: &Path) -> Result<Checkpoint, PersistenceError> {
-        Mapping::new(path).map(|mapping| Checkpoint {
+        let mapping = Mapping::new(path)?;
+        let cold_pages = ColdPages::open(path)?;
+        Ok(Checkpoint {
             mapping: mapping.map(Arc::new),
+            cold_pages: cold_pages.map(Arc::new),
         })
     }
 
     /// Returns the page with the specified `page_number`.
+    ///
+    /// Panics if a cold page can't be loaded.  The cold page index is
+    /// validated when the checkpoint is opened, so this only happens on I/O
+    /// errors, which are as fatal as for the pages of the mapped heap file.
     pub fn get_page(&self, page_index: PageIndex) -> &[u8] {
+        if let Some(cold_pages) = &self.cold_pages {
+            match cold_pages.get_page(page_index) {
+                Ok(Some(page)) => return page,
+                Ok(None) => (),
+                Err(err) => panic!("Failed to load cold page {}: {}", page_index, err),
+            }
+        }
         match self.mapping {
             Some(ref mapping) => mapping.get_page(page_index),
             None => &ZEROED_PAGE,
         }
     }
 
+    /// Returns the indices of the cold pages that were read since the
+    /// checkpoint was opened.
+    pub fn accessed_cold_page_indices(&self) -> Vec<PageIndex> {
+        self.cold_pages
+            .as_ref()
+            .map_or_else(Vec::new, |cold_pages| cold_pages.accessed_page_indices())
+    }
+
+    /// Returns the device and inode number of the file backing this
+    /// checkpoint, or None if it isn't backed by a non-empty file.
+    pub fn file_id(&self) -> Option<(u64, u64)> {
+        self.mapping.as_ref().map(|mapping| mapping.file_id)
+    }
+
     /// Returns the max number of (possibly) non-zero pages in this
     /// checkpoint.
     pub fn num_pages(&self) -> usize {
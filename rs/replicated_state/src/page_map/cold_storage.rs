@@ -0,0 +1,679 @@
+//! Cold storage of canister heap pages.
+//!
+//! With tiered storage, heap pages that were not accessed for a number of
+//! checkpoints are moved out of the heap file into compressed cold page files,
+//! which usually live on a cheaper and slower disk than the state root.  The
+//! heap file keeps its size, but the cold pages are punched out of it.  A
+//! page counts as accessed if it was modified, or if it was cold and was read
+//! from cold storage; reads of pages in the heap file are not tracked.
+//!
+//! The cold pages of a heap file are described by a cold page index that
+//! records
+//!
+//! * the location of each cold page in the cold page data files, and
+//! * for every page of the heap file, the number of checkpoints since the page
+//!   was last accessed.
+//!
+//! A heap file with a cold page index is accompanied by a symbolic link next
+//! to it (see `cold_pages_link`) that points to its index, so the cold pages
+//! can be found given only the path of the heap file.  The links are skipped
+//! when computing manifests, so the manifest of a checkpoint doesn't depend on
+//! the storage tier of its pages.
+//!
+//! Index and data files are immutable once written, so they can be shared by
+//! the tip and by all checkpoints created from it.
+//!
+//! Layout of an index file (all integers are little-endian):
+//!
+//! ```text
+//! "ICCOLDI1" zstd(
+//!     num_data_files: u64, { name_len: u16, name: [u8] }*,
+//!     num_pages: u64, ages: [u8; num_pages],
+//!     num_cold_pages: u64, { page: u64, data_file: u32, offset: u64, len: u32 }*
+//! )
+//! ```
+//!
+//! A data file is a concatenation of zstd-compressed pages.
+//!
+//! Cold pages are decompressed on demand into a scratch file next to the data
+//! files, which is mapped into memory.  Loaded pages thus live in the page
+//! cache rather than on the heap, and the kernel reclaims them under memory
+//! pressure.
+
+use super::{PageIndex, PersistenceError};
+use ic_sys::{mmap::ScopedMmap, PAGE_SIZE};
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryInto;
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const INDEX_MAGIC: &[u8; 8] = b"ICCOLDI1";
+
+/// The zstd compression level of cold pages.
+const COMPRESSION_LEVEL: i32 = 3;
+
+/// A data file is compacted once less than this fraction of its bytes belongs
+/// to cold pages that are still referenced.
+const MIN_LIVE_DATA_FRACTION: f64 = 0.5;
+
+/// Returns the path of the symbolic link that points to the cold page index of
+/// the given heap file.
+pub fn cold_pages_link(heap_file: &Path) -> PathBuf {
+    let mut link = OsString::from(heap_file.as_os_str());
+    link.push(".cold");
+    PathBuf::from(link)
+}
+
+/// Returns true if the given path is a link created by `cold_pages_link`.
+pub fn is_cold_pages_link(path: &Path) -> bool {
+    path.extension().map_or(false, |ext| ext == "cold")
+        && path
+            .symlink_metadata()
+            .map_or(false, |metadata| metadata.file_type().is_symlink())
+}
+
+fn io_error(path: &Path, context: &str, err: std::io::Error) -> PersistenceError {
+    PersistenceError::FileSystemError {
+        path: path.display().to_string(),
+        context: context.to_string(),
+        internal_error: err.to_string(),
+    }
+}
+
+fn invalid_index(path: &Path, message: &str) -> PersistenceError {
+    PersistenceError::InvalidColdPageIndex {
+        path: path.display().to_string(),
+        message: message.to_string(),
+    }
+}
+
+/// The location of a compressed cold page.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct ColdPageLocation {
+    data_file: u32,
+    offset: u64,
+    len: u32,
+}
+
+/// The cold pages loaded by `ColdPages::get_page`.
+struct LoadedPages {
+    /// The unlinked scratch file holding the loaded pages at their offsets in
+    /// the heap file.
+    file: File,
+    /// The read-only mapping of the scratch file.
+    mapping: ScopedMmap,
+    /// The indices of the loaded pages.
+    pages: BTreeSet<u64>,
+}
+
+impl LoadedPages {
+    fn new(dir: &Path, num_pages: usize) -> Result<Self, PersistenceError> {
+        let len = num_pages * *PAGE_SIZE;
+        let file = tempfile::tempfile_in(dir)
+            .and_then(|file| file.set_len(len as u64).map(|_| file))
+            .map_err(|err| io_error(dir, "Failed to create cold page scratch file", err))?;
+        let mapping = ScopedMmap::from_readonly_file(&file, len)
+            .map_err(|err| io_error(dir, "Failed to map cold page scratch file", err))?;
+        Ok(Self {
+            file,
+            mapping,
+            pages: BTreeSet::new(),
+        })
+    }
+}
+
+/// The cold pages of a heap file.  Pages are decompressed on demand, see the
+/// module documentation.
+pub struct ColdPages {
+    index_path: PathBuf,
+    data_file_names: Vec<String>,
+    data_files: Vec<File>,
+    ages: Vec<u8>,
+    locations: BTreeMap<u64, ColdPageLocation>,
+    loaded: Mutex<Option<LoadedPages>>,
+}
+
+impl ColdPages {
+    /// Opens the cold pages of the given heap file.  Returns `None` if the
+    /// heap file has no cold page index.
+    pub fn open(heap_file: &Path) -> Result<Option<Self>, PersistenceError> {
+        let link = cold_pages_link(heap_file);
+        match std::fs::read_link(&link) {
+            Ok(index_path) => Self::open_index(&index_path).map(Some),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(io_error(&link, "Failed to read cold page link", err)),
+        }
+    }
+
+    /// Opens the cold page index at the given path.
+    pub fn open_index(index_path: &Path) -> Result<Self, PersistenceError> {
+        let compressed = std::fs::read(index_path)
+            .map_err(|err| io_error(index_path, "Failed to read cold page index", err))?;
+        if !compressed.starts_with(INDEX_MAGIC) {
+            return Err(invalid_index(index_path, "bad magic"));
+        }
+        let payload = zstd::stream::decode_all(&compressed[INDEX_MAGIC.len()..])
+            .map_err(|err| io_error(index_path, "Failed to decompress cold page index", err))?;
+        let mut reader = IndexReader {
+            path: index_path,
+            data: &payload[..],
+        };
+
+        let num_data_files = reader.u64()?;
+        let mut data_file_names = Vec::new();
+        for _ in 0..num_data_files {
+            let len = reader.u16()? as usize;
+            let name = String::from_utf8(reader.bytes(len)?.to_vec())
+                .map_err(|_| invalid_index(index_path, "data file name is not UTF-8"))?;
+            data_file_names.push(name);
+        }
+        let num_pages = reader.u64()? as usize;
+        let ages = reader.bytes(num_pages)?.to_vec();
+        let num_cold_pages = reader.u64()?;
+        let mut locations = BTreeMap::new();
+        for _ in 0..num_cold_pages {
+            let page = reader.u64()?;
+            let location = ColdPageLocation {
+                data_file: reader.u32()?,
+                offset: reader.u64()?,
+                len: reader.u32()?,
+            };
+            if location.data_file as usize >= data_file_names.len() {
+                return Err(invalid_index(index_path, "unknown data file"));
+            }
+            if page >= num_pages as u64 {
+                return Err(invalid_index(index_path, "cold page beyond the heap file"));
+            }
+            locations.insert(page, location);
+        }
+
+        let dir = index_path.parent().unwrap_or_else(|| Path::new(""));
+        let data_files = data_file_names
+            .iter()
+            .map(|name| {
+                let path = dir.join(name);
+                File::open(&path)
+                    .map_err(|err| io_error(&path, "Failed to open cold page data file", err))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Detect truncated data files when opening the index rather than when
+        // a page is needed.
+        for (data_file, file) in data_files.iter().enumerate() {
+            let path = dir.join(&data_file_names[data_file]);
+            let len = file
+                .metadata()
+                .map_err(|err| io_error(&path, "Failed to stat cold page data file", err))?
+                .len();
+            let truncated = locations.values().any(|location| {
+                location.data_file as usize == data_file
+                    && location.offset + location.len as u64 > len
+            });
+            if truncated {
+                return Err(invalid_index(index_path, "cold page beyond its data file"));
+            }
+        }
+
+        Ok(Self {
+            index_path: index_path.to_path_buf(),
+            data_file_names,
+            data_files,
+            ages,
+            locations,
+            loaded: Mutex::new(None),
+        })
+    }
+
+    /// Returns the paths of the index and the data files.
+    pub fn files(&self) -> Vec<PathBuf> {
+        let dir = self.index_path.parent().unwrap_or_else(|| Path::new(""));
+        std::iter::once(self.index_path.clone())
+            .chain(self.data_file_names.iter().map(|name| dir.join(name)))
+            .collect()
+    }
+
+    /// Returns the number of checkpoints since the given page was last
+    /// accessed, as recorded when the index was written.
+    pub fn age(&self, page_index: PageIndex) -> u8 {
+        self.ages
+            .get(page_index.get() as usize)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Returns true if the given page is a cold page.
+    pub fn contains(&self, page_index: PageIndex) -> bool {
+        self.locations.contains_key(&page_index.get())
+    }
+
+    /// Enumerates the cold pages.
+    pub fn page_indices(&self) -> impl Iterator<Item = PageIndex> + '_ {
+        self.locations.keys().map(|page| PageIndex::new(*page))
+    }
+
+    /// Returns the number of cold pages.
+    pub fn len(&self) -> usize {
+        self.locations.len()
+    }
+
+    /// Returns true if there are no cold pages.
+    pub fn is_empty(&self) -> bool {
+        self.locations.is_empty()
+    }
+
+    /// Returns the indices of the cold pages that were loaded by `get_page`,
+    /// i.e., that were accessed since the cold pages were opened.
+    pub fn accessed_page_indices(&self) -> Vec<PageIndex> {
+        match &*self.loaded.lock().unwrap() {
+            Some(loaded) => loaded
+                .pages
+                .iter()
+                .map(|page| PageIndex::new(*page))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Returns the contents of the given cold page, loading it if necessary.
+    /// Returns `None` if the page is not a cold page.
+    pub fn get_page(&self, page_index: PageIndex) -> Result<Option<&[u8]>, PersistenceError> {
+        let location = match self.locations.get(&page_index.get()) {
+            Some(location) => *location,
+            None => return Ok(None),
+        };
+        let mut loaded = self.loaded.lock().unwrap();
+        if loaded.is_none() {
+            let dir = self.index_path.parent().unwrap_or_else(|| Path::new(""));
+            *loaded = Some(LoadedPages::new(dir, self.ages.len())?);
+        }
+        let loaded = loaded.as_mut().unwrap();
+        let offset = page_index.get() as usize * *PAGE_SIZE;
+        if !loaded.pages.contains(&page_index.get()) {
+            let mut page = vec![0; *PAGE_SIZE];
+            self.decompress_page(location, &mut page)?;
+            loaded
+                .file
+                .write_all_at(&page, offset as u64)
+                .map_err(|err| io_error(&self.index_path, "Failed to store cold page", err))?;
+            loaded.pages.insert(page_index.get());
+        }
+        // SAFETY: the mapping covers all pages of the heap file and lives as
+        // long as `self`.  A page is written once, before a reference to it is
+        // first handed out, and never modified afterwards.
+        Ok(Some(unsafe {
+            std::slice::from_raw_parts(loaded.mapping.addr().add(offset), *PAGE_SIZE)
+        }))
+    }
+
+    fn read_compressed(&self, location: ColdPageLocation) -> Result<Vec<u8>, PersistenceError> {
+        let mut compressed = vec![0; location.len as usize];
+        self.data_files[location.data_file as usize]
+            .read_exact_at(&mut compressed, location.offset)
+            .map_err(|err| io_error(&self.index_path, "Failed to read cold page", err))?;
+        Ok(compressed)
+    }
+
+    /// Decompresses the page at the given location into `page`, which has the
+    /// size of a page.
+    fn decompress_page(
+        &self,
+        location: ColdPageLocation,
+        page: &mut [u8],
+    ) -> Result<(), PersistenceError> {
+        let compressed = self.read_compressed(location)?;
+        let mut contents = Vec::with_capacity(*PAGE_SIZE);
+        zstd::stream::read::Decoder::new(&compressed[..])
+            .and_then(|decoder| {
+                decoder
+                    .take(*PAGE_SIZE as u64 + 1)
+                    .read_to_end(&mut contents)
+            })
+            .map_err(|err| io_error(&self.index_path, "Failed to decompress cold page", err))?;
+        if contents.len() != *PAGE_SIZE {
+            return Err(PersistenceError::BadPageSize {
+                expected: *PAGE_SIZE,
+                actual: contents.len(),
+            });
+        }
+        page.copy_from_slice(&contents);
+        Ok(())
+    }
+
+    /// Overwrites the parts of `buf`, which holds the bytes of the heap file
+    /// starting at `offset`, that belong to cold pages with their contents.
+    ///
+    /// The pages are decompressed without being loaded, so overlaying does
+    /// not count as an access.
+    pub fn overlay(&self, offset: u64, buf: &mut [u8]) -> Result<(), PersistenceError> {
+        let page_size = *PAGE_SIZE as u64;
+        let end = offset + buf.len() as u64;
+        let first_page = offset / page_size;
+        let last_page = (end + page_size - 1) / page_size;
+        let mut contents = vec![0; *PAGE_SIZE];
+        for (page, location) in self.locations.range(first_page..last_page) {
+            self.decompress_page(*location, &mut contents)?;
+            let page_start = page * page_size;
+            let from = page_start.max(offset);
+            let to = (page_start + page_size).min(end);
+            buf[(from - offset) as usize..(to - offset) as usize].copy_from_slice(
+                &contents[(from - page_start) as usize..(to - page_start) as usize],
+            );
+        }
+        Ok(())
+    }
+}
+
+struct IndexReader<'a> {
+    path: &'a Path,
+    data: &'a [u8],
+}
+
+impl<'a> IndexReader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], PersistenceError> {
+        if self.data.len() < len {
+            return Err(invalid_index(self.path, "unexpected end of index"));
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, PersistenceError> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, PersistenceError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, PersistenceError> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+}
+
+/// Creates a new file with a unique name and the given suffix in `dir`.
+fn create_unique_file(dir: &Path, suffix: &str) -> Result<(File, PathBuf), PersistenceError> {
+    let (file, path) = tempfile::Builder::new()
+        .prefix("cold_")
+        .suffix(suffix)
+        .tempfile_in(dir)
+        .and_then(|file| file.keep().map_err(|err| err.error))
+        .map_err(|err| io_error(dir, "Failed to create cold page file", err))?;
+    Ok((file, path))
+}
+
+/// Writes a cold page index for a heap file to `cold_storage_root` and returns
+/// its path.
+///
+/// `ages` holds the number of checkpoints since each page of the heap file
+/// was last accessed, and `cold_pages` the pages to store.  The compressed
+/// cold pages of `previous` are reused unless they are in a data file that
+/// mostly holds pages that are no longer cold.  The contents of all other
+/// pages are obtained from `read_page`.
+pub fn write_cold_pages<F>(
+    cold_storage_root: &Path,
+    previous: Option<&ColdPages>,
+    ages: &[u8],
+    cold_pages: &BTreeSet<PageIndex>,
+    mut read_page: F,
+) -> Result<PathBuf, PersistenceError>
+where
+    F: FnMut(PageIndex) -> Result<Vec<u8>, PersistenceError>,
+{
+    // Determine the data files of the previous index that are worth keeping.
+    let mut kept_data_files = BTreeMap::new();
+    if let Some(previous) = previous {
+        let mut live_bytes = vec![0u64; previous.data_files.len()];
+        for (page, location) in previous.locations.iter() {
+            if cold_pages.contains(&PageIndex::new(*page)) {
+                live_bytes[location.data_file as usize] += location.len as u64;
+            }
+        }
+        for (data_file, file) in previous.data_files.iter().enumerate() {
+            let len = file
+                .metadata()
+                .map_err(|err| io_error(&previous.index_path, "Failed to stat data file", err))?
+                .len();
+            if live_bytes[data_file] > 0
+                && live_bytes[data_file] as f64 >= len as f64 * MIN_LIVE_DATA_FRACTION
+            {
+                let new_index = kept_data_files.len() as u32;
+                kept_data_files.insert(data_file as u32, new_index);
+            }
+        }
+    }
+
+    let mut data_file_names: Vec<String> = kept_data_files
+        .keys()
+        .map(|data_file| previous.unwrap().data_file_names[*data_file as usize].clone())
+        .collect();
+    let mut locations = BTreeMap::new();
+    let mut new_data: Option<(File, PathBuf, u64)> = None;
+    let new_data_file = data_file_names.len() as u32;
+
+    for page_index in cold_pages.iter() {
+        let previous_location =
+            previous.and_then(|previous| previous.locations.get(&page_index.get()));
+        if let Some(location) = previous_location {
+            if let Some(data_file) = kept_data_files.get(&location.data_file) {
+                locations.insert(
+                    page_index.get(),
+                    ColdPageLocation {
+                        data_file: *data_file,
+                        ..*location
+                    },
+                );
+                continue;
+            }
+        }
+
+        let compressed = match previous_location {
+            // Move the page from a compacted data file without recompressing it.
+            Some(location) => previous.unwrap().read_compressed(*location)?,
+            None => {
+                let contents = read_page(*page_index)?;
+                zstd::stream::encode_all(&contents[..], COMPRESSION_LEVEL).map_err(|err| {
+                    io_error(cold_storage_root, "Failed to compress cold page", err)
+                })?
+            }
+        };
+        if new_data.is_none() {
+            let (file, path) = create_unique_file(cold_storage_root, ".data")?;
+            new_data = Some((file, path, 0));
+        }
+        let (file, path, offset) = new_data.as_mut().unwrap();
+        file.write_all(&compressed)
+            .map_err(|err| io_error(path, "Failed to write cold page", err))?;
+        locations.insert(
+            page_index.get(),
+            ColdPageLocation {
+                data_file: new_data_file,
+                offset: *offset,
+                len: compressed.len() as u32,
+            },
+        );
+        *offset += compressed.len() as u64;
+    }
+
+    if let Some((file, path, _)) = new_data {
+        file.sync_all()
+            .map_err(|err| io_error(&path, "Failed to sync cold page data file", err))?;
+        data_file_names.push(
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .expect("cold page file names are UTF-8")
+                .to_string(),
+        );
+    }
+
+    let mut payload = Vec::new();
+    payload.extend_from_slice(&(data_file_names.len() as u64).to_le_bytes());
+    for name in data_file_names.iter() {
+        payload.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        payload.extend_from_slice(name.as_bytes());
+    }
+    payload.extend_from_slice(&(ages.len() as u64).to_le_bytes());
+    payload.extend_from_slice(ages);
+    payload.extend_from_slice(&(locations.len() as u64).to_le_bytes());
+    for (page, location) in locations.iter() {
+        payload.extend_from_slice(&page.to_le_bytes());
+        payload.extend_from_slice(&location.data_file.to_le_bytes());
+        payload.extend_from_slice(&location.offset.to_le_bytes());
+        payload.extend_from_slice(&location.len.to_le_bytes());
+    }
+
+    let (mut file, index_path) = create_unique_file(cold_storage_root, ".index")?;
+    let compressed = zstd::stream::encode_all(&payload[..], COMPRESSION_LEVEL)
+        .map_err(|err| io_error(&index_path, "Failed to compress cold page index", err))?;
+    file.write_all(INDEX_MAGIC)
+        .and_then(|_| file.write_all(&compressed))
+        .and_then(|_| file.sync_all())
+        .map_err(|err| io_error(&index_path, "Failed to write cold page index", err))?;
+    Ok(index_path)
+}
+
+/// Removes the contents of the given pages from the heap file, keeping its
+/// size.  The pages read as zeros afterwards.
+///
+/// This is a no-op on platforms that don't support punching holes into
+/// files, which is correct because cold pages take precedence over the
+/// contents of the heap file.
+pub fn punch_holes(heap_file: &Path, pages: &BTreeSet<PageIndex>) -> Result<(), PersistenceError> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::io::AsRawFd;
+
+        let file = OpenOptions::new()
+            .write(true)
+            .open(heap_file)
+            .map_err(|err| io_error(heap_file, "Failed to open file", err))?;
+        let page_size = *PAGE_SIZE as u64;
+        let mut pages = pages.iter().map(|page| page.get()).peekable();
+        while let Some(first) = pages.next() {
+            let mut last = first;
+            while pages.peek() == Some(&(last + 1)) {
+                last = pages.next().unwrap();
+            }
+            let result = unsafe {
+                libc::fallocate(
+                    file.as_raw_fd(),
+                    libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+                    (first * page_size) as libc::off_t,
+                    ((last - first + 1) * page_size) as libc::off_t,
+                )
+            };
+            if result != 0 {
+                return Err(io_error(
+                    heap_file,
+                    "Failed to punch holes",
+                    std::io::Error::last_os_error(),
+                ));
+            }
+        }
+        file.sync_all()
+            .map_err(|err| io_error(heap_file, "Failed to sync file", err))?;
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (heap_file, pages);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(byte: u8) -> Vec<u8> {
+        vec![byte; *PAGE_SIZE]
+    }
+
+    #[test]
+    fn cold_pages_roundtrip() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let cold_pages: BTreeSet<_> = vec![PageIndex::new(1), PageIndex::new(3)]
+            .into_iter()
+            .collect();
+        let index = write_cold_pages(dir.path(), None, &[0, 5, 0, 7], &cold_pages, |p| {
+            Ok(page(p.get() as u8))
+        })
+        .unwrap();
+
+        let cold = ColdPages::open_index(&index).unwrap();
+        assert_eq!(cold.len(), 2);
+        assert_eq!(cold.age(PageIndex::new(1)), 5);
+        assert_eq!(cold.age(PageIndex::new(100)), 0);
+        assert!(cold.get_page(PageIndex::new(0)).unwrap().is_none());
+        let mut buf = vec![9; 3 * *PAGE_SIZE];
+        cold.overlay(*PAGE_SIZE as u64 / 2, &mut buf).unwrap();
+        assert!(cold.accessed_page_indices().is_empty());
+        assert_eq!(
+            cold.get_page(PageIndex::new(3)).unwrap().unwrap(),
+            &page(3)[..]
+        );
+        assert_eq!(cold.accessed_page_indices(), vec![PageIndex::new(3)]);
+
+        assert_eq!(buf[0], 9);
+        assert_eq!(buf[*PAGE_SIZE / 2], 1);
+        assert_eq!(buf[2 * *PAGE_SIZE], 9);
+        assert_eq!(buf[3 * *PAGE_SIZE - 1], 3);
+    }
+
+    #[test]
+    fn previous_cold_pages_are_reused_or_compacted() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let all: BTreeSet<_> = (0..4).map(PageIndex::new).collect();
+        let first =
+            write_cold_pages(dir.path(), None, &[1; 4], &all, |p| Ok(page(p.get() as u8))).unwrap();
+        let first = ColdPages::open_index(&first).unwrap();
+
+        // Most pages are still cold, so the data file is kept.
+        let most: BTreeSet<_> = (1..4).map(PageIndex::new).collect();
+        let second = write_cold_pages(dir.path(), Some(&first), &[1; 4], &most, |_| {
+            panic!("cold pages must be reused")
+        })
+        .unwrap();
+        let second = ColdPages::open_index(&second).unwrap();
+        assert_eq!(second.files()[1..], first.files()[1..]);
+
+        // A single page is left, so it's moved to a new data file.
+        let one: BTreeSet<_> = vec![PageIndex::new(2)].into_iter().collect();
+        let third = write_cold_pages(dir.path(), Some(&second), &[1; 4], &one, |_| {
+            panic!("cold pages must be moved")
+        })
+        .unwrap();
+        let third = ColdPages::open_index(&third).unwrap();
+        assert_ne!(third.files()[1..], first.files()[1..]);
+        assert_eq!(
+            third.get_page(PageIndex::new(2)).unwrap().unwrap(),
+            &page(2)[..]
+        );
+    }
+
+    #[test]
+    fn truncated_data_files_are_rejected() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let cold_pages: BTreeSet<_> = (0..2).map(PageIndex::new).collect();
+        let index = write_cold_pages(dir.path(), None, &[1; 2], &cold_pages, |p| {
+            Ok(page(p.get() as u8 + 1))
+        })
+        .unwrap();
+        let data_file = ColdPages::open_index(&index).unwrap().files()[1].clone();
+        let len = std::fs::metadata(&data_file).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&data_file)
+            .unwrap()
+            .set_len(len - 1)
+            .unwrap();
+        match ColdPages::open_index(&index) {
+            Err(PersistenceError::InvalidColdPageIndex { .. }) => (),
+            Err(err) => panic!("Unexpected error {}", err),
+            Ok(_) => panic!("Expected a truncated data file to be rejected"),
+        }
+    }
+}
@@ -5,6 +5,7 @@ use super::{
 use crate::{canister_state::QUEUE_INDEX_NONE, CanisterQueues};
 use ic_base_types::PrincipalId;
 use ic_logger::{fatal, ReplicaLogger};
+use ic_registry_routing_table::CanisterIdRange;
 use ic_registry_subnet_type::SubnetType;
 use ic_types::messages::{RequestOrResponse, Response};
 use ic_types::{
@@ -353,6 +354,54 @@ impl ReplicatedState {
         self.metadata.time()
     }
 
+    /// Splits this state along `canister_id_range`: the canisters in the range
+    /// move to a new state of the subnet `new_subnet_id`, all other canisters
+    /// stay. Returns the resulting states of this subnet and of the new
+    /// subnet, in this order.
+    ///
+    /// The canisters take their queues with them and the ingress history is
+    /// split by receiver. The streams, the subnet queues and the subnet call
+    /// contexts stay with this subnet; message routing reroutes messages in
+    /// flight to and from the moved canisters based on the canister migrations
+    /// in the network topology.
+    pub fn split(
+        mut self,
+        canister_id_range: &CanisterIdRange,
+        new_subnet_id: SubnetId,
+    ) -> (ReplicatedState, ReplicatedState) {
+        let in_range = |canister_id: CanisterId| {
+            canister_id_range.start <= canister_id && canister_id <= canister_id_range.end
+        };
+
+        let (moved, kept): (BTreeMap<_, _>, BTreeMap<_, _>) = self
+            .take_canister_states()
+            .into_iter()
+            .partition(|(canister_id, _)| in_range(*canister_id));
+        self.put_canister_states(kept);
+
+        let mut new_state = ReplicatedState::new_rooted_at(
+            new_subnet_id,
+            self.metadata.own_subnet_type,
+            self.root.clone(),
+        );
+        new_state.put_canister_states(moved);
+
+        let metadata = &mut new_state.metadata;
+        metadata.batch_time = self.metadata.batch_time;
+        metadata.network_topology = self.metadata.network_topology.clone();
+        metadata.state_sync_version = self.metadata.state_sync_version;
+        metadata.certification_version = self.metadata.certification_version;
+        metadata.ingress_history = self.metadata.ingress_history.clone();
+        metadata
+            .ingress_history
+            .retain(|status| status.receiver().map_or(false, in_range));
+        self.metadata
+            .ingress_history
+            .retain(|status| !status.receiver().map_or(false, in_range));
+
+        (self, new_state)
+    }
+
     /// Iterates over all canisters on the subnet, checking if a source canister
     /// has output messages for a destination canister on the same subnet and
     /// moving them from the source to the destination canister if the
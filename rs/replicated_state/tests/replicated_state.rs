@@ -2,12 +2,13 @@
 mod replicated_state {
     use ic_registry_routing_table::{CanisterIdRange, RoutingTable};
     use ic_test_utilities::{
+        mock_time,
         state::get_initial_state,
-        types::ids::{canister_test_id, subnet_test_id},
+        types::ids::{canister_test_id, message_test_id, subnet_test_id, user_test_id},
         types::messages::RequestBuilder,
         with_test_replica_logger,
     };
-    use ic_types::{CanisterId, SubnetId};
+    use ic_types::{ingress::IngressStatus, CanisterId, SubnetId};
     use maplit::btreemap;
 
     fn setup_routing_table() -> (SubnetId, RoutingTable) {
@@ -88,4 +89,50 @@ mod replicated_state {
             assert!(source_canister.has_output());
         })
     }
+
+    /// Splits a state with four canisters and ingress messages addressed to
+    /// two of them. Ensures that the canisters in the range and their ingress
+    /// statuses move to the new state and all others stay.
+    #[test]
+    fn split_moves_canisters_in_range() {
+        let mut state = get_initial_state(4, 0);
+        let received = |receiver: u64| IngressStatus::Received {
+            receiver: canister_test_id(receiver).get(),
+            user_id: user_test_id(1),
+            time: mock_time(),
+        };
+        state.set_ingress_status(message_test_id(1), received(0));
+        state.set_ingress_status(message_test_id(2), received(3));
+
+        let range = CanisterIdRange {
+            start: canister_test_id(2),
+            end: canister_test_id(3),
+        };
+        let (state, new_state) = state.split(&range, subnet_test_id(2));
+
+        assert_eq!(
+            state.canister_states.keys().collect::<Vec<_>>(),
+            vec![&canister_test_id(0), &canister_test_id(1)]
+        );
+        assert_eq!(
+            new_state.canister_states.keys().collect::<Vec<_>>(),
+            vec![&canister_test_id(2), &canister_test_id(3)]
+        );
+        assert_eq!(new_state.metadata.own_subnet_id, subnet_test_id(2));
+        assert_eq!(new_state.time(), state.time());
+
+        assert_eq!(state.get_ingress_status(&message_test_id(1)), received(0));
+        assert_eq!(
+            state.get_ingress_status(&message_test_id(2)),
+            IngressStatus::Unknown
+        );
+        assert_eq!(
+            new_state.get_ingress_status(&message_test_id(1)),
+            IngressStatus::Unknown
+        );
+        assert_eq!(
+            new_state.get_ingress_status(&message_test_id(2)),
+            received(3)
+        );
+    }
 }
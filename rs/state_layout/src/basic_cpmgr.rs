@@ -62,6 +62,11 @@ use std::{
 ///   3. Rename "<state_root>/fs_tmp/scratchpad_<height>" to
 ///      "<state_root>/checkpoints/<height>", sync "<state_root>/checkpoints".
 ///
+/// To keep the sync off the critical path, step 2 can be split: the tip is
+/// reflinked/copied into the scratchpad without syncing (`tip_to_scratchpad`),
+/// and the scratchpad is later synced and promoted like a State Sync artifact
+/// (`scratchpad_to_checkpoint`, see below).
+///
 /// ## Promoting a State Sync artifact to a checkpoint
 ///
 ///   1. Create state files directly in
@@ -122,6 +127,7 @@ impl BasicCheckpointManager {
                 src,
                 scratchpad.as_path(),
                 FilePermissions::ReadOnly,
+                FsyncPolicy::Sync,
             )?;
             std::fs::rename(&scratchpad, &dst)?;
             sync_path(&dst)
@@ -173,6 +179,29 @@ impl CheckpointManager for BasicCheckpointManager {
         Ok(cp_path)
     }
 
+    fn tip_to_scratchpad(&self, tip: &Path, name: &str) -> std::io::Result<PathBuf> {
+        if self.checkpoints().join(name).exists() {
+            return Err(Error::new(io::ErrorKind::AlreadyExists, name));
+        }
+        let scratchpad = self.tmp().join(format!("scratchpad_{}", name));
+        if scratchpad.exists() {
+            std::fs::remove_dir_all(&scratchpad)?;
+        }
+        match copy_recursively_respecting_tombstones(
+            &self.log,
+            tip,
+            &scratchpad,
+            FilePermissions::ReadWrite,
+            FsyncPolicy::NoSync,
+        ) {
+            Ok(()) => Ok(scratchpad),
+            Err(err) => {
+                let _ = std::fs::remove_dir_all(&scratchpad);
+                Err(err)
+            }
+        }
+    }
+
     fn scratchpad_to_checkpoint(&self, scratchpad: &Path, name: &str) -> std::io::Result<PathBuf> {
         self.ensure_dir_exists(&self.checkpoints())?;
         sync_and_mark_files_readonly(scratchpad)?;
@@ -190,6 +219,7 @@ impl CheckpointManager for BasicCheckpointManager {
             &cp_path,
             scratchpad,
             FilePermissions::ReadWrite,
+            FsyncPolicy::Sync,
         )
     }
 
@@ -308,6 +338,7 @@ impl CheckpointManager for BasicCheckpointManager {
             cp_path.as_path(),
             tip,
             FilePermissions::ReadWrite,
+            FsyncPolicy::Sync,
         ) {
             Ok(()) => Ok(()),
             Err(e) => {
@@ -341,9 +372,16 @@ enum FilePermissions {
     ReadWrite,
 }
 
+#[derive(Clone, Copy, PartialEq)]
+enum FsyncPolicy {
+    Sync,
+    NoSync,
+}
+
 /// Recursively copies `src` to `dst` using the given permission policy for
-/// files. Directories containing a file called "tombstone" are not copied to
-/// the destination.
+/// files, syncing the copies if the fsync policy says so. Directories containing a file called "tombstone" are not copied to
+/// the destination. Symbolic links, e.g. those pointing to cold heap pages,
+/// are copied as links.
 ///
 /// NOTE: If the function returns an error, the changes to the file
 /// system applied by this function are not undone.
@@ -352,10 +390,14 @@ fn copy_recursively_respecting_tombstones(
     src: &Path,
     dst: &Path,
     dst_permissions: FilePermissions,
+    fsync: FsyncPolicy,
 ) -> std::io::Result<()> {
-    let src_metadata = src.metadata()?;
+    let src_metadata = src.symlink_metadata()?;
 
-    if src_metadata.is_dir() {
+    if src_metadata.file_type().is_symlink() {
+        std::os::unix::fs::symlink(fs::read_link(src)?, dst)?;
+        return Ok(());
+    } else if src_metadata.is_dir() {
         if src.join("tombstone").exists() {
             // The source directory was marked as removed by placing a
             // 'tombstone' file inside. We don't want this directory in
@@ -386,6 +428,7 @@ fn copy_recursively_respecting_tombstones(
                 &entry.path(),
                 &dst_entry,
                 dst_permissions,
+                fsync,
             )?;
         }
     } else {
@@ -404,5 +447,8 @@ fn copy_recursively_respecting_tombstones(
 
     // Note that the directory is synced after all the files and directories in
     // it had been recursively synced.
-    sync_path(dst)
+    if fsync == FsyncPolicy::Sync {
+        sync_path(dst)?;
+    }
+    Ok(())
 }
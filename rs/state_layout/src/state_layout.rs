@@ -35,6 +35,15 @@ pub trait CheckpointManager: Send + Sync {
     /// and it need not be a directory path.
     fn tip_to_checkpoint(&self, tip: &Path, name: &str) -> std::io::Result<PathBuf>;
 
+    /// Copies the "tip" directory into a scratchpad for the checkpoint
+    /// identified by "name" without syncing the copy, and returns the path of
+    /// the scratchpad.  Files are reflinked where possible, which makes this
+    /// a cheap metadata operation on copy-on-write filesystems.
+    ///
+    /// The scratchpad becomes the checkpoint once it is promoted with
+    /// `scratchpad_to_checkpoint`, which also syncs it.
+    fn tip_to_scratchpad(&self, tip: &Path, name: &str) -> std::io::Result<PathBuf>;
+
     /// Removes a checkpoint identified by "name".
     fn remove_checkpoint(&self, name: &str) -> std::io::Result<()>;
 
@@ -262,6 +271,34 @@ impl StateLayout {
         Ok(tmp.join(format!("state_sync_scratchpad_{:016x}", height.get())))
     }
 
+    /// Returns the path to the file recording the root hash of the state
+    /// whose chunks are being fetched into the state sync scratchpad at the
+    /// given height, so that an interrupted state sync can be resumed.
+    pub fn state_sync_progress(&self, height: Height) -> Result<PathBuf, LayoutError> {
+        let tmp = self.tmp()?;
+        Ok(tmp.join(format!("state_sync_progress_{:016x}", height.get())))
+    }
+
+    /// Returns a sorted list of the heights of the state sync scratchpads,
+    /// i.e. of state syncs that were interrupted.
+    pub fn state_sync_scratchpad_heights(&self) -> Result<Vec<Height>, LayoutError> {
+        let tmp = self.tmp()?;
+        let entries = std::fs::read_dir(&tmp).map_err(|io_err| LayoutError::IoError {
+            path: tmp.clone(),
+            message: "failed to enumerate state sync scratchpads".to_string(),
+            io_err,
+        })?;
+        let mut heights: Vec<Height> = entries
+            .filter_map(|entry| {
+                let name = entry.ok()?.file_name().into_string().ok()?;
+                let height = name.strip_prefix("state_sync_scratchpad_")?;
+                u64::from_str_radix(height, 16).ok().map(Height::new)
+            })
+            .collect();
+        heights.sort_unstable();
+        Ok(heights)
+    }
+
     pub fn cleanup_tip(&self) -> Result<(), LayoutError> {
         if self.tip_path().exists() {
             std::fs::remove_dir_all(self.tip_path()).map_err(|err| LayoutError::IoError {
@@ -297,6 +334,30 @@ impl StateLayout {
         }
     }
 
+    /// Snapshots the "tip" state into a scratchpad that can be promoted to
+    /// the checkpoint at the given height with `scratchpad_to_checkpoint`.
+    /// Unlike `tip_to_checkpoint`, this doesn't sync any files.
+    pub fn tip_to_scratchpad(
+        &self,
+        tip: CheckpointLayout<RwPolicy>,
+        height: Height,
+    ) -> Result<CheckpointLayout<RwPolicy>, LayoutError> {
+        let cp_name = self.checkpoint_name(height);
+        match self.cp_manager.tip_to_scratchpad(tip.raw_path(), &cp_name) {
+            Ok(scratchpad) => CheckpointLayout::new(scratchpad, height),
+            Err(err) if is_dir_already_exists_err(&err) => Err(LayoutError::AlreadyExists(height)),
+            Err(err) => Err(LayoutError::IoError {
+                path: tip.raw_path().to_path_buf(),
+                message: format!(
+                    "Failed to snapshot tip for checkpoint {} (err kind: {:?})",
+                    cp_name,
+                    err.kind()
+                ),
+                io_err: err,
+            }),
+        }
+    }
+
     pub fn scratchpad_to_checkpoint(
         &self,
         layout: CheckpointLayout<RwPolicy>,
@@ -60,7 +60,13 @@ fn criterion_make_checkpoint(c: &mut Criterion) {
             },
             // Do the actual measurement
             |data| {
-                let _node_state = make_checkpoint(&data.state, data.height, &data.layout);
+                let _node_state = make_checkpoint(
+                    &data.state,
+                    data.height,
+                    &data.layout,
+                    None,
+                    &Default::default(),
+                );
             },
         )
     });
@@ -1,4 +1,6 @@
+use crate::tiered_storage::{remove_cold_pages_link, update_cold_pages, HeapPageAccesses};
 use crate::CheckpointError;
+use ic_config::state_manager::TieredStorageConfig;
 use ic_cow_state::{CowMemoryManager, CowMemoryManagerImpl, MappedState};
 use ic_registry_subnet_type::SubnetType;
 use ic_replicated_state::{
@@ -6,7 +8,7 @@ use ic_replicated_state::{
 };
 use ic_replicated_state::{SchedulerState, SystemState};
 use ic_state_layout::{
-    CanisterStateBits, CheckpointLayout, ExecutionStateBits, ReadPolicy, ReadWritePolicy,
+    CanisterStateBits, CheckpointLayout, ExecutionStateBits, ReadPolicy, ReadWritePolicy, RwPolicy,
     StateLayout,
 };
 use ic_types::Height;
@@ -22,11 +24,50 @@ use std::sync::Arc;
 /// If the result is `Ok`, the returned state is "rebased" to use
 /// files from the newly created checkpoint. If the result is `Err`,
 /// the returned state is exactly the one that was passed as argument.
+///
+/// The heap pages of each canister in `page_accesses` were accessed since
+/// the last checkpoint; the other pages move to cold storage over time if
+/// `tiered_storage` is configured.
 pub fn make_checkpoint(
     state: &ReplicatedState,
     height: Height,
     layout: &StateLayout,
+    tiered_storage: Option<&TieredStorageConfig>,
+    page_accesses: &HeapPageAccesses,
 ) -> Result<ReplicatedState, CheckpointError> {
+    let tip = write_tip(state, layout, tiered_storage, page_accesses)?;
+    let cp = layout.tip_to_checkpoint(tip, height)?;
+    let state = load_checkpoint(&cp, state.metadata.own_subnet_type)?;
+
+    Ok(state)
+}
+
+/// Dumps the node state into the tip and snapshots the tip into a scratchpad
+/// for the checkpoint at `height`, without syncing any files.  This only
+/// takes a few milliseconds on filesystems supporting reflinks; elsewhere,
+/// the files are copied.
+///
+/// The returned scratchpad becomes the checkpoint once it is promoted with
+/// `StateLayout::scratchpad_to_checkpoint`.  Its files are never modified, so
+/// the state can be loaded from it right away with `load_checkpoint`.
+pub fn snapshot_tip(
+    state: &ReplicatedState,
+    height: Height,
+    layout: &StateLayout,
+    tiered_storage: Option<&TieredStorageConfig>,
+    page_accesses: &HeapPageAccesses,
+) -> Result<CheckpointLayout<RwPolicy>, CheckpointError> {
+    let tip = write_tip(state, layout, tiered_storage, page_accesses)?;
+    Ok(layout.tip_to_scratchpad(tip, height)?)
+}
+
+/// Writes the given state to the tip and returns the layout of the tip.
+fn write_tip(
+    state: &ReplicatedState,
+    layout: &StateLayout,
+    tiered_storage: Option<&TieredStorageConfig>,
+    page_accesses: &HeapPageAccesses,
+) -> Result<CheckpointLayout<RwPolicy>, CheckpointError> {
     let tip = layout.tip().map_err(CheckpointError::from)?;
 
     tip.system_metadata()
@@ -54,6 +95,13 @@ pub fn make_checkpoint(
                 execution_state
                     .page_map
                     .persist_and_sync_delta(&canister_layout.vmemory_0())?;
+                update_cold_pages(
+                    tiered_storage,
+                    &canister_layout.vmemory_0(),
+                    page_accesses
+                        .get(&canister_state.canister_id())
+                        .unwrap_or(&Default::default()),
+                )?;
 
                 execution_state.cow_mem_mgr.checkpoint();
 
@@ -102,10 +150,7 @@ pub fn make_checkpoint(
         )?;
     }
 
-    let cp = layout.tip_to_checkpoint(tip, height)?;
-    let state = load_checkpoint(&cp, state.metadata.own_subnet_type)?;
-
-    Ok(state)
+    Ok(tip)
 }
 
 /// loads the node state heighted with `height` using the specified
@@ -274,6 +319,7 @@ pub fn handle_disk_format_changes<P: ReadWritePolicy>(
                     message: "Failed to overwrite file".to_string(),
                     io_err: err.to_string(),
                 })?;
+                remove_cold_pages_link(memory_path)?;
 
                 CowMemoryManagerImpl::purge(&canister_base);
 
@@ -356,7 +402,7 @@ mod tests {
         height: Height,
         layout: &StateLayout,
     ) -> ReplicatedState {
-        make_checkpoint(state, height, &layout)
+        make_checkpoint(state, height, &layout, None, &Default::default())
             .unwrap_or_else(|err| panic!("Expected make_checkpoint to succeed, got {:?}", err))
     }
 
@@ -446,7 +492,7 @@ mod tests {
             // Scratchpad directory is "tmp/scatchpad_{hex(height)}"
             let expected_scratchpad_dir = root.join("tmp").join("scratchpad_000000000000002a");
 
-            match make_checkpoint(&state, HEIGHT, &layout) {
+            match make_checkpoint(&state, HEIGHT, &layout, None, &Default::default()) {
                 Err(_) => assert!(
                     !expected_scratchpad_dir.exists(),
                     "Expected incomplete scratchpad to be deleted"
@@ -610,7 +656,7 @@ mod tests {
                 NumSeconds::from(100_000),
             ));
 
-            let result = make_checkpoint(&state, HEIGHT, &layout);
+            let result = make_checkpoint(&state, HEIGHT, &layout, None, &Default::default());
 
             assert!(
                 result.is_err()
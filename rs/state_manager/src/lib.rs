@@ -3,22 +3,26 @@
 pub mod checkpoint;
 pub mod labeled_tree_visitor;
 pub mod manifest;
+mod scrubber;
 pub mod state_sync;
 pub mod stream_encoding;
+mod tiered_storage;
 pub mod tree_diff;
 pub mod tree_hash;
+mod witness_cache;
 
-use crossbeam_channel::{unbounded, Sender};
+use crossbeam_channel::{bounded, unbounded, Sender};
 use ic_canonical_state::{
     hash_tree::{hash_lazy_tree, HashTree},
     lazy_tree::{materialize::materialize_partial, LazyTree},
 };
-use ic_config::state_manager::Config;
+use ic_config::state_manager::{Config, StatePruningPolicy, TieredStorageConfig};
 use ic_cow_state::CowMemoryManager;
 use ic_crypto_tree_hash::{recompute_digest, Digest, LabeledTree, MixedHashTree, Witness};
 use ic_interfaces::{
     certification::Verifier,
     certified_stream_store::{CertifiedStreamStore, DecodeStreamError, EncodeStreamError},
+    consensus_pool::HeightWatch,
     state_manager::{
         CertificationMask, CertificationScope, Labeled, StateManager, StateManagerError,
         StateManagerResult, StateReader, CERT_CERTIFIED, CERT_UNCERTIFIED,
@@ -28,9 +32,10 @@ use ic_logger::{debug, error, fatal, info, warn, ReplicaLogger};
 use ic_metrics::{buckets::decimal_buckets, MetricsRegistry};
 use ic_protobuf::proxy::{ProtoProxy, ProxyDecodeError};
 use ic_protobuf::{messaging::xnet::v1, state::v1 as pb};
+use ic_registry_routing_table::CanisterIdRange;
 use ic_registry_subnet_type::SubnetType;
 use ic_replicated_state::{page_map::PersistenceError, ReplicatedState};
-use ic_state_layout::{error::LayoutError, StateLayout};
+use ic_state_layout::{error::LayoutError, CheckpointLayout, RwPolicy, StateLayout};
 use ic_types::{
     artifact::StateSyncArtifactId,
     chunkable::Chunkable,
@@ -42,8 +47,10 @@ use ic_types::{
     CryptoHashOfPartialState, CryptoHashOfState, ExecutionRound, Height, RegistryVersion, SubnetId,
 };
 use ic_utils::{ic_features::*, thread::JoinOnDrop};
-use prometheus::{HistogramVec, IntCounterVec, IntGauge};
+use manifest::{DirtyFile, DirtyPages};
+use prometheus::{HistogramVec, IntCounter, IntCounterVec, IntGauge};
 use prost::Message;
+use state_sync::chunkable::{remove_stale_scratchpads, StateSyncRefs};
 use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use std::convert::{From, TryFrom};
 use std::fmt;
@@ -51,9 +58,12 @@ use std::fs::OpenOptions;
 use std::path::{Path, PathBuf};
 use std::sync::{
     atomic::{AtomicBool, AtomicU64, Ordering},
-    Arc,
+    Arc, Mutex,
 };
 use std::time::Instant;
+use tiered_storage::ColdFileCleaner;
+use tokio::sync::watch;
+use witness_cache::WitnessCache;
 
 #[derive(Clone)]
 pub struct StateManagerMetrics {
@@ -69,6 +79,9 @@ pub struct StateManagerMetrics {
     state_sync_size: IntCounterVec,
     state_sync_duration: HistogramVec,
     state_size: IntGauge,
+    scrubbed_chunks: IntCounter,
+    corrupted_chunks: IntCounter,
+    witness_cache_lookups: IntCounterVec,
 }
 
 // Note [Metrics preallocation]
@@ -141,12 +154,12 @@ impl StateManagerMetrics {
 
         let state_sync_size = metrics_registry.int_counter_vec(
             "state_sync_size_bytes_total",
-            "Size of chunks synchronized by different operations ('fetch', 'copy', 'preallocate') during all the state sync in bytes.",
+            "Size of chunks synchronized by different operations ('fetch', 'copy', 'resume', 'preallocate') during all the state sync in bytes.",
             &["op"],
         );
 
         // Note [Metrics preallocation]
-        for op in &["fetch", "copy"] {
+        for op in &["fetch", "copy", "resume"] {
             state_sync_size.with_label_values(&[*op]);
         }
 
@@ -168,6 +181,27 @@ impl StateManagerMetrics {
             state_sync_duration.with_label_values(&[*status]);
         }
 
+        let scrubbed_chunks = metrics_registry.int_counter(
+            "state_manager_scrubbed_chunks_total",
+            "Total number of checkpoint chunks verified by the scrubber.",
+        );
+
+        let corrupted_chunks = metrics_registry.int_counter(
+            "state_manager_corrupted_chunks_total",
+            "Total number of checkpoint chunks the scrubber found not matching the manifest.",
+        );
+
+        let witness_cache_lookups = metrics_registry.int_counter_vec(
+            "state_manager_witness_cache_lookups_total",
+            "Total number of lookups of witnesses of certified reads in the witness cache, by status ('hit', 'miss').",
+            &["status"],
+        );
+
+        // Note [Metrics preallocation]
+        for status in &["hit", "miss"] {
+            witness_cache_lookups.with_label_values(&[*status]);
+        }
+
         Self {
             state_manager_error_count,
             checkpoint_op_duration,
@@ -181,6 +215,9 @@ impl StateManagerMetrics {
             state_sync_size,
             state_sync_duration,
             state_size,
+            scrubbed_chunks,
+            corrupted_chunks,
+            witness_cache_lookups,
         }
     }
 }
@@ -189,6 +226,7 @@ impl StateManagerMetrics {
 struct PersistedStatesMetadata {
     by_height: StatesMetadata,
     oldest_required_state: Height,
+    pinned_heights: BTreeSet<Height>,
 }
 
 type StatesMetadata = BTreeMap<Height, StateMetadata>;
@@ -204,12 +242,17 @@ struct StateMetadata {
     // None before the values are computed.
     root_hash: Option<CryptoHashOfState>,
     manifest: Option<Manifest>,
+    // Set by the scrubber if the checkpoint on disk doesn't match the
+    // manifest. Persisted, so that a corrupted checkpoint is not served
+    // again after a restart.
+    corrupted: bool,
 }
 
 impl From<&StateMetadata> for pb::StateMetadata {
     fn from(metadata: &StateMetadata) -> Self {
         Self {
             manifest: metadata.manifest.as_ref().map(|m| m.clone().into()),
+            corrupted: metadata.corrupted,
         }
     }
 }
@@ -229,6 +272,7 @@ impl TryFrom<pb::StateMetadata> for StateMetadata {
                     checkpoint_ref: None,
                     manifest: Some(manifest),
                     root_hash: Some(root_hash),
+                    corrupted: proto.corrupted,
                 })
             }
         }
@@ -345,6 +389,64 @@ impl fmt::Debug for CheckpointRef {
 
 struct ComputeManifestRequest {
     checkpoint_ref: CheckpointRef,
+    /// The pages of the checkpoint files written since they were copied from
+    /// an earlier checkpoint, see `compute_manifest_incremental`.
+    dirty_pages: DirtyPages,
+}
+
+/// A request to promote a snapshot of the tip to a checkpoint.
+struct CheckpointRequest {
+    snapshot: CheckpointLayout<RwPolicy>,
+    height: Height,
+    /// The reference to pass on to the state hasher once the checkpoint
+    /// exists, if the checkpoint is registered in the states metadata.
+    checkpoint_ref: Option<CheckpointRef>,
+    /// The dirty pages to pass on to the state hasher.
+    dirty_pages: DirtyPages,
+}
+
+/// Tracks the snapshots of the tip that wait for promotion to checkpoints and
+/// coordinates their promotion with divergence reports, so that no
+/// checkpoint at or above a diverged height appears on disk after the
+/// divergence was reported.
+#[derive(Default)]
+struct CheckpointPromotion {
+    /// The heights of the snapshots that were not promoted yet.
+    pending_heights: Mutex<BTreeSet<Height>>,
+    /// The height at which the replica diverged, if reported.  The
+    /// checkpointer holds the lock while it promotes a snapshot.
+    diverged_height: Mutex<Option<Height>>,
+}
+
+impl CheckpointPromotion {
+    fn add_pending(&self, height: Height) {
+        self.pending_heights.lock().unwrap().insert(height);
+    }
+
+    /// Returns the height of the oldest snapshot that was not promoted yet.
+    fn oldest_pending(&self) -> Option<Height> {
+        self.pending_heights.lock().unwrap().iter().next().cloned()
+    }
+
+    /// Runs `promote` unless the state diverged at or below `height`, in
+    /// which case `None` is returned.  Either way, the snapshot at `height`
+    /// is no longer pending afterwards.
+    fn promote_unless_diverged<R>(&self, height: Height, promote: impl FnOnce() -> R) -> Option<R> {
+        let diverged_height = self.diverged_height.lock().unwrap();
+        let result = match *diverged_height {
+            Some(diverged) if diverged <= height => None,
+            _ => Some(promote()),
+        };
+        self.pending_heights.lock().unwrap().remove(&height);
+        result
+    }
+
+    /// Records the divergence at `height`.  Waits for the promotion in
+    /// progress, if any, but not for the queued ones: these are discarded.
+    fn mark_diverged(&self, height: Height) {
+        let mut diverged_height = self.diverged_height.lock().unwrap();
+        *diverged_height = Some(diverged_height.map_or(height, |h| h.min(height)));
+    }
 }
 
 /// SharedState is mutable state that can be accessed from multiple threads.
@@ -376,6 +478,14 @@ impl SharedState {
 // the cost of deallocation over a longer period of time, and avoid long pauses.
 type Deallocation = Box<dyn std::any::Any + Send + 'static>;
 
+/// A certified state whose witnesses of the prefetched paths are computed
+/// in the background.
+struct WitnessPrefetchRequest {
+    state: Arc<ReplicatedState>,
+    height: Height,
+    hash_tree: Arc<HashTree>,
+}
+
 // We will not use the deallocation thread when the number of pending
 // deallocation objects goes above the threshold.
 const DEALLOCATION_BACKLOG_THRESHOLD: usize = 500;
@@ -383,6 +493,14 @@ const DEALLOCATION_BACKLOG_THRESHOLD: usize = 500;
 /// The number of diverged states to keep before we start deleting the old ones.
 const MAX_DIVERGED_STATES_TO_KEEP: usize = 2;
 
+/// One of the two states produced by `StateManagerImpl::split_state`.
+#[derive(Clone, Debug)]
+pub struct SplitState {
+    pub state: ReplicatedState,
+    /// The hash of the partial canonical state of `state`.
+    pub hash: CryptoHashOfPartialState,
+}
+
 pub struct StateManagerImpl {
     log: ReplicaLogger,
     metrics: StateManagerMetrics,
@@ -392,15 +510,40 @@ pub struct StateManagerImpl {
     own_subnet_id: SubnetId,
     own_subnet_type: SubnetType,
     compute_manifest_request_sender: Sender<ComputeManifestRequest>,
+    checkpoint_request_sender: Sender<CheckpointRequest>,
+    checkpoint_promotion: Arc<CheckpointPromotion>,
     deallocation_sender: Sender<Deallocation>,
     // Cached latest state height.  We cache it separately because it's
     // requested quite often and this causes high contention on the lock.
     latest_state_height: AtomicU64,
     latest_certified_height: AtomicU64,
     /// The last height passed to remove_states_below()
-    requested_to_remove_states_below: AtomicU64,
+    requested_to_remove_states_below: Arc<AtomicU64>,
+    // Whether `remove_states_below` kept states because of a snapshot that
+    // was not promoted yet.
+    removal_deferred: AtomicBool,
+    tiered_storage: Option<TieredStorageConfig>,
+    cold_file_cleaner: Option<Arc<ColdFileCleaner>>,
+    pruning_policy: StatePruningPolicy,
+    // Heights whose states are not removed until they are unpinned.
+    pinned_heights: Arc<Mutex<BTreeSet<Height>>>,
+    // Heights of the state syncs in progress.
+    state_sync_refs: StateSyncRefs,
+    // Notified whenever a newer state is certified.
+    certified_height_watch: HeightWatch,
+    // Witnesses of certified reads at the latest certified height.
+    witness_cache: Arc<Mutex<WitnessCache>>,
+    witness_prefetch_sender: Sender<WitnessPrefetchRequest>,
+    // Declared before the state hasher handle: the checkpointer sends
+    // requests to the state hasher, so it must stop first.
+    _checkpointer_handle: JoinOnDrop<()>,
     _state_hasher_handle: JoinOnDrop<()>,
     _deallocation_handle: JoinOnDrop<()>,
+    _witness_prefetcher_handle: JoinOnDrop<()>,
+    // Dropping the sender stops the scrubber, so it's declared before the
+    // scrubber handle.
+    _scrubber_stop_sender: Sender<()>,
+    _scrubber_handle: Option<JoinOnDrop<()>>,
 }
 
 fn load_checkpoint(
@@ -487,6 +630,61 @@ fn cleanup_diverged_states(log: &ReplicaLogger, layout: &StateLayout) {
     }
 }
 
+/// Writes the given states metadata, the oldest required state and the pinned
+/// heights to disk. The caller must hold the write lock of the shared state,
+/// as all threads write the metadata via the same temporary file.
+fn persist_metadata_or_die(
+    log: &ReplicaLogger,
+    metrics: &StateManagerMetrics,
+    state_layout: &StateLayout,
+    metadata: &StatesMetadata,
+    requested_to_remove_states_below: &AtomicU64,
+    pinned_heights: &Mutex<BTreeSet<Height>>,
+) {
+    use std::io::Write;
+
+    let started_at = Instant::now();
+    let tmp = state_layout
+        .tmp()
+        .unwrap_or_else(|err| fatal!(log, "Failed to create temporary directory: {}", err))
+        .join("tmp_states_metadata.pb");
+
+    ic_utils::fs::write_atomically_using_tmp_file(state_layout.states_metadata(), &tmp, |w| {
+        let mut pb_meta = pb::StatesMetadata::default();
+        for (h, m) in metadata.iter() {
+            pb_meta.by_height.insert(h.get(), m.into());
+        }
+        pb_meta.oldest_required_state = requested_to_remove_states_below.load(Ordering::Relaxed);
+        pb_meta.pinned_heights = pinned_heights
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|h| h.get())
+            .collect();
+
+        let mut buf = vec![];
+        pb_meta.encode(&mut buf).unwrap_or_else(|e| {
+            fatal!(log, "Failed to encode states metadata to protobuf: {}", e);
+        });
+        w.write_all(&buf[..])
+    })
+    .unwrap_or_else(|err| {
+        fatal!(
+            log,
+            "Failed to serialize states metadata to {}: {}",
+            tmp.display(),
+            err
+        )
+    });
+    let elapsed = started_at.elapsed();
+    metrics
+        .checkpoint_op_duration
+        .with_label_values(&["persist_meta"])
+        .observe(elapsed.as_secs_f64());
+
+    debug!(log, "Persisted states metadata in {:?}", elapsed);
+}
+
 fn report_last_diverged_checkpoint(
     log: &ReplicaLogger,
     metrics: &StateManagerMetrics,
@@ -567,6 +765,46 @@ fn copy_page_maps(dst: &mut ReplicatedState, src: &ReplicatedState) {
     }
 }
 
+/// Returns the heights below the requested one whose states survive
+/// `remove_states_below`: the latest checkpoint at or below the requested
+/// height, because the state manager loads it when restarting, the
+/// checkpoints kept by the pruning policy, and the pinned heights.
+///
+/// `checkpoint_heights` must be sorted in ascending order.
+fn heights_to_keep(
+    policy: &StatePruningPolicy,
+    checkpoint_heights: &[Height],
+    requested_height: Height,
+    pinned_heights: &BTreeSet<Height>,
+) -> BTreeSet<Height> {
+    let mut heights = pinned_heights.clone();
+    heights.extend(
+        checkpoint_heights
+            .iter()
+            .filter(|h| **h <= requested_height)
+            .max()
+            .cloned(),
+    );
+    heights.extend(
+        checkpoint_heights
+            .iter()
+            .rev()
+            .take(policy.recent_checkpoints)
+            .cloned(),
+    );
+    if let Some(interval_length) = policy.interval_length.filter(|l| *l > 0) {
+        let mut last_interval = None;
+        for h in checkpoint_heights {
+            let interval = h.get() / interval_length;
+            if last_interval != Some(interval) {
+                heights.insert(*h);
+                last_interval = Some(interval);
+            }
+        }
+    }
+    heights
+}
+
 impl StateManagerImpl {
     /// Height for the initial default state.
     const INITIAL_STATE_HEIGHT: Height = Height::new(0);
@@ -587,10 +825,33 @@ impl StateManagerImpl {
             config.state_root().display()
         );
         let state_layout = StateLayout::new(log.clone(), config.state_root());
+        if let Some(tiered_storage) = config.tiered_storage() {
+            info!(
+                log,
+                "Using path '{}' to store cold heap pages",
+                tiered_storage.cold_storage_root.display()
+            );
+            std::fs::create_dir_all(&tiered_storage.cold_storage_root).unwrap_or_else(|err| {
+                fatal!(
+                    &log,
+                    "Failed to create cold storage root {}: {}",
+                    tiered_storage.cold_storage_root.display(),
+                    err
+                )
+            });
+        }
+        let cold_file_cleaner = config.tiered_storage().map(|tiered_storage| {
+            Arc::new(ColdFileCleaner::new(
+                log.clone(),
+                state_layout.raw_path().to_path_buf(),
+                tiered_storage,
+            ))
+        });
 
         let PersistedStatesMetadata {
             by_height: mut states_metadata,
             oldest_required_state,
+            mut pinned_heights,
         } = Self::load_metadata(&log, state_layout.states_metadata().as_path());
 
         let mut checkpoint_heights = state_layout
@@ -638,6 +899,12 @@ impl StateManagerImpl {
             }
         }
 
+        // The metadata may list checkpoints that were not yet promoted when the
+        // replica stopped.
+        states_metadata.retain(|height, _| checkpoint_heights.contains(height));
+        // Only the checkpoints of pinned heights survive a restart.
+        pinned_heights.retain(|height| checkpoint_heights.contains(height));
+
         state_layout
             .cleanup_tip()
             .unwrap_or_else(|err| fatal!(&log, "Failed to cleanup old tip {:?}", err));
@@ -741,6 +1008,37 @@ impl StateManagerImpl {
                 .expect("failed to spawn background state hasher"),
         );
 
+        // At most one checkpoint waits for promotion while another one is
+        // promoted; creating more checkpoints blocks until one completes.
+        let (checkpoint_request_sender, checkpoint_request_receiver) = bounded(1);
+        let checkpoint_promotion = Arc::new(CheckpointPromotion::default());
+        let _checkpointer_handle = JoinOnDrop::new(
+            std::thread::Builder::new()
+                .name("StateCheckpointer".to_string())
+                .spawn({
+                    let log = log.clone();
+                    let metrics = metrics.clone();
+                    let state_layout = state_layout.clone();
+                    let cold_file_cleaner = cold_file_cleaner.clone();
+                    let compute_manifest_request_sender = compute_manifest_request_sender.clone();
+                    let checkpoint_promotion = Arc::clone(&checkpoint_promotion);
+                    move || {
+                        while let Ok(req) = checkpoint_request_receiver.recv() {
+                            Self::handle_checkpoint_request(
+                                &metrics,
+                                &log,
+                                &state_layout,
+                                cold_file_cleaner.as_deref(),
+                                &compute_manifest_request_sender,
+                                &checkpoint_promotion,
+                                req,
+                            );
+                        }
+                    }
+                })
+                .expect("failed to spawn background checkpointer"),
+        );
+
         let (deallocation_sender, deallocation_receiver) = unbounded();
         let _deallocation_handle = JoinOnDrop::new(
             std::thread::Builder::new()
@@ -757,6 +1055,73 @@ impl StateManagerImpl {
                 .expect("failed to spawn background deallocation thread"),
         );
 
+        let witness_cache = Arc::new(Mutex::new(WitnessCache::default()));
+        let (witness_prefetch_sender, witness_prefetch_receiver) = unbounded();
+        let _witness_prefetcher_handle = JoinOnDrop::new(
+            std::thread::Builder::new()
+                .name("WitnessPrefetcher".to_string())
+                .spawn({
+                    let witness_cache = Arc::clone(&witness_cache);
+                    move || {
+                        while let Ok(mut req) = witness_prefetch_receiver.recv() {
+                            // Only the latest certified state is worth
+                            // prefetching.
+                            while let Ok(newer) = witness_prefetch_receiver.try_recv() {
+                                req = newer;
+                            }
+                            Self::prefetch_witnesses(&witness_cache, req);
+                        }
+                    }
+                })
+                .expect("failed to spawn background witness prefetcher"),
+        );
+
+        let requested_to_remove_states_below =
+            Arc::new(AtomicU64::new(oldest_required_state.get()));
+        let pinned_heights = Arc::new(Mutex::new(pinned_heights));
+
+        let (_scrubber_stop_sender, scrubber_stop_receiver) = bounded(0);
+        let _scrubber_handle = if config.scrubber().enabled {
+            Some(JoinOnDrop::new(
+                std::thread::Builder::new()
+                    .name("StateScrubber".to_string())
+                    .spawn({
+                        let log = log.clone();
+                        let metrics = metrics.clone();
+                        let state_layout = state_layout.clone();
+                        let states = Arc::clone(&states);
+                        let scrubber_config = config.scrubber().clone();
+                        let requested_to_remove_states_below =
+                            Arc::clone(&requested_to_remove_states_below);
+                        let pinned_heights = Arc::clone(&pinned_heights);
+                        move || {
+                            let persist_metadata = |metadata: &StatesMetadata| {
+                                persist_metadata_or_die(
+                                    &log,
+                                    &metrics,
+                                    &state_layout,
+                                    metadata,
+                                    &requested_to_remove_states_below,
+                                    &pinned_heights,
+                                )
+                            };
+                            scrubber::run(
+                                &log,
+                                &metrics,
+                                &state_layout,
+                                &states,
+                                &persist_metadata,
+                                &scrubber_config,
+                                &scrubber_stop_receiver,
+                            )
+                        }
+                    })
+                    .expect("failed to spawn background state scrubber"),
+            ))
+        } else {
+            None
+        };
+
         for req in compute_manifest_requests {
             compute_manifest_request_sender
                 .send(req)
@@ -774,12 +1139,27 @@ impl StateManagerImpl {
             own_subnet_id,
             own_subnet_type,
             compute_manifest_request_sender,
+            checkpoint_request_sender,
+            checkpoint_promotion,
             deallocation_sender,
             latest_state_height,
             latest_certified_height,
-            requested_to_remove_states_below: AtomicU64::new(oldest_required_state.get()),
+            requested_to_remove_states_below,
+            removal_deferred: AtomicBool::new(false),
+            tiered_storage: config.tiered_storage().cloned(),
+            cold_file_cleaner,
+            pruning_policy: config.pruning_policy().clone(),
+            pinned_heights,
+            state_sync_refs: StateSyncRefs::default(),
+            certified_height_watch: HeightWatch::new(Self::INITIAL_STATE_HEIGHT),
+            witness_cache,
+            witness_prefetch_sender,
+            _checkpointer_handle,
             _state_hasher_handle,
             _deallocation_handle,
+            _witness_prefetcher_handle,
+            _scrubber_stop_sender,
+            _scrubber_handle,
         }
     }
 
@@ -789,6 +1169,137 @@ impl StateManagerImpl {
         &self.state_layout
     }
 
+    /// Pins the state at the given height, e.g. while it is being backed up.
+    /// Neither the in-memory state nor the checkpoint at a pinned height is
+    /// removed by `remove_states_below`. Pins are persisted with the states
+    /// metadata: the checkpoints at pinned heights stay pinned across
+    /// restarts, the in-memory states are gone.
+    ///
+    /// Returns `Err(StateNotFound)` if there is neither a state nor a
+    /// checkpoint at `height`; and `Err(TooManyPins)` if
+    /// `MAX_PINNED_HEIGHTS` heights are pinned already.
+    pub fn pin_height(&self, height: Height) -> Result<(), PinHeightError> {
+        let states = self.states.write();
+        {
+            let mut pinned_heights = self.pinned_heights.lock().unwrap();
+            if pinned_heights.contains(&height) {
+                return Ok(());
+            }
+            if !states.snapshots.iter().any(|s| s.height == height)
+                && !states.states_metadata.contains_key(&height)
+            {
+                return Err(PinHeightError::StateNotFound(height));
+            }
+            if pinned_heights.len() >= MAX_PINNED_HEIGHTS {
+                return Err(PinHeightError::TooManyPins(MAX_PINNED_HEIGHTS));
+            }
+            pinned_heights.insert(height);
+        }
+        self.persist_metadata_or_die(&states.states_metadata);
+        Ok(())
+    }
+
+    /// Unpins the state at the given height and returns true if it was
+    /// pinned. The state is removed by the next call of `remove_states_below`
+    /// unless it is still needed.
+    pub fn unpin_height(&self, height: Height) -> bool {
+        let states = self.states.write();
+        let unpinned = self.pinned_heights.lock().unwrap().remove(&height);
+        if unpinned {
+            self.persist_metadata_or_die(&states.states_metadata);
+        }
+        unpinned
+    }
+
+    /// Returns the pinned heights in ascending order.
+    pub fn pinned_heights(&self) -> Vec<Height> {
+        self.pinned_heights
+            .lock()
+            .unwrap()
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the heights of the checkpoints the scrubber found corrupted on
+    /// disk, in ascending order.
+    pub fn corrupted_checkpoint_heights(&self) -> Vec<Height> {
+        self.states
+            .read()
+            .states_metadata
+            .iter()
+            .filter(|(_, metadata)| metadata.corrupted)
+            .map(|(height, _)| *height)
+            .collect()
+    }
+
+    /// Splits the checkpoint at the given height along `canister_id_range`,
+    /// see `ReplicatedState::split`. Returns the state of this subnet without
+    /// the canisters in the range and the state of `new_subnet_id` hosting
+    /// them, and persists each of them as the checkpoint at `height` of a new
+    /// state layout, rooted at `own_state_root` and `new_state_root`
+    /// respectively. The replicas of both subnets start from these layouts.
+    ///
+    /// The migration of the range to `new_subnet_id` must be recorded in the
+    /// registry, so that message routing reroutes the messages in flight.
+    pub fn split_state(
+        &self,
+        height: Height,
+        canister_id_range: &CanisterIdRange,
+        new_subnet_id: SubnetId,
+        own_state_root: PathBuf,
+        new_state_root: PathBuf,
+    ) -> Result<(SplitState, SplitState), CheckpointError> {
+        let state = load_checkpoint(
+            &self.state_layout,
+            height,
+            &self.metrics,
+            self.own_subnet_type,
+        )?;
+        let (state, new_state) = state.split(canister_id_range, new_subnet_id);
+
+        let own = self.persist_split_state(height, state, own_state_root)?;
+        let new = self.persist_split_state(height, new_state, new_state_root)?;
+        Ok((own, new))
+    }
+
+    /// Writes one of the states produced by a split as the checkpoint at
+    /// `height` of the state layout rooted at `root`. The canister files are
+    /// copied from the checkpoint of this replica at `height`, except for the
+    /// canisters that moved to the other subnet.
+    fn persist_split_state(
+        &self,
+        height: Height,
+        state: ReplicatedState,
+        root: PathBuf,
+    ) -> Result<SplitState, CheckpointError> {
+        let scratchpad = self.state_layout.checkpoint_to_scratchpad(height)?;
+        let scratchpad_path = scratchpad.raw_path().to_path_buf();
+        let result = (|| -> Result<(), CheckpointError> {
+            for canister_id in scratchpad.canister_ids()? {
+                if state.canister_state(&canister_id).is_none() {
+                    scratchpad.canister(&canister_id)?.mark_deleted()?;
+                }
+            }
+            scratchpad
+                .system_metadata()
+                .serialize(state.system_metadata().into())?;
+            scratchpad
+                .subnet_queues()
+                .serialize((&state.subnet_queues).into())?;
+            StateLayout::new(self.log.clone(), root).tip_to_checkpoint(scratchpad, height)?;
+            Ok(())
+        })();
+        let _ = std::fs::remove_dir_all(&scratchpad_path);
+        result?;
+
+        let hash_tree = hash_lazy_tree(&LazyTree::from(&state));
+        Ok(SplitState {
+            state,
+            hash: CryptoHashOfPartialState::from(crypto_hash_of_tree(&hash_tree)),
+        })
+    }
+
     /// Returns requested state as a Chunkable artifact for StateSync.
     pub fn create_chunkable_state(
         &self,
@@ -799,9 +1310,10 @@ impl StateManagerImpl {
             id.height,
             id.hash.clone(),
             self.state_layout.clone(),
-            self.latest_manifest(),
+            self.local_manifests(),
             self.metrics.clone(),
             self.own_subnet_type,
+            self.state_sync_refs.clone(),
         ))
     }
 
@@ -868,6 +1380,11 @@ impl StateManagerImpl {
                 PersistedStatesMetadata {
                     by_height: map,
                     oldest_required_state: Height::new(pb_meta.oldest_required_state),
+                    pinned_heights: pb_meta
+                        .pinned_heights
+                        .into_iter()
+                        .map(Height::new)
+                        .collect(),
                 }
             }
             Err(err) => {
@@ -883,53 +1400,80 @@ impl StateManagerImpl {
     }
 
     fn persist_metadata_or_die(&self, metadata: &StatesMetadata) {
-        use std::io::Write;
-
-        let started_at = Instant::now();
-        let tmp = self
-            .state_layout
-            .tmp()
-            .unwrap_or_else(|err| fatal!(self.log, "Failed to create temporary directory: {}", err))
-            .join("tmp_states_metadata.pb");
-
-        ic_utils::fs::write_atomically_using_tmp_file(
-            self.state_layout.states_metadata(),
-            &tmp,
-            |w| {
-                let mut pb_meta = pb::StatesMetadata::default();
-                for (h, m) in metadata.iter() {
-                    pb_meta.by_height.insert(h.get(), m.into());
-                }
-                pb_meta.oldest_required_state = self
-                    .requested_to_remove_states_below
-                    .load(Ordering::Relaxed);
-
-                let mut buf = vec![];
-                pb_meta.encode(&mut buf).unwrap_or_else(|e| {
-                    fatal!(
-                        self.log,
-                        "Failed to encode states metadata to protobuf: {}",
-                        e
-                    );
-                });
-                w.write_all(&buf[..])
-            },
+        persist_metadata_or_die(
+            &self.log,
+            &self.metrics,
+            &self.state_layout,
+            metadata,
+            &self.requested_to_remove_states_below,
+            &self.pinned_heights,
         )
-        .unwrap_or_else(|err| {
-            fatal!(
-                self.log,
-                "Failed to serialize states metadata to {}: {}",
-                tmp.display(),
-                err
-            )
+    }
+
+    /// Syncs the snapshot of the tip in the given request and promotes it to
+    /// a checkpoint, then requests the computation of its manifest.  The
+    /// snapshot is discarded if the state diverged at or below its height.
+    fn handle_checkpoint_request(
+        metrics: &StateManagerMetrics,
+        log: &ReplicaLogger,
+        state_layout: &StateLayout,
+        cold_file_cleaner: Option<&ColdFileCleaner>,
+        compute_manifest_request_sender: &Sender<ComputeManifestRequest>,
+        checkpoint_promotion: &CheckpointPromotion,
+        req: CheckpointRequest,
+    ) {
+        let start = Instant::now();
+        let snapshot_path = req.snapshot.raw_path().to_path_buf();
+        let height = req.height;
+        let promotion = checkpoint_promotion.promote_unless_diverged(height, || {
+            state_layout.scratchpad_to_checkpoint(req.snapshot, height)
         });
-        let elapsed = started_at.elapsed();
-        self.metrics
+        let discard_reason = match promotion {
+            None => Some("the state diverged"),
+            Some(Ok(_)) => None,
+            // The checkpoint was fetched via state sync in the meantime.
+            Some(Err(LayoutError::AlreadyExists(_))) => Some("the checkpoint already exists"),
+            Some(Err(err)) => fatal!(
+                log,
+                "Failed to promote the tip snapshot to checkpoint @{}: {:?}",
+                height,
+                err
+            ),
+        };
+        if let Some(discard_reason) = discard_reason {
+            warn!(
+                log,
+                "Discarding the snapshot of the tip @{} because {}", height, discard_reason
+            );
+            if let Err(err) = std::fs::remove_dir_all(&snapshot_path) {
+                warn!(
+                    log,
+                    "Failed to remove tip snapshot {}: {}",
+                    snapshot_path.display(),
+                    err
+                );
+            }
+            return;
+        }
+        let elapsed = start.elapsed();
+        info!(log, "Persisted checkpoint @{} in {:?}", height, elapsed);
+        metrics
             .checkpoint_op_duration
-            .with_label_values(&["persist_meta"])
+            .with_label_values(&["finalize"])
             .observe(elapsed.as_secs_f64());
 
-        debug!(self.log, "Persisted states metadata in {:?}", elapsed);
+        if let Some(cold_file_cleaner) = cold_file_cleaner {
+            cold_file_cleaner.request_cleanup();
+        }
+
+        if let Some(checkpoint_ref) = req.checkpoint_ref {
+            compute_manifest_request_sender
+                .send(ComputeManifestRequest {
+                    checkpoint_ref,
+                    dirty_pages: req.dirty_pages,
+                })
+                .expect("failed to send ComputeManifestRequest message");
+        }
     }
 
     fn handle_compute_manifest_request(
@@ -963,12 +1507,54 @@ impl StateManagerImpl {
                 fatal!(log, "Failed to decode system metadata @{}: {}", height, err)
             });
 
+        // The hashes of the chunks without dirty pages of the files copied from
+        // the latest checkpoint with a computed manifest are reused. The
+        // checkpoint reference keeps that checkpoint alive meanwhile.
+        let base = states
+            .read()
+            .states_metadata
+            .range(..height)
+            .rev()
+            .filter(|(_, metadata)| !metadata.corrupted)
+            .find_map(|(_, metadata)| {
+                Some((metadata.checkpoint_ref.clone()?, metadata.manifest.clone()?))
+            });
+
         let start = Instant::now();
-        let manifest = crate::manifest::compute_manifest(
-            system_metadata.state_sync_version,
-            checkpoint_layout.raw_path(),
-            crate::manifest::DEFAULT_CHUNK_SIZE,
-        )
+        let manifest = match &base {
+            Some((base_checkpoint_ref, base_manifest)) => {
+                let base_height = base_checkpoint_ref.0.height;
+                match base_checkpoint_ref.0.state_layout.checkpoint(base_height) {
+                    Ok(base_layout) => crate::manifest::compute_manifest_incremental(
+                        system_metadata.state_sync_version,
+                        checkpoint_layout.raw_path(),
+                        crate::manifest::DEFAULT_CHUNK_SIZE,
+                        base_layout.raw_path(),
+                        base_manifest,
+                        &req.dirty_pages,
+                    ),
+                    Err(err) => {
+                        warn!(
+                            log,
+                            "Failed to get checkpoint path for height {}, computing manifest @{} from scratch: {}",
+                            base_height,
+                            height,
+                            err
+                        );
+                        crate::manifest::compute_manifest(
+                            system_metadata.state_sync_version,
+                            checkpoint_layout.raw_path(),
+                            crate::manifest::DEFAULT_CHUNK_SIZE,
+                        )
+                    }
+                }
+            }
+            None => crate::manifest::compute_manifest(
+                system_metadata.state_sync_version,
+                checkpoint_layout.raw_path(),
+                crate::manifest::DEFAULT_CHUNK_SIZE,
+            ),
+        }
         .unwrap_or_else(|err| {
             fatal!(
                 log,
@@ -987,7 +1573,11 @@ impl StateManagerImpl {
 
         info!(
             log,
-            "Computed manifest of state @{} in {:?}", height, elapsed
+            "Computed manifest of state @{} in {:?} (base checkpoint: {:?})",
+            height,
+            elapsed,
+            base.as_ref()
+                .map(|(checkpoint_ref, _)| checkpoint_ref.0.height)
         );
 
         let state_size_bytes: i64 = manifest
@@ -1038,9 +1628,61 @@ impl StateManagerImpl {
         Some((state.take(), certification, hash_tree))
     }
 
+    /// Computes the witness of `paths` in the certified `state` with the given
+    /// hash tree.
+    fn compute_witness(
+        state: &ReplicatedState,
+        hash_tree: &HashTree,
+        paths: &LabeledTree<()>,
+    ) -> Option<MixedHashTree> {
+        let lazy_tree = LazyTree::from(state);
+        let partial_tree = materialize_partial(&lazy_tree, paths)?;
+        Some(hash_tree.witness::<MixedHashTree>(&partial_tree))
+    }
+
+    /// Asks the witness prefetcher to compute the witnesses of the prefetched
+    /// paths in the latest certified state.
+    fn request_witness_prefetch(&self) {
+        if let Some((state, certification, hash_tree)) = self.latest_certified_state() {
+            self.witness_prefetch_sender
+                .send(WitnessPrefetchRequest {
+                    state,
+                    height: certification.height,
+                    hash_tree,
+                })
+                .expect("failed to send request to witness prefetcher");
+        }
+    }
+
+    /// Computes the witnesses of the prefetched paths in the requested
+    /// certified state, unless they are cached already. The lock is only held
+    /// to look up and insert witnesses, not while computing them.
+    fn prefetch_witnesses(witness_cache: &Mutex<WitnessCache>, req: WitnessPrefetchRequest) {
+        let prefetched_paths = witness_cache.lock().unwrap().prefetched_paths();
+        for paths in prefetched_paths {
+            if witness_cache
+                .lock()
+                .unwrap()
+                .get(req.height, &paths)
+                .is_some()
+            {
+                continue;
+            }
+            if let Some(witness) = Self::compute_witness(&req.state, &req.hash_tree, &paths) {
+                witness_cache
+                    .lock()
+                    .unwrap()
+                    .insert(req.height, &paths, witness);
+            }
+        }
+    }
+
     /// Returns the manifest of the latest checkpoint on disk with its
     /// checkpoint ref.
-    fn latest_manifest(&self) -> Option<(Manifest, CheckpointRef)> {
+    /// Returns the manifests of all local checkpoints whose manifests are
+    /// computed, the latest checkpoint first.
+    fn local_manifests(&self) -> Vec<(Manifest, CheckpointRef)> {
+        let states = self.states.read();
         self.state_layout
             .checkpoint_heights()
             .unwrap_or_else(|err| {
@@ -1048,13 +1690,16 @@ impl StateManagerImpl {
             })
             .iter()
             .rev()
-            .find_map(|checkpointed_height| {
-                let states = self.states.read();
+            .filter_map(|checkpointed_height| {
                 let metadata = states.states_metadata.get(checkpointed_height)?;
+                if metadata.corrupted {
+                    return None;
+                }
                 let manifest = metadata.manifest.clone()?;
                 let checkpoint_ref = metadata.checkpoint_ref.clone()?;
                 Some((manifest, checkpoint_ref))
             })
+            .collect()
     }
 
     fn compute_certification_metadata(
@@ -1136,6 +1781,7 @@ impl StateManagerImpl {
 
             compute_manifest_requests.push(ComputeManifestRequest {
                 checkpoint_ref: checkpoint_ref.clone(),
+                dirty_pages: DirtyPages::new(),
             });
 
             metadata.insert(
@@ -1144,6 +1790,7 @@ impl StateManagerImpl {
                     checkpoint_ref: Some(checkpoint_ref),
                     manifest: None,
                     root_hash: None,
+                    corrupted: false,
                 },
             );
         }
@@ -1188,6 +1835,60 @@ impl StateManagerImpl {
 
     /// Flushes to disk all the canister heap deltas accumulated in memory
     /// during one round of execution.
+    /// Returns the pages of the canister memory files of the tip written since
+    /// the files were copied from the checkpoint the page maps were opened
+    /// from. Must be called before the page map deltas are stripped.
+    fn dirty_pages(&self, tip_state: &ReplicatedState) -> DirtyPages {
+        let tip_layout = self
+            .state_layout
+            .tip()
+            .unwrap_or_else(|err| fatal!(self.log, "Failed to access @TIP: {}", err));
+        let relative_path = |path: PathBuf| {
+            path.strip_prefix(tip_layout.raw_path())
+                .expect("canister files are located in @TIP")
+                .to_path_buf()
+        };
+
+        let mut dirty_pages = DirtyPages::new();
+        for canister in tip_state.canisters_iter() {
+            let canister_layout =
+                tip_layout
+                    .canister(&canister.canister_id())
+                    .unwrap_or_else(|err| {
+                        fatal!(
+                            self.log,
+                            "Failed to access canister {} layout @TIP {}: {}",
+                            canister.canister_id(),
+                            tip_layout.raw_path().display(),
+                            err
+                        )
+                    });
+            let mut page_maps = vec![(
+                &canister.system_state.stable_memory,
+                canister_layout.stable_memory_blob(),
+            )];
+            if let Some(execution_state) = &canister.execution_state {
+                page_maps.push((&execution_state.page_map, canister_layout.vmemory_0()));
+            }
+            for (page_map, path) in page_maps {
+                if let Some(base_file_id) = page_map.checkpoint_file_id() {
+                    dirty_pages.insert(
+                        relative_path(path),
+                        DirtyFile {
+                            base_file_id,
+                            dirty_pages: page_map
+                                .delta_page_indices()
+                                .into_iter()
+                                .map(|page_index| page_index.get())
+                                .collect(),
+                        },
+                    );
+                }
+            }
+        }
+        dirty_pages
+    }
+
     fn flush_page_maps(&self, tip_state: &mut ReplicatedState) {
         let tip_layout = self
             .state_layout
@@ -1329,6 +2030,7 @@ impl StateManagerImpl {
                 manifest: Some(manifest),
                 checkpoint_ref: Some(self.new_checkpoint_ref(height)),
                 root_hash: Some(root_hash),
+                corrupted: false,
             },
         );
 
@@ -1594,6 +2296,7 @@ impl StateManager for StateManagerImpl {
             .start_timer();
 
         let certification_height = certification.height;
+        let mut is_latest_certified = false;
         let mut states = self.states.write();
         if let Some(metadata) = states
             .certifications_metadata
@@ -1613,6 +2316,7 @@ impl StateManager for StateManagerImpl {
             self.metrics
                 .latest_certified_height
                 .set(latest_certified as i64);
+            is_latest_certified = latest_certified == certification_height.get();
 
             metadata.certification = Some(certification);
         }
@@ -1627,6 +2331,12 @@ impl StateManager for StateManagerImpl {
                     .expect("failed to send object to deallocation thread");
             }
         }
+        drop(states);
+
+        if is_latest_certified {
+            self.certified_height_watch.update(certification_height);
+            self.request_witness_prefetch();
+        }
     }
 
     /// # Panics
@@ -1682,6 +2392,10 @@ impl StateManager for StateManagerImpl {
         self.requested_to_remove_states_below
             .store(requested_height.get(), Ordering::Relaxed);
 
+        // The pending snapshots are looked up before the checkpoints on disk,
+        // so that a snapshot promoted in between is in one of the two.
+        let oldest_pending = self.checkpoint_promotion.oldest_pending();
+
         let checkpoint_heights = self
             .state_layout
             .checkpoint_heights()
@@ -1691,28 +2405,37 @@ impl StateManager for StateManagerImpl {
 
         let last_checkpoint = checkpoint_heights.last();
 
-        // The last checkpoint and higher states will be kept.
+        // The last checkpoint and higher states will be kept.  Older
+        // checkpoints must not be removed before the newer ones are persisted,
+        // so the states from the oldest snapshot waiting for promotion are
+        // kept as well; their removal is deferred until the next commit after
+        // the promotion.
         let last_height_to_keep = last_checkpoint
             .map(|h| (*h).min(requested_height))
             .unwrap_or(requested_height);
+        let deferred = oldest_pending.map_or(false, |pending| pending <= requested_height);
+        self.removal_deferred.store(deferred, Ordering::Relaxed);
+        let last_height_to_keep = oldest_pending
+            .map(|pending| pending.min(last_height_to_keep))
+            .unwrap_or(last_height_to_keep);
 
         let heights_to_remove = std::ops::Range {
             start: Height::new(1),
             end: last_height_to_keep,
         };
 
-        // The latest checkpoint below or at the requested height will also be kept
-        // because the state manager needs to load from it when restarting.
-        // Therefore, it is excluded from the `heights_to_remove` above.
-        // Note: the `oldest_checkpoint_to_keep`, if set, is <= `last_checkpoint`.
-        let oldest_checkpoint_to_keep = checkpoint_heights
-            .iter()
-            .filter(|x| **x <= requested_height)
-            .max()
-            .cloned();
-
+        // Holding the states lock, so that no state is pinned in between.
         let mut states = self.states.write();
 
+        // Some of the `heights_to_remove` are kept nevertheless, see
+        // `heights_to_keep`.
+        let heights_to_keep = heights_to_keep(
+            &self.pruning_policy,
+            &checkpoint_heights,
+            requested_height,
+            &self.pinned_heights.lock().unwrap(),
+        );
+
         // Send object to deallocation thread if it has capacity.
         let deallocate = |x| {
             if self.deallocation_sender.len() < DEALLOCATION_BACKLOG_THRESHOLD {
@@ -1726,7 +2449,7 @@ impl StateManager for StateManagerImpl {
 
         let (removed, retained) = states.snapshots.drain(0..).partition(|snapshot| {
             heights_to_remove.contains(&snapshot.height)
-                && Some(snapshot.height) != oldest_checkpoint_to_keep
+                && !heights_to_keep.contains(&snapshot.height)
         });
         states.snapshots = retained;
 
@@ -1743,7 +2466,10 @@ impl StateManager for StateManagerImpl {
         self.latest_state_height
             .store(latest_height.get(), Ordering::Relaxed);
 
-        let min_resident_height = oldest_checkpoint_to_keep
+        let min_resident_height = heights_to_keep
+            .iter()
+            .next()
+            .cloned()
             .unwrap_or(last_height_to_keep)
             .min(last_height_to_keep);
 
@@ -1758,7 +2484,7 @@ impl StateManager for StateManagerImpl {
         deallocate(Box::new(removed));
 
         for (height, ref metadata) in states.states_metadata.range(heights_to_remove) {
-            if Some(*height) == oldest_checkpoint_to_keep {
+            if heights_to_keep.contains(height) {
                 continue;
             }
             if let Some(ref checkpoint_ref) = metadata.checkpoint_ref {
@@ -1770,9 +2496,9 @@ impl StateManager for StateManagerImpl {
             .certifications_metadata
             .split_off(&last_height_to_keep);
 
-        if let Some(h) = oldest_checkpoint_to_keep {
-            if let Some(cert_metadata) = states.certifications_metadata.remove(&h) {
-                certifications_metadata.insert(h, cert_metadata);
+        for h in heights_to_keep.iter() {
+            if let Some(cert_metadata) = states.certifications_metadata.remove(h) {
+                certifications_metadata.insert(*h, cert_metadata);
             }
         }
 
@@ -1803,14 +2529,25 @@ impl StateManager for StateManagerImpl {
         // handling.
         let mut metadata_to_keep = states.states_metadata.split_off(&last_height_to_keep);
 
-        if let Some(h) = oldest_checkpoint_to_keep {
-            if let Some(metadata) = states.states_metadata.remove(&h) {
-                metadata_to_keep.insert(h, metadata);
+        for h in heights_to_keep.iter() {
+            if let Some(metadata) = states.states_metadata.remove(h) {
+                metadata_to_keep.insert(*h, metadata);
             }
         }
         states.states_metadata = metadata_to_keep;
 
         self.persist_metadata_or_die(&states.states_metadata);
+        drop(states);
+
+        // Interrupted state syncs up to the last checkpoint won't be resumed.
+        if let Some(last_checkpoint) = last_checkpoint {
+            remove_stale_scratchpads(
+                &self.log,
+                &self.state_layout,
+                &self.state_sync_refs,
+                *last_checkpoint,
+            );
+        }
     }
 
     fn commit_and_certify(
@@ -1845,28 +2582,77 @@ impl StateManager for StateManagerImpl {
         self.populate_extra_metadata(&mut state, height);
         self.flush_page_maps(&mut state);
 
+        // The snapshot of the tip that still needs to be promoted to a
+        // checkpoint in the background.
+        let mut tip_snapshot = None;
+        let mut remove_states_below_checkpoint = false;
+        let mut dirty_pages = DirtyPages::new();
+
         let checkpointed_state = match scope {
             CertificationScope::Full => {
                 let start = Instant::now();
 
+                // The pages accessed since the last checkpoint determine
+                // which heap pages move to cold storage.
+                let heap_page_accesses = tiered_storage::heap_page_accesses(&state);
+                // Copy-on-write memory files are always hashed in full.
+                if !cow_state_feature::is_enabled(cow_state_feature::cow_state) {
+                    dirty_pages = self.dirty_pages(&state);
+                }
+
                 // We don't need to persist the deltas to the tip because we
                 // flush deltas separately every round, see flush_page_maps.
                 strip_page_map_deltas(&mut state);
-                let result = checkpoint::make_checkpoint(&state, height, &self.state_layout);
+                let result = if cow_state_feature::is_enabled(cow_state_feature::cow_state) {
+                    // Copy-on-write canister states are tied to the paths of
+                    // the checkpoint, so the checkpoint is created in place.
+                    checkpoint::make_checkpoint(
+                        &state,
+                        height,
+                        &self.state_layout,
+                        self.tiered_storage.as_ref(),
+                        &heap_page_accesses,
+                    )
+                    .map(|checkpointed_state| {
+                        copy_page_maps(&mut state, &checkpointed_state);
+                        if let Some(cold_file_cleaner) = &self.cold_file_cleaner {
+                            cold_file_cleaner.request_cleanup();
+                        }
+                        checkpointed_state
+                    })
+                } else {
+                    // Only the snapshot of the tip blocks the round.  The
+                    // snapshot files don't change anymore, so the state is
+                    // loaded from them right away, and syncing and promoting
+                    // the snapshot happens in the background.
+                    checkpoint::snapshot_tip(
+                        &state,
+                        height,
+                        &self.state_layout,
+                        self.tiered_storage.as_ref(),
+                        &heap_page_accesses,
+                    )
+                    .and_then(|snapshot| {
+                        let checkpointed_state =
+                            checkpoint::load_checkpoint(&snapshot, self.own_subnet_type)?;
+                        copy_page_maps(&mut state, &checkpointed_state);
+                        tip_snapshot = Some(snapshot);
+                        Ok(checkpointed_state)
+                    })
+                };
                 purge_cow_rounds_below(&mut state, self.first_known_height());
 
                 let low_water_mark = self
                     .requested_to_remove_states_below
                     .load(Ordering::Relaxed);
 
-                if height == Height::new(low_water_mark) {
-                    self.remove_states_below(height);
-                }
+                // The older states are removed once the checkpoint is
+                // persisted, see the end of this function.
+                remove_states_below_checkpoint = height == Height::new(low_water_mark);
 
                 let elapsed = start.elapsed();
                 match result {
                     Ok(checkpointed_state) => {
-                        copy_page_maps(&mut state, &checkpointed_state);
                         info!(self.log, "Created checkpoint @{} in {:?}", height, elapsed);
                         self.metrics
                             .checkpoint_op_duration
@@ -1875,6 +2661,8 @@ impl StateManager for StateManagerImpl {
                         checkpointed_state
                     }
                     Err(CheckpointError::AlreadyExists(_)) => {
+                        // The existing checkpoint wasn't written from the tip.
+                        dirty_pages.clear();
                         warn!(
                                 self.log,
                                 "Failed to create checkpoint @{} because it already exists, re-loading the checkpoint from disk", height
@@ -1942,6 +2730,13 @@ impl StateManager for StateManagerImpl {
             );
         }
 
+        let mut checkpoint_request = tip_snapshot.map(|snapshot| CheckpointRequest {
+            snapshot,
+            height,
+            checkpoint_ref: None,
+            dirty_pages: std::mem::take(&mut dirty_pages),
+        });
+
         let (tip_height, tip) = match states.snapshots.back() {
             Some(latest_snapshot) if height <= latest_snapshot.height => {
                 // This state is older than the one we already have.  This can
@@ -1983,12 +2778,22 @@ impl StateManager for StateManagerImpl {
                             checkpoint_ref: Some(checkpoint_ref.clone()),
                             manifest: None,
                             root_hash: None,
+                            corrupted: false,
                         },
                     );
 
-                    self.compute_manifest_request_sender
-                        .send(ComputeManifestRequest { checkpoint_ref })
-                        .expect("failed to send ComputeManifestRequest message");
+                    match &mut checkpoint_request {
+                        // The manifest is computed once the checkpoint is
+                        // promoted.
+                        Some(req) => req.checkpoint_ref = Some(checkpoint_ref),
+                        None => self
+                            .compute_manifest_request_sender
+                            .send(ComputeManifestRequest {
+                                checkpoint_ref,
+                                dirty_pages,
+                            })
+                            .expect("failed to send ComputeManifestRequest message"),
+                    }
                     self.persist_metadata_or_die(&states.states_metadata);
                 }
 
@@ -2011,6 +2816,23 @@ impl StateManager for StateManagerImpl {
             .set(tip_height.get() as i64);
 
         states.tip = Some((tip_height, tip));
+        drop(states);
+
+        if let Some(req) = checkpoint_request {
+            self.checkpoint_promotion.add_pending(req.height);
+            self.checkpoint_request_sender
+                .send(req)
+                .expect("failed to send CheckpointRequest message");
+        }
+
+        if remove_states_below_checkpoint {
+            self.remove_states_below(height);
+        } else if self.removal_deferred.load(Ordering::Relaxed) {
+            self.remove_states_below(Height::new(
+                self.requested_to_remove_states_below
+                    .load(Ordering::Relaxed),
+            ));
+        }
     }
 
     fn report_diverged_state(&self, height: Height) {
@@ -2020,6 +2842,10 @@ impl StateManager for StateManagerImpl {
             .with_label_values(&["report_diverged_state"])
             .start_timer();
 
+        // The snapshots at or above the diverged height that still wait for
+        // promotion are discarded by the checkpointer.
+        self.checkpoint_promotion.mark_diverged(height);
+
         let mut states = self.states.write();
         let mut heights = self
             .state_layout
@@ -2059,6 +2885,10 @@ impl StateReader for StateManagerImpl {
         Height::new(self.latest_certified_height.load(Ordering::Relaxed))
     }
 
+    fn subscribe_certified_height(&self) -> watch::Receiver<Height> {
+        self.certified_height_watch.subscribe()
+    }
+
     fn get_latest_state(&self) -> Labeled<Arc<Self::State>> {
         let _timer = self
             .metrics
@@ -2130,14 +2960,47 @@ impl StateReader for StateManagerImpl {
             .start_timer();
 
         let (state, certification, hash_tree) = self.latest_certified_state()?;
-        let mixed_hash_tree = {
-            let lazy_tree = LazyTree::from(&*state);
-            let partial_tree = materialize_partial(&lazy_tree, paths)?;
-            hash_tree.witness::<MixedHashTree>(&partial_tree)
+        let cached_witness = self
+            .witness_cache
+            .lock()
+            .unwrap()
+            .get(certification.height, paths);
+        let mixed_hash_tree = match cached_witness {
+            Some(witness) => {
+                self.metrics
+                    .witness_cache_lookups
+                    .with_label_values(&["hit"])
+                    .inc();
+                witness
+            }
+            None => {
+                self.metrics
+                    .witness_cache_lookups
+                    .with_label_values(&["miss"])
+                    .inc();
+                let witness = Self::compute_witness(&state, &hash_tree, paths)?;
+                self.witness_cache.lock().unwrap().insert(
+                    certification.height,
+                    paths,
+                    witness.clone(),
+                );
+                witness
+            }
         };
 
         Some((state, mixed_hash_tree, certification))
     }
+
+    fn prefetch_certified_paths(&self, paths: LabeledTree<()>) {
+        if self
+            .witness_cache
+            .lock()
+            .unwrap()
+            .add_prefetched_paths(paths)
+        {
+            self.request_witness_prefetch();
+        }
+    }
 }
 
 impl CertifiedStreamStore for StateManagerImpl {
@@ -2311,6 +3174,33 @@ pub enum CheckpointError {
     Persistence(PersistenceError),
 }
 
+/// The maximum number of heights that can be pinned at the same time.
+pub const MAX_PINNED_HEIGHTS: usize = 16;
+
+/// Errors returned by `StateManagerImpl::pin_height()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PinHeightError {
+    /// There is neither a state nor a checkpoint at the given height.
+    StateNotFound(Height),
+    /// The given maximum number of heights is pinned already.
+    TooManyPins(usize),
+}
+
+impl std::error::Error for PinHeightError {}
+
+impl std::fmt::Display for PinHeightError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PinHeightError::StateNotFound(height) => {
+                write!(f, "no state or checkpoint @{} found", height)
+            }
+            PinHeightError::TooManyPins(max) => {
+                write!(f, "the maximum of {} heights is pinned already", max)
+            }
+        }
+    }
+}
+
 impl std::error::Error for CheckpointError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
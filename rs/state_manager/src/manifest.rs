@@ -9,14 +9,16 @@ use hash::{
 };
 use ic_cow_state::{CowMemoryManager, CowMemoryManagerImpl, MappedState};
 use ic_crypto_sha256::Sha256;
-use ic_sys::mmap::ScopedMmap;
+use ic_replicated_state::page_map::cold_storage::{is_cold_pages_link, ColdPages};
+use ic_sys::{mmap::ScopedMmap, PAGE_SIZE};
 use ic_types::{
     state_sync::{ChunkInfo, FileInfo, Manifest},
     CryptoHashOfState,
 };
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fmt;
 use std::ops::Range;
+use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
 
 pub const STATE_SYNC_V1: u32 = 1;
@@ -148,11 +150,102 @@ fn write_chunk_hash(hasher: &mut Sha256, chunk_info: &ChunkInfo) {
     chunk_info.hash.update_hash(hasher);
 }
 
+/// The pages of a file of a checkpoint that may differ from the file with
+/// the same relative path in the previous checkpoint.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DirtyFile {
+    /// The device and inode number of the file that the file of the
+    /// checkpoint was copied from, before its dirty pages were written.
+    pub base_file_id: (u64, u64),
+    /// The indices of the pages written since.
+    pub dirty_pages: BTreeSet<u64>,
+}
+
+/// The files of a checkpoint whose dirty pages are known, keyed by their path
+/// relative to the checkpoint root.
+pub type DirtyPages = HashMap<PathBuf, DirtyFile>;
+
+/// A checkpoint with a computed manifest whose chunk hashes can be reused when
+/// computing the manifest of a later checkpoint.
+struct BaseCheckpoint<'a> {
+    root: &'a Path,
+    /// Maps relative paths to indices in the file table of the manifest.
+    file_indices: HashMap<&'a Path, usize>,
+    /// Maps file indices and offsets to chunks of the manifest.
+    chunks: HashMap<(usize, u64), &'a ChunkInfo>,
+    dirty_pages: &'a DirtyPages,
+}
+
+impl<'a> BaseCheckpoint<'a> {
+    fn new(root: &'a Path, manifest: &'a Manifest, dirty_pages: &'a DirtyPages) -> Self {
+        Self {
+            root,
+            file_indices: manifest
+                .file_table
+                .iter()
+                .enumerate()
+                .map(|(file_index, file_info)| (file_info.relative_path.as_path(), file_index))
+                .collect(),
+            chunks: manifest
+                .chunk_table
+                .iter()
+                .map(|chunk_info| {
+                    (
+                        (chunk_info.file_index as usize, chunk_info.offset),
+                        chunk_info,
+                    )
+                })
+                .collect(),
+            dirty_pages,
+        }
+    }
+
+    /// Returns the index of the file with the given relative path in the
+    /// base manifest together with its dirty pages, if they are known
+    /// relative to the file of the base checkpoint, i.e. if the file of the
+    /// base checkpoint is the one the file was copied from.
+    fn dirty_file(&self, relative_path: &Path) -> Option<(usize, &'a BTreeSet<u64>)> {
+        let dirty_file = self.dirty_pages.get(relative_path)?;
+        let file_index = *self.file_indices.get(relative_path)?;
+        let metadata = self.root.join(relative_path).metadata().ok()?;
+        if (metadata.dev(), metadata.ino()) != dirty_file.base_file_id {
+            return None;
+        }
+        Some((file_index, &dirty_file.dirty_pages))
+    }
+
+    /// Returns the hash of the chunk of the base file at the given offset if
+    /// it has the given size and none of its pages is dirty, without reading
+    /// the chunk.
+    fn reusable_chunk_hash(
+        &self,
+        (file_index, dirty_pages): (usize, &BTreeSet<u64>),
+        offset: u64,
+        size_bytes: u64,
+    ) -> Option<[u8; 32]> {
+        let chunk_info = self.chunks.get(&(file_index, offset))?;
+        if chunk_info.size_bytes as u64 != size_bytes {
+            return None;
+        }
+        let page_size = *PAGE_SIZE as u64;
+        let pages = offset / page_size..=(offset + size_bytes - 1) / page_size;
+        if dirty_pages.range(pages).next().is_some() {
+            return None;
+        }
+        Some(chunk_info.hash)
+    }
+}
+
 /// Build a chunk table from the file table.
+///
+/// The hashes of the chunks of files with known dirty pages, see
+/// [DirtyPages], that contain no dirty page are copied from the base
+/// manifest; these chunks are not read at all.
 fn build_chunk_table(
     root: &Path,
     files: Vec<FileWithSize>,
     max_chunk_size: u32,
+    base: Option<&BaseCheckpoint>,
 ) -> (Vec<FileInfo>, Vec<ChunkInfo>) {
     let mut chunk_table = Vec::new();
     let mut file_table = Vec::new();
@@ -171,25 +264,55 @@ fn build_chunk_table(
 
         (num_chunks as u32).update_hash(&mut file_hash);
 
-        let compute_file_chunk_hashes = |data: &[u8]| {
+        // The hashes of cow memory files are always computed.
+        let dirty_file = match base {
+            Some(base) if !relative_path.ends_with("state_file") => base
+                .dirty_file(&relative_path)
+                .map(|dirty_file| (base, dirty_file)),
+            _ => None,
+        };
+
+        let compute_file_chunk_hashes = |data: &[u8], cold_pages: Option<&ColdPages>| {
             // It's OK to not have any chunks for 0-sized files (though it's unlikely that
             // we have any).
             while bytes_left > 0 {
                 let chunk_size = bytes_left.min(max_chunk_size as u64);
                 let offset = size_bytes - bytes_left;
 
-                let mut hasher = if relative_path.ends_with("state_file") {
-                    cow_chunk_hasher()
-                } else {
-                    chunk_hasher()
-                };
-                hasher.write(&data[offset as usize..(offset + chunk_size) as usize]);
+                let reused_hash = dirty_file.and_then(|(base, dirty_file)| {
+                    base.reusable_chunk_hash(dirty_file, offset, chunk_size)
+                });
+
+                let hash = reused_hash.unwrap_or_else(|| {
+                    let chunk_range = offset as usize..(offset + chunk_size) as usize;
+                    let overlaid_chunk_data;
+                    let chunk_data = match cold_pages {
+                        Some(cold_pages) => {
+                            overlaid_chunk_data = {
+                                let mut buf = data[chunk_range].to_vec();
+                                cold_pages.overlay(offset, &mut buf).unwrap_or_else(|err| {
+                                    panic!("failed to read the cold pages of a chunk: {}", err)
+                                });
+                                buf
+                            };
+                            &overlaid_chunk_data[..]
+                        }
+                        None => &data[chunk_range],
+                    };
+                    let mut hasher = if relative_path.ends_with("state_file") {
+                        cow_chunk_hasher()
+                    } else {
+                        chunk_hasher()
+                    };
+                    hasher.write(chunk_data);
+                    hasher.finish()
+                });
 
                 let chunk_info = ChunkInfo {
                     file_index: file_index as u32,
                     size_bytes: chunk_size as u32,
                     offset: offset as u64,
-                    hash: hasher.finish(),
+                    hash,
                 };
 
                 write_chunk_hash(&mut file_hash, &chunk_info);
@@ -215,12 +338,19 @@ fn build_chunk_table(
             let data = unsafe {
                 std::slice::from_raw_parts(mapped_state.get_heap_base(), size_bytes as usize)
             };
-            compute_file_chunk_hashes(data);
+            compute_file_chunk_hashes(data, None);
         } else {
-            let mmap =
-                ScopedMmap::from_path(root.join(&relative_path)).expect("failed to open file");
+            let absolute_path = root.join(&relative_path);
+            let mmap = ScopedMmap::from_path(&absolute_path).expect("failed to open file");
+            let cold_pages = ColdPages::open(&absolute_path).unwrap_or_else(|err| {
+                panic!(
+                    "failed to open cold pages of {}: {}",
+                    absolute_path.display(),
+                    err
+                )
+            });
             let data = mmap.as_slice();
-            compute_file_chunk_hashes(data);
+            compute_file_chunk_hashes(data, cold_pages.as_ref());
         };
     }
 
@@ -235,6 +365,11 @@ fn files_with_sizes(
     files: &mut Vec<FileWithSize>,
 ) -> Result<(), CheckpointError> {
     let absolute_path = root.join(&relative_path);
+    // The storage tier of heap pages is a local choice, so it must not affect
+    // the manifest.
+    if is_cold_pages_link(&absolute_path) {
+        return Ok(());
+    }
     let metadata = absolute_path
         .metadata()
         .map_err(|io_err| CheckpointError::IoError {
@@ -309,13 +444,46 @@ pub fn compute_manifest(
     version: u32,
     checkpoint_root_path: &Path,
     max_chunk_size: u32,
+) -> Result<Manifest, CheckpointError> {
+    compute_manifest_impl(version, checkpoint_root_path, max_chunk_size, None)
+}
+
+/// Computes manifest for the checkpoint located at `checkpoint_root_path`,
+/// reusing the hashes of the chunks of the checkpoint located at
+/// `base_root_path` with the manifest `base_manifest` that `dirty_pages`
+/// proves unchanged.
+///
+/// The result is the same as that of `compute_manifest` as long as
+/// `dirty_pages` includes all pages written since the files were copied.
+/// Manifests of other versions are not reused.
+pub fn compute_manifest_incremental(
+    version: u32,
+    checkpoint_root_path: &Path,
+    max_chunk_size: u32,
+    base_root_path: &Path,
+    base_manifest: &Manifest,
+    dirty_pages: &DirtyPages,
+) -> Result<Manifest, CheckpointError> {
+    if base_manifest.version != version {
+        return compute_manifest(version, checkpoint_root_path, max_chunk_size);
+    }
+    let base = BaseCheckpoint::new(base_root_path, base_manifest, dirty_pages);
+    compute_manifest_impl(version, checkpoint_root_path, max_chunk_size, Some(&base))
+}
+
+fn compute_manifest_impl(
+    version: u32,
+    checkpoint_root_path: &Path,
+    max_chunk_size: u32,
+    base: Option<&BaseCheckpoint>,
 ) -> Result<Manifest, CheckpointError> {
     let mut files = Vec::new();
     files_with_sizes(checkpoint_root_path, "".into(), &mut files)?;
     // We sort the table to make sure that the table is the same on all replicas
     files.sort_unstable_by(|lhs, rhs| lhs.0.cmp(&rhs.0));
 
-    let (file_table, chunk_table) = build_chunk_table(checkpoint_root_path, files, max_chunk_size);
+    let (file_table, chunk_table) =
+        build_chunk_table(checkpoint_root_path, files, max_chunk_size, base);
 
     Ok(Manifest {
         version,
@@ -1,10 +1,11 @@
 use super::{
-    compute_manifest, diff_manifest, filter_out_zero_chunks, hash::ManifestHash, manifest_hash,
-    validate_chunk, validate_manifest, ChunkValidationError, DiffScript, ManifestValidationError,
-    STATE_SYNC_V1,
+    compute_manifest, compute_manifest_incremental, diff_manifest, filter_out_zero_chunks,
+    hash::ManifestHash, manifest_hash, validate_chunk, validate_manifest, ChunkValidationError,
+    DiffScript, DirtyFile, DirtyPages, ManifestValidationError, STATE_SYNC_V1,
 };
 
 use ic_crypto_sha256::Sha256;
+use ic_sys::PAGE_SIZE;
 use ic_types::{
     crypto::CryptoHash,
     state_sync::{decode_manifest, encode_manifest, ChunkInfo, FileInfo, Manifest},
@@ -13,6 +14,8 @@ use ic_types::{
 
 use std::collections::{HashMap, HashSet};
 use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
 
 macro_rules! hash_concat {
     ($( $x:expr ),*) => {
@@ -419,6 +422,99 @@ fn test_diff_manifest() {
     );
 }
 
+#[test]
+fn test_incremental_manifest_computation() {
+    let base_dir = tempfile::TempDir::new().expect("failed to create a temporary directory");
+    let base_root = base_dir.path();
+    let dir = tempfile::TempDir::new().expect("failed to create a temporary directory");
+    let root = dir.path();
+
+    for root in [base_root, root].iter() {
+        let subdir = root.join("subdir");
+        fs::create_dir_all(&subdir).expect("failed to create dir 'subdir'");
+        fs::write(subdir.join("memory"), vec![1u8; 3000 * 1024])
+            .expect("failed to create file 'memory'");
+        fs::write(subdir.join("metadata"), vec![3u8; 1050 * 1024])
+            .expect("failed to create file 'metadata'");
+    }
+    let base_manifest = compute_manifest(STATE_SYNC_V1, &base_root, 1024 * 1024)
+        .expect("failed to compute manifest");
+
+    // Change the second chunk of 'memory', grow 'metadata' and add a new file.
+    let mut memory = vec![1u8; 3000 * 1024];
+    memory[1500 * 1024] = 2;
+    fs::write(root.join("subdir").join("memory"), memory).expect("failed to write file 'memory'");
+    fs::write(root.join("subdir").join("metadata"), vec![3u8; 2048 * 1024])
+        .expect("failed to write file 'metadata'");
+    fs::write(root.join("root.bin"), vec![2u8; 1000 * 1024])
+        .expect("failed to create file 'root.bin'");
+
+    let dirty_file = |relative_path: &str, dirty_pages: Vec<u64>| {
+        let metadata = base_root
+            .join(relative_path)
+            .metadata()
+            .expect("failed to stat base file");
+        (
+            PathBuf::from(relative_path),
+            DirtyFile {
+                base_file_id: (metadata.dev(), metadata.ino()),
+                dirty_pages: dirty_pages.into_iter().collect(),
+            },
+        )
+    };
+    let page_size = *PAGE_SIZE as u64;
+    let dirty_pages: DirtyPages = vec![
+        dirty_file("subdir/memory", vec![1500 * 1024 / page_size]),
+        dirty_file(
+            "subdir/metadata",
+            (1050 * 1024 / page_size..2048 * 1024 / page_size).collect(),
+        ),
+    ]
+    .into_iter()
+    .collect();
+    let compute_incremental = |dirty_pages: &DirtyPages| {
+        compute_manifest_incremental(
+            STATE_SYNC_V1,
+            &root,
+            1024 * 1024,
+            &base_root,
+            &base_manifest,
+            dirty_pages,
+        )
+        .expect("failed to compute manifest")
+    };
+    let expected_manifest =
+        compute_manifest(STATE_SYNC_V1, &root, 1024 * 1024).expect("failed to compute manifest");
+
+    let manifest = compute_incremental(&dirty_pages);
+    assert_eq!(manifest, expected_manifest);
+    assert_ne!(
+        manifest.chunk_table[2].hash,
+        base_manifest.chunk_table[1].hash
+    );
+
+    // Without dirty pages the chunks are not read, so the changed chunk
+    // keeps its base hash.
+    let mut clean_pages = dirty_pages.clone();
+    clean_pages
+        .get_mut(Path::new("subdir/memory"))
+        .unwrap()
+        .dirty_pages
+        .clear();
+    assert_eq!(
+        compute_incremental(&clean_pages).chunk_table[2].hash,
+        base_manifest.chunk_table[1].hash
+    );
+
+    // Dirty pages relative to another file are ignored.
+    clean_pages
+        .get_mut(Path::new("subdir/memory"))
+        .unwrap()
+        .base_file_id
+        .1 += 1;
+    assert_eq!(compute_incremental(&clean_pages), expected_manifest);
+}
+
 #[test]
 fn test_filter_all_zero_chunks() {
     let dir = tempfile::TempDir::new().expect("failed to create a temporary directory");
@@ -0,0 +1,173 @@
+//! The scrubber re-reads the checkpoints on disk in the background and
+//! compares the hashes of their chunks with the manifests.
+//!
+//! The scrubber verifies one chunk at a time and pauses after each chunk, so
+//! that it doesn't compete with the execution for disk bandwidth.  A
+//! checkpoint with a chunk that can't be read or doesn't match the manifest is
+//! marked as corrupted and is neither advertised to state sync peers nor used
+//! as a source of chunks for state sync or incremental manifest computation.
+//! The mark is persisted with the states metadata, so it survives restarts.
+//!
+//! The scrubber only holds a checkpoint reference while it verifies a single
+//! chunk, so it doesn't delay the removal of checkpoints.
+
+use crate::{manifest::validate_chunk, state_sync::chunkable::get_state_sync_chunk};
+use crate::{SharedState, StateManagerMetrics, StatesMetadata};
+use crossbeam_channel::{Receiver, RecvTimeoutError};
+use ic_config::state_manager::ScrubberConfig;
+use ic_logger::{debug, error, ReplicaLogger};
+use ic_state_layout::StateLayout;
+use ic_types::{state_sync::Manifest, Height};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Verifies the checkpoints in `states` one after another until `stop` is
+/// disconnected. `persist_metadata` is called with the metadata of the states
+/// once a checkpoint is marked as corrupted, under the write lock of `states`.
+pub(crate) fn run(
+    log: &ReplicaLogger,
+    metrics: &StateManagerMetrics,
+    state_layout: &StateLayout,
+    states: &Arc<parking_lot::RwLock<SharedState>>,
+    persist_metadata: &dyn Fn(&StatesMetadata),
+    config: &ScrubberConfig,
+    stop: &Receiver<()>,
+) {
+    let chunk_pause = Duration::from_millis(config.chunk_pause_millis);
+    let pass_pause = Duration::from_secs(config.pass_pause_seconds);
+    // The checkpoints are verified in the order of their heights, wrapping
+    // around once the latest one is verified.
+    let mut next_height = Height::from(0);
+
+    loop {
+        let next = next_checkpoint_to_scrub(states, next_height);
+        if let Some((height, manifest)) = next {
+            if !scrub_checkpoint(
+                log,
+                metrics,
+                state_layout,
+                states,
+                persist_metadata,
+                height,
+                &manifest,
+                chunk_pause,
+                stop,
+            ) {
+                return;
+            }
+            next_height = height.increment();
+        }
+        if should_stop(stop, pass_pause) {
+            return;
+        }
+    }
+}
+
+/// Returns the height and manifest of the first checkpoint at or above
+/// `from` that has a manifest and isn't known to be corrupted, or of the
+/// lowest such checkpoint if there is none above `from`.
+fn next_checkpoint_to_scrub(
+    states: &parking_lot::RwLock<SharedState>,
+    from: Height,
+) -> Option<(Height, Manifest)> {
+    let states = states.read();
+    states
+        .states_metadata
+        .range(from..)
+        .chain(states.states_metadata.range(..from))
+        .find_map(|(height, metadata)| {
+            if metadata.corrupted || metadata.checkpoint_ref.is_none() {
+                return None;
+            }
+            Some((*height, metadata.manifest.clone()?))
+        })
+}
+
+/// Verifies all chunks of the checkpoint at `height`.  Returns false if the
+/// scrubber should stop.
+#[allow(clippy::too_many_arguments)]
+fn scrub_checkpoint(
+    log: &ReplicaLogger,
+    metrics: &StateManagerMetrics,
+    state_layout: &StateLayout,
+    states: &parking_lot::RwLock<SharedState>,
+    persist_metadata: &dyn Fn(&StatesMetadata),
+    height: Height,
+    manifest: &Manifest,
+    chunk_pause: Duration,
+    stop: &Receiver<()>,
+) -> bool {
+    for chunk_ix in 0..manifest.chunk_table.len() {
+        if should_stop(stop, chunk_pause) {
+            return false;
+        }
+
+        // The checkpoint reference keeps the checkpoint on disk while the
+        // chunk is read.  If the checkpoint was removed meanwhile, there is
+        // nothing left to verify.
+        let checkpoint_ref = match states
+            .read()
+            .states_metadata
+            .get(&height)
+            .and_then(|metadata| metadata.checkpoint_ref.clone())
+        {
+            Some(checkpoint_ref) => checkpoint_ref,
+            None => return true,
+        };
+        let checkpoint_root = match state_layout.checkpoint(height) {
+            Ok(layout) => layout.raw_path().to_path_buf(),
+            Err(_) => return true,
+        };
+
+        let result = verify_chunk(&checkpoint_root, manifest, chunk_ix);
+        drop(checkpoint_ref);
+        metrics.scrubbed_chunks.inc();
+
+        if let Err(err) = result {
+            metrics.corrupted_chunks.inc();
+            metrics
+                .state_manager_error_count
+                .with_label_values(&["scrubber"])
+                .inc();
+            error!(
+                log,
+                "Checkpoint @{} is corrupted: chunk {}: {}", height, chunk_ix, err
+            );
+            let mut states = states.write();
+            if let Some(metadata) = states.states_metadata.get_mut(&height) {
+                metadata.corrupted = true;
+                persist_metadata(&states.states_metadata);
+            }
+            return true;
+        }
+    }
+    debug!(log, "Verified all chunks of checkpoint @{}", height);
+    true
+}
+
+/// Reads the chunk `chunk_ix` of the checkpoint at `checkpoint_root` and
+/// compares it with the manifest.
+fn verify_chunk(
+    checkpoint_root: &Path,
+    manifest: &Manifest,
+    chunk_ix: usize,
+) -> Result<(), String> {
+    let chunk = &manifest.chunk_table[chunk_ix];
+    let file = &manifest.file_table[chunk.file_index as usize];
+    let bytes = get_state_sync_chunk(
+        checkpoint_root.join(&file.relative_path),
+        chunk.offset,
+        chunk.size_bytes,
+    )
+    .map_err(|err| format!("failed to read {}: {}", file.relative_path.display(), err))?;
+    validate_chunk(chunk_ix, &bytes, manifest).map_err(|err| err.to_string())
+}
+
+/// Waits for `pause` and returns true if the scrubber should stop.
+fn should_stop(stop: &Receiver<()>, pause: Duration) -> bool {
+    match stop.recv_timeout(pause) {
+        Err(RecvTimeoutError::Timeout) => false,
+        Ok(()) | Err(RecvTimeoutError::Disconnected) => true,
+    }
+}
@@ -15,6 +15,7 @@ use ic_types::{
         StateSyncFilter, StateSyncMessage,
     },
     chunkable::Chunkable,
+    crypto::CryptoHash,
     Height, NodeId,
 };
 
@@ -46,9 +47,13 @@ impl ArtifactKind for StateSyncArtifact {
                 root_hash: msg.root_hash.clone(),
             },
             size: size as usize,
-            integrity_hash: crypto_hash(msg).get(),
+            integrity_hash: Self::integrity_hash(msg),
         }
     }
+
+    fn integrity_hash(msg: &StateSyncMessage) -> CryptoHash {
+        crypto_hash(msg).get()
+    }
 }
 
 impl ArtifactClient<StateSyncArtifact> for StateManagerImpl {
@@ -83,7 +88,7 @@ impl ArtifactClient<StateSyncArtifact> for StateManagerImpl {
             .states_metadata
             .iter()
             .find_map(|(height, metadata)| {
-                if metadata.root_hash.as_ref() == Some(&msg_id.hash) {
+                if metadata.root_hash.as_ref() == Some(&msg_id.hash) && !metadata.corrupted {
                     let manifest = metadata.manifest.as_ref()?;
                     let checkpoint_root = self.state_layout.checkpoint(*height).ok()?;
                     Some(StateSyncMessage {
@@ -107,7 +112,9 @@ impl ArtifactClient<StateSyncArtifact> for StateManagerImpl {
             .states_metadata
             .iter()
             .any(|(height, metadata)| {
-                *height == msg_id.height && metadata.root_hash.as_ref() == Some(&msg_id.hash)
+                *height == msg_id.height
+                    && metadata.root_hash.as_ref() == Some(&msg_id.hash)
+                    && !metadata.corrupted
             })
     }
 
@@ -134,6 +141,9 @@ impl ArtifactClient<StateSyncArtifact> for StateManagerImpl {
             .filter_map(|h| {
                 if h > filter.height {
                     let metadata = states.states_metadata.get(&h)?;
+                    if metadata.corrupted {
+                        return None;
+                    }
                     let manifest = metadata.manifest.as_ref()?;
                     let checkpoint_root = self.state_layout.checkpoint(h).ok()?;
                     let msg = StateSyncMessage {
@@ -5,6 +5,7 @@ use crate::{
 use ic_cow_state::{CowMemoryManager, CowMemoryManagerImpl, MappedState};
 use ic_logger::{debug, fatal, info, trace, warn, ReplicaLogger};
 use ic_registry_subnet_type::SubnetType;
+use ic_replicated_state::page_map::{cold_storage::ColdPages, PersistenceError};
 use ic_state_layout::utils::do_copy_overwrite;
 use ic_state_layout::{error::LayoutError, CheckpointLayout, ReadOnly, RwPolicy, StateLayout};
 use ic_sys::{mmap::ScopedMmap, PAGE_SIZE};
@@ -19,9 +20,12 @@ use ic_types::{
     state_sync::{decode_manifest, Manifest, MANIFEST_CHUNK},
     CryptoHashOfState, Height,
 };
-use std::collections::{HashMap, HashSet};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::Range;
 use std::os::unix::fs::FileExt;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use std::time::Instant;
 
 /// The state of the communication with up-to-date nodes.
@@ -40,6 +44,12 @@ enum DownloadState {
 
 /// An implementation of Chunkable trait that represents a (on-disk) state under
 /// construction.
+///
+/// The chunks of the state are copied from local checkpoints where possible
+/// and only the remaining ones are fetched from peers. The scratchpad of a
+/// state sync that is interrupted before completion is kept, so that a later
+/// state sync of the same state only fetches the chunks that are still
+/// missing.
 pub struct IncompleteState {
     log: ReplicaLogger,
     root: PathBuf,
@@ -47,22 +57,115 @@ pub struct IncompleteState {
     height: Height,
     root_hash: CryptoHashOfState,
     state: DownloadState,
-    manifest_with_checkpoint_ref: Option<(Manifest, CheckpointRef)>,
+    /// The manifests of the local checkpoints, the latest checkpoint first.
+    manifests_with_checkpoint_refs: Vec<(Manifest, CheckpointRef)>,
     metrics: StateManagerMetrics,
     started_at: Instant,
     own_subnet_type: SubnetType,
+    state_sync_refs: StateSyncRefs,
 }
 
 impl Drop for IncompleteState {
     fn drop(&mut self) {
-        if let Err(err) = std::fs::remove_dir_all(&self.root) {
-            // If the state sync succeeded, the root will be moved to
-            // checkpoints, so NotFound is expected here.
+        self.state_sync_refs.release(self.height);
+        match self.state {
+            // Nothing was fetched yet.
+            DownloadState::Blank => (),
+            // The state sync won't be resumed if the replica has a checkpoint
+            // at the same or a greater height already.
+            DownloadState::Loading { .. } if self.is_superseded() => {
+                remove_scratchpad(&self.log, &self.state_layout, self.height)
+            }
+            // Keep the chunks fetched so far, a later state sync of the same
+            // state resumes from them.
+            DownloadState::Loading { .. } => info!(
+                self.log,
+                "Keeping incomplete state sync state at {}",
+                self.root.display()
+            ),
+            // If the state sync succeeded, the root was moved to checkpoints,
+            // so only the progress file remains.
+            DownloadState::Complete(_) => {
+                remove_scratchpad(&self.log, &self.state_layout, self.height)
+            }
+        }
+    }
+}
+
+/// The heights of the state syncs in progress, whose scratchpads must not be
+/// removed.
+#[derive(Clone, Default)]
+pub(crate) struct StateSyncRefs {
+    active: Arc<Mutex<BTreeMap<Height, usize>>>,
+}
+
+impl StateSyncRefs {
+    fn acquire(&self, height: Height) {
+        *self.active.lock().unwrap().entry(height).or_insert(0) += 1;
+    }
+
+    fn release(&self, height: Height) {
+        let mut active = self.active.lock().unwrap();
+        if let Some(count) = active.get_mut(&height) {
+            *count -= 1;
+            if *count == 0 {
+                active.remove(&height);
+            }
+        }
+    }
+
+    fn is_active(&self, height: Height) -> bool {
+        self.active.lock().unwrap().contains_key(&height)
+    }
+}
+
+/// Removes the scratchpads of the interrupted state syncs at heights up to
+/// and including `up_to`, unless a state sync at that height is in progress.
+/// Such state syncs won't be resumed anymore, because the replica has a
+/// newer state already.
+pub(crate) fn remove_stale_scratchpads(
+    log: &ReplicaLogger,
+    state_layout: &StateLayout,
+    state_sync_refs: &StateSyncRefs,
+    up_to: Height,
+) {
+    let heights = state_layout
+        .state_sync_scratchpad_heights()
+        .unwrap_or_else(|err| {
+            warn!(log, "Failed to list state sync scratchpads: {}", err);
+            vec![]
+        });
+    for height in heights.into_iter().take_while(|h| *h <= up_to) {
+        if !state_sync_refs.is_active(height) {
+            remove_scratchpad(log, state_layout, height);
+        }
+    }
+}
+
+/// Removes the state sync scratchpad at the given height together with its
+/// progress file.
+fn remove_scratchpad(log: &ReplicaLogger, state_layout: &StateLayout, height: Height) {
+    let paths = state_layout
+        .state_sync_scratchpad(height)
+        .and_then(|root| Ok((root, state_layout.state_sync_progress(height)?)));
+    let (root, progress) = match paths {
+        Ok(paths) => paths,
+        Err(err) => {
+            warn!(log, "Failed to get state sync scratchpad path: {}", err);
+            return;
+        }
+    };
+    let results = vec![
+        (&root, std::fs::remove_dir_all(&root)),
+        (&progress, std::fs::remove_file(&progress)),
+    ];
+    for (path, result) in results {
+        if let Err(err) = result {
             if err.kind() != std::io::ErrorKind::NotFound {
                 warn!(
-                    self.log,
+                    log,
                     "Failed to remove incomplete state sync state at {}: {}",
-                    self.root.display(),
+                    path.display(),
                     err
                 );
             }
@@ -88,20 +191,75 @@ pub(crate) fn get_state_sync_chunk(
         let mut buf = vec![0; len as usize];
         let f = std::fs::File::open(&file_path)?;
         f.read_exact_at(&mut buf[..], offset)?;
+        let cold_pages = ColdPages::open(&file_path)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err.to_string()))?;
+        if let Some(cold_pages) = cold_pages {
+            cold_pages
+                .overlay(offset, &mut buf)
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err.to_string()))?;
+        }
         Ok(buf)
     }
 }
 
+/// A file of a local checkpoint whose chunks are reused by a state sync.
+///
+/// The cold pages of heap files are punched out of the file, so the chunks
+/// are read with their cold pages filled in from cold storage.
+struct LocalFile {
+    map: ScopedMmap,
+    cold_pages: Option<ColdPages>,
+}
+
+impl LocalFile {
+    fn open(path: &Path) -> Result<Self, String> {
+        let map = ScopedMmap::from_path(path).map_err(|err| err.to_string())?;
+        let cold_pages = ColdPages::open(path).map_err(|err| err.to_string())?;
+        Ok(Self { map, cold_pages })
+    }
+
+    fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns true if the whole file can be copied, i.e., if it has no cold
+    /// pages.
+    fn is_copyable(&self) -> bool {
+        self.cold_pages.is_none()
+    }
+
+    /// Returns the bytes in the given range of the file, which must lie
+    /// within the file.
+    fn read(&self, byte_range: Range<usize>) -> Result<Cow<'_, [u8]>, PersistenceError> {
+        let data = &self.map.as_slice()[byte_range.clone()];
+        match &self.cold_pages {
+            Some(cold_pages) => {
+                let mut buf = data.to_vec();
+                cold_pages.overlay(byte_range.start as u64, &mut buf)?;
+                Ok(Cow::Owned(buf))
+            }
+            None => Ok(Cow::Borrowed(data)),
+        }
+    }
+}
+
 impl IncompleteState {
     pub fn new(
         log: ReplicaLogger,
         height: Height,
         root_hash: CryptoHashOfState,
         state_layout: StateLayout,
-        manifest_with_checkpoint_ref: Option<(Manifest, CheckpointRef)>,
+        manifests_with_checkpoint_refs: Vec<(Manifest, CheckpointRef)>,
         metrics: StateManagerMetrics,
         own_subnet_type: SubnetType,
+        state_sync_refs: StateSyncRefs,
     ) -> Self {
+        // Interrupted state syncs of lower heights won't be resumed anymore.
+        if let Some(below) = height.get().checked_sub(1) {
+            remove_stale_scratchpads(&log, &state_layout, &state_sync_refs, Height::new(below));
+        }
+        state_sync_refs.acquire(height);
+
         Self {
             log,
             root: state_layout
@@ -111,16 +269,31 @@ impl IncompleteState {
             height,
             root_hash,
             state: DownloadState::Blank,
-            manifest_with_checkpoint_ref,
+            manifests_with_checkpoint_refs,
             metrics,
             started_at: Instant::now(),
             own_subnet_type,
+            state_sync_refs,
+        }
+    }
+
+    /// Returns true if there is a local checkpoint at the height of the state
+    /// sync or above it.
+    fn is_superseded(&self) -> bool {
+        match self.state_layout.checkpoint_heights() {
+            Ok(heights) => heights.last().map_or(false, |last| *last >= self.height),
+            Err(err) => {
+                warn!(self.log, "Failed to gather checkpoint heights: {}", err);
+                false
+            }
         }
     }
 
     /// Creates all the files listed in the manifest and resizes them to their
     /// expected sizes.  This way we won't have to worry about creating parent
-    /// directories when we receive chunks.
+    /// directories when we receive chunks.  Files that exist already keep
+    /// their contents, so that the chunks of an interrupted state sync can be
+    /// reused.
     pub(crate) fn preallocate_layout(log: &ReplicaLogger, root: &Path, manifest: &Manifest) {
         for file_info in manifest.file_table.iter() {
             let path = root.join(&file_info.relative_path);
@@ -143,9 +316,13 @@ impl IncompleteState {
                 continue;
             }
 
-            let f = std::fs::File::create(&path).unwrap_or_else(|err| {
-                fatal!(log, "Failed to create file {}: {}", path.display(), err)
-            });
+            let f = std::fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .open(&path)
+                .unwrap_or_else(|err| {
+                    fatal!(log, "Failed to create file {}: {}", path.display(), err)
+                });
             f.set_len(file_info.size_bytes).unwrap_or_else(|err| {
                 fatal!(
                     log,
@@ -199,10 +376,9 @@ impl IncompleteState {
             } else {
                 assert!(!src_path.ends_with("state_file"));
 
-                let src_map = ScopedMmap::from_path(&src_path).unwrap_or_else(|err| {
-                    fatal!(log, "Failed to mmap file {}: {}", src_path.display(), err)
+                let src_file = LocalFile::open(&src_path).unwrap_or_else(|err| {
+                    fatal!(log, "Failed to open file {}: {}", src_path.display(), err)
                 });
-                let src_data = src_map.as_slice();
 
                 let old_chunk_range = crate::manifest::file_chunk_range(manifest_old, *old_index);
                 let new_chunk_range = crate::manifest::file_chunk_range(manifest_new, *new_index);
@@ -222,7 +398,7 @@ impl IncompleteState {
                     let new_chunk_idx = new_chunk_range.start + chunk_offset;
                     let byte_range = chunk.byte_range();
 
-                    if src_data.len() < byte_range.end {
+                    if src_file.len() < byte_range.end {
                         warn!(
                             log,
                             "Local chunk {} ({}@{}—{}) is out of range (file len = {}), \
@@ -231,7 +407,7 @@ impl IncompleteState {
                             src_path.display(),
                             byte_range.start,
                             byte_range.end,
-                            src_data.len(),
+                            src_file.len(),
                             new_chunk_idx + 1
                         );
                         bad_chunks.push(idx);
@@ -239,11 +415,14 @@ impl IncompleteState {
                         continue;
                     }
 
-                    if let Err(err) = crate::manifest::validate_chunk(
-                        idx,
-                        &src_data[byte_range.clone()],
-                        manifest_old,
-                    ) {
+                    if let Err(err) = src_file
+                        .read(byte_range.clone())
+                        .map_err(|err| err.to_string())
+                        .and_then(|data| {
+                            crate::manifest::validate_chunk(idx, &data, manifest_old)
+                                .map_err(|err| err.to_string())
+                        })
+                    {
                         warn!(
                             log,
                             "Local chunk {} ({}@{}–{}) doesn't pass validation: {}, \
@@ -262,11 +441,13 @@ impl IncompleteState {
                 }
 
                 if bad_chunks.is_empty()
-                    && src_data.len() == manifest_old.file_table[*old_index].size_bytes as usize
+                    && src_file.is_copyable()
+                    && src_file.len() == manifest_old.file_table[*old_index].size_bytes as usize
                 {
-                    // All the hash sums and the file size match, so we can
-                    // simply copy the whole file.  That's much faster than
-                    // copying one chunk at a time.
+                    // All the hash sums and the file size match and no pages
+                    // are in cold storage, so we can simply copy the whole
+                    // file.  That's much faster than copying one chunk at a
+                    // time.
                     do_copy_overwrite(log, &src_path, &dst_path).unwrap_or_else(|err| {
                         fatal!(
                             log,
@@ -292,7 +473,16 @@ impl IncompleteState {
                         }
 
                         let chunk = &manifest_old.chunk_table[idx];
-                        let data = &src_data[chunk.byte_range()];
+                        let data = src_file.read(chunk.byte_range()).unwrap_or_else(|err| {
+                            fatal!(
+                                log,
+                                "Failed to read chunk (offset = {}, size = {}) of file {}: {}",
+                                chunk.offset,
+                                chunk.size_bytes,
+                                src_path.display(),
+                                err
+                            )
+                        });
 
                         dst.write_at(&data, chunk.offset).unwrap_or_else(|err| {
                             fatal!(
@@ -386,8 +576,8 @@ impl IncompleteState {
                     fatal!(log, "Failed to open file {}: {}", dst_path.display(), err)
                 });
 
-            let src_map = ScopedMmap::from_path(&src_path).unwrap_or_else(|err| {
-                fatal!(log, "Failed to mmap file {}: {}", src_path.display(), err)
+            let src_file = LocalFile::open(&src_path).unwrap_or_else(|err| {
+                fatal!(log, "Failed to open file {}: {}", src_path.display(), err)
             });
 
             // Validate each chunk that we happen to have locally.  If the
@@ -398,7 +588,7 @@ impl IncompleteState {
                 let src_chunk = &manifest_old.chunk_table[*src_chunk_index];
                 let byte_range = src_chunk.byte_range();
 
-                if src_map.len() < byte_range.end {
+                if src_file.len() < byte_range.end {
                     warn!(
                         log,
                         "Local chunk {} ({}@{}—{}) is out of range (file len = {}), \
@@ -407,14 +597,28 @@ impl IncompleteState {
                         src_path.display(),
                         byte_range.start,
                         byte_range.end,
-                        src_map.len(),
+                        src_file.len(),
                         *dst_chunk_index + 1
                     );
                     fetch_chunks.insert(*dst_chunk_index + 1);
                     continue;
                 }
 
-                let src_data = &src_map.as_slice()[byte_range];
+                let src_data = match src_file.read(byte_range) {
+                    Ok(src_data) => src_data,
+                    Err(err) => {
+                        warn!(
+                            log,
+                            "Failed to read local chunk {} ({}): {}, will request chunk {} instead",
+                            *src_chunk_index,
+                            src_path.display(),
+                            err,
+                            *dst_chunk_index + 1
+                        );
+                        fetch_chunks.insert(*dst_chunk_index + 1);
+                        continue;
+                    }
+                };
                 if let Err(err) =
                     crate::manifest::validate_chunk(*dst_chunk_index, &src_data, &manifest_new)
                 {
@@ -450,6 +654,163 @@ impl IncompleteState {
         }
     }
 
+    /// Removes the chunks that the scratchpad of an interrupted state sync of
+    /// the same state holds already from the chunks to fetch, and returns
+    /// their total size in bytes.
+    pub(crate) fn retain_missing_chunks(
+        root: &Path,
+        manifest: &Manifest,
+        fetch_chunks: &mut HashSet<usize>,
+    ) -> u64 {
+        let mut files: HashMap<usize, Option<std::fs::File>> = HashMap::default();
+        let mut present_bytes = 0;
+        fetch_chunks.retain(|ix| {
+            let chunk = &manifest.chunk_table[*ix - 1];
+            let file_index = chunk.file_index as usize;
+            let path = root.join(&manifest.file_table[file_index].relative_path);
+            if path.ends_with("state_file") {
+                // The chunks of cow memory files are always fetched again.
+                return true;
+            }
+            let file = files
+                .entry(file_index)
+                .or_insert_with(|| std::fs::File::open(&path).ok());
+            let mut buf = vec![0; chunk.size_bytes as usize];
+            let present = match file {
+                Some(f) => {
+                    f.read_exact_at(&mut buf[..], chunk.offset).is_ok()
+                        && crate::manifest::validate_chunk(*ix - 1, &buf, manifest).is_ok()
+                }
+                None => false,
+            };
+            if present {
+                present_bytes += chunk.size_bytes as u64;
+            }
+            !present
+        });
+        present_bytes
+    }
+
+    /// Copies the chunks to fetch whose contents match a chunk of one of the
+    /// given local checkpoints, and returns their total size in bytes.
+    pub(crate) fn copy_matching_chunks(
+        log: &ReplicaLogger,
+        checkpoints: &[(PathBuf, &Manifest)],
+        root_new: &Path,
+        manifest_new: &Manifest,
+        fetch_chunks: &mut HashSet<usize>,
+    ) -> u64 {
+        // Maps chunk hashes to the checkpoint and the index of a chunk with
+        // that hash.
+        let mut local_chunks: HashMap<[u8; 32], (usize, usize)> = HashMap::default();
+        for (checkpoint_index, (_, manifest_old)) in checkpoints.iter().enumerate() {
+            for (chunk_index, chunk) in manifest_old.chunk_table.iter().enumerate() {
+                local_chunks
+                    .entry(chunk.hash)
+                    .or_insert((checkpoint_index, chunk_index));
+            }
+        }
+
+        // The local files opened so far, or the error opening them.
+        let mut src_files: HashMap<PathBuf, Result<LocalFile, String>> = HashMap::default();
+        let mut copied_chunks = vec![];
+        let mut copied_bytes = 0;
+        for ix in fetch_chunks.iter() {
+            let dst_chunk = &manifest_new.chunk_table[*ix - 1];
+            let dst_path = root_new
+                .join(&manifest_new.file_table[dst_chunk.file_index as usize].relative_path);
+            let (checkpoint_index, src_chunk_index) = match local_chunks.get(&dst_chunk.hash) {
+                Some(local_chunk) => *local_chunk,
+                None => continue,
+            };
+            let (root_old, manifest_old) = &checkpoints[checkpoint_index];
+            let src_chunk = &manifest_old.chunk_table[src_chunk_index];
+            let src_path = root_old
+                .join(&manifest_old.file_table[src_chunk.file_index as usize].relative_path);
+            if dst_path.ends_with("state_file") || src_path.ends_with("state_file") {
+                continue;
+            }
+
+            let buf = match src_files
+                .entry(src_path.clone())
+                .or_insert_with(|| LocalFile::open(&src_path))
+            {
+                Ok(src_file) if src_file.len() >= src_chunk.byte_range().end => src_file
+                    .read(src_chunk.byte_range())
+                    .map_err(|err| err.to_string()),
+                Ok(src_file) => Err(format!("file len {} is too short", src_file.len())),
+                Err(err) => Err(err.clone()),
+            };
+            let buf = match buf {
+                Ok(buf) => buf,
+                Err(err) => {
+                    warn!(
+                        log,
+                        "Failed to read local chunk {} ({}@{}): {}, will request chunk {} instead",
+                        src_chunk_index,
+                        src_path.display(),
+                        src_chunk.offset,
+                        err,
+                        ix
+                    );
+                    continue;
+                }
+            };
+            if let Err(err) = crate::manifest::validate_chunk(*ix - 1, &buf, manifest_new) {
+                warn!(
+                    log,
+                    "Local chunk {} ({}@{}) doesn't pass validation: {}, \
+                     will request chunk {} instead",
+                    src_chunk_index,
+                    src_path.display(),
+                    src_chunk.offset,
+                    err,
+                    ix
+                );
+                continue;
+            }
+
+            Self::apply_chunk(log, root_new, *ix - 1, &buf, manifest_new);
+            copied_chunks.push(*ix);
+            copied_bytes += dst_chunk.size_bytes as u64;
+        }
+        for ix in copied_chunks {
+            fetch_chunks.remove(&ix);
+        }
+        copied_bytes
+    }
+
+    /// Prepares the scratchpad for fetching the chunks of the given state and
+    /// returns true if it holds the chunks of an interrupted state sync of
+    /// the same state.
+    fn prepare_scratchpad(
+        log: &ReplicaLogger,
+        state_layout: &StateLayout,
+        height: Height,
+        root_hash: &CryptoHashOfState,
+    ) -> bool {
+        let progress = state_layout
+            .state_sync_progress(height)
+            .expect("failed to get state sync progress path");
+        let resumable = match std::fs::read(&progress) {
+            Ok(recorded_hash) => recorded_hash == root_hash.get_ref().0,
+            Err(_) => false,
+        };
+        if !resumable {
+            // The scratchpad, if any, belongs to a different state.
+            remove_scratchpad(log, state_layout, height);
+            std::fs::write(&progress, &root_hash.get_ref().0).unwrap_or_else(|err| {
+                fatal!(
+                    log,
+                    "Failed to write state sync progress {}: {}",
+                    progress.display(),
+                    err
+                )
+            });
+        }
+        resumable
+    }
+
     pub(crate) fn apply_chunk(
         log: &ReplicaLogger,
         root: &Path,
@@ -696,25 +1057,49 @@ impl Chunkable for IncompleteState {
 
                     trace!(self.log, "Received manifest:\n{}", manifest);
 
-                    Self::preallocate_layout(&self.log, &self.root, &manifest);
-
-                    let state_sync_size_fetch =
-                        self.metrics.state_sync_size.with_label_values(&["fetch"]);
-
-                    let state_sync_size_copy =
-                        self.metrics.state_sync_size.with_label_values(&["copy"]);
+                    let resuming = Self::prepare_scratchpad(
+                        &self.log,
+                        &self.state_layout,
+                        self.height,
+                        &self.root_hash,
+                    );
+                    if resuming {
+                        info!(
+                            self.log,
+                            "Resuming interrupted state sync of state {}", self.height
+                        );
+                    }
 
-                    let state_sync_size_preallocate = self
-                        .metrics
-                        .state_sync_size
-                        .with_label_values(&["preallocate"]);
+                    Self::preallocate_layout(&self.log, &self.root, &manifest);
 
                     let total_bytes: u64 = manifest.file_table.iter().map(|f| f.size_bytes).sum();
                     let mut fetch_chunks: HashSet<usize>;
+                    let preallocate_bytes: u64;
+
+                    let log = &self.log;
+                    let local_checkpoints =
+                        std::mem::take(&mut self.manifests_with_checkpoint_refs);
+                    let checkpoint_roots: Vec<(PathBuf, &Manifest)> = local_checkpoints
+                        .iter()
+                        .map(|(manifest_old, checkpoint_ref)| {
+                            let height_old = checkpoint_ref.0.height;
+                            let checkpoint_old = checkpoint_ref
+                                .0
+                                .state_layout
+                                .checkpoint(height_old)
+                                .unwrap_or_else(|err| {
+                                    fatal!(
+                                        log,
+                                        "Failed to get checkpoint path for height {}: {}",
+                                        height_old,
+                                        err
+                                    )
+                                });
+                            (checkpoint_old.raw_path().to_path_buf(), manifest_old)
+                        })
+                        .collect();
 
-                    if let Some((manifest_old, checkpoint_ref)) =
-                        &self.manifest_with_checkpoint_ref.take()
-                    {
+                    if let Some((manifest_old, checkpoint_ref)) = local_checkpoints.first() {
                         info!(
                             self.log,
                             "Will use local state {} to speed up state sync",
@@ -733,20 +1118,7 @@ impl Chunkable for IncompleteState {
                             diff_script
                         );
 
-                        let height_old = checkpoint_ref.0.height;
-                        let checkpoint_old = checkpoint_ref
-                            .0
-                            .state_layout
-                            .checkpoint(height_old)
-                            .unwrap_or_else(|err| {
-                                fatal!(
-                                    &self.log,
-                                    "Failed to get checkpoint path for height {}: {}",
-                                    height_old,
-                                    err
-                                )
-                            });
-                        let root_old = checkpoint_old.raw_path();
+                        let root_old = &checkpoint_roots[0].0;
 
                         fetch_chunks = diff_script.fetch_chunks.iter().map(|i| *i + 1).collect();
 
@@ -770,31 +1142,59 @@ impl Chunkable for IncompleteState {
                             &mut fetch_chunks,
                         );
 
-                        let diff_bytes: u64 = diff_script
-                            .fetch_chunks
-                            .iter()
-                            .map(|i| manifest_new.chunk_table[*i].size_bytes as u64)
-                            .sum();
-
-                        let preallocate_bytes =
-                            diff_script.zeros_chunks * crate::manifest::DEFAULT_CHUNK_SIZE;
-
-                        state_sync_size_fetch.inc_by(diff_bytes);
-                        state_sync_size_preallocate.inc_by(preallocate_bytes as u64);
-                        state_sync_size_copy
-                            .inc_by(total_bytes - diff_bytes - preallocate_bytes as u64);
+                        preallocate_bytes = diff_script.zeros_chunks as u64
+                            * crate::manifest::DEFAULT_CHUNK_SIZE as u64;
                     } else {
                         let non_zero_chunks = filter_out_zero_chunks(&manifest);
-                        let diff_bytes: u64 = non_zero_chunks
+                        let non_zero_bytes: u64 = non_zero_chunks
                             .iter()
                             .map(|i| manifest.chunk_table[*i].size_bytes as u64)
                             .sum();
-                        state_sync_size_fetch.inc_by(diff_bytes);
-                        state_sync_size_preallocate.inc_by(total_bytes - diff_bytes);
+                        preallocate_bytes = total_bytes - non_zero_bytes;
 
                         fetch_chunks = non_zero_chunks.iter().map(|i| *i + 1).collect();
                     }
 
+                    let resume_bytes = if resuming {
+                        Self::retain_missing_chunks(&self.root, &manifest, &mut fetch_chunks)
+                    } else {
+                        0
+                    };
+
+                    // The latest checkpoint was diffed against already, look
+                    // for the remaining chunks in the older ones.
+                    Self::copy_matching_chunks(
+                        &self.log,
+                        checkpoint_roots.get(1..).unwrap_or(&[]),
+                        &self.root,
+                        &manifest,
+                        &mut fetch_chunks,
+                    );
+
+                    let fetch_bytes: u64 = fetch_chunks
+                        .iter()
+                        .map(|i| manifest.chunk_table[*i - 1].size_bytes as u64)
+                        .sum();
+                    self.metrics
+                        .state_sync_size
+                        .with_label_values(&["fetch"])
+                        .inc_by(fetch_bytes);
+                    self.metrics
+                        .state_sync_size
+                        .with_label_values(&["resume"])
+                        .inc_by(resume_bytes);
+                    self.metrics
+                        .state_sync_size
+                        .with_label_values(&["preallocate"])
+                        .inc_by(preallocate_bytes);
+                    self.metrics
+                        .state_sync_size
+                        .with_label_values(&["copy"])
+                        .inc_by(
+                            total_bytes
+                                .saturating_sub(fetch_bytes + resume_bytes + preallocate_bytes),
+                        );
+
                     if fetch_chunks.is_empty() {
                         debug!(
                             self.log,
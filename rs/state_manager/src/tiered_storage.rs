@@ -0,0 +1,425 @@
+//! Maintenance of the cold pages of canister heaps in the tip, see
+//! `ic_replicated_state::page_map::cold_storage`.
+//!
+//! The cold pages of a heap file are updated when a checkpoint is created,
+//! right before the tip is copied into the checkpoint.  The page maps of the
+//! current states may still map the heap files of the tip, so holes are never
+//! punched into a heap file in place: a copy without the new cold pages
+//! replaces the heap file instead.
+//!
+//! The cold page files that are no longer referenced are removed by the
+//! `ColdFileCleaner` in the background, as finding them walks the whole state
+//! root.
+
+use crate::CheckpointError;
+use crossbeam_channel::{bounded, Sender};
+use ic_config::state_manager::TieredStorageConfig;
+use ic_logger::{debug, warn, ReplicaLogger};
+use ic_replicated_state::page_map::cold_storage::{
+    cold_pages_link, is_cold_pages_link, punch_holes, write_cold_pages, ColdPages,
+};
+use ic_replicated_state::{
+    page_map::{PageIndex, PersistenceError},
+    ReplicatedState,
+};
+use ic_sys::PAGE_SIZE;
+use ic_types::CanisterId;
+use ic_utils::thread::JoinOnDrop;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+/// The heap pages of a canister that were accessed since the last
+/// checkpoint.
+#[derive(Default)]
+pub(crate) struct PageAccesses {
+    /// The pages that were modified, and hence written to the heap file.
+    pub modified: BTreeSet<PageIndex>,
+    /// The cold pages that were read from cold storage.
+    pub cold_reads: BTreeSet<PageIndex>,
+}
+
+/// The heap page accesses of each canister since the last checkpoint.
+pub(crate) type HeapPageAccesses = BTreeMap<CanisterId, PageAccesses>;
+
+fn io_error(path: &Path, message: &str, err: std::io::Error) -> CheckpointError {
+    CheckpointError::IoError {
+        path: path.to_path_buf(),
+        message: message.to_string(),
+        io_err: err.to_string(),
+    }
+}
+
+/// Returns the heap pages of each canister that were accessed since the last
+/// checkpoint.  Must be called before the page map deltas are stripped.
+pub(crate) fn heap_page_accesses(state: &ReplicatedState) -> HeapPageAccesses {
+    state
+        .canisters_iter()
+        .filter_map(|canister| {
+            canister.execution_state.as_ref().map(|execution_state| {
+                let page_map = &execution_state.page_map;
+                (
+                    canister.canister_id(),
+                    PageAccesses {
+                        modified: page_map.delta_page_indices().into_iter().collect(),
+                        cold_reads: page_map.accessed_cold_page_indices().into_iter().collect(),
+                    },
+                )
+            })
+        })
+        .collect()
+}
+
+fn remove_link(link: &Path) -> Result<(), CheckpointError> {
+    match std::fs::remove_file(link) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+            Err(io_error(link, "Failed to remove cold page link", err))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Removes the cold page link of the given heap file, if any.
+pub(crate) fn remove_cold_pages_link(heap_file: &Path) -> Result<(), CheckpointError> {
+    remove_link(&cold_pages_link(heap_file))
+}
+
+/// Returns the path of the given file with the given suffix appended.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = OsString::from(path);
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// Reads the given page of the file, padding with zeros beyond its end.
+fn read_page(file: &File, path: &Path, page_index: PageIndex) -> Result<Vec<u8>, PersistenceError> {
+    let mut buf = vec![0; *PAGE_SIZE];
+    let mut offset = 0;
+    while offset < buf.len() {
+        let read = file
+            .read_at(
+                &mut buf[offset..],
+                page_index.get() * *PAGE_SIZE as u64 + offset as u64,
+            )
+            .map_err(|err| PersistenceError::FileSystemError {
+                path: path.display().to_string(),
+                context: "Failed to read heap page".to_string(),
+                internal_error: err.to_string(),
+            })?;
+        if read == 0 {
+            break;
+        }
+        offset += read;
+    }
+    Ok(buf)
+}
+
+/// Updates the cold pages of the given heap file of the tip after the given
+/// pages were accessed since the last checkpoint.  The modified pages were
+/// written to the heap file already.
+///
+/// Pages become cold once they were not accessed for
+/// `cold_after_checkpoints` checkpoints.  Without tiered storage, or if the
+/// heap file is too small, all cold pages are written back to the heap file.
+pub(crate) fn update_cold_pages(
+    config: Option<&TieredStorageConfig>,
+    heap_file: &Path,
+    accesses: &PageAccesses,
+) -> Result<(), CheckpointError> {
+    let modified = &accesses.modified;
+    let previous = ColdPages::open(heap_file)?;
+    let file_size = match heap_file.metadata() {
+        Ok(metadata) => metadata.len(),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return remove_cold_pages_link(heap_file);
+        }
+        Err(err) => return Err(io_error(heap_file, "Failed to stat heap file", err)),
+    };
+    let num_pages = (file_size + *PAGE_SIZE as u64 - 1) / *PAGE_SIZE as u64;
+
+    let ages: Vec<u8> = (0..num_pages)
+        .map(PageIndex::new)
+        .map(|page| {
+            if modified.contains(&page) || accesses.cold_reads.contains(&page) {
+                0
+            } else {
+                previous
+                    .as_ref()
+                    .map_or(0, |previous| previous.age(page))
+                    .saturating_add(1)
+            }
+        })
+        .collect();
+
+    let config = config.filter(|config| file_size >= config.min_heap_size_bytes);
+    let cold: BTreeSet<PageIndex> = match config {
+        Some(config) => (0..num_pages)
+            .map(PageIndex::new)
+            .filter(|page| ages[page.get() as usize] >= config.cold_after_checkpoints.max(1))
+            .collect(),
+        None => BTreeSet::new(),
+    };
+
+    // Previously cold pages that are neither cold anymore nor were modified
+    // are holes in the heap file, so their contents must be written back.
+    // Page maps that still use the previous index serve these pages from cold
+    // storage, hence writing them in place is safe.
+    if let Some(previous) = &previous {
+        let restored: Vec<_> = previous
+            .page_indices()
+            .filter(|page| {
+                page.get() < num_pages && !cold.contains(page) && !modified.contains(page)
+            })
+            .collect();
+        if !restored.is_empty() {
+            let file = OpenOptions::new()
+                .write(true)
+                .open(heap_file)
+                .map_err(|err| io_error(heap_file, "Failed to open heap file", err))?;
+            let mut contents = vec![0; *PAGE_SIZE];
+            for page in restored {
+                let offset = page.get() * *PAGE_SIZE as u64;
+                previous.overlay(offset, &mut contents)?;
+                file.write_all_at(&contents, offset)
+                    .map_err(|err| io_error(heap_file, "Failed to restore cold page", err))?;
+            }
+            file.sync_all()
+                .map_err(|err| io_error(heap_file, "Failed to sync heap file", err))?;
+        }
+    }
+
+    let config = match config {
+        Some(config) => config,
+        None => return remove_cold_pages_link(heap_file),
+    };
+
+    let heap = File::open(heap_file)
+        .map_err(|err| io_error(heap_file, "Failed to open heap file", err))?;
+    let index = write_cold_pages(
+        &config.cold_storage_root,
+        previous.as_ref(),
+        &ages,
+        &cold,
+        |page| read_page(&heap, heap_file, page),
+    )?;
+
+    let newly_cold: BTreeSet<PageIndex> = cold
+        .iter()
+        .filter(|page| !previous.as_ref().map_or(false, |p| p.contains(**page)))
+        .cloned()
+        .collect();
+    if !newly_cold.is_empty() {
+        let copy = with_suffix(heap_file, ".tmp");
+        if ic_sys::fs::clone_file(heap_file, &copy).is_err() {
+            ic_utils::fs::copy_file_sparse(heap_file, &copy)
+                .map_err(|err| io_error(&copy, "Failed to copy heap file", err))?;
+        }
+        punch_holes(&copy, &newly_cold)?;
+        std::fs::rename(&copy, heap_file)
+            .map_err(|err| io_error(heap_file, "Failed to replace heap file", err))?;
+    }
+
+    // The link is replaced atomically.  The temporary link also carries the
+    // extension of cold page links, so it is never part of a manifest.
+    let link = cold_pages_link(heap_file);
+    let tmp_link = with_suffix(heap_file, ".tmp.cold");
+    remove_link(&tmp_link)?;
+    std::os::unix::fs::symlink(&index, &tmp_link)
+        .map_err(|err| io_error(&tmp_link, "Failed to create cold page link", err))?;
+    std::fs::rename(&tmp_link, &link)
+        .map_err(|err| io_error(&link, "Failed to replace cold page link", err))?;
+    Ok(())
+}
+
+/// Collects the names of the cold page files referenced by the cold page
+/// links under the given directory.
+fn collect_referenced_files(
+    dir: &Path,
+    referenced: &mut HashSet<OsString>,
+) -> Result<(), CheckpointError> {
+    let entries = dir
+        .read_dir()
+        .map_err(|err| io_error(dir, "Failed to read directory", err))?;
+    for entry in entries {
+        let path = entry
+            .map_err(|err| io_error(dir, "Failed to read directory", err))?
+            .path();
+        let metadata = path
+            .symlink_metadata()
+            .map_err(|err| io_error(&path, "Failed to stat file", err))?;
+        if metadata.is_dir() {
+            collect_referenced_files(&path, referenced)?;
+        } else if is_cold_pages_link(&path) {
+            let index = std::fs::read_link(&path)
+                .map_err(|err| io_error(&path, "Failed to read cold page link", err))?;
+            referenced.extend(
+                ColdPages::open_index(&index)?
+                    .files()
+                    .into_iter()
+                    .filter_map(|file| file.file_name().map(|name| name.to_owned())),
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Removes the files in the cold storage root that are not referenced by any
+/// cold page link under the state root and returns their number.
+pub(crate) fn remove_unreferenced_cold_files(
+    state_root: &Path,
+    cold_storage_root: &Path,
+) -> Result<usize, CheckpointError> {
+    let mut referenced = HashSet::new();
+    collect_referenced_files(state_root, &mut referenced)?;
+
+    let mut removed = 0;
+    let entries = cold_storage_root
+        .read_dir()
+        .map_err(|err| io_error(cold_storage_root, "Failed to read directory", err))?;
+    for entry in entries {
+        let path = entry
+            .map_err(|err| io_error(cold_storage_root, "Failed to read directory", err))?
+            .path();
+        let name = match path.file_name() {
+            Some(name) => name.to_owned(),
+            None => continue,
+        };
+        let is_cold_file = name
+            .to_str()
+            .map_or(false, |name| name.starts_with("cold_"));
+        if is_cold_file && !referenced.contains(&name) {
+            std::fs::remove_file(&path)
+                .map_err(|err| io_error(&path, "Failed to remove cold page file", err))?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Removes the unreferenced cold page files on a background thread, see
+/// `remove_unreferenced_cold_files`.
+pub(crate) struct ColdFileCleaner {
+    request_sender: Sender<()>,
+    // Declared after the sender: dropping the sender stops the thread, which
+    // is then joined.
+    _handle: JoinOnDrop<()>,
+}
+
+impl ColdFileCleaner {
+    pub(crate) fn new(
+        log: ReplicaLogger,
+        state_root: PathBuf,
+        config: &TieredStorageConfig,
+    ) -> Self {
+        // A request that arrives while another one is pending is covered by
+        // the pending one.
+        let (request_sender, request_receiver) = bounded(1);
+        let cold_storage_root = config.cold_storage_root.clone();
+        let handle = std::thread::Builder::new()
+            .name("ColdFileCleaner".to_string())
+            .spawn(move || {
+                while let Ok(()) = request_receiver.recv() {
+                    match remove_unreferenced_cold_files(&state_root, &cold_storage_root) {
+                        Ok(removed) => {
+                            debug!(log, "Removed {} unreferenced cold page files", removed)
+                        }
+                        Err(err) => warn!(
+                            log,
+                            "Failed to remove unreferenced cold page files: {:?}", err
+                        ),
+                    }
+                }
+            })
+            .expect("failed to spawn cold file cleaner");
+        Self {
+            request_sender,
+            _handle: JoinOnDrop::new(handle),
+        }
+    }
+
+    /// Requests the removal of the unreferenced cold page files, e.g. after a
+    /// checkpoint was created.
+    pub(crate) fn request_cleanup(&self) {
+        let _ = self.request_sender.try_send(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(cold_storage_root: &Path) -> TieredStorageConfig {
+        TieredStorageConfig {
+            cold_storage_root: cold_storage_root.to_path_buf(),
+            cold_after_checkpoints: 2,
+            min_heap_size_bytes: 0,
+        }
+    }
+
+    fn heap_page(heap_file: &Path, page: u64) -> Vec<u8> {
+        let file = File::open(heap_file).unwrap();
+        read_page(&file, heap_file, PageIndex::new(page)).unwrap()
+    }
+
+    #[test]
+    fn unaccessed_pages_move_to_cold_storage_and_back() {
+        let tmp = tempfile::Builder::new().prefix("test").tempdir().unwrap();
+        let cold_storage_root = tmp.path().join("cold");
+        std::fs::create_dir(&cold_storage_root).unwrap();
+        let heap_file = tmp.path().join("vmemory_0.bin");
+        let contents: Vec<u8> = (0..3u8).flat_map(|b| vec![b + 1; *PAGE_SIZE]).collect();
+        std::fs::write(&heap_file, &contents).unwrap();
+
+        let config = config(&cold_storage_root);
+        let accesses = PageAccesses {
+            modified: vec![PageIndex::new(1)].into_iter().collect(),
+            cold_reads: BTreeSet::new(),
+        };
+        update_cold_pages(Some(&config), &heap_file, &accesses).unwrap();
+        assert!(ColdPages::open(&heap_file).unwrap().unwrap().is_empty());
+
+        update_cold_pages(Some(&config), &heap_file, &accesses).unwrap();
+        let cold_pages = ColdPages::open(&heap_file).unwrap().unwrap();
+        assert_eq!(
+            cold_pages.page_indices().collect::<Vec<_>>(),
+            vec![PageIndex::new(0), PageIndex::new(2)]
+        );
+        assert_eq!(
+            cold_pages.get_page(PageIndex::new(2)).unwrap().unwrap(),
+            &vec![3; *PAGE_SIZE][..]
+        );
+        assert_eq!(heap_page(&heap_file, 1), vec![2; *PAGE_SIZE]);
+        if cfg!(target_os = "linux") {
+            assert_eq!(heap_page(&heap_file, 2), vec![0; *PAGE_SIZE]);
+        }
+        drop(cold_pages);
+
+        // Unreferenced files of earlier indices are removed.
+        assert_eq!(
+            remove_unreferenced_cold_files(tmp.path(), &cold_storage_root).unwrap(),
+            1
+        );
+        assert!(ColdPages::open(&heap_file).unwrap().is_some());
+
+        // Cold pages that were read become hot again.
+        let accesses = PageAccesses {
+            modified: BTreeSet::new(),
+            cold_reads: vec![PageIndex::new(2)].into_iter().collect(),
+        };
+        update_cold_pages(Some(&config), &heap_file, &accesses).unwrap();
+        let cold_pages = ColdPages::open(&heap_file).unwrap().unwrap();
+        assert_eq!(
+            cold_pages.page_indices().collect::<Vec<_>>(),
+            vec![PageIndex::new(0)]
+        );
+        assert_eq!(heap_page(&heap_file, 2), vec![3; *PAGE_SIZE]);
+        drop(cold_pages);
+
+        // Without tiered storage, the cold pages are written back.
+        update_cold_pages(None, &heap_file, &PageAccesses::default()).unwrap();
+        assert!(ColdPages::open(&heap_file).unwrap().is_none());
+        assert_eq!(std::fs::read(&heap_file).unwrap(), contents);
+    }
+}
@@ -0,0 +1,233 @@
+//! A cache of the witnesses of paths in the latest certified state.
+//!
+//! Building a witness requires materializing the requested part of the state
+//! tree and pruning the hash tree of the state, which `read_state` requests
+//! used to do from scratch for every request, although most requests ask for
+//! the same few paths (`/time`, the public key and canister ranges of a
+//! subnet, or the status of a request that the client polls). The cache holds
+//! the witnesses of a single certified height and is cleared when a newer
+//! height is certified.
+//!
+//! The witnesses of prefetched paths are computed in the background as soon
+//! as a height is certified. The witnesses of other paths are computed on the
+//! first read and kept until their total size exceeds
+//! `MAX_CACHED_WITNESS_BYTES`, after which the oldest ones are evicted.
+//!
+//! Witnesses are looked up by the SHA-256 hash of the requested paths, so a
+//! lookup does not compare the paths against every cached entry.
+
+use ic_crypto_sha256::Sha256;
+use ic_crypto_tree_hash::{LabeledTree, MixedHashTree};
+use ic_types::Height;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+/// The maximum total size of the cached witnesses of paths that are not
+/// prefetched.
+const MAX_CACHED_WITNESS_BYTES: usize = 16 << 20;
+
+/// The hash of a set of paths, under which their witness is cached.
+type PathsKey = [u8; 32];
+
+#[derive(Default)]
+pub(crate) struct WitnessCache {
+    /// The paths whose witnesses are computed for every certified height
+    prefetched_paths: BTreeMap<PathsKey, LabeledTree<()>>,
+
+    /// The certified height of the cached witnesses
+    height: Height,
+
+    /// The witnesses of the prefetched paths at `height`
+    prefetched: HashMap<PathsKey, MixedHashTree>,
+
+    /// The witnesses of other paths at `height`, with their sizes
+    recent: HashMap<PathsKey, (MixedHashTree, usize)>,
+
+    /// The keys of `recent`, the oldest first
+    recent_order: VecDeque<PathsKey>,
+
+    /// The total size of the witnesses in `recent`
+    recent_bytes: usize,
+}
+
+impl WitnessCache {
+    /// Returns the paths whose witnesses are computed for every certified
+    /// height.
+    pub(crate) fn prefetched_paths(&self) -> Vec<LabeledTree<()>> {
+        self.prefetched_paths.values().cloned().collect()
+    }
+
+    /// Adds the paths to the prefetched paths. Returns false if they were
+    /// prefetched already.
+    pub(crate) fn add_prefetched_paths(&mut self, paths: LabeledTree<()>) -> bool {
+        let key = paths_key(&paths);
+        if self.prefetched_paths.contains_key(&key) {
+            return false;
+        }
+        self.prefetched_paths.insert(key, paths);
+        true
+    }
+
+    /// Returns the witness of the paths at the given certified height, if it
+    /// is cached.
+    pub(crate) fn get(&self, height: Height, paths: &LabeledTree<()>) -> Option<MixedHashTree> {
+        if height != self.height {
+            return None;
+        }
+        let key = paths_key(paths);
+        self.prefetched
+            .get(&key)
+            .or_else(|| self.recent.get(&key).map(|(witness, _)| witness))
+            .cloned()
+    }
+
+    /// Caches the witness of the paths at the given certified height. Witnesses
+    /// of heights below the height of the cache are ignored.
+    pub(crate) fn insert(
+        &mut self,
+        height: Height,
+        paths: &LabeledTree<()>,
+        witness: MixedHashTree,
+    ) {
+        if height < self.height {
+            return;
+        }
+        if height > self.height {
+            self.height = height;
+            self.prefetched.clear();
+            self.recent.clear();
+            self.recent_order.clear();
+            self.recent_bytes = 0;
+        }
+        let key = paths_key(paths);
+        if self.prefetched_paths.contains_key(&key) {
+            self.prefetched.insert(key, witness);
+            return;
+        }
+        if self.recent.contains_key(&key) {
+            return;
+        }
+        let size_bytes = witness_size_bytes(&witness);
+        if size_bytes > MAX_CACHED_WITNESS_BYTES {
+            return;
+        }
+        while self.recent_bytes + size_bytes > MAX_CACHED_WITNESS_BYTES {
+            let oldest = match self.recent_order.pop_front() {
+                Some(oldest) => oldest,
+                None => break,
+            };
+            if let Some((_, oldest_size_bytes)) = self.recent.remove(&oldest) {
+                self.recent_bytes -= oldest_size_bytes;
+            }
+        }
+        self.recent.insert(key, (witness, size_bytes));
+        self.recent_order.push_back(key);
+        self.recent_bytes += size_bytes;
+    }
+}
+
+/// Hashes the paths such that different sets of paths have different hashes.
+fn paths_key(paths: &LabeledTree<()>) -> PathsKey {
+    fn hash_tree(hasher: &mut Sha256, tree: &LabeledTree<()>) {
+        match tree {
+            LabeledTree::Leaf(()) => hasher.write(&[0]),
+            LabeledTree::SubTree(children) => {
+                hasher.write(&[1]);
+                hasher.write(&(children.len() as u64).to_be_bytes());
+                for (label, child) in children.iter() {
+                    hasher.write(&(label.as_bytes().len() as u64).to_be_bytes());
+                    hasher.write(label.as_bytes());
+                    hash_tree(hasher, child);
+                }
+            }
+        }
+    }
+
+    let mut hasher = Sha256::new();
+    hash_tree(&mut hasher, paths);
+    hasher.finish()
+}
+
+/// Estimates the memory used by the witness.
+fn witness_size_bytes(witness: &MixedHashTree) -> usize {
+    std::mem::size_of::<MixedHashTree>()
+        + match witness {
+            MixedHashTree::Empty | MixedHashTree::Pruned(_) => 0,
+            MixedHashTree::Fork(lr) => witness_size_bytes(&lr.0) + witness_size_bytes(&lr.1),
+            MixedHashTree::Labeled(label, subtree) => {
+                label.as_bytes().len() + witness_size_bytes(subtree)
+            }
+            MixedHashTree::Leaf(buf) => buf.len(),
+        }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_crypto_tree_hash::{flatmap, Label};
+
+    fn paths(i: u64) -> LabeledTree<()> {
+        LabeledTree::SubTree(flatmap! {
+            Label::from(i.to_be_bytes().to_vec()) => LabeledTree::Leaf(())
+        })
+    }
+
+    fn witness(size_bytes: usize) -> MixedHashTree {
+        MixedHashTree::Leaf(vec![0; size_bytes])
+    }
+
+    #[test]
+    fn oldest_witnesses_are_evicted_beyond_the_byte_limit() {
+        let mut cache = WitnessCache::default();
+        let size_bytes = MAX_CACHED_WITNESS_BYTES / 4;
+        let height = Height::from(1);
+        for i in 0..5 {
+            cache.insert(height, &paths(i), witness(size_bytes));
+        }
+
+        assert!(cache.recent_bytes <= MAX_CACHED_WITNESS_BYTES);
+        assert_eq!(cache.get(height, &paths(0)), None);
+        assert_eq!(cache.get(height, &paths(1)), None);
+        for i in 2..5 {
+            assert_eq!(cache.get(height, &paths(i)), Some(witness(size_bytes)));
+        }
+    }
+
+    #[test]
+    fn witnesses_above_the_byte_limit_are_not_cached() {
+        let mut cache = WitnessCache::default();
+        let height = Height::from(1);
+        cache.insert(height, &paths(0), witness(1));
+        cache.insert(height, &paths(1), witness(MAX_CACHED_WITNESS_BYTES));
+
+        assert_eq!(cache.get(height, &paths(0)), Some(witness(1)));
+        assert_eq!(cache.get(height, &paths(1)), None);
+    }
+
+    #[test]
+    fn prefetched_witnesses_are_not_evicted() {
+        let mut cache = WitnessCache::default();
+        let height = Height::from(1);
+        assert!(cache.add_prefetched_paths(paths(0)));
+        assert!(!cache.add_prefetched_paths(paths(0)));
+        cache.insert(height, &paths(0), witness(1));
+        for i in 1..10 {
+            cache.insert(height, &paths(i), witness(MAX_CACHED_WITNESS_BYTES / 2));
+        }
+
+        assert_eq!(cache.get(height, &paths(0)), Some(witness(1)));
+    }
+
+    #[test]
+    fn newer_height_clears_the_cache() {
+        let mut cache = WitnessCache::default();
+        cache.insert(Height::from(1), &paths(0), witness(1));
+        cache.insert(Height::from(2), &paths(1), witness(1));
+        cache.insert(Height::from(1), &paths(2), witness(1));
+
+        assert_eq!(cache.get(Height::from(1), &paths(0)), None);
+        assert_eq!(cache.get(Height::from(2), &paths(0)), None);
+        assert_eq!(cache.get(Height::from(2), &paths(1)), Some(witness(1)));
+        assert_eq!(cache.get(Height::from(2), &paths(2)), None);
+        assert_eq!(cache.recent_bytes, witness_size_bytes(&witness(1)));
+    }
+}
@@ -1,4 +1,5 @@
-use ic_config::state_manager::Config;
+use assert_matches::assert_matches;
+use ic_config::state_manager::{Config, ScrubberConfig, StatePruningPolicy};
 use ic_crypto_tree_hash::{flatmap, Label, LabeledTree, MixedHashTree};
 use ic_interfaces::{
     artifact_manager::{ArtifactClient, ArtifactProcessor},
@@ -6,9 +7,15 @@ use ic_interfaces::{
     certified_stream_store::{CertifiedStreamStore, EncodeStreamError},
     state_manager::*,
 };
+use ic_logger::replica_logger::no_op_logger;
 use ic_metrics::MetricsRegistry;
+use ic_registry_routing_table::CanisterIdRange;
 use ic_replicated_state::{NumWasmPages, PageMap, ReplicatedState, Stream};
-use ic_state_manager::StateManagerImpl;
+use ic_state_layout::StateLayout;
+use ic_state_manager::{
+    checkpoint::load_checkpoint, tree_hash::hash_state, CheckpointError, PinHeightError,
+    StateManagerImpl, MAX_PINNED_HEIGHTS,
+};
 use ic_test_utilities::{
     consensus::fake::FakeVerifier,
     metrics::fetch_int_gauge,
@@ -64,6 +71,59 @@ fn label<T: Into<Label>>(t: T) -> Label {
     t.into()
 }
 
+#[test]
+fn only_existing_heights_can_be_pinned_up_to_the_limit() {
+    state_manager_test(|state_manager| {
+        assert_eq!(
+            state_manager.pin_height(height(1)),
+            Err(PinHeightError::StateNotFound(height(1)))
+        );
+
+        for h in 1..=MAX_PINNED_HEIGHTS as u64 + 1 {
+            let (_height, state) = state_manager.take_tip();
+            state_manager.commit_and_certify(state, height(h), CertificationScope::Metadata);
+        }
+        for h in 1..=MAX_PINNED_HEIGHTS as u64 {
+            state_manager.pin_height(height(h)).unwrap();
+        }
+        // Pinning a height again is a no-op.
+        state_manager.pin_height(height(1)).unwrap();
+
+        assert_eq!(
+            state_manager.pin_height(height(MAX_PINNED_HEIGHTS as u64 + 1)),
+            Err(PinHeightError::TooManyPins(MAX_PINNED_HEIGHTS))
+        );
+        assert_eq!(state_manager.pinned_heights().len(), MAX_PINNED_HEIGHTS);
+    });
+}
+
+#[test]
+fn pinned_checkpoints_survive_restart() {
+    state_manager_restart_test(|state_manager, restart_fn| {
+        for h in 1..=3 {
+            let (_height, state) = state_manager.take_tip();
+            state_manager.commit_and_certify(state, height(h), CertificationScope::Full);
+            wait_for_checkpoint(&state_manager, height(h));
+        }
+        state_manager.pin_height(height(1)).unwrap();
+
+        let state_manager = restart_fn(state_manager);
+        assert_eq!(state_manager.pinned_heights(), vec![height(1)]);
+
+        state_manager.remove_states_below(height(3));
+        assert!(state_manager
+            .state_layout()
+            .checkpoint_heights()
+            .unwrap()
+            .contains(&height(1)));
+
+        // Unpinning is persisted as well.
+        assert!(state_manager.unpin_height(height(1)));
+        let state_manager = restart_fn(state_manager);
+        assert!(state_manager.pinned_heights().is_empty());
+    });
+}
+
 #[test]
 fn tip_can_be_recovered_if_no_checkpoint_exists() {
     // three scenarios
@@ -735,6 +795,311 @@ fn can_remove_below_future_states_once_reached() {
     });
 }
 
+#[test]
+fn removal_below_a_pending_checkpoint_is_deferred_until_it_is_promoted() {
+    state_manager_test(|state_manager| {
+        for h in 1..=3 {
+            let (_height, state) = state_manager.take_tip();
+            state_manager.commit_and_certify(state, height(h), CertificationScope::Full);
+        }
+
+        // Doesn't block on the checkpoints that are still being promoted.
+        state_manager.remove_states_below(height(3));
+        wait_for_checkpoint(&state_manager, height(3));
+
+        let (_height, state) = state_manager.take_tip();
+        state_manager.commit_and_certify(state, height(4), CertificationScope::Metadata);
+
+        for h in 1..3 {
+            assert_eq!(
+                state_manager.get_state_at(height(h)),
+                Err(StateManagerError::StateRemoved(height(h)))
+            );
+        }
+        assert!(state_manager.get_state_at(height(3)).is_ok());
+    });
+}
+
+#[test]
+fn pinned_states_are_not_removed() {
+    state_manager_test(|state_manager| {
+        let commit_state = |h| {
+            let (_height, state) = state_manager.take_tip();
+            state_manager.commit_and_certify(state, height(h), CertificationScope::Full);
+            wait_for_checkpoint(&state_manager, height(h));
+        };
+
+        commit_state(1);
+        commit_state(2);
+        commit_state(3);
+
+        state_manager.pin_height(height(1)).unwrap();
+        assert_eq!(state_manager.pinned_heights(), vec![height(1)]);
+
+        state_manager.remove_states_below(height(3));
+
+        assert!(state_manager.get_state_at(height(1)).is_ok());
+        assert_eq!(
+            state_manager.get_state_at(height(2)),
+            Err(StateManagerError::StateRemoved(height(2)))
+        );
+        assert!(state_manager
+            .state_layout()
+            .checkpoint_heights()
+            .unwrap()
+            .contains(&height(1)));
+
+        assert!(state_manager.unpin_height(height(1)));
+        assert!(!state_manager.unpin_height(height(1)));
+
+        state_manager.remove_states_below(height(3));
+
+        assert_eq!(
+            state_manager.get_state_at(height(1)),
+            Err(StateManagerError::StateRemoved(height(1)))
+        );
+    });
+}
+
+#[test]
+fn pruning_policy_keeps_recent_and_interval_checkpoints() {
+    let tmp = Builder::new().prefix("test").tempdir().unwrap();
+    let config = Config::new(tmp.path().into()).with_pruning_policy(StatePruningPolicy {
+        recent_checkpoints: 2,
+        interval_length: Some(4),
+    });
+
+    with_test_replica_logger(|log| {
+        let state_manager = StateManagerImpl::new(
+            Arc::new(FakeVerifier::new()),
+            subnet_test_id(42),
+            SubnetType::Application,
+            log,
+            &MetricsRegistry::new(),
+            &config,
+            ic_types::malicious_flags::MaliciousFlags::default(),
+        );
+
+        for h in 1..=7 {
+            let (_height, state) = state_manager.take_tip();
+            state_manager.commit_and_certify(state, height(h), CertificationScope::Full);
+            wait_for_checkpoint(&state_manager, height(h));
+        }
+
+        state_manager.remove_states_below(height(7));
+
+        // Heights 1 and 4 start the intervals [0, 4) and [4, 8); heights 6
+        // and 7 are the two most recent checkpoints.
+        let checkpoint_heights = state_manager.state_layout().checkpoint_heights().unwrap();
+        for h in &[1, 4, 6, 7] {
+            assert!(checkpoint_heights.contains(&height(*h)));
+            assert!(state_manager.get_state_at(height(*h)).is_ok());
+        }
+        assert_eq!(
+            state_manager.get_state_at(height(5)),
+            Err(StateManagerError::StateRemoved(height(5)))
+        );
+    });
+}
+
+/// Overwrites the beginning of the state of the given canister in the
+/// checkpoint at height `h`.
+fn corrupt_canister_state(state_manager: &StateManagerImpl, h: Height, canister_id: CanisterId) {
+    use ic_state_layout::{CheckpointLayout, RwPolicy};
+
+    let cp_layout = CheckpointLayout::<RwPolicy>::new(
+        state_manager
+            .state_layout()
+            .checkpoint(h)
+            .unwrap()
+            .raw_path()
+            .to_path_buf(),
+        h,
+    )
+    .unwrap();
+    let canister_pb = cp_layout
+        .canister(&canister_id)
+        .unwrap()
+        .canister()
+        .raw_path()
+        .to_path_buf();
+    make_mutable(&canister_pb).unwrap();
+    write_at(&canister_pb, b"Garbage", 0).unwrap();
+}
+
+/// Waits until the scrubber found a corrupted checkpoint.
+fn wait_for_corrupted_checkpoint(state_manager: &StateManagerImpl) {
+    use std::time::{Duration, Instant};
+
+    let started = Instant::now();
+    while state_manager.corrupted_checkpoint_heights().is_empty() {
+        assert!(
+            started.elapsed() < Duration::from_secs(10),
+            "the scrubber didn't detect the corruption"
+        );
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
+
+fn scrubber_config() -> ScrubberConfig {
+    ScrubberConfig {
+        enabled: true,
+        chunk_pause_millis: 0,
+        pass_pause_seconds: 1,
+    }
+}
+
+#[test]
+fn scrubber_detects_corrupted_checkpoints() {
+    let tmp = Builder::new().prefix("test").tempdir().unwrap();
+    let config = Config::new(tmp.path().into()).with_scrubber(scrubber_config());
+
+    with_test_replica_logger(|log| {
+        let state_manager = StateManagerImpl::new(
+            Arc::new(FakeVerifier::new()),
+            subnet_test_id(42),
+            SubnetType::Application,
+            log,
+            &MetricsRegistry::new(),
+            &config,
+            ic_types::malicious_flags::MaliciousFlags::default(),
+        );
+
+        let (_height, mut state) = state_manager.take_tip();
+        insert_dummy_canister(&mut state, canister_test_id(100));
+        state_manager.commit_and_certify(state, height(1), CertificationScope::Full);
+        let hash = wait_for_checkpoint(&state_manager, height(1));
+
+        let id = StateSyncArtifactId {
+            height: height(1),
+            hash,
+        };
+        assert!(state_manager.get_validated_by_identifier(&id).is_some());
+        assert!(state_manager.corrupted_checkpoint_heights().is_empty());
+
+        corrupt_canister_state(&state_manager, height(1), canister_test_id(100));
+        wait_for_corrupted_checkpoint(&state_manager);
+
+        assert_eq!(
+            state_manager.corrupted_checkpoint_heights(),
+            vec![height(1)]
+        );
+        assert!(state_manager.get_validated_by_identifier(&id).is_none());
+        assert!(!state_manager.has_artifact(&id));
+    });
+}
+
+#[test]
+fn corrupted_checkpoints_stay_corrupted_after_restart() {
+    let tmp = Builder::new().prefix("test").tempdir().unwrap();
+    let new_state_manager = |log, config: &Config| {
+        StateManagerImpl::new(
+            Arc::new(FakeVerifier::new()),
+            subnet_test_id(42),
+            SubnetType::Application,
+            log,
+            &MetricsRegistry::new(),
+            config,
+            ic_types::malicious_flags::MaliciousFlags::default(),
+        )
+    };
+
+    with_test_replica_logger(|log| {
+        let config = Config::new(tmp.path().into()).with_scrubber(scrubber_config());
+        let state_manager = new_state_manager(log.clone(), &config);
+        let (_height, mut state) = state_manager.take_tip();
+        insert_dummy_canister(&mut state, canister_test_id(100));
+        state_manager.commit_and_certify(state, height(1), CertificationScope::Full);
+        let hash = wait_for_checkpoint(&state_manager, height(1));
+
+        corrupt_canister_state(&state_manager, height(1), canister_test_id(100));
+        wait_for_corrupted_checkpoint(&state_manager);
+
+        // The replica restarts from the latest checkpoint, which is intact.
+        let (_height, state) = state_manager.take_tip();
+        state_manager.commit_and_certify(state, height(2), CertificationScope::Full);
+        wait_for_checkpoint(&state_manager, height(2));
+        drop(state_manager);
+
+        // The scrubber is disabled by default, the mark is read from disk.
+        let state_manager = new_state_manager(log, &Config::new(tmp.path().into()));
+        assert_eq!(
+            state_manager.corrupted_checkpoint_heights(),
+            vec![height(1)]
+        );
+        let id = StateSyncArtifactId {
+            height: height(1),
+            hash,
+        };
+        assert!(state_manager.get_validated_by_identifier(&id).is_none());
+    });
+}
+
+#[test]
+fn can_split_state() {
+    state_manager_test(|state_manager| {
+        let (_height, mut state) = state_manager.take_tip();
+        insert_dummy_canister(&mut state, canister_test_id(100));
+        insert_dummy_canister(&mut state, canister_test_id(200));
+        state_manager.commit_and_certify(state, height(1), CertificationScope::Full);
+
+        let own_root = Builder::new().prefix("own_split").tempdir().unwrap();
+        let new_root = Builder::new().prefix("new_split").tempdir().unwrap();
+        let range = CanisterIdRange {
+            start: canister_test_id(200),
+            end: canister_test_id(299),
+        };
+        let (own, new) = state_manager
+            .split_state(
+                height(1),
+                &range,
+                subnet_test_id(43),
+                own_root.path().into(),
+                new_root.path().into(),
+            )
+            .unwrap();
+
+        assert!(own.state.canister_state(&canister_test_id(100)).is_some());
+        assert!(own.state.canister_state(&canister_test_id(200)).is_none());
+        assert!(new.state.canister_state(&canister_test_id(100)).is_none());
+        assert!(new.state.canister_state(&canister_test_id(200)).is_some());
+        assert_eq!(new.state.metadata.own_subnet_id, subnet_test_id(43));
+
+        for (split, root) in &[(own, own_root.path()), (new, new_root.path())] {
+            let hash_tree = hash_state(&split.state);
+            assert_eq!(
+                split.hash,
+                CryptoHashOfPartialState::from(CryptoHash(hash_tree.root_hash().0.to_vec()))
+            );
+
+            // The split state is persisted as the checkpoint at the split height.
+            let layout = StateLayout::new(no_op_logger(), root.to_path_buf());
+            assert_eq!(layout.checkpoint_heights().unwrap(), vec![height(1)]);
+            let checkpoint = layout.checkpoint(height(1)).unwrap();
+            let loaded = load_checkpoint(&checkpoint, SubnetType::Application).unwrap();
+            assert_eq!(
+                loaded.canister_states.keys().collect::<Vec<_>>(),
+                split.state.canister_states.keys().collect::<Vec<_>>()
+            );
+            assert_eq!(
+                loaded.metadata.own_subnet_id,
+                split.state.metadata.own_subnet_id
+            );
+        }
+
+        assert_matches!(
+            state_manager.split_state(
+                height(2),
+                &range,
+                subnet_test_id(43),
+                own_root.path().join("missing"),
+                new_root.path().join("missing"),
+            ),
+            Err(CheckpointError::NotFound(h)) if h == height(2)
+        );
+    });
+}
+
 #[test]
 fn latest_certified_state_is_updated_on_state_removal() {
     state_manager_test(|state_manager| {
@@ -895,6 +1260,49 @@ fn delivers_state_adverts_once() {
     });
 }
 
+#[test]
+fn checkpoint_is_not_affected_by_later_rounds() {
+    use ic_replicated_state::page_map::{PageDelta, PageIndex};
+    use ic_sys::PAGE_SIZE;
+
+    fn write_page(state: &mut ReplicatedState, byte: u8) {
+        let canister_state = state.canister_state_mut(&canister_test_id(100)).unwrap();
+        let execution_state = canister_state.execution_state.as_mut().unwrap();
+        execution_state.page_map.update(PageDelta::from(
+            &[(PageIndex::new(1), &vec![byte; *PAGE_SIZE][..])][..],
+        ));
+    }
+
+    state_manager_restart_test(|state_manager, restart_fn| {
+        let (_height, mut state) = state_manager.take_tip();
+        insert_dummy_canister(&mut state, canister_test_id(100));
+        write_page(&mut state, 1);
+        state_manager.commit_and_certify(state, height(1), CertificationScope::Full);
+
+        // The checkpoint is promoted in the background while the next round
+        // modifies the tip.
+        let (_height, mut state) = state_manager.take_tip();
+        write_page(&mut state, 2);
+        state_manager.commit_and_certify(state, height(2), CertificationScope::Metadata);
+        wait_for_checkpoint(&state_manager, height(1));
+
+        let state_manager = restart_fn(state_manager);
+
+        let (tip_height, state) = state_manager.take_tip();
+        assert_eq!(height(1), tip_height);
+        let execution_state = state
+            .canister_state(&canister_test_id(100))
+            .unwrap()
+            .execution_state
+            .as_ref()
+            .unwrap();
+        assert_eq!(
+            execution_state.page_map.get_page(PageIndex::new(1)),
+            &vec![1u8; *PAGE_SIZE][..]
+        );
+    });
+}
+
 #[test]
 fn recomputes_metadata_on_restart_if_missing() {
     state_manager_restart_test(|state_manager, restart_fn| {
@@ -1176,6 +1584,116 @@ fn can_state_sync_based_on_old_checkpoint() {
     });
 }
 
+#[test]
+fn can_resume_interrupted_state_sync() {
+    use ic_types::{
+        chunkable::{ArtifactErrorCode::ChunksMoreNeeded, Chunkable, ChunkableArtifact},
+        state_sync::MANIFEST_CHUNK,
+    };
+
+    fn add_chunk(
+        msg: &ic_types::artifact::StateSyncMessage,
+        chunkable: &mut Box<dyn Chunkable + Send + Sync>,
+        id: ic_types::chunkable::ChunkId,
+    ) {
+        let chunk = Box::new(msg.clone())
+            .get_chunk(id)
+            .unwrap_or_else(|| panic!("Requested unknown chunk {}", id));
+        assert!(matches!(chunkable.add_chunk(chunk), Err(ChunksMoreNeeded)));
+    }
+
+    state_manager_test(|src_state_manager| {
+        let (_height, mut state) = src_state_manager.take_tip();
+        insert_dummy_canister(&mut state, canister_test_id(100));
+        insert_dummy_canister(&mut state, canister_test_id(200));
+        src_state_manager.commit_and_certify(state, height(1), CertificationScope::Full);
+
+        let hash = wait_for_checkpoint(&src_state_manager, height(1));
+        let id = StateSyncArtifactId {
+            height: height(1),
+            hash,
+        };
+        let msg = src_state_manager
+            .get_validated_by_identifier(&id)
+            .expect("failed to get state sync message");
+
+        state_manager_test(|dst_state_manager| {
+            // Fetch a single chunk, then interrupt the state sync.
+            let mut chunkable = dst_state_manager.create_chunkable_state(&id);
+            add_chunk(&msg, &mut chunkable, MANIFEST_CHUNK);
+            let chunks: Vec<_> = chunkable.chunks_to_download().collect();
+            assert!(chunks.len() > 1);
+            add_chunk(&msg, &mut chunkable, chunks[0]);
+            drop(chunkable);
+
+            // The resumed state sync doesn't fetch that chunk again.
+            let mut chunkable = dst_state_manager.create_chunkable_state(&id);
+            add_chunk(&msg, &mut chunkable, MANIFEST_CHUNK);
+            let remaining_chunks: Vec<_> = chunkable.chunks_to_download().collect();
+            assert_eq!(remaining_chunks.len(), chunks.len() - 1);
+            assert!(!remaining_chunks.contains(&chunks[0]));
+
+            let dst_msg = pipe_state_sync(msg, chunkable);
+            dst_state_manager
+                .check_artifact_acceptance(dst_msg, &node_test_id(0))
+                .expect("failed to process state sync artifact");
+
+            let expected_state = src_state_manager.get_latest_state();
+            assert_eq!(dst_state_manager.get_latest_state(), expected_state);
+        })
+    });
+}
+
+#[test]
+fn interrupted_state_sync_is_removed_once_superseded() {
+    use ic_types::{
+        chunkable::{ArtifactErrorCode::ChunksMoreNeeded, Chunkable, ChunkableArtifact},
+        state_sync::MANIFEST_CHUNK,
+    };
+
+    state_manager_test(|src_state_manager| {
+        let (_height, mut state) = src_state_manager.take_tip();
+        insert_dummy_canister(&mut state, canister_test_id(100));
+        src_state_manager.commit_and_certify(state, height(1), CertificationScope::Full);
+
+        let hash = wait_for_checkpoint(&src_state_manager, height(1));
+        let id = StateSyncArtifactId {
+            height: height(1),
+            hash,
+        };
+        let msg = src_state_manager
+            .get_validated_by_identifier(&id)
+            .expect("failed to get state sync message");
+
+        state_manager_test(|dst_state_manager| {
+            let mut chunkable = dst_state_manager.create_chunkable_state(&id);
+            let chunk = Box::new(msg.clone()).get_chunk(MANIFEST_CHUNK).unwrap();
+            assert!(matches!(chunkable.add_chunk(chunk), Err(ChunksMoreNeeded)));
+            drop(chunkable);
+            assert_eq!(
+                dst_state_manager
+                    .state_layout()
+                    .state_sync_scratchpad_heights()
+                    .unwrap(),
+                vec![height(1)]
+            );
+
+            // The replica catches up by itself, so the state sync won't be
+            // resumed.
+            let (_height, state) = dst_state_manager.take_tip();
+            dst_state_manager.commit_and_certify(state, height(1), CertificationScope::Full);
+            wait_for_checkpoint(&dst_state_manager, height(1));
+            dst_state_manager.remove_states_below(height(1));
+
+            assert!(dst_state_manager
+                .state_layout()
+                .state_sync_scratchpad_heights()
+                .unwrap()
+                .is_empty());
+        })
+    });
+}
+
 #[test]
 fn can_recover_from_corruption_on_state_sync() {
     use ic_replicated_state::page_map::{PageDelta, PageIndex};
@@ -1465,6 +1983,38 @@ fn certified_read_can_certify_time() {
     })
 }
 
+#[test]
+fn prefetched_paths_are_read_at_every_certified_height() {
+    use std::time::Duration;
+    use LabeledTree::*;
+
+    state_manager_test(|state_manager| {
+        let path: LabeledTree<()> = LabeledTree::SubTree(flatmap! {
+            label("time") => Leaf(())
+        });
+        state_manager.prefetch_certified_paths(path.clone());
+
+        for h in 1..3 {
+            let (_, mut state) = state_manager.take_tip();
+            state.metadata.batch_time += Duration::new(0, 10);
+            state_manager.commit_and_certify(state, height(h), CertificationScope::Metadata);
+            let delivered_certification = certify_height(&state_manager, height(h));
+
+            for _ in 0..2 {
+                let (_state, mixed_tree, cert) = state_manager
+                    .read_certified_state(&path)
+                    .expect("failed to read certified state");
+
+                assert_eq!(cert, delivered_certification);
+                assert_eq!(
+                    tree_payload(mixed_tree),
+                    SubTree(flatmap!(label("time") => Leaf(vec![10 * h as u8])))
+                );
+            }
+        }
+    })
+}
+
 #[test]
 fn certified_read_can_certify_canister_data() {
     use LabeledTree::*;
@@ -1,5 +1,6 @@
 pub mod fs;
 pub mod mmap;
+pub mod tls;
 pub mod utility_command;
 
 use lazy_static::lazy_static;
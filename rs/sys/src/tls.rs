@@ -0,0 +1,53 @@
+//! Resumption of TLS sessions on the client side.
+//!
+//! Setting the session that a connection offers for resumption is unsafe in
+//! `openssl`, because the session must have been established with the same
+//! context as the connection. `SessionResumingConnector` upholds this by only
+//! ever offering sessions handed out by its own context, so that crates that
+//! forbid unsafe code can resume sessions.
+
+use openssl::error::ErrorStack;
+use openssl::ssl::{
+    ConnectConfiguration, SslConnector, SslConnectorBuilder, SslSession, SslSessionCacheMode,
+};
+use std::sync::{Arc, Mutex};
+
+/// A TLS connector that offers the most recent session it established when
+/// configuring a new connection.
+#[derive(Clone)]
+pub struct SessionResumingConnector {
+    connector: SslConnector,
+    session: Arc<Mutex<Option<SslSession>>>,
+}
+
+impl SessionResumingConnector {
+    /// Builds the connector from the given builder, recording the sessions
+    /// established by the connections of the connector.
+    pub fn new(mut builder: SslConnectorBuilder) -> Self {
+        let session = Arc::new(Mutex::new(None));
+        let new_session = Arc::clone(&session);
+        builder.set_session_cache_mode(SslSessionCacheMode::CLIENT);
+        builder.set_new_session_callback(move |_ssl, ssl_session| {
+            if let Ok(mut session) = new_session.lock() {
+                *session = Some(ssl_session);
+            }
+        });
+        Self {
+            connector: builder.build(),
+            session,
+        }
+    }
+
+    /// Returns the configuration for a new connection, which offers the most
+    /// recent session of the connector for resumption, if any.
+    pub fn configure(&self) -> Result<ConnectConfiguration, ErrorStack> {
+        let mut connect_config = self.connector.configure()?;
+        if let Some(session) = self.session.lock().ok().and_then(|s| s.clone()) {
+            // SAFETY: the session was handed out by the new session callback
+            // of `self.connector`, i.e., it is associated with the same
+            // context as the connection configuration.
+            unsafe { connect_config.set_session(&session)? };
+        }
+        Ok(connect_config)
+    }
+}
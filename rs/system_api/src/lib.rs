@@ -3260,7 +3260,7 @@ mod test {
         let api = get_system_api(
             get_update_api_type(),
             running_system_state,
-            cycles_account_manager,
+            cycles_account_manager.clone(),
         );
         assert_eq!(api.ic0_canister_status(), Ok(1));
 
@@ -3273,7 +3273,7 @@ mod test {
         let api = get_system_api(
             get_update_api_type(),
             stopping_system_state,
-            cycles_account_manager,
+            cycles_account_manager.clone(),
         );
         assert_eq!(api.ic0_canister_status(), Ok(2));
 
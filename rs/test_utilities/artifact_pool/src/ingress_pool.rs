@@ -10,7 +10,7 @@ use ic_interfaces::{
 };
 use ic_logger::replica_logger::no_op_logger;
 use ic_metrics::MetricsRegistry;
-use ic_types::{messages::SignedIngress, Time};
+use ic_types::{messages::SignedIngress, CanisterId, Time};
 
 pub struct TestIngressPool {
     pub pool: IngressPoolImpl,
@@ -38,6 +38,10 @@ impl IngressPoolThrottler for TestIngressPool {
     fn exceeds_threshold(&self) -> bool {
         self.pool.exceeds_threshold()
     }
+
+    fn exceeds_canister_quota(&self, canister_id: &CanisterId, size_bytes: usize) -> bool {
+        self.pool.exceeds_canister_quota(canister_id, size_bytes)
+    }
 }
 
 impl MutableIngressPool for TestIngressPool {
@@ -7,7 +7,7 @@ use crate::crypto::empty_ni_dkg_transcripts_with_committee;
 use ic_consensus_message::{make_genesis, ConsensusMessageHashable};
 use ic_interfaces::{
     consensus::*,
-    consensus_pool::{ChangeAction, ChangeSet, ConsensusPool, ConsensusPoolCache},
+    consensus_pool::{ChangeAction, ChangeSet, ConsensusPool, ConsensusPoolCache, HeightWatch},
     ingress_pool::IngressPoolSelect,
     registry::RegistryClient,
     validation::*,
@@ -19,6 +19,7 @@ use ic_types::{
     Height, SubnetId, Time,
 };
 use std::sync::{Arc, RwLock};
+use tokio::sync::watch;
 
 #[macro_export]
 macro_rules! assert_changeset_matches_pattern {
@@ -85,6 +86,10 @@ mock! {
         fn summary_block(&self) -> Block;
 
         fn cup_with_protobuf(&self) -> CUPWithOriginalProtobuf;
+
+        fn subscribe_finalized_height(&self) -> watch::Receiver<Height>;
+
+        fn subscribe_certified_height(&self) -> watch::Receiver<Height>;
     }
 }
 
@@ -97,6 +102,8 @@ struct CachedData {
 
 pub struct FakeConsensusPoolCache {
     cache: RwLock<CachedData>,
+    finalized_height: HeightWatch,
+    certified_height: HeightWatch,
 }
 
 // FakeConsensusPoolCache. Used as fake which allows for updating CUP and blocks
@@ -105,6 +112,8 @@ impl FakeConsensusPoolCache {
     pub fn new(catch_up_package: CUPWithOriginalProtobuf) -> Self {
         let latest_block = catch_up_package.cup.content.block.as_ref();
         Self {
+            finalized_height: HeightWatch::new(latest_block.height()),
+            certified_height: HeightWatch::new(latest_block.context.certified_height),
             cache: RwLock::new(CachedData {
                 finalized_block: latest_block.clone(),
                 summary_block: latest_block.clone(),
@@ -118,6 +127,9 @@ impl FakeConsensusPoolCache {
         let cache = &mut *self.cache.write().unwrap();
         cache.finalized_block = latest_block.clone();
         cache.summary_block = latest_block.clone();
+        self.finalized_height.update(latest_block.height());
+        self.certified_height
+            .update(latest_block.context.certified_height);
         cache.catch_up_package = catch_up_package;
     }
 }
@@ -147,6 +159,14 @@ impl ConsensusPoolCache for FakeConsensusPoolCache {
     fn summary_block(&self) -> Block {
         self.cache.read().unwrap().summary_block.clone()
     }
+
+    fn subscribe_finalized_height(&self) -> watch::Receiver<Height> {
+        self.finalized_height.subscribe()
+    }
+
+    fn subscribe_certified_height(&self) -> watch::Receiver<Height> {
+        self.certified_height.subscribe()
+    }
 }
 
 /// Return a CatchUpPackage created with empty transcript, from the given
@@ -451,6 +451,14 @@ impl KeyManager for CryptoReturningOk {
     fn node_public_keys(&self) -> NodePublicKeys {
         unimplemented!()
     }
+
+    fn generate_rotated_node_keys(&self) -> CryptoResult<NodePublicKeys> {
+        unimplemented!()
+    }
+
+    fn remove_rotated_out_node_keys(&self, _rotated_out_keys: &NodePublicKeys) -> CryptoResult<()> {
+        unimplemented!()
+    }
 }
 
 pub fn mock_random_number_generator() -> Box<dyn RngCore> {
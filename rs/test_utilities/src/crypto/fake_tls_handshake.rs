@@ -1,7 +1,7 @@
 use async_trait::async_trait;
 use ic_crypto_tls_interfaces::{
     AllowedClients, AuthenticatedPeer, Peer, TlsClientHandshakeError, TlsHandshake,
-    TlsServerHandshakeError, TlsStream,
+    TlsServerHandshakeError, TlsStream, TlsValidation,
 };
 use ic_types::{NodeId, RegistryVersion};
 use tokio::net::TcpStream;
@@ -58,4 +58,24 @@ impl TlsHandshake for FakeTlsHandshake {
     ) -> Result<TlsStream, TlsClientHandshakeError> {
         unimplemented!()
     }
+
+    async fn perform_tls_server_handshake_with_validation(
+        &self,
+        _tcp_stream: TcpStream,
+        _allowed_clients: AllowedClients,
+        _validation: &TlsValidation,
+        _registry_version: RegistryVersion,
+    ) -> Result<(TlsStream, AuthenticatedPeer), TlsServerHandshakeError> {
+        unimplemented!()
+    }
+
+    async fn perform_tls_client_handshake_with_validation(
+        &self,
+        _tcp_stream: TcpStream,
+        _server: NodeId,
+        _validation: &TlsValidation,
+        _registry_version: RegistryVersion,
+    ) -> Result<TlsStream, TlsClientHandshakeError> {
+        unimplemented!()
+    }
 }
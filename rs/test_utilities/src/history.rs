@@ -4,6 +4,7 @@ use ic_interfaces::execution_environment::{
 use ic_replicated_state::ReplicatedState;
 use ic_types::{ingress::IngressStatus, messages::MessageId, Height};
 use mockall::*;
+use tokio::sync::{mpsc, oneshot};
 
 mock! {
     pub IngressHistory {}
@@ -26,5 +27,15 @@ mock! {
             &self,
             height: Height,
         ) -> Result<Box<dyn Fn(&MessageId) -> IngressStatus>, IngressHistoryError>;
+
+        fn subscribe_to_terminal_status(
+            &self,
+            message_id: MessageId,
+        ) -> oneshot::Receiver<IngressStatus>;
+
+        fn subscribe_to_status_transitions(
+            &self,
+            message_id: MessageId,
+        ) -> mpsc::UnboundedReceiver<IngressStatus>;
     }
 }
@@ -4,8 +4,8 @@
 #![allow(clippy::ptr_arg)]
 
 use ic_interfaces::messaging::{
-    MessageRouting, MessageRoutingError, XNetPayloadBuilder, XNetPayloadError,
-    XNetPayloadValidationError,
+    BatchDelivered, BatchPipelineOccupancy, MessageRouting, MessageRoutingError,
+    RoundSlicingStatus, XNetPayloadBuilder, XNetPayloadError, XNetPayloadValidationError,
 };
 use ic_interfaces::state_manager::{CertificationScope, StateManager};
 use ic_interfaces::validation::ValidationResult;
@@ -55,7 +55,7 @@ impl Default for FakeMessageRouting {
 }
 
 impl MessageRouting for FakeMessageRouting {
-    fn deliver_batch(&self, batch: Batch) -> Result<(), MessageRoutingError> {
+    fn deliver_batch(&self, batch: Batch) -> Result<BatchDelivered, MessageRoutingError> {
         let mut next_batch_height = self.next_batch_height.write().unwrap();
 
         let expected_height = *next_batch_height;
@@ -71,7 +71,7 @@ impl MessageRouting for FakeMessageRouting {
                 let (_height, state) = state_manager.take_tip();
                 state_manager.commit_and_certify(state, expected_height, scope);
             }
-            return Ok(());
+            return Ok(BatchDelivered::default());
         }
         Err(MessageRoutingError::Ignored {
             expected_height,
@@ -81,14 +81,24 @@ impl MessageRouting for FakeMessageRouting {
     fn expected_batch_height(&self) -> Height {
         *self.next_batch_height.read().unwrap()
     }
+    // Batches are executed synchronously, nothing is ever pending.
+    fn batch_pipeline_occupancy(&self) -> BatchPipelineOccupancy {
+        BatchPipelineOccupancy::default()
+    }
+    // Batches are not executed, so no round is ever sliced.
+    fn last_round_slicing_status(&self) -> Option<RoundSlicingStatus> {
+        None
+    }
 }
 
 mock! {
     pub MessageRouting {}
 
     trait MessageRouting {
-        fn deliver_batch(& self, b: Batch) -> Result<(), MessageRoutingError>;
+        fn deliver_batch(& self, b: Batch) -> Result<BatchDelivered, MessageRoutingError>;
         fn expected_batch_height(&self) -> Height;
+        fn batch_pipeline_occupancy(&self) -> BatchPipelineOccupancy;
+        fn last_round_slicing_status(&self) -> Option<RoundSlicingStatus>;
     }
 }
 
@@ -326,10 +326,17 @@ pub fn get_replica_transport_config(
 
     TransportConfig {
         node_ip: "127.0.0.1".to_string(),
+        secondary_node_ip: None,
+        external_ip: None,
+        nat_traversal: false,
+        reconnect: Default::default(),
+        wire_codecs: Vec::new(),
+        tls_validation: Default::default(),
         p2p_flows: vec![TransportFlowConfig {
             flow_tag: 0,
             server_port: port,
             queue_size: 8,
+            rate_limit: None,
         }],
     }
 }
@@ -165,6 +165,9 @@ pub fn test_subnet_record() -> SubnetRecord {
         max_instructions_per_message: 5_000_000_000,
         max_instructions_per_round: 7_000_000_000,
         max_instructions_per_install_code: 200_000_000_000,
+        max_ingress_ttl_millis: 0,
+        ingress_clock_skew_tolerance_millis: 0,
+        block_maker_ranking: 0,
     }
 }
 
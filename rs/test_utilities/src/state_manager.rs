@@ -3,6 +3,7 @@ use ic_crypto_sha256::Sha256;
 use ic_crypto_tree_hash::{LabeledTree, MixedHashTree};
 use ic_interfaces::{
     certified_stream_store::{CertifiedStreamStore, DecodeStreamError, EncodeStreamError},
+    consensus_pool::HeightWatch,
     state_manager::{
         CertificationMask, CertificationScope, Labeled, StateManager, StateManagerError,
         StateManagerResult, StateReader, CERT_ANY, CERT_CERTIFIED, CERT_UNCERTIFIED,
@@ -19,6 +20,7 @@ use ic_types::{
     CryptoHashOfPartialState, CryptoHashOfState, Height, RegistryVersion, SubnetId,
 };
 use std::sync::{Arc, RwLock};
+use tokio::sync::watch;
 
 use mockall::*;
 
@@ -36,6 +38,8 @@ mock! {
 
         fn latest_certified_height(&self) -> Height;
 
+        fn subscribe_certified_height(&self) -> watch::Receiver<Height>;
+
         fn read_certified_state(
             &self,
             _paths: &LabeledTree<()>
@@ -103,6 +107,7 @@ impl Snapshot {
 pub struct FakeStateManager {
     states: Arc<RwLock<Vec<Snapshot>>>,
     tip: Arc<RwLock<Option<(Height, ReplicatedState)>>>,
+    certified_height_watch: Arc<HeightWatch>,
     _tempdir: Arc<tempfile::TempDir>,
 }
 
@@ -136,6 +141,7 @@ impl FakeStateManager {
                     tmpdir.path().into(),
                 ),
             )))),
+            certified_height_watch: Arc::new(HeightWatch::new(height)),
             _tempdir: Arc::new(tmpdir),
         }
     }
@@ -235,6 +241,9 @@ impl StateManager for FakeStateManager {
         {
             snapshot.certification = Some(certification);
         }
+        drop(snapshots);
+        self.certified_height_watch
+            .update(self.latest_certified_height());
     }
 
     fn list_state_heights(&self, cert_mask: CertificationMask) -> Vec<Height> {
@@ -312,6 +321,10 @@ impl StateReader for FakeStateManager {
             .unwrap_or_else(|| Height::from(0))
     }
 
+    fn subscribe_certified_height(&self) -> watch::Receiver<Height> {
+        self.certified_height_watch.subscribe()
+    }
+
     fn get_latest_state(&self) -> Labeled<Arc<Self::State>> {
         self.states
             .read()
@@ -520,6 +533,10 @@ impl StateReader for RefMockStateManager {
         self.mock.read().unwrap().latest_certified_height()
     }
 
+    fn subscribe_certified_height(&self) -> watch::Receiver<Height> {
+        self.mock.read().unwrap().subscribe_certified_height()
+    }
+
     fn get_latest_state(&self) -> Labeled<Arc<Self::State>> {
         self.mock.read().unwrap().get_latest_state()
     }
@@ -2,7 +2,7 @@ use ic_interfaces::transport::{AsyncTransportEventHandler, Transport};
 use ic_logger::{info, ReplicaLogger};
 use ic_protobuf::registry::node::v1::NodeRecord;
 use ic_types::transport::{
-    FlowId, FlowTag, TransportClientType, TransportErrorCode, TransportPayload,
+    FlowId, FlowTag, TransportClientType, TransportErrorCode, TransportPayload, WireCodecId,
 };
 use ic_types::{NodeId, RegistryVersion};
 
@@ -254,6 +254,25 @@ impl Transport for ThreadPort {
         Ok(())
     }
 
+    /// Messages are handed over to the peer right away, so the send queues
+    /// are always empty.
+    fn queue_len(
+        &self,
+        _client_type: TransportClientType,
+        _peer_id: &NodeId,
+        _flow_tag: FlowTag,
+    ) -> Result<usize, TransportErrorCode> {
+        Ok(0)
+    }
+
+    fn wire_codec(
+        &self,
+        _client_type: TransportClientType,
+        _peer_id: &NodeId,
+    ) -> Result<WireCodecId, TransportErrorCode> {
+        Ok(WireCodecId::Protobuf)
+    }
+
     fn clear_send_queues(&self, _client_type: TransportClientType, _peer_id: &NodeId) {}
 
     fn clear_send_queue(
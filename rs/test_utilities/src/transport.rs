@@ -4,7 +4,7 @@ use ic_protobuf::registry::node::v1::NodeRecord;
 use ic_types::{
     transport::{
         FlowId, FlowTag, TransportClientType, TransportErrorCode, TransportPayload,
-        TransportStateChange,
+        TransportStateChange, WireCodecId,
     },
     NodeId, RegistryVersion,
 };
@@ -44,6 +44,19 @@ mock! {
             message: TransportPayload,
         ) -> Result<(), TransportErrorCode>;
 
+        fn queue_len(
+            &self,
+            client_type: TransportClientType,
+            peer: &NodeId,
+            flow_tag: FlowTag,
+        ) -> Result<usize, TransportErrorCode>;
+
+        fn wire_codec(
+            &self,
+            client_type: TransportClientType,
+            peer: &NodeId,
+        ) -> Result<WireCodecId, TransportErrorCode>;
+
         fn clear_send_queues(
             &self,
             client_type: TransportClientType,
@@ -8,10 +8,10 @@ impl Default for PayloadBuilder {
     /// Create a default, empty, XNetPayload
     fn default() -> Self {
         Self {
-            payload: BatchPayload {
-                ingress: super::ingress_payload::IngressPayloadBuilder::default().build(),
-                xnet: super::xnet_payload::XNetPayloadBuilder::default().build(),
-            },
+            payload: BatchPayload::new(
+                super::ingress_payload::IngressPayloadBuilder::default().build(),
+                super::xnet_payload::XNetPayloadBuilder::default().build(),
+            ),
         }
     }
 }
@@ -45,10 +45,10 @@ fn batch_payload_serialize_then_deserialize() {
     let ingress_0 = crate::types::messages::SignedIngressBuilder::new()
         .nonce(0)
         .build();
-    let batch_payload_0 = BatchPayload {
-        ingress: IngressPayload::from(vec![ingress_0]),
-        xnet: XNetPayload::default(),
-    };
+    let batch_payload_0 = BatchPayload::new(
+        IngressPayload::from(vec![ingress_0]),
+        XNetPayload::default(),
+    );
     let vec = serde_cbor::ser::to_vec(&batch_payload_0).unwrap();
     let batch_payload_1: BatchPayload = serde_cbor::de::from_slice(&vec).unwrap();
     assert_eq!(batch_payload_0, batch_payload_1);
@@ -85,10 +85,10 @@ fn payload_serialize_then_deserialize() {
     let ingress_0 = crate::types::messages::SignedIngressBuilder::new()
         .nonce(0)
         .build();
-    let batch_payload_0 = BatchPayload {
-        ingress: IngressPayload::from(vec![ingress_0]),
-        xnet: XNetPayload::default(),
-    };
+    let batch_payload_0 = BatchPayload::new(
+        IngressPayload::from(vec![ingress_0]),
+        XNetPayload::default(),
+    );
     let payload_0 = Payload::new(
         ic_crypto::crypto_hash,
         (batch_payload_0, dkg::Dealings::new_empty(Height::from(0))).into(),
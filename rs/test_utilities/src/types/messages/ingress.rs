@@ -96,6 +96,7 @@ impl Default for SignedIngressBuilder {
             sender: Blob(PrincipalId::new_anonymous().into()),
             ingress_expiry: current_time_and_expiry_time().1.as_nanos_since_unix_epoch(),
             nonce: None,
+            priority: None,
         };
         Self {
             update,
@@ -135,6 +136,11 @@ impl SignedIngressBuilder {
         self
     }
 
+    pub fn priority(mut self, priority: u64) -> Self {
+        self.update.priority = Some(priority);
+        self
+    }
+
     pub fn expiry_time(mut self, expiry_time: Time) -> Self {
         self.update.ingress_expiry = expiry_time.as_nanos_since_unix_epoch();
         self
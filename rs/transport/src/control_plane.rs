@@ -8,30 +8,33 @@
 //! The control plane module implements control plane functionality for
 //! [`TransportImpl`](../types/struct.TransportImpl.html).
 
+use crate::reconnect::ReconnectPolicy;
 use crate::types::{
-    ClientState, Connecting, ConnectionRole, ConnectionState, FlowState, PeerState, QueueSize,
-    ServerPort, ServerPortState, TransportImpl,
+    ClientState, Connecting, ConnectionRole, ConnectionState, FlowState, Listening, PeerState,
+    QueueSize, ServerPort, ServerPortState, TransportImpl, Watermark, WatermarkCallback,
 };
-use crate::utils::{get_flow_ips, get_flow_label, SendQueueImpl};
+use crate::utils::{get_flow_endpoints, get_flow_ips, get_flow_label, SendQueueImpl};
 use futures::future::{AbortHandle, Abortable, Aborted};
 use ic_crypto_tls_interfaces::{AllowedClients, AuthenticatedPeer, TlsReadHalf, TlsWriteHalf};
 use ic_interfaces::transport::AsyncTransportEventHandler;
-use ic_logger::{error, info, warn, ReplicaLogger};
+use ic_logger::{info, warn, ReplicaLogger};
 use ic_protobuf::registry::node::v1::NodeRecord;
 use ic_types::{
-    transport::{FlowId, FlowTag, TransportClientType, TransportErrorCode},
+    transport::{
+        FlowId, FlowTag, TransportClientType, TransportErrorCode, TransportFlowInfo,
+        TransportStateChange,
+    },
     NodeId, RegistryVersion,
 };
+use ic_utils::token_bucket::TokenBucket;
 use std::collections::HashMap;
 use std::net::{IpAddr, SocketAddr};
 use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::net::{TcpListener, TcpSocket, TcpStream};
-use tokio::time::sleep;
-
-/// Time to wait before retrying an unsuccessful connection attempt
-const CONNECT_RETRY_SECONDS: u64 = 3;
+use tokio::sync::mpsc::unbounded_channel;
+use tokio::time::{sleep, Instant};
 
 /// Time to wait for the TLS handshake (for both client/server sides)
 const TLS_HANDSHAKE_TIMEOUT_SECONDS: u64 = 30;
@@ -118,8 +121,13 @@ impl TransportImpl {
         Ok(())
     }
 
-    /// Starts all connections to a peer and initializes the corresponding data
-    /// structures and tasks
+    /// Starts the connection to a peer and initializes the corresponding data
+    /// structures and tasks.
+    ///
+    /// All flows with the peer share a single connection. The connection uses
+    /// the endpoint of the first flow in the transport config, falling back to
+    /// the first endpoint in the peer's node record if the peer does not
+    /// announce that flow.
     fn start_peer(
         &self,
         client_type: TransportClientType,
@@ -131,89 +139,20 @@ impl TransportImpl {
         if client_state.peer_map.get(&peer_id).is_some() {
             return Err(TransportErrorCode::PeerAlreadyRegistered);
         }
-
-        let mut peer_state = PeerState {
-            flow_map: HashMap::new(),
+        let connection_flow_tag = match self.config.p2p_flows.first() {
+            Some(flow_config) => FlowTag::from(flow_config.flow_tag),
+            None => return Err(TransportErrorCode::FlowNotFound),
         };
 
         // TODO: P2P-514
-        let mut queue_size_map = HashMap::new();
         let flow_ips = get_flow_ips(peer_record)?;
+        let mut flow_map = HashMap::new();
         for flow_config in &self.config.p2p_flows {
             let flow_tag = FlowTag::from(flow_config.flow_tag);
-            queue_size_map.insert(flow_tag, QueueSize::from(flow_config.queue_size));
-            if role == ConnectionRole::Server {
-                let peer_ip = flow_ips
-                    .get(&flow_tag)
-                    .map_or("Unknown Peer IP".to_string(), |x| x.to_string());
-                let flow_label = get_flow_label(&peer_ip, peer_id);
-                let flow_id = FlowId {
-                    client_type,
-                    peer_id: *peer_id,
-                    flow_tag,
-                };
-                let flow_state = FlowState::new(
-                    flow_id,
-                    flow_config.flow_tag.to_string(),
-                    flow_label.clone(),
-                    ConnectionState::Listening,
-                    Box::new(SendQueueImpl::new(
-                        flow_label,
-                        &flow_tag,
-                        QueueSize::from(flow_config.queue_size),
-                        self.send_queue_metrics.clone(),
-                    )),
-                    self.control_plane_metrics.clone(),
-                );
-                peer_state.flow_map.insert(flow_tag, flow_state);
-            }
-        }
-        if role == ConnectionRole::Server {
-            client_state.peer_map.insert(*peer_id, peer_state);
-            return Ok(());
-        }
-
-        for flow_endpoint in &peer_record.p2p_flow_endpoints {
-            let endpoint = match &flow_endpoint.endpoint {
-                Some(x) => x,
-                None => {
-                    warn!(
-                        self.log,
-                        "ControlPlane::start_peer(): missing endpoint flow = {:?}",
-                        flow_endpoint.flow_tag
-                    );
-                    continue;
-                }
-            };
-
-            let flow_tag = FlowTag::from(flow_endpoint.flow_tag);
-            let queue_size = match queue_size_map.get(&flow_tag) {
-                Some(queue_size) => queue_size,
-                None => {
-                    error!(
-                        self.log,
-                        "ControlPlane::start_peer(): TransportConfig NodeRecord mismatch = {:?}",
-                        flow_endpoint.flow_tag
-                    );
-                    continue;
-                }
-            };
-
-            let peer_ip = IpAddr::from_str(endpoint.ip_addr.as_str())
-                .unwrap_or_else(|_| panic!("Invalid node IP: {}", endpoint.ip_addr));
-            let flow_label = get_flow_label(&endpoint.ip_addr.as_str(), peer_id);
-            let server_port = endpoint.port as u16;
-            let connecting_task = self.spawn_connect_task(
-                client_type,
-                flow_endpoint.flow_tag.into(),
-                *peer_id,
-                peer_ip,
-                ServerPort::from(server_port),
-            );
-            let connecting_state = Connecting {
-                peer_addr: SocketAddr::new(peer_ip, server_port),
-                connecting_task,
-            };
+            let peer_ip = flow_ips
+                .get(&flow_tag)
+                .map_or("Unknown Peer IP".to_string(), |x| x.to_string());
+            let flow_label = get_flow_label(&peer_ip, peer_id);
             let flow_id = FlowId {
                 client_type,
                 peer_id: *peer_id,
@@ -221,26 +160,174 @@ impl TransportImpl {
             };
             let flow_state = FlowState::new(
                 flow_id,
-                flow_endpoint.flow_tag.to_string(),
+                flow_config.flow_tag.to_string(),
                 flow_label.clone(),
-                ConnectionState::Connecting(connecting_state),
                 Box::new(SendQueueImpl::new(
-                    flow_label.clone(),
+                    flow_label,
                     &flow_tag,
-                    *queue_size,
+                    QueueSize::from(flow_config.queue_size),
+                    Self::watermark_callback(client_state, *peer_id, flow_tag),
                     self.send_queue_metrics.clone(),
                 )),
+                flow_config.rate_limit.as_ref().map(|rate_limit| {
+                    TokenBucket::new(
+                        rate_limit.bytes_per_second as f64,
+                        rate_limit.burst_bytes as f64,
+                        Instant::now().into_std(),
+                    )
+                }),
+            );
+            flow_map.insert(flow_tag, flow_state);
+        }
+
+        // Connect to the endpoints of the connection flow, or to the endpoints
+        // of the first flow in the node record if the peer does not announce
+        // the connection flow.
+        let mut peer_addrs = get_flow_endpoints(peer_record, connection_flow_tag);
+        if peer_addrs.is_empty() {
+            if let Some(flow_endpoint) = peer_record.p2p_flow_endpoints.first() {
+                peer_addrs = get_flow_endpoints(peer_record, FlowTag::from(flow_endpoint.flow_tag));
+            }
+        }
+        let connect_addrs = self.connect_addrs(&peer_addrs);
+
+        if role == ConnectionRole::Server {
+            let peer_ip = flow_ips
+                .get(&connection_flow_tag)
+                .map_or("Unknown Peer IP".to_string(), |x| x.to_string());
+            // With NAT traversal, the server connects to the peer as well, so
+            // that the connection attempts of both sides open the NAT.
+            let connect_addrs = if self.config.nat_traversal {
+                connect_addrs
+            } else {
+                Vec::new()
+            };
+            let connecting_task = if connect_addrs.is_empty() {
+                None
+            } else {
+                Some(self.spawn_connect_task(
+                    client_type,
+                    connection_flow_tag,
+                    *peer_id,
+                    role,
+                    connect_addrs.clone(),
+                ))
+            };
+            let peer_state = PeerState::new(
+                connection_flow_tag,
+                get_flow_label(&peer_ip, peer_id),
+                connect_addrs,
+                ConnectionState::Listening(Listening { connecting_task }),
+                flow_map,
                 self.control_plane_metrics.clone(),
             );
-            peer_state
-                .flow_map
-                .insert(flow_endpoint.flow_tag.into(), flow_state);
+            client_state.peer_map.insert(*peer_id, peer_state);
+            return Ok(());
         }
 
+        let peer_addr = match connect_addrs.first() {
+            Some((_, peer_addr)) => *peer_addr,
+            None => {
+                warn!(
+                    self.log,
+                    "ControlPlane::start_peer(): no usable endpoint: peer_id = {:?}, \
+                     endpoints = {:?}",
+                    peer_id,
+                    peer_addrs
+                );
+                return Err(TransportErrorCode::NodeRecordMissingConnectionEndpoint);
+            }
+        };
+
+        let connecting_task = self.spawn_connect_task(
+            client_type,
+            connection_flow_tag,
+            *peer_id,
+            role,
+            connect_addrs.clone(),
+        );
+        let connecting_state = Connecting {
+            peer_addr,
+            connecting_task,
+        };
+        let peer_state = PeerState::new(
+            connection_flow_tag,
+            get_flow_label(&peer_addr.ip().to_string(), peer_id),
+            connect_addrs,
+            ConnectionState::Connecting(connecting_state),
+            flow_map,
+            self.control_plane_metrics.clone(),
+        );
         client_state.peer_map.insert(*peer_id, peer_state);
         Ok(())
     }
 
+    /// Returns if the peer is connected, or `None` if the peer was removed
+    fn is_peer_connected(
+        &self,
+        client_type: TransportClientType,
+        peer_id: &NodeId,
+    ) -> Option<bool> {
+        let client_map = self.client_map.read().unwrap();
+        let peer_state = client_map.get(&client_type)?.peer_map.get(peer_id)?;
+        Some(matches!(
+            peer_state.connection_state,
+            ConnectionState::Connected(_)
+        ))
+    }
+
+    /// Returns the IP addresses of this node
+    fn node_ips(&self) -> impl Iterator<Item = IpAddr> {
+        std::iter::once(self.node_ip).chain(self.secondary_node_ip)
+    }
+
+    /// Returns the `(local, peer)` address pairs to connect to the given peer
+    /// addresses, in order of preference.
+    ///
+    /// Each peer address is paired with the node IP of the same address
+    /// family; peer addresses of an address family this node has no IP for
+    /// are skipped. IPv6 addresses are preferred over IPv4 addresses.
+    ///
+    /// With NAT traversal, connections are made from the server port, so
+    /// that the NAT maps them to the same external port as the listener.
+    pub(crate) fn connect_addrs(&self, peer_addrs: &[SocketAddr]) -> Vec<(SocketAddr, SocketAddr)> {
+        let local_port = match self.config.p2p_flows.first() {
+            Some(flow_config) if self.config.nat_traversal => flow_config.server_port,
+            _ => 0,
+        };
+        let mut connect_addrs = peer_addrs
+            .iter()
+            .filter_map(|peer_addr| {
+                self.node_ips()
+                    .find(|node_ip| node_ip.is_ipv6() == peer_addr.is_ipv6())
+                    .map(|node_ip| (SocketAddr::new(node_ip, local_port), *peer_addr))
+            })
+            .collect::<Vec<_>>();
+        // The sort is stable, so the order of the node record is kept within
+        // each address family.
+        connect_addrs.sort_by_key(|(_, peer_addr)| !peer_addr.is_ipv6());
+        connect_addrs
+    }
+
+    /// Returns the callback that reports the send queue watermarks of a flow
+    /// to the transport client.
+    fn watermark_callback(
+        client_state: &ClientState,
+        peer_id: NodeId,
+        flow_tag: FlowTag,
+    ) -> WatermarkCallback {
+        let state_change_sender = client_state.state_change_sender.clone();
+        Box::new(move |watermark| {
+            let flow_info = TransportFlowInfo { peer_id, flow_tag };
+            let state_change = match watermark {
+                Watermark::High => TransportStateChange::SendQueueHighWatermark(flow_info),
+                Watermark::Low => TransportStateChange::SendQueueLowWatermark(flow_info),
+            };
+            // Fails only if the client was removed
+            let _ = state_change_sender.send(state_change);
+        })
+    }
+
     /// Starts the async task to accept the incoming TcpStreams in server mode.
     fn spawn_accept_task(
         &self,
@@ -263,7 +350,7 @@ impl TransportImpl {
                     _ => return,
                 };
                 match tcp_listener.accept().await {
-                    Ok((stream, _)) => {
+                    Ok((stream, peer_addr)) => {
                         let metrics = metrics.clone();
                         metrics
                             .tcp_accepts
@@ -275,11 +362,26 @@ impl TransportImpl {
                                 Ok(stream) => stream,
                                 Err(_) => return,
                             };
-                            // Errors are reported in tls_server_handshake
-                            if let Ok(()) = arc_self
-                                .tls_server_handshake(client_type, flow_tag, stream)
-                                .await
-                            {
+                            // Errors are reported in the handshakes
+                            let handshake_result =
+                                match arc_self.nat_client_peer(client_type, &peer_addr) {
+                                    Some(peer_id) => {
+                                        arc_self
+                                            .tls_client_handshake(
+                                                peer_id,
+                                                client_type,
+                                                flow_tag,
+                                                stream,
+                                            )
+                                            .await
+                                    }
+                                    None => {
+                                        arc_self
+                                            .tls_server_handshake(client_type, flow_tag, stream)
+                                            .await
+                                    }
+                                };
+                            if let Ok(()) = handshake_result {
                                 metrics
                                     .tcp_accept_conn_success
                                     .with_label_values(&[&flow_tag.to_string()])
@@ -314,33 +416,75 @@ impl TransportImpl {
         abort_handle
     }
 
+    /// Returns the peer an accepted connection comes from if NAT traversal is
+    /// enabled and we are the client of that peer.
+    ///
+    /// With NAT traversal, the server connects to us as well, from its server
+    /// port. Unless its attempt crosses ours, it ends up at our listener, and
+    /// we perform the client side of the TLS handshake, as the roles follow
+    /// the node IDs rather than the direction of the TCP connection.
+    fn nat_client_peer(
+        &self,
+        client_type: TransportClientType,
+        peer_addr: &SocketAddr,
+    ) -> Option<NodeId> {
+        if !self.config.nat_traversal {
+            return None;
+        }
+        let client_map = self.client_map.read().unwrap();
+        client_map
+            .get(&client_type)?
+            .peer_map
+            .iter()
+            .find(|(peer_id, peer_state)| {
+                Self::connection_role(&self.node_id, peer_id) == ConnectionRole::Client
+                    && peer_state
+                        .connect_addrs
+                        .iter()
+                        .any(|(_, addr)| addr == peer_addr)
+            })
+            .map(|(peer_id, _)| *peer_id)
+    }
+
     /// Spawn a task that tries to connect to a peer (forever, or until
     /// connection is established or peer is removed)
+    ///
+    /// The given `(local, peer)` address pairs are tried in order, moving on
+    /// to the next pair after each failed attempt.
+    ///
+    /// If we are the server, the task is only used for NAT traversal: the TCP
+    /// connection results from the simultaneous open with the peer's connect
+    /// task, and we perform the server side of the TLS handshake. The task
+    /// stops once the peer is connected.
     #[allow(clippy::too_many_arguments)]
     fn spawn_connect_task(
         &self,
         client_type: TransportClientType,
         flow_tag: FlowTag,
         peer_id: NodeId,
-        peer_ip: IpAddr,
-        server_port: ServerPort,
+        role: ConnectionRole,
+        connect_addrs: Vec<(SocketAddr, SocketAddr)>,
     ) -> AbortHandle {
-        let node_ip = self.node_ip;
+        assert!(!connect_addrs.is_empty());
         let weak_self = self.weak_self.read().unwrap().clone();
         let metrics = self.control_plane_metrics.clone();
+        let reconnect_policy = ReconnectPolicy::new(self.config.reconnect.clone());
         let connect_task = async move {
-            let local_addr = SocketAddr::new(node_ip, 0);
-            let peer_addr = SocketAddr::new(peer_ip, server_port.get());
-
             // Loop till connection is established
             let mut retries: u32 = 0;
             loop {
+                let (local_addr, peer_addr) = connect_addrs[retries as usize % connect_addrs.len()];
                 retries += 1;
                 // If the TransportImpl has been deleted, abort.
                 let arc_self = match weak_self.upgrade() {
                     Some(arc_self) => arc_self,
                     _ => return,
                 };
+                if role == ConnectionRole::Server
+                    && arc_self.is_peer_connected(client_type, &peer_id) != Some(false)
+                {
+                    return;
+                }
 
                 // We currently retry forever, which is fine as we have per-connection
                 // async task. This loop will terminate when the peer is removed from
@@ -351,10 +495,19 @@ impl TransportImpl {
                     .inc();
                 match Self::connect_to_server(&local_addr, &peer_addr, &arc_self.log).await {
                     Ok(stream) => {
-                        match arc_self
-                            .tls_client_handshake(peer_id, client_type, flow_tag, stream)
-                            .await
-                        {
+                        let handshake_result = match role {
+                            ConnectionRole::Client => {
+                                arc_self
+                                    .tls_client_handshake(peer_id, client_type, flow_tag, stream)
+                                    .await
+                            }
+                            ConnectionRole::Server => {
+                                arc_self
+                                    .tls_server_handshake(client_type, flow_tag, stream)
+                                    .await
+                            }
+                        };
+                        match handshake_result {
                             Ok(()) => {
                                 metrics
                                     .tcp_conn_to_server_success
@@ -395,7 +548,7 @@ impl TransportImpl {
                                     e,
                                     retries
                                 );
-                                sleep(Duration::from_secs(CONNECT_RETRY_SECONDS)).await;
+                                sleep(reconnect_policy.retry_interval(retries)).await;
                                 continue;
                             }
                         }
@@ -416,7 +569,7 @@ impl TransportImpl {
                             e,
                             retries,
                         );
-                        sleep(Duration::from_secs(CONNECT_RETRY_SECONDS)).await;
+                        sleep(reconnect_policy.retry_interval(retries)).await;
                     }
                 }
             }
@@ -447,25 +600,36 @@ impl TransportImpl {
         tls_reader: TlsReadHalf,
         tls_writer: TlsWriteHalf,
     ) -> Result<(), TransportErrorCode> {
-        // Pass the established connection to the data plane to start IOs.
-        let flow_id = FlowId {
-            client_type,
-            peer_id,
-            flow_tag,
-        };
-        self.on_connect(
-            flow_id,
-            role,
-            peer_addr,
-            Box::new(tls_reader),
-            Box::new(tls_writer),
-        )
-        .await
-        .map_err(|e| {
+        let mut reader = Box::new(tls_reader);
+        let mut writer = Box::new(tls_writer);
+        // Negotiate the wire codec, then pass the established connection to the
+        // data plane to start IOs. With NAT traversal, the peer is told the
+        // address we observe it under as well.
+        let result = async {
+            let (wire_codec, pending_message) =
+                Self::exchange_hello(&self.config.wire_codecs, role, &mut reader, &mut writer)
+                    .await?;
+            if self.config.nat_traversal {
+                Self::send_observed_addr(peer_addr, &mut writer).await?;
+            }
+            self.on_connect(
+                client_type,
+                peer_id,
+                role,
+                wire_codec,
+                peer_addr,
+                reader,
+                pending_message,
+                writer,
+            )
+            .await
+        }
+        .await;
+        result.map_err(|e| {
             warn!(
                 every_n_seconds => 30,
                 self.log,
-                "ControlPlane::handshake_result(): failed to add flow: \
+                "ControlPlane::handshake_result(): failed to add connection: \
                  node_id = {:?}, local_addr = {:?}, peer_addr = {:?}, role = {:?}, \
                  flow = {:?}, error = {:?}",
                 self.node_id,
@@ -479,71 +643,120 @@ impl TransportImpl {
         })
     }
 
-    /// Retries to establish a connection
-    pub(crate) fn retry_connection(&self, flow_id: &FlowId) -> Result<(), TransportErrorCode> {
+    /// Handles the address under which a peer observes this node, as reported
+    /// by the peer with NAT traversal. The observed address is expected to be
+    /// the announced one, i.e., the external IP if configured, or one of the
+    /// node IPs otherwise; a mismatch means the peers cannot reach the node
+    /// under the address in the registry.
+    pub(crate) fn on_observed_addr(&self, peer_id: NodeId, observed_addr: SocketAddr) {
+        let announced = match &self.config.external_ip {
+            Some(external_ip) => IpAddr::from_str(external_ip).ok() == Some(observed_addr.ip()),
+            None => self.node_ips().any(|node_ip| node_ip == observed_addr.ip()),
+        };
+        if !announced {
+            self.control_plane_metrics
+                .observed_addr_mismatches
+                .with_label_values(&[&peer_id.to_string()])
+                .inc();
+            warn!(
+                every_n_seconds => 300,
+                self.log,
+                "ControlPlane::on_observed_addr(): peer observes an address that is not the \
+                 announced one: node_id = {:?}, peer_id = {:?}, observed_addr = {:?}, \
+                 external_ip = {:?}",
+                self.node_id,
+                peer_id,
+                observed_addr,
+                self.config.external_ip,
+            );
+        }
+    }
+
+    /// Retries to establish the connection with a peer
+    pub(crate) fn retry_connection(
+        &self,
+        client_type: TransportClientType,
+        peer_id: &NodeId,
+    ) -> Result<(), TransportErrorCode> {
         warn!(
             self.log,
-            "ControlPlane::retry_connection(): node_id = {:?}, flow = {:?}", self.node_id, flow_id,
+            "ControlPlane::retry_connection(): node_id = {:?}, client_type = {:?}, peer_id = {:?}",
+            self.node_id,
+            client_type,
+            peer_id,
         );
-        self.control_plane_metrics
-            .retry_connection
-            .with_label_values(&[&flow_id.peer_id.to_string(), &flow_id.flow_tag.to_string()])
-            .inc();
 
         let mut client_map = self.client_map.write().unwrap();
         let client_state = client_map
-            .get_mut(&flow_id.client_type)
+            .get_mut(&client_type)
             .ok_or(TransportErrorCode::TransportClientNotFound)?;
         let peer_state = client_state
             .peer_map
-            .get_mut(&flow_id.peer_id)
+            .get_mut(peer_id)
             .ok_or(TransportErrorCode::PeerNotFound)?;
-        let flow_state = peer_state
-            .flow_map
-            .get_mut(&flow_id.flow_tag)
-            .ok_or(TransportErrorCode::FlowNotFound)?;
+        self.control_plane_metrics
+            .retry_connection
+            .with_label_values(&[
+                &peer_id.to_string(),
+                &peer_state.connection_flow_tag.to_string(),
+            ])
+            .inc();
 
-        let sa = match &flow_state.connection_state {
-            ConnectionState::Connected(sa) => sa,
+        match &peer_state.connection_state {
+            ConnectionState::Connected(_) => (),
             _ => {
-                // Flow is already disconnected/reconnecting, skip reconnect processing
+                // Connection is already disconnected/reconnecting, skip reconnect processing
                 return Err(TransportErrorCode::FlowConnectionDown);
             }
         };
 
-        if Self::connection_role(&self.node_id, &flow_id.peer_id) == ConnectionRole::Server {
+        if Self::connection_role(&self.node_id, peer_id) == ConnectionRole::Server {
             // We are the server, wait for the peer to connect
-            flow_state.update(ConnectionState::Listening);
+            let connecting_task = if peer_state.connect_addrs.is_empty() {
+                None
+            } else {
+                Some(self.spawn_connect_task(
+                    client_type,
+                    peer_state.connection_flow_tag,
+                    *peer_id,
+                    ConnectionRole::Server,
+                    peer_state.connect_addrs.clone(),
+                ))
+            };
+            peer_state.update(ConnectionState::Listening(Listening { connecting_task }));
             warn!(
                 self.log,
-                "ControlPlane::process_disconnect(): node_id = {:?}, flow = {:?}, \
+                "ControlPlane::process_disconnect(): node_id = {:?}, peer_id = {:?}, \
                     waiting for peer to reconnect",
                 self.node_id,
-                flow_id,
+                peer_id,
             );
         } else {
             // reconnect if we have a listener
-            if client_state.accept_ports.contains_key(&flow_id.flow_tag) {
-                let socket_addr = sa.peer_addr;
+            if client_state
+                .accept_ports
+                .contains_key(&peer_state.connection_flow_tag)
+            {
+                let socket_addr = peer_state.connect_addrs[0].1;
                 let connecting_task = self.spawn_connect_task(
-                    flow_id.client_type,
-                    flow_id.flow_tag,
-                    flow_id.peer_id,
-                    socket_addr.ip(),
-                    ServerPort::from(socket_addr.port()),
+                    client_type,
+                    peer_state.connection_flow_tag,
+                    *peer_id,
+                    ConnectionRole::Client,
+                    peer_state.connect_addrs.clone(),
                 );
                 let connecting_state = Connecting {
                     peer_addr: socket_addr,
                     connecting_task,
                 };
-                flow_state.update(ConnectionState::Connecting(connecting_state));
+                peer_state.update(ConnectionState::Connecting(connecting_state));
 
                 warn!(
                     self.log,
                     "ControlPlane::process_disconnect(): spawning reconnect task: node_id = {:?}, \
-                flow = {:?}, local_addr = {:?}, peer_addr = {:?}, peer_port = {:?}",
+                peer_id = {:?}, local_addr = {:?}, peer_addr = {:?}, peer_port = {:?}",
                     self.node_id,
-                    flow_id,
+                    peer_id,
                     self.node_ip,
                     socket_addr.ip(),
                     socket_addr.port(),
@@ -634,8 +847,12 @@ impl TransportImpl {
         let registry_version = *self.registry_version.read().unwrap();
         let ret = tokio::time::timeout(
             Duration::from_secs(TLS_HANDSHAKE_TIMEOUT_SECONDS),
-            self.crypto
-                .perform_tls_server_handshake(stream, allowed_clients, registry_version),
+            self.crypto.perform_tls_server_handshake_with_validation(
+                stream,
+                allowed_clients,
+                &self.tls_validation,
+                registry_version,
+            ),
         )
         .await;
         if ret.is_err() {
@@ -723,8 +940,12 @@ impl TransportImpl {
 
         let ret = tokio::time::timeout(
             Duration::from_secs(TLS_HANDSHAKE_TIMEOUT_SECONDS),
-            self.crypto
-                .perform_tls_client_handshake(stream, peer_id, registry_version),
+            self.crypto.perform_tls_client_handshake_with_validation(
+                stream,
+                peer_id,
+                &self.tls_validation,
+                registry_version,
+            ),
         )
         .await;
         if ret.is_err() {
@@ -857,6 +1078,15 @@ impl TransportImpl {
                 return Err(TransportErrorCode::ClientSocketCreateFailed);
             }
         };
+        // A fixed local port is shared with the listener (NAT traversal)
+        if local_addr.port() != 0 {
+            if socket.set_reuseaddr(true).is_err() {
+                return Err(TransportErrorCode::ServerSocketAddrReuseFailed);
+            }
+            if socket.set_reuseport(true).is_err() {
+                return Err(TransportErrorCode::ServerSocketPortReuseFailed);
+            }
+        }
         match socket.bind(*local_addr) {
             Ok(()) => Ok(socket),
             Err(e) => {
@@ -913,35 +1143,51 @@ impl TransportImpl {
             return Err(TransportErrorCode::TransportClientAlreadyRegistered);
         }
 
-        // Bind to the server ports.
+        // Bind to the server port of the first flow. All flows with a peer
+        // share a single connection, so no other ports are needed.
+        // For dual-stack operation, the port is bound on both node IPs.
         let mut listeners = Vec::new();
-        for flow_config in &self.config.p2p_flows {
-            let server_addr = SocketAddr::new(self.node_ip, flow_config.server_port);
-            listeners.push((
-                flow_config.flow_tag,
-                flow_config.server_port,
-                self.init_listener(&server_addr)?,
-            ));
+        if let Some(flow_config) = self.config.p2p_flows.first() {
+            let mut tcp_listeners = Vec::new();
+            for node_ip in self.node_ips() {
+                let server_addr = SocketAddr::new(node_ip, flow_config.server_port);
+                tcp_listeners.push(self.init_listener(&server_addr)?);
+            }
+            listeners.push((flow_config.flow_tag, flow_config.server_port, tcp_listeners));
         }
 
         let mut accept_ports = HashMap::new();
-        for (config_flow_tag, config_server_port, tcp_listener) in listeners {
+        for (config_flow_tag, config_server_port, tcp_listeners) in listeners {
             let flow_tag = FlowTag::from(config_flow_tag);
-            let accept_task = self.spawn_accept_task(client_type, flow_tag, tcp_listener);
+            let accept_tasks = tcp_listeners
+                .into_iter()
+                .map(|tcp_listener| self.spawn_accept_task(client_type, flow_tag, tcp_listener))
+                .collect();
             accept_ports.insert(
                 flow_tag,
                 ServerPortState {
                     port: ServerPort::from(config_server_port),
-                    accept_task,
+                    accept_tasks,
                 },
             );
         }
+
+        // Deliver the state changes reported from a sync context in order.
+        let (state_change_sender, mut state_change_receiver) = unbounded_channel();
+        let event_handler_cl = event_handler.clone();
+        self.tokio_runtime.spawn(async move {
+            while let Some(state_change) = state_change_receiver.recv().await {
+                event_handler_cl.state_changed(state_change).await;
+            }
+        });
+
         client_map.insert(
             client_type,
             ClientState {
                 accept_ports,
                 peer_map: HashMap::new(),
                 event_handler,
+                state_change_sender,
             },
         );
 
@@ -955,8 +1201,8 @@ mod tests {
     use async_trait::async_trait;
     use crossbeam_channel::{bounded, Sender};
     use ic_crypto::utils::TempCryptoComponent;
-    use ic_interfaces::transport::{AsyncTransportEventHandler, SendError};
-    use ic_logger::warn;
+    use ic_interfaces::transport::{AsyncTransportEventHandler, SendError, Transport};
+    use ic_logger::{warn, ReplicaLogger};
     use ic_metrics::MetricsRegistry;
     use ic_protobuf::registry::node::v1::{
         connection_endpoint::Protocol, ConnectionEndpoint, FlowEndpoint, NodeRecord,
@@ -964,17 +1210,19 @@ mod tests {
     use ic_registry_client::fake::FakeRegistryClient;
     use ic_registry_common::proto_registry_data_provider::ProtoRegistryDataProvider;
     use ic_registry_keys::make_crypto_tls_cert_key;
+    use ic_test_utilities::metrics::{fetch_int_counter_vec, labels};
     use ic_test_utilities::types::ids::{NODE_1, NODE_2};
     use ic_test_utilities::with_test_replica_logger;
     use ic_types::transport::TransportErrorCode;
     use ic_types::{
         transport::{
             FlowId, TransportClientType, TransportConfig, TransportFlowConfig, TransportPayload,
-            TransportStateChange,
+            TransportReconnectConfig, TransportStateChange,
         },
         NodeId, RegistryVersion,
     };
     use std::sync::Arc;
+    use std::time::{Duration, Instant};
 
     const NODE_ID_1: NodeId = NODE_1;
     const NODE_ID_2: NodeId = NODE_2;
@@ -984,6 +1232,12 @@ mod tests {
 
     const PORT_1: u16 = 65001;
     const PORT_2: u16 = 65002;
+    const PORT_3: u16 = 65003;
+    const PORT_4: u16 = 65004;
+    const PORT_5: u16 = 65005;
+    const PORT_6: u16 = 65006;
+
+    const RETRY_INTERVAL_MS: u64 = 50;
 
     struct FakeEventHandler {
         connected: Sender<bool>,
@@ -1043,12 +1297,19 @@ mod tests {
 
             let mut client_config_1 = TransportConfig {
                 node_ip: "0.0.0.0".to_string(),
+                secondary_node_ip: None,
+                external_ip: None,
+                nat_traversal: false,
+                reconnect: Default::default(),
+                wire_codecs: Vec::new(),
+                tls_validation: Default::default(),
                 p2p_flows: Vec::new(),
             };
             let flow_internal_1 = TransportFlowConfig {
                 flow_tag: FLOW_TAG_1,
                 server_port: PORT_1,
                 queue_size: 10,
+                rate_limit: None,
             };
             client_config_1.p2p_flows.push(flow_internal_1);
             let control_plane_1 = create_transport(
@@ -1059,16 +1320,24 @@ mod tests {
                 Arc::new(crypto_1),
                 tokio::runtime::Handle::current(),
                 logger.clone(),
-            );
+            )
+            .expect("Failed to create the transport");
 
             let mut client_config_2 = TransportConfig {
                 node_ip: "0.0.0.0".to_string(),
+                secondary_node_ip: None,
+                external_ip: None,
+                nat_traversal: false,
+                reconnect: Default::default(),
+                wire_codecs: Vec::new(),
+                tls_validation: Default::default(),
                 p2p_flows: Vec::new(),
             };
             let flow_internal_2 = TransportFlowConfig {
                 flow_tag: FLOW_TAG_2,
                 server_port: PORT_2,
                 queue_size: 10,
+                rate_limit: None,
             };
             client_config_2.p2p_flows.push(flow_internal_2);
             let control_plane_2 = create_transport(
@@ -1079,7 +1348,8 @@ mod tests {
                 Arc::new(crypto_2),
                 tokio::runtime::Handle::current(),
                 logger.clone(),
-            );
+            )
+            .expect("Failed to create the transport");
 
             let fake_event_handler_1 = Arc::new(FakeEventHandler {
                 connected: connected_1,
@@ -1124,6 +1394,219 @@ mod tests {
         });
     }
 
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn should_handshake_with_nat_traversal() {
+        let (connected_1, done_1) = bounded(0);
+        let (connected_2, done_2) = bounded(0);
+        with_test_replica_logger(|logger| {
+            let registry_and_data = empty_registry();
+            let crypto_1 =
+                temp_crypto_component_with_tls_keys_in_registry(&registry_and_data, NODE_ID_1);
+            let crypto_2 =
+                temp_crypto_component_with_tls_keys_in_registry(&registry_and_data, NODE_ID_2);
+            registry_and_data.registry.update_to_latest_version();
+
+            // Node 1 announces an external IP its peer does not observe.
+            let metrics_registry_1 = MetricsRegistry::new();
+            let metrics_registry_2 = MetricsRegistry::new();
+            let transport_1 = create_nat_transport(
+                NODE_ID_1,
+                crypto_1,
+                PORT_3,
+                Some("192.0.2.1"),
+                metrics_registry_1.clone(),
+                connected_1,
+                logger.clone(),
+            );
+            let transport_2 = create_nat_transport(
+                NODE_ID_2,
+                crypto_2,
+                PORT_4,
+                Some("127.0.0.1"),
+                metrics_registry_2.clone(),
+                connected_2,
+                logger.clone(),
+            );
+
+            // The server, i.e. node 2, starts connecting before its peer does.
+            transport_2
+                .start_connections(
+                    TransportClientType::P2P,
+                    &NODE_ID_1,
+                    &node_record(PORT_3),
+                    REG_V1,
+                )
+                .expect("start_connections");
+            std::thread::sleep(Duration::from_millis(5 * RETRY_INTERVAL_MS));
+            transport_1
+                .start_connections(
+                    TransportClientType::P2P,
+                    &NODE_ID_2,
+                    &node_record(PORT_4),
+                    REG_V1,
+                )
+                .expect("start_connections");
+            assert_eq!(done_1.recv(), Ok(true));
+            assert_eq!(done_2.recv(), Ok(true));
+
+            // Each node reports the address it observes its peer under.
+            wait_until(|| observed_addr_mismatches(&metrics_registry_1, NODE_ID_2) > 0);
+            assert_eq!(observed_addr_mismatches(&metrics_registry_2, NODE_ID_1), 0);
+        });
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn should_handshake_on_simultaneous_open() {
+        let (connected_1, done_1) = bounded(0);
+        let (connected_2, done_2) = bounded(0);
+        with_test_replica_logger(|logger| {
+            let registry_and_data = empty_registry();
+            let crypto_1 =
+                temp_crypto_component_with_tls_keys_in_registry(&registry_and_data, NODE_ID_1);
+            let crypto_2 =
+                temp_crypto_component_with_tls_keys_in_registry(&registry_and_data, NODE_ID_2);
+            registry_and_data.registry.update_to_latest_version();
+
+            let metrics_registry_2 = MetricsRegistry::new();
+            let transport_1 = create_nat_transport(
+                NODE_ID_1,
+                crypto_1,
+                PORT_5,
+                None,
+                MetricsRegistry::new(),
+                connected_1,
+                logger.clone(),
+            );
+            let transport_2 = create_nat_transport(
+                NODE_ID_2,
+                crypto_2,
+                PORT_6,
+                None,
+                metrics_registry_2.clone(),
+                connected_2,
+                logger.clone(),
+            );
+
+            // Both nodes connect to each other at the same time, and exactly
+            // one connection comes up, whichever attempt succeeds.
+            transport_1
+                .start_connections(
+                    TransportClientType::P2P,
+                    &NODE_ID_2,
+                    &node_record(PORT_6),
+                    REG_V1,
+                )
+                .expect("start_connections");
+            transport_2
+                .start_connections(
+                    TransportClientType::P2P,
+                    &NODE_ID_1,
+                    &node_record(PORT_5),
+                    REG_V1,
+                )
+                .expect("start_connections");
+            assert_eq!(done_1.recv(), Ok(true));
+            assert_eq!(done_2.recv(), Ok(true));
+
+            // The connect task of the server stops with the connection.
+            let tcp_connects = tcp_connects(&metrics_registry_2, NODE_ID_1);
+            std::thread::sleep(Duration::from_millis(5 * RETRY_INTERVAL_MS));
+            assert_eq!(tcp_connects(&metrics_registry_2, NODE_ID_1), tcp_connects);
+        });
+    }
+
+    /// Creates a transport with NAT traversal and a single flow, with the P2P
+    /// client registered.
+    fn create_nat_transport(
+        node_id: NodeId,
+        crypto: TempCryptoComponent,
+        server_port: u16,
+        external_ip: Option<&str>,
+        metrics_registry: MetricsRegistry,
+        connected: Sender<bool>,
+        logger: ReplicaLogger,
+    ) -> Arc<dyn Transport> {
+        let config = TransportConfig {
+            node_ip: "0.0.0.0".to_string(),
+            secondary_node_ip: None,
+            external_ip: external_ip.map(str::to_string),
+            nat_traversal: true,
+            reconnect: TransportReconnectConfig {
+                initial_retry_interval_ms: RETRY_INTERVAL_MS,
+                max_retry_interval_ms: RETRY_INTERVAL_MS,
+                backoff_multiplier: 1,
+                jitter_percent: 0,
+            },
+            wire_codecs: Vec::new(),
+            tls_validation: Default::default(),
+            p2p_flows: vec![TransportFlowConfig {
+                flow_tag: FLOW_TAG_1,
+                server_port,
+                queue_size: 10,
+                rate_limit: None,
+            }],
+        };
+        let transport = create_transport(
+            node_id,
+            config,
+            REG_V1,
+            metrics_registry,
+            Arc::new(crypto),
+            tokio::runtime::Handle::current(),
+            logger,
+        )
+        .expect("Failed to create the transport");
+        transport
+            .register_client(
+                TransportClientType::P2P,
+                Arc::new(FakeEventHandler { connected }),
+            )
+            .expect("register_client");
+        transport
+    }
+
+    /// Returns the node record of a peer listening on the given port.
+    fn node_record(port: u16) -> NodeRecord {
+        let mut node_record: NodeRecord = Default::default();
+        node_record.p2p_flow_endpoints.push(FlowEndpoint {
+            flow_tag: FLOW_TAG_1,
+            endpoint: Some(ConnectionEndpoint {
+                ip_addr: "127.0.0.1".to_string(),
+                port: port as u32,
+                protocol: Protocol::P2p1Tls13 as i32,
+            }),
+        });
+        node_record
+    }
+
+    fn observed_addr_mismatches(metrics_registry: &MetricsRegistry, peer_id: NodeId) -> u64 {
+        fetch_int_counter_vec(metrics_registry, "transport_observed_addr_mismatches")
+            .get(&labels(&[("peer_id", peer_id.to_string())]))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    fn tcp_connects(metrics_registry: &MetricsRegistry, peer_id: NodeId) -> u64 {
+        fetch_int_counter_vec(metrics_registry, "transport_tcp_connects")
+            .get(&labels(&[
+                ("peer_id", peer_id.to_string()),
+                ("flow_tag", FLOW_TAG_1.to_string()),
+            ]))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    fn wait_until<F: Fn() -> bool>(condition: F) {
+        let start = Instant::now();
+        while !condition() {
+            assert!(
+                start.elapsed() < Duration::from_secs(30),
+                "Condition not met in time"
+            );
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
     struct RegistryAndDataProvider {
         data_provider: Arc<ProtoRegistryDataProvider>,
         registry: Arc<FakeRegistryClient>,
@@ -9,10 +9,31 @@
 //! reconnection, the socket is passed back to the data plane by the control
 //! plane. The data plane is also responsible for detecting errors in the
 //! connection (missing heartbeats, error notifications), and raising these to
-//! the control plane. The data plane itself is composed of two async tasks per
-//! connection: one each for send and receive. The connections established by
-//! the control plane are split into read and write halves and given to these
-//! two tasks.
+//! the control plane.
+//!
+//! Right after the TLS handshake, both peers send a hello message listing the
+//! wire codecs their clients support. The codec preferred by the server among
+//! those supported by the client is used for the connection, see
+//! `Transport::wire_codec`. Peers that predate the hello do not send one;
+//! their first message is delivered as usual, and protobuf is used. With NAT
+//! traversal, each peer follows its hello with a second one reporting the
+//! address it observes the other peer under.
+//!
+//! All flows with a peer share a single connection. The data plane itself is
+//! composed of the following async tasks per connection:
+//!
+//! * One send task per flow. It dequeues the messages from the flow's send
+//!   queue and frames them, i.e., prefixes each message with a header that
+//!   carries the flow tag. If the flow is rate limited, the task waits until
+//!   the flow's token bucket allows the messages to be sent.
+//! * One write task. It multiplexes the framed messages of all flows onto the
+//!   write half of the connection. Flows are prioritized in the order in which
+//!   they appear in the transport config, i.e., pending messages of the first
+//!   flow are always written before the messages of the second flow and so
+//!   on. If no flow has anything to send, a heartbeat is written instead.
+//! * One read task. It reads the messages from the read half of the
+//!   connection and de-multiplexes them to the flows, based on the flow tag in
+//!   the header.
 //!
 //! The data plane module implements data plane functionality for
 //! [`TransportImpl`](../types/struct.TransportImpl.html).
@@ -20,27 +41,38 @@
 use crate::metrics::DataPlaneMetrics;
 use crate::types::{
     Connected, ConnectionRole, ConnectionState, SendQueueReader, TransportHeader, TransportImpl,
-    TRANSPORT_FLAGS_IS_HEARTBEAT, TRANSPORT_FLAGS_SENDER_ERROR, TRANSPORT_HEADER_SIZE,
+    TRANSPORT_FLAGS_IS_HEARTBEAT, TRANSPORT_FLAGS_IS_HELLO, TRANSPORT_FLAGS_IS_OBSERVED_ADDR,
+    TRANSPORT_FLAGS_SENDER_ERROR, TRANSPORT_HEADER_SIZE, TRANSPORT_HEADER_VERSION,
+    TRANSPORT_HELLO_FLOW_TAG,
 };
+use crate::utils::{negotiate_wire_codec, supported_wire_codecs};
 use ic_crypto_tls_interfaces::{TlsReadHalf, TlsWriteHalf};
 use ic_interfaces::transport::AsyncTransportEventHandler;
 use ic_logger::warn;
 use ic_types::transport::{
-    FlowId, TransportErrorCode, TransportFlowInfo, TransportPayload, TransportStateChange,
+    FlowId, FlowTag, TransportClientType, TransportErrorCode, TransportFlowInfo, TransportPayload,
+    TransportStateChange, WireCodecId,
 };
+use ic_types::NodeId;
+use ic_utils::token_bucket::TokenBucket;
 
-use futures::future::{AbortHandle, Abortable, Aborted};
-use std::convert::TryInto;
+use bytes::{Buf, Bytes};
+use futures::future::{poll_fn, select, AbortHandle, Abortable, Aborted};
+use std::collections::{HashMap, VecDeque};
+use std::convert::{TryFrom, TryInto};
+use std::io::IoSlice;
 use std::net::SocketAddr;
-use std::sync::{Arc, Weak};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::time::{Duration, Instant};
+use std::sync::{Arc, Mutex, Weak};
+use std::task::Poll;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::time::{sleep, Duration, Instant};
 
 // DEQUEUE_BYTES is the number of bytes which we will attempt to dequeue and
-// aggregate before sending to the network via write_all(). Tokio currently
-// does not support writev so aggregation is performed manually. This is
-// necessary because we are setting TCP_NODELAY which causes each write to be
-// pushed to the network. Without aggregation, we would have many small writes
+// aggregate before sending to the network via a vectored write, which hands
+// the headers and payloads to the socket without copying them into a single
+// buffer. This is necessary because we are setting TCP_NODELAY which causes
+// each write to be pushed to the network. Without aggregation, we would have many small writes
 // and thus many small packets. A value of ~800K here works well with a queue
 // size of 1K. Values down to 8K work with queue size >= 4K. Smaller sizes make
 // the system more responsive in clearing the queues at the cost of increased
@@ -51,6 +83,10 @@ use tokio::time::{Duration, Instant};
 /// sending to the network
 const DEQUEUE_BYTES: usize = 100 * 4 * 1490;
 
+/// The maximum number of buffers passed to a single vectored write (IOV_MAX
+/// on Linux is 1024)
+const MAX_IO_SLICES: usize = 1024;
+
 // Payloads are received/collected in units of SOCKET_READ_CHUNK_SIZE
 /// Size of read chunks
 const SOCKET_READ_CHUNK_SIZE: usize = 32 * 1024;
@@ -60,26 +96,51 @@ const TRANSPORT_HEARTBEAT_SEND_INTERVAL_MS: u64 = 200;
 /// Heartbeat wait interval (timeout on receiver side)
 const TRANSPORT_HEARTBEAT_WAIT_INTERVAL_MS: u64 = 5000;
 
+/// A message as read from the socket: its header and, unless it is a
+/// heartbeat, its payload
+type ReceivedMessage = (TransportHeader, Option<TransportPayload>);
+
 /// Error type for read errors
 #[derive(Debug)]
 enum ReadError {
     SocketReadFailed(std::io::Error),
     SocketReadTimeOut,
+    /// The header has a version this node does not understand
+    UnsupportedHeaderVersion(u8),
+}
+
+/// A batch of framed messages of a flow, ready to be written to the socket.
+struct FramedMessages {
+    /// The tag of the flow the messages belong to
+    flow_tag: FlowTag,
+    /// The flow label, used for metrics
+    flow_label: String,
+    /// The serialized headers and the payloads, in the order in which they
+    /// are written
+    buffers: Vec<Bytes>,
+}
+
+impl FramedMessages {
+    /// Returns the total number of bytes
+    fn len(&self) -> usize {
+        self.buffers.iter().map(|buffer| buffer.len()).sum()
+    }
 }
 
 /// Implementation for the transport data plane
 impl TransportImpl {
     /// Create header bytes to send with payload.
     fn pack_header(
+        flow_tag: FlowTag,
         payload: Option<&TransportPayload>,
         sender_err: bool,
         heartbeat: bool,
     ) -> Vec<u8> {
-        let mut result = Vec::<u8>::new();
         let mut header = TransportHeader {
-            version: 0,
+            version: TRANSPORT_HEADER_VERSION,
             flags: 0,
             reserved: 0,
+            flow_tag: flow_tag.get(),
             payload_length: match payload {
                 Some(data) => data.0.len() as u32,
                 None => 0,
@@ -91,9 +152,27 @@ impl TransportImpl {
         if heartbeat {
             header.flags |= TRANSPORT_FLAGS_IS_HEARTBEAT;
         }
+        Self::serialize_header(&header)
+    }
+
+    /// Create the header bytes of a hello message.
+    fn pack_hello_header(payload: &TransportPayload, flags: u8) -> Vec<u8> {
+        Self::serialize_header(&TransportHeader {
+            version: TRANSPORT_HEADER_VERSION,
+            flags: TRANSPORT_FLAGS_IS_HELLO | flags,
+            reserved: 0,
+            flow_tag: TRANSPORT_HELLO_FLOW_TAG,
+            payload_length: payload.0.len() as u32,
+        })
+    }
+
+    /// Serialize the header to its fixed size wire format.
+    fn serialize_header(header: &TransportHeader) -> Vec<u8> {
+        let mut result = Vec::<u8>::new();
         result.append(&mut header.version.to_le_bytes().to_vec());
         result.append(&mut header.flags.to_le_bytes().to_vec());
         result.append(&mut header.reserved.to_le_bytes().to_vec());
+        result.append(&mut header.flow_tag.to_le_bytes().to_vec());
         result.append(&mut header.payload_length.to_le_bytes().to_vec());
 
         assert_eq!(result.len(), TRANSPORT_HEADER_SIZE);
@@ -101,38 +180,112 @@ impl TransportImpl {
         result
     }
 
-    /// Read header bytes received in payload.
-    fn unpack_header(data: Vec<u8>) -> TransportHeader {
+    /// Read header bytes received in payload. Headers of versions other than
+    /// `TRANSPORT_HEADER_VERSION` are rejected, as the layout of the
+    /// remaining bytes is unknown.
+    fn unpack_header(data: Vec<u8>) -> Result<TransportHeader, ReadError> {
         let mut header = TransportHeader {
             version: 0,
             flags: 0,
             reserved: 0,
+            flow_tag: 0,
             payload_length: 0,
         };
         let (version_byte, rest) = data.split_at(std::mem::size_of::<u8>());
         header.version = u8::from_le_bytes(version_byte.try_into().unwrap());
+        if header.version != TRANSPORT_HEADER_VERSION {
+            return Err(ReadError::UnsupportedHeaderVersion(header.version));
+        }
         let (flags_byte, rest) = rest.split_at(std::mem::size_of::<u8>());
         header.flags = u8::from_le_bytes(flags_byte.try_into().unwrap());
         let (reserved_bytes, rest) = rest.split_at(std::mem::size_of::<u16>());
         header.reserved = u16::from_le_bytes(reserved_bytes.try_into().unwrap());
+        let (flow_tag_bytes, rest) = rest.split_at(std::mem::size_of::<u32>());
+        header.flow_tag = u32::from_le_bytes(flow_tag_bytes.try_into().unwrap());
         let (payload_length_bytes, _rest) = rest.split_at(std::mem::size_of::<u32>());
         header.payload_length = u32::from_le_bytes(payload_length_bytes.try_into().unwrap());
 
-        header
+        Ok(header)
     }
 
-    /// Per-flow send task. Reads the requests from the send queue and writes to
-    /// the socket.
-    async fn flow_write_task(
+    /// Per-flow send task. Reads the requests from the send queue, frames them
+    /// and passes them on to the write task of the connection.
+    async fn flow_send_task(
         flow_id: FlowId,
         flow_label: String,
         mut send_queue_reader: Box<dyn SendQueueReader + Send + Sync>,
+        rate_limiter: Option<Arc<Mutex<TokenBucket>>>,
+        framed_sender: Sender<FramedMessages>,
+        metrics: DataPlaneMetrics,
+    ) {
+        let flow_tag_label = flow_id.flow_tag.to_string();
+        loop {
+            // Wait for the send requests
+            let dequeued = send_queue_reader
+                .dequeue(
+                    DEQUEUE_BYTES,
+                    Duration::from_millis(TRANSPORT_HEARTBEAT_SEND_INTERVAL_MS),
+                )
+                .await;
+            if dequeued.is_empty() {
+                // Heartbeats are sent by the write task
+                continue;
+            }
+
+            // The payloads are not copied, only the headers are allocated.
+            let mut buffers = Vec::with_capacity(2 * dequeued.len());
+            for msg in dequeued {
+                buffers.push(Bytes::from(Self::pack_header(
+                    flow_id.flow_tag,
+                    Some(&msg.payload),
+                    msg.sender_error,
+                    false,
+                )));
+                buffers.push(msg.payload.0);
+            }
+            let framed = FramedMessages {
+                flow_tag: flow_id.flow_tag,
+                flow_label: flow_label.clone(),
+                buffers,
+            };
+
+            // Shape the flow. While this flow waits, the write task serves the
+            // other flows.
+            if let Some(rate_limiter) = &rate_limiter {
+                let delay = rate_limiter
+                    .lock()
+                    .unwrap()
+                    .reserve(framed.len() as f64, Instant::now().into_std());
+                if delay > Duration::from_secs(0) {
+                    metrics
+                        .shaped_bytes
+                        .with_label_values(&[&flow_label, &flow_tag_label])
+                        .inc_by(framed.len() as u64);
+                    sleep(delay).await;
+                }
+            }
+            if framed_sender.send(framed).await.is_err() {
+                // The write task exited
+                return;
+            }
+        }
+    }
+
+    /// Per-connection write task. Multiplexes the framed messages of all flows
+    /// onto the socket, in the order of the flow priorities.
+    #[allow(clippy::too_many_arguments)]
+    async fn connection_write_task(
+        client_type: TransportClientType,
+        peer_id: NodeId,
+        connection_label: String,
+        connection_flow_tag: FlowTag,
+        mut framed_receivers: Vec<Receiver<FramedMessages>>,
         mut writer: Box<TlsWriteHalf>,
         metrics: DataPlaneMetrics,
         state: Weak<TransportImpl>,
     ) {
         let _updater = MetricsUpdater::new(metrics.clone(), true);
-        let flow_tag = flow_id.flow_tag.to_string();
+        let connection_flow_tag_label = connection_flow_tag.to_string();
         loop {
             let loop_start_time = Instant::now();
             // If the TransportImpl has been deleted, abort.
@@ -140,33 +293,49 @@ impl TransportImpl {
                 Some(transport) => transport,
                 _ => return,
             };
-            // Wait for the send requests
-            let dequeued = send_queue_reader
-                .dequeue(
-                    DEQUEUE_BYTES,
-                    Duration::from_millis(TRANSPORT_HEARTBEAT_SEND_INTERVAL_MS),
-                )
-                .await;
 
-            let mut to_send = Vec::<u8>::new();
-            if dequeued.is_empty() {
-                // There is nothing to send, so issue a heartbeat message
-                to_send.append(&mut Self::pack_header(None, false, true));
-                state
-                    .data_plane_metrics
-                    .heart_beats_sent
-                    .with_label_values(&[&flow_label, &flow_tag])
-                    .inc();
-            } else {
-                for mut msg in dequeued {
-                    to_send.append(&mut Self::pack_header(
-                        Some(&msg.payload),
-                        msg.sender_error,
-                        false,
-                    ));
-                    to_send.append(&mut msg.payload.0);
+            // Wait for the next framed messages. The receivers are polled in
+            // priority order, so that the messages of the flow with the highest
+            // priority are written first.
+            let next_framed = tokio::time::timeout(
+                Duration::from_millis(TRANSPORT_HEARTBEAT_SEND_INTERVAL_MS),
+                poll_fn(|cx| {
+                    for framed_receiver in framed_receivers.iter_mut() {
+                        if let Poll::Ready(framed) = framed_receiver.poll_recv(cx) {
+                            return Poll::Ready(framed);
+                        }
+                    }
+                    Poll::Pending
+                }),
+            )
+            .await;
+
+            let to_send = match next_framed {
+                Ok(Some(framed)) => framed,
+                // The send tasks only exit together with the write task
+                Ok(None) => return,
+                Err(_) => {
+                    // There is nothing to send, so issue a heartbeat message
+                    state
+                        .data_plane_metrics
+                        .heart_beats_sent
+                        .with_label_values(&[&connection_label, &connection_flow_tag_label])
+                        .inc();
+                    FramedMessages {
+                        flow_tag: connection_flow_tag,
+                        flow_label: connection_label.clone(),
+                        buffers: vec![Bytes::from(Self::pack_header(
+                            connection_flow_tag,
+                            None,
+                            false,
+                            true,
+                        ))],
+                    }
                 }
-            }
+            };
+            let flow_label = to_send.flow_label.clone();
+            let flow_tag = to_send.flow_tag.to_string();
+            let to_send_len = to_send.len();
             state
                 .data_plane_metrics
                 .write_task_overhead_time_msec
@@ -175,14 +344,16 @@ impl TransportImpl {
 
             // Send the payload
             let start_time = Instant::now();
-            if let Err(e) = writer.write_all(&to_send).await {
+            if let Err(e) = Self::write_all_vectored(&mut writer, to_send.buffers).await {
                 warn!(
                     state.log,
-                    "DataPlane::flow_write_task(): failed to write payload: flow: {:?}, {:?}",
-                    flow_id,
+                    "DataPlane::connection_write_task(): failed to write payload: \
+                     client_type = {:?}, peer = {:?}, {:?}",
+                    client_type,
+                    peer_id,
                     e,
                 );
-                state.on_disconnect(flow_id).await;
+                state.on_disconnect(client_type, peer_id).await;
                 return;
             }
             state
@@ -194,28 +365,71 @@ impl TransportImpl {
                 .data_plane_metrics
                 .socket_write_bytes
                 .with_label_values(&[&flow_label, &flow_tag])
-                .inc_by(to_send.len() as u64);
+                .inc_by(to_send_len as u64);
             state
                 .data_plane_metrics
                 .socket_write_size
                 .with_label_values(&[&flow_label, &flow_tag])
-                .observe(to_send.len() as f64);
+                .observe(to_send_len as f64);
         }
     }
 
-    /// Per-flow receive task. Reads the messages from the socket and passes to
-    /// the client.
-    async fn flow_read_task(
-        flow_id: FlowId,
-        flow_label: String,
+    /// Writes the buffers to the socket with vectored writes, until all bytes
+    /// are written
+    async fn write_all_vectored<W: AsyncWrite + Unpin>(
+        writer: &mut W,
+        buffers: Vec<Bytes>,
+    ) -> std::io::Result<()> {
+        let mut buffers: VecDeque<Bytes> = buffers
+            .into_iter()
+            .filter(|buffer| !buffer.is_empty())
+            .collect();
+        while !buffers.is_empty() {
+            let io_slices = buffers
+                .iter()
+                .take(MAX_IO_SLICES)
+                .map(|buffer| IoSlice::new(buffer))
+                .collect::<Vec<_>>();
+            let mut written = writer.write_vectored(&io_slices).await?;
+            if written == 0 {
+                return Err(std::io::ErrorKind::WriteZero.into());
+            }
+            // Drop the fully written buffers and advance the partially
+            // written one
+            while written > 0 {
+                let front = buffers.front_mut().unwrap();
+                if written < front.len() {
+                    front.advance(written);
+                    written = 0;
+                } else {
+                    written -= front.len();
+                    buffers.pop_front();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Per-connection receive task. Reads the messages from the socket and
+    /// passes them to the client, on the flow indicated in the header. A
+    /// message that was already read from the socket during the connection
+    /// setup is processed first.
+    #[allow(clippy::too_many_arguments)]
+    async fn connection_read_task(
+        client_type: TransportClientType,
+        peer_id: NodeId,
+        connection_label: String,
+        connection_flow_tag: FlowTag,
+        flow_labels: HashMap<FlowTag, String>,
         event_handler: Arc<dyn AsyncTransportEventHandler>,
         mut reader: Box<TlsReadHalf>,
+        mut pending_message: Option<ReceivedMessage>,
         metrics: DataPlaneMetrics,
         state: Weak<TransportImpl>,
     ) {
         let heartbeat_timeout = Duration::from_millis(TRANSPORT_HEARTBEAT_WAIT_INTERVAL_MS);
         let _updater = MetricsUpdater::new(metrics.clone(), false);
-        let flow_tag = flow_id.flow_tag.to_string();
+        let connection_flow_tag_label = connection_flow_tag.to_string();
         loop {
             // If the TransportImpl has been deleted, abort.
             let state = match state.upgrade() {
@@ -224,39 +438,79 @@ impl TransportImpl {
             };
 
             // Read the next message from the socket
-            let ret = Self::read_one_message(&mut reader, heartbeat_timeout).await;
+            let ret = match pending_message.take() {
+                Some(message) => Ok(message),
+                None => Self::read_one_message(&mut reader, heartbeat_timeout).await,
+            };
             if ret.is_err() {
                 warn!(
                     state.log,
-                    "DataPlane::flow_read_task(): failed to receive message: flow: {:?}, {:?}",
-                    flow_id,
+                    "DataPlane::connection_read_task(): failed to receive message: \
+                     client_type = {:?}, peer = {:?}, {:?}",
+                    client_type,
+                    peer_id,
                     ret.as_ref().err(),
                 );
 
                 if let Err(ReadError::SocketReadTimeOut) = ret {
-                    event_handler
-                        .error(flow_id, TransportErrorCode::TimeoutExpired)
-                        .await;
+                    for flow_tag in flow_labels.keys() {
+                        let flow_id = FlowId::new(client_type, peer_id, *flow_tag);
+                        event_handler
+                            .error(flow_id, TransportErrorCode::TimeoutExpired)
+                            .await;
+                    }
                     metrics
                         .socket_heart_beat_timeouts
-                        .with_label_values(&[&flow_label, &flow_tag])
+                        .with_label_values(&[&connection_label, &connection_flow_tag_label])
                         .inc();
                 }
-                state.on_disconnect(flow_id).await;
+                state.on_disconnect(client_type, peer_id).await;
                 return;
             }
 
             // Process the received message
             let (header, payload) = ret.unwrap();
+            if header.flags & TRANSPORT_FLAGS_IS_HELLO != 0 {
+                // The hello was already exchanged during connection setup,
+                // only the observed address reported after it is left.
+                if let Some(observed_addr) = Self::parse_observed_addr(&header, payload.as_ref()) {
+                    state.on_observed_addr(peer_id, observed_addr);
+                }
+                continue;
+            }
             if header.flags & TRANSPORT_FLAGS_IS_HEARTBEAT != 0 {
                 // It's an empty heartbeat message -- do nothing
                 metrics
                     .heart_beats_received
-                    .with_label_values(&[&flow_label, &flow_tag])
+                    .with_label_values(&[&connection_label, &connection_flow_tag_label])
                     .inc();
                 continue;
             }
 
+            // Look up the flow the message belongs to
+            let flow_tag = FlowTag::from(header.flow_tag);
+            let flow_tag_label = flow_tag.to_string();
+            let flow_label = match flow_labels.get(&flow_tag) {
+                Some(flow_label) => flow_label,
+                None => {
+                    metrics
+                        .unknown_flow_messages
+                        .with_label_values(&[&connection_label, &flow_tag_label])
+                        .inc();
+                    warn!(
+                        every_n_seconds => 30,
+                        state.log,
+                        "DataPlane::connection_read_task(): dropping message for unknown flow: \
+                         client_type = {:?}, peer = {:?}, flow_tag = {:?}",
+                        client_type,
+                        peer_id,
+                        flow_tag,
+                    );
+                    continue;
+                }
+            };
+            let flow_id = FlowId::new(client_type, peer_id, flow_tag);
+
             // Pass up sender indicated error
             if header.flags & TRANSPORT_FLAGS_SENDER_ERROR != 0 {
                 event_handler
@@ -264,7 +518,7 @@ impl TransportImpl {
                     .await;
                 metrics
                     .send_errors_received
-                    .with_label_values(&[&flow_label, &flow_tag])
+                    .with_label_values(&[flow_label, &flow_tag_label])
                     .inc();
             }
 
@@ -274,13 +528,13 @@ impl TransportImpl {
             let payload = payload.unwrap();
             metrics
                 .socket_read_bytes
-                .with_label_values(&[&flow_label, &flow_tag])
+                .with_label_values(&[flow_label, &flow_tag_label])
                 .inc_by(payload.0.len() as u64);
             let start_time = Instant::now();
             let _ = event_handler.send_message(flow_id, payload).await;
             metrics
                 .client_send_time_msec
-                .with_label_values(&[&flow_label, &flow_tag])
+                .with_label_values(&[flow_label, &flow_tag_label])
                 .observe(start_time.elapsed().as_millis() as f64);
         }
     }
@@ -288,15 +542,15 @@ impl TransportImpl {
     /// Reads and returns the next <message hdr, message payload> from the
     /// socket. The timeout is for each socket read (header, payload chunks)
     /// and not the full message.
-    async fn read_one_message(
-        reader: &mut Box<TlsReadHalf>,
+    async fn read_one_message<R: AsyncRead + Unpin>(
+        reader: &mut R,
         timeout: Duration,
-    ) -> Result<(TransportHeader, Option<TransportPayload>), ReadError> {
+    ) -> Result<ReceivedMessage, ReadError> {
         // Read the hdr
         let mut header_buffer = vec![0u8; TRANSPORT_HEADER_SIZE];
         Self::read_from_socket(reader, &mut header_buffer, timeout).await?;
 
-        let header = Self::unpack_header(header_buffer);
+        let header = Self::unpack_header(header_buffer)?;
         if header.flags & TRANSPORT_FLAGS_IS_HEARTBEAT != 0 {
             return Ok((header, None));
         }
@@ -319,13 +573,13 @@ impl TransportImpl {
             cur_offset += cur_chunk_size;
         }
 
-        let payload = TransportPayload(payload_buffer);
+        let payload = TransportPayload::from(payload_buffer);
         Ok((header, Some(payload)))
     }
 
     /// Reads the requested bytes from the socket with a timeout
-    async fn read_from_socket(
-        reader: &mut Box<TlsReadHalf>,
+    async fn read_from_socket<R: AsyncRead + Unpin>(
+        reader: &mut R,
         buf: &mut [u8],
         timeout: Duration,
     ) -> Result<(), ReadError> {
@@ -341,90 +595,219 @@ impl TransportImpl {
         }
     }
 
+    /// Exchanges the hello messages with the peer over a newly established
+    /// connection, before any other message is sent, and returns the
+    /// negotiated wire codec. Both sides pick the codec preferred by the
+    /// server among the codecs supported by the client.
+    ///
+    /// A peer that predates the hello sends a regular message instead. Its
+    /// message is returned, so that it is delivered by the read task, and
+    /// protobuf is used for the connection.
+    pub(crate) async fn exchange_hello<R, W>(
+        configured_codecs: &[WireCodecId],
+        role: ConnectionRole,
+        reader: &mut R,
+        writer: &mut W,
+    ) -> Result<(WireCodecId, Option<ReceivedMessage>), TransportErrorCode>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let local_codecs = supported_wire_codecs(configured_codecs);
+        let payload = TransportPayload::from(
+            local_codecs
+                .iter()
+                .map(|codec| *codec as u8)
+                .collect::<Vec<_>>(),
+        );
+        let buffers = vec![Bytes::from(Self::pack_hello_header(&payload, 0)), payload.0];
+        Self::write_all_vectored(writer, buffers)
+            .await
+            .map_err(|e| TransportErrorCode::ConnectionWriteFailed(e.to_string()))?;
+
+        let heartbeat_timeout = Duration::from_millis(TRANSPORT_HEARTBEAT_WAIT_INTERVAL_MS);
+        let (header, payload) = Self::read_one_message(reader, heartbeat_timeout)
+            .await
+            .map_err(|e| TransportErrorCode::ConnectionReadFailed(format!("{:?}", e)))?;
+        if header.flags & TRANSPORT_FLAGS_IS_HELLO == 0 {
+            return Ok((WireCodecId::Protobuf, Some((header, payload))));
+        }
+        // Codecs unknown to this node are ignored.
+        let peer_codecs: Vec<WireCodecId> = payload
+            .map(|payload| payload.0.to_vec())
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|id| WireCodecId::try_from(id).ok())
+            .collect();
+        let wire_codec = match role {
+            ConnectionRole::Server => negotiate_wire_codec(&local_codecs, &peer_codecs),
+            ConnectionRole::Client => negotiate_wire_codec(&peer_codecs, &local_codecs),
+        };
+        Ok((wire_codec, None))
+    }
+
+    /// Reports to the peer the address it is observed under, in a hello
+    /// message that follows the one of `exchange_hello`.
+    pub(crate) async fn send_observed_addr<W>(
+        observed_addr: SocketAddr,
+        writer: &mut W,
+    ) -> Result<(), TransportErrorCode>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let payload = TransportPayload::from(observed_addr.to_string().into_bytes());
+        let header = Self::pack_hello_header(&payload, TRANSPORT_FLAGS_IS_OBSERVED_ADDR);
+        Self::write_all_vectored(writer, vec![Bytes::from(header), payload.0])
+            .await
+            .map_err(|e| TransportErrorCode::ConnectionWriteFailed(e.to_string()))
+    }
+
+    /// Parses the observed address reported in a hello message, if any.
+    fn parse_observed_addr(
+        header: &TransportHeader,
+        payload: Option<&TransportPayload>,
+    ) -> Option<SocketAddr> {
+        if header.flags & TRANSPORT_FLAGS_IS_OBSERVED_ADDR == 0 {
+            return None;
+        }
+        std::str::from_utf8(&payload?.0).ok()?.parse().ok()
+    }
+
     /// Handle peer disconnect.
-    async fn on_disconnect(&self, flow_id: FlowId) {
-        if let Err(e) = self.retry_connection(&flow_id) {
+    async fn on_disconnect(&self, client_type: TransportClientType, peer_id: NodeId) {
+        if let Err(e) = self.retry_connection(client_type, &peer_id) {
             warn!(
                 self.log,
-                "DataPlane::on_disconnect(): retry_connection error {:?}: flow: {:?}", flow_id, e
+                "DataPlane::on_disconnect(): retry_connection error {:?}: \
+                 client_type = {:?}, peer = {:?}",
+                e,
+                client_type,
+                peer_id
             );
             return;
         }
-        let event_handler = {
-            let mut cl_map = self.client_map.write().unwrap();
-            let client_state = match cl_map.get_mut(&flow_id.client_type) {
+        let (event_handler, flow_tags) = {
+            let client_map = self.client_map.read().unwrap();
+            let client_state = match client_map.get(&client_type) {
                 Some(client_state) => client_state,
                 _ => return,
             };
-            client_state.event_handler.clone()
+            let flow_tags = match client_state.peer_map.get(&peer_id) {
+                Some(peer_state) => peer_state.flow_map.keys().copied().collect::<Vec<_>>(),
+                _ => return,
+            };
+            (client_state.event_handler.clone(), flow_tags)
         };
+        for flow_tag in flow_tags {
+            event_handler
+                .state_changed(TransportStateChange::PeerFlowDown(TransportFlowInfo {
+                    peer_id,
+                    flow_tag,
+                }))
+                .await;
+        }
         event_handler
-            .state_changed(TransportStateChange::PeerFlowDown(TransportFlowInfo {
-                peer_id: flow_id.peer_id,
-                flow_tag: flow_id.flow_tag,
-            }))
+            .state_changed(TransportStateChange::PeerConnectionDown(peer_id))
             .await;
     }
 
-    /// Handle connection setup. Starts flow read and write tasks.
+    /// Handle connection setup. Starts the send tasks of all flows with the
+    /// peer, and the connection read and write tasks.
+    ///
+    /// Returns the event handler and the tags of the flows that are up.
+    #[allow(clippy::too_many_arguments)]
     fn on_connect_setup(
         &self,
-        flow_id: FlowId,
+        client_type: TransportClientType,
+        peer_id: NodeId,
         role: ConnectionRole,
+        wire_codec: WireCodecId,
         peer_addr: SocketAddr,
         reader: Box<TlsReadHalf>,
+        pending_message: Option<ReceivedMessage>,
         writer: Box<TlsWriteHalf>,
-    ) -> Result<Arc<dyn AsyncTransportEventHandler>, TransportErrorCode> {
+    ) -> Result<(Arc<dyn AsyncTransportEventHandler>, Vec<FlowTag>), TransportErrorCode> {
         let mut client_map = self.client_map.write().unwrap();
-        let client_state = match client_map.get_mut(&flow_id.client_type) {
+        let client_state = match client_map.get_mut(&client_type) {
             Some(client_state) => client_state,
             None => return Err(TransportErrorCode::TransportClientNotFound),
         };
         let event_handler = client_state.event_handler.clone();
 
-        let peer_state = match client_state.peer_map.get_mut(&flow_id.peer_id) {
-            Some(client_state) => client_state,
+        let peer_state = match client_state.peer_map.get_mut(&peer_id) {
+            Some(peer_state) => peer_state,
             None => return Err(TransportErrorCode::TransportClientNotFound),
         };
-        let flow_state = match peer_state.flow_map.get_mut(&flow_id.flow_tag) {
-            Some(flow_state) => flow_state,
-            None => return Err(TransportErrorCode::FlowNotFound),
-        };
 
-        if let ConnectionState::Connected(_) = flow_state.connection_state {
+        if let ConnectionState::Connected(_) = peer_state.connection_state {
             // TODO: P2P-516
             return Err(TransportErrorCode::FlowConnectionUp);
         }
 
+        // Set up the send tasks in priority order, i.e., in the order of the
+        // flows in the config.
+        let mut flow_tags = Vec::new();
+        let mut flow_labels = HashMap::new();
+        let mut send_tasks = Vec::new();
+        let mut framed_receivers = Vec::new();
+        for flow_config in &self.config.p2p_flows {
+            let flow_tag = FlowTag::from(flow_config.flow_tag);
+            let flow_state = match peer_state.flow_map.get(&flow_tag) {
+                Some(flow_state) => flow_state,
+                None => continue,
+            };
+            let (framed_sender, framed_receiver) = channel(1);
+            send_tasks.push(Self::flow_send_task(
+                flow_state.flow_id,
+                flow_state.flow_label.clone(),
+                flow_state.send_queue.get_reader(),
+                flow_state.rate_limiter.clone(),
+                framed_sender,
+                self.data_plane_metrics.clone(),
+            ));
+            framed_receivers.push(framed_receiver);
+            flow_labels.insert(flow_tag, flow_state.flow_label.clone());
+            flow_tags.push(flow_tag);
+        }
+
         // Spawn write task
-        let flow_id_cl = flow_state.flow_id;
-        let flow_label_cl = flow_state.flow_label.clone();
-        let send_queue_reader = flow_state.send_queue.get_reader();
+        let connection_label_cl = peer_state.connection_label.clone();
+        let connection_flow_tag_cl = peer_state.connection_flow_tag;
         let metrics_cl = self.data_plane_metrics.clone();
         let weak_self = self.weak_self.read().unwrap().clone();
         let write_task = async move {
-            Self::flow_write_task(
-                flow_id_cl,
-                flow_label_cl,
-                send_queue_reader,
-                writer,
-                metrics_cl,
-                weak_self,
+            // The send tasks run as long as the write task.
+            select(
+                Box::pin(Self::connection_write_task(
+                    client_type,
+                    peer_id,
+                    connection_label_cl,
+                    connection_flow_tag_cl,
+                    framed_receivers,
+                    writer,
+                    metrics_cl,
+                    weak_self,
+                )),
+                Box::pin(futures::future::join_all(send_tasks)),
             )
             .await;
         };
 
-        let flow_id_cl = flow_id;
-        let flow_label_cl = flow_state.flow_label.clone();
+        let connection_label_cl = peer_state.connection_label.clone();
+        let connection_flow_tag_cl = peer_state.connection_flow_tag;
         let event_handler_cl = event_handler.clone();
         let metrics_cl = self.data_plane_metrics.clone();
         let weak_self = self.weak_self.read().unwrap().clone();
         let read_task = async move {
-            Self::flow_read_task(
-                flow_id_cl,
-                flow_label_cl,
+            Self::connection_read_task(
+                client_type,
+                peer_id,
+                connection_label_cl,
+                connection_flow_tag_cl,
+                flow_labels,
                 event_handler_cl,
                 reader,
+                pending_message,
                 metrics_cl,
                 weak_self,
             )
@@ -434,53 +817,84 @@ impl TransportImpl {
         // Spawn the tasks with abort handles so tasks can be aborted if needed.
         let (write_abort_handle, abort_registration) = AbortHandle::new_pair();
         let log_cl = self.log.clone();
-        let flow_id_cl = flow_id;
         self.tokio_runtime.spawn(async move {
             if let Err(Aborted) = Abortable::new(write_task, abort_registration).await {
                 warn!(
                     log_cl,
-                    "DataPlane:: Send task aborted: flow = {:?}", flow_id_cl
+                    "DataPlane:: Send task aborted: client_type = {:?}, peer = {:?}",
+                    client_type,
+                    peer_id
                 );
             }
         });
 
         let (read_abort_handle, abort_registration) = AbortHandle::new_pair();
         let log_cl = self.log.clone();
-        let flow_id_cl = flow_id;
         self.tokio_runtime.spawn(async move {
             if let Err(Aborted) = Abortable::new(read_task, abort_registration).await {
                 warn!(
                     log_cl,
-                    "DataPlane:: Receive task aborted: flow = {:?}", flow_id_cl
+                    "DataPlane:: Receive task aborted: client_type = {:?}, peer = {:?}",
+                    client_type,
+                    peer_id
                 );
             }
         });
 
+        // With NAT traversal, a peer connects from its server port, so the
+        // address it connects from is the one its NAT maps the server port
+        // to. Reconnections try that address first.
+        if self.config.nat_traversal && role == ConnectionRole::Server {
+            for connect_addr in self.connect_addrs(&[peer_addr]) {
+                peer_state.prefer_connect_addr(connect_addr);
+            }
+        }
+
         let connected_state = Connected {
             peer_addr,
             read_task: read_abort_handle,
             write_task: write_abort_handle,
             role,
+            wire_codec,
         };
-        flow_state.update(ConnectionState::Connected(connected_state));
-        Ok(event_handler)
+        peer_state.update(ConnectionState::Connected(connected_state));
+        Ok((event_handler, flow_tags))
     }
 
     /// Handle peer connection
+    #[allow(clippy::too_many_arguments)]
     pub(crate) async fn on_connect(
         &self,
-        flow_id: FlowId,
+        client_type: TransportClientType,
+        peer_id: NodeId,
         role: ConnectionRole,
+        wire_codec: WireCodecId,
         peer_addr: SocketAddr,
         reader: Box<TlsReadHalf>,
+        pending_message: Option<ReceivedMessage>,
         writer: Box<TlsWriteHalf>,
     ) -> Result<(), TransportErrorCode> {
-        self.on_connect_setup(flow_id, role, peer_addr, reader, writer)?
-            // Notify the client that peer flow is up.
-            .state_changed(TransportStateChange::PeerFlowUp(TransportFlowInfo {
-                peer_id: flow_id.peer_id,
-                flow_tag: flow_id.flow_tag,
-            }))
+        let (event_handler, flow_tags) = self.on_connect_setup(
+            client_type,
+            peer_id,
+            role,
+            wire_codec,
+            peer_addr,
+            reader,
+            pending_message,
+            writer,
+        )?;
+        // Notify the client that the peer flows are up.
+        for flow_tag in flow_tags {
+            event_handler
+                .state_changed(TransportStateChange::PeerFlowUp(TransportFlowInfo {
+                    peer_id,
+                    flow_tag,
+                }))
+                .await;
+        }
+        event_handler
+            .state_changed(TransportStateChange::PeerConnectionUp(peer_id))
             .await;
         Ok(())
     }
@@ -517,3 +931,152 @@ impl Drop for MetricsUpdater {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_carries_flow_tag() {
+        let payload = TransportPayload::from(vec![0u8; 42]);
+        let header = TransportImpl::unpack_header(TransportImpl::pack_header(
+            FlowTag::from(1235),
+            Some(&payload),
+            true,
+            false,
+        ))
+        .unwrap();
+        assert_eq!(header.version, TRANSPORT_HEADER_VERSION);
+        assert_eq!(header.flags, TRANSPORT_FLAGS_SENDER_ERROR);
+        assert_eq!(header.flow_tag, 1235);
+        assert_eq!(header.payload_length, 42);
+    }
+    #[test]
+    fn test_unknown_header_version_is_rejected() {
+        let mut header = TransportImpl::pack_header(FlowTag::from(1235), None, false, true);
+        header[0] = TRANSPORT_HEADER_VERSION + 1;
+        match TransportImpl::unpack_header(header) {
+            Err(ReadError::UnsupportedHeaderVersion(version)) => {
+                assert_eq!(version, TRANSPORT_HEADER_VERSION + 1)
+            }
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hello_negotiates_the_server_codec() {
+        let (server_stream, client_stream) = tokio::io::duplex(1024);
+        let (mut server_reader, mut server_writer) = tokio::io::split(server_stream);
+        let (mut client_reader, mut client_writer) = tokio::io::split(client_stream);
+        let (server, client) = tokio::join!(
+            TransportImpl::exchange_hello(
+                &[WireCodecId::Bincode, WireCodecId::Cbor],
+                ConnectionRole::Server,
+                &mut server_reader,
+                &mut server_writer,
+            ),
+            TransportImpl::exchange_hello(
+                &[WireCodecId::Cbor, WireCodecId::Bincode],
+                ConnectionRole::Client,
+                &mut client_reader,
+                &mut client_writer,
+            ),
+        );
+        let (server_codec, server_pending) = server.unwrap();
+        let (client_codec, client_pending) = client.unwrap();
+        assert_eq!(server_codec, WireCodecId::Bincode);
+        assert_eq!(client_codec, WireCodecId::Bincode);
+        assert!(server_pending.is_none());
+        assert!(client_pending.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_hello_is_sent_on_the_reserved_flow() {
+        let (local_stream, mut peer_stream) = tokio::io::duplex(1024);
+        let (mut local_reader, mut local_writer) = tokio::io::split(local_stream);
+        // The peer never answers, so the exchange times out eventually.
+        let _ = tokio::time::timeout(
+            Duration::from_millis(100),
+            TransportImpl::exchange_hello(
+                &[WireCodecId::Cbor],
+                ConnectionRole::Client,
+                &mut local_reader,
+                &mut local_writer,
+            ),
+        )
+        .await;
+
+        let (header, payload) = TransportImpl::read_one_message(
+            &mut peer_stream,
+            Duration::from_millis(TRANSPORT_HEARTBEAT_WAIT_INTERVAL_MS),
+        )
+        .await
+        .unwrap();
+        assert_eq!(header.flags, TRANSPORT_FLAGS_IS_HELLO);
+        assert_eq!(header.flow_tag, TRANSPORT_HELLO_FLOW_TAG);
+        assert_eq!(
+            payload.unwrap().0.to_vec(),
+            vec![WireCodecId::Cbor as u8, WireCodecId::Protobuf as u8]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_observed_addr_is_reported_in_a_hello() {
+        let (mut local_stream, mut peer_stream) = tokio::io::duplex(1024);
+        let observed_addr: SocketAddr = "[2001:db8::1]:4100".parse().unwrap();
+        TransportImpl::send_observed_addr(observed_addr, &mut local_stream)
+            .await
+            .unwrap();
+
+        let (header, payload) = TransportImpl::read_one_message(
+            &mut peer_stream,
+            Duration::from_millis(TRANSPORT_HEARTBEAT_WAIT_INTERVAL_MS),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            header.flags,
+            TRANSPORT_FLAGS_IS_HELLO | TRANSPORT_FLAGS_IS_OBSERVED_ADDR
+        );
+        assert_eq!(header.flow_tag, TRANSPORT_HELLO_FLOW_TAG);
+        assert_eq!(
+            TransportImpl::parse_observed_addr(&header, payload.as_ref()),
+            Some(observed_addr)
+        );
+
+        // The codec hello does not report an address.
+        let payload = TransportPayload::from(vec![WireCodecId::Protobuf as u8]);
+        let header =
+            TransportImpl::unpack_header(TransportImpl::pack_hello_header(&payload, 0)).unwrap();
+        assert_eq!(
+            TransportImpl::parse_observed_addr(&header, Some(&payload)),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_peer_without_hello_uses_protobuf() {
+        let (local_stream, mut peer_stream) = tokio::io::duplex(1024);
+        let (mut local_reader, mut local_writer) = tokio::io::split(local_stream);
+        // A peer that predates the hello starts with a regular message.
+        let payload = TransportPayload::from(vec![1u8, 2, 3]);
+        let mut message =
+            TransportImpl::pack_header(FlowTag::from(1234), Some(&payload), false, false);
+        message.extend_from_slice(&payload.0);
+        peer_stream.write_all(&message).await.unwrap();
+
+        let (wire_codec, pending_message) = TransportImpl::exchange_hello(
+            &[WireCodecId::Cbor],
+            ConnectionRole::Server,
+            &mut local_reader,
+            &mut local_writer,
+        )
+        .await
+        .unwrap();
+        assert_eq!(wire_codec, WireCodecId::Protobuf);
+        let (header, pending_payload) = pending_message.unwrap();
+        assert_eq!(header.flags & TRANSPORT_FLAGS_IS_HELLO, 0);
+        assert_eq!(header.flow_tag, 1234);
+        assert_eq!(pending_payload, Some(payload));
+    }
+}
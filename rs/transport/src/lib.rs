@@ -30,11 +30,14 @@
 //! Gossip uses separate flows for control messages (adverts, requests) and data
 //! messages (artifact chunks), for ingress manager, consensus (incl DKG and
 //! certification) and state sync. Thus, Transport has to handle 3 x 3 flows per
-//! peer for Gossip.
+//! peer for Gossip. All flows with a peer are multiplexed over a single TLS
+//! connection.
 
 mod control_plane;
 mod data_plane;
+pub mod loopback;
 mod metrics;
+mod reconnect;
 pub mod transport;
 mod types;
 mod utils;
@@ -0,0 +1,820 @@
+//! In-memory loopback transport.
+//!
+//! The loopback transport implements the `Transport` interface over
+//! in-process channels, so that tests can run many replicas in a single
+//! process without opening sockets. All loopback transports of a test are
+//! connected through a `LoopbackHub`, which simulates the network between
+//! them:
+//!
+//! * Every message is delayed by the configured latency plus a random jitter.
+//!   Messages of a flow are delivered in the order in which they were sent,
+//!   as with a TCP connection.
+//! * Messages are dropped with the configured drop rate.
+//! * The network can be partitioned into groups of nodes. Connections between
+//!   nodes in different groups go down until the partition is healed.
+//!
+//! The connection with a peer is up when both nodes have started connections
+//! to each other and they are not partitioned. The client is notified about
+//! connection changes for each flow, like with the TCP transport. Messages
+//! sent while the connection is down, and messages still queued when it goes
+//! down, are dropped.
+//!
+//! The conditions can be changed while the test is running. A node can be
+//! detached from the hub, e.g. to simulate a crash, after which it is
+//! disconnected from all peers. A hub created with a seed draws the jitter and
+//! drops of messages deterministically.
+
+use ic_interfaces::transport::{AsyncTransportEventHandler, Transport};
+use ic_protobuf::registry::node::v1::NodeRecord;
+use ic_types::transport::{
+    FlowId, FlowTag, TransportClientType, TransportErrorCode, TransportFlowInfo, TransportPayload,
+    TransportStateChange, WireCodecId,
+};
+use ic_types::{NodeId, RegistryVersion};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::time::Duration;
+use tokio::runtime::Handle;
+use tokio::sync::{mpsc, Notify};
+use tokio::time::{sleep_until, Instant};
+
+/// The network conditions simulated by the loopback hub.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LoopbackConfig {
+    /// The delay of every message
+    pub latency: Duration,
+
+    /// The maximum random delay added to the latency of a message
+    pub jitter: Duration,
+
+    /// The probability with which a message is dropped, between 0 and 1
+    pub drop_rate: f64,
+
+    /// The flows set up with every peer
+    pub flow_tags: Vec<FlowTag>,
+}
+
+impl Default for LoopbackConfig {
+    fn default() -> Self {
+        Self {
+            latency: Duration::from_millis(0),
+            jitter: Duration::from_millis(0),
+            drop_rate: 0.0,
+            flow_tags: vec![FlowTag::from(0)],
+        }
+    }
+}
+
+/// The hub connecting the loopback transports of a test.
+pub struct LoopbackHub {
+    /// The simulated network conditions
+    config: RwLock<LoopbackConfig>,
+
+    /// The partition group of each node, if the network is partitioned
+    partition: RwLock<Option<BTreeMap<NodeId, usize>>>,
+
+    /// The transports connected to the hub
+    transports: RwLock<BTreeMap<NodeId, Weak<LoopbackTransport>>>,
+
+    /// Serializes the connection state changes of all transports
+    connection_lock: Mutex<()>,
+
+    /// Draws the jitter and the drops of messages
+    rng: Mutex<StdRng>,
+}
+
+impl LoopbackHub {
+    /// Creates a hub simulating the given network conditions.
+    pub fn new(config: LoopbackConfig) -> Arc<Self> {
+        Self::with_rng(config, StdRng::from_entropy())
+    }
+
+    /// Creates a hub simulating the given network conditions, which draws the
+    /// jitter and the drops of messages from an RNG with the given seed.
+    pub fn with_seed(config: LoopbackConfig, seed: u64) -> Arc<Self> {
+        Self::with_rng(config, StdRng::seed_from_u64(seed))
+    }
+
+    fn with_rng(config: LoopbackConfig, rng: StdRng) -> Arc<Self> {
+        validate_config(&config);
+        Arc::new(Self {
+            config: RwLock::new(config),
+            partition: RwLock::new(None),
+            transports: RwLock::new(BTreeMap::new()),
+            connection_lock: Mutex::new(()),
+            rng: Mutex::new(rng),
+        })
+    }
+
+    /// Creates the transport of the given node. The tasks of the transport
+    /// are spawned on the given runtime.
+    pub fn create_transport(
+        self: &Arc<Self>,
+        node_id: NodeId,
+        rt_handle: Handle,
+    ) -> Arc<LoopbackTransport> {
+        let (state_change_sender, mut state_change_receiver) = mpsc::unbounded_channel();
+        let transport = Arc::new(LoopbackTransport {
+            node_id,
+            hub: self.clone(),
+            rt_handle: rt_handle.clone(),
+            clients: RwLock::new(HashMap::new()),
+            peers: Mutex::new(HashMap::new()),
+            state_change_sender,
+        });
+        // The state changes are reported in order by a single task.
+        rt_handle.spawn(async move {
+            while let Some((event_handler, state_change)) = state_change_receiver.recv().await {
+                event_handler.state_changed(state_change).await;
+            }
+        });
+        let previous = self
+            .transports
+            .write()
+            .unwrap()
+            .insert(node_id, Arc::downgrade(&transport));
+        assert!(
+            previous.map_or(true, |previous| previous.upgrade().is_none()),
+            "Node {} is already connected to the hub",
+            node_id
+        );
+        transport
+    }
+
+    /// Changes the simulated network conditions. The latency, jitter and drop
+    /// rate apply to the messages sent after the change.
+    pub fn set_config(&self, config: LoopbackConfig) {
+        validate_config(&config);
+        *self.config.write().unwrap() = config;
+    }
+
+    /// Partitions the network into the given groups of nodes. Nodes can only
+    /// reach the nodes of their own group. Nodes that are not in any of the
+    /// groups form one additional group.
+    pub fn partition(&self, groups: &[Vec<NodeId>]) {
+        let partition = groups
+            .iter()
+            .enumerate()
+            .flat_map(|(group, node_ids)| node_ids.iter().map(move |node_id| (*node_id, group)))
+            .collect();
+        *self.partition.write().unwrap() = Some(partition);
+        self.update_connections();
+    }
+
+    /// Heals the partition of the network.
+    pub fn heal(&self) {
+        *self.partition.write().unwrap() = None;
+        self.update_connections();
+    }
+
+    /// Detaches the transport of the given node from the hub. The node is
+    /// disconnected from all its peers, and a new transport can be created for
+    /// it, e.g. when the node restarts.
+    pub fn detach(&self, node_id: &NodeId) {
+        let transport = self
+            .transports
+            .write()
+            .unwrap()
+            .remove(node_id)
+            .and_then(|transport| transport.upgrade());
+        if let Some(transport) = &transport {
+            let _guard = self.connection_lock.lock().unwrap();
+            let peers: Vec<_> = transport.peers.lock().unwrap().keys().copied().collect();
+            for (client_type, peer_id) in peers {
+                transport.update_connection(client_type, &peer_id);
+            }
+        }
+        self.update_connections();
+    }
+
+    /// Returns the transport of the given node.
+    fn transport(&self, node_id: &NodeId) -> Option<Arc<LoopbackTransport>> {
+        self.transports
+            .read()
+            .unwrap()
+            .get(node_id)
+            .and_then(Weak::upgrade)
+    }
+
+    /// Returns true if the nodes are not partitioned.
+    fn reachable(&self, node_id: &NodeId, peer_id: &NodeId) -> bool {
+        match &*self.partition.read().unwrap() {
+            Some(partition) => partition.get(node_id) == partition.get(peer_id),
+            None => true,
+        }
+    }
+
+    /// Updates the connection state of all transports.
+    fn update_connections(&self) {
+        let _guard = self.connection_lock.lock().unwrap();
+        let transports: Vec<_> = self
+            .transports
+            .read()
+            .unwrap()
+            .values()
+            .filter_map(Weak::upgrade)
+            .collect();
+        for transport in transports {
+            let peers: Vec<_> = transport.peers.lock().unwrap().keys().copied().collect();
+            for (client_type, peer_id) in peers {
+                transport.update_connection(client_type, &peer_id);
+            }
+        }
+    }
+}
+
+/// Panics if the config is invalid.
+fn validate_config(config: &LoopbackConfig) {
+    assert!(
+        (0.0..=1.0).contains(&config.drop_rate),
+        "Invalid drop rate {}",
+        config.drop_rate
+    );
+}
+
+/// The messages of a flow that are waiting for delivery, with their delivery
+/// times.
+#[derive(Default)]
+struct FlowQueue {
+    messages: Mutex<VecDeque<(Instant, TransportPayload)>>,
+    notify: Notify,
+    closed: AtomicBool,
+}
+
+impl FlowQueue {
+    fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.notify.notify_one();
+    }
+}
+
+/// The state of a peer of a loopback transport.
+struct PeerState {
+    /// True if the connection with the peer is up
+    connected: bool,
+
+    /// The send queues of the flows with the peer
+    flows: Vec<(FlowTag, Arc<FlowQueue>)>,
+}
+
+impl PeerState {
+    fn flow(&self, flow_tag: FlowTag) -> Result<&Arc<FlowQueue>, TransportErrorCode> {
+        self.flows
+            .iter()
+            .find(|(tag, _)| *tag == flow_tag)
+            .map(|(_, queue)| queue)
+            .ok_or(TransportErrorCode::FlowNotFound)
+    }
+}
+
+/// The loopback transport of a node.
+pub struct LoopbackTransport {
+    /// The ID of the node
+    node_id: NodeId,
+
+    /// The hub connecting the transport with its peers
+    hub: Arc<LoopbackHub>,
+
+    /// The runtime on which the tasks of the transport are spawned
+    rt_handle: Handle,
+
+    /// The event handlers of the registered clients
+    clients: RwLock<HashMap<TransportClientType, Arc<dyn AsyncTransportEventHandler>>>,
+
+    /// The peers with started connections
+    peers: Mutex<HashMap<(TransportClientType, NodeId), PeerState>>,
+
+    /// Reports the state changes to the clients
+    state_change_sender:
+        mpsc::UnboundedSender<(Arc<dyn AsyncTransportEventHandler>, TransportStateChange)>,
+}
+
+impl LoopbackTransport {
+    /// Brings the connection with the peer up or down, depending on the
+    /// state of both nodes and the partition of the network. Must be called
+    /// with the connection lock of the hub held.
+    fn update_connection(&self, client_type: TransportClientType, peer_id: &NodeId) {
+        let attached = self
+            .hub
+            .transport(&self.node_id)
+            .map_or(false, |transport| std::ptr::eq(&*transport, self));
+        let connected = attached
+            && self.hub.reachable(&self.node_id, peer_id)
+            && self.hub.transport(peer_id).map_or(false, |peer| {
+                peer.peers
+                    .lock()
+                    .unwrap()
+                    .contains_key(&(client_type, self.node_id))
+            });
+        let flow_tags: Vec<_> = {
+            let mut peers = self.peers.lock().unwrap();
+            let peer = match peers.get_mut(&(client_type, *peer_id)) {
+                Some(peer) if peer.connected != connected => peer,
+                _ => return,
+            };
+            peer.connected = connected;
+            if !connected {
+                for (_, queue) in &peer.flows {
+                    queue.messages.lock().unwrap().clear();
+                }
+            }
+            peer.flows.iter().map(|(flow_tag, _)| *flow_tag).collect()
+        };
+        self.report_connection_change(client_type, *peer_id, &flow_tags, connected);
+    }
+
+    /// Reports the connection change with the peer for each flow and for the
+    /// connection.
+    fn report_connection_change(
+        &self,
+        client_type: TransportClientType,
+        peer_id: NodeId,
+        flow_tags: &[FlowTag],
+        connected: bool,
+    ) {
+        let event_handler = match self.clients.read().unwrap().get(&client_type) {
+            Some(event_handler) => event_handler.clone(),
+            None => return,
+        };
+        let flow_state_changes = flow_tags.iter().map(|flow_tag| {
+            let flow_info = TransportFlowInfo {
+                peer_id,
+                flow_tag: *flow_tag,
+            };
+            if connected {
+                TransportStateChange::PeerFlowUp(flow_info)
+            } else {
+                TransportStateChange::PeerFlowDown(flow_info)
+            }
+        });
+        let connection_state_change = if connected {
+            TransportStateChange::PeerConnectionUp(peer_id)
+        } else {
+            TransportStateChange::PeerConnectionDown(peer_id)
+        };
+        for state_change in flow_state_changes.chain(std::iter::once(connection_state_change)) {
+            // The receiver only stops when the runtime shuts down.
+            let _ = self
+                .state_change_sender
+                .send((event_handler.clone(), state_change));
+        }
+    }
+
+    /// Returns the event handler of the client, if the connection with the
+    /// peer is up.
+    fn connected_event_handler(
+        &self,
+        client_type: TransportClientType,
+        peer_id: &NodeId,
+    ) -> Option<Arc<dyn AsyncTransportEventHandler>> {
+        let connected = self
+            .peers
+            .lock()
+            .unwrap()
+            .get(&(client_type, *peer_id))
+            .map_or(false, |peer| peer.connected);
+        if !connected {
+            return None;
+        }
+        self.clients.read().unwrap().get(&client_type).cloned()
+    }
+}
+
+impl Drop for LoopbackTransport {
+    fn drop(&mut self) {
+        for peer in self.peers.get_mut().unwrap().values() {
+            for (_, queue) in &peer.flows {
+                queue.close();
+            }
+        }
+    }
+}
+
+/// Delivers the messages of a flow from the sender to the receiver, once their
+/// delivery time has come.
+async fn flow_delivery_task(
+    hub: Arc<LoopbackHub>,
+    flow_id: FlowId,
+    receiver_id: NodeId,
+    queue: Arc<FlowQueue>,
+) {
+    while !queue.closed.load(Ordering::SeqCst) {
+        let next = {
+            let mut messages = queue.messages.lock().unwrap();
+            match messages.front().map(|(deliver_at, _)| *deliver_at) {
+                Some(deliver_at) if deliver_at <= Instant::now() => {
+                    Ok(messages.pop_front().unwrap().1)
+                }
+                deliver_at => Err(deliver_at),
+            }
+        };
+        match next {
+            Ok(message) => {
+                let event_handler = hub.transport(&receiver_id).and_then(|receiver| {
+                    receiver.connected_event_handler(flow_id.client_type, &flow_id.peer_id)
+                });
+                if let Some(event_handler) = event_handler {
+                    let _ = event_handler.send_message(flow_id, message).await;
+                }
+            }
+            Err(Some(deliver_at)) => sleep_until(deliver_at).await,
+            Err(None) => queue.notify.notified().await,
+        }
+    }
+}
+
+impl Transport for LoopbackTransport {
+    fn register_client(
+        &self,
+        client_type: TransportClientType,
+        async_event_handler: Arc<dyn AsyncTransportEventHandler>,
+    ) -> Result<(), TransportErrorCode> {
+        let mut clients = self.clients.write().unwrap();
+        if clients.contains_key(&client_type) {
+            return Err(TransportErrorCode::TransportClientAlreadyRegistered);
+        }
+        clients.insert(client_type, async_event_handler);
+        Ok(())
+    }
+
+    fn start_connections(
+        &self,
+        client_type: TransportClientType,
+        peer_id: &NodeId,
+        _node_record: &NodeRecord,
+        _registry_version: RegistryVersion,
+    ) -> Result<(), TransportErrorCode> {
+        if !self.clients.read().unwrap().contains_key(&client_type) {
+            return Err(TransportErrorCode::TransportClientNotFound);
+        }
+        let _guard = self.hub.connection_lock.lock().unwrap();
+        {
+            let mut peers = self.peers.lock().unwrap();
+            if peers.contains_key(&(client_type, *peer_id)) {
+                return Err(TransportErrorCode::PeerAlreadyRegistered);
+            }
+            let flow_tags = self.hub.config.read().unwrap().flow_tags.clone();
+            let flows = flow_tags
+                .into_iter()
+                .map(|flow_tag| {
+                    let queue = Arc::new(FlowQueue::default());
+                    let flow_id = FlowId {
+                        client_type,
+                        peer_id: self.node_id,
+                        flow_tag,
+                    };
+                    self.rt_handle.spawn(flow_delivery_task(
+                        self.hub.clone(),
+                        flow_id,
+                        *peer_id,
+                        queue.clone(),
+                    ));
+                    (flow_tag, queue)
+                })
+                .collect();
+            peers.insert(
+                (client_type, *peer_id),
+                PeerState {
+                    connected: false,
+                    flows,
+                },
+            );
+        }
+        self.update_connection(client_type, peer_id);
+        if let Some(peer) = self.hub.transport(peer_id) {
+            peer.update_connection(client_type, &self.node_id);
+        }
+        Ok(())
+    }
+
+    fn stop_connections(
+        &self,
+        client_type: TransportClientType,
+        peer_id: &NodeId,
+        _registry_version: RegistryVersion,
+    ) -> Result<(), TransportErrorCode> {
+        let _guard = self.hub.connection_lock.lock().unwrap();
+        let peer = self
+            .peers
+            .lock()
+            .unwrap()
+            .remove(&(client_type, *peer_id))
+            .ok_or(TransportErrorCode::PeerNotFound)?;
+        for (_, queue) in &peer.flows {
+            queue.close();
+        }
+        if peer.connected {
+            let flow_tags: Vec<_> = peer.flows.iter().map(|(flow_tag, _)| *flow_tag).collect();
+            self.report_connection_change(client_type, *peer_id, &flow_tags, false);
+        }
+        if let Some(peer) = self.hub.transport(peer_id) {
+            peer.update_connection(client_type, &self.node_id);
+        }
+        Ok(())
+    }
+
+    fn send(
+        &self,
+        client_type: TransportClientType,
+        peer_id: &NodeId,
+        flow_tag: FlowTag,
+        message: TransportPayload,
+    ) -> Result<(), TransportErrorCode> {
+        let queue = {
+            let peers = self.peers.lock().unwrap();
+            let peer = peers
+                .get(&(client_type, *peer_id))
+                .ok_or(TransportErrorCode::PeerNotFound)?;
+            let queue = peer.flow(flow_tag)?;
+            if !peer.connected {
+                return Ok(());
+            }
+            queue.clone()
+        };
+        let config = self.hub.config.read().unwrap();
+        let mut rng = self.hub.rng.lock().unwrap();
+        if config.drop_rate > 0.0 && rng.gen_bool(config.drop_rate) {
+            return Ok(());
+        }
+        let delay = config.latency + config.jitter.mul_f64(rng.gen::<f64>());
+        queue
+            .messages
+            .lock()
+            .unwrap()
+            .push_back((Instant::now() + delay, message));
+        queue.notify.notify_one();
+        Ok(())
+    }
+
+    fn queue_len(
+        &self,
+        client_type: TransportClientType,
+        peer_id: &NodeId,
+        flow_tag: FlowTag,
+    ) -> Result<usize, TransportErrorCode> {
+        let peers = self.peers.lock().unwrap();
+        let peer = peers
+            .get(&(client_type, *peer_id))
+            .ok_or(TransportErrorCode::PeerNotFound)?;
+        let len = peer.flow(flow_tag)?.messages.lock().unwrap().len();
+        Ok(len)
+    }
+
+    /// Messages are not encoded by the loopback transport, so the clients
+    /// always use protobuf.
+    fn wire_codec(
+        &self,
+        client_type: TransportClientType,
+        peer_id: &NodeId,
+    ) -> Result<WireCodecId, TransportErrorCode> {
+        match self.peers.lock().unwrap().get(&(client_type, *peer_id)) {
+            Some(peer) if peer.connected => Ok(WireCodecId::Protobuf),
+            Some(_) => Err(TransportErrorCode::FlowConnectionDown),
+            None => Err(TransportErrorCode::PeerNotFound),
+        }
+    }
+
+    fn clear_send_queues(&self, client_type: TransportClientType, peer_id: &NodeId) {
+        if let Some(peer) = self.peers.lock().unwrap().get(&(client_type, *peer_id)) {
+            for (_, queue) in &peer.flows {
+                queue.messages.lock().unwrap().clear();
+            }
+        }
+    }
+
+    fn clear_send_queue(
+        &self,
+        client_type: TransportClientType,
+        peer_id: &NodeId,
+        flow_tag: FlowTag,
+    ) {
+        if let Some(peer) = self.peers.lock().unwrap().get(&(client_type, *peer_id)) {
+            if let Ok(queue) = peer.flow(flow_tag) {
+                queue.messages.lock().unwrap().clear();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use ic_interfaces::transport::SendError;
+    use ic_test_utilities::types::ids::node_test_id;
+    use ic_types::transport::TransportClientType::P2P;
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+    use tokio::time::timeout;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Forwards the received messages and state changes to the test.
+    struct TestEventHandler {
+        messages: UnboundedSender<(FlowId, TransportPayload)>,
+        state_changes: UnboundedSender<TransportStateChange>,
+    }
+
+    #[async_trait]
+    impl AsyncTransportEventHandler for TestEventHandler {
+        async fn send_message(
+            &self,
+            flow: FlowId,
+            message: TransportPayload,
+        ) -> Result<(), SendError> {
+            let _ = self.messages.send((flow, message));
+            Ok(())
+        }
+
+        async fn state_changed(&self, state_change: TransportStateChange) {
+            let _ = self.state_changes.send(state_change);
+        }
+
+        async fn error(&self, _flow: FlowId, _error: TransportErrorCode) {}
+    }
+
+    struct TestNode {
+        transport: Arc<LoopbackTransport>,
+        messages: UnboundedReceiver<(FlowId, TransportPayload)>,
+        state_changes: UnboundedReceiver<TransportStateChange>,
+    }
+
+    fn test_node(hub: &Arc<LoopbackHub>, id: u64) -> TestNode {
+        let transport = hub.create_transport(node_test_id(id), Handle::current());
+        let (messages_sender, messages) = unbounded_channel();
+        let (state_changes_sender, state_changes) = unbounded_channel();
+        transport
+            .register_client(
+                P2P,
+                Arc::new(TestEventHandler {
+                    messages: messages_sender,
+                    state_changes: state_changes_sender,
+                }),
+            )
+            .unwrap();
+        TestNode {
+            transport,
+            messages,
+            state_changes,
+        }
+    }
+
+    fn connect(node: &TestNode, peer_id: u64) {
+        node.transport
+            .start_connections(
+                P2P,
+                &node_test_id(peer_id),
+                &NodeRecord::default(),
+                RegistryVersion::from(1),
+            )
+            .unwrap();
+    }
+
+    async fn expect_connection_change(node: &mut TestNode, peer_id: u64, connected: bool) {
+        let flow_info = TransportFlowInfo {
+            peer_id: node_test_id(peer_id),
+            flow_tag: FlowTag::from(0),
+        };
+        let expected = if connected {
+            vec![
+                TransportStateChange::PeerFlowUp(flow_info),
+                TransportStateChange::PeerConnectionUp(node_test_id(peer_id)),
+            ]
+        } else {
+            vec![
+                TransportStateChange::PeerFlowDown(flow_info),
+                TransportStateChange::PeerConnectionDown(node_test_id(peer_id)),
+            ]
+        };
+        for state_change in expected {
+            let received = timeout(TIMEOUT, node.state_changes.recv()).await.unwrap();
+            assert_eq!(received, Some(state_change));
+        }
+    }
+
+    fn send(node: &TestNode, peer_id: u64, payload: u8) {
+        node.transport
+            .send(
+                P2P,
+                &node_test_id(peer_id),
+                FlowTag::from(0),
+                TransportPayload::from(vec![payload]),
+            )
+            .unwrap();
+    }
+
+    /// Tests that the connection comes up once both nodes started
+    /// connections, and that messages are delivered in order.
+    #[tokio::test]
+    async fn loopback_delivers_messages_in_order() {
+        let hub = LoopbackHub::new(LoopbackConfig {
+            latency: Duration::from_millis(5),
+            jitter: Duration::from_millis(20),
+            ..Default::default()
+        });
+        let mut node_1 = test_node(&hub, 1);
+        let mut node_2 = test_node(&hub, 2);
+        connect(&node_1, 2);
+        assert_eq!(
+            node_1.transport.wire_codec(P2P, &node_test_id(2)),
+            Err(TransportErrorCode::FlowConnectionDown)
+        );
+        connect(&node_2, 1);
+        expect_connection_change(&mut node_1, 2, true).await;
+        expect_connection_change(&mut node_2, 1, true).await;
+
+        for payload in 0..10 {
+            send(&node_1, 2, payload);
+        }
+        for payload in 0..10 {
+            let (flow_id, message) = timeout(TIMEOUT, node_2.messages.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(flow_id.peer_id, node_test_id(1));
+            assert_eq!(message, TransportPayload::from(vec![payload]));
+        }
+    }
+
+    /// Tests that partitioned nodes are disconnected until the partition is
+    /// healed.
+    #[tokio::test]
+    async fn loopback_partition_and_heal() {
+        let hub = LoopbackHub::new(LoopbackConfig::default());
+        let mut node_1 = test_node(&hub, 1);
+        let mut node_2 = test_node(&hub, 2);
+        connect(&node_1, 2);
+        connect(&node_2, 1);
+        expect_connection_change(&mut node_1, 2, true).await;
+        expect_connection_change(&mut node_2, 1, true).await;
+
+        hub.partition(&[vec![node_test_id(1)]]);
+        expect_connection_change(&mut node_1, 2, false).await;
+        expect_connection_change(&mut node_2, 1, false).await;
+        send(&node_1, 2, 1);
+
+        hub.heal();
+        expect_connection_change(&mut node_1, 2, true).await;
+        expect_connection_change(&mut node_2, 1, true).await;
+        send(&node_1, 2, 2);
+        let (_, message) = timeout(TIMEOUT, node_2.messages.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(message, TransportPayload::from(vec![2]));
+    }
+
+    /// Tests that a detached node is disconnected from its peers, and that it
+    /// can reconnect with a new transport.
+    #[tokio::test]
+    async fn loopback_detach_and_reattach() {
+        let hub = LoopbackHub::new(LoopbackConfig::default());
+        let mut node_1 = test_node(&hub, 1);
+        let mut node_2 = test_node(&hub, 2);
+        connect(&node_1, 2);
+        connect(&node_2, 1);
+        expect_connection_change(&mut node_1, 2, true).await;
+        expect_connection_change(&mut node_2, 1, true).await;
+
+        hub.detach(&node_test_id(2));
+        expect_connection_change(&mut node_1, 2, false).await;
+        expect_connection_change(&mut node_2, 1, false).await;
+        drop(node_2);
+
+        let mut node_2 = test_node(&hub, 2);
+        connect(&node_2, 1);
+        expect_connection_change(&mut node_1, 2, true).await;
+        expect_connection_change(&mut node_2, 1, true).await;
+        send(&node_1, 2, 1);
+        let (_, message) = timeout(TIMEOUT, node_2.messages.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(message, TransportPayload::from(vec![1]));
+    }
+
+    /// Tests that all messages are dropped with a drop rate of 1.
+    #[tokio::test]
+    async fn loopback_drops_messages() {
+        let hub = LoopbackHub::new(LoopbackConfig::default());
+        let mut node_1 = test_node(&hub, 1);
+        let mut node_2 = test_node(&hub, 2);
+        connect(&node_1, 2);
+        connect(&node_2, 1);
+        expect_connection_change(&mut node_1, 2, true).await;
+
+        hub.set_config(LoopbackConfig {
+            drop_rate: 1.0,
+            ..Default::default()
+        });
+        send(&node_1, 2, 1);
+        hub.set_config(LoopbackConfig::default());
+        send(&node_1, 2, 2);
+        let (_, message) = timeout(TIMEOUT, node_2.messages.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(message, TransportPayload::from(vec![2]));
+    }
+}
@@ -17,6 +17,7 @@ pub(crate) struct ControlPlaneMetrics {
     pub(crate) tcp_client_handshake_failed: IntCounterVec,
     pub(crate) tcp_client_handshake_success: IntCounterVec,
     pub(crate) retry_connection: IntCounterVec,
+    pub(crate) observed_addr_mismatches: IntCounterVec,
 }
 
 impl ControlPlaneMetrics {
@@ -82,6 +83,11 @@ impl ControlPlaneMetrics {
                 "Connection retries to reconnect to a peer from Transport",
                 &["peer_id", "flow_tag"],
             ),
+            observed_addr_mismatches: metrics_registry.int_counter_vec(
+                "transport_observed_addr_mismatches",
+                "Addresses of this node observed by a peer that differ from the announced one",
+                &["peer_id"],
+            ),
         }
     }
 }
@@ -99,9 +105,12 @@ pub(crate) struct DataPlaneMetrics {
     pub(crate) heart_beats_sent: IntCounterVec,
     pub(crate) heart_beats_received: IntCounterVec,
     pub(crate) send_errors_received: IntCounterVec,
+    pub(crate) unknown_flow_messages: IntCounterVec,
     pub(crate) write_tasks: IntGauge,
     pub(crate) read_tasks: IntGauge,
     pub(crate) write_task_overhead_time_msec: HistogramVec,
+    pub(crate) shaped_bytes: IntCounterVec,
+    pub(crate) shaped_dropped_bytes: IntCounterVec,
 }
 
 impl DataPlaneMetrics {
@@ -155,6 +164,11 @@ impl DataPlaneMetrics {
                 "Number of peer send error notifications",
                 &["flow_peer_id", "flow_tag"],
             ),
+            unknown_flow_messages: metrics_registry.int_counter_vec(
+                "transport_unknown_flow_messages",
+                "Number of received messages dropped due to an unknown flow tag",
+                &["flow_peer_id", "flow_tag"],
+            ),
             heart_beats_received: metrics_registry.int_counter_vec(
                 "transport_heart_beats_received",
                 "Number of heart beats as seen by receiver",
@@ -176,6 +190,16 @@ impl DataPlaneMetrics {
                 decimal_buckets(0, 5),
                 &["flow_peer_id", "flow_tag"],
             ),
+            shaped_bytes: metrics_registry.int_counter_vec(
+                "transport_shaped_bytes",
+                "Bytes whose sending was delayed by the flow rate limit",
+                &["flow_peer_id", "flow_tag"],
+            ),
+            shaped_dropped_bytes: metrics_registry.int_counter_vec(
+                "transport_shaped_dropped_bytes",
+                "Bytes rejected because the send queue of a rate limited flow was full",
+                &["flow_peer_id", "flow_tag"],
+            ),
         }
     }
 }
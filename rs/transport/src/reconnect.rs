@@ -0,0 +1,125 @@
+//! Reconnection policy for transport connections.
+//!
+//! If a connection attempt fails, the control plane waits before retrying.
+//! The wait interval starts at the configured initial retry interval and
+//! grows by the backoff multiplier after each failed retry, up to the maximum
+//! retry interval. Each interval is randomized by the configured jitter, so
+//! that the nodes of a subnet do not retry in lock step, e.g., after a subnet
+//! wide restart.
+//!
+//! The config is validated when it is loaded, but the policy does not rely on
+//! that: zero intervals are raised to [MIN_RETRY_INTERVAL_MS] and a zero
+//! multiplier is treated as 1, so that the control plane never retries
+//! without waiting.
+
+use ic_types::transport::TransportReconnectConfig;
+use rand::Rng;
+use std::time::Duration;
+
+/// The lower bound of the retry intervals
+pub(crate) const MIN_RETRY_INTERVAL_MS: u64 = 10;
+
+/// The reconnection policy
+#[derive(Clone, Debug)]
+pub(crate) struct ReconnectPolicy {
+    config: TransportReconnectConfig,
+}
+
+impl ReconnectPolicy {
+    pub(crate) fn new(mut config: TransportReconnectConfig) -> Self {
+        config.initial_retry_interval_ms =
+            config.initial_retry_interval_ms.max(MIN_RETRY_INTERVAL_MS);
+        config.max_retry_interval_ms = config
+            .max_retry_interval_ms
+            .max(config.initial_retry_interval_ms);
+        config.backoff_multiplier = config.backoff_multiplier.max(1);
+        Self { config }
+    }
+
+    /// Returns the interval to wait before the next connection attempt,
+    /// given the number of failed attempts so far
+    pub(crate) fn retry_interval(&self, retries: u32) -> Duration {
+        let backoff_ms = self.backoff_interval_ms(retries);
+        let jitter_ms = backoff_ms * u64::from(self.config.jitter_percent.min(100)) / 100;
+        let retry_ms = if jitter_ms > 0 {
+            rand::thread_rng().gen_range(backoff_ms - jitter_ms, backoff_ms + jitter_ms + 1)
+        } else {
+            backoff_ms
+        };
+        Duration::from_millis(retry_ms)
+    }
+
+    /// Returns the retry interval without jitter
+    fn backoff_interval_ms(&self, retries: u32) -> u64 {
+        let max_ms = self.config.max_retry_interval_ms;
+        let mut backoff_ms = self.config.initial_retry_interval_ms;
+        for _ in 1..retries {
+            if backoff_ms >= max_ms {
+                break;
+            }
+            backoff_ms = backoff_ms.saturating_mul(u64::from(self.config.backoff_multiplier));
+        }
+        backoff_ms.min(max_ms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_config(jitter_percent: u32) -> TransportReconnectConfig {
+        TransportReconnectConfig {
+            initial_retry_interval_ms: 100,
+            max_retry_interval_ms: 1000,
+            backoff_multiplier: 2,
+            jitter_percent,
+        }
+    }
+
+    #[test]
+    fn test_backoff_grows_up_to_max() {
+        let policy = ReconnectPolicy::new(test_config(0));
+        let intervals = (1..=6)
+            .map(|retries| policy.retry_interval(retries).as_millis())
+            .collect::<Vec<_>>();
+        assert_eq!(intervals, vec![100, 200, 400, 800, 1000, 1000]);
+        assert_eq!(policy.retry_interval(u32::MAX).as_millis(), 1000);
+    }
+
+    #[test]
+    fn test_zero_intervals_are_clamped() {
+        let policy = ReconnectPolicy::new(TransportReconnectConfig {
+            initial_retry_interval_ms: 0,
+            max_retry_interval_ms: 0,
+            backoff_multiplier: 0,
+            jitter_percent: 0,
+        });
+        for retries in 0..5 {
+            assert_eq!(
+                policy.retry_interval(retries).as_millis(),
+                u128::from(MIN_RETRY_INTERVAL_MS)
+            );
+        }
+    }
+
+    #[test]
+    fn test_max_below_initial_is_raised_to_initial() {
+        let policy = ReconnectPolicy::new(TransportReconnectConfig {
+            initial_retry_interval_ms: 100,
+            max_retry_interval_ms: 0,
+            backoff_multiplier: 2,
+            jitter_percent: 0,
+        });
+        assert_eq!(policy.retry_interval(1).as_millis(), 100);
+        assert_eq!(policy.retry_interval(5).as_millis(), 100);
+    }
+
+    #[test]
+    fn test_jitter_is_bounded() {
+        let policy = ReconnectPolicy::new(test_config(20));
+        for _ in 0..100 {
+            let interval = policy.retry_interval(2).as_millis();
+            assert!((160..=240).contains(&interval));
+        }
+    }
+}
@@ -360,7 +360,7 @@ impl TestClient {
             v.push(rng.gen::<u8>());
         }
 
-        TransportPayload(v)
+        TransportPayload::from(v)
     }
 
     // Compares the two messages(hdr and payload parts)
@@ -419,6 +419,10 @@ impl TestClientEventHandler {
             TransportStateChange::PeerFlowDown(flow) => {
                 self.active_flows.lock().unwrap().remove(&flow);
             }
+            TransportStateChange::SendQueueHighWatermark(_)
+            | TransportStateChange::SendQueueLowWatermark(_)
+            | TransportStateChange::PeerConnectionUp(_)
+            | TransportStateChange::PeerConnectionDown(_) => (),
         }
     }
 }
@@ -483,16 +487,24 @@ fn generate_config_and_registry(node_id: &NodeId) -> ConfigAndRecords {
         if *node_id == n.0 {
             config = Some(TransportConfig {
                 node_ip: n.1.clone(),
+                secondary_node_ip: None,
+                external_ip: None,
+                nat_traversal: false,
+                reconnect: Default::default(),
+                wire_codecs: Vec::new(),
+                tls_validation: Default::default(),
                 p2p_flows: vec![
                     TransportFlowConfig {
                         flow_tag: FLOW_TAG_1,
                         server_port: n.2,
                         queue_size: 1024,
+                        rate_limit: None,
                     },
                     TransportFlowConfig {
                         flow_tag: FLOW_TAG_2,
                         server_port: n.3,
                         queue_size: 1024,
+                        rate_limit: None,
                     },
                 ],
             });
@@ -621,7 +633,8 @@ async fn task_main(
         crypto,
         tokio::runtime::Handle::current(),
         log.clone(),
-    );
+    )
+    .expect("Failed to create the transport");
 
     println!("starting test client... [Node: {}]", node_id_val);
     let test_client = TestClient::new(
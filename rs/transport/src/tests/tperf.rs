@@ -250,7 +250,7 @@ impl TestClient {
     // Sends the given message to the peer, with retries on qfull. Returns the
     // number of qfulls.
     fn send_message(&self, message: TestMessage) -> usize {
-        let mut payload = TransportPayload(serialize(&message).unwrap());
+        let mut payload = TransportPayload::from(serialize(&message).unwrap());
         let mut qfull = 0;
         loop {
             match self.transport.send(
@@ -542,6 +542,10 @@ impl TestClientEventHandler {
             TransportStateChange::PeerFlowDown(_) => {
                 exit(1);
             }
+            TransportStateChange::SendQueueHighWatermark(_)
+            | TransportStateChange::SendQueueLowWatermark(_)
+            | TransportStateChange::PeerConnectionUp(_)
+            | TransportStateChange::PeerConnectionDown(_) => (),
         }
     }
 }
@@ -635,10 +639,17 @@ fn generate_config_and_registry(
 ) -> ConfigAndRecords {
     let config = TransportConfig {
         node_ip: node_ip.to_string(),
+        secondary_node_ip: None,
+        external_ip: None,
+        nat_traversal: false,
+        reconnect: Default::default(),
+        wire_codecs: Vec::new(),
+        tls_validation: Default::default(),
         p2p_flows: vec![TransportFlowConfig {
             flow_tag: FLOW_TAG,
             server_port: FLOW_PORT as u16,
             queue_size: 8192,
+            rate_limit: None,
         }],
     };
 
@@ -742,7 +753,8 @@ async fn main() {
         crypto,
         tokio::runtime::Handle::current(),
         log.clone(),
-    );
+    )
+    .expect("Failed to create the transport");
 
     let test_client = TestClient::new(
         transport,
@@ -12,34 +12,43 @@
 //! The diagram below shows the flow/connect set up sequence:
 //!
 //! Transport clients invoke add_peer(peer_id) to set up the
-//! connections with a valid peer. Control plane then looks up the
-//! client config and sets up the peer state. If we are the TCP/TLS
-//! client, the control plane initiates the connections for all the
-//! flows with the peer. If we are the server, we wait for peers to
-//! initiate the flow connections. When a connection is established,
-//! the ownership is passed from control plane to the data plane via
-//! on_connnect(flow_id, socket_read_half, socket_write_half)
-//! callback. The read/write halves are passed to the receive/send
-//! tasks respectively. At this point, data plane can start performing
-//! IOs on the peer connection.  If the data plane tasks detect a
-//! connection error, IOs are paused on the connection, and control
-//! plane is notified via on_disconnect(flow_id) callback. Control
-//! plane then initiates re-connection with the peer.  Successfully
-//! re-connections are handled according via the on_connect() described
-//! earlier.
+//! connection with a valid peer. All flows with a peer share a single
+//! TLS connection, which uses the endpoint of the first flow in the
+//! transport config. Control plane then looks up the client config
+//! and sets up the peer state. If we are the TCP/TLS client, the
+//! control plane initiates the connection with the peer. If we are
+//! the server, we wait for the peer to initiate the connection. With
+//! NAT traversal enabled, the server connects to the peer as well,
+//! both sides connecting from their server port, so that the TCP
+//! simultaneous open passes through NATs; the TLS roles stay the
+//! same. When
+//! the connection is established, the ownership is passed from
+//! control plane to the data plane via on_connnect(peer_id,
+//! socket_read_half, socket_write_half) callback. The read/write
+//! halves are passed to the receive/write tasks respectively. At this
+//! point, data plane can start performing IOs on the peer connection.
+//! If the data plane tasks detect a connection error, IOs are paused
+//! on the connection, and control plane is notified via
+//! on_disconnect(peer_id) callback. Control plane then initiates
+//! re-connection with the peer. Successfully re-connections are
+//! handled according via the on_connect() described earlier. The
+//! client is notified about connection changes for each flow.
 //!
-//! The send data path has two hops:
+//! The send data path has three hops:
 //!
 //! Transport client calls send(flow_id, message). The message is
-//! en-queued to the per-flow send queue. The send data plane task then
-//! dequeues the messages and sends it over the socket.
+//! en-queued to the per-flow send queue. The per-flow send task then
+//! dequeues the messages and frames them with a header that carries
+//! the flow tag. The write task of the connection multiplexes the
+//! framed messages of all flows, in the order of the flow priorities,
+//! and sends them over the socket.
 //!
 //! The receive data path has one hop:
 //!
 //! The receive data plane task scans the read half of the
-//! connections. Once a complete message is read from the socket, the
+//! connection. Once a complete message is read from the socket, the
 //! client's on_message(flow_id, message) callback is invoked for
-//! messages delivery.
+//! messages delivery, with the flow indicated in the header.
 //!
 //! ```text
 //! +-----------------------------------------------------+
@@ -53,9 +62,12 @@
 //! | Control  | on_connect()    |   Send Queues       |         |
 //! | Plane    |---------------->|         |           |         |
 //! |          |                 |  +------v----+  +----------+  |
-//! |          | on_disconnect() |  | Send Task |  | Receive  |  |
+//! |          | on_disconnect() |  | Send Tasks|  | Receive  |  |
 //! |          |<----------------|  +-----------+  | Task     |  |
 //! +----------+                 |         |       +----------+  |
+//!                              |  +------v----+      |         |
+//!                              |  | Write Task|      |         |
+//!                              |  +-----------+      |         |
 //!                              |         |           |         |
 //!                              |         v           |         |
 //!                              |  +------------+ +------------+|
@@ -67,14 +79,15 @@
 //! ```
 
 use crate::metrics::{ControlPlaneMetrics, DataPlaneMetrics, SendQueueMetrics};
-use crate::types::TransportImpl;
-use ic_crypto_tls_interfaces::TlsHandshake;
+use crate::types::{ConnectionState, TransportImpl};
+use ic_crypto_tls_interfaces::{TlsHandshake, TlsValidation};
 use ic_interfaces::transport::{AsyncTransportEventHandler, Transport};
 use ic_logger::ReplicaLogger;
 use ic_metrics::MetricsRegistry;
 use ic_protobuf::registry::node::v1::NodeRecord;
 use ic_types::transport::{
-    FlowTag, TransportClientType, TransportConfig, TransportErrorCode, TransportPayload,
+    FlowTag, TlsValidationConfig, TransportClientType, TransportConfig, TransportErrorCode,
+    TransportPayload, WireCodecId,
 };
 use ic_types::{NodeId, RegistryVersion};
 
@@ -94,15 +107,29 @@ impl TransportImpl {
         crypto: Arc<dyn TlsHandshake + Send + Sync>,
         tokio_runtime: Handle,
         log: ReplicaLogger,
-    ) -> Arc<Self> {
+    ) -> Result<Arc<Self>, String> {
         let node_ip = IpAddr::from_str(&config.node_ip)
             .unwrap_or_else(|_| panic!("Invalid node IP: {}", &config.node_ip));
+        let secondary_node_ip = config.secondary_node_ip.as_ref().map(|ip| {
+            let secondary_node_ip = IpAddr::from_str(ip)
+                .unwrap_or_else(|_| panic!("Invalid secondary node IP: {}", ip));
+            assert_ne!(
+                secondary_node_ip.is_ipv6(),
+                node_ip.is_ipv6(),
+                "Node IP and secondary node IP must be of different address families"
+            );
+            secondary_node_ip
+        });
+        let tls_validation = load_tls_validation(&config.tls_validation)
+            .map_err(|e| format!("Invalid TLS validation config: {}", e))?;
         let arc = Arc::new(Self {
             node_id,
             node_ip,
+            secondary_node_ip,
             config,
             allowed_clients: Arc::new(RwLock::new(BTreeSet::<NodeId>::new())),
             crypto,
+            tls_validation,
             registry_version: Arc::new(RwLock::new(registry_version)),
             tokio_runtime,
             data_plane_metrics: DataPlaneMetrics::new(metrics_registry.clone()),
@@ -113,12 +140,34 @@ impl TransportImpl {
             weak_self: RwLock::new(Weak::new()),
         });
         *arc.weak_self.write().unwrap() = Arc::downgrade(&arc);
-        arc
+        Ok(arc)
+    }
+}
+
+/// Reads the certificates of the configured TLS validation mode.
+fn load_tls_validation(config: &TlsValidationConfig) -> Result<TlsValidation, String> {
+    let read = |path: &std::path::Path| {
+        std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))
+    };
+    match config {
+        TlsValidationConfig::RegistryPinned => Ok(TlsValidation::RegistryPinned),
+        TlsValidationConfig::CaIssued {
+            ca_cert_path,
+            cert_path,
+            ..
+        } => TlsValidation::ca_issued_from_pem(&read(ca_cert_path)?, &read(cert_path)?)
+            .map_err(|e| e.internal_error),
+        TlsValidationConfig::AllowList { path, .. } => {
+            TlsValidation::allow_list_from_pem(&read(path)?).map_err(|e| e.internal_error)
+        }
     }
 }
 
 /// Creates a new instance of
 /// [`TransportImpl`](../types/struct.TransportImpl.html).
+///
+/// Returns an error if the certificates of the configured TLS validation mode
+/// cannot be read.
 pub fn create_transport(
     node_id: NodeId,
     transport_config: TransportConfig,
@@ -127,8 +176,8 @@ pub fn create_transport(
     crypto: Arc<dyn TlsHandshake + Send + Sync>,
     tokio_runtime: Handle,
     log: ReplicaLogger,
-) -> Arc<dyn Transport> {
-    TransportImpl::new(
+) -> Result<Arc<dyn Transport>, String> {
+    let transport = TransportImpl::new(
         node_id,
         transport_config,
         registry_version,
@@ -136,7 +185,8 @@ pub fn create_transport(
         crypto,
         tokio_runtime,
         log,
-    )
+    )?;
+    Ok(transport)
 }
 
 /// Trait implementation for
@@ -190,11 +240,59 @@ impl Transport for TransportImpl {
             None => return Err(TransportErrorCode::FlowNotFound),
         };
         match flow_state.send_queue.enqueue(message) {
-            Some(unsent) => Err(TransportErrorCode::TransportBusy(unsent)),
+            Some(unsent) => {
+                if flow_state.rate_limiter.is_some() {
+                    self.data_plane_metrics
+                        .shaped_dropped_bytes
+                        .with_label_values(&[&flow_state.flow_label, &flow_state.flow_tag_label])
+                        .inc_by(unsent.0.len() as u64);
+                }
+                Err(TransportErrorCode::TransportBusy(unsent))
+            }
             None => Ok(()),
         }
     }
 
+    fn queue_len(
+        &self,
+        client_type: TransportClientType,
+        peer_id: &NodeId,
+        flow_tag: FlowTag,
+    ) -> Result<usize, TransportErrorCode> {
+        let client_map = self.client_map.read().unwrap();
+        let client_state = client_map
+            .get(&client_type)
+            .ok_or(TransportErrorCode::TransportClientNotFound)?;
+        let peer_state = client_state
+            .peer_map
+            .get(peer_id)
+            .ok_or(TransportErrorCode::PeerNotFound)?;
+        let flow_state = peer_state
+            .flow_map
+            .get(&flow_tag)
+            .ok_or(TransportErrorCode::FlowNotFound)?;
+        Ok(flow_state.send_queue.queue_len())
+    }
+
+    fn wire_codec(
+        &self,
+        client_type: TransportClientType,
+        peer_id: &NodeId,
+    ) -> Result<WireCodecId, TransportErrorCode> {
+        let client_map = self.client_map.read().unwrap();
+        let client_state = client_map
+            .get(&client_type)
+            .ok_or(TransportErrorCode::TransportClientNotFound)?;
+        let peer_state = client_state
+            .peer_map
+            .get(peer_id)
+            .ok_or(TransportErrorCode::PeerNotFound)?;
+        match &peer_state.connection_state {
+            ConnectionState::Connected(connected) => Ok(connected.wire_codec),
+            _ => Err(TransportErrorCode::FlowConnectionDown),
+        }
+    }
+
     fn clear_send_queues(&self, client_type: TransportClientType, peer_id: &NodeId) {
         let client_map = self.client_map.read().unwrap();
         let client_state = client_map
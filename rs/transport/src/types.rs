@@ -1,13 +1,15 @@
 //! Shared types internal to transport crate
 
 use crate::metrics::{ControlPlaneMetrics, DataPlaneMetrics, SendQueueMetrics};
-use ic_crypto_tls_interfaces::TlsHandshake;
+use ic_crypto_tls_interfaces::{TlsHandshake, TlsValidation};
 use ic_interfaces::transport::AsyncTransportEventHandler;
 use ic_logger::ReplicaLogger;
 use ic_types::transport::{
-    FlowId, FlowTag, TransportClientType, TransportConfig, TransportPayload,
+    FlowId, FlowTag, TransportClientType, TransportConfig, TransportPayload, TransportStateChange,
+    WireCodecId,
 };
 use ic_types::{NodeId, RegistryVersion};
+use ic_utils::token_bucket::TokenBucket;
 use phantom_newtype::{AmountOf, Id};
 
 use async_trait::async_trait;
@@ -17,8 +19,9 @@ use std::collections::{BTreeSet, HashMap};
 use std::fmt::{self, Debug, Formatter};
 use std::net::IpAddr;
 use std::net::SocketAddr;
-use std::sync::{Arc, RwLock, Weak};
+use std::sync::{Arc, Mutex, RwLock, Weak};
 use tokio::runtime::Handle;
+use tokio::sync::mpsc::UnboundedSender;
 use tokio::time::Duration;
 
 /// A tag for the server port
@@ -34,7 +37,10 @@ pub struct QueueSizeTag;
 pub type QueueSize = AmountOf<QueueSizeTag, usize>;
 
 /// The size (in bytes) of the transport header
-pub const TRANSPORT_HEADER_SIZE: usize = 8;
+pub const TRANSPORT_HEADER_SIZE: usize = 12;
+
+/// The version of the transport header
+pub const TRANSPORT_HEADER_VERSION: u8 = 1;
 
 /// Flag: sender-indicated error
 ///
@@ -48,6 +54,26 @@ pub const TRANSPORT_FLAGS_SENDER_ERROR: u8 = 1;
 /// payload, and that it was sent by the heartbeats mechanism to keep the
 /// connection alive.
 pub const TRANSPORT_FLAGS_IS_HEARTBEAT: u8 = 2;
+/// Flag: message is a hello
+///
+/// Hello messages are exchanged once, right after the TLS handshake of a
+/// connection. The payload lists the wire codecs supported by the sender, in
+/// order of preference. Hello messages are sent on
+/// [`TRANSPORT_HELLO_FLOW_TAG`], so that nodes that predate the hello drop
+/// them as messages of an unknown flow. Such nodes do not send a hello
+/// either, and protobuf is used on connections with them.
+pub const TRANSPORT_FLAGS_IS_HELLO: u8 = 4;
+/// Flag: hello message reporting the observed address
+///
+/// With NAT traversal, the codec hello is followed by a second hello message
+/// with this flag on, whose payload is the address under which the sender
+/// observes the receiver, as a string. Nodes that do not know this flag skip
+/// it like any other hello after connection setup.
+pub const TRANSPORT_FLAGS_IS_OBSERVED_ADDR: u8 = 8;
+
+/// The flow tag of hello messages, which is reserved and never configured
+/// for a flow.
+pub const TRANSPORT_HELLO_FLOW_TAG: u32 = u32::MAX;
 
 /// The transport header format.
 ///
@@ -56,6 +82,9 @@ pub const TRANSPORT_FLAGS_IS_HEARTBEAT: u8 = 2;
 ///   1. The TransportHeader
 ///   2. The payload (client message, which is an opaque byte array)
 ///
+/// All flows with a peer share a single connection. The flow tag in the
+/// header identifies the flow a message belongs to.
+///
 /// Note:
 ///
 /// For message framing the transport header must serialize to the
@@ -66,13 +95,15 @@ pub const TRANSPORT_FLAGS_IS_HEARTBEAT: u8 = 2;
 /// To maintain the size invariant the header is manually serialized.
 /// This struct is ephemeral hence the lack of derivations or tagging.
 pub(crate) struct TransportHeader {
-    /// The version of the Transport being used (currently 0)
-    pub(crate) version: u8, // Currently 0
+    /// The version of the Transport being used (currently 1)
+    pub(crate) version: u8, // Currently 1
     /// Transport flags: defined by the constants named `TRANSPORT_FLAGS_*` in
     /// this module
     pub(crate) flags: u8,
     /// Reserved space (currently 0)
     pub(crate) reserved: u16, // Currently 0, serialized little endian.
+    /// The tag of the flow the payload belongs to
+    pub(crate) flow_tag: u32, // Serialized little endian.
     /// The length of the byte payload that follows next
     pub(crate) payload_length: u32, // Serialized little endian.
 }
@@ -84,6 +115,9 @@ pub(crate) struct TransportImpl {
     pub node_id: NodeId,
     /// The IP address of this node
     pub node_ip: IpAddr,
+    /// The IP address of this node of the other address family, if the node
+    /// is dual-stack
+    pub secondary_node_ip: Option<IpAddr>,
     /// Configuration
     pub config: TransportConfig,
     /// Map of clients to their corresponding state
@@ -96,6 +130,8 @@ pub(crate) struct TransportImpl {
     pub registry_version: Arc<RwLock<RegistryVersion>>,
     /// Reference to the crypto component
     pub crypto: Arc<dyn TlsHandshake + Send + Sync>,
+    /// How the TLS certificates are validated in the handshakes
+    pub tls_validation: TlsValidation,
 
     /// Data plane metrics
     pub data_plane_metrics: DataPlaneMetrics,
@@ -113,7 +149,7 @@ pub(crate) struct TransportImpl {
 }
 
 /// Our role in a connection
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub(crate) enum ConnectionRole {
     /// We connect to the peer as a client
     Client,
@@ -130,6 +166,10 @@ pub(crate) struct ClientState {
     pub peer_map: HashMap<NodeId, PeerState>,
     /// Event handler to report back to the transport client
     pub event_handler: Arc<dyn AsyncTransportEventHandler>,
+    /// Sender for state changes that are reported from a sync context, e.g.,
+    /// send queue watermarks. The state changes are delivered to the event
+    /// handler in order.
+    pub state_change_sender: UnboundedSender<TransportStateChange>,
 }
 
 /// State about the server ports we are listening on
@@ -137,20 +177,81 @@ pub(crate) struct ServerPortState {
     /// The port number
     pub port: ServerPort,
 
-    /// Handle to the accept task for this port
-    pub accept_task: AbortHandle,
+    /// Handles to the accept tasks for this port, one per node IP
+    pub accept_tasks: Vec<AbortHandle>,
 }
 
 impl Drop for ServerPortState {
     fn drop(&mut self) {
-        self.accept_task.abort();
+        for accept_task in &self.accept_tasks {
+            accept_task.abort();
+        }
     }
 }
 
 /// Per-peer state, specific to a transport client
 pub(crate) struct PeerState {
+    /// Tag of the flow whose endpoint is used for the connection, used as a
+    /// metrics label
+    pub connection_flow_tag: FlowTag,
+    /// Connection label, used for metrics
+    pub connection_label: String,
+    /// `(local, peer)` address pairs to connect to the peer, in order of
+    /// preference. Empty if we are the server, unless NAT traversal is
+    /// enabled. With NAT traversal, the address the peer last connected from
+    /// comes first.
+    pub connect_addrs: Vec<(SocketAddr, SocketAddr)>,
+    /// State of the connection with the peer, shared by all flows
+    pub connection_state: ConnectionState,
     /// State of the flows with the peer
     pub flow_map: HashMap<FlowTag, FlowState>,
+    /// Metrics
+    pub control_plane_metrics: ControlPlaneMetrics,
+}
+
+impl PeerState {
+    pub(crate) fn new(
+        connection_flow_tag: FlowTag,
+        connection_label: String,
+        connect_addrs: Vec<(SocketAddr, SocketAddr)>,
+        connection_state: ConnectionState,
+        flow_map: HashMap<FlowTag, FlowState>,
+        control_plane_metrics: ControlPlaneMetrics,
+    ) -> Self {
+        let ret = Self {
+            connection_flow_tag,
+            connection_label,
+            connect_addrs,
+            connection_state,
+            flow_map,
+            control_plane_metrics,
+        };
+        ret.report_connection_state();
+        ret
+    }
+
+    /// Updates the state of the connection
+    pub(crate) fn update(&mut self, connection_state: ConnectionState) {
+        self.connection_state.update(connection_state);
+        self.report_connection_state();
+    }
+
+    /// Moves the given address pair to the front of the address pairs to
+    /// connect to the peer, adding it if it is not known yet
+    pub(crate) fn prefer_connect_addr(&mut self, connect_addr: (SocketAddr, SocketAddr)) {
+        self.connect_addrs.retain(|addr| *addr != connect_addr);
+        self.connect_addrs.insert(0, connect_addr);
+    }
+
+    /// Reports the state of the connection to metrics, for each flow
+    fn report_connection_state(&self) {
+        for flow_state in self.flow_map.values() {
+            self.control_plane_metrics
+                .flow_state
+                .with_label_values(&[&flow_state.flow_label, &flow_state.flow_tag_label])
+                .set(self.connection_state.idx());
+        }
+    }
 }
 
 /// Per-flow state, specific to a transport-client and a peer.
@@ -161,12 +262,12 @@ pub(crate) struct FlowState {
     pub flow_tag_label: String,
     /// Flow label, used for metrics
     pub flow_label: String,
-    /// Connection state
-    pub connection_state: ConnectionState,
     /// The send queue of this flow
     pub send_queue: Box<dyn SendQueue + Send + Sync>,
-    /// Metrics
-    pub control_plane_metrics: ControlPlaneMetrics,
+    /// The rate limiter of this flow, if the flow is rate limited. Its tokens
+    /// are bytes. It is shared with the send task of the current connection,
+    /// and retained across reconnections.
+    pub rate_limiter: Option<Arc<Mutex<TokenBucket>>>,
 }
 
 impl FlowState {
@@ -174,48 +275,37 @@ impl FlowState {
         flow_id: FlowId,
         flow_tag_label: String,
         flow_label: String,
-        connection_state: ConnectionState,
         send_queue: Box<dyn SendQueue + Send + Sync>,
-        control_plane_metrics: ControlPlaneMetrics,
+        rate_limiter: Option<TokenBucket>,
     ) -> Self {
-        let ret = Self {
+        Self {
             flow_id,
             flow_tag_label,
             flow_label,
-            connection_state,
             send_queue,
-            control_plane_metrics,
-        };
-        ret.report_connection_state();
-        ret
-    }
-
-    /// Updates the state of the connection
-    pub(crate) fn update(&mut self, connection_state: ConnectionState) {
-        self.connection_state.update(connection_state);
-        self.report_connection_state();
-    }
-
-    /// Reports the state of a flow to metrics
-    fn report_connection_state(&self) {
-        self.control_plane_metrics
-            .flow_state
-            .with_label_values(&[&self.flow_label, &self.flow_tag_label])
-            .set(self.connection_state.idx());
+            rate_limiter: rate_limiter.map(|bucket| Arc::new(Mutex::new(bucket))),
+        }
     }
 }
 
-/// The connection state machine for a flow with a peer
+/// The connection state machine for the connection with a peer
 pub(crate) enum ConnectionState {
     /// We are the server, waiting for peer to connect
-    Listening,
+    Listening(Listening),
     /// We are the client, connection in progress
     Connecting(Connecting),
     /// Connection established
     Connected(Connected),
 }
 
-/// Info about a flow in ConnectionState::Connecting
+/// Info about a connection in ConnectionState::Listening
+pub(crate) struct Listening {
+    /// The task connecting to the peer for NAT traversal, if NAT traversal is
+    /// enabled
+    pub connecting_task: Option<AbortHandle>,
+}
+
+/// Info about a connection in ConnectionState::Connecting
 pub(crate) struct Connecting {
     /// Server node we are connecting to
     pub peer_addr: SocketAddr,
@@ -224,7 +314,7 @@ pub(crate) struct Connecting {
     pub connecting_task: AbortHandle,
 }
 
-/// Info about a flow in ConnectionState::Connected
+/// Info about a connection in ConnectionState::Connected
 pub(crate) struct Connected {
     /// Peer node
     pub peer_addr: SocketAddr,
@@ -237,6 +327,9 @@ pub(crate) struct Connected {
 
     /// Our role
     pub role: ConnectionRole,
+
+    /// The wire codec negotiated in the hello exchange
+    pub wire_codec: WireCodecId,
 }
 
 impl ConnectionState {
@@ -257,7 +350,7 @@ impl ConnectionState {
     fn is_valid_transition(&self, next_state: &Self) -> bool {
         let mut valid = false;
         match self {
-            Self::Listening => {
+            Self::Listening(_) => {
                 if let Self::Connected(s) = next_state {
                     if s.role == ConnectionRole::Server {
                         valid = true;
@@ -272,7 +365,7 @@ impl ConnectionState {
                 }
             }
             Self::Connected(s) => match next_state {
-                Self::Listening => {
+                Self::Listening(_) => {
                     if s.role == ConnectionRole::Server {
                         valid = true;
                     }
@@ -290,7 +383,7 @@ impl ConnectionState {
 
     fn idx(&self) -> i64 {
         match self {
-            Self::Listening => 1,
+            Self::Listening(_) => 1,
             Self::Connecting(_) => 2,
             Self::Connected(_) => 3,
         }
@@ -300,7 +393,7 @@ impl ConnectionState {
 impl Debug for ConnectionState {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match &self {
-            Self::Listening => {
+            Self::Listening(_) => {
                 write!(f, "ConnectionState::Listening")
             }
             Self::Connecting(state) => {
@@ -324,12 +417,16 @@ impl Debug for ConnectionState {
 impl Drop for ConnectionState {
     fn drop(&mut self) {
         match &self {
+            Self::Listening(state) => {
+                if let Some(connecting_task) = &state.connecting_task {
+                    connecting_task.abort();
+                }
+            }
             Self::Connecting(state) => state.connecting_task.abort(),
             Self::Connected(state) => {
                 state.read_task.abort();
                 state.write_task.abort();
             }
-            _ => (),
         }
     }
 }
@@ -351,10 +448,25 @@ pub(crate) trait SendQueue {
     /// cannot be enqueued, the message is returned back to the caller.
     fn enqueue(&self, message: TransportPayload) -> Option<TransportPayload>;
 
+    /// Returns the number of enqueued messages.
+    fn queue_len(&self) -> usize;
+
     /// Discards enqueued messages and clears the queue.
     fn clear(&self);
 }
 
+/// The watermarks of a send queue
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Watermark {
+    /// The queue filled up to the high watermark
+    High,
+    /// The queue drained down to the low watermark
+    Low,
+}
+
+/// Callback invoked when a send queue crosses one of its watermarks
+pub(crate) type WatermarkCallback = Box<dyn Fn(Watermark) + Send + Sync>;
+
 /// Per-flow: send queue read end
 #[async_trait]
 pub(crate) trait SendQueueReader {
@@ -374,6 +486,14 @@ pub(crate) struct DequeuedMessage {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use futures::executor::block_on;
+    use futures::future::{pending, Abortable};
+
+    fn listening_state() -> ConnectionState {
+        ConnectionState::Listening(Listening {
+            connecting_task: None,
+        })
+    }
 
     fn connecting_state() -> ConnectionState {
         let (connecting_task, _) = AbortHandle::new_pair();
@@ -391,6 +511,7 @@ mod tests {
             read_task,
             write_task,
             role,
+            wire_codec: WireCodecId::Protobuf,
         })
     }
 
@@ -408,9 +529,9 @@ mod tests {
 
     #[test]
     fn test_connection_state_machine_listening() {
-        let state = ConnectionState::Listening;
+        let state = listening_state();
         let expected = vec![
-            (ConnectionState::Listening, false),
+            (listening_state(), false),
             (connecting_state(), false),
             (connected_state(ConnectionRole::Server), true),
             (connected_state(ConnectionRole::Client), false),
@@ -422,7 +543,7 @@ mod tests {
     fn test_connection_state_machine_connecting() {
         let state = connecting_state();
         let expected = vec![
-            (ConnectionState::Listening, false),
+            (listening_state(), false),
             (connecting_state(), false),
             (connected_state(ConnectionRole::Server), false),
             (connected_state(ConnectionRole::Client), true),
@@ -434,7 +555,7 @@ mod tests {
     fn test_connection_state_machine_connected() {
         let state = connected_state(ConnectionRole::Server);
         let expected = vec![
-            (ConnectionState::Listening, true),
+            (listening_state(), true),
             (connecting_state(), false),
             (connected_state(ConnectionRole::Server), false),
             (connected_state(ConnectionRole::Client), false),
@@ -443,11 +564,22 @@ mod tests {
 
         let state = connected_state(ConnectionRole::Client);
         let expected = vec![
-            (ConnectionState::Listening, false),
+            (listening_state(), false),
             (connecting_state(), true),
             (connected_state(ConnectionRole::Server), false),
             (connected_state(ConnectionRole::Client), false),
         ];
         verify_state_transitions(state, expected);
     }
+
+    #[test]
+    fn test_leaving_listening_state_aborts_the_connecting_task() {
+        let (connecting_task, abort_registration) = AbortHandle::new_pair();
+        let task = Abortable::new(pending::<()>(), abort_registration);
+        let mut state = ConnectionState::Listening(Listening {
+            connecting_task: Some(connecting_task),
+        });
+        state.update(connected_state(ConnectionRole::Server));
+        assert!(block_on(task).is_err());
+    }
 }
@@ -1,14 +1,18 @@
 //! Helper functionality for transport.
 
 use crate::metrics::SendQueueMetrics;
-use crate::types::{DequeuedMessage, QueueSize, SendQueue, SendQueueReader};
+use crate::types::{
+    DequeuedMessage, QueueSize, SendQueue, SendQueueReader, Watermark, WatermarkCallback,
+};
 use ic_protobuf::registry::node::v1::NodeRecord;
-use ic_types::transport::{FlowTag, TransportErrorCode, TransportPayload};
+use ic_types::transport::{FlowTag, TransportErrorCode, TransportPayload, WireCodecId};
 use ic_types::NodeId;
 
 use async_trait::async_trait;
-use std::collections::HashMap;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex, RwLock};
 use tokio::sync::mpsc::{channel, error::TrySendError, Receiver, Sender};
 use tokio::time::Duration;
@@ -40,6 +44,14 @@ pub(crate) type ReceiveEnd = Receiver<(Instant, TransportPayload)>;
 /// Maximal time to wait for batching
 const MAX_BATCHING_DURATION_MSEC: u64 = 20;
 
+/// Fill level of a send queue, in percent of the queue size, at which the
+/// queue reaches its high watermark
+const SEND_QUEUE_HIGH_WATERMARK_PERCENT: usize = 75;
+
+/// Fill level of a send queue, in percent of the queue size, at which the
+/// queue reaches its low watermark
+const SEND_QUEUE_LOW_WATERMARK_PERCENT: usize = 25;
+
 /// Guarded receive end
 struct ReceiveEndContainer {
     state: Mutex<Option<ReceiveEnd>>,
@@ -84,6 +96,116 @@ impl ReceiveEndContainer {
     }
 }
 
+/// Tracks the number of messages in a send queue, shared between the send
+/// queue and its reader.
+///
+/// The watermark callback is invoked when the queue fills up to the high
+/// watermark, and again when it drains down to the low watermark.
+struct QueueDepth {
+    /// Flow label, string for use as the value for a metric label
+    flow_label: String,
+    /// Flow Tag, string for use as the value for a metric label
+    flow_tag: String,
+    /// The number of enqueued messages
+    len: AtomicUsize,
+    /// The high watermark
+    high_watermark: usize,
+    /// The low watermark
+    low_watermark: usize,
+    /// Flag indicating if the queue reached the high watermark and has not
+    /// drained down to the low watermark since
+    above_low_watermark: AtomicBool,
+    /// The watermark callback
+    on_watermark: WatermarkCallback,
+    /// Metrics
+    metrics: SendQueueMetrics,
+}
+
+impl QueueDepth {
+    fn new(
+        flow_label: String,
+        flow_tag: String,
+        queue_size: QueueSize,
+        on_watermark: WatermarkCallback,
+        metrics: SendQueueMetrics,
+    ) -> Self {
+        let high_watermark = std::cmp::max(
+            1,
+            queue_size.get() * SEND_QUEUE_HIGH_WATERMARK_PERCENT / 100,
+        );
+        let low_watermark = std::cmp::min(
+            high_watermark - 1,
+            queue_size.get() * SEND_QUEUE_LOW_WATERMARK_PERCENT / 100,
+        );
+        Self {
+            flow_label,
+            flow_tag,
+            len: AtomicUsize::new(0),
+            high_watermark,
+            low_watermark,
+            above_low_watermark: AtomicBool::new(false),
+            on_watermark,
+            metrics,
+        }
+    }
+
+    /// Returns the number of enqueued messages
+    fn get(&self) -> usize {
+        self.len.load(Ordering::Acquire)
+    }
+
+    /// Records an enqueued message
+    fn add(&self) {
+        let len = self.len.fetch_add(1, Ordering::AcqRel) + 1;
+        self.report(len);
+        if len >= self.high_watermark
+            && self
+                .above_low_watermark
+                .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+        {
+            (self.on_watermark)(Watermark::High);
+        }
+    }
+
+    /// Records dequeued messages
+    fn remove(&self, count: usize) {
+        let len = self
+            .len
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |len| {
+                Some(len.saturating_sub(count))
+            })
+            .unwrap()
+            .saturating_sub(count);
+        self.on_len_decreased(len);
+    }
+
+    /// Records that all enqueued messages were discarded
+    fn reset(&self) {
+        self.len.store(0, Ordering::Release);
+        self.on_len_decreased(0);
+    }
+
+    fn on_len_decreased(&self, len: usize) {
+        self.report(len);
+        if len <= self.low_watermark
+            && self
+                .above_low_watermark
+                .compare_exchange(true, false, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+        {
+            (self.on_watermark)(Watermark::Low);
+        }
+    }
+
+    fn report(&self, len: usize) {
+        self.metrics
+            .queue_size
+            .with_label_values(&[&self.flow_label, &self.flow_tag])
+            .set(len as i64);
+    }
+}
+
 /// Transport client -> scheduler adapter.
 pub(crate) struct SendQueueImpl {
     /// Flow label, string for use as the value for a metric label
@@ -102,6 +224,9 @@ pub(crate) struct SendQueueImpl {
     /// Error flag
     error: Arc<AtomicBool>,
 
+    /// Number of enqueued messages
+    depth: Arc<QueueDepth>,
+
     /// Metrics
     metrics: SendQueueMetrics,
 }
@@ -113,14 +238,23 @@ impl SendQueueImpl {
         flow_label: String,
         flow_tag: &FlowTag,
         queue_size: QueueSize,
+        on_watermark: WatermarkCallback,
         metrics: SendQueueMetrics,
     ) -> Self {
         let (send_end, receive_end) = channel(queue_size.get());
         let receieve_end_wrapper = ReceiveEndContainer::new(receive_end);
+        let depth = Arc::new(QueueDepth::new(
+            flow_label.clone(),
+            flow_tag.to_string(),
+            queue_size,
+            on_watermark,
+            metrics.clone(),
+        ));
         Self {
             flow_label,
             flow_tag: flow_tag.to_string(),
             error: Arc::new(AtomicBool::new(false)),
+            depth,
             queue_size,
             channel_ends: RwLock::new((send_end, Arc::new(receieve_end_wrapper))),
             metrics,
@@ -134,8 +268,10 @@ impl SendQueue for SendQueueImpl {
         let (send_end, receive_end) = channel(self.queue_size.get());
         let mut channel_ends = self.channel_ends.write().unwrap();
         if channel_ends.1.try_update(receive_end).is_ok() {
-            // Receive end was updated, so update send end as well.
+            // Receive end was updated, so update send end as well. The messages
+            // in the previous channel are discarded.
             channel_ends.0 = send_end;
+            self.depth.reset();
         }
 
         let reader = SendQueueReaderImpl {
@@ -144,6 +280,7 @@ impl SendQueue for SendQueueImpl {
             receive_end_container: channel_ends.1.clone(),
             cur_receive_end: None,
             error: self.error.clone(),
+            depth: self.depth.clone(),
             metrics: self.metrics.clone(),
         };
         Box::new(reader)
@@ -161,7 +298,10 @@ impl SendQueue for SendQueueImpl {
 
         let channel_ends = self.channel_ends.read().unwrap();
         match channel_ends.0.try_send((Instant::now(), message)) {
-            Ok(_) => None,
+            Ok(_) => {
+                self.depth.add();
+                None
+            }
             Err(TrySendError::Full((_, unsent))) => {
                 self.error.store(true, Ordering::Release);
                 self.metrics
@@ -181,12 +321,17 @@ impl SendQueue for SendQueueImpl {
         }
     }
 
+    fn queue_len(&self) -> usize {
+        self.depth.get()
+    }
+
     fn clear(&self) {
         let (send_end, receive_end) = channel(self.queue_size.get());
         {
             let mut channel_ends = self.channel_ends.write().unwrap();
             channel_ends.0 = send_end;
             channel_ends.1.update(receive_end);
+            self.depth.reset();
         }
         self.metrics
             .queue_clear
@@ -202,6 +347,7 @@ struct SendQueueReaderImpl {
     receive_end_container: Arc<ReceiveEndContainer>,
     cur_receive_end: Option<ReceiveEnd>,
     error: Arc<AtomicBool>,
+    depth: Arc<QueueDepth>,
     metrics: SendQueueMetrics,
 }
 
@@ -294,6 +440,7 @@ impl SendQueueReader for SendQueueReaderImpl {
             }
         }
 
+        self.depth.remove(removed);
         self.metrics
             .remove_count
             .with_label_values(&[&self.flow_label, &self.flow_tag])
@@ -307,25 +454,49 @@ impl SendQueueReader for SendQueueReaderImpl {
 }
 
 /// Returns a map of flow_tag -> peer_ip for that flow.
+///
+/// A dual-stack node announces up to two endpoints per flow, one per address
+/// family. In this case, the IPv6 address is returned.
 pub(crate) fn get_flow_ips(
     node_record: &NodeRecord,
 ) -> Result<HashMap<FlowTag, String>, TransportErrorCode> {
     let mut ret = HashMap::new();
+    let mut address_families = HashSet::new();
     for flow_endpoint in &node_record.p2p_flow_endpoints {
         let flow_tag = FlowTag::from(flow_endpoint.flow_tag);
-        if ret.contains_key(&flow_tag) {
-            return Err(TransportErrorCode::NodeRecordDuplicateFlowTag);
-        }
-
-        match &flow_endpoint.endpoint {
-            Some(connection_endpoint) => ret.insert(flow_tag, connection_endpoint.ip_addr.clone()),
+        let connection_endpoint = match &flow_endpoint.endpoint {
+            Some(connection_endpoint) => connection_endpoint,
             None => return Err(TransportErrorCode::NodeRecordMissingConnectionEndpoint),
         };
+
+        let is_ipv6 = connection_endpoint.ip_addr.contains(':');
+        if !address_families.insert((flow_tag, is_ipv6)) {
+            return Err(TransportErrorCode::NodeRecordDuplicateFlowTag);
+        }
+        if is_ipv6 || !ret.contains_key(&flow_tag) {
+            ret.insert(flow_tag, connection_endpoint.ip_addr.clone());
+        }
     }
 
     Ok(ret)
 }
 
+/// Returns the socket addresses announced for the given flow, in the order of
+/// the node record. Endpoints with an invalid IP address are skipped.
+pub(crate) fn get_flow_endpoints(node_record: &NodeRecord, flow_tag: FlowTag) -> Vec<SocketAddr> {
+    node_record
+        .p2p_flow_endpoints
+        .iter()
+        .filter(|flow_endpoint| FlowTag::from(flow_endpoint.flow_tag) == flow_tag)
+        .filter_map(|flow_endpoint| flow_endpoint.endpoint.as_ref())
+        .filter_map(|endpoint| {
+            IpAddr::from_str(&endpoint.ip_addr)
+                .ok()
+                .map(|ip| SocketAddr::new(ip, endpoint.port as u16))
+        })
+        .collect()
+}
+
 /// Builds the flow label to use for metrics, from the IP address and the NodeId
 pub(crate) fn get_flow_label(node_ip: &str, node_id: &NodeId) -> String {
     // 35: Includes the first 6 groups of 5 chars each + the 5 separators
@@ -333,13 +504,69 @@ pub(crate) fn get_flow_label(node_ip: &str, node_id: &NodeId) -> String {
     return format!("{}_{}", node_ip, prefix);
 }
 
+/// Returns the wire codecs supported by this node in order of preference:
+/// the configured codecs, followed by protobuf if it is not configured.
+pub(crate) fn supported_wire_codecs(configured: &[WireCodecId]) -> Vec<WireCodecId> {
+    let mut codecs = Vec::new();
+    for codec in configured
+        .iter()
+        .chain(std::iter::once(&WireCodecId::Protobuf))
+    {
+        if !codecs.contains(codec) {
+            codecs.push(*codec);
+        }
+    }
+    codecs
+}
+
+/// Negotiates the wire codec of a connection: the first codec of the server
+/// that is also supported by the client, or protobuf if there is none.
+pub(crate) fn negotiate_wire_codec(
+    server_codecs: &[WireCodecId],
+    client_codecs: &[WireCodecId],
+) -> WireCodecId {
+    server_codecs
+        .iter()
+        .find(|codec| client_codecs.contains(codec))
+        .copied()
+        .unwrap_or_default()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use ic_metrics::MetricsRegistry;
     use ic_protobuf::registry::node::v1::{
         connection_endpoint::Protocol, ConnectionEndpoint, FlowEndpoint,
     };
 
+    #[test]
+    fn test_negotiate_wire_codec() {
+        let server = supported_wire_codecs(&[WireCodecId::Bincode, WireCodecId::Cbor]);
+        assert_eq!(
+            server,
+            vec![
+                WireCodecId::Bincode,
+                WireCodecId::Cbor,
+                WireCodecId::Protobuf
+            ]
+        );
+
+        // The server's preference wins.
+        let client = supported_wire_codecs(&[WireCodecId::Cbor, WireCodecId::Bincode]);
+        assert_eq!(negotiate_wire_codec(&server, &client), WireCodecId::Bincode);
+        assert_eq!(negotiate_wire_codec(&client, &server), WireCodecId::Cbor);
+
+        // Nodes without configured codecs only support protobuf.
+        let client = supported_wire_codecs(&[]);
+        assert_eq!(client, vec![WireCodecId::Protobuf]);
+        assert_eq!(
+            negotiate_wire_codec(&server, &client),
+            WireCodecId::Protobuf
+        );
+        assert_eq!(negotiate_wire_codec(&server, &[]), WireCodecId::Protobuf);
+    }
+
     #[test]
     fn test_get_flow_ips() {
         let mut node_record: NodeRecord = Default::default();
@@ -401,6 +628,44 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_get_flow_ips_dual_stack() {
+        let mut node_record: NodeRecord = Default::default();
+        node_record.p2p_flow_endpoints.push(FlowEndpoint {
+            flow_tag: 1000,
+            endpoint: Some(ConnectionEndpoint {
+                ip_addr: "10.0.0.1".to_string(),
+                port: 100,
+                protocol: Protocol::P2p1Tls13 as i32,
+            }),
+        });
+        node_record.p2p_flow_endpoints.push(FlowEndpoint {
+            flow_tag: 1000,
+            endpoint: Some(ConnectionEndpoint {
+                ip_addr: "fd00::1".to_string(),
+                port: 100,
+                protocol: Protocol::P2p1Tls13 as i32,
+            }),
+        });
+
+        let ip_map = get_flow_ips(&node_record).unwrap();
+        assert_eq!(ip_map.len(), 1);
+        assert_eq!(
+            *ip_map.get(&FlowTag::from(1000)).unwrap(),
+            "fd00::1".to_string()
+        );
+
+        let endpoints = get_flow_endpoints(&node_record, FlowTag::from(1000));
+        assert_eq!(
+            endpoints,
+            vec![
+                SocketAddr::from_str("10.0.0.1:100").unwrap(),
+                SocketAddr::from_str("[fd00::1]:100").unwrap(),
+            ]
+        );
+        assert!(get_flow_endpoints(&node_record, FlowTag::from(2000)).is_empty());
+    }
+
     #[test]
     fn test_get_flow_ips_missing_endpoint() {
         let mut node_record: NodeRecord = Default::default();
@@ -413,4 +678,48 @@ mod tests {
             Err(TransportErrorCode::NodeRecordMissingConnectionEndpoint)
         );
     }
+
+    #[test]
+    fn test_queue_depth_watermarks() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let events_cl = events.clone();
+        let depth = QueueDepth::new(
+            "flow".to_string(),
+            "1000".to_string(),
+            QueueSize::from(8),
+            Box::new(move |watermark| events_cl.lock().unwrap().push(watermark)),
+            SendQueueMetrics::new(MetricsRegistry::new()),
+        );
+
+        // The high watermark is reached at 6 messages.
+        (0..5).for_each(|_| depth.add());
+        assert!(events.lock().unwrap().is_empty());
+        depth.add();
+        depth.add();
+        assert_eq!(*events.lock().unwrap(), vec![Watermark::High]);
+
+        // The low watermark is reached at 2 messages.
+        depth.remove(4);
+        assert_eq!(*events.lock().unwrap(), vec![Watermark::High]);
+        depth.remove(1);
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![Watermark::High, Watermark::Low]
+        );
+        assert_eq!(depth.get(), 2);
+
+        // Clearing the queue of a congested queue reports the low watermark.
+        (0..4).for_each(|_| depth.add());
+        depth.reset();
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                Watermark::High,
+                Watermark::Low,
+                Watermark::High,
+                Watermark::Low
+            ]
+        );
+        assert_eq!(depth.get(), 0);
+    }
 }
@@ -1,4 +1,6 @@
 use prost_build::Config;
+use std::env;
+use std::path::PathBuf;
 
 // Build protos using prost_build.
 fn main() {
@@ -7,10 +9,36 @@ fn main() {
     let mut config = Config::new();
 
     config.out_dir("gen");
+    config.file_descriptor_set_path(out_dir().join("file_descriptor_set.bin"));
+    config.type_attribute(
+        ".",
+        "#[derive(serde::Serialize, serde::Deserialize, Eq, Hash)]",
+    );
     config.type_attribute(
         "ic_base_types.pb.v1.PrincipalId",
-        "#[derive(candid::CandidType, candid::Deserialize)]",
+        "#[derive(candid::CandidType)]",
     );
     println!("cargo:rerun-if-changed={}", proto_file);
     config.compile_protos(&[proto_file], &["proto"]).unwrap();
+
+    build_released_protos();
+}
+
+// Compiles the last released protos, which are vendored in `released/`, to
+// the file descriptor set that the current ones are checked against.
+fn build_released_protos() {
+    let proto_file = "released/ic_base_types/pb/v1/types.proto";
+    // The generated code is not used.
+    let generated_dir = out_dir().join("released");
+    std::fs::create_dir_all(&generated_dir).expect("Failed to create the output directory");
+
+    let mut config = Config::new();
+    config.out_dir(generated_dir);
+    config.file_descriptor_set_path(out_dir().join("released_file_descriptor_set.bin"));
+    println!("cargo:rerun-if-changed={}", proto_file);
+    config.compile_protos(&[proto_file], &["released"]).unwrap();
+}
+
+fn out_dir() -> PathBuf {
+    PathBuf::from(env::var("OUT_DIR").expect("OUT_DIR environment variable not set"))
 }
@@ -3,14 +3,14 @@
 
 use candid::CandidType;
 use ic_protobuf::proxy::ProxyDecodeError;
-use ic_protobuf::types::v1 as pb;
+use ic_protobuf::types::v1 as pb_types;
 use phantom_newtype::{AmountOf, DisplayerOf, Id};
 use serde::{Deserialize, Serialize};
 use std::{convert::TryFrom, fmt, slice::Iter};
 use strum_macros::EnumString;
 
 mod canister_id;
-mod pb_internal;
+pub mod pb;
 mod principal_id;
 
 pub use candid::types::ic_types;
@@ -153,16 +153,18 @@ impl From<CanisterInstallMode> for String {
 /// Converts a SubnetId into its protobuf definition.  Normally, we would use
 /// `impl From<SubnetId> for pb::SubnetId` here however we cannot as both
 /// `Id` and `pb::SubnetId` are defined in other crates.
-pub fn subnet_id_into_protobuf(id: SubnetId) -> pb::SubnetId {
-    pb::SubnetId {
-        principal_id: Some(pb::PrincipalId::from(id.get())),
+pub fn subnet_id_into_protobuf(id: SubnetId) -> pb_types::SubnetId {
+    pb_types::SubnetId {
+        principal_id: Some(pb_types::PrincipalId::from(id.get())),
     }
 }
 
 /// From its protobuf definition convert to a SubnetId.  Normally, we would
 /// use `impl TryFrom<pb::SubnetId> for SubnetId` here however we cannot as
 /// both `Id` and `pb::SubnetId` are defined in other crates.
-pub fn subnet_id_try_from_protobuf(value: pb::SubnetId) -> Result<SubnetId, ProxyDecodeError> {
+pub fn subnet_id_try_from_protobuf(
+    value: pb_types::SubnetId,
+) -> Result<SubnetId, ProxyDecodeError> {
     let principal_id = PrincipalId::try_from(
         value
             .principal_id
@@ -182,3 +184,38 @@ impl From<CanisterIdError> for ProxyDecodeError {
         Self::InvalidCanisterId(Box::new(err))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subnet_id_roundtrips_through_protobuf() {
+        let id = SubnetId::from(PrincipalId::new_subnet_test_id(42));
+        assert_eq!(
+            subnet_id_try_from_protobuf(subnet_id_into_protobuf(id)).unwrap(),
+            id
+        );
+    }
+
+    #[test]
+    fn subnet_id_without_principal_id_is_rejected() {
+        assert!(matches!(
+            subnet_id_try_from_protobuf(pb_types::SubnetId { principal_id: None }),
+            Err(ProxyDecodeError::MissingField("SubnetId::principal_id"))
+        ));
+    }
+
+    #[test]
+    fn subnet_id_with_invalid_principal_id_is_rejected() {
+        let proto = pb_types::SubnetId {
+            principal_id: Some(pb_types::PrincipalId {
+                raw: vec![0; PrincipalId::MAX_LENGTH_IN_BYTES + 1],
+            }),
+        };
+        assert!(matches!(
+            subnet_id_try_from_protobuf(proto),
+            Err(ProxyDecodeError::InvalidPrincipalId(_))
+        ));
+    }
+}
@@ -0,0 +1,21 @@
+//! The protobuf types of this crate, see `proto/ic_base_types/pb/v1`.
+//!
+//! The generated types derive `serde` and `Hash`, and convert from and to
+//! the native types of the crate.
+#[path = "../../gen/ic_base_types.pb.v1.rs"]
+#[rustfmt::skip]
+pub mod v1;
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn protos_are_compatible_with_the_released_ones() {
+        ic_protobuf_compatibility::assert_compatible(
+            include_bytes!(concat!(
+                env!("OUT_DIR"),
+                "/released_file_descriptor_set.bin"
+            )),
+            include_bytes!(concat!(env!("OUT_DIR"), "/file_descriptor_set.bin")),
+        );
+    }
+}
@@ -1,3 +0,0 @@
-#[path = "../../gen/ic_base_types.pb.v1.rs"]
-#[rustfmt::skip]
-pub mod v1;
@@ -1,5 +1,5 @@
 use crate::ic_types::{Principal, PrincipalError};
-use crate::pb_internal::v1::PrincipalId as PrincipalIdProto;
+use crate::pb::v1::PrincipalId as PrincipalIdProto;
 use candid::types::{Type, TypeId};
 use ic_crypto_sha256::Sha224;
 use ic_protobuf::types::v1 as pb;
@@ -353,9 +353,11 @@ impl From<PrincipalId> for PrincipalIdProto {
     }
 }
 
-impl From<PrincipalIdProto> for PrincipalId {
-    fn from(pb: PrincipalIdProto) -> Self {
-        PrincipalId::try_from(pb.serialized_id).unwrap()
+impl TryFrom<PrincipalIdProto> for PrincipalId {
+    type Error = PrincipalIdError;
+
+    fn try_from(pb: PrincipalIdProto) -> Result<Self, Self::Error> {
+        PrincipalId::try_from(pb.serialized_id)
     }
 }
 
@@ -387,7 +389,8 @@ impl prost::Message for PrincipalId {
     {
         let mut pid_proto = PrincipalIdProto::from(*self);
         pid_proto.merge_field(tag, wire_type, buf, ctx)?;
-        *self = Self::from(pid_proto);
+        *self = Self::try_from(pid_proto)
+            .map_err(|err| prost::DecodeError::new(format!("invalid principal id: {}", err)))?;
         Ok(())
     }
     fn encoded_len(&self) -> usize {
@@ -395,9 +398,9 @@ impl prost::Message for PrincipalId {
     }
 
     fn clear(&mut self) {
-        let mut pid_proto = PrincipalIdProto::from(*self);
-        pid_proto.clear();
-        *self = Self::from(pid_proto);
+        // The default of the serialized id is empty, i.e. the management
+        // canister.
+        *self = Self(Principal::management_canister());
     }
 }
 
@@ -453,12 +456,31 @@ mod tests {
             assert_eq!(PrincipalId::from_str(&text[..]), Ok(id));
         }
 
+        #[test]
+        fn roundtrip_proto(id in arb_principal_id()) {
+            assert_eq!(PrincipalId::try_from(PrincipalIdProto::from(id)), Ok(id));
+        }
+
         #[test]
         fn parse_from_str_does_not_crash(s in "\\PC*") {
             let _ignore = PrincipalId::from_str(&s[..]);
         }
     }
 
+    #[test]
+    fn decoding_an_invalid_principal_id_fails() {
+        use prost::Message;
+
+        let proto = PrincipalIdProto {
+            serialized_id: vec![0; PrincipalId::MAX_LENGTH_IN_BYTES + 1],
+        };
+        let mut buf = Vec::new();
+        proto.encode(&mut buf).unwrap();
+
+        assert!(PrincipalId::try_from(proto).is_err());
+        assert!(PrincipalId::decode(&buf[..]).is_err());
+    }
+
     #[test]
     fn parse_bad_checksum() {
         let good = PrincipalId::from_str(&"bfozs-kwa73-7nadi".to_string())
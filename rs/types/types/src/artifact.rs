@@ -14,23 +14,33 @@
 //! All [`Artifact`] sub-types must also implement [`ChunkableArtifact`] trait
 //! defined in the chunkable module.
 use crate::{
+    chunkable::Chunkable,
     consensus::{certification::CertificationMessageHash, ConsensusMessageHash},
     crypto::{CryptoHash, CryptoHashOf},
     filetree_sync::{FileTreeSyncArtifact, FileTreeSyncId},
     messages::{MessageId, SignedRequestBytes},
     p2p::GossipAdvert,
-    CryptoHashOfState, Height, Time,
+    subnet_id_into_protobuf, subnet_id_try_from_protobuf,
+    xnet::{CertifiedStreamSlice, StreamIndex},
+    CryptoHashOfState, Height, RegistryVersion, SubnetId, Time,
 };
 use derive_more::{AsMut, AsRef, From, TryInto};
 use ic_protobuf::p2p::v1 as pb;
 use ic_protobuf::proxy::{try_from_option_field, ProxyDecodeError};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::convert::{TryFrom, TryInto};
 use strum_macros::EnumIter;
 
 pub use crate::{
     consensus::{
-        certification::CertificationMessage, dkg::Message as DkgMessage, ecdsa::EcdsaMessage,
+        canister_http::CanisterHttpMessage,
+        certification::CertificationMessage,
+        dkg::Message as DkgMessage,
+        ecdsa::EcdsaMessage,
+        equivocation::EquivocationProof,
+        query_stats::{QueryStatsEpoch, QueryStatsMessage},
+        remote_dkg::RemoteDkgMessage,
         ConsensusMessage, ConsensusMessageAttribute,
     },
     messages::SignedIngress,
@@ -48,6 +58,12 @@ pub enum Artifact {
     EcdsaMessage(EcdsaMessage),
     FileTreeSync(FileTreeSyncArtifact),
     StateSync(StateSyncMessage),
+    EquivocationProof(EquivocationProof),
+    RemoteDkgMessage(RemoteDkgMessage),
+    CanisterHttpMessage(CanisterHttpMessage),
+    QueryStatsMessage(QueryStatsMessage),
+    XNetStreamSlice(XNetStreamSliceMessage),
+    RegistryDelta(RegistryDeltaMessage),
 }
 
 /// Artifact attribute type.
@@ -61,6 +77,12 @@ pub enum ArtifactAttribute {
     EcdsaMessage(EcdsaMessageAttribute),
     FileTreeSync(FileTreeSyncAttribute),
     StateSync(StateSyncAttribute),
+    EquivocationProof(EquivocationProofAttribute),
+    RemoteDkgMessage(RemoteDkgMessageAttribute),
+    CanisterHttpMessage(CanisterHttpMessageAttribute),
+    QueryStatsMessage(QueryStatsMessageAttribute),
+    XNetStreamSlice(XNetStreamSliceAttribute),
+    RegistryDelta(RegistryDeltaAttribute),
 }
 
 /// Artifact identifier type.
@@ -74,6 +96,12 @@ pub enum ArtifactId {
     EcdsaMessage(EcdsaMessageId),
     FileTreeSync(FileTreeSyncId),
     StateSync(StateSyncArtifactId),
+    EquivocationProof(EquivocationProofId),
+    RemoteDkgMessage(RemoteDkgMessageId),
+    CanisterHttpMessage(CanisterHttpMessageId),
+    QueryStatsMessage(QueryStatsMessageId),
+    XNetStreamSlice(XNetStreamSliceId),
+    RegistryDelta(RegistryDeltaId),
 }
 
 /// Artifact tags is used to select an artifact subtype when we do not have
@@ -88,6 +116,12 @@ pub enum ArtifactTag {
     EcdsaArtifact,
     FileTreeSyncArtifact,
     StateSyncArtifact,
+    EquivocationArtifact,
+    RemoteDkgArtifact,
+    CanisterHttpArtifact,
+    QueryStatsArtifact,
+    XNetStreamSliceArtifact,
+    RegistryDeltaArtifact,
 }
 
 impl std::fmt::Display for ArtifactTag {
@@ -103,6 +137,12 @@ impl std::fmt::Display for ArtifactTag {
                 ArtifactTag::EcdsaArtifact => "ECDSA",
                 ArtifactTag::FileTreeSyncArtifact => "FileTreeSync",
                 ArtifactTag::StateSyncArtifact => "StateSync",
+                ArtifactTag::EquivocationArtifact => "Equivocation",
+                ArtifactTag::RemoteDkgArtifact => "RemoteDKG",
+                ArtifactTag::CanisterHttpArtifact => "CanisterHttp",
+                ArtifactTag::QueryStatsArtifact => "QueryStats",
+                ArtifactTag::XNetStreamSliceArtifact => "XNetStreamSlice",
+                ArtifactTag::RegistryDeltaArtifact => "RegistryDelta",
             }
         )
     }
@@ -118,6 +158,12 @@ impl From<&ArtifactId> for ArtifactTag {
             ArtifactId::EcdsaMessage(_) => ArtifactTag::EcdsaArtifact,
             ArtifactId::FileTreeSync(_) => ArtifactTag::FileTreeSyncArtifact,
             ArtifactId::StateSync(_) => ArtifactTag::StateSyncArtifact,
+            ArtifactId::EquivocationProof(_) => ArtifactTag::EquivocationArtifact,
+            ArtifactId::RemoteDkgMessage(_) => ArtifactTag::RemoteDkgArtifact,
+            ArtifactId::CanisterHttpMessage(_) => ArtifactTag::CanisterHttpArtifact,
+            ArtifactId::QueryStatsMessage(_) => ArtifactTag::QueryStatsArtifact,
+            ArtifactId::XNetStreamSlice(_) => ArtifactTag::XNetStreamSliceArtifact,
+            ArtifactId::RegistryDelta(_) => ArtifactTag::RegistryDeltaArtifact,
         }
     }
 }
@@ -134,6 +180,12 @@ impl From<&Artifact> for ArtifactTag {
             Artifact::EcdsaMessage(_) => ArtifactTag::EcdsaArtifact,
             Artifact::FileTreeSync(_) => ArtifactTag::FileTreeSyncArtifact,
             Artifact::StateSync(_) => ArtifactTag::StateSyncArtifact,
+            Artifact::EquivocationProof(_) => ArtifactTag::EquivocationArtifact,
+            Artifact::RemoteDkgMessage(_) => ArtifactTag::RemoteDkgArtifact,
+            Artifact::CanisterHttpMessage(_) => ArtifactTag::CanisterHttpArtifact,
+            Artifact::QueryStatsMessage(_) => ArtifactTag::QueryStatsArtifact,
+            Artifact::XNetStreamSlice(_) => ArtifactTag::XNetStreamSliceArtifact,
+            Artifact::RegistryDelta(_) => ArtifactTag::RegistryDeltaArtifact,
         }
     }
 }
@@ -142,12 +194,13 @@ impl From<&Artifact> for ArtifactTag {
 /// of artifact pools. At the moment it only has consensus filter.
 /// Note that it is a struct instead of an enum, because we most likely
 /// are interested in all filters.
-#[derive(AsMut, AsRef, Default, Clone, Debug, Eq, PartialEq, Hash)]
+#[derive(AsMut, AsRef, Default, Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub struct ArtifactFilter {
     pub consensus_filter: ConsensusMessageFilter,
     pub ingress_filter: IngressMessageFilter,
     pub certification_filter: CertificationMessageFilter,
     pub state_sync_filter: StateSyncFilter,
+    pub xnet_filter: XNetStreamSliceFilter,
     pub no_filter: (),
 }
 
@@ -197,6 +250,25 @@ pub trait ArtifactKind: Sized {
     /// Returns the advert of the given message.
     fn message_to_advert(msg: &<Self as ArtifactKind>::Message) -> Advert<Self>;
 
+    /// Returns the integrity hash of the given message, in the form in which
+    /// it is sent over the wire. It is the hash advertised in
+    /// `Advert::integrity_hash`, and recomputed by the receiver of the message
+    /// to check that the downloaded chunks match the advert.
+    fn integrity_hash(msg: &<Self as ArtifactKind>::SerializeAs) -> CryptoHash;
+
+    /// Returns the tracker to download an artifact of this kind with the
+    /// given advertised size as multiple chunks, typically a
+    /// `chunkable::MultiChunked` tracker. Artifacts larger than
+    /// `chunkable::ARTIFACT_CHUNK_SIZE` are downloaded this way, unless this
+    /// returns `None`, as it does by default, in which case they are
+    /// downloaded as a single chunk.
+    ///
+    /// Kinds that return a tracker must advertise the size of the serialized
+    /// artifact in `Advert::size`, as it determines the number of chunks.
+    fn multi_chunk_tracker(_size: usize) -> Option<Box<dyn Chunkable + Send + Sync>> {
+        None
+    }
+
     /// Checks if the given advert matches what is computed from the message.
     /// Returns the advert derived from artifact on mismatch.
     fn check_advert(
@@ -405,11 +477,12 @@ pub struct DkgMessageAttribute {
 // -----------------------------------------------------------------------------
 // ECDSA artifacts
 
-#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
-pub struct EcdsaMessageId;
+pub type EcdsaMessageId = CryptoHashOf<EcdsaMessage>;
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
-pub struct EcdsaMessageAttribute;
+pub struct EcdsaMessageAttribute {
+    pub height: Height,
+}
 
 #[derive(Default, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct EcdsaMessageFilter;
@@ -479,11 +552,140 @@ pub struct StateSyncAttribute {
 }
 
 /// State sync filter is by height.
-#[derive(Default, Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Default, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct StateSyncFilter {
     pub height: Height,
 }
 
+// ------------------------------------------------------------------------------
+// Equivocation artifacts
+
+/// Identifier of an equivocation proof.
+pub type EquivocationProofId = CryptoHashOf<EquivocationProof>;
+
+/// The equivocation proof attribute used by the priority function.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct EquivocationProofAttribute {
+    /// The height of the equivocating block proposals.
+    pub height: Height,
+}
+
+// ------------------------------------------------------------------------------
+// Remote DKG artifacts
+
+/// Identifier of a remote DKG message.
+pub type RemoteDkgMessageId = CryptoHashOf<RemoteDkgMessage>;
+
+/// The remote DKG message attribute used by the priority function.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct RemoteDkgMessageAttribute {
+    /// The height of the DKG summary block the message was taken from.
+    pub summary_height: Height,
+}
+
+// ------------------------------------------------------------------------------
+// Canister HTTP artifacts
+
+/// Identifier of a canister HTTP message.
+pub type CanisterHttpMessageId = CryptoHashOf<CanisterHttpMessage>;
+
+/// The canister HTTP message attribute used by the priority function.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct CanisterHttpMessageAttribute {
+    /// The time after which the request of the message times out.
+    pub timeout: Time,
+}
+
+// ------------------------------------------------------------------------------
+// Query statistics artifacts
+
+/// Identifier of a query statistics message.
+pub type QueryStatsMessageId = CryptoHashOf<QueryStatsMessage>;
+
+/// The query statistics message attribute used by the priority function.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct QueryStatsMessageAttribute {
+    /// The epoch the statistics of the message were collected in.
+    pub epoch: QueryStatsEpoch,
+}
+
+// ------------------------------------------------------------------------------
+// XNet stream slice artifacts
+
+/// Identifier of a certified slice of the stream from the `source` to the
+/// `destination` subnet, pulled over gossip instead of from the XNet endpoint.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct XNetStreamSliceId {
+    pub source: SubnetId,
+    pub destination: SubnetId,
+    /// The height of the certified state of the source subnet that the slice
+    /// was encoded from.
+    pub height: Height,
+    pub witness_begin: StreamIndex,
+    pub msg_begin: StreamIndex,
+}
+
+/// A certified stream slice, together with its identifier.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct XNetStreamSliceMessage {
+    pub id: XNetStreamSliceId,
+    pub slice: CertifiedStreamSlice,
+}
+
+/// The XNet stream slice attribute used by the priority function.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct XNetStreamSliceAttribute {
+    /// The subnet the stream is addressed to.
+    pub destination: SubnetId,
+}
+
+/// The slice of a stream from a remote subnet that a subnet asks for, with the
+/// same meaning as the query parameters of the XNet endpoint.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct XNetStreamSliceRequest {
+    pub witness_begin: StreamIndex,
+    pub msg_begin: StreamIndex,
+    pub byte_limit: usize,
+}
+
+/// XNet stream slices are filtered by the subnet requesting them and the
+/// position in the stream from each remote subnet that it has reached.
+///
+/// A `destination` of `None` means that the requesting node doesn't pull XNet
+/// streams over gossip.
+#[derive(Default, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct XNetStreamSliceFilter {
+    pub destination: Option<SubnetId>,
+    pub requests: BTreeMap<SubnetId, XNetStreamSliceRequest>,
+}
+
+// ------------------------------------------------------------------------------
+// Registry delta artifacts
+
+/// Identifier of a certified response of the registry canister that holds the
+/// registry deltas of the versions above `since_version` up to `version`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct RegistryDeltaId {
+    pub since_version: RegistryVersion,
+    pub version: RegistryVersion,
+}
+
+/// A response of the `get_certified_changes_since` method of the registry
+/// canister, certified by the NNS subnet, together with its identifier.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct RegistryDeltaMessage {
+    pub id: RegistryDeltaId,
+    #[serde(with = "serde_bytes")]
+    pub certified_response: Vec<u8>,
+}
+
+/// The registry delta attribute used by the priority function.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct RegistryDeltaAttribute {
+    /// The latest registry version of the deltas.
+    pub version: RegistryVersion,
+}
+
 // ------------------------------------------------------------------------------
 // FileTreeSync artifacts
 
@@ -505,6 +707,7 @@ impl From<ArtifactFilter> for pb::ArtifactFilter {
             }),
             certification_message_filter: Some(filter.certification_filter.into()),
             state_sync_filter: Some(filter.state_sync_filter.into()),
+            xnet_filter: Some(filter.xnet_filter.into()),
         }
     }
 }
@@ -533,6 +736,12 @@ impl TryFrom<pb::ArtifactFilter> for ArtifactFilter {
                 filter.state_sync_filter,
                 "ArtifactFilter.state_sync_filter",
             )?,
+            // Nodes that don't know about XNet stream slices don't send a filter.
+            xnet_filter: filter
+                .xnet_filter
+                .map(XNetStreamSliceFilter::try_from)
+                .transpose()?
+                .unwrap_or_default(),
             no_filter: (),
         })
     }
@@ -588,3 +797,48 @@ impl TryFrom<pb::StateSyncFilter> for StateSyncFilter {
         })
     }
 }
+
+impl From<XNetStreamSliceFilter> for pb::XNetStreamSliceFilter {
+    fn from(filter: XNetStreamSliceFilter) -> Self {
+        Self {
+            destination: filter.destination.map(subnet_id_into_protobuf),
+            requests: filter
+                .requests
+                .into_iter()
+                .map(|(source, request)| pb::XNetStreamSliceRequest {
+                    source: Some(subnet_id_into_protobuf(source)),
+                    witness_begin: request.witness_begin.get(),
+                    msg_begin: request.msg_begin.get(),
+                    byte_limit: request.byte_limit as u64,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<pb::XNetStreamSliceFilter> for XNetStreamSliceFilter {
+    type Error = ProxyDecodeError;
+    fn try_from(filter: pb::XNetStreamSliceFilter) -> Result<Self, Self::Error> {
+        let mut requests = BTreeMap::new();
+        for request in filter.requests {
+            let source = subnet_id_try_from_protobuf(request.source.ok_or(
+                ProxyDecodeError::MissingField("XNetStreamSliceRequest.source"),
+            )?)?;
+            requests.insert(
+                source,
+                XNetStreamSliceRequest {
+                    witness_begin: StreamIndex::from(request.witness_begin),
+                    msg_begin: StreamIndex::from(request.msg_begin),
+                    byte_limit: request.byte_limit as usize,
+                },
+            );
+        }
+        Ok(Self {
+            destination: filter
+                .destination
+                .map(subnet_id_try_from_protobuf)
+                .transpose()?,
+            requests,
+        })
+    }
+}
@@ -0,0 +1,374 @@
+//! Versioned encoding of gossiped artifacts.
+//!
+//! While a subnet is upgraded, replicas running the old and the new replica
+//! version gossip artifacts with each other, even if the encoding of some
+//! artifact kinds changed between the two versions. Therefore, artifacts sent
+//! in unit chunks are wrapped in an [`ArtifactEnvelope`] that carries the
+//! version of their encoding:
+//!
+//! - The requester of a chunk announces the latest encoding version it
+//!   understands, and the sender encodes the artifact in the older of that
+//!   version and its own, down-converting it if needed.
+//! - The receiver up-converts an artifact encoded in an older version to its
+//!   own version before decoding it.
+//!
+//! Artifacts downloaded as multiple chunks are chunks of the encoded artifact
+//! of an envelope, see `chunkable::MultiChunked`.
+//!
+//! The kind of the artifact is identified on the wire by the fixed number
+//! returned by [`artifact_tag_number`], so that adding, removing or reordering
+//! `ArtifactTag` variants does not change the encoding.
+//!
+//! When the encoding of an artifact kind changes, bump
+//! [`ARTIFACT_ENCODING_VERSION`] and register the conversion between the
+//! previous and the new version for the kind in `conversion`, see
+//! [`ArtifactEncodingConversion`]. Conversions only need to cover the versions
+//! of the replica versions that run on a subnet at the same time.
+//!
+//! Replicas predating the envelope don't announce an encoding version, i.e. it
+//! is [`LEGACY_ARTIFACT_ENCODING_VERSION`], and are sent the artifact without
+//! an envelope. Version 1 encodes artifacts the same way as these replicas.
+use crate::artifact::{Artifact, ArtifactTag};
+use bincode::{deserialize, serialize};
+use std::fmt;
+use strum::IntoEnumIterator;
+
+/// The version of the encoding of gossiped artifacts.
+pub type ArtifactEncodingVersion = u32;
+
+/// The encoding version of replicas that don't support versioned artifacts.
+pub const LEGACY_ARTIFACT_ENCODING_VERSION: ArtifactEncodingVersion = 0;
+
+/// The encoding version of the artifacts of this replica.
+pub const ARTIFACT_ENCODING_VERSION: ArtifactEncodingVersion = 1;
+
+/// Returns the number identifying the given artifact kind on the wire. The
+/// numbers must never change or be reused.
+pub fn artifact_tag_number(tag: ArtifactTag) -> u32 {
+    match tag {
+        ArtifactTag::ConsensusArtifact => 1,
+        ArtifactTag::IngressArtifact => 2,
+        ArtifactTag::CertificationArtifact => 3,
+        ArtifactTag::DkgArtifact => 4,
+        ArtifactTag::EcdsaArtifact => 5,
+        ArtifactTag::FileTreeSyncArtifact => 6,
+        ArtifactTag::StateSyncArtifact => 7,
+        ArtifactTag::EquivocationArtifact => 8,
+        ArtifactTag::RemoteDkgArtifact => 9,
+        ArtifactTag::CanisterHttpArtifact => 10,
+        ArtifactTag::QueryStatsArtifact => 11,
+        ArtifactTag::XNetStreamSliceArtifact => 12,
+        ArtifactTag::RegistryDeltaArtifact => 13,
+    }
+}
+
+/// Returns the artifact kind identified by the given number on the wire, see
+/// [`artifact_tag_number`].
+pub fn artifact_tag_from_number(number: u32) -> Result<ArtifactTag, ArtifactEncodingError> {
+    ArtifactTag::iter()
+        .find(|tag| artifact_tag_number(*tag) == number)
+        .ok_or(ArtifactEncodingError::UnknownTag(number))
+}
+
+/// An artifact encoded in a given encoding version.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ArtifactEnvelope {
+    pub version: ArtifactEncodingVersion,
+    /// The kind of the artifact.
+    pub tag: ArtifactTag,
+    /// The encoded artifact, without the `Artifact` variant.
+    pub encoded: Vec<u8>,
+}
+
+/// Errors encoding or decoding an [`ArtifactEnvelope`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ArtifactEncodingError {
+    /// The version is newer than the one of this replica.
+    UnsupportedVersion(ArtifactEncodingVersion),
+    /// The number does not identify an artifact kind known to this replica.
+    UnknownTag(u32),
+    /// The conversion of the artifact between two versions failed.
+    ConversionFailed {
+        tag: ArtifactTag,
+        version: ArtifactEncodingVersion,
+        reason: String,
+    },
+    /// The artifact could not be encoded or decoded.
+    InvalidArtifact(String),
+}
+
+impl fmt::Display for ArtifactEncodingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported artifact encoding version {}, the latest supported version is {}",
+                version, ARTIFACT_ENCODING_VERSION
+            ),
+            Self::UnknownTag(number) => write!(f, "unknown artifact tag {}", number),
+            Self::ConversionFailed {
+                tag,
+                version,
+                reason,
+            } => write!(
+                f,
+                "failed to convert {} artifact to or from encoding version {}: {}",
+                tag, version, reason
+            ),
+            Self::InvalidArtifact(reason) => write!(f, "invalid artifact: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ArtifactEncodingError {}
+
+/// Hooks converting the encoding of the artifacts of one kind between two
+/// consecutive encoding versions: `upgrade(v, ..)` converts an artifact
+/// encoded in version `v - 1` into version `v`, and `downgrade(v, ..)` converts
+/// an artifact encoded in version `v` into version `v - 1`.
+pub trait ArtifactEncodingConversion: Sync {
+    fn upgrade(
+        &self,
+        version: ArtifactEncodingVersion,
+        encoded: Vec<u8>,
+    ) -> Result<Vec<u8>, String>;
+
+    fn downgrade(
+        &self,
+        version: ArtifactEncodingVersion,
+        encoded: Vec<u8>,
+    ) -> Result<Vec<u8>, String>;
+}
+
+/// The conversion of the artifact kinds whose encoding did not change.
+struct Unchanged;
+
+impl ArtifactEncodingConversion for Unchanged {
+    fn upgrade(&self, _: ArtifactEncodingVersion, encoded: Vec<u8>) -> Result<Vec<u8>, String> {
+        Ok(encoded)
+    }
+
+    fn downgrade(&self, _: ArtifactEncodingVersion, encoded: Vec<u8>) -> Result<Vec<u8>, String> {
+        Ok(encoded)
+    }
+}
+
+/// Returns the conversion of the encoding of the artifacts with the given tag.
+fn conversion(_tag: ArtifactTag) -> &'static dyn ArtifactEncodingConversion {
+    // The encoding of no artifact kind changed since version 1.
+    &Unchanged
+}
+
+impl ArtifactEnvelope {
+    /// Encodes the given artifact in the given version.
+    pub fn encode(
+        artifact: &Artifact,
+        version: ArtifactEncodingVersion,
+    ) -> Result<Self, ArtifactEncodingError> {
+        let tag = ArtifactTag::from(artifact);
+        let envelope = Self {
+            version: ARTIFACT_ENCODING_VERSION,
+            tag,
+            encoded: encode_artifact(artifact)
+                .map_err(|err| ArtifactEncodingError::InvalidArtifact(err.to_string()))?,
+        };
+        envelope.convert(version, conversion(tag))
+    }
+
+    /// Decodes the artifact, converting it to the encoding version of this
+    /// replica first.
+    pub fn decode(self) -> Result<Artifact, ArtifactEncodingError> {
+        let tag = self.tag;
+        let envelope = self.convert(ARTIFACT_ENCODING_VERSION, conversion(tag))?;
+        decode_artifact(tag, &envelope.encoded)
+            .map_err(|err| ArtifactEncodingError::InvalidArtifact(err.to_string()))
+    }
+
+    /// Converts the artifact to the given version, one version at a time.
+    fn convert(
+        self,
+        version: ArtifactEncodingVersion,
+        conversion: &dyn ArtifactEncodingConversion,
+    ) -> Result<Self, ArtifactEncodingError> {
+        for v in [self.version, version].iter() {
+            if *v > ARTIFACT_ENCODING_VERSION {
+                return Err(ArtifactEncodingError::UnsupportedVersion(*v));
+            }
+        }
+        let tag = self.tag;
+        let failed = |version, reason| ArtifactEncodingError::ConversionFailed {
+            tag,
+            version,
+            reason,
+        };
+        let mut current = self.version;
+        let mut encoded = self.encoded;
+        while current < version {
+            current += 1;
+            encoded = conversion
+                .upgrade(current, encoded)
+                .map_err(|reason| failed(current, reason))?;
+        }
+        while current > version {
+            encoded = conversion
+                .downgrade(current, encoded)
+                .map_err(|reason| failed(current, reason))?;
+            current -= 1;
+        }
+        Ok(Self {
+            version,
+            tag,
+            encoded,
+        })
+    }
+}
+
+fn encode_artifact(artifact: &Artifact) -> bincode::Result<Vec<u8>> {
+    match artifact {
+        Artifact::ConsensusMessage(msg) => serialize(msg),
+        Artifact::IngressMessage(msg) => serialize(msg),
+        Artifact::CertificationMessage(msg) => serialize(msg),
+        Artifact::DkgMessage(msg) => serialize(msg),
+        Artifact::EcdsaMessage(msg) => serialize(msg),
+        Artifact::FileTreeSync(msg) => serialize(msg),
+        Artifact::StateSync(msg) => serialize(msg),
+        Artifact::EquivocationProof(msg) => serialize(msg),
+        Artifact::RemoteDkgMessage(msg) => serialize(msg),
+        Artifact::CanisterHttpMessage(msg) => serialize(msg),
+        Artifact::QueryStatsMessage(msg) => serialize(msg),
+        Artifact::XNetStreamSlice(msg) => serialize(msg),
+        Artifact::RegistryDelta(msg) => serialize(msg),
+    }
+}
+
+fn decode_artifact(tag: ArtifactTag, encoded: &[u8]) -> bincode::Result<Artifact> {
+    Ok(match tag {
+        ArtifactTag::ConsensusArtifact => Artifact::ConsensusMessage(deserialize(encoded)?),
+        ArtifactTag::IngressArtifact => Artifact::IngressMessage(deserialize(encoded)?),
+        ArtifactTag::CertificationArtifact => Artifact::CertificationMessage(deserialize(encoded)?),
+        ArtifactTag::DkgArtifact => Artifact::DkgMessage(deserialize(encoded)?),
+        ArtifactTag::EcdsaArtifact => Artifact::EcdsaMessage(deserialize(encoded)?),
+        ArtifactTag::FileTreeSyncArtifact => Artifact::FileTreeSync(deserialize(encoded)?),
+        ArtifactTag::StateSyncArtifact => Artifact::StateSync(deserialize(encoded)?),
+        ArtifactTag::EquivocationArtifact => Artifact::EquivocationProof(deserialize(encoded)?),
+        ArtifactTag::RemoteDkgArtifact => Artifact::RemoteDkgMessage(deserialize(encoded)?),
+        ArtifactTag::CanisterHttpArtifact => Artifact::CanisterHttpMessage(deserialize(encoded)?),
+        ArtifactTag::QueryStatsArtifact => Artifact::QueryStatsMessage(deserialize(encoded)?),
+        ArtifactTag::XNetStreamSliceArtifact => Artifact::XNetStreamSlice(deserialize(encoded)?),
+        ArtifactTag::RegistryDeltaArtifact => Artifact::RegistryDelta(deserialize(encoded)?),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        artifact::{RegistryDeltaId, RegistryDeltaMessage},
+        RegistryVersion,
+    };
+
+    fn artifact() -> Artifact {
+        Artifact::RegistryDelta(RegistryDeltaMessage {
+            id: RegistryDeltaId {
+                since_version: RegistryVersion::from(1),
+                version: RegistryVersion::from(2),
+            },
+            certified_response: vec![1, 2, 3],
+        })
+    }
+
+    /// Pretends that version 1 appended a zero byte to the encoding.
+    struct AppendZero;
+
+    impl ArtifactEncodingConversion for AppendZero {
+        fn upgrade(&self, version: u32, mut encoded: Vec<u8>) -> Result<Vec<u8>, String> {
+            assert_eq!(version, 1);
+            encoded.push(0);
+            Ok(encoded)
+        }
+
+        fn downgrade(&self, version: u32, mut encoded: Vec<u8>) -> Result<Vec<u8>, String> {
+            assert_eq!(version, 1);
+            match encoded.pop() {
+                Some(0) => Ok(encoded),
+                _ => Err("missing zero byte".to_string()),
+            }
+        }
+    }
+
+    #[test]
+    fn artifact_roundtrips_through_the_envelope() {
+        let envelope = ArtifactEnvelope::encode(&artifact(), ARTIFACT_ENCODING_VERSION).unwrap();
+        assert_eq!(envelope.version, ARTIFACT_ENCODING_VERSION);
+        assert_eq!(envelope.tag, ArtifactTag::RegistryDeltaArtifact);
+        assert_eq!(envelope.decode(), Ok(artifact()));
+    }
+
+    #[test]
+    fn artifacts_are_converted_between_versions() {
+        let envelope = ArtifactEnvelope {
+            version: 0,
+            tag: ArtifactTag::RegistryDeltaArtifact,
+            encoded: vec![7],
+        };
+        let upgraded = envelope.clone().convert(1, &AppendZero).unwrap();
+        assert_eq!(upgraded.version, 1);
+        assert_eq!(upgraded.encoded, vec![7, 0]);
+        assert_eq!(upgraded.convert(0, &AppendZero), Ok(envelope.clone()));
+
+        assert_eq!(
+            envelope.convert(0, &AppendZero).unwrap().encoded,
+            vec![7],
+            "converting to the same version is a no-op"
+        );
+    }
+
+    #[test]
+    fn failed_conversions_are_reported() {
+        let envelope = ArtifactEnvelope {
+            version: 1,
+            tag: ArtifactTag::RegistryDeltaArtifact,
+            encoded: vec![7],
+        };
+        assert_eq!(
+            envelope.convert(0, &AppendZero),
+            Err(ArtifactEncodingError::ConversionFailed {
+                tag: ArtifactTag::RegistryDeltaArtifact,
+                version: 1,
+                reason: "missing zero byte".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn artifact_tag_numbers_are_distinct_and_roundtrip() {
+        let numbers: std::collections::BTreeSet<_> =
+            ArtifactTag::iter().map(artifact_tag_number).collect();
+        assert_eq!(numbers.len(), ArtifactTag::iter().count());
+        for tag in ArtifactTag::iter() {
+            assert_eq!(artifact_tag_from_number(artifact_tag_number(tag)), Ok(tag));
+        }
+        assert_eq!(
+            artifact_tag_from_number(0),
+            Err(ArtifactEncodingError::UnknownTag(0))
+        );
+    }
+
+    #[test]
+    fn newer_versions_are_rejected() {
+        let newer = ARTIFACT_ENCODING_VERSION + 1;
+        assert_eq!(
+            ArtifactEnvelope::encode(&artifact(), newer),
+            Err(ArtifactEncodingError::UnsupportedVersion(newer))
+        );
+        let envelope = ArtifactEnvelope {
+            version: newer,
+            tag: ArtifactTag::RegistryDeltaArtifact,
+            encoded: vec![],
+        };
+        assert_eq!(
+            envelope.decode(),
+            Err(ArtifactEncodingError::UnsupportedVersion(newer))
+        );
+    }
+}
@@ -2,6 +2,10 @@
 //! Consensus and Message Routing.
 use super::{
     artifact::IngressMessageId,
+    consensus::{
+        canister_http::CanisterHttpResponseWithConsensus, equivocation::EquivocationProof,
+        query_stats::QueryStatsMessage,
+    },
     messages::{MessageId, Response, SignedIngress, EXPECTED_MESSAGE_ID_LENGTH},
     xnet::CertifiedStreamSlice,
     CountBytes, Height, Randomness, RegistryVersion, SubnetId, Time,
@@ -59,11 +63,20 @@ impl ValidationContext {
 
 /// The payload of a batch.
 ///
-/// Contains ingress and XNet messages.
+/// Contains ingress and XNet messages, the proofs of block makers that
+/// equivocated, the responses to HTTP outcalls that the subnet agreed on, and
+/// the reports of replicas on the queries they executed. Sections without a
+/// dedicated field are kept in `sections`.
 #[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct BatchPayload {
     pub ingress: IngressPayload,
     pub xnet: XNetPayload,
+    pub equivocation_proofs: Vec<EquivocationProof>,
+    pub canister_http: Vec<CanisterHttpResponseWithConsensus>,
+    pub query_stats: Vec<QueryStatsMessage>,
+    /// The other sections of the payload by name, each encoded by the payload
+    /// builder of the section.
+    pub sections: BTreeMap<String, Vec<u8>>,
 }
 
 /// Return ingress messages, xnet messages, and consensus responses.
@@ -71,7 +84,14 @@ pub type IngressAndXNetMessages = (Vec<SignedIngress>, BTreeMap<SubnetId, Certif
 
 impl BatchPayload {
     pub fn new(ingress: IngressPayload, xnet: XNetPayload) -> Self {
-        BatchPayload { ingress, xnet }
+        BatchPayload {
+            ingress,
+            xnet,
+            equivocation_proofs: Vec::new(),
+            canister_http: Vec::new(),
+            query_stats: Vec::new(),
+            sections: BTreeMap::new(),
+        }
     }
 
     /// Extract and return the set of ingress and xnet messages in a
@@ -82,7 +102,12 @@ impl BatchPayload {
     }
 
     pub fn is_empty(&self) -> bool {
-        self.ingress.is_empty() && self.xnet.stream_slices.is_empty()
+        self.ingress.is_empty()
+            && self.xnet.stream_slices.is_empty()
+            && self.equivocation_proofs.is_empty()
+            && self.canister_http.is_empty()
+            && self.query_stats.is_empty()
+            && self.sections.is_empty()
     }
 }
 
@@ -349,6 +374,7 @@ mod tests {
                 sender: Blob(vec![0x05]),
                 nonce: Some(Blob(vec![1, 2, 3, 4])),
                 ingress_expiry: ingress_expiry.as_nanos_since_unix_epoch(),
+                priority: None,
             },
         };
         let update_messages = vec![
@@ -13,12 +13,28 @@
 //! All variants of the Artifact should implement the [`Chunkable`]
 //! interface.
 //!
+//! Besides the unit chunk, every artifact can be served as multiple chunks of
+//! [`ARTIFACT_CHUNK_SIZE`] bytes of its encoded form in a given artifact
+//! encoding version, see `artifact_encoding`, so that artifacts larger than a
+//! single transport message, e.g. large blocks, can be gossiped. Whether an
+//! artifact is downloaded as multiple chunks is up to the `ArtifactKind` of
+//! the artifact, see [`MultiChunked`].
+//!
 //! Polymorphism is implemented as static dispatch over enumerated variants
 //! that implement a common trait.
 use crate::{
-    artifact::{Artifact, StateSyncMessage},
+    artifact::{
+        Artifact, ArtifactTag, RegistryDeltaMessage, StateSyncMessage, XNetStreamSliceMessage,
+    },
+    artifact_encoding::{
+        artifact_tag_from_number, artifact_tag_number, ArtifactEncodingError,
+        ArtifactEncodingVersion, ArtifactEnvelope, ARTIFACT_ENCODING_VERSION,
+        LEGACY_ARTIFACT_ENCODING_VERSION,
+    },
     consensus::{
-        certification::CertificationMessage, dkg::Message as DkgMessage, ConsensusMessage,
+        canister_http::CanisterHttpMessage, certification::CertificationMessage,
+        dkg::Message as DkgMessage, equivocation::EquivocationProof,
+        query_stats::QueryStatsMessage, remote_dkg::RemoteDkgMessage, ConsensusMessage,
     },
     crypto::CryptoHash,
     messages::SignedIngress,
@@ -27,6 +43,8 @@ use bincode::{deserialize, serialize};
 use ic_protobuf::p2p::v1 as pb;
 use ic_protobuf::proxy::ProxyDecodeError;
 use phantom_newtype::Id;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::convert::TryFrom;
 
 /// Error codes returned by the `Chunkable` interface.
@@ -38,17 +56,32 @@ pub enum ArtifactErrorCode {
 
 /// The chunk type.
 pub type ChunkId = Id<ArtifactChunk, u32>;
-const CHUNKID_UNIT_CHUNK: u32 = 0;
+pub const CHUNKID_UNIT_CHUNK: u32 = 0;
+
+/// The size in bytes of the chunks of an artifact that is transferred as
+/// multiple chunks. Only the last chunk of an artifact may be smaller.
+pub const ARTIFACT_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// The maximum number of chunks of an artifact that is transferred as multiple
+/// chunks. Peers keep the encoded artifacts they serve as multiple chunks in
+/// memory, so this bounds the memory a single advert can make them use.
+pub const MAX_ARTIFACT_CHUNKS: u32 = 64;
 
 /// The data contained in an artifact chunk.
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum ArtifactChunkData {
     UnitChunkData(Artifact), // Unit chunk data has 1:1 mapping with real artifacts
     SemiStructuredChunkData(Vec<u8>),
+    /// A chunk of an artifact encoded in the given artifact encoding version,
+    /// as downloaded by a [`MultiChunked`] tracker.
+    EncodedChunkData {
+        version: ArtifactEncodingVersion,
+        data: Vec<u8>,
+    },
 }
 
 /// An artifact chunk.
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct ArtifactChunk {
     // Chunk number/id for this chunk
     pub chunk_id: ChunkId,
@@ -91,6 +124,12 @@ pub enum SingleChunked {
     Certification,
     Dkg,
     Ecdsa,
+    EquivocationProof,
+    RemoteDkg,
+    CanisterHttp,
+    QueryStats,
+    XNetStreamSlice,
+    RegistryDelta,
 }
 
 /// Interface providing access to artifact chunks.
@@ -100,35 +139,95 @@ pub trait ChunkableArtifact {
     /// The chunk ID for single-chunked artifacts must be
     /// [`CHUNKID_UNIT_CHUNK`].
     fn get_chunk(self: Box<Self>, chunk_id: ChunkId) -> Option<ArtifactChunk>;
+
+    /// Retrieves the artifact chunk with the given ID for a requester that
+    /// understands the given artifact encoding version. The chunks of artifacts
+    /// downloaded as multiple chunks are encoded in that version; other chunks
+    /// don't depend on it.
+    fn get_versioned_chunk(
+        self: Box<Self>,
+        chunk_id: ChunkId,
+        _version: ArtifactEncodingVersion,
+    ) -> Option<ArtifactChunk> {
+        self.get_chunk(chunk_id)
+    }
+
+    /// Returns the artifact encoded in the given artifact encoding version,
+    /// from which all of its chunks other than the unit chunk can be cut with
+    /// [`multi_chunk`] without encoding the artifact again, or `None` if the
+    /// artifact is not served as multiple chunks of its encoded form.
+    fn encode(self: Box<Self>, _version: ArtifactEncodingVersion) -> Option<ArtifactEnvelope> {
+        None
+    }
+}
+
+/// Returns the chunk with the given ID of `artifact` encoded in the given
+/// version, as downloaded by a [`MultiChunked`] tracker.
+fn get_multi_chunk(
+    artifact: &Artifact,
+    chunk_id: ChunkId,
+    version: ArtifactEncodingVersion,
+) -> Option<ArtifactChunk> {
+    multi_chunk(&ArtifactEnvelope::encode(artifact, version).ok()?, chunk_id)
+}
+
+/// Returns the chunk with the given ID of the encoded artifact, as downloaded
+/// by a [`MultiChunked`] tracker, or `None` if there is no such chunk.
+pub fn multi_chunk(envelope: &ArtifactEnvelope, chunk_id: ChunkId) -> Option<ArtifactChunk> {
+    let index = chunk_id.get().checked_sub(1)? as usize;
+    let chunk = envelope.encoded.chunks(ARTIFACT_CHUNK_SIZE).nth(index)?;
+    Some(ArtifactChunk::new(
+        chunk_id,
+        ArtifactChunkData::EncodedChunkData {
+            version: envelope.version,
+            data: chunk.to_vec(),
+        },
+    ))
 }
 
 macro_rules! chunkable_artifact_impl {
-    ($id:path, |$self:ident| $v:expr) => {
+    ($id:path, $variant:path, |$self:ident| $v:expr) => {
         impl ChunkableArtifact for $id {
-            fn get_chunk($self: Box<Self>, chunk_id: ChunkId) -> Option<ArtifactChunk> {
-                if chunk_id != ChunkId::from(CHUNKID_UNIT_CHUNK) {
-                    // Single chunked in identified only chunk CHUNKID_UNIT_CHUNK
-                    None
+            fn get_chunk(self: Box<Self>, chunk_id: ChunkId) -> Option<ArtifactChunk> {
+                self.get_versioned_chunk(chunk_id, ARTIFACT_ENCODING_VERSION)
+            }
+
+            fn get_versioned_chunk(
+                $self: Box<Self>,
+                chunk_id: ChunkId,
+                version: ArtifactEncodingVersion,
+            ) -> Option<ArtifactChunk> {
+                let artifact = $variant($v);
+                if chunk_id == ChunkId::from(CHUNKID_UNIT_CHUNK) {
+                    Some(ArtifactChunk::new(
+                        chunk_id,
+                        ArtifactChunkData::UnitChunkData(artifact),
+                    ))
                 } else {
-                    Some(ArtifactChunk::new(chunk_id, $v))
+                    get_multi_chunk(&artifact, chunk_id, version)
                 }
             }
+
+            fn encode(
+                $self: Box<Self>,
+                version: ArtifactEncodingVersion,
+            ) -> Option<ArtifactEnvelope> {
+                ArtifactEnvelope::encode(&$variant($v), version).ok()
+            }
         }
     };
 }
 
-chunkable_artifact_impl! {ConsensusMessage, |self|
-    ArtifactChunkData::UnitChunkData(Artifact::ConsensusMessage(*self))
-}
-chunkable_artifact_impl! {SignedIngress, |self|
-    ArtifactChunkData::UnitChunkData(Artifact::IngressMessage((*self).into()))
-}
-chunkable_artifact_impl! {CertificationMessage, |self|
-    ArtifactChunkData::UnitChunkData(Artifact::CertificationMessage(*self))
-}
-chunkable_artifact_impl! {DkgMessage, |self|
-    ArtifactChunkData::UnitChunkData(Artifact::DkgMessage(*self))
-}
+chunkable_artifact_impl! {ConsensusMessage, Artifact::ConsensusMessage, |self| *self}
+chunkable_artifact_impl! {SignedIngress, Artifact::IngressMessage, |self| (*self).into()}
+chunkable_artifact_impl! {CertificationMessage, Artifact::CertificationMessage, |self| *self}
+chunkable_artifact_impl! {DkgMessage, Artifact::DkgMessage, |self| *self}
+chunkable_artifact_impl! {EquivocationProof, Artifact::EquivocationProof, |self| *self}
+chunkable_artifact_impl! {RemoteDkgMessage, Artifact::RemoteDkgMessage, |self| *self}
+chunkable_artifact_impl! {CanisterHttpMessage, Artifact::CanisterHttpMessage, |self| *self}
+chunkable_artifact_impl! {QueryStatsMessage, Artifact::QueryStatsMessage, |self| *self}
+chunkable_artifact_impl! {XNetStreamSliceMessage, Artifact::XNetStreamSlice, |self| *self}
+chunkable_artifact_impl! {RegistryDeltaMessage, Artifact::RegistryDelta, |self| *self}
 
 impl ChunkableArtifact for StateSyncMessage {
     fn get_chunk(self: Box<Self>, chunk_id: ChunkId) -> Option<ArtifactChunk> {
@@ -166,6 +265,13 @@ pub trait Chunkable {
     fn add_chunk(&mut self, artifact_chunk: ArtifactChunk) -> Result<Artifact, ArtifactErrorCode>;
     fn is_complete(&self) -> bool;
     fn get_chunk_size(&self, chunk_id: ChunkId) -> usize;
+
+    /// Makes the tracker download the artifact as its unit chunk only, for
+    /// peers that don't serve the other chunks, e.g. replicas running an
+    /// older version. Returns whether the tracker supports this.
+    fn fall_back_to_unit_chunk(&mut self) -> bool {
+        false
+    }
 }
 
 // Basic chunking impl for [`SingleChunked`] object tracking
@@ -199,24 +305,178 @@ impl Chunkable for SingleChunked {
     }
 }
 
-impl From<ArtifactChunk> for pb::ArtifactChunk {
-    fn from(chunk: ArtifactChunk) -> Self {
-        let data: pb::artifact_chunk::Data = match chunk.artifact_chunk_data {
-            ArtifactChunkData::UnitChunkData(artifact) => {
+/// Chunk tracker for an artifact that is downloaded as multiple chunks of
+/// [`ARTIFACT_CHUNK_SIZE`] bytes of the encoded artifact of its
+/// [`ArtifactEnvelope`], as served by [`ChunkableArtifact::get_versioned_chunk`].
+/// The chunks have the IDs 1 up to the number of chunks, as 0 is the ID of the
+/// unit chunk.
+///
+/// All chunks must be encoded in the same version, which is the version the
+/// artifact is up-converted from once it is complete. The advertised size is
+/// the size of the artifact in the encoding version of the advertiser, so
+/// chunks served by peers that encode the artifact differently don't match it
+/// and are downloaded again.
+///
+/// Peers that don't serve the chunks other than the unit chunk make the tracker
+/// fall back to downloading the unit chunk only, see
+/// [`Chunkable::fall_back_to_unit_chunk`].
+pub struct MultiChunked {
+    /// The kind of the artifact.
+    tag: ArtifactTag,
+    /// The size of the encoded artifact, as advertised.
+    size: usize,
+    /// Whether the artifact is downloaded as its unit chunk only.
+    unit_chunk_only: bool,
+    /// The encoding version of the chunks added so far.
+    version: Option<ArtifactEncodingVersion>,
+    chunks: BTreeMap<ChunkId, Vec<u8>>,
+}
+
+impl MultiChunked {
+    /// Returns the tracker of an artifact of the given kind and size, or
+    /// `None` if the artifact would have more than [`MAX_ARTIFACT_CHUNKS`]
+    /// chunks.
+    pub fn new(tag: ArtifactTag, size: usize) -> Option<Self> {
+        let tracker = Self {
+            tag,
+            size,
+            unit_chunk_only: false,
+            version: None,
+            chunks: BTreeMap::new(),
+        };
+        if size <= MAX_ARTIFACT_CHUNKS as usize * ARTIFACT_CHUNK_SIZE {
+            Some(tracker)
+        } else {
+            None
+        }
+    }
+
+    fn chunk_count(&self) -> u32 {
+        let partial_chunk = self.size % ARTIFACT_CHUNK_SIZE != 0;
+        (self.size / ARTIFACT_CHUNK_SIZE + partial_chunk as usize) as u32
+    }
+
+    fn chunk_ids(&self) -> impl Iterator<Item = ChunkId> {
+        (1..=self.chunk_count()).map(ChunkId::from)
+    }
+}
+
+impl Chunkable for MultiChunked {
+    fn get_artifact_hash(&self) -> CryptoHash {
+        unimplemented!("")
+    }
+
+    fn chunks_to_download(&self) -> Box<dyn Iterator<Item = ChunkId>> {
+        if self.unit_chunk_only {
+            return Box::new(std::iter::once(ChunkId::from(CHUNKID_UNIT_CHUNK)));
+        }
+        let missing: Vec<ChunkId> = self
+            .chunk_ids()
+            .filter(|chunk_id| !self.chunks.contains_key(chunk_id))
+            .collect();
+        Box::new(missing.into_iter())
+    }
+
+    fn get_artifact_identifier(&self) -> CryptoHash {
+        unimplemented!("")
+    }
+
+    fn add_chunk(&mut self, artifact_chunk: ArtifactChunk) -> Result<Artifact, ArtifactErrorCode> {
+        let chunk_id = artifact_chunk.chunk_id;
+        match artifact_chunk.artifact_chunk_data {
+            ArtifactChunkData::UnitChunkData(artifact)
+                if self.unit_chunk_only && ArtifactTag::from(&artifact) == self.tag =>
+            {
+                return Ok(artifact);
+            }
+            ArtifactChunkData::EncodedChunkData { version, data }
+                if chunk_id.get() >= 1
+                    && chunk_id.get() <= self.chunk_count()
+                    && data.len() == self.get_chunk_size(chunk_id)
+                    && self.version.map_or(true, |v| v == version) =>
+            {
+                self.version = Some(version);
+                self.chunks.insert(chunk_id, data);
+            }
+            _ => return Err(ArtifactErrorCode::ChunkVerificationFailed),
+        }
+        if !self.is_complete() {
+            return Err(ArtifactErrorCode::ChunksMoreNeeded);
+        }
+        let envelope = ArtifactEnvelope {
+            version: self.version.unwrap_or(ARTIFACT_ENCODING_VERSION),
+            tag: self.tag,
+            encoded: self.chunks.values().flatten().copied().collect(),
+        };
+        match envelope.decode() {
+            Ok(artifact) => Ok(artifact),
+            Err(_) => {
+                // One of the chunks is corrupted, but there is no way to tell
+                // which one, so all of them are downloaded again.
+                self.chunks.clear();
+                self.version = None;
+                Err(ArtifactErrorCode::ChunkVerificationFailed)
+            }
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        !self.unit_chunk_only && self.chunks.len() == self.chunk_count() as usize
+    }
+
+    fn get_chunk_size(&self, chunk_id: ChunkId) -> usize {
+        if self.unit_chunk_only {
+            return self.size;
+        }
+        let offset = (chunk_id.get() as usize).saturating_sub(1) * ARTIFACT_CHUNK_SIZE;
+        self.size.saturating_sub(offset).min(ARTIFACT_CHUNK_SIZE)
+    }
+
+    fn fall_back_to_unit_chunk(&mut self) -> bool {
+        self.unit_chunk_only = true;
+        self.chunks.clear();
+        self.version = None;
+        true
+    }
+}
+
+impl ArtifactChunk {
+    /// Converts the chunk into its protobuf definition. The artifact of a unit
+    /// chunk is encoded in the given artifact encoding version, see
+    /// `artifact_encoding`.
+    pub fn into_protobuf(
+        self,
+        version: ArtifactEncodingVersion,
+    ) -> Result<pb::ArtifactChunk, ArtifactEncodingError> {
+        let data: pb::artifact_chunk::Data = match self.artifact_chunk_data {
+            ArtifactChunkData::UnitChunkData(artifact)
+                if version == LEGACY_ARTIFACT_ENCODING_VERSION =>
+            {
                 pb::artifact_chunk::Data::Artifact(serialize(&artifact).unwrap())
             }
+            ArtifactChunkData::UnitChunkData(artifact) => {
+                let envelope = ArtifactEnvelope::encode(&artifact, version)?;
+                pb::artifact_chunk::Data::Envelope(pb::ArtifactEnvelope {
+                    version: envelope.version,
+                    tag: artifact_tag_number(envelope.tag),
+                    artifact: envelope.encoded,
+                })
+            }
             ArtifactChunkData::SemiStructuredChunkData(chunk_data) => {
                 pb::artifact_chunk::Data::Chunk(chunk_data)
             }
+            ArtifactChunkData::EncodedChunkData { version, data } => {
+                pb::artifact_chunk::Data::EncodedChunk(pb::EncodedChunk { version, data })
+            }
         };
-        Self {
-            witnesses: chunk
+        Ok(pb::ArtifactChunk {
+            witnesses: self
                 .witness
                 .iter()
                 .map(|w| serialize(&w).unwrap())
                 .collect(),
             data: Some(data),
-        }
+        })
     }
 }
 
@@ -244,6 +504,23 @@ impl TryFrom<pb::ArtifactChunk> for ArtifactChunk {
                     ArtifactChunkData::UnitChunkData(deserialize(&a)?)
                 }
                 pb::artifact_chunk::Data::Chunk(d) => ArtifactChunkData::SemiStructuredChunkData(d),
+                pb::artifact_chunk::Data::Envelope(e) => {
+                    let envelope = ArtifactEnvelope {
+                        version: e.version,
+                        tag: artifact_tag_from_number(e.tag)
+                            .map_err(|err| ProxyDecodeError::Other(err.to_string()))?,
+                        encoded: e.artifact,
+                    };
+                    ArtifactChunkData::UnitChunkData(
+                        envelope
+                            .decode()
+                            .map_err(|err| ProxyDecodeError::Other(err.to_string()))?,
+                    )
+                }
+                pb::artifact_chunk::Data::EncodedChunk(c) => ArtifactChunkData::EncodedChunkData {
+                    version: c.version,
+                    data: c.data,
+                },
             },
         };
         Ok(Self {
@@ -256,3 +533,224 @@ impl TryFrom<pb::ArtifactChunk> for ArtifactChunk {
 }
 
 // -----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{artifact::RegistryDeltaId, RegistryVersion};
+
+    fn registry_delta(size: usize) -> RegistryDeltaMessage {
+        RegistryDeltaMessage {
+            id: RegistryDeltaId {
+                since_version: RegistryVersion::from(1),
+                version: RegistryVersion::from(2),
+            },
+            certified_response: (0..size).map(|i| i as u8).collect(),
+        }
+    }
+
+    #[test]
+    fn artifact_is_downloaded_as_multiple_chunks() {
+        let message = registry_delta(2 * ARTIFACT_CHUNK_SIZE + 42);
+        let size = serialize(&message).unwrap().len();
+        let mut tracker = MultiChunked::new(ArtifactTag::RegistryDeltaArtifact, size).unwrap();
+        let chunk_ids: Vec<_> = tracker.chunks_to_download().collect();
+        assert_eq!(chunk_ids, (1..=3).map(ChunkId::from).collect::<Vec<_>>());
+
+        let mut result = None;
+        for chunk_id in chunk_ids.into_iter().rev() {
+            assert!(result.is_none());
+            let chunk = Box::new(message.clone()).get_chunk(chunk_id).unwrap();
+            result = match tracker.add_chunk(chunk) {
+                Ok(artifact) => Some(artifact),
+                Err(ArtifactErrorCode::ChunksMoreNeeded) => None,
+                Err(err) => panic!("unexpected error: {:?}", err),
+            };
+        }
+        assert_eq!(result, Some(Artifact::RegistryDelta(message)));
+        assert!(tracker.is_complete());
+        assert_eq!(tracker.chunks_to_download().count(), 0);
+    }
+
+    #[test]
+    fn the_unit_chunk_is_still_served() {
+        let message = registry_delta(10);
+        let chunk = Box::new(message.clone())
+            .get_chunk(ChunkId::from(CHUNKID_UNIT_CHUNK))
+            .unwrap();
+        assert_eq!(
+            chunk.artifact_chunk_data,
+            ArtifactChunkData::UnitChunkData(Artifact::RegistryDelta(message.clone()))
+        );
+        assert_eq!(Box::new(message).get_chunk(ChunkId::from(2)), None);
+    }
+
+    #[test]
+    fn unit_chunks_are_sent_in_the_encoding_version_of_the_requester() {
+        let artifact = Artifact::RegistryDelta(registry_delta(10));
+        let chunk = ArtifactChunk::new(
+            ChunkId::from(CHUNKID_UNIT_CHUNK),
+            ArtifactChunkData::UnitChunkData(artifact),
+        );
+
+        let legacy = chunk
+            .clone()
+            .into_protobuf(LEGACY_ARTIFACT_ENCODING_VERSION)
+            .unwrap();
+        assert!(matches!(
+            legacy.data,
+            Some(pb::artifact_chunk::Data::Artifact(_))
+        ));
+        assert_eq!(ArtifactChunk::try_from(legacy).unwrap(), chunk);
+
+        let versioned = chunk
+            .clone()
+            .into_protobuf(ARTIFACT_ENCODING_VERSION)
+            .unwrap();
+        match &versioned.data {
+            Some(pb::artifact_chunk::Data::Envelope(envelope)) => {
+                assert_eq!(envelope.version, ARTIFACT_ENCODING_VERSION)
+            }
+            data => panic!("expected an envelope, got {:?}", data),
+        }
+        assert_eq!(ArtifactChunk::try_from(versioned).unwrap(), chunk);
+    }
+
+    #[test]
+    fn chunks_of_the_wrong_size_are_rejected() {
+        let mut tracker =
+            MultiChunked::new(ArtifactTag::RegistryDeltaArtifact, ARTIFACT_CHUNK_SIZE + 1).unwrap();
+        let chunk = ArtifactChunk::new(
+            ChunkId::from(2),
+            ArtifactChunkData::EncodedChunkData {
+                version: ARTIFACT_ENCODING_VERSION,
+                data: vec![0; 2],
+            },
+        );
+        assert_eq!(
+            tracker.add_chunk(chunk),
+            Err(ArtifactErrorCode::ChunkVerificationFailed)
+        );
+        assert_eq!(tracker.chunks_to_download().count(), 2);
+    }
+
+    #[test]
+    fn corrupted_artifacts_are_downloaded_again() {
+        let mut tracker =
+            MultiChunked::new(ArtifactTag::RegistryDeltaArtifact, ARTIFACT_CHUNK_SIZE + 1).unwrap();
+        for chunk_id in 1..=2 {
+            let chunk_id = ChunkId::from(chunk_id);
+            let data = vec![0xff; tracker.get_chunk_size(chunk_id)];
+            let chunk = ArtifactChunk::new(
+                chunk_id,
+                ArtifactChunkData::EncodedChunkData {
+                    version: ARTIFACT_ENCODING_VERSION,
+                    data,
+                },
+            );
+            let _ = tracker.add_chunk(chunk);
+        }
+        assert!(!tracker.is_complete());
+        assert_eq!(tracker.chunks_to_download().count(), 2);
+    }
+
+    #[test]
+    fn chunks_encoded_in_different_versions_are_rejected() {
+        let message = registry_delta(ARTIFACT_CHUNK_SIZE + 42);
+        let size = serialize(&message).unwrap().len();
+        let mut tracker = MultiChunked::new(ArtifactTag::RegistryDeltaArtifact, size).unwrap();
+
+        let chunk = Box::new(message.clone())
+            .get_versioned_chunk(ChunkId::from(1), ARTIFACT_ENCODING_VERSION)
+            .unwrap();
+        assert_eq!(
+            tracker.add_chunk(chunk),
+            Err(ArtifactErrorCode::ChunksMoreNeeded)
+        );
+
+        let mut chunk = Box::new(message)
+            .get_versioned_chunk(ChunkId::from(2), ARTIFACT_ENCODING_VERSION)
+            .unwrap();
+        if let ArtifactChunkData::EncodedChunkData { version, .. } = &mut chunk.artifact_chunk_data
+        {
+            *version = LEGACY_ARTIFACT_ENCODING_VERSION;
+        }
+        assert_eq!(
+            tracker.add_chunk(chunk),
+            Err(ArtifactErrorCode::ChunkVerificationFailed)
+        );
+        assert_eq!(tracker.chunks_to_download().count(), 1);
+    }
+
+    #[test]
+    fn multi_chunks_roundtrip_through_protobuf() {
+        let chunk = Box::new(registry_delta(10))
+            .get_versioned_chunk(ChunkId::from(1), ARTIFACT_ENCODING_VERSION)
+            .unwrap();
+        let decoded = ArtifactChunk::try_from(
+            chunk
+                .clone()
+                .into_protobuf(ARTIFACT_ENCODING_VERSION)
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(decoded.artifact_chunk_data, chunk.artifact_chunk_data);
+    }
+
+    #[test]
+    fn artifacts_with_too_many_chunks_are_not_tracked() {
+        let max_size = MAX_ARTIFACT_CHUNKS as usize * ARTIFACT_CHUNK_SIZE;
+        assert!(MultiChunked::new(ArtifactTag::RegistryDeltaArtifact, max_size).is_some());
+        assert!(MultiChunked::new(ArtifactTag::RegistryDeltaArtifact, max_size + 1).is_none());
+    }
+
+    #[test]
+    fn chunks_are_cut_from_the_encoded_artifact() {
+        let message = registry_delta(ARTIFACT_CHUNK_SIZE + 42);
+        let envelope = Box::new(message.clone())
+            .encode(ARTIFACT_ENCODING_VERSION)
+            .unwrap();
+        for chunk_id in 1..=3 {
+            let chunk_id = ChunkId::from(chunk_id);
+            assert_eq!(
+                multi_chunk(&envelope, chunk_id),
+                Box::new(message.clone()).get_versioned_chunk(chunk_id, ARTIFACT_ENCODING_VERSION)
+            );
+        }
+        assert_eq!(
+            multi_chunk(&envelope, ChunkId::from(CHUNKID_UNIT_CHUNK)),
+            None
+        );
+    }
+
+    #[test]
+    fn tracker_falls_back_to_the_unit_chunk() {
+        let message = registry_delta(ARTIFACT_CHUNK_SIZE + 42);
+        let size = serialize(&message).unwrap().len();
+        let mut tracker = MultiChunked::new(ArtifactTag::RegistryDeltaArtifact, size).unwrap();
+        let chunk = Box::new(message.clone())
+            .get_chunk(ChunkId::from(1))
+            .unwrap();
+        assert_eq!(
+            tracker.add_chunk(chunk),
+            Err(ArtifactErrorCode::ChunksMoreNeeded)
+        );
+
+        assert!(tracker.fall_back_to_unit_chunk());
+        assert_eq!(
+            tracker.chunks_to_download().collect::<Vec<_>>(),
+            vec![ChunkId::from(CHUNKID_UNIT_CHUNK)]
+        );
+        assert_eq!(
+            tracker.get_chunk_size(ChunkId::from(CHUNKID_UNIT_CHUNK)),
+            size
+        );
+        let chunk = Box::new(message.clone())
+            .get_chunk(ChunkId::from(CHUNKID_UNIT_CHUNK))
+            .unwrap();
+        assert_eq!(
+            tracker.add_chunk(chunk),
+            Ok(Artifact::RegistryDelta(message))
+        );
+    }
+}
@@ -10,15 +10,21 @@ use ic_protobuf::log::block_log_entry::v1::BlockLogEntry;
 use ic_protobuf::types::v1 as pb;
 use serde::{Deserialize, Serialize};
 use std::cmp::PartialOrd;
+use std::collections::BTreeMap;
 use std::convert::TryInto;
 use std::hash::Hash;
 
+pub mod canister_http;
 pub mod catchup;
 pub mod certification;
 pub mod dkg;
 pub mod ecdsa;
+pub mod equivocation;
 pub mod hashed;
 mod payload;
+pub mod query_stats;
+pub mod remote_dkg;
+pub mod round_timeline;
 pub mod thunk;
 
 pub use catchup::*;
@@ -1220,14 +1226,46 @@ pub fn get_faults_tolerated(n: usize) -> usize {
 impl From<&Block> for pb::Block {
     fn from(block: &Block) -> Self {
         let payload: &BlockPayload = block.payload.as_ref();
-        let (dkg_payload, xnet_payload, ingress_payload) = if payload.is_summary() {
-            (pb::DkgPayload::from(payload.as_summary()), None, None)
+        let (
+            dkg_payload,
+            xnet_payload,
+            ingress_payload,
+            equivocation_proofs,
+            canister_http,
+            query_stats,
+            sections,
+        ) = if payload.is_summary() {
+            (
+                pb::DkgPayload::from(payload.as_summary()),
+                None,
+                None,
+                vec![],
+                vec![],
+                vec![],
+                BTreeMap::new(),
+            )
         } else {
             let batch = payload.as_batch_payload();
             (
                 pb::DkgPayload::from(payload.as_dealings()),
                 Some(pb::XNetPayload::from(&batch.xnet)),
                 Some(pb::IngressPayload::from(&batch.ingress)),
+                batch
+                    .equivocation_proofs
+                    .iter()
+                    .map(pb::EquivocationProof::from)
+                    .collect(),
+                batch
+                    .canister_http
+                    .iter()
+                    .map(pb::CanisterHttpResponseWithConsensus::from)
+                    .collect(),
+                batch
+                    .query_stats
+                    .iter()
+                    .map(pb::QueryStatsMessage::from)
+                    .collect(),
+                batch.sections.clone(),
             )
         };
         Self {
@@ -1242,6 +1280,10 @@ impl From<&Block> for pb::Block {
             xnet_payload,
             ingress_payload,
             payload_hash: block.payload.get_hash().clone().get().0,
+            equivocation_proofs,
+            canister_http,
+            query_stats,
+            sections,
         }
     }
 }
@@ -1254,7 +1296,7 @@ impl TryFrom<pb::Block> for Block {
                 .dkg_payload
                 .ok_or_else(|| String::from("Error: Block missing dkg_payload"))?,
         )?;
-        let batch = BatchPayload::new(
+        let mut batch = BatchPayload::new(
             block
                 .ingress_payload
                 .map(crate::batch::IngressPayload::try_from)
@@ -1266,6 +1308,22 @@ impl TryFrom<pb::Block> for Block {
                 .transpose()?
                 .unwrap_or_default(),
         );
+        batch.equivocation_proofs = block
+            .equivocation_proofs
+            .into_iter()
+            .map(equivocation::EquivocationProof::try_from)
+            .collect::<Result<_, _>>()?;
+        batch.canister_http = block
+            .canister_http
+            .into_iter()
+            .map(canister_http::CanisterHttpResponseWithConsensus::try_from)
+            .collect::<Result<_, _>>()?;
+        batch.query_stats = block
+            .query_stats
+            .into_iter()
+            .map(query_stats::QueryStatsMessage::try_from)
+            .collect::<Result<_, _>>()?;
+        batch.sections = block.sections;
         let payload = match dkg_payload {
             dkg::Payload::Summary(summary) => {
                 assert!(
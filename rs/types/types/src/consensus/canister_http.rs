@@ -0,0 +1,269 @@
+//! Defines the responses of the HTTP outcalls of canisters, and the shares
+//! that replicas sign over them to agree on their content.
+//!
+//! A response is only included in a block, and hence delivered to the
+//! canister, once enough replicas of the subnet signed the same metadata,
+//! i.e. saw the same content for the same request.
+use crate::{
+    consensus::{BasicSignature, BasicSigned},
+    crypto::{BasicSig, BasicSigOf, CryptoHash, CryptoHashOf, SignedBytesWithoutDomainSeparator},
+    messages::{CallbackId, Payload, RejectContext, Response},
+    time::Time,
+    CanisterId, CountBytes, Cycles, NodeId, PrincipalId, RegistryVersion,
+};
+use ic_error_types::RejectCode;
+use ic_protobuf::types::v1 as pb;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+
+/// Identifies the request of a response. Callback ids are only unique within
+/// the call contexts of a single canister.
+pub type CanisterHttpRequestId = (CanisterId, CallbackId);
+
+/// The content of the response to an HTTP outcall.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum CanisterHttpResponseContent {
+    /// The body of the response of the remote server.
+    Success(Vec<u8>),
+    /// The reason why the request failed, e.g. because the server could not
+    /// be reached.
+    Reject(String),
+}
+
+/// The response to an HTTP outcall, as seen by a single replica.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct CanisterHttpResponse {
+    /// The canister that made the request.
+    pub canister_id: CanisterId,
+    /// The callback of the request in the call context of the canister.
+    pub id: CallbackId,
+    /// The time after which the request times out.
+    pub timeout: Time,
+    /// The content of the response.
+    pub content: CanisterHttpResponseContent,
+}
+
+/// The metadata of a [CanisterHttpResponse] that replicas sign, so that
+/// shares of different replicas can be compared without their content.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct CanisterHttpResponseMetadata {
+    /// The canister that made the request.
+    pub canister_id: CanisterId,
+    /// The callback of the request.
+    pub id: CallbackId,
+    /// The time after which the request times out.
+    pub timeout: Time,
+    /// The hash of the response.
+    pub content_hash: CryptoHashOf<CanisterHttpResponse>,
+    /// The registry version used to verify the signature of the share.
+    pub registry_version: RegistryVersion,
+}
+
+impl CanisterHttpResponse {
+    /// The request the response belongs to.
+    pub fn request_id(&self) -> CanisterHttpRequestId {
+        (self.canister_id, self.id)
+    }
+
+    /// The response delivered to the canister. HTTP outcalls are calls to
+    /// the management canister, so it is the respondent.
+    pub fn to_response(&self) -> Response {
+        Response {
+            originator: self.canister_id,
+            respondent: CanisterId::ic_00(),
+            originator_reply_callback: self.id,
+            refund: Cycles::zero(),
+            response_payload: match &self.content {
+                CanisterHttpResponseContent::Success(body) => Payload::Data(body.clone()),
+                CanisterHttpResponseContent::Reject(reason) => {
+                    Payload::Reject(RejectContext::new(RejectCode::SysTransient, reason.clone()))
+                }
+            },
+        }
+    }
+}
+
+impl CountBytes for CanisterHttpResponse {
+    fn count_bytes(&self) -> usize {
+        let content_bytes = match &self.content {
+            CanisterHttpResponseContent::Success(body) => body.len(),
+            CanisterHttpResponseContent::Reject(reason) => reason.len(),
+        };
+        std::mem::size_of::<Self>() + content_bytes
+    }
+}
+
+impl CanisterHttpResponseMetadata {
+    /// The request the metadata belongs to.
+    pub fn request_id(&self) -> CanisterHttpRequestId {
+        (self.canister_id, self.id)
+    }
+}
+
+impl SignedBytesWithoutDomainSeparator for CanisterHttpResponseMetadata {
+    fn as_signed_bytes_without_domain_separator(&self) -> Vec<u8> {
+        serde_cbor::to_vec(&self).unwrap()
+    }
+}
+
+/// The signature of a replica on the metadata of a response it received.
+pub type CanisterHttpResponseShare = BasicSigned<CanisterHttpResponseMetadata>;
+
+/// A response together with the shares of the replicas that agree on it,
+/// which is what is included in a block.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct CanisterHttpResponseWithConsensus {
+    /// The response.
+    pub content: CanisterHttpResponse,
+    /// The shares of distinct replicas over the metadata of the response.
+    pub proof: Vec<CanisterHttpResponseShare>,
+}
+
+impl CountBytes for CanisterHttpResponseWithConsensus {
+    fn count_bytes(&self) -> usize {
+        self.content.count_bytes()
+            + self
+                .proof
+                .iter()
+                .map(|share| {
+                    std::mem::size_of::<CanisterHttpResponseShare>()
+                        + share.signature.signature.get_ref().0.len()
+                })
+                .sum::<usize>()
+    }
+}
+
+/// The messages of the HTTP outcalls that are gossiped between replicas.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum CanisterHttpMessage {
+    /// A response received by one of the replicas.
+    Response(CanisterHttpResponse),
+    /// The share of a replica over the metadata of a response.
+    Share(CanisterHttpResponseShare),
+}
+
+impl CanisterHttpMessage {
+    /// The request the message belongs to.
+    pub fn request_id(&self) -> CanisterHttpRequestId {
+        match self {
+            CanisterHttpMessage::Response(response) => response.request_id(),
+            CanisterHttpMessage::Share(share) => share.content.request_id(),
+        }
+    }
+
+    /// The time after which the request the message belongs to times out.
+    pub fn timeout(&self) -> Time {
+        match self {
+            CanisterHttpMessage::Response(response) => response.timeout,
+            CanisterHttpMessage::Share(share) => share.content.timeout,
+        }
+    }
+}
+
+fn try_from_canister_id(canister_id: Option<pb::CanisterId>) -> Result<CanisterId, String> {
+    CanisterId::try_from(
+        canister_id.ok_or_else(|| "Canister http message without canister id".to_string())?,
+    )
+    .map_err(|err| format!("Couldn't parse canister id: {:?}", err))
+}
+
+impl From<&CanisterHttpResponse> for pb::CanisterHttpResponse {
+    fn from(response: &CanisterHttpResponse) -> Self {
+        use pb::canister_http_response::Content;
+        Self {
+            canister_id: Some(pb::CanisterId::from(response.canister_id)),
+            id: response.id.get(),
+            timeout: response.timeout.as_nanos_since_unix_epoch(),
+            content: Some(match &response.content {
+                CanisterHttpResponseContent::Success(body) => Content::Success(body.clone()),
+                CanisterHttpResponseContent::Reject(reason) => Content::Reject(reason.clone()),
+            }),
+        }
+    }
+}
+
+impl TryFrom<pb::CanisterHttpResponse> for CanisterHttpResponse {
+    type Error = String;
+    fn try_from(response: pb::CanisterHttpResponse) -> Result<Self, Self::Error> {
+        use pb::canister_http_response::Content;
+        Ok(Self {
+            canister_id: try_from_canister_id(response.canister_id)?,
+            id: CallbackId::from(response.id),
+            timeout: Time::from_nanos_since_unix_epoch(response.timeout),
+            content: match response
+                .content
+                .ok_or_else(|| "Canister http response without content".to_string())?
+            {
+                Content::Success(body) => CanisterHttpResponseContent::Success(body),
+                Content::Reject(reason) => CanisterHttpResponseContent::Reject(reason),
+            },
+        })
+    }
+}
+
+impl From<&CanisterHttpResponseShare> for pb::CanisterHttpResponseShare {
+    fn from(share: &CanisterHttpResponseShare) -> Self {
+        Self {
+            canister_id: Some(pb::CanisterId::from(share.content.canister_id)),
+            id: share.content.id.get(),
+            timeout: share.content.timeout.as_nanos_since_unix_epoch(),
+            content_hash: share.content.content_hash.clone().get().0,
+            registry_version: share.content.registry_version.get(),
+            signature: share.signature.signature.clone().get().0,
+            signer: share.signature.signer.get().into_vec(),
+        }
+    }
+}
+
+impl TryFrom<pb::CanisterHttpResponseShare> for CanisterHttpResponseShare {
+    type Error = String;
+    fn try_from(share: pb::CanisterHttpResponseShare) -> Result<Self, Self::Error> {
+        Ok(Self {
+            content: CanisterHttpResponseMetadata {
+                canister_id: try_from_canister_id(share.canister_id)?,
+                id: CallbackId::from(share.id),
+                timeout: Time::from_nanos_since_unix_epoch(share.timeout),
+                content_hash: CryptoHashOf::from(CryptoHash(share.content_hash)),
+                registry_version: RegistryVersion::from(share.registry_version),
+            },
+            signature: BasicSignature {
+                signature: BasicSigOf::from(BasicSig(share.signature)),
+                signer: NodeId::from(
+                    PrincipalId::try_from(share.signer)
+                        .map_err(|err| format!("Couldn't parse signer: {:?}", err))?,
+                ),
+            },
+        })
+    }
+}
+
+impl From<&CanisterHttpResponseWithConsensus> for pb::CanisterHttpResponseWithConsensus {
+    fn from(response: &CanisterHttpResponseWithConsensus) -> Self {
+        Self {
+            response: Some((&response.content).into()),
+            proof: response
+                .proof
+                .iter()
+                .map(pb::CanisterHttpResponseShare::from)
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<pb::CanisterHttpResponseWithConsensus> for CanisterHttpResponseWithConsensus {
+    type Error = String;
+    fn try_from(response: pb::CanisterHttpResponseWithConsensus) -> Result<Self, Self::Error> {
+        Ok(Self {
+            content: CanisterHttpResponse::try_from(
+                response
+                    .response
+                    .ok_or_else(|| "Canister http payload without response".to_string())?,
+            )?,
+            proof: response
+                .proof
+                .into_iter()
+                .map(CanisterHttpResponseShare::try_from)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
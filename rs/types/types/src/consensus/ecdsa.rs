@@ -3,6 +3,11 @@
 // TODO: Remove once we have implemented the functionality
 #![allow(dead_code)]
 
+use crate::{
+    consensus::{BasicSigned, HasHeight},
+    crypto::SignedBytesWithoutDomainSeparator,
+    Height, NodeId,
+};
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 
@@ -57,9 +62,100 @@ pub struct EcdsaSummaryPayload {
     random_transcripts: Vec<RandomTranscript>,
 }
 
-/// The ECDSA message that goes into the artifact pool and gossiped with peers
+/// The maximum number of messages a node signs for the work requested at one
+/// height. Messages beyond it are invalid, which bounds the validated section
+/// of the ECDSA pool between two catch-up packages.
+pub const MAX_ECDSA_MESSAGES_PER_SIGNER_AND_HEIGHT: usize = 64;
+
+/// The id of a quadruple, i.e. of the pre-signature that is consumed by one
+/// signature.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Hash)]
+pub struct QuadrupleId(pub u64);
+
+/// A dealing of a node for one of the transcripts that a quadruple is built
+/// from.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Hash)]
+pub struct EcdsaDealing {
+    /// The height of the finalized block that requested the quadruple.
+    pub requested_height: Height,
+    /// The quadruple the dealing is for.
+    pub quadruple_id: QuadrupleId,
+    /// The node that created the dealing.
+    pub dealer: NodeId,
+    /// The serialized dealing, until the crypto types are available.
+    pub dealing: Vec<u8>,
+}
+
+/// A share of a node of the signature using the given quadruple.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Hash)]
+pub struct EcdsaSigShare {
+    /// The height of the finalized block that requested the signature.
+    pub requested_height: Height,
+    /// The quadruple consumed by the signature.
+    pub quadruple_id: QuadrupleId,
+    /// The node that created the share.
+    pub signer: NodeId,
+    /// The serialized signature share, until the crypto types are available.
+    pub share: Vec<u8>,
+}
+
+/// A complaint of a node about the dealing of another node.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Hash)]
+pub struct EcdsaComplaint {
+    /// The height of the finalized block that requested the quadruple.
+    pub requested_height: Height,
+    /// The quadruple the complained dealing is for.
+    pub quadruple_id: QuadrupleId,
+    /// The node whose dealing is complained about.
+    pub dealer: NodeId,
+    /// The node that complains.
+    pub complainer: NodeId,
+    /// The serialized complaint, until the crypto types are available.
+    pub complaint: Vec<u8>,
+}
+
+/// The content of an ECDSA message, which is signed by the node that created
+/// it.
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Hash)]
-pub struct EcdsaMessage;
+pub enum EcdsaMessageContent {
+    /// A dealing for a quadruple.
+    EcdsaDealing(EcdsaDealing),
+    /// A signature share.
+    EcdsaSigShare(EcdsaSigShare),
+    /// A complaint about a dealing.
+    EcdsaComplaint(EcdsaComplaint),
+}
+
+impl EcdsaMessageContent {
+    /// The node that claims to have created the message, which has to be the
+    /// signer of the message.
+    pub fn signer(&self) -> NodeId {
+        match self {
+            EcdsaMessageContent::EcdsaDealing(dealing) => dealing.dealer,
+            EcdsaMessageContent::EcdsaSigShare(share) => share.signer,
+            EcdsaMessageContent::EcdsaComplaint(complaint) => complaint.complainer,
+        }
+    }
+}
+
+impl HasHeight for EcdsaMessageContent {
+    fn height(&self) -> Height {
+        match self {
+            EcdsaMessageContent::EcdsaDealing(dealing) => dealing.requested_height,
+            EcdsaMessageContent::EcdsaSigShare(share) => share.requested_height,
+            EcdsaMessageContent::EcdsaComplaint(complaint) => complaint.requested_height,
+        }
+    }
+}
+
+impl SignedBytesWithoutDomainSeparator for EcdsaMessageContent {
+    fn as_signed_bytes_without_domain_separator(&self) -> Vec<u8> {
+        serde_cbor::to_vec(&self).unwrap()
+    }
+}
+
+/// The ECDSA message that goes into the artifact pool and gossiped with peers
+pub type EcdsaMessage = BasicSigned<EcdsaMessageContent>;
 
 #[allow(missing_docs)]
 /// Mock module of the crypto types that are needed by consensus for threshold
@@ -0,0 +1,121 @@
+//! Defines the evidence of block makers that equivocate, i.e. that sign two
+//! different block proposals at the same height.
+use crate::{
+    consensus::{BlockProposal, HasHeight, HasRank, Rank},
+    Height, NodeId,
+};
+use ic_protobuf::types::v1 as pb;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+
+/// An EquivocationProof consists of two different block proposals at the same
+/// height that are signed by the same block maker. As a replica has a single
+/// rank per height, the two proposals are for the same rank, and prove that the
+/// replica misbehaved.
+///
+/// The proposals are ordered by their hash, so that every pair of proposals
+/// has a single proof. Deserialization goes through `EquivocationProof::new`,
+/// so that a proof received from a peer upholds the same invariants.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "EquivocationProofFields")]
+pub struct EquivocationProof {
+    first: BlockProposal,
+    second: BlockProposal,
+}
+
+/// The serialized fields of an `EquivocationProof`, before its invariants are
+/// checked.
+#[derive(Deserialize)]
+struct EquivocationProofFields {
+    first: BlockProposal,
+    second: BlockProposal,
+}
+
+impl TryFrom<EquivocationProofFields> for EquivocationProof {
+    type Error = String;
+    fn try_from(fields: EquivocationProofFields) -> Result<Self, Self::Error> {
+        EquivocationProof::new(fields.first, fields.second)
+            .ok_or_else(|| "Equivocation proof of non-equivocating proposals".to_string())
+    }
+}
+
+impl EquivocationProof {
+    /// Returns the proof that the signer of the given block proposals
+    /// equivocated, or None if the proposals are not different proposals of
+    /// the same signer, height and rank.
+    pub fn new(first: BlockProposal, second: BlockProposal) -> Option<Self> {
+        if first.signature.signer != second.signature.signer
+            || first.height() != second.height()
+            || first.rank() != second.rank()
+            || first.content.get_hash() == second.content.get_hash()
+        {
+            return None;
+        }
+        if first.content.get_hash().get_ref().0 < second.content.get_hash().get_ref().0 {
+            Some(Self { first, second })
+        } else {
+            Some(Self {
+                first: second,
+                second: first,
+            })
+        }
+    }
+
+    /// The replica that equivocated.
+    pub fn signer(&self) -> NodeId {
+        self.first.signature.signer
+    }
+
+    /// The rank of the replica at the height of the proposals.
+    pub fn rank(&self) -> Rank {
+        self.first.rank()
+    }
+
+    /// The two block proposals.
+    pub fn proposals(&self) -> (&BlockProposal, &BlockProposal) {
+        (&self.first, &self.second)
+    }
+
+    /// Returns true if the two proposals are different proposals of the same
+    /// signer, height and rank, in hash order.
+    pub fn is_well_formed(&self) -> bool {
+        self.first.signature.signer == self.second.signature.signer
+            && self.first.height() == self.second.height()
+            && self.first.rank() == self.second.rank()
+            && self.first.content.get_hash().get_ref().0
+                < self.second.content.get_hash().get_ref().0
+    }
+}
+
+impl HasHeight for EquivocationProof {
+    fn height(&self) -> Height {
+        self.first.height()
+    }
+}
+
+impl From<&EquivocationProof> for pb::EquivocationProof {
+    fn from(proof: &EquivocationProof) -> Self {
+        Self {
+            first: Some((&proof.first).into()),
+            second: Some((&proof.second).into()),
+        }
+    }
+}
+
+impl TryFrom<pb::EquivocationProof> for EquivocationProof {
+    type Error = String;
+    fn try_from(proof: pb::EquivocationProof) -> Result<Self, Self::Error> {
+        let first = BlockProposal::try_from(
+            proof
+                .first
+                .ok_or_else(|| "Equivocation proof without first proposal".to_string())?,
+        )?;
+        let second = BlockProposal::try_from(
+            proof
+                .second
+                .ok_or_else(|| "Equivocation proof without second proposal".to_string())?,
+        )?;
+        EquivocationProof::new(first, second)
+            .ok_or_else(|| "Equivocation proof of non-equivocating proposals".to_string())
+    }
+}
@@ -0,0 +1,150 @@
+//! Defines the statistics of the queries that replicas execute, which are
+//! reported by every replica once per epoch and aggregated in the replicated
+//! state, so that canisters can be charged for their query load.
+//!
+//! An epoch is a fixed range of heights. The report of a replica for an epoch
+//! is signed, gossiped and included in a block during the following epoch.
+use crate::{
+    consensus::{BasicSignature, BasicSigned},
+    crypto::{BasicSig, BasicSigOf, SignedBytesWithoutDomainSeparator},
+    CanisterId, CountBytes, Height, NodeId, PrincipalId, RegistryVersion,
+};
+use ic_protobuf::types::v1 as pb;
+use phantom_newtype::AmountOf;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+/// The number of heights of an epoch of query statistics.
+pub const QUERY_STATS_EPOCH_LENGTH: u64 = 600;
+
+/// The maximum number of canisters in a report. A replica that executed
+/// queries on more canisters reports the ones with the most instructions and
+/// the others in a later epoch.
+pub const MAX_QUERY_STATS_CANISTERS_PER_REPORT: usize = 10_000;
+
+pub struct QueryStatsEpochTag {}
+/// The index of an epoch of query statistics.
+pub type QueryStatsEpoch = AmountOf<QueryStatsEpochTag, u64>;
+
+/// Returns the epoch of query statistics the given height belongs to.
+pub fn epoch_from_height(height: Height) -> QueryStatsEpoch {
+    QueryStatsEpoch::from(height.get() / QUERY_STATS_EPOCH_LENGTH)
+}
+
+/// The statistics of the queries executed on a canister.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct QueryStats {
+    /// The number of executed query calls and callbacks.
+    pub num_calls: u64,
+    /// The number of instructions executed by the queries.
+    pub num_instructions: u64,
+    /// The total size of the payloads the queries were called with.
+    pub ingress_payload_size: u64,
+    /// The total size of the replies of the queries.
+    pub egress_payload_size: u64,
+}
+
+impl QueryStats {
+    /// Adds the given statistics to these ones, saturating at the maximum.
+    pub fn saturating_accumulate(&mut self, other: &QueryStats) {
+        self.num_calls = self.num_calls.saturating_add(other.num_calls);
+        self.num_instructions = self.num_instructions.saturating_add(other.num_instructions);
+        self.ingress_payload_size = self
+            .ingress_payload_size
+            .saturating_add(other.ingress_payload_size);
+        self.egress_payload_size = self
+            .egress_payload_size
+            .saturating_add(other.egress_payload_size);
+    }
+}
+
+/// The statistics of the queries a replica executed during an epoch.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct QueryStatsContent {
+    /// The epoch the statistics were collected in.
+    pub epoch: QueryStatsEpoch,
+    /// The statistics by canister.
+    pub stats: BTreeMap<CanisterId, QueryStats>,
+    /// The registry version used to verify the signature of the report.
+    pub registry_version: RegistryVersion,
+}
+
+impl SignedBytesWithoutDomainSeparator for QueryStatsContent {
+    fn as_signed_bytes_without_domain_separator(&self) -> Vec<u8> {
+        serde_cbor::to_vec(&self).unwrap()
+    }
+}
+
+/// The signed report of a replica on the queries it executed during an epoch.
+pub type QueryStatsMessage = BasicSigned<QueryStatsContent>;
+
+impl CountBytes for QueryStatsMessage {
+    fn count_bytes(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.content.stats.len()
+                * (std::mem::size_of::<CanisterId>() + std::mem::size_of::<QueryStats>())
+            + self.signature.signature.get_ref().0.len()
+    }
+}
+
+impl From<&QueryStatsMessage> for pb::QueryStatsMessage {
+    fn from(message: &QueryStatsMessage) -> Self {
+        Self {
+            epoch: message.content.epoch.get(),
+            stats: message
+                .content
+                .stats
+                .iter()
+                .map(|(canister_id, stats)| pb::CanisterQueryStats {
+                    canister_id: Some(pb::CanisterId::from(*canister_id)),
+                    num_calls: stats.num_calls,
+                    num_instructions: stats.num_instructions,
+                    ingress_payload_size: stats.ingress_payload_size,
+                    egress_payload_size: stats.egress_payload_size,
+                })
+                .collect(),
+            registry_version: message.content.registry_version.get(),
+            signature: message.signature.signature.clone().get().0,
+            signer: message.signature.signer.get().into_vec(),
+        }
+    }
+}
+
+impl TryFrom<pb::QueryStatsMessage> for QueryStatsMessage {
+    type Error = String;
+    fn try_from(message: pb::QueryStatsMessage) -> Result<Self, Self::Error> {
+        let mut stats = BTreeMap::new();
+        for entry in message.stats {
+            let canister_id = CanisterId::try_from(
+                entry
+                    .canister_id
+                    .ok_or_else(|| "Query stats without canister id".to_string())?,
+            )
+            .map_err(|err| format!("Couldn't parse canister id: {:?}", err))?;
+            stats.insert(
+                canister_id,
+                QueryStats {
+                    num_calls: entry.num_calls,
+                    num_instructions: entry.num_instructions,
+                    ingress_payload_size: entry.ingress_payload_size,
+                    egress_payload_size: entry.egress_payload_size,
+                },
+            );
+        }
+        Ok(Self {
+            content: QueryStatsContent {
+                epoch: QueryStatsEpoch::from(message.epoch),
+                stats,
+                registry_version: RegistryVersion::from(message.registry_version),
+            },
+            signature: BasicSignature {
+                signature: BasicSigOf::from(BasicSig(message.signature)),
+                signer: NodeId::from(
+                    PrincipalId::try_from(message.signer)
+                        .map_err(|err| format!("Couldn't parse signer: {:?}", err))?,
+                ),
+            },
+        })
+    }
+}
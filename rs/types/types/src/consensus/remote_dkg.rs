@@ -0,0 +1,75 @@
+//! Defines the messages of the DKGs that a subnet runs for other subnets, e.g.
+//! to create or recover them.
+//!
+//! The requests are the configs of the remote DKGs, and the responses are the
+//! resulting transcripts. Both are taken from the DKG summary block at the
+//! start of an interval, so that they can be validated against the finalized
+//! chain by any replica of the subnet.
+use crate::{
+    consensus::HasHeight,
+    crypto::threshold_sig::ni_dkg::{
+        config::NiDkgConfig, NiDkgId, NiDkgTargetId, NiDkgTargetSubnet, NiDkgTranscript,
+    },
+    Height,
+};
+use serde::{Deserialize, Serialize};
+
+/// A request of another subnet to run a DKG for it, with the config of the DKG
+/// that is run in the interval starting at `summary_height`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct RemoteDkgRequest {
+    /// The height of the DKG summary block containing the config.
+    pub summary_height: Height,
+    /// The config of the remote DKG.
+    pub config: NiDkgConfig,
+}
+
+/// The response to a [RemoteDkgRequest], with the transcript of the DKG, or
+/// the reason why it could not be computed, as contained in the DKG summary
+/// block at `summary_height`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct RemoteDkgResponse {
+    /// The height of the DKG summary block containing the transcript.
+    pub summary_height: Height,
+    /// The id of the remote DKG.
+    pub dkg_id: NiDkgId,
+    /// The transcript, or the reason why it could not be computed.
+    pub transcript: Result<NiDkgTranscript, String>,
+}
+
+/// The messages of the remote DKGs.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum RemoteDkgMessage {
+    /// A request to run a DKG for another subnet.
+    Request(RemoteDkgRequest),
+    /// The transcript of a DKG that was run for another subnet.
+    Response(RemoteDkgResponse),
+}
+
+impl RemoteDkgMessage {
+    /// The id of the remote DKG.
+    pub fn dkg_id(&self) -> NiDkgId {
+        match self {
+            RemoteDkgMessage::Request(request) => request.config.dkg_id(),
+            RemoteDkgMessage::Response(response) => response.dkg_id,
+        }
+    }
+
+    /// The target of the remote DKG, or None if the DKG is for the local
+    /// subnet, which is never the case for valid messages.
+    pub fn target_id(&self) -> Option<NiDkgTargetId> {
+        match self.dkg_id().target_subnet {
+            NiDkgTargetSubnet::Remote(target_id) => Some(target_id),
+            NiDkgTargetSubnet::Local => None,
+        }
+    }
+}
+
+impl HasHeight for RemoteDkgMessage {
+    fn height(&self) -> Height {
+        match self {
+            RemoteDkgMessage::Request(request) => request.summary_height,
+            RemoteDkgMessage::Response(response) => response.summary_height,
+        }
+    }
+}
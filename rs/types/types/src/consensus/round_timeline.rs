@@ -0,0 +1,68 @@
+//! Defines the timelines of consensus rounds, which record when the phases of
+//! a round complete, to diagnose slow rounds in production.
+use crate::{time::Time, Height};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use strum_macros::AsRefStr;
+
+/// The phases of a consensus round.
+#[derive(
+    Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, AsRefStr, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum RoundPhase {
+    /// A block proposal at the height of the round was validated.
+    BlockProposal,
+    /// A block at the height of the round was notarized.
+    Notarization,
+    /// A block at the height of the round was finalized.
+    Finalization,
+    /// The random beacon at the height of the round was made, which starts
+    /// the next round.
+    RandomBeacon,
+}
+
+/// All phases of a consensus round.
+pub const ALL_ROUND_PHASES: [RoundPhase; 4] = [
+    RoundPhase::BlockProposal,
+    RoundPhase::Notarization,
+    RoundPhase::Finalization,
+    RoundPhase::RandomBeacon,
+];
+
+/// The timeline of the consensus round at one height.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoundTimeline {
+    /// The height of the round.
+    pub height: Height,
+    /// When the round started.
+    pub start: Time,
+    /// When the phases of the round completed.
+    pub phases: BTreeMap<RoundPhase, Time>,
+}
+
+impl RoundTimeline {
+    /// The time from the start of the round to the completion of the given
+    /// phase, or None if the phase did not complete yet.
+    pub fn phase_duration(&self, phase: RoundPhase) -> Option<Duration> {
+        self.phases
+            .get(&phase)
+            .map(|time| duration_since(self.start, *time))
+    }
+
+    /// Whether all phases of the round completed.
+    pub fn is_complete(&self) -> bool {
+        self.phases.len() == ALL_ROUND_PHASES.len()
+    }
+}
+
+/// The time from `start` to `end`, or zero if `end` is not after `start`.
+pub fn duration_since(start: Time, end: Time) -> Duration {
+    if end > start {
+        end - start
+    } else {
+        Duration::from_secs(0)
+    }
+}
@@ -26,6 +26,62 @@ pub const MAX_INGRESS_TTL: Duration = Duration::from_secs(5 * 60); // 5 minutes
 /// `current_time_and_expiry_time()`.
 pub const PERMITTED_DRIFT: Duration = Duration::from_secs(60);
 
+/// The range of expiry times the ingress manager accepts for ingress messages,
+/// relative to the current time.
+///
+/// Messages entering the ingress pool are checked against the local clock of
+/// the replica, and must expire within `max_ttl`. Messages included in blocks
+/// are checked against the block time, which lags behind the local clocks, so
+/// they may expire up to `clock_skew_tolerance` later. Together, the two never
+/// exceed `MAX_INGRESS_TTL`, for which the ingress history keeps the statuses
+/// that are used to deduplicate messages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IngressExpiryWindow {
+    /// The maximum time to live of messages, with respect to the local clock.
+    pub max_ttl: Duration,
+    /// The additional time to live of messages, with respect to the block
+    /// time.
+    pub clock_skew_tolerance: Duration,
+}
+
+impl IngressExpiryWindow {
+    /// Creates a window with the given bounds, capping them such that their
+    /// sum does not exceed `MAX_INGRESS_TTL`.
+    pub fn new(max_ttl: Duration, clock_skew_tolerance: Duration) -> Self {
+        let clock_skew_tolerance = clock_skew_tolerance.min(MAX_INGRESS_TTL);
+        Self {
+            max_ttl: max_ttl.min(MAX_INGRESS_TTL - clock_skew_tolerance),
+            clock_skew_tolerance,
+        }
+    }
+
+    /// The maximum time to live of messages with respect to the block time.
+    pub fn max_ttl_with_tolerance(&self) -> Duration {
+        self.max_ttl + self.clock_skew_tolerance
+    }
+}
+
+/// The expiry window of application subnets whose subnet record does not
+/// specify one.
+pub const APPLICATION_SUBNET_INGRESS_EXPIRY_WINDOW: IngressExpiryWindow = IngressExpiryWindow {
+    max_ttl: MAX_INGRESS_TTL,
+    clock_skew_tolerance: Duration::from_secs(0),
+};
+
+/// The expiry window of system subnets whose subnet record does not specify
+/// one. System subnets have more nodes and thus a slower block rate, so their
+/// block time lags further behind the local clocks.
+///
+/// Note that this changes the default of system subnets: previously, they
+/// accepted messages expiring up to `MAX_INGRESS_TTL` after the local time;
+/// now, they reject messages expiring more than 4.5 minutes after it. Agents
+/// that set the expiry of messages to system subnets to the maximum must set
+/// it at least 30 seconds earlier.
+pub const SYSTEM_SUBNET_INGRESS_EXPIRY_WINDOW: IngressExpiryWindow = IngressExpiryWindow {
+    max_ttl: Duration::from_secs(5 * 60 - 30),
+    clock_skew_tolerance: Duration::from_secs(30),
+};
+
 /// The status of an ingress message.
 #[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum IngressStatus {
@@ -55,6 +55,7 @@
 // the sum of all compute allocations with the multiplier.
 
 pub mod artifact;
+pub mod artifact_encoding;
 pub mod batch;
 pub mod chunkable;
 pub mod consensus;
@@ -65,6 +66,7 @@ pub mod ic00;
 pub mod ingress;
 pub mod malicious_behaviour;
 pub mod malicious_flags;
+pub mod malicious_strategies;
 pub mod messages;
 pub mod methods;
 pub mod nominal_cycles;
@@ -153,6 +155,63 @@ pub struct HeightTag {}
 // Note [ExecutionRound vs Height]
 pub type Height = AmountOf<HeightTag, u64>;
 
+/// An inclusive range of heights, from `min` up to and including `max`. The
+/// range is empty if `min` is above `max`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct HeightRange {
+    pub min: Height,
+    pub max: Height,
+}
+
+impl HeightRange {
+    pub fn new(min: Height, max: Height) -> HeightRange {
+        HeightRange { min, max }
+    }
+
+    /// Returns the range of the heights above `height`, up to and including
+    /// `max`. The range is empty if `height` is the largest height.
+    pub fn above(height: Height, max: Height) -> HeightRange {
+        match height.checked_increment() {
+            Some(min) => HeightRange::new(min, max),
+            None => HeightRange::new(height, Height::from(0)),
+        }
+    }
+
+    /// Returns the part of the range below `height`, or `None` if no height of
+    /// the range is below `height`.
+    pub fn below(&self, height: Height) -> Option<HeightRange> {
+        let max = height.checked_decrement()?.min(self.max);
+        let range = HeightRange::new(self.min, max);
+        if range.is_empty() {
+            None
+        } else {
+            Some(range)
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min > self.max
+    }
+
+    pub fn contains(&self, height: Height) -> bool {
+        self.min <= height && height <= self.max
+    }
+
+    /// Returns the number of heights in the range.
+    pub fn len(&self) -> u64 {
+        if self.is_empty() {
+            0
+        } else {
+            (self.max - self.min).get().saturating_add(1)
+        }
+    }
+
+    /// Returns the heights of the range in ascending order.
+    pub fn heights(&self) -> impl Iterator<Item = Height> {
+        (self.min.get()..=self.max.get()).map(Height::from)
+    }
+}
+
 /// Converts a NodeId into its protobuf definition.  Normally, we would use
 /// `impl From<NodeId> for pb::NodeId` here however we cannot as both
 /// `Id` and `pb::NodeId` are defined in other crates.
@@ -681,4 +740,40 @@ mod test {
         ))
         .is_err());
     }
+
+    #[test]
+    fn height_range_is_inclusive() {
+        let range = HeightRange::new(Height::from(3), Height::from(5));
+        assert!(!range.is_empty());
+        assert_eq!(range.len(), 3);
+        assert!(range.contains(Height::from(3)));
+        assert!(range.contains(Height::from(5)));
+        assert!(!range.contains(Height::from(2)));
+        assert!(!range.contains(Height::from(6)));
+        assert_eq!(
+            range.heights().collect::<Vec<_>>(),
+            vec![Height::from(3), Height::from(4), Height::from(5)]
+        );
+
+        let empty = HeightRange::new(Height::from(5), Height::from(4));
+        assert!(empty.is_empty());
+        assert_eq!(empty.len(), 0);
+        assert!(!empty.contains(Height::from(5)));
+        assert_eq!(empty.heights().count(), 0);
+    }
+
+    #[test]
+    fn height_range_above_and_below() {
+        let range = HeightRange::above(Height::from(2), Height::from(5));
+        assert_eq!(range, HeightRange::new(Height::from(3), Height::from(5)));
+        assert!(HeightRange::above(Height::from(u64::MAX), Height::from(u64::MAX)).is_empty());
+
+        assert_eq!(
+            range.below(Height::from(5)),
+            Some(HeightRange::new(Height::from(3), Height::from(4)))
+        );
+        assert_eq!(range.below(Height::from(10)), Some(range));
+        assert_eq!(range.below(Height::from(3)), None);
+        assert_eq!(range.below(Height::from(0)), None);
+    }
 }
@@ -4,8 +4,9 @@
 //! malicious behavior to enable in different components.
 //! Both struct and fields have to be public
 //!
-//! Introducing a new malicious behavior starts with extending
-//! this component struct with a new flag
+//! The flags of the networking stack are turned into the strategies of
+//! `malicious_strategies::MaliciousBehaviors`, which tests can also inject
+//! directly. Only behaviors that are configured per replica need a flag.
 //!
 //! It is desirable to have a description for each flag in this file
 
@@ -0,0 +1,266 @@
+//! Defines the malicious behaviors that tests can inject into the components
+//! of a replica.
+//!
+//! A malicious behavior is a strategy implementing [MaliciousBehavior], which
+//! names the component whose behavior it changes. The behaviors of a replica
+//! are collected in a [MaliciousBehaviors] registry, which is passed to the
+//! networking stack. Each component receives the behaviors that target it,
+//! and looks up the strategies it knows by their type. Strategies compose:
+//! all behaviors in the registry are active at the same time, e.g. a replica
+//! can equivocate and withhold its shares.
+//!
+//! The strategies of consensus and the [DelayAdverts] strategy only take
+//! effect in builds with the `malicious_code` feature.
+//!
+//! Introducing a new malicious behavior starts with adding a strategy to this
+//! file, and then looking it up in the component it targets. The registry can
+//! also be built from the [MaliciousFlags] of the replica configuration.
+
+use crate::malicious_flags::MaliciousFlags;
+use std::any::Any;
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The components into which malicious behaviors can be injected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum MaliciousComponent {
+    Gossip,
+    Consensus,
+    IngressManager,
+}
+
+/// Allows to look up strategies by their type.
+pub trait AsAny {
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<T: Any> AsAny for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// A strategy that changes the behavior of a component.
+pub trait MaliciousBehavior: AsAny + Debug + Send + Sync {
+    /// The component whose behavior is changed.
+    fn component(&self) -> MaliciousComponent;
+}
+
+/// Implements `MaliciousBehavior` for strategies targeting the given
+/// component.
+macro_rules! malicious_behavior_impl {
+    ($component:ident, $($strategy:ident),+) => {
+        $(
+            impl MaliciousBehavior for $strategy {
+                fn component(&self) -> MaliciousComponent {
+                    MaliciousComponent::$component
+                }
+            }
+        )+
+    };
+}
+
+/// Gossip drops all chunk requests.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DropChunkRequests;
+
+/// Gossip responds to all chunk requests that the artifact was not found.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ArtifactNotFound;
+
+/// Gossip responds to every chunk request with thousands of copies of the
+/// chunk.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SendManyChunks;
+
+/// Gossip responds to chunk requests with empty, invalid chunks.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CorruptChunks;
+
+/// Gossip holds back the adverts of its artifacts for the given duration
+/// before sending them to peers.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DelayAdverts {
+    pub delay: Duration,
+}
+
+malicious_behavior_impl!(
+    Gossip,
+    DropChunkRequests,
+    ArtifactNotFound,
+    SendManyChunks,
+    CorruptChunks,
+    DelayAdverts
+);
+
+/// Consensus proposes several different blocks at every height at which the
+/// replica is a block maker.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Equivocate;
+
+/// Consensus only proposes empty blocks.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProposeEmptyBlocks;
+
+/// Consensus validates and notarizes all block proposals.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NotarizeAll;
+
+/// Consensus finalizes all notarized blocks.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FinalizeAll;
+
+/// Consensus does not create any shares, i.e. the replica does not take part
+/// in notarization, finalization, the random beacon, the random tape and
+/// catch-up packages.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WithholdShares;
+
+malicious_behavior_impl!(
+    Consensus,
+    Equivocate,
+    ProposeEmptyBlocks,
+    NotarizeAll,
+    FinalizeAll,
+    WithholdShares
+);
+
+/// The ingress manager accepts all ingress messages without validating them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DisableIngressValidation;
+
+malicious_behavior_impl!(IngressManager, DisableIngressValidation);
+
+/// The registry of the malicious behaviors of a replica.
+#[derive(Clone, Debug, Default)]
+pub struct MaliciousBehaviors {
+    behaviors: Vec<Arc<dyn MaliciousBehavior>>,
+}
+
+impl MaliciousBehaviors {
+    /// Creates an empty registry, i.e. an honest replica.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the strategy to the registry.
+    pub fn with<B: MaliciousBehavior + 'static>(mut self, behavior: B) -> Self {
+        self.behaviors.push(Arc::new(behavior));
+        self
+    }
+
+    /// Returns the behaviors that target the given component.
+    pub fn for_component(&self, component: MaliciousComponent) -> Self {
+        Self {
+            behaviors: self
+                .behaviors
+                .iter()
+                .filter(|behavior| behavior.component() == component)
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Returns the first strategy of the given type, if any.
+    pub fn get<B: MaliciousBehavior + 'static>(&self) -> Option<&B> {
+        self.behaviors
+            .iter()
+            // The strategy is looked up through the vtable of the trait
+            // object, not through the blanket implementation for `Arc`.
+            .find_map(|behavior| (**behavior).as_any().downcast_ref::<B>())
+    }
+
+    /// Returns true if a strategy of the given type is in the registry.
+    pub fn contains<B: MaliciousBehavior + 'static>(&self) -> bool {
+        self.get::<B>().is_some()
+    }
+
+    /// Returns true if the registry contains no behaviors.
+    pub fn is_empty(&self) -> bool {
+        self.behaviors.is_empty()
+    }
+}
+
+impl From<&MaliciousFlags> for MaliciousBehaviors {
+    /// Builds the registry from the flags of the replica configuration.
+    fn from(flags: &MaliciousFlags) -> Self {
+        let mut behaviors = MaliciousBehaviors::new();
+        if flags.maliciously_gossip_drop_requests {
+            behaviors = behaviors.with(DropChunkRequests);
+        }
+        if flags.maliciously_gossip_artifact_not_found {
+            behaviors = behaviors.with(ArtifactNotFound);
+        }
+        if flags.maliciously_gossip_send_many_artifacts {
+            behaviors = behaviors.with(SendManyChunks);
+        }
+        if flags.maliciously_gossip_send_invalid_artifacts {
+            behaviors = behaviors.with(CorruptChunks);
+        }
+        if flags.maliciously_propose_equivocating_blocks {
+            behaviors = behaviors.with(Equivocate);
+        }
+        if flags.maliciously_propose_empty_blocks {
+            behaviors = behaviors.with(ProposeEmptyBlocks);
+        }
+        if flags.maliciously_notarize_all {
+            behaviors = behaviors.with(NotarizeAll);
+        }
+        if flags.maliciously_finalize_all {
+            behaviors = behaviors.with(FinalizeAll);
+        }
+        if flags.maliciously_disable_ingress_validation {
+            behaviors = behaviors.with(DisableIngressValidation);
+        }
+        behaviors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strategies_are_looked_up_by_type() {
+        let delay = Duration::from_secs(3);
+        let behaviors = MaliciousBehaviors::new()
+            .with(Equivocate)
+            .with(DelayAdverts { delay });
+        assert!(behaviors.contains::<Equivocate>());
+        assert!(!behaviors.contains::<WithholdShares>());
+        assert_eq!(
+            behaviors.get::<DelayAdverts>(),
+            Some(&DelayAdverts { delay })
+        );
+    }
+
+    #[test]
+    fn components_only_receive_their_strategies() {
+        let behaviors = MaliciousBehaviors::new()
+            .with(Equivocate)
+            .with(WithholdShares)
+            .with(CorruptChunks);
+        let consensus = behaviors.for_component(MaliciousComponent::Consensus);
+        assert!(consensus.contains::<Equivocate>());
+        assert!(consensus.contains::<WithholdShares>());
+        assert!(!consensus.contains::<CorruptChunks>());
+        assert!(behaviors
+            .for_component(MaliciousComponent::IngressManager)
+            .is_empty());
+    }
+
+    #[test]
+    fn registry_is_built_from_flags() {
+        assert!(MaliciousBehaviors::from(&MaliciousFlags::default()).is_empty());
+        let flags = MaliciousFlags {
+            maliciously_gossip_send_invalid_artifacts: true,
+            maliciously_propose_equivocating_blocks: true,
+            ..Default::default()
+        };
+        let behaviors = MaliciousBehaviors::from(&flags);
+        assert!(behaviors.contains::<CorruptChunks>());
+        assert!(behaviors.contains::<Equivocate>());
+        assert!(!behaviors.contains::<ProposeEmptyBlocks>());
+    }
+}
@@ -1,5 +1,7 @@
 //! Types related to various messages that the Internet Computer handles.
 mod blob;
+mod cbor;
+mod correlation;
 mod http;
 mod ingress_messages;
 mod inter_canister;
@@ -10,12 +12,16 @@ mod webauthn;
 
 use crate::{user_id_into_protobuf, user_id_try_from_protobuf, Cycles, Funds, NumBytes, UserId};
 pub use blob::Blob;
+pub use cbor::{
+    from_cbor, is_self_describing_cbor, to_canonical_cbor, Tagged, CBOR_SELF_DESCRIBE_TAG,
+};
+pub use correlation::{correlation_id, CorrelationId, MAX_CORRELATED_MESSAGES};
 pub use http::{
     Authentication, Certificate, CertificateDelegation, Delegation, HasCanisterId,
     HttpCanisterUpdate, HttpQueryResponse, HttpQueryResponseReply, HttpReadContent, HttpReadState,
     HttpReadStateResponse, HttpReply, HttpRequest, HttpRequestContent, HttpRequestEnvelope,
     HttpResponseStatus, HttpStatusResponse, HttpSubmitContent, HttpUserQuery, RawHttpRequestVal,
-    ReadContent, ReplicaHealthStatus, SignedDelegation,
+    ReadContent, ReplicaHealthAssessment, ReplicaHealthStatus, SignedDelegation,
 };
 pub use ic_base_types::CanisterInstallMode;
 use ic_base_types::{CanisterId, CanisterIdError, PrincipalId};
@@ -53,6 +59,19 @@ pub const MAX_INTER_CANISTER_PAYLOAD_IN_BYTES: NumBytes = NumBytes::new(2 * 1024
 /// have allocated here is sufficient.
 pub const MAX_XNET_PAYLOAD_IN_BYTES: NumBytes = NumBytes::new(2202009); // 2.1 MiB
 
+/// Maximum byte size of the responses to canister HTTP outcalls in a valid
+/// block payload.
+pub const MAX_CANISTER_HTTP_PAYLOAD_IN_BYTES: NumBytes = NumBytes::new(2 * 1024 * 1024); // 2 MiB
+
+/// Maximum byte size of the query statistics reports in a valid block
+/// payload.
+pub const MAX_QUERY_STATS_PAYLOAD_IN_BYTES: NumBytes = NumBytes::new(1024 * 1024); // 1 MiB
+
+/// The highest priority class of an ingress message. Senders choose the
+/// class of their messages, so higher classes are treated as this one; this
+/// bounds the number of classes and the advantage a sender can claim.
+pub const MAX_INGRESS_PRIORITY: u64 = 3;
+
 /// An end user's signature.
 #[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct UserSignature {
@@ -221,9 +240,10 @@ impl From<CanisterIdError> for HttpHandlerError {
     }
 }
 
-/// Bytes representation of signed HTTP requests, using CBOR as a serialization
-/// format. Use `TryFrom` or `TryInto` to convert between `SignedRequestBytes`
-/// and other types, corresponding to serialization/deserialization.
+/// Bytes representation of signed HTTP requests, using canonical CBOR as a
+/// serialization format, see `to_canonical_cbor`. Use `TryFrom` or `TryInto`
+/// to convert between `SignedRequestBytes` and other types, corresponding to
+/// serialization/deserialization.
 #[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct SignedRequestBytes(#[serde(with = "serde_bytes")] Vec<u8>);
 
@@ -249,11 +269,7 @@ impl<T: Serialize> TryFrom<HttpRequestEnvelope<T>> for SignedRequestBytes {
     type Error = serde_cbor::Error;
 
     fn try_from(request: HttpRequestEnvelope<T>) -> Result<Self, Self::Error> {
-        let mut serialized_bytes = Vec::new();
-        let mut serializer = serde_cbor::Serializer::new(&mut serialized_bytes);
-        serializer.self_describe()?;
-        request.serialize(&mut serializer)?;
-        Ok(serialized_bytes.into())
+        Ok(to_canonical_cbor(&request)?.into())
     }
 }
 
@@ -264,7 +280,7 @@ where
     type Error = serde_cbor::Error;
 
     fn try_from(bytes: &'a SignedRequestBytes) -> Result<Self, Self::Error> {
-        serde_cbor::from_slice::<HttpRequestEnvelope<T>>(bytes.as_ref())
+        from_cbor::<HttpRequestEnvelope<T>>(bytes.as_ref())
     }
 }
 
@@ -389,6 +405,7 @@ mod tests {
                         sender: Blob(vec![0x04]),
                         nonce: None,
                         ingress_expiry: expiry_time.as_nanos_since_unix_epoch(),
+                        priority: None,
                     },
                 },
                 sender_pubkey: Some(Blob(vec![])),
@@ -423,6 +440,7 @@ mod tests {
                         sender: Blob(vec![0x04]),
                         nonce: None,
                         ingress_expiry: expiry_time.as_nanos_since_unix_epoch(),
+                        priority: None,
                     },
                 },
                 sender_pubkey: Some(Blob(vec![])),
@@ -457,6 +475,7 @@ mod tests {
                         sender: Blob(vec![0x04]),
                         nonce: Some(Blob(vec![1, 2, 3, 4, 5])),
                         ingress_expiry: expiry_time.as_nanos_since_unix_epoch(),
+                        priority: None,
                     },
                 },
                 sender_pubkey: Some(Blob(vec![])),
@@ -491,6 +510,7 @@ mod tests {
                     sender: Blob(vec![0x04]),
                     nonce: None,
                     ingress_expiry: expiry_time.as_nanos_since_unix_epoch(),
+                    priority: None,
                 },
             },
             sender_pubkey: Some(Blob(vec![2; 32])),
@@ -515,6 +535,7 @@ mod tests {
                     sender: Blob(vec![0x04]),
                     nonce: None,
                     ingress_expiry: expiry_time.as_nanos_since_unix_epoch(),
+                    priority: None,
                 },
             },
             sender_pubkey: None,
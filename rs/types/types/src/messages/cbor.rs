@@ -0,0 +1,774 @@
+//! The CBOR encoding of the messages exchanged with users over HTTP.
+//!
+//! All requests and responses are encoded with [to_canonical_cbor], so that
+//! the replica, the agents and the tests produce the same bytes for the same
+//! value. The encoding follows the core deterministic encoding requirements
+//! of RFC 8949, section 4.2.1: integers, lengths and tags are encoded in
+//! their shortest form, floats in the shortest form that preserves their
+//! value, all lengths are definite, and the keys of every map are sorted by
+//! the bytewise lexicographic order of their encodings. The encoding starts
+//! with the self-describe tag, as the interface spec recommends, and any
+//! other value can be tagged by wrapping it in a [Tagged].
+//!
+//! Values are encoded in a single pass; only the entries of maps are
+//! buffered, to sort them. Decoding accepts any well-formed CBOR and skips
+//! all tags, so a [Tagged] value decodes as the value it wraps.
+//!
+//! Note that the ID of a request, which is what users sign, does not depend
+//! on the encoding, as it is the representation-independent hash of the
+//! request content, see `MessageId`.
+use serde::{
+    de::DeserializeOwned,
+    ser::{self, Error as _, Serializer as _},
+    Serialize,
+};
+use serde_cbor::Error;
+
+/// The self-describe tag, which marks the bytes that follow it as CBOR.
+pub const CBOR_SELF_DESCRIBE_TAG: u64 = 55799;
+
+/// The encoding of the self-describe tag.
+const CBOR_SELF_DESCRIBE_TAG_BYTES: [u8; 3] = [0xd9, 0xd9, 0xf7];
+
+const MAJOR_UNSIGNED: u8 = 0;
+const MAJOR_NEGATIVE: u8 = 1;
+const MAJOR_BYTES: u8 = 2;
+const MAJOR_TEXT: u8 = 3;
+const MAJOR_ARRAY: u8 = 4;
+const MAJOR_MAP: u8 = 5;
+const MAJOR_TAG: u8 = 6;
+
+const FALSE: u8 = 0xf4;
+const TRUE: u8 = 0xf5;
+const NULL: u8 = 0xf6;
+const FLOAT16: u8 = 0xf9;
+const FLOAT32: u8 = 0xfa;
+const FLOAT64: u8 = 0xfb;
+
+/// The canonical NaN, a quiet NaN of half precision.
+const NAN_BYTES: [u8; 3] = [FLOAT16, 0x7e, 0x00];
+
+/// The name of the newtype struct under which a [Tagged] is serialized.
+const TAGGED_NAME: &str = "\u{0}ic_types::messages::Tagged";
+
+/// A value that is encoded with the given tag, see RFC 8949, section 3.4.
+///
+/// Serializers other than [to_canonical_cbor] see a tuple of the tag and the
+/// value. Tags are skipped by [from_cbor], so the field of a `Tagged<T>` is
+/// decoded into a field of type `T`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Tagged<T> {
+    pub tag: u64,
+    pub value: T,
+}
+
+impl<T: Serialize> Serialize for Tagged<T> {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_newtype_struct(TAGGED_NAME, &(self.tag, &self.value))
+    }
+}
+
+/// Encodes the given value as deterministic CBOR, starting with the
+/// self-describe tag.
+pub fn to_canonical_cbor<T: Serialize + ?Sized>(value: &T) -> serde_cbor::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    write_header(&mut bytes, MAJOR_TAG, CBOR_SELF_DESCRIBE_TAG);
+    value.serialize(CanonicalEncoder { out: &mut bytes })?;
+    Ok(bytes)
+}
+
+/// Decodes a value from the given CBOR, which may or may not start with the
+/// self-describe tag. The encoding does not need to be canonical, as agents
+/// are not required to encode canonically.
+pub fn from_cbor<T: DeserializeOwned>(bytes: &[u8]) -> serde_cbor::Result<T> {
+    serde_cbor::from_slice(bytes)
+}
+
+/// Whether the given bytes start with the self-describe tag.
+pub fn is_self_describing_cbor(bytes: &[u8]) -> bool {
+    bytes.starts_with(&CBOR_SELF_DESCRIBE_TAG_BYTES)
+}
+
+/// Writes the head of a data item of the given major type with the given
+/// argument, in its shortest form.
+fn write_header(out: &mut Vec<u8>, major: u8, argument: u64) {
+    let major = major << 5;
+    if argument < 24 {
+        out.push(major | argument as u8);
+    } else if argument <= u8::MAX as u64 {
+        out.push(major | 24);
+        out.push(argument as u8);
+    } else if argument <= u16::MAX as u64 {
+        out.push(major | 25);
+        out.extend_from_slice(&(argument as u16).to_be_bytes());
+    } else if argument <= u32::MAX as u64 {
+        out.push(major | 26);
+        out.extend_from_slice(&(argument as u32).to_be_bytes());
+    } else {
+        out.push(major | 27);
+        out.extend_from_slice(&argument.to_be_bytes());
+    }
+}
+
+/// Reads the head of a data item of the given major type from the start of
+/// the given bytes, and returns its argument and the bytes that follow it.
+fn read_header(bytes: &[u8], major: u8) -> Option<(u64, &[u8])> {
+    let (first, rest) = bytes.split_first()?;
+    if first >> 5 != major {
+        return None;
+    }
+    let len = match first & 0x1f {
+        info if info < 24 => return Some((info as u64, rest)),
+        24 => 1,
+        25 => 2,
+        26 => 4,
+        27 => 8,
+        _ => return None,
+    };
+    if rest.len() < len {
+        return None;
+    }
+    let (argument, rest) = rest.split_at(len);
+    let argument = argument
+        .iter()
+        .fold(0, |argument, byte| (argument << 8) | *byte as u64);
+    Some((argument, rest))
+}
+
+fn write_f64(out: &mut Vec<u8>, value: f64) {
+    let single = value as f32;
+    if value.is_nan() || f64::from(single) == value {
+        write_f32(out, single);
+    } else {
+        out.push(FLOAT64);
+        out.extend_from_slice(&value.to_bits().to_be_bytes());
+    }
+}
+
+fn write_f32(out: &mut Vec<u8>, value: f32) {
+    if value.is_nan() {
+        out.extend_from_slice(&NAN_BYTES);
+    } else if let Some(half) = to_f16_bits(value) {
+        out.push(FLOAT16);
+        out.extend_from_slice(&half.to_be_bytes());
+    } else {
+        out.push(FLOAT32);
+        out.extend_from_slice(&value.to_bits().to_be_bytes());
+    }
+}
+
+/// Returns the bits of the half-precision float with the given value, if
+/// there is one. The value must not be NaN.
+fn to_f16_bits(value: f32) -> Option<u16> {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+    match exponent {
+        // Zero, or a subnormal, which is too small for half precision.
+        0 if mantissa == 0 => Some(sign),
+        0 => None,
+        // Infinity.
+        0xff => Some(sign | 0x7c00),
+        _ => {
+            let exponent = exponent - 127;
+            if (-14..=15).contains(&exponent) {
+                // A normal half-precision float, which has 10 bits of mantissa.
+                if mantissa & 0x1fff != 0 {
+                    return None;
+                }
+                Some(sign | (((exponent + 15) as u16) << 10) | (mantissa >> 13) as u16)
+            } else if (-24..-14).contains(&exponent) {
+                // A subnormal half-precision float, i.e. a multiple of 2^-24.
+                let significand = 0x80_0000 | mantissa;
+                let shift = (-1 - exponent) as u32;
+                if significand & ((1 << shift) - 1) != 0 {
+                    return None;
+                }
+                Some(sign | (significand >> shift) as u16)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+/// A `serde` serializer that writes the deterministic encoding of a value.
+struct CanonicalEncoder<'a> {
+    out: &'a mut Vec<u8>,
+}
+
+impl<'a> CanonicalEncoder<'a> {
+    fn write_text(&mut self, text: &str) {
+        write_header(self.out, MAJOR_TEXT, text.len() as u64);
+        self.out.extend_from_slice(text.as_bytes());
+    }
+
+    /// Writes the head of a map with a single entry, keyed by the given
+    /// variant, which is how `serde_cbor` encodes enum variants with data.
+    fn write_variant(&mut self, variant: &str) {
+        write_header(self.out, MAJOR_MAP, 1);
+        self.write_text(variant);
+    }
+}
+
+impl<'a> ser::Serializer for CanonicalEncoder<'a> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = ArrayEncoder<'a>;
+    type SerializeTuple = ArrayEncoder<'a>;
+    type SerializeTupleStruct = ArrayEncoder<'a>;
+    type SerializeTupleVariant = ArrayEncoder<'a>;
+    type SerializeMap = MapEncoder<'a>;
+    type SerializeStruct = MapEncoder<'a>;
+    type SerializeStructVariant = MapEncoder<'a>;
+
+    fn serialize_bool(self, value: bool) -> Result<(), Error> {
+        self.out.push(if value { TRUE } else { FALSE });
+        Ok(())
+    }
+
+    fn serialize_i8(self, value: i8) -> Result<(), Error> {
+        self.serialize_i64(value as i64)
+    }
+
+    fn serialize_i16(self, value: i16) -> Result<(), Error> {
+        self.serialize_i64(value as i64)
+    }
+
+    fn serialize_i32(self, value: i32) -> Result<(), Error> {
+        self.serialize_i64(value as i64)
+    }
+
+    fn serialize_i64(self, value: i64) -> Result<(), Error> {
+        if value < 0 {
+            // A negative integer `n` is encoded as `-1 - n`.
+            write_header(self.out, MAJOR_NEGATIVE, !value as u64);
+        } else {
+            write_header(self.out, MAJOR_UNSIGNED, value as u64);
+        }
+        Ok(())
+    }
+
+    fn serialize_u8(self, value: u8) -> Result<(), Error> {
+        self.serialize_u64(value as u64)
+    }
+
+    fn serialize_u16(self, value: u16) -> Result<(), Error> {
+        self.serialize_u64(value as u64)
+    }
+
+    fn serialize_u32(self, value: u32) -> Result<(), Error> {
+        self.serialize_u64(value as u64)
+    }
+
+    fn serialize_u64(self, value: u64) -> Result<(), Error> {
+        write_header(self.out, MAJOR_UNSIGNED, value);
+        Ok(())
+    }
+
+    fn serialize_f32(self, value: f32) -> Result<(), Error> {
+        write_f32(self.out, value);
+        Ok(())
+    }
+
+    fn serialize_f64(self, value: f64) -> Result<(), Error> {
+        write_f64(self.out, value);
+        Ok(())
+    }
+
+    fn serialize_char(self, value: char) -> Result<(), Error> {
+        self.serialize_str(value.encode_utf8(&mut [0; 4]))
+    }
+
+    fn serialize_str(mut self, value: &str) -> Result<(), Error> {
+        self.write_text(value);
+        Ok(())
+    }
+
+    fn serialize_bytes(self, value: &[u8]) -> Result<(), Error> {
+        write_header(self.out, MAJOR_BYTES, value.len() as u64);
+        self.out.extend_from_slice(value);
+        Ok(())
+    }
+
+    fn serialize_none(self) -> Result<(), Error> {
+        self.serialize_unit()
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), Error> {
+        self.out.push(NULL);
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), Error> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<(), Error> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        if name != TAGGED_NAME {
+            return value.serialize(self);
+        }
+        // The value is the tuple of the tag and the tagged value, which is
+        // encoded as an array of two elements.
+        let mut tuple = Vec::new();
+        value.serialize(CanonicalEncoder { out: &mut tuple })?;
+        let (tag, tagged) = read_header(&tuple, MAJOR_ARRAY)
+            .filter(|(len, _)| *len == 2)
+            .and_then(|(_, elements)| read_header(elements, MAJOR_UNSIGNED))
+            .ok_or_else(|| Error::custom("a tagged value must be a tag and a value"))?;
+        write_header(self.out, MAJOR_TAG, tag);
+        self.out.extend_from_slice(tagged);
+        Ok(())
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        mut self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.write_variant(variant);
+        value.serialize(self)
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<ArrayEncoder<'a>, Error> {
+        Ok(ArrayEncoder::new(self.out, len))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<ArrayEncoder<'a>, Error> {
+        Ok(ArrayEncoder::new(self.out, Some(len)))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<ArrayEncoder<'a>, Error> {
+        Ok(ArrayEncoder::new(self.out, Some(len)))
+    }
+
+    fn serialize_tuple_variant(
+        mut self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<ArrayEncoder<'a>, Error> {
+        self.write_variant(variant);
+        Ok(ArrayEncoder::new(self.out, Some(len)))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<MapEncoder<'a>, Error> {
+        Ok(MapEncoder::new(self.out))
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<MapEncoder<'a>, Error> {
+        Ok(MapEncoder::new(self.out))
+    }
+
+    fn serialize_struct_variant(
+        mut self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<MapEncoder<'a>, Error> {
+        self.write_variant(variant);
+        Ok(MapEncoder::new(self.out))
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+/// Encodes the elements of an array. If the length of the array is known
+/// upfront, the elements are written as they are serialized, otherwise they
+/// are buffered until the length is known.
+struct ArrayEncoder<'a> {
+    out: &'a mut Vec<u8>,
+    /// The encoded elements and their number, if the length was not known.
+    buffered: Option<(Vec<u8>, u64)>,
+}
+
+impl<'a> ArrayEncoder<'a> {
+    fn new(out: &'a mut Vec<u8>, len: Option<usize>) -> Self {
+        let buffered = match len {
+            Some(len) => {
+                write_header(out, MAJOR_ARRAY, len as u64);
+                None
+            }
+            None => Some((Vec::new(), 0)),
+        };
+        Self { out, buffered }
+    }
+
+    fn element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        match &mut self.buffered {
+            Some((buffer, count)) => {
+                *count += 1;
+                value.serialize(CanonicalEncoder { out: buffer })
+            }
+            None => value.serialize(CanonicalEncoder { out: self.out }),
+        }
+    }
+
+    fn finish(self) -> Result<(), Error> {
+        if let Some((buffer, count)) = self.buffered {
+            write_header(self.out, MAJOR_ARRAY, count);
+            self.out.extend_from_slice(&buffer);
+        }
+        Ok(())
+    }
+}
+
+impl<'a> ser::SerializeSeq for ArrayEncoder<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.finish()
+    }
+}
+
+impl<'a> ser::SerializeTuple for ArrayEncoder<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.finish()
+    }
+}
+
+impl<'a> ser::SerializeTupleStruct for ArrayEncoder<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.finish()
+    }
+}
+
+impl<'a> ser::SerializeTupleVariant for ArrayEncoder<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.finish()
+    }
+}
+
+/// Encodes the entries of a map, which are buffered until the end of the map
+/// to sort them by their encoded keys.
+struct MapEncoder<'a> {
+    out: &'a mut Vec<u8>,
+    /// The encoded keys and values of the entries.
+    entries: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl<'a> MapEncoder<'a> {
+    fn new(out: &'a mut Vec<u8>) -> Self {
+        Self {
+            out,
+            entries: Vec::new(),
+        }
+    }
+
+    fn key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        let mut encoded = Vec::new();
+        key.serialize(CanonicalEncoder { out: &mut encoded })?;
+        self.entries.push((encoded, Vec::new()));
+        Ok(())
+    }
+
+    fn value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let (_, encoded) = self
+            .entries
+            .last_mut()
+            .ok_or_else(|| Error::custom("a map value must follow its key"))?;
+        value.serialize(CanonicalEncoder { out: encoded })
+    }
+
+    fn finish(mut self) -> Result<(), Error> {
+        self.entries.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        write_header(self.out, MAJOR_MAP, self.entries.len() as u64);
+        for (key, value) in self.entries {
+            self.out.extend_from_slice(&key);
+            self.out.extend_from_slice(&value);
+        }
+        Ok(())
+    }
+}
+
+impl<'a> ser::SerializeMap for MapEncoder<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        self.key(key)
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.value(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.finish()
+    }
+}
+
+impl<'a> ser::SerializeStruct for MapEncoder<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.key(key)?;
+        self.value(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.finish()
+    }
+}
+
+impl<'a> ser::SerializeStructVariant for MapEncoder<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.key(key)?;
+        self.value(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Request {
+        sender: u64,
+        arg: String,
+        canister_id: Vec<u8>,
+    }
+
+    #[derive(Serialize)]
+    struct ReorderedRequest {
+        canister_id: Vec<u8>,
+        sender: u64,
+        arg: String,
+    }
+
+    #[test]
+    fn encoding_starts_with_the_self_describe_tag() {
+        let bytes = to_canonical_cbor(&1u64).unwrap();
+        assert!(is_self_describing_cbor(&bytes));
+        assert_eq!(bytes, vec![0xd9, 0xd9, 0xf7, 0x01]);
+    }
+
+    #[test]
+    fn encoding_does_not_depend_on_the_order_of_fields() {
+        let request = Request {
+            sender: 4,
+            arg: "arg".to_string(),
+            canister_id: vec![42],
+        };
+        let reordered = ReorderedRequest {
+            canister_id: vec![42],
+            sender: 4,
+            arg: "arg".to_string(),
+        };
+        let bytes = to_canonical_cbor(&request).unwrap();
+        assert_eq!(bytes, to_canonical_cbor(&reordered).unwrap());
+
+        // Shorter keys sort first.
+        let arg = bytes.windows(4).position(|w| w == b"\x63arg").unwrap();
+        let sender = bytes.windows(7).position(|w| w == b"\x66sender").unwrap();
+        let canister_id = bytes
+            .windows(12)
+            .position(|w| w == b"\x6bcanister_id")
+            .unwrap();
+        assert!(arg < sender && sender < canister_id);
+    }
+
+    /// Returns the encoding of the given value without the self-describe tag.
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Vec<u8> {
+        let bytes = to_canonical_cbor(value).unwrap();
+        assert!(is_self_describing_cbor(&bytes));
+        bytes[CBOR_SELF_DESCRIBE_TAG_BYTES.len()..].to_vec()
+    }
+
+    #[test]
+    fn integers_are_encoded_in_their_shortest_form() {
+        // The examples of RFC 8949, appendix A.
+        assert_eq!(encode(&0u64), vec![0x00]);
+        assert_eq!(encode(&23u8), vec![0x17]);
+        assert_eq!(encode(&24u64), vec![0x18, 0x18]);
+        assert_eq!(encode(&1000u32), vec![0x19, 0x03, 0xe8]);
+        assert_eq!(encode(&1_000_000u64), vec![0x1a, 0x00, 0x0f, 0x42, 0x40]);
+        assert_eq!(
+            encode(&u64::MAX),
+            vec![0x1b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]
+        );
+        assert_eq!(encode(&-1i8), vec![0x20]);
+        assert_eq!(encode(&-100i32), vec![0x38, 0x63]);
+        assert_eq!(encode(&-1000i64), vec![0x39, 0x03, 0xe7]);
+    }
+
+    #[test]
+    fn floats_are_encoded_in_their_shortest_form() {
+        // The examples of RFC 8949, appendix A.
+        assert_eq!(encode(&0.0f64), vec![0xf9, 0x00, 0x00]);
+        assert_eq!(encode(&-0.0f64), vec![0xf9, 0x80, 0x00]);
+        assert_eq!(encode(&1.5f64), vec![0xf9, 0x3e, 0x00]);
+        assert_eq!(encode(&65504.0f64), vec![0xf9, 0x7b, 0xff]);
+        assert_eq!(encode(&5.960464477539063e-8f64), vec![0xf9, 0x00, 0x01]);
+        assert_eq!(encode(&0.00006103515625f64), vec![0xf9, 0x04, 0x00]);
+        assert_eq!(encode(&-4.0f32), vec![0xf9, 0xc4, 0x00]);
+        assert_eq!(encode(&f64::INFINITY), vec![0xf9, 0x7c, 0x00]);
+        assert_eq!(encode(&f64::NAN), vec![0xf9, 0x7e, 0x00]);
+        assert_eq!(encode(&100000.0f64), vec![0xfa, 0x47, 0xc3, 0x50, 0x00]);
+        assert_eq!(
+            encode(&1.1f64),
+            vec![0xfb, 0x3f, 0xf1, 0x99, 0x99, 0x99, 0x99, 0x99, 0x9a]
+        );
+    }
+
+    #[test]
+    fn map_keys_are_sorted_by_their_encoding() {
+        // The key 24 is encoded as 0x1818 and -1 as 0x20, so ordering by
+        // length first, as in RFC 7049, would put -1 first.
+        let map: std::collections::BTreeMap<i64, u8> = vec![(-1, 1), (24, 0)].into_iter().collect();
+        assert_eq!(encode(&map), vec![0xa2, 0x18, 0x18, 0x00, 0x20, 0x01]);
+    }
+
+    #[test]
+    fn arrays_of_unknown_length_are_encoded_with_definite_length() {
+        struct Evens(u64);
+
+        impl Serialize for Evens {
+            fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_seq((0..self.0).filter(|n| n % 2 == 0))
+            }
+        }
+
+        assert_eq!(encode(&Evens(5)), vec![0x83, 0x00, 0x02, 0x04]);
+    }
+
+    #[test]
+    fn tagged_values_are_encoded_with_their_tag() {
+        // The epoch-based date/time example of RFC 8949, appendix A.
+        let tagged = Tagged {
+            tag: 1,
+            value: 1_363_896_240u64,
+        };
+        let bytes = to_canonical_cbor(&tagged).unwrap();
+        assert_eq!(
+            bytes[CBOR_SELF_DESCRIBE_TAG_BYTES.len()..],
+            [0xc1, 0x1a, 0x51, 0x4b, 0x67, 0xb0]
+        );
+        // Decoding skips the tag.
+        assert_eq!(from_cbor::<u64>(&bytes).unwrap(), tagged.value);
+
+        let nested = Tagged {
+            tag: CBOR_SELF_DESCRIBE_TAG,
+            value: vec![Tagged {
+                tag: 24,
+                value: "a".to_string(),
+            }],
+        };
+        assert_eq!(
+            encode(&nested),
+            vec![0xd9, 0xd9, 0xf7, 0x81, 0xd8, 0x18, 0x61, 0x61]
+        );
+    }
+
+    #[test]
+    fn enums_are_encoded_like_serde_cbor() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        enum Content {
+            Empty,
+            Reply(Vec<u8>),
+            Reject { code: u64, message: String },
+        }
+
+        for content in vec![
+            Content::Empty,
+            Content::Reply(vec![1, 2]),
+            Content::Reject {
+                code: 4,
+                message: "rejected".to_string(),
+            },
+        ] {
+            let bytes = to_canonical_cbor(&content).unwrap();
+            assert_eq!(from_cbor::<Content>(&bytes).unwrap(), content);
+            // The fields of the variant are in canonical order already.
+            let mut expected = CBOR_SELF_DESCRIBE_TAG_BYTES.to_vec();
+            expected.extend(serde_cbor::to_vec(&content).unwrap());
+            assert_eq!(bytes, expected);
+        }
+    }
+
+    #[test]
+    fn decoding_accepts_tagged_and_untagged_cbor() {
+        let request = Request {
+            sender: 4,
+            arg: "arg".to_string(),
+            canister_id: vec![42],
+        };
+        let tagged = to_canonical_cbor(&request).unwrap();
+        assert_eq!(from_cbor::<Request>(&tagged).unwrap(), request);
+
+        let untagged = serde_cbor::to_vec(&request).unwrap();
+        assert!(!is_self_describing_cbor(&untagged));
+        assert_eq!(from_cbor::<Request>(&untagged).unwrap(), request);
+    }
+}
@@ -0,0 +1,105 @@
+//! Correlation IDs, which link the `tracing` spans and events that the
+//! components of a replica emit for one ingress message.
+//!
+//! The correlation ID of a message is assigned by the first component that
+//! handles the message on this replica: the ingress event handler if the
+//! message is submitted to this replica, or the ingress pool if the message
+//! arrives by gossip. The later hops, i.e. the inclusion in a payload, the
+//! delivery in a batch and the execution, look the ID up by the ID of the
+//! message. Correlation IDs are local to a replica and only retained for the
+//! [`MAX_CORRELATED_MESSAGES`] most recently correlated messages; a message
+//! that is handled again after its ID was evicted gets a new one.
+use super::MessageId;
+use crate::time::current_time;
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::Mutex;
+
+/// The maximum number of messages whose correlation IDs are retained.
+pub const MAX_CORRELATED_MESSAGES: usize = 100_000;
+
+/// The ID correlating the spans and events of one ingress message.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct CorrelationId(u64);
+
+impl fmt::Display for CorrelationId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// The correlation IDs of the most recently correlated messages.
+struct Correlations {
+    ids: HashMap<MessageId, CorrelationId>,
+    /// The correlated messages, in the order in which they were assigned an
+    /// ID.
+    order: VecDeque<MessageId>,
+    next_id: u64,
+}
+
+impl Correlations {
+    fn new() -> Self {
+        Self {
+            ids: HashMap::new(),
+            order: VecDeque::new(),
+            // IDs continue from the time of the start of the replica, so that
+            // the IDs assigned before and after a restart do not collide.
+            next_id: current_time().as_nanos_since_unix_epoch(),
+        }
+    }
+
+    fn get_or_assign(&mut self, message_id: &MessageId, capacity: usize) -> CorrelationId {
+        if let Some(id) = self.ids.get(message_id) {
+            return *id;
+        }
+        if self.order.len() >= capacity {
+            if let Some(evicted) = self.order.pop_front() {
+                self.ids.remove(&evicted);
+            }
+        }
+        let id = CorrelationId(self.next_id);
+        self.next_id = self.next_id.wrapping_add(1);
+        self.ids.insert(message_id.clone(), id);
+        self.order.push_back(message_id.clone());
+        id
+    }
+}
+
+static CORRELATIONS: Lazy<Mutex<Correlations>> = Lazy::new(|| Mutex::new(Correlations::new()));
+
+/// Returns the correlation ID of the given message, assigning a new one if
+/// the message has none yet.
+pub fn correlation_id(message_id: &MessageId) -> CorrelationId {
+    CORRELATIONS
+        .lock()
+        .unwrap()
+        .get_or_assign(message_id, MAX_CORRELATED_MESSAGES)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message_id(i: u8) -> MessageId {
+        MessageId::from([i; 32])
+    }
+
+    #[test]
+    fn a_message_keeps_its_correlation_id() {
+        let id = correlation_id(&message_id(1));
+        assert_eq!(correlation_id(&message_id(1)), id);
+        assert_ne!(correlation_id(&message_id(2)), id);
+    }
+
+    #[test]
+    fn the_least_recently_correlated_messages_are_evicted() {
+        let mut correlations = Correlations::new();
+        let first = correlations.get_or_assign(&message_id(1), 2);
+        let second = correlations.get_or_assign(&message_id(2), 2);
+        correlations.get_or_assign(&message_id(3), 2);
+
+        assert_eq!(correlations.get_or_assign(&message_id(2), 2), second);
+        assert_ne!(correlations.get_or_assign(&message_id(1), 2), first);
+    }
+}
@@ -31,6 +31,11 @@ pub struct HttpCanisterUpdate {
     // Do not include omitted fields in MessageId calculation
     #[serde(skip_serializing_if = "Option::is_none")]
     pub nonce: Option<Blob>,
+    /// The priority class of the message. When the ingress of a block
+    /// exceeds its budget, messages of higher classes are preferred. Omitted
+    /// means class 0.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u64>,
 }
 
 impl HttpCanisterUpdate {
@@ -50,6 +55,9 @@ impl HttpCanisterUpdate {
         if let Some(nonce) = &self.nonce {
             map.insert("nonce".to_string(), Bytes(nonce.0.clone()));
         }
+        if let Some(priority) = self.priority {
+            map.insert("priority".to_string(), U64(priority));
+        }
         hash_of_map(&map)
     }
 
@@ -552,6 +560,23 @@ pub enum ReplicaHealthStatus {
     Healthy,
 }
 
+/// The replica's assessment of its own health, derived from the progress of
+/// its components. In contrast to the `ReplicaHealthStatus`, which only moves
+/// forward during the initialization of the HttpHandler, the assessment can
+/// change in both directions while the replica runs.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplicaHealthAssessment {
+    /// All components make progress.
+    Healthy,
+    /// A component falls behind, but the replica still keeps up with its
+    /// subnet.
+    Degraded,
+    /// A component stalled, or the replica lost its connections to its
+    /// peers.
+    Unhealthy,
+}
+
 /// The response to `/api/v1/status`.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
@@ -565,6 +590,8 @@ pub struct HttpStatusResponse {
     pub impl_hash: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub replica_health_status: Option<ReplicaHealthStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replica_health_assessment: Option<ReplicaHealthAssessment>,
 }
 
 #[cfg(test)]
@@ -704,6 +731,7 @@ mod test {
                 impl_version: Some("0.0".to_string()),
                 impl_hash: None,
                 replica_health_status: Some(ReplicaHealthStatus::Starting),
+                replica_health_assessment: None,
             },
             Value::Map(btreemap! {
                 text("ic_api_version") => text("foobar"),
@@ -722,12 +750,14 @@ mod test {
                 impl_version: Some("0.0".to_string()),
                 impl_hash: None,
                 replica_health_status: Some(ReplicaHealthStatus::Healthy),
+                replica_health_assessment: Some(ReplicaHealthAssessment::Degraded),
             },
             Value::Map(btreemap! {
                 text("ic_api_version") => text("foobar"),
                 text("root_key") => bytes(&[1, 2, 3]),
                 text("impl_version") => text("0.0"),
                 text("replica_health_status") => text("healthy"),
+                text("replica_health_assessment") => text("degraded"),
             }),
         );
     }
@@ -741,6 +771,7 @@ mod test {
                 impl_version: Some("0.0".to_string()),
                 impl_hash: None,
                 replica_health_status: None,
+                replica_health_assessment: None,
             },
             Value::Map(btreemap! {
                 text("ic_api_version") => text("foobar"),
@@ -6,7 +6,7 @@ use crate::{
     messages::message_id::hash_of_map,
     messages::{
         Authentication, HasCanisterId, HttpCanisterUpdate, HttpRequest, HttpRequestContent,
-        HttpRequestEnvelope, HttpSubmitContent, SignedRequestBytes,
+        HttpRequestEnvelope, HttpSubmitContent, SignedRequestBytes, MAX_INGRESS_PRIORITY,
     },
     CanisterId, CountBytes, PrincipalId, SubnetId, Time, UserId,
 };
@@ -32,6 +32,7 @@ pub struct SignedIngressContent {
     arg: Vec<u8>,
     ingress_expiry: u64,
     nonce: Option<Vec<u8>>,
+    priority: Option<u64>,
 }
 
 impl SignedIngressContent {
@@ -58,6 +59,12 @@ impl SignedIngressContent {
     pub fn ingress_expiry(&self) -> Time {
         Time::from_nanos_since_unix_epoch(self.ingress_expiry)
     }
+
+    /// Returns the priority class of the message, 0 if it has none, capped
+    /// at `MAX_INGRESS_PRIORITY`.
+    pub fn priority(&self) -> u64 {
+        self.priority.unwrap_or(0).min(MAX_INGRESS_PRIORITY)
+    }
 }
 
 impl HasCanisterId for SignedIngressContent {
@@ -82,6 +89,9 @@ impl HttpRequestContent for SignedIngressContent {
         if let Some(nonce) = &self.nonce {
             map.insert("nonce".to_string(), Bytes(nonce.clone()));
         }
+        if let Some(priority) = self.priority {
+            map.insert("priority".to_string(), U64(priority));
+        }
         MessageId::from(hash_of_map(&map))
     }
 
@@ -119,6 +129,7 @@ impl TryFrom<HttpCanisterUpdate> for SignedIngressContent {
             arg: update.arg.0,
             ingress_expiry: update.ingress_expiry,
             nonce: update.nonce.map(|n| n.0),
+            priority: update.priority,
         })
     }
 }
@@ -258,6 +269,12 @@ impl SignedIngress {
     pub fn nonce(&self) -> Option<Vec<u8>> {
         self.signed.nonce()
     }
+
+    /// Returns the priority class of the message, 0 if it has none, capped
+    /// at `MAX_INGRESS_PRIORITY`.
+    pub fn priority(&self) -> u64 {
+        self.content().priority()
+    }
 }
 
 impl TryFrom<SignedRequestBytes> for SignedIngress {
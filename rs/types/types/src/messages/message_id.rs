@@ -434,6 +434,7 @@ mod tests {
             sender: Blob(vec![0; 29]),
             ingress_expiry: expiry_time.as_nanos_since_unix_epoch(),
             nonce: None,
+            priority: None,
         };
         let content = HttpSubmitContent::Call { update };
         let envelope = HttpRequestEnvelope::<HttpSubmitContent> {
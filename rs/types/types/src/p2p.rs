@@ -17,6 +17,9 @@ use std::convert::TryFrom;
 #[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct GossipAdvert {
     pub attribute: ArtifactAttribute,
+    /// The size of the artifact. For artifacts of kinds that can be downloaded
+    /// as multiple chunks, it is the size of the serialized artifact, which
+    /// determines the number of chunks, see `ArtifactKind::multi_chunk_tracker`.
     pub size: usize,
     pub artifact_id: ArtifactId,
     /// the root hash of the Merkle tree of chunks forming the Artifact
@@ -46,8 +49,8 @@ pub const MAX_DUPLICITY: u32 = 1;
 /// Maximum size in bytes of an artifact chunk. Used to compute the chunk
 /// timeout interval.
 //
-// Once universal chunking is implemented (P2P-292), chunks larger than this
-// size will not be requested.
+// Artifacts larger than `chunkable::ARTIFACT_CHUNK_SIZE` can be downloaded as
+// multiple chunks, see `ArtifactKind::multi_chunk_tracker`.
 pub const MAX_CHUNK_SIZE: u32 = 4096;
 
 /// Size of each receive check hash set for each peer
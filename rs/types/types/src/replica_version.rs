@@ -1,9 +1,11 @@
 //! ReplicaVersion can be converted to/from string representation.
+use crate::crypto::SignedBytesWithoutDomainSeparator;
 use once_cell::sync::OnceCell;
 use serde::{Deserialize, Serialize};
 use std::convert::TryFrom;
 use std::error::Error;
 use std::fmt;
+use std::path::PathBuf;
 
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct ReplicaVersion {
@@ -107,6 +109,50 @@ impl fmt::Display for ReplicaVersionParseError {
 
 impl Error for ReplicaVersionParseError {}
 
+/// The content the NNS signs to authorize the release package of a replica
+/// version: the version and the SHA-256 hash of the package.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ReleasePackageContent {
+    pub replica_version: ReplicaVersion,
+    /// The hex-formatted SHA-256 hash of the release package
+    pub sha256_hex: String,
+}
+
+impl SignedBytesWithoutDomainSeparator for ReleasePackageContent {
+    fn as_signed_bytes_without_domain_separator(&self) -> Vec<u8> {
+        serde_cbor::to_vec(&self).unwrap()
+    }
+}
+
+/// The status of the upgrade of a node to the release package of a replica
+/// version.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UpgradeStatus {
+    /// No upgrade is in progress.
+    Idle,
+    /// The release package is being downloaded.
+    Downloading(ReplicaVersion),
+    /// The hash and the signature of the release package are being verified.
+    Verifying(ReplicaVersion),
+    /// The release package is verified and staged at the given path, ready
+    /// to be installed.
+    Staged {
+        replica_version: ReplicaVersion,
+        image: PathBuf,
+    },
+    /// The release package could not be staged.
+    Failed {
+        replica_version: ReplicaVersion,
+        reason: String,
+    },
+}
+
+impl Default for UpgradeStatus {
+    fn default() -> Self {
+        UpgradeStatus::Idle
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
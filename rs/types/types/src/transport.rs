@@ -1,9 +1,13 @@
 //! Transport layer public types.
 
 use crate::NodeId;
+use bytes::Bytes;
 use phantom_newtype::Id;
 
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::path::PathBuf;
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct FlowTagType;
@@ -11,8 +15,18 @@ pub struct FlowTagType;
 pub type FlowTag = Id<FlowTagType, u32>;
 
 /// The payload for the transport layer.
+///
+/// The payload is reference counted, so that large payloads (e.g., state sync
+/// chunks) are passed from the transport client to the socket without being
+/// copied.
 #[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
-pub struct TransportPayload(#[serde(with = "serde_bytes")] pub Vec<u8>);
+pub struct TransportPayload(pub Bytes);
+
+impl From<Vec<u8>> for TransportPayload {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self(Bytes::from(bytes))
+    }
+}
 
 /// A transport notification.
 #[derive(Debug)]
@@ -53,10 +67,159 @@ pub struct FlowId {
 pub struct TransportConfig {
     pub node_ip: String,
 
+    /// Optional IP address of the other address family, for nodes that are
+    /// reachable via both IPv4 and IPv6. Connections are accepted on both
+    /// addresses, and outgoing connections prefer IPv6 over IPv4.
+    #[serde(default)]
+    pub secondary_node_ip: Option<String>,
+
+    /// The external IP address of this node, as observed from outside of its
+    /// NAT. If set, it is registered as the address of the node's endpoints
+    /// instead of the node IP. With NAT traversal, peers report the address
+    /// they observe the node under when connecting, and reports that differ
+    /// from this address are logged and counted in
+    /// `transport_observed_addr_mismatches`.
+    #[serde(default)]
+    pub external_ip: Option<String>,
+
+    /// Enables NAT traversal: both sides of a connection connect to each
+    /// other from their server port (TCP simultaneous open), so that replicas
+    /// behind NAT can connect without port forwarding. The address a peer
+    /// connects from is preferred over its registered endpoints when
+    /// reconnecting. Intended for test subnets.
+    #[serde(default)]
+    pub nat_traversal: bool,
+
+    /// Policy for retrying failed connection attempts
+    #[serde(default)]
+    pub reconnect: TransportReconnectConfig,
+
+    /// The wire codecs supported by the transport clients, in order of
+    /// preference. The codec used with a peer is negotiated during the
+    /// connection handshake. Protobuf is always supported.
+    #[serde(default)]
+    pub wire_codecs: Vec<WireCodecId>,
+
+    /// How the TLS certificates of the peers are validated
+    #[serde(default)]
+    pub tls_validation: TlsValidationConfig,
+
     /// P2P specific config. In future, this will be made more generic.
     pub p2p_flows: Vec<TransportFlowConfig>,
 }
 
+/// How the TLS certificates of the peers are validated. The modes other than
+/// `RegistryPinned` allow connections between nodes whose certificates are
+/// not in the registry, and are intended for test networks and air-gapped
+/// deployments.
+///
+/// In these modes, the registry does not need to contain the node records of
+/// the peers either: `peer_ips` maps the textual form of a peer's node ID to
+/// its IP address, which is used if the registry contains no node record for
+/// the peer. Such peers are connected to at the `server_port`s of this node's
+/// `p2p_flows`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TlsValidationConfig {
+    /// The certificates of the node and its peers are taken from the
+    /// registry
+    RegistryPinned,
+    /// The peers' certificates must be issued by the CA whose PEM encoded
+    /// certificate is at `ca_cert_path`. The node presents the certificate at
+    /// `cert_path`, which the CA issued for the node's TLS key.
+    CaIssued {
+        ca_cert_path: PathBuf,
+        cert_path: PathBuf,
+        #[serde(default)]
+        peer_ips: BTreeMap<String, String>,
+    },
+    /// The peers' certificates must be among the PEM encoded certificates at
+    /// `path`, each of which is allowed for the node ID in its subject common
+    /// name. The node presents its own self-signed certificate.
+    AllowList {
+        path: PathBuf,
+        #[serde(default)]
+        peer_ips: BTreeMap<String, String>,
+    },
+}
+
+impl TlsValidationConfig {
+    /// Returns the IP addresses of the peers whose node records need not be
+    /// in the registry.
+    pub fn peer_ips(&self) -> Option<&BTreeMap<String, String>> {
+        match self {
+            TlsValidationConfig::RegistryPinned => None,
+            TlsValidationConfig::CaIssued { peer_ips, .. }
+            | TlsValidationConfig::AllowList { peer_ips, .. } => Some(peer_ips),
+        }
+    }
+}
+
+impl Default for TlsValidationConfig {
+    fn default() -> Self {
+        TlsValidationConfig::RegistryPinned
+    }
+}
+
+/// The encodings of client messages on the wire
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum WireCodecId {
+    /// Protocol buffers, supported by all nodes
+    Protobuf = 0,
+    /// CBOR
+    Cbor = 1,
+    /// Length-prefixed bincode
+    Bincode = 2,
+}
+
+impl Default for WireCodecId {
+    fn default() -> Self {
+        WireCodecId::Protobuf
+    }
+}
+
+impl TryFrom<u8> for WireCodecId {
+    type Error = u8;
+
+    fn try_from(id: u8) -> Result<Self, Self::Error> {
+        match id {
+            0 => Ok(WireCodecId::Protobuf),
+            1 => Ok(WireCodecId::Cbor),
+            2 => Ok(WireCodecId::Bincode),
+            _ => Err(id),
+        }
+    }
+}
+
+/// The reconnection policy: failed connection attempts are retried with
+/// exponential backoff, randomized by a jitter.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TransportReconnectConfig {
+    /// The interval before the first retry
+    pub initial_retry_interval_ms: u64,
+
+    /// The maximum interval between retries
+    pub max_retry_interval_ms: u64,
+
+    /// The factor by which the interval grows after each failed retry
+    pub backoff_multiplier: u32,
+
+    /// The maximum deviation of a retry interval from the backoff interval,
+    /// in percent
+    pub jitter_percent: u32,
+}
+
+impl Default for TransportReconnectConfig {
+    fn default() -> Self {
+        Self {
+            initial_retry_interval_ms: 1000,
+            max_retry_interval_ms: 30_000,
+            backoff_multiplier: 2,
+            jitter_percent: 20,
+        }
+    }
+}
+
 /// Per-flow config
 #[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct TransportFlowConfig {
@@ -69,6 +232,24 @@ pub struct TransportFlowConfig {
 
     /// Flow queue size
     pub queue_size: usize,
+
+    /// Optional limit of the bandwidth used by the flow, per peer. Can be
+    /// used to leave headroom for other flows, e.g., to cap the state sync
+    /// traffic.
+    #[serde(default)]
+    pub rate_limit: Option<TransportRateLimitConfig>,
+}
+
+/// Token bucket rate limit of a flow: messages are sent at the given rate on
+/// average, with bursts of up to the bucket size.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TransportRateLimitConfig {
+    /// The rate at which the bucket is refilled, in bytes per second
+    pub bytes_per_second: u64,
+
+    /// The size of the bucket, i.e., the number of bytes that can be sent
+    /// in a burst
+    pub burst_bytes: u64,
 }
 
 /// State changes that can happen in the transport layer.
@@ -79,6 +260,21 @@ pub enum TransportStateChange {
 
     /// Peer flow went down
     PeerFlowDown(TransportFlowInfo),
+
+    /// The send queue of a peer flow filled up to its high watermark
+    SendQueueHighWatermark(TransportFlowInfo),
+
+    /// The send queue of a peer flow drained down to its low watermark, after
+    /// having reached its high watermark
+    SendQueueLowWatermark(TransportFlowInfo),
+
+    /// The connection with the peer was established. Reported once per
+    /// connection, after the `PeerFlowUp` of all flows with the peer.
+    PeerConnectionUp(NodeId),
+
+    /// The connection with the peer went down. Reported once per connection,
+    /// after the `PeerFlowDown` of all flows with the peer.
+    PeerConnectionDown(NodeId),
 }
 
 /// Errors that are returned by the transport layer.
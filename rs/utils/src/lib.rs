@@ -13,3 +13,4 @@ pub mod fs;
 pub mod ic_features;
 pub mod rle;
 pub mod thread;
+pub mod token_bucket;
@@ -0,0 +1,133 @@
+//! A token bucket rate limiter.
+//!
+//! The bucket holds up to `capacity` tokens and is refilled at `rate` tokens
+//! per second. Callers either take tokens only if enough are available, or
+//! reserve tokens in advance, in which case the bucket may go into debt and
+//! the caller waits until the debt is refilled.
+
+use std::time::{Duration, Instant};
+
+/// A token bucket.
+pub struct TokenBucket {
+    /// The refill rate, in tokens per second.
+    rate: f64,
+    /// The maximum number of tokens.
+    capacity: f64,
+    /// The current number of tokens. Negative if more tokens were taken than
+    /// were available.
+    tokens: f64,
+    /// The time of the last refill.
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// Creates a full bucket.
+    pub fn new(rate: f64, capacity: f64, now: Instant) -> Self {
+        Self {
+            rate,
+            capacity,
+            tokens: capacity,
+            last_refill: now,
+        }
+    }
+
+    /// Refills the bucket for the time elapsed since the last refill and
+    /// returns the number of available tokens.
+    pub fn available(&mut self, now: Instant) -> f64 {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last_refill = now;
+        self.tokens
+    }
+
+    /// Returns true if the bucket is full, i.e., it is equivalent to a new
+    /// bucket.
+    pub fn is_full(&mut self, now: Instant) -> bool {
+        self.available(now) >= self.capacity
+    }
+
+    /// Takes the given number of tokens, even if fewer are available.
+    pub fn take(&mut self, tokens: f64) {
+        self.tokens -= tokens;
+    }
+
+    /// Returns tokens that were taken but not used.
+    pub fn give_back(&mut self, tokens: f64) {
+        self.tokens = (self.tokens + tokens).min(self.capacity);
+    }
+
+    /// Takes the given number of tokens and returns how long the caller has to
+    /// wait until the bucket holds them. A bucket with a zero rate does not
+    /// limit the caller.
+    pub fn reserve(&mut self, tokens: f64, now: Instant) -> Duration {
+        if self.rate <= 0.0 {
+            return Duration::from_secs(0);
+        }
+        self.available(now);
+        self.take(tokens);
+        if self.tokens >= 0.0 {
+            Duration::from_secs(0)
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_burst_is_not_delayed() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(1000.0, 5000.0, now);
+        assert_eq!(bucket.reserve(2000.0, now), Duration::from_secs(0));
+        assert_eq!(bucket.reserve(3000.0, now), Duration::from_secs(0));
+        assert_eq!(bucket.reserve(1000.0, now), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_bucket_refills_up_to_capacity() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(1000.0, 5000.0, now);
+        assert_eq!(bucket.reserve(5000.0, now), Duration::from_secs(0));
+
+        // Two seconds refill 2000 tokens.
+        let now = now + Duration::from_secs(2);
+        assert_eq!(bucket.reserve(2000.0, now), Duration::from_secs(0));
+        assert_eq!(bucket.reserve(500.0, now), Duration::from_millis(500));
+
+        // The bucket does not hold more than its capacity.
+        let now = now + Duration::from_secs(60);
+        assert!(bucket.is_full(now));
+        assert_eq!(bucket.reserve(5000.0, now), Duration::from_secs(0));
+        assert_eq!(bucket.reserve(1000.0, now), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_zero_rate_is_unlimited() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(0.0, 0.0, now);
+        assert_eq!(
+            bucket.reserve((1 << 30) as f64, now),
+            Duration::from_secs(0)
+        );
+    }
+
+    #[test]
+    fn test_tokens_given_back_are_available_again() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(1.0, 2.0, now);
+        bucket.take(2.0);
+        bucket.give_back(1.0);
+        assert_eq!(bucket.reserve(1.0, now), Duration::from_secs(0));
+        assert_eq!(bucket.reserve(1.0, now), Duration::from_secs(1));
+
+        // The bucket does not hold more than its capacity.
+        bucket.give_back(5.0);
+        assert!(bucket.is_full(now));
+        assert_eq!(bucket.reserve(3.0, now), Duration::from_secs(1));
+    }
+}
@@ -5,39 +5,43 @@ use ic_types::crypto::{CanisterSig, CanisterSigOf};
 use ic_types::{
     crypto::{AlgorithmId, BasicSig, BasicSigOf, CryptoError, UserPublicKey},
     ingress::MAX_INGRESS_TTL,
-    malicious_flags::MaliciousFlags,
+    malicious_strategies::{DisableIngressValidation, MaliciousBehaviors},
     messages::{
         Authentication, Delegation, HasCanisterId, HttpRequest, HttpRequestContent, MessageId,
         SignedDelegation, UserSignature, WebAuthnSignature,
     },
     CanisterId, PrincipalId, RegistryVersion, Time, UserId,
 };
-use std::{collections::BTreeSet, convert::TryFrom, fmt};
+use std::{collections::BTreeSet, convert::TryFrom, fmt, time::Duration};
 
 /// Validates the `request` and that the sender is authorized to send
-/// a message to the receiving canister.
+/// a message to the receiving canister. The request may expire at most
+/// `max_ingress_ttl` after `current_time`, i.e. within the expiry window of the
+/// subnet.
 ///
 /// See notes on request validity in the crate docs.
 pub fn validate_request<C: HttpRequestContent + HasCanisterId>(
     request: &HttpRequest<C>,
     ingress_signature_verifier: &dyn IngressSigVerifier,
     current_time: Time,
+    max_ingress_ttl: Duration,
     registry_version: RegistryVersion,
-    malicious_flags: &MaliciousFlags,
+    malicious_behaviors: &MaliciousBehaviors,
 ) -> Result<(), RequestValidationError> {
     #[cfg(feature = "malicious_code")]
     {
-        if malicious_flags.maliciously_disable_ingress_validation {
+        if malicious_behaviors.contains::<DisableIngressValidation>() {
             return Ok(());
         }
     }
 
-    get_authorized_canisters(
+    get_authorized_canisters_with_max_ingress_ttl(
         &request,
         ingress_signature_verifier,
         current_time,
+        max_ingress_ttl,
         registry_version,
-        malicious_flags,
+        malicious_behaviors,
     )
     .and_then(|targets| {
         if targets.contains(&request.content().canister_id()) {
@@ -59,16 +63,34 @@ pub fn get_authorized_canisters<C: HttpRequestContent>(
     ingress_signature_verifier: &dyn IngressSigVerifier,
     current_time: Time,
     registry_version: RegistryVersion,
-    #[allow(unused_variables)] malicious_flags: &MaliciousFlags,
+    malicious_behaviors: &MaliciousBehaviors,
+) -> Result<CanisterIdSet, RequestValidationError> {
+    get_authorized_canisters_with_max_ingress_ttl(
+        request,
+        ingress_signature_verifier,
+        current_time,
+        MAX_INGRESS_TTL,
+        registry_version,
+        malicious_behaviors,
+    )
+}
+
+fn get_authorized_canisters_with_max_ingress_ttl<C: HttpRequestContent>(
+    request: &HttpRequest<C>,
+    ingress_signature_verifier: &dyn IngressSigVerifier,
+    current_time: Time,
+    max_ingress_ttl: Duration,
+    registry_version: RegistryVersion,
+    #[allow(unused_variables)] malicious_behaviors: &MaliciousBehaviors,
 ) -> Result<CanisterIdSet, RequestValidationError> {
     #[cfg(feature = "malicious_code")]
     {
-        if malicious_flags.maliciously_disable_ingress_validation {
+        if malicious_behaviors.contains::<DisableIngressValidation>() {
             return Ok(CanisterIdSet::All);
         }
     }
 
-    validate_ingress_expiry(request, current_time)?;
+    validate_ingress_expiry(request, current_time, max_ingress_ttl)?;
     validate_user_id_and_signature(
         ingress_signature_verifier,
         &request.sender(),
@@ -177,11 +199,12 @@ impl CanisterIdSet {
 fn validate_ingress_expiry<C: HttpRequestContent>(
     request: &HttpRequest<C>,
     current_time: Time,
+    max_ingress_ttl: Duration,
 ) -> Result<(), RequestValidationError> {
     let ingress_expiry = request.ingress_expiry();
     let provided_expiry = Time::from_nanos_since_unix_epoch(ingress_expiry);
     let min_allowed_expiry = current_time;
-    let max_allowed_expiry = min_allowed_expiry + MAX_INGRESS_TTL;
+    let max_allowed_expiry = min_allowed_expiry + max_ingress_ttl;
     if !(min_allowed_expiry <= provided_expiry && provided_expiry <= max_allowed_expiry) {
         let msg = format!(
             "Specified ingress_expiry not within expected range:\n\
@@ -436,6 +459,27 @@ mod tests {
         RegistryVersion::from(0)
     }
 
+    #[test]
+    fn ingress_expiry_is_bounded_by_the_given_max_ingress_ttl() {
+        use ic_test_utilities::types::messages::SignedIngressBuilder;
+
+        let current_time = UNIX_EPOCH + Duration::from_secs(1000);
+        let max_ingress_ttl = Duration::from_secs(60);
+        let validate = |expiry_delay: Duration| {
+            let request = SignedIngressBuilder::new()
+                .expiry_time(current_time + expiry_delay)
+                .build();
+            validate_ingress_expiry(request.as_ref(), current_time, max_ingress_ttl)
+        };
+
+        assert_matches!(validate(Duration::from_secs(0)), Ok(()));
+        assert_matches!(validate(max_ingress_ttl), Ok(()));
+        assert_matches!(
+            validate(max_ingress_ttl + Duration::from_secs(1)),
+            Err(InvalidIngressExpiry(_))
+        );
+    }
+
     #[test]
     fn plain_authentication_correct_signature_passes() {
         let sig_verifier = temp_crypto_component_with_fake_registry(node_test_id(0));